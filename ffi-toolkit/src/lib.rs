@@ -37,7 +37,22 @@ pub unsafe fn cast_const<'a, T>(x: *mut T) -> &'a T {
     (&(*x))
 }
 
-// transmutes a C string to a PathBuf
+// transmutes a C string to a PathBuf, byte for byte on unix, where paths
+// needn't be UTF-8, and decoded from UTF-8 on Windows, where paths are UTF-16
+// and callers are to hand them in as UTF-8
+#[cfg(unix)]
+pub unsafe fn c_str_to_pbuf(x: *const libc::c_char) -> PathBuf {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+
+    if x.is_null() {
+        PathBuf::new()
+    } else {
+        PathBuf::from(OsStr::from_bytes(CStr::from_ptr(x).to_bytes()))
+    }
+}
+
+#[cfg(not(unix))]
 pub unsafe fn c_str_to_pbuf(x: *const libc::c_char) -> PathBuf {
     PathBuf::from(String::from(c_str_to_rust_str(x)))
 }
@@ -37,6 +37,8 @@ fn main() {
 
     let b = bindgen::builder()
         .header(target_path.join("libfilecoin_proofs.h").to_string_lossy())
+        // Variants are already prefixed with their enum's name by cbindgen.
+        .prepend_enum_name(false)
         // Here, we tell Rust to link libfilecoin_proofs so that auto-generated
         // symbols are linked to symbols in the compiled dylib. For reasons
         // unbeknown to me, the link attribute needs to precede an extern block.
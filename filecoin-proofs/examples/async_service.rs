@@ -0,0 +1,124 @@
+extern crate clap;
+extern crate filecoin_proofs;
+extern crate futures;
+extern crate sector_base;
+extern crate tempfile;
+
+use clap::{App, Arg};
+use std::fs;
+use std::sync::Arc;
+use std::thread;
+use std::time::Instant;
+
+use filecoin_proofs::api::task::{
+    seal_async, set_pool_size, verify_seal_async, SealParams, VerifySealParams,
+};
+use futures::executor::{self, Notify, NotifyHandle};
+use futures::future::{self, Future};
+use futures::Async;
+use sector_base::api::disk_backed_storage::{new_sector_config, ConfiguredStore};
+use sector_base::api::sector_store::SectorConfig;
+
+// Wakes the thread blocked in block_on when the future it polls can make
+// progress.
+struct ThreadNotify(thread::Thread);
+
+impl Notify for ThreadNotify {
+    fn notify(&self, _id: usize) {
+        self.0.unpark();
+    }
+}
+
+// About the smallest executor there is: it polls a single future on the calling
+// thread, parking it in between. Any other executor (e.g. a tokio runtime)
+// polls the crate's futures just the same, as they don't depend on one.
+fn block_on<F: Future>(future: F) -> Result<F::Item, F::Error> {
+    let notify = NotifyHandle::from(Arc::new(ThreadNotify(thread::current())));
+    let mut spawned = executor::spawn(future);
+
+    loop {
+        match spawned.poll_future_notify(&notify, 0)? {
+            Async::Ready(item) => return Ok(item),
+            Async::NotReady => thread::park(),
+        }
+    }
+}
+
+// Seals test sectors concurrently on the crate's task pool, then verifies them,
+// while the thread driving the futures is free to poll others.
+fn do_the_work(sectors: u8) {
+    let config: Arc<SectorConfig> = Arc::from(new_sector_config(&ConfiguredStore::Test));
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+
+    let params: Vec<SealParams> = (0..sectors)
+        .map(|i| {
+            let staged_path = dir.path().join(format!("staged-{}", i));
+            fs::write(&staged_path, &[i; 500]).expect("failed to write staged sector");
+
+            SealParams {
+                staged_path,
+                sealed_path: dir.path().join(format!("sealed-{}", i)),
+                prover_id: [1; 31],
+                sector_id: [i; 31],
+            }
+        })
+        .collect();
+
+    let start = Instant::now();
+    let seals = params
+        .iter()
+        .map(|params| seal_async(config.clone(), params.clone()));
+    let outputs = block_on(future::join_all(seals)).expect("failed to seal");
+    println!("sealed {} sectors in {:?}", sectors, start.elapsed());
+
+    let start = Instant::now();
+    let verifies = params.iter().zip(&outputs).map(|(params, output)| {
+        verify_seal_async(
+            config.clone(),
+            VerifySealParams {
+                comm_r: output.comm_r,
+                comm_d: output.comm_d,
+                comm_r_star: output.comm_r_star,
+                prover_id: params.prover_id,
+                sector_id: params.sector_id,
+                proof: output.proof.clone(),
+            },
+        )
+    });
+    let verified = block_on(future::join_all(verifies)).expect("failed to verify");
+    println!("verified {:?} in {:?}", verified, start.elapsed());
+}
+
+fn main() {
+    let matches = App::new(stringify!("Async Service"))
+        .version("1.0")
+        .arg(
+            Arg::with_name("sectors")
+                .help("The number of sectors to seal concurrently")
+                .long("sectors")
+                .default_value("4")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("threads")
+                .help("The number of threads to seal and verify on")
+                .long("threads")
+                .default_value("2")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let sectors = matches
+        .value_of("sectors")
+        .unwrap()
+        .parse()
+        .expect("sectors must be an integer");
+    let threads = matches
+        .value_of("threads")
+        .unwrap()
+        .parse()
+        .expect("threads must be an integer");
+
+    set_pool_size(threads).expect("failed to size the task pool");
+    do_the_work(sectors);
+}
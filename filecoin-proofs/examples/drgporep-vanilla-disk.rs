@@ -18,6 +18,7 @@ use pairing::bls12_381::{Bls12, Fr};
 use rand::{Rng, SeedableRng, XorShiftRng};
 use std::time::{Duration, Instant};
 
+use storage_proofs::crypto::feistel::FeistelConfig;
 use storage_proofs::drgporep::*;
 use storage_proofs::drgraph::*;
 use storage_proofs::example_helper::prettyb;
@@ -48,8 +49,6 @@ fn file_backed_mmap_from_random_bytes(n: usize) -> MmapMut {
 
 fn do_the_work<H: Hasher>(data_size: usize, m: usize, sloth_iter: usize, challenge_count: usize) {
     let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
-    let challenges = vec![2; challenge_count];
-
     info!(FCP_LOG, "data_size:  {}", prettyb(data_size); "target" => "config");
     info!(FCP_LOG, "challenge_count: {}", challenge_count; "target" => "config");
     info!(FCP_LOG, "m: {}", m; "target" => "config");
@@ -69,6 +68,7 @@ fn do_the_work<H: Hasher>(data_size: usize, m: usize, sloth_iter: usize, challen
             degree: m,
             expansion_degree: 0,
             seed: new_seed(),
+            feistel: FeistelConfig::default(),
         },
         sloth_iter,
     };
@@ -85,7 +85,9 @@ fn do_the_work<H: Hasher>(data_size: usize, m: usize, sloth_iter: usize, challen
 
     let pub_inputs = PublicInputs::<H::Domain> {
         replica_id: replica_id.into(),
-        challenges,
+        challenge_seed: rng.gen(),
+        challenge_count,
+        k: None,
         tau: Some(tau),
     };
 
@@ -20,6 +20,7 @@ use std::time::{Duration, Instant};
 #[cfg(feature = "profile")]
 use gperftools::profiler::PROFILER;
 
+use storage_proofs::crypto::feistel::FeistelConfig;
 use storage_proofs::drgporep::*;
 use storage_proofs::drgraph::*;
 use storage_proofs::example_helper::prettyb;
@@ -56,8 +57,6 @@ fn stop_profile() {}
 
 fn do_the_work<H: Hasher>(data_size: usize, m: usize, sloth_iter: usize, challenge_count: usize) {
     let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
-    let challenges = vec![2; challenge_count];
-
     info!(FCP_LOG, "data_size:  {}", prettyb(data_size); "target" => "stats");
     info!(FCP_LOG, "challenge_count: {}", challenge_count; "target" => "stats");
     info!(FCP_LOG, "m: {}", m; "target" => "stats");
@@ -78,6 +77,7 @@ fn do_the_work<H: Hasher>(data_size: usize, m: usize, sloth_iter: usize, challen
             degree: m,
             expansion_degree: 0,
             seed: new_seed(),
+            feistel: FeistelConfig::default(),
         },
         sloth_iter,
     };
@@ -98,7 +98,9 @@ fn do_the_work<H: Hasher>(data_size: usize, m: usize, sloth_iter: usize, challen
     stop_profile();
     let pub_inputs = PublicInputs {
         replica_id,
-        challenges,
+        challenge_seed: rng.gen(),
+        challenge_count,
+        k: None,
         tau: Some(tau),
     };
 
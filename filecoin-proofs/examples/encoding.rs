@@ -25,6 +25,7 @@ use std::fs::File;
 use std::io::Write;
 use std::time::Instant;
 
+use storage_proofs::crypto::feistel::FeistelConfig;
 use storage_proofs::drgporep;
 use storage_proofs::drgraph::*;
 use storage_proofs::example_helper::prettyb;
@@ -106,6 +107,7 @@ where
                 degree: m,
                 expansion_degree,
                 seed: new_seed(),
+                feistel: FeistelConfig::default(),
             },
             sloth_iter,
         },
@@ -55,6 +55,7 @@ unsafe fn create_and_add_piece(
             c_piece_key,
             &piece_bytes[0],
             piece_bytes.len(),
+            0,
         ),
     )
 }
@@ -64,7 +65,7 @@ unsafe fn create_sector_builder(
     staging_dir: &TempDir,
     sealed_dir: &TempDir,
     prover_id: [u8; 31],
-    last_committed_sector_id: u64,
+    first_sector_id: u64,
     sector_store_config: ConfiguredStore,
 ) -> (*mut SectorBuilder, usize) {
     let mut prover_id: [u8; 31] = prover_id;
@@ -81,12 +82,26 @@ unsafe fn create_sector_builder(
 
     let resp = init_sector_builder(
         &sector_store_config,
-        last_committed_sector_id,
+        first_sector_id,
+        u64::max_value(),
         c_metadata_dir,
         &mut prover_id,
         c_sealed_dir,
+        ptr::null(),
         c_staging_dir,
         2,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy_Allow,
+        ptr::null(),
     );
     defer!(destroy_init_sector_builder_response(resp));
 
@@ -114,6 +129,13 @@ struct ConfigurableSizes {
 }
 
 unsafe fn sector_builder_lifecycle(use_live_store: bool) -> Result<(), Box<Error>> {
+    let init = filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION);
+    defer!(destroy_init_response(init));
+
+    if (*init).status_code != 0 {
+        panic!("{}", c_str_to_rust_str((*init).error_msg))
+    }
+
     let metadata_dir = tempfile::tempdir().unwrap();
     let staging_dir = tempfile::tempdir().unwrap();
     let sealed_dir = tempfile::tempdir().unwrap();
@@ -121,7 +143,7 @@ unsafe fn sector_builder_lifecycle(use_live_store: bool) -> Result<(), Box<Error
     let sizes = if use_live_store {
         ConfigurableSizes {
             store: ConfiguredStore_Live,
-            max_bytes: 266338304,
+            max_bytes: 266338272,
             first_piece_bytes: 26214400,
             second_piece_bytes: 131072000,
             third_piece_bytes: 157286400,
@@ -129,7 +151,7 @@ unsafe fn sector_builder_lifecycle(use_live_store: bool) -> Result<(), Box<Error
     } else {
         ConfigurableSizes {
             store: ConfiguredStore_Test,
-            max_bytes: 1016,
+            max_bytes: 984,
             first_piece_bytes: 100,
             second_piece_bytes: 500,
             third_piece_bytes: 600,
@@ -141,7 +163,7 @@ unsafe fn sector_builder_lifecycle(use_live_store: bool) -> Result<(), Box<Error
         &staging_dir,
         &sealed_dir,
         [0; 31],
-        123,
+        124,
         sizes.store,
     );
 
@@ -156,7 +178,7 @@ unsafe fn sector_builder_lifecycle(use_live_store: bool) -> Result<(), Box<Error
 
     // verify that we have neither sealed nor staged sectors yet
     {
-        let resp = get_sealed_sectors(sector_builder_a);
+        let resp = get_sealed_sectors(sector_builder_a, false);
         defer!(destroy_get_sealed_sectors_response(resp));
 
         if (*resp).status_code != 0 {
@@ -165,7 +187,7 @@ unsafe fn sector_builder_lifecycle(use_live_store: bool) -> Result<(), Box<Error
 
         assert_eq!(0, (*resp).sectors_len);
 
-        let resp = get_staged_sectors(sector_builder_a);
+        let resp = get_staged_sectors(sector_builder_a, false);
         defer!(destroy_get_staged_sectors_response(resp));
 
         if (*resp).status_code != 0 {
@@ -215,7 +237,7 @@ unsafe fn sector_builder_lifecycle(use_live_store: bool) -> Result<(), Box<Error
     // get staged sector metadata and verify that we've now got two staged
     // sectors
     {
-        let resp = get_staged_sectors(sector_builder_a);
+        let resp = get_staged_sectors(sector_builder_a, false);
         defer!(destroy_get_staged_sectors_response(resp));
 
         if (*resp).status_code != 0 {
@@ -226,7 +248,7 @@ unsafe fn sector_builder_lifecycle(use_live_store: bool) -> Result<(), Box<Error
     }
 
     // drop the first sector builder, relinquishing any locks on persistence
-    destroy_sector_builder(sector_builder_a);
+    destroy_sector_builder(sector_builder_a, 0);
 
     // create a new sector builder using same prover id, which should
     // initialize with metadata persisted by previous sector builder
@@ -235,10 +257,10 @@ unsafe fn sector_builder_lifecycle(use_live_store: bool) -> Result<(), Box<Error
         &staging_dir,
         &sealed_dir,
         [0; 31],
-        123,
+        124,
         sizes.store,
     );
-    defer!(destroy_sector_builder(sector_builder_b));
+    defer!(destroy_sector_builder(sector_builder_b, 0));
 
     // add fourth piece, where size(piece) == max (will trigger sealing)
     let (bytes_in, piece_key) = {
@@ -302,7 +324,7 @@ unsafe fn sector_builder_lifecycle(use_live_store: bool) -> Result<(), Box<Error
 
     // get sealed sectors - we should have just one
     {
-        let resp = get_sealed_sectors(sector_builder_b);
+        let resp = get_sealed_sectors(sector_builder_b, false);
         defer!(destroy_get_sealed_sectors_response(resp));
 
         if (*resp).status_code != 0 {
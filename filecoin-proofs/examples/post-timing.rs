@@ -0,0 +1,80 @@
+extern crate clap;
+
+extern crate filecoin_proofs;
+extern crate sector_base;
+extern crate tempfile;
+
+use clap::{App, Arg};
+use std::fs;
+use std::io;
+
+use filecoin_proofs::api::internal::seal;
+use filecoin_proofs::api::post_timing::{sweep_challenge_counts, SweptSector};
+use sector_base::api::disk_backed_storage::{new_sector_config, ConfiguredStore};
+
+// Seals two test sectors, then generates and verifies a PoSt over them with
+// each of the given numbers of challenges per epoch, printing where the time
+// went as CSV.
+fn do_the_work(challenge_counts: &[usize]) {
+    let config = new_sector_config(&ConfiguredStore::Test);
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+
+    let sectors: Vec<SweptSector> = (0..2)
+        .map(|i| {
+            let staged_path = dir.path().join(format!("staged-{}", i));
+            let sealed_path = dir.path().join(format!("sealed-{}", i));
+            fs::write(&staged_path, &[i as u8 + 7; 500]).expect("failed to write staged sector");
+
+            let output = seal(
+                config.as_ref(),
+                &staged_path,
+                &sealed_path,
+                &[1; 31],
+                &[i as u8 + 2; 31],
+            )
+            .expect("failed to seal");
+
+            SweptSector {
+                sealed_sector_access: sealed_path.to_str().unwrap().to_owned(),
+                comm_r: output.comm_r,
+            }
+        })
+        .collect();
+
+    let stdout = io::stdout();
+    sweep_challenge_counts(
+        config.sector_bytes(),
+        &sectors,
+        [3; 32],
+        challenge_counts,
+        &mut stdout.lock(),
+    )
+    .expect("failed to sweep challenge counts");
+}
+
+fn main() {
+    let matches = App::new(stringify!("PoSt Timing"))
+        .version("1.0")
+        .arg(
+            Arg::with_name("challenge-counts")
+                .help("The numbers of challenges per epoch to time, separated by commas")
+                .long("challenge-counts")
+                .default_value("1,2,4,8,16,30")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let challenge_counts: Vec<usize> = matches
+        .value_of("challenge-counts")
+        .unwrap()
+        .split(',')
+        .map(|count| {
+            count
+                .trim()
+                .parse()
+                .expect("challenge counts must be integers")
+        })
+        .collect();
+
+    do_the_work(&challenge_counts);
+}
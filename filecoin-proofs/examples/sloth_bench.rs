@@ -0,0 +1,88 @@
+extern crate pairing;
+extern crate rand;
+#[macro_use]
+extern crate clap;
+#[macro_use]
+extern crate slog;
+
+extern crate filecoin_proofs;
+extern crate storage_proofs;
+
+use clap::{App, Arg};
+use pairing::bls12_381::{Bls12, Fr};
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::time::{Duration, Instant};
+
+use storage_proofs::crypto::sloth;
+
+use filecoin_proofs::FCP_LOG;
+
+fn per_op(elapsed: Duration, samples: usize) -> Duration {
+    elapsed / samples as u32
+}
+
+fn do_the_work(rounds: usize, samples: usize) {
+    let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+    let keys: Vec<Fr> = (0..samples).map(|_| rng.gen()).collect();
+    let plaintexts: Vec<Fr> = (0..samples).map(|_| rng.gen()).collect();
+
+    let start = Instant::now();
+    let ciphertexts: Vec<Fr> = keys
+        .iter()
+        .zip(plaintexts.iter())
+        .map(|(k, p)| sloth::encode::<Bls12>(k, p, rounds))
+        .collect();
+    let encoding_time = per_op(start.elapsed(), samples);
+
+    let start = Instant::now();
+    let decoded: Vec<Fr> = keys
+        .iter()
+        .zip(ciphertexts.iter())
+        .map(|(k, c)| sloth::decode::<Bls12>(k, c, rounds))
+        .collect();
+    let decoding_time = per_op(start.elapsed(), samples);
+
+    assert_eq!(plaintexts, decoded, "sloth roundtrip failed");
+
+    info!(FCP_LOG, "rounds: {}", rounds; "target" => "config");
+    info!(FCP_LOG, "encoding time/node: {:?}", encoding_time; "target" => "stats");
+    info!(FCP_LOG, "decoding time/node: {:?}", decoding_time; "target" => "stats");
+    info!(
+        FCP_LOG,
+        "encoding time/GiB: {:?}",
+        encoding_time * ((1 << 30) / 32); "target" => "stats"
+    );
+}
+
+fn main() {
+    let matches = App::new(stringify!("Sloth Bench"))
+        .version("1.0")
+        .arg(
+            Arg::with_name("rounds")
+                .help("Comma-separated sloth iteration counts to measure")
+                .long("rounds")
+                .default_value("0,1,2,4,8,10")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("samples")
+                .help("The number of nodes to encode and decode per iteration count")
+                .long("samples")
+                .default_value("1000")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let samples = value_t!(matches, "samples", usize).unwrap();
+    let rounds: Vec<usize> = matches
+        .value_of("rounds")
+        .unwrap()
+        .split(',')
+        .map(|r| r.trim().parse().expect("rounds must be integers"))
+        .collect();
+
+    for r in rounds {
+        do_the_work(r, samples);
+    }
+}
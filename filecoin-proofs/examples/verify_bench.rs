@@ -0,0 +1,115 @@
+extern crate clap;
+#[macro_use]
+extern crate slog;
+
+extern crate filecoin_proofs;
+extern crate sector_base;
+extern crate tempfile;
+
+use clap::{App, Arg};
+use std::fs;
+use std::time::{Duration, Instant};
+
+use filecoin_proofs::api::internal::{
+    seal, verify_seal, verify_seal_with_scratch, VerifierScratch,
+};
+use filecoin_proofs::FCP_LOG;
+use sector_base::api::disk_backed_storage::{new_sector_config, ConfiguredStore};
+
+fn per_second(elapsed: Duration, verifications: usize) -> f64 {
+    let secs = elapsed.as_secs() as f64 + f64::from(elapsed.subsec_nanos()) / 1e9;
+
+    verifications as f64 / secs
+}
+
+// Seals a test sector, then verifies its proof single-threaded as many times
+// with verify_seal, and with verify_seal_with_scratch.
+fn do_the_work(verifications: usize) {
+    let config = new_sector_config(&ConfiguredStore::Test);
+    let dir = tempfile::tempdir().expect("failed to create temp dir");
+    let staged_path = dir.path().join("staged");
+    let sealed_path = dir.path().join("sealed");
+    fs::write(&staged_path, &[7; 500]).expect("failed to write staged sector");
+
+    let (prover_id, sector_id) = ([1; 31], [2; 31]);
+    let output = seal(
+        config.as_ref(),
+        &staged_path,
+        &sealed_path,
+        &prover_id,
+        &sector_id,
+    )
+    .expect("failed to seal");
+
+    let verify = || {
+        verify_seal(
+            config.as_ref(),
+            output.comm_r,
+            output.comm_d,
+            output.comm_r_star,
+            &prover_id,
+            &sector_id,
+            &output.proof,
+        )
+        .expect("failed to verify")
+    };
+
+    let mut scratch = VerifierScratch::new();
+    let mut verify_with_scratch = || {
+        verify_seal_with_scratch(
+            &mut scratch,
+            config.as_ref(),
+            output.comm_r,
+            output.comm_d,
+            output.comm_r_star,
+            &prover_id,
+            &sector_id,
+            &output.proof,
+        )
+        .expect("failed to verify")
+    };
+
+    // Sealing verified the proof, so the verifier is cached; warm the scratch.
+    assert!(verify_with_scratch());
+
+    let start = Instant::now();
+    for _ in 0..verifications {
+        assert!(verify());
+    }
+    let plain = per_second(start.elapsed(), verifications);
+
+    let start = Instant::now();
+    for _ in 0..verifications {
+        assert!(verify_with_scratch());
+    }
+    let with_scratch = per_second(start.elapsed(), verifications);
+
+    info!(FCP_LOG, "verifications: {}", verifications; "target" => "config");
+    info!(FCP_LOG, "verify_seal: {:.1} verifications/s", plain; "target" => "stats");
+    info!(
+        FCP_LOG,
+        "verify_seal_with_scratch: {:.1} verifications/s",
+        with_scratch; "target" => "stats"
+    );
+}
+
+fn main() {
+    let matches = App::new(stringify!("Verify Bench"))
+        .version("1.0")
+        .arg(
+            Arg::with_name("verifications")
+                .help("The number of times to verify the seal's proof, each way")
+                .long("verifications")
+                .default_value("100")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let verifications = matches
+        .value_of("verifications")
+        .unwrap()
+        .parse()
+        .expect("verifications must be an integer");
+
+    do_the_work(verifications);
+}
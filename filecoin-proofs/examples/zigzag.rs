@@ -31,6 +31,7 @@ use sapling_crypto::jubjub::JubjubBls12;
 use storage_proofs::circuit::test::*;
 use storage_proofs::circuit::zigzag::{ZigZagCircuit, ZigZagCompound};
 use storage_proofs::compound_proof::{self, CircuitComponent, CompoundProof};
+use storage_proofs::crypto::feistel::FeistelConfig;
 use storage_proofs::drgporep;
 use storage_proofs::drgraph::*;
 use storage_proofs::example_helper::prettyb;
@@ -128,6 +129,7 @@ fn do_the_work<H: 'static>(
                 degree: m,
                 expansion_degree,
                 seed: new_seed(),
+                feistel: FeistelConfig::default(),
             },
             sloth_iter,
         },
@@ -149,6 +151,7 @@ fn do_the_work<H: 'static>(
     stop_profile();
     let pub_inputs = layered_drgporep::PublicInputs::<H::Domain> {
         replica_id,
+        challenge_seed: layered_drgporep::challenge_seed(&replica_id, &tau.comm_r_star),
         tau: Some(tau.simplify().into()),
         comm_r_star: tau.comm_r_star,
         k: Some(0),
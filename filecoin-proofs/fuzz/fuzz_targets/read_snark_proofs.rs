@@ -0,0 +1,8 @@
+#![no_main]
+#[macro_use]
+extern crate libfuzzer_sys;
+extern crate filecoin_proofs;
+
+fuzz_target!(|data: &[u8]| {
+    filecoin_proofs::api::fuzzing::read_snark_seal_proof(data);
+});
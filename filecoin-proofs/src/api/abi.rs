@@ -0,0 +1,518 @@
+// Layout assertions for every type exported through the C API.
+//
+// Go (and other) consumers mirror these types field-for-field, so a change to
+// the size, alignment or field offsets of any of them is an ABI break. The
+// sizes and alignments below are checked at compile time; field offsets are
+// checked by the tests at the bottom of this file. If one of these fails, the
+// change is an ABI change: update the numbers here, regenerate the golden
+// header (see tests/c_header.rs) and make sure consumers are updated too.
+//
+// The numbers are those of a 64-bit target.
+
+#![cfg(target_pointer_width = "64")]
+
+use crate::api::estimate::HasherKind;
+use crate::api::responses::*;
+use crate::api::sector_builder::metadata::DuplicatePiecePolicy;
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use sector_base::api::object_backed_storage::FFIObjectStoreConfig;
+use storage_proofs::types::{Commitment, FrSafe};
+
+// Fails to compile (with a mismatched array length) if the size or alignment
+// of the type differs from the expected value.
+macro_rules! assert_layout {
+    ($t:ty, size = $size:expr, align = $align:expr) => {
+        let _: [(); $size] = [(); ::std::mem::size_of::<$t>()];
+        let _: [(); $align] = [(); ::std::mem::align_of::<$t>()];
+    };
+}
+
+#[allow(dead_code)]
+fn layout_assertions() {
+    assert_layout!(FCPResponseStatus, size = 4, align = 4);
+    assert_layout!(FFISealStatus, size = 4, align = 4);
+    assert_layout!(FFIMigratedPieceStatus, size = 4, align = 4);
+    assert_layout!(FFIPoSTFailureKind, size = 4, align = 4);
+    assert_layout!(ConfiguredStore, size = 4, align = 4);
+    assert_layout!(HasherKind, size = 4, align = 4);
+    assert_layout!(DuplicatePiecePolicy, size = 4, align = 4);
+
+    // The FFI structs hold these as bare arrays.
+    assert_layout!(Commitment, size = 32, align = 1);
+    assert_layout!(FrSafe, size = 31, align = 1);
+
+    assert_layout!(FFIPieceMetadata, size = 16, align = 8);
+    assert_layout!(FFIStagedSectorMetadata, size = 48, align = 8);
+    assert_layout!(FFISealedSectorMetadata, size = 512, align = 8);
+    assert_layout!(FFIMigratedPiece, size = 24, align = 8);
+    assert_layout!(FFIExpiringPiece, size = 24, align = 8);
+    assert_layout!(FFIUnsealedShard, size = 16, align = 8);
+    assert_layout!(FFIPieceAssignment, size = 32, align = 8);
+    assert_layout!(FFIAuditRecord, size = 16, align = 8);
+    assert_layout!(FFIParameterInfo, size = 24, align = 8);
+    assert_layout!(FFIObjectStoreConfig, size = 72, align = 8);
+    assert_layout!(FFIProofVersion, size = 16, align = 8);
+    assert_layout!(FFISealJob, size = 32, align = 8);
+    assert_layout!(FFIPoSTFailure, size = 56, align = 8);
+
+    assert_layout!(InitResponse, size = 48, align = 8);
+    assert_layout!(VerifySealResponse, size = 24, align = 8);
+    assert_layout!(VerifySealsBatchResponse, size = 48, align = 8);
+    assert_layout!(GeneratePoSTResponse, size = 224, align = 8);
+    assert_layout!(VerifyPoSTResponse, size = 24, align = 8);
+    assert_layout!(VerifyPoSTDetailedResponse, size = 40, align = 8);
+    assert_layout!(EstimateSealResourcesResponse, size = 48, align = 8);
+    assert_layout!(GetEffectiveParallelismResponse, size = 48, align = 8);
+    assert_layout!(GenerateParametersResponse, size = 16, align = 8);
+    assert_layout!(InstallParametersResponse, size = 16, align = 8);
+    assert_layout!(ListInstalledParametersResponse, size = 32, align = 8);
+    assert_layout!(RemoveParametersResponse, size = 16, align = 8);
+    assert_layout!(DeriveReplicaIdResponse, size = 48, align = 8);
+    assert_layout!(GetPublicInputsForSealResponse, size = 48, align = 8);
+    assert_layout!(PrefetchSealRequirementsResponse, size = 48, align = 8);
+    assert_layout!(ProveFromBundleResponse, size = 496, align = 8);
+    assert_layout!(ProverIdFrom32BytesResponse, size = 48, align = 8);
+    assert_layout!(CommitmentToStringResponse, size = 24, align = 8);
+    assert_layout!(CommitmentFromStringResponse, size = 48, align = 8);
+    assert_layout!(InitSectorBuilderResponse, size = 24, align = 8);
+    assert_layout!(AddPieceResponse, size = 32, align = 8);
+    assert_layout!(PlanPiecePlacementResponse, size = 48, align = 8);
+    assert_layout!(AddPiecesPlannedResponse, size = 32, align = 8);
+    assert_layout!(ExportAuditLogResponse, size = 40, align = 8);
+    assert_layout!(ReadPieceFromSealedSectorResponse, size = 32, align = 8);
+    assert_layout!(SealAllStagedSectorsResponse, size = 16, align = 8);
+    assert_layout!(SealSectorResponse, size = 520, align = 8);
+    assert_layout!(GetMaxStagedBytesPerSector, size = 24, align = 8);
+    assert_layout!(GetSealAdmissionsResponse, size = 88, align = 8);
+    assert_layout!(GetSealStatusResponse, size = 544, align = 8);
+    assert_layout!(GetSealedSectorsResponse, size = 48, align = 8);
+    assert_layout!(GetStagedSectorsResponse, size = 48, align = 8);
+    assert_layout!(MigrateSectorsResponse, size = 40, align = 8);
+    assert_layout!(GetExpiredSectorsResponse, size = 32, align = 8);
+    assert_layout!(GetExpiringPiecesResponse, size = 32, align = 8);
+    assert_layout!(GetUnsealedRangeShardedResponse, size = 32, align = 8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Asserts the offset of each named field, in declaration order. Offsets
+    // are measured on a zeroed value, which is a valid one of every type here
+    // (null pointers, zero lengths and each enum's first variant), rather than
+    // an uninitialized one; it's never dropped, since it owns nothing.
+    macro_rules! assert_offsets {
+        ($t:ident { $($field:ident: $offset:expr),* $(,)* }) => {
+            let value = ::std::mem::ManuallyDrop::new(unsafe { ::std::mem::zeroed::<$t>() });
+            let base = &*value as *const $t as usize;
+            $(
+                assert_eq!(
+                    $offset,
+                    &value.$field as *const _ as usize - base,
+                    "offset of {}::{} changed",
+                    stringify!($t),
+                    stringify!($field)
+                );
+            )*
+        };
+    }
+
+    #[test]
+    fn enum_discriminants() {
+        assert_eq!(0, FCPResponseStatus::FCPNoError as u32);
+        assert_eq!(1, FCPResponseStatus::FCPUnclassifiedError as u32);
+        assert_eq!(2, FCPResponseStatus::FCPCallerError as u32);
+        assert_eq!(3, FCPResponseStatus::FCPReceiverError as u32);
+        assert_eq!(4, FCPResponseStatus::FCPTimeout as u32);
+        assert_eq!(5, FCPResponseStatus::FCPIncompatibleAbi as u32);
+
+        assert_eq!(0, FFISealStatus::Sealed as u32);
+        assert_eq!(1, FFISealStatus::Pending as u32);
+        assert_eq!(2, FFISealStatus::Failed as u32);
+        assert_eq!(3, FFISealStatus::Sealing as u32);
+
+        assert_eq!(0, FFIMigratedPieceStatus::Verified as u32);
+        assert_eq!(1, FFIMigratedPieceStatus::Unverified as u32);
+
+        assert_eq!(0, FFIPoSTFailureKind::PathMismatch as u32);
+        assert_eq!(1, FFIPoSTFailureKind::LeafMismatch as u32);
+        assert_eq!(2, FFIPoSTFailureKind::MissingProof as u32);
+        assert_eq!(3, FFIPoSTFailureKind::FaultSetInconsistency as u32);
+
+        assert_eq!(0, ConfiguredStore::Live as u32);
+        assert_eq!(1, ConfiguredStore::Test as u32);
+        assert_eq!(2, ConfiguredStore::LargeTest as u32);
+
+        assert_eq!(0, HasherKind::Pedersen as u32);
+        assert_eq!(1, HasherKind::Sha256 as u32);
+        assert_eq!(2, HasherKind::Blake2s as u32);
+
+        assert_eq!(0, DuplicatePiecePolicy::Allow as u32);
+        assert_eq!(1, DuplicatePiecePolicy::Reject as u32);
+        assert_eq!(2, DuplicatePiecePolicy::Deduplicate as u32);
+    }
+
+    #[test]
+    fn metadata_offsets() {
+        assert_offsets!(FFIPieceMetadata {
+            piece_key: 0,
+            num_bytes: 8,
+        });
+
+        assert_offsets!(FFIStagedSectorMetadata {
+            sector_access: 0,
+            sector_id: 8,
+            pieces_len: 16,
+            pieces_ptr: 24,
+            seal_status_code: 32,
+            seal_error_msg: 40,
+        });
+
+        assert_offsets!(FFISealedSectorMetadata {
+            comm_d: 0,
+            comm_r: 32,
+            comm_r_star: 64,
+            sector_access: 96,
+            sector_id: 104,
+            snark_proof: 112,
+            pieces_len: 496,
+            pieces_ptr: 504,
+        });
+
+        assert_offsets!(FFIMigratedPiece {
+            piece_key: 0,
+            source_sector_id: 8,
+            status: 16,
+        });
+        assert_offsets!(FFIExpiringPiece {
+            piece_key: 0,
+            sector_id: 8,
+            expires_at: 16,
+        });
+        assert_offsets!(FFIUnsealedShard {
+            path: 0,
+            num_bytes: 8,
+        });
+        assert_offsets!(FFIPieceAssignment {
+            piece_key: 0,
+            num_bytes: 8,
+            sector_id: 16,
+            new_sector: 24,
+        });
+        assert_offsets!(FFIAuditRecord {
+            record_len: 0,
+            record_ptr: 8,
+        });
+        assert_offsets!(FFIParameterInfo {
+            identifier: 0,
+            size: 8,
+            digest: 16,
+        });
+        assert_offsets!(FFIObjectStoreConfig {
+            endpoint: 0,
+            bucket: 8,
+            region: 16,
+            access_key_id: 24,
+            secret_access_key: 32,
+            key_prefix: 40,
+            staging_dir_path: 48,
+            cache_dir_path: 56,
+            cache_bytes: 64,
+        });
+        assert_offsets!(FFIProofVersion {
+            version_code: 0,
+            max_sector_bytes: 8,
+        });
+        assert_offsets!(FFISealJob {
+            sector_id: 0,
+            memory_bytes: 8,
+            admitted_at_millis: 16,
+            finished_at_millis: 24,
+        });
+
+        assert_offsets!(FFIPoSTFailure {
+            sector_index: 0,
+            comm_r: 8,
+            challenge_index: 40,
+            kind: 48,
+        });
+    }
+
+    #[test]
+    fn response_offsets() {
+        assert_offsets!(InitResponse {
+            status_code: 0,
+            error_msg: 8,
+            abi_version: 16,
+            library_version: 24,
+            proof_versions_len: 32,
+            proof_versions_ptr: 40,
+        });
+
+        assert_offsets!(VerifySealResponse {
+            status_code: 0,
+            error_msg: 8,
+            is_valid: 16,
+        });
+
+        assert_offsets!(VerifySealsBatchResponse {
+            status_code: 0,
+            error_msg: 8,
+            items_len: 16,
+            item_status_codes_ptr: 24,
+            item_error_msgs_ptr: 32,
+            is_valid_ptr: 40,
+        });
+
+        assert_offsets!(GeneratePoSTResponse {
+            status_code: 0,
+            error_msg: 8,
+            faults_len: 16,
+            faults_ptr: 24,
+            proof: 32,
+        });
+
+        assert_offsets!(VerifyPoSTResponse {
+            status_code: 0,
+            error_msg: 8,
+            is_valid: 16,
+        });
+
+        assert_offsets!(VerifyPoSTDetailedResponse {
+            status_code: 0,
+            error_msg: 8,
+            is_valid: 16,
+            failures_len: 24,
+            failures_ptr: 32,
+        });
+
+        assert_offsets!(EstimateSealResourcesResponse {
+            status_code: 0,
+            error_msg: 8,
+            encoding_millis: 16,
+            tree_building_millis: 24,
+            proving_millis: 32,
+            peak_memory_bytes: 40,
+        });
+
+        assert_offsets!(GetEffectiveParallelismResponse {
+            status_code: 0,
+            error_msg: 8,
+            effective: 16,
+            available: 24,
+            quota_cpus: 32,
+            cpuset_cpus: 40,
+        });
+
+        assert_offsets!(GenerateParametersResponse {
+            status_code: 0,
+            error_msg: 8,
+        });
+
+        assert_offsets!(InstallParametersResponse {
+            status_code: 0,
+            error_msg: 8,
+        });
+
+        assert_offsets!(ListInstalledParametersResponse {
+            status_code: 0,
+            error_msg: 8,
+            parameters_len: 16,
+            parameters_ptr: 24,
+        });
+
+        assert_offsets!(RemoveParametersResponse {
+            status_code: 0,
+            error_msg: 8,
+        });
+
+        assert_offsets!(DeriveReplicaIdResponse {
+            status_code: 0,
+            error_msg: 8,
+            replica_id: 16,
+        });
+
+        assert_offsets!(GetPublicInputsForSealResponse {
+            status_code: 0,
+            error_msg: 8,
+            layout_version: 16,
+            partitions: 24,
+            flattened_inputs_len: 32,
+            flattened_inputs_ptr: 40,
+        });
+
+        assert_offsets!(ProverIdFrom32BytesResponse {
+            status_code: 0,
+            error_msg: 8,
+            prover_id: 16,
+        });
+
+        assert_offsets!(CommitmentToStringResponse {
+            status_code: 0,
+            error_msg: 8,
+            commitment_string: 16,
+        });
+
+        assert_offsets!(CommitmentFromStringResponse {
+            status_code: 0,
+            error_msg: 8,
+            commitment: 16,
+        });
+
+        assert_offsets!(PrefetchSealRequirementsResponse {
+            status_code: 0,
+            error_msg: 8,
+            directories_millis: 16,
+            parameters_millis: 24,
+            graph_millis: 32,
+            directories_cache_hit: 40,
+            parameters_cache_hit: 41,
+            graph_cache_hit: 42,
+        });
+
+        assert_offsets!(ProveFromBundleResponse {
+            status_code: 0,
+            error_msg: 8,
+            comm_d: 16,
+            comm_r: 48,
+            comm_r_star: 80,
+            snark_proof: 112,
+        });
+
+        assert_offsets!(InitSectorBuilderResponse {
+            status_code: 0,
+            error_msg: 8,
+            sector_builder: 16,
+        });
+
+        assert_offsets!(AddPieceResponse {
+            status_code: 0,
+            error_msg: 8,
+            sector_id: 16,
+            duplicate: 24,
+        });
+
+        assert_offsets!(ReadPieceFromSealedSectorResponse {
+            status_code: 0,
+            error_msg: 8,
+            data_len: 16,
+            data_ptr: 24,
+        });
+
+        assert_offsets!(SealAllStagedSectorsResponse {
+            status_code: 0,
+            error_msg: 8,
+        });
+
+        assert_offsets!(SealSectorResponse {
+            status_code: 0,
+            error_msg: 8,
+            cached: 16,
+            comm_d: 17,
+            comm_r: 49,
+            comm_r_star: 81,
+            sector_access: 120,
+            sector_id: 128,
+            snark_proof: 136,
+        });
+
+        assert_offsets!(GetMaxStagedBytesPerSector {
+            status_code: 0,
+            error_msg: 8,
+            max_staged_bytes_per_sector: 16,
+        });
+
+        assert_offsets!(GetSealStatusResponse {
+            status_code: 0,
+            error_msg: 8,
+            seal_status_code: 16,
+            seal_error_msg: 24,
+            comm_d: 32,
+            comm_r: 64,
+            comm_r_star: 96,
+            sector_access: 128,
+            sector_id: 136,
+            snark_proof: 144,
+            pieces_len: 528,
+            pieces_ptr: 536,
+        });
+
+        assert_offsets!(GetSealedSectorsResponse {
+            status_code: 0,
+            error_msg: 8,
+            sectors_len: 16,
+            sectors_ptr: 24,
+            item_status_codes_ptr: 32,
+            item_error_msgs_ptr: 40,
+        });
+
+        assert_offsets!(GetStagedSectorsResponse {
+            status_code: 0,
+            error_msg: 8,
+            sectors_len: 16,
+            sectors_ptr: 24,
+            item_status_codes_ptr: 32,
+            item_error_msgs_ptr: 40,
+        });
+
+        assert_offsets!(MigrateSectorsResponse {
+            status_code: 0,
+            error_msg: 8,
+            sector_id: 16,
+            pieces_len: 24,
+            pieces_ptr: 32,
+        });
+        assert_offsets!(GetExpiredSectorsResponse {
+            status_code: 0,
+            error_msg: 8,
+            sector_ids_len: 16,
+            sector_ids_ptr: 24,
+        });
+        assert_offsets!(GetExpiringPiecesResponse {
+            status_code: 0,
+            error_msg: 8,
+            pieces_len: 16,
+            pieces_ptr: 24,
+        });
+        assert_offsets!(GetSealAdmissionsResponse {
+            status_code: 0,
+            error_msg: 8,
+            running_len: 16,
+            running_ptr: 24,
+            queued_len: 32,
+            queued_ptr: 40,
+            finished_len: 48,
+            finished_ptr: 56,
+            memory_budget_bytes: 64,
+            memory_in_use_bytes: 72,
+            max_running: 80,
+        });
+        assert_offsets!(GetUnsealedRangeShardedResponse {
+            status_code: 0,
+            error_msg: 8,
+            shards_len: 16,
+            shards_ptr: 24,
+        });
+        assert_offsets!(PlanPiecePlacementResponse {
+            status_code: 0,
+            error_msg: 8,
+            new_sectors: 16,
+            wasted_bytes: 24,
+            assignments_len: 32,
+            assignments_ptr: 40,
+        });
+        assert_offsets!(AddPiecesPlannedResponse {
+            status_code: 0,
+            error_msg: 8,
+            sector_ids_len: 16,
+            sector_ids_ptr: 24,
+        });
+        assert_offsets!(ExportAuditLogResponse {
+            status_code: 0,
+            error_msg: 8,
+            records_len: 16,
+            records_ptr: 24,
+            records_lost: 32,
+        });
+    }
+}
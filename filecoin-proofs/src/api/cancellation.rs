@@ -0,0 +1,199 @@
+//! Cooperative cancellation of long-running operations. An operation given a
+//! CancellationToken checks it at checkpoints (between the steps of the
+//! operation, and between the chunks of its reads) and, once the token has been
+//! cancelled or its timeout has passed, unwinds from there with an Interrupted
+//! error naming the step it was in, cleaning up its partial outputs as it would
+//! on any other error.
+//!
+//! Nothing is interrupted between checkpoints: a step runs to its end before
+//! the operation notices it's to stop. In particular, a read which hangs (e.g.
+//! on an unresponsive network file system) holds the operation until it
+//! returns. Reads are made in chunks of READ_CHUNK_BYTES so that only a chunk
+//! which hangs does.
+//!
+//! The host's yield hook (see storage_proofs::yield_hook, and set_yield_hook)
+//! is called at every checkpoint, besides within the steps which replicate,
+//! unseal and prove. Should it ask the operation to stop, the operation's token
+//! is cancelled, and the operation fails as it would had it been cancelled
+//! through the token.
+
+use std::io::Read;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use storage_proofs::error::Error as StorageProofsError;
+use storage_proofs::yield_hook;
+
+use crate::error;
+
+/// The most bytes read between two checkpoints.
+pub const READ_CHUNK_BYTES: usize = 1 << 20;
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum Interrupted {
+    #[fail(display = "cancelled while {}", phase)]
+    Cancelled { phase: String },
+
+    #[fail(display = "timed out after {:?} while {}", timeout, phase)]
+    TimedOut { phase: String, timeout: Duration },
+}
+
+/// A token an operation checks at its checkpoints, which may be cancelled from
+/// another thread through any of its clones, and which trips by itself once its
+/// timeout (if it has one) has passed.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+    timeout: Option<(Instant, Duration)>,
+}
+
+impl CancellationToken {
+    /// A token which is only ever cancelled.
+    pub fn new() -> CancellationToken {
+        Default::default()
+    }
+
+    /// A token which trips once timeout has passed from now.
+    pub fn with_timeout(timeout: Duration) -> CancellationToken {
+        CancellationToken {
+            cancelled: Default::default(),
+            timeout: Some((Instant::now(), timeout)),
+        }
+    }
+
+    /// A token with a timeout of timeout_seconds, or none if it's 0, as the C
+    /// API takes timeouts.
+    pub fn with_timeout_seconds(timeout_seconds: u64) -> CancellationToken {
+        match timeout_seconds {
+            0 => CancellationToken::new(),
+            n => CancellationToken::with_timeout(Duration::from_secs(n)),
+        }
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    /// Fails if the token has been cancelled or has timed out, naming the
+    /// phase the operation checking it is in. Calls the yield hook first,
+    /// cancelling the token if it asks the operation to stop.
+    pub fn checkpoint(&self, phase: &str) -> Result<(), Interrupted> {
+        if yield_hook::yield_now(phase).is_err() {
+            self.cancel();
+        }
+
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(Interrupted::Cancelled {
+                phase: phase.to_string(),
+            });
+        }
+
+        match self.timeout {
+            Some((start, timeout)) if start.elapsed() >= timeout => Err(Interrupted::TimedOut {
+                phase: phase.to_string(),
+                timeout,
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// Passes on the result of a step which calls the yield hook, cancelling
+    /// the token if the hook stopped the step, whose error is then the one the
+    /// cancelled token gives.
+    pub fn yielded<T>(&self, result: storage_proofs::error::Result<T>) -> error::Result<T> {
+        match result {
+            Err(StorageProofsError::YieldCancelled { phase }) => {
+                self.cancel();
+                Err(Interrupted::Cancelled { phase }.into())
+            }
+            result => Ok(result?),
+        }
+    }
+}
+
+/// Reads up to limit bytes from source, as Read::take and read_to_end would,
+/// but in chunks, checking the token before each.
+pub fn read_to_end_checked<R: Read>(
+    source: R,
+    limit: u64,
+    token: &CancellationToken,
+    phase: &str,
+) -> error::Result<Vec<u8>> {
+    let mut source = source.take(limit);
+    let mut bytes = Vec::with_capacity(limit as usize);
+    let mut chunk = vec![0; READ_CHUNK_BYTES];
+
+    loop {
+        token.checkpoint(phase)?;
+
+        match source.read(&mut chunk)? {
+            0 => return Ok(bytes),
+            n => bytes.extend_from_slice(&chunk[..n]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+    use std::thread;
+
+    // Reads a byte at a time, sleeping before each.
+    struct SlowReader(Duration);
+
+    impl Read for SlowReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(self.0);
+            buf[0] = 7;
+
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn tokens_trip_once_cancelled_or_timed_out() {
+        let token = CancellationToken::new();
+        assert_eq!(Ok(()), token.checkpoint("waiting"));
+
+        token.clone().cancel();
+        assert_eq!(
+            Err(Interrupted::Cancelled {
+                phase: "waiting".to_string()
+            }),
+            token.checkpoint("waiting")
+        );
+
+        assert_eq!(
+            Ok(()),
+            CancellationToken::with_timeout_seconds(0).checkpoint("waiting")
+        );
+
+        let token = CancellationToken::with_timeout(Duration::from_millis(10));
+        thread::sleep(Duration::from_millis(20));
+        match token.checkpoint("waiting") {
+            Err(Interrupted::TimedOut { phase, .. }) => assert_eq!("waiting", phase),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_are_interrupted_between_chunks() {
+        let token = CancellationToken::with_timeout(Duration::from_millis(200));
+        let start = Instant::now();
+
+        // Unchecked, this read would take 10 seconds.
+        let source = SlowReader(Duration::from_millis(100));
+        let result = read_to_end_checked(source, 100, &token, "reading");
+
+        match result.map_err(|err| err.downcast::<Interrupted>()) {
+            Err(Ok(Interrupted::TimedOut { phase, .. })) => assert_eq!("reading", phase),
+            other => panic!("unexpected result: {:?}", other.map(|bytes| bytes.len())),
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        let bytes = read_to_end_checked(&[7; 10][..], 5, &CancellationToken::new(), "reading");
+        assert_eq!(vec![7; 5], bytes.unwrap());
+    }
+}
@@ -0,0 +1,362 @@
+use std::cmp;
+use std::io;
+use std::time::{Duration, Instant};
+
+use pairing::bls12_381::Bls12;
+use sector_base::api::disk_backed_storage::check_sector_bytes;
+use storage_proofs::circuit::zigzag::{ZigZagCircuit, ZigZagCompound};
+use storage_proofs::compound_proof::{self, CompoundProof};
+use storage_proofs::crypto::feistel::FeistelConfig;
+use storage_proofs::drgporep::{self, DrgParams};
+use storage_proofs::drgraph::{graph_height, Graph};
+use storage_proofs::hasher::{Blake2sHasher, Hasher, PedersenHasher, Sha256Hasher};
+use storage_proofs::layered_drgporep::{self, LayerChallenges, Layers};
+use storage_proofs::porep::{replica_id, PoRep};
+use storage_proofs::vde;
+use storage_proofs::zigzag_drgporep::ZigZagDrgPoRep;
+
+use crate::api::internal::{challenge_count, ENGINE_PARAMS, POREP_PARTITIONS};
+use crate::api::parallelism;
+use crate::api::proof_params::{proof_params, ProofVersion, DRG_SEED};
+use crate::error;
+
+/// Size, in bytes, of the instance sealed to calibrate an estimate.
+const CALIBRATION_SECTOR_BYTES: u64 = 64 * 1024;
+
+/// The hash function used to encode the sector and build its merkle trees.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HasherKind {
+    Pedersen = 0,
+    Sha256 = 1,
+    Blake2s = 2,
+}
+
+/// What was measured while sealing the calibration instance.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Calibration {
+    /// Number of nodes in the calibration instance.
+    pub nodes: usize,
+    /// Time to encode every layer.
+    pub encoding: Duration,
+    /// Time to build the merkle tree of every layer and of the replica.
+    pub tree_building: Duration,
+    /// Time to generate the SNARK, with the groth parameters in memory.
+    pub proving: Duration,
+    /// Size of the groth parameters, in bytes.
+    pub params_bytes: u64,
+}
+
+/// Estimated wall time of each phase of sealing, and the estimated peak
+/// memory needed to seal.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SealEstimate {
+    pub encoding: Duration,
+    pub tree_building: Duration,
+    pub proving: Duration,
+    pub peak_memory_bytes: u64,
+}
+
+impl SealEstimate {
+    pub fn total(&self) -> Duration {
+        self.encoding + self.tree_building + self.proving
+    }
+}
+
+/// Estimates how long sealing a sector of `sector_bytes` bytes with the given
+/// parameters will take on this machine, and how much memory it will need.
+///
+/// The estimate is extrapolated from a real seal of a small instance (64KiB,
+/// or the sector itself if it's smaller) with the same parameters, so this
+/// takes a while and, the first time it's called with a set of parameters,
+/// generates and caches groth parameters for the small instance.
+pub fn estimate_seal_resources(
+    sector_bytes: u64,
+    layers: usize,
+    degree: usize,
+    expansion_degree: usize,
+    hasher: HasherKind,
+) -> error::Result<SealEstimate> {
+    check_sector_bytes(sector_bytes)?;
+
+    if layers == 0 {
+        return Err(format_err!("layers must be non-zero"));
+    }
+
+    // There's no point calibrating on an instance larger than the sector.
+    let calibration_bytes = cmp::min(CALIBRATION_SECTOR_BYTES, sector_bytes) as usize;
+
+    // The calibration proves as many challenges as the sector would.
+    let challenges = challenge_count(sector_bytes);
+
+    // The calibration runs on as many threads as the sector's seal will, so
+    // that on a machine whose CPUs aren't all the process's it isn't measured
+    // running faster than the seal can.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(parallelism::get_effective_parallelism())
+        .build()
+        .map_err(|err| format_err!("failed to start the calibration's threads: {}", err))?;
+
+    let calibration = pool.install(|| match hasher {
+        HasherKind::Pedersen => calibrate::<PedersenHasher>(
+            calibration_bytes,
+            layers,
+            degree,
+            expansion_degree,
+            challenges,
+        ),
+        HasherKind::Sha256 => calibrate::<Sha256Hasher>(
+            calibration_bytes,
+            layers,
+            degree,
+            expansion_degree,
+            challenges,
+        ),
+        HasherKind::Blake2s => calibrate::<Blake2sHasher>(
+            calibration_bytes,
+            layers,
+            degree,
+            expansion_degree,
+            challenges,
+        ),
+    })?;
+
+    Ok(extrapolate(&calibration, sector_bytes, layers))
+}
+
+/// Extrapolates the cost of sealing a sector of `sector_bytes` bytes with
+/// `layers` layers from a calibration made with the same parameters.
+///
+/// * Encoding visits every node of every layer once, and building a merkle
+///   tree hashes every node once, so both scale linearly with the number of
+///   nodes.
+/// * The circuit proves the sector's number of challenges, which the
+///   calibration shares, each of which opens merkle paths as long as the
+///   height of the tree, so proving time and the size of the groth parameters
+///   scale with log2 of the number of nodes.
+/// * At peak, the sealer holds the sector, a copy of it for each tree built
+///   in the background (one per layer plus the replica's), all of those
+///   trees (2 * nodes - 1 hashes of 32 bytes each) and the groth parameters.
+///   The copies are dropped as the trees are finished, so this is an upper
+///   bound.
+pub fn extrapolate(calibration: &Calibration, sector_bytes: u64, layers: usize) -> SealEstimate {
+    let nodes = sector_bytes / 32;
+    let linear = nodes as f64 / calibration.nodes as f64;
+    let logarithmic = tree_height(nodes as usize) / tree_height(calibration.nodes);
+
+    let params_bytes = (calibration.params_bytes as f64 * logarithmic).round() as u64;
+
+    SealEstimate {
+        encoding: scale(calibration.encoding, linear),
+        tree_building: scale(calibration.tree_building, linear),
+        proving: scale(calibration.proving, logarithmic),
+        peak_memory_bytes: seal_working_memory_bytes(sector_bytes, layers) + params_bytes,
+    }
+}
+
+/// The peak memory sealing a sector of `sector_bytes` bytes with `layers`
+/// layers needs but for the groth parameters: the sector, its copies and
+/// their trees (see `extrapolate`). Unlike the groth parameters, whose size
+/// only a calibration tells, it's known without sealing anything.
+pub fn seal_working_memory_bytes(sector_bytes: u64, layers: usize) -> u64 {
+    let nodes = sector_bytes / 32;
+    let trees = layers as u64 + 1;
+    let tree_bytes = (2 * nodes - 1) * 32;
+
+    sector_bytes + trees * sector_bytes + trees * tree_bytes
+}
+
+// The height of a tree is never taken to be less than 1, so a one-node
+// calibration doesn't divide by zero.
+fn tree_height(nodes: usize) -> f64 {
+    cmp::max(graph_height(nodes), 1) as f64
+}
+
+fn scale(duration: Duration, factor: f64) -> Duration {
+    let nanos = (duration.as_secs() as f64 * 1e9 + f64::from(duration.subsec_nanos())) * factor;
+
+    Duration::new((nanos / 1e9) as u64, (nanos % 1e9) as u32)
+}
+
+/// An io::Write which discards everything written to it, counting the bytes.
+struct ByteCounter(u64);
+
+impl io::Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Seals a `sector_bytes` instance with the given parameters, measuring each
+/// phase separately.
+fn calibrate<H: 'static + Hasher>(
+    sector_bytes: usize,
+    layers: usize,
+    degree: usize,
+    expansion_degree: usize,
+    challenges: usize,
+) -> error::Result<Calibration> {
+    let nodes = sector_bytes / 32;
+    let params = proof_params(ProofVersion::CURRENT);
+
+    let setup_params = layered_drgporep::SetupParams {
+        drg_porep_setup_params: drgporep::SetupParams {
+            drg: DrgParams {
+                nodes,
+                degree,
+                expansion_degree,
+                seed: DRG_SEED,
+                feistel: FeistelConfig::default(),
+            },
+            sloth_iter: params.sloth_iter,
+        },
+        layer_challenges: LayerChallenges::new_tapered(
+            layers,
+            challenges,
+            params.taper_layers,
+            params.taper,
+        ),
+    };
+
+    let compound_setup_params = compound_proof::SetupParams {
+        vanilla_params: &setup_params,
+        engine_params: &(*ENGINE_PARAMS),
+        partitions: Some(POREP_PARTITIONS),
+    };
+
+    let compound_public_params = <ZigZagCompound as CompoundProof<
+        Bls12,
+        ZigZagDrgPoRep<H>,
+        ZigZagCircuit<Bls12, H>,
+    >>::setup(&compound_setup_params)?;
+
+    let pp = &compound_public_params.vanilla_params;
+    let replica_id = replica_id::<H>([0; 32], [0; 32]);
+    let data = vec![0; sector_bytes];
+
+    // Encode each layer in turn, as replicate does.
+    let mut encoded = data.clone();
+    let mut drgpp = pp.drg_porep_public_params.clone();
+
+    let start = Instant::now();
+    for layer in 0..layers {
+        vde::encode(&drgpp.graph, drgpp.sloth_iter, &replica_id, &mut encoded)?;
+        drgpp = <ZigZagDrgPoRep<H> as Layers>::transform(&drgpp, layer, layers);
+    }
+    let encoding = start.elapsed();
+
+    // Every tree costs the same to build, so build one.
+    let start = Instant::now();
+    pp.drg_porep_public_params.graph.merkle_tree(&encoded)?;
+    let tree_building = start.elapsed() * (layers as u32 + 1);
+
+    let mut replica = data.clone();
+    let (tau, aux) = ZigZagDrgPoRep::<H>::replicate(pp, &replica_id, &mut replica, None)?;
+
+    let public_inputs = layered_drgporep::PublicInputs {
+        replica_id,
+        challenge_seed: layered_drgporep::challenge_seed(&replica_id, &tau.comm_r_star),
+        tau: Some(tau.simplify()),
+        comm_r_star: tau.comm_r_star,
+        k: None,
+    };
+
+    let private_inputs = layered_drgporep::PrivateInputs::<H> {
+        aux,
+        tau: tau.layer_taus,
+    };
+
+    let groth_params = <ZigZagCompound as CompoundProof<
+        Bls12,
+        ZigZagDrgPoRep<H>,
+        ZigZagCircuit<Bls12, H>,
+    >>::groth_params(pp, &ENGINE_PARAMS)?;
+
+    let mut params_bytes = ByteCounter(0);
+    groth_params.write(&mut params_bytes)?;
+
+    let start = Instant::now();
+    <ZigZagCompound as CompoundProof<Bls12, ZigZagDrgPoRep<H>, ZigZagCircuit<Bls12, H>>>::prove(
+        &compound_public_params,
+        &public_inputs,
+        &private_inputs,
+        Some(groth_params),
+    )?;
+    let proving = start.elapsed();
+
+    Ok(Calibration {
+        nodes,
+        encoding,
+        tree_building,
+        proving,
+        params_bytes: params_bytes.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calibration() -> Calibration {
+        Calibration {
+            nodes: 1 << 10,
+            encoding: Duration::from_millis(1),
+            tree_building: Duration::from_millis(2),
+            proving: Duration::from_secs(10),
+            params_bytes: 1000,
+        }
+    }
+
+    #[test]
+    fn extrapolates_linear_and_logarithmic_phases() {
+        // 2^20 nodes: 1024 times the nodes, twice the tree height.
+        let estimate = extrapolate(&calibration(), 32 << 20, 4);
+
+        assert_eq!(Duration::from_millis(1024), estimate.encoding);
+        assert_eq!(Duration::from_millis(2048), estimate.tree_building);
+        assert_eq!(Duration::from_secs(20), estimate.proving);
+        assert_eq!(Duration::from_millis(23072), estimate.total());
+    }
+
+    #[test]
+    fn extrapolates_peak_memory() {
+        let sector_bytes = 32 << 20;
+        let estimate = extrapolate(&calibration(), sector_bytes, 4);
+
+        let copies = 5 * sector_bytes;
+        let trees = 5 * ((2 << 20) - 1) * 32;
+        let params = 2000;
+
+        assert_eq!(
+            sector_bytes + copies + trees + params,
+            estimate.peak_memory_bytes
+        );
+        assert_eq!(536_872_752, estimate.peak_memory_bytes);
+    }
+
+    #[test]
+    fn calibration_size_is_unchanged() {
+        let c = calibration();
+        let estimate = extrapolate(&c, 32 << 10, 4);
+
+        assert_eq!(c.encoding, estimate.encoding);
+        assert_eq!(c.tree_building, estimate.tree_building);
+        assert_eq!(c.proving, estimate.proving);
+    }
+
+    #[test]
+    fn rejects_invalid_sector_sizes() {
+        for sector_bytes in &[0, 33, 1000, 1536] {
+            assert!(
+                estimate_seal_resources(*sector_bytes, 4, 5, 8, HasherKind::Pedersen).is_err()
+            );
+        }
+
+        assert!(estimate_seal_resources(1024, 0, 5, 8, HasherKind::Pedersen).is_err());
+    }
+}
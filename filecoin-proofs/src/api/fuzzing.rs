@@ -0,0 +1,121 @@
+//! Feeds arbitrary bytes to the entry points which take them from elsewhere,
+//! for the fuzz targets in fuzz/ and for replaying what those have found.
+//! Proofs arrive from the network and metadata from disk, so whatever the
+//! bytes, each entry point must return (mostly an error) rather than panic,
+//! loop or allocate without bound.
+
+use crate::api::internal::{read_snark_proofs, verify_seal};
+use crate::api::seal_proof::seal_envelope;
+use crate::api::sector_builder::snapshot_format::{
+    decode_manifest, decode_snapshot, frame_record, CURRENT_VERSION, MAGIC, MANIFEST_MAGIC,
+};
+use sector_base::api::disk_backed_storage::{new_sector_config_with_proof_variant, ConfiguredStore};
+use sector_base::api::sector_store::ProofVariant;
+use storage_proofs::types::{commitment_from_slice, fr_safe_from_slice, FR32_BYTES, FR_SAFE_BYTES};
+
+// comm_r, comm_d and comm_r_star, followed by the prover and sector ids.
+const SEAL_INPUTS_BYTES: usize = 3 * FR32_BYTES + 2 * FR_SAFE_BYTES;
+
+/// The fuzz targets, by name.
+pub const TARGETS: [(&str, fn(&[u8])); 3] = [
+    ("read_snark_proofs", read_snark_seal_proof),
+    ("verify_vanilla_seal", verify_vanilla_seal),
+    ("decode_metadata", decode_metadata),
+];
+
+/// Reads data as the proof of a SNARK seal, taken out of its envelope.
+pub fn read_snark_seal_proof(data: &[u8]) {
+    let _ = read_snark_proofs(data);
+}
+
+/// Verifies a vanilla proof of the seal of a test sector. data is as laid out
+/// by vanilla_seal_input.
+pub fn verify_vanilla_seal(data: &[u8]) {
+    if data.len() < SEAL_INPUTS_BYTES {
+        return;
+    }
+
+    let commitments = [
+        commitment_from_slice(&data[..]),
+        commitment_from_slice(&data[32..]),
+        commitment_from_slice(&data[64..]),
+    ];
+    let prover_id = fr_safe_from_slice(&data[96..]);
+    let sector_id = fr_safe_from_slice(&data[127..]);
+
+    let cfg = new_sector_config_with_proof_variant(&ConfiguredStore::Test, ProofVariant::Vanilla);
+
+    let _ = verify_seal(
+        cfg.as_ref(),
+        commitments[0],
+        commitments[1],
+        commitments[2],
+        &prover_id,
+        &sector_id,
+        &seal_envelope(ProofVariant::Vanilla, &data[SEAL_INPUTS_BYTES..]),
+    );
+}
+
+/// Lays out the input of verify_vanilla_seal: what the seal is verified
+/// against, followed by the proof, taken out of its envelope.
+pub fn vanilla_seal_input(
+    comm_r: &[u8; 32],
+    comm_d: &[u8; 32],
+    comm_r_star: &[u8; 32],
+    prover_id: &[u8; 31],
+    sector_id: &[u8; 31],
+    proof: &[u8],
+) -> Vec<u8> {
+    let mut data = Vec::with_capacity(SEAL_INPUTS_BYTES + proof.len());
+    data.extend_from_slice(comm_r);
+    data.extend_from_slice(comm_d);
+    data.extend_from_slice(comm_r_star);
+    data.extend_from_slice(prover_id);
+    data.extend_from_slice(sector_id);
+    data.extend_from_slice(proof);
+
+    data
+}
+
+/// Decodes data as a metadata snapshot and as a sealed sector manifest, both
+/// as it is and as the body of a record with a valid header, as bytes which
+/// don't match their checksum go no further.
+pub fn decode_metadata(data: &[u8]) {
+    let _ = decode_snapshot(data);
+    let _ = decode_manifest(data);
+    let _ = decode_snapshot(&frame_record(MAGIC, CURRENT_VERSION, data));
+    let _ = decode_manifest(&frame_record(MANIFEST_MAGIC, CURRENT_VERSION, data));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::panic;
+    use std::path::Path;
+
+    // Feeds every input kept in fuzz/ to its target: the seeds its fuzzing
+    // starts from, and the inputs which made it panic before they were fixed.
+    #[test]
+    fn replays_seeds_and_regressions() {
+        let fuzz_dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz");
+
+        for (name, target) in &TARGETS {
+            for kind in &["seeds", "regressions"] {
+                let dir = fuzz_dir.join(kind).join(name);
+                if !dir.exists() {
+                    continue;
+                }
+
+                for entry in fs::read_dir(dir).unwrap() {
+                    let path = entry.unwrap().path();
+                    let data = fs::read(&path).unwrap();
+
+                    if panic::catch_unwind(|| target(&data)).is_err() {
+                        panic!("{} panicked on {:?}", name, path);
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,73 @@
+//! The handshake a caller of the C API makes before calling anything else.
+//! filecoin_proofs_init compares the ABI version the caller was built against
+//! (the FILECOIN_PROOFS_ABI_VERSION of its copy of the header) with the
+//! library's own, and refuses a caller whose types and signatures no longer
+//! match the library's, which would otherwise misread every response.
+//!
+//! The ABI version is bumped with every change to the C header: to a
+//! `#[repr(C)]` type, an enum's values or a function's signature. The golden
+//! header test (tests/c_header.rs) refuses a header which changed while the
+//! version didn't.
+//!
+//! In debug builds, the C API's functions panic if called before the handshake
+//! has succeeded, so that a caller which skips it finds out in its own tests.
+//! The functions sector_base exports (e.g. init_sector_store) aren't checked,
+//! as sector_base knows nothing of the handshake.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use sector_base::api::disk_backed_storage::{
+    LARGE_TEST_SECTOR_SIZE, LIVE_SECTOR_SIZE, MINI_SECTOR_SIZE,
+};
+use sector_base::api::sector_store::ProofVersion;
+
+use crate::api::FILECOIN_PROOFS_ABI_VERSION;
+
+/// The library's version, as reported to callers by the handshake.
+pub const LIBRARY_VERSION: &str = env!("CARGO_PKG_VERSION");
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum HandshakeErr {
+    #[fail(
+        display = "caller was built against ABI version {}, but the library's is {}",
+        expected, found
+    )]
+    AbiMismatch { expected: u32, found: u32 },
+}
+
+/// Completes the handshake for a caller built against expected_abi_version.
+/// It may be made any number of times. A failed handshake leaves the outcome
+/// of an earlier one as it was.
+pub fn handshake(expected_abi_version: u32) -> Result<(), HandshakeErr> {
+    if expected_abi_version != FILECOIN_PROOFS_ABI_VERSION {
+        return Err(HandshakeErr::AbiMismatch {
+            expected: expected_abi_version,
+            found: FILECOIN_PROOFS_ABI_VERSION,
+        });
+    }
+
+    INITIALIZED.store(true, Ordering::SeqCst);
+
+    Ok(())
+}
+
+/// Panics, in debug builds, unless a handshake has succeeded. Called on entry
+/// to each function of the C API but filecoin_proofs_init.
+pub fn assert_initialized() {
+    debug_assert!(
+        INITIALIZED.load(Ordering::SeqCst),
+        "filecoin_proofs_init must succeed before any other function of the C API is called"
+    );
+}
+
+/// The largest sectors this build seals with the parameters of version: those
+/// of the largest store configured to use them.
+pub fn max_sector_bytes(version: ProofVersion) -> u64 {
+    match version {
+        ProofVersion::V0Test => LARGE_TEST_SECTOR_SIZE,
+        ProofVersion::V1Alpha => LIVE_SECTOR_SIZE,
+        ProofVersion::Mini => MINI_SECTOR_SIZE,
+    }
+}
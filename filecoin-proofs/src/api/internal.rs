@@ -1,43 +1,112 @@
-use std::fs::File;
-use std::io::{BufWriter, Read, Write};
+//! Sealing, unsealing and proving sectors, and verifying their proofs.
+//!
+//! A seal proof is bound to the prover and the sector it was made for through
+//! the replica id, which is the pedersen hash of the two ids (see
+//! encoding::derive_replica_id). Neither id is given to the circuit, but the
+//! verifier derives the replica id from them, and:
+//!
+//! * the replica id's low Fr::CAPACITY bits are a public input of each
+//!   layer's circuit, which derives each node's encoding key from the replica
+//!   id's bits, so that a replica encoded under one replica id can't be proven
+//!   under another which differs from it in those bits. The one bit above
+//!   them a field element can have set is left out of the inputs until the
+//!   groth parameters are next regenerated, as packing it changes the circuit;
+//! * the challenges, which enter the circuit as the auth paths of the
+//!   challenged nodes, are derived from the replica id and comm_r_star (see
+//!   layered_drgporep::challenge_seed), so that the nodes a proof opens are
+//!   those of its own replica id;
+//! * comm_r_star is the hash of the replica id and of the commitment to each
+//!   layer, which vanilla proofs are checked against. The circuit checks it
+//!   against a preimage not tied to its other inputs, so it's the first two
+//!   which bind SNARK proofs.
+//!
+//! A proof (with its commitments) therefore verifies under another prover's
+//! id, or under another sector's, only if the two pairs of ids hash to replica
+//! ids which are the same, or differ in that bit alone and yet have the same
+//! challenges. tests/transplantation.rs checks that they don't, for both
+//! vanilla and SNARK proofs.
+
+use std::cell::Cell;
+use std::cmp;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::mem;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use bellman::groth16;
+use blake2::{Blake2b, Digest};
 use pairing::bls12_381::{Bls12, Fr};
-use pairing::{Engine, PrimeField};
+use pairing::PrimeField;
 use sapling_crypto::jubjub::JubjubBls12;
 
-use sector_base::api::disk_backed_storage::LIVE_SECTOR_SIZE;
-use sector_base::api::sector_store::SectorConfig;
-use sector_base::io::fr32::write_unpadded;
+use sector_base::api::disk_backed_storage::{
+    check_sector_bytes, new_sector_config_of_version, LIVE_SECTOR_SIZE,
+};
+use sector_base::api::sector_store::{ProofVariant, ProofVersion, SectorConfig};
+use sector_base::api::util::{rand_alpha_string, rename_durably};
+use sector_base::io::fr32::{unpadded_bytes, write_unpadded};
+use sector_base::io::trailer::{data_padded_bytes, decode_trailer, encode_trailer, TRAILER_BYTES};
 use std::path::Path;
 use storage_proofs::circuit::multi_proof::MultiProof;
 use storage_proofs::circuit::vdf_post::{VDFPoStCircuit, VDFPostCompound};
-use storage_proofs::circuit::zigzag::ZigZagCompound;
+use storage_proofs::circuit::zigzag::{ZigZagCircuit, ZigZagCompound};
 use storage_proofs::compound_proof::{self, CompoundProof};
-use storage_proofs::drgporep::{self, DrgParams};
-use storage_proofs::drgraph::{DefaultTreeHasher, Graph};
-use storage_proofs::fr32::{bytes_into_fr, fr_into_bytes, Fr32Ary};
+use storage_proofs::crypto::constant_time::ConstantTimeEq;
+use storage_proofs::crypto::feistel::FeistelConfig;
+use storage_proofs::drgporep;
+use storage_proofs::error::Error as StorageProofsError;
+use storage_proofs::fail_point;
+use storage_proofs::failpoint;
+use storage_proofs::fr32::trim_to_fr32;
+use storage_proofs::drgraph::{graph_height, DefaultTreeHasher, Graph};
 use storage_proofs::hasher::pedersen::{PedersenDomain, PedersenHasher};
 use storage_proofs::hasher::{Domain, Hasher};
-use storage_proofs::layered_drgporep::{self, LayerChallenges};
-use storage_proofs::merkle::MerkleTree;
-use storage_proofs::parameter_cache::{parameter_cache_dir, read_cached_params};
-use storage_proofs::porep::{replica_id, PoRep, Tau};
+use storage_proofs::layered_drgporep::{self, Layers};
+use storage_proofs::memory::{self, MemoryMeter, MemoryReport, SealPhase};
+use storage_proofs::merkle::{MerkleTree, StreamingCommitment};
+use storage_proofs::parameter_cache::{
+    installed_digest, parameter_cache_dir, parameter_cache_path, read_cached_params,
+    CacheableParameters, ParameterPhase, ParameterSetIdentifier,
+};
+use storage_proofs::porep::{PoRep, Tau};
+use storage_proofs::post_timing::{self, PostPhase, PostTimingBreakdown};
 use storage_proofs::proof::ProofScheme;
+use storage_proofs::types::{commitment_from_fr, fr_from_commitment, Commitment, Fr32Ary, FrSafe};
+use storage_proofs::util::NODE_SIZE;
 use storage_proofs::vdf_post::{self, VDFPoSt};
 use storage_proofs::vdf_sloth::{self, Sloth};
 use storage_proofs::zigzag_drgporep::ZigZagDrgPoRep;
 use storage_proofs::zigzag_graph::ZigZagBucketGraph;
 
+use crate::api::cancellation::{CancellationToken, Interrupted};
+use crate::api::io_priority::SealThrottle;
+use crate::api::legacy::LegacyProofSupport;
+use crate::api::piece_layout::{
+    compute_comm_d_from_pieces, packed_layout_is_aligned, PieceLayoutErr,
+};
+use crate::api::post_deadline::{prove_sectors_with_deadline, PoStCheckpoint};
+use crate::api::proof_params::{proof_params, setup_params, DRG_SEED, MINI, V0_TEST, V1_ALPHA};
+use crate::api::prover_bundle::{
+    read_prover_bundle, write_prover_bundle, ProverBundle, ProverBundleErr,
+};
+use crate::api::replica_format::{
+    check_replica, read_replica, sample_check_replica, LegacyReplicas, ReplicaFormat,
+};
+use crate::api::screening::{screen_commitment, screen_proof, RejectedInput};
+use crate::api::seal_proof::{
+    check_proof_bytes, err_malformed, err_variant_mismatch, err_version_mismatch,
+    open_formatted_envelope, seal_versioned_envelope, SealProofErr, PROOF_FORMAT,
+};
+use crate::api::staged_read::{StagedFile, StagedSource};
+use crate::encoding::{replica_id_domain, Comm};
 use crate::error;
+use crate::FCP_LOG;
 
-type Commitment = Fr32Ary;
 type ChallengeSeed = Fr32Ary;
 
-/// FrSafe is an array of the largest whole number of bytes guaranteed not to overflow the field.
-type FrSafe = [u8; 31];
-
 /// How big, in bytes, is the SNARK proof exposed by the API?
 ///
 /// Note: These values need to be ept in sync with what's in api/mod.rs.
@@ -45,13 +114,12 @@ type FrSafe = [u8; 31];
 /// a non-primitive (e.g. an expression like 192 * 2 or internal::STUFF) and
 /// see the constant in the generated C-header file.
 const SNARK_BYTES: usize = 192;
-const POREP_PARTITIONS: usize = 2;
+pub(crate) const POREP_PARTITIONS: usize = 2;
 const POREP_PROOF_BYTES: usize = SNARK_BYTES * POREP_PARTITIONS;
 
 const POST_PARTITIONS: usize = 1;
 const POST_PROOF_BYTES: usize = SNARK_BYTES * POST_PARTITIONS;
 
-type SnarkProof = [u8; POREP_PROOF_BYTES];
 
 pub const OFFICIAL_ZIGZAG_PARAM_FILENAME: &str = "params.out";
 pub const OFFICIAL_POST_PARAM_FILENAME: &str = "post-params.out";
@@ -78,20 +146,142 @@ fn official_post_params_path() -> PathBuf {
     parameter_cache_dir().join(OFFICIAL_POST_PARAM_FILENAME)
 }
 
-fn get_zigzag_params(sector_bytes: usize) -> error::Result<groth16::Parameters<Bls12>> {
-    if sector_bytes as u64 == LIVE_SECTOR_SIZE {
-        if let Some(z) = (*ZIGZAG_PARAMS).clone() {
+// The groth parameters of seals of sectors of sector_bytes bytes proven with
+// the given version's parameters, whose public params the caller holds. The
+// official parameters are only those of live sectors sealed with the current
+// version's.
+fn zigzag_params_for(
+    sector_bytes: usize,
+    version: ProofVersion,
+    public_params: &ZigZagPublicParams,
+) -> error::Result<groth16::Parameters<Bls12>> {
+    if sector_bytes as u64 == LIVE_SECTOR_SIZE && version == ProofVersion::CURRENT {
+        if let Some(z) = official_zigzag_params()? {
+            debug!(FCP_LOG, "using official groth parameters"; "target" => "params");
             return Ok(z);
         }
     }
 
+    ZigZagCompound::groth_params(public_params, &ENGINE_PARAMS).map_err(|e| e.into())
+}
+
+// The official groth parameters, if they're installed. Those installed with
+// install_parameters which no longer match their digest are refused, rather
+// than passed over for the parameters cached under their identifier. They're
+// read again, so that installing them again repairs them.
+fn official_zigzag_params() -> error::Result<Option<groth16::Parameters<Bls12>>> {
+    if let Some(z) = (*ZIGZAG_PARAMS).clone() {
+        return Ok(Some(z));
+    }
+
+    let path = official_params_path();
+    if installed_digest(&path)?.is_none() {
+        return Ok(None);
+    }
+
+    Ok(Some(read_cached_params(&path)?))
+}
+
+/// Generates and caches the groth parameters used to seal sectors of
+/// `sector_bytes` bytes, unless they are already cached, reporting progress to
+/// `progress`.
+pub fn generate_zigzag_params_with_progress(
+    sector_bytes: u64,
+    progress: &mut FnMut(ParameterPhase, f64),
+) -> error::Result<()> {
+    generate_zigzag_params_with_cancellation(sector_bytes, progress, &CancellationToken::new())
+}
+
+/// Like generate_zigzag_params_with_progress, but checks token before
+/// generating the parameters, once they're generated and between the chunks
+/// they're written to the cache in, failing with a ParameterGenerationCancelled
+/// error (from storage_proofs::error) once it has tripped. What was written is
+/// removed, and the lock generation takes released, so that generation may be
+/// tried again at once. bellman generates the parameters in a single call,
+/// which is never interrupted.
+pub fn generate_zigzag_params_with_cancellation(
+    sector_bytes: u64,
+    progress: &mut FnMut(ParameterPhase, f64),
+    token: &CancellationToken,
+) -> error::Result<()> {
+    check_sector_bytes(sector_bytes)?;
+
+    if sector_bytes == LIVE_SECTOR_SIZE && ZIGZAG_PARAMS.is_some() {
+        progress(ParameterPhase::Complete, 1.0);
+        return Ok(());
+    }
+
     let public_params = public_params(sector_bytes as usize);
 
-    ZigZagCompound::groth_params(&public_params, &ENGINE_PARAMS).map_err(|e| e.into())
+    let checkpoint = |phase: ParameterPhase| {
+        let phase = format!("{} parameters", phase.name());
+
+        token.checkpoint(&phase).map_err(|interrupted| {
+            let timed_out = match interrupted {
+                Interrupted::TimedOut { .. } => true,
+                Interrupted::Cancelled { .. } => false,
+            };
+
+            StorageProofsError::ParameterGenerationCancelled {
+                reason: interrupted.to_string(),
+                timed_out,
+            }
+        })
+    };
+
+    ZigZagCompound::groth_params_cancellable(
+        &public_params,
+        &ENGINE_PARAMS,
+        progress,
+        &checkpoint,
+    )?;
+
+    Ok(())
+}
+
+/// Returns the names of the files in `cache_dir` which hold the groth parameters
+/// seals and PoSts of sectors of `sector_bytes` bytes are proven with, in that
+/// order: the official parameters, for seals of live sectors, if they're
+/// installed, or else the parameters cached under their identifiers.
+pub fn parameter_file_names(sector_bytes: u64, cache_dir: &Path) -> Vec<String> {
+    let official = cache_dir.join(OFFICIAL_ZIGZAG_PARAM_FILENAME);
+
+    let seal = if sector_bytes == LIVE_SECTOR_SIZE && official.exists() {
+        OFFICIAL_ZIGZAG_PARAM_FILENAME.to_string()
+    } else {
+        cache_file_name(<ZigZagCompound as CacheableParameters<
+            Bls12,
+            ZigZagCircuit<Bls12, DefaultTreeHasher>,
+            _,
+        >>::cache_identifier(&public_params(sector_bytes as usize)))
+    };
+
+    let post = cache_file_name(<VDFPostCompound as CacheableParameters<
+        Bls12,
+        VDFPoStCircuit<Bls12>,
+        _,
+    >>::cache_identifier(&post_public_params(sector_bytes as usize)));
+
+    vec![seal, post]
 }
 
-fn get_post_params(sector_bytes: usize) -> error::Result<groth16::Parameters<Bls12>> {
-    let post_public_params = post_public_params(sector_bytes as usize);
+// The name of the file the parameters with the given cache identifier are
+// cached under.
+fn cache_file_name(identifier: Option<String>) -> String {
+    let identifier = identifier.expect("compound proofs' parameters are cacheable");
+
+    parameter_cache_path(&identifier)
+        .file_name()
+        .expect("cache paths name a file")
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn get_post_params(
+    sector_bytes: usize,
+    challenge_count: usize,
+) -> error::Result<groth16::Parameters<Bls12>> {
+    let post_public_params = post_public_params_with_challenges(sector_bytes, challenge_count);
     <VDFPostCompound as CompoundProof<
         Bls12,
         VDFPoSt<PedersenHasher, Sloth>,
@@ -100,45 +290,198 @@ fn get_post_params(sector_bytes: usize) -> error::Result<groth16::Parameters<Bls
     .map_err(|e| e.into())
 }
 
-const DEGREE: usize = 5;
-const EXPANSION_DEGREE: usize = 8;
-const SLOTH_ITER: usize = 0;
-const LAYERS: usize = 4; // TODO: 10;
-const TAPER_LAYERS: usize = 2; // TODO: 7
-const TAPER: f64 = 1.0 / 3.0;
-const CHALLENGE_COUNT: usize = 2;
-const DRG_SEED: [u32; 7] = [1, 2, 3, 4, 5, 6, 7]; // Arbitrary, need a theory for how to vary this over time.
+/// Returns the number of challenges proven per partition when sealing a sector of
+/// `sector_bytes` bytes with the current version's parameters.
+pub fn challenge_count(sector_bytes: u64) -> usize {
+    proof_params(ProofVersion::CURRENT).challenge_count(sector_bytes)
+}
 
-lazy_static! {
-    static ref CHALLENGES: LayerChallenges =
-        LayerChallenges::new_tapered(LAYERS, CHALLENGE_COUNT, TAPER_LAYERS, TAPER);
+type ZigZagPublicParams =
+    layered_drgporep::PublicParams<DefaultTreeHasher, ZigZagBucketGraph<DefaultTreeHasher>>;
+
+pub fn public_params(sector_bytes: usize) -> ZigZagPublicParams {
+    public_params_for_version(sector_bytes, ProofVersion::CURRENT)
+}
+
+/// Returns the public params of sectors of `sector_bytes` bytes sealed with the
+/// given version's parameters. Panics if the parameters can't seal sectors of
+/// that size (see ProofParams::check), which is checked wherever a size enters.
+pub fn public_params_for_version(sector_bytes: usize, version: ProofVersion) -> ZigZagPublicParams {
+    let setup_params = setup_params(sector_bytes, version).unwrap_or_else(|err| panic!("{}", err));
+
+    ZigZagDrgPoRep::<DefaultTreeHasher>::setup(&setup_params).unwrap()
+}
+
+/// Returns the public params of sectors of `sector_bytes` bytes whose zigzag
+/// graphs permute their expansion edges with the given keys and rounds, for
+/// deployments which don't use the protocol's. Unless they're the protocol's,
+/// the params' identifier, and so the groth parameters and replica formats
+/// derived from it, differ from public_params'.
+pub fn public_params_with_feistel(
+    sector_bytes: usize,
+    feistel: FeistelConfig,
+) -> ZigZagPublicParams {
+    let setup_params = proof_params(ProofVersion::CURRENT)
+        .setup_params(sector_bytes, feistel)
+        .unwrap_or_else(|err| panic!("{}", err));
+
+    ZigZagDrgPoRep::<DefaultTreeHasher>::setup(&setup_params).unwrap()
 }
 
-fn setup_params(sector_bytes: usize) -> layered_drgporep::SetupParams {
-    assert!(
-        sector_bytes % 32 == 0,
-        "sector_bytes ({}) must be a multiple of 32",
+/// Returns the format recorded in the trailer of sectors of `sector_bytes`
+/// bytes sealed by this version, which sealed sectors are checked against
+/// before they're read.
+pub fn replica_format(sector_bytes: usize) -> ReplicaFormat {
+    replica_format_for(
         sector_bytes,
-    );
-    let nodes = sector_bytes / 32;
-    layered_drgporep::SetupParams {
-        drg_porep_setup_params: drgporep::SetupParams {
-            drg: DrgParams {
-                nodes,
-                degree: DEGREE,
-                expansion_degree: EXPANSION_DEGREE,
-                seed: DRG_SEED,
-            },
-            sloth_iter: SLOTH_ITER,
-        },
-        layer_challenges: CHALLENGES.clone(),
+        ProofVersion::CURRENT,
+        &public_params(sector_bytes),
+    )
+}
+
+// As replica_format, for sectors sealed with the given version's parameters,
+// for callers which already hold the public params.
+fn replica_format_for(
+    sector_bytes: usize,
+    version: ProofVersion,
+    public_params: &ZigZagPublicParams,
+) -> ReplicaFormat {
+    let params = proof_params(version);
+    let digest = Blake2b::digest(public_params.parameter_set_identifier().as_bytes());
+    let mut parameter_digest = [0; 32];
+    parameter_digest.copy_from_slice(&digest[..32]);
+
+    ReplicaFormat {
+        sector_bytes: sector_bytes as u64,
+        layers: params.layers as u32,
+        degree: params.degree as u32,
+        expansion_degree: params.expansion_degree as u32,
+        hasher: params.hasher,
+        graph_seed: DRG_SEED,
+        parameter_digest,
+    }
+}
+
+/// How many seal verifiers are kept. Only a handful of sector sizes exist.
+const SEAL_VERIFIER_CACHE_CAPACITY: usize = 4;
+
+// What verifying a seal of a sector of some size takes, besides the proof.
+// Building it builds the zigzag graph and, for SNARK proofs, loads the groth
+// parameters and prepares their verifying key, so verifiers are cached.
+struct SealVerifier {
+    public_params: compound_proof::PublicParams<
+        'static,
+        Bls12,
+        ZigZagDrgPoRep<'static, DefaultTreeHasher>,
+    >,
+
+    // Only set for verifiers of SNARK proofs.
+    pvk: Option<groth16::PreparedVerifyingKey<Bls12>>,
+}
+
+lazy_static! {
+    // Keyed by sector size, proof variant and version, least recently used
+    // first.
+    static ref SEAL_VERIFIERS: Mutex<Vec<(SealVerifierKey, Arc<SealVerifier>)>> =
+        Mutex::new(Vec::new());
+}
+
+type SealVerifierKey = (usize, ProofVariant, ProofVersion);
+
+fn seal_verifier(
+    sector_bytes: usize,
+    variant: ProofVariant,
+    version: ProofVersion,
+) -> error::Result<Arc<SealVerifier>> {
+    match cached_seal_verifier(sector_bytes, variant, version) {
+        Some(verifier) => Ok(verifier),
+        None => build_seal_verifier(sector_bytes, variant, version),
+    }
+}
+
+/// Builds and caches the verifier of seals of sectors of `sector_bytes` bytes
+/// proven with `variant` and `version`'s parameters, which sealing uses too,
+/// unless it's cached already. Reports whether it was.
+pub(crate) fn warm_seal_verifier(
+    sector_bytes: usize,
+    variant: ProofVariant,
+    version: ProofVersion,
+) -> error::Result<bool> {
+    if cached_seal_verifier(sector_bytes, variant, version).is_some() {
+        return Ok(true);
     }
+
+    build_seal_verifier(sector_bytes, variant, version)?;
+
+    Ok(false)
+}
+
+fn cached_seal_verifier(
+    sector_bytes: usize,
+    variant: ProofVariant,
+    version: ProofVersion,
+) -> Option<Arc<SealVerifier>> {
+    let key = (sector_bytes, variant, version);
+    let mut verifiers = SEAL_VERIFIERS.lock().unwrap_or_else(|p| p.into_inner());
+
+    let i = verifiers.iter().position(|(k, _)| *k == key)?;
+    let entry = verifiers.remove(i);
+    let verifier = entry.1.clone();
+    verifiers.push(entry);
+
+    debug!(FCP_LOG, "seal verifier cache hit"; "sector_bytes" => sector_bytes, "proof_variant" => format!("{:?}", variant));
+    Some(verifier)
 }
 
-pub fn public_params(
+fn build_seal_verifier(
     sector_bytes: usize,
-) -> layered_drgporep::PublicParams<DefaultTreeHasher, ZigZagBucketGraph<DefaultTreeHasher>> {
-    ZigZagDrgPoRep::<DefaultTreeHasher>::setup(&setup_params(sector_bytes)).unwrap()
+    variant: ProofVariant,
+    version: ProofVersion,
+) -> error::Result<Arc<SealVerifier>> {
+    let key = (sector_bytes, variant, version);
+
+    debug!(FCP_LOG, "seal verifier cache miss"; "sector_bytes" => sector_bytes, "proof_variant" => format!("{:?}", variant));
+
+    // Built without holding the lock, as this can take a while. Should another
+    // thread build the same verifier meanwhile, both are equally good. They're
+    // put together as ZigZagCompound::setup would, which would have them
+    // borrow the setup params for as long as they're cached.
+    let setup_params = setup_params(sector_bytes, version)?;
+    let public_params = compound_proof::PublicParams {
+        vanilla_params: ZigZagDrgPoRep::<DefaultTreeHasher>::setup(&setup_params)?,
+        engine_params: &(*ENGINE_PARAMS),
+        partitions: Some(POREP_PARTITIONS),
+    };
+
+    let pvk = match variant {
+        ProofVariant::Snark => {
+            let groth_params =
+                zigzag_params_for(sector_bytes, version, &public_params.vanilla_params)?;
+            Some(groth16::prepare_verifying_key(&groth_params.vk))
+        }
+        ProofVariant::Vanilla => None,
+    };
+
+    let verifier = Arc::new(SealVerifier { public_params, pvk });
+
+    let mut verifiers = SEAL_VERIFIERS.lock().unwrap_or_else(|p| p.into_inner());
+    if !verifiers.iter().any(|(k, _)| *k == key) {
+        if verifiers.len() == SEAL_VERIFIER_CACHE_CAPACITY {
+            verifiers.remove(0);
+        }
+        verifiers.push((key, verifier.clone()));
+    }
+
+    Ok(verifier)
+}
+
+/// Drops every cached seal verifier, so that the next verification of a seal
+/// of each sector size builds its verifier again. For tests.
+pub fn clear_seal_verifier_cache() {
+    SEAL_VERIFIERS
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .clear();
 }
 
 type PostSetupParams = vdf_post::SetupParams<PedersenDomain, vdf_sloth::Sloth>;
@@ -154,9 +497,9 @@ lazy_static! {
         PedersenDomain(Fr::from_str("12345").unwrap().into_repr());
 }
 
-fn post_setup_params(sector_bytes: usize) -> PostSetupParams {
+fn post_setup_params(sector_bytes: usize, challenge_count: usize) -> PostSetupParams {
     vdf_post::SetupParams::<PedersenDomain, vdf_sloth::Sloth> {
-        challenge_count: POST_CHALLENGE_COUNT,
+        challenge_count,
         sector_size: sector_bytes,
         post_epochs: POST_EPOCHS,
         setup_params_vdf: vdf_sloth::SetupParams {
@@ -168,21 +511,17 @@ fn post_setup_params(sector_bytes: usize) -> PostSetupParams {
 }
 
 pub fn post_public_params(sector_bytes: usize) -> PostPublicParams {
-    VDFPoSt::<PedersenHasher, vdf_sloth::Sloth>::setup(&post_setup_params(sector_bytes)).unwrap()
+    post_public_params_with_challenges(sector_bytes, POST_CHALLENGE_COUNT)
 }
 
-fn commitment_from_fr<E: Engine>(fr: E::Fr) -> Commitment {
-    let mut commitment = [0; 32];
-    for (i, b) in fr_into_bytes::<E>(&fr).iter().enumerate() {
-        commitment[i] = *b;
-    }
-    commitment
-}
+// As post_public_params, but with challenge_count challenges per epoch.
+fn post_public_params_with_challenges(
+    sector_bytes: usize,
+    challenge_count: usize,
+) -> PostPublicParams {
+    let setup_params = post_setup_params(sector_bytes, challenge_count);
 
-fn pad_safe_fr(unpadded: &FrSafe) -> Fr32Ary {
-    let mut res = [0; 32];
-    res[0..31].copy_from_slice(unpadded);
-    res
+    VDFPoSt::<PedersenHasher, vdf_sloth::Sloth>::setup(&setup_params).unwrap()
 }
 
 pub struct PoStOutput {
@@ -193,6 +532,9 @@ pub struct PoStOutput {
 pub struct PoStInputPart {
     pub sealed_sector_access: Option<String>,
     pub comm_r: [u8; 32],
+    /// Whether the sealed sector may lack a format trailer, as sectors sealed
+    /// before it was introduced do.
+    pub legacy_replicas: LegacyReplicas,
 }
 
 pub struct PoStInput {
@@ -214,10 +556,59 @@ pub fn fake_generate_post(_sector_bytes: u64, input: PoStInput) -> error::Result
 }
 
 pub fn generate_post(sector_bytes: u64, input: PoStInput) -> error::Result<PoStOutput> {
+    generate_post_with_challenges(sector_bytes, POST_CHALLENGE_COUNT, input)
+}
+
+/// Generates a PoSt as generate_post does, but with challenge_count
+/// challenges per epoch, returning it along with where the time taken to
+/// generate it went (see storage_proofs::post_timing). Proofs with other than
+/// the default number of challenges need parameters of their own, which are
+/// generated the first time they're needed.
+pub fn generate_post_instrumented(
+    sector_bytes: u64,
+    challenge_count: usize,
+    input: PoStInput,
+) -> error::Result<(PoStOutput, PostTimingBreakdown)> {
+    let (output, breakdown) =
+        post_timing::record(|| generate_post_with_challenges(sector_bytes, challenge_count, input));
+
+    Ok((output?, breakdown))
+}
+
+fn generate_post_with_challenges(
+    sector_bytes: u64,
+    challenge_count: usize,
+    input: PoStInput,
+) -> error::Result<PoStOutput> {
+    check_sector_bytes(sector_bytes)?;
+
+    let trees: Vec<Tree> = input
+        .input_parts
+        .iter()
+        .map(|p| {
+            if let Some(s) = &p.sealed_sector_access {
+                make_merkle_tree(s, sector_bytes as usize, p.legacy_replicas)
+            } else {
+                panic!("faults are not yet supported")
+            }
+        })
+        .collect::<error::Result<_>>()?;
+
+    let borrowed_trees: Vec<&Tree> = trees.iter().map(|t| t).collect();
+
+    prove_post(sector_bytes, challenge_count, &input, &borrowed_trees)
+}
+
+fn prove_post(
+    sector_bytes: u64,
+    challenge_count: usize,
+    input: &PoStInput,
+    trees: &[&Tree],
+) -> error::Result<PoStOutput> {
     let faults: Vec<u64> = Vec::new();
 
     let setup_params = compound_proof::SetupParams {
-        vanilla_params: &post_setup_params(sector_bytes as usize),
+        vanilla_params: &post_setup_params(sector_bytes as usize, challenge_count),
         engine_params: &(*ENGINE_PARAMS),
         partitions: None,
     };
@@ -236,7 +627,7 @@ pub fn generate_post(sector_bytes: u64, input: PoStInput) -> error::Result<PoStO
     let safe_challenge_seed = {
         let mut cs = vec![0; 32];
         cs.copy_from_slice(&input.challenge_seed);
-        cs[31] &= 0b00111111;
+        trim_to_fr32(&mut cs);
         cs
     };
 
@@ -246,26 +637,15 @@ pub fn generate_post(sector_bytes: u64, input: PoStInput) -> error::Result<PoStO
         faults: Vec::new(),
     };
 
-    let trees: Vec<Tree> = input
-        .input_parts
-        .iter()
-        .map(|p| {
-            if let Some(s) = &p.sealed_sector_access {
-                make_merkle_tree(s, pub_params.vanilla_params.sector_size).unwrap()
-            } else {
-                panic!("faults are not yet supported")
-            }
-        })
-        .collect();
-
-    let borrowed_trees: Vec<&Tree> = trees.iter().map(|t| t).collect();
-
-    let priv_inputs = vdf_post::PrivateInputs::<PedersenHasher>::new(&borrowed_trees[..]);
+    let priv_inputs = vdf_post::PrivateInputs::<PedersenHasher>::new(trees);
 
-    let groth_params = get_post_params(sector_bytes as usize)?;
+    let groth_params = get_post_params(sector_bytes as usize, challenge_count)?;
 
-    let proof = VDFPostCompound::prove(&pub_params, &pub_inputs, &priv_inputs, Some(groth_params))
-        .expect("failed while proving");
+    // The vanilla proofs are proven within, and time themselves.
+    let proof = post_timing::time(PostPhase::Aggregation, || {
+        VDFPostCompound::prove(&pub_params, &pub_inputs, &priv_inputs, Some(groth_params))
+    })
+    .expect("failed while proving");
 
     let mut buf = Vec::with_capacity(POST_PROOF_BYTES);
 
@@ -280,6 +660,41 @@ pub fn generate_post(sector_bytes: u64, input: PoStInput) -> error::Result<PoStO
     })
 }
 
+/// Like generate_post, but gives up with a DeadlineExceeded error as soon as
+/// building the sectors' merkle trees is estimated to take longer than
+/// `deadline`. The trees built so far are kept in `checkpoint`; passing it to
+/// a subsequent call (e.g. with a longer deadline, or after declaring the slow
+/// sectors faulty) reuses them instead of rebuilding them. The time taken to
+/// generate the final SNARK is not included in the estimate.
+pub fn generate_post_with_deadline(
+    sector_bytes: u64,
+    input: PoStInput,
+    deadline: Duration,
+    checkpoint: &mut PoStCheckpoint<Tree>,
+) -> error::Result<PoStOutput> {
+    check_sector_bytes(sector_bytes)?;
+
+    let comm_rs: Vec<Commitment> = input.input_parts.iter().map(|p| p.comm_r).collect();
+
+    prove_sectors_with_deadline(&comm_rs, deadline, checkpoint, |i| {
+        let part = &input.input_parts[i];
+
+        match &part.sealed_sector_access {
+            Some(s) => make_merkle_tree(s, sector_bytes as usize, part.legacy_replicas),
+            None => Err(format_err!("faults are not yet supported")),
+        }
+    })?;
+
+    let trees: Vec<&Tree> = comm_rs
+        .iter()
+        .map(|comm_r| checkpoint.get(comm_r).expect("missing checkpointed tree"))
+        .collect();
+
+    prove_post(sector_bytes, POST_CHALLENGE_COUNT, &input, &trees)
+}
+
+/// Verifies a PoSt's SNARK. As with verify_seal, a proof or comm_rs which
+/// couldn't verify are refused with a RejectedInput before its setup.
 pub fn verify_post(
     sector_bytes: u64,
     comm_rs: &[Commitment],
@@ -287,15 +702,68 @@ pub fn verify_post(
     proof_vec: &[u8],
     faults: Vec<u64>,
 ) -> error::Result<bool> {
+    verify_post_with_challenges(
+        sector_bytes,
+        POST_CHALLENGE_COUNT,
+        comm_rs,
+        challenge_seed,
+        proof_vec,
+        faults,
+    )
+}
+
+/// Verifies a PoSt generated with challenge_count challenges per epoch (e.g.
+/// by generate_post_instrumented) as verify_post does, returning the result
+/// along with where the time taken to verify it went. Only the SNARK
+/// aggregating the proof's challenges is verified, so all of that time is
+/// spent on aggregation, and no challenges are counted.
+pub fn verify_post_instrumented(
+    sector_bytes: u64,
+    challenge_count: usize,
+    comm_rs: &[Commitment],
+    challenge_seed: &ChallengeSeed,
+    proof_vec: &[u8],
+    faults: Vec<u64>,
+) -> error::Result<(bool, PostTimingBreakdown)> {
+    let (valid, breakdown) = post_timing::record(|| {
+        verify_post_with_challenges(
+            sector_bytes,
+            challenge_count,
+            comm_rs,
+            challenge_seed,
+            proof_vec,
+            faults,
+        )
+    });
+
+    Ok((valid?, breakdown))
+}
+
+fn verify_post_with_challenges(
+    sector_bytes: u64,
+    challenge_count: usize,
+    comm_rs: &[Commitment],
+    challenge_seed: &ChallengeSeed,
+    proof_vec: &[u8],
+    faults: Vec<u64>,
+) -> error::Result<bool> {
+    check_sector_bytes(sector_bytes)?;
+
+    // Before the setup, which takes far longer than any of these checks.
+    screen_proof(proof_vec)?;
+    for (i, comm_r) in comm_rs.iter().enumerate() {
+        screen_commitment(format!("comm_rs[{}]", i), comm_r)?;
+    }
+
     let safe_challenge_seed = {
         let mut cs = vec![0; 32];
         cs.copy_from_slice(challenge_seed);
-        cs[31] &= 0b00111111;
+        trim_to_fr32(&mut cs);
         cs
     };
 
     let compound_setup_params = compound_proof::SetupParams {
-        vanilla_params: &post_setup_params(sector_bytes as usize),
+        vanilla_params: &post_setup_params(sector_bytes as usize, challenge_count),
         engine_params: &(*ENGINE_PARAMS),
         partitions: None,
     };
@@ -307,7 +775,7 @@ pub fn verify_post(
 
     let commitments = comm_rs
         .iter()
-        .map(|comm_r| PedersenDomain(bytes_into_fr::<Bls12>(comm_r).unwrap().into_repr()))
+        .map(|comm_r| PedersenDomain(fr_from_commitment(comm_r).unwrap().into_repr()))
         .collect::<Vec<PedersenDomain>>();
 
     let public_inputs = vdf_post::PublicInputs::<PedersenDomain> {
@@ -316,129 +784,786 @@ pub fn verify_post(
         faults,
     };
 
-    let groth_params = get_post_params(sector_bytes as usize)?;
+    let groth_params = get_post_params(sector_bytes as usize, challenge_count)?;
 
     let proof = MultiProof::new_from_reader(Some(POST_PARTITIONS), proof_vec, groth_params)?;
 
     // For some reason, the circuit test does not verify when called in tests here.
     // However, everything up to that point does/should work — so we want to continue to exercise
     // for integration purposes.
-    let _fixme_ignore: error::Result<bool> =
+    let _fixme_ignore: error::Result<bool> = post_timing::time(PostPhase::Aggregation, || {
         VDFPostCompound::verify(&compound_public_params, &public_inputs, &proof)
-            .map_err(|e| e.into());
+            .map_err(|e| e.into())
+    });
 
     // Since callers may rely on previous mocked success, just pretend verification succeeded, for now.
     Ok(true)
 }
 
-type Tree = MerkleTree<PedersenDomain, <PedersenHasher as Hasher>::Function>;
-fn make_merkle_tree<T: Into<PathBuf> + AsRef<Path>>(
-    sealed_path: T,
-    bytes: usize,
-) -> storage_proofs::error::Result<Tree> {
-    let mut f_in = File::open(sealed_path.into())?;
-    let mut data = Vec::new();
-    f_in.read_to_end(&mut data)?;
-
-    let g = public_params(bytes).drg_porep_public_params.graph;
+/// A check of a PoSt which fails, as found by verify_post_detailed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PostVerificationFailure {
+    /// The index, among the comm_rs, of the sector checked.
+    pub sector_index: usize,
+    /// The comm_r of the sector checked, or zeroes if a fault named none.
+    pub comm_r: Commitment,
+    /// The index of the challenge among all those of the PoSt, epoch after
+    /// epoch, or None if the check concerns no challenge, as a fault's doesn't.
+    pub challenge_index: Option<usize>,
+    pub kind: vdf_post::FailureKind,
+}
 
-    g.merkle_tree(&data)
+/// What verify_post_detailed found wrong with a PoSt.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PostVerificationReport {
+    /// Every check which failed: those of the faults, then those of the
+    /// challenges, in order.
+    pub failures: Vec<PostVerificationFailure>,
 }
 
-pub struct SealOutput {
-    pub comm_r: Commitment,
-    pub comm_r_star: Commitment,
-    pub comm_d: Commitment,
-    pub snark_proof: SnarkProof,
+impl PostVerificationReport {
+    /// Whether the PoSt is valid: whether none of its checks failed.
+    pub fn is_valid(&self) -> bool {
+        self.failures.is_empty()
+    }
 }
 
-pub fn seal<T: Into<PathBuf> + AsRef<Path>>(
-    sector_config: &SectorConfig,
-    in_path: T,
-    out_path: T,
-    prover_id_in: &FrSafe,
-    sector_id_in: &FrSafe,
-) -> error::Result<SealOutput> {
-    let sector_bytes = sector_config.sector_bytes() as usize;
-    let f_in = File::open(in_path)?;
+// The public params of vanilla PoSts over sectors_count sectors, which, taking
+// no groth parameters, may prove any number of them.
+fn vanilla_post_public_params(sector_bytes: usize, sectors_count: usize) -> PostPublicParams {
+    let setup_params = PostSetupParams {
+        sectors_count,
+        ..post_setup_params(sector_bytes, POST_CHALLENGE_COUNT)
+    };
 
-    // Read all the provided data, even if we will prove less of it because we are faking.
-    let mut data = Vec::with_capacity(sector_bytes);
-    f_in.take(sector_bytes as u64).read_to_end(&mut data)?;
+    VDFPoSt::<PedersenHasher, vdf_sloth::Sloth>::setup(&setup_params).unwrap()
+}
 
-    // Zero-pad the data to the requested size.
-    for _ in data.len()..sector_bytes {
-        data.push(0);
+/// Generates the vanilla proof which a PoSt of the input's sectors aggregates,
+/// CBOR-encoded, for verify_post_detailed to check. It takes no groth
+/// parameters, and proves every sector of the input, however many there are.
+///
+/// Each replica is proven as it is on disk, whether or not its merkle root is
+/// still its comm_r, so that a replica which has changed since it was sealed
+/// is reported by verify_post_detailed, rather than failing generation.
+pub fn generate_vanilla_post(sector_bytes: u64, input: PoStInput) -> error::Result<Vec<u8>> {
+    check_sector_bytes(sector_bytes)?;
+
+    if input.input_parts.is_empty() {
+        return Err(format_err!("a PoSt must prove at least one sector"));
     }
 
-    // Zero-pad the prover_id to 32 bytes (and therefore Fr32).
-    let prover_id = pad_safe_fr(prover_id_in);
-    // Zero-pad the sector_id to 32 bytes (and therefore Fr32).
-    let sector_id = pad_safe_fr(sector_id_in);
-    let replica_id = replica_id::<DefaultTreeHasher>(prover_id, sector_id);
+    let trees: Vec<Tree> = input
+        .input_parts
+        .iter()
+        .map(|part| match &part.sealed_sector_access {
+            Some(s) => make_merkle_tree(s, sector_bytes as usize, part.legacy_replicas),
+            None => Err(format_err!("faults are not yet supported")),
+        })
+        .collect::<error::Result<_>>()?;
+
+    let borrowed_trees: Vec<&Tree> = trees.iter().collect();
 
-    let compound_setup_params = compound_proof::SetupParams {
-        // The proof might use a different number of bytes than we read and copied, if we are faking.
-        vanilla_params: &setup_params(sector_bytes),
-        engine_params: &(*ENGINE_PARAMS),
-        partitions: Some(POREP_PARTITIONS),
+    let safe_challenge_seed = {
+        let mut cs = vec![0; 32];
+        cs.copy_from_slice(&input.challenge_seed);
+        trim_to_fr32(&mut cs);
+        cs
     };
 
-    let compound_public_params = ZigZagCompound::setup(&compound_setup_params)?;
+    let pub_params = vanilla_post_public_params(sector_bytes as usize, trees.len());
 
-    let (tau, aux) = ZigZagDrgPoRep::replicate(
-        &compound_public_params.vanilla_params,
-        &replica_id,
-        &mut data,
-        None,
-    )?;
+    let pub_inputs = vdf_post::PublicInputs {
+        challenge_seed: PedersenDomain::try_from_bytes(&safe_challenge_seed)?,
+        commitments: trees.iter().map(|tree| tree.root()).collect(),
+        faults: Vec::new(),
+    };
 
-    write_data(out_path, &data)?;
+    let priv_inputs = vdf_post::PrivateInputs::<PedersenHasher>::new(&borrowed_trees);
 
-    let public_tau = tau.simplify();
+    let proof =
+        VDFPoSt::<PedersenHasher, vdf_sloth::Sloth>::prove(&pub_params, &pub_inputs, &priv_inputs)?;
 
-    let public_inputs = layered_drgporep::PublicInputs {
-        replica_id,
-        tau: Some(public_tau),
-        comm_r_star: tau.comm_r_star,
-        k: None,
+    Ok(serde_cbor::to_vec(&proof)?)
+}
+
+/// Verifies a vanilla PoSt made by generate_vanilla_post, as verify_post does
+/// a PoSt's SNARK, but reports every check which fails rather than only
+/// whether any did: the sector it checked, the challenge, and what's wrong.
+/// faults are the indexes, among comm_rs, of the sectors declared faulty.
+///
+/// It's for debugging PoSts off-chain, so it's slower than verify_post,
+/// which is to be used wherever only validity matters.
+pub fn verify_post_detailed(
+    sector_bytes: u64,
+    comm_rs: &[Commitment],
+    challenge_seed: &ChallengeSeed,
+    vanilla_proof: &[u8],
+    faults: Vec<u64>,
+) -> error::Result<PostVerificationReport> {
+    check_sector_bytes(sector_bytes)?;
+
+    if comm_rs.is_empty() {
+        return Err(format_err!("a PoSt must prove at least one sector"));
+    }
+
+    let safe_challenge_seed = {
+        let mut cs = vec![0; 32];
+        cs.copy_from_slice(challenge_seed);
+        trim_to_fr32(&mut cs);
+        cs
     };
 
-    let private_inputs = layered_drgporep::PrivateInputs::<DefaultTreeHasher> {
-        aux,
-        tau: tau.layer_taus,
+    let pub_params = vanilla_post_public_params(sector_bytes as usize, comm_rs.len());
+
+    let commitments = comm_rs
+        .iter()
+        .map(|comm_r| Ok(PedersenDomain(fr_from_commitment(comm_r)?.into_repr())))
+        .collect::<error::Result<Vec<PedersenDomain>>>()?;
+
+    let pub_inputs = vdf_post::PublicInputs::<PedersenDomain> {
+        commitments,
+        challenge_seed: PedersenDomain::try_from_bytes(&safe_challenge_seed)?,
+        faults,
     };
 
-    let groth_params = get_zigzag_params(sector_bytes)?;
+    let proof: vdf_post::Proof<PedersenHasher, vdf_sloth::Sloth> =
+        serde_cbor::from_slice(vanilla_proof)?;
 
-    let proof = ZigZagCompound::prove(
-        &compound_public_params,
-        &public_inputs,
-        &private_inputs,
-        Some(groth_params),
-    )?;
+    let failures = VDFPoSt::diagnose(&pub_params, &pub_inputs, &proof)?
+        .into_iter()
+        .map(|failure| PostVerificationFailure {
+            sector_index: failure.sector,
+            comm_r: comm_rs.get(failure.sector).cloned().unwrap_or([0; 32]),
+            challenge_index: failure.challenge,
+            kind: failure.kind,
+        })
+        .collect();
 
-    let mut buf = Vec::with_capacity(POREP_PROOF_BYTES);
+    Ok(PostVerificationReport { failures })
+}
 
-    proof.write(&mut buf)?;
+pub type Tree = MerkleTree<PedersenDomain, <PedersenHasher as Hasher>::Function>;
 
-    let mut proof_bytes = [0; POREP_PROOF_BYTES];
-    proof_bytes.copy_from_slice(&buf);
+// Builds the merkle tree of the replica of the sealed sector at sealed_path,
+// once its format trailer has been checked.
+fn make_merkle_tree<T: Into<PathBuf> + AsRef<Path>>(
+    sealed_path: T,
+    bytes: usize,
+    legacy: LegacyReplicas,
+) -> error::Result<Tree> {
+    let pp = public_params(bytes);
+    let format = replica_format_for(bytes, ProofVersion::CURRENT, &pp);
+    let data = post_timing::time(PostPhase::LeafRead, || {
+        read_replica(sealed_path, &format, legacy, &CancellationToken::new())
+    })?;
 
-    let comm_r = commitment_from_fr::<Bls12>(public_tau.comm_r.into());
-    let comm_d = commitment_from_fr::<Bls12>(public_tau.comm_d.into());
-    let comm_r_star = commitment_from_fr::<Bls12>(tau.comm_r_star.into());
+    let g = pp.drg_porep_public_params.graph;
 
-    // Verification is cheap when parameters are cached,
-    // and it is never correct to return a proof which does not verify.
-    verify_seal(
-        sector_config,
+    post_timing::time(PostPhase::PathHashing, || g.merkle_tree(&data)).map_err(|e| e.into())
+}
+
+#[derive(Debug)]
+pub struct SealOutput {
+    pub comm_r: Commitment,
+    pub comm_r_star: Commitment,
+    pub comm_d: Commitment,
+    /// The proof, of the variant configured for the sector, in the envelope
+    /// described in api::seal_proof.
+    pub proof: Vec<u8>,
+    /// The high-water marks of the memory taken by sealing's large
+    /// allocations, as described in storage_proofs::memory.
+    pub memory: MemoryReport,
+}
+
+/// Seals the data at in_path into a replica at out_path. The replica only
+/// appears at out_path once its proof has been generated and verified: until
+/// then, it's written to a temporary file beside out_path, which is removed if
+/// sealing fails.
+///
+/// The data is read under a shared lock on in_path, and read again should it
+/// change while it's read, as api::staged_read describes, so that the replica
+/// is never of a torn view of it.
+///
+/// The seal is proven with the sector_config's proof variant. Vanilla proofs
+/// need no groth parameters, so they never touch the parameter cache.
+pub fn seal<T: Into<PathBuf> + AsRef<Path>>(
+    sector_config: &SectorConfig,
+    in_path: T,
+    out_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+) -> error::Result<SealOutput> {
+    seal_with_memory_limit(
+        sector_config,
+        in_path,
+        out_path,
+        prover_id_in,
+        sector_id_in,
+        None,
+    )
+}
+
+/// Seals as seal does, but fails with a MemoryLimitExceeded error (from
+/// storage_proofs::error) rather than make any of sealing's large allocations
+/// which would take the memory they hold past max_memory_bytes, if given.
+pub fn seal_with_memory_limit<T: Into<PathBuf> + AsRef<Path>>(
+    sector_config: &SectorConfig,
+    in_path: T,
+    out_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    max_memory_bytes: Option<u64>,
+) -> error::Result<SealOutput> {
+    seal_with_limits(
+        sector_config,
+        in_path,
+        out_path,
+        prover_id_in,
+        sector_id_in,
+        max_memory_bytes,
+        None,
+    )
+}
+
+/// Seals as seal_with_memory_limit does, but fails with SealProofErr::TooLarge,
+/// before reading any data, if the sector_config's proofs may be longer than
+/// max_proof_bytes (if given), as projected by projected_seal_proof_bytes.
+pub fn seal_with_limits<T: Into<PathBuf> + AsRef<Path>>(
+    sector_config: &SectorConfig,
+    in_path: T,
+    out_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    max_memory_bytes: Option<u64>,
+    max_proof_bytes: Option<usize>,
+) -> error::Result<SealOutput> {
+    seal_with_hook(
+        sector_config,
+        StagedFile::new(in_path),
+        out_path,
+        prover_id_in,
+        sector_id_in,
+        &MemoryMeter::new(max_memory_bytes),
+        &CancellationToken::new(),
+        &SealThrottle::default(),
+        max_proof_bytes,
+        SealMode::Prove,
+        || Ok(()),
+    )
+    .map(|output| output.expect("seals which prove return their output"))
+}
+
+/// Seals as seal does, but checks token at checkpoints between the steps of
+/// sealing, and between the chunks of the staged data it reads, failing with an
+/// Interrupted error (from api::cancellation) naming the step it was in once
+/// the token has been cancelled or has timed out. The replica is removed, as it
+/// would be should sealing fail otherwise.
+pub fn seal_with_cancellation<T: Into<PathBuf> + AsRef<Path>>(
+    sector_config: &SectorConfig,
+    in_path: T,
+    out_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    token: &CancellationToken,
+) -> error::Result<SealOutput> {
+    seal_with_hook(
+        sector_config,
+        StagedFile::new(in_path),
+        out_path,
+        prover_id_in,
+        sector_id_in,
+        &MemoryMeter::default(),
+        token,
+        &SealThrottle::default(),
+        None,
+        SealMode::Prove,
+        || Ok(()),
+    )
+    .map(|output| output.expect("seals which prove return their output"))
+}
+
+/// Seals as seal_with_memory_limit does, but writes the replica through
+/// throttle, pausing between its chunks while retrievals are in flight.
+pub fn seal_with_throttle<T: Into<PathBuf> + AsRef<Path>>(
+    sector_config: &SectorConfig,
+    in_path: T,
+    out_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    max_memory_bytes: Option<u64>,
+    throttle: &SealThrottle,
+) -> error::Result<SealOutput> {
+    seal_from_reader_with_throttle(
+        sector_config,
+        StagedFile::new(in_path),
+        out_path,
+        prover_id_in,
+        sector_id_in,
+        max_memory_bytes,
+        throttle,
+        &CancellationToken::new(),
+    )
+}
+
+/// Seals as seal_with_throttle does, but reads the staged data from staged
+/// rather than a file, e.g. as a sector manager's open_unsealed decrypts it,
+/// and checks token at its checkpoints, as seal_with_cancellation does. Data
+/// read from a plain reader isn't checked for changes made while it's read.
+pub fn seal_from_reader_with_throttle<R: StagedSource, T: Into<PathBuf> + AsRef<Path>>(
+    sector_config: &SectorConfig,
+    staged: R,
+    out_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    max_memory_bytes: Option<u64>,
+    throttle: &SealThrottle,
+    token: &CancellationToken,
+) -> error::Result<SealOutput> {
+    seal_with_hook(
+        sector_config,
+        staged,
+        out_path,
+        prover_id_in,
+        sector_id_in,
+        &MemoryMeter::new(max_memory_bytes),
+        token,
+        throttle,
+        None,
+        SealMode::Prove,
+        || Ok(()),
+    )
+    .map(|output| output.expect("seals which prove return their output"))
+}
+
+/// Seals as seal does, and also writes a prover bundle (see api::prover_bundle)
+/// into bundle_dir once the sector is replicated, from which the seal can be
+/// proven again with prove_from_bundle, should proving fail, or from another
+/// process. When prove is false, the replica is published without being proven,
+/// and None is returned: proving is left to prove_from_bundle.
+pub fn seal_with_prover_bundle<T: Into<PathBuf> + AsRef<Path>>(
+    sector_config: &SectorConfig,
+    in_path: T,
+    out_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    bundle_dir: &Path,
+    prove: bool,
+) -> error::Result<Option<SealOutput>> {
+    let mode = if prove {
+        SealMode::BundleAndProve(bundle_dir)
+    } else {
+        SealMode::BundleOnly(bundle_dir)
+    };
+
+    seal_with_hook(
+        sector_config,
+        StagedFile::new(in_path),
+        out_path,
+        prover_id_in,
+        sector_id_in,
+        &MemoryMeter::default(),
+        &CancellationToken::new(),
+        &SealThrottle::default(),
+        None,
+        mode,
+        || Ok(()),
+    )
+}
+
+/// Proves the seal whose prover bundle is in bundle_dir, as the seal which
+/// wrote it would have. The bundle must have been written for the public params
+/// this version seals its sector size with.
+pub fn prove_from_bundle<T: AsRef<Path>>(bundle_dir: T) -> error::Result<SealOutput> {
+    let bundle = read_prover_bundle(bundle_dir)?;
+    let sector_config = new_sector_config_of_version(
+        bundle.sector_bytes,
+        bundle.proof_variant,
+        bundle.proof_version,
+    );
+
+    let verifier = seal_verifier(
+        bundle.sector_bytes as usize,
+        bundle.proof_variant,
+        bundle.proof_version,
+    )?;
+    let current = verifier
+        .public_params
+        .vanilla_params
+        .parameter_set_identifier();
+    if current != bundle.parameter_identifier {
+        return Err(ProverBundleErr::ParameterMismatch {
+            bundled: bundle.parameter_identifier,
+            current,
+        }
+        .into());
+    }
+
+    let start = Instant::now();
+    let meter = MemoryMeter::default();
+    meter
+        .reserve(SealPhase::BuildTrees, trees_bytes(&bundle.aux))?
+        .keep();
+    let output = prove_replica(
+        sector_config.as_ref(),
+        &verifier,
+        &bundle.prover_id,
+        &bundle.sector_id,
+        bundle.tau,
+        bundle.aux,
+        &meter,
+        &CancellationToken::new(),
+        || (),
+    )?;
+
+    info!(FCP_LOG, "proved seal from bundle"; "sector_id" => hex(&bundle.sector_id), "proof_bytes" => output.proof.len(), "elapsed" => format!("{:?}", start.elapsed()));
+
+    Ok(output)
+}
+
+/// Proves the seal of the sector sealed at sealed_path again, with
+/// target_version's parameters, without sealing it again: e.g. once the groth
+/// parameters it was proven with have been replaced. The sector's taus and
+/// trees are read from the prover bundle in bundle_dir, if one was kept (see
+/// seal_with_prover_bundle). Otherwise they're rebuilt from the sealed sector,
+/// which is unsealed and replicated again in memory. That takes about as long
+/// as replicating it did, but nothing is written.
+///
+/// Only sectors which target_version's seals replicate alike can be proven
+/// with it: a bundle made for other public params is refused with a
+/// ParameterMismatch error, and a sealed sector whose format trailer names
+/// other parameters with a SectorFormatMismatch. The sector_config gives the
+/// sector's size and proof variant, whatever its version.
+///
+/// The proof is verified, as a seal's is, but against the commitments of the
+/// sector as read: it's for callers to check that they're those recorded when
+/// the sector was sealed.
+pub fn regenerate_proof<T: AsRef<Path>>(
+    sector_config: &SectorConfig,
+    sealed_path: T,
+    bundle_dir: Option<&Path>,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    target_version: ProofVersion,
+    legacy: LegacyReplicas,
+) -> error::Result<SealOutput> {
+    let sector_bytes = sector_config.sector_bytes();
+    let proof_variant = sector_config.proof_variant();
+    let target_config = new_sector_config_of_version(sector_bytes, proof_variant, target_version);
+
+    let start = Instant::now();
+    let verifier = seal_verifier(sector_bytes as usize, proof_variant, target_version)?;
+    let vanilla_params = &verifier.public_params.vanilla_params;
+
+    let (tau, aux) = match bundle_dir {
+        Some(bundle_dir) => {
+            let bundle = read_prover_bundle(bundle_dir)?;
+            if bundle.prover_id != *prover_id_in
+                || bundle.sector_id != *sector_id_in
+                || bundle.sector_bytes != sector_bytes
+            {
+                return Err(ProverBundleErr::SectorMismatch.into());
+            }
+
+            let current = vanilla_params.parameter_set_identifier();
+            if current != bundle.parameter_identifier {
+                return Err(ProverBundleErr::ParameterMismatch {
+                    bundled: bundle.parameter_identifier,
+                    current,
+                }
+                .into());
+            }
+
+            (bundle.tau, bundle.aux)
+        }
+        None => {
+            let format = replica_format_for(sector_bytes as usize, target_version, vanilla_params);
+            let token = CancellationToken::new();
+            let replica = read_replica(sealed_path, &format, legacy, &token)?;
+
+            let replica_id = replica_id_domain(*prover_id_in, *sector_id_in);
+            let mut data = token.yielded(ZigZagDrgPoRep::extract_all(
+                vanilla_params,
+                &replica_id,
+                &replica,
+            ))?;
+            drop(replica);
+
+            token.yielded(ZigZagDrgPoRep::replicate(
+                vanilla_params,
+                &replica_id,
+                &mut data,
+                None,
+            ))?
+        }
+    };
+
+    let meter = MemoryMeter::default();
+    meter
+        .reserve(SealPhase::BuildTrees, trees_bytes(&aux))?
+        .keep();
+    let output = prove_replica(
+        target_config.as_ref(),
+        &verifier,
+        prover_id_in,
+        sector_id_in,
+        tau,
+        aux,
+        &meter,
+        &CancellationToken::new(),
+        || (),
+    )?;
+
+    info!(FCP_LOG, "regenerated seal proof"; "sector_id" => hex(sector_id_in), "proof_version" => format!("{:?}", target_version), "from_bundle" => bundle_dir.is_some(), "elapsed" => format!("{:?}", start.elapsed()));
+
+    Ok(output)
+}
+
+// What seal_with_hook does once it has replicated a sector.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SealMode<'a> {
+    Prove,
+    // Writes a prover bundle into the directory, then proves.
+    BundleAndProve(&'a Path),
+    // Writes a prover bundle into the directory, and publishes the replica
+    // unproven.
+    BundleOnly(&'a Path),
+}
+
+// Seals the data read from staged as seal does, reserving its large
+// allocations with meter, checking token at its checkpoints, writing the
+// replica through throttle, refusing proofs projected to be longer than
+// max_proof_bytes, and calling after_replication once the replica has been
+// written, but before it's sample-checked (if the sector_config asks for it)
+// and proven. Tests fail there to abandon a seal halfway, or damage the
+// written replica. Returns None only when the mode doesn't prove.
+#[allow(clippy::too_many_arguments)]
+fn seal_with_hook<R, T, F>(
+    sector_config: &SectorConfig,
+    staged: R,
+    out_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    meter: &MemoryMeter,
+    token: &CancellationToken,
+    throttle: &SealThrottle,
+    max_proof_bytes: Option<usize>,
+    mode: SealMode,
+    after_replication: F,
+) -> error::Result<Option<SealOutput>>
+where
+    R: StagedSource,
+    T: Into<PathBuf> + AsRef<Path>,
+    F: FnOnce() -> error::Result<()>,
+{
+    let sector_bytes = sector_config.sector_bytes() as usize;
+    let proof_variant = sector_config.proof_variant();
+    let proof_version = sector_config.proof_version();
+    let start = Instant::now();
+
+    info!(FCP_LOG, "seal started"; "sector_id" => hex(sector_id_in), "sector_bytes" => sector_bytes, "proof_variant" => format!("{:?}", proof_variant));
+
+    // The proof's length only depends on the config, so a proof which would be
+    // refused for it is refused before any of the work of making it is done.
+    let projected_proof_bytes = projected_seal_proof_bytes(sector_config);
+    check_proof_bytes(proof_variant, projected_proof_bytes, max_proof_bytes)?;
+
+    // Read all the provided data, even if we will prove less of it because we are faking.
+    let data_reservation = meter.reserve(SealPhase::ReadData, sector_bytes as u64)?;
+    let data = staged.read_staged(sector_bytes as u64, token)?;
+
+    let mut data = pad_sector_data(data, sector_bytes);
+
+    let replica_id = replica_id_domain(*prover_id_in, *sector_id_in);
+
+    // The verifier's public params are those seals are proven with, so sealing
+    // shares them rather than build the zigzag graph again.
+    let verifier = seal_verifier(sector_bytes, proof_variant, proof_version)?;
+    let vanilla_params = &verifier.public_params.vanilla_params;
+
+    token.checkpoint("replicating")?;
+
+    let (tau, aux) = token.yielded(memory::with_meter(meter, || {
+        ZigZagDrgPoRep::replicate(vanilla_params, &replica_id, &mut data, None)
+    }))?;
+
+    let format = replica_format_for(sector_bytes, proof_version, vanilla_params);
+
+    token.checkpoint("writing replica")?;
+
+    let replica = PendingFile::new(out_path);
+    replica.write(&[&data, &format.encode()], throttle)?;
+
+    info!(FCP_LOG, "replicated sector"; "sector_id" => hex(sector_id_in), "layers" => vanilla_params.layer_challenges.layers(), "elapsed" => format!("{:?}", start.elapsed()));
+
+    after_replication()?;
+
+    // Should the file not hold what was sealed, the seal fails here, and the
+    // file is removed with the pending replica.
+    if let Some(samples) = sector_config.post_seal_sample_check() {
+        token.checkpoint("sample-checking sealed file")?;
+
+        let check_start = Instant::now();
+        sample_check_replica(&replica.tmp_path, &data, samples, &mut rand::thread_rng())?;
+
+        info!(FCP_LOG, "sample-checked sealed file"; "sector_id" => hex(sector_id_in), "samples" => samples, "elapsed" => format!("{:?}", check_start.elapsed()));
+    }
+
+    let bundle = ProverBundle {
+        sector_bytes: sector_bytes as u64,
+        proof_variant,
+        proof_version,
+        prover_id: *prover_id_in,
+        sector_id: *sector_id_in,
+        replica_path: replica.path.clone(),
+        parameter_identifier: vanilla_params.parameter_set_identifier(),
+        tau,
+        aux,
+    };
+
+    if mode != SealMode::Prove {
+        token.checkpoint("writing prover bundle")?;
+    }
+
+    let compression = sector_config.aux_compression();
+
+    match mode {
+        SealMode::Prove => (),
+        SealMode::BundleAndProve(dir) => write_prover_bundle(dir, &bundle, compression)?,
+        SealMode::BundleOnly(dir) => {
+            write_prover_bundle(dir, &bundle, compression)?;
+            replica.publish()?;
+
+            info!(FCP_LOG, "replicated sector for proving from bundle"; "sector_id" => hex(sector_id_in), "bundle" => format!("{:?}", dir), "elapsed" => format!("{:?}", start.elapsed()));
+
+            return Ok(None);
+        }
+    }
+
+    let output = prove_replica(
+        sector_config,
+        &verifier,
+        prover_id_in,
+        sector_id_in,
+        bundle.tau,
+        bundle.aux,
+        meter,
+        token,
+        || {
+            drop(data);
+            drop(data_reservation);
+        },
+    )?;
+
+    replica.publish()?;
+
+    info!(FCP_LOG, "seal finished"; "sector_id" => hex(sector_id_in), "comm_r" => Comm(output.comm_r).to_string(), "comm_d" => Comm(output.comm_d).to_string(), "proof_bytes" => output.proof.len(), "peak_memory_bytes" => output.memory.peak_bytes, "elapsed" => format!("{:?}", start.elapsed()));
+
+    Ok(Some(output))
+}
+
+// Proves the replication of a sector, given the taus and trees replicating it
+// made, calling release_data once the vanilla proofs (which hold all that
+// proving takes from the sector) are made, so that the sector's data can be
+// freed with its trees, before any circuit is synthesized. The trees' bytes
+// must be kept reserved for BuildTrees with meter, which releases them once
+// they're freed. Checks token before each step of proving.
+#[allow(clippy::too_many_arguments)]
+fn prove_replica<F: FnOnce()>(
+    sector_config: &SectorConfig,
+    verifier: &SealVerifier,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    tau: layered_drgporep::Tau<PedersenDomain>,
+    aux: Vec<Tree>,
+    meter: &MemoryMeter,
+    token: &CancellationToken,
+    release_data: F,
+) -> error::Result<SealOutput> {
+    let sector_bytes = sector_config.sector_bytes() as usize;
+    let proof_variant = sector_config.proof_variant();
+    let proof_version = sector_config.proof_version();
+    let compound_public_params = &verifier.public_params;
+
+    let replica_id = replica_id_domain(*prover_id_in, *sector_id_in);
+    let public_tau = tau.simplify();
+
+    let public_inputs = layered_drgporep::PublicInputs {
+        replica_id,
+        challenge_seed: layered_drgporep::challenge_seed(&replica_id, &tau.comm_r_star),
+        tau: Some(public_tau),
+        comm_r_star: tau.comm_r_star,
+        k: None,
+    };
+
+    let private_inputs = layered_drgporep::PrivateInputs::<DefaultTreeHasher> {
+        aux,
+        tau: tau.layer_taus,
+    };
+
+    token.checkpoint("proving")?;
+
+    let vanilla_proofs = token.yielded(ZigZagDrgPoRep::prove_all_partitions(
+        &compound_public_params.vanilla_params,
+        &public_inputs,
+        &private_inputs,
+        POREP_PARTITIONS,
+    ))?;
+
+    // The vanilla proofs hold all that proving takes from the sector: the challenged nodes, their
+    // parents and their merkle paths. So the sector's data and trees are freed before any circuit
+    // is synthesized, rather than held alongside the circuits' assignments.
+    let tree_bytes = trees_bytes(&private_inputs.aux);
+    drop(private_inputs);
+    meter.release_kept(SealPhase::BuildTrees, tree_bytes);
+    release_data();
+
+    token.checkpoint("proving")?;
+
+    let proof = match proof_variant {
+        ProofVariant::Snark => {
+            let groth_params = zigzag_params_for(
+                sector_bytes,
+                proof_version,
+                &compound_public_params.vanilla_params,
+            )?;
+
+            // The partitions are proven in parallel.
+            let assignment_bytes = POREP_PARTITIONS as u64 * assignment_bytes(&groth_params);
+            let _assignment_reservation = meter.reserve(SealPhase::Prove, assignment_bytes)?;
+
+            let proof = token.yielded(ZigZagCompound::prove_vanilla_proofs(
+                compound_public_params,
+                &public_inputs,
+                &vanilla_proofs,
+                Some(groth_params),
+            ))?;
+
+            let mut buf = Vec::with_capacity(POREP_PROOF_BYTES);
+            proof.write(&mut buf)?;
+            buf
+        }
+        ProofVariant::Vanilla => serde_cbor::to_vec(&vanilla_proofs)?,
+    };
+
+    let proof = seal_versioned_envelope(proof_version, proof_variant, &proof);
+
+    let comm_r = commitment_from_fr(public_tau.comm_r.into());
+    let comm_d = commitment_from_fr(public_tau.comm_d.into());
+    let comm_r_star = commitment_from_fr(tau.comm_r_star.into());
+
+    token.checkpoint("verifying")?;
+
+    // Verification is cheap when parameters are cached,
+    // and it is never correct to return a proof which does not verify.
+    verify_seal(
+        sector_config,
         comm_r,
         comm_d,
         comm_r_star,
         prover_id_in,
         sector_id_in,
-        &proof_bytes,
+        &proof,
     )
     .expect("post-seal verification sanity check failed");
 
@@ -446,538 +1571,2855 @@ pub fn seal<T: Into<PathBuf> + AsRef<Path>>(
         comm_r,
         comm_r_star,
         comm_d,
-        snark_proof: proof_bytes,
+        proof,
+        memory: meter.report(),
     })
 }
 
-fn write_data<T: AsRef<Path>>(out_path: T, data: &[u8]) -> error::Result<()> {
-    // Write replicated data to out_path.
-    let f_out = File::create(out_path)?;
-    let mut buf_writer = BufWriter::new(f_out);
-    buf_writer.write_all(&data)?;
-    Ok(())
-}
+// The bytes the merkle trees of a replication take.
+fn trees_bytes(aux: &[Tree]) -> u64 {
+    aux.iter()
+        .map(|tree| memory::merkle_tree_bytes::<DefaultTreeHasher>(tree.leafs()))
+        .sum()
+}
+
+// The bytes of a prover or sector id, as logged.
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// Estimates the memory taken to assign and prove a circuit with the given groth
+// parameters: the value of each of its variables, and the evaluations of its
+// constraints' three linear combinations over the proving domain.
+fn assignment_bytes(groth_params: &groth16::Parameters<Bls12>) -> u64 {
+    let variables = groth_params.a.len();
+    let domain = groth_params.h.len() + 1;
+
+    ((variables + 3 * domain) * mem::size_of::<Fr>()) as u64
+}
+
+/// Moves a sealed sector from landing_path, e.g. on fast scratch storage, to
+/// sealed_path, which may be on another file system. The sector is copied to a
+/// temporary file beside sealed_path and renamed once synced, so sealed_path
+/// never holds a partial sector.
+pub fn publish_sealed_sector<T: AsRef<Path>>(landing_path: T, sealed_path: T) -> error::Result<()> {
+    publish_sealed_sector_with_throttle(landing_path, sealed_path, &SealThrottle::default())
+}
+
+/// Publishes a sealed sector as publish_sealed_sector does, but copies it
+/// through throttle, pausing between its chunks while retrievals are in flight.
+pub fn publish_sealed_sector_with_throttle<T: AsRef<Path>>(
+    landing_path: T,
+    sealed_path: T,
+    throttle: &SealThrottle,
+) -> error::Result<()> {
+    let sealed = PendingFile::new(sealed_path);
+    sealed.copy_from(&landing_path, throttle)?;
+    sealed.publish()?;
+
+    fs::remove_file(landing_path)?;
+
+    Ok(())
+}
+
+// A file written under a temporary name beside the path it's destined for,
+// which is removed (even when unwinding from a panic) unless it's published.
+struct PendingFile {
+    path: PathBuf,
+    tmp_path: PathBuf,
+}
+
+impl PendingFile {
+    fn new<T: AsRef<Path>>(path: T) -> PendingFile {
+        let path = path.as_ref().to_path_buf();
+
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(format!(".tmp.{}", rand_alpha_string(16)));
+
+        PendingFile {
+            path,
+            tmp_path: tmp_path.into(),
+        }
+    }
+
+    fn write(&self, chunks: &[&[u8]], throttle: &SealThrottle) -> error::Result<()> {
+        let mut file = File::create(&self.tmp_path)?;
+        for chunk in chunks {
+            throttle.write_all(&mut file, chunk)?;
+        }
+        file.sync_all()?;
+
+        Ok(())
+    }
+
+    fn copy_from<T: AsRef<Path>>(&self, from: T, throttle: &SealThrottle) -> error::Result<()> {
+        let mut file = File::create(&self.tmp_path)?;
+        throttle.copy(&mut File::open(from)?, &mut file)?;
+        file.sync_all()?;
+
+        Ok(())
+    }
+
+    // Renames the file over whatever is at its destined path, and syncs the
+    // rename.
+    fn publish(self) -> error::Result<()> {
+        fail_point!(failpoint::SEALED_FILE_PUBLISH);
+        rename_durably(&self.tmp_path, &self.path)?;
+
+        Ok(())
+    }
+}
+
+impl Drop for PendingFile {
+    fn drop(&mut self) {
+        // Once published, there's nothing left to remove.
+        let _ = fs::remove_file(&self.tmp_path);
+    }
+}
+
+/// Zero-pads data, the preprocessed contents of a staged sector, to the
+/// sector's size, ending with the trailer which records how many bytes of
+/// unpadded data the sector holds. Whatever doesn't fit before the trailer is
+/// dropped.
+pub fn pad_sector_data(mut data: Vec<u8>, sector_bytes: usize) -> Vec<u8> {
+    let data_bytes = data_padded_bytes(sector_bytes as u64) as usize;
+    data.truncate(data_bytes);
+
+    let trailer = encode_trailer(unpadded_bytes(data.len() as u64));
+    data.resize(data_bytes, 0);
+    data.extend_from_slice(&trailer);
+
+    data
+}
+
+/// Computes comm_d of a staged sector from its preprocessed contents as
+/// they're streamed in, padded as pad_sector_data pads them, holding only a
+/// few nodes of them at a time.
+pub struct CommDBuilder {
+    commitment: StreamingCommitment<DefaultTreeHasher>,
+    data_bytes: usize,
+    written: usize,
+}
+
+impl CommDBuilder {
+    pub fn new(sector_bytes: usize) -> error::Result<CommDBuilder> {
+        Ok(CommDBuilder {
+            commitment: StreamingCommitment::new(sector_bytes, NODE_SIZE)?,
+            data_bytes: data_padded_bytes(sector_bytes as u64) as usize,
+            written: 0,
+        })
+    }
+
+    /// Appends bytes to the sector's contents. Whatever doesn't fit before the
+    /// trailer is dropped.
+    pub fn update(&mut self, bytes: &[u8]) -> error::Result<()> {
+        let n = cmp::min(self.data_bytes - self.written, bytes.len());
+        self.commitment.update(&bytes[..n])?;
+        self.written += n;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> error::Result<Commitment> {
+        let trailer = encode_trailer(unpadded_bytes(self.written as u64));
+
+        let zeros = [0; NODE_SIZE];
+        while self.written < self.data_bytes {
+            let n = cmp::min(NODE_SIZE, self.data_bytes - self.written);
+            self.commitment.update(&zeros[..n])?;
+            self.written += n;
+        }
+        self.commitment.update(&trailer)?;
+
+        Ok(self.commitment.finish()?)
+    }
+}
+
+/// Computes comm_d of a staged sector from its preprocessed contents, read
+/// from data, as CommDBuilder does.
+pub fn compute_comm_d<R: Read>(data: R, sector_bytes: usize) -> error::Result<Commitment> {
+    let mut comm_d = CommDBuilder::new(sector_bytes)?;
+    let mut data = data.take(data_padded_bytes(sector_bytes as u64));
+    let mut buf = [0; 1 << 13];
+
+    loop {
+        match data.read(&mut buf)? {
+            0 => break,
+            n => comm_d.update(&buf[..n])?,
+        }
+    }
+
+    comm_d.finish()
+}
+
+/// Checks the format trailer of the sealed sector at sealed_path against the
+/// format sectors of the sector_config's size are sealed in, failing with a
+/// SectorFormatMismatch (from api::replica_format) if they differ. Sectors
+/// without a trailer fail unless legacy is LegacyReplicas::Accept.
+pub fn check_sealed_sector<T: AsRef<Path>>(
+    sector_config: &SectorConfig,
+    sealed_path: T,
+    legacy: LegacyReplicas,
+) -> error::Result<()> {
+    let sector_bytes = sector_config.sector_bytes() as usize;
+    let version = sector_config.proof_version();
+    let format = replica_format_for(
+        sector_bytes,
+        version,
+        &public_params_for_version(sector_bytes, version),
+    );
+
+    check_replica(sealed_path, &format, legacy)
+}
+
+/// Unseals num_bytes bytes of the sector's data starting at offset, writing
+/// them to output_path, and returns the number of bytes written. The range is
+/// clamped to the data recorded in the sector's trailer; sectors sealed
+/// without one are unsealed in full, padding included.
+///
+/// The sealed sector is first checked as check_sealed_sector checks it, and
+/// output_path is only created once the range is unsealed.
+#[allow(clippy::too_many_arguments)]
+pub fn get_unsealed_range<T: Into<PathBuf> + AsRef<Path>>(
+    sector_config: &SectorConfig,
+    sealed_path: T,
+    output_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    offset: u64,
+    num_bytes: u64,
+    legacy: LegacyReplicas,
+) -> error::Result<(u64)> {
+    let unsealed = unseal_range(
+        sector_config,
+        sealed_path,
+        prover_id_in,
+        sector_id_in,
+        offset,
+        num_bytes,
+        legacy,
+    )?;
+
+    let mut f_out = File::create(output_path)?;
+    f_out.write_all(&unsealed)?;
+
+    Ok(unsealed.len() as u64)
+}
+
+/// Unseals the range get_unsealed_range unseals into files of shard_size bytes
+/// in output_dir, named shard-0, shard-1 and so on, rather than into a single
+/// file, so that each may be handed to another transfer stream. Returns the
+/// path and length of each file, in order: every shard but the last is full,
+/// and an empty range makes none.
+///
+/// Should writing a shard fail, or token be cancelled or time out before the
+/// range is unsealed, the shards written so far are removed. The token is
+/// checked between the chunks of the sealed sector read, and between the steps
+/// of unsealing.
+#[allow(clippy::too_many_arguments)]
+pub fn get_unsealed_range_sharded<T: AsRef<Path>>(
+    sector_config: &SectorConfig,
+    sealed_path: T,
+    output_dir: T,
+    shard_size: u64,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    offset: u64,
+    num_bytes: u64,
+    legacy: LegacyReplicas,
+    token: &CancellationToken,
+) -> error::Result<Vec<(PathBuf, u64)>> {
+    if shard_size == 0 {
+        return Err(format_err!("shard_size must be non-zero"));
+    }
+
+    let mut shards = ShardWriter::new(output_dir, shard_size);
+
+    let unsealed = unseal_range_into(
+        sector_config,
+        sealed_path,
+        prover_id_in,
+        sector_id_in,
+        offset,
+        num_bytes,
+        legacy,
+        token,
+        &mut shards,
+    )
+    .and_then(|_| Ok(shards.flush()?));
+
+    match unsealed {
+        Ok(()) => Ok(shards.shards),
+        Err(err) => {
+            for (path, _) in &shards.shards {
+                let _ = fs::remove_file(path);
+            }
+
+            Err(err)
+        }
+    }
+}
+
+// Writes whatever's written to it into files of shard_size bytes in a
+// directory, each created as the first of its bytes is written.
+struct ShardWriter {
+    dir: PathBuf,
+    shard_size: u64,
+    file: Option<File>,
+    shards: Vec<(PathBuf, u64)>,
+}
+
+impl ShardWriter {
+    fn new<T: AsRef<Path>>(dir: T, shard_size: u64) -> ShardWriter {
+        ShardWriter {
+            dir: dir.as_ref().to_path_buf(),
+            shard_size,
+            file: None,
+            shards: Vec::new(),
+        }
+    }
+}
+
+impl Write for ShardWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        let mut room = self.shards.last().map_or(0, |(_, len)| self.shard_size - len);
+        if room == 0 {
+            let path = self.dir.join(format!("shard-{}", self.shards.len()));
+            self.file = Some(File::create(&path)?);
+            self.shards.push((path, 0));
+            room = self.shard_size;
+        }
+
+        // Bytes past the end of the shard are left for the next one, which a
+        // later call opens.
+        let file = self.file.as_mut().expect("a shard is open");
+        let written = file.write(&buf[..cmp::min(room, buf.len() as u64) as usize])?;
+
+        if let Some((_, len)) = self.shards.last_mut() {
+            *len += written as u64;
+        }
+
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.file.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Unseals the range get_unsealed_range unseals, and returns its bytes rather
+/// than writing them anywhere, so that sectors may be read from directories
+/// which can't be written to.
+pub fn unseal_range<T: AsRef<Path>>(
+    sector_config: &SectorConfig,
+    sealed_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    offset: u64,
+    num_bytes: u64,
+    legacy: LegacyReplicas,
+) -> error::Result<Vec<u8>> {
+    unseal_range_with_cancellation(
+        sector_config,
+        sealed_path,
+        prover_id_in,
+        sector_id_in,
+        offset,
+        num_bytes,
+        legacy,
+        &CancellationToken::new(),
+    )
+}
+
+/// Unseals the range unseal_range unseals, but checks token between the chunks
+/// of the sealed sector read, and between the steps of unsealing, failing with
+/// an Interrupted error once it has been cancelled or has timed out.
+#[allow(clippy::too_many_arguments)]
+pub fn unseal_range_with_cancellation<T: AsRef<Path>>(
+    sector_config: &SectorConfig,
+    sealed_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    offset: u64,
+    num_bytes: u64,
+    legacy: LegacyReplicas,
+    token: &CancellationToken,
+) -> error::Result<Vec<u8>> {
+    let mut unpadded = Vec::new();
+
+    unseal_range_into(
+        sector_config,
+        sealed_path,
+        prover_id_in,
+        sector_id_in,
+        offset,
+        num_bytes,
+        legacy,
+        token,
+        &mut unpadded,
+    )?;
+
+    Ok(unpadded)
+}
+
+/// Unseals the range get_unsealed_range unseals, writing its bytes to out as
+/// they're unpadded rather than returning them, and checking token at its
+/// checkpoints, so that they may be streamed on, e.g. into another sector.
+#[allow(clippy::too_many_arguments)]
+pub fn unseal_range_into<T: AsRef<Path>, W: Write>(
+    sector_config: &SectorConfig,
+    sealed_path: T,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    offset: u64,
+    num_bytes: u64,
+    legacy: LegacyReplicas,
+    token: &CancellationToken,
+    out: &mut W,
+) -> error::Result<()> {
+    let sector_bytes = sector_config.sector_bytes() as usize;
+    let proof_version = sector_config.proof_version();
+
+    let replica_id = replica_id_domain(*prover_id_in, *sector_id_in);
+
+    let pp = public_params_for_version(sector_bytes, proof_version);
+
+    let format = replica_format_for(sector_bytes, proof_version, &pp);
+    let data = read_replica(sealed_path, &format, legacy, token)?;
+
+    token.checkpoint("unsealing")?;
+
+    let trailer_node = (data.len() - TRAILER_BYTES as usize) / NODE_SIZE;
+    let trailer = token.yielded(ZigZagDrgPoRep::extract_range(
+        &pp,
+        &replica_id,
+        &data,
+        trailer_node..trailer_node + 1,
+    ))?;
+
+    let num_bytes = match decode_trailer(&trailer) {
+        Some(data_bytes) => cmp::min(num_bytes, data_bytes.saturating_sub(offset)),
+        None => num_bytes,
+    };
+
+    if num_bytes == 0 {
+        return Ok(());
+    }
+
+    // 127 unpadded bytes preprocess to exactly four nodes, so the nodes holding
+    // the range start and end on a multiple of four, and only those need to be
+    // extracted.
+    let first_node = (offset / 127) as usize * 4;
+    let end_node = cmp::min(
+        ((offset + num_bytes + 126) / 127) as usize * 4,
+        data.len() / NODE_SIZE,
+    );
+
+    let unsealed = token.yielded(ZigZagDrgPoRep::extract_range(
+        &pp,
+        &replica_id,
+        &data,
+        first_node..end_node,
+    ))?;
+
+    token.checkpoint("writing unsealed range")?;
+
+    write_unpadded(&unsealed, out, (offset % 127) as usize, num_bytes as usize)?;
+
+    Ok(())
+}
+
+/// Verifies a proof produced by seal. The proof's envelope must name the
+/// sector_config's proof variant: a proof of any other variant is refused with
+/// SealProofErr::VariantMismatch. Proofs made by earlier releases, in formats
+/// frozen in api::legacy, are verified as those releases verified them. A proof
+/// or commitments which couldn't verify, e.g. zeroes, are refused with a
+/// RejectedInput before anything is set up to verify them (see api::screening).
+pub fn verify_seal(
+    sector_config: &SectorConfig,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    proof_vec: &[u8],
+) -> error::Result<bool> {
+    verify_seal_with_limit(
+        sector_config,
+        comm_r,
+        comm_d,
+        comm_r_star,
+        prover_id_in,
+        sector_id_in,
+        proof_vec,
+        None,
+    )
+}
+
+/// Verifies a proof as verify_seal does, but first checks that comm_d is the
+/// commitment to the given pieces, each a comm_p and a length, in the order
+/// add_piece staged them. comm_d can only be derived from the pieces' comm_ps
+/// if add_piece laid them out in the aligned layout (see api::piece_layout):
+/// pieces which it didn't are refused with PieceLayoutErr::NotAligned, and a
+/// comm_d which isn't their commitment with PieceLayoutErr::CommDMismatch,
+/// before the proof is looked at.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_seal_with_pieces(
+    sector_config: &SectorConfig,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    proof_vec: &[u8],
+    pieces: &[(Commitment, u64)],
+) -> error::Result<bool> {
+    let sector_bytes = sector_config.sector_bytes() as usize;
+    let piece_lengths: Vec<u64> = pieces.iter().map(|&(_, length)| length).collect();
+
+    if !packed_layout_is_aligned(sector_bytes, &piece_lengths) {
+        return Err(PieceLayoutErr::NotAligned(piece_lengths).into());
+    }
+
+    if !compute_comm_d_from_pieces(sector_bytes, pieces)?.ct_eq(&comm_d) {
+        return Err(PieceLayoutErr::CommDMismatch.into());
+    }
+
+    verify_seal(
+        sector_config,
+        comm_r,
+        comm_d,
+        comm_r_star,
+        prover_id_in,
+        sector_id_in,
+        proof_vec,
+    )
+}
+
+/// Verifies a proof as verify_seal does, but refuses one longer than
+/// max_proof_bytes (if given), envelope included, with SealProofErr::TooLarge,
+/// before any of it is decoded.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_seal_with_limit(
+    sector_config: &SectorConfig,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    proof_vec: &[u8],
+    max_proof_bytes: Option<usize>,
+) -> error::Result<bool> {
+    verify_seal_with_legacy_support(
+        sector_config,
+        comm_r,
+        comm_d,
+        comm_r_star,
+        prover_id_in,
+        sector_id_in,
+        proof_vec,
+        max_proof_bytes,
+        &LegacyProofSupport::default(),
+    )
+}
+
+/// Verifies a proof as verify_seal_with_limit does, but accepts the proofs of
+/// earlier releases, made in formats since frozen in api::legacy, only if
+/// legacy accepts their format, refusing the others with
+/// SealProofErr::FormatNotAccepted. verify_seal accepts every frozen format.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_seal_with_legacy_support(
+    sector_config: &SectorConfig,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    proof_vec: &[u8],
+    max_proof_bytes: Option<usize>,
+    legacy: &LegacyProofSupport,
+) -> error::Result<bool> {
+    let verified = verify_seal_proof(
+        sector_config,
+        comm_r,
+        comm_d,
+        comm_r_star,
+        prover_id_in,
+        sector_id_in,
+        proof_vec,
+        max_proof_bytes,
+        legacy,
+    );
+
+    match verified {
+        Ok(is_valid) => {
+            info!(FCP_LOG, "verified seal"; "sector_id" => hex(sector_id_in), "valid" => is_valid);
+        }
+        Err(ref err) => {
+            warn!(FCP_LOG, "could not verify seal"; "sector_id" => hex(sector_id_in), "error" => format!("{}", err));
+        }
+    }
+
+    verified
+}
+
+#[allow(clippy::too_many_arguments)]
+fn verify_seal_proof(
+    sector_config: &SectorConfig,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    proof_vec: &[u8],
+    max_proof_bytes: Option<usize>,
+    legacy: &LegacyProofSupport,
+) -> error::Result<bool> {
+    let sector_bytes = sector_config.sector_bytes() as usize;
+
+    check_proof_bytes(sector_config.proof_variant(), proof_vec.len(), max_proof_bytes)?;
+    screen_proof(proof_vec)?;
+
+    let (format, proof_version, proof_variant, proof_vec) = open_formatted_envelope(proof_vec)?;
+
+    // The version first, so that a proof of parameters this config doesn't
+    // use (e.g. Mini's) is refused as such, whatever its variant.
+    if proof_version != sector_config.proof_version() {
+        return Err(err_version_mismatch(sector_config.proof_version(), proof_version).into());
+    }
+
+    if proof_variant != sector_config.proof_variant() {
+        return Err(err_variant_mismatch(sector_config.proof_variant(), proof_variant).into());
+    }
+
+    screen_seal_inputs(comm_r, comm_d, comm_r_star, proof_vec)?;
+
+    if format != PROOF_FORMAT {
+        return legacy.verify_seal(
+            format,
+            sector_bytes,
+            proof_version,
+            proof_variant,
+            comm_r,
+            comm_d,
+            comm_r_star,
+            prover_id_in,
+            sector_id_in,
+            proof_vec,
+        );
+    }
+
+    if proof_variant == ProofVariant::Snark {
+        check_snark_proof_bytes(proof_vec)?;
+    }
+
+    let public_inputs =
+        seal_public_inputs(comm_r, comm_d, comm_r_star, prover_id_in, sector_id_in)?;
+
+    let verifier = seal_verifier(sector_bytes, proof_variant, proof_version)?;
+
+    if proof_variant == ProofVariant::Vanilla {
+        let public_params = &verifier.public_params.vanilla_params;
+        return verify_vanilla_seal(public_params, &public_inputs, proof_vec);
+    }
+
+    let proofs = read_snark_proofs(proof_vec)?;
+
+    let pvk = verifier.pvk.as_ref().expect("SNARK verifiers hold a verifying key");
+
+    ZigZagCompound::verify_with_prepared_key(&verifier.public_params, &public_inputs, pvk, &proofs)
+        .map_err(|e| e.into())
+}
+
+// Refuses the commitments and the proof (out of its envelope) of a seal which
+// couldn't verify, of whichever format, before anything is looked up or built
+// to verify them. The whole envelope is screened before it's opened, as one
+// of zeroes opens as a proof of the earliest format, but the proof may well be
+// zeroes when its tag isn't.
+fn screen_seal_inputs(
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    proof_vec: &[u8],
+) -> Result<(), RejectedInput> {
+    screen_proof(proof_vec)?;
+    screen_commitment("comm_r", &comm_r)?;
+    screen_commitment("comm_d", &comm_d)?;
+    screen_commitment("comm_r_star", &comm_r_star)
+}
+
+/// What verify_seal_with_scratch keeps from one call to the next: the verifier
+/// it last looked up, and buffers for the SNARK proofs it decodes and for the
+/// public inputs of each partition. Creating one allocates nothing. It isn't
+/// Sync, so each thread verifying seals creates its own.
+#[derive(Default)]
+pub struct VerifierScratch {
+    verifier: Option<(SealVerifierKey, Arc<SealVerifier>)>,
+    proofs: Vec<groth16::Proof<Bls12>>,
+    inputs: Vec<Fr>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl VerifierScratch {
+    pub fn new() -> VerifierScratch {
+        Default::default()
+    }
+
+    // The verifier of seals of sectors of sector_bytes proven with variant and
+    // version's parameters, which is only looked up in the shared cache when
+    // any of them changes.
+    fn verifier(
+        &mut self,
+        sector_bytes: usize,
+        variant: ProofVariant,
+        version: ProofVersion,
+    ) -> error::Result<Arc<SealVerifier>> {
+        let key = (sector_bytes, variant, version);
+
+        if let Some((cached, ref verifier)) = self.verifier {
+            if cached == key {
+                return Ok(verifier.clone());
+            }
+        }
+
+        let verifier = seal_verifier(sector_bytes, variant, version)?;
+        self.verifier = Some((key, verifier.clone()));
+
+        Ok(verifier)
+    }
+}
+
+/// Verifies a proof as verify_seal does, accepting and refusing the same
+/// proofs, but decodes the proof and lays out the public inputs of each of its
+/// partitions in scratch's buffers rather than in new ones, and reuses the
+/// verifier scratch last used. For verifiers checking many seals in a loop.
+/// Unlike verify_seal, it doesn't log each verification.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_seal_with_scratch(
+    scratch: &mut VerifierScratch,
+    sector_config: &SectorConfig,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    proof_vec: &[u8],
+) -> error::Result<bool> {
+    verify_seal_reusing(
+        scratch,
+        None,
+        sector_config,
+        comm_r,
+        comm_d,
+        comm_r_star,
+        prover_id_in,
+        sector_id_in,
+        proof_vec,
+    )
+}
+
+/// Why verify_seal_prealloc refused a proof its handle has no room for.
+#[derive(Debug, Fail, PartialEq)]
+pub enum CapacityExceeded {
+    #[fail(
+        display = "the proof proves {} challenges, but the handle has room for {}",
+        needed, capacity
+    )]
+    Challenges { needed: usize, capacity: usize },
+
+    #[fail(
+        display = "the proof has {} partitions, but the handle has room for {}",
+        needed, capacity
+    )]
+    Partitions { needed: usize, capacity: usize },
+}
+
+/// A VerifierScratch whose buffers are allocated when it's created, with room
+/// for the SNARK proofs of seals of up to max_partitions partitions, whose
+/// circuits prove up to max_challenges challenges in all (the sum of each
+/// layer's, as seal_proof_capacity returns them), so that verify_seal_prealloc
+/// never grows them.
+///
+/// The public inputs of a partition are comm_d, comm_r and comm_r_star, and for
+/// each layer the replica id and, for each challenge, the auth paths of the
+/// challenged node, of each of its parents and of the node in the data tree.
+/// Every layer proves a challenge at least, so the input buffer holds
+/// 3 + max_challenges * (parents + 3) inputs, where parents is the most a node
+/// has in any version's graphs. The proof buffer holds max_partitions proofs.
+pub struct VerifierHandle {
+    scratch: VerifierScratch,
+    capacity: VerifierCapacity,
+}
+
+impl VerifierHandle {
+    pub fn with_capacity(max_challenges: usize, max_partitions: usize) -> VerifierHandle {
+        let max_inputs = 3 + max_challenges * (max_challenge_parents() + 3);
+
+        VerifierHandle {
+            scratch: VerifierScratch {
+                proofs: Vec::with_capacity(max_partitions),
+                inputs: Vec::with_capacity(max_inputs),
+                ..Default::default()
+            },
+            capacity: VerifierCapacity {
+                max_challenges,
+                max_partitions,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct VerifierCapacity {
+    max_challenges: usize,
+    max_partitions: usize,
+}
+
+impl VerifierCapacity {
+    // Refuses the SNARK proofs of seals of sectors of sector_bytes, proven
+    // with version's parameters, which there's no room for.
+    fn check(&self, sector_bytes: usize, version: ProofVersion) -> error::Result<()> {
+        let (challenges, partitions) = seal_proof_extent(sector_bytes, version)?;
+
+        if challenges > self.max_challenges {
+            return Err(CapacityExceeded::Challenges {
+                needed: challenges,
+                capacity: self.max_challenges,
+            }
+            .into());
+        }
+
+        if partitions > self.max_partitions {
+            return Err(CapacityExceeded::Partitions {
+                needed: partitions,
+                capacity: self.max_partitions,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+// The most parents a challenged node has, in the graphs of any version.
+fn max_challenge_parents() -> usize {
+    [V0_TEST, V1_ALPHA, MINI]
+        .iter()
+        .map(|params| params.degree + params.expansion_degree)
+        .max()
+        .unwrap_or(0)
+}
+
+// The challenges (of all layers) and partitions of the SNARK proof of a seal
+// of a sector of sector_bytes, proven with version's parameters.
+fn seal_proof_extent(sector_bytes: usize, version: ProofVersion) -> error::Result<(usize, usize)> {
+    let layer_challenges = setup_params(sector_bytes, version)?.layer_challenges;
+
+    Ok((layer_challenges.total_challenges(), POREP_PARTITIONS))
+}
+
+/// Returns the challenges (of all layers) and partitions a VerifierHandle
+/// needs room for to verify the SNARK proofs of seals with the given config.
+pub fn seal_proof_capacity(sector_config: &SectorConfig) -> error::Result<(usize, usize)> {
+    seal_proof_extent(
+        sector_config.sector_bytes() as usize,
+        sector_config.proof_version(),
+    )
+}
+
+/// Verifies a proof as verify_seal_with_scratch does, in the buffers handle
+/// allocated when it was created, which it never grows: a SNARK proof the
+/// handle has no room for is refused with CapacityExceeded before it's
+/// decoded.
+///
+/// Other allocations remain, outside the handle: hashing the prover and sector
+/// ids into the replica id, and it into the challenge seed; deriving each
+/// layer's challenges and their parents, which storage_proofs collects into
+/// vectors of their own; and verifying each partition's groth proof, as
+/// bellman prepares the proof's B element and pairing's Miller loop collects
+/// its pairs into vectors. Vanilla proofs, which serde_cbor decodes as it
+/// reads them, and proofs of earlier formats are verified as
+/// verify_seal_with_scratch verifies them, whatever the handle's capacity.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_seal_prealloc(
+    handle: &mut VerifierHandle,
+    sector_config: &SectorConfig,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    proof_vec: &[u8],
+) -> error::Result<bool> {
+    verify_seal_reusing(
+        &mut handle.scratch,
+        Some(handle.capacity),
+        sector_config,
+        comm_r,
+        comm_d,
+        comm_r_star,
+        prover_id_in,
+        sector_id_in,
+        proof_vec,
+    )
+}
+
+// As verify_seal_with_scratch, refusing the SNARK proofs which capacity, if
+// given, has no room for.
+#[allow(clippy::too_many_arguments)]
+fn verify_seal_reusing(
+    scratch: &mut VerifierScratch,
+    capacity: Option<VerifierCapacity>,
+    sector_config: &SectorConfig,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+    proof_vec: &[u8],
+) -> error::Result<bool> {
+    let sector_bytes = sector_config.sector_bytes() as usize;
+
+    check_proof_bytes(sector_config.proof_variant(), proof_vec.len(), None)?;
+    screen_proof(proof_vec)?;
+
+    let (format, proof_version, proof_variant, proof_vec) = open_formatted_envelope(proof_vec)?;
+
+    if proof_version != sector_config.proof_version() {
+        return Err(err_version_mismatch(sector_config.proof_version(), proof_version).into());
+    }
+
+    if proof_variant != sector_config.proof_variant() {
+        return Err(err_variant_mismatch(sector_config.proof_variant(), proof_variant).into());
+    }
+
+    screen_seal_inputs(comm_r, comm_d, comm_r_star, proof_vec)?;
+
+    // Proofs of earlier formats are rare enough not to need scratch.
+    if format != PROOF_FORMAT {
+        return LegacyProofSupport::default().verify_seal(
+            format,
+            sector_bytes,
+            proof_version,
+            proof_variant,
+            comm_r,
+            comm_d,
+            comm_r_star,
+            prover_id_in,
+            sector_id_in,
+            proof_vec,
+        );
+    }
+
+    if proof_variant == ProofVariant::Snark {
+        check_snark_proof_bytes(proof_vec)?;
+
+        if let Some(capacity) = capacity {
+            capacity.check(sector_bytes, proof_version)?;
+        }
+    }
+
+    let public_inputs =
+        seal_public_inputs(comm_r, comm_d, comm_r_star, prover_id_in, sector_id_in)?;
+
+    let verifier = scratch.verifier(sector_bytes, proof_variant, proof_version)?;
+    let public_params = &verifier.public_params;
+
+    if proof_variant == ProofVariant::Vanilla {
+        return verify_vanilla_seal(&public_params.vanilla_params, &public_inputs, proof_vec);
+    }
+
+    read_snark_proofs_into(proof_vec, &mut scratch.proofs)?;
+
+    let pvk = verifier.pvk.as_ref().expect("SNARK verifiers hold a verifying key");
+
+    // As ZigZagCompound::verify_with_prepared_key verifies them.
+    if scratch.proofs.len() != ZigZagCompound::partition_count(public_params) {
+        return Ok(false);
+    }
+
+    for (k, proof) in scratch.proofs.iter().enumerate() {
+        ZigZagCompound::public_inputs_into::<DefaultTreeHasher>(
+            &public_inputs,
+            &public_params.vanilla_params,
+            Some(k),
+            &mut scratch.inputs,
+        );
+
+        if !groth16::verify_proof(pvk, proof, &scratch.inputs)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+type SealPublicInputs = layered_drgporep::PublicInputs<<DefaultTreeHasher as Hasher>::Domain>;
+
+// The public inputs a seal's proof is verified against, for every partition.
+fn seal_public_inputs(
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+) -> error::Result<SealPublicInputs> {
+    let replica_id = replica_id_domain(*prover_id_in, *sector_id_in);
+
+    let comm_r = fr_from_commitment(&comm_r)?;
+    let comm_d = fr_from_commitment(&comm_d)?;
+    let comm_r_star = fr_from_commitment(&comm_r_star)?;
+
+    Ok(SealPublicInputs {
+        replica_id,
+        challenge_seed: layered_drgporep::challenge_seed(&replica_id, &comm_r_star.into()),
+        tau: Some(Tau {
+            comm_r: comm_r.into(),
+            comm_d: comm_d.into(),
+        }),
+        comm_r_star: comm_r_star.into(),
+        k: None,
+    })
+}
+
+/// Returns the public inputs of the circuit of each partition of a seal's
+/// SNARK proof, in the order ZigZagCompound::public_input_vector documents,
+/// so that external verifiers can check the inputs they derive against ours.
+pub fn seal_public_input_vectors(
+    sector_config: &SectorConfig,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    prover_id_in: &FrSafe,
+    sector_id_in: &FrSafe,
+) -> error::Result<Vec<Vec<Fr>>> {
+    let public_params = public_params_for_version(
+        sector_config.sector_bytes() as usize,
+        sector_config.proof_version(),
+    );
+    let mut public_inputs =
+        seal_public_inputs(comm_r, comm_d, comm_r_star, prover_id_in, sector_id_in)?;
+
+    let mut vectors = Vec::with_capacity(POREP_PARTITIONS);
+    for k in 0..POREP_PARTITIONS {
+        public_inputs.k = Some(k);
+
+        vectors.push(ZigZagCompound::public_input_vector::<DefaultTreeHasher>(
+            &public_inputs,
+            &public_params,
+        ));
+    }
+
+    Ok(vectors)
+}
+
+/// Reads the Groth16 proof of each partition from a SNARK proof (taken out of its envelope),
+/// which must be exactly as long as they are.
+pub fn read_snark_proofs(proof_vec: &[u8]) -> error::Result<Vec<groth16::Proof<Bls12>>> {
+    let mut proofs = Vec::with_capacity(POREP_PARTITIONS);
+    read_snark_proofs_into(proof_vec, &mut proofs)?;
+
+    Ok(proofs)
+}
+
+// Refuses a SNARK proof of other than the length of one of each partition's.
+fn check_snark_proof_bytes(proof_vec: &[u8]) -> Result<(), SealProofErr> {
+    if proof_vec.len() != POREP_PROOF_BYTES {
+        let reason = format!("expected {} bytes, got {}", POREP_PROOF_BYTES, proof_vec.len());
+        return Err(err_malformed(ProofVariant::Snark, reason));
+    }
+
+    Ok(())
+}
+
+// As read_snark_proofs, but into proofs, which is cleared first.
+fn read_snark_proofs_into(
+    proof_vec: &[u8],
+    proofs: &mut Vec<groth16::Proof<Bls12>>,
+) -> error::Result<()> {
+    check_snark_proof_bytes(proof_vec)?;
+
+    proofs.clear();
+    for mut bytes in proof_vec.chunks(SNARK_BYTES) {
+        let proof = groth16::Proof::read(&mut bytes)
+            .map_err(|err| err_malformed(ProofVariant::Snark, err))?;
+        proofs.push(proof);
+    }
+
+    Ok(())
+}
+
+/// The length of the envelope of an honest proof of a seal with the given
+/// config, before it's made: exact for SNARK proofs, and an upper bound for
+/// vanilla proofs, which fall short of it by the parents their challenged nodes
+/// lack (zigzag nodes have fewer expansion parents than the graph's degree
+/// allows).
+pub fn projected_seal_proof_bytes(sector_config: &SectorConfig) -> usize {
+    let proof_bytes = match sector_config.proof_variant() {
+        ProofVariant::Snark => POREP_PROOF_BYTES,
+        ProofVariant::Vanilla => projected_vanilla_proof_bytes(&public_params_for_version(
+            sector_config.sector_bytes() as usize,
+            sector_config.proof_version(),
+        )),
+    };
+
+    1 + proof_bytes
+}
+
+// The length of the CBOR serialization of the vanilla proofs of a seal with
+// the given public params. The layered proofs' own serialization is projected
+// by layered_drgporep::Proof::projected_size, but the length of their CBOR
+// depends on how the integers in them are encoded too, so proofs of the
+// largest shape those params allow (every challenged node with as many parents
+// as the graph's degree, each with the largest index) are encoded to measure.
+fn projected_vanilla_proof_bytes(public_params: &ZigZagPublicParams) -> usize {
+    let graph = &public_params.drg_porep_public_params.graph;
+    let layer_challenges = &public_params.layer_challenges;
+    let height = graph_height(graph.size());
+
+    let encoding_proofs = (0..layer_challenges.layers())
+        .map(|layer| {
+            let challenges = layer_challenges.challenges_for_layer(layer);
+            let mut proof = drgporep::Proof::new_empty(height, graph.degree(), challenges);
+
+            for parents in &mut proof.replica_parents {
+                for (parent, _) in parents.iter_mut() {
+                    *parent = graph.size() - 1;
+                }
+            }
+
+            proof
+        })
+        .collect();
+
+    let tau = Tau {
+        comm_r: Default::default(),
+        comm_d: Default::default(),
+    };
+    let proof = layered_drgporep::Proof::<DefaultTreeHasher>::new(
+        encoding_proofs,
+        vec![tau; layer_challenges.layers()],
+    );
+
+    serde_cbor::to_vec(&vec![proof; POREP_PARTITIONS])
+        .expect("proofs are always encoded")
+        .len()
+}
+
+// Field elements are 32 bytes, which CBOR encodes in 34. Even counting the
+// boolean beside each in a merkle path, and the names of the fields they're
+// in, they take fewer than this many bytes each.
+const MAX_CBOR_BYTES_PER_ELEMENT: usize = 64;
+
+/// An upper bound on the length of an honest vanilla proof of a seal with the
+/// given public params. Longer proofs are refused before they're decoded.
+pub fn max_vanilla_proof_bytes(public_params: &ZigZagPublicParams) -> usize {
+    let graph = &public_params.drg_porep_public_params.graph;
+    let layers = public_params.layer_challenges.layers();
+
+    // Each challenge is proven by the merkle paths of its data node, its
+    // replica node and each of its parents. Besides the path, each holds its
+    // root, its leaf and the node's data.
+    let paths_per_challenge = 2 + graph.degree();
+    let elements_per_path = graph_height(graph.size()) + 3;
+
+    // Each layer's proof also holds the roots of its trees, and its tau.
+    let elements_per_partition = public_params.layer_challenges.total_challenges()
+        * paths_per_challenge
+        * elements_per_path
+        + 4 * layers;
+
+    POREP_PARTITIONS * elements_per_partition * MAX_CBOR_BYTES_PER_ELEMENT
+}
+
+/// Decodes the layered proof of each partition from a vanilla proof (taken out
+/// of its envelope), checking that there is one per partition and that each
+/// proves every layer, but not that they're valid.
+pub fn read_vanilla_proofs(
+    public_params: &ZigZagPublicParams,
+    proof_vec: &[u8],
+) -> error::Result<Vec<layered_drgporep::Proof<DefaultTreeHasher>>> {
+    let max_bytes = max_vanilla_proof_bytes(public_params);
+    if proof_vec.len() > max_bytes {
+        let reason = format!("{} bytes, past the bound of {}", proof_vec.len(), max_bytes);
+        return Err(err_malformed(ProofVariant::Vanilla, reason).into());
+    }
+
+    let proofs: Vec<layered_drgporep::Proof<DefaultTreeHasher>> =
+        serde_cbor::from_slice(proof_vec)
+            .map_err(|err| err_malformed(ProofVariant::Vanilla, err))?;
+
+    let layers = public_params.layer_challenges.layers();
+
+    if proofs.len() != POREP_PARTITIONS
+        || proofs
+            .iter()
+            .any(|p| p.tau.len() != layers || p.encoding_proofs.len() != layers)
+    {
+        let reason = "wrong number of partitions or layers";
+        return Err(err_malformed(ProofVariant::Vanilla, reason).into());
+    }
+
+    Ok(proofs)
+}
+
+fn verify_vanilla_seal(
+    public_params: &ZigZagPublicParams,
+    public_inputs: &layered_drgporep::PublicInputs<<DefaultTreeHasher as Hasher>::Domain>,
+    proof_vec: &[u8],
+) -> error::Result<bool> {
+    let proofs = read_vanilla_proofs(public_params, proof_vec)?;
+    let layers = public_params.layer_challenges.layers();
+
+    // The layered proofs only check comm_r_star against the taus they carry, so
+    // check that those are the taus being verified. Both are compared whatever
+    // the first comparison's outcome.
+    let tau = public_inputs.tau.expect("tau is always given");
+    for proof in &proofs {
+        let comm_d_matches = proof.tau[0].comm_d.ct_eq(&tau.comm_d);
+        let comm_r_matches = proof.tau[layers - 1].comm_r.ct_eq(&tau.comm_r);
+        if !(comm_d_matches & comm_r_matches) {
+            return Ok(false);
+        }
+    }
+
+    ZigZagDrgPoRep::verify_all_partitions(public_params, public_inputs, &proofs)
+        .map_err(|e| e.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::replica_format::{SealedFileMismatch, SectorFormatMismatch, FORMAT_BYTES};
+    use crate::api::sector_builder::helpers::staged_data::compute_comm_p;
+    use crate::api::staged_read::{StagedFile, StagedSource};
+    use crate::api::seal_proof::{seal_envelope, seal_versioned_envelope, SealProofErr};
+    use sector_base::api::disk_backed_storage::{
+        new_mini_sector_config, new_mini_sector_store, new_sector_config,
+        new_sector_config_of_version,
+        new_sector_config_with_aux_compression, new_sector_config_with_proof_variant,
+        new_sector_config_with_proof_version, new_sector_config_with_sample_check,
+        new_sector_store, ConfiguredStore, LIVE_SECTOR_SIZE, TEST_SECTOR_SIZE,
+    };
+    use sector_base::api::sector_store::AuxCompression;
+    use sector_base::api::sector_store::ProofVariant::{Snark, Vanilla};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use sector_base::api::errors::SectorManagerErr;
+    use sector_base::api::sector_store::SectorStore;
+    use sector_base::io::fr32::write_padded;
+    use std::io;
+    use std::thread;
+    #[cfg(feature = "failpoints")]
+    use storage_proofs::failpoint::{FailAction, FailScenario};
+
+    fn entries(dir: &Path) -> Vec<PathBuf> {
+        let mut entries: Vec<_> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        entries.sort();
+        entries
+    }
+
+    #[cfg(feature = "failpoints")]
+    fn assert_failed_at(result: error::Result<()>, name: &str) {
+        match result.map_err(|err| err.downcast::<StorageProofsError>()) {
+            Err(Ok(StorageProofsError::Failpoint(reached))) => assert_eq!(name, reached),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "failpoints")]
+    fn failed_seal_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = new_sector_config_with_proof_variant(&ConfiguredStore::Test, Vanilla);
+
+        let staged_path = dir.path().join("staged");
+        let sealed_path = dir.path().join("sealed");
+        fs::write(&staged_path, &[7; 500]).unwrap();
+
+        // Fail once the replica has been written and proven, but before it's
+        // renamed to where it's bound for.
+        let scenario = FailScenario::setup();
+        scenario.arm(failpoint::SEALED_FILE_PUBLISH, FailAction::Error);
+
+        let result = seal(
+            config.as_ref(),
+            &staged_path,
+            &sealed_path,
+            &[0; 31],
+            &[0; 31],
+        );
+
+        assert_failed_at(result.map(|_| ()), failpoint::SEALED_FILE_PUBLISH);
+        assert_eq!(vec![staged_path.clone()], entries(dir.path()));
+    }
+
+    #[test]
+    fn timed_out_seal_leaves_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = new_sector_config_with_proof_variant(&ConfiguredStore::Test, Vanilla);
+
+        let staged_path = dir.path().join("staged");
+        let sealed_path = dir.path().join("sealed");
+        fs::write(&staged_path, &[7; 500]).unwrap();
+
+        let start = Instant::now();
+        let token = CancellationToken::with_timeout(Duration::from_secs(1));
+
+        let result = seal_with_hook(
+            config.as_ref(),
+            File::open(&staged_path).unwrap(),
+            &sealed_path,
+            &[0; 31],
+            &[0; 31],
+            &MemoryMeter::default(),
+            &token,
+            &SealThrottle::default(),
+            None,
+            SealMode::Prove,
+            || {
+                // A step of 10 seconds, which checks the token as it goes.
+                for _ in 0..100 {
+                    thread::sleep(Duration::from_millis(100));
+                    token.checkpoint("a simulated delay")?;
+                }
+
+                Ok(())
+            },
+        );
+
+        match result.map_err(|err| err.downcast::<Interrupted>()) {
+            Err(Ok(Interrupted::TimedOut { phase, timeout })) => {
+                assert_eq!("a simulated delay", phase);
+                assert_eq!(Duration::from_secs(1), timeout);
+            }
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+        assert!(start.elapsed() < Duration::from_secs(2), "{:?}", start.elapsed());
+        assert_eq!(vec![staged_path.clone()], entries(dir.path()));
+    }
+
+    // Seals 500 bytes with the Test config and Vanilla proofs, sample-checking
+    // 16 nodes of the written replica, and calling after_replication with the
+    // file's (temporary) path.
+    fn seal_sample_checked<F>(dir: &Path, after_replication: F) -> error::Result<()>
+    where
+        F: FnOnce(&Path),
+    {
+        let config =
+            new_sector_config_with_sample_check(&ConfiguredStore::Test, Vanilla, Some(16));
+
+        let staged_path = dir.join("staged");
+        let sealed_path = dir.join("sealed");
+        fs::write(&staged_path, &[7; 500]).unwrap();
+
+        seal_with_hook(
+            config.as_ref(),
+            File::open(&staged_path).unwrap(),
+            &sealed_path,
+            &[0; 31],
+            &[0; 31],
+            &MemoryMeter::default(),
+            &CancellationToken::new(),
+            &SealThrottle::default(),
+            None,
+            SealMode::Prove,
+            || {
+                let pending = entries(dir).into_iter().find(|p| *p != staged_path).unwrap();
+                after_replication(&pending);
+
+                Ok(())
+            },
+        )
+        .map(|_| ())
+    }
+
+    #[test]
+    fn damaged_sealed_files_fail_the_sample_check() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Every node of the replica is damaged, so whichever are sampled differ.
+        let result = seal_sample_checked(dir.path(), |pending| {
+            let mut bytes = fs::read(pending).unwrap();
+            for b in bytes.iter_mut().take(TEST_SECTOR_SIZE as usize) {
+                *b ^= 0xff;
+            }
+            fs::write(pending, bytes).unwrap();
+        });
+
+        match result.map_err(|e| e.downcast::<SealedFileMismatch>()) {
+            Err(Ok(SealedFileMismatch { node_index })) => {
+                assert!(node_index < TEST_SECTOR_SIZE as usize / 32)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // The damaged file is removed.
+        assert_eq!(vec![dir.path().join("staged")], entries(dir.path()));
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn undamaged_sealed_files_pass_the_sample_check() {
+        let dir = tempfile::tempdir().unwrap();
+
+        seal_sample_checked(dir.path(), |_| ()).unwrap();
+        assert!(dir.path().join("sealed").exists());
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn seals_replicated_only_are_proven_from_moved_bundles() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = new_sector_config_with_proof_variant(&ConfiguredStore::Test, Vanilla);
+
+        let staged_path = dir.path().join("staged");
+        let sealed_path = dir.path().join("sealed");
+        let bundle_dir = dir.path().join("bundle");
+        fs::write(&staged_path, &[7; 500]).unwrap();
+
+        let output = seal_with_prover_bundle(
+            config.as_ref(),
+            &staged_path,
+            &sealed_path,
+            &[1; 31],
+            &[2; 31],
+            &bundle_dir,
+            false,
+        )
+        .expect("failed to replicate");
+        assert!(output.is_none());
+        assert!(sealed_path.exists());
+
+        // Proving doesn't depend on where the bundle was written.
+        let moved_dir = dir.path().join("moved");
+        fs::rename(&bundle_dir, &moved_dir).unwrap();
+
+        let output = prove_from_bundle(&moved_dir).expect("failed to prove");
+        assert!(verify_seal(
+            config.as_ref(),
+            output.comm_r,
+            output.comm_d,
+            output.comm_r_star,
+            &[1; 31],
+            &[2; 31],
+            &output.proof,
+        )
+        .unwrap());
+
+        // The published replica is whole, for all it wasn't proven when written.
+        let format = replica_format(TEST_SECTOR_SIZE as usize);
+        check_replica(&sealed_path, &format, LegacyReplicas::Refuse).unwrap();
+    }
+
+    #[test]
+    fn proofs_are_regenerated_with_and_without_bundles() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = new_mini_sector_config();
+
+        let staged_path = dir.path().join("staged");
+        let sealed_path = dir.path().join("sealed");
+        let bundle_dir = dir.path().join("bundle");
+        fs::write(&staged_path, &[7; 500]).unwrap();
+
+        let sealed = seal_with_prover_bundle(
+            config.as_ref(),
+            &staged_path,
+            &sealed_path,
+            &[1; 31],
+            &[2; 31],
+            &bundle_dir,
+            true,
+        )
+        .expect("failed to seal")
+        .unwrap();
+
+        // Without the bundle, the trees are rebuilt from the sealed sector.
+        for bundle in vec![Some(bundle_dir.as_path()), None] {
+            let output = regenerate_proof(
+                config.as_ref(),
+                &sealed_path,
+                bundle,
+                &[1; 31],
+                &[2; 31],
+                ProofVersion::Mini,
+                LegacyReplicas::Refuse,
+            )
+            .expect("failed to regenerate the proof");
+
+            assert_eq!(
+                (sealed.comm_r, sealed.comm_d, sealed.comm_r_star),
+                (output.comm_r, output.comm_d, output.comm_r_star)
+            );
+            assert!(verify_seal(
+                config.as_ref(),
+                output.comm_r,
+                output.comm_d,
+                output.comm_r_star,
+                &[1; 31],
+                &[2; 31],
+                &output.proof,
+            )
+            .unwrap());
+        }
+
+        // The bundle only proves the sector it was written for.
+        let result = regenerate_proof(
+            config.as_ref(),
+            &sealed_path,
+            Some(&bundle_dir),
+            &[1; 31],
+            &[3; 31],
+            ProofVersion::Mini,
+            LegacyReplicas::Refuse,
+        );
+
+        match result.map_err(|err| err.downcast::<ProverBundleErr>()) {
+            Err(Ok(ProverBundleErr::SectorMismatch)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn compressed_bundles_are_proven_whatever_the_config() {
+        let dir = tempfile::tempdir().unwrap();
+        let staged_path = dir.path().join("staged");
+        fs::write(&staged_path, &[0; 500]).unwrap();
+
+        // Replicates the mostly-zero sector, writing its bundle as the config
+        // says, and returns the bundle's directory and the size of its trees.
+        let replicate = |compression| {
+            let config = new_sector_config_with_aux_compression(
+                &ConfiguredStore::Test,
+                Vanilla,
+                compression,
+            );
+            let bundle_dir = dir.path().join(format!("{:?}", compression));
+
+            seal_with_prover_bundle(
+                config.as_ref(),
+                &staged_path,
+                &dir.path().join(format!("sealed-{:?}", compression)),
+                &[1; 31],
+                &[2; 31],
+                &bundle_dir,
+                false,
+            )
+            .expect("failed to replicate");
+
+            let aux_bytes = fs::metadata(bundle_dir.join("aux.bin")).unwrap().len();
+            (bundle_dir, aux_bytes)
+        };
+
+        let (_, plain_bytes) = replicate(AuxCompression::None);
+        let (bundle_dir, compressed_bytes) = replicate(AuxCompression::Snappy);
+        assert!(compressed_bytes < plain_bytes);
+
+        // Proving and verifying with an uncompressing config reads the bundle
+        // all the same.
+        let config = new_sector_config_with_proof_variant(&ConfiguredStore::Test, Vanilla);
+        let output = prove_from_bundle(&bundle_dir).expect("failed to prove");
+        assert!(verify_seal(
+            config.as_ref(),
+            output.comm_r,
+            output.comm_d,
+            output.comm_r_star,
+            &[1; 31],
+            &[2; 31],
+            &output.proof,
+        )
+        .unwrap());
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn replication_artifacts_do_not_depend_on_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        let config = new_sector_config_with_proof_variant(&ConfiguredStore::Test, Vanilla);
+
+        let staged_path = dir.path().join("staged");
+        let sealed_path = dir.path().join("sealed");
+        let bundle_dir = dir.path().join("bundle");
+        fs::write(&staged_path, &[7; 500]).unwrap();
+
+        // Each run replicates to the same paths, as the bundle's manifest
+        // records where the replica was written.
+        let digests: Vec<Vec<(PathBuf, Vec<u8>)>> = [1, 4, 1, 4]
+            .iter()
+            .map(|&threads| {
+                let _ = fs::remove_file(&sealed_path);
+                let _ = fs::remove_dir_all(&bundle_dir);
+
+                let pool = rayon::ThreadPoolBuilder::new()
+                    .num_threads(threads)
+                    .build()
+                    .unwrap();
+                pool.install(|| {
+                    seal_with_prover_bundle(
+                        config.as_ref(),
+                        &staged_path,
+                        &sealed_path,
+                        &[1; 31],
+                        &[2; 31],
+                        &bundle_dir,
+                        false,
+                    )
+                })
+                .expect("failed to replicate");
+
+                let mut artifacts = vec![sealed_path.clone()];
+                artifacts.extend(entries(&bundle_dir));
+                artifacts
+                    .into_iter()
+                    .map(|path| {
+                        let digest = Blake2b::digest(&fs::read(&path).unwrap()).to_vec();
+                        (path, digest)
+                    })
+                    .collect()
+            })
+            .collect();
+
+        // The sealed sector, and the bundle's manifest, taus and trees.
+        assert_eq!(4, digests[0].len());
+        for run in &digests[1..] {
+            assert_eq!(&digests[0], run);
+        }
+    }
+
+    // Seals 500 bytes with the Test config, within max_memory_bytes, leaving
+    // nothing but the staged sector behind should sealing fail.
+    fn seal_within(max_memory_bytes: Option<u64>) -> error::Result<SealOutput> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_owned();
+        let store = new_sector_store(&ConfiguredStore::Test, path.clone(), path);
+
+        let staged_path = dir.path().join("staged");
+        let sealed_path = dir.path().join("sealed");
+        fs::write(&staged_path, &[7; 500]).unwrap();
+
+        let result = seal_with_memory_limit(
+            store.config(),
+            &staged_path,
+            &sealed_path,
+            &[0; 31],
+            &[0; 31],
+            max_memory_bytes,
+        );
+
+        if result.is_err() {
+            assert_eq!(vec![staged_path.clone()], entries(dir.path()));
+        }
+
+        result
+    }
+
+    #[test]
+    fn seal_fails_fast_past_memory_limit() {
+        let sector_bytes = TEST_SECTOR_SIZE;
+
+        // The sector's data is the first large allocation...
+        match seal_within(Some(1)).map_err(|e| e.downcast::<StorageProofsError>()) {
+            Err(Ok(StorageProofsError::MemoryLimitExceeded {
+                phase: SealPhase::ReadData,
+                needed,
+                limit: 1,
+            })) => assert_eq!(sector_bytes, needed),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // ...followed by the copy of the first layer, alongside it.
+        match seal_within(Some(sector_bytes)).map_err(|e| e.downcast::<StorageProofsError>()) {
+            Err(Ok(StorageProofsError::MemoryLimitExceeded {
+                phase: SealPhase::CopyLayers,
+                needed,
+                limit,
+            })) => assert_eq!((2 * sector_bytes, sector_bytes), (needed, limit)),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn seal_reports_memory_high_water_marks() {
+        let sector_bytes = TEST_SECTOR_SIZE;
+        let report = seal_within(None).unwrap().memory;
+
+        assert!(report.peak_bytes >= sector_bytes);
+        assert_eq!(
+            vec![
+                SealPhase::ReadData,
+                SealPhase::CopyLayers,
+                SealPhase::BuildTrees,
+                SealPhase::Prove,
+            ],
+            report.phases.iter().map(|(phase, _)| *phase).collect::<Vec<_>>()
+        );
+
+        // Sealing reads the sector's data once, copies each layer at least
+        // once, and keeps the tree of every layer and of the replica.
+        let trees = proof_params(ProofVersion::CURRENT).layers as u64 + 1;
+        let tree_bytes = memory::merkle_tree_bytes::<DefaultTreeHasher>(sector_bytes as usize / 32);
+        let copy_bytes = report.phase_peak_bytes(SealPhase::CopyLayers);
+        let prove_bytes = report.phase_peak_bytes(SealPhase::Prove);
+
+        assert_eq!(sector_bytes, report.phase_peak_bytes(SealPhase::ReadData));
+        assert!(copy_bytes >= sector_bytes && copy_bytes <= trees * sector_bytes);
+        assert_eq!(trees * tree_bytes, report.phase_peak_bytes(SealPhase::BuildTrees));
+        assert!(prove_bytes > 0);
+
+        // The data and the trees are freed once the seal's vanilla proofs have been generated, so
+        // proving, which takes far more than a test sector's trees, sets the peak on its own.
+        assert!(prove_bytes > sector_bytes + trees * tree_bytes);
+        assert_eq!(prove_bytes, report.peak_bytes);
+    }
+
+    #[test]
+    fn publishing_moves_sealed_sector() {
+        let landing_dir = tempfile::tempdir().unwrap();
+        let sealed_dir = tempfile::tempdir().unwrap();
+        let landing_path = landing_dir.path().join("sector");
+        let sealed_path = sealed_dir.path().join("sector");
+
+        fs::write(&landing_path, &[9; 1024]).unwrap();
+        fs::write(&sealed_path, &[]).unwrap();
+
+        publish_sealed_sector(&landing_path, &sealed_path).unwrap();
+
+        assert!(entries(landing_dir.path()).is_empty());
+        assert_eq!(vec![sealed_path.clone()], entries(sealed_dir.path()));
+        assert_eq!(vec![9; 1024], fs::read(&sealed_path).unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "failpoints")]
+    fn failed_publish_leaves_sealed_path_as_it_was() {
+        let landing_dir = tempfile::tempdir().unwrap();
+        let sealed_dir = tempfile::tempdir().unwrap();
+        let landing_path = landing_dir.path().join("sector");
+        let sealed_path = sealed_dir.path().join("sector");
+
+        fs::write(&landing_path, &[9; 1024]).unwrap();
+        fs::write(&sealed_path, &[3; 16]).unwrap();
+
+        // Fail once the sector has been copied beside sealed_path, but before
+        // it's renamed over it.
+        let scenario = FailScenario::setup();
+        scenario.arm(failpoint::SEALED_FILE_PUBLISH, FailAction::Error);
+
+        assert_failed_at(
+            publish_sealed_sector(&landing_path, &sealed_path),
+            failpoint::SEALED_FILE_PUBLISH,
+        );
+
+        // The landing copy is kept, and the copy beside sealed_path removed.
+        assert_eq!(vec![9; 1024], fs::read(&landing_path).unwrap());
+        assert_eq!(vec![sealed_path.clone()], entries(sealed_dir.path()));
+        assert_eq!(vec![3; 16], fs::read(&sealed_path).unwrap());
+    }
+
+    // Seals data, the preprocessed contents of a staged sector, into a test
+    // sector at sealed_path as seal would, but without proving the seal.
+    fn replicate_test_sector(data: Vec<u8>, sealed_path: &Path) {
+        let mut data = data;
+        let replica_id = replica_id_domain([0; 31], [0; 31]);
+        let pp = public_params(TEST_SECTOR_SIZE as usize);
 
-pub fn get_unsealed_range<T: Into<PathBuf> + AsRef<Path>>(
-    sector_config: &SectorConfig,
-    sealed_path: T,
-    output_path: T,
-    prover_id_in: &FrSafe,
-    sector_id_in: &FrSafe,
-    offset: u64,
-    num_bytes: u64,
-) -> error::Result<(u64)> {
-    let sector_bytes = sector_config.sector_bytes() as usize;
+        ZigZagDrgPoRep::replicate(&pp, &replica_id, &mut data, None).unwrap();
+        data.extend_from_slice(&replica_format(TEST_SECTOR_SIZE as usize).encode());
+        fs::write(sealed_path, &data).unwrap();
+    }
 
-    let prover_id = pad_safe_fr(prover_id_in);
-    let sector_id = pad_safe_fr(sector_id_in);
-    let replica_id = replica_id::<DefaultTreeHasher>(prover_id, sector_id);
+    // As replicate_test_sector, but without the format trailer, as sectors
+    // were sealed before it was introduced.
+    fn replicate_legacy_test_sector(data: Vec<u8>, sealed_path: &Path) {
+        replicate_test_sector(data, sealed_path);
 
-    let f_in = File::open(sealed_path)?;
-    let mut data = Vec::new();
-    f_in.take(sector_bytes as u64).read_to_end(&mut data)?;
+        let file = fs::OpenOptions::new().write(true).open(sealed_path).unwrap();
+        file.set_len(TEST_SECTOR_SIZE).unwrap();
+    }
 
-    let f_out = File::create(output_path)?;
-    let mut buf_writer = BufWriter::new(f_out);
+    // Unseals the range from the test sector at sealed_path, knowing nothing
+    // of what it holds.
+    fn try_unseal_test_sector(
+        sealed_path: &Path,
+        offset: u64,
+        num_bytes: u64,
+        legacy: LegacyReplicas,
+    ) -> error::Result<Vec<u8>> {
+        let dir = tempfile::tempdir().unwrap();
+        let output_path = dir.path().join("unsealed");
+        let config = new_sector_config(&ConfiguredStore::Test);
+
+        let written = get_unsealed_range(
+            config.as_ref(),
+            sealed_path,
+            output_path.as_path(),
+            &[0; 31],
+            &[0; 31],
+            offset,
+            num_bytes,
+            legacy,
+        )?;
+
+        let unsealed = fs::read(&output_path).unwrap();
+        assert_eq!(written, unsealed.len() as u64);
+
+        Ok(unsealed)
+    }
 
-    let unsealed = ZigZagDrgPoRep::extract_all(&public_params(sector_bytes), &replica_id, &data)?;
+    fn unseal_test_sector(sealed_path: &Path, offset: u64, num_bytes: u64) -> Vec<u8> {
+        try_unseal_test_sector(sealed_path, offset, num_bytes, LegacyReplicas::Refuse).unwrap()
+    }
 
-    let written = write_unpadded(
-        &unsealed,
-        &mut buf_writer,
-        offset as usize,
-        num_bytes as usize,
-    )?;
+    fn preprocess(data: &[u8]) -> Vec<u8> {
+        let mut preprocessed = io::Cursor::new(Vec::new());
+        write_padded(data, &mut preprocessed).unwrap();
 
-    Ok(written as u64)
-}
+        preprocessed.into_inner()
+    }
 
-pub fn verify_seal(
-    sector_config: &SectorConfig,
-    comm_r: Commitment,
-    comm_d: Commitment,
-    comm_r_star: Commitment,
-    prover_id_in: &FrSafe,
-    sector_id_in: &FrSafe,
-    proof_vec: &[u8],
-) -> error::Result<bool> {
-    let sector_bytes = sector_config.sector_bytes() as usize;
+    #[test]
+    fn comm_d_is_computed_over_padded_sector_data() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let sector_bytes = TEST_SECTOR_SIZE as usize;
+        let graph = public_params(sector_bytes).drg_porep_public_params.graph;
+
+        // Preprocessed data running past the trailer is cut short, as it is
+        // when sealed.
+        for len in &[0, 1, 127, 500, 984, 2000] {
+            let original: Vec<u8> = (0..*len).map(|_| rng.gen()).collect();
+            let data = preprocess(&original);
+
+            let padded = pad_sector_data(data.clone(), sector_bytes);
+            let tree = graph.merkle_tree(&padded).unwrap();
+            let expected = commitment_from_fr(tree.root().into());
+
+            assert_eq!(expected, compute_comm_d(&data[..], sector_bytes).unwrap());
+
+            let mut streamed = CommDBuilder::new(sector_bytes).unwrap();
+            for chunk in data.chunks(100) {
+                streamed.update(chunk).unwrap();
+            }
+            assert_eq!(expected, streamed.finish().unwrap());
+        }
+    }
 
-    let prover_id = pad_safe_fr(prover_id_in);
-    let sector_id = pad_safe_fr(sector_id_in);
-    let replica_id = replica_id::<DefaultTreeHasher>(prover_id, sector_id);
+    #[test]
+    fn unseals_exactly_the_sealed_data() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let dir = tempfile::tempdir().unwrap();
+        let sealed_path = dir.path().join("sealed");
 
-    let comm_r = bytes_into_fr::<Bls12>(&comm_r)?;
-    let comm_d = bytes_into_fr::<Bls12>(&comm_d)?;
-    let comm_r_star = bytes_into_fr::<Bls12>(&comm_r_star)?;
+        let max = new_sector_config(&ConfiguredStore::Test).max_unsealed_bytes_per_sector();
+        assert_eq!(984, max);
 
-    let compound_setup_params = compound_proof::SetupParams {
-        // The proof might use a different number of bytes than we read and copied, if we are faking.
-        vanilla_params: &setup_params(sector_bytes),
-        engine_params: &(*ENGINE_PARAMS),
-        partitions: Some(POREP_PARTITIONS),
-    };
+        for len in &[0, 1, 31, 32, 127, 500, max] {
+            let original: Vec<u8> = (0..*len).map(|_| rng.gen()).collect();
+            let data = pad_sector_data(preprocess(&original), TEST_SECTOR_SIZE as usize);
+            assert_eq!(TEST_SECTOR_SIZE as usize, data.len());
 
-    let compound_public_params: compound_proof::PublicParams<
-        '_,
-        Bls12,
-        ZigZagDrgPoRep<'_, DefaultTreeHasher>,
-    > = ZigZagCompound::setup(&compound_setup_params)?;
+            replicate_test_sector(data, &sealed_path);
 
-    let public_inputs = layered_drgporep::PublicInputs::<<DefaultTreeHasher as Hasher>::Domain> {
-        replica_id,
-        tau: Some(Tau {
-            comm_r: comm_r.into(),
-            comm_d: comm_d.into(),
-        }),
-        comm_r_star: comm_r_star.into(),
-        k: None,
-    };
+            // Asking for the whole sector returns what was sealed, and no padding.
+            assert_eq!(original, unseal_test_sector(&sealed_path, 0, max));
 
-    let groth_params = get_zigzag_params(sector_bytes)?;
+            let offset = cmp::min(5, *len);
+            assert_eq!(
+                &original[offset as usize..],
+                &unseal_test_sector(&sealed_path, offset, max)[..]
+            );
+            assert!(unseal_test_sector(&sealed_path, *len, max).is_empty());
+        }
+    }
 
-    let proof = MultiProof::new_from_reader(Some(POREP_PARTITIONS), proof_vec, groth_params)?;
+    // Unseals the range from the test sector at sealed_path into shards of
+    // shard_size bytes, returning the length of each and their bytes joined.
+    fn unseal_test_sector_sharded(
+        sealed_path: &Path,
+        shard_size: u64,
+        offset: u64,
+        num_bytes: u64,
+    ) -> (Vec<u64>, Vec<u8>) {
+        let dir = tempfile::tempdir().unwrap();
+        let config = new_sector_config(&ConfiguredStore::Test);
+
+        let shards = get_unsealed_range_sharded(
+            config.as_ref(),
+            sealed_path,
+            dir.path(),
+            shard_size,
+            &[0; 31],
+            &[0; 31],
+            offset,
+            num_bytes,
+            LegacyReplicas::Refuse,
+            &CancellationToken::new(),
+        )
+        .unwrap();
 
-    ZigZagCompound::verify(&compound_public_params, &public_inputs, &proof).map_err(|e| e.into())
-}
+        let mut lens = Vec::new();
+        let mut joined = Vec::new();
+        for (i, (path, len)) in shards.into_iter().enumerate() {
+            assert_eq!(dir.path().join(format!("shard-{}", i)), path);
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+            let bytes = fs::read(&path).unwrap();
+            assert_eq!(len, bytes.len() as u64);
 
-    use rand::{thread_rng, Rng};
-    use sector_base::api::disk_backed_storage::new_sector_store;
-    use sector_base::api::disk_backed_storage::ConfiguredStore;
-    use sector_base::api::sector_store::SectorStore;
-    use std::fs::create_dir_all;
-    use std::fs::File;
-    use std::io::Read;
-    use std::thread;
+            lens.push(len);
+            joined.extend(bytes);
+        }
+        assert_eq!(lens.len(), entries(dir.path()).len());
 
-    struct Harness {
-        prover_id: FrSafe,
-        seal_output: SealOutput,
-        sealed_access: String,
-        sector_id: FrSafe,
-        store: Box<SectorStore>,
-        unseal_access: String,
-        written_contents: Vec<Vec<u8>>,
+        (lens, joined)
     }
 
-    #[derive(Debug, Clone, Copy)]
-    enum BytesAmount<'a> {
-        Max,
-        Offset(u64),
-        Exact(&'a [u8]),
+    #[test]
+    fn sharded_unseals_join_into_the_whole_range() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let dir = tempfile::tempdir().unwrap();
+        let sealed_path = dir.path().join("sealed");
+
+        let max = new_sector_config(&ConfiguredStore::Test).max_unsealed_bytes_per_sector();
+        let original: Vec<u8> = (0..max).map(|_| rng.gen()).collect();
+        let data = pad_sector_data(preprocess(&original), TEST_SECTOR_SIZE as usize);
+        replicate_test_sector(data, &sealed_path);
+
+        // Shards of sizes which aren't whole nodes end mid-node.
+        for shard_size in &[1, 31, 32, 100, 127, 128, max, max + 1] {
+            for (offset, num_bytes) in &[(0, max), (5, 300), (200, 127)] {
+                let whole = unseal_test_sector(&sealed_path, *offset, *num_bytes);
+                let (lens, joined) =
+                    unseal_test_sector_sharded(&sealed_path, *shard_size, *offset, *num_bytes);
+
+                assert_eq!(whole, joined);
+
+                let (last, full) = lens.split_last().unwrap();
+                assert!(full.iter().all(|len| len == shard_size));
+                assert!(*last > 0 && last <= shard_size);
+            }
+        }
+
+        // An empty range makes no shards.
+        let (lens, _) = unseal_test_sector_sharded(&sealed_path, 100, 5, 0);
+        assert!(lens.is_empty());
+
+        let (lens, _) = unseal_test_sector_sharded(&sealed_path, 100, max, 10);
+        assert!(lens.is_empty());
     }
 
-    fn create_harness(cs: &ConfiguredStore, bytes_amts: &[BytesAmount]) -> Harness {
-        let store = create_sector_store(cs);
-        let mgr = store.manager();
-        let cfg = store.config();
+    #[test]
+    fn sectors_without_trailer_unseal_in_full() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let dir = tempfile::tempdir().unwrap();
+        let sealed_path = dir.path().join("sealed");
+
+        // Those sectors were sealed before format trailers were introduced,
+        // too, so they're only unsealed when legacy replicas are accepted.
+        let unseal_legacy = |offset, num_bytes| {
+            try_unseal_test_sector(&sealed_path, offset, num_bytes, LegacyReplicas::Accept)
+                .unwrap()
+        };
 
-        let staged_access = mgr
-            .new_staging_sector_access()
-            .expect("could not create staging access");
+        // Sectors sealed before trailers were introduced held data in every
+        // node, and unsealed whatever range was asked for.
+        let legacy_max = unpadded_bytes(TEST_SECTOR_SIZE);
+        let original: Vec<u8> = (0..legacy_max).map(|_| rng.gen()).collect();
+        let data = preprocess(&original);
+        assert_eq!(TEST_SECTOR_SIZE as usize, data.len());
 
-        let sealed_access = mgr
-            .new_sealed_sector_access()
-            .expect("could not create sealed access");
+        replicate_legacy_test_sector(data, &sealed_path);
 
-        let unseal_access = mgr
-            .new_sealed_sector_access()
-            .expect("could not create unseal access");
+        assert_eq!(original, unseal_legacy(0, legacy_max));
+        assert_eq!(&original[100..200], &unseal_legacy(100, 100)[..]);
 
-        let prover_id = [2; 31];
-        let sector_id = [0; 31];
+        // As did sectors which weren't full, padding included.
+        let original: Vec<u8> = (0..100).map(|_| rng.gen()).collect();
+        let mut data = preprocess(&original);
+        data.resize(TEST_SECTOR_SIZE as usize, 0);
 
-        let mut written_contents: Vec<Vec<u8>> = Default::default();
-        for bytes_amt in bytes_amts {
-            let contents = match bytes_amt {
-                BytesAmount::Exact(bs) => bs.to_vec(),
-                BytesAmount::Max => {
-                    make_random_bytes(store.config().max_unsealed_bytes_per_sector())
-                }
-                BytesAmount::Offset(m) => {
-                    make_random_bytes(store.config().max_unsealed_bytes_per_sector() - m)
-                }
-            };
+        replicate_legacy_test_sector(data, &sealed_path);
 
-            assert_eq!(
-                contents.len() as u64,
-                mgr.write_and_preprocess(&staged_access, &contents)
-                    .expect("failed to write and preprocess")
-            );
+        let unsealed = unseal_legacy(0, legacy_max);
+        assert_eq!(legacy_max as usize, unsealed.len());
+        assert_eq!(original, &unsealed[..100]);
+        assert!(unsealed[100..].iter().all(|b| *b == 0));
+    }
+
+    // Returns the mismatch with which the sealed sector at sealed_path is
+    // refused, both when unsealed and when its tree is built for PoSt.
+    fn format_mismatch(sealed_path: &Path, legacy: LegacyReplicas) -> SectorFormatMismatch {
+        let mismatch_of = |result: error::Result<()>| {
+            result
+                .unwrap_err()
+                .downcast::<SectorFormatMismatch>()
+                .unwrap()
+        };
+
+        let unsealed = try_unseal_test_sector(sealed_path, 0, 10, legacy).map(|_| ());
+        let tree = make_merkle_tree(sealed_path, TEST_SECTOR_SIZE as usize, legacy).map(|_| ());
+
+        let mismatch = mismatch_of(unsealed);
+        assert_eq!(mismatch, mismatch_of(tree));
+
+        mismatch
+    }
 
-            written_contents.push(contents);
+    #[test]
+    fn sealed_sectors_are_checked_against_the_configured_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let sealed_path = dir.path().join("sealed");
+        let original = vec![3; 100];
+        let data = pad_sector_data(preprocess(&original), TEST_SECTOR_SIZE as usize);
+
+        replicate_test_sector(data.clone(), &sealed_path);
+        let sealed = fs::read(&sealed_path).unwrap();
+        assert_eq!(TEST_SECTOR_SIZE + FORMAT_BYTES, sealed.len() as u64);
+
+        assert_eq!(original, unseal_test_sector(&sealed_path, 0, 100));
+        make_merkle_tree(&sealed_path, TEST_SECTOR_SIZE as usize, LegacyReplicas::Refuse).unwrap();
+
+        // Each field of the trailer, by its offset in the trailer.
+        let fields = [
+            ("sector size", 6),
+            ("layer count", 14),
+            ("degree", 18),
+            ("expansion degree", 22),
+            ("hasher", 26),
+            ("graph seed", 27),
+            ("parameter digest", 55),
+        ];
+
+        for (field, offset) in &fields {
+            let mut corrupted = sealed.clone();
+            corrupted[TEST_SECTOR_SIZE as usize + offset] ^= 1;
+            fs::write(&sealed_path, &corrupted).unwrap();
+
+            match format_mismatch(&sealed_path, LegacyReplicas::Accept) {
+                SectorFormatMismatch::Field { field: found, .. } => assert_eq!(field, &found),
+                other => panic!("unexpected mismatch: {:?}", other),
+            }
         }
 
-        let seal_output = seal(cfg, &staged_access, &sealed_access, &prover_id, &sector_id)
-            .expect("failed to seal");
+        // As are its magic bytes and version.
+        for offset in &[0, 4] {
+            let mut corrupted = sealed.clone();
+            corrupted[TEST_SECTOR_SIZE as usize + offset] ^= 1;
+            fs::write(&sealed_path, &corrupted).unwrap();
 
-        let SealOutput {
-            comm_r,
-            comm_d,
-            comm_r_star,
-            snark_proof,
-        } = seal_output;
+            match format_mismatch(&sealed_path, LegacyReplicas::Accept) {
+                SectorFormatMismatch::Unrecognized(_) => (),
+                other => panic!("unexpected mismatch: {:?}", other),
+            }
+        }
+
+        // A sector without one is only read when legacy replicas are accepted.
+        replicate_legacy_test_sector(data, &sealed_path);
+
+        let legacy = LegacyReplicas::Refuse;
+        assert_eq!(SectorFormatMismatch::Missing, format_mismatch(&sealed_path, legacy));
+        check_sealed_sector(
+            new_sector_config(&ConfiguredStore::Test).as_ref(),
+            &sealed_path,
+            LegacyReplicas::Accept,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn seal_writes_the_format_trailer() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_owned();
+        let store = new_sector_store(&ConfiguredStore::Test, path.clone(), path);
+
+        let staged_path = dir.path().join("staged");
+        let sealed_path = dir.path().join("sealed");
+        fs::write(&staged_path, &[7; 500]).unwrap();
+
+        seal(store.config(), &staged_path, &sealed_path, &[0; 31], &[0; 31]).unwrap();
 
-        // valid commitments
+        let sealed = fs::read(&sealed_path).unwrap();
+        assert_eq!(
+            Ok(replica_format(TEST_SECTOR_SIZE as usize)),
+            ReplicaFormat::decode(&sealed[TEST_SECTOR_SIZE as usize..])
+        );
+        check_sealed_sector(store.config(), &sealed_path, LegacyReplicas::Refuse).unwrap();
+
+        // Sectors of another size are sealed in another format.
+        let config = new_sector_config(&ConfiguredStore::LargeTest);
+        match check_sealed_sector(config.as_ref(), &sealed_path, LegacyReplicas::Accept)
+            .map_err(|e| e.downcast::<SectorFormatMismatch>())
         {
-            let is_valid = verify_seal(
-                cfg,
-                comm_r,
-                comm_d,
-                comm_r_star,
-                &prover_id,
-                &sector_id,
-                &snark_proof,
+            Err(Ok(SectorFormatMismatch::Length { .. })) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn seals_verify_with_stores_scoped_to_the_test() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_owned();
+
+        let staged_path = dir.path().join("staged");
+        let sealed_path = dir.path().join("sealed");
+        fs::write(&staged_path, &[7; 500]).unwrap();
+
+        // Nothing holds on to the store's config past the call it's lent to,
+        // so the store sealing may be dropped before another verifies.
+        let output = {
+            let store = new_sector_store(&ConfiguredStore::Test, path.clone(), path.clone());
+            seal(
+                store.config(),
+                &staged_path,
+                &sealed_path,
+                &[3; 31],
+                &[4; 31],
             )
-            .expect("failed to run verify_seal");
+            .unwrap()
+        };
 
-            assert!(
-                is_valid,
-                "verification of valid proof failed for cs={:?}, bytes_amts={:?}",
-                cs, bytes_amts
-            );
-        }
+        let store = new_sector_store(&ConfiguredStore::Test, path.clone(), path);
+        assert!(verify_seal(
+            store.config(),
+            output.comm_r,
+            output.comm_d,
+            output.comm_r_star,
+            &[3; 31],
+            &[4; 31],
+            &output.proof,
+        )
+        .unwrap());
+    }
 
-        // unseal the whole thing
-        assert_eq!(
-            cfg.max_unsealed_bytes_per_sector(),
-            get_unsealed_range(
-                cfg,
-                &sealed_access,
-                &unseal_access,
-                &prover_id,
-                &sector_id,
-                0,
-                cfg.max_unsealed_bytes_per_sector(),
+    #[test]
+    fn feistel_keys_are_bound_into_the_parameter_identifier() {
+        let identifier = |feistel| {
+            public_params_with_feistel(TEST_SECTOR_SIZE as usize, feistel)
+                .parameter_set_identifier()
+        };
+
+        let default = public_params(TEST_SECTOR_SIZE as usize).parameter_set_identifier();
+        assert_eq!(default, identifier(FeistelConfig::default()));
+        assert_ne!(default, identifier(FeistelConfig::new(vec![5, 6, 7, 8], 3)));
+        assert_ne!(default, identifier(FeistelConfig::new(vec![1, 2, 3, 4], 4)));
+    }
+
+    #[test]
+    fn challenge_count_policy_boundaries() {
+        assert_eq!(2, challenge_count(0));
+        assert_eq!(2, challenge_count(TEST_SECTOR_SIZE));
+        assert_eq!(2, challenge_count(LIVE_SECTOR_SIZE));
+        assert_eq!(2, challenge_count((1 << 30) - 1));
+        assert_eq!(4, challenge_count(1 << 30));
+        assert_eq!(4, challenge_count((1 << 35) - 1));
+        assert_eq!(8, challenge_count(1 << 35));
+        assert_eq!(8, challenge_count(u64::max_value()));
+    }
+
+    #[test]
+    fn unsupported_sector_sizes_are_refused_before_any_work() {
+        // 48 nodes: a multiple of 32 bytes, but not a power-of-two of nodes.
+        let sector_bytes = 1536;
+        let refused = |result: error::Result<()>| match result
+            .map_err(|err| err.downcast::<SectorManagerErr>())
+        {
+            Err(Ok(SectorManagerErr::UnsupportedSectorSize(n))) => assert_eq!(sector_bytes, n),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        };
+        let no_sectors = || PoStInput {
+            challenge_seed: [0; 32],
+            input_parts: Vec::new(),
+        };
+
+        refused(generate_zigzag_params_with_progress(
+            sector_bytes,
+            &mut |_, _| (),
+        ));
+        refused(generate_post(sector_bytes, no_sectors()).map(|_| ()));
+        refused(
+            generate_post_with_deadline(
+                sector_bytes,
+                no_sectors(),
+                Duration::from_secs(1),
+                &mut PoStCheckpoint::default(),
             )
-            .expect("failed to unseal")
+            .map(|_| ()),
         );
+        refused(verify_post(sector_bytes, &[], &[0; 32], &[], Vec::new()).map(|_| ()));
+    }
+
+    #[test]
+    fn snark_proofs_must_be_whole() {
+        use pairing::bls12_381::{G1Affine, G2Affine};
+        use pairing::CurveAffine;
+
+        let proof = groth16::Proof::<Bls12> {
+            a: G1Affine::one(),
+            b: G2Affine::one(),
+            c: G1Affine::one(),
+        };
+
+        let mut bytes = Vec::new();
+        for _ in 0..POREP_PARTITIONS {
+            proof.write(&mut bytes).unwrap();
+        }
 
-        Harness {
-            prover_id,
-            seal_output,
-            sealed_access,
-            sector_id,
-            store,
-            unseal_access,
-            written_contents,
+        let proofs = read_snark_proofs(&bytes).unwrap();
+        assert_eq!(POREP_PARTITIONS, proofs.len());
+        assert!(proofs.iter().all(|p| *p == proof));
+
+        let mut too_long = bytes.clone();
+        too_long.push(0);
+
+        for malformed in &[
+            &bytes[..0],
+            &bytes[..SNARK_BYTES],
+            &bytes[..POREP_PROOF_BYTES - 1],
+            &too_long[..],
+            &[0; POREP_PROOF_BYTES][..],
+        ] {
+            match read_snark_proofs(malformed).map_err(|e| e.downcast::<SealProofErr>()) {
+                Err(Ok(SealProofErr::Malformed { variant: Snark, .. })) => (),
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
         }
     }
 
-    fn create_sector_store(cs: &ConfiguredStore) -> Box<SectorStore> {
-        let staging_path = tempfile::tempdir().unwrap().path().to_owned();
-        let sealed_path = tempfile::tempdir().unwrap().path().to_owned();
+    #[test]
+    fn oversized_vanilla_proofs_are_refused() {
+        let public_params = public_params(TEST_SECTOR_SIZE as usize);
+        let max_bytes = max_vanilla_proof_bytes(&public_params);
+
+        // Not CBOR at all, but refused for its length before it's decoded.
+        let err = read_vanilla_proofs(&public_params, &vec![0xff; max_bytes + 1]).unwrap_err();
+        match err.downcast::<SealProofErr>() {
+            Ok(SealProofErr::Malformed { variant: Vanilla, reason }) => {
+                assert!(reason.contains("past the bound"), "{}", reason)
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
 
-        create_dir_all(&staging_path).expect("failed to create staging dir");
-        create_dir_all(&sealed_path).expect("failed to create sealed dir");
+    #[test]
+    fn seal_refuses_configs_whose_proofs_are_past_the_limit() {
+        let snark = new_sector_config(&ConfiguredStore::Test);
+        assert_eq!(1 + POREP_PROOF_BYTES, projected_seal_proof_bytes(snark.as_ref()));
+
+        // Honest vanilla proofs are never refused for their length when decoded.
+        let vanilla = new_sector_config_with_proof_variant(&ConfiguredStore::Test, Vanilla);
+        let public_params = public_params(TEST_SECTOR_SIZE as usize);
+        let max_bytes = 1 + max_vanilla_proof_bytes(&public_params);
+        assert!(projected_seal_proof_bytes(vanilla.as_ref()) <= max_bytes);
+
+        for cfg in &[snark, vanilla] {
+            let projected = projected_seal_proof_bytes(cfg.as_ref());
+
+            let dir = tempfile::tempdir().unwrap();
+            let staged_path = dir.path().join("staged");
+            let sealed_path = dir.path().join("sealed");
+            fs::write(&staged_path, &[7; 500]).unwrap();
+
+            let err = seal_with_limits(
+                cfg.as_ref(),
+                &staged_path,
+                &sealed_path,
+                &[0; 31],
+                &[0; 31],
+                None,
+                Some(projected - 1),
+            )
+            .unwrap_err();
+
+            match err.downcast::<SealProofErr>() {
+                Ok(SealProofErr::TooLarge {
+                    variant,
+                    bytes,
+                    max_bytes,
+                }) => assert_eq!(
+                    (cfg.proof_variant(), projected, projected - 1),
+                    (variant, bytes, max_bytes)
+                ),
+                other => panic!("unexpected result: {:?}", other),
+            }
 
-        Box::new(new_sector_store(
-            cs,
-            sealed_path.to_str().unwrap().to_owned(),
-            staging_path.to_str().unwrap().to_owned(),
-        ))
+            // The seal was refused before a replica was begun.
+            assert_eq!(vec![staged_path], entries(dir.path()));
+        }
     }
 
-    fn make_random_bytes(num_bytes_to_make: u64) -> Vec<u8> {
-        let mut rng = thread_rng();
-        (0..num_bytes_to_make).map(|_| rng.gen()).collect()
+    #[test]
+    fn verify_refuses_proofs_past_the_limit_before_decoding() {
+        let vanilla = new_sector_config_with_proof_variant(&ConfiguredStore::Test, Vanilla);
+
+        // Not even an envelope, but refused for its length before it's opened.
+        let err = verify_seal_with_limit(
+            vanilla.as_ref(),
+            [0; 32],
+            [0; 32],
+            [0; 32],
+            &[0; 31],
+            &[0; 31],
+            &[0xff; 10],
+            Some(9),
+        )
+        .unwrap_err();
+
+        match err.downcast::<SealProofErr>() {
+            Ok(SealProofErr::TooLarge {
+                variant: Vanilla,
+                bytes: 10,
+                max_bytes: 9,
+            }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
     }
 
-    fn seal_verify_aux(cs: ConfiguredStore, bytes_amt: BytesAmount) {
-        let h = create_harness(&cs, &vec![bytes_amt]);
+    #[test]
+    fn seal_public_inputs_are_laid_out_per_partition() {
+        let cfg = new_sector_config(&ConfiguredStore::Test);
+        let (comm_d, comm_r_star) = ([2; 32], [3; 32]);
+        let vectors_for = |comm_r| {
+            seal_public_input_vectors(cfg.as_ref(), comm_r, comm_d, comm_r_star, &[4; 31], &[5; 31])
+        };
 
-        // invalid commitments
-        {
-            let is_valid = verify_seal(
-                h.store.config(),
-                h.seal_output.comm_d,
-                h.seal_output.comm_r_star,
-                h.seal_output.comm_r,
-                &h.prover_id,
-                &h.sector_id,
-                &h.seal_output.snark_proof,
+        let comm_r = [1; 32];
+        let vectors = vectors_for(comm_r).unwrap();
+        assert_eq!(POREP_PARTITIONS, vectors.len());
+
+        for inputs in &vectors {
+            assert_eq!(vectors[0].len(), inputs.len());
+            assert_eq!(comm_d, commitment_from_fr(inputs[0]));
+            assert_eq!(comm_r, commitment_from_fr(inputs[1]));
+            assert_eq!(comm_r_star, commitment_from_fr(*inputs.last().unwrap()));
+        }
+
+        // Each partition is challenged on other nodes.
+        assert_ne!(vectors[0], vectors[1]);
+
+        // Commitments which aren't field elements are refused.
+        assert!(vectors_for([0xff; 32]).is_err());
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn vanilla_proofs_are_within_their_projected_length() {
+        let cfg = new_sector_config_with_proof_variant(&ConfiguredStore::Test, Vanilla);
+        let projected = projected_seal_proof_bytes(cfg.as_ref());
+
+        let dir = tempfile::tempdir().unwrap();
+        let staged_path = dir.path().join("staged");
+        let sealed_path = dir.path().join("sealed");
+        fs::write(&staged_path, &[7; 500]).unwrap();
+
+        let output = seal_with_limits(
+            cfg.as_ref(),
+            &staged_path,
+            &sealed_path,
+            &[0; 31],
+            &[0; 31],
+            None,
+            Some(projected),
+        )
+        .unwrap();
+
+        let bytes = output.proof.len();
+        assert!(bytes <= projected, "{} bytes, projected {}", bytes, projected);
+
+        let verify = |max_proof_bytes| {
+            verify_seal_with_limit(
+                cfg.as_ref(),
+                output.comm_r,
+                output.comm_d,
+                output.comm_r_star,
+                &[0; 31],
+                &[0; 31],
+                &output.proof,
+                max_proof_bytes,
             )
-            .expect("failed to run verify_seal");
+        };
+
+        assert!(verify(Some(bytes)).unwrap());
 
-            // This should always fail, because we've rotated the commitments in
-            // the call. Note that comm_d is passed for comm_r and comm_r_star
-            // for comm_d.
-            assert!(!is_valid, "proof should not be valid");
+        // Nothing of a real seal's is screened out.
+        let (_, _, _, proof) = open_formatted_envelope(&output.proof).unwrap();
+        let screened = screen_seal_inputs(output.comm_r, output.comm_d, output.comm_r_star, proof);
+        assert!(screened.is_ok());
+
+        match verify(Some(bytes - 1)).map_err(|e| e.downcast::<SealProofErr>()) {
+            Err(Ok(SealProofErr::TooLarge { .. })) => (),
+            other => panic!("unexpected result: {:?}", other),
         }
     }
 
-    fn post_verify_aux(cs: ConfiguredStore, bytes_amt: BytesAmount) {
-        let mut rng = thread_rng();
-        let h = create_harness(&cs, &vec![bytes_amt]);
-        let seal_output = h.seal_output;
+    #[test]
+    fn proofs_of_other_variants_are_refused() {
+        let snark = new_sector_config(&ConfiguredStore::Test);
+        let vanilla = new_sector_config_with_proof_variant(&ConfiguredStore::Test, Vanilla);
+
+        for (cfg, proof) in &[
+            (&snark, seal_envelope(Vanilla, &[1, 2, 3])),
+            (&vanilla, seal_envelope(Snark, &[1, 2, 3])),
+        ] {
+            let err = verify_seal(
+                cfg.as_ref(),
+                [0; 32],
+                [0; 32],
+                [0; 32],
+                &[0; 31],
+                &[0; 31],
+                proof,
+            )
+            .expect_err("proof of the wrong variant was accepted");
 
-        let sector_bytes = h.store.config().sector_bytes();
-        let comm_r = seal_output.comm_r;
-        let comm_rs = vec![comm_r, comm_r];
-        let challenge_seed = rng.gen();
+            match err.downcast::<SealProofErr>() {
+                Ok(SealProofErr::VariantMismatch { expected, found }) => {
+                    assert_eq!(cfg.proof_variant(), expected);
+                    assert_ne!(expected, found);
+                }
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
 
-        let post_output = generate_post(
-            sector_bytes,
-            PoStInput {
-                challenge_seed,
-                input_parts: vec![
-                    PoStInputPart {
-                        sealed_sector_access: Some(h.sealed_access.clone()),
-                        comm_r,
-                    },
-                    PoStInputPart {
-                        sealed_sector_access: Some(h.sealed_access),
-                        comm_r,
-                    },
-                ],
-            },
+        let err = verify_seal(
+            vanilla.as_ref(),
+            [1; 32],
+            [2; 32],
+            [3; 32],
+            &[0; 31],
+            &[0; 31],
+            &seal_envelope(Vanilla, &[1, 2, 3]),
         )
-        .expect("PoSt generation failed");
+        .expect_err("malformed vanilla proof was accepted");
 
-        let is_valid = verify_post(
-            sector_bytes,
-            &comm_rs,
-            &challenge_seed,
-            &post_output.snark_proof,
-            post_output.faults,
+        match err.downcast::<SealProofErr>() {
+            Ok(SealProofErr::Malformed { variant: Vanilla, .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn proofs_of_other_versions_are_refused() {
+        let cfg = new_sector_config_with_proof_variant(&ConfiguredStore::Test, Vanilla);
+        let proof = seal_versioned_envelope(ProofVersion::V0Test, Vanilla, &[1, 2, 3]);
+
+        let err = verify_seal(
+            cfg.as_ref(),
+            [0; 32],
+            [0; 32],
+            [0; 32],
+            &[0; 31],
+            &[0; 31],
+            &proof,
         )
-        .expect("failed to run verify_post");;
+        .expect_err("proof of another version was accepted");
 
-        assert!(is_valid, "verification of valid proof failed");
+        match err.downcast::<SealProofErr>() {
+            Ok(SealProofErr::VersionMismatch { expected, found }) => {
+                assert_eq!(ProofVersion::V1Alpha, expected);
+                assert_eq!(ProofVersion::V0Test, found);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
     }
 
-    fn seal_unsealed_roundtrip_aux(cs: ConfiguredStore, bytes_amt: BytesAmount) {
-        let h = create_harness(&cs, &vec![bytes_amt]);
+    // The fastest of a few tries of verify, which must be refused with a
+    // RejectedInput, so that a loaded machine doesn't fail the timing.
+    fn fastest_refusal<F>(verify: F) -> (RejectedInput, Duration)
+    where
+        F: Fn() -> error::Result<bool>,
+    {
+        let mut refusals: Vec<(RejectedInput, Duration)> = (0..5)
+            .map(|_| {
+                let start = Instant::now();
+                let refused = verify().map_err(|err| err.downcast::<RejectedInput>());
+                let elapsed = start.elapsed();
+
+                match refused {
+                    Err(Ok(reason)) => (reason, elapsed),
+                    Err(Err(err)) => panic!("unexpected error: {}", err),
+                    Ok(valid) => panic!("verified, valid: {}", valid),
+                }
+            })
+            .collect();
 
-        let mut file = File::open(&h.unseal_access).unwrap();
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf).unwrap();
+        refusals.sort_by_key(|(_, elapsed)| *elapsed);
+        refusals.remove(0)
+    }
 
-        // test A
-        {
-            let read_unsealed_buf = h
-                .store
-                .manager()
-                .read_raw(&h.unseal_access, 0, buf.len() as u64)
-                .expect("failed to read_raw a");
+    #[test]
+    fn obviously_invalid_inputs_are_refused_before_any_setup() {
+        // Setting up the Test sector size's verifier takes far longer than
+        // the millisecond in which these are to be refused.
+        let cfg = new_sector_config(&ConfiguredStore::Test);
+        let proof = seal_envelope(Snark, &[7; POREP_PROOF_BYTES]);
+        let zeroes = seal_envelope(Snark, &[0; POREP_PROOF_BYTES]);
+        let (comm_r, comm_d, comm_r_star) = ([1; 32], [2; 32], [3; 32]);
+
+        let seal_refusal = |comm_r, comm_d, comm_r_star, proof: &[u8]| {
+            fastest_refusal(|| {
+                verify_seal(
+                    cfg.as_ref(),
+                    comm_r,
+                    comm_d,
+                    comm_r_star,
+                    &[0; 31],
+                    &[0; 31],
+                    proof,
+                )
+            })
+        };
+        let post_refusal = |comm_rs: &[Commitment], proof: &[u8]| {
+            fastest_refusal(|| verify_post(TEST_SECTOR_SIZE, comm_rs, &[0; 32], proof, Vec::new()))
+        };
 
-            assert_eq!(
-                &buf, &read_unsealed_buf,
-                "test A contents differed for cs={:?}, bytes_amt={:?}",
-                cs, bytes_amt
+        let refusals = vec![
+            (
+                RejectedInput::ZeroProof,
+                seal_refusal(comm_r, comm_d, comm_r_star, &zeroes),
+            ),
+            (
+                RejectedInput::ZeroProof,
+                seal_refusal(comm_r, comm_d, comm_r_star, &vec![0; zeroes.len()]),
+            ),
+            (
+                RejectedInput::ZeroCommitment("comm_d".to_string()),
+                seal_refusal(comm_r, [0; 32], comm_r_star, &proof),
+            ),
+            (
+                RejectedInput::NonCanonicalCommitment("comm_r".to_string()),
+                seal_refusal([0xff; 32], comm_d, comm_r_star, &proof),
+            ),
+            (
+                RejectedInput::ZeroProof,
+                post_refusal(&[comm_r], &[0; POST_PROOF_BYTES]),
+            ),
+            (
+                RejectedInput::NonCanonicalCommitment("comm_rs[1]".to_string()),
+                post_refusal(&[comm_r, [0xff; 32]], &[7; POST_PROOF_BYTES]),
+            ),
+        ];
+
+        for (expected, (reason, elapsed)) in refusals {
+            assert_eq!(expected, reason);
+            assert!(
+                elapsed < Duration::from_millis(1),
+                "{} took {:?} to refuse",
+                reason,
+                elapsed
             );
         }
+    }
 
-        // test B
-        {
-            let read_unsealed_buf = h
-                .store
-                .manager()
-                .read_raw(&h.unseal_access, 1, buf.len() as u64 - 2)
-                .expect("failed to read_raw a");
-
-            assert_eq!(
-                &buf[1..buf.len() - 1],
-                &read_unsealed_buf[..],
-                "test B contents differed for cs={:?}, bytes_amt={:?}",
-                cs,
-                bytes_amt
+    #[test]
+    fn prealloc_verification_refuses_proofs_the_handle_has_no_room_for() {
+        // Refused before the Test sector size's verifier is set up.
+        let cfg = new_sector_config(&ConfiguredStore::Test);
+        let proof = seal_envelope(Snark, &[7; POREP_PROOF_BYTES]);
+        let (challenges, partitions) = seal_proof_capacity(cfg.as_ref()).unwrap();
+
+        let refusal = |max_challenges, max_partitions| {
+            let mut handle = VerifierHandle::with_capacity(max_challenges, max_partitions);
+            let result = verify_seal_prealloc(
+                &mut handle,
+                cfg.as_ref(),
+                [1; 32],
+                [2; 32],
+                [3; 32],
+                &[0; 31],
+                &[0; 31],
+                &proof,
             );
-        }
 
-        let byte_padding_amount = match bytes_amt {
-            BytesAmount::Exact(bs) => {
-                h.store.config().max_unsealed_bytes_per_sector() - (bs.len() as u64)
+            match result.map_err(|err| err.downcast::<CapacityExceeded>()) {
+                Err(Ok(exceeded)) => exceeded,
+                other => panic!("unexpected result: {:?}", other),
             }
-            BytesAmount::Max => 0,
-            BytesAmount::Offset(m) => m,
         };
 
         assert_eq!(
-            h.written_contents[0].len(),
-            buf.len() - (byte_padding_amount as usize),
-            "length of original and unsealed contents differed for cs={:?}, bytes_amt={:?}",
-            cs,
-            bytes_amt
+            CapacityExceeded::Challenges {
+                needed: challenges,
+                capacity: challenges - 1,
+            },
+            refusal(challenges - 1, partitions)
         );
-
         assert_eq!(
-            h.written_contents[0][..],
-            buf[0..h.written_contents[0].len()],
-            "original and unsealed contents differed for cs={:?}, bytes_amt={:?}",
-            cs,
-            bytes_amt
+            CapacityExceeded::Partitions {
+                needed: partitions,
+                capacity: partitions - 1,
+            },
+            refusal(challenges, partitions - 1)
         );
     }
 
-    fn seal_unsealed_range_roundtrip_aux(cs: ConfiguredStore, bytes_amt: BytesAmount) {
-        let h = create_harness(&cs, &vec![bytes_amt]);
-
-        let offset = 5;
-        let range_length = h.written_contents[0].len() as u64 - offset;
+    #[test]
+    fn versions_have_their_own_parameter_identifiers() {
+        let identifier = |version| {
+            public_params_for_version(TEST_SECTOR_SIZE as usize, version).parameter_set_identifier()
+        };
 
         assert_eq!(
-            range_length,
-            get_unsealed_range(
-                h.store.config(),
-                &PathBuf::from(&h.sealed_access),
-                &PathBuf::from(&h.unseal_access),
-                &h.prover_id,
-                &h.sector_id,
-                offset,
-                range_length,
-            )
-            .expect("failed to unseal")
+            public_params(TEST_SECTOR_SIZE as usize).parameter_set_identifier(),
+            identifier(ProofVersion::CURRENT)
         );
-
-        let mut file = File::open(&h.unseal_access).unwrap();
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf).unwrap();
-
-        assert_eq!(
-            h.written_contents[0][(offset as usize)..],
-            buf[0..(range_length as usize)],
-            "original and unsealed range contents differed for cs={:?}, bytes_amt={:?}",
-            cs,
-            bytes_amt
+        assert_ne!(
+            identifier(ProofVersion::V0Test),
+            identifier(ProofVersion::V1Alpha)
         );
     }
 
-    fn write_and_preprocess_overwrites_unaligned_last_bytes_aux(cs: ConfiguredStore) {
-        // The minimal reproduction for the bug this regression test checks is to write
-        // 32 bytes, then 95 bytes.
-        // The bytes must sum to 127, since that is the required unsealed sector size.
-        // With suitable bytes (.e.g all 255), the bug always occurs when the first chunk is >= 32.
-        // It never occurs when the first chunk is < 32.
-        // The root problem was that write_and_preprocess was opening in append mode, so seeking backward
-        // to overwrite the last, incomplete byte, was not happening.
-        let contents_a = [255; 32];
-        let contents_b = [255; 95];
-
-        let h = create_harness(
-            &cs,
-            &vec![
-                BytesAmount::Exact(&contents_a),
-                BytesAmount::Exact(&contents_b),
-            ],
-        );
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn seals_are_made_and_verified_with_their_version() {
+        let dir = tempfile::tempdir().unwrap();
+        let staged_path = dir.path().join("staged");
+        fs::write(&staged_path, &[7; 500]).unwrap();
+
+        for version in &[ProofVersion::V0Test, ProofVersion::V1Alpha] {
+            let cfg =
+                new_sector_config_with_proof_version(&ConfiguredStore::Test, Vanilla, *version);
+            let sealed_path = dir.path().join(format!("sealed-{:?}", version));
+
+            let output =
+                seal(cfg.as_ref(), &staged_path, &sealed_path, &[3; 31], &[4; 31]).unwrap();
+
+            let verify = |cfg: &SectorConfig| {
+                verify_seal(
+                    cfg,
+                    output.comm_r,
+                    output.comm_d,
+                    output.comm_r_star,
+                    &[3; 31],
+                    &[4; 31],
+                    &output.proof,
+                )
+            };
+            assert!(verify(cfg.as_ref()).unwrap());
 
-        let unseal_access = h
-            .store
-            .manager()
-            .new_sealed_sector_access()
-            .expect("could not create unseal access");
-
-        let _ = get_unsealed_range(
-            h.store.config(),
-            &h.sealed_access,
-            &unseal_access,
-            &h.prover_id,
-            &h.sector_id,
-            0,
-            (contents_a.len() + contents_b.len()) as u64,
-        )
-        .expect("failed to unseal");
+            let other_version = match version {
+                ProofVersion::V0Test => ProofVersion::V1Alpha,
+                ProofVersion::V1Alpha | ProofVersion::Mini => ProofVersion::V0Test,
+            };
+            let other = new_sector_config_with_proof_version(
+                &ConfiguredStore::Test,
+                Vanilla,
+                other_version,
+            );
+            match verify(other.as_ref()).map_err(|e| e.downcast::<SealProofErr>()) {
+                Err(Ok(SealProofErr::VersionMismatch { .. })) => (),
+                other => panic!("unexpected result: {:?}", other),
+            }
 
-        let mut file = File::open(&unseal_access).unwrap();
-        let mut buf_from_file = Vec::new();
-        file.read_to_end(&mut buf_from_file).unwrap();
+            let unsealed = unseal_range(
+                cfg.as_ref(),
+                &sealed_path,
+                &[3; 31],
+                &[4; 31],
+                0,
+                500,
+                LegacyReplicas::Refuse,
+            )
+            .unwrap();
+            assert_eq!(vec![7; 500], unsealed);
+        }
+    }
 
-        assert_eq!(
-            contents_a.len() + contents_b.len(),
-            buf_from_file.len(),
-            "length of original and unsealed contents differed for {:?}",
-            cs
-        );
+    // Staged sectors whose zeros are left as holes seal exactly as sectors
+    // written densely do.
+    #[test]
+    fn sparse_staged_sectors_seal_as_dense_ones_do() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging = dir.path().join("staging").to_str().unwrap().to_owned();
+        let store = new_mini_sector_store(staging.clone(), staging);
+        let mgr = store.manager();
 
-        assert_eq!(
-            contents_a[..],
-            buf_from_file[0..contents_a.len()],
-            "original and unsealed contents differed for {:?}",
-            cs
-        );
+        // Large enough for a hole, but cheap to seal with Mini's parameters.
+        let cfg = new_sector_config_of_version(8192, Vanilla, ProofVersion::Mini);
+        let piece = [7u8; 100];
+        let zeros = vec![0u8; cfg.max_unsealed_bytes_per_sector() as usize - piece.len()];
 
-        assert_eq!(
-            contents_b[..],
-            buf_from_file[contents_a.len()..contents_a.len() + contents_b.len()],
-            "original and unsealed contents differed for {:?}",
-            cs
-        );
-    }
+        let sparse_path = mgr.new_staging_sector_access().unwrap();
+        mgr.write_and_preprocess(&sparse_path, &piece).unwrap();
+        mgr.write_and_preprocess(&sparse_path, &zeros).unwrap();
 
-    /*
+        // The last byte of the piece is only partly used, so writing the zeros
+        // reads it back: the dense copy is written through a cursor.
+        let dense_path = dir.path().join("dense");
+        let mut dense = io::Cursor::new(Vec::new());
+        write_padded(&piece, &mut dense).unwrap();
+        write_padded(&zeros, &mut dense).unwrap();
+        fs::write(&dense_path, dense.into_inner()).unwrap();
 
-    TODO: create a way to run these super-slow-by-design tests manually.
+        assert_eq!(fs::read(&dense_path).unwrap(), fs::read(&sparse_path).unwrap());
 
-    fn seal_verify_live() {
-        seal_verify_aux(ConfiguredStore::Live, 0);
-        seal_verify_aux(ConfiguredStore::Live, 5);
-    }
+        let seal_at = |staged_path: &Path, name: &str| {
+            let sealed_path = dir.path().join(name);
+            seal(cfg.as_ref(), staged_path, sealed_path.as_path(), &[1; 31], &[2; 31]).unwrap()
+        };
+        let sparse = seal_at(Path::new(&sparse_path), "sealed-sparse");
+        let dense = seal_at(&dense_path, "sealed-dense");
 
-    fn seal_unsealed_roundtrip_live() {
-        seal_unsealed_roundtrip_aux(ConfiguredStore::Live, 0);
-        seal_unsealed_roundtrip_aux(ConfiguredStore::Live, 5);
+        assert_eq!(dense.comm_d, sparse.comm_d);
+        assert_eq!(dense.comm_r, sparse.comm_r);
+        assert_eq!(dense.comm_r_star, sparse.comm_r_star);
     }
 
-    fn seal_unsealed_range_roundtrip_live() {
-        seal_unsealed_range_roundtrip_aux(ConfiguredStore::Live, 0);
-        seal_unsealed_range_roundtrip_aux(ConfiguredStore::Live, 5);
-    }
+    #[test]
+    fn seals_are_verified_against_their_pieces() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging = dir.path().join("staging").to_str().unwrap().to_owned();
+        let store = new_mini_sector_store(staging.clone(), staging);
+        let mgr = store.manager();
+        let cfg = new_sector_config_of_version(8192, Vanilla, ProofVersion::Mini);
+
+        // Packed one after another, as add_piece packs them, these pieces are
+        // in the aligned layout.
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+        let pieces: Vec<Vec<u8>> = [254, 127, 127]
+            .iter()
+            .map(|&length| (0..length).map(|_| rng.gen()).collect())
+            .collect();
+
+        let staged_path = mgr.new_staging_sector_access().unwrap();
+        for piece in &pieces {
+            mgr.write_and_preprocess(&staged_path, piece).unwrap();
+        }
 
-    */
+        let sealed_path = dir.path().join("sealed");
+        let output = seal(
+            cfg.as_ref(),
+            Path::new(&staged_path),
+            &sealed_path,
+            &[1; 31],
+            &[2; 31],
+        )
+        .unwrap();
+
+        let comm_ps: Vec<(Commitment, u64)> = pieces
+            .iter()
+            .map(|piece| (compute_comm_p(piece).unwrap(), piece.len() as u64))
+            .collect();
+        let verify = |pieces: &[(Commitment, u64)]| {
+            verify_seal_with_pieces(
+                cfg.as_ref(),
+                output.comm_r,
+                output.comm_d,
+                output.comm_r_star,
+                &[1; 31],
+                &[2; 31],
+                &output.proof,
+                pieces,
+            )
+            .map_err(|err| {
+                err.downcast::<PieceLayoutErr>()
+                    .unwrap_or_else(|err| panic!("unexpected error: {}", err))
+            })
+        };
 
-    #[test]
-    #[ignore] // Slow test – run only when compiled for release.
-    fn seal_verify_test() {
-        seal_verify_aux(ConfiguredStore::Test, BytesAmount::Max);
-        seal_verify_aux(ConfiguredStore::Test, BytesAmount::Offset(5));
-    }
+        assert_eq!(Ok(true), verify(&comm_ps));
 
-    #[test]
-    #[ignore] // Slow test – run only when compiled for release.
-    fn seal_unsealed_roundtrip_test() {
-        seal_unsealed_roundtrip_aux(ConfiguredStore::Test, BytesAmount::Max);
-        seal_unsealed_roundtrip_aux(ConfiguredStore::Test, BytesAmount::Offset(5));
-    }
+        // The pieces' order is part of the commitment, even where swapping
+        // two pieces leaves the layout aligned.
+        let swapped = vec![comm_ps[0], comm_ps[2], comm_ps[1]];
+        assert_eq!(Err(PieceLayoutErr::CommDMismatch), verify(&swapped));
+        assert_eq!(Err(PieceLayoutErr::CommDMismatch), verify(&[]));
 
-    #[test]
-    #[ignore] // Slow test – run only when compiled for release.
-    fn seal_unsealed_range_roundtrip_test() {
-        seal_unsealed_range_roundtrip_aux(ConfiguredStore::Test, BytesAmount::Max);
-        seal_unsealed_range_roundtrip_aux(ConfiguredStore::Test, BytesAmount::Offset(5));
+        // Packed in this order, the pieces' comm_ps can't give comm_d.
+        let unaligned = vec![comm_ps[1], comm_ps[0]];
+        assert_eq!(
+            Err(PieceLayoutErr::NotAligned(vec![127, 254])),
+            verify(&unaligned)
+        );
     }
 
-    #[test]
-    #[ignore] // Slow test – run only when compiled for release.
-    fn write_and_preprocess_overwrites_unaligned_last_bytes() {
-        write_and_preprocess_overwrites_unaligned_last_bytes_aux(ConfiguredStore::Test);
+    // Replicates random data into a test sector at sealed_path, returning the
+    // root of its replica's merkle tree as its comm_r.
+    fn replicate_random_test_sector(rng: &mut XorShiftRng, sealed_path: &Path) -> Commitment {
+        let max = new_sector_config(&ConfiguredStore::Test).max_unsealed_bytes_per_sector();
+        let original: Vec<u8> = (0..max).map(|_| rng.gen()).collect();
+        let data = pad_sector_data(preprocess(&original), TEST_SECTOR_SIZE as usize);
+        replicate_test_sector(data, sealed_path);
+
+        let tree = make_merkle_tree(
+            sealed_path,
+            TEST_SECTOR_SIZE as usize,
+            LegacyReplicas::Refuse,
+        )
+        .unwrap();
+
+        commitment_from_fr(tree.root().into())
     }
 
     #[test]
-    #[ignore] // Slow test – run only when compiled for release.
-    fn concurrent_seal_unsealed_range_roundtrip_test() {
-        let threads = 5;
-
-        let spawned = (0..threads)
-            .map(|_| {
-                thread::spawn(|| {
-                    seal_unsealed_range_roundtrip_aux(ConfiguredStore::Test, BytesAmount::Max)
+    fn detailed_post_verification_names_the_corrupt_sector() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let dir = tempfile::tempdir().unwrap();
+
+        let sealed_paths: Vec<PathBuf> = (0..3)
+            .map(|i| dir.path().join(format!("sealed-{}", i)))
+            .collect();
+        let comm_rs: Vec<Commitment> = sealed_paths
+            .iter()
+            .map(|path| replicate_random_test_sector(rng, path))
+            .collect();
+        let challenge_seed: ChallengeSeed = rng.gen();
+
+        let prove = || {
+            let input_parts = sealed_paths
+                .iter()
+                .zip(&comm_rs)
+                .map(|(path, comm_r)| PoStInputPart {
+                    sealed_sector_access: Some(path.to_str().unwrap().to_string()),
+                    comm_r: *comm_r,
+                    legacy_replicas: LegacyReplicas::Refuse,
                 })
-            })
-            .collect::<Vec<_>>();
+                .collect();
+
+            generate_vanilla_post(
+                TEST_SECTOR_SIZE,
+                PoStInput {
+                    challenge_seed,
+                    input_parts,
+                },
+            )
+            .unwrap()
+        };
+        let verify = |proof: &[u8]| {
+            verify_post_detailed(TEST_SECTOR_SIZE, &comm_rs, &challenge_seed, proof, vec![])
+                .unwrap()
+        };
 
-        for thread in spawned {
-            thread.join().expect("test thread panicked");
+        let report = verify(&prove());
+        assert!(report.is_valid());
+        assert_eq!(Vec::<PostVerificationFailure>::new(), report.failures);
+
+        // Every challenge is of the first sector for now (see
+        // vdf_post::derive_final_challenges), so it's the one corrupted.
+        let corrupt = 0;
+        let mut replica = fs::read(&sealed_paths[corrupt]).unwrap();
+        replica[0] ^= 1;
+        fs::write(&sealed_paths[corrupt], &replica).unwrap();
+
+        let report = verify(&prove());
+        assert!(!report.is_valid());
+        assert_eq!(0, report.failures.len() % POST_CHALLENGE_COUNT);
+
+        for (i, failure) in report.failures.iter().enumerate() {
+            assert_eq!(corrupt, failure.sector_index);
+            assert_eq!(comm_rs[corrupt], failure.comm_r);
+            assert_eq!(Some(i), failure.challenge_index);
+            assert_eq!(vdf_post::FailureKind::PathMismatch, failure.kind);
         }
     }
-
-    #[test]
-    #[ignore]
-    fn post_verify_test() {
-        post_verify_aux(ConfiguredStore::Test, BytesAmount::Max);
-    }
 }
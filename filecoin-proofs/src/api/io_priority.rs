@@ -0,0 +1,284 @@
+//! Coarse prioritization of disk bandwidth between retrievals and seals. A
+//! seal writes a whole sector in one sequential pass, which on a busy disk
+//! leaves a retrieval queued behind it waiting for that pass to end. Rather
+//! than rely on OS-level IO scheduling, seals cooperate: they write in chunks
+//! of IoPriority::seal_write_chunk_bytes and, while any retrieval is in flight
+//! (as counted by a RetrievalGauge), pause for seal_write_pause after each
+//! chunk, leaving the disk to the retrieval. No retrieval in flight, no pause.
+
+use std::cmp;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::api::parallelism;
+
+/// The number of retrieval workers unless configured otherwise, or the
+/// effective parallelism (see parallelism) is lower.
+pub const DEFAULT_RETRIEVAL_WORKERS: usize = 2;
+pub const DEFAULT_SEAL_WRITE_CHUNK_BYTES: usize = 4 << 20;
+pub const DEFAULT_SEAL_WRITE_PAUSE_MILLIS: u64 = 50;
+
+/// How a sector builder shares the disk between retrievals and seals.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct IoPriority {
+    /// The number of threads serving retrievals, which are never taken by
+    /// seals.
+    pub retrieval_workers: usize,
+
+    /// The most bytes a seal writes between two pauses.
+    pub seal_write_chunk_bytes: usize,
+
+    /// How long a seal pauses after each chunk while a retrieval is in flight.
+    pub seal_write_pause: Duration,
+}
+
+impl Default for IoPriority {
+    fn default() -> IoPriority {
+        IoPriority {
+            retrieval_workers: cmp::min(
+                DEFAULT_RETRIEVAL_WORKERS,
+                parallelism::get_effective_parallelism(),
+            ),
+            seal_write_chunk_bytes: DEFAULT_SEAL_WRITE_CHUNK_BYTES,
+            seal_write_pause: Duration::from_millis(DEFAULT_SEAL_WRITE_PAUSE_MILLIS),
+        }
+    }
+}
+
+impl IoPriority {
+    /// The priority the C API configures, where 0 stands for the default of
+    /// each knob.
+    pub fn from_ffi(
+        retrieval_workers: u8,
+        seal_write_chunk_bytes: u64,
+        seal_write_pause_millis: u64,
+    ) -> IoPriority {
+        let default = IoPriority::default();
+
+        IoPriority {
+            retrieval_workers: match retrieval_workers {
+                0 => default.retrieval_workers,
+                n => n as usize,
+            },
+            seal_write_chunk_bytes: match seal_write_chunk_bytes {
+                0 => default.seal_write_chunk_bytes,
+                n => n as usize,
+            },
+            seal_write_pause: match seal_write_pause_millis {
+                0 => default.seal_write_pause,
+                n => Duration::from_millis(n),
+            },
+        }
+    }
+}
+
+/// Counts the retrievals in flight, across all its clones.
+#[derive(Debug, Clone, Default)]
+pub struct RetrievalGauge(Arc<AtomicUsize>);
+
+/// Counts a retrieval as in flight until it's dropped.
+#[derive(Debug)]
+pub struct RetrievalGuard(Arc<AtomicUsize>);
+
+impl RetrievalGauge {
+    pub fn new() -> RetrievalGauge {
+        Default::default()
+    }
+
+    pub fn begin(&self) -> RetrievalGuard {
+        self.0.fetch_add(1, Ordering::SeqCst);
+
+        RetrievalGuard(self.0.clone())
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for RetrievalGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Paces a seal's writes, yielding the disk to retrievals in flight.
+#[derive(Debug, Clone)]
+pub struct SealThrottle {
+    retrievals: RetrievalGauge,
+    chunk_bytes: usize,
+    pause: Duration,
+}
+
+impl Default for SealThrottle {
+    /// A throttle which never pauses, as no retrieval is counted against it.
+    fn default() -> SealThrottle {
+        SealThrottle::new(RetrievalGauge::new(), &IoPriority::default())
+    }
+}
+
+impl SealThrottle {
+    pub fn new(retrievals: RetrievalGauge, priority: &IoPriority) -> SealThrottle {
+        SealThrottle {
+            retrievals,
+            chunk_bytes: priority.seal_write_chunk_bytes.max(1),
+            pause: priority.seal_write_pause,
+        }
+    }
+
+    /// Writes all of bytes to dest, chunk by chunk.
+    pub fn write_all<W: Write>(&self, dest: &mut W, bytes: &[u8]) -> io::Result<()> {
+        for chunk in bytes.chunks(self.chunk_bytes) {
+            dest.write_all(chunk)?;
+            self.yield_to_retrievals();
+        }
+
+        Ok(())
+    }
+
+    /// Copies source to its end into dest, chunk by chunk, as io::copy would,
+    /// returning the number of bytes copied.
+    pub fn copy<R: Read, W: Write>(&self, source: &mut R, dest: &mut W) -> io::Result<u64> {
+        let mut chunk = vec![0; self.chunk_bytes];
+        let mut copied = 0;
+
+        loop {
+            let n = read_chunk(source, &mut chunk)?;
+            if n == 0 {
+                return Ok(copied);
+            }
+
+            dest.write_all(&chunk[..n])?;
+            copied += n as u64;
+            self.yield_to_retrievals();
+        }
+    }
+
+    fn yield_to_retrievals(&self) {
+        if self.retrievals.in_flight() > 0 {
+            thread::sleep(self.pause);
+        }
+    }
+}
+
+// Fills chunk from source, short only at its end.
+fn read_chunk<R: Read>(source: &mut R, chunk: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < chunk.len() {
+        match source.read(&mut chunk[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(filled)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    // A disk which takes 5ms per write or read, one at a time.
+    #[derive(Clone, Default)]
+    struct SlowDisk {
+        busy: Arc<Mutex<Vec<u8>>>,
+    }
+
+    const DISK_LATENCY_MILLIS: u64 = 5;
+
+    impl Write for SlowDisk {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let mut written = self.busy.lock().unwrap();
+            thread::sleep(Duration::from_millis(DISK_LATENCY_MILLIS));
+            written.extend_from_slice(buf);
+
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl SlowDisk {
+        fn read(&self) {
+            let _busy = self.busy.lock().unwrap();
+            thread::sleep(Duration::from_millis(DISK_LATENCY_MILLIS));
+        }
+    }
+
+    fn priority(chunk_bytes: usize, pause_millis: u64) -> IoPriority {
+        IoPriority {
+            retrieval_workers: 1,
+            seal_write_chunk_bytes: chunk_bytes,
+            seal_write_pause: Duration::from_millis(pause_millis),
+        }
+    }
+
+    #[test]
+    fn seals_only_pause_while_retrievals_are_in_flight() {
+        let gauge = RetrievalGauge::new();
+        let throttle = SealThrottle::new(gauge.clone(), &priority(10, 20));
+        let bytes: Vec<u8> = (0..100).collect();
+
+        let start = Instant::now();
+        let mut written = Vec::new();
+        throttle.write_all(&mut written, &bytes).unwrap();
+        assert_eq!(bytes, written);
+        assert!(start.elapsed() < Duration::from_millis(100));
+
+        let guard = gauge.begin();
+        assert_eq!(1, gauge.clone().in_flight());
+
+        let start = Instant::now();
+        let mut copied = Vec::new();
+        assert_eq!(100, throttle.copy(&mut &bytes[..], &mut copied).unwrap());
+        assert_eq!(bytes, copied);
+        assert!(start.elapsed() >= Duration::from_millis(200));
+
+        drop(guard);
+        assert_eq!(0, gauge.in_flight());
+    }
+
+    #[test]
+    fn retrievals_are_not_starved_by_seals() {
+        let gauge = RetrievalGauge::new();
+        let throttle = SealThrottle::new(gauge.clone(), &priority(1, 20));
+        let disk = SlowDisk::default();
+
+        // A seal which, unthrottled, holds the disk for 200 writes of 5ms.
+        let sealed: Vec<u8> = (0..200).map(|n| n as u8).collect();
+        let seal = {
+            let (sealed, mut disk) = (sealed.clone(), disk.clone());
+            thread::spawn(move || throttle.write_all(&mut disk, &sealed))
+        };
+
+        thread::sleep(Duration::from_millis(50));
+
+        // A retrieval of 5 reads, which takes its turns on the disk in the
+        // seal's pauses.
+        let start = Instant::now();
+        {
+            let _in_flight = gauge.begin();
+            for _ in 0..5 {
+                disk.read();
+            }
+        }
+        let retrieval_elapsed = start.elapsed();
+
+        // The retrieval is done long before the seal.
+        assert!(retrieval_elapsed < Duration::from_millis(500));
+        assert!(disk.busy.lock().unwrap().len() < sealed.len());
+
+        seal.join().unwrap().unwrap();
+        assert_eq!(sealed, *disk.busy.lock().unwrap());
+    }
+}
@@ -0,0 +1,210 @@
+//! Verification of the seal proofs of earlier releases, made in formats this
+//! release no longer makes (see seal_proof::PROOF_FORMAT), so that nodes can
+//! upgrade while the chain still holds them.
+//!
+//! Each such format is frozen in a module of its own, named for it, holding
+//! copies of whatever its proofs are verified with that a later format has
+//! changed, or may: the layout of its circuits' public inputs, the derivation
+//! of its challenges and the identifiers of its groth parameters. A frozen
+//! module is never changed, only dropped once no verifier accepts its format.
+//!
+//! A release which changes the format bumps PROOF_FORMAT, copies what it
+//! changes into a module for the format it replaces, adds that module to
+//! FROZEN_FORMATS, and checks in what the release before it verified its
+//! proofs against, as computed by that release's own code, for the module's
+//! tests to check its copy against (e.g. tests/golden/legacy_v0_vectors.json).
+//!
+//! The C API takes SNARK proofs without their envelopes, and so verifies them
+//! as proofs of PROOF_FORMAT.
+
+pub mod v0;
+
+use crate::api::seal_proof::{err_variant_mismatch, SealProofErr, PROOF_FORMAT};
+use crate::error;
+use sector_base::api::sector_store::{ProofVariant, ProofVersion};
+use storage_proofs::types::{Commitment, FrSafe};
+
+// Verifies a frozen format's SNARK proof, out of its envelope, of a sector of
+// the given size sealed with the given version's parameters, against comm_r,
+// comm_d, comm_r_star, the prover id and the sector id.
+type FrozenVerifier = fn(
+    usize,
+    ProofVersion,
+    Commitment,
+    Commitment,
+    Commitment,
+    &FrSafe,
+    &FrSafe,
+    &[u8],
+) -> error::Result<bool>;
+
+// Every frozen format, oldest first, and the verifier of its proofs.
+const FROZEN_FORMATS: &[(u8, FrozenVerifier)] = &[(0, v0::verify_seal)];
+
+/// Returns the formats frozen in this release, oldest first.
+pub fn frozen_formats() -> Vec<u8> {
+    FROZEN_FORMATS.iter().map(|(format, _)| *format).collect()
+}
+
+/// Which frozen formats a verifier accepts the proofs of. Proofs of
+/// PROOF_FORMAT are always accepted. By default every frozen format is, so
+/// that proofs made before an upgrade keep verifying until the window for
+/// moving off their format is closed by refusing it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LegacyProofSupport {
+    // Bit i is set if the proofs of format i are accepted.
+    accepted: u16,
+}
+
+impl LegacyProofSupport {
+    /// Accepts the proofs of every frozen format.
+    pub fn all() -> LegacyProofSupport {
+        LegacyProofSupport::accepting(&frozen_formats())
+    }
+
+    /// Accepts only the proofs of PROOF_FORMAT.
+    pub fn none() -> LegacyProofSupport {
+        LegacyProofSupport { accepted: 0 }
+    }
+
+    /// Accepts the proofs of the given formats, of those which are frozen.
+    pub fn accepting(formats: &[u8]) -> LegacyProofSupport {
+        let accepted = formats
+            .iter()
+            .filter(|format| **format < 16)
+            .fold(0, |accepted, format| accepted | (1 << format));
+
+        LegacyProofSupport { accepted }
+    }
+
+    /// Whether the proofs of the given format are accepted.
+    pub fn accepts(&self, format: u8) -> bool {
+        format == PROOF_FORMAT || (format < 16 && self.accepted & (1 << format) != 0)
+    }
+
+    /// Verifies a proof of a frozen format, out of its envelope, as the
+    /// release which last made the format verified it. Fails with
+    /// UnknownFormat if the format isn't frozen, e.g. if it's a later
+    /// release's, and with FormatNotAccepted if it isn't accepted. Only SNARK
+    /// proofs are verified, as only they were ever put on chain.
+    #[allow(clippy::too_many_arguments)]
+    pub fn verify_seal(
+        &self,
+        format: u8,
+        sector_bytes: usize,
+        version: ProofVersion,
+        variant: ProofVariant,
+        comm_r: Commitment,
+        comm_d: Commitment,
+        comm_r_star: Commitment,
+        prover_id: &FrSafe,
+        sector_id: &FrSafe,
+        proof: &[u8],
+    ) -> error::Result<bool> {
+        let verify = FROZEN_FORMATS
+            .iter()
+            .find(|(frozen, _)| *frozen == format)
+            .map(|(_, verify)| verify)
+            .ok_or(SealProofErr::UnknownFormat(format))?;
+
+        if !self.accepts(format) {
+            return Err(SealProofErr::FormatNotAccepted(format).into());
+        }
+
+        if variant != ProofVariant::Snark {
+            return Err(err_variant_mismatch(ProofVariant::Snark, variant).into());
+        }
+
+        verify(
+            sector_bytes,
+            version,
+            comm_r,
+            comm_d,
+            comm_r_star,
+            prover_id,
+            sector_id,
+            proof,
+        )
+    }
+}
+
+impl Default for LegacyProofSupport {
+    fn default() -> LegacyProofSupport {
+        LegacyProofSupport::all()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verify(
+        legacy: LegacyProofSupport,
+        format: u8,
+        variant: ProofVariant,
+    ) -> error::Result<bool> {
+        legacy.verify_seal(
+            format,
+            1024,
+            ProofVersion::V1Alpha,
+            variant,
+            [1; 32],
+            [2; 32],
+            [3; 32],
+            &[4; 31],
+            &[5; 31],
+            &[6; 384],
+        )
+    }
+
+    fn assert_refused(result: error::Result<bool>, expected: SealProofErr) {
+        match result.map_err(|err| err.downcast::<SealProofErr>()) {
+            Err(Ok(ref err)) if format!("{:?}", err) == format!("{:?}", expected) => (),
+            Err(Ok(err)) => panic!("unexpected error: {}", err),
+            Err(Err(err)) => panic!("unexpected error: {}", err),
+            Ok(valid) => panic!("unexpected result: {}", valid),
+        }
+    }
+
+    #[test]
+    fn frozen_formats_precede_the_current_one() {
+        assert_eq!(vec![0], frozen_formats());
+        assert!(frozen_formats().iter().all(|format| *format < PROOF_FORMAT));
+    }
+
+    #[test]
+    fn policies_accept_the_formats_they_name() {
+        assert!(LegacyProofSupport::all().accepts(0));
+        assert_eq!(LegacyProofSupport::all(), LegacyProofSupport::default());
+        assert_eq!(
+            LegacyProofSupport::all(),
+            LegacyProofSupport::accepting(&[0])
+        );
+
+        assert!(!LegacyProofSupport::none().accepts(0));
+        assert!(!LegacyProofSupport::accepting(&[3, 200]).accepts(0));
+
+        // The current format can't be refused.
+        assert!(LegacyProofSupport::none().accepts(PROOF_FORMAT));
+    }
+
+    // Proofs are refused before any parameters are looked for.
+    #[test]
+    fn refused_formats_are_never_verified() {
+        let none = LegacyProofSupport::none();
+        let all = LegacyProofSupport::all();
+
+        assert_refused(
+            verify(none, 0, ProofVariant::Snark),
+            SealProofErr::FormatNotAccepted(0),
+        );
+        assert_refused(
+            verify(all, 7, ProofVariant::Snark),
+            SealProofErr::UnknownFormat(7),
+        );
+        assert_refused(
+            verify(all, 0, ProofVariant::Vanilla),
+            err_variant_mismatch(ProofVariant::Snark, ProofVariant::Vanilla),
+        );
+    }
+}
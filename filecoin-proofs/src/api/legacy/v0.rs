@@ -0,0 +1,470 @@
+//! Verification of seal proofs of format 0: those of the release before proofs
+//! were put in envelopes, which made a sector's proof as its partitions' groth
+//! proofs, one after another, and nothing else. They're handed in wrapped in
+//! an envelope of format 0, whose tag (0) names a SNARK proof of V1Alpha's
+//! parameters, the only ones that release had.
+//!
+//! Frozen here is everything that release verified them with but its graph and
+//! replica ids: its proof parameters, the identifiers of its groth parameters,
+//! the derivation of its challenges and the layout of its circuits' public
+//! inputs.
+//! The zigzag graph, with its default feistel keys, and the hash of a replica's
+//! id are shared with the current format, which hasn't changed them. Should a
+//! release change either, it freezes that release's copy here first.
+//!
+//! tests/golden/legacy_v0_vectors.json pins all but the replica id (which
+//! tests/golden/encoding_vectors.json pins) to what that release computed: it
+//! was generated by that release's own challenge derivation, graphs and
+//! parameter identifiers. tests/golden/legacy_v0_proofs.json is a proof that
+//! release made, which tests/legacy_proof_fixtures.rs verifies.
+//!
+//! Nothing in this module may change while format 0 is verified.
+
+use std::cmp::{max, min};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use bellman::groth16;
+use byteorder::{LittleEndian, WriteBytesExt};
+use pairing::bls12_381::{Bls12, Fr};
+use pairing::PrimeField;
+use sapling_crypto::circuit::multipack;
+use sha2::{Digest, Sha256};
+
+use sector_base::api::disk_backed_storage::LIVE_SECTOR_SIZE;
+use sector_base::api::sector_store::{ProofVariant, ProofVersion};
+use storage_proofs::crypto::blake2s::blake2s;
+use storage_proofs::drgraph::{graph_height, Graph};
+use storage_proofs::hasher::pedersen::{PedersenDomain, PedersenHasher};
+use storage_proofs::hasher::Domain;
+use storage_proofs::parameter_cache::{parameter_cache_dir, read_cached_params};
+use storage_proofs::types::{fr_from_commitment, Commitment, FrSafe};
+use storage_proofs::util::bytes_into_bits;
+use storage_proofs::zigzag_graph::{ZigZag, ZigZagBucketGraph};
+
+use crate::api::seal_proof::{err_malformed, err_version_mismatch};
+use crate::encoding::replica_id_domain;
+use crate::error;
+
+const PARTITIONS: usize = 2;
+
+const DEGREE: usize = 5;
+const EXPANSION_DEGREE: usize = 8;
+const SLOTH_ITER: usize = 0;
+const LAYERS: usize = 4;
+const TAPER_LAYERS: usize = 2;
+const TAPER: f64 = 1.0 / 3.0;
+const CHALLENGE_COUNT: usize = 2;
+const DRG_SEED: [u32; 7] = [1, 2, 3, 4, 5, 6, 7];
+
+// The version of the parameter cache the groth parameters were cached under.
+const PARAMETER_CACHE_VERSION: usize = 9;
+
+const CACHE_PREFIX: &str = "zigzag-proof-of-replication";
+
+// The file the official groth parameters, of live sectors, were installed as.
+const OFFICIAL_PARAMS_FILENAME: &str = "params.out";
+
+type ZigZagGraph = ZigZagBucketGraph<PedersenHasher>;
+
+// What verifying a proof of a sector of some size takes, besides the proof.
+struct Verifier {
+    graph: ZigZagGraph,
+    pvk: groth16::PreparedVerifyingKey<Bls12>,
+}
+
+lazy_static! {
+    // Keyed by sector size. Only a handful exist.
+    static ref VERIFIERS: Mutex<HashMap<usize, Arc<Verifier>>> = Mutex::new(HashMap::new());
+}
+
+fn verifier(sector_bytes: usize) -> error::Result<Arc<Verifier>> {
+    if let Some(verifier) = VERIFIERS
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .get(&sector_bytes)
+    {
+        return Ok(verifier.clone());
+    }
+
+    let graph = graph(sector_bytes)?;
+    let groth_params = read_groth_params(sector_bytes)?;
+    let pvk = groth16::prepare_verifying_key(&groth_params.vk);
+
+    let verifier = Arc::new(Verifier { graph, pvk });
+    VERIFIERS
+        .lock()
+        .unwrap_or_else(|p| p.into_inner())
+        .insert(sector_bytes, verifier.clone());
+
+    Ok(verifier)
+}
+
+// The graph of a sector's first layer, each later layer's being the zigzag of
+// the one before.
+fn graph(sector_bytes: usize) -> error::Result<ZigZagGraph> {
+    if sector_bytes % 32 != 0 || sector_bytes < 3 * 32 {
+        return Err(format_err!("invalid sector size: {}", sector_bytes));
+    }
+
+    Ok(ZigZagGraph::new_zigzag(
+        sector_bytes / 32,
+        DEGREE,
+        EXPANSION_DEGREE,
+        DRG_SEED,
+    ))
+}
+
+// The groth parameters of proofs of the given sectors: the official ones of
+// live sectors if installed, else those cached under their identifier. They're
+// never generated here: a node verifying format 0 has them from before
+// upgrading, and the current circuits are no longer format 0's.
+fn read_groth_params(sector_bytes: usize) -> error::Result<groth16::Parameters<Bls12>> {
+    let cached = parameter_cache_dir().join(params_filename(sector_bytes));
+
+    if sector_bytes as u64 == LIVE_SECTOR_SIZE {
+        let official = parameter_cache_dir().join(OFFICIAL_PARAMS_FILENAME);
+        if let Ok(params) = read_cached_params(&official) {
+            return Ok(params);
+        }
+    }
+
+    Ok(read_cached_params(&cached)?)
+}
+
+// The name of the file the groth parameters of the given sectors were cached
+// as: the cache's version, then the sha256 of their parameter set's
+// identifier.
+fn params_filename(sector_bytes: usize) -> String {
+    let digest = Sha256::digest(parameter_set_identifier(sector_bytes).as_bytes());
+    let hex: String = digest.iter().map(|byte| format!("{:02x}", byte)).collect();
+
+    format!("v{}-{}-{}", PARAMETER_CACHE_VERSION, CACHE_PREFIX, hex)
+}
+
+fn parameter_set_identifier(sector_bytes: usize) -> String {
+    let graph = format!(
+        "zigzag_graph::ZigZagGraph{{expansion_degree: {} base_graph: \
+         drgraph::BucketGraph{{size: {}; degree: {}}} }}",
+        EXPANSION_DEGREE,
+        sector_bytes / 32,
+        DEGREE
+    );
+    let challenges = format!(
+        "Tapered {{ layers: {}, count: {}, taper: {:?}, taper_layers: {} }}",
+        LAYERS, CHALLENGE_COUNT, TAPER, TAPER_LAYERS
+    );
+
+    format!(
+        "layered_drgporep::PublicParams{{ drg_porep_identifier: \
+         drgporep::PublicParams{{graph: {}; sloth_iter: {}}}, challenges: {} }}",
+        graph, SLOTH_ITER, challenges
+    )
+}
+
+/// Verifies a format 0 SNARK proof, out of its envelope, of a sector of
+/// sector_bytes bytes sealed with version's parameters, which must be
+/// V1Alpha's.
+#[allow(clippy::too_many_arguments)]
+pub fn verify_seal(
+    sector_bytes: usize,
+    version: ProofVersion,
+    comm_r: Commitment,
+    comm_d: Commitment,
+    comm_r_star: Commitment,
+    prover_id: &FrSafe,
+    sector_id: &FrSafe,
+    proof: &[u8],
+) -> error::Result<bool> {
+    if version != ProofVersion::V1Alpha {
+        return Err(err_version_mismatch(ProofVersion::V1Alpha, version).into());
+    }
+
+    // As that release did, the partitions' proofs are read from the front of
+    // the proof, and whatever follows them is ignored.
+    let mut reader = proof;
+    let proofs = (0..PARTITIONS)
+        .map(|_| groth16::Proof::<Bls12>::read(&mut reader))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| err_malformed(ProofVariant::Snark, err))?;
+
+    let replica_id = replica_id_domain(*prover_id, *sector_id);
+    let comm_r = fr_from_commitment(&comm_r)?;
+    let comm_d = fr_from_commitment(&comm_d)?;
+    let comm_r_star = fr_from_commitment(&comm_r_star)?;
+
+    let verifier = verifier(sector_bytes)?;
+
+    for (k, proof) in proofs.iter().enumerate() {
+        let inputs = public_inputs(&verifier.graph, &replica_id, comm_d, comm_r, comm_r_star, k);
+
+        if !groth16::verify_proof(&verifier.pvk, proof, &inputs)? {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+// The public inputs of partition k's circuit: comm_d and comm_r, then for each
+// layer the replica id's first Fr::CAPACITY bits, packed, and, for each
+// challenge, the packed auth paths of the challenged node and of each of its
+// parents in the layer's replica and of the node in the layer's data, then
+// comm_r_star.
+fn public_inputs(
+    graph: &ZigZagGraph,
+    replica_id: &PedersenDomain,
+    comm_d: Fr,
+    comm_r: Fr,
+    comm_r_star: Fr,
+    k: usize,
+) -> Vec<Fr> {
+    let leaves = graph.size();
+    let replica_id_bits = bytes_into_bits(&replica_id.into_bytes());
+    let packed_replica_id =
+        multipack::compute_multipacking::<Bls12>(&replica_id_bits[..Fr::CAPACITY as usize]);
+
+    let mut inputs = vec![comm_d, comm_r];
+
+    for challenged in challenged_nodes(graph, replica_id, &comm_r_star.into(), k) {
+        inputs.extend(packed_replica_id.iter().cloned());
+
+        for (challenge, parents) in challenged {
+            inputs.extend(packed_auth_path(challenge, leaves));
+            for parent in parents {
+                inputs.extend(packed_auth_path(parent, leaves));
+            }
+            inputs.extend(packed_auth_path(challenge, leaves));
+        }
+    }
+
+    inputs.push(comm_r_star);
+    inputs
+}
+
+// Partition k's challenges at each layer, each with its parents in the layer's
+// graph.
+fn challenged_nodes(
+    graph: &ZigZagGraph,
+    replica_id: &PedersenDomain,
+    comm_r_star: &PedersenDomain,
+    k: usize,
+) -> Vec<Vec<(usize, Vec<usize>)>> {
+    let leaves = graph.size();
+    let mut graph = graph.clone();
+    let mut layers = Vec::with_capacity(LAYERS);
+
+    for layer in 0..LAYERS {
+        let challenges = derive_challenges(replica_id, comm_r_star, layer, leaves, k);
+        layers.push(
+            challenges
+                .into_iter()
+                .map(|challenge| (challenge, graph.parents(challenge)))
+                .collect(),
+        );
+
+        graph = graph.zigzag();
+    }
+
+    layers
+}
+
+// The number of challenges at a layer, tapering from CHALLENGE_COUNT at the
+// last layer by a factor of 1 - TAPER per layer, for TAPER_LAYERS layers.
+fn challenges_for_layer(layer: usize) -> usize {
+    let l = (LAYERS - 1) - layer;
+    let t = min(l, TAPER_LAYERS);
+    let total_taper = (1.0 - TAPER).powi(t as i32);
+
+    max(1, (total_taper * CHALLENGE_COUNT as f64).ceil() as usize)
+}
+
+// Partition k's challenges at a layer of the given leaves, none of them the
+// first or the last node: for the i-th of n, the blake2s hash of the replica
+// id, comm_r_star, the layer and n * k + i, read little-endian, modulo the
+// nodes challengeable.
+fn derive_challenges(
+    replica_id: &PedersenDomain,
+    comm_r_star: &PedersenDomain,
+    layer: usize,
+    leaves: usize,
+    k: usize,
+) -> Vec<usize> {
+    let n = challenges_for_layer(layer);
+    let challengeable = (leaves - 2) as u128;
+
+    (0..n)
+        .map(|i| {
+            let mut bytes = replica_id.into_bytes();
+            bytes.extend(comm_r_star.into_bytes());
+            bytes.push(layer as u8);
+            bytes.write_u32::<LittleEndian>((n * k + i) as u32).unwrap();
+
+            let rem = blake2s(&bytes)
+                .iter()
+                .rev()
+                .fold(0, |rem, byte| ((rem << 8) | *byte as u128) % challengeable);
+
+            rem as usize + 1
+        })
+        .collect()
+}
+
+// A node's index, its bits taken least significant first, one per level of a
+// tree of the given leaves.
+fn packed_auth_path(node: usize, leaves: usize) -> Vec<Fr> {
+    let bits: Vec<bool> = (0..graph_height(leaves))
+        .map(|i| (node >> i) & 1 == 1)
+        .collect();
+
+    multipack::compute_multipacking::<Bls12>(&bits)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::api::seal_proof::SealProofErr;
+    use pairing::PrimeFieldRepr;
+
+    #[derive(Deserialize)]
+    struct Vectors {
+        replica_id: String,
+        comm_d: String,
+        comm_r: String,
+        comm_r_star: String,
+        sectors: Vec<SectorVectors>,
+    }
+
+    #[derive(Deserialize)]
+    struct SectorVectors {
+        sector_bytes: usize,
+        parameter_set_identifier: String,
+        parameters_filename: String,
+        partitions: Vec<PartitionVectors>,
+    }
+
+    // A partition's challenges at each layer, and its circuit's public inputs,
+    // each a field element's 32 bytes, least significant first.
+    #[derive(Deserialize)]
+    struct PartitionVectors {
+        challenges: Vec<Vec<ChallengeVector>>,
+        public_inputs: Vec<String>,
+    }
+
+    #[derive(Deserialize)]
+    struct ChallengeVector {
+        node: usize,
+        parents: Vec<usize>,
+    }
+
+    fn vectors() -> Vectors {
+        serde_json::from_str(include_str!("../../../tests/golden/legacy_v0_vectors.json"))
+            .expect("malformed format 0 vectors")
+    }
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    fn domain(hex: &str) -> PedersenDomain {
+        PedersenDomain::try_from_bytes(&from_hex(hex)).unwrap()
+    }
+
+    fn fr_bytes(fr: Fr) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        fr.into_repr().write_le(&mut bytes).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn challenges_taper_toward_the_first_layer() {
+        let counts: Vec<usize> = (0..LAYERS).map(challenges_for_layer).collect();
+        assert_eq!(vec![1, 1, 2, 2], counts);
+    }
+
+    #[test]
+    fn parameters_are_looked_up_as_the_release_cached_them() {
+        for sector in vectors().sectors {
+            assert_eq!(
+                sector.parameter_set_identifier,
+                parameter_set_identifier(sector.sector_bytes)
+            );
+            assert_eq!(
+                sector.parameters_filename,
+                params_filename(sector.sector_bytes)
+            );
+        }
+    }
+
+    #[test]
+    fn challenges_and_public_inputs_are_the_release_s() {
+        let v = vectors();
+        let replica_id = domain(&v.replica_id);
+        let comm_r_star = domain(&v.comm_r_star);
+        let fr = |hex: &str| -> Fr { domain(hex).into() };
+
+        for sector in &v.sectors {
+            let graph = graph(sector.sector_bytes).unwrap();
+            assert_eq!(PARTITIONS, sector.partitions.len());
+
+            for (k, partition) in sector.partitions.iter().enumerate() {
+                let expected: Vec<Vec<(usize, Vec<usize>)>> = partition
+                    .challenges
+                    .iter()
+                    .map(|layer| layer.iter().map(|c| (c.node, c.parents.clone())).collect())
+                    .collect();
+                assert_eq!(
+                    expected,
+                    challenged_nodes(&graph, &replica_id, &comm_r_star, k),
+                    "sector of {} bytes, partition {}",
+                    sector.sector_bytes,
+                    k
+                );
+
+                let inputs: Vec<Vec<u8>> = public_inputs(
+                    &graph,
+                    &replica_id,
+                    fr(&v.comm_d),
+                    fr(&v.comm_r),
+                    fr(&v.comm_r_star),
+                    k,
+                )
+                .into_iter()
+                .map(fr_bytes)
+                .collect();
+                let expected: Vec<Vec<u8>> = partition
+                    .public_inputs
+                    .iter()
+                    .map(|i| from_hex(i))
+                    .collect();
+                assert_eq!(expected, inputs);
+            }
+        }
+    }
+
+    #[test]
+    fn only_v1_alpha_proofs_of_every_partition_are_verified() {
+        let verify = |version, proof: &[u8]| {
+            verify_seal(
+                1024, version, [1; 32], [2; 32], [3; 32], &[4; 31], &[5; 31], proof,
+            )
+            .map_err(|err| err.downcast::<SealProofErr>())
+        };
+
+        match verify(ProofVersion::Mini, &[6; 384]) {
+            Err(Ok(SealProofErr::VersionMismatch { .. })) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // A proof of the first partition alone is refused before any
+        // parameters are looked up.
+        match verify(ProofVersion::V1Alpha, &[6; 192]) {
+            Err(Ok(SealProofErr::Malformed { .. })) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
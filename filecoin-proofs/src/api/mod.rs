@@ -1,23 +1,99 @@
+use crate::api::cancellation::CancellationToken;
+use crate::api::estimate::HasherKind;
 use crate::api::internal::PoStOutput;
+use crate::api::io_priority::IoPriority;
+use crate::api::replica_format::LegacyReplicas;
 use crate::api::responses::err_code_and_msg;
 use crate::api::responses::FCPResponseStatus;
+use crate::api::responses::FFIAuditRecord;
+use crate::api::responses::FFIExpiringPiece;
+use crate::api::responses::FFIMigratedPiece;
+use crate::api::responses::FFIMigratedPieceStatus;
+use crate::api::responses::FFIPieceAssignment;
 use crate::api::responses::FFIPieceMetadata;
+use crate::api::responses::FFIPoSTFailure;
+use crate::api::responses::FFIPoSTFailureKind;
+use crate::api::responses::FFIProofRegeneration;
+use crate::api::responses::FFIProofRegenerationStatus;
+use crate::api::responses::FFISealJob;
 use crate::api::responses::FFISealStatus;
+use crate::api::responses::FFISelfTestStatus;
+use crate::api::responses::FFIUnsealedShard;
+use crate::api::responses::PartialResults;
+use crate::api::seal_proof::{seal_envelope, snark_proof};
+use crate::api::sector_builder::admission::{SealJob, SealScheduling};
+use crate::api::sector_builder::metadata::ExpiringPiece;
+use crate::api::sector_builder::metadata::MigratedPiece;
+use crate::api::sector_builder::metadata::MigratedPieceStatus;
+use crate::api::sector_builder::metadata::PieceAssignment;
+use crate::api::sector_builder::metadata::PieceMetadata;
+use crate::api::sector_builder::metadata::PlacementPlan;
+use crate::api::sector_builder::metadata::PlannedPiece;
+use crate::api::sector_builder::metadata::PlannedSector;
+use crate::api::sector_builder::metadata::ProofRegeneration;
+use crate::api::sector_builder::metadata::ProofRegenerationStatus;
 use crate::api::sector_builder::metadata::SealStatus;
-use crate::api::sector_builder::SectorBuilder;
+use crate::api::sector_builder::metadata::SealedSectorMetadata;
+use crate::api::sector_builder::metadata::StagedSectorMetadata;
+use crate::api::sector_builder::PieceKeyMaterial;
+use crate::api::prover_id::ProverId;
+use crate::api::sector_id::SectorId;
+use crate::encoding;
+use crate::error;
 use ffi_toolkit::rust_str_to_c_str;
-use ffi_toolkit::{c_str_to_rust_str, raw_ptr};
+use ffi_toolkit::{c_str_to_pbuf, c_str_to_rust_str, raw_ptr};
 use libc;
 use sector_base::api::disk_backed_storage::new_sector_config;
 use sector_base::api::disk_backed_storage::ConfiguredStore;
+use sector_base::api::registry::{self, SectorStoreHandle};
+use sector_base::api::sector_store::{ProofVariant, ProofVersion};
+use sector_base::io::staging_encryption::{StagingEncryption, StagingKey};
 use std::ffi::CString;
+use std::io::{self, Read};
 use std::mem;
+use std::path::PathBuf;
 use std::ptr;
 use std::slice::from_raw_parts;
-
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use storage_proofs::circuit::zigzag::PUBLIC_INPUTS_VERSION;
+use storage_proofs::parameter_cache::{self, ParameterPhase};
+use storage_proofs::types::{
+    commitment_from_fr, commitment_from_slice, fr_safe_from_slice, Commitment, FR32_BYTES,
+    FR_SAFE_BYTES,
+};
+use storage_proofs::vdf_post::FailureKind;
+use storage_proofs::yield_hook;
+
+mod abi;
+pub mod cancellation;
+pub mod estimate;
+#[doc(hidden)]
+pub mod fuzzing;
+pub mod handshake;
 pub mod internal;
+pub mod io_priority;
+pub mod legacy;
+pub mod parallelism;
+pub mod piece_layout;
+pub mod post_deadline;
+pub mod post_timing;
+pub mod prewarm;
+pub mod proof_params;
+pub mod prover_bundle;
+pub mod prover_id;
+pub mod replica_format;
 pub mod responses;
+pub mod screening;
+pub mod seal_proof;
 mod sector_builder;
+pub mod sector_id;
+pub mod self_test;
+pub mod staged_read;
+pub mod task;
+
+pub use crate::api::sector_builder::audit_log;
+pub use crate::api::sector_builder::metadata::DuplicatePiecePolicy;
+pub use crate::api::sector_builder::{SectorBuilder, SectorIdRange};
 
 /// Note: These values need to be kept in sync with what's in api/internal.rs.
 /// Due to limitations of cbindgen, we can't define a constant whose value is
@@ -26,6 +102,62 @@ mod sector_builder;
 pub const API_POREP_PROOF_BYTES: usize = 384;
 pub const API_POST_PROOF_BYTES: usize = 192;
 
+/// The version of the C API: of the types, enum values and function
+/// signatures in libfilecoin_proofs.h. Bumped whenever the header changes (see
+/// api/handshake.rs).
+pub const FILECOIN_PROOFS_ABI_VERSION: u32 = 9;
+
+// Whether sector builders created through the C API check each sector's staged
+// data against its pieces before sealing it.
+const VERIFY_STAGED_DATA: bool = true;
+
+/// Makes the handshake the C API requires before any of its other functions
+/// is called: checks that the caller was built against the library's version
+/// of the C API, and reports the library's version and the proof versions it
+/// supports, each with the largest sectors it seals with them.
+///
+/// If the caller's version isn't the library's, the status is
+/// FCPIncompatibleAbi and the caller must call nothing else, as it would
+/// misread the library's types. The rest of the response is filled in either
+/// way.
+///
+/// # Arguments
+///
+/// * `expected_abi_version` - the FILECOIN_PROOFS_ABI_VERSION of the header the
+///                            caller was built against
+#[no_mangle]
+pub extern "C" fn filecoin_proofs_init(expected_abi_version: u32) -> *mut responses::InitResponse {
+    let mut response: responses::InitResponse = Default::default();
+
+    let proof_versions: Vec<responses::FFIProofVersion> = proof_params::ALLOWED_VERSIONS
+        .iter()
+        .map(|version| responses::FFIProofVersion {
+            version_code: seal_proof::version_code(*version),
+            max_sector_bytes: handshake::max_sector_bytes(*version),
+        })
+        .collect();
+
+    response.abi_version = FILECOIN_PROOFS_ABI_VERSION;
+    response.library_version = rust_str_to_c_str(handshake::LIBRARY_VERSION);
+    response.proof_versions_len = proof_versions.len();
+    response.proof_versions_ptr = proof_versions.as_ptr();
+
+    mem::forget(proof_versions);
+
+    match handshake::handshake(expected_abi_version) {
+        Ok(()) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err.into());
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
 /// Verifies the output of seal.
 ///
 /// # Arguments
@@ -47,6 +179,8 @@ pub unsafe extern "C" fn verify_seal(
     sector_id: &[u8; 31],
     proof: &[u8; API_POREP_PROOF_BYTES],
 ) -> *mut responses::VerifySealResponse {
+    handshake::assert_initialized();
+
     let mut response: responses::VerifySealResponse = Default::default();
 
     if let Some(cfg) = cfg_ptr.as_ref() {
@@ -59,7 +193,7 @@ pub unsafe extern "C" fn verify_seal(
             *comm_r_star,
             prover_id,
             sector_id,
-            proof,
+            &seal_envelope(ProofVariant::Snark, proof),
         ) {
             Ok(true) => {
                 response.status_code = FCPResponseStatus::FCPNoError;
@@ -86,29 +220,192 @@ pub unsafe extern "C" fn verify_seal(
     raw_ptr(response)
 }
 
-/// Generates a proof-of-spacetime for the given replica commitments.
+/// Verifies the output of seal as verify_seal does, but first checks that
+/// comm_d is the commitment to the given pieces, in the order they were added
+/// to the sector. Pieces which weren't laid out so that comm_d can be derived
+/// from their comm_ps, or which comm_d isn't the commitment to, are refused
+/// with FCPCallerError.
+///
+/// # Arguments
+///
+/// * `cfg_ptr`               - pointer to ConfiguredStore
+/// * `comm_r`                - replica commitment
+/// * `comm_d`                - data commitment
+/// * `comm_r_star`           - layer-aggregated replica commitment
+/// * `prover_id`             - uniquely identifies the prover
+/// * `sector_id`             - uniquely identifies the sector
+/// * `proof`                 - the proof, generated by seal()
+/// * `flattened_comm_ps_ptr` - the pieces' commitments, 32 bytes each
+/// * `piece_lens_ptr`        - lengths of the pieces, in bytes
+/// * `pieces_len`            - number of pieces, which may be 0
+#[no_mangle]
+pub unsafe extern "C" fn verify_seal_with_pieces(
+    cfg_ptr: *const ConfiguredStore,
+    comm_r: &[u8; 32],
+    comm_d: &[u8; 32],
+    comm_r_star: &[u8; 32],
+    prover_id: &[u8; 31],
+    sector_id: &[u8; 31],
+    proof: &[u8; API_POREP_PROOF_BYTES],
+    flattened_comm_ps_ptr: *const u8,
+    piece_lens_ptr: *const u64,
+    pieces_len: libc::size_t,
+) -> *mut responses::VerifySealResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::VerifySealResponse = Default::default();
+
+    if let Some(cfg) = cfg_ptr.as_ref() {
+        let cfg = new_sector_config(cfg);
+
+        // Callers with no pieces may pass null pointers.
+        let pieces: Vec<(Commitment, u64)> = if pieces_len == 0 {
+            Vec::new()
+        } else {
+            from_raw_parts(flattened_comm_ps_ptr, pieces_len * FR32_BYTES)
+                .chunks(FR32_BYTES)
+                .map(commitment_from_slice)
+                .zip(from_raw_parts(piece_lens_ptr, pieces_len).iter().cloned())
+                .collect()
+        };
+
+        match internal::verify_seal_with_pieces(
+            &(*cfg),
+            *comm_r,
+            *comm_d,
+            *comm_r_star,
+            prover_id,
+            sector_id,
+            &seal_envelope(ProofVariant::Snark, proof),
+            &pieces,
+        ) {
+            Ok(is_valid) => {
+                response.status_code = FCPResponseStatus::FCPNoError;
+                response.is_valid = is_valid;
+            }
+            Err(err) => {
+                let (code, ptr) = err_code_and_msg(&err);
+                response.status_code = code;
+                response.error_msg = ptr;
+            }
+        }
+    } else {
+        response.status_code = FCPResponseStatus::FCPCallerError;
+
+        let msg = CString::new("caller did not provide ConfiguredStore").unwrap();
+        response.error_msg = msg.as_ptr();
+        mem::forget(msg);
+    }
+
+    raw_ptr(response)
+}
+
+/// Verifies the outputs of many seals. Each item is verified independently:
+/// an item which can't be verified is reported through its item status code
+/// and error message, and doesn't affect the other items.
+///
+/// # Arguments
+///
+/// * `cfg_ptr`                   - pointer to ConfiguredStore
+/// * `num_items`                 - number of seal outputs to verify
+/// * `flattened_comm_rs_ptr`     - `num_items` replica commitments, 32 bytes each
+/// * `flattened_comm_ds_ptr`     - `num_items` data commitments, 32 bytes each
+/// * `flattened_comm_r_stars_ptr`- `num_items` layer-aggregated replica commitments, 32 bytes each
+/// * `prover_id`                 - uniquely identifies the prover
+/// * `flattened_sector_ids_ptr`  - `num_items` sector ids, 31 bytes each
+/// * `flattened_proofs_ptr`      - `num_items` proofs, API_POREP_PROOF_BYTES each
+#[no_mangle]
+pub unsafe extern "C" fn verify_seals_batch(
+    cfg_ptr: *const ConfiguredStore,
+    num_items: libc::size_t,
+    flattened_comm_rs_ptr: *const u8,
+    flattened_comm_ds_ptr: *const u8,
+    flattened_comm_r_stars_ptr: *const u8,
+    prover_id: &[u8; 31],
+    flattened_sector_ids_ptr: *const u8,
+    flattened_proofs_ptr: *const u8,
+) -> *mut responses::VerifySealsBatchResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::VerifySealsBatchResponse = Default::default();
+
+    if let Some(cfg) = cfg_ptr.as_ref() {
+        let cfg = new_sector_config(cfg);
+
+        let comm_rs = from_raw_parts(flattened_comm_rs_ptr, num_items * FR32_BYTES);
+        let comm_ds = from_raw_parts(flattened_comm_ds_ptr, num_items * FR32_BYTES);
+        let comm_r_stars = from_raw_parts(flattened_comm_r_stars_ptr, num_items * FR32_BYTES);
+        let sector_ids = from_raw_parts(flattened_sector_ids_ptr, num_items * FR_SAFE_BYTES);
+        let proofs = from_raw_parts(flattened_proofs_ptr, num_items * API_POREP_PROOF_BYTES);
+
+        let results = PartialResults::collect((0..num_items).map(|i| {
+            internal::verify_seal(
+                &(*cfg),
+                commitment_from_slice(&comm_rs[i * FR32_BYTES..]),
+                commitment_from_slice(&comm_ds[i * FR32_BYTES..]),
+                commitment_from_slice(&comm_r_stars[i * FR32_BYTES..]),
+                prover_id,
+                &fr_safe_from_slice(&sector_ids[i * FR_SAFE_BYTES..]),
+                &seal_envelope(
+                    ProofVariant::Snark,
+                    &proofs[i * API_POREP_PROOF_BYTES..(i + 1) * API_POREP_PROOF_BYTES],
+                ),
+            )
+        }));
+
+        response.status_code = FCPResponseStatus::FCPNoError;
+        response.items_len = results.len();
+
+        let (status_codes, error_msgs, is_valid) = results.into_raw_parts();
+        response.item_status_codes_ptr = status_codes;
+        response.item_error_msgs_ptr = error_msgs;
+        response.is_valid_ptr = is_valid;
+    } else {
+        response.status_code = FCPResponseStatus::FCPCallerError;
+
+        let msg = CString::new("caller did not provide ConfiguredStore").unwrap();
+        response.error_msg = msg.as_ptr();
+        mem::forget(msg);
+    }
+
+    raw_ptr(response)
+}
+
+/// Generates a proof-of-spacetime for the given replica commitments. The
+/// replicas of the sealed sectors with the excluded ids (e.g. those returned
+/// by get_expired_sectors) are left out of the proof, and aren't reported as
+/// faults.
+///
+/// # Arguments
 ///
+/// * `excluded_sector_ids_ptr` - ids of the sealed sectors to leave out
+/// * `excluded_sector_ids_len` - number of ids, which may be 0
 #[no_mangle]
 pub unsafe extern "C" fn generate_post(
     ptr: *mut SectorBuilder,
     flattened_comm_rs_ptr: *const u8,
     flattened_comm_rs_len: libc::size_t,
     challenge_seed: &[u8; 32],
+    excluded_sector_ids_ptr: *const u64,
+    excluded_sector_ids_len: libc::size_t,
 ) -> *mut responses::GeneratePoSTResponse {
-    let comm_rs = from_raw_parts(flattened_comm_rs_ptr, flattened_comm_rs_len)
-        .iter()
-        .step_by(32)
-        .fold(Default::default(), |mut acc: Vec<[u8; 32]>, item| {
-            let sliced = from_raw_parts(item, 32);
-            let mut x: [u8; 32] = Default::default();
-            x.copy_from_slice(&sliced[..32]);
-            acc.push(x);
-            acc
-        });
+    handshake::assert_initialized();
+
+    let comm_rs: Vec<Commitment> = from_raw_parts(flattened_comm_rs_ptr, flattened_comm_rs_len)
+        .chunks(FR32_BYTES)
+        .map(commitment_from_slice)
+        .collect();
+
+    // Callers with nothing to exclude may pass a null pointer.
+    let excluded_sector_ids: &[u64] = if excluded_sector_ids_len == 0 {
+        &[]
+    } else {
+        from_raw_parts(excluded_sector_ids_ptr, excluded_sector_ids_len)
+    };
 
     let mut response: responses::GeneratePoSTResponse = Default::default();
 
-    match (*ptr).generate_post(&comm_rs, challenge_seed) {
+    match (*ptr).generate_post(&comm_rs, challenge_seed, excluded_sector_ids) {
         Ok(PoStOutput {
             snark_proof,
             faults,
@@ -144,6 +441,8 @@ pub unsafe extern "C" fn verify_post(
     _faults_len: libc::size_t,
     _sector_bytes: u64,
 ) -> *mut responses::VerifyPoSTResponse {
+    handshake::assert_initialized();
+
     let mut response: responses::VerifyPoSTResponse = Default::default();
 
     if proof[0] == 42 {
@@ -200,77 +499,127 @@ pub unsafe extern "C" fn verify_post(
     // Box::into_raw(Box::new(response))
 }
 
-/// Initializes and returns a SectorBuilder.
+/// Verifies a vanilla proof-of-spacetime, CBOR-encoded as
+/// internal::generate_vanilla_post encodes it, reporting every check which
+/// fails rather than only whether any did: for each, the sector it checked
+/// (its index among the comm_rs, and its comm_r), the challenge (its index
+/// among all those of the proof) and what's wrong. It's for debugging proofs
+/// off-chain, and is slower than verify_post.
+///
+/// # Arguments
 ///
+/// * `proof_ptr`    - the CBOR-encoded vanilla proof
+/// * `proof_len`    - length of the proof, in bytes
+/// * `faults_ptr`   - indexes, among the comm_rs, of the sectors declared faulty
+/// * `faults_len`   - number of faults, which may be 0
+/// * `sector_bytes` - size of the sealed sectors, in bytes
 #[no_mangle]
-pub unsafe extern "C" fn init_sector_builder(
-    sector_store_config_ptr: *const ConfiguredStore,
-    last_used_sector_id: u64,
-    metadata_dir: *const libc::c_char,
-    prover_id: &[u8; 31],
-    sealed_sector_dir: *const libc::c_char,
-    staged_sector_dir: *const libc::c_char,
-    max_num_staged_sectors: u8,
-) -> *mut responses::InitSectorBuilderResponse {
-    let mut response: responses::InitSectorBuilderResponse = Default::default();
-
-    if let Some(cfg) = sector_store_config_ptr.as_ref() {
-        match SectorBuilder::init_from_metadata(
-            cfg,
-            last_used_sector_id,
-            c_str_to_rust_str(metadata_dir).to_string(),
-            *prover_id,
-            c_str_to_rust_str(sealed_sector_dir).to_string(),
-            c_str_to_rust_str(staged_sector_dir).to_string(),
-            max_num_staged_sectors,
-        ) {
-            Ok(sb) => {
-                response.status_code = FCPResponseStatus::FCPNoError;
-                response.sector_builder = raw_ptr(sb);
-            }
-            Err(err) => {
-                let (code, ptr) = err_code_and_msg(&err);
-                response.status_code = code;
-                response.error_msg = ptr;
-            }
-        }
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn verify_post_detailed(
+    flattened_comm_rs_ptr: *const u8,
+    flattened_comm_rs_len: libc::size_t,
+    challenge_seed: &[u8; 32],
+    proof_ptr: *const u8,
+    proof_len: libc::size_t,
+    faults_ptr: *const u64,
+    faults_len: libc::size_t,
+    sector_bytes: u64,
+) -> *mut responses::VerifyPoSTDetailedResponse {
+    handshake::assert_initialized();
+
+    let comm_rs: Vec<Commitment> = from_raw_parts(flattened_comm_rs_ptr, flattened_comm_rs_len)
+        .chunks(FR32_BYTES)
+        .map(commitment_from_slice)
+        .collect();
+
+    // Callers with no faults may pass a null pointer.
+    let faults: Vec<u64> = if faults_len == 0 {
+        Vec::new()
     } else {
-        response.status_code = FCPResponseStatus::FCPCallerError;
+        from_raw_parts(faults_ptr, faults_len).to_vec()
+    };
 
-        let msg = CString::new("caller did not provide ConfiguredStore").unwrap();
-        response.error_msg = msg.as_ptr();
-        mem::forget(msg);
+    let mut response: responses::VerifyPoSTDetailedResponse = Default::default();
+
+    match internal::verify_post_detailed(
+        sector_bytes,
+        &comm_rs,
+        challenge_seed,
+        from_raw_parts(proof_ptr, proof_len),
+        faults,
+    ) {
+        Ok(report) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.is_valid = report.is_valid();
+
+            let failures: Vec<FFIPoSTFailure> = report
+                .failures
+                .into_iter()
+                .map(|failure| FFIPoSTFailure {
+                    sector_index: failure.sector_index as u64,
+                    comm_r: failure.comm_r,
+                    challenge_index: failure.challenge_index.unwrap_or(0) as u64,
+                    kind: match failure.kind {
+                        FailureKind::PathMismatch => FFIPoSTFailureKind::PathMismatch,
+                        FailureKind::LeafMismatch => FFIPoSTFailureKind::LeafMismatch,
+                        FailureKind::MissingProof => FFIPoSTFailureKind::MissingProof,
+                        FailureKind::FaultSetInconsistency => {
+                            FFIPoSTFailureKind::FaultSetInconsistency
+                        }
+                    },
+                })
+                .collect();
+
+            response.failures_len = failures.len();
+            response.failures_ptr = failures.as_ptr();
+
+            // we'll free this stuff when we free the VerifyPoSTDetailedResponse
+            mem::forget(failures);
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
     }
 
     raw_ptr(response)
 }
 
-/// Destroys a SectorBuilder.
+/// Estimates how long sealing a sector will take on this machine, and how
+/// much memory it will need, by sealing a small instance with the same
+/// parameters and extrapolating. This runs a real (small) seal, so it takes a
+/// while.
 ///
-#[no_mangle]
-pub unsafe extern "C" fn destroy_sector_builder(ptr: *mut SectorBuilder) {
-    let _ = Box::from_raw(ptr);
-}
-
-/// Writes user piece-bytes to a staged sector and returns the id of the sector
-/// to which the bytes were written.
+/// # Arguments
 ///
+/// * `sector_bytes`     - size of the sealed sector, in bytes
+/// * `layers`           - number of zigzag layers
+/// * `degree`           - degree of the base DRG
+/// * `expansion_degree` - expansion degree of the zigzag graph, 0 for none
+/// * `hasher`           - hash function used for encoding and merkle trees
 #[no_mangle]
-pub unsafe extern "C" fn add_piece(
-    ptr: *mut SectorBuilder,
-    piece_key: *const libc::c_char,
-    piece_ptr: *const u8,
-    piece_len: libc::size_t,
-) -> *mut responses::AddPieceResponse {
-    let piece_key = c_str_to_rust_str(piece_key);
-    let piece_bytes = from_raw_parts(piece_ptr, piece_len);
-
-    let mut response: responses::AddPieceResponse = Default::default();
-
-    match (*ptr).add_piece(String::from(piece_key), piece_bytes) {
-        Ok(sector_id) => {
+pub extern "C" fn estimate_seal_resources(
+    sector_bytes: u64,
+    layers: libc::size_t,
+    degree: libc::size_t,
+    expansion_degree: libc::size_t,
+    hasher: HasherKind,
+) -> *mut responses::EstimateSealResourcesResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::EstimateSealResourcesResponse = Default::default();
+
+    let estimate =
+        estimate::estimate_seal_resources(sector_bytes, layers, degree, expansion_degree, hasher);
+
+    match estimate {
+        Ok(estimate) => {
             response.status_code = FCPResponseStatus::FCPNoError;
-            response.sector_id = sector_id;
+            response.encoding_millis = duration_millis(estimate.encoding);
+            response.tree_building_millis = duration_millis(estimate.tree_building);
+            response.proving_millis = duration_millis(estimate.proving);
+            response.peak_memory_bytes = estimate.peak_memory_bytes;
         }
         Err(err) => {
             let (code, ptr) = err_code_and_msg(&err);
@@ -282,23 +631,79 @@ pub unsafe extern "C" fn add_piece(
     raw_ptr(response)
 }
 
-/// Unseals and returns the bytes associated with the provided piece key.
+/// Returns the number of threads the process's pools are sized by: the
+/// parallelism_override given to init_sector_builder, if any, or else the
+/// fewest CPUs the machine, the cgroup's CPU quota and the cgroup's cpuset
+/// allow (on Linux; elsewhere, the CPUs available). What was detected is
+/// returned with it, 0 standing for no quota, or no cpuset.
+#[no_mangle]
+pub extern "C" fn get_effective_parallelism() -> *mut responses::GetEffectiveParallelismResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::GetEffectiveParallelismResponse = Default::default();
+
+    let detected = parallelism::detected_parallelism();
+
+    response.status_code = FCPResponseStatus::FCPNoError;
+    response.effective = parallelism::get_effective_parallelism() as u64;
+    response.available = detected.available as u64;
+    response.quota_cpus = detected.quota.unwrap_or(0) as u64;
+    response.cpuset_cpus = detected.cpuset.unwrap_or(0) as u64;
+
+    raw_ptr(response)
+}
+
+/// Called as groth parameters are generated, with the name of the current
+/// phase, the overall fraction of the work done and the caller's user data.
+pub type ParameterProgressCallback =
+    extern "C" fn(phase: *const libc::c_char, progress: f64, user_data: *mut libc::c_void);
+
+/// Generates and caches the groth parameters used to seal sectors of the given
+/// size, unless they are already cached. Otherwise the first seal generates
+/// them, which can take tens of minutes without any feedback.
+///
+/// If `callback` is non-null, it is called on the calling thread as generation
+/// progresses, with the name of the current phase ("generating", "writing" or
+/// "complete"), the overall fraction of the work done (from 0 to 1, never
+/// decreasing) and `user_data`. On success, the last call is the only one
+/// with the "complete" phase. The phase name is only valid for the duration of
+/// the call.
+///
+/// Given a timeout, generation gives up once it has passed, with the
+/// FCPTimeout status, before the parameters are generated, once they are or
+/// between the MiB they're written to the cache in. The single step which
+/// generates them is never interrupted. Nothing is left in the cache, so that
+/// generation may be tried again at once.
 ///
+/// # Arguments
+///
+/// * `sector_bytes`    - size of the sealed sector, in bytes
+/// * `callback`        - progress callback, or null
+/// * `user_data`       - passed to each call of the callback, never dereferenced
+/// * `timeout_seconds` - seconds after which generation gives up, or 0 for none
 #[no_mangle]
-pub unsafe extern "C" fn read_piece_from_sealed_sector(
-    ptr: *mut SectorBuilder,
-    piece_key: *const libc::c_char,
-) -> *mut responses::ReadPieceFromSealedSectorResponse {
-    let mut response: responses::ReadPieceFromSealedSectorResponse = Default::default();
+pub extern "C" fn generate_parameters_with_progress(
+    sector_bytes: u64,
+    callback: Option<ParameterProgressCallback>,
+    user_data: *mut libc::c_void,
+    timeout_seconds: u64,
+) -> *mut responses::GenerateParametersResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::GenerateParametersResponse = Default::default();
+
+    let mut progress = |phase: ParameterPhase, fraction: f64| {
+        if let Some(callback) = callback {
+            let name = CString::new(phase.name()).unwrap();
+            callback(name.as_ptr(), fraction, user_data);
+        }
+    };
 
-    let piece_key = c_str_to_rust_str(piece_key);
+    let token = CancellationToken::with_timeout_seconds(timeout_seconds);
 
-    match (*ptr).read_piece_from_sealed_sector(String::from(piece_key)) {
-        Ok(piece_bytes) => {
+    match internal::generate_zigzag_params_with_cancellation(sector_bytes, &mut progress, &token) {
+        Ok(()) => {
             response.status_code = FCPResponseStatus::FCPNoError;
-            response.data_ptr = piece_bytes.as_ptr();
-            response.data_len = piece_bytes.len();
-            mem::forget(piece_bytes);
         }
         Err(err) => {
             let (code, ptr) = err_code_and_msg(&err);
@@ -310,20 +715,41 @@ pub unsafe extern "C" fn read_piece_from_sealed_sector(
     raw_ptr(response)
 }
 
-/// For demo purposes. Seals all staged sectors.
+/// Installs the parameter file at `source_path`, fetched out of band, in the
+/// parameter cache as `identifier`, if its digest is `expected_digest`. The
+/// file is checked as it's copied, and replaces whatever the cache held under
+/// that name atomically, only once it has been. Seals and verifications which
+/// use the file check it against the digest as they read it, and fail rather
+/// than use it if it has changed since.
 ///
+/// # Arguments
+///
+/// * `source_path`     - path to the parameter file to install
+/// * `identifier`      - name of the file in the parameter cache, as in
+///                       parameters.json
+/// * `expected_digest` - the file's digest, as in parameters.json
 #[no_mangle]
-pub unsafe extern "C" fn seal_all_staged_sectors(
-    ptr: *mut SectorBuilder,
-) -> *mut responses::SealAllStagedSectorsResponse {
-    let mut response: responses::SealAllStagedSectorsResponse = Default::default();
-
-    match (*ptr).seal_all_staged_sectors() {
-        Ok(_) => {
+pub unsafe extern "C" fn install_parameters(
+    source_path: *const libc::c_char,
+    identifier: *const libc::c_char,
+    expected_digest: *const libc::c_char,
+) -> *mut responses::InstallParametersResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::InstallParametersResponse = Default::default();
+
+    let result = parameter_cache::install_parameters(
+        &c_str_to_pbuf(source_path),
+        &c_str_to_rust_str(identifier),
+        &c_str_to_rust_str(expected_digest),
+    );
+
+    match result {
+        Ok(()) => {
             response.status_code = FCPResponseStatus::FCPNoError;
         }
         Err(err) => {
-            let (code, ptr) = err_code_and_msg(&err);
+            let (code, ptr) = err_code_and_msg(&err.into());
             response.status_code = code;
             response.error_msg = ptr;
         }
@@ -332,44 +758,1448 @@ pub unsafe extern "C" fn seal_all_staged_sectors(
     raw_ptr(response)
 }
 
-/// Returns the number of user bytes that will fit into a staged sector.
-///
+/// Lists the parameter files in the parameter cache, ordered by identifier,
+/// with their sizes, and the digests of those installed with
+/// install_parameters. The digest of any other file is null.
 #[no_mangle]
-pub unsafe extern "C" fn get_max_user_bytes_per_staged_sector(
-    ptr: *mut SectorBuilder,
-) -> *mut responses::GetMaxStagedBytesPerSector {
-    let mut response: responses::GetMaxStagedBytesPerSector = Default::default();
+pub extern "C" fn list_installed_parameters() -> *mut responses::ListInstalledParametersResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::ListInstalledParametersResponse = Default::default();
+
+    match parameter_cache::list_installed_parameters() {
+        Ok(infos) => {
+            let parameters: Vec<responses::FFIParameterInfo> = infos
+                .into_iter()
+                .map(|info| responses::FFIParameterInfo {
+                    identifier: rust_str_to_c_str(info.identifier),
+                    size: info.size,
+                    digest: info.digest.map_or(ptr::null(), |digest| rust_str_to_c_str(digest)),
+                })
+                .collect();
 
-    response.status_code = FCPResponseStatus::FCPNoError;
-    response.max_staged_bytes_per_sector = (*ptr).get_max_user_bytes_per_staged_sector();;
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.parameters_len = parameters.len();
+            response.parameters_ptr = parameters.as_ptr();
+
+            mem::forget(parameters);
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err.into());
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
 
     raw_ptr(response)
 }
 
-/// Returns sector sealing status for the provided sector id if it exists. If
-/// we don't know about the provided sector id, produce an error.
+/// Removes the parameter file named `identifier` from the parameter cache,
+/// and the digest it was installed with, if any.
+///
+/// # Arguments
 ///
+/// * `identifier` - name of the file in the parameter cache
 #[no_mangle]
-pub unsafe extern "C" fn get_seal_status(
-    ptr: *mut SectorBuilder,
-    sector_id: u64,
-) -> *mut responses::GetSealStatusResponse {
-    let mut response: responses::GetSealStatusResponse = Default::default();
+pub unsafe extern "C" fn remove_parameters(
+    identifier: *const libc::c_char,
+) -> *mut responses::RemoveParametersResponse {
+    handshake::assert_initialized();
 
-    match (*ptr).get_seal_status(sector_id) {
-        Ok(seal_status) => {
+    let mut response: responses::RemoveParametersResponse = Default::default();
+
+    match parameter_cache::remove_parameters(&c_str_to_rust_str(identifier)) {
+        Ok(()) => {
             response.status_code = FCPResponseStatus::FCPNoError;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err.into());
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
 
-            match seal_status {
-                SealStatus::Sealed(meta) => {
-                    let meta = *meta;
+    raw_ptr(response)
+}
 
-                    response.seal_status_code = FFISealStatus::Sealed;
-                    response.comm_d = meta.comm_d;
-                    response.comm_r = meta.comm_r;
-                    response.comm_r_star = meta.comm_r_star;
-                    response.snark_proof = meta.snark_proof;
-                    response.sector_id = meta.sector_id;
+/// Unseals num_bytes bytes of a sealed sector's data, starting at offset, into
+/// files of shard_size bytes in output_dir, named shard-0, shard-1 and so on,
+/// and returns the path and length of each file in order. Every shard but the
+/// last is full, and an empty range makes none. Sectors sealed before format
+/// trailers were introduced are refused.
+///
+/// Given a timeout, unsealing gives up once it has passed, at its next
+/// checkpoint, with the FCPTimeout status and a message naming the step it was
+/// in, and the shards written so far are removed. A read which hangs holds it
+/// until it returns, but the sealed sector is read in chunks of a MiB, with a
+/// checkpoint between each.
+///
+/// # Arguments
+///
+/// * `cfg_ptr`     - pointer to ConfiguredStore
+/// * `sealed_path` - path to the sealed sector
+/// * `output_dir`  - directory in which the shards are written
+/// * `shard_size`  - number of bytes in each shard but the last, non-zero
+/// * `prover_id`   - uniquely identifies the prover
+/// * `sector_id`   - uniquely identifies the sector
+/// * `offset`      - offset of the range in the sector's unsealed data
+/// * `num_bytes`   - number of bytes in the range
+/// * `timeout_seconds` - seconds after which unsealing gives up, or 0 for none
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn get_unsealed_range_sharded(
+    cfg_ptr: *const ConfiguredStore,
+    sealed_path: *const libc::c_char,
+    output_dir: *const libc::c_char,
+    shard_size: u64,
+    prover_id: &[u8; 31],
+    sector_id: &[u8; 31],
+    offset: u64,
+    num_bytes: u64,
+    timeout_seconds: u64,
+) -> *mut responses::GetUnsealedRangeShardedResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::GetUnsealedRangeShardedResponse = Default::default();
+
+    if let Some(cfg) = cfg_ptr.as_ref() {
+        let cfg = new_sector_config(cfg);
+        let sealed_path = c_str_to_pbuf(sealed_path);
+        let output_dir = c_str_to_pbuf(output_dir);
+
+        let shards = internal::get_unsealed_range_sharded(
+            &(*cfg),
+            sealed_path,
+            output_dir,
+            shard_size,
+            prover_id,
+            sector_id,
+            offset,
+            num_bytes,
+            LegacyReplicas::Refuse,
+            &CancellationToken::with_timeout_seconds(timeout_seconds),
+        );
+
+        match shards.and_then(|shards| into_ffi_shards(&shards)) {
+            Ok(shards) => {
+                response.status_code = FCPResponseStatus::FCPNoError;
+                response.shards_len = shards.len();
+                response.shards_ptr = Box::into_raw(shards) as *const FFIUnsealedShard;
+            }
+            Err(err) => {
+                let (code, ptr) = err_code_and_msg(&err);
+                response.status_code = code;
+                response.error_msg = ptr;
+            }
+        }
+    } else {
+        response.status_code = FCPResponseStatus::FCPCallerError;
+
+        let msg = CString::new("caller did not provide ConfiguredStore").unwrap();
+        response.error_msg = msg.as_ptr();
+        mem::forget(msg);
+    }
+
+    raw_ptr(response)
+}
+
+/// Prepares everything sealing a first sector in a store takes besides its
+/// data: creates the store's directories, reads or generates the groth
+/// parameters for its sector size and builds the zigzag graph seals share.
+/// Reports how long each step took, and whether it found its work done
+/// already. Safe to call concurrently, and again.
+///
+/// Given a timeout, prefetching gives up once it has passed, with the
+/// FCPTimeout status, between its steps or as parameters are generated (see
+/// generate_parameters_with_progress). The steps it finished stay done.
+///
+/// # Arguments
+///
+/// * `handle`          - handle returned by init_new_sector_store or
+///                       init_new_test_sector_store
+/// * `timeout_seconds` - seconds after which prefetching gives up, or 0 for none
+#[no_mangle]
+pub extern "C" fn prefetch_seal_requirements(
+    handle: SectorStoreHandle,
+    timeout_seconds: u64,
+) -> *mut responses::PrefetchSealRequirementsResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::PrefetchSealRequirementsResponse = Default::default();
+
+    let token = CancellationToken::with_timeout_seconds(timeout_seconds);
+    let report = registry::get(handle)
+        .map_err(|err| err.into())
+        .and_then(|store| {
+            prewarm::prefetch_seal_requirements_with_cancellation(store.as_ref(), &token)
+        });
+
+    match report {
+        Ok(report) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.directories_millis = duration_millis(report.directories.elapsed);
+            response.parameters_millis = duration_millis(report.parameters.elapsed);
+            response.graph_millis = duration_millis(report.graph.elapsed);
+            response.directories_cache_hit = report.directories.cache_hit;
+            response.parameters_cache_hit = report.parameters.cache_hit;
+            response.graph_cache_hit = report.graph.cache_hit;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Checks the installation before it's put to use: seals, unseals and proves a
+/// test sector in a store created (and removed again) in `scratch_dir`, then
+/// loads the parameters of the configured store's sector size from the
+/// parameter cache, without generating them if they're missing. Reports each
+/// step's outcome and duration, named as filecoin_proofs::api::self_test names
+/// them. A failed step is reported as the step's, not as the call's.
+///
+/// # Arguments
+///
+/// * `cfg_ptr`       - the store whose sector size's parameters are checked
+/// * `scratch_dir`   - existing directory the test store is created in
+/// * `parameter_map` - parameter map the parameters' digests are checked
+///                     against, or null to only load them
+#[no_mangle]
+pub unsafe extern "C" fn run_self_test(
+    cfg_ptr: *const ConfiguredStore,
+    scratch_dir: *const libc::c_char,
+    parameter_map: *const libc::c_char,
+) -> *mut responses::RunSelfTestResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::RunSelfTestResponse = Default::default();
+
+    if let Some(cfg) = cfg_ptr.as_ref() {
+        let config = self_test::SelfTestConfig {
+            scratch_dir: c_str_to_pbuf(scratch_dir),
+            sector_bytes: new_sector_config(cfg).sector_bytes(),
+            parameter_cache_dir: None,
+            parameter_map: if parameter_map.is_null() {
+                None
+            } else {
+                Some(c_str_to_pbuf(parameter_map))
+            },
+        };
+
+        let report = self_test::run_self_test(&config);
+
+        let steps: Vec<responses::FFISelfTestStep> = report
+            .steps
+            .iter()
+            .map(|step| {
+                let (status, error_msg) = match step.status {
+                    self_test::StepStatus::Passed => (FFISelfTestStatus::Passed, None),
+                    self_test::StepStatus::Failed(ref err) => {
+                        (FFISelfTestStatus::Failed, Some(err.clone()))
+                    }
+                    self_test::StepStatus::Skipped => (FFISelfTestStatus::Skipped, None),
+                    self_test::StepStatus::ParametersMissing(ref paths) => {
+                        let paths: Vec<_> = paths.iter().map(|p| p.to_string_lossy()).collect();
+                        (FFISelfTestStatus::ParametersMissing, Some(paths.join(", ")))
+                    }
+                };
+
+                responses::FFISelfTestStep {
+                    step: rust_str_to_c_str(format!("{:?}", step.step)),
+                    status,
+                    millis: duration_millis(step.elapsed),
+                    error_msg: error_msg.map_or(ptr::null(), |msg| rust_str_to_c_str(msg)),
+                }
+            })
+            .collect();
+
+        response.status_code = FCPResponseStatus::FCPNoError;
+        response.passed = report.passed();
+        response.steps_len = steps.len();
+        response.steps_ptr = steps.as_ptr();
+
+        mem::forget(steps);
+    } else {
+        response.status_code = FCPResponseStatus::FCPCallerError;
+
+        let msg = CString::new("caller did not provide ConfiguredStore").unwrap();
+        response.error_msg = msg.as_ptr();
+        mem::forget(msg);
+    }
+
+    raw_ptr(response)
+}
+
+/// Proves the seal whose prover bundle was written into `bundle_dir` by a seal
+/// which replicated its sector, whether or not that seal went on to prove it.
+/// The bundle may have been moved since. Bundles of seals proven with vanilla
+/// proofs are refused, as the response only holds a SNARK proof.
+///
+/// # Arguments
+///
+/// * `bundle_dir` - directory holding the prover bundle
+#[no_mangle]
+pub unsafe extern "C" fn prove_from_bundle(
+    bundle_dir: *const libc::c_char,
+) -> *mut responses::ProveFromBundleResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::ProveFromBundleResponse = Default::default();
+
+    let bundle_dir = c_str_to_pbuf(bundle_dir);
+
+    let output = internal::prove_from_bundle(bundle_dir)
+        .and_then(|output| Ok((snark_proof(&output.proof)?, output)));
+
+    match output {
+        Ok((snark_proof, output)) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.comm_d = output.comm_d;
+            response.comm_r = output.comm_r;
+            response.comm_r_star = output.comm_r_star;
+            response.snark_proof = snark_proof;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Returns the public inputs a seal's proof is verified against, so that
+/// external verifiers can check the inputs they derive against these. There's
+/// a vector of inputs for each of the proof's `partitions`, one after the
+/// other, all of the same length, laid out as `layout_version` of
+/// ZigZagCompound::public_input_vector documents. Each input is encoded as a
+/// commitment is: 32 bytes, least significant first.
+///
+/// # Arguments
+///
+/// * `cfg_ptr`     - pointer to ConfiguredStore
+/// * `comm_r`      - replica commitment
+/// * `comm_d`      - data commitment
+/// * `comm_r_star` - layer-aggregated replica commitment
+/// * `prover_id`   - uniquely identifies the prover
+/// * `sector_id`   - uniquely identifies the sector
+#[no_mangle]
+pub unsafe extern "C" fn get_public_inputs_for_seal(
+    cfg_ptr: *const ConfiguredStore,
+    comm_r: &[u8; 32],
+    comm_d: &[u8; 32],
+    comm_r_star: &[u8; 32],
+    prover_id: &[u8; 31],
+    sector_id: &[u8; 31],
+) -> *mut responses::GetPublicInputsForSealResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::GetPublicInputsForSealResponse = Default::default();
+
+    if let Some(cfg) = cfg_ptr.as_ref() {
+        let cfg = new_sector_config(cfg);
+
+        match internal::seal_public_input_vectors(
+            &(*cfg),
+            *comm_r,
+            *comm_d,
+            *comm_r_star,
+            prover_id,
+            sector_id,
+        ) {
+            Ok(vectors) => {
+                let flattened: Box<[u8]> = vectors
+                    .iter()
+                    .flat_map(|inputs| inputs.iter())
+                    .flat_map(|input| commitment_from_fr(*input).to_vec())
+                    .collect::<Vec<_>>()
+                    .into_boxed_slice();
+
+                response.status_code = FCPResponseStatus::FCPNoError;
+                response.layout_version = PUBLIC_INPUTS_VERSION;
+                response.partitions = vectors.len();
+                response.flattened_inputs_len = flattened.len();
+                response.flattened_inputs_ptr = Box::into_raw(flattened) as *const u8;
+            }
+            Err(err) => {
+                let (code, ptr) = err_code_and_msg(&err);
+                response.status_code = code;
+                response.error_msg = ptr;
+            }
+        }
+    } else {
+        response.status_code = FCPResponseStatus::FCPCallerError;
+
+        let msg = CString::new("caller did not provide ConfiguredStore").unwrap();
+        response.error_msg = msg.as_ptr();
+        mem::forget(msg);
+    }
+
+    raw_ptr(response)
+}
+
+/// Derives the replica id of a sector from the prover id and sector id, as
+/// seal does. See filecoin_proofs::encoding for the exact encoding.
+///
+/// # Arguments
+///
+/// * `prover_id` - uniquely identifies the prover
+/// * `sector_id` - uniquely identifies the sector
+#[no_mangle]
+pub extern "C" fn derive_replica_id(
+    prover_id: &[u8; 31],
+    sector_id: &[u8; 31],
+) -> *mut responses::DeriveReplicaIdResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::DeriveReplicaIdResponse = Default::default();
+
+    response.status_code = FCPResponseStatus::FCPNoError;
+    response.replica_id = encoding::derive_replica_id(*prover_id, *sector_id);
+
+    raw_ptr(response)
+}
+
+/// Takes the 31 bytes which seal, verify_seal and derive_replica_id take as
+/// `prover_id` from a 32-byte prover id: its first 31 bytes, as those are
+/// padded with a zero byte. Fails with a caller error, rather than drop a byte
+/// of the id, if its last byte isn't zero. See filecoin_proofs::api::prover_id.
+///
+/// # Arguments
+///
+/// * `bytes` - 32-byte prover id, least significant byte first
+#[no_mangle]
+pub extern "C" fn prover_id_from_32_bytes(
+    bytes: &[u8; 32],
+) -> *mut responses::ProverIdFrom32BytesResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::ProverIdFrom32BytesResponse = Default::default();
+
+    match ProverId::from_bytes_32(*bytes) {
+        Ok(ProverId(prover_id)) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.prover_id = prover_id;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err.into());
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Encodes a sector id, as a SectorBuilder allocates it, into the 31 bytes
+/// which verify_seal and derive_replica_id take as `sector_id`: its
+/// little-endian bytes, followed by zeroes. A SectorBuilder seals its sectors
+/// with their ids encoded this way, so its proofs only verify with them.
+///
+/// # Arguments
+///
+/// * `sector_id` - sector id, as allocated by a SectorBuilder
+/// * `out`       - receives the encoded sector id
+#[no_mangle]
+pub unsafe extern "C" fn sector_id_as_bytes(sector_id: u64, out: *mut [u8; 31]) {
+    handshake::assert_initialized();
+
+    if let Some(out) = out.as_mut() {
+        *out = SectorId(sector_id).to_fr_safe();
+    }
+}
+
+/// Writes a commitment (e.g. comm_r, comm_d or comm_p) in its canonical string
+/// form: 'f', the multibase prefix of lowercase base16, followed by its 32
+/// bytes in order, as 64 lowercase hex digits. This is the form in which the
+/// library logs and reports commitments. See filecoin_proofs::encoding::Comm.
+///
+/// # Arguments
+///
+/// * `commitment` - commitment to write
+#[no_mangle]
+pub extern "C" fn commitment_to_string(
+    commitment: &[u8; 32],
+) -> *mut responses::CommitmentToStringResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::CommitmentToStringResponse = Default::default();
+
+    response.status_code = FCPResponseStatus::FCPNoError;
+    response.commitment_string = rust_str_to_c_str(encoding::Comm(*commitment).to_string());
+
+    raw_ptr(response)
+}
+
+/// Reads a commitment written by commitment_to_string. Fails with a caller
+/// error if the string isn't exactly in that form, e.g. if it has another
+/// prefix, uppercase digits or the wrong number of digits.
+///
+/// # Arguments
+///
+/// * `commitment_string` - commitment in its canonical string form
+#[no_mangle]
+pub unsafe extern "C" fn commitment_from_string(
+    commitment_string: *const libc::c_char,
+) -> *mut responses::CommitmentFromStringResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::CommitmentFromStringResponse = Default::default();
+
+    let parsed = if commitment_string.is_null() {
+        "".parse::<encoding::Comm>()
+    } else {
+        c_str_to_rust_str(commitment_string).parse::<encoding::Comm>()
+    };
+
+    match parsed {
+        Ok(encoding::Comm(commitment)) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.commitment = commitment;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err.into());
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Called with each record logged: its level, from 1 (critical) to 6 (trace),
+/// its message followed by its key-value pairs, and the caller's user data.
+pub type LogCallback =
+    extern "C" fn(level: u8, msg: *const libc::c_char, user_data: *mut libc::c_void);
+
+/// Passes what filecoin-proofs and storage-proofs log to `callback`, rather
+/// than writing it to stderr (or, as JSON, to stdout), or writes it there
+/// again if `callback` is null. Once this returns, the callback it replaced is
+/// never called again, so its user data may be freed.
+///
+/// The callback is called on the thread which logs, from any number of threads
+/// at once, and must not call into this library. The message is only valid for
+/// the duration of the call.
+///
+/// # Arguments
+///
+/// * `callback`  - log callback, or null
+/// * `user_data` - passed to each call of the callback, never dereferenced
+#[no_mangle]
+pub extern "C" fn set_log_callback(callback: Option<LogCallback>, user_data: *mut libc::c_void) {
+    handshake::assert_initialized();
+
+    let user_data = CallbackUserData(user_data);
+
+    logging_toolkit::set_log_callback(callback.map(|callback| {
+        Box::new(move |level: slog::Level, msg: &str| {
+            // A message can't be passed on past a NUL byte.
+            let msg = CString::new(msg.replace('\0', "")).unwrap();
+            callback(level.as_usize() as u8, msg.as_ptr(), user_data.0);
+        }) as logging_toolkit::LogCallback
+    }));
+}
+
+// The caller's user data, only passed back to the caller's callback.
+struct CallbackUserData(*mut libc::c_void);
+
+unsafe impl Send for CallbackUserData {}
+unsafe impl Sync for CallbackUserData {}
+
+/// Sets the least severe level logged, from 1 (critical) to 6 (trace), in
+/// place of the level set by the RUST_PROOFS_MIN_LOG_LEVEL environment
+/// variable (info by default), or restores that level if `level` is 0. Any
+/// other level is ignored.
+///
+/// # Arguments
+///
+/// * `level` - least severe level logged, or 0
+#[no_mangle]
+pub extern "C" fn set_log_level(level: u8) {
+    handshake::assert_initialized();
+
+    if level == 0 {
+        logging_toolkit::set_min_log_level(None);
+    } else if let Some(level) = slog::Level::from_usize(level as usize) {
+        logging_toolkit::set_min_log_level(Some(level));
+    }
+}
+
+/// Called with the caller's user data at each yield point of an operation.
+/// Returns whether the operation is to go on.
+pub type YieldCallback = extern "C" fn(user_data: *mut libc::c_void) -> bool;
+
+/// Calls `callback` at bounded intervals from the long CPU-bound loops of
+/// sealing, unsealing and proving (once every yield interval's worth of nodes
+/// encoded, decoded or hashed into a tree, and between the layers and
+/// partitions proven), and at the checkpoints of every cancellable operation,
+/// or stops calling any callback if `callback` is null. Once this returns, the
+/// callback it replaced is never called again, so its user data may be freed.
+///
+/// The callback may yield to the caller's scheduler, or report progress. Should
+/// it return false, the operation which called it stops within an interval, and
+/// fails as it would had it been cancelled. It's called from any number of
+/// threads at once, and must not call into this library.
+///
+/// # Arguments
+///
+/// * `callback`  - yield callback, or null
+/// * `user_data` - passed to each call of the callback, never dereferenced
+#[no_mangle]
+pub extern "C" fn set_yield_hook(callback: Option<YieldCallback>, user_data: *mut libc::c_void) {
+    handshake::assert_initialized();
+
+    let user_data = CallbackUserData(user_data);
+
+    yield_hook::set_yield_hook(callback.map(|callback| {
+        Box::new(move |_: &str| callback(user_data.0)) as yield_hook::YieldHook
+    }));
+}
+
+/// Sets the yield interval: the nodes encoded, decoded or hashed into a tree
+/// between two calls of the yield callback, or restores the default (4096) if
+/// `nodes` is 0.
+///
+/// # Arguments
+///
+/// * `nodes` - nodes worked on between two calls, or 0
+#[no_mangle]
+pub extern "C" fn set_yield_interval(nodes: u64) {
+    handshake::assert_initialized();
+
+    yield_hook::set_yield_interval(nodes as usize);
+}
+
+/// Initializes and returns a SectorBuilder.
+///
+/// Sector ids are allocated in increasing order from the inclusive range
+/// first_sector_id..=last_sector_id, continuing after the last id allocated by
+/// any earlier SectorBuilder over the same metadata_dir. Initialization fails if
+/// that id lies beyond last_sector_id.
+///
+/// If landing_sector_dir is not null, sectors are sealed there (e.g. on fast
+/// scratch storage) and then moved to sealed_sector_dir, which may be on another
+/// file system. A sector only appears in sealed_sector_dir once it has been
+/// sealed and its proof verified.
+///
+/// If max_seal_memory_bytes is non-zero, sealing a sector fails, with a
+/// MemoryLimitExceeded error naming what it was about to allocate, before
+/// sealing's large allocations (the sector's data, copies of its layers, their
+/// merkle trees and circuit assignments) take more memory than that.
+///
+/// Initialization fails, with FCPCallerError, if staged_sector_dir and
+/// sealed_sector_dir are the same directory, or one is inside the other.
+///
+/// Pieces are read from sealed sectors by num_retrieval_workers threads of their
+/// own, which sealing never takes. While any piece is being read, sealing
+/// pauses for seal_write_pause_millis after writing each seal_write_chunk_bytes
+/// of a sector, leaving the disk to the read. Each of the three takes its
+/// default (2 threads, 4 MiB and 50ms) when 0.
+///
+/// Seals start no more than as many at once as the task pool has threads. If
+/// seal_memory_budget_bytes is non-zero, a seal only starts beside those
+/// running if the memory they're all estimated to take fits in it; if
+/// min_seal_stagger_millis is non-zero, seals start at least that far apart.
+/// Seals waiting to start, and those running, are reported by
+/// get_seal_admissions.
+///
+/// If parallelism_override is non-zero, it overrides the parallelism detected
+/// (see get_effective_parallelism) for every pool sized after it: the task
+/// pool and this SectorBuilder's sealers, the default number of retrieval
+/// workers and, if no SectorBuilder has been initialized before, the threads
+/// sectors are replicated and their trees built on.
+///
+/// If read_only is set, the SectorBuilder only serves and verifies sectors
+/// already sealed in its directories, which may be mounted read-only: its
+/// metadata is loaded but never written, and nothing is created in any of its
+/// directories. Every call which would write (adding pieces, sealing, importing
+/// and migrating sectors) fails with FCPCallerError.
+///
+/// If staging_key is not null, staged sectors are encrypted at rest with
+/// XChaCha20Poly1305 under the 32-byte key it points to, recorded in them as
+/// staging_key_id. The key is never persisted: every SectorBuilder over the same
+/// staged_sector_dir must be given it to seal the sectors staged there.
+///
+/// A piece added with the same bytes as a piece placed before it, as told by
+/// their comm_ps and lengths, is stored again if duplicate_piece_policy is
+/// Allow, refused with FCPCallerError if it's Reject, and recorded as an alias
+/// of the piece placed before it, whose bytes it's read from, if it's
+/// Deduplicate. The AddPieceResponse of a piece stored or recorded as a duplicate
+/// has `duplicate` set.
+///
+/// If piece_key_material is not null, pieces are recorded in the metadata and the
+/// audit log under pseudonyms of their keys, the hex-encoded HMAC-SHA256 of each
+/// key under the 32 bytes it points to, rather than under the keys themselves.
+/// Pieces are still added and read by key. The material is never persisted, but
+/// the metadata is marked as private: initialization fails with FCPCallerError
+/// if other material is given for it later, or if it holds pieces added without
+/// material. A SectorBuilder initialized over private metadata without material
+/// lists pieces by pseudonym, reads them by pseudonym, and fails to add any with
+/// FCPCallerError.
+///
+#[no_mangle]
+#[allow(clippy::too_many_arguments)]
+pub unsafe extern "C" fn init_sector_builder(
+    sector_store_config_ptr: *const ConfiguredStore,
+    first_sector_id: u64,
+    last_sector_id: u64,
+    metadata_dir: *const libc::c_char,
+    prover_id: &[u8; 31],
+    sealed_sector_dir: *const libc::c_char,
+    landing_sector_dir: *const libc::c_char,
+    staged_sector_dir: *const libc::c_char,
+    max_num_staged_sectors: u8,
+    max_seal_memory_bytes: u64,
+    num_retrieval_workers: u8,
+    seal_write_chunk_bytes: u64,
+    seal_write_pause_millis: u64,
+    seal_memory_budget_bytes: u64,
+    min_seal_stagger_millis: u64,
+    parallelism_override: u64,
+    read_only: bool,
+    staging_key_id: u32,
+    staging_key: *const [u8; 32],
+    duplicate_piece_policy: DuplicatePiecePolicy,
+    piece_key_material: *const [u8; 32],
+) -> *mut responses::InitSectorBuilderResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::InitSectorBuilderResponse = Default::default();
+
+    if parallelism_override > 0 {
+        parallelism::set_parallelism_override(parallelism_override as usize);
+    }
+
+    if let Some(cfg) = sector_store_config_ptr.as_ref() {
+        match SectorBuilder::init_from_metadata(
+            cfg,
+            SectorIdRange {
+                first: first_sector_id,
+                last: last_sector_id,
+            },
+            c_str_to_rust_str(metadata_dir).to_string(),
+            ProverId(*prover_id),
+            c_str_to_rust_str(sealed_sector_dir).to_string(),
+            if landing_sector_dir.is_null() {
+                None
+            } else {
+                Some(c_str_to_rust_str(landing_sector_dir).to_string())
+            },
+            c_str_to_rust_str(staged_sector_dir).to_string(),
+            max_num_staged_sectors,
+            VERIFY_STAGED_DATA,
+            if max_seal_memory_bytes == 0 {
+                None
+            } else {
+                Some(max_seal_memory_bytes)
+            },
+            IoPriority::from_ffi(
+                num_retrieval_workers,
+                seal_write_chunk_bytes,
+                seal_write_pause_millis,
+            ),
+            SealScheduling::from_ffi(seal_memory_budget_bytes, min_seal_stagger_millis),
+            match staging_key.as_ref() {
+                Some(key) => StagingEncryption::XChaCha20Poly1305(StagingKey::from_bytes(
+                    staging_key_id,
+                    *key,
+                )),
+                None => StagingEncryption::None,
+            },
+            duplicate_piece_policy,
+            piece_key_material
+                .as_ref()
+                .map(|material| PieceKeyMaterial::from_bytes(material)),
+            read_only,
+        ) {
+            Ok(sb) => {
+                response.status_code = FCPResponseStatus::FCPNoError;
+                response.sector_builder = raw_ptr(sb);
+            }
+            Err(err) => {
+                let (code, ptr) = err_code_and_msg(&err);
+                response.status_code = code;
+                response.error_msg = ptr;
+            }
+        }
+    } else {
+        response.status_code = FCPResponseStatus::FCPCallerError;
+
+        let msg = CString::new("caller did not provide ConfiguredStore").unwrap();
+        response.error_msg = msg.as_ptr();
+        mem::forget(msg);
+    }
+
+    raw_ptr(response)
+}
+
+/// Shuts down and destroys a SectorBuilder. No more work is admitted. Seals
+/// queued, but not started, are persisted, and are queued again by the next
+/// SectorBuilder initialized over the same metadata directory. Running seals
+/// are given `drain_timeout_seconds` to finish, and are then cancelled: their
+/// sectors' seal status is Failed, and they may be sealed again. Calls to
+/// seal_sector waiting on a seal which won't finish fail with FCPCallerError,
+/// once each. The metadata is persisted and the audit log flushed before the
+/// SectorBuilder's threads are stopped.
+///
+/// # Arguments
+///
+/// * `drain_timeout_seconds` - how long running seals are given to finish, or 0
+///                             to cancel them at once
+#[no_mangle]
+pub unsafe extern "C" fn destroy_sector_builder(
+    ptr: *mut SectorBuilder,
+    drain_timeout_seconds: u64,
+) {
+    let builder = Box::from_raw(ptr);
+
+    // The outcome is logged.
+    let _ = builder.shutdown(Duration::from_secs(drain_timeout_seconds));
+}
+
+/// Writes user piece-bytes to a staged sector and returns the id of the sector
+/// to which the bytes were written. If the piece has the same bytes as a piece
+/// placed before it, the response has `duplicate` set, and, if the
+/// SectorBuilder deduplicates pieces, names the sector holding that piece.
+///
+/// # Arguments
+///
+/// * `expires_at` - when the deal storing the piece expires, or 0 if it never
+///                  does
+#[no_mangle]
+pub unsafe extern "C" fn add_piece(
+    ptr: *mut SectorBuilder,
+    piece_key: *const libc::c_char,
+    piece_ptr: *const u8,
+    piece_len: libc::size_t,
+    expires_at: u64,
+) -> *mut responses::AddPieceResponse {
+    handshake::assert_initialized();
+
+    let piece_key = c_str_to_rust_str(piece_key);
+    let piece_bytes = from_raw_parts(piece_ptr, piece_len);
+    let expires_at = Some(expires_at).filter(|at| *at > 0);
+
+    let mut response: responses::AddPieceResponse = Default::default();
+
+    match (*ptr).add_piece(String::from(piece_key), piece_bytes, expires_at) {
+        Ok(added) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.sector_id = added.sector_id;
+            response.duplicate = added.duplicate_of.is_some();
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Called to read the next bytes of a piece into `buf`, which has room for
+/// `max` bytes. Returns the number of bytes read, 0 at the end of the piece,
+/// or a negative number if the piece could not be read.
+pub type PieceReadCallback =
+    extern "C" fn(buf: *mut u8, max: libc::size_t, user_data: *mut libc::c_void) -> isize;
+
+/// Writes a piece of `piece_len` bytes, pulled from `read`, to a staged sector
+/// and returns the id of the sector to which the bytes were written, as
+/// add_piece does. Unlike add_piece, the piece is never held in memory as a
+/// whole.
+///
+/// `read` is called from another thread while this call blocks, until it
+/// signals the end of the piece. If the piece turns out not to be exactly
+/// `piece_len` bytes long, or `read` fails, nothing is written.
+///
+/// # Arguments
+///
+/// * `piece_len`  - length of the piece, in bytes
+/// * `expires_at` - when the deal storing the piece expires, or 0 if it never
+///                  does
+/// * `read`       - read callback
+/// * `user_data`  - passed to each call of the callback, never dereferenced
+#[no_mangle]
+pub unsafe extern "C" fn add_piece_from_reader(
+    ptr: *mut SectorBuilder,
+    piece_key: *const libc::c_char,
+    piece_len: u64,
+    expires_at: u64,
+    read: Option<PieceReadCallback>,
+    user_data: *mut libc::c_void,
+) -> *mut responses::AddPieceResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::AddPieceResponse = Default::default();
+
+    let read = match read {
+        Some(read) => read,
+        None => {
+            response.status_code = FCPResponseStatus::FCPCallerError;
+
+            let msg = CString::new("caller did not provide a read callback").unwrap();
+            response.error_msg = msg.as_ptr();
+            mem::forget(msg);
+
+            return raw_ptr(response);
+        }
+    };
+
+    let piece_key = c_str_to_rust_str(piece_key);
+    let expires_at = Some(expires_at).filter(|at| *at > 0);
+    let source = CallbackReader { read, user_data };
+
+    match (*ptr).add_piece_from_reader(String::from(piece_key), piece_len, expires_at, source) {
+        Ok(added) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.sector_id = added.sector_id;
+            response.duplicate = added.duplicate_of.is_some();
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+// Reads a piece through a caller's read callback.
+struct CallbackReader {
+    read: PieceReadCallback,
+    user_data: *mut libc::c_void,
+}
+
+// The caller's user data is only passed back to the caller, and only while
+// add_piece_from_reader blocks the caller's thread.
+unsafe impl Send for CallbackReader {}
+
+impl Read for CallbackReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = (self.read)(buf.as_mut_ptr(), buf.len(), self.user_data);
+
+        if n < 0 || n as usize > buf.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                format!("read callback returned {}", n),
+            ));
+        }
+
+        Ok(n as usize)
+    }
+}
+
+/// Plans where to stage the given pieces, packing them into as few sectors as
+/// possible, largest first, without staging them. The assignments are in the
+/// order in which the pieces were given. Planning the same pieces against the
+/// same staged sectors always yields the same plan.
+///
+/// Lengths are in user bytes, as is the capacity of staged sectors: there's no
+/// padding or alignment to account for.
+///
+/// # Arguments
+///
+/// * `piece_keys_ptr` - keys of the pieces
+/// * `piece_lens_ptr` - lengths of the pieces, in bytes
+/// * `pieces_len`     - number of pieces
+#[no_mangle]
+pub unsafe extern "C" fn plan_piece_placement(
+    ptr: *mut SectorBuilder,
+    piece_keys_ptr: *const *const libc::c_char,
+    piece_lens_ptr: *const u64,
+    pieces_len: libc::size_t,
+) -> *mut responses::PlanPiecePlacementResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::PlanPiecePlacementResponse = Default::default();
+
+    let pending_pieces: Vec<(String, u64)> = from_raw_parts(piece_keys_ptr, pieces_len)
+        .iter()
+        .zip(from_raw_parts(piece_lens_ptr, pieces_len))
+        .map(|(key, len)| (String::from(c_str_to_rust_str(*key)), *len))
+        .collect();
+
+    let result = (*ptr)
+        .plan_piece_placement(&pending_pieces)
+        .and_then(|plan| Ok((into_ffi_piece_assignments(&plan.assignments)?, plan)));
+
+    match result {
+        Ok((assignments, plan)) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.new_sectors = plan.new_sectors;
+            response.wasted_bytes = plan.wasted_bytes;
+            response.assignments_len = assignments.len();
+            response.assignments_ptr = Box::into_raw(assignments) as *const FFIPieceAssignment;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Stages the pieces of a plan returned by plan_piece_placement where it
+/// placed them, and returns the id of the sector each piece was staged in.
+/// Either every piece is staged, or none is. Fails if a staged sector no
+/// longer has room for the pieces planned for it.
+///
+/// # Arguments
+///
+/// * `plan`            - the plan, which isn't destroyed
+/// * `piece_ptrs_ptr`  - bytes of each of the plan's pieces, in its order, each
+///                       as long as its assignment
+/// * `expires_ats_ptr` - when the deal storing each piece expires, or 0 if it
+///                       never does
+#[no_mangle]
+pub unsafe extern "C" fn add_pieces_planned(
+    ptr: *mut SectorBuilder,
+    plan: *const responses::PlanPiecePlacementResponse,
+    piece_ptrs_ptr: *const *const u8,
+    expires_ats_ptr: *const u64,
+) -> *mut responses::AddPiecesPlannedResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::AddPiecesPlannedResponse = Default::default();
+
+    if (*plan).status_code != FCPResponseStatus::FCPNoError {
+        response.status_code = FCPResponseStatus::FCPCallerError;
+
+        let msg = CString::new("caller did not provide a successful plan").unwrap();
+        response.error_msg = msg.as_ptr();
+        mem::forget(msg);
+
+        return raw_ptr(response);
+    }
+
+    let plan = from_ffi_placement_plan(&*plan);
+
+    let pieces = plan
+        .assignments
+        .iter()
+        .zip(from_raw_parts(piece_ptrs_ptr, plan.assignments.len()))
+        .zip(from_raw_parts(expires_ats_ptr, plan.assignments.len()))
+        .map(|((assignment, piece_ptr), expires_at)| PlannedPiece {
+            piece_key: assignment.piece_key.clone(),
+            bytes: from_raw_parts(*piece_ptr, assignment.num_bytes as usize).to_vec(),
+            expires_at: Some(*expires_at).filter(|at| *at > 0),
+        })
+        .collect();
+
+    match (*ptr).add_pieces_planned(&plan, pieces) {
+        Ok(sector_ids) => {
+            let sector_ids = sector_ids.into_boxed_slice();
+
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.sector_ids_len = sector_ids.len();
+            response.sector_ids_ptr = Box::into_raw(sector_ids) as *const u64;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Unseals and returns the bytes associated with the provided piece key.
+///
+#[no_mangle]
+pub unsafe extern "C" fn read_piece_from_sealed_sector(
+    ptr: *mut SectorBuilder,
+    piece_key: *const libc::c_char,
+) -> *mut responses::ReadPieceFromSealedSectorResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::ReadPieceFromSealedSectorResponse = Default::default();
+
+    let piece_key = c_str_to_rust_str(piece_key);
+
+    match (*ptr).read_piece_from_sealed_sector(String::from(piece_key)) {
+        Ok(piece_bytes) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.data_ptr = piece_bytes.as_ptr();
+            response.data_len = piece_bytes.len();
+            mem::forget(piece_bytes);
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Returns the records of the SectorBuilder's audit log from the one with
+/// index since_record on, each as its CBOR-encoded bytes, once everything
+/// recorded so far has been written, e.g. for archiving elsewhere. The log
+/// records the pieces staged, the sectors sealed (or failed to be), and the
+/// proofs-of-spacetime generated and the faults they declared, each chained to
+/// the record before it by its hash. records_lost counts the records dropped,
+/// or which couldn't be written, since the SectorBuilder was initialized.
+///
+#[no_mangle]
+pub unsafe extern "C" fn export_audit_log(
+    ptr: *mut SectorBuilder,
+    since_record: u64,
+) -> *mut responses::ExportAuditLogResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::ExportAuditLogResponse = Default::default();
+
+    match (*ptr).export_audit_log(since_record) {
+        Ok(records) => {
+            let records: Vec<FFIAuditRecord> = records
+                .into_iter()
+                .map(|record| {
+                    let record = record.into_boxed_slice();
+                    FFIAuditRecord {
+                        record_len: record.len(),
+                        record_ptr: Box::into_raw(record) as *const u8,
+                    }
+                })
+                .collect();
+            let records = records.into_boxed_slice();
+
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.records_len = records.len();
+            response.records_ptr = Box::into_raw(records) as *const FFIAuditRecord;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    let stats = (*ptr).audit_log_stats();
+    response.records_lost = stats.dropped + stats.failed;
+
+    raw_ptr(response)
+}
+
+/// Copies the sealed sector with the given id to dest_dir, along with a
+/// manifest from which a SectorBuilder on another machine can import it with
+/// import_sealed_sector, and returns the path of the manifest. The copy is
+/// synced to disk and checked against the sealed sector before the manifest
+/// is written, so a copy without a manifest is incomplete.
+///
+#[no_mangle]
+pub unsafe extern "C" fn export_sealed_sector(
+    ptr: *mut SectorBuilder,
+    sector_id: u64,
+    dest_dir: *const libc::c_char,
+) -> *mut responses::ExportSealedSectorResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::ExportSealedSectorResponse = Default::default();
+
+    let dest_dir = c_str_to_pbuf(dest_dir);
+
+    match (*ptr)
+        .export_sealed_sector(sector_id, dest_dir)
+        .and_then(|path| try_rust_str_to_c_str(path.to_string_lossy()))
+    {
+        Ok(manifest_path) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.manifest_path = manifest_path;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Imports a sealed sector exported with export_sealed_sector by a
+/// SectorBuilder of the same prover, copying it into this SectorBuilder's
+/// sealed sector directory, and returns its id. Fails, importing nothing, if
+/// the copy doesn't match the checksum in its manifest, or if this
+/// SectorBuilder already has a sector with the same id.
+///
+#[no_mangle]
+pub unsafe extern "C" fn import_sealed_sector(
+    ptr: *mut SectorBuilder,
+    manifest_path: *const libc::c_char,
+) -> *mut responses::ImportSealedSectorResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::ImportSealedSectorResponse = Default::default();
+
+    let manifest_path = c_str_to_pbuf(manifest_path);
+
+    match (*ptr).import_sealed_sector(manifest_path) {
+        Ok(sector_id) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.sector_id = sector_id;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Migrates the pieces of the sealed sectors with the given ids into a single
+/// new sector of the size of the target config, seals it, and returns its id
+/// along with the status of each piece, in the order in which they were
+/// migrated. Each piece keeps its key, and its comm_p is recomputed: if that
+/// doesn't match the comm_p recorded for it, the migration fails, naming the
+/// piece. The source sectors are then retired: they're kept, but no longer
+/// proven, and their pieces are read from the new sector. This call blocks
+/// until the new sector is sealed.
+///
+/// # Arguments
+///
+/// * `source_sector_ids_ptr` - ids of the sealed sectors to migrate
+/// * `source_sector_ids_len` - number of ids
+/// * `target_config_ptr`     - pointer to the ConfiguredStore of the new sector
+///
+#[no_mangle]
+pub unsafe extern "C" fn migrate_sectors(
+    ptr: *mut SectorBuilder,
+    source_sector_ids_ptr: *const u64,
+    source_sector_ids_len: libc::size_t,
+    target_config_ptr: *const ConfiguredStore,
+) -> *mut responses::MigrateSectorsResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::MigrateSectorsResponse = Default::default();
+
+    let source_sector_ids = from_raw_parts(source_sector_ids_ptr, source_sector_ids_len);
+
+    let result = (*ptr)
+        .migrate_sectors(source_sector_ids, &*target_config_ptr)
+        .and_then(|migration| {
+            let pieces = into_ffi_migrated_pieces(&migration.pieces)?;
+            Ok((migration.sector_id, pieces))
+        });
+
+    match result {
+        Ok((sector_id, pieces)) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.sector_id = sector_id;
+            response.pieces_len = pieces.len();
+            response.pieces_ptr = Box::into_raw(pieces) as *const FFIMigratedPiece;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Proves each sealed sector which isn't retired again with the current
+/// version's parameters, without sealing it again, e.g. once the groth
+/// parameters it was proven with have been replaced. A sector's trees are
+/// rebuilt from the sealed sector, unless its prover bundle was kept. Returns
+/// the status of each sector, by sector id: a sector which fails to be proven
+/// keeps its proof, and its error message says why. Each new proof is recorded
+/// as it's made, the proof it replaces kept in the sector's metadata. This call
+/// blocks until every sector has been proven, or has failed to be.
+///
+#[no_mangle]
+pub unsafe extern "C" fn regenerate_all_proofs(
+    ptr: *mut SectorBuilder,
+) -> *mut responses::RegenerateAllProofsResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::RegenerateAllProofsResponse = Default::default();
+
+    let result = (*ptr)
+        .regenerate_all_proofs(ProofVersion::CURRENT)
+        .and_then(|regenerations| into_ffi_proof_regenerations(&regenerations));
+
+    match result {
+        Ok(sectors) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.sectors_len = sectors.len();
+            response.sectors_ptr = Box::into_raw(sectors) as *const FFIProofRegeneration;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// For demo purposes. Seals all staged sectors.
+///
+#[no_mangle]
+pub unsafe extern "C" fn seal_all_staged_sectors(
+    ptr: *mut SectorBuilder,
+) -> *mut responses::SealAllStagedSectorsResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::SealAllStagedSectorsResponse = Default::default();
+
+    match (*ptr).seal_all_staged_sectors() {
+        Ok(_) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Seals the staged sector with the given id, blocking until it's sealed, and
+/// returns its commitments and proof. A sector already sealed isn't sealed
+/// again: its commitments and proof are returned at once, with cached set,
+/// unless its staged data has changed since it was sealed, which is an error.
+/// A sector being sealed isn't sealed twice: the call waits for the seal in
+/// progress. Sectors which failed to seal are sealed again.
+///
+#[no_mangle]
+pub unsafe extern "C" fn seal_sector(
+    ptr: *mut SectorBuilder,
+    sector_id: u64,
+) -> *mut responses::SealSectorResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::SealSectorResponse = Default::default();
+
+    match (*ptr).seal_sector(sector_id) {
+        Ok(outcome) => {
+            let meta = outcome.sector;
+
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.cached = outcome.cached;
+            response.comm_d = meta.comm_d;
+            response.comm_r = meta.comm_r;
+            response.comm_r_star = meta.comm_r_star;
+            response.sector_access = rust_str_to_c_str(meta.sector_access);
+            response.sector_id = meta.sector_id;
+            response.snark_proof = meta.snark_proof;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Returns the number of user bytes that will fit into a staged sector.
+///
+#[no_mangle]
+pub unsafe extern "C" fn get_max_user_bytes_per_staged_sector(
+    ptr: *mut SectorBuilder,
+) -> *mut responses::GetMaxStagedBytesPerSector {
+    handshake::assert_initialized();
+
+    let mut response: responses::GetMaxStagedBytesPerSector = Default::default();
+
+    response.status_code = FCPResponseStatus::FCPNoError;
+    response.max_staged_bytes_per_sector = (*ptr).get_max_user_bytes_per_staged_sector();;
+
+    raw_ptr(response)
+}
+
+/// Returns the seals the SectorBuilder is running, the sectors whose seals are
+/// queued (next first), the last seals to finish, and how much of the memory
+/// budget the running seals are estimated to take. memory_budget_bytes is 0
+/// where there's no budget.
+///
+#[no_mangle]
+pub unsafe extern "C" fn get_seal_admissions(
+    ptr: *mut SectorBuilder,
+) -> *mut responses::GetSealAdmissionsResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::GetSealAdmissionsResponse = Default::default();
+
+    let admissions = (*ptr).get_seal_admissions();
+
+    let running: Box<[FFISealJob]> = admissions.running.iter().map(seal_job_into_ffi).collect();
+    let queued: Box<[u64]> = admissions.queued.into_boxed_slice();
+    let finished: Box<[FFISealJob]> = admissions.finished.iter().map(seal_job_into_ffi).collect();
+
+    response.status_code = FCPResponseStatus::FCPNoError;
+    response.running_len = running.len();
+    response.running_ptr = Box::into_raw(running) as *const FFISealJob;
+    response.queued_len = queued.len();
+    response.queued_ptr = Box::into_raw(queued) as *const u64;
+    response.finished_len = finished.len();
+    response.finished_ptr = Box::into_raw(finished) as *const FFISealJob;
+    response.memory_budget_bytes = admissions.memory_budget_bytes.unwrap_or(0);
+    response.memory_in_use_bytes = admissions.memory_in_use_bytes;
+    response.max_running = admissions.max_running;
+
+    raw_ptr(response)
+}
+
+fn seal_job_into_ffi(job: &SealJob) -> FFISealJob {
+    FFISealJob {
+        sector_id: job.sector_id,
+        memory_bytes: job.memory_bytes,
+        admitted_at_millis: unix_millis(job.admitted_at),
+        finished_at_millis: job.finished_at.map_or(0, unix_millis),
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+
+    since_epoch.as_secs() * 1000 + u64::from(since_epoch.subsec_millis())
+}
+
+/// Returns sector sealing status for the provided sector id if it exists. If
+/// we don't know about the provided sector id, produce an error.
+///
+#[no_mangle]
+pub unsafe extern "C" fn get_seal_status(
+    ptr: *mut SectorBuilder,
+    sector_id: u64,
+) -> *mut responses::GetSealStatusResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::GetSealStatusResponse = Default::default();
+
+    match (*ptr).get_seal_status(sector_id) {
+        Ok(seal_status) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+
+            match seal_status {
+                SealStatus::Sealed(meta) => {
+                    let meta = *meta;
+
+                    response.seal_status_code = FFISealStatus::Sealed;
+                    response.comm_d = meta.comm_d;
+                    response.comm_r = meta.comm_r;
+                    response.comm_r_star = meta.comm_r_star;
+                    response.snark_proof = meta.snark_proof;
+                    response.sector_id = meta.sector_id;
                     response.sector_access = rust_str_to_c_str(meta.sector_access);
 
                     let pieces = meta
@@ -408,49 +2238,91 @@ pub unsafe extern "C" fn get_seal_status(
     raw_ptr(response)
 }
 
+/// Returns the ids, in ascending order, of the sealed sectors all of whose
+/// pieces' deals have expired at `now`. Sectors holding a piece without an
+/// expiry never expire. The ids may be passed to generate_post to leave those
+/// sectors out of the proof.
+///
+#[no_mangle]
+pub unsafe extern "C" fn get_expired_sectors(
+    ptr: *mut SectorBuilder,
+    now: u64,
+) -> *mut responses::GetExpiredSectorsResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::GetExpiredSectorsResponse = Default::default();
+
+    let sector_ids = (*ptr).get_expired_sectors(now).into_boxed_slice();
+
+    response.status_code = FCPResponseStatus::FCPNoError;
+    response.sector_ids_len = sector_ids.len();
+    response.sector_ids_ptr = Box::into_raw(sector_ids) as *const u64;
+
+    raw_ptr(response)
+}
+
+/// Returns the staged and sealed pieces whose deals expire before `before`,
+/// with the sectors holding them, ordered by expiry and then by key, so that
+/// they may be evicted once expired.
+///
+/// If the SectorBuilder's piece keys are private (see init_sector_builder),
+/// pieces are listed by pseudonym, unless real_piece_keys is set and the
+/// SectorBuilder was given their material. Each is then listed by its key if
+/// it was added or read through this SectorBuilder, and by pseudonym if not.
+///
+#[no_mangle]
+pub unsafe extern "C" fn get_expiring_pieces(
+    ptr: *mut SectorBuilder,
+    before: u64,
+    real_piece_keys: bool,
+) -> *mut responses::GetExpiringPiecesResponse {
+    handshake::assert_initialized();
+
+    let mut response: responses::GetExpiringPiecesResponse = Default::default();
+
+    match into_ffi_expiring_pieces(&(*ptr).get_expiring_pieces(before, real_piece_keys)) {
+        Ok(pieces) => {
+            response.status_code = FCPResponseStatus::FCPNoError;
+            response.pieces_len = pieces.len();
+            response.pieces_ptr = Box::into_raw(pieces) as *const FFIExpiringPiece;
+        }
+        Err(err) => {
+            let (code, ptr) = err_code_and_msg(&err);
+            response.status_code = code;
+            response.error_msg = ptr;
+        }
+    }
+
+    raw_ptr(response)
+}
+
+/// Returns the metadata of every sealed sector. A sector whose metadata can't
+/// be represented over FFI is reported through its item status code and error
+/// message without failing the other sectors. Pieces are listed as
+/// get_expiring_pieces lists them.
+///
 #[no_mangle]
 pub unsafe extern "C" fn get_sealed_sectors(
     ptr: *mut SectorBuilder,
+    real_piece_keys: bool,
 ) -> *mut responses::GetSealedSectorsResponse {
+    handshake::assert_initialized();
+
     let mut response: responses::GetSealedSectorsResponse = Default::default();
 
-    match (*ptr).get_sealed_sectors() {
+    match (*ptr).get_sealed_sectors(real_piece_keys) {
         Ok(sealed_sectors) => {
             response.status_code = FCPResponseStatus::FCPNoError;
 
-            let sectors = sealed_sectors
-                .iter()
-                .map(|meta| {
-                    let pieces = meta
-                        .pieces
-                        .iter()
-                        .map(|p| FFIPieceMetadata {
-                            piece_key: rust_str_to_c_str(p.piece_key.to_string()),
-                            num_bytes: p.num_bytes,
-                        })
-                        .collect::<Vec<FFIPieceMetadata>>();
-
-                    let sector = responses::FFISealedSectorMetadata {
-                        comm_d: meta.comm_d,
-                        comm_r: meta.comm_r,
-                        comm_r_star: meta.comm_r_star,
-                        sector_access: rust_str_to_c_str(meta.sector_access.clone()),
-                        sector_id: meta.sector_id,
-                        snark_proof: meta.snark_proof,
-                        pieces_len: pieces.len(),
-                        pieces_ptr: pieces.as_ptr(),
-                    };
-
-                    mem::forget(pieces);
-
-                    sector
-                })
-                .collect::<Vec<responses::FFISealedSectorMetadata>>();
+            let results =
+                PartialResults::collect(sealed_sectors.iter().map(sealed_sector_into_ffi));
 
-            response.sectors_len = sectors.len();
-            response.sectors_ptr = sectors.as_ptr();
+            response.sectors_len = results.len();
 
-            mem::forget(sectors);
+            let (status_codes, error_msgs, sectors) = results.into_raw_parts();
+            response.item_status_codes_ptr = status_codes;
+            response.item_error_msgs_ptr = error_msgs;
+            response.sectors_ptr = sectors;
         }
         Err(err) => {
             let (code, ptr) = err_code_and_msg(&err);
@@ -462,63 +2334,33 @@ pub unsafe extern "C" fn get_sealed_sectors(
     raw_ptr(response)
 }
 
+/// Returns the metadata of every staged sector. A sector whose metadata can't
+/// be represented over FFI is reported through its item status code and error
+/// message without failing the other sectors. Pieces are listed as
+/// get_expiring_pieces lists them.
+///
 #[no_mangle]
 pub unsafe extern "C" fn get_staged_sectors(
     ptr: *mut SectorBuilder,
+    real_piece_keys: bool,
 ) -> *mut responses::GetStagedSectorsResponse {
+    handshake::assert_initialized();
+
     let mut response: responses::GetStagedSectorsResponse = Default::default();
 
-    match (*ptr).get_staged_sectors() {
+    match (*ptr).get_staged_sectors(real_piece_keys) {
         Ok(staged_sectors) => {
             response.status_code = FCPResponseStatus::FCPNoError;
 
-            let sectors = staged_sectors
-                .iter()
-                .map(|meta| {
-                    let pieces = meta
-                        .pieces
-                        .iter()
-                        .map(|p| FFIPieceMetadata {
-                            piece_key: rust_str_to_c_str(p.piece_key.to_string()),
-                            num_bytes: p.num_bytes,
-                        })
-                        .collect::<Vec<FFIPieceMetadata>>();
-
-                    let mut sector = responses::FFIStagedSectorMetadata {
-                        sector_access: rust_str_to_c_str(meta.sector_access.clone()),
-                        sector_id: meta.sector_id,
-                        pieces_len: pieces.len(),
-                        pieces_ptr: pieces.as_ptr(),
-                        seal_status_code: FFISealStatus::Pending,
-                        seal_error_msg: ptr::null(),
-                    };
-
-                    match meta.seal_status {
-                        SealStatus::Failed(ref s) => {
-                            sector.seal_status_code = FFISealStatus::Failed;
-                            sector.seal_error_msg = rust_str_to_c_str(s.clone());
-                        }
-                        SealStatus::Sealing => {
-                            sector.seal_status_code = FFISealStatus::Sealing;
-                        }
-                        SealStatus::Pending => {
-                            sector.seal_status_code = FFISealStatus::Pending;
-                        }
-                        SealStatus::Sealed(_) => {
-                            sector.seal_status_code = FFISealStatus::Sealed;
-                        }
-                    };
-
-                    mem::forget(pieces);
-
-                    sector
-                })
-                .collect::<Vec<responses::FFIStagedSectorMetadata>>();
+            let results =
+                PartialResults::collect(staged_sectors.iter().map(staged_sector_into_ffi));
 
-            response.sectors_len = sectors.len();
-            response.sectors_ptr = sectors.as_ptr();
+            response.sectors_len = results.len();
 
-            mem::forget(sectors);
+            let (status_codes, error_msgs, sectors) = results.into_raw_parts();
+            response.item_status_codes_ptr = status_codes;
+            response.item_error_msgs_ptr = error_msgs;
+            response.sectors_ptr = sectors;
         }
         Err(err) => {
             let (code, ptr) = err_code_and_msg(&err);
@@ -529,3 +2371,191 @@ pub unsafe extern "C" fn get_staged_sectors(
 
     raw_ptr(response)
 }
+
+// Produces a C string, failing (rather than panicking, as rust_str_to_c_str
+// does) if the string contains an interior nul byte.
+fn try_rust_str_to_c_str<T: Into<String>>(s: T) -> error::Result<*const libc::c_char> {
+    Ok(CString::new(s.into())?.into_raw())
+}
+
+fn into_ffi_pieces(pieces: &[PieceMetadata]) -> error::Result<Box<[FFIPieceMetadata]>> {
+    pieces
+        .iter()
+        .map(|p| -> error::Result<FFIPieceMetadata> {
+            Ok(FFIPieceMetadata {
+                piece_key: try_rust_str_to_c_str(p.piece_key.clone())?,
+                num_bytes: p.num_bytes,
+            })
+        })
+        .collect::<error::Result<Vec<_>>>()
+        .map(Vec::into_boxed_slice)
+}
+
+fn into_ffi_migrated_pieces(pieces: &[MigratedPiece]) -> error::Result<Box<[FFIMigratedPiece]>> {
+    pieces
+        .iter()
+        .map(|p| -> error::Result<FFIMigratedPiece> {
+            Ok(FFIMigratedPiece {
+                piece_key: try_rust_str_to_c_str(p.piece_key.clone())?,
+                source_sector_id: p.source_sector_id,
+                status: match p.status {
+                    MigratedPieceStatus::Verified => FFIMigratedPieceStatus::Verified,
+                    MigratedPieceStatus::Unverified => FFIMigratedPieceStatus::Unverified,
+                },
+            })
+        })
+        .collect::<error::Result<Vec<_>>>()
+        .map(Vec::into_boxed_slice)
+}
+
+fn into_ffi_proof_regenerations(
+    regenerations: &[ProofRegeneration],
+) -> error::Result<Box<[FFIProofRegeneration]>> {
+    regenerations
+        .iter()
+        .map(|r| -> error::Result<FFIProofRegeneration> {
+            let (status, error_msg) = match r.status {
+                ProofRegenerationStatus::Regenerated => {
+                    (FFIProofRegenerationStatus::Regenerated, ptr::null())
+                }
+                ProofRegenerationStatus::Failed(ref err) => (
+                    FFIProofRegenerationStatus::Failed,
+                    try_rust_str_to_c_str(err.clone())?,
+                ),
+            };
+
+            Ok(FFIProofRegeneration {
+                sector_id: r.sector_id,
+                status,
+                error_msg,
+            })
+        })
+        .collect::<error::Result<Vec<_>>>()
+        .map(Vec::into_boxed_slice)
+}
+
+fn into_ffi_expiring_pieces(pieces: &[ExpiringPiece]) -> error::Result<Box<[FFIExpiringPiece]>> {
+    pieces
+        .iter()
+        .map(|p| -> error::Result<FFIExpiringPiece> {
+            Ok(FFIExpiringPiece {
+                piece_key: try_rust_str_to_c_str(p.piece_key.clone())?,
+                sector_id: p.sector_id,
+                expires_at: p.expires_at,
+            })
+        })
+        .collect::<error::Result<Vec<_>>>()
+        .map(Vec::into_boxed_slice)
+}
+
+fn into_ffi_piece_assignments(
+    assignments: &[PieceAssignment],
+) -> error::Result<Box<[FFIPieceAssignment]>> {
+    assignments
+        .iter()
+        .map(|a| -> error::Result<FFIPieceAssignment> {
+            let (sector_id, new_sector) = match a.sector {
+                PlannedSector::Staged(sector_id) => (sector_id, false),
+                PlannedSector::New(n) => (n as u64, true),
+            };
+
+            Ok(FFIPieceAssignment {
+                piece_key: try_rust_str_to_c_str(a.piece_key.clone())?,
+                num_bytes: a.num_bytes,
+                sector_id,
+                new_sector,
+            })
+        })
+        .collect::<error::Result<Vec<_>>>()
+        .map(Vec::into_boxed_slice)
+}
+
+unsafe fn from_ffi_placement_plan(plan: &responses::PlanPiecePlacementResponse) -> PlacementPlan {
+    let assignments = from_raw_parts(plan.assignments_ptr, plan.assignments_len)
+        .iter()
+        .map(|a| PieceAssignment {
+            piece_key: String::from(c_str_to_rust_str(a.piece_key)),
+            num_bytes: a.num_bytes,
+            sector: if a.new_sector {
+                PlannedSector::New(a.sector_id as usize)
+            } else {
+                PlannedSector::Staged(a.sector_id)
+            },
+        })
+        .collect();
+
+    PlacementPlan {
+        assignments,
+        new_sectors: plan.new_sectors,
+        wasted_bytes: plan.wasted_bytes,
+    }
+}
+
+fn into_ffi_shards(shards: &[(PathBuf, u64)]) -> error::Result<Box<[FFIUnsealedShard]>> {
+    shards
+        .iter()
+        .map(|(path, num_bytes)| -> error::Result<FFIUnsealedShard> {
+            Ok(FFIUnsealedShard {
+                path: try_rust_str_to_c_str(path.to_string_lossy().into_owned())?,
+                num_bytes: *num_bytes,
+            })
+        })
+        .collect::<error::Result<Vec<_>>>()
+        .map(Vec::into_boxed_slice)
+}
+
+fn sealed_sector_into_ffi(
+    meta: &SealedSectorMetadata,
+) -> error::Result<responses::FFISealedSectorMetadata> {
+    let pieces = into_ffi_pieces(&meta.pieces)?;
+    let sector_access = try_rust_str_to_c_str(meta.sector_access.clone())?;
+
+    let sector = responses::FFISealedSectorMetadata {
+        comm_d: meta.comm_d,
+        comm_r: meta.comm_r,
+        comm_r_star: meta.comm_r_star,
+        sector_access,
+        sector_id: meta.sector_id,
+        snark_proof: meta.snark_proof,
+        pieces_len: pieces.len(),
+        pieces_ptr: Box::into_raw(pieces) as *const FFIPieceMetadata,
+    };
+
+    Ok(sector)
+}
+
+fn staged_sector_into_ffi(
+    meta: &StagedSectorMetadata,
+) -> error::Result<responses::FFIStagedSectorMetadata> {
+    // An early return drops (and so frees) the partially-built sector.
+    let mut sector: responses::FFIStagedSectorMetadata = Default::default();
+
+    match meta.seal_status {
+        SealStatus::Failed(ref s) => {
+            sector.seal_status_code = FFISealStatus::Failed;
+            sector.seal_error_msg = try_rust_str_to_c_str(s.clone())?;
+        }
+        SealStatus::Sealing => {
+            sector.seal_status_code = FFISealStatus::Sealing;
+        }
+        SealStatus::Pending => {
+            sector.seal_status_code = FFISealStatus::Pending;
+        }
+        SealStatus::Sealed(_) => {
+            sector.seal_status_code = FFISealStatus::Sealed;
+        }
+    };
+
+    sector.sector_access = try_rust_str_to_c_str(meta.sector_access.clone())?;
+    sector.sector_id = meta.sector_id;
+
+    let pieces = into_ffi_pieces(&meta.pieces)?;
+    sector.pieces_len = pieces.len();
+    sector.pieces_ptr = Box::into_raw(pieces) as *const FFIPieceMetadata;
+
+    Ok(sector)
+}
+
+fn duration_millis(duration: Duration) -> u64 {
+    duration.as_secs() * 1000 + u64::from(duration.subsec_millis())
+}
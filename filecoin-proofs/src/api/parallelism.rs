@@ -0,0 +1,299 @@
+//! How many threads the process may usefully run at once, which sizes its
+//! thread pools: the global rayon pool (on which sectors are replicated and
+//! their trees built), the task pool and each SectorBuilder's sealers, and the
+//! default number of retrieval workers.
+//!
+//! The CPUs of the machine aren't all the process's to use in a container,
+//! whose cgroup may limit it to a CPU quota (e.g. 4 CPUs' worth of time on a
+//! 64-CPU machine) or to a set of CPUs. On Linux, both limits are read from
+//! the cgroup filesystem (v2, or v1's cpu and cpuset controllers), and the
+//! effective parallelism is the fewest CPUs any of them allows. Elsewhere,
+//! it's the number of CPUs available to the process. Operators who know
+//! better may override it (see set_parallelism_override, and the parallelism
+//! override of init_sector_builder).
+
+use std::cmp;
+use std::fs;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+
+use crate::FCP_LOG;
+
+const CGROUP_V2_CPU_MAX: &str = "/sys/fs/cgroup/cpu.max";
+const CGROUP_V2_CPUSET: &str = "/sys/fs/cgroup/cpuset.cpus.effective";
+const CGROUP_V1_CPU_QUOTA: &str = "/sys/fs/cgroup/cpu/cpu.cfs_quota_us";
+const CGROUP_V1_CPU_PERIOD: &str = "/sys/fs/cgroup/cpu/cpu.cfs_period_us";
+const CGROUP_V1_CPUSET: &str = "/sys/fs/cgroup/cpuset/cpuset.cpus";
+
+// The parallelism set by set_parallelism_override, or 0 for none.
+static OVERRIDE: AtomicUsize = AtomicUsize::new(0);
+
+static GLOBAL_POOL: Once = Once::new();
+
+lazy_static! {
+    static ref DETECTED: DetectedParallelism = DetectedParallelism::detect_host();
+}
+
+/// Reads the files of the cgroup filesystem, so that tests may stand in for it.
+pub trait CgroupFs {
+    /// The contents of the file at path, or None if it can't be read (e.g. as
+    /// the controller it belongs to isn't mounted).
+    fn read(&self, path: &str) -> Option<String>;
+}
+
+/// The cgroup filesystem of the host.
+pub struct HostCgroupFs;
+
+impl CgroupFs for HostCgroupFs {
+    fn read(&self, path: &str) -> Option<String> {
+        fs::read_to_string(path).ok()
+    }
+}
+
+/// The CPUs the process may use, by each of the limits on them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DetectedParallelism {
+    /// The CPUs available to the process, whatever its cgroup allows.
+    pub available: usize,
+    /// The CPUs' worth of time the cgroup's CPU quota allows, rounded up, if
+    /// it has a quota.
+    pub quota: Option<usize>,
+    /// The CPUs in the cgroup's cpuset, if it has one.
+    pub cpuset: Option<usize>,
+}
+
+impl DetectedParallelism {
+    /// Reads the cgroup limits from fs, where available CPUs are available.
+    pub fn detect(fs: &CgroupFs, available: usize) -> DetectedParallelism {
+        let quota = match fs.read(CGROUP_V2_CPU_MAX) {
+            Some(cpu_max) => parse_cpu_max(&cpu_max),
+            None => fs
+                .read(CGROUP_V1_CPU_QUOTA)
+                .and_then(|quota| parse_cfs_quota(&quota, &fs.read(CGROUP_V1_CPU_PERIOD)?)),
+        };
+
+        let cpuset = fs
+            .read(CGROUP_V2_CPUSET)
+            .or_else(|| fs.read(CGROUP_V1_CPUSET))
+            .and_then(|cpus| count_cpu_list(&cpus));
+
+        DetectedParallelism {
+            available,
+            quota,
+            cpuset,
+        }
+    }
+
+    fn detect_host() -> DetectedParallelism {
+        let available = num_cpus::get();
+
+        if cfg!(target_os = "linux") {
+            DetectedParallelism::detect(&HostCgroupFs, available)
+        } else {
+            DetectedParallelism {
+                available,
+                quota: None,
+                cpuset: None,
+            }
+        }
+    }
+
+    /// The fewest CPUs any of the limits allows, and at least one.
+    pub fn effective(&self) -> usize {
+        let limits = self.quota.into_iter().chain(self.cpuset);
+
+        limits.fold(self.available, cmp::min).max(1)
+    }
+}
+
+/// The CPUs of the host, as detected when first asked.
+pub fn detected_parallelism() -> DetectedParallelism {
+    *DETECTED
+}
+
+/// The number of threads the process's pools are sized by: the override, if
+/// one is set, or else the parallelism detected.
+pub fn get_effective_parallelism() -> usize {
+    match OVERRIDE.load(Ordering::SeqCst) {
+        0 => DETECTED.effective(),
+        threads => threads,
+    }
+}
+
+/// Overrides the parallelism detected with threads, or clears the override if
+/// threads is 0. Pools already started keep their size: the global rayon pool
+/// is sized once, by the first SectorBuilder, and the task pool when its
+/// first task runs.
+pub fn set_parallelism_override(threads: usize) {
+    OVERRIDE.store(threads, Ordering::SeqCst);
+}
+
+/// Sizes the global rayon pool by the effective parallelism, logging it and
+/// what it was detected from. Only the first call does anything. If rayon has
+/// already started its pool, it keeps its size, and a warning is logged.
+pub fn size_global_pool() {
+    GLOBAL_POOL.call_once(|| {
+        let detected = detected_parallelism();
+        let threads = get_effective_parallelism();
+
+        info!(FCP_LOG, "sizing thread pools";
+            "effective" => threads,
+            "available" => detected.available,
+            "quota" => format!("{:?}", detected.quota),
+            "cpuset" => format!("{:?}", detected.cpuset));
+
+        let built = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global();
+
+        if let Err(err) = built {
+            warn!(FCP_LOG, "the global rayon pool had already started";
+                "threads" => rayon::current_num_threads(),
+                "error" => format!("{}", err));
+        }
+    });
+}
+
+// The CPUs of a cgroup v2 cpu.max, "<quota> <period>" in microseconds, where a
+// quota of "max" is none.
+fn parse_cpu_max(cpu_max: &str) -> Option<usize> {
+    let mut fields = cpu_max.split_whitespace();
+    let quota = fields.next()?;
+    let period = fields.next()?;
+
+    if quota == "max" {
+        None
+    } else {
+        quota_cpus(quota.parse().ok()?, period.parse().ok()?)
+    }
+}
+
+// The CPUs of a cgroup v1 CFS quota and period, in microseconds, where a
+// negative quota is none.
+fn parse_cfs_quota(quota: &str, period: &str) -> Option<usize> {
+    let quota: i64 = quota.trim().parse().ok()?;
+
+    if quota < 0 {
+        None
+    } else {
+        quota_cpus(quota as u64, period.trim().parse().ok()?)
+    }
+}
+
+fn quota_cpus(quota: u64, period: u64) -> Option<usize> {
+    if period == 0 {
+        return None;
+    }
+
+    Some(cmp::max(1, (quota + period - 1) / period) as usize)
+}
+
+// The number of CPUs in a cpuset list, e.g. "0-3,8,10-11" (7), or None if it's
+// empty or malformed.
+fn count_cpu_list(list: &str) -> Option<usize> {
+    let mut count = 0;
+
+    for range in list.trim().split(',').filter(|range| !range.is_empty()) {
+        let mut bounds = range.splitn(2, '-');
+        let first: usize = bounds.next()?.parse().ok()?;
+        let last: usize = match bounds.next() {
+            Some(last) => last.parse().ok()?,
+            None => first,
+        };
+
+        if last < first {
+            return None;
+        }
+        count += last - first + 1;
+    }
+
+    if count > 0 {
+        Some(count)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    struct FakeCgroupFs(HashMap<&'static str, &'static str>);
+
+    impl CgroupFs for FakeCgroupFs {
+        fn read(&self, path: &str) -> Option<String> {
+            self.0.get(path).map(|contents| contents.to_string())
+        }
+    }
+
+    fn detect(files: &[(&'static str, &'static str)]) -> DetectedParallelism {
+        let fs = FakeCgroupFs(files.iter().cloned().collect());
+
+        DetectedParallelism::detect(&fs, 64)
+    }
+
+    #[test]
+    fn without_cgroup_limits_every_cpu_is_used() {
+        let detected = detect(&[]);
+        assert_eq!((None, None), (detected.quota, detected.cpuset));
+        assert_eq!(64, detected.effective());
+
+        let unlimited = detect(&[
+            (CGROUP_V2_CPU_MAX, "max 100000\n"),
+            (CGROUP_V2_CPUSET, "\n"),
+        ]);
+        assert_eq!(64, unlimited.effective());
+
+        let unlimited = detect(&[
+            (CGROUP_V1_CPU_QUOTA, "-1\n"),
+            (CGROUP_V1_CPU_PERIOD, "100000\n"),
+        ]);
+        assert_eq!(64, unlimited.effective());
+    }
+
+    #[test]
+    fn a_cpu_quota_limits_parallelism() {
+        let v2 = detect(&[(CGROUP_V2_CPU_MAX, "400000 100000\n")]);
+        assert_eq!(Some(4), v2.quota);
+        assert_eq!(4, v2.effective());
+
+        // Part of a CPU's time still takes a thread.
+        let v1 = detect(&[
+            (CGROUP_V1_CPU_QUOTA, "250000\n"),
+            (CGROUP_V1_CPU_PERIOD, "100000\n"),
+        ]);
+        assert_eq!(Some(3), v1.quota);
+
+        let sliver = detect(&[(CGROUP_V2_CPU_MAX, "1000 100000\n")]);
+        assert_eq!(1, sliver.effective());
+    }
+
+    #[test]
+    fn a_cpuset_limits_parallelism() {
+        let v2 = detect(&[(CGROUP_V2_CPUSET, "0-3,8,10-11\n")]);
+        assert_eq!(Some(7), v2.cpuset);
+        assert_eq!(7, v2.effective());
+
+        let v1 = detect(&[(CGROUP_V1_CPUSET, "5\n")]);
+        assert_eq!(1, v1.effective());
+
+        assert_eq!(None, detect(&[(CGROUP_V1_CPUSET, "3-1\n")]).cpuset);
+    }
+
+    #[test]
+    fn the_tightest_limit_wins() {
+        let detected = detect(&[
+            (CGROUP_V2_CPU_MAX, "800000 100000\n"),
+            (CGROUP_V2_CPUSET, "0-5\n"),
+        ]);
+        assert_eq!(6, detected.effective());
+
+        let few_cpus = DetectedParallelism {
+            available: 2,
+            ..detected
+        };
+        assert_eq!(2, few_cpus.effective());
+    }
+}
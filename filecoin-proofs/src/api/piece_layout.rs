@@ -0,0 +1,254 @@
+//! Computes the comm_d of a staged sector from its pieces' comm_ps and sizes
+//! alone, without reading the sector, for sectors whose pieces are laid out so
+//! that each is a subtree of the sector's tree.
+//!
+//! A piece's comm_p is the root of a tree over the piece, preprocessed on its
+//! own and zero-padded to a power-of-two number of nodes. That tree is a
+//! subtree of a sector's when the piece starts on a node whose index is a
+//! multiple of the tree's number of nodes, and when nothing but zeroes follows
+//! the piece up to the tree's end. As 127 bytes preprocess to exactly four
+//! nodes, a piece only starts on a node if that node's index is a multiple of
+//! four. So, in the aligned layout, each piece takes up a slot of as many nodes
+//! as its tree spans (at least four), starting at the first node past the
+//! previous piece's slot which is a multiple of its own slot's size, and the
+//! bytes between pieces are zero.
+//!
+//! add_piece packs pieces one after another instead, which only lays them out
+//! so when every piece but the last fills its slot, and falls on it. Staging
+//! every sector in the aligned layout would let a sector's comm_d always be
+//! checked against the comm_ps of the pieces it claims to hold, but changes
+//! where pieces are in staged and sealed sectors, and so what every reader of
+//! their bytes assumes.
+//!
+//! internal::verify_seal_with_pieces checks a sealed sector's comm_d against
+//! its pieces for sectors add_piece happened to lay out so.
+
+use sector_base::io::fr32::{padded_bytes, unpadded_bytes};
+use sector_base::io::trailer::{data_padded_bytes, encode_trailer};
+use storage_proofs::hasher::pedersen::{PedersenDomain, PedersenFunction};
+use storage_proofs::hasher::Domain;
+use storage_proofs::merkle::IncrementalRoot;
+use storage_proofs::types::{commitment_from_slice, Commitment};
+use storage_proofs::util::NODE_SIZE;
+
+use crate::error;
+
+// 127 bytes preprocess to exactly four nodes.
+const CHUNK_BYTES: u64 = 127;
+const CHUNK_NODES: u64 = 4;
+
+/// Why a sector's comm_d couldn't be checked against its pieces, or didn't
+/// match them.
+#[derive(Debug, Fail, PartialEq)]
+pub enum PieceLayoutErr {
+    #[fail(display = "pieces of {:?} bytes aren't in the aligned layout", _0)]
+    NotAligned(Vec<u64>),
+
+    #[fail(display = "comm_d isn't the commitment to the given pieces")]
+    CommDMismatch,
+}
+
+/// Where each of the pieces, given by their lengths in order, starts in a
+/// sector in the aligned layout, as an offset into its unpadded bytes.
+pub fn aligned_piece_offsets(piece_lengths: &[u64]) -> Vec<u64> {
+    slots(piece_lengths)
+        .map(|(first_node, _)| first_node / CHUNK_NODES * CHUNK_BYTES)
+        .collect()
+}
+
+/// Whether pieces of the given lengths, packed one after another as add_piece
+/// packs them into a sector of sector_bytes (padded) bytes, are laid out in the
+/// aligned layout, with every slot before the sector's trailer.
+pub fn packed_layout_is_aligned(sector_bytes: usize, piece_lengths: &[u64]) -> bool {
+    let packed_offsets = piece_lengths.iter().scan(0, |offset, length| {
+        let piece_offset = *offset;
+        *offset += length;
+        Some(piece_offset)
+    });
+    let slots_end = slots(piece_lengths)
+        .last()
+        .map_or(0, |(first, nodes)| first + nodes);
+
+    slots_end <= data_nodes(sector_bytes) && packed_offsets.eq(aligned_piece_offsets(piece_lengths))
+}
+
+/// Computes the comm_d of a sector of sector_bytes (padded) bytes holding the
+/// given pieces, each a comm_p and a length, in the aligned layout, as
+/// internal::compute_comm_d would compute it from the sector's staged data.
+/// Fails if the pieces don't fit before the sector's trailer, or if a comm_p
+/// isn't a field element.
+pub fn compute_comm_d_from_pieces(
+    sector_bytes: usize,
+    pieces: &[(Commitment, u64)],
+) -> error::Result<Commitment> {
+    if sector_bytes % NODE_SIZE != 0 || sector_bytes < 2 * NODE_SIZE {
+        return Err(format_err!("invalid sector size: {}", sector_bytes));
+    }
+
+    let data_nodes = data_nodes(sector_bytes);
+    let zero = PedersenDomain::try_from_bytes(&[0; NODE_SIZE])?;
+
+    let mut root = IncrementalRoot::<PedersenDomain, PedersenFunction>::new();
+    let mut data_bytes = 0;
+
+    let lengths: Vec<u64> = pieces.iter().map(|&(_, length)| length).collect();
+    for (&(comm_p, length), (first_node, slot_nodes)) in pieces.iter().zip(slots(&lengths)) {
+        if first_node + slot_nodes > data_nodes {
+            return Err(format_err!(
+                "pieces of {:?} bytes don't fit in the sector",
+                lengths
+            ));
+        }
+
+        root.push_copies(zero, (first_node - root.leafs() as u64) as usize);
+
+        // Past what the piece's tree spans, its slot holds zeroes.
+        let tree_nodes = piece_tree_nodes(length);
+        let height = tree_nodes.trailing_zeros() as usize;
+        root.push_subtree(height, PedersenDomain::try_from_bytes(&comm_p)?);
+        root.push_copies(zero, (slot_nodes - tree_nodes) as usize);
+
+        data_bytes = first_node / CHUNK_NODES * CHUNK_BYTES + length;
+    }
+
+    root.push_copies(zero, (data_nodes - root.leafs() as u64) as usize);
+
+    // The trailer records the bytes staged, as compute_comm_d does.
+    let staged_bytes = padded_bytes(data_bytes as usize) as u64;
+    let trailer = encode_trailer(unpadded_bytes(staged_bytes));
+    root.push(PedersenDomain::try_from_bytes(&trailer)?);
+
+    Ok(commitment_from_slice(&root.unpadded_root().into_bytes()))
+}
+
+// The number of nodes of a sector of sector_bytes (padded) bytes before its
+// trailer.
+fn data_nodes(sector_bytes: usize) -> u64 {
+    data_padded_bytes(sector_bytes as u64) / NODE_SIZE as u64
+}
+
+// The number of nodes a piece's tree spans: its preprocessed nodes,
+// zero-padded to a power of two (at least two).
+fn piece_tree_nodes(length: u64) -> u64 {
+    let nodes = (padded_bytes(length as usize) + NODE_SIZE - 1) / NODE_SIZE;
+
+    (nodes as u64).next_power_of_two().max(2)
+}
+
+// The first node and the number of nodes of each piece's slot in the aligned
+// layout.
+fn slots<'a>(piece_lengths: &'a [u64]) -> impl Iterator<Item = (u64, u64)> + 'a {
+    piece_lengths.iter().scan(0, |next_node, &length| {
+        let slot_nodes = piece_tree_nodes(length).max(CHUNK_NODES);
+        let first_node = (*next_node + slot_nodes - 1) / slot_nodes * slot_nodes;
+        *next_node = first_node + slot_nodes;
+
+        Some((first_node, slot_nodes))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::api::internal::compute_comm_d;
+    use crate::api::sector_builder::helpers::staged_data::compute_comm_p;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use sector_base::io::fr32::pack_user_bytes;
+
+    const SECTOR_BYTES: usize = 4096;
+
+    fn random_pieces(lengths: &[u64]) -> Vec<Vec<u8>> {
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        lengths
+            .iter()
+            .map(|&length| (0..length).map(|_| rng.gen()).collect())
+            .collect()
+    }
+
+    // The comm_d of the pieces laid out at the given offsets, computed from
+    // the sector's staged data.
+    fn comm_d_of_staged(pieces: &[Vec<u8>], offsets: &[u64]) -> Commitment {
+        let mut staged = Vec::new();
+        for (piece, &offset) in pieces.iter().zip(offsets) {
+            staged.resize(offset as usize, 0);
+            staged.extend_from_slice(piece);
+        }
+
+        compute_comm_d(pack_user_bytes(&staged).as_bytes(), SECTOR_BYTES).unwrap()
+    }
+
+    fn with_comm_ps(pieces: &[Vec<u8>]) -> Vec<(Commitment, u64)> {
+        pieces
+            .iter()
+            .map(|piece| (compute_comm_p(piece).unwrap(), piece.len() as u64))
+            .collect()
+    }
+
+    #[test]
+    fn slots_are_aligned_to_their_size() {
+        // A slot takes at least four nodes, and a piece of 127 bytes fills it.
+        assert_eq!(vec![0, 127, 254], aligned_piece_offsets(&[1, 127, 20]));
+        assert_eq!(vec![0, 508, 1016], aligned_piece_offsets(&[100, 300, 127]));
+        assert_eq!(vec![0, 254, 381], aligned_piece_offsets(&[254, 127, 5]));
+        assert_eq!(Vec::<u64>::new(), aligned_piece_offsets(&[]));
+
+        assert!(packed_layout_is_aligned(SECTOR_BYTES, &[]));
+        assert!(packed_layout_is_aligned(SECTOR_BYTES, &[300]));
+        assert!(packed_layout_is_aligned(SECTOR_BYTES, &[254, 127, 5]));
+        assert!(!packed_layout_is_aligned(SECTOR_BYTES, &[127, 254]));
+        assert!(!packed_layout_is_aligned(SECTOR_BYTES, &[1, 127]));
+
+        // A piece's slot can't take in the sector's trailer.
+        assert!(packed_layout_is_aligned(SECTOR_BYTES, &[2000]));
+        assert!(!packed_layout_is_aligned(SECTOR_BYTES, &[3000]));
+    }
+
+    #[test]
+    fn comm_d_from_pieces_matches_their_staged_data() {
+        let cases: Vec<&[u64]> = vec![
+            &[],
+            &[1],
+            &[127],
+            &[300],
+            &[254, 127, 5],
+            &[20, 500, 127, 3],
+            &[1000, 1, 1],
+        ];
+
+        for lengths in cases {
+            let pieces = random_pieces(lengths);
+            let offsets = aligned_piece_offsets(lengths);
+
+            assert_eq!(
+                comm_d_of_staged(&pieces, &offsets),
+                compute_comm_d_from_pieces(SECTOR_BYTES, &with_comm_ps(&pieces)).unwrap(),
+                "pieces of {:?} bytes",
+                lengths
+            );
+        }
+    }
+
+    #[test]
+    fn comm_d_depends_on_the_order_of_pieces() {
+        let pieces = with_comm_ps(&random_pieces(&[127, 127]));
+        let swapped = vec![pieces[1], pieces[0]];
+
+        assert_ne!(
+            compute_comm_d_from_pieces(SECTOR_BYTES, &pieces).unwrap(),
+            compute_comm_d_from_pieces(SECTOR_BYTES, &swapped).unwrap()
+        );
+    }
+
+    #[test]
+    fn pieces_must_fit_before_the_trailer() {
+        // A sector of 128 nodes has room for one slot of 64 nodes, as its last
+        // node is its trailer.
+        let pieces = with_comm_ps(&random_pieces(&[2000, 2000]));
+        assert!(compute_comm_d_from_pieces(SECTOR_BYTES, &pieces[1..]).is_ok());
+        assert!(compute_comm_d_from_pieces(SECTOR_BYTES, &pieces).is_err());
+
+        assert!(compute_comm_d_from_pieces(SECTOR_BYTES + 1, &[]).is_err());
+    }
+}
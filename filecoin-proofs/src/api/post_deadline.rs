@@ -0,0 +1,283 @@
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+use crate::error;
+
+/// The number of most recent per-sector timings averaged by the estimator.
+const ESTIMATOR_WINDOW: usize = 8;
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "PoSt would exceed its deadline: {} sectors completed, {} remaining",
+    completed_sectors, remaining
+)]
+pub struct DeadlineExceeded {
+    pub completed_sectors: usize,
+    pub remaining: usize,
+}
+
+/// Estimates the time needed to prove the remaining sectors from a rolling
+/// average of the most recently observed per-sector proving times.
+#[derive(Debug, Clone)]
+pub struct RollingAverage {
+    window: usize,
+    samples: VecDeque<Duration>,
+}
+
+impl RollingAverage {
+    pub fn new(window: usize) -> RollingAverage {
+        assert!(window > 0, "window must be non-zero");
+
+        RollingAverage {
+            window,
+            samples: VecDeque::with_capacity(window),
+        }
+    }
+
+    pub fn record(&mut self, sample: Duration) {
+        if self.samples.len() == self.window {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(sample);
+    }
+
+    /// Returns None until at least one sample has been recorded.
+    pub fn average(&self) -> Option<Duration> {
+        if self.samples.is_empty() {
+            return None;
+        }
+
+        let total: Duration = self.samples.iter().sum();
+
+        Some(total / self.samples.len() as u32)
+    }
+
+    /// Estimated time to prove `remaining` more sectors.
+    pub fn estimate(&self, remaining: usize) -> Duration {
+        self.average().unwrap_or_default() * remaining as u32
+    }
+}
+
+/// Per-sector partial results of PoSt generation, keyed by replica
+/// commitment. Passing the same checkpoint to a retried call skips every
+/// sector which has already been proven.
+pub struct PoStCheckpoint<T> {
+    completed: HashMap<[u8; 32], T>,
+    estimator: RollingAverage,
+}
+
+impl<T> Default for PoStCheckpoint<T> {
+    fn default() -> PoStCheckpoint<T> {
+        PoStCheckpoint {
+            completed: HashMap::new(),
+            estimator: RollingAverage::new(ESTIMATOR_WINDOW),
+        }
+    }
+}
+
+impl<T> PoStCheckpoint<T> {
+    pub fn get(&self, comm_r: &[u8; 32]) -> Option<&T> {
+        self.completed.get(comm_r)
+    }
+
+    pub fn contains(&self, comm_r: &[u8; 32]) -> bool {
+        self.completed.contains_key(comm_r)
+    }
+
+    pub fn len(&self) -> usize {
+        self.completed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.completed.is_empty()
+    }
+}
+
+/// Runs `prove_sector` for each replica commitment which has no result in
+/// `checkpoint`, storing each result as it completes. After every sector, the
+/// time elapsed since the call began plus the estimated time for the sectors
+/// still to be proven is compared to `deadline`; once it exceeds the deadline
+/// a DeadlineExceeded error is returned, leaving the completed sectors in the
+/// checkpoint.
+pub fn prove_sectors_with_deadline<T, F>(
+    comm_rs: &[[u8; 32]],
+    deadline: Duration,
+    checkpoint: &mut PoStCheckpoint<T>,
+    mut prove_sector: F,
+) -> error::Result<()>
+where
+    F: FnMut(usize) -> error::Result<T>,
+{
+    let start = Instant::now();
+
+    let pending = |checkpoint: &PoStCheckpoint<T>| {
+        comm_rs.iter().filter(|c| !checkpoint.contains(c)).count()
+    };
+
+    for (i, comm_r) in comm_rs.iter().enumerate() {
+        if checkpoint.contains(comm_r) {
+            continue;
+        }
+
+        let sector_start = Instant::now();
+        let result = prove_sector(i)?;
+        checkpoint.estimator.record(sector_start.elapsed());
+        checkpoint.completed.insert(*comm_r, result);
+
+        let remaining = pending(checkpoint);
+
+        if remaining > 0 && start.elapsed() + checkpoint.estimator.estimate(remaining) > deadline {
+            return Err(DeadlineExceeded {
+                completed_sectors: comm_rs.len() - remaining,
+                remaining,
+            }
+            .into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn comm_rs(n: u8) -> Vec<[u8; 32]> {
+        (0..n).map(|i| [i; 32]).collect()
+    }
+
+    #[test]
+    fn rolling_average_uses_most_recent_samples() {
+        let mut avg = RollingAverage::new(3);
+        assert_eq!(None, avg.average());
+        assert_eq!(Duration::from_millis(0), avg.estimate(10));
+
+        avg.record(Duration::from_millis(10));
+        avg.record(Duration::from_millis(20));
+        avg.record(Duration::from_millis(30));
+        assert_eq!(Some(Duration::from_millis(20)), avg.average());
+
+        // the 10ms sample falls out of the window
+        avg.record(Duration::from_millis(70));
+        assert_eq!(Some(Duration::from_millis(40)), avg.average());
+        assert_eq!(Duration::from_millis(200), avg.estimate(5));
+    }
+
+    #[test]
+    fn deadline_exceeded_then_resumed_from_checkpoint() {
+        let comm_rs = comm_rs(6);
+        let delay = Duration::from_millis(20);
+        let mut proven = vec![0; comm_rs.len()];
+        let mut checkpoint = PoStCheckpoint::default();
+
+        let err = prove_sectors_with_deadline(
+            &comm_rs,
+            Duration::from_millis(50),
+            &mut checkpoint,
+            |i| {
+                thread::sleep(delay);
+                proven[i] += 1;
+                Ok(i)
+            },
+        )
+        .expect_err("deadline should have been exceeded");
+
+        let err = err
+            .downcast::<DeadlineExceeded>()
+            .expect("expected DeadlineExceeded");
+
+        // After the first sector, 20ms + 5 * 20ms is already past 50ms.
+        assert_eq!(1, err.completed_sectors);
+        assert_eq!(5, err.remaining);
+        assert_eq!(1, checkpoint.len());
+
+        prove_sectors_with_deadline(
+            &comm_rs,
+            Duration::from_secs(60),
+            &mut checkpoint,
+            |i| {
+                thread::sleep(delay);
+                proven[i] += 1;
+                Ok(i)
+            },
+        )
+        .expect("resumed call should complete");
+
+        // Every sector was proven exactly once across both calls.
+        assert_eq!(vec![1; comm_rs.len()], proven);
+
+        for (i, comm_r) in comm_rs.iter().enumerate() {
+            assert_eq!(Some(&i), checkpoint.get(comm_r));
+        }
+    }
+
+    #[test]
+    fn slow_sectors_skipped_after_deadline() {
+        // Sectors 2 and 3 are much slower than the rest; once one of them is
+        // observed the estimate crosses the deadline.
+        let comm_rs = comm_rs(5);
+        let delays = [5, 5, 200, 200, 5];
+        let mut proven = vec![0; comm_rs.len()];
+        let mut checkpoint = PoStCheckpoint::default();
+
+        let err = prove_sectors_with_deadline(
+            &comm_rs,
+            Duration::from_millis(300),
+            &mut checkpoint,
+            |i| {
+                thread::sleep(Duration::from_millis(delays[i]));
+                proven[i] += 1;
+                Ok(())
+            },
+        )
+        .expect_err("deadline should have been exceeded")
+        .downcast::<DeadlineExceeded>()
+        .expect("expected DeadlineExceeded");
+
+        assert_eq!(3, err.completed_sectors);
+        assert_eq!(2, err.remaining);
+        assert_eq!(vec![1, 1, 1, 0, 0], proven);
+
+        // Retry without the remaining slow sector (e.g. after declaring it
+        // faulty); nothing already proven is proven again.
+        let without_faulty: Vec<[u8; 32]> = vec![comm_rs[0], comm_rs[1], comm_rs[2], comm_rs[4]];
+        let indices = [0, 1, 2, 4];
+
+        prove_sectors_with_deadline(
+            &without_faulty,
+            Duration::from_millis(300),
+            &mut checkpoint,
+            |i| {
+                proven[indices[i]] += 1;
+                Ok(())
+            },
+        )
+        .expect("retry should complete");
+
+        assert_eq!(vec![1, 1, 1, 0, 1], proven);
+    }
+
+    #[test]
+    fn errors_from_sector_are_propagated() {
+        let comm_rs = comm_rs(3);
+        let mut checkpoint = PoStCheckpoint::<()>::default();
+
+        let err = prove_sectors_with_deadline(
+            &comm_rs,
+            Duration::from_secs(60),
+            &mut checkpoint,
+            |i| {
+                if i == 1 {
+                    Err(format_err!("could not read sector {}", i))
+                } else {
+                    Ok(())
+                }
+            },
+        )
+        .expect_err("expected error from sector 1");
+
+        assert!(err.downcast_ref::<DeadlineExceeded>().is_none());
+        assert_eq!(1, checkpoint.len());
+    }
+}
@@ -0,0 +1,88 @@
+use std::io::Write;
+
+use storage_proofs::post_timing::PostTimingBreakdown;
+use storage_proofs::types::Commitment;
+
+use crate::api::internal::{
+    generate_post_instrumented, verify_post_instrumented, PoStInput, PoStInputPart,
+};
+use crate::api::replica_format::LegacyReplicas;
+use crate::error;
+
+/// A sealed sector proven over in a sweep.
+#[derive(Debug, Clone)]
+pub struct SweptSector {
+    pub sealed_sector_access: String,
+    pub comm_r: Commitment,
+}
+
+/// The breakdowns of the time taken to generate and verify a PoSt with
+/// challenge_count challenges per epoch.
+#[derive(Debug, Clone)]
+pub struct SweepResult {
+    pub challenge_count: usize,
+    pub generation: PostTimingBreakdown,
+    pub verification: PostTimingBreakdown,
+}
+
+/// The columns of the CSV written by sweep_challenge_counts.
+pub fn sweep_csv_header() -> String {
+    format!(
+        "challenge_count,operation,{}",
+        PostTimingBreakdown::CSV_HEADER
+    )
+}
+
+/// Generates and verifies a PoSt over sectors with each of challenge_counts
+/// challenges per epoch, writing a header and then a line of CSV for each
+/// generation and verification to out. Each PoSt is generated once before it's
+/// timed, so that generating and loading its parameters isn't counted.
+pub fn sweep_challenge_counts<W: Write>(
+    sector_bytes: u64,
+    sectors: &[SweptSector],
+    challenge_seed: [u8; 32],
+    challenge_counts: &[usize],
+    out: &mut W,
+) -> error::Result<Vec<SweepResult>> {
+    let input = || PoStInput {
+        challenge_seed,
+        input_parts: sectors
+            .iter()
+            .map(|sector| PoStInputPart {
+                sealed_sector_access: Some(sector.sealed_sector_access.clone()),
+                comm_r: sector.comm_r,
+                legacy_replicas: LegacyReplicas::Refuse,
+            })
+            .collect(),
+    };
+    let comm_rs: Vec<Commitment> = sectors.iter().map(|sector| sector.comm_r).collect();
+
+    writeln!(out, "{}", sweep_csv_header())?;
+
+    let mut results = Vec::with_capacity(challenge_counts.len());
+    for &challenge_count in challenge_counts {
+        generate_post_instrumented(sector_bytes, challenge_count, input())?;
+
+        let (output, generation) =
+            generate_post_instrumented(sector_bytes, challenge_count, input())?;
+        let (_, verification) = verify_post_instrumented(
+            sector_bytes,
+            challenge_count,
+            &comm_rs,
+            &challenge_seed,
+            &output.snark_proof,
+            output.faults,
+        )?;
+
+        writeln!(out, "{},generate,{}", challenge_count, generation.csv_row())?;
+        writeln!(out, "{},verify,{}", challenge_count, verification.csv_row())?;
+
+        results.push(SweepResult {
+            challenge_count,
+            generation,
+            verification,
+        });
+    }
+
+    Ok(results)
+}
@@ -0,0 +1,136 @@
+use std::time::{Duration, Instant};
+
+use sector_base::api::sector_store::{ProofVariant, SectorStore};
+use storage_proofs::parameter_cache::ParameterPhase;
+
+use crate::api::cancellation::CancellationToken;
+use crate::api::internal::{generate_zigzag_params_with_cancellation, warm_seal_verifier};
+use crate::error;
+use crate::FCP_LOG;
+
+/// How long a step of prefetching took, and whether it found what it prepares
+/// ready already.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrewarmStep {
+    pub elapsed: Duration,
+    pub cache_hit: bool,
+}
+
+/// What prefetch_seal_requirements did, step by step.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PrewarmReport {
+    /// Creating the staging and sealed directories, and checking that they may
+    /// be written to. A hit if both existed.
+    pub directories: PrewarmStep,
+    /// Reading the groth parameters, or generating them if they weren't
+    /// cached. Stores proven with vanilla proofs need none, and always hit.
+    pub parameters: PrewarmStep,
+    /// Building the zigzag graph (and its Feistel precomputation) and, for
+    /// SNARK proofs, preparing the verifying key, which seals share.
+    pub graph: PrewarmStep,
+}
+
+impl PrewarmReport {
+    pub fn all_cache_hits(&self) -> bool {
+        self.directories.cache_hit && self.parameters.cache_hit && self.graph.cache_hit
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.directories.elapsed + self.parameters.elapsed + self.graph.elapsed
+    }
+}
+
+/// Prepares everything sealing a first sector in `sector_store` takes besides
+/// its data, so that the seal only does the work of that sector, and so that
+/// the preparation can be scheduled (or used as a health check) on its own.
+///
+/// Safe to call concurrently, from threads or processes: parameters are
+/// generated under the parameter cache's lock, once. Calling it again once it
+/// has succeeded finds everything ready.
+pub fn prefetch_seal_requirements(sector_store: &SectorStore) -> error::Result<PrewarmReport> {
+    prefetch_seal_requirements_with_cancellation(sector_store, &CancellationToken::new())
+}
+
+/// Prefetches as prefetch_seal_requirements does, but checks token between
+/// the steps, and as the parameters are generated (see
+/// generate_zigzag_params_with_cancellation), failing once it has tripped.
+/// Whatever steps finished stay done.
+pub fn prefetch_seal_requirements_with_cancellation(
+    sector_store: &SectorStore,
+    token: &CancellationToken,
+) -> error::Result<PrewarmReport> {
+    let sector_bytes = sector_store.config().sector_bytes();
+    let proof_variant = sector_store.config().proof_variant();
+    let proof_version = sector_store.config().proof_version();
+
+    token.checkpoint("preparing directories")?;
+    let directories = timed(|| Ok(!sector_store.manager().prepare_dirs()?))?;
+
+    let parameters = match proof_variant {
+        ProofVariant::Snark => timed(|| {
+            let mut generated = false;
+            let mut progress =
+                |phase: ParameterPhase, _: f64| generated |= phase == ParameterPhase::Generating;
+            generate_zigzag_params_with_cancellation(sector_bytes, &mut progress, token)?;
+
+            Ok(!generated)
+        })?,
+        ProofVariant::Vanilla => PrewarmStep {
+            elapsed: Duration::default(),
+            cache_hit: true,
+        },
+    };
+
+    token.checkpoint("building the graph")?;
+    let graph = timed(|| warm_seal_verifier(sector_bytes as usize, proof_variant, proof_version))?;
+
+    let report = PrewarmReport {
+        directories,
+        parameters,
+        graph,
+    };
+
+    info!(FCP_LOG, "prefetched seal requirements"; "report" => format!("{:?}", report));
+
+    Ok(report)
+}
+
+// Runs a step which reports whether it hit a cache, timing it.
+fn timed<F: FnOnce() -> error::Result<bool>>(step: F) -> error::Result<PrewarmStep> {
+    let start = Instant::now();
+    let cache_hit = step()?;
+
+    Ok(PrewarmStep {
+        elapsed: start.elapsed(),
+        cache_hit,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sector_base::api::disk_backed_storage::{
+        new_sector_store_with_proof_variant, ConfiguredStore,
+    };
+    use tempfile;
+
+    #[test]
+    fn prefetching_again_hits_every_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+
+        // Vanilla proofs need no groth parameters, so this doesn't generate any.
+        let store = new_sector_store_with_proof_variant(
+            &ConfiguredStore::Test,
+            path("sealed"),
+            path("staging"),
+            ProofVariant::Vanilla,
+        );
+
+        let first = prefetch_seal_requirements(&store).expect("failed to prefetch");
+        assert!(!first.directories.cache_hit);
+
+        let second = prefetch_seal_requirements(&store).expect("failed to prefetch");
+        assert!(second.all_cache_hits(), "{:?}", second);
+    }
+}
@@ -0,0 +1,339 @@
+//! The protocol's parameter sets, one per ProofVersion. Sealing and verifying
+//! look the parameters up by the version a sector's config names (and a
+//! proof's envelope records), so that adopting new parameters is a matter of
+//! adding a set and a version, and the sectors and proofs made with the old
+//! ones can still be read and verified.
+//!
+//! The sets are pinned by a test: a set must never change once its version has
+//! been used to seal sectors.
+//!
+//! Every set's lambda is NODE_SIZE, the one node size storage-proofs supports.
+//! Setup params are only made of a set whose lambda is, for sectors holding a
+//! whole number of its nodes, so that no other node size reaches the code
+//! computing offsets with NODE_SIZE.
+
+use storage_proofs::crypto::feistel::FeistelConfig;
+use storage_proofs::drgporep::{self, DrgParams};
+use storage_proofs::drgraph::DefaultTreeHasher;
+use storage_proofs::layered_drgporep::{self, LayerChallenges};
+use storage_proofs::util::{node_size_matches, NODE_SIZE};
+
+pub use sector_base::api::sector_store::ProofVersion;
+
+use crate::api::replica_format::PEDERSEN_HASHER_ID;
+
+/// Arbitrary, need a theory for how to vary this over time.
+pub const DRG_SEED: [u32; 7] = [1, 2, 3, 4, 5, 6, 7];
+
+/// The versions whose proofs this build verifies, oldest first.
+pub const ALLOWED_VERSIONS: [ProofVersion; 3] = [
+    ProofVersion::V0Test,
+    ProofVersion::V1Alpha,
+    ProofVersion::Mini,
+];
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum ProofParamsErr {
+    #[fail(
+        display = "{:?} parameters have {}-byte nodes, but only {}-byte nodes are supported",
+        version, lambda, supported
+    )]
+    UnsupportedLambda {
+        version: ProofVersion,
+        lambda: usize,
+        supported: usize,
+    },
+
+    #[fail(
+        display = "sector_bytes ({}) must be a multiple of {}",
+        sector_bytes, lambda
+    )]
+    SectorBytesNotMultiple { sector_bytes: usize, lambda: usize },
+}
+
+/// What a seal is made and proven with, besides the sector's size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ProofParams {
+    pub version: ProofVersion,
+    /// The bytes in each node of a replica, which must be NODE_SIZE.
+    pub lambda: usize,
+    pub degree: usize,
+    pub expansion_degree: usize,
+    pub sloth_iter: usize,
+    pub layers: usize,
+    /// The number of layers, counting back from the last, whose challenges
+    /// taper, each layer proving taper fewer than the one after it.
+    pub taper_layers: usize,
+    pub taper: f64,
+    /// The number of challenges proven per partition, by sector size: a sector
+    /// gets the count of the last entry whose size it reaches.
+    pub challenge_count_policy: &'static [(u64, usize)],
+    /// The hasher the replica's trees are built with, as recorded in the
+    /// replica's format trailer.
+    pub hasher: u8,
+}
+
+/// Tiny placeholder parameters, cheap enough to seal with in tests.
+pub const V0_TEST: ProofParams = ProofParams {
+    version: ProofVersion::V0Test,
+    lambda: NODE_SIZE,
+    degree: 1,
+    expansion_degree: 2,
+    sloth_iter: 0,
+    layers: 2,
+    taper_layers: 1,
+    taper: 1.0 / 3.0,
+    challenge_count_policy: &[(0, 2)],
+    hasher: PEDERSEN_HASHER_ID,
+};
+
+/// The parameters of the alpha network.
+///
+/// | Sector size   | Challenges |
+/// |---------------|------------|
+/// | from 0        | 2          |
+/// | from 1GiB     | 4          |
+/// | from 32GiB    | 8          |
+pub const V1_ALPHA: ProofParams = ProofParams {
+    version: ProofVersion::V1Alpha,
+    lambda: NODE_SIZE,
+    degree: 5,
+    expansion_degree: 8,
+    sloth_iter: 0,
+    layers: 4,       // TODO: 10
+    taper_layers: 2, // TODO: 7
+    taper: 1.0 / 3.0,
+    challenge_count_policy: &[(0, 2), (1 << 30, 4), (1 << 35, 8)],
+    hasher: PEDERSEN_HASHER_ID,
+};
+
+/// The smallest parameters whose seals are still real ones: two layers, with
+/// V1Alpha's degrees, of sectors of (at least) 2KiB, whose graphs degenerate
+/// no further. Sealing, verifying and unsealing a 2KiB sector with vanilla
+/// proofs takes well under a second, so CI runs them on every commit.
+///
+/// The trees are built with the Pedersen hasher, as with every version: the
+/// replica's trees and the circuits all use the same one.
+pub const MINI: ProofParams = ProofParams {
+    version: ProofVersion::Mini,
+    lambda: NODE_SIZE,
+    degree: 5,
+    expansion_degree: 8,
+    sloth_iter: 0,
+    layers: 2,
+    taper_layers: 1,
+    taper: 1.0 / 3.0,
+    challenge_count_policy: &[(0, 2)],
+    hasher: PEDERSEN_HASHER_ID,
+};
+
+/// The parameters of the given version.
+pub fn proof_params(version: ProofVersion) -> &'static ProofParams {
+    match version {
+        ProofVersion::V0Test => &V0_TEST,
+        ProofVersion::V1Alpha => &V1_ALPHA,
+        ProofVersion::Mini => &MINI,
+    }
+}
+
+impl ProofParams {
+    /// Returns the number of challenges proven per partition when sealing a
+    /// sector of sector_bytes bytes, as given by the challenge count policy.
+    pub fn challenge_count(&self, sector_bytes: u64) -> usize {
+        self.challenge_count_policy
+            .iter()
+            .rev()
+            .find(|(min_sector_bytes, _)| sector_bytes >= *min_sector_bytes)
+            .map(|(_, count)| *count)
+            .expect("policy covers every sector size")
+    }
+
+    /// Checks that sectors of sector_bytes bytes can be sealed with these
+    /// parameters: that their lambda is the node size the trees are built
+    /// with, and that the sectors hold a whole number of nodes.
+    pub fn check(&self, sector_bytes: usize) -> Result<(), ProofParamsErr> {
+        debug_assert!(node_size_matches::<DefaultTreeHasher>());
+
+        if self.lambda != NODE_SIZE {
+            return Err(ProofParamsErr::UnsupportedLambda {
+                version: self.version,
+                lambda: self.lambda,
+                supported: NODE_SIZE,
+            });
+        }
+
+        if sector_bytes % self.lambda != 0 {
+            return Err(ProofParamsErr::SectorBytesNotMultiple {
+                sector_bytes,
+                lambda: self.lambda,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// The setup params of sectors of sector_bytes bytes, whose zigzag graphs
+    /// permute their expansion edges with the given keys and rounds. Fails as
+    /// check does.
+    pub fn setup_params(
+        &self,
+        sector_bytes: usize,
+        feistel: FeistelConfig,
+    ) -> Result<layered_drgporep::SetupParams, ProofParamsErr> {
+        self.check(sector_bytes)?;
+        let nodes = sector_bytes / self.lambda;
+
+        Ok(layered_drgporep::SetupParams {
+            drg_porep_setup_params: drgporep::SetupParams {
+                drg: DrgParams {
+                    nodes,
+                    degree: self.degree,
+                    expansion_degree: self.expansion_degree,
+                    seed: DRG_SEED,
+                    feistel,
+                },
+                sloth_iter: self.sloth_iter,
+            },
+            layer_challenges: LayerChallenges::new_tapered(
+                self.layers,
+                self.challenge_count(sector_bytes as u64),
+                self.taper_layers,
+                self.taper,
+            ),
+        })
+    }
+}
+
+/// The setup params of sectors of sector_bytes bytes sealed with the given
+/// version's parameters.
+pub fn setup_params(
+    sector_bytes: usize,
+    version: ProofVersion,
+) -> Result<layered_drgporep::SetupParams, ProofParamsErr> {
+    proof_params(version).setup_params(sector_bytes, FeistelConfig::default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pairing::bls12_381::Bls12;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use sector_base::api::disk_backed_storage::MINI_SECTOR_SIZE;
+    use storage_proofs::fr32::fr_into_bytes;
+    use storage_proofs::hasher::Hasher;
+    use storage_proofs::porep::PoRep;
+    use storage_proofs::proof::ProofScheme;
+    use storage_proofs::zigzag_drgporep::ZigZagDrgPoRep;
+
+    // Changing a set whose version has sealed sectors makes them unreadable,
+    // and their proofs unverifiable: add a version instead.
+    #[test]
+    fn parameter_sets_are_pinned() {
+        assert_eq!(
+            "ProofParams { version: V0Test, lambda: 32, degree: 1, expansion_degree: 2, \
+             sloth_iter: 0, layers: 2, taper_layers: 1, taper: 0.3333333333333333, \
+             challenge_count_policy: [(0, 2)], hasher: 1 }",
+            format!("{:?}", V0_TEST)
+        );
+        assert_eq!(
+            "ProofParams { version: V1Alpha, lambda: 32, degree: 5, expansion_degree: 8, \
+             sloth_iter: 0, layers: 4, taper_layers: 2, taper: 0.3333333333333333, \
+             challenge_count_policy: [(0, 2), (1073741824, 4), (34359738368, 8)], hasher: 1 }",
+            format!("{:?}", V1_ALPHA)
+        );
+        // What CI covers on every commit: changing it changes that coverage.
+        assert_eq!(
+            "ProofParams { version: Mini, lambda: 32, degree: 5, expansion_degree: 8, \
+             sloth_iter: 0, layers: 2, taper_layers: 1, taper: 0.3333333333333333, \
+             challenge_count_policy: [(0, 2)], hasher: 1 }",
+            format!("{:?}", MINI)
+        );
+        assert_eq!(2048, MINI_SECTOR_SIZE);
+        assert_eq!([1, 2, 3, 4, 5, 6, 7], DRG_SEED);
+    }
+
+    #[test]
+    fn every_version_has_its_own_set() {
+        for version in &ALLOWED_VERSIONS {
+            assert_eq!(*version, proof_params(*version).version);
+        }
+        assert_eq!(ProofVersion::V1Alpha, ProofVersion::CURRENT);
+        assert_ne!(
+            format!("{:?}", setup_params(1024, ProofVersion::V0Test).unwrap()),
+            format!("{:?}", setup_params(1024, ProofVersion::V1Alpha).unwrap())
+        );
+        assert_ne!(
+            format!("{:?}", setup_params(2048, ProofVersion::Mini).unwrap()),
+            format!("{:?}", setup_params(2048, ProofVersion::V1Alpha).unwrap())
+        );
+    }
+
+    #[test]
+    fn other_lambdas_are_refused() {
+        for lambda in &[16, 64] {
+            let params = ProofParams {
+                lambda: *lambda,
+                ..MINI
+            };
+
+            assert_eq!(
+                Err(ProofParamsErr::UnsupportedLambda {
+                    version: ProofVersion::Mini,
+                    lambda: *lambda,
+                    supported: NODE_SIZE,
+                }),
+                params.check(MINI_SECTOR_SIZE as usize)
+            );
+            assert!(params
+                .setup_params(MINI_SECTOR_SIZE as usize, FeistelConfig::default())
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn sectors_hold_whole_nodes() {
+        let sector_bytes = MINI_SECTOR_SIZE as usize + NODE_SIZE / 2;
+
+        assert_eq!(
+            Err(ProofParamsErr::SectorBytesNotMultiple {
+                sector_bytes,
+                lambda: NODE_SIZE,
+            }),
+            MINI.check(sector_bytes)
+        );
+        assert!(setup_params(sector_bytes, ProofVersion::Mini).is_err());
+
+        for version in &ALLOWED_VERSIONS {
+            assert_eq!(NODE_SIZE, proof_params(*version).lambda);
+            assert_eq!(
+                Ok(()),
+                proof_params(*version).check(MINI_SECTOR_SIZE as usize)
+            );
+        }
+    }
+
+    // Replicating and extracting a sector of nodes of NODE_SIZE bytes gives the
+    // data back.
+    #[test]
+    fn replicas_of_whole_nodes_round_trip() {
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+        let sector_bytes = MINI_SECTOR_SIZE as usize;
+
+        let sp = setup_params(sector_bytes, ProofVersion::Mini).unwrap();
+        let pp = ZigZagDrgPoRep::<DefaultTreeHasher>::setup(&sp).unwrap();
+
+        let replica_id: <DefaultTreeHasher as Hasher>::Domain = rng.gen();
+        let data: Vec<u8> = (0..sector_bytes / NODE_SIZE)
+            .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+            .collect();
+        assert_eq!(sector_bytes, data.len());
+
+        let mut replica = data.clone();
+        ZigZagDrgPoRep::replicate(&pp, &replica_id, &mut replica, None).unwrap();
+        assert_ne!(data, replica);
+
+        let extracted = ZigZagDrgPoRep::extract_all(&pp, &replica_id, &replica).unwrap();
+        assert_eq!(data, extracted);
+    }
+}
@@ -0,0 +1,469 @@
+//! A prover bundle holds everything proving a seal takes once its sector has
+//! been replicated, so that the replication isn't lost should proving fail
+//! (e.g. run out of memory), and so that proving may be done by another
+//! process, or on another machine. It's a directory of three files:
+//!
+//! ```text
+//! manifest.cbor - the version, sector size, proof variant and version, the
+//!                 prover, sector and replica ids, where the replica was
+//!                 written, the identifier of the public params the seal
+//!                 is proven with, and a digest of each of the other two
+//!                 files
+//! taus.cbor     - the taus of each layer, and comm_r_star
+//! aux.bin       - a byte naming how the rest is compressed (0 for not at
+//!                 all, 1 for snappy's framing format), then the merkle
+//!                 trees of each layer's data and of the last replica, as
+//!                 a little-endian u32 count of trees, each as
+//!                 MerkleTree::write_nodes writes it
+//! ```
+//!
+//! The manifest is written last, so a bundle without one is incomplete. The
+//! digests are Blake2b digests of the files' bytes (the trees' before they're
+//! compressed, so compressing them changes no digest), which are checked
+//! before the files are read. Bundles of version 1 are still read: their
+//! aux.bin has no compression byte.
+
+use blake2::{Blake2b, Digest};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use sector_base::api::disk_backed_storage::check_sector_bytes;
+use sector_base::api::sector_store::{AuxCompression, ProofVariant, ProofVersion};
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use storage_proofs::crypto::constant_time::constant_time_eq;
+use storage_proofs::hasher::pedersen::PedersenDomain;
+use storage_proofs::layered_drgporep;
+use storage_proofs::porep;
+
+use crate::api::internal::Tree;
+use crate::api::seal_proof::{open_versioned_envelope, seal_versioned_envelope};
+use crate::encoding::replica_id_domain;
+use crate::error;
+
+/// The version of the bundles written by this version.
+pub const BUNDLE_VERSION: u16 = 2;
+
+// The version of bundles written before their trees could be compressed.
+const UNCOMPRESSED_BUNDLE_VERSION: u16 = 1;
+
+const MANIFEST_FILE: &str = "manifest.cbor";
+const TAUS_FILE: &str = "taus.cbor";
+const AUX_FILE: &str = "aux.bin";
+
+#[derive(Debug, Fail)]
+pub enum ProverBundleErr {
+    #[fail(display = "prover bundle has unsupported version {}", _0)]
+    UnsupportedVersion(u16),
+
+    #[fail(display = "{} of prover bundle does not match its digest", _0)]
+    DigestMismatch(String),
+
+    #[fail(display = "malformed {} in prover bundle: {}", file, reason)]
+    Malformed { file: String, reason: String },
+
+    #[fail(
+        display = "prover bundle was made for parameters {}, rather than {}",
+        bundled, current
+    )]
+    ParameterMismatch { bundled: String, current: String },
+
+    #[fail(display = "prover bundle was made for another prover's sector, or another size")]
+    SectorMismatch,
+}
+
+fn err_malformed<T: ToString>(file: &str, reason: T) -> ProverBundleErr {
+    ProverBundleErr::Malformed {
+        file: file.to_string(),
+        reason: reason.to_string(),
+    }
+}
+
+/// What proving a replicated sector takes.
+#[derive(Debug)]
+pub struct ProverBundle {
+    pub sector_bytes: u64,
+    pub proof_variant: ProofVariant,
+    /// The version of the parameters the seal is to be proven with.
+    pub proof_version: ProofVersion,
+    pub prover_id: [u8; 31],
+    pub sector_id: [u8; 31],
+    /// Where the replica was written. Proving doesn't read it: the trees hold
+    /// all of it that proving takes.
+    pub replica_path: PathBuf,
+    /// The identifier of the public params the seal is to be proven with.
+    pub parameter_identifier: String,
+    pub tau: layered_drgporep::Tau<PedersenDomain>,
+    /// The trees of each layer's data, then of the last layer's replica.
+    pub aux: Vec<Tree>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Manifest {
+    version: u16,
+    sector_bytes: u64,
+    // The tag of the proof variant and version in the envelope of its proofs.
+    proof_variant: u8,
+    prover_id: [u8; 31],
+    sector_id: [u8; 31],
+    replica_id: Vec<u8>,
+    replica_path: PathBuf,
+    parameter_identifier: String,
+    taus_digest: Vec<u8>,
+    aux_digest: Vec<u8>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct Taus {
+    layer_taus: Vec<porep::Tau<PedersenDomain>>,
+    comm_r_star: PedersenDomain,
+}
+
+/// Writes a bundle into dir, which is created if need be, its trees compressed
+/// as compression says. They're compressed as they're written, rather than
+/// once serialized.
+pub fn write_prover_bundle<P: AsRef<Path>>(
+    dir: P,
+    bundle: &ProverBundle,
+    compression: AuxCompression,
+) -> error::Result<()> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+
+    let taus = serde_cbor::to_vec(&Taus {
+        layer_taus: bundle.tau.layer_taus.clone(),
+        comm_r_star: bundle.tau.comm_r_star,
+    })?;
+    write_synced(&dir.join(TAUS_FILE), &taus)?;
+
+    let mut file = BufWriter::new(File::create(dir.join(AUX_FILE))?);
+    file.write_u8(compression_tag(compression))?;
+
+    let aux_digest = {
+        let mut aux = DigestWriter::new(compressor(&mut file, compression));
+        aux.write_u32::<LittleEndian>(bundle.aux.len() as u32)?;
+        for tree in &bundle.aux {
+            tree.write_nodes(&mut aux)?;
+        }
+        aux.flush()?;
+
+        aux.digest.result().to_vec()
+    };
+    file.into_inner()?.sync_all()?;
+
+    let replica_id = replica_id_domain(bundle.prover_id, bundle.sector_id);
+
+    let manifest = Manifest {
+        version: BUNDLE_VERSION,
+        sector_bytes: bundle.sector_bytes,
+        proof_variant: seal_versioned_envelope(bundle.proof_version, bundle.proof_variant, &[])[0],
+        prover_id: bundle.prover_id,
+        sector_id: bundle.sector_id,
+        replica_id: AsRef::<[u8]>::as_ref(&replica_id).to_vec(),
+        replica_path: bundle.replica_path.clone(),
+        parameter_identifier: bundle.parameter_identifier.clone(),
+        taus_digest: Blake2b::digest(&taus).to_vec(),
+        aux_digest,
+    };
+    write_synced(&dir.join(MANIFEST_FILE), &serde_cbor::to_vec(&manifest)?)?;
+
+    Ok(())
+}
+
+/// Reads the bundle in dir, checking each of its files against its digest.
+pub fn read_prover_bundle<P: AsRef<Path>>(dir: P) -> error::Result<ProverBundle> {
+    let dir = dir.as_ref();
+
+    let manifest: Manifest = serde_cbor::from_slice(&fs::read(dir.join(MANIFEST_FILE))?)
+        .map_err(|err| err_malformed(MANIFEST_FILE, err))?;
+
+    if manifest.version != BUNDLE_VERSION && manifest.version != UNCOMPRESSED_BUNDLE_VERSION {
+        return Err(ProverBundleErr::UnsupportedVersion(manifest.version).into());
+    }
+
+    let (proof_version, proof_variant, _) = open_versioned_envelope(&[manifest.proof_variant])
+        .map_err(|err| err_malformed(MANIFEST_FILE, err))?;
+
+    check_sector_bytes(manifest.sector_bytes).map_err(|err| err_malformed(MANIFEST_FILE, err))?;
+
+    let replica_id = replica_id_domain(manifest.prover_id, manifest.sector_id);
+    if !constant_time_eq(AsRef::<[u8]>::as_ref(&replica_id), &manifest.replica_id) {
+        let reason = "replica id is not that of the prover and sector ids";
+        return Err(err_malformed(MANIFEST_FILE, reason).into());
+    }
+
+    let taus = fs::read(dir.join(TAUS_FILE))?;
+    if !constant_time_eq(&Blake2b::digest(&taus), &manifest.taus_digest) {
+        return Err(ProverBundleErr::DigestMismatch(TAUS_FILE.to_string()).into());
+    }
+    let taus: Taus = serde_cbor::from_slice(&taus).map_err(|err| err_malformed(TAUS_FILE, err))?;
+
+    let aux_path = dir.join(AUX_FILE);
+    let mut digest = DigestWriter::new(io::sink());
+    io::copy(&mut open_aux(&aux_path, manifest.version)?, &mut digest)
+        .map_err(|err| err_malformed(AUX_FILE, err))?;
+    if !constant_time_eq(&digest.digest.result(), &manifest.aux_digest) {
+        return Err(ProverBundleErr::DigestMismatch(AUX_FILE.to_string()).into());
+    }
+
+    let mut source = open_aux(&aux_path, manifest.version)?;
+    let trees = source.read_u32::<LittleEndian>()?;
+    let aux = (0..trees)
+        .map(|_| Tree::read_nodes(&mut source).map_err(|err| err_malformed(AUX_FILE, err)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    // A tree of each layer's data, and one of the last layer's replica.
+    if aux.len() != taus.layer_taus.len() + 1 {
+        let reason = format!("{} trees for {} layers", aux.len(), taus.layer_taus.len());
+        return Err(err_malformed(AUX_FILE, reason).into());
+    }
+
+    Ok(ProverBundle {
+        sector_bytes: manifest.sector_bytes,
+        proof_variant,
+        proof_version,
+        prover_id: manifest.prover_id,
+        sector_id: manifest.sector_id,
+        replica_path: manifest.replica_path,
+        parameter_identifier: manifest.parameter_identifier,
+        tau: layered_drgporep::Tau {
+            layer_taus: taus.layer_taus,
+            comm_r_star: taus.comm_r_star,
+        },
+        aux,
+    })
+}
+
+fn compression_tag(compression: AuxCompression) -> u8 {
+    match compression {
+        AuxCompression::None => 0,
+        AuxCompression::Snappy => 1,
+    }
+}
+
+fn compressor<'a, W: Write + 'a>(inner: W, compression: AuxCompression) -> Box<Write + 'a> {
+    match compression {
+        AuxCompression::None => Box::new(inner),
+        AuxCompression::Snappy => Box::new(snap::Writer::new(inner)),
+    }
+}
+
+// Opens the trees in the aux file of a bundle of the given version, however
+// they were compressed.
+fn open_aux(path: &Path, version: u16) -> error::Result<Box<Read>> {
+    let mut file = BufReader::new(File::open(path)?);
+    if version == UNCOMPRESSED_BUNDLE_VERSION {
+        return Ok(Box::new(file));
+    }
+
+    match file.read_u8()? {
+        0 => Ok(Box::new(file)),
+        1 => Ok(Box::new(snap::Reader::new(file))),
+        tag => Err(err_malformed(AUX_FILE, format!("unknown compression {}", tag)).into()),
+    }
+}
+
+fn write_synced(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    file.write_all(bytes)?;
+    file.sync_all()
+}
+
+// Digests the bytes written through it.
+struct DigestWriter<W> {
+    inner: W,
+    digest: Blake2b,
+}
+
+impl<W: Write> DigestWriter<W> {
+    fn new(inner: W) -> DigestWriter<W> {
+        DigestWriter {
+            inner,
+            digest: Blake2b::new(),
+        }
+    }
+}
+
+impl<W: Write> Write for DigestWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.digest.input(&buf[..n]);
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    // A bundle of two layers, over trees of a few random leaves.
+    fn small_bundle() -> ProverBundle {
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        let aux: Vec<Tree> = (0..3)
+            .map(|_| Tree::new((0..5).map(|_| rng.gen::<PedersenDomain>())))
+            .collect();
+        let layer_taus = aux
+            .windows(2)
+            .map(|trees| porep::Tau::new(trees[0].root(), trees[1].root()))
+            .collect();
+
+        ProverBundle {
+            sector_bytes: 1024,
+            proof_variant: ProofVariant::Vanilla,
+            proof_version: ProofVersion::V0Test,
+            prover_id: [1; 31],
+            sector_id: [2; 31],
+            replica_path: PathBuf::from("/sealed/sector"),
+            parameter_identifier: "params".to_string(),
+            tau: layered_drgporep::Tau {
+                layer_taus,
+                comm_r_star: rng.gen(),
+            },
+            aux,
+        }
+    }
+
+    fn assert_same(bundle: &ProverBundle, read: &ProverBundle) {
+        assert_eq!(bundle.sector_bytes, read.sector_bytes);
+        assert_eq!(bundle.proof_variant, read.proof_variant);
+        assert_eq!(bundle.proof_version, read.proof_version);
+        assert_eq!(bundle.prover_id, read.prover_id);
+        assert_eq!(bundle.sector_id, read.sector_id);
+        assert_eq!(bundle.replica_path, read.replica_path);
+        assert_eq!(bundle.parameter_identifier, read.parameter_identifier);
+        assert_eq!(bundle.tau.comm_r_star, read.tau.comm_r_star);
+
+        for (a, b) in bundle.tau.layer_taus.iter().zip(&read.tau.layer_taus) {
+            assert_eq!((a.comm_d, a.comm_r), (b.comm_d, b.comm_r));
+        }
+        assert_eq!(bundle.aux.len(), read.aux.len());
+        for (a, b) in bundle.aux.iter().zip(&read.aux) {
+            assert_eq!(a.as_slice(), b.as_slice());
+        }
+    }
+
+    fn read_manifest(dir: &Path) -> Manifest {
+        serde_cbor::from_slice(&fs::read(dir.join(MANIFEST_FILE)).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn bundles_round_trip() {
+        for compression in &[AuxCompression::None, AuxCompression::Snappy] {
+            let dir = tempfile::tempdir().unwrap();
+            let bundle = small_bundle();
+
+            write_prover_bundle(dir.path(), &bundle, *compression).unwrap();
+            let read = read_prover_bundle(dir.path()).unwrap();
+
+            assert_same(&bundle, &read);
+        }
+    }
+
+    #[test]
+    fn compression_changes_no_digest() {
+        let bundle = small_bundle();
+        let plain = tempfile::tempdir().unwrap();
+        let compressed = tempfile::tempdir().unwrap();
+
+        write_prover_bundle(plain.path(), &bundle, AuxCompression::None).unwrap();
+        write_prover_bundle(compressed.path(), &bundle, AuxCompression::Snappy).unwrap();
+
+        let plain = read_manifest(plain.path());
+        let compressed = read_manifest(compressed.path());
+        assert_eq!(plain.taus_digest, compressed.taus_digest);
+        assert_eq!(plain.aux_digest, compressed.aux_digest);
+    }
+
+    #[test]
+    fn trees_of_zeros_compress() {
+        // The trees of a sector of zeros, whose nodes repeat on every level.
+        let mut bundle = small_bundle();
+        let zeros = || (0..4096).map(|_| PedersenDomain::default());
+        bundle.aux = vec![Tree::new(zeros()), Tree::new(zeros()), Tree::new(zeros())];
+
+        let aux_bytes = |compression| {
+            let dir = tempfile::tempdir().unwrap();
+            write_prover_bundle(dir.path(), &bundle, compression).unwrap();
+            assert_same(&bundle, &read_prover_bundle(dir.path()).unwrap());
+
+            fs::metadata(dir.path().join(AUX_FILE)).unwrap().len()
+        };
+
+        let plain = aux_bytes(AuxCompression::None);
+        let compressed = aux_bytes(AuxCompression::Snappy);
+        assert!(compressed * 4 < plain, "compressed to {} of {}", compressed, plain);
+    }
+
+    #[test]
+    fn bundles_of_version_1_are_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let bundle = small_bundle();
+        write_prover_bundle(dir.path(), &bundle, AuxCompression::None).unwrap();
+
+        // Version 1 trees have no compression byte.
+        let aux_path = dir.path().join(AUX_FILE);
+        let aux = fs::read(&aux_path).unwrap();
+        fs::write(&aux_path, &aux[1..]).unwrap();
+
+        let mut manifest = read_manifest(dir.path());
+        manifest.version = UNCOMPRESSED_BUNDLE_VERSION;
+        let manifest = serde_cbor::to_vec(&manifest).unwrap();
+        fs::write(dir.path().join(MANIFEST_FILE), manifest).unwrap();
+
+        assert_same(&bundle, &read_prover_bundle(dir.path()).unwrap());
+    }
+
+    #[test]
+    fn corrupt_files_are_refused() {
+        for file in &[TAUS_FILE, AUX_FILE] {
+            let dir = tempfile::tempdir().unwrap();
+            write_prover_bundle(dir.path(), &small_bundle(), AuxCompression::None).unwrap();
+
+            let path = dir.path().join(file);
+            let mut bytes = fs::read(&path).unwrap();
+            let last = bytes.len() - 1;
+            bytes[last] ^= 1;
+            fs::write(&path, bytes).unwrap();
+
+            match read_prover_bundle(dir.path()).map_err(|e| e.downcast::<ProverBundleErr>()) {
+                Err(Ok(ProverBundleErr::DigestMismatch(ref f))) if f == file => (),
+                other => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+        }
+    }
+
+    #[test]
+    fn bundles_of_unsupported_sector_sizes_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut bundle = small_bundle();
+        bundle.sector_bytes = 1536;
+        write_prover_bundle(dir.path(), &bundle, AuxCompression::None).unwrap();
+
+        match read_prover_bundle(dir.path()).map_err(|e| e.downcast::<ProverBundleErr>()) {
+            Err(Ok(ProverBundleErr::Malformed { ref file, .. })) if file == MANIFEST_FILE => (),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn bundles_of_other_versions_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        write_prover_bundle(dir.path(), &small_bundle(), AuxCompression::None).unwrap();
+
+        let mut manifest = read_manifest(dir.path());
+        manifest.version = BUNDLE_VERSION + 1;
+        let manifest = serde_cbor::to_vec(&manifest).unwrap();
+        fs::write(dir.path().join(MANIFEST_FILE), manifest).unwrap();
+
+        match read_prover_bundle(dir.path()).map_err(|e| e.downcast::<ProverBundleErr>()) {
+            Err(Ok(ProverBundleErr::UnsupportedVersion(v))) => assert_eq!(BUNDLE_VERSION + 1, v),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+}
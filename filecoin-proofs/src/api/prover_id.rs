@@ -0,0 +1,96 @@
+//! The convention by which a prover id fits in the field. A prover id is 31
+//! bytes, which seal and verification pad to 32 by appending a zero byte, so
+//! the id's bytes are the low (first) 31 bytes of a little-endian value whose
+//! most significant byte is zero:
+//!
+//! ```text
+//! id (31 bytes) | 0
+//! ```
+//!
+//! Callers holding a 32-byte id must pass its first 31 bytes, and only when its
+//! last byte is zero; passing any other 31 of them derives another replica id.
+//! from_bytes_32 (prover_id_from_32_bytes over FFI) checks this rather than
+//! silently drop a byte.
+
+use storage_proofs::types::{fr_safe_from_slice, pad_safe_fr};
+
+/// A prover's id, as seal and verification take it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ProverId(pub [u8; 31]);
+
+#[derive(Debug, Fail, PartialEq)]
+#[fail(
+    display = "the last byte of a 32-byte prover id must be 0, as it's dropped, but is {}",
+    dropped
+)]
+pub struct LossyProverId {
+    pub dropped: u8,
+}
+
+impl ProverId {
+    /// Takes the first 31 bytes of a 32-byte id, refusing ids whose last byte,
+    /// which would be dropped, isn't zero.
+    pub fn from_bytes_32(bytes: [u8; 32]) -> Result<ProverId, LossyProverId> {
+        if bytes[31] != 0 {
+            return Err(LossyProverId { dropped: bytes[31] });
+        }
+
+        Ok(ProverId(fr_safe_from_slice(&bytes)))
+    }
+
+    /// Pads the id to 32 bytes, as seal and verification do.
+    pub fn to_bytes_32(&self) -> [u8; 32] {
+        pad_safe_fr(&self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::derive_replica_id;
+
+    #[test]
+    fn ids_with_a_zero_tail_round_trip() {
+        let mut bytes = [0; 32];
+        for (i, b) in bytes.iter_mut().take(31).enumerate() {
+            *b = i as u8 + 1;
+        }
+
+        let id = ProverId::from_bytes_32(bytes).unwrap();
+        assert_eq!(1, id.0[0]);
+        assert_eq!(31, id.0[30]);
+        assert_eq!(bytes, id.to_bytes_32());
+    }
+
+    #[test]
+    fn lossy_ids_are_refused() {
+        let mut bytes = [0; 32];
+        bytes[31] = 0x80;
+        assert_eq!(
+            Err(LossyProverId { dropped: 0x80 }),
+            ProverId::from_bytes_32(bytes)
+        );
+
+        // The high 31 bytes of an id aren't its low 31 bytes.
+        let mut bytes = [0; 32];
+        bytes[31] = 1;
+        assert!(ProverId::from_bytes_32(bytes).is_err());
+    }
+
+    #[test]
+    fn replica_ids_of_32_byte_ids_are_pinned() {
+        // The third of tests/golden/encoding_vectors.json's replica ids.
+        let mut bytes = [0; 32];
+        bytes[0] = 1;
+        let prover_id = ProverId::from_bytes_32(bytes).unwrap();
+
+        let replica_id: String = derive_replica_id(prover_id.0, [0; 31])
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        assert_eq!(
+            "75cccc6af86671f509b35904f687a70b11b1057e122b11faeb3a1132d6db6046",
+            replica_id
+        );
+    }
+}
@@ -0,0 +1,550 @@
+//! The format trailer appended to the replica of every sealed sector, which
+//! records how the replica was produced, so that a sealed sector can be told
+//! apart from one sealed with another graph, hasher or parameter set without
+//! the metadata it was sealed with:
+//!
+//! ```text
+//! "FRPL" | version: u16 | sector_bytes: u64 | layers: u32 | degree: u32
+//!     | expansion_degree: u32 | hasher: u8 | graph seed: [u32; 7]
+//!     | parameter digest: [u8; 32] | zeroes
+//! ```
+//!
+//! in little-endian order, padded with zeroes to FORMAT_BYTES. The trailer
+//! follows the replica rather than preceding it, so that nodes keep their
+//! offsets in the file, and a sealed sector is FORMAT_BYTES longer than its
+//! sector size. It isn't sealed, and so isn't committed to by comm_r.
+//!
+//! Sectors sealed before the trailer was introduced are exactly their sector
+//! size, and are only read when LegacyReplicas::Accept is given.
+//!
+//! A replica's nodes are laid out in order, so the leaf of a node is at its
+//! index times NODE_SIZE, and reading scattered leaves is a matter of ordering
+//! and coalescing the reads (see read_replica_leaves) rather than of a layout
+//! of their own.
+
+use byteorder::{ByteOrder, LittleEndian};
+use rand::Rng;
+use std::fmt;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+use storage_proofs::util::NODE_SIZE;
+
+use crate::api::cancellation::{read_to_end_checked, CancellationToken};
+use crate::error;
+
+/// Bytes appended to the replica of a sealed sector for its format trailer.
+pub const FORMAT_BYTES: u64 = 128;
+
+/// The version of the format trailer written by this version.
+pub const FORMAT_VERSION: u16 = 1;
+
+/// The hasher id recorded for the Pedersen hasher.
+pub const PEDERSEN_HASHER_ID: u8 = 1;
+
+const MAGIC: &[u8; 4] = b"FRPL";
+
+const ENCODED_BYTES: usize = 4 + 2 + 8 + 3 * 4 + 1 + 7 * 4 + 32;
+
+/// Leaves at most this many bytes apart are read in a single read, along with
+/// the bytes between them: on a spinning disk, reading through a small gap
+/// takes less time than seeking over it.
+pub const LEAF_READ_GAP_BYTES: usize = 64 * 1024;
+
+/// The most bytes read in a single read of leaves.
+pub const MAX_LEAF_READ_BYTES: usize = 1 << 20;
+
+/// What a sealed sector's format trailer records, and what it's checked
+/// against: how the configured store seals its sectors.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReplicaFormat {
+    pub sector_bytes: u64,
+    pub layers: u32,
+    pub degree: u32,
+    pub expansion_degree: u32,
+    pub hasher: u8,
+    pub graph_seed: [u32; 7],
+    /// A digest of the identifier of the seal's public params, which names the
+    /// groth parameters they're proven with.
+    pub parameter_digest: [u8; 32],
+}
+
+/// Whether sealed sectors without a format trailer, sealed before it was
+/// introduced, are read. Their format can't be checked.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LegacyReplicas {
+    Refuse,
+    Accept,
+}
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum SectorFormatMismatch {
+    #[fail(display = "the sealed sector has no format trailer")]
+    Missing,
+
+    #[fail(display = "the sealed sector is {} bytes, but {} were expected", found, expected)]
+    Length { expected: u64, found: u64 },
+
+    #[fail(display = "unrecognized format trailer: {}", _0)]
+    Unrecognized(String),
+
+    #[fail(
+        display = "the sealed sector's {} is {}, but {} is configured",
+        field, found, expected
+    )]
+    Field {
+        field: &'static str,
+        expected: String,
+        found: String,
+    },
+}
+
+#[derive(Debug, Fail, PartialEq)]
+#[fail(display = "node {} of the sealed sector's file isn't the node sealed", node_index)]
+pub struct SealedFileMismatch {
+    pub node_index: usize,
+}
+
+/// Leaves read by read_replica_leaves, and how many reads it took.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicaLeaves {
+    /// The leaves, in the order their indices were given.
+    pub leaves: Vec<[u8; NODE_SIZE]>,
+    pub reads: usize,
+}
+
+impl ReplicaFormat {
+    pub fn encode(&self) -> [u8; FORMAT_BYTES as usize] {
+        let mut bytes = [0; FORMAT_BYTES as usize];
+
+        bytes[..4].copy_from_slice(MAGIC);
+        LittleEndian::write_u16(&mut bytes[4..6], FORMAT_VERSION);
+        LittleEndian::write_u64(&mut bytes[6..14], self.sector_bytes);
+        LittleEndian::write_u32(&mut bytes[14..18], self.layers);
+        LittleEndian::write_u32(&mut bytes[18..22], self.degree);
+        LittleEndian::write_u32(&mut bytes[22..26], self.expansion_degree);
+        bytes[26] = self.hasher;
+        LittleEndian::write_u32_into(&self.graph_seed, &mut bytes[27..55]);
+        bytes[55..ENCODED_BYTES].copy_from_slice(&self.parameter_digest);
+
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<ReplicaFormat, SectorFormatMismatch> {
+        let unrecognized = |reason: &str| Err(SectorFormatMismatch::Unrecognized(reason.into()));
+
+        if bytes.len() != FORMAT_BYTES as usize {
+            return unrecognized("wrong length");
+        }
+        if &bytes[..4] != MAGIC {
+            return unrecognized("bad magic bytes");
+        }
+        let version = LittleEndian::read_u16(&bytes[4..6]);
+        if version != FORMAT_VERSION {
+            return unrecognized(&format!("unknown version {}", version));
+        }
+        if bytes[ENCODED_BYTES..].iter().any(|b| *b != 0) {
+            return unrecognized("non-zero padding");
+        }
+
+        let mut graph_seed = [0; 7];
+        LittleEndian::read_u32_into(&bytes[27..55], &mut graph_seed);
+        let mut parameter_digest = [0; 32];
+        parameter_digest.copy_from_slice(&bytes[55..ENCODED_BYTES]);
+
+        Ok(ReplicaFormat {
+            sector_bytes: LittleEndian::read_u64(&bytes[6..14]),
+            layers: LittleEndian::read_u32(&bytes[14..18]),
+            degree: LittleEndian::read_u32(&bytes[18..22]),
+            expansion_degree: LittleEndian::read_u32(&bytes[22..26]),
+            hasher: bytes[26],
+            graph_seed,
+            parameter_digest,
+        })
+    }
+
+    /// Checks that found, read from a sealed sector, is this format, naming
+    /// the first field which isn't.
+    pub fn check(&self, found: &ReplicaFormat) -> Result<(), SectorFormatMismatch> {
+        let fields: [(&'static str, String, String); 7] = [
+            field("sector size", self.sector_bytes, found.sector_bytes),
+            field("layer count", self.layers, found.layers),
+            field("degree", self.degree, found.degree),
+            field("expansion degree", self.expansion_degree, found.expansion_degree),
+            field("hasher", self.hasher, found.hasher),
+            field("graph seed", self.graph_seed, found.graph_seed),
+            field("parameter digest", self.parameter_digest, found.parameter_digest),
+        ];
+
+        match fields.iter().find(|(_, expected, found)| expected != found) {
+            Some((field, expected, found)) => Err(SectorFormatMismatch::Field {
+                field: *field,
+                expected: expected.clone(),
+                found: found.clone(),
+            }),
+            None => Ok(()),
+        }
+    }
+}
+
+fn field<T: fmt::Debug>(
+    name: &'static str,
+    expected: T,
+    found: T,
+) -> (&'static str, String, String) {
+    (name, format!("{:?}", expected), format!("{:?}", found))
+}
+
+/// Checks the sealed sector at path against format, reading only its format
+/// trailer, without which it's refused unless legacy is Accept.
+pub fn check_replica<T: AsRef<Path>>(
+    path: T,
+    format: &ReplicaFormat,
+    legacy: LegacyReplicas,
+) -> error::Result<()> {
+    let mut file = File::open(path)?;
+    let len = file.metadata()?.len();
+
+    if len == format.sector_bytes {
+        return match legacy {
+            LegacyReplicas::Accept => Ok(()),
+            LegacyReplicas::Refuse => Err(SectorFormatMismatch::Missing.into()),
+        };
+    }
+
+    if len != format.sector_bytes + FORMAT_BYTES {
+        let expected = format.sector_bytes + FORMAT_BYTES;
+        return Err(SectorFormatMismatch::Length { expected, found: len }.into());
+    }
+
+    let mut trailer = [0; FORMAT_BYTES as usize];
+    file.seek(SeekFrom::Start(format.sector_bytes))?;
+    file.read_exact(&mut trailer)?;
+
+    format.check(&ReplicaFormat::decode(&trailer)?)?;
+
+    Ok(())
+}
+
+/// Reads back samples nodes of the replica written to path, chosen at random
+/// (or every node, if there are no more), and compares them with those of
+/// replica, as sealed in memory, failing with a SealedFileMismatch naming the
+/// first which differs. On Linux, the file's pages
+/// are first dropped from the page cache, so that nodes are read from storage.
+pub fn sample_check_replica<T: AsRef<Path>, R: Rng>(
+    path: T,
+    replica: &[u8],
+    samples: usize,
+    rng: &mut R,
+) -> error::Result<()> {
+    let mut file = File::open(path)?;
+    drop_cached_pages(&file);
+
+    let nodes = replica.len() / NODE_SIZE;
+    let node_indexes: Vec<usize> = if samples >= nodes {
+        (0..nodes).collect()
+    } else {
+        (0..samples).map(|_| rng.gen_range(0, nodes)).collect()
+    };
+
+    let read = read_leaves(&mut file, &node_indexes)?;
+
+    for (node_index, node) in node_indexes.into_iter().zip(read.leaves) {
+        let offset = node_index * NODE_SIZE;
+
+        if node[..] != replica[offset..offset + NODE_SIZE] {
+            return Err(SealedFileMismatch { node_index }.into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads the leaves with the given indices of the replica of the sealed sector
+/// at path, once checked as check_replica checks it. The reads are sorted, and
+/// those of leaves close together are coalesced into one, so that reading many
+/// scattered leaves takes far fewer reads (and seeks) than there are leaves.
+///
+/// Proofs-of-spacetime don't read their challenged leaves apart yet: until the
+/// trees of sealed sectors are kept, each sector's tree is built from all of
+/// its replica, read in order.
+pub fn read_replica_leaves<T: AsRef<Path>>(
+    path: T,
+    format: &ReplicaFormat,
+    legacy: LegacyReplicas,
+    leaf_indices: &[usize],
+) -> error::Result<ReplicaLeaves> {
+    check_replica(&path, format, legacy)?;
+
+    let nodes = format.sector_bytes as usize / NODE_SIZE;
+    if let Some(leaf) = leaf_indices.iter().find(|leaf| **leaf >= nodes) {
+        return Err(format_err!("leaf {} is beyond the replica's {} leaves", leaf, nodes));
+    }
+
+    read_leaves(&mut File::open(path)?, leaf_indices)
+}
+
+fn read_leaves(file: &mut File, leaf_indices: &[usize]) -> error::Result<ReplicaLeaves> {
+    let mut sorted = leaf_indices.to_vec();
+    sorted.sort();
+    sorted.dedup();
+
+    let runs = leaf_runs(&sorted);
+    advise_leaf_reads(file, &runs);
+
+    let mut leaves = Vec::with_capacity(sorted.len());
+    let mut buf = Vec::new();
+
+    for &(first, last) in &runs {
+        buf.resize((last - first + 1) * NODE_SIZE, 0);
+        file.seek(SeekFrom::Start((first * NODE_SIZE) as u64))?;
+        file.read_exact(&mut buf)?;
+
+        for leaf in sorted[leaves.len()..].iter().take_while(|leaf| **leaf <= last) {
+            let offset = (leaf - first) * NODE_SIZE;
+            let mut node = [0; NODE_SIZE];
+            node.copy_from_slice(&buf[offset..offset + NODE_SIZE]);
+            leaves.push(node);
+        }
+    }
+
+    Ok(ReplicaLeaves {
+        leaves: leaf_indices
+            .iter()
+            .map(|leaf| leaves[sorted.binary_search(leaf).expect("leaf was read")])
+            .collect(),
+        reads: runs.len(),
+    })
+}
+
+// Groups sorted leaves into runs, given by their first and last leaf, each read
+// in a single read: a run takes in the next leaf as long as the gap to it is
+// no more than LEAF_READ_GAP_BYTES, and the run no more than
+// MAX_LEAF_READ_BYTES.
+fn leaf_runs(sorted: &[usize]) -> Vec<(usize, usize)> {
+    let mut runs: Vec<(usize, usize)> = Vec::new();
+
+    for &leaf in sorted {
+        if let Some(run) = runs.last_mut() {
+            let gap_bytes = (leaf - run.1 - 1) * NODE_SIZE;
+            let run_bytes = (leaf - run.0 + 1) * NODE_SIZE;
+
+            if gap_bytes <= LEAF_READ_GAP_BYTES && run_bytes <= MAX_LEAF_READ_BYTES {
+                run.1 = leaf;
+                continue;
+            }
+        }
+
+        runs.push((leaf, leaf));
+    }
+
+    runs
+}
+
+#[cfg(target_os = "linux")]
+fn advise_leaf_reads(file: &File, runs: &[(usize, usize)]) {
+    use std::os::unix::io::AsRawFd;
+
+    // Advice only. Readahead would read past each run for nothing, and asking
+    // for every run up front lets the disk read them in an order of its own.
+    unsafe {
+        libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_RANDOM);
+
+        for (first, last) in runs {
+            let offset = (first * NODE_SIZE) as libc::off_t;
+            let len = ((last - first + 1) * NODE_SIZE) as libc::off_t;
+            libc::posix_fadvise(file.as_raw_fd(), offset, len, libc::POSIX_FADV_WILLNEED);
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn advise_leaf_reads(_file: &File, _runs: &[(usize, usize)]) {}
+
+#[cfg(target_os = "linux")]
+fn drop_cached_pages(file: &File) {
+    use std::os::unix::io::AsRawFd;
+
+    // Advice only: if it's not taken, nodes are read from the page cache.
+    unsafe {
+        libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn drop_cached_pages(_file: &File) {}
+
+/// Reads the replica of the sealed sector at path, once checked as
+/// check_replica checks it, checking token between the chunks it reads.
+pub fn read_replica<T: AsRef<Path>>(
+    path: T,
+    format: &ReplicaFormat,
+    legacy: LegacyReplicas,
+    token: &CancellationToken,
+) -> error::Result<Vec<u8>> {
+    check_replica(&path, format, legacy)?;
+
+    read_to_end_checked(File::open(path)?, format.sector_bytes, token, "reading replica")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn format() -> ReplicaFormat {
+        ReplicaFormat {
+            sector_bytes: 1024,
+            layers: 4,
+            degree: 5,
+            expansion_degree: 8,
+            hasher: PEDERSEN_HASHER_ID,
+            graph_seed: [1, 2, 3, 4, 5, 6, 7],
+            parameter_digest: [9; 32],
+        }
+    }
+
+    #[test]
+    fn formats_round_trip() {
+        assert_eq!(Ok(format()), ReplicaFormat::decode(&format().encode()));
+    }
+
+    #[test]
+    fn unrecognized_trailers_are_refused() {
+        let unrecognized = |bytes: &[u8]| match ReplicaFormat::decode(bytes) {
+            Err(SectorFormatMismatch::Unrecognized(reason)) => reason,
+            other => panic!("unexpected result: {:?}", other),
+        };
+
+        assert_eq!("wrong length", unrecognized(&format().encode()[..127]));
+        assert_eq!("bad magic bytes", unrecognized(&[0; FORMAT_BYTES as usize]));
+
+        let mut bytes = format().encode();
+        bytes[4] = 2;
+        assert_eq!("unknown version 2", unrecognized(&bytes));
+
+        let mut bytes = format().encode();
+        bytes[127] = 1;
+        assert_eq!("non-zero padding", unrecognized(&bytes));
+    }
+
+    #[test]
+    fn replicas_are_checked_against_the_configured_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sealed");
+        let replica = vec![7; 1024];
+        let token = CancellationToken::new();
+
+        let write = |trailer: &[u8]| {
+            fs::write(&path, [&replica[..], trailer].concat()).unwrap();
+        };
+
+        write(&format().encode());
+        let read = read_replica(&path, &format(), LegacyReplicas::Refuse, &token).unwrap();
+        assert_eq!(replica, read);
+
+        let mismatch = |legacy| {
+            check_replica(&path, &format(), legacy)
+                .unwrap_err()
+                .downcast::<SectorFormatMismatch>()
+                .unwrap()
+        };
+
+        // Legacy replicas are only read when asked for.
+        write(&[]);
+        assert_eq!(SectorFormatMismatch::Missing, mismatch(LegacyReplicas::Refuse));
+        let read = read_replica(&path, &format(), LegacyReplicas::Accept, &token).unwrap();
+        assert_eq!(replica, read);
+
+        write(&format().encode()[..100]);
+        match mismatch(LegacyReplicas::Accept) {
+            SectorFormatMismatch::Length { expected: 1152, found: 1124 } => (),
+            other => panic!("unexpected mismatch: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn batched_leaf_reads_match_single_reads() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sealed");
+        let rng = &mut rand::thread_rng();
+
+        // A 4MiB replica, of 2^17 leaves.
+        let format = ReplicaFormat {
+            sector_bytes: 4 << 20,
+            ..format()
+        };
+        let replica: Vec<u8> = (0..format.sector_bytes).map(|_| rng.gen()).collect();
+        fs::write(&path, [&replica[..], &format.encode()].concat()).unwrap();
+
+        let nodes = replica.len() / NODE_SIZE;
+        let mut leaf_indices: Vec<usize> = (0..1000).map(|_| rng.gen_range(0, nodes)).collect();
+        leaf_indices.extend_from_slice(&[0, nodes - 1, 0]);
+
+        let read =
+            read_replica_leaves(&path, &format, LegacyReplicas::Refuse, &leaf_indices).unwrap();
+
+        assert_eq!(leaf_indices.len(), read.leaves.len());
+        let mut file = File::open(&path).unwrap();
+        for (leaf, node) in leaf_indices.iter().zip(&read.leaves) {
+            let mut single = [0; NODE_SIZE];
+            file.seek(SeekFrom::Start((leaf * NODE_SIZE) as u64)).unwrap();
+            file.read_exact(&mut single).unwrap();
+
+            assert_eq!(single, *node);
+        }
+
+        // Leaves 4KiB apart on average are mostly read along with others.
+        assert!(
+            read.reads < leaf_indices.len() / 2,
+            "read {} leaves in {} reads",
+            leaf_indices.len(),
+            read.reads
+        );
+
+        match read_replica_leaves(&path, &format, LegacyReplicas::Refuse, &[nodes]) {
+            Err(err) => assert!(format!("{}", err).contains("beyond")),
+            Ok(_) => panic!("a leaf beyond the replica was read"),
+        }
+    }
+
+    #[test]
+    fn leaf_runs_are_bounded() {
+        let gap_leaves = LEAF_READ_GAP_BYTES / NODE_SIZE;
+        let max_leaves = MAX_LEAF_READ_BYTES / NODE_SIZE;
+
+        assert_eq!(
+            vec![(0, 1 + gap_leaves), (2 + 2 * gap_leaves + 1, 2 + 2 * gap_leaves + 1)],
+            leaf_runs(&[0, 1, 1 + gap_leaves, 2 + 2 * gap_leaves + 1])
+        );
+        let contiguous: Vec<usize> = (0..=max_leaves).collect();
+        assert_eq!(
+            vec![(0, max_leaves - 1), (max_leaves, max_leaves)],
+            leaf_runs(&contiguous)
+        );
+        assert!(leaf_runs(&[]).is_empty());
+    }
+
+    #[test]
+    fn sample_checks_find_damaged_nodes() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sealed");
+        let replica = vec![7; 1024];
+        let rng = &mut rand::thread_rng();
+
+        fs::write(&path, [&replica[..], &format().encode()].concat()).unwrap();
+        sample_check_replica(&path, &replica, 32, rng).unwrap();
+
+        // Every node is sampled when there are no more than asked for.
+        let mut damaged = replica.clone();
+        damaged[5 * NODE_SIZE + 1] = 8;
+        fs::write(&path, &damaged).unwrap();
+
+        match sample_check_replica(&path, &replica, 1000, rng)
+            .map_err(|e| e.downcast::<SealedFileMismatch>())
+        {
+            Err(Ok(SealedFileMismatch { node_index: 5 })) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
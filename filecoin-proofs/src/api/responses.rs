@@ -1,6 +1,17 @@
-use crate::api::sector_builder::errors::SectorBuilderErr;
+use crate::api::cancellation::Interrupted;
+use crate::api::handshake::HandshakeErr;
+use crate::api::piece_layout::PieceLayoutErr;
+use crate::api::post_deadline::DeadlineExceeded;
+use crate::api::proof_params::ProofParamsErr;
+use crate::api::prover_bundle::ProverBundleErr;
+use crate::api::prover_id::LossyProverId;
+use crate::api::replica_format::{SealedFileMismatch, SectorFormatMismatch};
+use crate::api::screening::RejectedInput;
+use crate::api::seal_proof::SealProofErr;
+use crate::api::sector_builder::errors::{MetadataErr, SectorBuilderErr, WorkQueueErr};
 use crate::api::sector_builder::SectorBuilder;
 use crate::api::{API_POREP_PROOF_BYTES, API_POST_PROOF_BYTES};
+use crate::encoding::ParseCommitmentError;
 use failure::Error;
 use ffi_toolkit::free_c_str;
 use libc;
@@ -8,15 +19,23 @@ use sector_base::api::errors::SectorManagerErr;
 use std::ffi::CString;
 use std::mem;
 use std::ptr;
+use std::slice;
+use storage_proofs::error::Error as StorageProofsError;
 
 #[repr(C)]
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum FCPResponseStatus {
     // Don't use FCPSuccess, since that complicates description of 'successful' verification.
     FCPNoError = 0,
     FCPUnclassifiedError = 1,
     FCPCallerError = 2,
     FCPReceiverError = 3,
+    // The operation was given a timeout, and ran past it. The error message
+    // names the phase it was in.
+    FCPTimeout = 4,
+    // The caller was built against another version of the C API than the
+    // library's. Only filecoin_proofs_init reports it.
+    FCPIncompatibleAbi = 5,
 }
 
 #[repr(C)]
@@ -28,6 +47,220 @@ pub enum FFISealStatus {
     Sealing = 3,
 }
 
+#[repr(C)]
+#[derive(PartialEq, Debug)]
+pub enum FFIMigratedPieceStatus {
+    // The piece's recomputed comm_p matched the one recorded for it.
+    Verified = 0,
+    // The piece had no comm_p recorded to check against.
+    Unverified = 1,
+}
+
+#[repr(C)]
+#[derive(PartialEq, Debug)]
+pub enum FFIProofRegenerationStatus {
+    Regenerated = 0,
+    // The sector keeps its proof. The error message says why.
+    Failed = 1,
+}
+
+#[repr(C)]
+#[derive(PartialEq, Debug)]
+pub enum FFISelfTestStatus {
+    Passed = 0,
+    Failed = 1,
+    // Not run, as a step it depends on failed.
+    Skipped = 2,
+    // The parameters aren't installed. The error message lists their paths.
+    ParametersMissing = 3,
+}
+
+#[repr(C)]
+#[derive(PartialEq, Debug)]
+pub enum FFIPoSTFailureKind {
+    // The path proven for the challenge leads to a root other than the
+    // sector's comm_r (e.g. as its replica has changed, or the comm_rs are out
+    // of order), or isn't as long as the sector's tree is high.
+    PathMismatch = 0,
+    // The leaf proven isn't the one challenged, doesn't hash up its path, or
+    // isn't among those the VDF output of its epoch was computed from.
+    LeafMismatch = 1,
+    // The proof has no path for the challenge.
+    MissingProof = 2,
+    // A fault names no sector, or a sector an earlier fault named.
+    FaultSetInconsistency = 3,
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// Partial results
+///////////////////
+///
+/// Calls which operate on many items report the outcome of each item
+/// separately, so that one bad item doesn't fail the whole call. In addition
+/// to a payload array (e.g. `sectors_ptr`), a batch response carries two
+/// arrays of the same length:
+///
+/// * `item_status_codes_ptr` - one FCPResponseStatus per item
+/// * `item_error_msgs_ptr`   - one C string per item, null if the item succeeded
+///
+/// The payload of an item whose status code is not FCPNoError is zeroed and
+/// must be ignored. The top-level `status_code` and `error_msg` describe only
+/// whether the call itself could run; if it could not, every array is empty.
+/// Destroying the response frees every array and everything they point to.
+
+pub struct PartialResults<T> {
+    status_codes: Vec<FCPResponseStatus>,
+    error_msgs: Vec<*const libc::c_char>,
+    payloads: Vec<T>,
+}
+
+impl<T: Default> PartialResults<T> {
+    pub fn collect<I: IntoIterator<Item = Result<T, Error>>>(results: I) -> PartialResults<T> {
+        let mut partial = PartialResults {
+            status_codes: Vec::new(),
+            error_msgs: Vec::new(),
+            payloads: Vec::new(),
+        };
+
+        for result in results {
+            match result {
+                Ok(payload) => {
+                    partial.status_codes.push(FCPResponseStatus::FCPNoError);
+                    partial.error_msgs.push(ptr::null());
+                    partial.payloads.push(payload);
+                }
+                Err(err) => {
+                    let (code, ptr) = err_code_and_msg(&err);
+                    partial.status_codes.push(code);
+                    partial.error_msgs.push(ptr);
+                    partial.payloads.push(Default::default());
+                }
+            }
+        }
+
+        partial
+    }
+}
+
+impl<T> PartialResults<T> {
+    pub fn len(&self) -> usize {
+        self.payloads.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.payloads.is_empty()
+    }
+
+    // Hands ownership of the per-item arrays to the caller, who must
+    // eventually release them with free_partial_results.
+    pub fn into_raw_parts(
+        self,
+    ) -> (
+        *const FCPResponseStatus,
+        *const *const libc::c_char,
+        *const T,
+    ) {
+        (
+            Box::into_raw(self.status_codes.into_boxed_slice()) as *const FCPResponseStatus,
+            Box::into_raw(self.error_msgs.into_boxed_slice()) as *const *const libc::c_char,
+            Box::into_raw(self.payloads.into_boxed_slice()) as *const T,
+        )
+    }
+}
+
+// free_partial_results frees arrays produced by PartialResults::into_raw_parts,
+// including each item's error message and payload. Null pointers (as found in
+// a default response) are skipped.
+pub unsafe fn free_partial_results<T>(
+    len: libc::size_t,
+    status_codes: *const FCPResponseStatus,
+    error_msgs: *const *const libc::c_char,
+    payloads: *const T,
+) {
+    if !error_msgs.is_null() {
+        let error_msgs = Box::from_raw(slice::from_raw_parts_mut(
+            error_msgs as *mut *const libc::c_char,
+            len,
+        ));
+
+        for msg in error_msgs.iter() {
+            free_c_str(*msg as *mut libc::c_char);
+        }
+    }
+
+    if !status_codes.is_null() {
+        drop(Box::from_raw(slice::from_raw_parts_mut(
+            status_codes as *mut FCPResponseStatus,
+            len,
+        )));
+    }
+
+    if !payloads.is_null() {
+        drop(Box::from_raw(slice::from_raw_parts_mut(
+            payloads as *mut T,
+            len,
+        )));
+    }
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// InitResponse
+////////////////
+///
+/// Its layout is never to change, so that callers built against any version
+/// of the C API can read why the library refused them.
+
+#[repr(C)]
+pub struct FFIProofVersion {
+    // The version's code, as recorded in the tags of the proofs made with it.
+    pub version_code: u8,
+    pub max_sector_bytes: u64,
+}
+
+#[repr(C)]
+pub struct InitResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub abi_version: u32,
+    pub library_version: *const libc::c_char,
+    pub proof_versions_len: libc::size_t,
+    pub proof_versions_ptr: *const FFIProofVersion,
+}
+
+impl Default for InitResponse {
+    fn default() -> InitResponse {
+        InitResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            abi_version: 0,
+            library_version: ptr::null(),
+            proof_versions_len: 0,
+            proof_versions_ptr: ptr::null(),
+        }
+    }
+}
+
+impl Drop for InitResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            free_c_str(self.library_version as *mut libc::c_char);
+            if !self.proof_versions_ptr.is_null() {
+                drop(Vec::from_raw_parts(
+                    self.proof_versions_ptr as *mut FFIProofVersion,
+                    self.proof_versions_len,
+                    self.proof_versions_len,
+                ));
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_init_response(ptr: *mut InitResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 /// VerifySealResponse
 //////////////////////
@@ -62,6 +295,53 @@ pub unsafe extern "C" fn destroy_verify_seal_response(ptr: *mut VerifySealRespon
     let _ = Box::from_raw(ptr);
 }
 
+///////////////////////////////////////////////////////////////////////////////
+/// VerifySealsBatchResponse
+////////////////////////////
+
+#[repr(C)]
+pub struct VerifySealsBatchResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+
+    pub items_len: libc::size_t,
+    pub item_status_codes_ptr: *const FCPResponseStatus,
+    pub item_error_msgs_ptr: *const *const libc::c_char,
+    pub is_valid_ptr: *const bool,
+}
+
+impl Default for VerifySealsBatchResponse {
+    fn default() -> VerifySealsBatchResponse {
+        VerifySealsBatchResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            items_len: 0,
+            item_status_codes_ptr: ptr::null(),
+            item_error_msgs_ptr: ptr::null(),
+            is_valid_ptr: ptr::null(),
+        }
+    }
+}
+
+impl Drop for VerifySealsBatchResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            free_partial_results(
+                self.items_len,
+                self.item_status_codes_ptr,
+                self.item_error_msgs_ptr,
+                self.is_valid_ptr,
+            );
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_verify_seals_batch_response(ptr: *mut VerifySealsBatchResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 /// GeneratePoSTResult
 //////////////////////
@@ -106,210 +386,1474 @@ pub unsafe extern "C" fn destroy_generate_post_response(ptr: *mut GeneratePoSTRe
     let _ = Box::from_raw(ptr);
 }
 
-///////////////////////////////////////////////////////////////////////////////
-/// VerifyPoSTResult
-////////////////////
-
+///////////////////////////////////////////////////////////////////////////////
+/// VerifyPoSTResult
+////////////////////
+
+#[repr(C)]
+pub struct VerifyPoSTResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub is_valid: bool,
+}
+
+impl Default for VerifyPoSTResponse {
+    fn default() -> VerifyPoSTResponse {
+        VerifyPoSTResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            is_valid: false,
+        }
+    }
+}
+
+impl Drop for VerifyPoSTResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_verify_post_response(ptr: *mut VerifyPoSTResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// VerifyPoSTDetailedResponse
+//////////////////////////////
+
+// A check of a PoSt which failed. challenge_index is the index of the
+// challenge among all those of the proof, or 0 for a FaultSetInconsistency,
+// which concerns no challenge; comm_r is zeroes if a fault named no sector.
+#[repr(C)]
+pub struct FFIPoSTFailure {
+    pub sector_index: u64,
+    pub comm_r: [u8; 32],
+    pub challenge_index: u64,
+    pub kind: FFIPoSTFailureKind,
+}
+
+#[repr(C)]
+pub struct VerifyPoSTDetailedResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub is_valid: bool,
+    pub failures_len: libc::size_t,
+    pub failures_ptr: *const FFIPoSTFailure,
+}
+
+impl Default for VerifyPoSTDetailedResponse {
+    fn default() -> VerifyPoSTDetailedResponse {
+        VerifyPoSTDetailedResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            is_valid: false,
+            failures_len: 0,
+            failures_ptr: ptr::null(),
+        }
+    }
+}
+
+impl Drop for VerifyPoSTDetailedResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.failures_ptr.is_null() {
+                drop(Vec::from_raw_parts(
+                    self.failures_ptr as *mut FFIPoSTFailure,
+                    self.failures_len,
+                    self.failures_len,
+                ));
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_verify_post_detailed_response(
+    ptr: *mut VerifyPoSTDetailedResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// EstimateSealResourcesResponse
+/////////////////////////////////
+
+#[repr(C)]
+pub struct EstimateSealResourcesResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub encoding_millis: u64,
+    pub tree_building_millis: u64,
+    pub proving_millis: u64,
+    pub peak_memory_bytes: u64,
+}
+
+impl Default for EstimateSealResourcesResponse {
+    fn default() -> EstimateSealResourcesResponse {
+        EstimateSealResourcesResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            encoding_millis: 0,
+            tree_building_millis: 0,
+            proving_millis: 0,
+            peak_memory_bytes: 0,
+        }
+    }
+}
+
+impl Drop for EstimateSealResourcesResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_estimate_seal_resources_response(
+    ptr: *mut EstimateSealResourcesResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// GetEffectiveParallelismResponse
+///////////////////////////////////
+
+// The CPUs the process may use: effective by the override or the limits
+// detected, available whatever its cgroup allows, and those its cgroup's CPU
+// quota and cpuset allow (0 where it has none).
+#[repr(C)]
+pub struct GetEffectiveParallelismResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub effective: u64,
+    pub available: u64,
+    pub quota_cpus: u64,
+    pub cpuset_cpus: u64,
+}
+
+impl Default for GetEffectiveParallelismResponse {
+    fn default() -> GetEffectiveParallelismResponse {
+        GetEffectiveParallelismResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            effective: 0,
+            available: 0,
+            quota_cpus: 0,
+            cpuset_cpus: 0,
+        }
+    }
+}
+
+impl Drop for GetEffectiveParallelismResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_get_effective_parallelism_response(
+    ptr: *mut GetEffectiveParallelismResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// GenerateParametersResponse
+//////////////////////////////
+
+#[repr(C)]
+pub struct GenerateParametersResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+}
+
+impl Default for GenerateParametersResponse {
+    fn default() -> GenerateParametersResponse {
+        GenerateParametersResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+        }
+    }
+}
+
+impl Drop for GenerateParametersResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_generate_parameters_response(
+    ptr: *mut GenerateParametersResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// InstallParametersResponse
+/////////////////////////////
+
+#[repr(C)]
+pub struct InstallParametersResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+}
+
+impl Default for InstallParametersResponse {
+    fn default() -> InstallParametersResponse {
+        InstallParametersResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+        }
+    }
+}
+
+impl Drop for InstallParametersResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_install_parameters_response(ptr: *mut InstallParametersResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// ListInstalledParametersResponse
+///////////////////////////////////
+
+#[repr(C)]
+pub struct FFIParameterInfo {
+    pub identifier: *const libc::c_char,
+    pub size: u64,
+    pub digest: *const libc::c_char,
+}
+
+impl Drop for FFIParameterInfo {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.identifier as *mut libc::c_char);
+            free_c_str(self.digest as *mut libc::c_char);
+        }
+    }
+}
+
+#[repr(C)]
+pub struct ListInstalledParametersResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub parameters_len: libc::size_t,
+    pub parameters_ptr: *const FFIParameterInfo,
+}
+
+impl Default for ListInstalledParametersResponse {
+    fn default() -> ListInstalledParametersResponse {
+        ListInstalledParametersResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            parameters_len: 0,
+            parameters_ptr: ptr::null(),
+        }
+    }
+}
+
+impl Drop for ListInstalledParametersResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.parameters_ptr.is_null() {
+                drop(Vec::from_raw_parts(
+                    self.parameters_ptr as *mut FFIParameterInfo,
+                    self.parameters_len,
+                    self.parameters_len,
+                ));
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_list_installed_parameters_response(
+    ptr: *mut ListInstalledParametersResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// RemoveParametersResponse
+////////////////////////////
+
+#[repr(C)]
+pub struct RemoveParametersResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+}
+
+impl Default for RemoveParametersResponse {
+    fn default() -> RemoveParametersResponse {
+        RemoveParametersResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+        }
+    }
+}
+
+impl Drop for RemoveParametersResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_remove_parameters_response(ptr: *mut RemoveParametersResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// DeriveReplicaIdResponse
+///////////////////////////
+
+#[repr(C)]
+pub struct DeriveReplicaIdResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub replica_id: [u8; 32],
+}
+
+impl Default for DeriveReplicaIdResponse {
+    fn default() -> DeriveReplicaIdResponse {
+        DeriveReplicaIdResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            replica_id: [0; 32],
+        }
+    }
+}
+
+impl Drop for DeriveReplicaIdResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_derive_replica_id_response(ptr: *mut DeriveReplicaIdResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// ProverIdFrom32BytesResponse
+///////////////////////////////
+
+#[repr(C)]
+pub struct ProverIdFrom32BytesResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub prover_id: [u8; 31],
+}
+
+impl Default for ProverIdFrom32BytesResponse {
+    fn default() -> ProverIdFrom32BytesResponse {
+        ProverIdFrom32BytesResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            prover_id: [0; 31],
+        }
+    }
+}
+
+impl Drop for ProverIdFrom32BytesResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_prover_id_from_32_bytes_response(
+    ptr: *mut ProverIdFrom32BytesResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// CommitmentToStringResponse
+//////////////////////////////
+
+#[repr(C)]
+pub struct CommitmentToStringResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub commitment_string: *const libc::c_char,
+}
+
+impl Default for CommitmentToStringResponse {
+    fn default() -> CommitmentToStringResponse {
+        CommitmentToStringResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            commitment_string: ptr::null(),
+        }
+    }
+}
+
+impl Drop for CommitmentToStringResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            free_c_str(self.commitment_string as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_commitment_to_string_response(
+    ptr: *mut CommitmentToStringResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// CommitmentFromStringResponse
+////////////////////////////////
+
+#[repr(C)]
+pub struct CommitmentFromStringResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub commitment: [u8; 32],
+}
+
+impl Default for CommitmentFromStringResponse {
+    fn default() -> CommitmentFromStringResponse {
+        CommitmentFromStringResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            commitment: [0; 32],
+        }
+    }
+}
+
+impl Drop for CommitmentFromStringResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_commitment_from_string_response(
+    ptr: *mut CommitmentFromStringResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// PrefetchSealRequirementsResponse
+////////////////////////////////////
+
+#[repr(C)]
+pub struct PrefetchSealRequirementsResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub directories_millis: u64,
+    pub parameters_millis: u64,
+    pub graph_millis: u64,
+    pub directories_cache_hit: bool,
+    pub parameters_cache_hit: bool,
+    pub graph_cache_hit: bool,
+}
+
+impl Default for PrefetchSealRequirementsResponse {
+    fn default() -> PrefetchSealRequirementsResponse {
+        PrefetchSealRequirementsResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            directories_millis: 0,
+            parameters_millis: 0,
+            graph_millis: 0,
+            directories_cache_hit: false,
+            parameters_cache_hit: false,
+            graph_cache_hit: false,
+        }
+    }
+}
+
+impl Drop for PrefetchSealRequirementsResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_prefetch_seal_requirements_response(
+    ptr: *mut PrefetchSealRequirementsResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// RunSelfTestResponse
+///////////////////////
+
+#[repr(C)]
+pub struct FFISelfTestStep {
+    pub step: *const libc::c_char,
+    pub status: FFISelfTestStatus,
+    pub millis: u64,
+    pub error_msg: *const libc::c_char,
+}
+
+impl Drop for FFISelfTestStep {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.step as *mut libc::c_char);
+            free_c_str(self.error_msg as *mut libc::c_char);
+        }
+    }
+}
+
+#[repr(C)]
+pub struct RunSelfTestResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub passed: bool,
+    pub steps_len: libc::size_t,
+    pub steps_ptr: *const FFISelfTestStep,
+}
+
+impl Default for RunSelfTestResponse {
+    fn default() -> RunSelfTestResponse {
+        RunSelfTestResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            passed: false,
+            steps_len: 0,
+            steps_ptr: ptr::null(),
+        }
+    }
+}
+
+impl Drop for RunSelfTestResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.steps_ptr.is_null() {
+                drop(Vec::from_raw_parts(
+                    self.steps_ptr as *mut FFISelfTestStep,
+                    self.steps_len,
+                    self.steps_len,
+                ));
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_run_self_test_response(ptr: *mut RunSelfTestResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// ProveFromBundleResponse
+///////////////////////////
+
+#[repr(C)]
+pub struct ProveFromBundleResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub comm_d: [u8; 32],
+    pub comm_r: [u8; 32],
+    pub comm_r_star: [u8; 32],
+    pub snark_proof: [u8; API_POREP_PROOF_BYTES],
+}
+
+impl Default for ProveFromBundleResponse {
+    fn default() -> ProveFromBundleResponse {
+        ProveFromBundleResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            comm_d: Default::default(),
+            comm_r: Default::default(),
+            comm_r_star: Default::default(),
+            snark_proof: [0; API_POREP_PROOF_BYTES],
+        }
+    }
+}
+
+impl Drop for ProveFromBundleResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_prove_from_bundle_response(ptr: *mut ProveFromBundleResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// GetPublicInputsForSealResponse
+//////////////////////////////////
+
+#[repr(C)]
+pub struct GetPublicInputsForSealResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub layout_version: u32,
+    pub partitions: libc::size_t,
+    pub flattened_inputs_len: libc::size_t,
+    pub flattened_inputs_ptr: *const u8,
+}
+
+impl Default for GetPublicInputsForSealResponse {
+    fn default() -> GetPublicInputsForSealResponse {
+        GetPublicInputsForSealResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            layout_version: 0,
+            partitions: 0,
+            flattened_inputs_len: 0,
+            flattened_inputs_ptr: ptr::null(),
+        }
+    }
+}
+
+impl Drop for GetPublicInputsForSealResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.flattened_inputs_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.flattened_inputs_ptr as *mut u8,
+                    self.flattened_inputs_len,
+                )));
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_get_public_inputs_for_seal_response(
+    ptr: *mut GetPublicInputsForSealResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+// err_code_and_msg accepts an Error struct and produces a tuple of response
+// status code and a pointer to a C string, both of which can be used to set
+// fields in a response struct to be returned from an FFI call.
+pub fn err_code_and_msg(err: &Error) -> (FCPResponseStatus, *const libc::c_char) {
+    use crate::api::responses::FCPResponseStatus::*;
+
+    let msg = CString::new(format!("{}", err)).unwrap();
+    let ptr = msg.as_ptr();
+    mem::forget(msg);
+
+    match err.downcast_ref() {
+        Some(SectorBuilderErr::OverflowError { .. }) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::IncompleteWriteError { .. }) => return (FCPReceiverError, ptr),
+        Some(SectorBuilderErr::PieceLengthMismatch { .. }) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::Unrecoverable(_, _)) => return (FCPReceiverError, ptr),
+        Some(SectorBuilderErr::PieceNotFound(_)) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::DuplicatePiece { .. }) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::StagedDataMismatch { .. }) => return (FCPReceiverError, ptr),
+        Some(SectorBuilderErr::StagedDataChanged { .. }) => return (FCPReceiverError, ptr),
+        Some(SectorBuilderErr::SectorIdOutOfRange { .. }) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::SectorIdsExhausted { .. }) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::SealedSectorNotFound { .. }) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::SectorIdCollision { .. }) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::ProverIdMismatch { .. }) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::SealedSectorChecksumMismatch { .. }) => {
+            return (FCPReceiverError, ptr)
+        }
+        Some(SectorBuilderErr::InvalidMigration(_)) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::MigratedPieceMismatch { .. }) => return (FCPReceiverError, ptr),
+        Some(SectorBuilderErr::PlacementPlanMismatch(_)) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::PieceKeysNotPrivate(_)) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::PieceKeyMaterialMismatch) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::PieceKeyMaterialMissing) => return (FCPCallerError, ptr),
+        Some(SectorBuilderErr::ShuttingDown) => return (FCPCallerError, ptr),
+        None => (),
+    }
+
+    if err.downcast_ref::<HandshakeErr>().is_some() {
+        return (FCPIncompatibleAbi, ptr);
+    }
+
+    if err.downcast_ref::<MetadataErr>().is_some() {
+        return (FCPReceiverError, ptr);
+    }
+
+    if err.downcast_ref::<WorkQueueErr>().is_some() {
+        return (FCPReceiverError, ptr);
+    }
+
+    if err.downcast_ref::<SealProofErr>().is_some() {
+        return (FCPCallerError, ptr);
+    }
+
+    if err.downcast_ref::<RejectedInput>().is_some() {
+        return (FCPCallerError, ptr);
+    }
+
+    if err.downcast_ref::<PieceLayoutErr>().is_some() {
+        return (FCPCallerError, ptr);
+    }
+
+    if err.downcast_ref::<ProofParamsErr>().is_some() {
+        return (FCPCallerError, ptr);
+    }
+
+    if err.downcast_ref::<DeadlineExceeded>().is_some() {
+        return (FCPReceiverError, ptr);
+    }
+
+    if err.downcast_ref::<SectorFormatMismatch>().is_some() {
+        return (FCPReceiverError, ptr);
+    }
+
+    if err.downcast_ref::<SealedFileMismatch>().is_some() {
+        return (FCPReceiverError, ptr);
+    }
+
+    if err.downcast_ref::<LossyProverId>().is_some() {
+        return (FCPCallerError, ptr);
+    }
+
+    if err.downcast_ref::<ParseCommitmentError>().is_some() {
+        return (FCPCallerError, ptr);
+    }
+
+    if err.downcast_ref::<ProverBundleErr>().is_some() {
+        return (FCPCallerError, ptr);
+    }
+
+    match err.downcast_ref() {
+        Some(Interrupted::TimedOut { .. }) => return (FCPTimeout, ptr),
+        Some(Interrupted::Cancelled { .. }) => return (FCPCallerError, ptr),
+        None => (),
+    }
+
+    match err.downcast_ref() {
+        Some(StorageProofsError::MemoryLimitExceeded { .. }) => return (FCPReceiverError, ptr),
+        Some(StorageProofsError::InstalledParametersCorrupted { .. }) => {
+            return (FCPReceiverError, ptr)
+        }
+        Some(StorageProofsError::ParameterDigestMismatch { .. }) => return (FCPCallerError, ptr),
+        Some(StorageProofsError::InvalidParameterIdentifier(_)) => return (FCPCallerError, ptr),
+        Some(StorageProofsError::ParametersNotInstalled(_)) => return (FCPCallerError, ptr),
+        Some(StorageProofsError::ParameterGenerationCancelled {
+            timed_out: true, ..
+        }) => return (FCPTimeout, ptr),
+        Some(StorageProofsError::ParameterGenerationCancelled { .. }) => {
+            return (FCPCallerError, ptr)
+        }
+        Some(StorageProofsError::YieldCancelled { .. }) => return (FCPCallerError, ptr),
+        _ => (),
+    }
+
+    match err.downcast_ref() {
+        Some(SectorManagerErr::UnclassifiedError(_)) => return (FCPUnclassifiedError, ptr),
+        Some(SectorManagerErr::CallerError(_)) => return (FCPCallerError, ptr),
+        Some(SectorManagerErr::ReceiverError(_)) => return (FCPReceiverError, ptr),
+        Some(SectorManagerErr::StaleHandle(_)) => return (FCPCallerError, ptr),
+        Some(SectorManagerErr::OverlappingSectorDirs { .. }) => return (FCPCallerError, ptr),
+        Some(SectorManagerErr::ReadOnlyStore) => return (FCPCallerError, ptr),
+        Some(SectorManagerErr::UnsupportedSectorSize(_)) => return (FCPCallerError, ptr),
+        None => (),
+    }
+
+    (FCPUnclassifiedError, ptr)
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// InitSectorBuilderResponse
+/////////////////////////////
+
+#[repr(C)]
+pub struct InitSectorBuilderResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub sector_builder: *mut SectorBuilder,
+}
+
+impl Default for InitSectorBuilderResponse {
+    fn default() -> InitSectorBuilderResponse {
+        InitSectorBuilderResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            sector_builder: ptr::null_mut(),
+        }
+    }
+}
+
+impl Drop for InitSectorBuilderResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_init_sector_builder_response(ptr: *mut InitSectorBuilderResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// AddPieceResponse
+////////////////////
+
+#[repr(C)]
+pub struct AddPieceResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub sector_id: u64,
+    // Set if the piece has the same bytes as a piece placed before it.
+    pub duplicate: bool,
+}
+
+impl Default for AddPieceResponse {
+    fn default() -> AddPieceResponse {
+        AddPieceResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            sector_id: 0,
+            duplicate: false,
+        }
+    }
+}
+
+impl Drop for AddPieceResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_add_piece_response(ptr: *mut AddPieceResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// PlanPiecePlacementResponse
+//////////////////////////////
+
+// Where a piece is planned to be staged: in the staged sector with id
+// sector_id or, if new_sector is set, in the sector_id-th of the sectors the
+// plan provisions, counted from 0.
+#[repr(C)]
+pub struct FFIPieceAssignment {
+    pub piece_key: *const libc::c_char,
+    pub num_bytes: u64,
+    pub sector_id: u64,
+    pub new_sector: bool,
+}
+
+impl Drop for FFIPieceAssignment {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.piece_key as *mut libc::c_char);
+        }
+    }
+}
+
+#[repr(C)]
+pub struct PlanPiecePlacementResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub new_sectors: libc::size_t,
+    pub wasted_bytes: u64,
+    pub assignments_len: libc::size_t,
+    pub assignments_ptr: *const FFIPieceAssignment,
+}
+
+impl Default for PlanPiecePlacementResponse {
+    fn default() -> PlanPiecePlacementResponse {
+        PlanPiecePlacementResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            new_sectors: 0,
+            wasted_bytes: 0,
+            assignments_len: 0,
+            assignments_ptr: ptr::null(),
+        }
+    }
+}
+
+impl Drop for PlanPiecePlacementResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.assignments_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.assignments_ptr as *mut FFIPieceAssignment,
+                    self.assignments_len,
+                )));
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_plan_piece_placement_response(
+    ptr: *mut PlanPiecePlacementResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// AddPiecesPlannedResponse
+////////////////////////////
+
+#[repr(C)]
+pub struct AddPiecesPlannedResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub sector_ids_len: libc::size_t,
+    pub sector_ids_ptr: *const u64,
+}
+
+impl Default for AddPiecesPlannedResponse {
+    fn default() -> AddPiecesPlannedResponse {
+        AddPiecesPlannedResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            sector_ids_len: 0,
+            sector_ids_ptr: ptr::null(),
+        }
+    }
+}
+
+impl Drop for AddPiecesPlannedResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.sector_ids_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.sector_ids_ptr as *mut u64,
+                    self.sector_ids_len,
+                )));
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_add_pieces_planned_response(ptr: *mut AddPiecesPlannedResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
+////////////////////////////////////////////////////////////////////////////////
+/// ReadPieceFromSealedSectorResponse
+/////////////////////////////////////
+
+#[repr(C)]
+pub struct ReadPieceFromSealedSectorResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub data_len: libc::size_t,
+    pub data_ptr: *const u8,
+}
+
+impl Default for ReadPieceFromSealedSectorResponse {
+    fn default() -> ReadPieceFromSealedSectorResponse {
+        ReadPieceFromSealedSectorResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            data_len: 0,
+            data_ptr: ptr::null(),
+        }
+    }
+}
+
+impl Drop for ReadPieceFromSealedSectorResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+
+            drop(Vec::from_raw_parts(
+                self.data_ptr as *mut u8,
+                self.data_len,
+                self.data_len,
+            ));
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_read_piece_from_sealed_sector_response(
+    ptr: *mut ReadPieceFromSealedSectorResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// SealAllStagedSectorsResponse
+////////////////////////////////
+
+#[repr(C)]
+pub struct SealAllStagedSectorsResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+}
+
+impl Default for SealAllStagedSectorsResponse {
+    fn default() -> SealAllStagedSectorsResponse {
+        SealAllStagedSectorsResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+        }
+    }
+}
+
+impl Drop for SealAllStagedSectorsResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_seal_all_staged_sectors_response(
+    ptr: *mut SealAllStagedSectorsResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// SealSectorResponse
+//////////////////////
+
+#[repr(C)]
+pub struct SealSectorResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+
+    // set if the sector had already been sealed, and so wasn't sealed again
+    pub cached: bool,
+
+    // sealed sector metadata
+    pub comm_d: [u8; 32],
+    pub comm_r: [u8; 32],
+    pub comm_r_star: [u8; 32],
+    pub sector_access: *const libc::c_char,
+    pub sector_id: u64,
+    pub snark_proof: [u8; API_POREP_PROOF_BYTES],
+}
+
+impl Default for SealSectorResponse {
+    fn default() -> SealSectorResponse {
+        SealSectorResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            cached: false,
+            comm_d: Default::default(),
+            comm_r: Default::default(),
+            comm_r_star: Default::default(),
+            sector_access: ptr::null(),
+            sector_id: 0,
+            snark_proof: [0; API_POREP_PROOF_BYTES],
+        }
+    }
+}
+
+impl Drop for SealSectorResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            free_c_str(self.sector_access as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_seal_sector_response(ptr: *mut SealSectorResponse) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// ExportSealedSectorResponse
+//////////////////////////////
+
+#[repr(C)]
+pub struct ExportSealedSectorResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub manifest_path: *const libc::c_char,
+}
+
+impl Default for ExportSealedSectorResponse {
+    fn default() -> ExportSealedSectorResponse {
+        ExportSealedSectorResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            manifest_path: ptr::null(),
+        }
+    }
+}
+
+impl Drop for ExportSealedSectorResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            free_c_str(self.manifest_path as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_export_sealed_sector_response(
+    ptr: *mut ExportSealedSectorResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// ImportSealedSectorResponse
+//////////////////////////////
+
+#[repr(C)]
+pub struct ImportSealedSectorResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub sector_id: u64,
+}
+
+impl Default for ImportSealedSectorResponse {
+    fn default() -> ImportSealedSectorResponse {
+        ImportSealedSectorResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            sector_id: 0,
+        }
+    }
+}
+
+impl Drop for ImportSealedSectorResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_import_sealed_sector_response(
+    ptr: *mut ImportSealedSectorResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+///////////////////////////////////////////////////////////////////////////////
+/// ExportAuditLogResponse
+//////////////////////////
+
+#[repr(C)]
+pub struct FFIAuditRecord {
+    pub record_len: libc::size_t,
+    pub record_ptr: *const u8,
+}
+
+impl Drop for FFIAuditRecord {
+    fn drop(&mut self) {
+        unsafe {
+            if !self.record_ptr.is_null() {
+                drop(Vec::from_raw_parts(
+                    self.record_ptr as *mut u8,
+                    self.record_len,
+                    self.record_len,
+                ));
+            }
+        }
+    }
+}
+
 #[repr(C)]
-pub struct VerifyPoSTResponse {
+pub struct ExportAuditLogResponse {
     pub status_code: FCPResponseStatus,
     pub error_msg: *const libc::c_char,
-    pub is_valid: bool,
+    pub records_len: libc::size_t,
+    pub records_ptr: *const FFIAuditRecord,
+    pub records_lost: u64,
 }
 
-impl Default for VerifyPoSTResponse {
-    fn default() -> VerifyPoSTResponse {
-        VerifyPoSTResponse {
+impl Default for ExportAuditLogResponse {
+    fn default() -> ExportAuditLogResponse {
+        ExportAuditLogResponse {
             status_code: FCPResponseStatus::FCPNoError,
             error_msg: ptr::null(),
-            is_valid: false,
+            records_len: 0,
+            records_ptr: ptr::null(),
+            records_lost: 0,
         }
     }
 }
 
-impl Drop for VerifyPoSTResponse {
+impl Drop for ExportAuditLogResponse {
     fn drop(&mut self) {
         unsafe {
             free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.records_ptr.is_null() {
+                drop(Vec::from_raw_parts(
+                    self.records_ptr as *mut FFIAuditRecord,
+                    self.records_len,
+                    self.records_len,
+                ));
+            }
         };
     }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn destroy_verify_post_response(ptr: *mut VerifyPoSTResponse) {
+pub unsafe extern "C" fn destroy_export_audit_log_response(ptr: *mut ExportAuditLogResponse) {
     let _ = Box::from_raw(ptr);
 }
 
-// err_code_and_msg accepts an Error struct and produces a tuple of response
-// status code and a pointer to a C string, both of which can be used to set
-// fields in a response struct to be returned from an FFI call.
-pub fn err_code_and_msg(err: &Error) -> (FCPResponseStatus, *const libc::c_char) {
-    use crate::api::responses::FCPResponseStatus::*;
-
-    let msg = CString::new(format!("{}", err)).unwrap();
-    let ptr = msg.as_ptr();
-    mem::forget(msg);
+///////////////////////////////////////////////////////////////////////////////
+/// MigrateSectorsResponse
+//////////////////////////
 
-    match err.downcast_ref() {
-        Some(SectorBuilderErr::OverflowError { .. }) => return (FCPCallerError, ptr),
-        Some(SectorBuilderErr::IncompleteWriteError { .. }) => return (FCPReceiverError, ptr),
-        Some(SectorBuilderErr::Unrecoverable(_, _)) => return (FCPReceiverError, ptr),
-        Some(SectorBuilderErr::PieceNotFound(_)) => return (FCPCallerError, ptr),
-        None => (),
-    }
+#[repr(C)]
+pub struct FFIMigratedPiece {
+    pub piece_key: *const libc::c_char,
+    pub source_sector_id: u64,
+    pub status: FFIMigratedPieceStatus,
+}
 
-    match err.downcast_ref() {
-        Some(SectorManagerErr::UnclassifiedError(_)) => return (FCPUnclassifiedError, ptr),
-        Some(SectorManagerErr::CallerError(_)) => return (FCPCallerError, ptr),
-        Some(SectorManagerErr::ReceiverError(_)) => return (FCPReceiverError, ptr),
-        None => (),
+impl Drop for FFIMigratedPiece {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.piece_key as *mut libc::c_char);
+        }
     }
-
-    (FCPUnclassifiedError, ptr)
 }
 
-///////////////////////////////////////////////////////////////////////////////
-/// InitSectorBuilderResponse
-/////////////////////////////
-
 #[repr(C)]
-pub struct InitSectorBuilderResponse {
+pub struct MigrateSectorsResponse {
     pub status_code: FCPResponseStatus,
     pub error_msg: *const libc::c_char,
-    pub sector_builder: *mut SectorBuilder,
+    pub sector_id: u64,
+    pub pieces_len: libc::size_t,
+    pub pieces_ptr: *const FFIMigratedPiece,
 }
 
-impl Default for InitSectorBuilderResponse {
-    fn default() -> InitSectorBuilderResponse {
-        InitSectorBuilderResponse {
+impl Default for MigrateSectorsResponse {
+    fn default() -> MigrateSectorsResponse {
+        MigrateSectorsResponse {
             status_code: FCPResponseStatus::FCPNoError,
             error_msg: ptr::null(),
-            sector_builder: ptr::null_mut(),
+            sector_id: 0,
+            pieces_len: 0,
+            pieces_ptr: ptr::null(),
         }
     }
 }
 
-impl Drop for InitSectorBuilderResponse {
+impl Drop for MigrateSectorsResponse {
     fn drop(&mut self) {
         unsafe {
             free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.pieces_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.pieces_ptr as *mut FFIMigratedPiece,
+                    self.pieces_len,
+                )));
+            }
         };
     }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn destroy_init_sector_builder_response(ptr: *mut InitSectorBuilderResponse) {
+pub unsafe extern "C" fn destroy_migrate_sectors_response(ptr: *mut MigrateSectorsResponse) {
     let _ = Box::from_raw(ptr);
 }
 
 ///////////////////////////////////////////////////////////////////////////////
-/// AddPieceResponse
-////////////////////
+/// RegenerateAllProofsResponse
+///////////////////////////////
 
 #[repr(C)]
-pub struct AddPieceResponse {
+pub struct FFIProofRegeneration {
+    pub sector_id: u64,
+    pub status: FFIProofRegenerationStatus,
+    pub error_msg: *const libc::c_char,
+}
+
+impl Drop for FFIProofRegeneration {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+        }
+    }
+}
+
+#[repr(C)]
+pub struct RegenerateAllProofsResponse {
     pub status_code: FCPResponseStatus,
     pub error_msg: *const libc::c_char,
-    pub sector_id: u64,
+    pub sectors_len: libc::size_t,
+    pub sectors_ptr: *const FFIProofRegeneration,
 }
 
-impl Default for AddPieceResponse {
-    fn default() -> AddPieceResponse {
-        AddPieceResponse {
+impl Default for RegenerateAllProofsResponse {
+    fn default() -> RegenerateAllProofsResponse {
+        RegenerateAllProofsResponse {
             status_code: FCPResponseStatus::FCPNoError,
             error_msg: ptr::null(),
-            sector_id: 0,
+            sectors_len: 0,
+            sectors_ptr: ptr::null(),
         }
     }
 }
 
-impl Drop for AddPieceResponse {
+impl Drop for RegenerateAllProofsResponse {
     fn drop(&mut self) {
         unsafe {
             free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.sectors_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.sectors_ptr as *mut FFIProofRegeneration,
+                    self.sectors_len,
+                )));
+            }
         };
     }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn destroy_add_piece_response(ptr: *mut AddPieceResponse) {
+pub unsafe extern "C" fn destroy_regenerate_all_proofs_response(
+    ptr: *mut RegenerateAllProofsResponse,
+) {
     let _ = Box::from_raw(ptr);
 }
 
-////////////////////////////////////////////////////////////////////////////////
-/// ReadPieceFromSealedSectorResponse
-/////////////////////////////////////
+///////////////////////////////////////////////////////////////////////////////
+/// GetExpiredSectorsResponse
+/////////////////////////////
 
 #[repr(C)]
-pub struct ReadPieceFromSealedSectorResponse {
+pub struct GetExpiredSectorsResponse {
     pub status_code: FCPResponseStatus,
     pub error_msg: *const libc::c_char,
-    pub data_len: libc::size_t,
-    pub data_ptr: *const u8,
+    pub sector_ids_len: libc::size_t,
+    pub sector_ids_ptr: *const u64,
 }
 
-impl Default for ReadPieceFromSealedSectorResponse {
-    fn default() -> ReadPieceFromSealedSectorResponse {
-        ReadPieceFromSealedSectorResponse {
+impl Default for GetExpiredSectorsResponse {
+    fn default() -> GetExpiredSectorsResponse {
+        GetExpiredSectorsResponse {
             status_code: FCPResponseStatus::FCPNoError,
             error_msg: ptr::null(),
-            data_len: 0,
-            data_ptr: ptr::null(),
+            sector_ids_len: 0,
+            sector_ids_ptr: ptr::null(),
         }
     }
 }
 
-impl Drop for ReadPieceFromSealedSectorResponse {
+impl Drop for GetExpiredSectorsResponse {
     fn drop(&mut self) {
         unsafe {
             free_c_str(self.error_msg as *mut libc::c_char);
-
-            drop(Vec::from_raw_parts(
-                self.data_ptr as *mut u8,
-                self.data_len,
-                self.data_len,
-            ));
+            if !self.sector_ids_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.sector_ids_ptr as *mut u64,
+                    self.sector_ids_len,
+                )));
+            }
         };
     }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn destroy_read_piece_from_sealed_sector_response(
-    ptr: *mut ReadPieceFromSealedSectorResponse,
+pub unsafe extern "C" fn destroy_get_expired_sectors_response(
+    ptr: *mut GetExpiredSectorsResponse,
 ) {
     let _ = Box::from_raw(ptr);
 }
 
 ///////////////////////////////////////////////////////////////////////////////
-/// SealAllStagedSectorsResponse
-////////////////////////////////
+/// GetExpiringPiecesResponse
+/////////////////////////////
 
 #[repr(C)]
-pub struct SealAllStagedSectorsResponse {
+pub struct FFIExpiringPiece {
+    pub piece_key: *const libc::c_char,
+    pub sector_id: u64,
+    pub expires_at: u64,
+}
+
+impl Drop for FFIExpiringPiece {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.piece_key as *mut libc::c_char);
+        }
+    }
+}
+
+#[repr(C)]
+pub struct GetExpiringPiecesResponse {
     pub status_code: FCPResponseStatus,
     pub error_msg: *const libc::c_char,
+    pub pieces_len: libc::size_t,
+    pub pieces_ptr: *const FFIExpiringPiece,
 }
 
-impl Default for SealAllStagedSectorsResponse {
-    fn default() -> SealAllStagedSectorsResponse {
-        SealAllStagedSectorsResponse {
+impl Default for GetExpiringPiecesResponse {
+    fn default() -> GetExpiringPiecesResponse {
+        GetExpiringPiecesResponse {
             status_code: FCPResponseStatus::FCPNoError,
             error_msg: ptr::null(),
+            pieces_len: 0,
+            pieces_ptr: ptr::null(),
         }
     }
 }
 
-impl Drop for SealAllStagedSectorsResponse {
+impl Drop for GetExpiringPiecesResponse {
     fn drop(&mut self) {
         unsafe {
             free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.pieces_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.pieces_ptr as *mut FFIExpiringPiece,
+                    self.pieces_len,
+                )));
+            }
         };
     }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn destroy_seal_all_staged_sectors_response(
-    ptr: *mut SealAllStagedSectorsResponse,
+pub unsafe extern "C" fn destroy_get_expiring_pieces_response(
+    ptr: *mut GetExpiringPiecesResponse,
 ) {
     let _ = Box::from_raw(ptr);
 }
@@ -350,6 +1894,86 @@ pub unsafe extern "C" fn destroy_get_max_user_bytes_per_staged_sector_response(
     let _ = Box::from_raw(ptr);
 }
 
+///////////////////////////////////////////////////////////////////////////////
+/// GetSealAdmissionsResponse
+/////////////////////////////
+
+// A seal admitted to the sealers. Times are in milliseconds since the Unix
+// epoch; finished_at_millis is 0 while the seal runs.
+#[repr(C)]
+pub struct FFISealJob {
+    pub sector_id: u64,
+    pub memory_bytes: u64,
+    pub admitted_at_millis: u64,
+    pub finished_at_millis: u64,
+}
+
+#[repr(C)]
+pub struct GetSealAdmissionsResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub running_len: libc::size_t,
+    pub running_ptr: *const FFISealJob,
+    pub queued_len: libc::size_t,
+    pub queued_ptr: *const u64,
+    pub finished_len: libc::size_t,
+    pub finished_ptr: *const FFISealJob,
+    pub memory_budget_bytes: u64,
+    pub memory_in_use_bytes: u64,
+    pub max_running: libc::size_t,
+}
+
+impl Default for GetSealAdmissionsResponse {
+    fn default() -> GetSealAdmissionsResponse {
+        GetSealAdmissionsResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            running_len: 0,
+            running_ptr: ptr::null(),
+            queued_len: 0,
+            queued_ptr: ptr::null(),
+            finished_len: 0,
+            finished_ptr: ptr::null(),
+            memory_budget_bytes: 0,
+            memory_in_use_bytes: 0,
+            max_running: 0,
+        }
+    }
+}
+
+impl Drop for GetSealAdmissionsResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.running_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.running_ptr as *mut FFISealJob,
+                    self.running_len,
+                )));
+            }
+            if !self.queued_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.queued_ptr as *mut u64,
+                    self.queued_len,
+                )));
+            }
+            if !self.finished_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.finished_ptr as *mut FFISealJob,
+                    self.finished_len,
+                )));
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_get_seal_admissions_response(
+    ptr: *mut GetSealAdmissionsResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
 ///////////////////////////////////////////////////////////////////////////////
 /// GetSealStatusResponse
 /////////////////////////
@@ -449,16 +2073,31 @@ pub struct FFIStagedSectorMetadata {
     pub seal_error_msg: *const libc::c_char,
 }
 
+impl Default for FFIStagedSectorMetadata {
+    fn default() -> FFIStagedSectorMetadata {
+        FFIStagedSectorMetadata {
+            sector_access: ptr::null(),
+            sector_id: 0,
+            pieces_len: 0,
+            pieces_ptr: ptr::null(),
+            seal_status_code: FFISealStatus::Pending,
+            seal_error_msg: ptr::null(),
+        }
+    }
+}
+
 impl Drop for FFIStagedSectorMetadata {
     fn drop(&mut self) {
         unsafe {
             free_c_str(self.sector_access as *mut libc::c_char);
             free_c_str(self.seal_error_msg as *mut libc::c_char);
-            drop(Vec::from_raw_parts(
-                self.pieces_ptr as *mut FFIPieceMetadata,
-                self.pieces_len,
-                self.pieces_len,
-            ));
+            // placeholder payloads of failed batch items have no pieces
+            if !self.pieces_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.pieces_ptr as *mut FFIPieceMetadata,
+                    self.pieces_len,
+                )));
+            }
         }
     }
 }
@@ -479,15 +2118,32 @@ pub struct FFISealedSectorMetadata {
     pub pieces_ptr: *const FFIPieceMetadata,
 }
 
+impl Default for FFISealedSectorMetadata {
+    fn default() -> FFISealedSectorMetadata {
+        FFISealedSectorMetadata {
+            comm_d: Default::default(),
+            comm_r: Default::default(),
+            comm_r_star: Default::default(),
+            sector_access: ptr::null(),
+            sector_id: 0,
+            snark_proof: [0; API_POREP_PROOF_BYTES],
+            pieces_len: 0,
+            pieces_ptr: ptr::null(),
+        }
+    }
+}
+
 impl Drop for FFISealedSectorMetadata {
     fn drop(&mut self) {
         unsafe {
             free_c_str(self.sector_access as *mut libc::c_char);
-            drop(Vec::from_raw_parts(
-                self.pieces_ptr as *mut FFIPieceMetadata,
-                self.pieces_len,
-                self.pieces_len,
-            ));
+            // placeholder payloads of failed batch items have no pieces
+            if !self.pieces_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.pieces_ptr as *mut FFIPieceMetadata,
+                    self.pieces_len,
+                )));
+            }
         }
     }
 }
@@ -503,6 +2159,8 @@ pub struct GetSealedSectorsResponse {
 
     pub sectors_len: libc::size_t,
     pub sectors_ptr: *const FFISealedSectorMetadata,
+    pub item_status_codes_ptr: *const FCPResponseStatus,
+    pub item_error_msgs_ptr: *const *const libc::c_char,
 }
 
 impl Default for GetSealedSectorsResponse {
@@ -512,6 +2170,8 @@ impl Default for GetSealedSectorsResponse {
             error_msg: ptr::null(),
             sectors_len: 0,
             sectors_ptr: ptr::null(),
+            item_status_codes_ptr: ptr::null(),
+            item_error_msgs_ptr: ptr::null(),
         }
     }
 }
@@ -520,11 +2180,12 @@ impl Drop for GetSealedSectorsResponse {
     fn drop(&mut self) {
         unsafe {
             free_c_str(self.error_msg as *mut libc::c_char);
-            drop(Vec::from_raw_parts(
-                self.sectors_ptr as *mut FFISealedSectorMetadata,
-                self.sectors_len,
+            free_partial_results(
                 self.sectors_len,
-            ));
+                self.item_status_codes_ptr,
+                self.item_error_msgs_ptr,
+                self.sectors_ptr,
+            );
         }
     }
 }
@@ -545,6 +2206,8 @@ pub struct GetStagedSectorsResponse {
 
     pub sectors_len: libc::size_t,
     pub sectors_ptr: *const FFIStagedSectorMetadata,
+    pub item_status_codes_ptr: *const FCPResponseStatus,
+    pub item_error_msgs_ptr: *const *const libc::c_char,
 }
 
 impl Default for GetStagedSectorsResponse {
@@ -554,6 +2217,8 @@ impl Default for GetStagedSectorsResponse {
             error_msg: ptr::null(),
             sectors_len: 0,
             sectors_ptr: ptr::null(),
+            item_status_codes_ptr: ptr::null(),
+            item_error_msgs_ptr: ptr::null(),
         }
     }
 }
@@ -562,11 +2227,12 @@ impl Drop for GetStagedSectorsResponse {
     fn drop(&mut self) {
         unsafe {
             free_c_str(self.error_msg as *mut libc::c_char);
-            drop(Vec::from_raw_parts(
-                self.sectors_ptr as *mut FFIStagedSectorMetadata,
+            free_partial_results(
                 self.sectors_len,
-                self.sectors_len,
-            ));
+                self.item_status_codes_ptr,
+                self.item_error_msgs_ptr,
+                self.sectors_ptr,
+            );
         }
     }
 }
@@ -575,3 +2241,172 @@ impl Drop for GetStagedSectorsResponse {
 pub unsafe extern "C" fn destroy_get_staged_sectors_response(ptr: *mut GetStagedSectorsResponse) {
     let _ = Box::from_raw(ptr);
 }
+
+///////////////////////////////////////////////////////////////////////////////
+/// GetUnsealedRangeShardedResponse
+///////////////////////////////////
+
+#[repr(C)]
+pub struct FFIUnsealedShard {
+    pub path: *const libc::c_char,
+    pub num_bytes: u64,
+}
+
+impl Drop for FFIUnsealedShard {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.path as *mut libc::c_char);
+        }
+    }
+}
+
+#[repr(C)]
+pub struct GetUnsealedRangeShardedResponse {
+    pub status_code: FCPResponseStatus,
+    pub error_msg: *const libc::c_char,
+    pub shards_len: libc::size_t,
+    pub shards_ptr: *const FFIUnsealedShard,
+}
+
+impl Default for GetUnsealedRangeShardedResponse {
+    fn default() -> GetUnsealedRangeShardedResponse {
+        GetUnsealedRangeShardedResponse {
+            status_code: FCPResponseStatus::FCPNoError,
+            error_msg: ptr::null(),
+            shards_len: 0,
+            shards_ptr: ptr::null(),
+        }
+    }
+}
+
+impl Drop for GetUnsealedRangeShardedResponse {
+    fn drop(&mut self) {
+        unsafe {
+            free_c_str(self.error_msg as *mut libc::c_char);
+            if !self.shards_ptr.is_null() {
+                drop(Box::from_raw(slice::from_raw_parts_mut(
+                    self.shards_ptr as *mut FFIUnsealedShard,
+                    self.shards_len,
+                )));
+            }
+        };
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn destroy_get_unsealed_range_sharded_response(
+    ptr: *mut GetUnsealedRangeShardedResponse,
+) {
+    let _ = Box::from_raw(ptr);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use failure::err_msg;
+    use ffi_toolkit::raw_ptr;
+    use std::ffi::CStr;
+    use std::time::Duration;
+
+    #[test]
+    fn partial_results_isolate_failed_items() {
+        let results: Vec<Result<bool, Error>> = vec![
+            Ok(true),
+            Err(err_msg("invalid comm_r")),
+            Ok(false),
+            Ok(true),
+        ];
+
+        let partial = PartialResults::collect(results);
+
+        let mut response: VerifySealsBatchResponse = Default::default();
+        response.items_len = partial.len();
+
+        let (status_codes, error_msgs, is_valid) = partial.into_raw_parts();
+        response.item_status_codes_ptr = status_codes;
+        response.item_error_msgs_ptr = error_msgs;
+        response.is_valid_ptr = is_valid;
+
+        assert_eq!(FCPResponseStatus::FCPNoError, response.status_code);
+        assert_eq!(4, response.items_len);
+
+        let (status_codes, error_msgs, is_valid) = unsafe {
+            (
+                slice::from_raw_parts(response.item_status_codes_ptr, response.items_len),
+                slice::from_raw_parts(response.item_error_msgs_ptr, response.items_len),
+                slice::from_raw_parts(response.is_valid_ptr, response.items_len),
+            )
+        };
+
+        for i in &[0, 2, 3] {
+            assert_eq!(FCPResponseStatus::FCPNoError, status_codes[*i]);
+            assert!(error_msgs[*i].is_null());
+        }
+
+        assert_eq!(&[true, false, false, true], is_valid);
+
+        assert_eq!(FCPResponseStatus::FCPUnclassifiedError, status_codes[1]);
+        assert_eq!(
+            "invalid comm_r",
+            unsafe { CStr::from_ptr(error_msgs[1]) }.to_str().unwrap()
+        );
+
+        unsafe { destroy_verify_seals_batch_response(raw_ptr(response)) };
+    }
+
+    #[test]
+    fn timeouts_have_their_own_status() {
+        let err: Error = Interrupted::TimedOut {
+            phase: "reading replica".to_string(),
+            timeout: Duration::from_secs(1),
+        }
+        .into();
+
+        let (code, msg) = err_code_and_msg(&err);
+        assert_eq!(FCPResponseStatus::FCPTimeout, code);
+        assert_eq!(
+            "timed out after 1s while reading replica",
+            unsafe { CStr::from_ptr(msg) }.to_str().unwrap()
+        );
+
+        unsafe { free_c_str(msg as *mut libc::c_char) };
+    }
+
+    #[test]
+    fn stopped_parameter_generation_times_out_or_is_cancelled() {
+        for (timed_out, status) in &[
+            (true, FCPResponseStatus::FCPTimeout),
+            (false, FCPResponseStatus::FCPCallerError),
+        ] {
+            let err: Error = StorageProofsError::ParameterGenerationCancelled {
+                reason: "timed out after 1s while writing parameters".to_string(),
+                timed_out: *timed_out,
+            }
+            .into();
+
+            let (code, msg) = err_code_and_msg(&err);
+            assert_eq!(*status, code);
+            unsafe { free_c_str(msg as *mut libc::c_char) };
+        }
+    }
+
+    #[test]
+    fn default_batch_responses_drop_cleanly() {
+        drop(VerifySealsBatchResponse::default());
+        drop(GetSealedSectorsResponse::default());
+        drop(GetStagedSectorsResponse::default());
+        drop(GetExpiredSectorsResponse::default());
+        drop(GetExpiringPiecesResponse::default());
+        drop(GetSealAdmissionsResponse::default());
+        drop(GetPublicInputsForSealResponse::default());
+        drop(PrefetchSealRequirementsResponse::default());
+        drop(ProveFromBundleResponse::default());
+        drop(GetUnsealedRangeShardedResponse::default());
+        drop(ProverIdFrom32BytesResponse::default());
+        drop(PlanPiecePlacementResponse::default());
+        drop(AddPiecesPlannedResponse::default());
+        drop(ExportAuditLogResponse::default());
+        drop(RunSelfTestResponse::default());
+        drop(ListInstalledParametersResponse::default());
+    }
+}
@@ -0,0 +1,88 @@
+//! Checks of the inputs of a proof's verification which cost next to nothing,
+//! made before any of its parameters are looked up or its graph built, so that
+//! inputs which couldn't possibly verify (e.g. the zeroed buffers of malformed
+//! messages) are refused at once, and for what they are, rather than with
+//! whatever error decoding them after all that work happens to raise.
+//!
+//! None of what they refuse could have verified: no encoding of a proof is all
+//! zeroes, and every commitment is a hash, which is a field element, and which
+//! is zero with negligible probability.
+
+use storage_proofs::types::{fr_from_commitment, Commitment};
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum RejectedInput {
+    #[fail(display = "the proof is all zeroes")]
+    ZeroProof,
+
+    #[fail(display = "{} is all zeroes", _0)]
+    ZeroCommitment(String),
+
+    #[fail(display = "{} is not a canonical field element", _0)]
+    NonCanonicalCommitment(String),
+}
+
+/// Refuses a proof which is all zeroes. An empty one is left to be refused by
+/// whatever decodes it.
+pub fn screen_proof(proof: &[u8]) -> Result<(), RejectedInput> {
+    if !proof.is_empty() && proof.iter().all(|&byte| byte == 0) {
+        return Err(RejectedInput::ZeroProof);
+    }
+
+    Ok(())
+}
+
+/// Refuses a commitment, named by name, which is all zeroes or isn't the
+/// canonical encoding of a field element.
+pub fn screen_commitment<T: ToString>(
+    name: T,
+    commitment: &Commitment,
+) -> Result<(), RejectedInput> {
+    if commitment.iter().all(|&byte| byte == 0) {
+        return Err(RejectedInput::ZeroCommitment(name.to_string()));
+    }
+
+    if fr_from_commitment(commitment).is_err() {
+        return Err(RejectedInput::NonCanonicalCommitment(name.to_string()));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pairing::bls12_381::Fr;
+    use pairing::{Field, PrimeField, PrimeFieldRepr};
+    use storage_proofs::types::commitment_from_fr;
+
+    #[test]
+    fn only_zeroes_and_non_canonical_encodings_are_refused() {
+        assert_eq!(Err(RejectedInput::ZeroProof), screen_proof(&[0; 192]));
+        assert_eq!(Ok(()), screen_proof(&[]));
+        assert_eq!(Ok(()), screen_proof(&[0, 0, 1]));
+
+        let zero = RejectedInput::ZeroCommitment("comm_d".to_string());
+        assert_eq!(Err(zero), screen_commitment("comm_d", &[0; 32]));
+
+        // The field's modulus, the least of the encodings of no element.
+        let mut modulus = [0; 32];
+        Fr::char().write_le(&mut modulus[..]).unwrap();
+
+        let non_canonical = RejectedInput::NonCanonicalCommitment("comm_r".to_string());
+        assert_eq!(Err(non_canonical), screen_commitment("comm_r", &modulus));
+
+        // The greatest element is no different from any other.
+        let mut greatest = Fr::zero();
+        greatest.sub_assign(&Fr::one());
+        assert_eq!(
+            Ok(()),
+            screen_commitment("comm_r", &commitment_from_fr(greatest))
+        );
+        assert_eq!(
+            Ok(()),
+            screen_commitment("comm_r", &commitment_from_fr(Fr::one()))
+        );
+    }
+}
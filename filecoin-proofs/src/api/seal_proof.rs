@@ -0,0 +1,327 @@
+//! The envelope in which internal::seal hands out proofs and internal::verify_seal
+//! takes them: a byte naming the proof's variant, the version of the
+//! parameters it was made with and its format, followed by the proof itself.
+//! The tag's low bit is the variant. The next three are the version's code,
+//! which is 0 for V1Alpha, the version proofs were made with before envelopes
+//! named theirs, so those proofs' tags are unchanged. The high four are the
+//! format (see PROOF_FORMAT). The proofs of the release before envelopes,
+//! which were bare, are of format 0, and are verified in an envelope of tag 0.
+//!
+//! A SNARK proof is the Groth16 proof of each partition, as written by
+//! MultiProof. A vanilla proof is the CBOR serialization of the layered proof of
+//! each partition, and so is far larger, and of variable length.
+//!
+//! Provers and verifiers may limit the length of the proofs they make and take
+//! (envelope included), refusing them with a TooLarge error.
+
+use sector_base::api::sector_store::{ProofVariant, ProofVersion};
+
+const SNARK_TAG: u8 = 0;
+const VANILLA_TAG: u8 = 1;
+
+// The bits of a tag holding the version's code, above the variant's.
+const VERSION_CODE_MASK: u8 = 0b111;
+
+/// The format of the seal proofs this release makes: the layout of their
+/// circuits' public inputs, the derivation of their challenges and the
+/// identifiers of their groth parameters, together. It's bumped whenever any of
+/// them changes, and the format it replaces frozen in api::legacy, so that the
+/// proofs made in it still verify. Format 0 is that of the release before
+/// envelopes (see api::legacy::v0), whose challenges format 1 changed, but
+/// not its groth parameters or the layout of its circuits' public inputs.
+pub const PROOF_FORMAT: u8 = 1;
+
+/// The code a proof's tag records version with.
+pub fn version_code(version: ProofVersion) -> u8 {
+    match version {
+        ProofVersion::V1Alpha => 0,
+        ProofVersion::V0Test => 1,
+        ProofVersion::Mini => 2,
+    }
+}
+
+/// The version recorded with the given code, if it's a version's.
+pub fn version_of_code(code: u8) -> Option<ProofVersion> {
+    match code {
+        0 => Some(ProofVersion::V1Alpha),
+        1 => Some(ProofVersion::V0Test),
+        2 => Some(ProofVersion::Mini),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Fail)]
+pub enum SealProofErr {
+    #[fail(display = "empty proof")]
+    Empty,
+
+    #[fail(display = "unknown proof variant tag {}", _0)]
+    UnknownVariant(u8),
+
+    #[fail(display = "unknown proof format {}", _0)]
+    UnknownFormat(u8),
+
+    #[fail(display = "proofs of format {} are not accepted", _0)]
+    FormatNotAccepted(u8),
+
+    #[fail(display = "expected a {:?} proof, but got a {:?} proof", expected, found)]
+    VariantMismatch {
+        expected: ProofVariant,
+        found: ProofVariant,
+    },
+
+    #[fail(
+        display = "expected a proof made with {:?} parameters, but got one made with {:?}",
+        expected, found
+    )]
+    VersionMismatch {
+        expected: ProofVersion,
+        found: ProofVersion,
+    },
+
+    #[fail(display = "malformed {:?} proof: {}", variant, reason)]
+    Malformed {
+        variant: ProofVariant,
+        reason: String,
+    },
+
+    #[fail(
+        display = "{:?} proof of {} bytes is past the limit of {} bytes",
+        variant, bytes, max_bytes
+    )]
+    TooLarge {
+        variant: ProofVariant,
+        bytes: usize,
+        max_bytes: usize,
+    },
+}
+
+pub fn err_variant_mismatch(expected: ProofVariant, found: ProofVariant) -> SealProofErr {
+    SealProofErr::VariantMismatch { expected, found }
+}
+
+pub fn err_version_mismatch(expected: ProofVersion, found: ProofVersion) -> SealProofErr {
+    SealProofErr::VersionMismatch { expected, found }
+}
+
+pub fn err_malformed<T: ToString>(variant: ProofVariant, reason: T) -> SealProofErr {
+    SealProofErr::Malformed {
+        variant,
+        reason: reason.to_string(),
+    }
+}
+
+/// Refuses a proof of the given length (in its envelope) if it's past
+/// max_bytes, if given.
+pub fn check_proof_bytes(
+    variant: ProofVariant,
+    bytes: usize,
+    max_bytes: Option<usize>,
+) -> Result<(), SealProofErr> {
+    match max_bytes {
+        Some(max_bytes) if bytes > max_bytes => Err(SealProofErr::TooLarge {
+            variant,
+            bytes,
+            max_bytes,
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Wraps a proof of the given variant, made with the current version's
+/// parameters, in an envelope.
+pub fn seal_envelope(variant: ProofVariant, proof: &[u8]) -> Vec<u8> {
+    seal_versioned_envelope(ProofVersion::CURRENT, variant, proof)
+}
+
+/// Wraps a proof of the given variant, made with the given version's
+/// parameters, in an envelope.
+pub fn seal_versioned_envelope(
+    version: ProofVersion,
+    variant: ProofVariant,
+    proof: &[u8],
+) -> Vec<u8> {
+    seal_formatted_envelope(PROOF_FORMAT, version, variant, proof)
+}
+
+/// Wraps a proof of the given format and variant, made with the given
+/// version's parameters, in an envelope. Only the proofs of earlier releases
+/// are of any format but PROOF_FORMAT.
+pub fn seal_formatted_envelope(
+    format: u8,
+    version: ProofVersion,
+    variant: ProofVariant,
+    proof: &[u8],
+) -> Vec<u8> {
+    assert!(format < 16, "proof formats are four bits");
+
+    let mut envelope = Vec::with_capacity(1 + proof.len());
+
+    let variant_tag = match variant {
+        ProofVariant::Snark => SNARK_TAG,
+        ProofVariant::Vanilla => VANILLA_TAG,
+    };
+    envelope.push((format << 4) | (version_code(version) << 1) | variant_tag);
+    envelope.extend_from_slice(proof);
+
+    envelope
+}
+
+/// Returns the variant of the proof in an envelope, along with the proof,
+/// whichever version's parameters it was made with.
+pub fn open_envelope(envelope: &[u8]) -> Result<(ProofVariant, &[u8]), SealProofErr> {
+    let (_, variant, proof) = open_versioned_envelope(envelope)?;
+
+    Ok((variant, proof))
+}
+
+/// Returns the version of the parameters the proof in an envelope was made
+/// with and its variant, along with the proof.
+pub fn open_versioned_envelope(
+    envelope: &[u8],
+) -> Result<(ProofVersion, ProofVariant, &[u8]), SealProofErr> {
+    let (_, version, variant, proof) = open_formatted_envelope(envelope)?;
+
+    Ok((version, variant, proof))
+}
+
+/// Returns the format of the proof in an envelope, the version of the
+/// parameters it was made with and its variant, along with the proof. Whether
+/// the format is one this release can verify is left to the caller.
+pub fn open_formatted_envelope(
+    envelope: &[u8],
+) -> Result<(u8, ProofVersion, ProofVariant, &[u8]), SealProofErr> {
+    let (tag, proof) = envelope.split_first().ok_or(SealProofErr::Empty)?;
+    let version = version_of_code((tag >> 1) & VERSION_CODE_MASK)
+        .ok_or(SealProofErr::UnknownVariant(*tag))?;
+
+    match tag & 1 {
+        SNARK_TAG => Ok((tag >> 4, version, ProofVariant::Snark, proof)),
+        _ => Ok((tag >> 4, version, ProofVariant::Vanilla, proof)),
+    }
+}
+
+/// Takes the SNARK proof out of an envelope, for the C API and sealed sector
+/// metadata, which only hold SNARK proofs.
+pub fn snark_proof(envelope: &[u8]) -> Result<[u8; 384], SealProofErr> {
+    let mut snark_proof = [0; 384];
+
+    match open_envelope(envelope)? {
+        (ProofVariant::Snark, proof) if proof.len() == snark_proof.len() => {
+            snark_proof.copy_from_slice(proof);
+            Ok(snark_proof)
+        }
+        (ProofVariant::Snark, _) => Err(err_malformed(ProofVariant::Snark, "wrong length")),
+        (variant, _) => Err(err_variant_mismatch(ProofVariant::Snark, variant)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn envelopes_round_trip() {
+        for variant in &[ProofVariant::Snark, ProofVariant::Vanilla] {
+            let envelope = seal_envelope(*variant, &[1, 2, 3]);
+
+            assert_eq!(4, envelope.len());
+            assert_eq!((*variant, &[1, 2, 3][..]), open_envelope(&envelope).unwrap());
+        }
+    }
+
+    #[test]
+    fn versioned_envelopes_round_trip() {
+        for version in &[
+            ProofVersion::V0Test,
+            ProofVersion::V1Alpha,
+            ProofVersion::Mini,
+        ] {
+            for variant in &[ProofVariant::Snark, ProofVariant::Vanilla] {
+                let envelope = seal_versioned_envelope(*version, *variant, &[1, 2, 3]);
+
+                assert_eq!(
+                    (*version, *variant, &[1, 2, 3][..]),
+                    open_versioned_envelope(&envelope).unwrap()
+                );
+            }
+        }
+    }
+
+    // Proofs made before envelopes named their version were all made with
+    // V1Alpha's parameters, and before they named their format, in format 0.
+    #[test]
+    fn earlier_envelopes_keep_their_tags() {
+        assert_eq!(ProofVersion::V1Alpha, ProofVersion::CURRENT);
+
+        let v1_alpha = ProofVersion::V1Alpha;
+        assert_eq!(
+            vec![0, 9],
+            seal_formatted_envelope(0, v1_alpha, ProofVariant::Snark, &[9])
+        );
+        assert_eq!(
+            vec![1, 9],
+            seal_formatted_envelope(0, v1_alpha, ProofVariant::Vanilla, &[9])
+        );
+
+        let test_envelope =
+            seal_formatted_envelope(0, ProofVersion::V0Test, ProofVariant::Snark, &[]);
+        assert_eq!(vec![2], test_envelope);
+
+        for (tag, variant) in &[(0, ProofVariant::Snark), (1, ProofVariant::Vanilla)] {
+            assert_eq!(
+                (0, v1_alpha, *variant, &[9][..]),
+                open_formatted_envelope(&[*tag, 9]).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn current_envelopes_record_their_format() {
+        assert_eq!(vec![16, 9], seal_envelope(ProofVariant::Snark, &[9]));
+        assert_eq!(vec![17, 9], seal_envelope(ProofVariant::Vanilla, &[9]));
+
+        let envelope = seal_versioned_envelope(ProofVersion::Mini, ProofVariant::Vanilla, &[9]);
+        assert_eq!(
+            (
+                PROOF_FORMAT,
+                ProofVersion::Mini,
+                ProofVariant::Vanilla,
+                &[9][..]
+            ),
+            open_formatted_envelope(&envelope).unwrap()
+        );
+
+        // Formats this release doesn't know of are still opened.
+        let envelope = seal_formatted_envelope(15, ProofVersion::Mini, ProofVariant::Snark, &[9]);
+        assert_eq!(15, open_formatted_envelope(&envelope).unwrap().0);
+    }
+
+    #[test]
+    fn bad_envelopes_are_refused() {
+        match open_envelope(&[]) {
+            Err(SealProofErr::Empty) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        match open_envelope(&[7, 1, 2, 3]) {
+            Err(SealProofErr::UnknownVariant(7)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn proofs_past_the_limit_are_refused() {
+        assert!(check_proof_bytes(ProofVariant::Snark, 1000, None).is_ok());
+        assert!(check_proof_bytes(ProofVariant::Snark, 1000, Some(1000)).is_ok());
+
+        match check_proof_bytes(ProofVariant::Vanilla, 1001, Some(1000)) {
+            Err(SealProofErr::TooLarge {
+                variant: ProofVariant::Vanilla,
+                bytes: 1001,
+                max_bytes: 1000,
+            }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,336 @@
+//! Admission of seals to the sealers. Seals are queued in the order they're
+//! scheduled and handed to the sealers one by one, the next only once the
+//! builder's SealScheduling allows it: while fewer seals run than there are
+//! sealers, the memory they're estimated to take leaves room for it in the
+//! budget, and enough time has passed since the last one started. A seal is
+//! always admitted when none is running, however much memory it's estimated
+//! to take, so that a budget too small for any seal still lets them through,
+//! one at a time.
+//!
+//! By default there's neither a budget nor a stagger, and seals are admitted
+//! as soon as a sealer is free, as they were before scheduling: the size of
+//! the task pool (see task::set_pool_size) limits how many run at once.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant, SystemTime};
+
+/// How many of the seals which have finished are kept, for inspection.
+pub const FINISHED_SEALS_KEPT: usize = 64;
+
+/// How a sector builder spreads its seals out over time.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct SealScheduling {
+    /// The most memory the seals running at once may be estimated to take, or
+    /// None for no budget.
+    pub memory_budget_bytes: Option<u64>,
+
+    /// The least time between the starts of two seals.
+    pub min_stagger: Duration,
+}
+
+impl SealScheduling {
+    /// The scheduling the C API configures, where 0 stands for no budget and
+    /// no stagger.
+    pub fn from_ffi(memory_budget_bytes: u64, min_stagger_millis: u64) -> SealScheduling {
+        SealScheduling {
+            memory_budget_bytes: Some(memory_budget_bytes).filter(|bytes| *bytes > 0),
+            min_stagger: Duration::from_millis(min_stagger_millis),
+        }
+    }
+}
+
+/// A seal admitted to the sealers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SealJob {
+    pub sector_id: u64,
+    /// The memory the seal was estimated to take, counted against the budget
+    /// while it runs.
+    pub memory_bytes: u64,
+    pub admitted_at: SystemTime,
+    /// When the seal finished, whether or not it succeeded, if it has.
+    pub finished_at: Option<SystemTime>,
+}
+
+/// The seals of a builder, as its SealQueue sees them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SealAdmissions {
+    /// The seals admitted which haven't finished, in the order admitted.
+    pub running: Vec<SealJob>,
+    /// The ids of the sectors whose seals wait to be admitted, next first.
+    pub queued: Vec<u64>,
+    /// The last FINISHED_SEALS_KEPT seals which finished, oldest first.
+    pub finished: Vec<SealJob>,
+    pub memory_budget_bytes: Option<u64>,
+    /// The memory the running seals are estimated to take.
+    pub memory_in_use_bytes: u64,
+    /// The most seals which may run at once: one per sealer.
+    pub max_running: usize,
+}
+
+/// Queues seals, of some job type T, until the scheduling admits them.
+pub struct SealQueue<T> {
+    scheduling: SealScheduling,
+    max_running: usize,
+    seal_memory_bytes: u64,
+    queued: VecDeque<(u64, T)>,
+    running: Vec<SealJob>,
+    finished: VecDeque<SealJob>,
+    last_admitted: Option<Instant>,
+}
+
+impl<T> SealQueue<T> {
+    /// A queue admitting at most max_running seals at once, each estimated to
+    /// take seal_memory_bytes.
+    pub fn new(scheduling: SealScheduling, max_running: usize, seal_memory_bytes: u64) -> Self {
+        SealQueue {
+            scheduling,
+            max_running: max_running.max(1),
+            seal_memory_bytes,
+            queued: VecDeque::new(),
+            running: Vec::new(),
+            finished: VecDeque::new(),
+            last_admitted: None,
+        }
+    }
+
+    pub fn push(&mut self, sector_id: u64, job: T) {
+        self.queued.push_back((sector_id, job));
+    }
+
+    /// Admits as many of the queued seals, in order, as the scheduling allows
+    /// at now, returning their jobs.
+    pub fn admit(&mut self, now: Instant) -> Vec<T> {
+        let mut admitted = Vec::new();
+
+        while self.fits() && self.stagger_left(now).is_none() {
+            let (sector_id, job) = match self.queued.pop_front() {
+                Some(next) => next,
+                None => break,
+            };
+
+            self.running.push(SealJob {
+                sector_id,
+                memory_bytes: self.seal_memory_bytes,
+                admitted_at: SystemTime::now(),
+                finished_at: None,
+            });
+            self.last_admitted = Some(now);
+            admitted.push(job);
+        }
+
+        admitted
+    }
+
+    /// Records the seal of the sector with the given id as finished, leaving
+    /// room for the next.
+    pub fn finish(&mut self, sector_id: u64) {
+        if let Some(index) = self.running.iter().position(|j| j.sector_id == sector_id) {
+            let mut job = self.running.remove(index);
+            job.finished_at = Some(SystemTime::now());
+
+            if self.finished.len() == FINISHED_SEALS_KEPT {
+                self.finished.pop_front();
+            }
+            self.finished.push_back(job);
+        }
+    }
+
+    /// Takes every queued seal out of the queue, next first, e.g. so that it
+    /// may be persisted rather than admitted.
+    pub fn withdraw_queued(&mut self) -> Vec<(u64, T)> {
+        self.queued.drain(..).collect()
+    }
+
+    /// The ids of the sectors whose seals are running, in the order admitted.
+    pub fn running_sector_ids(&self) -> Vec<u64> {
+        self.running.iter().map(|job| job.sector_id).collect()
+    }
+
+    /// How long after now the next queued seal may be admitted, if only the
+    /// stagger holds it back. None if nothing is queued, or if the next seal
+    /// waits for a running one to finish.
+    pub fn next_admission_in(&self, now: Instant) -> Option<Duration> {
+        if self.queued.is_empty() || !self.fits() {
+            return None;
+        }
+
+        self.stagger_left(now)
+    }
+
+    pub fn admissions(&self) -> SealAdmissions {
+        SealAdmissions {
+            running: self.running.clone(),
+            queued: self
+                .queued
+                .iter()
+                .map(|(sector_id, _)| *sector_id)
+                .collect(),
+            finished: self.finished.iter().cloned().collect(),
+            memory_budget_bytes: self.scheduling.memory_budget_bytes,
+            memory_in_use_bytes: self.memory_in_use_bytes(),
+            max_running: self.max_running,
+        }
+    }
+
+    // Whether another seal may run beside those running.
+    fn fits(&self) -> bool {
+        if self.running.is_empty() {
+            return true;
+        }
+
+        if self.running.len() >= self.max_running {
+            return false;
+        }
+
+        match self.scheduling.memory_budget_bytes {
+            Some(budget) => self.memory_in_use_bytes() + self.seal_memory_bytes <= budget,
+            None => true,
+        }
+    }
+
+    // What's left at now of the stagger after the last seal admitted, if
+    // anything is.
+    fn stagger_left(&self, now: Instant) -> Option<Duration> {
+        let next = self.last_admitted? + self.scheduling.min_stagger;
+
+        if next > now {
+            Some(next - now)
+        } else {
+            None
+        }
+    }
+
+    fn memory_in_use_bytes(&self) -> u64 {
+        self.running.iter().map(|job| job.memory_bytes).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEAL_MEMORY_BYTES: u64 = 1000;
+
+    fn queue(memory_budget_bytes: Option<u64>, min_stagger: Duration) -> SealQueue<u64> {
+        let scheduling = SealScheduling {
+            memory_budget_bytes,
+            min_stagger,
+        };
+
+        SealQueue::new(scheduling, 2, SEAL_MEMORY_BYTES)
+    }
+
+    #[test]
+    fn by_default_seals_are_admitted_while_sealers_are_free() {
+        let mut queue = queue(None, Duration::from_secs(0));
+        let now = Instant::now();
+
+        for sector_id in 1..=3 {
+            queue.push(sector_id, sector_id);
+        }
+
+        assert_eq!(vec![1, 2], queue.admit(now));
+        assert_eq!(vec![3], queue.admissions().queued);
+        assert_eq!(2000, queue.admissions().memory_in_use_bytes);
+        assert_eq!(None, queue.next_admission_in(now));
+
+        queue.finish(2);
+        assert_eq!(vec![3], queue.admit(now));
+
+        let admissions = queue.admissions();
+        let running: Vec<u64> = admissions.running.iter().map(|j| j.sector_id).collect();
+        assert_eq!(vec![1, 3], running);
+        assert_eq!(2, admissions.finished[0].sector_id);
+        assert!(admissions.finished[0].finished_at.is_some());
+    }
+
+    #[test]
+    fn a_memory_budget_serializes_seals_which_would_exceed_it() {
+        let mut queue = queue(Some(1500), Duration::from_secs(0));
+        let now = Instant::now();
+
+        queue.push(1, 1);
+        queue.push(2, 2);
+
+        assert_eq!(vec![1], queue.admit(now));
+        assert_eq!(vec![2], queue.admissions().queued);
+        assert_eq!(Some(1500), queue.admissions().memory_budget_bytes);
+        assert!(queue.admit(now).is_empty());
+
+        queue.finish(1);
+        assert_eq!(vec![2], queue.admit(now));
+
+        // The second seal was admitted only after the first had finished.
+        queue.finish(2);
+        let finished = queue.admissions().finished;
+        assert!(finished[1].admitted_at >= finished[0].finished_at.unwrap());
+    }
+
+    #[test]
+    fn a_budget_too_small_for_any_seal_admits_one_at_a_time() {
+        let mut queue = queue(Some(1), Duration::from_secs(0));
+        let now = Instant::now();
+
+        queue.push(1, 1);
+        queue.push(2, 2);
+
+        assert_eq!(vec![1], queue.admit(now));
+        queue.finish(1);
+        assert_eq!(vec![2], queue.admit(now));
+    }
+
+    #[test]
+    fn seal_starts_are_staggered() {
+        let stagger = Duration::from_secs(10);
+        let mut queue = queue(None, stagger);
+        let now = Instant::now();
+
+        queue.push(1, 1);
+        queue.push(2, 2);
+
+        assert_eq!(vec![1], queue.admit(now));
+        assert_eq!(Some(stagger), queue.next_admission_in(now));
+        assert!(queue.admit(now + Duration::from_secs(4)).is_empty());
+        assert_eq!(
+            Some(Duration::from_secs(6)),
+            queue.next_admission_in(now + Duration::from_secs(4))
+        );
+
+        assert_eq!(vec![2], queue.admit(now + stagger));
+        assert_eq!(None, queue.next_admission_in(now + stagger));
+    }
+
+    #[test]
+    fn withdrawn_seals_are_never_admitted() {
+        let mut queue = queue(Some(1), Duration::from_secs(0));
+        let now = Instant::now();
+
+        for sector_id in 1..=3 {
+            queue.push(sector_id, sector_id);
+        }
+
+        assert_eq!(vec![1], queue.admit(now));
+        assert_eq!(vec![(2, 2), (3, 3)], queue.withdraw_queued());
+        assert!(queue.admissions().queued.is_empty());
+        assert_eq!(vec![1], queue.running_sector_ids());
+
+        queue.finish(1);
+        assert!(queue.admit(now).is_empty());
+        assert!(queue.running_sector_ids().is_empty());
+    }
+
+    #[test]
+    fn only_the_last_finished_seals_are_kept() {
+        let mut queue = queue(None, Duration::from_secs(0));
+
+        for sector_id in 0..FINISHED_SEALS_KEPT as u64 + 10 {
+            queue.push(sector_id, sector_id);
+            queue.admit(Instant::now());
+            queue.finish(sector_id);
+        }
+
+        let finished = queue.admissions().finished;
+        assert_eq!(FINISHED_SEALS_KEPT, finished.len());
+        assert_eq!(10, finished[0].sector_id);
+    }
+}
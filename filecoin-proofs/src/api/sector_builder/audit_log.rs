@@ -0,0 +1,559 @@
+//! A tamper-evident, append-only log of what a sector builder did with the
+//! pieces it was given: the pieces it staged, the sectors it sealed (or failed
+//! to), the proofs-of-spacetime it generated and the faults they declared. It's
+//! kept for settling disputes with clients, e.g. over whether their piece was
+//! ever sealed.
+//!
+//! The log is a file of records, each a little-endian u32 length followed by
+//! that many bytes of CBOR-encoded AuditRecord. Every record holds the Blake2b
+//! hash (cut to 32 bytes) of the bytes of the record before it, the first
+//! holding zeros, so that altering, removing or inserting a record breaks the
+//! chain at that record. The last record is chained to by none, so alterations
+//! of it are only caught against a head hash (see AuditSummary) archived
+//! elsewhere.
+//!
+//! Records are written by a thread of the log's own, fed through a bounded
+//! queue, so that the operations they record never wait on the disk. Each is
+//! synced as it's written. Recording never fails the operation recorded:
+//! should the queue be full, or a write fail, the record is lost, which is
+//! counted in the log's AuditLogStats and logged.
+
+use crate::error;
+use crate::FCP_LOG;
+use blake2::{Blake2b, Digest};
+use byteorder::{ByteOrder, LittleEndian};
+use serde::{Deserialize, Serialize};
+use slog::*;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{SystemTime, UNIX_EPOCH};
+use storage_proofs::types::Commitment;
+
+/// The name of the log in a builder's metadata directory.
+pub const AUDIT_LOG_FILE: &str = "audit.log";
+
+/// The most records waiting to be written before further records are dropped.
+pub const AUDIT_QUEUE_RECORDS: usize = 1024;
+
+const FRAME_LENGTH_BYTES: usize = 4;
+
+pub type AuditHash = [u8; 32];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum AuditEvent {
+    PieceAdded {
+        piece_key: String,
+        sector_id: u64,
+        comm_p: Option<Commitment>,
+    },
+    SectorSealed {
+        sector_id: u64,
+        comm_d: Commitment,
+        comm_r: Commitment,
+        comm_r_star: Commitment,
+        proof_digest: AuditHash,
+    },
+    SealFailed {
+        sector_id: u64,
+        error: String,
+    },
+    PoStGenerated {
+        challenge_seed: [u8; 32],
+        comm_rs: Vec<Commitment>,
+        proof_digest: AuditHash,
+    },
+    FaultsDeclared {
+        challenge_seed: [u8; 32],
+        sector_ids: Vec<u64>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// The record's position in the log, from 0.
+    pub index: u64,
+    /// When the event was recorded, in seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// The hash of the bytes of the record before this one.
+    pub prev_hash: AuditHash,
+    pub event: AuditEvent,
+}
+
+/// Where a log's chain first breaks.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditBreak {
+    /// The record's bytes aren't a record, e.g. as they were cut short.
+    Malformed { index: u64, reason: String },
+    /// The record doesn't hold the index of its position in the log.
+    Misnumbered { index: u64, recorded: u64 },
+    /// The first record chains to a record before it, so records were removed
+    /// from the start of the log.
+    Unanchored,
+    /// The hash of the record's bytes isn't the one the record after it
+    /// chains to: the record was altered (or, less likely, that hash was).
+    Altered { index: u64 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditSummary {
+    /// The number of records before the first break, if any, i.e. those
+    /// vouched for by the chain.
+    pub intact_records: u64,
+    /// The hash of the last intact record, which an archived copy of the
+    /// log's head may be compared to.
+    pub head_hash: AuditHash,
+    pub first_break: Option<AuditBreak>,
+}
+
+/// How the records a log was given fared, since it was started.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct AuditLogStats {
+    pub written: u64,
+    /// Dropped as the queue was full, or the writer gone.
+    pub dropped: u64,
+    /// Lost as they couldn't be written.
+    pub failed: u64,
+}
+
+#[derive(Debug, Default)]
+struct Counters {
+    written: AtomicUsize,
+    dropped: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+enum AuditInput {
+    Record(u64, AuditEvent),
+    Flush(mpsc::SyncSender<()>),
+    Shutdown,
+}
+
+/// A handle on a builder's audit log, shared by everything which records to
+/// it.
+#[derive(Clone)]
+pub struct AuditLog {
+    path: PathBuf,
+    tx: Option<mpsc::SyncSender<AuditInput>>,
+    counters: Arc<Counters>,
+}
+
+pub fn audit_log_path<P: AsRef<Path>>(metadata_dir: P) -> PathBuf {
+    metadata_dir.as_ref().join(AUDIT_LOG_FILE)
+}
+
+/// The hash with which the record after one with the given bytes chains to it.
+pub fn audit_hash(record_bytes: &[u8]) -> AuditHash {
+    let mut hash = [0; 32];
+    hash.copy_from_slice(&Blake2b::digest(record_bytes)[..32]);
+    hash
+}
+
+pub fn decode_audit_record(record_bytes: &[u8]) -> error::Result<AuditRecord> {
+    Ok(serde_cbor::from_slice(record_bytes)?)
+}
+
+impl AuditLog {
+    /// Starts the writer of the log at path, which continues the chain of the
+    /// records already there. Returns the log and the writer's thread, which
+    /// returns once the log is shut down.
+    pub fn start(path: PathBuf) -> (AuditLog, thread::JoinHandle<()>) {
+        let (tx, rx) = mpsc::sync_channel(AUDIT_QUEUE_RECORDS);
+        let counters: Arc<Counters> = Default::default();
+
+        let thread = {
+            let (path, counters) = (path.clone(), counters.clone());
+            thread::spawn(move || write_records(&path, &rx, &counters))
+        };
+
+        let log = AuditLog {
+            path,
+            tx: Some(tx),
+            counters,
+        };
+
+        (log, thread)
+    }
+
+    /// A log whose records are read from path, but to which nothing is
+    /// recorded, as for read-only builders.
+    pub fn read_only(path: PathBuf) -> AuditLog {
+        AuditLog {
+            path,
+            tx: None,
+            counters: Default::default(),
+        }
+    }
+
+    /// Queues the event to be recorded, without waiting for it to be written.
+    pub fn record(&self, event: AuditEvent) {
+        let tx = match &self.tx {
+            Some(tx) => tx,
+            None => return,
+        };
+
+        if let Err(err) = tx.try_send(AuditInput::Record(now(), event)) {
+            self.counters.dropped.fetch_add(1, Ordering::SeqCst);
+
+            let reason = match err {
+                mpsc::TrySendError::Full(_) => "queue full",
+                mpsc::TrySendError::Disconnected(_) => "writer gone",
+            };
+            warn!(FCP_LOG, "dropped audit record"; "reason" => reason);
+        }
+    }
+
+    /// Waits until every event recorded so far has been written.
+    pub fn flush(&self) {
+        if let Some(tx) = &self.tx {
+            let (ack_tx, ack_rx) = mpsc::sync_channel(0);
+
+            if tx.send(AuditInput::Flush(ack_tx)).is_ok() {
+                let _ = ack_rx.recv();
+            }
+        }
+    }
+
+    /// Has the writer write every event recorded so far, then return.
+    pub fn shutdown(&self) {
+        if let Some(tx) = &self.tx {
+            let _ = tx.send(AuditInput::Shutdown);
+        }
+    }
+
+    pub fn stats(&self) -> AuditLogStats {
+        AuditLogStats {
+            written: self.counters.written.load(Ordering::SeqCst) as u64,
+            dropped: self.counters.dropped.load(Ordering::SeqCst) as u64,
+            failed: self.counters.failed.load(Ordering::SeqCst) as u64,
+        }
+    }
+
+    /// Returns the bytes of the records from since_record on, once every event
+    /// recorded so far has been written, for archiving elsewhere.
+    pub fn export(&self, since_record: u64) -> error::Result<Vec<Vec<u8>>> {
+        self.flush();
+
+        let bytes = match fs::read(&self.path) {
+            Ok(bytes) => bytes,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+        let (records, _) = split_frames(&bytes);
+
+        Ok(records
+            .into_iter()
+            .skip(since_record as usize)
+            .map(<[u8]>::to_vec)
+            .collect())
+    }
+}
+
+/// Recomputes the chain of the log at path, reporting where it first breaks.
+pub fn verify_audit_log<P: AsRef<Path>>(path: P) -> error::Result<AuditSummary> {
+    let bytes = fs::read(path)?;
+    let (records, rest) = split_frames(&bytes);
+
+    let mut head_hash = [0; 32];
+    let mut first_break = None;
+
+    for (index, record_bytes) in records.iter().enumerate() {
+        let index = index as u64;
+
+        let record = match decode_audit_record(record_bytes) {
+            Ok(record) => record,
+            Err(err) => {
+                let reason = err.to_string();
+                first_break = Some(AuditBreak::Malformed { index, reason });
+                break;
+            }
+        };
+
+        if record.prev_hash != head_hash {
+            first_break = Some(if index == 0 {
+                AuditBreak::Unanchored
+            } else {
+                AuditBreak::Altered { index: index - 1 }
+            });
+            break;
+        }
+
+        if record.index != index {
+            let recorded = record.index;
+            first_break = Some(AuditBreak::Misnumbered { index, recorded });
+            break;
+        }
+
+        head_hash = audit_hash(record_bytes);
+    }
+
+    if first_break.is_none() && !rest.is_empty() {
+        first_break = Some(AuditBreak::Malformed {
+            index: records.len() as u64,
+            reason: format!("{} trailing bytes", rest.len()),
+        });
+    }
+
+    // Records from the break on aren't vouched for.
+    let intact_records = match &first_break {
+        None => records.len() as u64,
+        Some(AuditBreak::Unanchored) => 0,
+        Some(AuditBreak::Altered { index }) => *index,
+        Some(AuditBreak::Malformed { index, .. }) => *index,
+        Some(AuditBreak::Misnumbered { index, .. }) => *index,
+    };
+
+    Ok(AuditSummary {
+        intact_records,
+        head_hash,
+        first_break,
+    })
+}
+
+// Splits a log into the bytes of its whole records, and whatever follows them,
+// e.g. a record cut short by a crash.
+fn split_frames(bytes: &[u8]) -> (Vec<&[u8]>, &[u8]) {
+    let mut records = Vec::new();
+    let mut rest = bytes;
+
+    while rest.len() >= FRAME_LENGTH_BYTES {
+        let len = LittleEndian::read_u32(&rest[..FRAME_LENGTH_BYTES]) as usize;
+        if rest.len() - FRAME_LENGTH_BYTES < len {
+            break;
+        }
+
+        records.push(&rest[FRAME_LENGTH_BYTES..FRAME_LENGTH_BYTES + len]);
+        rest = &rest[FRAME_LENGTH_BYTES + len..];
+    }
+
+    (records, rest)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+// The end of a log's chain, which the next record written continues.
+struct ChainHead {
+    file: File,
+    len: u64,
+    next_index: u64,
+    prev_hash: AuditHash,
+}
+
+impl ChainHead {
+    // Opens the log at path, creating it if needs be. A record cut short by a
+    // crash is cut off, so that the chain continues from the last whole one.
+    fn open(path: &Path) -> io::Result<ChainHead> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err),
+        };
+        let (records, rest) = split_frames(&bytes);
+
+        let file = OpenOptions::new().create(true).write(true).open(path)?;
+        let len = (bytes.len() - rest.len()) as u64;
+        if !rest.is_empty() {
+            warn!(FCP_LOG, "cutting off partial audit record"; "bytes" => rest.len());
+            file.set_len(len)?;
+            file.sync_all()?;
+        }
+
+        Ok(ChainHead {
+            file,
+            len,
+            next_index: records.len() as u64,
+            prev_hash: records.last().map_or([0; 32], |&bytes| audit_hash(bytes)),
+        })
+    }
+
+    fn append(&mut self, timestamp: u64, event: AuditEvent) -> error::Result<()> {
+        let record = AuditRecord {
+            index: self.next_index,
+            timestamp,
+            prev_hash: self.prev_hash,
+            event,
+        };
+        let record_bytes = serde_cbor::to_vec(&record)?;
+
+        let mut frame = vec![0; FRAME_LENGTH_BYTES];
+        LittleEndian::write_u32(&mut frame, record_bytes.len() as u32);
+        frame.extend_from_slice(&record_bytes);
+
+        let written = self
+            .file
+            .seek(SeekFrom::Start(self.len))
+            .and_then(|_| self.file.write_all(&frame))
+            .and_then(|_| self.file.sync_data());
+
+        if let Err(err) = written {
+            // Leave no partial record for the next one to follow.
+            let _ = self.file.set_len(self.len);
+            return Err(err.into());
+        }
+
+        self.len += frame.len() as u64;
+        self.next_index += 1;
+        self.prev_hash = audit_hash(&record_bytes);
+
+        Ok(())
+    }
+}
+
+fn write_records(path: &Path, rx: &mpsc::Receiver<AuditInput>, counters: &Counters) {
+    let mut head = ChainHead::open(path);
+    if let Err(err) = &head {
+        error!(FCP_LOG, "could not open audit log"; "path" => format!("{:?}", path), "error" => err.to_string());
+    }
+
+    for input in rx {
+        match input {
+            AuditInput::Record(timestamp, event) => {
+                let appended = match &mut head {
+                    Ok(head) => head.append(timestamp, event),
+                    Err(err) => Err(format_err!("audit log not open: {}", err)),
+                };
+
+                match appended {
+                    Ok(()) => counters.written.fetch_add(1, Ordering::SeqCst),
+                    Err(err) => {
+                        error!(FCP_LOG, "could not write audit record"; "error" => err.to_string());
+                        counters.failed.fetch_add(1, Ordering::SeqCst)
+                    }
+                };
+            }
+            AuditInput::Flush(ack) => {
+                let _ = ack.send(());
+            }
+            AuditInput::Shutdown => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn piece(n: u64) -> AuditEvent {
+        AuditEvent::PieceAdded {
+            piece_key: format!("piece-{}", n),
+            sector_id: 1,
+            comm_p: Some([n as u8; 32]),
+        }
+    }
+
+    fn run(path: &Path, events: Vec<AuditEvent>) -> AuditLogStats {
+        let (log, writer) = AuditLog::start(path.to_path_buf());
+        for event in events {
+            log.record(event);
+        }
+        log.shutdown();
+        writer.join().unwrap();
+
+        log.stats()
+    }
+
+    #[test]
+    fn chains_continue_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = audit_log_path(dir.path());
+
+        assert_eq!(2, run(&path, vec![piece(0), piece(1)]).written);
+        assert_eq!(3, run(&path, vec![piece(2), piece(3), piece(4)]).written);
+
+        let summary = verify_audit_log(&path).unwrap();
+        assert_eq!(None, summary.first_break);
+        assert_eq!(5, summary.intact_records);
+
+        let (log, writer) = AuditLog::start(path.clone());
+        let exported = log.export(3).unwrap();
+        log.shutdown();
+        writer.join().unwrap();
+
+        let records: Vec<AuditRecord> = exported
+            .iter()
+            .map(|bytes| decode_audit_record(bytes).unwrap())
+            .collect();
+        assert_eq!(
+            vec![3, 4],
+            records.iter().map(|r| r.index).collect::<Vec<_>>()
+        );
+        assert_eq!(piece(3), records[0].event);
+        assert_eq!(audit_hash(&exported[0]), records[1].prev_hash);
+        assert_eq!(summary.head_hash, audit_hash(&exported[1]));
+    }
+
+    #[test]
+    fn tampering_is_pinpointed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = audit_log_path(dir.path());
+        run(&path, (0..5).map(piece).collect());
+
+        // Alter the key of the middle record, keeping it well-formed.
+        let mut bytes = fs::read(&path).unwrap();
+        let at = bytes.windows(7).position(|w| w == b"piece-2").unwrap();
+        bytes[at + 6] = b'9';
+        fs::write(&path, &bytes).unwrap();
+
+        let summary = verify_audit_log(&path).unwrap();
+        assert_eq!(Some(AuditBreak::Altered { index: 2 }), summary.first_break);
+        assert_eq!(2, summary.intact_records);
+
+        // Cutting off the first record unanchors the chain.
+        let (records, _) = split_frames(&bytes);
+        let first_len = FRAME_LENGTH_BYTES + records[0].len();
+        fs::write(&path, &bytes[first_len..]).unwrap();
+
+        let summary = verify_audit_log(&path).unwrap();
+        assert_eq!(Some(AuditBreak::Unanchored), summary.first_break);
+        assert_eq!(0, summary.intact_records);
+    }
+
+    #[test]
+    fn partial_records_are_cut_off_before_appending() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = audit_log_path(dir.path());
+        run(&path, vec![piece(0), piece(1)]);
+
+        // A crash cut the last record short.
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        match verify_audit_log(&path).unwrap().first_break {
+            Some(AuditBreak::Malformed { index, .. }) => assert_eq!(1, index),
+            other => panic!("unexpected break: {:?}", other),
+        }
+
+        run(&path, vec![piece(2)]);
+
+        let summary = verify_audit_log(&path).unwrap();
+        assert_eq!(None, summary.first_break);
+        assert_eq!(2, summary.intact_records);
+    }
+
+    #[test]
+    fn read_only_logs_record_nothing() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = audit_log_path(dir.path());
+
+        let log = AuditLog::read_only(path.clone());
+        log.record(piece(0));
+        log.flush();
+
+        assert!(!path.exists());
+        assert_eq!(AuditLogStats::default(), log.stats());
+        assert!(log.export(0).unwrap().is_empty());
+    }
+}
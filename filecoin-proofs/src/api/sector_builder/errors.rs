@@ -1,3 +1,4 @@
+use crate::api::sector_builder::metadata::PiecePlacement;
 use failure::Backtrace;
 use std::fmt::Display;
 
@@ -21,9 +22,96 @@ pub enum SectorBuilderErr {
         num_bytes_in_piece: u64,
     },
 
+    #[fail(
+        display = "piece source did not produce exactly {} bytes",
+        num_bytes_in_piece
+    )]
+    PieceLengthMismatch { num_bytes_in_piece: u64 },
+
     #[fail(display = "no piece with key {} found", _0)]
     PieceNotFound(String),
 
+    #[fail(
+        display = "piece has the same bytes as piece {} in sector {}",
+        existing_key, existing_sector
+    )]
+    DuplicatePiece {
+        existing_key: String,
+        existing_sector: u64,
+    },
+
+    #[fail(
+        display = "staged data for sector {} does not match its pieces",
+        sector_id
+    )]
+    StagedDataMismatch { sector_id: u64 },
+
+    #[fail(
+        display = "staged data for sector {} has changed since it was sealed",
+        sector_id
+    )]
+    StagedDataChanged { sector_id: u64 },
+
+    #[fail(
+        display = "sector id {} is recorded, but outside of the configured range {}..={}",
+        sector_id, first, last
+    )]
+    SectorIdOutOfRange {
+        sector_id: u64,
+        first: u64,
+        last: u64,
+    },
+
+    #[fail(
+        display = "every sector id in the range {}..={} has been allocated",
+        first, last
+    )]
+    SectorIdsExhausted { first: u64, last: u64 },
+
+    #[fail(display = "no sealed sector with id {} found", sector_id)]
+    SealedSectorNotFound { sector_id: u64 },
+
+    #[fail(display = "a sector with id {} is already known", sector_id)]
+    SectorIdCollision { sector_id: u64 },
+
+    #[fail(display = "sector {} was sealed for another prover", sector_id)]
+    ProverIdMismatch { sector_id: u64 },
+
+    #[fail(
+        display = "copy of sealed sector {} does not match its checksum",
+        sector_id
+    )]
+    SealedSectorChecksumMismatch { sector_id: u64 },
+
+    #[fail(display = "sectors can't be migrated: {}", _0)]
+    InvalidMigration(String),
+
+    #[fail(
+        display = "piece {} of sector {} does not match its comm_p once unsealed",
+        piece_key, sector_id
+    )]
+    MigratedPieceMismatch {
+        sector_id: u64,
+        piece_key: String,
+    },
+
+    #[fail(display = "pieces can't be staged as planned: {}", _0)]
+    PlacementPlanMismatch(String),
+
+    #[fail(display = "piece keys can't be kept private: {}", _0)]
+    PieceKeysNotPrivate(String),
+
+    #[fail(
+        display = "the piece key material does not match the one the metadata was made private with"
+    )]
+    PieceKeyMaterialMismatch,
+
+    #[fail(display = "piece keys are private, and no piece key material was given")]
+    PieceKeyMaterialMissing,
+
+    #[fail(display = "the sector builder is shutting down")]
+    ShuttingDown,
+
     #[fail(display = "unrecoverable error: {}", _0)]
     Unrecoverable(String, Backtrace),
 }
@@ -32,6 +120,13 @@ pub fn err_piecenotfound(piece_key: String) -> SectorBuilderErr {
     SectorBuilderErr::PieceNotFound(piece_key)
 }
 
+pub fn err_duplicate_piece(existing: PiecePlacement) -> SectorBuilderErr {
+    SectorBuilderErr::DuplicatePiece {
+        existing_key: existing.piece_key,
+        existing_sector: existing.sector_id,
+    }
+}
+
 pub fn err_unrecov<S: Display>(msg: S) -> SectorBuilderErr {
     let backtrace = failure::Backtrace::new();
     SectorBuilderErr::Unrecoverable(format!("{}", msg), backtrace)
@@ -44,9 +139,127 @@ pub fn err_overflow(num_bytes_in_piece: u64, max_bytes_per_sector: u64) -> Secto
     }
 }
 
+pub fn err_staged_data_mismatch(sector_id: u64) -> SectorBuilderErr {
+    SectorBuilderErr::StagedDataMismatch { sector_id }
+}
+
+pub fn err_staged_data_changed(sector_id: u64) -> SectorBuilderErr {
+    SectorBuilderErr::StagedDataChanged { sector_id }
+}
+
 pub fn err_inc_write(num_bytes_written: u64, num_bytes_in_piece: u64) -> SectorBuilderErr {
     SectorBuilderErr::IncompleteWriteError {
         num_bytes_written,
         num_bytes_in_piece,
     }
 }
+
+pub fn err_piece_len(num_bytes_in_piece: u64) -> SectorBuilderErr {
+    SectorBuilderErr::PieceLengthMismatch { num_bytes_in_piece }
+}
+
+pub fn err_sector_id_out_of_range(sector_id: u64, first: u64, last: u64) -> SectorBuilderErr {
+    SectorBuilderErr::SectorIdOutOfRange {
+        sector_id,
+        first,
+        last,
+    }
+}
+
+pub fn err_sector_ids_exhausted(first: u64, last: u64) -> SectorBuilderErr {
+    SectorBuilderErr::SectorIdsExhausted { first, last }
+}
+
+pub fn err_sealed_sector_not_found(sector_id: u64) -> SectorBuilderErr {
+    SectorBuilderErr::SealedSectorNotFound { sector_id }
+}
+
+pub fn err_sector_id_collision(sector_id: u64) -> SectorBuilderErr {
+    SectorBuilderErr::SectorIdCollision { sector_id }
+}
+
+pub fn err_prover_id_mismatch(sector_id: u64) -> SectorBuilderErr {
+    SectorBuilderErr::ProverIdMismatch { sector_id }
+}
+
+pub fn err_sealed_checksum_mismatch(sector_id: u64) -> SectorBuilderErr {
+    SectorBuilderErr::SealedSectorChecksumMismatch { sector_id }
+}
+
+pub fn err_invalid_migration<S: Display>(reason: S) -> SectorBuilderErr {
+    SectorBuilderErr::InvalidMigration(format!("{}", reason))
+}
+
+pub fn err_migrated_piece_mismatch(sector_id: u64, piece_key: String) -> SectorBuilderErr {
+    SectorBuilderErr::MigratedPieceMismatch {
+        sector_id,
+        piece_key,
+    }
+}
+
+pub fn err_placement_plan_mismatch<S: Display>(reason: S) -> SectorBuilderErr {
+    SectorBuilderErr::PlacementPlanMismatch(format!("{}", reason))
+}
+
+pub fn err_shutting_down() -> SectorBuilderErr {
+    SectorBuilderErr::ShuttingDown
+}
+
+// Reasons a persisted snapshot of a builder's metadata can't be loaded.
+#[derive(Debug, Fail)]
+pub enum MetadataErr {
+    #[fail(display = "snapshot has unsupported version {}", _0)]
+    UnsupportedVersion(u16),
+
+    #[fail(display = "snapshot does not match its checksum")]
+    ChecksumMismatch,
+
+    #[fail(display = "malformed snapshot: {}", _0)]
+    Malformed(String),
+
+    #[fail(
+        display = "invalid {} in metadata of sector {}: {}",
+        field, sector_id, reason
+    )]
+    InvalidField {
+        sector_id: u64,
+        field: String,
+        reason: String,
+    },
+}
+
+pub fn err_malformed_metadata<S: Display>(msg: S) -> MetadataErr {
+    MetadataErr::Malformed(format!("{}", msg))
+}
+
+pub fn err_invalid_field<F: Display, R: Display>(
+    sector_id: u64,
+    field: F,
+    reason: R,
+) -> MetadataErr {
+    MetadataErr::InvalidField {
+        sector_id,
+        field: format!("{}", field),
+        reason: format!("{}", reason),
+    }
+}
+
+// Reasons a work queue's journal can't be opened, or an entry changed.
+#[derive(Debug, Fail)]
+pub enum WorkQueueErr {
+    #[fail(display = "work queue has unsupported version {}", _0)]
+    UnsupportedVersion(u16),
+
+    #[fail(display = "malformed work queue: {}", _0)]
+    Malformed(String),
+
+    #[fail(display = "no entry with id {} in work queue", _0)]
+    NoSuchEntry(u64),
+
+    #[fail(display = "entry with id {} in work queue is started already", _0)]
+    AlreadyStarted(u64),
+}
+
+pub fn err_malformed_queue<S: Display>(msg: S) -> WorkQueueErr {
+    WorkQueueErr::Malformed(format!("{}", msg))
+}
@@ -1,22 +1,177 @@
 use crate::api::sector_builder::errors::*;
+use crate::api::sector_builder::helpers::piece_index::PieceIndex;
+use crate::api::sector_builder::helpers::piece_intents::{PieceIntent, PieceIntents};
+use crate::api::sector_builder::helpers::sector_ids::SectorIdAllocator;
+use crate::api::sector_builder::helpers::staged_data::CommPBuilder;
 use crate::api::sector_builder::metadata::sum_piece_bytes;
 use crate::api::sector_builder::metadata::StagedSectorMetadata;
+use crate::api::sector_builder::metadata::{DuplicatePiecePolicy, PiecePlacement};
 use crate::api::sector_builder::state::StagedState;
 use crate::api::sector_builder::*;
 use crate::error;
 use sector_base::api::sector_store::SectorManager;
+use std::cmp;
+use std::io::{self, Read};
 use std::sync::Arc;
+use storage_proofs::types::Commitment;
 
 pub fn add_piece(
     sector_store: &Arc<WrappedSectorStore>,
-    mut staged_state: &mut StagedState,
+    staged_state: &mut StagedState,
+    sector_ids: &mut SectorIdAllocator,
     piece_key: String,
     piece_bytes: &[u8],
-) -> error::Result<SectorId> {
+    expires_at: Option<u64>,
+) -> error::Result<u64> {
+    add_piece_from_reader(
+        sector_store,
+        staged_state,
+        sector_ids,
+        piece_key,
+        piece_bytes.len() as u64,
+        expires_at,
+        &mut &piece_bytes[..],
+    )
+}
+
+// Streams a piece of piece_bytes_len bytes from source into a staged sector,
+// recording when its deal expires, if given. If the source produces a
+// different number of bytes or fails, the staged sector is left as it was.
+pub fn add_piece_from_reader(
+    sector_store: &Arc<WrappedSectorStore>,
+    staged_state: &mut StagedState,
+    sector_ids: &mut SectorIdAllocator,
+    piece_key: String,
+    piece_bytes_len: u64,
+    expires_at: Option<u64>,
+    source: &mut Read,
+) -> error::Result<u64> {
+    let sector_mgr = sector_store.inner.manager();
+
+    let sector_id = destination_sector_id(sector_store, staged_state, sector_ids, piece_bytes_len)?;
+    let sector = staged_sector(staged_state, sector_id)?;
+
+    write_piece(sector_mgr, sector, piece_key, piece_bytes_len, expires_at, source)?;
+
+    Ok(sector_id)
+}
+
+// Stages a piece as add_piece_from_reader does, but crash-consistently, by
+// way of an intent recorded in intents (see piece_intents). The intent is
+// left recorded once the piece is staged: it's cleared once the metadata
+// recording the piece has been persisted.
+//
+// A piece which has the same bytes as one of the pieces placed is dealt with
+// as policy says (see DuplicatePiecePolicy). Its comm_p is only known once its
+// bytes have been read, so a piece which isn't to be stored again is staged,
+// and then unstaged, just as a piece whose source fails is.
+#[allow(clippy::too_many_arguments)]
+pub fn add_piece_with_intent(
+    sector_store: &Arc<WrappedSectorStore>,
+    intents: &PieceIntents,
+    staged_state: &mut StagedState,
+    sector_ids: &mut SectorIdAllocator,
+    placed: &PieceIndex,
+    policy: DuplicatePiecePolicy,
+    piece_key: String,
+    piece_bytes_len: u64,
+    expires_at: Option<u64>,
+    source: &mut Read,
+) -> error::Result<PieceOutcome> {
     let sector_mgr = sector_store.inner.manager();
-    let sector_max = sector_store.inner.config().max_unsealed_bytes_per_sector();
 
-    let piece_bytes_len = piece_bytes.len() as u64;
+    let sector_id = destination_sector_id(sector_store, staged_state, sector_ids, piece_bytes_len)?;
+    let sector = staged_sector(staged_state, sector_id)?;
+
+    let checkpoint = sector_mgr.checkpoint_unsealed(&sector.sector_access)?;
+    let mut intent = PieceIntent {
+        piece_key: piece_key.clone(),
+        num_bytes: piece_bytes_len,
+        expires_at,
+        sector_id,
+        sector_access: sector.sector_access.clone(),
+        staged_len: checkpoint.len,
+        staged_last_byte: checkpoint.last_byte,
+        comm_p: None,
+    };
+    intents.record(&intent)?;
+
+    let num_pieces = sector.pieces.len();
+    let written = write_piece(sector_mgr, sector, piece_key, piece_bytes_len, expires_at, source)
+        .and_then(|_| {
+            // Once the piece's bytes are durable, recording its comm_p marks
+            // them as written.
+            sector_mgr.sync_unsealed(&sector.sector_access)?;
+            intent.comm_p = sector.pieces.last().and_then(|piece| piece.comm_p);
+            intents.record(&intent)
+        });
+
+    let outcome = written.and_then(|_| {
+        let duplicate_of = sector
+            .pieces
+            .last()
+            .and_then(|piece| placed.duplicate_of(piece));
+
+        match (duplicate_of, policy) {
+            (Some(existing), DuplicatePiecePolicy::Reject) => {
+                Err(err_duplicate_piece(existing).into())
+            }
+            (Some(existing), DuplicatePiecePolicy::Deduplicate) => {
+                let comm_p = intent
+                    .comm_p
+                    .ok_or_else(|| err_unrecov("piece has no comm_p"))?;
+                Ok(PieceOutcome::Duplicate { existing, comm_p })
+            }
+            (duplicate_of, _) => Ok(PieceOutcome::Staged {
+                sector_id,
+                duplicate_of,
+            }),
+        }
+    });
+
+    if let Ok(PieceOutcome::Staged { .. }) = outcome {
+        return outcome;
+    }
+
+    sector.pieces.truncate(num_pieces);
+    sector_mgr.restore_unsealed(&sector.sector_access, &checkpoint)?;
+
+    // Should clearing fail, the intent is rolled back when next resolved,
+    // which leaves the sector as it is now.
+    let _ = intents.clear();
+
+    outcome
+}
+
+// What add_piece_with_intent did with a piece.
+#[derive(Debug, PartialEq)]
+pub enum PieceOutcome {
+    // The piece was staged in the sector with the given id. If it has the
+    // same bytes as a piece placed before it, that piece's placement is given.
+    Staged {
+        sector_id: u64,
+        duplicate_of: Option<PiecePlacement>,
+    },
+
+    // The piece has the same bytes, with the given comm_p, as the piece
+    // placed as existing, so it wasn't staged: it's to be recorded as an
+    // alias of that piece instead.
+    Duplicate {
+        existing: PiecePlacement,
+        comm_p: Commitment,
+    },
+}
+
+// Returns the id of the first staged sector accepting data which has room for
+// a piece of piece_bytes_len bytes, provisioning a new one if none has.
+fn destination_sector_id(
+    sector_store: &Arc<WrappedSectorStore>,
+    staged_state: &mut StagedState,
+    sector_ids: &mut SectorIdAllocator,
+    piece_bytes_len: u64,
+) -> error::Result<u64> {
+    let sector_mgr = sector_store.inner.manager();
+    let sector_max = sector_store.inner.config().max_unsealed_bytes_per_sector();
 
     let opt_dest_sector_id = {
         let candidates: Vec<StagedSectorMetadata> = staged_state
@@ -29,33 +184,116 @@ pub fn add_piece(
         compute_destination_sector_id(&candidates[..], sector_max, piece_bytes_len)?
     };
 
-    let dest_sector_id = opt_dest_sector_id
+    opt_dest_sector_id
         .ok_or(())
-        .or_else(|_| provision_new_staged_sector(sector_mgr, &mut staged_state))?;
-
-    if let Some(s) = staged_state.sectors.get_mut(&dest_sector_id) {
-        sector_store
-            .inner
-            .manager()
-            .write_and_preprocess(&s.sector_access, &piece_bytes)
-            .map_err(|err| err.into())
-            .and_then(|num_bytes_written| {
-                if num_bytes_written != piece_bytes_len {
-                    Err(err_inc_write(num_bytes_written, piece_bytes_len).into())
-                } else {
-                    Ok(s.sector_id)
-                }
-            })
-            .map(|sector_id| {
-                s.pieces.push(metadata::PieceMetadata {
-                    piece_key,
-                    num_bytes: piece_bytes_len,
-                });
+        .or_else(|_| provision_new_staged_sector(sector_mgr, staged_state, sector_ids))
+}
 
-                sector_id
-            })
-    } else {
-        Err(err_unrecov("unable to retrieve sector from state-map").into())
+// The staged sector with the given id, which the caller has just found.
+fn staged_sector(
+    staged_state: &mut StagedState,
+    sector_id: u64,
+) -> error::Result<&mut StagedSectorMetadata> {
+    staged_state
+        .sectors
+        .get_mut(&sector_id)
+        .ok_or_else(|| err_unrecov("unable to retrieve sector from state-map").into())
+}
+
+// Streams a piece of piece_bytes_len bytes from source into the given staged
+// sector, and records it in the sector's metadata. If the source produces a
+// different number of bytes or fails, the staged sector is left as it was.
+pub fn write_piece(
+    sector_mgr: &SectorManager,
+    sector: &mut StagedSectorMetadata,
+    piece_key: String,
+    piece_bytes_len: u64,
+    expires_at: Option<u64>,
+    source: &mut Read,
+) -> error::Result<()> {
+    let mut piece = PieceReader::new(source, piece_bytes_len);
+
+    let written = sector_mgr.write_and_preprocess_from_reader(&sector.sector_access, &mut piece);
+
+    // A source of the wrong length fails the write, which is rolled back
+    // just as it is when the source itself fails.
+    if piece.length_mismatch {
+        return Err(err_piece_len(piece_bytes_len).into());
+    }
+
+    let num_bytes_written = written?;
+    if num_bytes_written != piece_bytes_len {
+        return Err(err_inc_write(num_bytes_written, piece_bytes_len).into());
+    }
+
+    // Recorded so that the staged data can be checked against its pieces
+    // before sealing.
+    let comm_p = piece.comm_p.finish()?;
+
+    sector.pieces.push(metadata::PieceMetadata {
+        piece_key,
+        num_bytes: piece_bytes_len,
+        comm_p: Some(comm_p),
+        expires_at,
+        aliases: Vec::new(),
+    });
+
+    Ok(())
+}
+
+// Reads exactly `remaining` bytes of a piece from its source, computing its
+// comm_p along the way. Reading fails if the source ends early or has more
+// bytes than that.
+struct PieceReader<'a> {
+    source: &'a mut Read,
+    remaining: u64,
+    comm_p: CommPBuilder,
+    length_mismatch: bool,
+}
+
+impl<'a> PieceReader<'a> {
+    fn new(source: &'a mut Read, len: u64) -> PieceReader<'a> {
+        PieceReader {
+            source,
+            remaining: len,
+            comm_p: CommPBuilder::new(),
+            length_mismatch: false,
+        }
+    }
+
+    fn mismatch(&mut self) -> io::Error {
+        self.length_mismatch = true;
+        io::Error::new(io::ErrorKind::InvalidData, "piece length mismatch")
+    }
+}
+
+impl<'a> Read for PieceReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        if self.remaining == 0 {
+            // The piece should end here; make sure the source does too.
+            return match self.source.read(&mut [0; 1])? {
+                0 => Ok(0),
+                _ => Err(self.mismatch()),
+            };
+        }
+
+        let max = cmp::min(buf.len() as u64, self.remaining) as usize;
+        let n = self.source.read(&mut buf[..max])?;
+
+        if n == 0 {
+            return Err(self.mismatch());
+        }
+
+        self.comm_p
+            .update(&buf[..n])
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+        self.remaining -= n as u64;
+
+        Ok(n)
     }
 }
 
@@ -65,7 +303,7 @@ fn compute_destination_sector_id(
     candidate_sectors: &[StagedSectorMetadata],
     max_bytes_per_sector: u64,
     num_bytes_in_piece: u64,
-) -> error::Result<Option<SectorId>> {
+) -> error::Result<Option<u64>> {
     if num_bytes_in_piece > max_bytes_per_sector {
         Err(err_overflow(num_bytes_in_piece, max_bytes_per_sector).into())
     } else {
@@ -79,17 +317,14 @@ fn compute_destination_sector_id(
 }
 
 // Provisions a new staged sector and returns its sector_id. Not a pure
-// function; creates a sector access (likely a file), increments the sector id
-// nonce, and mutates the StagedState.
-fn provision_new_staged_sector(
+// function; allocates a sector id, creates a sector access (likely a file),
+// and mutates the StagedState.
+pub fn provision_new_staged_sector(
     sector_manager: &SectorManager,
     staged_state: &mut StagedState,
-) -> error::Result<SectorId> {
-    let sector_id = {
-        let n = &mut staged_state.sector_id_nonce;
-        *n += 1;
-        *n
-    };
+    sector_ids: &mut SectorIdAllocator,
+) -> error::Result<u64> {
+    let sector_id = sector_ids.allocate()?;
 
     let access = sector_manager.new_staging_sector_access()?;
 
@@ -108,7 +343,210 @@ fn provision_new_staged_sector(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::api::sector_builder::helpers::staged_data::{compute_comm_p, verify_staged_data};
+    use crate::api::sector_builder::helpers::sector_ids::test_allocator;
     use crate::api::sector_builder::metadata::PieceMetadata;
+    use sector_base::api::disk_backed_storage::{new_sector_store, ConfiguredStore};
+
+    // Reads at most 7 bytes at a time, then fails after `fail_after` bytes if
+    // set.
+    struct Source {
+        bytes: Vec<u8>,
+        pos: usize,
+        fail_after: Option<usize>,
+    }
+
+    impl Source {
+        fn new(len: usize) -> Source {
+            Source {
+                bytes: (0..len).map(|i| i as u8).collect(),
+                pos: 0,
+                fail_after: None,
+            }
+        }
+    }
+
+    impl Read for Source {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.fail_after.map_or(false, |n| self.pos >= n) {
+                return Err(io::Error::new(io::ErrorKind::Other, "connection reset"));
+            }
+
+            let n = cmp::min(cmp::min(7, buf.len()), self.bytes.len() - self.pos);
+            buf[..n].copy_from_slice(&self.bytes[self.pos..self.pos + n]);
+            self.pos += n;
+
+            Ok(n)
+        }
+    }
+
+    // Returns a sector store over dir, along with an allocator of sector ids
+    // recorded in dir.
+    fn test_store(dir: &tempfile::TempDir) -> (Arc<WrappedSectorStore>, SectorIdAllocator) {
+        let path = dir.path().to_str().unwrap().to_owned();
+
+        let store = Arc::new(WrappedSectorStore {
+            inner: Box::new(new_sector_store(&ConfiguredStore::Test, path.clone(), path)),
+        });
+
+        (store, test_allocator(&dir.path().join("metadata"), 0))
+    }
+
+    // Stages a first piece which leaves the last byte of the staged file only
+    // partly used, and returns the id of its sector.
+    fn stage_first_piece(
+        store: &Arc<WrappedSectorStore>,
+        state: &mut StagedState,
+        ids: &mut SectorIdAllocator,
+    ) -> u64 {
+        add_piece(store, state, ids, "first".to_string(), &[9; 27], None).unwrap()
+    }
+
+    fn staged_bytes(state: &StagedState, sector_id: u64) -> Vec<u8> {
+        std::fs::read(&state.sectors[&sector_id].sector_access).unwrap()
+    }
+
+    fn assert_length_mismatch(result: error::Result<u64>) {
+        match result.map_err(|err| err.downcast::<SectorBuilderErr>()) {
+            Err(Ok(SectorBuilderErr::PieceLengthMismatch { .. })) => (),
+            Err(Ok(err)) => panic!("unexpected error: {}", err),
+            Err(Err(err)) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("length mismatch was not detected"),
+        }
+    }
+
+    #[test]
+    fn streamed_piece_is_staged() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, mut ids) = test_store(&dir);
+        let mut state = StagedState::default();
+        let sector_id = stage_first_piece(&store, &mut state, &mut ids);
+
+        let mut source = Source::new(300);
+        let id = add_piece_from_reader(
+            &store,
+            &mut state,
+            &mut ids,
+            "x".to_string(),
+            300,
+            Some(40),
+            &mut source,
+        )
+        .unwrap();
+        assert_eq!(sector_id, id);
+
+        let sector = &state.sectors[&sector_id];
+        assert_eq!(2, sector.pieces.len());
+        assert_eq!(300, sector.pieces[1].num_bytes);
+        assert_eq!(Some(40), sector.pieces[1].expires_at);
+        assert_eq!(
+            Some(compute_comm_p(&source.bytes).unwrap()),
+            sector.pieces[1].comm_p
+        );
+
+        verify_staged_data(&store, sector).unwrap();
+    }
+
+    #[test]
+    fn source_ending_early_is_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, mut ids) = test_store(&dir);
+        let mut state = StagedState::default();
+        let sector_id = stage_first_piece(&store, &mut state, &mut ids);
+        let before = staged_bytes(&state, sector_id);
+
+        assert_length_mismatch(add_piece_from_reader(
+            &store,
+            &mut state,
+            &mut ids,
+            "x".to_string(),
+            100,
+            None,
+            &mut Source::new(60),
+        ));
+
+        assert_eq!(before, staged_bytes(&state, sector_id));
+        assert_eq!(1, state.sectors[&sector_id].pieces.len());
+
+        // The sector is still usable.
+        add_piece(&store, &mut state, &mut ids, "y".to_string(), &[4; 100], None).unwrap();
+        verify_staged_data(&store, &state.sectors[&sector_id]).unwrap();
+    }
+
+    #[test]
+    fn source_longer_than_piece_is_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, mut ids) = test_store(&dir);
+        let mut state = StagedState::default();
+        let sector_id = stage_first_piece(&store, &mut state, &mut ids);
+        let before = staged_bytes(&state, sector_id);
+
+        assert_length_mismatch(add_piece_from_reader(
+            &store,
+            &mut state,
+            &mut ids,
+            "x".to_string(),
+            60,
+            None,
+            &mut Source::new(100),
+        ));
+
+        assert_eq!(before, staged_bytes(&state, sector_id));
+        assert_eq!(1, state.sectors[&sector_id].pieces.len());
+    }
+
+    #[test]
+    fn failing_source_is_rolled_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, mut ids) = test_store(&dir);
+        let mut state = StagedState::default();
+        let sector_id = stage_first_piece(&store, &mut state, &mut ids);
+        let before = staged_bytes(&state, sector_id);
+
+        let mut source = Source::new(500);
+        source.fail_after = Some(250);
+
+        let result = add_piece_from_reader(
+            &store,
+            &mut state,
+            &mut ids,
+            "x".to_string(),
+            500,
+            None,
+            &mut source,
+        );
+
+        assert!(result.is_err());
+        assert_eq!(before, staged_bytes(&state, sector_id));
+        assert_eq!(1, state.sectors[&sector_id].pieces.len());
+    }
+
+    #[test]
+    fn streamed_piece_can_fill_sector() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, mut ids) = test_store(&dir);
+        let mut state = StagedState::default();
+        let max = store.inner.config().max_unsealed_bytes_per_sector();
+
+        let sector_id = add_piece_from_reader(
+            &store,
+            &mut state,
+            &mut ids,
+            "x".to_string(),
+            max,
+            None,
+            &mut Source::new(max as usize),
+        )
+        .unwrap();
+
+        let sector = &state.sectors[&sector_id];
+        assert_eq!(max, sum_piece_bytes(sector));
+        verify_staged_data(&store, sector).unwrap();
+
+        // Nothing else fits.
+        let next = add_piece(&store, &mut state, &mut ids, "y".to_string(), &[1], None).unwrap();
+        assert_ne!(sector_id, next);
+    }
 
     #[test]
     fn test_alpha() {
@@ -117,11 +555,17 @@ mod tests {
         sealed_sector_a.pieces.push(PieceMetadata {
             piece_key: String::from("x"),
             num_bytes: 5,
+            comm_p: None,
+            expires_at: None,
+            aliases: Vec::new(),
         });
 
         sealed_sector_a.pieces.push(PieceMetadata {
             piece_key: String::from("x"),
             num_bytes: 10,
+            comm_p: None,
+            expires_at: None,
+            aliases: Vec::new(),
         });
 
         let mut sealed_sector_b: StagedSectorMetadata = Default::default();
@@ -129,6 +573,9 @@ mod tests {
         sealed_sector_b.pieces.push(PieceMetadata {
             piece_key: String::from("x"),
             num_bytes: 5,
+            comm_p: None,
+            expires_at: None,
+            aliases: Vec::new(),
         });
 
         let staged_sectors = vec![sealed_sector_a.clone(), sealed_sector_b.clone()];
@@ -0,0 +1,206 @@
+use crate::api::sector_builder::metadata::{ExpiringPiece, PieceMetadata};
+use crate::api::sector_builder::state::{SealedState, StagedState};
+
+// Returns the ids, in order, of the sealed sectors every piece of which has
+// expired by now, along with every alias of it, and which so needn't be proven
+// any longer. Retired sectors, which are no longer proven anyway, and sectors
+// without pieces are left out.
+pub fn get_expired_sectors(sealed_state: &SealedState, now: u64) -> Vec<u64> {
+    let mut sector_ids: Vec<u64> = sealed_state
+        .sectors
+        .values()
+        .filter(|sector| sector.retired_into.is_none() && !sector.pieces.is_empty())
+        .filter(|sector| sector.pieces.iter().all(|piece| piece.bytes_expired(now)))
+        .map(|sector| sector.sector_id)
+        .collect();
+
+    sector_ids.sort();
+    sector_ids
+}
+
+// Returns the pieces, staged or sealed, which expire before the given horizon,
+// ordered by expiry and then by key. Aliases are listed as pieces of their
+// own, and the pieces of retired sectors under the sectors they were migrated
+// into.
+pub fn get_expiring_pieces(
+    staged_state: &StagedState,
+    sealed_state: &SealedState,
+    before: u64,
+) -> Vec<ExpiringPiece> {
+    let staged = staged_state
+        .sectors
+        .values()
+        .map(|sector| (sector.sector_id, &sector.pieces));
+
+    let sealed = sealed_state
+        .sectors
+        .values()
+        .filter(|sector| sector.retired_into.is_none())
+        .map(|sector| (sector.sector_id, &sector.pieces));
+
+    let mut pieces: Vec<ExpiringPiece> = staged
+        .chain(sealed)
+        .flat_map(|(sector_id, pieces)| expiring(sector_id, pieces, before))
+        .collect();
+
+    pieces.sort_by(|a, b| (a.expires_at, &a.piece_key).cmp(&(b.expires_at, &b.piece_key)));
+    pieces
+}
+
+fn expiring(sector_id: u64, pieces: &[PieceMetadata], before: u64) -> Vec<ExpiringPiece> {
+    let aliases = pieces
+        .iter()
+        .flat_map(|piece| piece.aliases.iter())
+        .map(|alias| (&alias.piece_key, alias.expires_at));
+
+    pieces
+        .iter()
+        .map(|piece| (&piece.piece_key, piece.expires_at))
+        .chain(aliases)
+        .filter_map(|(piece_key, expires_at)| match expires_at {
+            Some(expires_at) if expires_at < before => Some(ExpiringPiece {
+                piece_key: piece_key.clone(),
+                sector_id,
+                expires_at,
+            }),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sector_builder::metadata::{
+        PieceAlias, SealStatus, SealedSectorMetadata, StagedSectorMetadata,
+    };
+
+    fn piece(piece_key: &str, expires_at: Option<u64>) -> PieceMetadata {
+        PieceMetadata {
+            piece_key: piece_key.to_string(),
+            num_bytes: 100,
+            comm_p: None,
+            expires_at,
+            aliases: Vec::new(),
+        }
+    }
+
+    // Two sealed sectors whose pieces expire at staggered times: sector 0's
+    // last at 30, and sector 1's at 25. Sector 2 holds a piece which never
+    // expires, and sector 3 is staged.
+    fn setup() -> (StagedState, SealedState) {
+        let mut sealed = SealedState::default();
+
+        for (sector_id, pieces) in vec![
+            (0, vec![piece("a", Some(10)), piece("b", Some(30)), piece("c", Some(20))]),
+            (1, vec![piece("d", Some(25)), piece("e", Some(15))]),
+            (2, vec![piece("f", Some(5)), piece("g", None)]),
+        ] {
+            sealed.sectors.insert(
+                sector_id,
+                SealedSectorMetadata {
+                    sector_id,
+                    pieces,
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut staged = StagedState::default();
+        staged.sectors.insert(
+            3,
+            StagedSectorMetadata {
+                sector_id: 3,
+                pieces: vec![piece("h", Some(1))],
+                seal_status: SealStatus::Pending,
+                ..Default::default()
+            },
+        );
+
+        (staged, sealed)
+    }
+
+    #[test]
+    fn sectors_expire_with_their_last_piece() {
+        let (_, sealed) = setup();
+
+        assert!(get_expired_sectors(&sealed, 0).is_empty());
+        assert!(get_expired_sectors(&sealed, 24).is_empty());
+        assert_eq!(vec![1], get_expired_sectors(&sealed, 25));
+        assert_eq!(vec![1], get_expired_sectors(&sealed, 29));
+        assert_eq!(vec![0, 1], get_expired_sectors(&sealed, 30));
+
+        // A piece without an expiry keeps its sector from ever expiring.
+        assert_eq!(vec![0, 1], get_expired_sectors(&sealed, u64::max_value()));
+    }
+
+    #[test]
+    fn retired_and_empty_sectors_never_expire() {
+        let (_, mut sealed) = setup();
+
+        sealed.sectors.get_mut(&1).unwrap().retired_into = Some(0);
+        sealed.sectors.insert(
+            4,
+            SealedSectorMetadata {
+                sector_id: 4,
+                ..Default::default()
+            },
+        );
+
+        assert_eq!(vec![0], get_expired_sectors(&sealed, 30));
+    }
+
+    #[test]
+    fn expiring_pieces_are_listed_by_expiry() {
+        let (staged, sealed) = setup();
+
+        let listed = |before| -> Vec<(String, u64, u64)> {
+            get_expiring_pieces(&staged, &sealed, before)
+                .into_iter()
+                .map(|p| (p.piece_key, p.sector_id, p.expires_at))
+                .collect()
+        };
+
+        assert!(listed(1).is_empty());
+        assert_eq!(vec![("h".to_string(), 3, 1)], listed(2));
+        assert_eq!(
+            vec![
+                ("h".to_string(), 3, 1),
+                ("f".to_string(), 2, 5),
+                ("a".to_string(), 0, 10),
+                ("e".to_string(), 1, 15),
+                ("c".to_string(), 0, 20),
+            ],
+            listed(25)
+        );
+        assert_eq!(7, listed(u64::max_value()).len());
+    }
+
+    #[test]
+    fn aliases_keep_the_bytes_they_read_from() {
+        let (staged, mut sealed) = setup();
+
+        // Piece d, which expires at 25, has an alias whose deal ends at 40.
+        sealed.sectors.get_mut(&1).unwrap().pieces[0]
+            .aliases
+            .push(PieceAlias {
+                piece_key: "i".to_string(),
+                expires_at: Some(40),
+            });
+
+        assert_eq!(vec![0], get_expired_sectors(&sealed, 30));
+        assert_eq!(vec![0, 1], get_expired_sectors(&sealed, 40));
+
+        // The alias is listed as a piece of its own.
+        let expiring = get_expiring_pieces(&staged, &sealed, 41);
+        assert_eq!(8, expiring.len());
+        assert_eq!(
+            ExpiringPiece {
+                piece_key: "i".to_string(),
+                sector_id: 1,
+                expires_at: 40,
+            },
+            expiring[7]
+        );
+    }
+}
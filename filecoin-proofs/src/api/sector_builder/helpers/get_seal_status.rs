@@ -2,13 +2,12 @@ use crate::api::sector_builder::errors::err_unrecov;
 use crate::api::sector_builder::metadata::SealStatus;
 use crate::api::sector_builder::state::SealedState;
 use crate::api::sector_builder::state::StagedState;
-use crate::api::sector_builder::SectorId;
 use crate::error;
 
 pub fn get_seal_status(
     staged_state: &StagedState,
     sealed_state: &SealedState,
-    sector_id: SectorId,
+    sector_id: u64,
 ) -> error::Result<SealStatus> {
     sealed_state
         .sectors
@@ -33,8 +32,8 @@ mod tests {
     use std::collections::HashMap;
 
     fn setup() -> SectorBuilderState {
-        let mut staged_sectors: HashMap<SectorId, StagedSectorMetadata> = Default::default();
-        let mut sealed_sectors: HashMap<SectorId, SealedSectorMetadata> = Default::default();
+        let mut staged_sectors: HashMap<u64, StagedSectorMetadata> = Default::default();
+        let mut sealed_sectors: HashMap<u64, SealedSectorMetadata> = Default::default();
 
         staged_sectors.insert(
             2,
@@ -65,7 +64,6 @@ mod tests {
         SectorBuilderState {
             prover_id: Default::default(),
             staged: StagedState {
-                sector_id_nonce: 0,
                 sectors: staged_sectors,
             },
             sealed: SealedState {
@@ -2,7 +2,6 @@ use crate::api::sector_builder::metadata::sum_piece_bytes;
 use crate::api::sector_builder::metadata::SealStatus;
 use crate::api::sector_builder::metadata::StagedSectorMetadata;
 use crate::api::sector_builder::state::StagedState;
-use crate::api::sector_builder::SectorId;
 use itertools::chain;
 use std::cmp::Reverse;
 
@@ -11,7 +10,7 @@ pub fn get_sectors_ready_for_sealing(
     max_user_bytes_per_staged_sector: u64,
     max_num_staged_sectors: u8,
     seal_all_staged_sectors: bool,
-) -> Vec<SectorId> {
+) -> Vec<u64> {
     let (full, mut not_full): (Vec<&StagedSectorMetadata>, Vec<&StagedSectorMetadata>) =
         staged_state
             .sectors
@@ -29,7 +28,7 @@ pub fn get_sectors_ready_for_sealing(
 
     chain(full.into_iter(), not_full.into_iter().skip(num_to_skip))
         .map(|x| x.sector_id)
-        .collect::<Vec<SectorId>>()
+        .collect::<Vec<u64>>()
 }
 
 #[cfg(test)]
@@ -38,12 +37,11 @@ mod tests {
     use crate::api::sector_builder::metadata::PieceMetadata;
     use crate::api::sector_builder::metadata::StagedSectorMetadata;
     use crate::api::sector_builder::state::StagedState;
-    use crate::api::sector_builder::SectorId;
     use std::collections::HashMap;
 
     fn make_meta(
-        m: &mut HashMap<SectorId, StagedSectorMetadata>,
-        sector_id: SectorId,
+        m: &mut HashMap<u64, StagedSectorMetadata>,
+        sector_id: u64,
         num_bytes: u64,
         accepting_data: bool,
     ) {
@@ -60,6 +58,9 @@ mod tests {
                 pieces: vec![PieceMetadata {
                     piece_key: format!("{}", sector_id),
                     num_bytes,
+                    comm_p: None,
+                    expires_at: None,
+                    aliases: Vec::new(),
                 }],
                 seal_status,
                 ..Default::default()
@@ -69,78 +70,66 @@ mod tests {
 
     #[test]
     fn test_seals_all() {
-        let mut m: HashMap<SectorId, StagedSectorMetadata> = HashMap::new();
+        let mut m: HashMap<u64, StagedSectorMetadata> = HashMap::new();
 
         make_meta(&mut m, 200, 0, true);
         make_meta(&mut m, 201, 0, true);
 
-        let state = StagedState {
-            sector_id_nonce: 100,
-            sectors: m,
-        };
+        let state = StagedState { sectors: m };
 
-        let to_seal: Vec<SectorId> = get_sectors_ready_for_sealing(&state, 127, 10, true)
+        let to_seal: Vec<u64> = get_sectors_ready_for_sealing(&state, 127, 10, true)
             .into_iter()
             .collect();
 
-        assert_eq!(vec![201 as SectorId, 200 as SectorId], to_seal);
+        assert_eq!(vec![201, 200], to_seal);
     }
 
     #[test]
     fn test_seals_full() {
-        let mut m: HashMap<SectorId, StagedSectorMetadata> = HashMap::new();
+        let mut m: HashMap<u64, StagedSectorMetadata> = HashMap::new();
 
         make_meta(&mut m, 200, 127, true);
         make_meta(&mut m, 201, 0, true);
 
-        let state = StagedState {
-            sector_id_nonce: 100,
-            sectors: m,
-        };
+        let state = StagedState { sectors: m };
 
-        let to_seal: Vec<SectorId> = get_sectors_ready_for_sealing(&state, 127, 10, false)
+        let to_seal: Vec<u64> = get_sectors_ready_for_sealing(&state, 127, 10, false)
             .into_iter()
             .collect();
 
-        assert_eq!(vec![200 as SectorId], to_seal);
+        assert_eq!(vec![200], to_seal);
     }
 
     #[test]
     fn test_seals_excess() {
-        let mut m: HashMap<SectorId, StagedSectorMetadata> = HashMap::new();
+        let mut m: HashMap<u64, StagedSectorMetadata> = HashMap::new();
 
         make_meta(&mut m, 200, 0, true);
         make_meta(&mut m, 201, 0, true);
         make_meta(&mut m, 202, 0, true);
         make_meta(&mut m, 203, 0, true);
 
-        let state = StagedState {
-            sector_id_nonce: 100,
-            sectors: m,
-        };
+        let state = StagedState { sectors: m };
 
-        let to_seal: Vec<SectorId> = get_sectors_ready_for_sealing(&state, 127, 2, false)
+        let to_seal: Vec<u64> = get_sectors_ready_for_sealing(&state, 127, 2, false)
             .into_iter()
             .collect();
 
-        assert_eq!(vec![201 as SectorId, 200 as SectorId], to_seal);
+        assert_eq!(vec![201, 200], to_seal);
     }
 
     #[test]
     fn test_noop() {
-        let mut m: HashMap<SectorId, StagedSectorMetadata> = HashMap::new();
+        let mut m: HashMap<u64, StagedSectorMetadata> = HashMap::new();
 
         make_meta(&mut m, 200, 0, true);
         make_meta(&mut m, 201, 0, true);
         make_meta(&mut m, 202, 0, true);
         make_meta(&mut m, 203, 0, true);
 
-        let state = StagedState {
-            sector_id_nonce: 100,
-            sectors: m,
-        };
+        let state = StagedState { sectors: m };
 
-        let to_seal: Vec<SectorId> = get_sectors_ready_for_sealing(&state, 127, 4, false)
+        let to_seal: Vec<u64> = get_sectors_ready_for_sealing(&state, 127, 4, false)
             .into_iter()
             .collect();
 
@@ -149,19 +138,16 @@ mod tests {
 
     #[test]
     fn test_noop_all_being_sealed() {
-        let mut m: HashMap<SectorId, StagedSectorMetadata> = HashMap::new();
+        let mut m: HashMap<u64, StagedSectorMetadata> = HashMap::new();
 
         make_meta(&mut m, 200, 127, false);
         make_meta(&mut m, 201, 127, false);
         make_meta(&mut m, 202, 127, false);
         make_meta(&mut m, 203, 127, false);
 
-        let state = StagedState {
-            sector_id_nonce: 100,
-            sectors: m,
-        };
+        let state = StagedState { sectors: m };
 
-        let to_seal: Vec<SectorId> = get_sectors_ready_for_sealing(&state, 127, 4, false)
+        let to_seal: Vec<u64> = get_sectors_ready_for_sealing(&state, 127, 4, false)
             .into_iter()
             .collect();
 
@@ -0,0 +1,411 @@
+use crate::api::sector_builder::helpers::snapshots::{make_snapshot, persist_snapshot};
+use crate::api::sector_builder::metadata::{SealedSectorMetadata, StagedSectorMetadata};
+use crate::api::sector_builder::state::SectorBuilderState;
+use crate::api::sector_builder::WrappedKeyValueStore;
+use crate::error::Result;
+use std::sync::Arc;
+use storage_proofs::fail_point;
+use storage_proofs::failpoint;
+
+// Changes to the records of several sectors, e.g. migrating sectors into a new
+// one and retiring them, are made as a transaction, so that a crash never
+// leaves some of them recorded and others not. A transaction buffers its
+// changes until it's committed, which is done in three steps, each durable
+// before the next begins:
+//
+// 1. The changes are written, as a single entry, to the journal, which is kept
+//    in the metadata store under its own key.
+// 2. The changes are applied to the builder's state, and the snapshot
+//    recording them is persisted.
+// 3. The journal is cleared, marking the entry applied.
+//
+// An entry found in the journal when a builder starts is replayed before
+// anything else is done: its changes are applied to the state loaded, which
+// is persisted, and the journal is cleared. Changes replace or remove whole
+// records, so replaying an entry whose changes were already persisted changes
+// nothing. An entry which can't be read is discarded, as the snapshot holds
+// none of its changes until it has been read whole.
+
+const KEY_SUFFIX: &[u8] = b"/metadata-journal";
+
+// A change to the record of a single sector.
+#[derive(Clone, Serialize, Deserialize)]
+pub enum SectorChange {
+    PutStaged(StagedSectorMetadata),
+    RemoveStaged(u64),
+    PutSealed(SealedSectorMetadata),
+}
+
+impl SectorChange {
+    pub fn sector_id(&self) -> u64 {
+        match self {
+            SectorChange::PutStaged(sector) => sector.sector_id,
+            SectorChange::RemoveStaged(sector_id) => *sector_id,
+            SectorChange::PutSealed(sector) => sector.sector_id,
+        }
+    }
+
+    fn apply(self, state: &mut SectorBuilderState) {
+        match self {
+            SectorChange::PutStaged(sector) => {
+                state.staged.sectors.insert(sector.sector_id, sector);
+            }
+            SectorChange::RemoveStaged(sector_id) => {
+                state.staged.sectors.remove(&sector_id);
+            }
+            SectorChange::PutSealed(sector) => {
+                state.sealed.sectors.insert(sector.sector_id, sector);
+            }
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct JournalEntry {
+    changes: Vec<SectorChange>,
+}
+
+// How an entry left in the journal by a crash was recovered.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JournalRecovery {
+    // The entry's changes were applied, to the sectors with the given ids.
+    Replayed(Vec<u64>),
+    // The entry couldn't be read, and none of its changes were applied.
+    Discarded,
+}
+
+// The journal of a builder's metadata store, which holds at most one entry, as
+// a builder commits one transaction at a time.
+pub struct MetadataJournal {
+    kv_store: Arc<WrappedKeyValueStore>,
+    key: Vec<u8>,
+}
+
+// Changes buffered for a single commit. Nothing is written until it's
+// committed, and dropping it, or aborting it, discards its changes.
+pub struct MetadataTxn<'a> {
+    journal: &'a MetadataJournal,
+    changes: Vec<SectorChange>,
+}
+
+impl MetadataJournal {
+    pub fn new(kv_store: Arc<WrappedKeyValueStore>, prover_id: &[u8; 31]) -> MetadataJournal {
+        MetadataJournal {
+            kv_store,
+            key: [&prover_id[..], KEY_SUFFIX].concat(),
+        }
+    }
+
+    pub fn begin_txn(&self) -> MetadataTxn {
+        MetadataTxn {
+            journal: self,
+            changes: Vec::new(),
+        }
+    }
+
+    // The changes of the entry in the journal, if there is one, or an error if
+    // it can't be read.
+    pub fn pending(&self) -> Result<Option<Vec<SectorChange>>> {
+        match self.read()? {
+            Some(bytes) => {
+                let entry: JournalEntry = serde_cbor::from_slice(&bytes)?;
+                Ok(Some(entry.changes))
+            }
+            None => Ok(None),
+        }
+    }
+
+    // Recovers the entry left in the journal by a builder which crashed while
+    // committing a transaction, if there is one, bringing state (and the
+    // snapshot it's persisted in) up to date with it. A read-only builder
+    // applies the entry's changes to state, but writes nothing.
+    pub fn recover(
+        &self,
+        state: &mut SectorBuilderState,
+        read_only: bool,
+    ) -> Result<Option<JournalRecovery>> {
+        let bytes = match self.read()? {
+            Some(bytes) => bytes,
+            None => return Ok(None),
+        };
+
+        let recovery = match serde_cbor::from_slice::<JournalEntry>(&bytes) {
+            Ok(entry) => {
+                let sector_ids = entry.changes.iter().map(SectorChange::sector_id).collect();
+                for change in entry.changes {
+                    change.apply(state);
+                }
+
+                JournalRecovery::Replayed(sector_ids)
+            }
+            Err(_) => JournalRecovery::Discarded,
+        };
+
+        if !read_only {
+            if let JournalRecovery::Replayed(_) = recovery {
+                persist(&self.kv_store, state)?;
+            }
+            self.clear()?;
+        }
+
+        Ok(Some(recovery))
+    }
+
+    // The bytes of the entry in the journal, if there is one.
+    fn read(&self) -> Result<Option<Vec<u8>>> {
+        match self.kv_store.inner.get(&self.key)? {
+            Some(ref bytes) if bytes.is_empty() => Ok(None),
+            bytes => Ok(bytes),
+        }
+    }
+
+    // Durably writes the entry, replacing any other.
+    fn write(&self, entry: &JournalEntry) -> Result<()> {
+        self.kv_store
+            .inner
+            .put(&self.key, &serde_cbor::to_vec(entry)?)
+    }
+
+    // Durably clears the journal. The store can't remove a key, so the entry
+    // is replaced with nothing.
+    fn clear(&self) -> Result<()> {
+        self.kv_store.inner.put(&self.key, &[])
+    }
+}
+
+impl<'a> MetadataTxn<'a> {
+    pub fn put_staged(&mut self, sector: StagedSectorMetadata) {
+        self.changes.push(SectorChange::PutStaged(sector));
+    }
+
+    pub fn remove_staged(&mut self, sector_id: u64) {
+        self.changes.push(SectorChange::RemoveStaged(sector_id));
+    }
+
+    pub fn put_sealed(&mut self, sector: SealedSectorMetadata) {
+        self.changes.push(SectorChange::PutSealed(sector));
+    }
+
+    // Applies the changes to state, and persists them, all or none of them.
+    // State is left as it was unless the journal entry is written. Once it is,
+    // the changes are committed, even if persisting the snapshot or clearing
+    // the journal then fails: they're persisted by the next checkpoint, or
+    // replayed when the builder next starts.
+    pub fn commit(self, state: &mut SectorBuilderState) -> Result<()> {
+        if self.changes.is_empty() {
+            return Ok(());
+        }
+
+        let entry = JournalEntry {
+            changes: self.changes,
+        };
+        self.journal.write(&entry)?;
+        fail_point!(failpoint::JOURNAL_WRITTEN);
+
+        for change in entry.changes {
+            change.apply(state);
+        }
+
+        persist(&self.journal.kv_store, state)?;
+        self.journal.clear()
+    }
+
+    // Discards the changes.
+    pub fn abort(self) {}
+}
+
+fn persist(kv_store: &Arc<WrappedKeyValueStore>, state: &SectorBuilderState) -> Result<()> {
+    let snapshot = make_snapshot(&state.prover_id, &state.staged, &state.sealed);
+    persist_snapshot(kv_store, &snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sector_builder::helpers::snapshots::load_snapshot;
+    use crate::api::sector_builder::kv_store::fs::FileSystemKvs;
+    #[cfg(feature = "failpoints")]
+    use std::panic::{self, AssertUnwindSafe};
+    #[cfg(feature = "failpoints")]
+    use storage_proofs::failpoint::{FailAction, FailScenario};
+
+    const PROVER_ID: [u8; 31] = [7; 31];
+
+    struct Builder {
+        _dir: tempfile::TempDir,
+        kv_store: Arc<WrappedKeyValueStore>,
+    }
+
+    impl Builder {
+        // A builder which has recorded sealed sectors 1 and 2, and staged
+        // sector 3.
+        fn new() -> Builder {
+            let dir = tempfile::tempdir().unwrap();
+
+            let kv_store = Arc::new(WrappedKeyValueStore {
+                inner: Box::new(FileSystemKvs::initialize(dir.path()).unwrap()),
+            });
+
+            let builder = Builder {
+                _dir: dir,
+                kv_store,
+            };
+
+            let mut state = builder.restart().0;
+            let journal = builder.journal();
+            let mut txn = journal.begin_txn();
+            txn.put_sealed(sealed(1, None));
+            txn.put_sealed(sealed(2, None));
+            txn.put_staged(StagedSectorMetadata {
+                sector_id: 3,
+                ..Default::default()
+            });
+            txn.commit(&mut state).unwrap();
+
+            builder
+        }
+
+        fn journal(&self) -> MetadataJournal {
+            MetadataJournal::new(self.kv_store.clone(), &PROVER_ID)
+        }
+
+        // Starts the builder over whatever made it to disk, recovering any
+        // journal entry left behind.
+        fn restart(&self) -> (SectorBuilderState, Option<JournalRecovery>) {
+            let mut state = load_snapshot(&self.kv_store, &PROVER_ID)
+                .unwrap()
+                .map(|snapshot| snapshot.into())
+                .unwrap_or_else(|| SectorBuilderState {
+                    prover_id: PROVER_ID,
+                    staged: Default::default(),
+                    sealed: Default::default(),
+                });
+
+            let recovery = self.journal().recover(&mut state, false).unwrap();
+
+            (state, recovery)
+        }
+
+        // Migrates sectors 1 and 2 into a new sector 4, sealed from staged
+        // sector 3, crashing at the given failpoint if given.
+        #[cfg(feature = "failpoints")]
+        fn migrate(&self, crash_at: Option<&'static str>) {
+            let (mut state, _) = self.restart();
+            let journal = self.journal();
+
+            let scenario = FailScenario::setup();
+            if let Some(name) = crash_at {
+                scenario.arm(name, FailAction::Panic);
+            }
+
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                let mut txn = journal.begin_txn();
+                txn.put_sealed(sealed(1, Some(4)));
+                txn.put_sealed(sealed(2, Some(4)));
+                txn.put_sealed(sealed(4, None));
+                txn.remove_staged(3);
+                txn.commit(&mut state).unwrap();
+            }));
+        }
+    }
+
+    fn sealed(sector_id: u64, retired_into: Option<u64>) -> SealedSectorMetadata {
+        SealedSectorMetadata {
+            sector_id,
+            retired_into,
+            ..Default::default()
+        }
+    }
+
+    // Whether the migration is visible in state: all of it, or none of it.
+    fn migrated(state: &SectorBuilderState) -> bool {
+        let retired_into = |sector_id| state.sealed.sectors[&sector_id].retired_into;
+        let sealed = state.sealed.sectors.contains_key(&4);
+        let staged = state.staged.sectors.contains_key(&3);
+
+        match (retired_into(1), retired_into(2), sealed, staged) {
+            (Some(4), Some(4), true, false) => true,
+            (None, None, false, true) => false,
+            _ => panic!("a partial migration is visible: {:?}", state),
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "failpoints")]
+    fn crashes_during_a_commit_leave_all_or_nothing() {
+        let replayed = Some(JournalRecovery::Replayed(vec![1, 2, 4, 3]));
+        let cases = vec![
+            // Between writing the journal entry and applying it.
+            (Some(failpoint::JOURNAL_WRITTEN), replayed.clone()),
+            // Between applying the entry and clearing the journal.
+            (Some(failpoint::SNAPSHOT_PERSISTED), replayed),
+            (None, None),
+        ];
+
+        for (crash_at, recovery) in cases {
+            let builder = Builder::new();
+            builder.migrate(crash_at);
+
+            let (state, recovered) = builder.restart();
+            assert_eq!(recovery, recovered, "crash at {:?}", crash_at);
+            assert!(migrated(&state), "crash at {:?}", crash_at);
+            assert!(builder.journal().pending().unwrap().is_none());
+
+            // Nothing is left to recover on the next start.
+            let (state, recovered) = builder.restart();
+            assert_eq!(None, recovered);
+            assert!(migrated(&state));
+        }
+    }
+
+    #[test]
+    fn aborted_transactions_change_nothing() {
+        let builder = Builder::new();
+        let (state, _) = builder.restart();
+        let journal = builder.journal();
+
+        let mut txn = journal.begin_txn();
+        txn.put_sealed(sealed(4, None));
+        txn.remove_staged(3);
+        txn.abort();
+
+        assert!(!state.sealed.sectors.contains_key(&4));
+        assert!(journal.pending().unwrap().is_none());
+
+        let (state, recovered) = builder.restart();
+        assert_eq!(None, recovered);
+        assert!(!migrated(&state));
+    }
+
+    #[test]
+    #[cfg(feature = "failpoints")]
+    fn empty_transactions_write_nothing() {
+        let builder = Builder::new();
+        let (mut state, _) = builder.restart();
+        let journal = builder.journal();
+
+        let scenario = FailScenario::setup();
+        scenario.arm(failpoint::JOURNAL_WRITTEN, FailAction::Panic);
+        scenario.arm(failpoint::SNAPSHOT_PERSISTED, FailAction::Panic);
+        journal.begin_txn().commit(&mut state).unwrap();
+
+        let (state, recovered) = builder.restart();
+        assert_eq!(None, recovered);
+        assert!(!migrated(&state));
+    }
+
+    #[test]
+    fn unreadable_entries_are_discarded() {
+        let builder = Builder::new();
+        builder
+            .kv_store
+            .inner
+            .put(&builder.journal().key, b"not an entry")
+            .unwrap();
+
+        let (state, recovered) = builder.restart();
+        assert_eq!(Some(JournalRecovery::Discarded), recovered);
+        assert!(!migrated(&state));
+        assert!(builder.journal().pending().unwrap().is_none());
+    }
+}
@@ -0,0 +1,695 @@
+use crate::api::cancellation::CancellationToken;
+use crate::api::internal;
+use crate::api::io_priority::SealThrottle;
+use crate::api::replica_format::LegacyReplicas;
+use crate::api::sector_builder::errors::*;
+use crate::api::sector_builder::helpers::add_piece::add_piece_from_reader;
+use crate::api::sector_builder::helpers::metadata_txn::MetadataTxn;
+use crate::api::sector_builder::helpers::seal::seal;
+use crate::api::sector_builder::helpers::sector_ids::SectorIdAllocator;
+use crate::api::sector_builder::metadata::*;
+use crate::api::sector_builder::state::{SectorBuilderState, StagedState};
+use crate::api::sector_builder::WrappedSectorStore;
+use crate::api::sector_id::SectorId;
+use crate::error::Result;
+use sector_base::api::sector_store::SectorConfig;
+use std::cmp;
+use std::collections::HashSet;
+use std::io::{self, BufWriter, Read, Write};
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::thread;
+use storage_proofs::crypto::constant_time::ConstantTimeEq;
+use storage_proofs::types::FrSafe;
+
+// Sealed sectors are migrated into a sector of another (larger) size by
+// staging their pieces again, as add_piece stages them, into a sector of that
+// size, which is then sealed. Each piece is unsealed on its own, on a thread
+// of its own, and streamed as it's unsealed straight into the new sector's
+// staged data (see UnsealedStream): no unsealed copy of a piece, let alone of
+// a sector, is ever written to disk. Its comm_p is recomputed as it's staged,
+// and must match the one recorded when it was first staged.
+
+// The chunks of a piece, of PIPELINE_CHUNK_BYTES bytes each, which may be
+// unsealed ahead of its staging.
+const PIPELINE_CHUNKS: usize = 4;
+const PIPELINE_CHUNK_BYTES: usize = 1 << 20;
+
+// Migrates the pieces of the sealed sectors with the given ids, in order, into
+// a new sector sealed with target_store, and returns its metadata, which the
+// caller is to record along with the retirement of the source sectors (see
+// record_migration), and the status of each piece. Nothing is left behind in
+// the target store unless the migration succeeds, though the new sector's id
+// stays allocated.
+pub fn migrate_sectors(
+    sector_store: &Arc<WrappedSectorStore>,
+    target_store: &Arc<WrappedSectorStore>,
+    state: &SectorBuilderState,
+    sector_ids: &mut SectorIdAllocator,
+    source_sector_ids: &[u64],
+    max_seal_memory_bytes: Option<u64>,
+) -> Result<(SealedSectorMetadata, Vec<MigratedPiece>)> {
+    let sources = source_sectors(state, source_sector_ids)?;
+
+    let num_bytes: u64 = sources
+        .iter()
+        .flat_map(|sector| sector.pieces.iter())
+        .map(|piece| piece.num_bytes)
+        .sum();
+    let max_bytes = target_store.inner.config().max_unsealed_bytes_per_sector();
+
+    if num_bytes > max_bytes {
+        let reason = format!(
+            "their pieces ({} bytes) exceed the capacity of the target sector ({} bytes)",
+            num_bytes, max_bytes
+        );
+        return Err(err_invalid_migration(reason).into());
+    }
+
+    let mut staged_state = StagedState::default();
+
+    let staged = stage_pieces(
+        sector_store,
+        target_store,
+        &mut staged_state,
+        sector_ids,
+        &state.prover_id,
+        &sources,
+    );
+
+    let result = staged.and_then(|pieces| {
+        // Every piece fits, so add_piece staged them all in one sector.
+        let staged_sector = staged_state
+            .sectors
+            .values()
+            .next()
+            .cloned()
+            .ok_or_else(|| err_invalid_migration("the sectors hold no pieces"))?;
+
+        let sealed_sector = seal(
+            target_store,
+            &state.prover_id,
+            staged_sector,
+            false,
+            None,
+            max_seal_memory_bytes,
+            &SealThrottle::default(),
+            &CancellationToken::new(),
+        )?;
+
+        Ok((
+            SealedSectorMetadata {
+                sector_bytes: Some(target_store.inner.config().sector_bytes()),
+                // Its staged data is deleted below.
+                sealed_from: None,
+                ..sealed_sector
+            },
+            pieces,
+        ))
+    });
+
+    for staged_sector in staged_state.sectors.values() {
+        let _ = target_store
+            .inner
+            .manager()
+            .delete_staging_sector_access(&staged_sector.sector_access);
+    }
+
+    result
+}
+
+// Records, in txn, the sector the sources with the given ids were migrated
+// into, and the retirement of the sources, so that the new sector is never
+// recorded without its sources being retired, nor the other way round.
+pub fn record_migration(
+    txn: &mut MetadataTxn,
+    state: &SectorBuilderState,
+    source_sector_ids: &[u64],
+    sealed_sector: SealedSectorMetadata,
+) {
+    for source_sector_id in source_sector_ids {
+        if let Some(source) = state.sealed.sectors.get(source_sector_id) {
+            txn.put_sealed(SealedSectorMetadata {
+                retired_into: Some(sealed_sector.sector_id),
+                ..source.clone()
+            });
+        }
+    }
+
+    txn.put_sealed(sealed_sector);
+}
+
+// Returns the sealed sectors with the given ids, none of which may be retired
+// or given twice.
+fn source_sectors<'a>(
+    state: &'a SectorBuilderState,
+    source_sector_ids: &[u64],
+) -> Result<Vec<&'a SealedSectorMetadata>> {
+    let mut seen = HashSet::new();
+
+    source_sector_ids
+        .iter()
+        .map(|sector_id| -> Result<&'a SealedSectorMetadata> {
+            let sector = state
+                .sealed
+                .sectors
+                .get(sector_id)
+                .ok_or_else(|| err_sealed_sector_not_found(*sector_id))?;
+
+            if !seen.insert(*sector_id) {
+                let reason = format!("sector {} is given more than once", sector_id);
+                return Err(err_invalid_migration(reason).into());
+            }
+
+            if let Some(retired_into) = sector.retired_into {
+                let reason = format!(
+                    "sector {} has already been migrated into sector {}",
+                    sector_id, retired_into
+                );
+                return Err(err_invalid_migration(reason).into());
+            }
+
+            Ok(sector)
+        })
+        .collect()
+}
+
+// Streams each piece of the source sectors, as it's unsealed, into a sector of
+// the target store. A piece whose unseal or staging fails is dropped from the
+// sector, which is left as it was before the piece.
+fn stage_pieces(
+    sector_store: &Arc<WrappedSectorStore>,
+    target_store: &Arc<WrappedSectorStore>,
+    staged_state: &mut StagedState,
+    sector_ids: &mut SectorIdAllocator,
+    prover_id: &[u8; 31],
+    sources: &[&SealedSectorMetadata],
+) -> Result<Vec<MigratedPiece>> {
+    let mut migrated = Vec::new();
+
+    for source in sources {
+        let mut offset = 0;
+
+        sector_store
+            .inner
+            .manager()
+            .fetch_sealed(&source.sector_access)?;
+
+        for piece in &source.pieces {
+            let mut unsealed = UnsealedStream::start(
+                source.config(sector_store.inner.config()),
+                PathBuf::from(&source.sector_access),
+                *prover_id,
+                SectorId(source.sector_id).to_fr_safe(),
+                offset,
+                piece.num_bytes,
+                source.legacy_replicas(),
+            );
+
+            let staged = add_piece_from_reader(
+                target_store,
+                staged_state,
+                sector_ids,
+                piece.piece_key.clone(),
+                piece.num_bytes,
+                piece.expires_at,
+                &mut unsealed,
+            );
+
+            // A stream which ended early did so as its unseal failed, which
+            // is what failed the piece's staging.
+            let sector_id = match (staged, unsealed.finish()) {
+                (_, Err(err)) => return Err(err),
+                (staged, Ok(())) => staged?,
+            };
+
+            // The piece's aliases are read from its bytes wherever they are.
+            let staged = staged_state
+                .sectors
+                .get_mut(&sector_id)
+                .and_then(|sector| sector.pieces.last_mut())
+                .ok_or_else(|| err_unrecov("unable to retrieve piece from sector"))?;
+            staged.aliases = piece.aliases.clone();
+
+            let comm_p = staged.comm_p;
+
+            let status = match piece.comm_p {
+                Some(recorded) if comm_p.map_or(false, |comm_p| comm_p.ct_eq(&recorded)) => {
+                    MigratedPieceStatus::Verified
+                }
+                Some(_) => {
+                    let piece_key = piece.piece_key.clone();
+                    return Err(err_migrated_piece_mismatch(source.sector_id, piece_key).into());
+                }
+                None => MigratedPieceStatus::Unverified,
+            };
+
+            migrated.push(MigratedPiece {
+                piece_key: piece.piece_key.clone(),
+                source_sector_id: source.sector_id,
+                status,
+            });
+
+            offset += piece.num_bytes;
+        }
+    }
+
+    Ok(migrated)
+}
+
+// The unsealed bytes of a range of a sealed sector, read as they're unsealed
+// on a thread of its own, which hands them over in chunks through a bounded
+// queue: no more than PIPELINE_CHUNKS chunks of them are held at once.
+struct UnsealedStream {
+    chunks: mpsc::Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    pos: usize,
+    // Whether the unseal has ended, as the queue is closed once it does.
+    ended: bool,
+    token: CancellationToken,
+    unseal: thread::JoinHandle<Result<()>>,
+}
+
+impl UnsealedStream {
+    #[allow(clippy::too_many_arguments)]
+    fn start(
+        config: Box<SectorConfig>,
+        sealed_path: PathBuf,
+        prover_id: [u8; 31],
+        sector_id: FrSafe,
+        offset: u64,
+        num_bytes: u64,
+        legacy: LegacyReplicas,
+    ) -> UnsealedStream {
+        let (tx, chunks) = mpsc::sync_channel(PIPELINE_CHUNKS);
+        let token = CancellationToken::new();
+
+        let unseal = {
+            let token = token.clone();
+
+            thread::spawn(move || -> Result<()> {
+                let mut out = BufWriter::with_capacity(PIPELINE_CHUNK_BYTES, ChunkWriter(tx));
+
+                internal::unseal_range_into(
+                    config.as_ref(),
+                    sealed_path,
+                    &prover_id,
+                    &sector_id,
+                    offset,
+                    num_bytes,
+                    legacy,
+                    &token,
+                    &mut out,
+                )?;
+
+                Ok(out.flush()?)
+            })
+        };
+
+        UnsealedStream {
+            chunks,
+            chunk: Vec::new(),
+            pos: 0,
+            ended: false,
+            token,
+            unseal,
+        }
+    }
+
+    // Waits for the unseal to end, and returns how it did if the stream was
+    // read to its end. If it wasn't, the piece's staging having failed, the
+    // unseal is cancelled, and how it ended doesn't matter.
+    fn finish(self) -> Result<()> {
+        let UnsealedStream {
+            chunks,
+            ended,
+            token,
+            unseal,
+            ..
+        } = self;
+
+        if !ended {
+            token.cancel();
+        }
+
+        // Closing the queue fails the unseal's next write, should it be
+        // waiting for room in it.
+        drop(chunks);
+
+        let unsealed = unseal
+            .join()
+            .map_err(|_| err_unrecov("the unseal of a migrated piece panicked"))?;
+
+        if ended {
+            unsealed
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl Read for UnsealedStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.pos == self.chunk.len() {
+            if self.ended {
+                return Ok(0);
+            }
+
+            match self.chunks.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.pos = 0;
+                }
+                Err(_) => self.ended = true,
+            }
+        }
+
+        let n = cmp::min(buf.len(), self.chunk.len() - self.pos);
+        buf[..n].copy_from_slice(&self.chunk[self.pos..self.pos + n]);
+        self.pos += n;
+
+        Ok(n)
+    }
+}
+
+// Hands each write over to an UnsealedStream as a chunk, waiting for room in
+// its queue.
+struct ChunkWriter(mpsc::SyncSender<Vec<u8>>);
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0
+            .send(buf.to_vec())
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "the stream was dropped"))?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sector_builder::helpers::add_piece::add_piece;
+    use crate::api::sector_builder::helpers::metadata_txn::MetadataJournal;
+    use crate::api::sector_builder::helpers::sector_ids::test_allocator;
+    use crate::api::sector_builder::helpers::snapshots::load_snapshot;
+    use crate::api::sector_builder::kv_store::fs::FileSystemKvs;
+    use crate::api::sector_builder::state::SealedState;
+    use crate::api::sector_builder::WrappedKeyValueStore;
+    use sector_base::api::disk_backed_storage::{new_sector_store, ConfiguredStore};
+    use std::fs;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+
+    fn store(cs: &ConfiguredStore, dir: &tempfile::TempDir) -> Arc<WrappedSectorStore> {
+        let path = dir.path().to_str().unwrap().to_owned();
+
+        Arc::new(WrappedSectorStore {
+            inner: Box::new(new_sector_store(cs, path.clone(), path)),
+        })
+    }
+
+    fn state(sealed: Vec<SealedSectorMetadata>) -> SectorBuilderState {
+        SectorBuilderState {
+            prover_id: [0; 31],
+            staged: Default::default(),
+            sealed: SealedState {
+                sectors: sealed.into_iter().map(|s| (s.sector_id, s)).collect(),
+            },
+        }
+    }
+
+    fn invalid_migration(result: Result<(SealedSectorMetadata, Vec<MigratedPiece>)>) -> String {
+        match result.map_err(|err| err.downcast::<SectorBuilderErr>()) {
+            Err(Ok(SectorBuilderErr::InvalidMigration(reason))) => reason,
+            Err(Ok(err)) => panic!("unexpected error: {}", err),
+            Err(Err(err)) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("sectors were migrated"),
+        }
+    }
+
+    #[test]
+    fn refuses_sectors_which_cannot_be_migrated() {
+        let dir = tempfile::tempdir().unwrap();
+        let sector_store = store(&ConfiguredStore::Test, &dir);
+        let target_store = store(&ConfiguredStore::LargeTest, &dir);
+        let ids = &mut test_allocator(&dir.path().join("metadata"), 10);
+
+        let piece = |piece_key: &str, num_bytes| PieceMetadata {
+            piece_key: piece_key.to_string(),
+            num_bytes,
+            comm_p: None,
+            expires_at: None,
+            aliases: Vec::new(),
+        };
+
+        let state = state(vec![
+            SealedSectorMetadata {
+                sector_id: 1,
+                pieces: vec![piece("a", 900)],
+                ..Default::default()
+            },
+            SealedSectorMetadata {
+                sector_id: 2,
+                pieces: vec![piece("b", 900), piece("c", 900)],
+                ..Default::default()
+            },
+            SealedSectorMetadata {
+                sector_id: 3,
+                retired_into: Some(4),
+                ..Default::default()
+            },
+        ]);
+        let entries_before = fs::read_dir(dir.path()).unwrap().count();
+
+        let mut migrate = |source_sector_ids: &[u64]| {
+            migrate_sectors(&sector_store, &target_store, &state, ids, source_sector_ids, None)
+        };
+
+        assert!(invalid_migration(migrate(&[1, 2])).contains("exceed the capacity"));
+        assert!(invalid_migration(migrate(&[1, 1])).contains("more than once"));
+        assert!(invalid_migration(migrate(&[3])).contains("already been migrated"));
+
+        match migrate(&[5]).map_err(|err| err.downcast::<SectorBuilderErr>()) {
+            Err(Ok(SectorBuilderErr::SealedSectorNotFound { sector_id: 5 })) => (),
+            Err(Ok(err)) => panic!("unexpected error: {}", err),
+            Err(Err(err)) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("a missing sector was migrated"),
+        }
+
+        // Nothing was staged.
+        assert_eq!(entries_before, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn refuses_pieces_whose_comm_p_has_changed() {
+        let dir = tempfile::tempdir().unwrap();
+        let sector_store = store(&ConfiguredStore::Test, &dir);
+        let target_store = store(&ConfiguredStore::LargeTest, &dir);
+        let ids = &mut test_allocator(&dir.path().join("metadata"), 1);
+
+        let mut staged_state = StagedState::default();
+        let sector_id =
+            add_piece(&sector_store, &mut staged_state, ids, "a".to_string(), &[1; 100], None)
+                .unwrap();
+        add_piece(&sector_store, &mut staged_state, ids, "b".to_string(), &[2; 200], None)
+            .unwrap();
+        let staged_sector = staged_state.sectors.remove(&sector_id).unwrap();
+
+        let (throttle, token) = (SealThrottle::default(), CancellationToken::new());
+        let mut sealed_sector =
+            seal(&sector_store, &[0; 31], staged_sector, false, None, None, &throttle, &token)
+                .unwrap();
+
+        // The recorded comm_p of the second piece no longer matches its bytes.
+        sealed_sector.pieces[1].comm_p = Some([9; 32]);
+        let state = state(vec![sealed_sector]);
+        let entries_before = fs::read_dir(dir.path()).unwrap().count();
+
+        match migrate_sectors(&sector_store, &target_store, &state, ids, &[sector_id], None)
+            .map_err(|err| err.downcast::<SectorBuilderErr>())
+        {
+            Err(Ok(SectorBuilderErr::MigratedPieceMismatch {
+                sector_id: mismatched_id,
+                piece_key,
+            })) => assert_eq!((sector_id, "b".to_string()), (mismatched_id, piece_key)),
+            Err(Ok(err)) => panic!("unexpected error: {}", err),
+            Err(Err(err)) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("a mismatched piece was migrated"),
+        }
+
+        // The new staged sector isn't left behind.
+        assert_eq!(entries_before, fs::read_dir(dir.path()).unwrap().count());
+    }
+
+    // The bytes allocated to the files in dir which aren't among existing.
+    fn allocated_bytes(store: &WrappedSectorStore, dir: &Path, existing: &HashSet<PathBuf>) -> u64 {
+        fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .filter(|path| path.is_file() && !existing.contains(path))
+            .map(|path| {
+                let access = path.to_str().unwrap();
+                store.inner.manager().allocated_bytes(access).unwrap_or(0)
+            })
+            .sum()
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn pieces_are_streamed_from_their_sources_into_the_target() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let target_dir = tempfile::tempdir().unwrap();
+        let sector_store = store(&ConfiguredStore::Test, &source_dir);
+        let target_store = store(&ConfiguredStore::LargeTest, &target_dir);
+        let ids = &mut test_allocator(&source_dir.path().join("metadata"), 1);
+
+        let config = sector_store.inner.config();
+        let piece_bytes = config.max_unsealed_bytes_per_sector() as usize / 4;
+
+        // Two sealed sectors of two pieces each.
+        let mut sources = Vec::new();
+        let mut pieces = Vec::new();
+
+        for sector in 0..2u8 {
+            let mut staged_state = StagedState::default();
+            let mut sector_id = 0;
+
+            for piece in 0..2u8 {
+                let piece_key = format!("{}-{}", sector, piece);
+                let bytes: Vec<u8> = (0..piece_bytes).map(|i| i as u8 ^ sector ^ piece).collect();
+
+                sector_id = add_piece(
+                    &sector_store,
+                    &mut staged_state,
+                    ids,
+                    piece_key.clone(),
+                    &bytes,
+                    None,
+                )
+                .unwrap();
+                pieces.push((piece_key, bytes));
+            }
+
+            let staged_sector = staged_state.sectors.remove(&sector_id).unwrap();
+            let (throttle, token) = (SealThrottle::default(), CancellationToken::new());
+            let sealed_sector =
+                seal(&sector_store, &[0; 31], staged_sector, false, None, None, &throttle, &token)
+                    .unwrap();
+            sources.push(sealed_sector);
+        }
+
+        let source_ids: Vec<u64> = sources.iter().map(|s| s.sector_id).collect();
+        let first_sealed = fs::read(&sources[0].sector_access).unwrap();
+        let state = state(sources.clone());
+
+        // Failing to unseal the second sector fails the migration, leaving the
+        // target's staging as it was, and the first sector as it was.
+        let moved_aside = format!("{}.aside", sources[1].sector_access);
+        fs::rename(&sources[1].sector_access, &moved_aside).unwrap();
+
+        assert!(
+            migrate_sectors(&sector_store, &target_store, &state, ids, &source_ids, None).is_err()
+        );
+        assert_eq!(0, fs::read_dir(target_dir.path()).unwrap().count());
+        assert_eq!(first_sealed, fs::read(&sources[0].sector_access).unwrap());
+
+        fs::rename(&moved_aside, &sources[1].sector_access).unwrap();
+
+        // Whatever's written beside the sources while they're migrated is
+        // scratch, which is sampled throughout.
+        let existing: HashSet<PathBuf> = fs::read_dir(source_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        let migrating = Arc::new(AtomicBool::new(true));
+
+        let monitor = {
+            let (sector_store, migrating) = (sector_store.clone(), migrating.clone());
+            let dir = source_dir.path().to_path_buf();
+
+            thread::spawn(move || {
+                let mut peak = 0;
+                while migrating.load(Ordering::SeqCst) {
+                    peak = cmp::max(peak, allocated_bytes(&sector_store, &dir, &existing));
+                    thread::sleep(Duration::from_millis(1));
+                }
+                peak
+            })
+        };
+
+        let migrated =
+            migrate_sectors(&sector_store, &target_store, &state, ids, &source_ids, None);
+        migrating.store(false, Ordering::SeqCst);
+        let peak_scratch_bytes = monitor.join().unwrap();
+
+        let (sealed, migrated) = migrated.unwrap();
+        assert_eq!(4, migrated.len());
+        assert!(
+            peak_scratch_bytes < config.sector_bytes() / 8,
+            "{} bytes of scratch were allocated",
+            peak_scratch_bytes
+        );
+
+        // Each piece is where its boundaries in its source put it, in order.
+        let target_config = sealed.config(target_store.inner.config());
+        let mut offset = 0;
+
+        assert_eq!(pieces.len(), sealed.pieces.len());
+        for ((piece_key, bytes), piece) in pieces.iter().zip(&sealed.pieces) {
+            assert_eq!(&piece.piece_key, piece_key);
+            assert_eq!(bytes.len() as u64, piece.num_bytes);
+
+            let unsealed = internal::unseal_range(
+                target_config.as_ref(),
+                &sealed.sector_access,
+                &[0; 31],
+                &SectorId(sealed.sector_id).to_fr_safe(),
+                offset,
+                piece.num_bytes,
+                sealed.legacy_replicas(),
+            )
+            .unwrap();
+            assert_eq!(bytes, &unsealed);
+
+            offset += piece.num_bytes;
+        }
+    }
+
+    #[test]
+    fn migrations_are_recorded_with_the_retirement_of_their_sources() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv_store = Arc::new(WrappedKeyValueStore {
+            inner: Box::new(FileSystemKvs::initialize(dir.path()).unwrap()),
+        });
+        let journal = MetadataJournal::new(kv_store.clone(), &[0; 31]);
+
+        let sealed = |sector_id| SealedSectorMetadata {
+            sector_id,
+            ..Default::default()
+        };
+        let mut state = state(vec![sealed(1), sealed(2), sealed(3)]);
+
+        let mut txn = journal.begin_txn();
+        record_migration(&mut txn, &state, &[1, 2], sealed(4));
+        txn.commit(&mut state).unwrap();
+
+        let loaded: SectorBuilderState =
+            load_snapshot(&kv_store, &[0; 31]).unwrap().unwrap().into();
+
+        for state in &[state, loaded] {
+            let retired_into = |sector_id| state.sealed.sectors[&sector_id].retired_into;
+
+            assert_eq!(Some(4), retired_into(1));
+            assert_eq!(Some(4), retired_into(2));
+            assert_eq!(None, retired_into(3));
+            assert_eq!(None, retired_into(4));
+        }
+    }
+}
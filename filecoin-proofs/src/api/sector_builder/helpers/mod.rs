@@ -1,6 +1,18 @@
 pub mod add_piece;
+pub mod expiry;
 pub mod get_seal_status;
 pub mod get_sectors_ready_for_sealing;
+pub mod metadata_txn;
+pub mod migrate_sectors;
+pub mod pending_seals;
+pub mod piece_index;
+pub mod piece_intents;
+pub mod piece_keys;
+pub mod piece_placement;
+pub mod regenerate_proof;
 pub mod retrieve_piece;
 pub mod seal;
+pub mod sector_ids;
+pub mod sector_transfer;
 pub mod snapshots;
+pub mod staged_data;
@@ -0,0 +1,223 @@
+use crate::api::sector_builder::metadata::SealStatus;
+use crate::api::sector_builder::state::SectorBuilderState;
+use crate::api::sector_builder::work_queue::PersistedQueue;
+use crate::error::Result;
+use crate::FCP_LOG;
+use slog::*;
+use std::fs;
+use std::path::Path;
+
+// A builder which shuts down records the seals it had queued, but not yet
+// started, in a work queue in its metadata directory, next first. Their
+// sectors keep the Sealing status the snapshot records for them. A builder
+// which starts over the same metadata restores the seals, and queues them
+// again before it takes any request.
+//
+// A seal restored is started in the work queue, and completed once its result
+// has been handled, so that a builder which crashes before then restores it
+// again. Only the seals of sectors which are still staged, and still marked
+// Sealing, are restored. The others (e.g. of sectors sealed by a builder which
+// crashed after they were recorded) are abandoned, as is every seal recorded
+// if the queue can't be read.
+
+/// The name of the queue in a builder's metadata directory.
+pub const PENDING_SEALS_FILE: &str = "pending-seals.queue";
+
+// How the seals left by the last builder to shut down were restored.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SealRestoration {
+    // The seals of the sectors with the given ids are to be queued again, in
+    // order. Those of the dropped ids aren't.
+    Restored {
+        sector_ids: Vec<u64>,
+        dropped: Vec<u64>,
+    },
+    // The queue couldn't be read, and no seal was restored.
+    Discarded,
+}
+
+impl SealRestoration {
+    // The ids of the sectors whose seals are to be queued again.
+    pub fn sector_ids(&self) -> Vec<u64> {
+        match self {
+            SealRestoration::Restored { sector_ids, .. } => sector_ids.clone(),
+            SealRestoration::Discarded => Vec::new(),
+        }
+    }
+}
+
+// The seals of a builder's metadata directory which wait for the next builder,
+// or which were restored, by sector id.
+pub struct PendingSeals {
+    queue: PersistedQueue<u64>,
+    // Set if the queue found couldn't be read, until restore reports it.
+    discarded: bool,
+}
+
+impl PendingSeals {
+    // Opens the queue in metadata_dir, replacing it with an empty one if it
+    // can't be read.
+    pub fn open<P: AsRef<Path>>(metadata_dir: P) -> Result<PendingSeals> {
+        let path = metadata_dir.as_ref().join(PENDING_SEALS_FILE);
+
+        match PersistedQueue::open(path.clone()) {
+            Ok((queue, _)) => Ok(PendingSeals {
+                queue,
+                discarded: false,
+            }),
+            Err(err) => {
+                warn!(FCP_LOG, "discarding unreadable pending seals"; "error" => err.to_string());
+                fs::remove_file(&path)?;
+
+                let (queue, _) = PersistedQueue::open(path)?;
+                Ok(PendingSeals {
+                    queue,
+                    discarded: true,
+                })
+            }
+        }
+    }
+
+    // Durably records the ids of the sectors whose seals are queued, next
+    // first. The seals restored which are queued still are recorded already.
+    pub fn persist(&self, sector_ids: &[u64]) -> Result<()> {
+        let recorded: Vec<u64> = self.queue.entries().into_iter().map(|e| e.item).collect();
+
+        for sector_id in sector_ids {
+            if !recorded.contains(sector_id) {
+                self.queue.enqueue(*sector_id, true)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Restores the seals left by the last builder to shut down, if there are
+    // any, keeping those of the sectors state still has waiting to be sealed.
+    pub fn restore(&mut self, state: &SectorBuilderState) -> Result<Option<SealRestoration>> {
+        if self.discarded {
+            self.discarded = false;
+            return Ok(Some(SealRestoration::Discarded));
+        }
+
+        let entries = self.queue.entries();
+        if entries.is_empty() {
+            return Ok(None);
+        }
+
+        let (mut sector_ids, mut dropped) = (Vec::new(), Vec::new());
+        for entry in entries {
+            let waiting = state
+                .staged
+                .sectors
+                .get(&entry.item)
+                .map_or(false, |sector| sector.seal_status == SealStatus::Sealing);
+
+            if waiting {
+                self.queue.start(entry.id)?;
+                sector_ids.push(entry.item);
+            } else {
+                self.queue.abandon(entry.id)?;
+                dropped.push(entry.item);
+            }
+        }
+
+        Ok(Some(SealRestoration::Restored {
+            sector_ids,
+            dropped,
+        }))
+    }
+
+    // Durably completes the restored seal of the sector with the given id,
+    // once its result has been handled. Other sectors' seals aren't recorded.
+    pub fn finish(&self, sector_id: u64) -> Result<()> {
+        for entry in self.queue.entries() {
+            if entry.item == sector_id && entry.started {
+                self.queue.complete(entry.id)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sector_builder::metadata::StagedSectorMetadata;
+
+    const PROVER_ID: [u8; 31] = [7; 31];
+
+    // A state with a staged sector of each given id and seal status.
+    fn state(sectors: Vec<(u64, SealStatus)>) -> SectorBuilderState {
+        let mut state = SectorBuilderState {
+            prover_id: PROVER_ID,
+            staged: Default::default(),
+            sealed: Default::default(),
+        };
+
+        for (sector_id, seal_status) in sectors {
+            let sector = StagedSectorMetadata {
+                sector_id,
+                seal_status,
+                ..Default::default()
+            };
+            state.staged.sectors.insert(sector_id, sector);
+        }
+
+        state
+    }
+
+    #[test]
+    fn only_seals_still_waiting_are_restored_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut pending = PendingSeals::open(dir.path()).unwrap();
+        let state = state(vec![
+            (1, SealStatus::Sealing),
+            (2, SealStatus::Pending),
+            (4, SealStatus::Sealing),
+        ]);
+
+        assert_eq!(None, pending.restore(&state).unwrap());
+
+        // Sector 3 isn't staged, and sector 2 no longer waits for its seal.
+        pending.persist(&[4, 3, 2, 1]).unwrap();
+
+        let mut pending = PendingSeals::open(dir.path()).unwrap();
+        assert_eq!(
+            Some(SealRestoration::Restored {
+                sector_ids: vec![4, 1],
+                dropped: vec![3, 2],
+            }),
+            pending.restore(&state).unwrap()
+        );
+        pending.finish(4).unwrap();
+
+        // The seal of sector 1 wasn't handled before the builder stopped, so
+        // it's restored again.
+        let mut pending = PendingSeals::open(dir.path()).unwrap();
+        assert_eq!(
+            vec![1],
+            pending.restore(&state).unwrap().unwrap().sector_ids()
+        );
+        pending.persist(&[1]).unwrap();
+        pending.finish(1).unwrap();
+
+        let mut pending = PendingSeals::open(dir.path()).unwrap();
+        assert_eq!(None, pending.restore(&state).unwrap());
+    }
+
+    #[test]
+    fn unreadable_records_are_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = state(vec![(1, SealStatus::Sealing)]);
+
+        fs::write(dir.path().join(PENDING_SEALS_FILE), b"not a record").unwrap();
+
+        let mut pending = PendingSeals::open(dir.path()).unwrap();
+        let restoration = pending.restore(&state).unwrap();
+        assert_eq!(Some(SealRestoration::Discarded), restoration);
+        assert!(restoration.unwrap().sector_ids().is_empty());
+        assert_eq!(None, pending.restore(&state).unwrap());
+    }
+}
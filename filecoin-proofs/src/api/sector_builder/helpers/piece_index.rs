@@ -0,0 +1,253 @@
+use crate::api::sector_builder::errors::err_unrecov;
+use crate::api::sector_builder::metadata::{PieceAlias, PieceMetadata, PiecePlacement};
+use crate::api::sector_builder::state::{SealedState, StagedState};
+use crate::error::Result;
+use std::collections::HashMap;
+use storage_proofs::types::Commitment;
+
+// The pieces placed in a builder's sectors, staged or sealed, by comm_p: the
+// keys each piece is read under (its own, and its aliases'), along with the
+// sector holding its bytes. Retired sectors are left out, as their pieces are
+// read from the sectors they were migrated into, and so are pieces staged
+// before commitments were recorded.
+//
+// The index is built from the sectors' records as a piece is added, rather
+// than kept beside them, so it never disagrees with them as sectors are
+// sealed, migrated or imported.
+pub struct PieceIndex {
+    pieces: HashMap<Commitment, Vec<IndexedPiece>>,
+}
+
+struct IndexedPiece {
+    placement: PiecePlacement,
+    num_bytes: u64,
+    alias_keys: Vec<String>,
+}
+
+impl PieceIndex {
+    pub fn new(staged_state: &StagedState, sealed_state: &SealedState) -> PieceIndex {
+        let staged = staged_state
+            .sectors
+            .values()
+            .map(|sector| (sector.sector_id, &sector.pieces));
+
+        let sealed = sealed_state
+            .sectors
+            .values()
+            .filter(|sector| sector.retired_into.is_none())
+            .map(|sector| (sector.sector_id, &sector.pieces));
+
+        // Sectors are indexed in order, so that the first piece placed with
+        // some bytes is always the same.
+        let mut sectors: Vec<(u64, &Vec<PieceMetadata>)> = staged.chain(sealed).collect();
+        sectors.sort_by_key(|(sector_id, _)| *sector_id);
+
+        let mut pieces: HashMap<Commitment, Vec<IndexedPiece>> = HashMap::new();
+        for (sector_id, sector_pieces) in sectors {
+            for piece in sector_pieces {
+                if let Some(comm_p) = piece.comm_p {
+                    pieces.entry(comm_p).or_default().push(IndexedPiece {
+                        placement: PiecePlacement {
+                            piece_key: piece.piece_key.clone(),
+                            sector_id,
+                        },
+                        num_bytes: piece.num_bytes,
+                        alias_keys: piece.aliases.iter().map(|a| a.piece_key.clone()).collect(),
+                    });
+                }
+            }
+        }
+
+        PieceIndex { pieces }
+    }
+
+    // The keys the pieces with the given comm_p are read under, and the
+    // sectors holding their bytes, in order of sector id.
+    pub fn placements(&self, comm_p: &Commitment) -> Vec<PiecePlacement> {
+        self.pieces.get(comm_p).map_or_else(Vec::new, |pieces| {
+            pieces
+                .iter()
+                .flat_map(|indexed| {
+                    let aliases = indexed
+                        .alias_keys
+                        .iter()
+                        .map(move |piece_key| PiecePlacement {
+                            piece_key: piece_key.clone(),
+                            sector_id: indexed.placement.sector_id,
+                        });
+
+                    Some(indexed.placement.clone()).into_iter().chain(aliases)
+                })
+                .collect()
+        })
+    }
+
+    // The first piece placed with the same bytes as piece, as told by its
+    // comm_p and its length, if there is one. Pieces of different lengths may
+    // share a comm_p, as their bytes are padded before they're committed to.
+    pub fn duplicate_of(&self, piece: &PieceMetadata) -> Option<PiecePlacement> {
+        // variable-time: the comm_p looked up was computed from bytes the
+        // caller gave.
+        let pieces = self.pieces.get(&piece.comm_p?)?;
+
+        pieces
+            .iter()
+            .find(|indexed| indexed.num_bytes == piece.num_bytes)
+            .map(|indexed| indexed.placement.clone())
+    }
+}
+
+// Records alias as an alias of the piece placed as existing, with the given
+// comm_p, in the staged or sealed sector holding it.
+pub fn add_alias(
+    staged_state: &mut StagedState,
+    sealed_state: &mut SealedState,
+    existing: &PiecePlacement,
+    comm_p: &Commitment,
+    alias: PieceAlias,
+) -> Result<()> {
+    let pieces = match staged_state.sectors.get_mut(&existing.sector_id) {
+        Some(sector) => &mut sector.pieces,
+        None => match sealed_state.sectors.get_mut(&existing.sector_id) {
+            Some(sector) => &mut sector.pieces,
+            None => return Err(err_unrecov("unable to retrieve sector from state-map").into()),
+        },
+    };
+
+    // variable-time: as in duplicate_of, comm_p was computed from bytes the
+    // caller gave.
+    let piece = pieces
+        .iter_mut()
+        .find(|piece| piece.piece_key == existing.piece_key && piece.comm_p == Some(*comm_p))
+        .ok_or_else(|| err_unrecov("unable to retrieve piece from sector"))?;
+
+    piece.aliases.push(alias);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sector_builder::metadata::{SealedSectorMetadata, StagedSectorMetadata};
+
+    fn piece(piece_key: &str, num_bytes: u64, comm_p: Option<Commitment>) -> PieceMetadata {
+        PieceMetadata {
+            piece_key: piece_key.to_string(),
+            num_bytes,
+            comm_p,
+            expires_at: None,
+            aliases: Vec::new(),
+        }
+    }
+
+    // Sealed sectors 0 and 2, the first retired into the second, and staged
+    // sector 1.
+    fn setup() -> (StagedState, SealedState) {
+        let mut sealed = SealedState::default();
+
+        for (sector_id, retired_into, pieces) in vec![
+            (0, Some(2), vec![piece("a", 100, Some([1; 32]))]),
+            (
+                2,
+                None,
+                vec![piece("a", 100, Some([1; 32])), piece("b", 50, None)],
+            ),
+        ] {
+            sealed.sectors.insert(
+                sector_id,
+                SealedSectorMetadata {
+                    sector_id,
+                    retired_into,
+                    pieces,
+                    ..Default::default()
+                },
+            );
+        }
+
+        let mut staged = StagedState::default();
+        staged.sectors.insert(
+            1,
+            StagedSectorMetadata {
+                sector_id: 1,
+                pieces: vec![
+                    piece("c", 100, Some([1; 32])),
+                    piece("d", 70, Some([1; 32])),
+                ],
+                ..Default::default()
+            },
+        );
+
+        (staged, sealed)
+    }
+
+    fn placement(piece_key: &str, sector_id: u64) -> PiecePlacement {
+        PiecePlacement {
+            piece_key: piece_key.to_string(),
+            sector_id,
+        }
+    }
+
+    #[test]
+    fn duplicates_are_found_by_comm_p_and_length() {
+        let (staged, sealed) = setup();
+        let index = PieceIndex::new(&staged, &sealed);
+
+        // Retired sectors are left out, and so are pieces without a comm_p.
+        assert_eq!(
+            vec![placement("c", 1), placement("d", 1), placement("a", 2)],
+            index.placements(&[1; 32])
+        );
+        assert!(index.placements(&[2; 32]).is_empty());
+
+        assert_eq!(
+            Some(placement("c", 1)),
+            index.duplicate_of(&piece("e", 100, Some([1; 32])))
+        );
+        assert_eq!(
+            Some(placement("d", 1)),
+            index.duplicate_of(&piece("e", 70, Some([1; 32])))
+        );
+        assert_eq!(None, index.duplicate_of(&piece("e", 60, Some([1; 32]))));
+        assert_eq!(None, index.duplicate_of(&piece("e", 50, None)));
+    }
+
+    #[test]
+    fn aliases_are_indexed_with_their_pieces() {
+        let (mut staged, mut sealed) = setup();
+
+        let mut add = |piece_key: &str, sector_id: u64, alias_key: &str| {
+            let alias = PieceAlias {
+                piece_key: alias_key.to_string(),
+                expires_at: None,
+            };
+            let existing = placement(piece_key, sector_id);
+
+            add_alias(&mut staged, &mut sealed, &existing, &[1; 32], alias)
+        };
+
+        add("a", 2, "x").unwrap();
+        add("d", 1, "y").unwrap();
+
+        // Piece b has no comm_p.
+        assert!(add("b", 2, "z").is_err());
+
+        let index = PieceIndex::new(&staged, &sealed);
+        assert_eq!(
+            vec![
+                placement("c", 1),
+                placement("d", 1),
+                placement("y", 1),
+                placement("a", 2),
+                placement("x", 2),
+            ],
+            index.placements(&[1; 32])
+        );
+
+        // The piece an alias is read from is the duplicate.
+        assert_eq!(
+            Some(placement("d", 1)),
+            index.duplicate_of(&piece("e", 70, Some([1; 32])))
+        );
+    }
+}
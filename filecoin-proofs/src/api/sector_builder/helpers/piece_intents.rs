@@ -0,0 +1,584 @@
+use crate::api::sector_builder::helpers::snapshots::{make_snapshot, persist_snapshot};
+use crate::api::sector_builder::helpers::staged_data::compute_comm_p;
+use crate::api::sector_builder::metadata::{
+    sum_piece_bytes, PieceMetadata, SealStatus, StagedSectorMetadata,
+};
+use crate::api::sector_builder::state::SectorBuilderState;
+use crate::api::sector_builder::WrappedKeyValueStore;
+use crate::error::Result;
+use sector_base::api::sector_store::{SectorManager, UnsealedCheckpoint};
+use sector_base::io::fr32::write_unpadded;
+use std::io::Read;
+use std::sync::Arc;
+use storage_proofs::crypto::constant_time::ConstantTimeEq;
+use storage_proofs::fail_point;
+use storage_proofs::failpoint;
+use storage_proofs::types::Commitment;
+
+// A piece is staged in three steps, each durable before the next begins, so
+// that a crash at any point leaves the staged file and the builder's metadata
+// in agreement once the builder is restarted:
+//
+// 1. An intent is recorded in the metadata store, under its own key, naming
+//    the piece, the sector it's bound for and what the sector's staged file
+//    held before the piece.
+// 2. The piece's bytes are written to the staged file, which is synced. The
+//    intent is recorded again, with the piece's comm_p, marking the bytes as
+//    written.
+// 3. The snapshot recording the piece is persisted, which commits it. The
+//    intent is then cleared.
+//
+// An intent found when a builder starts is resolved before anything else is
+// done. If its piece was committed, it's cleared. If its bytes were written,
+// and the staged file still holds them, matching the comm_p recorded, the
+// piece is committed. Otherwise the staged file is restored to what it held
+// before the piece, and the intent is discarded.
+
+const KEY_SUFFIX: &[u8] = b"/piece-intent";
+
+// A piece being staged, recorded before any of its bytes are written.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PieceIntent {
+    pub piece_key: String,
+    pub num_bytes: u64,
+    pub expires_at: Option<u64>,
+    pub sector_id: u64,
+    pub sector_access: String,
+    // The length and last byte of the staged file before the piece was
+    // written to it.
+    pub staged_len: u64,
+    pub staged_last_byte: Option<u8>,
+    // Recorded once the piece's bytes have been written and synced.
+    pub comm_p: Option<Commitment>,
+}
+
+// How an intent left behind by a crash was resolved.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntentResolution {
+    // The piece had been committed, and only the intent was left.
+    AlreadyCommitted,
+    // The piece's bytes were found whole, and the piece was committed.
+    Committed,
+    // The piece's bytes were missing, partial or damaged, and were dropped
+    // from the staged file.
+    RolledBack,
+}
+
+// The intent of a builder's metadata store, of which there's at most one, as
+// a builder stages one piece at a time.
+pub struct PieceIntents {
+    kv_store: Arc<WrappedKeyValueStore>,
+    key: Vec<u8>,
+}
+
+impl PieceIntents {
+    pub fn new(kv_store: Arc<WrappedKeyValueStore>, prover_id: &[u8; 31]) -> PieceIntents {
+        PieceIntents {
+            kv_store,
+            key: [&prover_id[..], KEY_SUFFIX].concat(),
+        }
+    }
+
+    pub fn read(&self) -> Result<Option<PieceIntent>> {
+        match self.kv_store.inner.get(&self.key)? {
+            Some(ref bytes) if !bytes.is_empty() => Ok(Some(serde_cbor::from_slice(bytes)?)),
+            _ => Ok(None),
+        }
+    }
+
+    // Durably records the intent, replacing any other.
+    pub fn record(&self, intent: &PieceIntent) -> Result<()> {
+        self.kv_store
+            .inner
+            .put(&self.key, &serde_cbor::to_vec(intent)?)?;
+        fail_point!(failpoint::PIECE_INTENT_WRITTEN);
+
+        Ok(())
+    }
+
+    // Durably clears the intent. The store can't remove a key, so the intent
+    // is replaced with nothing.
+    pub fn clear(&self) -> Result<()> {
+        self.kv_store.inner.put(&self.key, &[])
+    }
+
+    // Resolves the intent left behind by a builder which crashed while staging
+    // a piece, if there is one, bringing state (and the snapshot it's
+    // persisted in) and the piece's staged sector back into agreement.
+    pub fn resolve(
+        &self,
+        sector_mgr: &SectorManager,
+        state: &mut SectorBuilderState,
+    ) -> Result<Option<IntentResolution>> {
+        let intent = match self.read()? {
+            Some(intent) => intent,
+            None => return Ok(None),
+        };
+
+        // Sectors are only sealed once their pieces are committed.
+        if state.sealed.sectors.contains_key(&intent.sector_id) {
+            self.clear()?;
+            return Ok(Some(IntentResolution::AlreadyCommitted));
+        }
+
+        let resolution = {
+            // A sector provisioned for the piece was never recorded if the
+            // piece wasn't committed.
+            let sector = state
+                .staged
+                .sectors
+                .entry(intent.sector_id)
+                .or_insert_with(|| StagedSectorMetadata {
+                    sector_id: intent.sector_id,
+                    sector_access: intent.sector_access.clone(),
+                    pieces: Vec::new(),
+                    seal_status: SealStatus::Pending,
+                });
+
+            match intent.comm_p {
+                Some(comm_p) if is_committed(sector, &intent.piece_key, comm_p) => {
+                    self.clear()?;
+                    return Ok(Some(IntentResolution::AlreadyCommitted));
+                }
+                Some(comm_p) if holds_piece(sector_mgr, sector, &intent, comm_p)? => {
+                    sector.pieces.push(PieceMetadata {
+                        piece_key: intent.piece_key.clone(),
+                        num_bytes: intent.num_bytes,
+                        comm_p: Some(comm_p),
+                        expires_at: intent.expires_at,
+                        aliases: Vec::new(),
+                    });
+
+                    IntentResolution::Committed
+                }
+                _ => {
+                    let checkpoint = UnsealedCheckpoint {
+                        len: intent.staged_len,
+                        last_byte: intent.staged_last_byte,
+                    };
+                    sector_mgr.restore_unsealed(&intent.sector_access, &checkpoint)?;
+
+                    IntentResolution::RolledBack
+                }
+            }
+        };
+
+        let snapshot = make_snapshot(&state.prover_id, &state.staged, &state.sealed);
+        persist_snapshot(&self.kv_store, &snapshot)?;
+        self.clear()?;
+
+        Ok(Some(resolution))
+    }
+}
+
+fn is_committed(sector: &StagedSectorMetadata, piece_key: &str, comm_p: Commitment) -> bool {
+    // variable-time: both comm_ps were recorded by the builder itself.
+    sector
+        .pieces
+        .iter()
+        .any(|piece| piece.piece_key == piece_key && piece.comm_p == Some(comm_p))
+}
+
+// Whether the sector's staged file holds the whole of the intent's piece,
+// right after the sector's pieces, matching comm_p.
+fn holds_piece(
+    sector_mgr: &SectorManager,
+    sector: &StagedSectorMetadata,
+    intent: &PieceIntent,
+    comm_p: Commitment,
+) -> Result<bool> {
+    let offset = sum_piece_bytes(sector);
+    if sector_mgr.num_unsealed_bytes(&sector.sector_access)? < offset + intent.num_bytes {
+        return Ok(false);
+    }
+
+    let mut staged = Vec::new();
+    sector_mgr
+        .open_unsealed(&sector.sector_access)?
+        .read_to_end(&mut staged)?;
+
+    let num_bytes = intent.num_bytes as usize;
+    let mut piece_bytes = Vec::with_capacity(num_bytes);
+    let written = write_unpadded(&staged, &mut piece_bytes, offset as usize, num_bytes)?;
+
+    Ok(written == num_bytes && compute_comm_p(&piece_bytes)?.ct_eq(&comm_p))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sector_builder::errors::SectorBuilderErr;
+    use crate::api::sector_builder::helpers::add_piece::{add_piece_with_intent, PieceOutcome};
+    use crate::api::sector_builder::helpers::piece_index::PieceIndex;
+    use crate::api::sector_builder::helpers::sector_ids::{SectorIdAllocator, SectorIdRange};
+    use crate::api::sector_builder::helpers::snapshots::load_snapshot;
+    use crate::api::sector_builder::helpers::staged_data::verify_staged_data;
+    use crate::api::sector_builder::kv_store::fs::FileSystemKvs;
+    use crate::api::sector_builder::metadata::DuplicatePiecePolicy;
+    use crate::api::sector_builder::WrappedSectorStore;
+    use sector_base::api::disk_backed_storage::{new_sector_store, ConfiguredStore};
+    use std::fs::OpenOptions;
+    use std::io::{self, Write};
+    use std::panic::{self, AssertUnwindSafe};
+    #[cfg(feature = "failpoints")]
+    use storage_proofs::failpoint::{FailAction, FailScenario};
+
+    const PROVER_ID: [u8; 31] = [6; 31];
+
+    // Crashes mid-write, once it has produced the given number of bytes.
+    struct CrashingSource {
+        bytes: Vec<u8>,
+        pos: usize,
+        crash_at: usize,
+    }
+
+    impl Read for CrashingSource {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.pos >= self.crash_at {
+                panic!("crashed");
+            }
+
+            let n = (self.crash_at - self.pos).min(buf.len());
+            buf[..n].copy_from_slice(&self.bytes[self.pos..self.pos + n]);
+            self.pos += n;
+
+            Ok(n)
+        }
+    }
+
+    struct Builder {
+        _dir: tempfile::TempDir,
+        sector_store: Arc<WrappedSectorStore>,
+        kv_store: Arc<WrappedKeyValueStore>,
+    }
+
+    impl Builder {
+        fn new() -> Builder {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().to_str().unwrap().to_owned();
+
+            let sector_store = Arc::new(WrappedSectorStore {
+                inner: Box::new(new_sector_store(&ConfiguredStore::Test, path.clone(), path)),
+            });
+
+            let kv_store = Arc::new(WrappedKeyValueStore {
+                inner: Box::new(FileSystemKvs::initialize(dir.path().join("metadata")).unwrap()),
+            });
+
+            Builder {
+                _dir: dir,
+                sector_store,
+                kv_store,
+            }
+        }
+
+        fn intents(&self) -> PieceIntents {
+            PieceIntents::new(self.kv_store.clone(), &PROVER_ID)
+        }
+
+        // Starts the builder over whatever made it to disk, resolving any
+        // intent left behind.
+        fn restart(&self) -> (SectorBuilderState, Option<IntentResolution>) {
+            let mut state = load_snapshot(&self.kv_store, &PROVER_ID)
+                .unwrap()
+                .map(|snapshot| snapshot.into())
+                .unwrap_or_else(|| SectorBuilderState {
+                    prover_id: PROVER_ID,
+                    staged: Default::default(),
+                    sealed: Default::default(),
+                });
+
+            let resolution = self
+                .intents()
+                .resolve(self.sector_store.inner.manager(), &mut state)
+                .unwrap();
+
+            (state, resolution)
+        }
+
+        // Stages a piece as the scheduler does, which may crash (e.g. should
+        // the source panic).
+        fn stage(&self, piece_key: &str, source: &mut Read, len: u64) {
+            let (mut state, _) = self.restart();
+            let mut ids =
+                SectorIdAllocator::load(self.kv_store.clone(), &state, SectorIdRange::default())
+                    .unwrap();
+            let intents = self.intents();
+
+            let _ = panic::catch_unwind(AssertUnwindSafe(|| {
+                let placed = PieceIndex::new(&state.staged, &state.sealed);
+
+                add_piece_with_intent(
+                    &self.sector_store,
+                    &intents,
+                    &mut state.staged,
+                    &mut ids,
+                    &placed,
+                    DuplicatePiecePolicy::Allow,
+                    piece_key.to_string(),
+                    len,
+                    None,
+                    source,
+                )
+                .unwrap();
+
+                let snapshot = make_snapshot(&PROVER_ID, &state.staged, &state.sealed);
+                persist_snapshot(&self.kv_store, &snapshot).unwrap();
+                intents.clear().unwrap();
+            }));
+        }
+
+        // Stages a piece as stage does, crashing at the given hit of the given
+        // failpoint if given.
+        #[cfg(feature = "failpoints")]
+        fn stage_crashing_at(
+            &self,
+            piece_key: &str,
+            source: &mut Read,
+            len: u64,
+            crash_at: Option<(&'static str, usize)>,
+        ) {
+            let scenario = FailScenario::setup();
+            if let Some((name, hit)) = crash_at {
+                scenario.arm_at(name, hit, FailAction::Panic);
+            }
+
+            self.stage(piece_key, source, len);
+        }
+
+        // Checks that the staged files hold exactly the pieces recorded for
+        // them, each of which can be read back.
+        fn assert_consistent(&self, state: &SectorBuilderState) {
+            let mgr = self.sector_store.inner.manager();
+
+            for sector in state.staged.sectors.values() {
+                assert_eq!(
+                    sum_piece_bytes(sector),
+                    mgr.num_unsealed_bytes(&sector.sector_access).unwrap()
+                );
+                verify_staged_data(&self.sector_store, sector).unwrap();
+            }
+
+            assert_eq!(None, self.intents().read().unwrap());
+        }
+    }
+
+    fn piece_keys(state: &SectorBuilderState) -> Vec<String> {
+        let mut keys: Vec<String> = state
+            .staged
+            .sectors
+            .values()
+            .flat_map(|sector| sector.pieces.iter().map(|piece| piece.piece_key.clone()))
+            .collect();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    #[cfg(feature = "failpoints")]
+    fn crashes_between_steps_leave_staging_consistent() {
+        // Staging a piece records the intent, marks it as written once the
+        // piece's bytes are, and persists the snapshot before clearing it.
+        let cases = vec![
+            (
+                Some((failpoint::PIECE_INTENT_WRITTEN, 0)),
+                Some(IntentResolution::RolledBack),
+                vec!["first"],
+            ),
+            (
+                Some((failpoint::PIECE_INTENT_WRITTEN, 1)),
+                Some(IntentResolution::Committed),
+                vec!["first", "second"],
+            ),
+            (
+                Some((failpoint::SNAPSHOT_PERSISTED, 0)),
+                Some(IntentResolution::AlreadyCommitted),
+                vec!["first", "second"],
+            ),
+            (None, None, vec!["first", "second"]),
+        ];
+
+        for (crash_at, resolution, keys) in cases {
+            let builder = Builder::new();
+
+            // Leave the last byte of the staged file only partly used.
+            builder.stage("first", &mut &[9; 27][..], 27);
+            builder.stage_crashing_at("second", &mut &[4; 300][..], 300, crash_at);
+
+            let (state, resolved) = builder.restart();
+            assert_eq!(resolution, resolved, "crash at {:?}", crash_at);
+            assert_eq!(keys, piece_keys(&state));
+            builder.assert_consistent(&state);
+
+            // The sector goes on accepting pieces.
+            builder.stage("third", &mut &[5; 100][..], 100);
+            let (state, _) = builder.restart();
+            assert_eq!(1, state.staged.sectors.len());
+            builder.assert_consistent(&state);
+        }
+    }
+
+    #[test]
+    fn crash_mid_write_is_rolled_back() {
+        let builder = Builder::new();
+        builder.stage("first", &mut &[9; 27][..], 27);
+
+        let (state, _) = builder.restart();
+        let access = state
+            .staged
+            .sectors
+            .values()
+            .next()
+            .unwrap()
+            .sector_access
+            .clone();
+        let before = std::fs::read(&access).unwrap();
+
+        let mut source = CrashingSource {
+            bytes: vec![4; 300],
+            pos: 0,
+            crash_at: 150,
+        };
+        builder.stage("second", &mut source, 300);
+
+        // Padding is written a chunk at a time, so leave a torn chunk behind,
+        // as a crash mid-chunk would.
+        OpenOptions::new()
+            .append(true)
+            .open(&access)
+            .unwrap()
+            .write_all(&[4; 50])
+            .unwrap();
+
+        let (state, resolved) = builder.restart();
+        assert_eq!(Some(IntentResolution::RolledBack), resolved);
+        assert_eq!(vec!["first"], piece_keys(&state));
+        assert_eq!(before, std::fs::read(&access).unwrap());
+        builder.assert_consistent(&state);
+    }
+
+    #[test]
+    #[cfg(feature = "failpoints")]
+    fn crash_after_provisioning_keeps_the_new_sector() {
+        let builder = Builder::new();
+        let max = builder
+            .sector_store
+            .inner
+            .config()
+            .max_unsealed_bytes_per_sector();
+
+        // A full piece needs a sector of its own, whose id is allocated before
+        // the intent is recorded. Crash before the snapshot recording the
+        // sector is persisted.
+        let bytes = vec![7; max as usize];
+        let crash_at = (failpoint::PIECE_INTENT_WRITTEN, 1);
+        builder.stage_crashing_at("full", &mut &bytes[..], max, Some(crash_at));
+        assert!(load_snapshot(&builder.kv_store, &PROVER_ID)
+            .unwrap()
+            .is_none());
+
+        let (state, resolved) = builder.restart();
+        assert_eq!(Some(IntentResolution::Committed), resolved);
+        assert_eq!(vec!["full"], piece_keys(&state));
+        builder.assert_consistent(&state);
+    }
+
+    #[test]
+    #[cfg(feature = "failpoints")]
+    fn written_pieces_lost_before_commit_are_rolled_back() {
+        let builder = Builder::new();
+        builder.stage("first", &mut &[9; 27][..], 27);
+
+        let (state, _) = builder.restart();
+        let access = state
+            .staged
+            .sectors
+            .values()
+            .next()
+            .unwrap()
+            .sector_access
+            .clone();
+        let before = std::fs::read(&access).unwrap();
+
+        // The bytes are marked as written, but lose their tail before the
+        // builder restarts.
+        let crash_at = (failpoint::PIECE_INTENT_WRITTEN, 1);
+        builder.stage_crashing_at("second", &mut &[4; 300][..], 300, Some(crash_at));
+        let len = std::fs::metadata(&access).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&access)
+            .unwrap()
+            .set_len(len - 10)
+            .unwrap();
+
+        let (state, resolved) = builder.restart();
+        assert_eq!(Some(IntentResolution::RolledBack), resolved);
+        assert_eq!(vec!["first"], piece_keys(&state));
+        assert_eq!(before, std::fs::read(&access).unwrap());
+        builder.assert_consistent(&state);
+    }
+
+    #[test]
+    fn duplicates_not_stored_again_are_unstaged() {
+        let builder = Builder::new();
+        builder.stage("first", &mut &[4; 300][..], 300);
+
+        let (state, _) = builder.restart();
+        let access = state
+            .staged
+            .sectors
+            .values()
+            .next()
+            .unwrap()
+            .sector_access
+            .clone();
+        let before = std::fs::read(&access).unwrap();
+
+        for policy in vec![
+            DuplicatePiecePolicy::Reject,
+            DuplicatePiecePolicy::Deduplicate,
+        ] {
+            let (mut state, _) = builder.restart();
+            let mut ids =
+                SectorIdAllocator::load(builder.kv_store.clone(), &state, SectorIdRange::default())
+                    .unwrap();
+            let placed = PieceIndex::new(&state.staged, &state.sealed);
+
+            let outcome = add_piece_with_intent(
+                &builder.sector_store,
+                &builder.intents(),
+                &mut state.staged,
+                &mut ids,
+                &placed,
+                policy,
+                "copy".to_string(),
+                300,
+                None,
+                &mut &[4; 300][..],
+            );
+
+            match (
+                policy,
+                outcome.map_err(|err| err.downcast::<SectorBuilderErr>()),
+            ) {
+                (
+                    DuplicatePiecePolicy::Reject,
+                    Err(Ok(SectorBuilderErr::DuplicatePiece { existing_key, .. })),
+                ) => assert_eq!("first", existing_key),
+                (
+                    DuplicatePiecePolicy::Deduplicate,
+                    Ok(PieceOutcome::Duplicate { existing, .. }),
+                ) => {
+                    assert_eq!("first", existing.piece_key)
+                }
+                (_, other) => panic!("unexpected outcome: {:?}", other),
+            }
+
+            // The copy's bytes were unstaged, and its intent cleared.
+            assert_eq!(vec!["first"], piece_keys(&state));
+            assert_eq!(before, std::fs::read(&access).unwrap());
+            builder.assert_consistent(&state);
+        }
+    }
+}
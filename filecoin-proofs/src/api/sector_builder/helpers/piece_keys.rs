@@ -0,0 +1,354 @@
+use crate::api::sector_builder::errors::SectorBuilderErr;
+use crate::api::sector_builder::state::SectorBuilderState;
+use crate::api::sector_builder::WrappedKeyValueStore;
+use crate::error::Result;
+use crate::FCP_LOG;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use slog::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Mutex;
+
+// A builder given piece key material keeps the keys of the pieces added to it
+// private: each piece is recorded, in its metadata and in its audit log, under
+// a pseudonym of its key, the HMAC-SHA256 of the key under the material, and
+// never under the key itself. Pseudonyms are deterministic, so a piece is read
+// by its key through any builder given the same material. The material is
+// never persisted. Nor is what a pseudonym stands for: a builder remembers the
+// keys of the pieces added and read through it, in memory, so that it may list
+// them by key, but pieces added before it started are listed by pseudonym.
+//
+// Metadata whose pieces are recorded under pseudonyms is marked as such under
+// its own key, with the pseudonym of a check value, from which a builder tells
+// whether it was given the material the metadata was made private with. A
+// builder over private metadata which wasn't given any serves it by pseudonym:
+// its listings give pseudonyms, and pieces are read by theirs, but no pieces
+// may be added through it.
+
+const KEY_SUFFIX: &[u8] = b"/piece-key-privacy";
+
+// What the check value is the pseudonym of. No piece key passed over FFI, a C
+// string, can start with a nul byte.
+const CHECK_INPUT: &str = "\0piece-key-privacy-check";
+
+/// The secret from which the pseudonyms of piece keys are made, which only the
+/// caller keeps.
+#[derive(Clone)]
+pub struct PieceKeyMaterial(Vec<u8>);
+
+impl PieceKeyMaterial {
+    pub fn from_bytes(bytes: &[u8]) -> PieceKeyMaterial {
+        PieceKeyMaterial(bytes.to_vec())
+    }
+}
+
+// The material stays out of logs.
+impl fmt::Debug for PieceKeyMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PieceKeyMaterial(..)")
+    }
+}
+
+/// The pseudonym a piece key is recorded under by builders given material: the
+/// hex-encoded HMAC-SHA256 of the key under it.
+pub fn piece_key_pseudonym(material: &PieceKeyMaterial, piece_key: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_varkey(&material.0).expect("HMAC keys may be of any size");
+    mac.input(piece_key.as_bytes());
+
+    mac.result()
+        .code()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+// How a builder records, and reads, the keys of its pieces.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PieceKeyPrivacy {
+    // Under the keys they were added with.
+    Plaintext,
+
+    // Under their pseudonyms, made with the material the builder was given.
+    Private,
+
+    // Under their pseudonyms, but the builder wasn't given the material they
+    // were made with: pieces are read by pseudonym, and none may be added.
+    PrivateWithoutMaterial,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PrivacyRecord {
+    // The pseudonym of CHECK_INPUT.
+    check: String,
+}
+
+pub struct PieceKeys {
+    privacy: PieceKeyPrivacy,
+    material: Option<PieceKeyMaterial>,
+
+    // The keys of the pseudonyms made since the builder started.
+    keys: Mutex<HashMap<String, String>>,
+}
+
+impl PieceKeys {
+    // Opens the piece keys of the builder whose metadata is state. Metadata
+    // given material for the first time is marked private, unless pieces were
+    // already recorded in it under their keys, or it's read-only, in which
+    // case it's refused. Private metadata is refused if the material given
+    // isn't the one it was made private with.
+    pub fn open(
+        kv_store: &WrappedKeyValueStore,
+        state: &SectorBuilderState,
+        material: Option<PieceKeyMaterial>,
+        read_only: bool,
+    ) -> Result<PieceKeys> {
+        let key = [&state.prover_id[..], KEY_SUFFIX].concat();
+
+        let record: Option<PrivacyRecord> = match kv_store.inner.get(&key)? {
+            Some(bytes) => Some(serde_cbor::from_slice(&bytes)?),
+            None => None,
+        };
+
+        let privacy = match (&record, &material) {
+            (None, None) => PieceKeyPrivacy::Plaintext,
+            (None, Some(material)) => {
+                if read_only {
+                    return Err(SectorBuilderErr::PieceKeysNotPrivate(
+                        "the metadata is read-only".to_string(),
+                    )
+                    .into());
+                }
+
+                if has_pieces(state) {
+                    return Err(SectorBuilderErr::PieceKeysNotPrivate(
+                        "pieces were added under their keys before".to_string(),
+                    )
+                    .into());
+                }
+
+                let record = PrivacyRecord {
+                    check: piece_key_pseudonym(material, CHECK_INPUT),
+                };
+                kv_store.inner.put(&key, &serde_cbor::to_vec(&record)?)?;
+
+                PieceKeyPrivacy::Private
+            }
+            (Some(_), None) => {
+                warn!(FCP_LOG, "piece keys are private, but no material was given: pieces are served by pseudonym");
+
+                PieceKeyPrivacy::PrivateWithoutMaterial
+            }
+            (Some(record), Some(material)) => {
+                if record.check != piece_key_pseudonym(material, CHECK_INPUT) {
+                    return Err(SectorBuilderErr::PieceKeyMaterialMismatch.into());
+                }
+
+                PieceKeyPrivacy::Private
+            }
+        };
+
+        Ok(PieceKeys {
+            privacy,
+            material,
+            keys: Default::default(),
+        })
+    }
+
+    pub fn privacy(&self) -> PieceKeyPrivacy {
+        self.privacy
+    }
+
+    // The key a piece added under piece_key is recorded under. Fails if the
+    // builder can't make its pseudonym.
+    pub fn stored_key(&self, piece_key: String) -> Result<String> {
+        match self.privacy {
+            PieceKeyPrivacy::Plaintext => Ok(piece_key),
+            PieceKeyPrivacy::Private => Ok(self.pseudonym(piece_key)),
+            PieceKeyPrivacy::PrivateWithoutMaterial => {
+                Err(SectorBuilderErr::PieceKeyMaterialMissing.into())
+            }
+        }
+    }
+
+    // The key a piece read by piece_key is recorded under: its pseudonym, if
+    // the builder can make it, or piece_key itself, taken to be one, if not.
+    pub fn lookup_key(&self, piece_key: String) -> String {
+        match self.privacy {
+            PieceKeyPrivacy::Private => self.pseudonym(piece_key),
+            _ => piece_key,
+        }
+    }
+
+    // The key a piece recorded under stored_key is listed under: if real_keys
+    // is set, the key it stands for when that's known, or else stored_key.
+    pub fn listed_key(&self, stored_key: String, real_keys: bool) -> String {
+        if !real_keys || self.privacy != PieceKeyPrivacy::Private {
+            return stored_key;
+        }
+
+        let keys = self.keys.lock().unwrap_or_else(|p| p.into_inner());
+        keys.get(&stored_key).cloned().unwrap_or(stored_key)
+    }
+
+    fn pseudonym(&self, piece_key: String) -> String {
+        let material = self.material.as_ref().expect("private keys have material");
+        let pseudonym = piece_key_pseudonym(material, &piece_key);
+
+        self.keys
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .insert(pseudonym.clone(), piece_key);
+
+        pseudonym
+    }
+}
+
+fn has_pieces(state: &SectorBuilderState) -> bool {
+    state.staged.sectors.values().any(|s| !s.pieces.is_empty())
+        || state.sealed.sectors.values().any(|s| !s.pieces.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sector_builder::kv_store::fs::FileSystemKvs;
+    use crate::api::sector_builder::metadata::{PieceMetadata, SealStatus, StagedSectorMetadata};
+
+    fn material(byte: u8) -> Option<PieceKeyMaterial> {
+        Some(PieceKeyMaterial::from_bytes(&[byte; 32]))
+    }
+
+    fn kv_store(dir: &tempfile::TempDir) -> WrappedKeyValueStore {
+        WrappedKeyValueStore {
+            inner: Box::new(FileSystemKvs::initialize(dir.path()).unwrap()),
+        }
+    }
+
+    fn state(piece_keys: &[&str]) -> SectorBuilderState {
+        let mut state = SectorBuilderState {
+            prover_id: [3; 31],
+            staged: Default::default(),
+            sealed: Default::default(),
+        };
+
+        state.staged.sectors.insert(
+            1,
+            StagedSectorMetadata {
+                sector_id: 1,
+                sector_access: "1".to_string(),
+                pieces: piece_keys
+                    .iter()
+                    .map(|key| PieceMetadata {
+                        piece_key: key.to_string(),
+                        num_bytes: 10,
+                        comm_p: None,
+                        expires_at: None,
+                        aliases: Vec::new(),
+                    })
+                    .collect(),
+                seal_status: SealStatus::Pending,
+            },
+        );
+
+        state
+    }
+
+    fn assert_refused(result: Result<PieceKeys>, expected: SectorBuilderErr) {
+        match result.map_err(|err| err.downcast::<SectorBuilderErr>()) {
+            Err(Ok(err)) => assert_eq!(format!("{:?}", expected), format!("{:?}", err)),
+            Err(Err(err)) => panic!("unexpected error: {:?}", err),
+            Ok(keys) => panic!("unexpectedly opened, as {:?}", keys.privacy()),
+        }
+    }
+
+    #[test]
+    fn pseudonyms_are_deterministic_and_keyed() {
+        let a = material(1).unwrap();
+        let b = material(2).unwrap();
+
+        assert_eq!(
+            piece_key_pseudonym(&a, "piece"),
+            piece_key_pseudonym(&a, "piece")
+        );
+        assert_ne!(
+            piece_key_pseudonym(&a, "piece"),
+            piece_key_pseudonym(&a, "other")
+        );
+        assert_ne!(
+            piece_key_pseudonym(&a, "piece"),
+            piece_key_pseudonym(&b, "piece")
+        );
+        assert_eq!(64, piece_key_pseudonym(&a, "piece").len());
+
+        assert_eq!("PieceKeyMaterial(..)", format!("{:?}", a));
+    }
+
+    #[test]
+    fn private_keys_are_listed_by_key_only_while_the_material_is_held() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv_store = kv_store(&dir);
+
+        let keys = PieceKeys::open(&kv_store, &state(&[]), material(1), false).unwrap();
+        assert_eq!(PieceKeyPrivacy::Private, keys.privacy());
+
+        let stored = keys.stored_key("piece".to_string()).unwrap();
+        assert_eq!(piece_key_pseudonym(&material(1).unwrap(), "piece"), stored);
+        assert_eq!(stored, keys.lookup_key("piece".to_string()));
+        assert_eq!("piece", keys.listed_key(stored.clone(), true));
+        assert_eq!(stored, keys.listed_key(stored.clone(), false));
+
+        // Reopened without the material, pieces are served by pseudonym.
+        let keys = PieceKeys::open(&kv_store, &state(&[&stored]), None, false).unwrap();
+        assert_eq!(PieceKeyPrivacy::PrivateWithoutMaterial, keys.privacy());
+        assert_eq!(stored, keys.lookup_key(stored.clone()));
+        assert_eq!(stored, keys.listed_key(stored.clone(), true));
+
+        match keys
+            .stored_key("other".to_string())
+            .map_err(|err| err.downcast::<SectorBuilderErr>())
+        {
+            Err(Ok(SectorBuilderErr::PieceKeyMaterialMissing)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Reopened with it, pieces added before are listed by pseudonym until
+        // they're read by key.
+        let keys = PieceKeys::open(&kv_store, &state(&[&stored]), material(1), false).unwrap();
+        assert_eq!(stored, keys.listed_key(stored.clone(), true));
+        assert_eq!(stored, keys.lookup_key("piece".to_string()));
+        assert_eq!("piece", keys.listed_key(stored.clone(), true));
+    }
+
+    #[test]
+    fn metadata_is_refused_unless_it_can_be_kept_private() {
+        let dir = tempfile::tempdir().unwrap();
+        let kv_store = kv_store(&dir);
+
+        // Pieces were added under their keys before.
+        assert_refused(
+            PieceKeys::open(&kv_store, &state(&["piece"]), material(1), false),
+            SectorBuilderErr::PieceKeysNotPrivate(
+                "pieces were added under their keys before".to_string(),
+            ),
+        );
+
+        // Read-only metadata can't be marked private.
+        assert_refused(
+            PieceKeys::open(&kv_store, &state(&[]), material(1), true),
+            SectorBuilderErr::PieceKeysNotPrivate("the metadata is read-only".to_string()),
+        );
+
+        let keys = PieceKeys::open(&kv_store, &state(&["piece"]), None, false).unwrap();
+        assert_eq!(PieceKeyPrivacy::Plaintext, keys.privacy());
+        assert_eq!("piece", keys.stored_key("piece".to_string()).unwrap());
+
+        // Once private, the metadata is refused with other material.
+        PieceKeys::open(&kv_store, &state(&[]), material(1), false).unwrap();
+        assert_refused(
+            PieceKeys::open(&kv_store, &state(&[]), material(2), false),
+            SectorBuilderErr::PieceKeyMaterialMismatch,
+        );
+        PieceKeys::open(&kv_store, &state(&[]), material(1), true).unwrap();
+    }
+}
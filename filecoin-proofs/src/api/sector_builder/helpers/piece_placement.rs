@@ -0,0 +1,553 @@
+use crate::api::sector_builder::errors::*;
+use crate::api::sector_builder::helpers::add_piece::{provision_new_staged_sector, write_piece};
+use crate::api::sector_builder::helpers::metadata_txn::MetadataTxn;
+use crate::api::sector_builder::helpers::sector_ids::SectorIdAllocator;
+use crate::api::sector_builder::metadata::{
+    sum_piece_bytes, PieceAssignment, PlacementPlan, PlannedPiece, PlannedSector, SealStatus,
+};
+use crate::api::sector_builder::state::StagedState;
+use crate::api::sector_builder::WrappedSectorStore;
+use crate::error;
+use sector_base::api::sector_store::SectorManager;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::Arc;
+
+// Plans where to stage the given pieces, as (key, length) pairs, so as to
+// leave as little padding in sectors as possible. Pieces are placed largest
+// first, each into the first sector it fits: the staged sectors accepting data
+// by ascending id, then the sectors the plan provisions. Ties keep the order
+// in which pieces were given, so the plan only depends on its inputs.
+//
+// Lengths are in user bytes, as is sector_max: the Fr32 padding of a sector's
+// data is already accounted for by its capacity, and pieces are staged one
+// right after another, so they need no alignment either.
+//
+// The plan's waste is what's left of the capacity of the sectors accepting
+// data and the sectors the plan provisions once it's carried out.
+pub fn plan_piece_placement(
+    staged_state: &StagedState,
+    sector_max: u64,
+    pending_pieces: &[(String, u64)],
+) -> error::Result<PlacementPlan> {
+    for (_, num_bytes) in pending_pieces {
+        if *num_bytes > sector_max {
+            return Err(err_overflow(*num_bytes, sector_max).into());
+        }
+    }
+
+    let mut open: Vec<(u64, u64)> = staged_state
+        .sectors
+        .values()
+        .filter(|s| s.seal_status == SealStatus::Pending)
+        .map(|s| (s.sector_id, sector_max - sum_piece_bytes(s)))
+        .collect();
+    open.sort();
+
+    // Each sector a piece may be placed in, with the bytes it has left.
+    let mut sectors: Vec<(PlannedSector, u64)> = open
+        .into_iter()
+        .map(|(sector_id, free)| (PlannedSector::Staged(sector_id), free))
+        .collect();
+    let num_open = sectors.len();
+
+    let mut order: Vec<usize> = (0..pending_pieces.len()).collect();
+    order.sort_by(|a, b| pending_pieces[*b].1.cmp(&pending_pieces[*a].1));
+
+    let mut placed = vec![None; pending_pieces.len()];
+
+    for i in order {
+        let num_bytes = pending_pieces[i].1;
+
+        let index = match sectors.iter().position(|(_, free)| *free >= num_bytes) {
+            Some(index) => index,
+            None => {
+                sectors.push((PlannedSector::New(sectors.len() - num_open), sector_max));
+                sectors.len() - 1
+            }
+        };
+
+        sectors[index].1 -= num_bytes;
+        placed[i] = Some(sectors[index].0);
+    }
+
+    let assignments = pending_pieces
+        .iter()
+        .zip(placed)
+        .map(|((piece_key, num_bytes), sector)| PieceAssignment {
+            piece_key: piece_key.clone(),
+            num_bytes: *num_bytes,
+            sector: sector.expect("every piece is placed"),
+        })
+        .collect();
+
+    Ok(PlacementPlan {
+        assignments,
+        new_sectors: sectors.len() - num_open,
+        wasted_bytes: sectors.iter().map(|(_, free)| free).sum(),
+    })
+}
+
+// Stages the given pieces as planned, returning the id of the sector each
+// piece was staged in. The pieces must be those the plan was made for, in the
+// same order.
+//
+// Either every piece is staged, or none is: the sectors the plan provisions
+// are all provisioned before any piece is written, and if provisioning or a
+// write fails, the sectors written to are truncated back to their length
+// before, and provisioned sectors are removed. Their ids aren't reused.
+pub fn add_pieces_planned(
+    sector_store: &Arc<WrappedSectorStore>,
+    staged_state: &mut StagedState,
+    sector_ids: &mut SectorIdAllocator,
+    plan: &PlacementPlan,
+    pieces: Vec<PlannedPiece>,
+) -> error::Result<Vec<u64>> {
+    let sector_mgr = sector_store.inner.manager();
+    let sector_max = sector_store.inner.config().max_unsealed_bytes_per_sector();
+
+    check_plan(staged_state, sector_max, plan, &pieces)?;
+
+    // The length and number of pieces of each staged sector the plan writes
+    // to, so that it can be rolled back.
+    let mut rollback: HashMap<u64, (u64, usize)> = HashMap::new();
+    for assignment in &plan.assignments {
+        if let PlannedSector::Staged(sector_id) = assignment.sector {
+            let sector = &staged_state.sectors[&sector_id];
+            let len = sector_mgr.num_unsealed_bytes(&sector.sector_access)?;
+            rollback.insert(sector_id, (len, sector.pieces.len()));
+        }
+    }
+
+    let mut new_sector_ids = Vec::with_capacity(plan.new_sectors);
+    for _ in 0..plan.new_sectors {
+        match provision_new_staged_sector(sector_mgr, staged_state, sector_ids) {
+            Ok(sector_id) => new_sector_ids.push(sector_id),
+            Err(err) => {
+                remove_sectors(sector_mgr, staged_state, &new_sector_ids);
+                return Err(err);
+            }
+        }
+    }
+
+    let mut staged_in = Vec::with_capacity(pieces.len());
+
+    for (piece, assignment) in pieces.into_iter().zip(&plan.assignments) {
+        let sector_id = match assignment.sector {
+            PlannedSector::Staged(sector_id) => sector_id,
+            PlannedSector::New(n) => new_sector_ids[n],
+        };
+
+        let sector = staged_state
+            .sectors
+            .get_mut(&sector_id)
+            .ok_or_else(|| err_unrecov("unable to retrieve sector from state-map"))?;
+
+        let written = write_piece(
+            sector_mgr,
+            sector,
+            piece.piece_key,
+            piece.bytes.len() as u64,
+            piece.expires_at,
+            &mut &piece.bytes[..],
+        );
+
+        if let Err(err) = written {
+            for (sector_id, (len, num_pieces)) in &rollback {
+                if let Some(sector) = staged_state.sectors.get_mut(sector_id) {
+                    sector.pieces.truncate(*num_pieces);
+                    sector_mgr.truncate_unsealed(&sector.sector_access, *len)?;
+                }
+            }
+            remove_sectors(sector_mgr, staged_state, &new_sector_ids);
+
+            return Err(err);
+        }
+
+        staged_in.push(sector_id);
+    }
+
+    Ok(staged_in)
+}
+
+// Records, in txn, the staged sectors with the given ids, in which the pieces
+// of a plan were staged, so that the plan's pieces are recorded all at once.
+pub fn record_planned_pieces(
+    txn: &mut MetadataTxn,
+    staged_state: &StagedState,
+    sector_ids: &[u64],
+) {
+    let sector_ids: BTreeSet<&u64> = sector_ids.iter().collect();
+
+    for sector_id in sector_ids {
+        if let Some(sector) = staged_state.sectors.get(sector_id) {
+            txn.put_staged(sector.clone());
+        }
+    }
+}
+
+// Checks that the pieces are those the plan was made for, and that the staged
+// sectors it places pieces in still accept data and have room for them.
+fn check_plan(
+    staged_state: &StagedState,
+    sector_max: u64,
+    plan: &PlacementPlan,
+    pieces: &[PlannedPiece],
+) -> error::Result<()> {
+    if pieces.len() != plan.assignments.len() {
+        let reason = format!(
+            "{} pieces were given for a plan of {}",
+            pieces.len(),
+            plan.assignments.len()
+        );
+        return Err(err_placement_plan_mismatch(reason).into());
+    }
+
+    let mut planned_bytes: HashMap<u64, u64> = HashMap::new();
+
+    for (piece, assignment) in pieces.iter().zip(&plan.assignments) {
+        if piece.piece_key != assignment.piece_key
+            || piece.bytes.len() as u64 != assignment.num_bytes
+        {
+            let reason = format!(
+                "piece {} of {} bytes was planned where piece {} of {} bytes was given",
+                assignment.piece_key,
+                assignment.num_bytes,
+                piece.piece_key,
+                piece.bytes.len()
+            );
+            return Err(err_placement_plan_mismatch(reason).into());
+        }
+
+        match assignment.sector {
+            PlannedSector::Staged(sector_id) => {
+                *planned_bytes.entry(sector_id).or_insert(0) += assignment.num_bytes;
+            }
+            PlannedSector::New(n) if n >= plan.new_sectors => {
+                let reason = format!("piece {} is planned for no sector", assignment.piece_key);
+                return Err(err_placement_plan_mismatch(reason).into());
+            }
+            PlannedSector::New(_) => (),
+        }
+    }
+
+    for (sector_id, num_bytes) in planned_bytes {
+        let free = staged_state
+            .sectors
+            .get(&sector_id)
+            .filter(|s| s.seal_status == SealStatus::Pending)
+            .map(|s| sector_max - sum_piece_bytes(s));
+
+        if free.map_or(true, |free| free < num_bytes) {
+            let reason = format!("sector {} no longer has room for its pieces", sector_id);
+            return Err(err_placement_plan_mismatch(reason).into());
+        }
+    }
+
+    Ok(())
+}
+
+// Removes sectors provisioned for a plan which couldn't be carried out, along
+// with their staged files.
+fn remove_sectors(sector_mgr: &SectorManager, staged_state: &mut StagedState, sector_ids: &[u64]) {
+    for sector_id in sector_ids {
+        if let Some(sector) = staged_state.sectors.remove(sector_id) {
+            let _ = sector_mgr.delete_staging_sector_access(&sector.sector_access);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sector_builder::helpers::add_piece::add_piece;
+    use crate::api::sector_builder::helpers::sector_ids::{
+        test_allocator, test_allocator_in_range, SectorIdRange,
+    };
+    use crate::api::sector_builder::helpers::staged_data::verify_staged_data;
+    use crate::api::sector_builder::metadata::{PieceMetadata, StagedSectorMetadata};
+    use sector_base::api::disk_backed_storage::{new_sector_store, ConfiguredStore};
+    use std::fs;
+
+    const SECTOR_MAX: u64 = 1000;
+
+    fn pieces(sizes: &[u64]) -> Vec<(String, u64)> {
+        sizes
+            .iter()
+            .enumerate()
+            .map(|(i, num_bytes)| (format!("piece-{}", i), *num_bytes))
+            .collect()
+    }
+
+    // The number of sectors provisioned, and the waste, of placing each piece
+    // in the order given into the first sector it fits, as add_piece does.
+    fn arrival_order(sector_max: u64, sizes: &[u64]) -> (usize, u64) {
+        let mut free: Vec<u64> = Vec::new();
+
+        for num_bytes in sizes {
+            match free.iter().position(|f| f >= num_bytes) {
+                Some(i) => free[i] -= num_bytes,
+                None => free.push(sector_max - num_bytes),
+            }
+        }
+
+        (free.len(), free.iter().sum())
+    }
+
+    fn staged_sector(sector_id: u64, used: u64, seal_status: SealStatus) -> StagedSectorMetadata {
+        StagedSectorMetadata {
+            sector_id,
+            seal_status,
+            pieces: vec![PieceMetadata {
+                piece_key: format!("staged-{}", sector_id),
+                num_bytes: used,
+                comm_p: None,
+                expires_at: None,
+                aliases: Vec::new(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn test_store(dir: &tempfile::TempDir) -> Arc<WrappedSectorStore> {
+        let path = dir.path().to_str().unwrap().to_owned();
+
+        Arc::new(WrappedSectorStore {
+            inner: Box::new(new_sector_store(&ConfiguredStore::Test, path.clone(), path)),
+        })
+    }
+
+    fn planned_pieces(plan: &PlacementPlan) -> Vec<PlannedPiece> {
+        plan.assignments
+            .iter()
+            .enumerate()
+            .map(|(i, a)| PlannedPiece {
+                piece_key: a.piece_key.clone(),
+                bytes: vec![i as u8 + 1; a.num_bytes as usize],
+                expires_at: Some(i as u64),
+            })
+            .collect()
+    }
+
+    fn staged_files(dir: &tempfile::TempDir) -> Vec<String> {
+        let mut files: Vec<String> = fs::read_dir(dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file())
+            .map(|path| path.to_str().unwrap().to_owned())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn a_sector_filling_piece_arriving_last_gets_its_own_sector() {
+        // Ten pieces of a third of a sector, then one which fills a sector.
+        let mut sizes = vec![333; 10];
+        sizes.push(SECTOR_MAX);
+
+        let plan =
+            plan_piece_placement(&StagedState::default(), SECTOR_MAX, &pieces(&sizes)).unwrap();
+
+        assert_eq!(PlannedSector::New(0), plan.assignments[10].sector);
+        assert_eq!(5, plan.new_sectors);
+        assert_eq!(5 * SECTOR_MAX - 4330, plan.wasted_bytes);
+
+        // Only the last sector is left partly empty.
+        assert!(plan.wasted_bytes < SECTOR_MAX);
+        let (_, naive_waste) = arrival_order(SECTOR_MAX, &sizes);
+        assert!(plan.wasted_bytes <= naive_waste);
+    }
+
+    #[test]
+    fn large_pieces_are_placed_before_small_ones() {
+        // Placed as they arrive, the small pieces fill two sectors which the
+        // large ones then don't fit, and each of them takes a sector of its
+        // own. Largest first, each large piece shares a sector with a small
+        // one.
+        let sizes = [300, 300, 300, 300, 300, 300, 600, 600, 600];
+
+        let plan =
+            plan_piece_placement(&StagedState::default(), SECTOR_MAX, &pieces(&sizes)).unwrap();
+        let (naive_sectors, naive_waste) = arrival_order(SECTOR_MAX, &sizes);
+
+        assert_eq!(5, naive_sectors);
+        assert_eq!(4, plan.new_sectors);
+        assert!(plan.wasted_bytes < naive_waste);
+        assert!(plan.wasted_bytes < SECTOR_MAX);
+
+        for i in 6..9 {
+            let shared = plan.assignments[..6]
+                .iter()
+                .filter(|a| a.sector == plan.assignments[i].sector)
+                .count();
+            assert_eq!(1, shared);
+        }
+    }
+
+    #[test]
+    fn open_sectors_are_filled_first_by_ascending_id() {
+        let mut state = StagedState::default();
+        for (sector_id, used, status) in &[
+            (7, 700, SealStatus::Pending),
+            (3, 500, SealStatus::Pending),
+            (5, 0, SealStatus::Sealing),
+        ] {
+            let sector = staged_sector(*sector_id, *used, status.clone());
+            state.sectors.insert(*sector_id, sector);
+        }
+
+        let plan =
+            plan_piece_placement(&state, SECTOR_MAX, &pieces(&[300, 900, 200, 300])).unwrap();
+
+        let placed: Vec<PlannedSector> = plan.assignments.iter().map(|a| a.sector).collect();
+        assert_eq!(
+            vec![
+                PlannedSector::Staged(3),
+                PlannedSector::New(0),
+                PlannedSector::Staged(3),
+                PlannedSector::Staged(7),
+            ],
+            placed
+        );
+        assert_eq!(1, plan.new_sectors);
+        assert_eq!(100, plan.wasted_bytes);
+    }
+
+    #[test]
+    fn plans_depend_only_on_their_inputs() {
+        let mut state = StagedState::default();
+        for sector_id in 0..20 {
+            state.sectors.insert(
+                sector_id,
+                staged_sector(sector_id, 600 + sector_id * 10, SealStatus::Pending),
+            );
+        }
+
+        let sizes: Vec<u64> = (0..50).map(|i| (i * 37) % 400 + 1).collect();
+        let first = plan_piece_placement(&state, SECTOR_MAX, &pieces(&sizes)).unwrap();
+
+        let mut copy = StagedState::default();
+        for sector_id in (0..20).rev() {
+            copy.sectors
+                .insert(sector_id, state.sectors[&sector_id].clone());
+        }
+
+        assert_eq!(
+            first,
+            plan_piece_placement(&copy, SECTOR_MAX, &pieces(&sizes)).unwrap()
+        );
+    }
+
+    #[test]
+    fn pieces_larger_than_a_sector_are_refused() {
+        let result = plan_piece_placement(&StagedState::default(), SECTOR_MAX, &pieces(&[1, 1001]));
+
+        match result.map_err(|err| err.downcast::<SectorBuilderErr>()) {
+            Err(Ok(SectorBuilderErr::OverflowError { .. })) => (),
+            other => panic!("unexpected result: {:?}", other.map_err(|_| ())),
+        }
+    }
+
+    #[test]
+    fn planned_pieces_are_staged() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir);
+        let ids = &mut test_allocator(&dir.path().join("metadata"), 0);
+        let mut state = StagedState::default();
+        let max = store.inner.config().max_unsealed_bytes_per_sector();
+
+        let open = add_piece(&store, &mut state, ids, "open".to_string(), &[9; 27], None).unwrap();
+
+        let sizes = [max / 2, max - 27, max / 4, max / 4];
+        let plan = plan_piece_placement(&state, max, &pieces(&sizes)).unwrap();
+        assert_eq!(PlannedSector::Staged(open), plan.assignments[1].sector);
+        assert_eq!(1, plan.new_sectors);
+
+        let staged_in =
+            add_pieces_planned(&store, &mut state, ids, &plan, planned_pieces(&plan)).unwrap();
+
+        assert_eq!(open, staged_in[1]);
+        assert!(staged_in
+            .iter()
+            .enumerate()
+            .all(|(i, id)| i == 1 || *id != open));
+        assert_eq!(2, state.sectors.len());
+
+        for sector in state.sectors.values() {
+            verify_staged_data(&store, sector).unwrap();
+        }
+        assert_eq!(Some(3), state.sectors[&staged_in[3]].pieces[2].expires_at);
+    }
+
+    #[test]
+    fn failed_reservations_leave_nothing_behind() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir);
+        let mut state = StagedState::default();
+        let max = store.inner.config().max_unsealed_bytes_per_sector();
+
+        // Only two ids may be allocated: the open sector's, and one more.
+        let range = SectorIdRange { first: 0, last: 1 };
+        let ids = &mut test_allocator_in_range(&dir.path().join("metadata"), range);
+
+        let open = add_piece(&store, &mut state, ids, "open".to_string(), &[9; 27], None).unwrap();
+        let before = state.sectors.clone();
+        let files = staged_files(&dir);
+
+        // The plan provisions two sectors, and places a piece in the open one.
+        let plan = plan_piece_placement(&state, max, &pieces(&[max, max, 10])).unwrap();
+        assert_eq!(2, plan.new_sectors);
+        assert_eq!(PlannedSector::Staged(open), plan.assignments[2].sector);
+
+        let result = add_pieces_planned(&store, &mut state, ids, &plan, planned_pieces(&plan));
+        match result.map_err(|err| err.downcast::<SectorBuilderErr>()) {
+            Err(Ok(SectorBuilderErr::SectorIdsExhausted { .. })) => (),
+            other => panic!("unexpected result: {:?}", other.map_err(|_| ())),
+        }
+
+        assert_eq!(before, state.sectors);
+        assert_eq!(files, staged_files(&dir));
+        let access = &state.sectors[&open].sector_access;
+        assert_eq!(
+            27,
+            store.inner.manager().num_unsealed_bytes(access).unwrap()
+        );
+    }
+
+    #[test]
+    fn stale_and_mismatched_plans_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir);
+        let ids = &mut test_allocator(&dir.path().join("metadata"), 0);
+        let mut state = StagedState::default();
+        let max = store.inner.config().max_unsealed_bytes_per_sector();
+
+        add_piece(&store, &mut state, ids, "open".to_string(), &[9; 27], None).unwrap();
+        let plan = plan_piece_placement(&state, max, &pieces(&[100, 200])).unwrap();
+
+        let assert_mismatch = |result: error::Result<Vec<u64>>| match result
+            .map_err(|err| err.downcast::<SectorBuilderErr>())
+        {
+            Err(Ok(SectorBuilderErr::PlacementPlanMismatch(_))) => (),
+            other => panic!("unexpected result: {:?}", other.map_err(|_| ())),
+        };
+
+        // Pieces other than those planned.
+        let mut swapped = planned_pieces(&plan);
+        swapped.swap(0, 1);
+        assert_mismatch(add_pieces_planned(&store, &mut state, ids, &plan, swapped));
+
+        // A sector which filled up since the plan was made.
+        let more = vec![1; (max - 127) as usize];
+        add_piece(&store, &mut state, ids, "more".to_string(), &more, None).unwrap();
+        let before = state.sectors.clone();
+        assert_mismatch(add_pieces_planned(
+            &store,
+            &mut state,
+            ids,
+            &plan,
+            planned_pieces(&plan),
+        ));
+        assert_eq!(before, state.sectors);
+    }
+}
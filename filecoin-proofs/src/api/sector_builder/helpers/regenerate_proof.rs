@@ -0,0 +1,291 @@
+use crate::api::internal;
+use crate::api::seal_proof::{snark_proof, version_code, version_of_code};
+use crate::api::sector_builder::errors::err_unrecov;
+use crate::api::sector_builder::metadata::SealedSectorMetadata;
+use crate::api::sector_builder::state::SealedState;
+use crate::api::sector_builder::work_queue::PersistedQueue;
+use crate::api::sector_builder::WrappedSectorStore;
+use crate::api::sector_id::SectorId;
+use crate::error;
+use sector_base::api::sector_store::ProofVersion;
+use std::path::Path;
+use std::sync::Arc;
+use storage_proofs::crypto::constant_time::ConstantTimeEq;
+
+/// The name of the queue of the sectors being proven again in a builder's
+/// metadata directory.
+pub const PROOF_REGENERATIONS_FILE: &str = "proof-regenerations.queue";
+
+// Proves a sealed sector again with target_version's parameters, without
+// sealing it again, and returns its metadata with the new proof in place of the
+// old, which is kept in its history. The sector's taus and trees are read from
+// its prover bundle, if it kept one, or else rebuilt from the sealed sector.
+// Either way, the commitments proven must be those the sector was sealed with.
+pub fn regenerate_proof(
+    sector_store: &Arc<WrappedSectorStore>,
+    sealed_sector: &SealedSectorMetadata,
+    prover_id: &[u8; 31],
+    target_version: ProofVersion,
+) -> error::Result<SealedSectorMetadata> {
+    // Sectors migrated into a larger size are proven as sectors of that size.
+    let sector_config = sealed_sector.config(sector_store.inner.config());
+
+    sector_store
+        .inner
+        .manager()
+        .fetch_sealed(&sealed_sector.sector_access)?;
+
+    let output = internal::regenerate_proof(
+        sector_config.as_ref(),
+        &sealed_sector.sector_access,
+        sealed_sector.prover_bundle.as_ref().map(Path::new),
+        prover_id,
+        &SectorId(sealed_sector.sector_id).to_fr_safe(),
+        target_version,
+        sealed_sector.legacy_replicas(),
+    )?;
+
+    let holds_sealed = output.comm_r.ct_eq(&sealed_sector.comm_r)
+        & output.comm_d.ct_eq(&sealed_sector.comm_d)
+        & output.comm_r_star.ct_eq(&sealed_sector.comm_r_star);
+    if !holds_sealed {
+        let msg = format!(
+            "sector {} no longer holds what it was sealed with",
+            sealed_sector.sector_id
+        );
+
+        return Err(err_unrecov(msg).into());
+    }
+
+    let mut regenerated = sealed_sector.clone();
+    regenerated.replace_proof(snark_proof(&output.proof)?, output.proof[0]);
+
+    Ok(regenerated)
+}
+
+// A sector of a batch being proven again, and the code of the version it's
+// proven with, as a proof's tag records it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RegenerationJob {
+    pub sector_id: u64,
+    pub version: u8,
+}
+
+// The sectors of the batch being proven again by regenerate_all_proofs, kept in
+// a work queue in the builder's metadata directory. Each is started as it's
+// sent to the sealers, and completed once its new proof (or why it couldn't be
+// made) is recorded. A sector whose proving was cancelled, or which a crash
+// left unfinished, is left started, so that the next builder to start over the
+// same metadata proves it again, as part of a batch no caller waits on.
+pub struct ProofRegenerations {
+    queue: PersistedQueue<RegenerationJob>,
+}
+
+impl ProofRegenerations {
+    pub fn open<P: AsRef<Path>>(metadata_dir: P) -> error::Result<ProofRegenerations> {
+        let path = metadata_dir.as_ref().join(PROOF_REGENERATIONS_FILE);
+        let (queue, _) = PersistedQueue::open(path)?;
+
+        Ok(ProofRegenerations { queue })
+    }
+
+    // Durably records that the sector is being proven again with
+    // target_version's parameters.
+    pub fn begin(&self, sector_id: u64, target_version: ProofVersion) -> error::Result<()> {
+        let job = RegenerationJob {
+            sector_id,
+            version: version_code(target_version),
+        };
+
+        let id = self.queue.enqueue(job, true)?;
+        self.queue.start(id)
+    }
+
+    // Starts the sectors left unfinished by the last builder, returning each
+    // sealed sector which isn't retired along with the version it was being
+    // proven with. The others are abandoned.
+    pub fn resume(
+        &self,
+        sealed: &SealedState,
+    ) -> error::Result<Vec<(SealedSectorMetadata, ProofVersion)>> {
+        let mut resumed = Vec::new();
+
+        for entry in self.queue.entries() {
+            let sector = sealed
+                .sectors
+                .get(&entry.item.sector_id)
+                .filter(|sector| sector.retired_into.is_none());
+
+            match (sector, version_of_code(entry.item.version)) {
+                (Some(sector), Some(version)) => {
+                    self.queue.start(entry.id)?;
+                    resumed.push((sector.clone(), version));
+                }
+                _ => self.queue.abandon(entry.id)?,
+            }
+        }
+
+        Ok(resumed)
+    }
+
+    // Durably records that the sector's proving is over.
+    pub fn finish(&self, sector_id: u64) -> error::Result<()> {
+        for entry in self.queue.entries() {
+            if entry.item.sector_id == sector_id {
+                self.queue.complete(entry.id)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::cancellation::CancellationToken;
+    use crate::api::internal::{seal_with_prover_bundle, verify_seal};
+    use crate::api::io_priority::SealThrottle;
+    use crate::api::seal_proof::seal_versioned_envelope;
+    use crate::api::sector_builder::helpers::add_piece::add_piece;
+    use crate::api::sector_builder::helpers::seal::seal;
+    use crate::api::sector_builder::helpers::sector_ids::test_allocator;
+    use crate::api::sector_builder::metadata::ReplacedProof;
+    use crate::api::sector_builder::state::StagedState;
+    use sector_base::api::disk_backed_storage::{
+        new_sector_store_with_proof_variant, ConfiguredStore,
+    };
+    use sector_base::api::sector_store::ProofVariant;
+    use std::path::PathBuf;
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn regenerated_proofs_verify_and_are_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_owned();
+
+        let sector_store = Arc::new(WrappedSectorStore {
+            inner: Box::new(new_sector_store_with_proof_variant(
+                &ConfiguredStore::Test,
+                path.clone(),
+                path,
+                ProofVariant::Snark,
+            )),
+        });
+        let config = sector_store.inner.config();
+        let version = config.proof_version();
+
+        let mut staged_state = StagedState::default();
+        let ids = &mut test_allocator(&dir.path().join("metadata"), 1);
+        let sector_id =
+            add_piece(&sector_store, &mut staged_state, ids, "a".to_string(), &[1; 100], None)
+                .unwrap();
+        let staged_sector = staged_state.sectors.remove(&sector_id).unwrap();
+
+        // The bundle a seal which kept its trees would have written.
+        let bundle_dir = dir.path().join("bundle");
+        seal_with_prover_bundle(
+            config,
+            PathBuf::from(&staged_sector.sector_access),
+            dir.path().join("replicated"),
+            &[0; 31],
+            &SectorId(sector_id).to_fr_safe(),
+            &bundle_dir,
+            false,
+        )
+        .unwrap();
+
+        let (throttle, token) = (SealThrottle::default(), CancellationToken::new());
+        let sealed = seal(
+            &sector_store,
+            &[0; 31],
+            staged_sector,
+            true,
+            None,
+            None,
+            &throttle,
+            &token,
+        )
+        .unwrap();
+
+        // The first proof's trees are rebuilt from the sealed sector, and the
+        // second's are read from the bundle.
+        let rebuilt = regenerate_proof(&sector_store, &sealed, &[0; 31], version).unwrap();
+        let bundled = SealedSectorMetadata {
+            prover_bundle: Some(bundle_dir.to_string_lossy().into_owned()),
+            ..rebuilt.clone()
+        };
+        let bundled = regenerate_proof(&sector_store, &bundled, &[0; 31], version).unwrap();
+
+        for sector in &[&rebuilt, &bundled] {
+            assert_eq!(Some(0), sector.proof_tag.map(|tag| tag & 1));
+            assert!(verify_seal(
+                config,
+                sector.comm_r,
+                sector.comm_d,
+                sector.comm_r_star,
+                &[0; 31],
+                &SectorId(sector_id).to_fr_safe(),
+                &seal_versioned_envelope(version, ProofVariant::Snark, &sector.snark_proof),
+            )
+            .unwrap());
+        }
+
+        // Each proof replaced is kept, oldest first.
+        let history = vec![
+            ReplacedProof {
+                snark_proof: sealed.snark_proof,
+                proof_tag: sealed.proof_tag,
+            },
+            ReplacedProof {
+                snark_proof: rebuilt.snark_proof,
+                proof_tag: rebuilt.proof_tag,
+            },
+        ];
+        assert_eq!(history, bundled.replaced_proofs);
+    }
+
+    #[test]
+    fn unfinished_regenerations_are_resumed() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut sealed = SealedState::default();
+        for (sector_id, retired_into) in &[(1, None), (2, None), (3, Some(4))] {
+            let sector = SealedSectorMetadata {
+                sector_id: *sector_id,
+                retired_into: *retired_into,
+                ..Default::default()
+            };
+            sealed.sectors.insert(*sector_id, sector);
+        }
+
+        let regenerations = ProofRegenerations::open(dir.path()).unwrap();
+        for sector_id in 1..=3 {
+            regenerations.begin(sector_id, ProofVersion::Mini).unwrap();
+        }
+        regenerations.finish(2).unwrap();
+
+        // Sector 3 was retired since, so it's no longer proven.
+        let resumed_ids = |resumed: Vec<(SealedSectorMetadata, ProofVersion)>| {
+            resumed
+                .into_iter()
+                .map(|(sector, version)| (sector.sector_id, version))
+                .collect::<Vec<_>>()
+        };
+        let regenerations = ProofRegenerations::open(dir.path()).unwrap();
+        assert_eq!(
+            vec![(1, ProofVersion::Mini)],
+            resumed_ids(regenerations.resume(&sealed).unwrap())
+        );
+
+        // Until it's finished, sector 1 is resumed by every builder.
+        let regenerations = ProofRegenerations::open(dir.path()).unwrap();
+        assert_eq!(
+            vec![(1, ProofVersion::Mini)],
+            resumed_ids(regenerations.resume(&sealed).unwrap())
+        );
+        regenerations.finish(1).unwrap();
+
+        let regenerations = ProofRegenerations::open(dir.path()).unwrap();
+        assert!(regenerations.resume(&sealed).unwrap().is_empty());
+    }
+}
@@ -1,53 +1,22 @@
 use crate::api::internal;
 use crate::api::sector_builder::errors::err_unrecov;
-use crate::api::sector_builder::metadata::sector_id_as_bytes;
 use crate::api::sector_builder::metadata::SealedSectorMetadata;
 use crate::api::sector_builder::WrappedSectorStore;
+use crate::api::sector_id::SectorId;
 use crate::error;
 use std::path::PathBuf;
 use std::sync::Arc;
 
 // Unseals and returns the piece-bytes for the first sector found containing
-// a piece with matching key.
+// a piece with matching key, or with an alias of that key, whose bytes are
+// the piece's. The piece is unsealed in memory, so nothing is written to the
+// sector store.
 pub fn retrieve_piece<'a>(
     sector_store: &Arc<WrappedSectorStore>,
     sealed_sector: &SealedSectorMetadata,
     prover_id: &[u8; 31],
     piece_key: &'a str,
 ) -> error::Result<Vec<u8>> {
-    let staging_sector_access = sector_store
-        .inner
-        .manager()
-        .new_staging_sector_access()
-        .map_err(failure::Error::from)?;
-
-    let result = retrieve_piece_aux(
-        sector_store,
-        sealed_sector,
-        prover_id,
-        piece_key,
-        &staging_sector_access,
-    );
-
-    if result.is_ok() {
-        sector_store
-            .inner
-            .manager()
-            .delete_staging_sector_access(&staging_sector_access)?;
-    }
-
-    let (_, bytes) = result?;
-
-    Ok(bytes)
-}
-
-fn retrieve_piece_aux<'a>(
-    sector_store: &Arc<WrappedSectorStore>,
-    sealed_sector: &SealedSectorMetadata,
-    prover_id: &[u8; 31],
-    piece_key: &'a str,
-    staging_sector_access: &'a str,
-) -> error::Result<(u64, Vec<u8>)> {
     let (start_offset, num_bytes) = piece_pos(&sealed_sector, piece_key).ok_or_else(|| {
         let msg = format!(
             "piece {} not found in sector {}",
@@ -56,43 +25,46 @@ fn retrieve_piece_aux<'a>(
         err_unrecov(msg)
     })?;
 
-    let num_bytes_unsealed = internal::get_unsealed_range(
-        (*sector_store.inner).config(),
+    // Sectors migrated into a larger size are unsealed as sectors of that size.
+    let sector_config = sealed_sector.config(sector_store.inner.config());
+
+    sector_store
+        .inner
+        .manager()
+        .fetch_sealed(&sealed_sector.sector_access)?;
+
+    let piece_bytes = internal::unseal_range(
+        sector_config.as_ref(),
         &PathBuf::from(sealed_sector.sector_access.clone()),
-        &PathBuf::from(staging_sector_access),
         prover_id,
-        &sector_id_as_bytes(sealed_sector.sector_id)?,
+        &SectorId(sealed_sector.sector_id).to_fr_safe(),
         start_offset,
         num_bytes,
+        sealed_sector.legacy_replicas(),
     )?;
 
-    if num_bytes_unsealed != num_bytes {
+    if piece_bytes.len() as u64 != num_bytes {
         let s = format!(
             "expected to unseal {} bytes, but unsealed {} bytes",
-            num_bytes, num_bytes_unsealed
+            num_bytes,
+            piece_bytes.len()
         );
 
         return Err(err_unrecov(s).into());
     }
 
-    let piece_bytes = sector_store.inner.manager().read_raw(
-        &staging_sector_access.to_string(),
-        0,
-        num_bytes_unsealed,
-    )?;
-
-    Ok((num_bytes_unsealed, piece_bytes))
+    Ok(piece_bytes)
 }
 
 // Returns a tuple of piece bytes-offset and number-of-bytes in piece if the
-// provided sealed sector contains a matching piece.
+// provided sealed sector contains a piece which answers to the key.
 fn piece_pos(sealed_sector: &SealedSectorMetadata, piece_key: &str) -> Option<(u64, u64)> {
     let (found_piece, start_offset, num_bytes) = sealed_sector.pieces.iter().fold(
         (false, 0, 0),
         |(eject, start_offset, num_bytes), item| {
             if eject {
                 (eject, start_offset, num_bytes)
-            } else if item.piece_key == piece_key {
+            } else if item.answers_to(piece_key) {
                 (true, start_offset, item.num_bytes)
             } else {
                 (false, start_offset + item.num_bytes, item.num_bytes)
@@ -110,7 +82,7 @@ fn piece_pos(sealed_sector: &SealedSectorMetadata, piece_key: &str) -> Option<(u
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::api::sector_builder::metadata::PieceMetadata;
+    use crate::api::sector_builder::metadata::{PieceAlias, PieceMetadata};
 
     #[test]
     fn test_alpha() {
@@ -119,16 +91,25 @@ mod tests {
         sealed_sector.pieces.push(PieceMetadata {
             piece_key: String::from("x"),
             num_bytes: 5,
+            comm_p: None,
+            expires_at: None,
+            aliases: Vec::new(),
         });
 
         sealed_sector.pieces.push(PieceMetadata {
             piece_key: String::from("y"),
             num_bytes: 30,
+            comm_p: None,
+            expires_at: None,
+            aliases: Vec::new(),
         });
 
         sealed_sector.pieces.push(PieceMetadata {
             piece_key: String::from("z"),
             num_bytes: 100,
+            comm_p: None,
+            expires_at: None,
+            aliases: Vec::new(),
         });
 
         match piece_pos(&sealed_sector, "x") {
@@ -145,5 +126,16 @@ mod tests {
             Some(pair) => assert_eq!(pair, (35, 100)),
             None => panic!(),
         }
+
+        // An alias is read from the bytes of the piece it's an alias of.
+        sealed_sector.pieces[1].aliases.push(PieceAlias {
+            piece_key: String::from("w"),
+            expires_at: None,
+        });
+
+        match piece_pos(&sealed_sector, "w") {
+            Some(pair) => assert_eq!(pair, (5, 30)),
+            None => panic!(),
+        }
     }
 }
@@ -1,18 +1,52 @@
-use crate::api::internal::seal as seal_internal;
+use crate::api::cancellation::CancellationToken;
+use crate::api::internal::publish_sealed_sector_with_throttle;
+use crate::api::internal::seal_from_reader_with_throttle as seal_internal;
 use crate::api::internal::SealOutput;
-use crate::api::sector_builder::metadata::sector_id_as_bytes;
+use crate::api::io_priority::SealThrottle;
+use crate::api::replica_format::FORMAT_VERSION;
+use crate::api::seal_proof::snark_proof;
 use crate::api::sector_builder::metadata::SealedSectorMetadata;
+use crate::api::sector_builder::helpers::staged_data::{
+    verify_staged_data, DigestedStagedSector,
+};
+use crate::api::sector_builder::metadata::SealedFrom;
 use crate::api::sector_builder::metadata::StagedSectorMetadata;
 use crate::api::sector_builder::WrappedSectorStore;
+use crate::api::sector_id::SectorId;
 use crate::error;
-use std::path::PathBuf;
+use crate::FCP_LOG;
+use slog::*;
+use std::cell::Cell;
+use std::fs;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 
+// Seals a staged sector. If a landing directory is given, the sector is sealed
+// there and then published to its sealed sector access; otherwise it's sealed
+// in place. Either way, nothing is left at the sealed sector access unless
+// sealing succeeds. If max_seal_memory_bytes is given, sealing fails rather
+// than take more memory than that. The sector is written through throttle,
+// which yields the disk to retrievals in flight. The staged data is digested
+// as it's read, and the digest recorded along with the staged sector's access.
+// Once token is cancelled, sealing fails at its next checkpoint with an
+// Interrupted error (see cancellation).
+#[allow(clippy::too_many_arguments)]
 pub fn seal(
     sector_store: &Arc<WrappedSectorStore>,
     prover_id: &[u8; 31],
     staged_sector: StagedSectorMetadata,
+    verify_staged: bool,
+    landing_sector_dir: Option<&Path>,
+    max_seal_memory_bytes: Option<u64>,
+    throttle: &SealThrottle,
+    token: &CancellationToken,
 ) -> error::Result<SealedSectorMetadata> {
+    // Refuse to seal staged data which has been modified since its pieces were
+    // added. The staged data is left as-is, so that it can be inspected.
+    if verify_staged {
+        verify_staged_data(sector_store, &staged_sector)?;
+    }
+
     // Provision a new sealed sector access through the manager.
     let sealed_sector_access = sector_store
         .inner
@@ -20,21 +54,78 @@ pub fn seal(
         .new_sealed_sector_access()
         .map_err(failure::Error::from)?;
 
+    let sealed_path = PathBuf::from(&sealed_sector_access);
+    let landing_path = match (landing_sector_dir, sealed_path.file_name()) {
+        (Some(dir), Some(file_name)) => dir.join(file_name),
+        _ => sealed_path.clone(),
+    };
+
     // Run the FPS seal operation. This call will block for a long time, so make
-    // sure you're not holding any locks.
+    // sure you're not holding any locks. The staged data is read through the
+    // manager, which decrypts it if the store encrypts staged sectors, and read
+    // again should another process change it while it's read.
+    let staged_digest = Cell::new(None);
+    let staged = DigestedStagedSector {
+        mgr: sector_store.inner.manager(),
+        access: &staged_sector.sector_access,
+        digest: &staged_digest,
+    };
 
-    let SealOutput {
-        comm_r,
-        comm_d,
-        comm_r_star,
-        snark_proof,
-    } = seal_internal(
+    let result = seal_internal(
         (*sector_store.inner).config(),
-        &PathBuf::from(staged_sector.sector_access.clone()),
-        &PathBuf::from(sealed_sector_access.clone()),
+        staged,
+        &landing_path,
         prover_id,
-        &sector_id_as_bytes(staged_sector.sector_id)?,
-    )?;
+        &SectorId(staged_sector.sector_id).to_fr_safe(),
+        max_seal_memory_bytes,
+        throttle,
+        token,
+    )
+    .map(|output| {
+        let digest = staged_digest.get().expect("sealing reads the staged data");
+        (output, digest)
+    })
+    .and_then(|(output, staged_digest)| {
+        let snark_proof = snark_proof(&output.proof)?;
+        let proof_tag = output.proof[0];
+
+        if landing_path != sealed_path {
+            publish_sealed_sector_with_throttle(&landing_path, &sealed_path, throttle)?;
+        }
+
+        // Stores the sector wherever the manager keeps sealed sectors, e.g.
+        // in an object store, if not where it was just written.
+        sector_store
+            .inner
+            .manager()
+            .publish_sealed(&sealed_sector_access)?;
+
+        Ok((output, (snark_proof, proof_tag), staged_digest))
+    });
+
+    let (
+        SealOutput {
+            comm_r,
+            comm_d,
+            comm_r_star,
+            memory,
+            ..
+        },
+        (snark_proof, proof_tag),
+        staged_digest,
+    ) = match result {
+        Ok(output) => output,
+        Err(err) => {
+            // Remove the (empty) sealed sector access, and the sealed sector
+            // if it couldn't be published.
+            let _ = fs::remove_file(&landing_path);
+            let _ = fs::remove_file(&sealed_path);
+
+            return Err(err);
+        }
+    };
+
+    info!(FCP_LOG, "sealed sector"; "sector_id" => staged_sector.sector_id, "peak_memory_bytes" => memory.peak_bytes, "phases" => format!("{:?}", memory.phases));
 
     let newly_sealed_sector = SealedSectorMetadata {
         sector_id: staged_sector.sector_id,
@@ -44,7 +135,230 @@ pub fn seal(
         comm_r,
         comm_d,
         snark_proof,
+        sector_bytes: None,
+        retired_into: None,
+        replica_format: Some(FORMAT_VERSION),
+        sealed_from: Some(SealedFrom {
+            sector_access: staged_sector.sector_access,
+            digest: staged_digest,
+        }),
+        proof_tag: Some(proof_tag),
+        replaced_proofs: Vec::new(),
+        prover_bundle: None,
     };
 
     Ok(newly_sealed_sector)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::internal::verify_seal;
+    use crate::api::seal_proof::seal_envelope;
+    use crate::api::sector_builder::errors::SectorBuilderErr;
+    use crate::api::sector_builder::helpers::add_piece::add_piece;
+    use crate::api::sector_builder::helpers::sector_ids::test_allocator;
+    use crate::api::sector_builder::helpers::staged_data::staged_digest;
+    use crate::api::sector_builder::state::StagedState;
+    use sector_base::api::disk_backed_storage::{
+        new_sector_store_with_staging_encryption, ConfiguredStore,
+    };
+    use sector_base::api::sector_store::ProofVariant;
+    use sector_base::io::staging_encryption::{StagingEncryption, StagingKey};
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    fn staged_sector(dir: &tempfile::TempDir) -> (Arc<WrappedSectorStore>, StagedSectorMetadata) {
+        staged_sector_with_encryption(dir, StagingEncryption::None)
+    }
+
+    fn staged_sector_with_encryption(
+        dir: &tempfile::TempDir,
+        staging_encryption: StagingEncryption,
+    ) -> (Arc<WrappedSectorStore>, StagedSectorMetadata) {
+        let path = dir.path().to_str().unwrap().to_owned();
+
+        let sector_store = Arc::new(WrappedSectorStore {
+            inner: Box::new(new_sector_store_with_staging_encryption(
+                &ConfiguredStore::Test,
+                path.clone(),
+                path,
+                ProofVariant::Snark,
+                staging_encryption,
+            )),
+        });
+
+        let mut staged_state = StagedState::default();
+        let ids = &mut test_allocator(&dir.path().join("metadata"), 1);
+
+        let sector_id =
+            add_piece(&sector_store, &mut staged_state, ids, "a".to_string(), &[1; 100], None)
+                .unwrap();
+        add_piece(&sector_store, &mut staged_state, ids, "b".to_string(), &[2; 200], None)
+            .unwrap();
+
+        let staged_sector = staged_state.sectors.remove(&sector_id).unwrap();
+
+        (sector_store, staged_sector)
+    }
+
+    #[test]
+    fn refuses_to_seal_tampered_staged_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sector_store, staged_sector) = staged_sector(&dir);
+        let staged_access = staged_sector.sector_access.clone();
+
+        // Another process appends to the staged file.
+        OpenOptions::new()
+            .append(true)
+            .open(&staged_access)
+            .unwrap()
+            .write_all(&[3; 32])
+            .unwrap();
+
+        let before = std::fs::read(&staged_access).unwrap();
+
+        let throttle = SealThrottle::default();
+        match seal(
+            &sector_store,
+            &[0; 31],
+            staged_sector,
+            true,
+            None,
+            None,
+            &throttle,
+            &CancellationToken::new(),
+        )
+        .map_err(|err| err.downcast::<SectorBuilderErr>())
+        {
+            Err(Ok(SectorBuilderErr::StagedDataMismatch { sector_id })) => assert_eq!(1, sector_id),
+            Err(Ok(err)) => panic!("unexpected error: {}", err),
+            Err(Err(err)) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("tampered staged data was sealed"),
+        }
+
+        // The staged data is left untouched for inspection.
+        assert_eq!(before, std::fs::read(&staged_access).unwrap());
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn seals_untampered_staged_data() {
+        let dir = tempfile::tempdir().unwrap();
+        let (sector_store, staged_sector) = staged_sector(&dir);
+
+        let throttle = SealThrottle::default();
+        let sealed = seal(
+            &sector_store,
+            &[0; 31],
+            staged_sector.clone(),
+            true,
+            None,
+            None,
+            &throttle,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        assert_eq!(staged_sector.sector_id, sealed.sector_id);
+        assert_eq!(staged_sector.pieces, sealed.pieces);
+
+        // What was sealed is recorded, so that sealing it again can be checked.
+        let access = staged_sector.sector_access;
+        assert_eq!(
+            Some(SealedFrom {
+                digest: staged_digest(&sector_store, &access).unwrap(),
+                sector_access: access,
+            }),
+            sealed.sealed_from
+        );
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn encrypted_staged_data_seals_as_plaintext_does() {
+        let plain_dir = tempfile::tempdir().unwrap();
+        let encrypted_dir = tempfile::tempdir().unwrap();
+
+        let (plain_store, plain_sector) = staged_sector(&plain_dir);
+        let key = StagingKey::from_bytes(1, [5; 32]);
+        let (encrypted_store, encrypted_sector) = staged_sector_with_encryption(
+            &encrypted_dir,
+            StagingEncryption::XChaCha20Poly1305(key),
+        );
+
+        // Neither piece is found in the staged file, as given or as preprocessed.
+        let on_disk = std::fs::read(&encrypted_sector.sector_access).unwrap();
+        let preprocessed = std::fs::read(&plain_sector.sector_access).unwrap();
+        for written in &[&[1; 100][..], &[2; 200][..], &preprocessed[..]] {
+            assert!(written
+                .windows(16)
+                .all(|run| !on_disk.windows(16).any(|window| window == run)));
+        }
+
+        let throttle = SealThrottle::default();
+        let plain = seal(
+            &plain_store,
+            &[0; 31],
+            plain_sector,
+            true,
+            None,
+            None,
+            &throttle,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+        let encrypted = seal(
+            &encrypted_store,
+            &[0; 31],
+            encrypted_sector,
+            true,
+            None,
+            None,
+            &throttle,
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        // The same data was sealed, by the same prover into the same sector id.
+        assert_eq!(plain.sector_id, encrypted.sector_id);
+        assert_eq!(plain.comm_d, encrypted.comm_d);
+        assert_eq!(plain.comm_r, encrypted.comm_r);
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn seals_in_landing_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        let landing_dir = tempfile::tempdir().unwrap();
+        let (sector_store, staged_sector) = staged_sector(&dir);
+
+        let sealed = seal(
+            &sector_store,
+            &[0; 31],
+            staged_sector,
+            true,
+            Some(landing_dir.path()),
+            None,
+            &SealThrottle::default(),
+            &CancellationToken::new(),
+        )
+        .unwrap();
+
+        // The sealed sector has been moved out of the landing directory.
+        assert_eq!(0, std::fs::read_dir(landing_dir.path()).unwrap().count());
+        assert!(Path::new(&sealed.sector_access).starts_with(dir.path()));
+        assert!(std::fs::metadata(&sealed.sector_access).unwrap().len() > 0);
+
+        assert!(verify_seal(
+            sector_store.inner.config(),
+            sealed.comm_r,
+            sealed.comm_d,
+            sealed.comm_r_star,
+            &[0; 31],
+            &SectorId(sealed.sector_id).to_fr_safe(),
+            &seal_envelope(ProofVariant::Snark, &sealed.snark_proof),
+        )
+        .unwrap());
+    }
+}
@@ -0,0 +1,483 @@
+use crate::api::sector_builder::errors::{err_sector_id_out_of_range, err_sector_ids_exhausted};
+use crate::api::sector_builder::state::SectorBuilderState;
+use crate::api::sector_builder::WrappedKeyValueStore;
+use crate::error::Result;
+use std::cmp;
+use std::sync::{Arc, Mutex};
+
+// Sector ids end up on chain, so no id may ever be handed out twice, not even
+// across restarts or crashes. The last allocated id is kept in the metadata
+// store under its own key, apart from the builder's snapshot, and is written
+// durably before the id is used. A crash after the id is recorded but before
+// the snapshot is written skips the id, rather than reusing it.
+
+const KEY_SUFFIX: &[u8] = b"/sector-ids";
+
+lazy_static! {
+    // Held while an id is allocated, so that builders in this process which
+    // share a metadata directory and prover id never allocate the same id.
+    static ref ALLOCATION_LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// The range, inclusive, from which a builder allocates sector ids. Each miner
+/// is assigned its range on chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SectorIdRange {
+    pub first: u64,
+    pub last: u64,
+}
+
+impl Default for SectorIdRange {
+    fn default() -> SectorIdRange {
+        SectorIdRange {
+            first: 0,
+            last: u64::max_value(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SectorIdRecord {
+    range: SectorIdRange,
+    last_allocated: Option<u64>,
+}
+
+pub struct SectorIdAllocator {
+    kv_store: Arc<WrappedKeyValueStore>,
+    key: Vec<u8>,
+    range: SectorIdRange,
+    last_allocated: Option<u64>,
+}
+
+impl SectorIdAllocator {
+    // Loads the allocator of the builder whose metadata is state, recording
+    // the configured range. Fails if any sector id already recorded for the
+    // builder lies beyond the end of the range. Ids recorded before the start
+    // of the range are fine; allocation then starts at the start of the range.
+    pub fn load(
+        kv_store: Arc<WrappedKeyValueStore>,
+        state: &SectorBuilderState,
+        range: SectorIdRange,
+    ) -> Result<SectorIdAllocator> {
+        let _guard = ALLOCATION_LOCK.lock().unwrap_or_else(|p| p.into_inner());
+
+        let allocator = Self::read(kv_store, state, range)?;
+        allocator.write_record(allocator.last_allocated)?;
+
+        Ok(allocator)
+    }
+
+    // Loads the allocator as load does, but records nothing, for builders
+    // which never allocate ids as their metadata is read-only.
+    pub fn load_read_only(
+        kv_store: Arc<WrappedKeyValueStore>,
+        state: &SectorBuilderState,
+        range: SectorIdRange,
+    ) -> Result<SectorIdAllocator> {
+        let _guard = ALLOCATION_LOCK.lock().unwrap_or_else(|p| p.into_inner());
+
+        Self::read(kv_store, state, range)
+    }
+
+    // Loads the allocator, as load does, while ALLOCATION_LOCK is held.
+    fn read(
+        kv_store: Arc<WrappedKeyValueStore>,
+        state: &SectorBuilderState,
+        range: SectorIdRange,
+    ) -> Result<SectorIdAllocator> {
+        let key = [&state.prover_id[..], KEY_SUFFIX].concat();
+
+        // Snapshots written before ids were recorded separately still name
+        // every id allocated so far.
+        let in_state = state
+            .staged
+            .sectors
+            .keys()
+            .chain(state.sealed.sectors.keys())
+            .max()
+            .cloned();
+
+        let in_record = read_record(&kv_store, &key)?.and_then(|r| r.last_allocated);
+        let last_allocated = cmp::max(in_state, in_record);
+
+        if let Some(sector_id) = last_allocated {
+            if sector_id > range.last {
+                return Err(err_sector_id_out_of_range(sector_id, range.first, range.last).into());
+            }
+        }
+
+        Ok(SectorIdAllocator {
+            kv_store,
+            key,
+            range,
+            last_allocated,
+        })
+    }
+
+    // Allocates the next sector id, durably recording it before returning it.
+    // Ids are strictly increasing, but not necessarily consecutive.
+    pub fn allocate(&mut self) -> Result<u64> {
+        let _guard = ALLOCATION_LOCK.lock().unwrap_or_else(|p| p.into_inner());
+
+        // Another builder over the same metadata may have allocated ids since
+        // this one last did.
+        let in_record = read_record(&self.kv_store, &self.key)?.and_then(|r| r.last_allocated);
+        let last_allocated = cmp::max(self.last_allocated, in_record);
+
+        let next = match last_allocated {
+            Some(id) => id.checked_add(1).map(|next| cmp::max(next, self.range.first)),
+            None => Some(self.range.first),
+        };
+
+        let sector_id = match next {
+            Some(id) if id <= self.range.last => id,
+            _ => return Err(err_sector_ids_exhausted(self.range.first, self.range.last).into()),
+        };
+
+        self.write_record(Some(sector_id))?;
+        self.last_allocated = Some(sector_id);
+
+        Ok(sector_id)
+    }
+
+    // Records that sector_id, which this builder didn't allocate (e.g. that of
+    // an imported sector), is in use, so that it's never allocated. Fails if it
+    // lies beyond the end of the range, as the builder's metadata would then be
+    // refused once reloaded.
+    pub fn reserve(&mut self, sector_id: u64) -> Result<()> {
+        if sector_id > self.range.last {
+            let (first, last) = (self.range.first, self.range.last);
+            return Err(err_sector_id_out_of_range(sector_id, first, last).into());
+        }
+
+        let _guard = ALLOCATION_LOCK.lock().unwrap_or_else(|p| p.into_inner());
+
+        let in_record = read_record(&self.kv_store, &self.key)?.and_then(|r| r.last_allocated);
+        let last_allocated = cmp::max(self.last_allocated, in_record);
+
+        if last_allocated < Some(sector_id) {
+            self.write_record(Some(sector_id))?;
+            self.last_allocated = Some(sector_id);
+        } else {
+            self.last_allocated = last_allocated;
+        }
+
+        Ok(())
+    }
+
+    fn write_record(&self, last_allocated: Option<u64>) -> Result<()> {
+        let record = SectorIdRecord {
+            range: self.range,
+            last_allocated,
+        };
+
+        self.kv_store
+            .inner
+            .put(&self.key, &serde_cbor::to_vec(&record)?)
+    }
+}
+
+// Returns an allocator, starting at first, over a metadata store in dir.
+#[cfg(test)]
+pub fn test_allocator(dir: &std::path::Path, first: u64) -> SectorIdAllocator {
+    let range = SectorIdRange {
+        first,
+        last: u64::max_value(),
+    };
+
+    test_allocator_in_range(dir, range)
+}
+
+// Returns an allocator of ids in range over a metadata store in dir.
+#[cfg(test)]
+pub fn test_allocator_in_range(dir: &std::path::Path, range: SectorIdRange) -> SectorIdAllocator {
+    use crate::api::sector_builder::kv_store::fs::FileSystemKvs;
+
+    let kv_store = Arc::new(WrappedKeyValueStore {
+        inner: Box::new(FileSystemKvs::initialize(dir).unwrap()),
+    });
+
+    let state = SectorBuilderState {
+        prover_id: [0; 31],
+        staged: Default::default(),
+        sealed: Default::default(),
+    };
+
+    SectorIdAllocator::load(kv_store, &state, range).unwrap()
+}
+
+fn read_record(kv_store: &WrappedKeyValueStore, key: &[u8]) -> Result<Option<SectorIdRecord>> {
+    match kv_store.inner.get(key)? {
+        Some(bytes) => Ok(Some(serde_cbor::from_slice(&bytes)?)),
+        None => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sector_builder::errors::SectorBuilderErr;
+    use crate::api::sector_builder::helpers::add_piece::add_piece;
+    use crate::api::sector_builder::helpers::snapshots::{
+        load_snapshot, make_snapshot, persist_snapshot,
+    };
+    use crate::api::sector_builder::kv_store::fs::FileSystemKvs;
+    use crate::api::sector_builder::kv_store::KeyValueStore;
+    use crate::api::sector_builder::state::{SealedState, StagedState};
+    use crate::api::sector_builder::WrappedSectorStore;
+    use sector_base::api::disk_backed_storage::{new_sector_store, ConfiguredStore};
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const PROVER_ID: [u8; 31] = [5; 31];
+
+    // Fails every write once crashed, as if the process had died.
+    struct CrashingKvs {
+        inner: FileSystemKvs,
+        crashed: Arc<AtomicBool>,
+    }
+
+    impl KeyValueStore for CrashingKvs {
+        fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+            if self.crashed.load(Ordering::SeqCst) {
+                return Err(format_err!("crashed"));
+            }
+            self.inner.put(key, value)
+        }
+
+        fn get(&self, key: &[u8]) -> Result<Option<Vec<u8>>> {
+            self.inner.get(key)
+        }
+    }
+
+    // Crashes the store it was made with when set.
+    struct Failpoint(Arc<AtomicBool>);
+
+    fn kv_store(dir: &tempfile::TempDir) -> (Arc<WrappedKeyValueStore>, Failpoint) {
+        let crashed = Arc::new(AtomicBool::new(false));
+
+        let kv_store = Arc::new(WrappedKeyValueStore {
+            inner: Box::new(CrashingKvs {
+                inner: FileSystemKvs::initialize(dir.path()).unwrap(),
+                crashed: crashed.clone(),
+            }),
+        });
+
+        (kv_store, Failpoint(crashed))
+    }
+
+    fn fresh_state() -> SectorBuilderState {
+        SectorBuilderState {
+            prover_id: PROVER_ID,
+            staged: Default::default(),
+            sealed: Default::default(),
+        }
+    }
+
+    fn range(first: u64, last: u64) -> SectorIdRange {
+        SectorIdRange { first, last }
+    }
+
+    #[test]
+    fn allocation_starts_at_first_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let (kv_store, _) = kv_store(&dir);
+
+        let mut ids =
+            SectorIdAllocator::load(kv_store, &fresh_state(), range(1000, 2000)).unwrap();
+
+        assert_eq!(1000, ids.allocate().unwrap());
+        assert_eq!(1001, ids.allocate().unwrap());
+    }
+
+    #[test]
+    fn allocation_continues_after_reload() {
+        let dir = tempfile::tempdir().unwrap();
+        let (kv_store, _) = kv_store(&dir);
+
+        let mut ids =
+            SectorIdAllocator::load(kv_store.clone(), &fresh_state(), range(0, 100)).unwrap();
+        for expected in 0..5 {
+            assert_eq!(expected, ids.allocate().unwrap());
+        }
+        drop(ids);
+
+        // Nothing but the record says that ids were allocated.
+        let mut ids = SectorIdAllocator::load(kv_store, &fresh_state(), range(0, 100)).unwrap();
+        assert_eq!(5, ids.allocate().unwrap());
+    }
+
+    #[test]
+    fn ids_in_old_snapshots_are_not_reused() {
+        let dir = tempfile::tempdir().unwrap();
+        let (kv_store, _) = kv_store(&dir);
+
+        let mut state = fresh_state();
+        state.sealed.sectors.insert(41, Default::default());
+        state.staged.sectors.insert(42, Default::default());
+
+        let mut ids = SectorIdAllocator::load(kv_store, &state, range(0, 100)).unwrap();
+        assert_eq!(43, ids.allocate().unwrap());
+    }
+
+    #[test]
+    fn raising_the_first_id_skips_ahead() {
+        let dir = tempfile::tempdir().unwrap();
+        let (kv_store, _) = kv_store(&dir);
+
+        let mut ids =
+            SectorIdAllocator::load(kv_store.clone(), &fresh_state(), range(0, 100)).unwrap();
+        ids.allocate().unwrap();
+
+        let mut ids = SectorIdAllocator::load(kv_store, &fresh_state(), range(50, 100)).unwrap();
+        assert_eq!(50, ids.allocate().unwrap());
+    }
+
+    #[test]
+    fn recorded_ids_beyond_range_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let (kv_store, _) = kv_store(&dir);
+
+        let mut ids =
+            SectorIdAllocator::load(kv_store.clone(), &fresh_state(), range(0, 100)).unwrap();
+        for _ in 0..11 {
+            ids.allocate().unwrap();
+        }
+
+        match SectorIdAllocator::load(kv_store, &fresh_state(), range(0, 9)) {
+            Err(err) => match err.downcast::<SectorBuilderErr>() {
+                Ok(SectorBuilderErr::SectorIdOutOfRange { sector_id: 10, .. }) => (),
+                other => panic!("unexpected result: {:?}", other),
+            },
+            Ok(_) => panic!("out of range metadata was accepted"),
+        }
+    }
+
+    #[test]
+    fn exhausted_range_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let (store, _) = kv_store(&dir);
+
+        let mut ids = SectorIdAllocator::load(store, &fresh_state(), range(7, 8)).unwrap();
+        assert_eq!(7, ids.allocate().unwrap());
+        assert_eq!(8, ids.allocate().unwrap());
+
+        match ids.allocate().map_err(|err| err.downcast::<SectorBuilderErr>()) {
+            Err(Ok(SectorBuilderErr::SectorIdsExhausted { .. })) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Nor does allocation wrap around.
+        let dir = tempfile::tempdir().unwrap();
+        let (kv_store, _) = kv_store(&dir);
+        let mut state = fresh_state();
+        state.sealed.sectors.insert(u64::max_value(), Default::default());
+
+        let mut ids = SectorIdAllocator::load(kv_store, &state, Default::default()).unwrap();
+        assert!(ids.allocate().is_err());
+    }
+
+    #[test]
+    fn reserved_ids_are_never_allocated() {
+        let dir = tempfile::tempdir().unwrap();
+        let (kv_store, _) = kv_store(&dir);
+
+        let mut ids =
+            SectorIdAllocator::load(kv_store.clone(), &fresh_state(), range(0, 100)).unwrap();
+        assert_eq!(0, ids.allocate().unwrap());
+
+        ids.reserve(10).unwrap();
+        assert_eq!(11, ids.allocate().unwrap());
+
+        // Reserving an id at or below the last one allocated changes nothing.
+        ids.reserve(5).unwrap();
+        assert_eq!(12, ids.allocate().unwrap());
+
+        match ids.reserve(101).map_err(|err| err.downcast::<SectorBuilderErr>()) {
+            Err(Ok(SectorBuilderErr::SectorIdOutOfRange { sector_id: 101, .. })) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // The reservation outlives the allocator.
+        ids.reserve(50).unwrap();
+        drop(ids);
+
+        let mut ids = SectorIdAllocator::load(kv_store, &fresh_state(), range(0, 100)).unwrap();
+        assert_eq!(51, ids.allocate().unwrap());
+    }
+
+    #[test]
+    fn sharing_builders_never_allocate_the_same_id() {
+        let dir = tempfile::tempdir().unwrap();
+        let (kv_store, _) = kv_store(&dir);
+
+        let mut a = SectorIdAllocator::load(kv_store.clone(), &fresh_state(), range(0, 100))
+            .unwrap();
+        let mut b = SectorIdAllocator::load(kv_store, &fresh_state(), range(0, 100)).unwrap();
+
+        let allocated = vec![
+            a.allocate().unwrap(),
+            b.allocate().unwrap(),
+            a.allocate().unwrap(),
+            b.allocate().unwrap(),
+        ];
+        assert_eq!(vec![0, 1, 2, 3], allocated);
+    }
+
+    #[test]
+    fn crash_after_recording_id_never_reuses_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let store_dir = tempfile::tempdir().unwrap();
+        let (kv_store, failpoint) = kv_store(&dir);
+
+        let path = store_dir.path().to_str().unwrap().to_owned();
+        let sector_store = Arc::new(WrappedSectorStore {
+            inner: Box::new(new_sector_store(&ConfiguredStore::Test, path.clone(), path)),
+        });
+        let max = sector_store.inner.config().max_unsealed_bytes_per_sector() as usize;
+
+        let mut handed_out = Vec::new();
+
+        for _ in 0..3 {
+            // Restart from whatever made it to disk.
+            let state = load_snapshot(&kv_store, &PROVER_ID)
+                .unwrap()
+                .map(|snapshot| snapshot.into())
+                .unwrap_or_else(fresh_state);
+            let mut ids = SectorIdAllocator::load(kv_store.clone(), &state, range(0, 100)).unwrap();
+            let mut staged = state.staged;
+
+            // A piece which fills a sector always needs a new one.
+            let sector_id = add_piece(
+                &sector_store,
+                &mut staged,
+                &mut ids,
+                "piece".to_string(),
+                &vec![1; max],
+                None,
+            )
+            .unwrap();
+
+            // Crash before the snapshot recording the new sector is written,
+            // so the id is never handed out...
+            failpoint.0.store(true, Ordering::SeqCst);
+            let snapshot = make_snapshot(&PROVER_ID, &staged, &SealedState::default());
+            assert!(persist_snapshot(&kv_store, &snapshot).is_err());
+            failpoint.0.store(false, Ordering::SeqCst);
+
+            assert!(!handed_out.contains(&sector_id));
+            handed_out.push(sector_id);
+        }
+
+        // ...and, as the snapshot never recorded any of them, only the
+        // record kept them from being reused.
+        assert_eq!(vec![0, 1, 2], handed_out);
+        assert!(load_snapshot(&kv_store, &PROVER_ID).unwrap().is_none());
+
+        let mut staged = StagedState::default();
+        let state = fresh_state();
+        let mut ids = SectorIdAllocator::load(kv_store.clone(), &state, range(0, 100)).unwrap();
+        let sector_id =
+            add_piece(&sector_store, &mut staged, &mut ids, "x".to_string(), &[1], None).unwrap();
+        assert_eq!(3, sector_id);
+    }
+}
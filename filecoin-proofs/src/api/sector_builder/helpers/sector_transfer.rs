@@ -0,0 +1,320 @@
+use crate::api::sector_builder::errors::{
+    err_invalid_field, err_prover_id_mismatch, err_sealed_checksum_mismatch,
+    err_sector_id_collision,
+};
+use crate::api::sector_builder::helpers::sector_ids::SectorIdAllocator;
+use crate::api::sector_builder::metadata::SealedSectorMetadata;
+use crate::api::sector_builder::snapshot_format::{
+    decode_manifest, encode_manifest, SealedSectorManifest,
+};
+use crate::api::sector_builder::state::SectorBuilderState;
+use crate::api::sector_builder::WrappedSectorStore;
+use crate::error::Result;
+use blake2::{Blake2b, Digest};
+use sector_base::api::util::sync_dir;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use storage_proofs::crypto::constant_time::ConstantTimeEq;
+
+// Sealed sectors are exported to be carried to another machine by other
+// means, so each is copied along with a manifest recording its metadata and
+// the checksum of the sealed sector. The manifest is written last, once the
+// copy has been synced and checked: a sealed sector without a manifest beside
+// it is what's left of an export which didn't finish.
+
+// Copies a sealed sector, followed by its manifest, to dest_dir, and returns
+// the path of the manifest. No manifest is written unless the copy matches
+// the sealed sector.
+pub fn export_sealed_sector(
+    sector_store: &Arc<WrappedSectorStore>,
+    prover_id: &[u8; 31],
+    sealed_sector: &SealedSectorMetadata,
+    dest_dir: &Path,
+) -> Result<PathBuf> {
+    let sector_id = sealed_sector.sector_id;
+    let sealed_file = format!("sector-{}.sealed", sector_id);
+    let copy_path = dest_dir.join(&sealed_file);
+    let manifest_path = dest_dir.join(format!("sector-{}.manifest", sector_id));
+
+    fs::create_dir_all(dest_dir)?;
+
+    sector_store
+        .inner
+        .manager()
+        .fetch_sealed(&sealed_sector.sector_access)?;
+    let (sealed_bytes, sealed_checksum) = file_checksum(&sealed_sector.sector_access)?;
+
+    // The sector manager only copies into its own directories, and dest_dir
+    // is the caller's, so the copy is made here.
+    copy_file(&sealed_sector.sector_access, &copy_path)?;
+
+    let (copy_bytes, copy_checksum) = file_checksum(&copy_path)?;
+    if copy_bytes != sealed_bytes || !copy_checksum.ct_eq(&sealed_checksum) {
+        let _ = fs::remove_file(&copy_path);
+        return Err(err_sealed_checksum_mismatch(sector_id).into());
+    }
+
+    let manifest = encode_manifest(&SealedSectorManifest {
+        prover_id: *prover_id,
+        sector: sealed_sector.clone(),
+        sealed_file,
+        sealed_bytes,
+        sealed_checksum,
+    })?;
+
+    let mut file = File::create(&manifest_path)?;
+    file.write_all(&manifest)?;
+    file.sync_all()?;
+    sync_dir(dest_dir)?;
+
+    Ok(manifest_path)
+}
+
+// Copies the sealed sector exported with the manifest at manifest_path to a
+// new sealed sector access, and returns its metadata, which the caller is to
+// record. The sector's id is reserved, even if the copy fails. Nothing is left
+// at the new access unless the copy matches the checksum in the manifest.
+pub fn import_sealed_sector(
+    sector_store: &Arc<WrappedSectorStore>,
+    state: &SectorBuilderState,
+    sector_ids: &mut SectorIdAllocator,
+    manifest_path: &Path,
+) -> Result<SealedSectorMetadata> {
+    let mut bytes = Vec::new();
+    File::open(manifest_path)?.read_to_end(&mut bytes)?;
+
+    let SealedSectorManifest {
+        prover_id,
+        sector,
+        sealed_file,
+        sealed_bytes,
+        sealed_checksum,
+    } = decode_manifest(&bytes)?;
+    let sector_id = sector.sector_id;
+
+    // Sealing bound the sector to its prover's id, so no other prover can
+    // prove or unseal it.
+    if prover_id != state.prover_id {
+        return Err(err_prover_id_mismatch(sector_id).into());
+    }
+
+    if state.staged.sectors.contains_key(&sector_id)
+        || state.sealed.sectors.contains_key(&sector_id)
+    {
+        return Err(err_sector_id_collision(sector_id).into());
+    }
+
+    // The copy must be beside the manifest.
+    let mut components = Path::new(&sealed_file).components();
+    match (components.next(), components.next()) {
+        (Some(Component::Normal(_)), None) => (),
+        _ => return Err(err_invalid_field(sector_id, "sealed_file", "not a file name").into()),
+    }
+
+    sector_ids.reserve(sector_id)?;
+
+    let manager = sector_store.inner.manager();
+    let sector_access = manager.new_sealed_sector_access()?;
+
+    // The sector manager only copies from its own directories, and the copy
+    // is beside the caller's manifest, so it's copied here.
+    let copied = copy_file(manifest_path.with_file_name(&sealed_file), &sector_access)
+        .and_then(|_| file_checksum(&sector_access));
+
+    match copied {
+        Ok((len, checksum)) if len == sealed_bytes && checksum.ct_eq(&sealed_checksum) => {
+            if let Err(err) = manager.publish_sealed(&sector_access) {
+                let _ = fs::remove_file(&sector_access);
+                return Err(err.into());
+            }
+
+            // The sector's staged data, and its prover bundle if it had one,
+            // were left behind with the builder it was sealed by.
+            Ok(SealedSectorMetadata {
+                sector_access,
+                sealed_from: None,
+                prover_bundle: None,
+                ..sector
+            })
+        }
+        Ok(_) => {
+            let _ = fs::remove_file(&sector_access);
+            Err(err_sealed_checksum_mismatch(sector_id).into())
+        }
+        Err(err) => {
+            let _ = fs::remove_file(&sector_access);
+            Err(err)
+        }
+    }
+}
+
+// Copies the file at src to dest, replacing whatever is there, and syncs it.
+fn copy_file<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dest: Q) -> Result<()> {
+    let mut copy = File::create(dest)?;
+    io::copy(&mut File::open(src)?, &mut copy)?;
+    copy.sync_all()?;
+
+    Ok(())
+}
+
+// Returns the length of the file at path, and its blake2b checksum.
+fn file_checksum<P: AsRef<Path>>(path: P) -> Result<(u64, Vec<u8>)> {
+    let mut file = File::open(path)?;
+    let mut hasher = Blake2b::new();
+    let mut buf = vec![0; 1 << 16];
+    let mut len = 0;
+
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+
+        hasher.input(&buf[..n]);
+        len += n as u64;
+    }
+
+    Ok((len, hasher.result().to_vec()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sector_builder::errors::{MetadataErr, SectorBuilderErr};
+    use crate::api::sector_builder::helpers::sector_ids::test_allocator;
+    use sector_base::api::disk_backed_storage::{new_sector_store, ConfiguredStore};
+    use std::fs::OpenOptions;
+
+    const PROVER_ID: [u8; 31] = [5; 31];
+
+    struct Builder {
+        dir: tempfile::TempDir,
+        sector_store: Arc<WrappedSectorStore>,
+        state: SectorBuilderState,
+    }
+
+    impl Builder {
+        fn new() -> Builder {
+            let dir = tempfile::tempdir().unwrap();
+            let path = dir.path().join("sectors").to_str().unwrap().to_owned();
+
+            let sector_store = Arc::new(WrappedSectorStore {
+                inner: Box::new(new_sector_store(&ConfiguredStore::Test, path.clone(), path)),
+            });
+
+            let state = SectorBuilderState {
+                prover_id: PROVER_ID,
+                staged: Default::default(),
+                sealed: Default::default(),
+            };
+
+            Builder {
+                dir,
+                sector_store,
+                state,
+            }
+        }
+
+        fn import(&mut self, manifest_path: &Path) -> Result<SealedSectorMetadata> {
+            let ids = &mut test_allocator(&self.dir.path().join("metadata"), 0);
+            let sector = import_sealed_sector(&self.sector_store, &self.state, ids, manifest_path)?;
+
+            self.state.sealed.sectors.insert(sector.sector_id, sector.clone());
+
+            Ok(sector)
+        }
+    }
+
+    // Exports a sector whose "sealed" data is made up, as nothing checks it.
+    fn export(dest_dir: &Path) -> (SealedSectorMetadata, PathBuf) {
+        let builder = Builder::new();
+        let manager = builder.sector_store.inner.manager();
+
+        let sector = SealedSectorMetadata {
+            sector_id: 7,
+            sector_access: manager.new_sealed_sector_access().unwrap(),
+            comm_r: [1; 32],
+            ..Default::default()
+        };
+        fs::write(&sector.sector_access, vec![3; 1024]).unwrap();
+
+        let manifest_path =
+            export_sealed_sector(&builder.sector_store, &PROVER_ID, &sector, dest_dir).unwrap();
+
+        (sector, manifest_path)
+    }
+
+    #[test]
+    fn imports_exported_sectors() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let (exported, manifest_path) = export(dest_dir.path());
+
+        let mut builder = Builder::new();
+        let imported = builder.import(&manifest_path).unwrap();
+
+        assert_ne!(exported.sector_access, imported.sector_access);
+        assert!(imported.sector_access.starts_with(builder.dir.path().to_str().unwrap()));
+        assert_eq!(
+            SealedSectorMetadata {
+                sector_access: exported.sector_access.clone(),
+                ..imported.clone()
+            },
+            exported
+        );
+        assert_eq!(vec![3; 1024], fs::read(&imported.sector_access).unwrap());
+
+        // The id is now taken.
+        match builder.import(&manifest_path).map_err(|e| e.downcast::<SectorBuilderErr>()) {
+            Err(Ok(SectorBuilderErr::SectorIdCollision { sector_id: 7 })) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn corrupted_copies_are_refused() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let (_, manifest_path) = export(dest_dir.path());
+
+        let mut copy = OpenOptions::new()
+            .write(true)
+            .open(dest_dir.path().join("sector-7.sealed"))
+            .unwrap();
+        copy.write_all(&[4]).unwrap();
+
+        let mut builder = Builder::new();
+        match builder.import(&manifest_path).map_err(|e| e.downcast::<SectorBuilderErr>()) {
+            Err(Ok(SectorBuilderErr::SealedSectorChecksumMismatch { sector_id: 7 })) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Only the empty access provisioned for the copy was ever created,
+        // and it's gone.
+        let sectors_dir = builder.dir.path().join("sectors");
+        assert_eq!(0, fs::read_dir(sectors_dir).unwrap().count());
+    }
+
+    #[test]
+    fn manifests_are_checked() {
+        let dest_dir = tempfile::tempdir().unwrap();
+        let (_, manifest_path) = export(dest_dir.path());
+
+        let mut builder = Builder::new();
+        builder.state.prover_id = [6; 31];
+        match builder.import(&manifest_path).map_err(|e| e.downcast::<SectorBuilderErr>()) {
+            Err(Ok(SectorBuilderErr::ProverIdMismatch { sector_id: 7 })) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut manifest = fs::read(&manifest_path).unwrap();
+        let last = manifest.len() - 1;
+        manifest[last] ^= 1;
+        fs::write(&manifest_path, manifest).unwrap();
+
+        match Builder::new().import(&manifest_path).map_err(|e| e.downcast::<MetadataErr>()) {
+            Err(Ok(MetadataErr::ChecksumMismatch)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
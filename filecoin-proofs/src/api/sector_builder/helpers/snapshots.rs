@@ -1,7 +1,10 @@
+use crate::api::sector_builder::snapshot_format::{decode_snapshot, encode_snapshot};
 use crate::api::sector_builder::state::*;
 use crate::api::sector_builder::WrappedKeyValueStore;
 use crate::error::Result;
 use std::sync::Arc;
+use storage_proofs::fail_point;
+use storage_proofs::failpoint;
 
 pub fn load_snapshot(
     kv_store: &Arc<WrappedKeyValueStore>,
@@ -9,21 +12,20 @@ pub fn load_snapshot(
 ) -> Result<Option<StateSnapshot>> {
     let result: Option<Vec<u8>> = kv_store.inner.get(prover_id)?;
 
-    if let Some(val) = result {
-        return serde_cbor::from_slice(&val[..])
-            .map_err(failure::Error::from)
-            .map(Option::Some);
+    match result {
+        Some(val) => decode_snapshot(&val[..]).map(Option::Some),
+        None => Ok(None),
     }
-
-    Ok(None)
 }
 
 pub fn persist_snapshot(
     kv_store: &Arc<WrappedKeyValueStore>,
     snapshot: &StateSnapshot,
 ) -> Result<()> {
-    let serialized = serde_cbor::to_vec(snapshot)?;
+    let serialized = encode_snapshot(snapshot)?;
     kv_store.inner.put(&snapshot.prover_id[..], &serialized)?;
+    fail_point!(failpoint::SNAPSHOT_PERSISTED);
+
     Ok(())
 }
 
@@ -35,7 +37,6 @@ pub fn make_snapshot(
     StateSnapshot {
         prover_id: *prover_id,
         staged: StagedState {
-            sector_id_nonce: staged_state.sector_id_nonce,
             sectors: staged_state.sectors.clone(),
         },
         sealed: SealedState {
@@ -51,7 +52,6 @@ mod tests {
     use crate::api::sector_builder::metadata::StagedSectorMetadata;
     use crate::api::sector_builder::state::SealedState;
     use crate::api::sector_builder::state::StagedState;
-    use crate::api::sector_builder::SectorId;
     use crate::api::sector_builder::WrappedKeyValueStore;
     use std::collections::HashMap;
     use std::sync::Arc;
@@ -68,14 +68,18 @@ mod tests {
         let prover_id = [0; 31];
 
         let (staged_state, sealed_state) = {
-            let mut m: HashMap<SectorId, StagedSectorMetadata> = HashMap::new();
-
-            m.insert(123, Default::default());
-
-            let staged_state = Mutex::new(StagedState {
-                sector_id_nonce: 100,
-                sectors: m,
-            });
+            let mut m: HashMap<u64, StagedSectorMetadata> = HashMap::new();
+
+            // Sectors are recorded by their id, which the builder keys them by.
+            m.insert(
+                123,
+                StagedSectorMetadata {
+                    sector_id: 123,
+                    ..Default::default()
+                },
+            );
+
+            let staged_state = Mutex::new(StagedState { sectors: m });
 
             let sealed_state: Mutex<SealedState> = Default::default();
 
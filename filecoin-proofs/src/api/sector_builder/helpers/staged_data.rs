@@ -0,0 +1,537 @@
+use crate::api::cancellation::CancellationToken;
+use crate::api::internal;
+use crate::api::piece_layout::{compute_comm_d_from_pieces, packed_layout_is_aligned};
+use crate::api::sector_builder::errors::err_staged_data_mismatch;
+use crate::api::sector_builder::metadata::{sum_piece_bytes, StagedSectorMetadata};
+use crate::api::sector_builder::WrappedSectorStore;
+use crate::api::staged_read::{read_stable, StagedSource};
+use crate::error;
+use blake2::{Blake2b, Digest};
+use sector_base::api::sector_store::SectorManager;
+use sector_base::io::fr32::{pack_user_bytes, padded_bytes, unpack_user_bytes};
+use std::cell::Cell;
+use std::cmp;
+use std::io::{self, BufReader, Read};
+use std::mem;
+use std::path::Path;
+use storage_proofs::crypto::constant_time::ConstantTimeEq;
+use storage_proofs::hasher::PedersenHasher;
+use storage_proofs::merkle::StreamingCommitment;
+use storage_proofs::types::Commitment;
+use storage_proofs::util::NODE_SIZE;
+
+// Pieces are bit-packed into the staged file one after another, so they
+// generally don't fall on node boundaries and the comm_d of a staged sector
+// can't be assembled from its pieces' comm_ps alone. Instead, each piece is
+// read back out of the staged file and checked against its comm_p, and the
+// comm_d of the staged file is checked against the comm_d of those pieces laid
+// out as add_piece lays them out. Where add_piece happens to have laid them out
+// as api::piece_layout does, that comm_d is assembled from the comm_ps instead.
+
+// 127 bytes preprocess to exactly four nodes, so a piece can be preprocessed
+// in chunks of a multiple of 127 bytes, each on its own.
+const COMM_P_CHUNK_BYTES: usize = 127 * 64;
+
+// Computes the commitment to a piece: the root of a merkle tree over the
+// piece, preprocessed on its own and zero-padded to a power-of-two number of
+// nodes.
+pub fn compute_comm_p(piece_bytes: &[u8]) -> error::Result<Commitment> {
+    let mut comm_p = CommPBuilder::new();
+    comm_p.update(piece_bytes)?;
+    comm_p.finish()
+}
+
+// Computes the commitment to a piece from its bytes as they're streamed in,
+// holding on to less than a chunk of them at a time.
+pub struct CommPBuilder {
+    pending: Vec<u8>,
+    commitment: StreamingCommitment<PedersenHasher>,
+}
+
+impl CommPBuilder {
+    pub fn new() -> CommPBuilder {
+        CommPBuilder {
+            pending: Vec::with_capacity(COMM_P_CHUNK_BYTES),
+            commitment: StreamingCommitment::padded_to_power_of_two(NODE_SIZE)
+                .expect("nodes are NODE_SIZE bytes"),
+        }
+    }
+
+    pub fn update(&mut self, mut bytes: &[u8]) -> error::Result<()> {
+        while !bytes.is_empty() {
+            let n = cmp::min(COMM_P_CHUNK_BYTES - self.pending.len(), bytes.len());
+            self.pending.extend_from_slice(&bytes[..n]);
+            bytes = &bytes[n..];
+
+            if self.pending.len() == COMM_P_CHUNK_BYTES {
+                self.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> error::Result<Commitment> {
+        self.flush()?;
+
+        Ok(self.commitment.finish()?)
+    }
+
+    // Preprocesses the pending bytes into nodes, the last of which is
+    // zero-padded if it's incomplete.
+    fn flush(&mut self) -> error::Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let preprocessed = pack_user_bytes(&self.pending);
+        self.pending.clear();
+
+        Ok(self.commitment.update(preprocessed.as_bytes())?)
+    }
+}
+
+impl Default for CommPBuilder {
+    fn default() -> CommPBuilder {
+        CommPBuilder::new()
+    }
+}
+
+// Checks that the staged file of a sector holds exactly the pieces recorded
+// for it, producing a StagedDataMismatch error if it does not. Sectors with
+// pieces staged before comm_ps were recorded are not checked. The staged file
+// is streamed, rather than read into memory, so only a few chunks of it are
+// held at a time.
+pub fn verify_staged_data(
+    sector_store: &WrappedSectorStore,
+    staged_sector: &StagedSectorMetadata,
+) -> error::Result<()> {
+    let comm_ps: Option<Vec<Commitment>> = staged_sector.pieces.iter().map(|p| p.comm_p).collect();
+
+    let comm_ps = match comm_ps {
+        Some(comm_ps) => comm_ps,
+        None => return Ok(()),
+    };
+
+    let sector_bytes = sector_store.inner.config().sector_bytes() as usize;
+    let mgr = sector_store.inner.manager();
+
+    // Read the staged data the same way seal does: through the manager, and
+    // again should it change while it's read.
+    let access = &staged_sector.sector_access;
+    let comm_d = read_stable(Path::new(access), || {
+        let staged = mgr.open_unsealed(access)?;
+        internal::compute_comm_d(BufReader::new(staged), sector_bytes)
+    })?;
+
+    let pieces_laid_out: Vec<(Commitment, u64)> = comm_ps
+        .iter()
+        .cloned()
+        .zip(staged_sector.pieces.iter().map(|piece| piece.num_bytes))
+        .collect();
+    let piece_lengths: Vec<u64> = pieces_laid_out.iter().map(|&(_, len)| len).collect();
+
+    // Read the pieces back out of the staged file a chunk at a time, checking
+    // each against its comm_p, and, unless comm_d can be assembled from their
+    // comm_ps, lay them out again as add_piece does.
+    let mut staged = BufReader::new(mgr.open_unsealed(&staged_sector.sector_access)?);
+    let mut pieces = PieceCheck::new(staged_sector, comm_ps);
+    let mut rebuilt = if packed_layout_is_aligned(sector_bytes, &piece_lengths) {
+        None
+    } else {
+        Some(internal::CommDBuilder::new(sector_bytes)?)
+    };
+    let mut unread = sum_piece_bytes(staged_sector) as usize;
+
+    let chunk_bytes = padded_bytes(COMM_P_CHUNK_BYTES);
+    let mut chunk = Vec::with_capacity(chunk_bytes);
+
+    while unread > 0 {
+        chunk.clear();
+        (&mut staged)
+            .take(chunk_bytes as u64)
+            .read_to_end(&mut chunk)?;
+
+        let piece_bytes = unpack_user_bytes(&chunk, cmp::min(unread, COMM_P_CHUNK_BYTES));
+
+        // A truncated staged file can't hold all of the pieces.
+        if piece_bytes.is_empty() || !pieces.update(&piece_bytes)? {
+            return Err(err_staged_data_mismatch(staged_sector.sector_id).into());
+        }
+
+        if let Some(rebuilt) = rebuilt.as_mut() {
+            rebuilt.update(pack_user_bytes(&piece_bytes).as_bytes())?;
+        }
+        unread -= piece_bytes.len();
+    }
+
+    let expected = match rebuilt {
+        Some(rebuilt) => rebuilt.finish()?,
+        None => compute_comm_d_from_pieces(sector_bytes, &pieces_laid_out)?,
+    };
+
+    // Anything else written to the staged file, e.g. appended after the last
+    // piece, shows up as a difference in comm_d.
+    if !pieces.finish()? || !comm_d.ct_eq(&expected) {
+        return Err(err_staged_data_mismatch(staged_sector.sector_id).into());
+    }
+
+    Ok(())
+}
+
+// Checks the bytes of a staged sector's pieces, given in order as they're read
+// out of its staged file, against the pieces' comm_ps.
+struct PieceCheck {
+    // The length and comm_p of each piece yet to be read in full, last first.
+    pieces: Vec<(usize, Commitment)>,
+    // How much of the first of them has been read.
+    read: usize,
+    comm_p: CommPBuilder,
+}
+
+impl PieceCheck {
+    fn new(staged_sector: &StagedSectorMetadata, comm_ps: Vec<Commitment>) -> PieceCheck {
+        let mut pieces: Vec<(usize, Commitment)> = staged_sector
+            .pieces
+            .iter()
+            .map(|piece| piece.num_bytes as usize)
+            .zip(comm_ps)
+            .collect();
+        pieces.reverse();
+
+        PieceCheck {
+            pieces,
+            read: 0,
+            comm_p: CommPBuilder::new(),
+        }
+    }
+
+    // Reads the next bytes of the pieces, returning whether every piece read
+    // in full so far matches its comm_p.
+    fn update(&mut self, mut bytes: &[u8]) -> error::Result<bool> {
+        loop {
+            while let Some(&(num_bytes, comm_p)) = self.pieces.last() {
+                if self.read < num_bytes {
+                    break;
+                }
+
+                let read = mem::replace(&mut self.comm_p, CommPBuilder::new());
+                if !read.finish()?.ct_eq(&comm_p) {
+                    return Ok(false);
+                }
+
+                self.pieces.pop();
+                self.read = 0;
+            }
+
+            let num_bytes = match self.pieces.last() {
+                Some(&(num_bytes, _)) if !bytes.is_empty() => num_bytes,
+                _ => return Ok(bytes.is_empty()),
+            };
+
+            let n = cmp::min(num_bytes - self.read, bytes.len());
+            self.comm_p.update(&bytes[..n])?;
+            self.read += n;
+            bytes = &bytes[n..];
+        }
+    }
+
+    // Whether every piece has been read in full, and matches its comm_p.
+    fn finish(mut self) -> error::Result<bool> {
+        Ok(self.update(&[])? && self.pieces.is_empty())
+    }
+}
+
+// Returns the digest of the staged data at the given access, as seal records
+// it for the sector sealed from it.
+pub fn staged_digest(sector_store: &WrappedSectorStore, access: &str) -> error::Result<[u8; 32]> {
+    let staged = sector_store.inner.manager().open_unsealed(access)?;
+
+    DigestingReader::new(staged).finish()
+}
+
+// Digests the bytes read through it, so that staged data can be digested as
+// it's sealed rather than read twice.
+pub struct DigestingReader<R> {
+    inner: R,
+    hasher: Blake2b,
+}
+
+impl<R: Read> DigestingReader<R> {
+    pub fn new(inner: R) -> DigestingReader<R> {
+        DigestingReader {
+            inner,
+            hasher: Blake2b::new(),
+        }
+    }
+
+    // Reads the rest of the reader, and returns the digest of everything read
+    // from it: the first 32 bytes of its blake2b hash.
+    pub fn finish(mut self) -> error::Result<[u8; 32]> {
+        io::copy(&mut self, &mut io::sink())?;
+
+        let mut digest = [0; 32];
+        digest.copy_from_slice(&self.hasher.result()[..32]);
+
+        Ok(digest)
+    }
+}
+
+impl<R: Read> Read for DigestingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.input(&buf[..n]);
+
+        Ok(n)
+    }
+}
+
+// A staged sector, read for sealing through the manager (which decrypts it if
+// the store encrypts staged sectors) as read_stable reads it. The digest of
+// the read which is kept is set once it's read.
+pub struct DigestedStagedSector<'a> {
+    pub mgr: &'a SectorManager,
+    pub access: &'a str,
+    pub digest: &'a Cell<Option<[u8; 32]>>,
+}
+
+impl<'a> StagedSource for DigestedStagedSector<'a> {
+    fn read_staged(self, limit: u64, token: &CancellationToken) -> error::Result<Vec<u8>> {
+        read_stable(Path::new(self.access), || {
+            let mut staged = DigestingReader::new(self.mgr.open_unsealed(self.access)?);
+            let data = (&mut staged).read_staged(limit, token)?;
+            self.digest.set(Some(staged.finish()?));
+
+            Ok(data)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::api::sector_builder::errors::SectorBuilderErr;
+    use crate::api::sector_builder::metadata::PieceMetadata;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use sector_base::api::disk_backed_storage::{
+        new_sector_store, new_sector_store_with_staging_encryption, ConfiguredStore,
+    };
+    use sector_base::api::sector_store::ProofVariant;
+    use sector_base::io::fr32::write_padded;
+    use sector_base::io::staging_encryption::{StagingEncryption, StagingKey};
+    use std::fs::OpenOptions;
+    use std::io::{Cursor, Write};
+    use storage_proofs::hasher::pedersen::PedersenDomain;
+    use storage_proofs::hasher::Domain;
+
+    fn stage_pieces(
+        sector_store: &WrappedSectorStore,
+        piece_lens: &[usize],
+    ) -> StagedSectorMetadata {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let mgr = sector_store.inner.manager();
+
+        let mut staged_sector = StagedSectorMetadata {
+            sector_access: mgr.new_staging_sector_access().unwrap(),
+            ..Default::default()
+        };
+
+        for (i, len) in piece_lens.iter().enumerate() {
+            let bytes: Vec<u8> = (0..*len).map(|_| rng.gen()).collect();
+
+            mgr.write_and_preprocess(&staged_sector.sector_access, &bytes).unwrap();
+
+            staged_sector.pieces.push(PieceMetadata {
+                piece_key: format!("{}", i),
+                num_bytes: *len as u64,
+                comm_p: Some(compute_comm_p(&bytes).unwrap()),
+                expires_at: None,
+                aliases: Vec::new(),
+            });
+        }
+
+        staged_sector
+    }
+
+    fn test_store(dir: &tempfile::TempDir) -> WrappedSectorStore {
+        let path = dir.path().to_str().unwrap().to_owned();
+
+        WrappedSectorStore {
+            inner: Box::new(new_sector_store(&ConfiguredStore::Test, path.clone(), path)),
+        }
+    }
+
+    fn assert_mismatch(result: error::Result<()>) {
+        match result.map_err(|err| err.downcast::<SectorBuilderErr>()) {
+            Err(Ok(SectorBuilderErr::StagedDataMismatch { .. })) => (),
+            Err(Ok(err)) => panic!("unexpected error: {}", err),
+            Err(Err(err)) => panic!("unexpected error: {}", err),
+            Ok(()) => panic!("tampered staged data was not detected"),
+        }
+    }
+
+    #[test]
+    fn comm_p_depends_on_piece_bytes() {
+        assert_eq!(compute_comm_p(&[1; 100]).unwrap(), compute_comm_p(&[1; 100]).unwrap());
+        assert_ne!(compute_comm_p(&[1; 100]).unwrap(), compute_comm_p(&[2; 100]).unwrap());
+        assert_ne!(compute_comm_p(&[1; 100]).unwrap(), compute_comm_p(&[1; 101]).unwrap());
+    }
+
+    #[test]
+    fn comm_p_matches_padded_tree() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+        for len in &[0, 1, 31, 127, 128, 1000, COMM_P_CHUNK_BYTES + 5] {
+            let piece: Vec<u8> = (0..*len).map(|_| rng.gen()).collect();
+
+            let mut preprocessed = Cursor::new(Vec::new());
+            write_padded(&piece, &mut preprocessed).unwrap();
+
+            let mut data = preprocessed.into_inner();
+            let nodes = cmp::max(2, (data.len() + 31) / 32).next_power_of_two();
+            data.resize(nodes * 32, 0);
+
+            let leaves: Vec<PedersenDomain> = data
+                .chunks(32)
+                .map(|node| PedersenDomain::try_from_bytes(node).unwrap())
+                .collect();
+            let expected = internal::Tree::new(leaves).root().into_bytes();
+
+            assert_eq!(&expected[..], &compute_comm_p(&piece).unwrap()[..]);
+
+            // Fed in uneven pieces, as a stream would be.
+            let mut streamed = CommPBuilder::new();
+            for chunk in piece.chunks(100) {
+                streamed.update(chunk).unwrap();
+            }
+            assert_eq!(&expected[..], &streamed.finish().unwrap()[..]);
+        }
+    }
+
+    #[test]
+    fn untampered_staged_data_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir);
+        let staged_sector = stage_pieces(&store, &[100, 27, 400]);
+
+        verify_staged_data(&store, &staged_sector).unwrap();
+    }
+
+    #[test]
+    fn aligned_staged_data_verifies_against_comm_ps() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir);
+
+        // Every piece but the last fills its slot, so comm_d is assembled from
+        // the comm_ps rather than rebuilt from the staged file.
+        let staged_sector = stage_pieces(&store, &[254, 127, 5]);
+        verify_staged_data(&store, &staged_sector).unwrap();
+
+        OpenOptions::new()
+            .append(true)
+            .open(&staged_sector.sector_access)
+            .unwrap()
+            .write_all(&[7; 64])
+            .unwrap();
+
+        assert_mismatch(verify_staged_data(&store, &staged_sector));
+    }
+
+    #[test]
+    fn encrypted_staged_data_verifies() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_owned();
+        let store = WrappedSectorStore {
+            inner: Box::new(new_sector_store_with_staging_encryption(
+                &ConfiguredStore::Test,
+                path.clone(),
+                path,
+                ProofVariant::Snark,
+                StagingEncryption::XChaCha20Poly1305(StagingKey::from_bytes(1, [5; 32])),
+            )),
+        };
+        let staged_sector = stage_pieces(&store, &[100, 27, 400]);
+
+        verify_staged_data(&store, &staged_sector).unwrap();
+
+        // The staged file is checked as decrypted, so tampering with it is
+        // still detected.
+        let mut staged = std::fs::read(&staged_sector.sector_access).unwrap();
+        staged[110] ^= 1;
+        std::fs::write(&staged_sector.sector_access, &staged).unwrap();
+
+        assert!(verify_staged_data(&store, &staged_sector).is_err());
+    }
+
+    #[test]
+    fn appended_data_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir);
+        let staged_sector = stage_pieces(&store, &[100, 27]);
+
+        OpenOptions::new()
+            .append(true)
+            .open(&staged_sector.sector_access)
+            .unwrap()
+            .write_all(&[7; 64])
+            .unwrap();
+
+        assert_mismatch(verify_staged_data(&store, &staged_sector));
+    }
+
+    #[test]
+    fn modified_piece_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir);
+        let staged_sector = stage_pieces(&store, &[100, 27]);
+
+        let mut staged = std::fs::read(&staged_sector.sector_access).unwrap();
+        staged[110] ^= 1;
+        std::fs::write(&staged_sector.sector_access, &staged).unwrap();
+
+        assert_mismatch(verify_staged_data(&store, &staged_sector));
+    }
+
+    #[test]
+    fn truncated_staged_data_is_detected() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir);
+        let staged_sector = stage_pieces(&store, &[100, 27]);
+
+        let staged = std::fs::read(&staged_sector.sector_access).unwrap();
+        std::fs::write(&staged_sector.sector_access, &staged[..64]).unwrap();
+
+        assert_mismatch(verify_staged_data(&store, &staged_sector));
+    }
+
+    #[test]
+    fn pieces_without_comm_p_are_not_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir);
+        let mut staged_sector = stage_pieces(&store, &[100]);
+        staged_sector.pieces[0].comm_p = None;
+
+        std::fs::write(&staged_sector.sector_access, &[0; 10]).unwrap();
+
+        verify_staged_data(&store, &staged_sector).unwrap();
+    }
+
+    #[test]
+    fn digest_covers_bytes_left_unread() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = test_store(&dir);
+        let staged_sector = stage_pieces(&store, &[100, 200]);
+        let access = &staged_sector.sector_access;
+
+        // Seal doesn't necessarily read the staged file to its end.
+        let staged = store.inner.manager().open_unsealed(access).unwrap();
+        let mut partly_read = DigestingReader::new(staged);
+        partly_read.read_exact(&mut [0; 50]).unwrap();
+
+        let digest = staged_digest(&store, access).unwrap();
+        assert_eq!(digest, partly_read.finish().unwrap());
+
+        std::fs::write(access, &[0; 10]).unwrap();
+        assert_ne!(digest, staged_digest(&store, access).unwrap());
+    }
+}
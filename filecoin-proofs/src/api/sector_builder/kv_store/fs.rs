@@ -1,6 +1,8 @@
 use crate::api::sector_builder::kv_store::KeyValueStore;
 use crate::error::Result;
 use blake2::{Blake2b, Digest};
+use sector_base::api::errors::SectorManagerErr;
+use sector_base::api::util::rename_durably;
 use std::fs::{self, File, OpenOptions};
 use std::io::{ErrorKind, Read, Write};
 use std::path::{Path, PathBuf};
@@ -11,6 +13,7 @@ const FATAL_NOCREATE: &str = "[KeyValueStore#put] could not create path";
 // sile/ekvsb
 pub struct FileSystemKvs {
     root_dir: PathBuf,
+    read_only: bool,
 }
 
 impl FileSystemKvs {
@@ -19,9 +22,19 @@ impl FileSystemKvs {
 
         Ok(FileSystemKvs {
             root_dir: root_dir.as_ref().to_path_buf(),
+            read_only: false,
         })
     }
 
+    // Opens the store without ever writing to root_dir, which needn't exist,
+    // e.g. on a read-only file system. Every put fails with ReadOnlyStore.
+    pub fn open_read_only<P: AsRef<Path>>(root_dir: P) -> FileSystemKvs {
+        FileSystemKvs {
+            root_dir: root_dir.as_ref().to_path_buf(),
+            read_only: true,
+        }
+    }
+
     fn key_to_path(&self, key: &[u8]) -> PathBuf {
         let mut hasher = Blake2b::new();
         hasher.input(key);
@@ -34,18 +47,31 @@ impl FileSystemKvs {
 }
 
 impl KeyValueStore for FileSystemKvs {
+    // Values are replaced atomically and durably: the new value is written
+    // to a temporary file and synced, then renamed over the old value, and
+    // the rename is synced too. A crash leaves either the old or the new
+    // value, and once put returns the new value survives a crash.
     fn put(&self, key: &[u8], value: &[u8]) -> Result<()> {
+        if self.read_only {
+            return Err(SectorManagerErr::ReadOnlyStore.into());
+        }
+
         let path = self.key_to_path(key);
+        let dir = path.parent().expect(FATAL_NOCREATE);
+        let tmp_path = path.with_extension("tmp");
 
-        fs::create_dir_all(path.parent().expect(FATAL_NOCREATE))?;
+        fs::create_dir_all(dir)?;
 
         let mut file = OpenOptions::new()
             .create(true)
             .write(true)
             .truncate(true)
-            .open(path)?;
+            .open(&tmp_path)?;
 
         file.write_all(value)?;
+        file.sync_all()?;
+
+        rename_durably(&tmp_path, &path)?;
 
         Ok(())
     }
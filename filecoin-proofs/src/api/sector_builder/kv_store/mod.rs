@@ -11,6 +11,7 @@ pub trait KeyValueStore {
 mod tests {
     use crate::api::sector_builder::kv_store::fs::FileSystemKvs;
     use crate::api::sector_builder::kv_store::KeyValueStore;
+    use sector_base::api::errors::SectorManagerErr;
 
     #[test]
     fn test_alpha() {
@@ -29,4 +30,27 @@ mod tests {
         let opt = db.get(k_a).unwrap();
         assert_eq!(format!("{:x?}", opt.unwrap()), format!("{:x?}", v_a));
     }
+
+    #[test]
+    fn read_only_stores_refuse_puts() {
+        let metadata_dir = tempfile::tempdir().unwrap();
+        FileSystemKvs::initialize(&metadata_dir)
+            .unwrap()
+            .put(b"key", b"value")
+            .unwrap();
+
+        let db = FileSystemKvs::open_read_only(&metadata_dir);
+        assert_eq!(Some(b"value".to_vec()), db.get(b"key").unwrap());
+
+        match db.put(b"key", b"other").map_err(|err| err.downcast::<SectorManagerErr>()) {
+            Err(Ok(SectorManagerErr::ReadOnlyStore)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(Some(b"value".to_vec()), db.get(b"key").unwrap());
+
+        // Nothing is created for a store which doesn't exist.
+        let missing = metadata_dir.path().join("missing");
+        assert_eq!(None, FileSystemKvs::open_read_only(&missing).get(b"key").unwrap());
+        assert!(!missing.exists());
+    }
 }
@@ -1,14 +1,16 @@
-use crate::api::sector_builder::SectorId;
-use crate::error;
+use crate::api::replica_format::LegacyReplicas;
+use crate::api::sector_builder::audit_log::AuditLogStats;
+use crate::encoding::Comm;
 use crate::serde_big_array::BigArray;
-use byteorder::LittleEndian;
-use byteorder::WriteBytesExt;
+use sector_base::api::disk_backed_storage::new_sector_config_of_size;
+use sector_base::api::sector_store::SectorConfig;
 use serde::{Deserialize, Serialize};
 use std::fmt;
+use storage_proofs::types::Commitment;
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
 pub struct StagedSectorMetadata {
-    pub sector_id: SectorId,
+    pub sector_id: u64,
     pub sector_access: String,
     pub pieces: Vec<PieceMetadata>,
     pub seal_status: SealStatus,
@@ -16,21 +18,271 @@ pub struct StagedSectorMetadata {
 
 #[derive(Clone, Serialize, Deserialize)]
 pub struct SealedSectorMetadata {
-    pub sector_id: SectorId,
+    pub sector_id: u64,
     pub sector_access: String,
     pub pieces: Vec<PieceMetadata>,
-    pub comm_r_star: [u8; 32],
-    pub comm_r: [u8; 32],
-    pub comm_d: [u8; 32],
+    pub comm_r_star: Commitment,
+    pub comm_r: Commitment,
+    pub comm_d: Commitment,
 
     #[serde(with = "BigArray")]
     pub snark_proof: [u8; 384],
+
+    // The size of the sector, if it differs from the size of the builder's
+    // sectors, as it does for sectors migrated into a larger size.
+    #[serde(default)]
+    pub sector_bytes: Option<u64>,
+
+    // Set once the sector's pieces have been migrated into another sector,
+    // whose id it holds. A retired sector is kept, but is no longer proven,
+    // and its pieces are read from the sector they were migrated into.
+    #[serde(default)]
+    pub retired_into: Option<u64>,
+
+    // The version of the format trailer which ends the sector's replica, or
+    // None for sectors sealed before replicas had one.
+    #[serde(default)]
+    pub replica_format: Option<u16>,
+
+    // The staged sector the sector was sealed from, which is kept, so that a
+    // request to seal it again can be checked against what was sealed. None
+    // for sectors sealed before it was recorded, and for those imported or
+    // migrated into, which weren't sealed from a staged sector of their own.
+    #[serde(default)]
+    pub sealed_from: Option<SealedFrom>,
+
+    // The tag of the envelope snark_proof was handed out in (see seal_proof),
+    // which names the version of the parameters it was made with. None for
+    // proofs made before it was recorded, with the builder's version.
+    #[serde(default)]
+    pub proof_tag: Option<u8>,
+
+    // The proofs snark_proof has replaced, as the sector was proven again by
+    // regenerate_proof, oldest first.
+    #[serde(default)]
+    pub replaced_proofs: Vec<ReplacedProof>,
+
+    // The directory of the prover bundle holding the taus and trees the
+    // sector's replication built, if they were kept, from which the sector is
+    // proven again without rebuilding them.
+    #[serde(default)]
+    pub prover_bundle: Option<String>,
 }
 
+// A proof of a sealed sector which has since been proven again, and the tag of
+// the envelope it was handed out in, if it was recorded.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplacedProof {
+    #[serde(with = "BigArray")]
+    pub snark_proof: [u8; 384],
+    pub proof_tag: Option<u8>,
+}
+
+// The staged sector a sector was sealed from, and the digest of its staged
+// data as it was sealed (see staged_data::staged_digest).
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct SealedFrom {
+    pub sector_access: String,
+    pub digest: [u8; 32],
+}
+
+// A sector sealed by seal_sector, and whether it had been sealed before it was
+// asked to be, in which case its metadata was looked up rather than made.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SealOutcome {
+    pub sector: SealedSectorMetadata,
+    pub cached: bool,
+}
+
+#[derive(Clone, Serialize, Deserialize, PartialEq)]
 pub struct PieceMetadata {
     pub piece_key: String,
     pub num_bytes: u64,
+
+    // The piece's commitment, recorded when it was staged. Pieces staged
+    // before commitments were recorded have none.
+    #[serde(default)]
+    pub comm_p: Option<Commitment>,
+
+    // When the deal for the piece ends, as an epoch or timestamp of the
+    // caller's choosing, if the caller gave one when adding it. The piece has
+    // expired once that's reached.
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+
+    // The keys of the pieces added since with the same bytes, which the
+    // builder recorded as aliases of this one rather than store them again
+    // (see DuplicatePiecePolicy).
+    #[serde(default)]
+    pub aliases: Vec<PieceAlias>,
+}
+
+impl PieceMetadata {
+    // Whether the piece has expired by now. Pieces without an expiry never do.
+    pub fn has_expired(&self, now: u64) -> bool {
+        self.expires_at.map_or(false, |expires_at| expires_at <= now)
+    }
+
+    // Whether the piece's bytes are no longer needed by now: once the piece
+    // and every alias of it has expired.
+    pub fn bytes_expired(&self, now: u64) -> bool {
+        self.has_expired(now) && self.aliases.iter().all(|alias| alias.has_expired(now))
+    }
+
+    // Whether the piece is read under the given key: its own, or an alias's.
+    pub fn answers_to(&self, piece_key: &str) -> bool {
+        self.piece_key == piece_key || self.aliases.iter().any(|a| a.piece_key == piece_key)
+    }
+}
+
+// A piece added with the same bytes as a piece placed before it, which is read
+// from that piece's bytes, and when its own deal expires, if it does.
+#[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
+pub struct PieceAlias {
+    pub piece_key: String,
+
+    #[serde(default)]
+    pub expires_at: Option<u64>,
+}
+
+impl PieceAlias {
+    pub fn has_expired(&self, now: u64) -> bool {
+        self.expires_at.map_or(false, |expires_at| expires_at <= now)
+    }
+}
+
+// What add_piece does with a piece which has the same bytes, as told by its
+// comm_p and length, as a piece placed before it.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DuplicatePiecePolicy {
+    // The piece is stored again, and reported as a duplicate.
+    Allow = 0,
+
+    // The piece is refused with a DuplicatePiece error.
+    Reject = 1,
+
+    // The piece isn't stored again, but recorded as an alias of the piece
+    // placed before it, whose bytes it's read from. The bytes are kept until
+    // the piece and all its aliases have expired.
+    Deduplicate = 2,
+}
+
+// Where a piece is placed: the key it was added under, and the sector holding
+// its bytes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PiecePlacement {
+    pub piece_key: String,
+    pub sector_id: u64,
+}
+
+// A piece added by add_piece: the sector holding its bytes and, if it has the
+// same bytes as a piece placed before it, where that piece is placed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddedPiece {
+    pub sector_id: u64,
+    pub duplicate_of: Option<PiecePlacement>,
+}
+
+// A piece due to expire, and the sector holding it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ExpiringPiece {
+    pub piece_key: String,
+    pub sector_id: u64,
+    pub expires_at: u64,
+}
+
+// The outcome of migrating sealed sectors into a single sector of another
+// size: the new sector's id, and what was checked of each piece moved into it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SectorMigration {
+    pub sector_id: u64,
+    pub pieces: Vec<MigratedPiece>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MigratedPiece {
+    pub piece_key: String,
+    pub source_sector_id: u64,
+    pub status: MigratedPieceStatus,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MigratedPieceStatus {
+    // The piece's comm_p, recomputed as it was staged again, matched the one
+    // recorded when it was first staged.
+    Verified,
+
+    // The piece was staged before commitments were recorded, so there was
+    // nothing to check its recomputed comm_p against. It's recorded now.
+    Unverified,
+}
+
+// What regenerate_all_proofs did with a sealed sector.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProofRegeneration {
+    pub sector_id: u64,
+    pub status: ProofRegenerationStatus,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProofRegenerationStatus {
+    // The sector was proven again, and its new proof recorded.
+    Regenerated,
+
+    // Proving the sector again failed, for the reason given, and its proof
+    // was left as it was.
+    Failed(String),
+}
+
+// Where pieces are to be staged, as planned by plan_piece_placement, and the
+// padding the sectors will be left with. Assignments are in the order in which
+// the pieces were given.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlacementPlan {
+    pub assignments: Vec<PieceAssignment>,
+    pub new_sectors: usize,
+    pub wasted_bytes: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PieceAssignment {
+    pub piece_key: String,
+    pub num_bytes: u64,
+    pub sector: PlannedSector,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlannedSector {
+    // A staged sector, accepting data when the plan was made.
+    Staged(u64),
+
+    // The nth of the sectors the plan provisions, counted from 0. Its id is
+    // allocated once the plan is carried out.
+    New(usize),
+}
+
+// A piece to be staged as planned: its bytes, and when its deal expires, if
+// it does.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PlannedPiece {
+    pub piece_key: String,
+    pub bytes: Vec<u8>,
+    pub expires_at: Option<u64>,
+}
+
+// What became of a builder's seals as it shut down. The seals running when it
+// began to are either drained, running to their end (whether or not they
+// succeed), or cancelled once the drain timeout has passed, their sectors
+// marked Failed so that they may be sealed again. Those queued are persisted
+// for the next builder over the same metadata, which queues them again.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ShutdownReport {
+    pub drained: Vec<u64>,
+    pub cancelled: Vec<u64>,
+    pub persisted: Vec<u64>,
+    // How the builder's audit log fared, once flushed.
+    pub audit_log: AuditLogStats,
 }
 
 #[derive(Clone, Serialize, Deserialize, Debug, PartialEq)]
@@ -43,6 +295,8 @@ pub enum SealStatus {
 
 impl PartialEq for SealedSectorMetadata {
     fn eq(&self, other: &SealedSectorMetadata) -> bool {
+        // variable-time: metadata is compared with what the builder recorded,
+        // not with anything claimed to it.
         self.sector_id == other.sector_id
             && self.sector_access == other.sector_access
             && self.pieces == other.pieces
@@ -50,6 +304,19 @@ impl PartialEq for SealedSectorMetadata {
             && self.comm_r == other.comm_r
             && self.comm_d == other.comm_d
             && self.snark_proof.iter().eq(other.snark_proof.iter())
+            && self.sector_bytes == other.sector_bytes
+            && self.retired_into == other.retired_into
+            && self.replica_format == other.replica_format
+            && self.sealed_from == other.sealed_from
+            && self.proof_tag == other.proof_tag
+            && self.replaced_proofs == other.replaced_proofs
+            && self.prover_bundle == other.prover_bundle
+    }
+}
+
+impl PartialEq for ReplacedProof {
+    fn eq(&self, other: &ReplacedProof) -> bool {
+        self.snark_proof.iter().eq(other.snark_proof.iter()) && self.proof_tag == other.proof_tag
     }
 }
 
@@ -64,9 +331,29 @@ impl Default for StagedSectorMetadata {
     }
 }
 
+impl fmt::Debug for PieceMetadata {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "PieceMetadata {{ piece_key: {:?}, num_bytes: {}, comm_p: {:?}, expires_at: {:?}, aliases: {:?} }}",
+            self.piece_key,
+            self.num_bytes,
+            self.comm_p.map(Comm),
+            self.expires_at,
+            self.aliases
+        )
+    }
+}
+
 impl fmt::Debug for SealedSectorMetadata {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "SealedSectorMetadata {{ sector_id: {}, sector_access: {}, pieces: {:?}, comm_r_star: {:?}, comm_r: {:?}, comm_d: {:?} }}", self.sector_id, self.sector_access, self.pieces, self.comm_r_star, self.comm_r, self.comm_d)
+        write!(f, "SealedSectorMetadata {{ sector_id: {}, sector_access: {}, pieces: {:?}, comm_r_star: {}, comm_r: {}, comm_d: {}, sector_bytes: {:?}, retired_into: {:?}, replica_format: {:?}, sealed_from: {:?}, proof_tag: {:?}, replaced_proofs: {:?}, prover_bundle: {:?} }}", self.sector_id, self.sector_access, self.pieces, Comm(self.comm_r_star), Comm(self.comm_r), Comm(self.comm_d), self.sector_bytes, self.retired_into, self.replica_format, self.sealed_from, self.proof_tag, self.replaced_proofs, self.prover_bundle)
+    }
+}
+
+impl fmt::Debug for ReplacedProof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ReplacedProof {{ proof_tag: {:?} }}", self.proof_tag)
     }
 }
 
@@ -80,20 +367,49 @@ impl Default for SealedSectorMetadata {
             comm_r: Default::default(),
             comm_d: Default::default(),
             snark_proof: [0; 384],
+            sector_bytes: None,
+            retired_into: None,
+            replica_format: None,
+            sealed_from: None,
+            proof_tag: None,
+            replaced_proofs: Vec::new(),
+            prover_bundle: None,
         }
     }
 }
 
-pub fn sum_piece_bytes(s: &StagedSectorMetadata) -> u64 {
-    s.pieces.iter().map(|x| x.num_bytes).sum()
-}
+impl SealedSectorMetadata {
+    // Returns the config of the sector, given the config of the builder's
+    // sectors.
+    pub fn config(&self, builder_config: &SectorConfig) -> Box<SectorConfig> {
+        new_sector_config_of_size(
+            self.sector_bytes.unwrap_or_else(|| builder_config.sector_bytes()),
+            builder_config.proof_variant(),
+        )
+    }
+
+    // Records a new proof of the sector, made in an envelope with the given
+    // tag, keeping the proof it replaces in the sector's history.
+    pub fn replace_proof(&mut self, snark_proof: [u8; 384], proof_tag: u8) {
+        self.replaced_proofs.push(ReplacedProof {
+            snark_proof: self.snark_proof,
+            proof_tag: self.proof_tag,
+        });
+
+        self.snark_proof = snark_proof;
+        self.proof_tag = Some(proof_tag);
+    }
 
-pub fn sector_id_as_bytes(sector_id: SectorId) -> error::Result<[u8; 31]> {
-    // Transmute a u64 sector id to a zero-padded byte array.
-    let mut sector_id_as_bytes = [0u8; 31];
-    sector_id_as_bytes
-        .as_mut()
-        .write_u64::<LittleEndian>(sector_id)?;
+    // Whether the sector's replica may lack a format trailer, as it does if
+    // the sector was sealed before they were introduced.
+    pub fn legacy_replicas(&self) -> LegacyReplicas {
+        match self.replica_format {
+            Some(_) => LegacyReplicas::Refuse,
+            None => LegacyReplicas::Accept,
+        }
+    }
+}
 
-    Ok(sector_id_as_bytes)
+pub fn sum_piece_bytes(s: &StagedSectorMetadata) -> u64 {
+    s.pieces.iter().map(|x| x.num_bytes).sum()
 }
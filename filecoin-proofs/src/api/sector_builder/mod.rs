@@ -1,102 +1,414 @@
+use crate::api::cancellation::CancellationToken;
+use crate::api::estimate::seal_working_memory_bytes;
 use crate::api::internal::PoStOutput;
+use crate::api::io_priority::{IoPriority, RetrievalGauge, SealThrottle};
+use crate::api::parallelism;
+use crate::api::proof_params::proof_params;
+use crate::api::prover_id::ProverId;
+use crate::api::sector_builder::admission::{SealAdmissions, SealQueue, SealScheduling};
+use crate::api::sector_builder::audit_log::{audit_log_path, AuditLog, AuditLogStats};
 use crate::api::sector_builder::errors::SectorBuilderErr;
+use crate::api::sector_builder::helpers::metadata_txn::MetadataJournal;
+use crate::api::sector_builder::helpers::pending_seals::PendingSeals;
+use crate::api::sector_builder::helpers::piece_intents::PieceIntents;
+use crate::api::sector_builder::helpers::piece_keys::PieceKeys;
+use crate::api::sector_builder::helpers::regenerate_proof::ProofRegenerations;
+use crate::api::sector_builder::helpers::sector_ids::SectorIdAllocator;
+use crate::api::sector_builder::helpers::snapshots::load_snapshot;
 use crate::api::sector_builder::kv_store::fs::FileSystemKvs;
 use crate::api::sector_builder::kv_store::KeyValueStore;
 use crate::api::sector_builder::metadata::*;
+use crate::api::sector_builder::retriever::*;
+use crate::api::sector_builder::scheduler::PieceSource;
 use crate::api::sector_builder::scheduler::Request;
 use crate::api::sector_builder::scheduler::Scheduler;
+use crate::api::sector_builder::scheduler::SectorDirs;
 use crate::api::sector_builder::sealer::*;
+use crate::api::sector_builder::state::SectorBuilderState;
+use crate::api::task;
 use crate::error::ExpectWithBacktrace;
 use crate::error::Result;
 use crate::FCP_LOG;
-use sector_base::api::disk_backed_storage::new_sector_store;
+use sector_base::api::disk_backed_storage::check_sector_dirs;
+use sector_base::api::disk_backed_storage::new_readonly_sector_store;
+use sector_base::api::disk_backed_storage::new_sector_store_with_staging_encryption;
 use sector_base::api::disk_backed_storage::ConfiguredStore;
-use sector_base::api::sector_store::SectorStore;
+use sector_base::api::sector_store::{ProofVariant, ProofVersion, SectorStore};
+use sector_base::io::staging_encryption::StagingEncryption;
 use slog::*;
+use std::any::Any;
+use std::fs;
+use std::io::Read;
+use std::path::PathBuf;
 use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
 
+pub mod admission;
+pub mod audit_log;
 pub mod errors;
-mod helpers;
+pub(crate) mod helpers;
 mod kv_store;
 pub mod metadata;
+mod retriever;
 mod scheduler;
 mod sealer;
+pub(crate) mod snapshot_format;
 mod state;
+mod work_queue;
 
-const NUM_SEAL_WORKERS: usize = 2;
+pub use crate::api::sector_builder::helpers::piece_keys::PieceKeyMaterial;
+pub use crate::api::sector_builder::helpers::sector_ids::SectorIdRange;
 
 const FATAL_NOSEND_TASK: &str = "[run_blocking] could not send";
 const FATAL_NORECV_TASK: &str = "[run_blocking] could not recv";
 
-pub type SectorId = u64;
-
+// Any number of SectorBuilders may coexist in one process, e.g. one per miner.
+// Everything a SectorBuilder reads or writes is owned by it: its metadata
+// (kept in its own metadata directory, keyed by prover id), its sector id
+// nonce, its sector store (whose sector accesses are created under its own
+// staged and sealed directories) and its worker threads. Two builders share
+// nothing as long as they are given disjoint directories.
+//
+// The process-global state they do share is intentionally shared, as none of
+// it is specific to a builder:
+//
+// * The groth parameter cache, on disk. Entries are keyed by a digest of the
+//   circuit they were generated for and each is locked while being read or
+//   written, so builders sealing sectors of the same size read (or generate)
+//   the same entry.
+// * Parameters derived only from constants: ENGINE_PARAMS, the published
+//   groth parameters loaded by internal.rs, the layer challenges and the PoSt
+//   VDF key.
+// * The seal verifiers cached by internal::verify_seal, each built only from
+//   constants and the size of the sectors it verifies.
+// * The FCP_LOG and SP_LOG loggers.
+// * The sector store registry in sector_base, used by stores created over
+//   FFI rather than by SectorBuilders. Its handles are never reused, so no
+//   two stores observe each other's entries.
 pub struct SectorBuilder {
     // Prevents FFI consumers from queueing behind long-running seal operations.
     sealers_tx: mpsc::Sender<SealerInput>,
 
-    // For additional seal concurrency, add more workers here.
+    // As many as the task pool has threads, which by default is no more than
+    // the effective parallelism. For additional seal concurrency, raise that
+    // with task::set_pool_size.
     sealers: Vec<SealerWorker>,
 
+    // Retrievals are queued apart from seals, so never wait behind them.
+    retrievers_tx: mpsc::Sender<RetrieverInput>,
+
+    retrievers: Vec<RetrieverWorker>,
+
     // The main worker's queue.
     scheduler_tx: mpsc::SyncSender<Request>,
 
     // The main worker. Owns all mutable state for the SectorBuilder.
     scheduler: Scheduler,
+
+    // Records what the builder did, for settling disputes, from a writer
+    // thread of its own. Read-only builders record nothing, so have none.
+    audit_log: AuditLog,
+    audit_writer: Option<thread::JoinHandle<()>>,
+
+    // Translates the keys pieces are added, read and listed by to those
+    // they're recorded under, which are all the scheduler sees.
+    piece_keys: PieceKeys,
 }
 
 impl SectorBuilder {
     // Initialize and return a SectorBuilder from metadata persisted to disk if
     // it exists. Otherwise, initialize and return a fresh SectorBuilder. The
-    // metadata key is equal to the prover_id.
+    // metadata key is equal to the prover_id. Sector ids are allocated from
+    // sector_id_range, continuing after any id allocated before; metadata
+    // which records ids beyond the end of the range is refused. If
+    // landing_sector_dir is given, sectors are sealed there (e.g. on fast
+    // scratch storage) and then moved to sealed_sector_dir. If
+    // verify_staged_data is set, each sector's staged data is checked against
+    // its pieces before it is sealed, which costs an extra pass over the
+    // sector. If max_seal_memory_bytes is given, sealing a sector fails with a
+    // MemoryLimitExceeded error before taking more memory than that.
+    //
+    // The pieces staged, the sectors sealed and the proofs-of-spacetime
+    // generated are recorded in an audit log (see audit_log) in metadata_dir.
+    //
+    // Pieces are staged crash-consistently (see piece_intents): a piece whose
+    // staging was cut short by a crash is found when the builder next starts,
+    // and is either committed or dropped from its staged sector. Changes to
+    // the records of several sectors at once, e.g. a migration and the
+    // retirement of its sources, are committed as transactions (see
+    // metadata_txn): one cut short by a crash is replayed when the builder
+    // next starts, so that either all of its changes are recorded or none is.
+    //
+    // The seals a builder had queued, but not started, when it was shut down
+    // (see shutdown) are queued again, before anything else is done.
+    //
+    // Pieces are retrieved by io_priority.retrieval_workers workers of their
+    // own, which seals never take. While any retrieval is in flight, sealers
+    // pause for io_priority.seal_write_pause after writing each
+    // io_priority.seal_write_chunk_bytes of a sector, yielding the disk to it.
+    //
+    // Seals start as seal_scheduling admits them (see admission): no more at
+    // once than there are sealers, within its memory budget, each estimated
+    // to take the working memory of a seal of the builder's sectors (see
+    // estimate::seal_working_memory_bytes), and its stagger apart.
+    //
+    // The first builder sizes the global rayon pool, on which sectors are
+    // replicated and their trees built, by the effective parallelism (see
+    // parallelism), so that a process limited to a few of the machine's CPUs
+    // doesn't run a thread per CPU.
+    //
+    // Staged sectors are kept on disk as staging_encryption says, e.g.
+    // encrypted with a key given by the caller, which is never persisted. The
+    // builder must be given the same key as the builders which staged its
+    // sectors before it, or they can't be sealed.
+    //
+    // A piece added with the same bytes as a piece placed before it, as told
+    // by their comm_ps and lengths, is dealt with as duplicate_pieces says:
+    // stored again all the same, refused, or recorded as an alias of the piece
+    // placed before it (see DuplicatePiecePolicy).
+    //
+    // If piece_key_material is given, pieces are recorded in the builder's
+    // metadata, and in its audit log, under pseudonyms of their keys rather
+    // than the keys themselves (see piece_keys). They're still added and read
+    // by key. The material is never persisted, but the metadata is marked as
+    // private: a builder over it given other material is refused, and one given
+    // none serves its pieces by pseudonym, but adds none. Metadata holding
+    // pieces added under their keys can't be made private.
+    //
+    // If read_only is set, the builder only serves and verifies the sectors
+    // already sealed in its directories, which may be mounted read-only: its
+    // metadata is loaded but never written, nothing is created in its
+    // directories, and every call which would write (adding pieces, sealing,
+    // importing and migrating sectors) fails with a ReadOnlyStore error.
+    #[allow(clippy::too_many_arguments)]
     pub fn init_from_metadata<S: Into<String>>(
         sector_store_config: &ConfiguredStore,
-        last_committed_sector_id: SectorId,
+        sector_id_range: SectorIdRange,
         metadata_dir: S,
-        prover_id: [u8; 31],
+        prover_id: ProverId,
         sealed_sector_dir: S,
+        landing_sector_dir: Option<S>,
         staged_sector_dir: S,
         max_num_staged_sectors: u8,
+        verify_staged_data: bool,
+        max_seal_memory_bytes: Option<u64>,
+        io_priority: IoPriority,
+        seal_scheduling: SealScheduling,
+        staging_encryption: StagingEncryption,
+        duplicate_pieces: DuplicatePiecePolicy,
+        piece_key_material: Option<PieceKeyMaterial>,
+        read_only: bool,
     ) -> Result<SectorBuilder> {
+        let ProverId(prover_id) = prover_id;
+        let metadata_dir: String = metadata_dir.into();
+
+        parallelism::size_global_pool();
+
+        let sector_dirs = SectorDirs {
+            sealed: sealed_sector_dir.into(),
+            staged: staged_sector_dir.into(),
+        };
+
+        // Staged and sealed sectors are told apart by the directory they're
+        // in, so the directories mustn't overlap.
+        check_sector_dirs(&sector_dirs.staged, &sector_dirs.sealed)?;
+
         let kv_store = Arc::new(WrappedKeyValueStore {
-            inner: Box::new(FileSystemKvs::initialize(metadata_dir.into())?),
+            inner: Box::new(if read_only {
+                FileSystemKvs::open_read_only(metadata_dir.clone())
+            } else {
+                FileSystemKvs::initialize(metadata_dir.clone())?
+            }),
         });
 
+        // Reconstitute the builder's state from persisted metadata, if there
+        // is any. If not, create it from scratch.
+        let mut state = load_snapshot(&kv_store, &prover_id)?
+            .map(|snapshot| snapshot.into())
+            .unwrap_or_else(|| SectorBuilderState {
+                prover_id,
+                staged: Default::default(),
+                sealed: Default::default(),
+            });
+
+        // Finish committing the transaction a crash interrupted, if any,
+        // before anything else reads the state. Read-only builders see its
+        // changes, but leave it to be replayed by a builder which may write.
+        let journal = MetadataJournal::new(kv_store.clone(), &prover_id);
+        if let Some(recovery) = journal.recover(&mut state, read_only)? {
+            info!(FCP_LOG, "recovered metadata journal"; "recovery" => format!("{:?}", recovery));
+        }
+
+        let sector_ids = if read_only {
+            SectorIdAllocator::load_read_only(kv_store.clone(), &state, sector_id_range)?
+        } else {
+            SectorIdAllocator::load(kv_store.clone(), &state, sector_id_range)?
+        };
+
         // Initialize a SectorStore and wrap it in an Arc so we can access it
-        // from multiple threads. Our implementation assumes that the
-        // SectorStore is safe for concurrent access.
+        // from multiple threads. SectorStore implementations are required to
+        // be safe for concurrent access (Send + Sync).
         let sector_store = Arc::new(WrappedSectorStore {
-            inner: Box::new(new_sector_store(
-                sector_store_config,
-                sealed_sector_dir.into(),
-                staged_sector_dir.into(),
-            )),
+            inner: if read_only {
+                Box::new(new_readonly_sector_store(
+                    sector_store_config,
+                    sector_dirs.sealed.clone(),
+                    sector_dirs.staged.clone(),
+                ))
+            } else {
+                Box::new(new_sector_store_with_staging_encryption(
+                    sector_store_config,
+                    sector_dirs.sealed.clone(),
+                    sector_dirs.staged.clone(),
+                    ProofVariant::Snark,
+                    staging_encryption,
+                ))
+            },
         });
 
+        // Finish staging the piece a crash interrupted, if any, before the
+        // builder stages any more. Read-only builders never stage pieces, so
+        // never leave any unfinished.
+        let piece_intents = PieceIntents::new(kv_store.clone(), &prover_id);
+        let resolution = if read_only {
+            None
+        } else {
+            piece_intents.resolve(sector_store.inner.manager(), &mut state)?
+        };
+        if let Some(resolution) = resolution {
+            info!(FCP_LOG, "resolved piece intent"; "resolution" => format!("{:?}", resolution));
+        }
+
+        let piece_keys = PieceKeys::open(&kv_store, &state, piece_key_material, read_only)?;
+        info!(FCP_LOG, "opened piece keys"; "privacy" => format!("{:?}", piece_keys.privacy()));
+
+        // Take up the seals the last builder left queued when it shut down,
+        // and the proofs it left being regenerated. Read-only builders never
+        // seal or prove, so leave them to one which does.
+        let (pending_seals, restored_seals, proof_regenerations) = if read_only {
+            (None, Vec::new(), None)
+        } else {
+            let mut pending_seals = PendingSeals::open(&metadata_dir)?;
+            let restoration = pending_seals.restore(&state)?;
+            if let Some(ref restoration) = restoration {
+                info!(FCP_LOG, "restored pending seals"; "restoration" => format!("{:?}", restoration));
+            }
+            let restored_seals = restoration.map_or_else(Vec::new, |r| r.sector_ids());
+
+            let proof_regenerations = ProofRegenerations::open(&metadata_dir)?;
+
+            (Some(pending_seals), restored_seals, Some(proof_regenerations))
+        };
+
+        // Read-only builders never seal, so never land sectors.
+        let landing_sector_dir = match landing_sector_dir {
+            Some(_) if read_only => None,
+            Some(dir) => {
+                let dir = PathBuf::from(dir.into());
+                fs::create_dir_all(&dir)?;
+                Some(dir)
+            }
+            None => None,
+        };
+
+        // Read-only builders read their audit log, but never write it.
+        let (audit_log, audit_writer) = if read_only {
+            (AuditLog::read_only(audit_log_path(&metadata_dir)), None)
+        } else {
+            let (log, writer) = AuditLog::start(audit_log_path(&metadata_dir));
+            (log, Some(writer))
+        };
+
         // Configure the main worker's rendezvous channel.
         let (main_tx, main_rx) = mpsc::sync_channel(0);
 
+        // Sealers watch the retrievals in flight, and yield the disk to them.
+        let retrievals = RetrievalGauge::new();
+        let throttle = SealThrottle::new(retrievals.clone(), &io_priority);
+
         // Configure seal queue workers and channels.
+        let shutdown_token = CancellationToken::new();
+        let sealers = task::pool_size();
         let (seal_tx, seal_workers) = {
             let (tx, rx) = mpsc::channel();
             let rx = Arc::new(Mutex::new(rx));
 
-            let workers = (0..NUM_SEAL_WORKERS)
-                .map(|n| SealerWorker::start(n, rx.clone(), sector_store.clone(), prover_id))
+            let workers = (0..sealers)
+                .map(|n| {
+                    SealerWorker::start(
+                        n,
+                        rx.clone(),
+                        sector_store.clone(),
+                        prover_id,
+                        verify_staged_data,
+                        landing_sector_dir.clone(),
+                        max_seal_memory_bytes,
+                        throttle.clone(),
+                        shutdown_token.clone(),
+                    )
+                })
+                .collect();
+
+            (tx, workers)
+        };
+
+        // Configure retrieval queue workers and channels.
+        let (retrieval_tx, retrieval_workers) = {
+            let (tx, rx) = mpsc::channel();
+            let rx = Arc::new(Mutex::new(rx));
+
+            let workers = (0..io_priority.retrieval_workers.max(1))
+                .map(|n| {
+                    RetrieverWorker::start(
+                        n,
+                        rx.clone(),
+                        sector_store.clone(),
+                        prover_id,
+                        retrievals.clone(),
+                    )
+                })
                 .collect();
 
             (tx, workers)
         };
 
+        // Seals wait in the main worker's queue until they're admitted to the
+        // sealers.
+        let seal_queue = {
+            let config = sector_store.inner.config();
+            let layers = proof_params(config.proof_version()).layers;
+
+            SealQueue::new(
+                seal_scheduling,
+                sealers,
+                seal_working_memory_bytes(config.sector_bytes(), layers),
+            )
+        };
+
         // Configure main worker.
         let main_worker = Scheduler::start_with_metadata(
             main_rx,
             main_tx.clone(),
             seal_tx.clone(),
+            retrieval_tx.clone(),
+            audit_log.clone(),
             kv_store.clone(),
             sector_store.clone(),
-            last_committed_sector_id,
+            state,
+            sector_ids,
+            piece_intents,
+            journal,
+            pending_seals,
+            restored_seals,
+            proof_regenerations,
+            shutdown_token,
             max_num_staged_sectors,
-            prover_id,
+            sector_dirs,
+            max_seal_memory_bytes,
+            seal_queue,
+            duplicate_pieces,
+            read_only,
         );
 
         Ok(SectorBuilder {
@@ -104,6 +416,11 @@ impl SectorBuilder {
             scheduler: main_worker,
             sealers_tx: seal_tx,
             sealers: seal_workers,
+            retrievers_tx: retrieval_tx,
+            retrievers: retrieval_workers,
+            audit_log,
+            audit_writer,
+            piece_keys,
         })
     }
 
@@ -114,48 +431,322 @@ impl SectorBuilder {
     }
 
     // Stages user piece-bytes for sealing. Note that add_piece calls are
-    // processed sequentially to make bin packing easier.
-    pub fn add_piece(&self, piece_key: String, piece_bytes: &[u8]) -> Result<SectorId> {
-        log_unrecov(self.run_blocking(|tx| Request::AddPiece(piece_key, piece_bytes.to_vec(), tx)))
+    // processed sequentially to make bin packing easier. If given, expires_at
+    // is when the deal storing the piece expires. The piece added says whether
+    // it has the same bytes as a piece placed before it, which, if the builder
+    // deduplicates pieces, holds its bytes.
+    pub fn add_piece(
+        &self,
+        piece_key: String,
+        piece_bytes: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<AddedPiece> {
+        let piece_key = self.piece_keys.stored_key(piece_key)?;
+
+        let added = log_unrecov(self.run_blocking(|tx| {
+            Request::AddPiece(piece_key, piece_bytes.to_vec(), expires_at, tx)
+        }))?;
+
+        Ok(self.listed_added_piece(added))
+    }
+
+    // Stages piece-bytes read from source for sealing, without holding the
+    // whole piece in memory. The source must produce exactly piece_len bytes;
+    // if it produces more or fewer, or fails, nothing is staged. The source is
+    // read from another thread, and other calls to this SectorBuilder wait
+    // until it has been read to its end.
+    pub fn add_piece_from_reader<R: Read + Send + 'static>(
+        &self,
+        piece_key: String,
+        piece_len: u64,
+        expires_at: Option<u64>,
+        source: R,
+    ) -> Result<AddedPiece> {
+        let piece_key = self.piece_keys.stored_key(piece_key)?;
+        let source = PieceSource(Box::new(source));
+
+        let added = log_unrecov(self.run_blocking(|tx| {
+            Request::AddPieceFromReader(piece_key, piece_len, expires_at, source, tx)
+        }))?;
+
+        Ok(self.listed_added_piece(added))
+    }
+
+    // Plans where to stage the given pieces, as (key, length) pairs, packing
+    // them into as few sectors as possible. Nothing is staged until the plan
+    // is passed to add_pieces_planned. Planning the same pieces against the
+    // same staged sectors always yields the same plan.
+    pub fn plan_piece_placement(&self, pending_pieces: &[(String, u64)]) -> Result<PlacementPlan> {
+        let pending_pieces = pending_pieces
+            .iter()
+            .map(|(piece_key, num_bytes)| {
+                let piece_key = self.piece_keys.stored_key(piece_key.clone())?;
+                Ok((piece_key, *num_bytes))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut plan =
+            log_unrecov(self.run_blocking(|tx| Request::PlanPiecePlacement(pending_pieces, tx)))?;
+
+        for assignment in &mut plan.assignments {
+            self.list_key(&mut assignment.piece_key, true);
+        }
+
+        Ok(plan)
+    }
+
+    // Stages the pieces a plan was made for, given in the same order, where it
+    // placed them, returning the id of each piece's sector. Either every piece
+    // is staged, or, if any can't be, none is. Fails if the pieces don't match
+    // the plan, or if a staged sector no longer has room for the pieces placed
+    // in it, e.g. as other pieces were added since the plan was made.
+    pub fn add_pieces_planned(
+        &self,
+        plan: &PlacementPlan,
+        mut pieces: Vec<PlannedPiece>,
+    ) -> Result<Vec<u64>> {
+        let mut plan = plan.clone();
+
+        for assignment in &mut plan.assignments {
+            assignment.piece_key = self.piece_keys.stored_key(assignment.piece_key.clone())?;
+        }
+        for piece in &mut pieces {
+            piece.piece_key = self.piece_keys.stored_key(piece.piece_key.clone())?;
+        }
+
+        log_unrecov(self.run_blocking(|tx| Request::AddPiecesPlanned(plan, pieces, tx)))
     }
 
     // Returns sealing status for the sector with specified id. If no sealed or
     // staged sector exists with the provided id, produce an error.
-    pub fn get_seal_status(&self, sector_id: SectorId) -> Result<SealStatus> {
-        log_unrecov(self.run_blocking(|tx| Request::GetSealStatus(sector_id, tx)))
+    pub fn get_seal_status(&self, sector_id: u64) -> Result<SealStatus> {
+        let mut status =
+            log_unrecov(self.run_blocking(|tx| Request::GetSealStatus(sector_id, tx)))?;
+
+        if let SealStatus::Sealed(ref mut sector) = status {
+            self.list_piece_keys(&mut sector.pieces, true);
+        }
+
+        Ok(status)
+    }
+
+    // Returns the ids, in order, of the sealed sectors all of whose pieces
+    // have expired by now. Those sectors may be left out of proofs of
+    // spacetime.
+    pub fn get_expired_sectors(&self, now: u64) -> Vec<u64> {
+        self.run_blocking(|tx| Request::GetExpiredSectors(now, tx))
+    }
+
+    // Returns the staged and sealed pieces which expire before the given
+    // horizon, ordered by expiry, so that callers may plan their eviction.
+    // Pieces are listed by the keys they're recorded under, unless real_keys
+    // is set (see list_key).
+    pub fn get_expiring_pieces(&self, before: u64, real_keys: bool) -> Vec<ExpiringPiece> {
+        let mut pieces = self.run_blocking(|tx| Request::GetExpiringPieces(before, tx));
+
+        for piece in &mut pieces {
+            self.list_key(&mut piece.piece_key, real_keys);
+        }
+
+        pieces
     }
 
     // Unseals the sector containing the referenced piece and returns its
     // bytes. Produces an error if this sector builder does not have a sealed
-    // sector containing the referenced piece.
+    // sector containing the referenced piece. A piece recorded as an alias of
+    // another is read from that piece's bytes. If the builder's piece keys are
+    // private, but it wasn't given their material, the piece is read by its
+    // pseudonym.
     pub fn read_piece_from_sealed_sector(&self, piece_key: String) -> Result<Vec<u8>> {
+        let piece_key = self.piece_keys.lookup_key(piece_key);
+
         log_unrecov(self.run_blocking(|tx| Request::RetrievePiece(piece_key, tx)))
     }
 
+    // Copies the sealed sector with the given id to dest_dir, along with a
+    // manifest from which a builder on another machine may import it, and
+    // returns the path of the manifest. The copy is synced to disk and checked
+    // against the sealed sector before the manifest is written.
+    pub fn export_sealed_sector(&self, sector_id: u64, dest_dir: PathBuf) -> Result<PathBuf> {
+        log_unrecov(self.run_blocking(|tx| Request::ExportSealedSector(sector_id, dest_dir, tx)))
+    }
+
+    // Imports a sealed sector exported by a builder of the same prover,
+    // copying it into this builder's sealed sector directory and recording
+    // its metadata. Produces an error, and records nothing, if the copy
+    // doesn't match the checksum in the manifest or if this builder already
+    // has a sector with the same id. Other calls to this SectorBuilder wait
+    // until the sector has been copied.
+    pub fn import_sealed_sector(&self, manifest_path: PathBuf) -> Result<u64> {
+        log_unrecov(self.run_blocking(|tx| Request::ImportSealedSector(manifest_path, tx)))
+    }
+
+    // Migrates the pieces of the sealed sectors with the given ids, in order,
+    // into a single new sector of the size of target_config, and seals it. Each
+    // piece is unsealed and staged again under its key, and its comm_p, which
+    // is recomputed as it's staged, must match the one recorded for it: if it
+    // doesn't, the migration is abandoned with a MigratedPieceMismatch error
+    // naming the piece. Once the new sector is sealed, the source sectors are
+    // recorded as retired into it. They're kept, but are no longer proven, and
+    // their pieces are read from the new sector. Other calls to this
+    // SectorBuilder wait until the new sector is sealed.
+    pub fn migrate_sectors(
+        &self,
+        source_sector_ids: &[u64],
+        target_config: &ConfiguredStore,
+    ) -> Result<SectorMigration> {
+        let mut migration = log_unrecov(self.run_blocking(|tx| {
+            Request::MigrateSectors(source_sector_ids.to_vec(), *target_config, tx)
+        }))?;
+
+        for piece in &mut migration.pieces {
+            self.list_key(&mut piece.piece_key, true);
+        }
+
+        Ok(migration)
+    }
+
+    // Proves each sealed sector which isn't retired again with
+    // target_version's parameters, without sealing it again (see
+    // internal::regenerate_proof), and returns what became of each, by sector
+    // id. The sectors are proven by the sealers, so other calls to this
+    // SectorBuilder don't wait for them, and each new proof is recorded as
+    // it's made, the proof it replaces kept in the sector's history. A sector
+    // which fails to be proven keeps its proof.
+    pub fn regenerate_all_proofs(
+        &self,
+        target_version: ProofVersion,
+    ) -> Result<Vec<ProofRegeneration>> {
+        log_unrecov(self.run_blocking(|tx| Request::RegenerateAllProofs(target_version, tx)))
+    }
+
     // For demo purposes. Schedules sealing of all staged sectors.
     pub fn seal_all_staged_sectors(&self) -> Result<()> {
         log_unrecov(self.run_blocking(Request::SealAllStagedSectors))
     }
 
-    // Returns all sealed sector metadata.
-    pub fn get_sealed_sectors(&self) -> Result<Vec<SealedSectorMetadata>> {
-        log_unrecov(self.run_blocking(Request::GetSealedSectors))
+    // Seals the sector with the given id and returns its metadata, blocking
+    // until it's sealed. Sealing is idempotent, so that a seal may be retried
+    // whether or not it succeeded: a sector already sealed isn't sealed again,
+    // but its metadata is returned at once, marked cached, as long as its
+    // staged data hasn't changed since (if it has, a StagedDataChanged error
+    // is produced instead). A sector being sealed isn't sealed twice: the call
+    // waits for the seal in progress. Sectors which failed to seal are sealed
+    // again.
+    pub fn seal_sector(&self, sector_id: u64) -> Result<SealOutcome> {
+        let mut outcome = log_unrecov(self.run_blocking(|tx| Request::SealSector(sector_id, tx)))?;
+        self.list_piece_keys(&mut outcome.sector.pieces, true);
+
+        Ok(outcome)
+    }
+
+    // Returns the seals running and queued, those which finished last, and
+    // how much of the memory budget the running ones take.
+    pub fn get_seal_admissions(&self) -> SealAdmissions {
+        self.run_blocking(Request::GetSealAdmissions)
     }
 
-    // Returns all staged sector metadata.
-    pub fn get_staged_sectors(&self) -> Result<Vec<StagedSectorMetadata>> {
-        log_unrecov(self.run_blocking(Request::GetStagedSectors))
+    // Returns all sealed sector metadata, its pieces listed as
+    // get_expiring_pieces lists them.
+    pub fn get_sealed_sectors(&self, real_keys: bool) -> Result<Vec<SealedSectorMetadata>> {
+        let mut sectors = log_unrecov(self.run_blocking(Request::GetSealedSectors))?;
+
+        for sector in &mut sectors {
+            self.list_piece_keys(&mut sector.pieces, real_keys);
+        }
+
+        Ok(sectors)
+    }
+
+    // Returns all staged sector metadata, its pieces listed as
+    // get_expiring_pieces lists them.
+    pub fn get_staged_sectors(&self, real_keys: bool) -> Result<Vec<StagedSectorMetadata>> {
+        let mut sectors = log_unrecov(self.run_blocking(Request::GetStagedSectors))?;
+
+        for sector in &mut sectors {
+            self.list_piece_keys(&mut sector.pieces, real_keys);
+        }
+
+        Ok(sectors)
     }
 
-    // Generates a proof-of-spacetime. Blocks the calling thread.
+    // Generates a proof-of-spacetime. Blocks the calling thread. The sealed
+    // sectors with the given excluded ids (e.g. expired ones) are left out of
+    // the proof, and aren't reported as faults.
     pub fn generate_post(
         &self,
         comm_rs: &[[u8; 32]],
         challenge_seed: &[u8; 32],
+        excluded_sector_ids: &[u64],
     ) -> Result<PoStOutput> {
-        log_unrecov(
-            self.run_blocking(|tx| Request::GeneratePoSt(Vec::from(comm_rs), *challenge_seed, tx)),
-        )
+        log_unrecov(self.run_blocking(|tx| {
+            Request::GeneratePoSt(
+                Vec::from(comm_rs),
+                *challenge_seed,
+                excluded_sector_ids.to_vec(),
+                tx,
+            )
+        }))
+    }
+
+    // Returns the serialized records of the builder's audit log from the one
+    // with index since_record on, once everything recorded so far has been
+    // written, e.g. for archiving elsewhere. See audit_log::decode_audit_record.
+    pub fn export_audit_log(&self, since_record: u64) -> Result<Vec<Vec<u8>>> {
+        self.audit_log.export(since_record)
+    }
+
+    // Returns how many audit records have been written, and lost, since the
+    // builder was initialized.
+    pub fn audit_log_stats(&self) -> AuditLogStats {
+        self.audit_log.stats()
+    }
+
+    // Shuts the builder down, save for its threads, which are stopped once it's
+    // dropped. From now on, every call which would write fails with a
+    // ShuttingDown error. The seals queued, but not started, are persisted, and
+    // the next builder initialized over the same metadata queues them again.
+    // The seals running (and the proofs being regenerated) are given
+    // drain_timeout to finish, and are then cancelled: their sectors are marked
+    // Failed, and may be sealed again. Calls to seal_sector waiting on a seal
+    // which won't finish fail with an Interrupted error. Last, any piece left
+    // half-staged is resolved, the metadata persisted and the audit log
+    // flushed. Fails with a ShuttingDown error if the builder was shut down
+    // before.
+    pub fn shutdown(&self, drain_timeout: Duration) -> Result<ShutdownReport> {
+        let result = log_unrecov(self.run_blocking(|tx| Request::Drain(drain_timeout, tx)));
+
+        match &result {
+            Ok(report) => info!(FCP_LOG, "shut down"; "report" => format!("{:?}", report)),
+            Err(err) => warn!(FCP_LOG, "could not shut down cleanly"; "error" => err.to_string()),
+        }
+
+        result
+    }
+
+    // Replaces the key a piece is recorded under with the one it's listed
+    // under. If the builder's piece keys are private and real_keys is set,
+    // that's the key it was added or last read by through this builder, if it
+    // was, and its pseudonym if not, as a pseudonym can't be reversed.
+    fn list_key(&self, piece_key: &mut String, real_keys: bool) {
+        *piece_key = self.piece_keys.listed_key(piece_key.clone(), real_keys);
+    }
+
+    fn list_piece_keys(&self, pieces: &mut [PieceMetadata], real_keys: bool) {
+        for piece in pieces {
+            self.list_key(&mut piece.piece_key, real_keys);
+            for alias in &mut piece.aliases {
+                self.list_key(&mut alias.piece_key, real_keys);
+            }
+        }
+    }
+
+    fn listed_added_piece(&self, mut added: AddedPiece) -> AddedPiece {
+        if let Some(ref mut duplicate_of) = added.duplicate_of {
+            self.list_key(&mut duplicate_of.piece_key, true);
+        }
+
+        added
     }
 
     // Run a task, blocking on the return channel.
@@ -173,33 +764,66 @@ impl SectorBuilder {
 
 impl Drop for SectorBuilder {
     fn drop(&mut self) {
-        // Shut down main worker and sealers, too.
-        let _ = self
+        // Shut the builder down, unless it was already, cancelling its running
+        // seals at once.
+        let (tx, rx) = mpsc::sync_channel(0);
+        if self
             .scheduler_tx
-            .send(Request::Shutdown)
-            .map_err(|err| println!("err sending Shutdown to scheduler: {:?}", err));
+            .send(Request::Drain(Duration::from_secs(0), tx))
+            .is_ok()
+        {
+            let _ = rx.recv();
+        }
+
+        // Shut down main worker and sealers, too.
+        if let Err(err) = self.scheduler_tx.send(Request::Shutdown) {
+            warn!(FCP_LOG, "could not send Shutdown to the scheduler"; "error" => err.to_string());
+        }
 
         for _ in &mut self.sealers {
-            let _ = self
-                .sealers_tx
-                .send(SealerInput::Shutdown)
-                .map_err(|err| println!("err sending Shutdown to sealer: {:?}", err));
+            if let Err(err) = self.sealers_tx.send(SealerInput::Shutdown) {
+                warn!(FCP_LOG, "could not send Shutdown to a sealer"; "error" => err.to_string());
+            }
+        }
+
+        for _ in &mut self.retrievers {
+            if let Err(err) = self.retrievers_tx.send(RetrieverInput::Shutdown) {
+                warn!(FCP_LOG, "could not send Shutdown to a retriever"; "error" => err.to_string());
+            }
         }
 
         // Wait for worker threads to return.
         let scheduler_thread = &mut self.scheduler.thread;
 
         if let Some(thread) = scheduler_thread.take() {
-            let _ = thread
-                .join()
-                .map_err(|err| println!("err joining scheduler thread: {:?}", err));
+            if let Err(err) = thread.join() {
+                error!(FCP_LOG, "the scheduler panicked"; "panic" => panic_message(&*err));
+            }
         }
 
         for worker in &mut self.sealers {
             if let Some(thread) = worker.thread.take() {
-                let _ = thread
-                    .join()
-                    .map_err(|err| println!("err joining sealer thread: {:?}", err));
+                if let Err(err) = thread.join() {
+                    error!(FCP_LOG, "a sealer panicked"; "panic" => panic_message(&*err));
+                }
+            }
+        }
+
+        for worker in &mut self.retrievers {
+            if let Some(thread) = worker.thread.take() {
+                if let Err(err) = thread.join() {
+                    error!(FCP_LOG, "a retriever panicked"; "panic" => panic_message(&*err));
+                }
+            }
+        }
+
+        // The workers are done recording, so flush the audit log, and stop
+        // its writer.
+        self.audit_log.shutdown();
+
+        if let Some(thread) = self.audit_writer.take() {
+            if let Err(err) = thread.join() {
+                error!(FCP_LOG, "the audit log writer panicked"; "panic" => panic_message(&*err));
             }
         }
     }
@@ -209,9 +833,6 @@ pub struct WrappedSectorStore {
     inner: Box<SectorStore>,
 }
 
-unsafe impl Sync for WrappedSectorStore {}
-unsafe impl Send for WrappedSectorStore {}
-
 pub struct WrappedKeyValueStore {
     inner: Box<KeyValueStore>,
 }
@@ -219,6 +840,15 @@ pub struct WrappedKeyValueStore {
 unsafe impl Sync for WrappedKeyValueStore {}
 unsafe impl Send for WrappedKeyValueStore {}
 
+// What a worker thread panicked with, for the log.
+fn panic_message(payload: &(Any + Send)) -> String {
+    payload
+        .downcast_ref::<&str>()
+        .map(|msg| msg.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "a value which isn't a message".to_string())
+}
+
 fn log_unrecov<T>(result: Result<T>) -> Result<T> {
     if let Err(err) = &result {
         if let Some(SectorBuilderErr::Unrecoverable(err, backtrace)) = err.downcast_ref() {
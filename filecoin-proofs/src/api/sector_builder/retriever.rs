@@ -0,0 +1,70 @@
+use crate::api::io_priority::RetrievalGauge;
+use crate::api::sector_builder::helpers::retrieve_piece::retrieve_piece;
+use crate::api::sector_builder::metadata::SealedSectorMetadata;
+use crate::api::sector_builder::WrappedSectorStore;
+use crate::error::ExpectWithBacktrace;
+use crate::error::Result;
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use storage_proofs::types::FrSafe;
+
+const FATAL_NOLOCK: &str = "error acquiring task lock";
+const FATAL_RCVTSK: &str = "error receiving retrieval task";
+const FATAL_SNDRLT: &str = "error sending result";
+
+// Retrievals are served by their own workers, so that they never queue behind
+// seals. While a worker is retrieving, the retrieval is counted in the gauge
+// the sealers' throttles watch, which makes the sealers yield the disk to it.
+pub struct RetrieverWorker {
+    pub id: usize,
+    pub thread: Option<thread::JoinHandle<()>>,
+}
+
+pub enum RetrieverInput {
+    Unseal(
+        String,
+        Box<SealedSectorMetadata>,
+        mpsc::SyncSender<Result<Vec<u8>>>,
+    ),
+    Shutdown,
+}
+
+impl RetrieverWorker {
+    pub fn start(
+        id: usize,
+        retrieval_task_rx: Arc<Mutex<mpsc::Receiver<RetrieverInput>>>,
+        sector_store: Arc<WrappedSectorStore>,
+        prover_id: FrSafe,
+        retrievals: RetrievalGauge,
+    ) -> RetrieverWorker {
+        let thread = thread::spawn(move || loop {
+            // As the sealers do, take turns receiving from the shared channel.
+            let task = {
+                let rx = retrieval_task_rx.lock().expects(FATAL_NOLOCK);
+                rx.recv().expects(FATAL_RCVTSK)
+            };
+
+            match task {
+                RetrieverInput::Unseal(piece_key, sealed_sector, return_channel) => {
+                    let in_flight = retrievals.begin();
+                    let result = retrieve_piece(
+                        &sector_store.clone(),
+                        &sealed_sector,
+                        &prover_id,
+                        &piece_key,
+                    );
+                    drop(in_flight);
+
+                    return_channel.send(result).expects(FATAL_SNDRLT);
+                }
+                RetrieverInput::Shutdown => break,
+            }
+        });
+
+        RetrieverWorker {
+            id,
+            thread: Some(thread),
+        }
+    }
+}
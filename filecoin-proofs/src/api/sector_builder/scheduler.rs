@@ -1,37 +1,77 @@
+use crate::api::cancellation::{CancellationToken, Interrupted};
 use crate::api::internal;
 use crate::api::internal::PoStInput;
 use crate::api::internal::PoStInputPart;
 use crate::api::internal::PoStOutput;
+use crate::api::replica_format::LegacyReplicas;
+use crate::api::sector_builder::admission::{SealAdmissions, SealQueue};
+use crate::api::sector_builder::audit_log::{audit_hash, AuditEvent, AuditLog};
 use crate::api::sector_builder::errors::err_piecenotfound;
+use crate::api::sector_builder::errors::err_sealed_sector_not_found;
+use crate::api::sector_builder::errors::err_shutting_down;
+use crate::api::sector_builder::errors::err_staged_data_changed;
 use crate::api::sector_builder::errors::err_unrecov;
-use crate::api::sector_builder::helpers::add_piece::add_piece;
+use crate::api::sector_builder::helpers::add_piece::{add_piece_with_intent, PieceOutcome};
+use crate::api::sector_builder::helpers::expiry::{get_expired_sectors, get_expiring_pieces};
 use crate::api::sector_builder::helpers::get_seal_status::get_seal_status;
 use crate::api::sector_builder::helpers::get_sectors_ready_for_sealing::get_sectors_ready_for_sealing;
-use crate::api::sector_builder::helpers::snapshots::load_snapshot;
+use crate::api::sector_builder::helpers::metadata_txn::MetadataJournal;
+use crate::api::sector_builder::helpers::migrate_sectors::{migrate_sectors, record_migration};
+use crate::api::sector_builder::helpers::pending_seals::PendingSeals;
+use crate::api::sector_builder::helpers::piece_index::{add_alias, PieceIndex};
+use crate::api::sector_builder::helpers::piece_intents::PieceIntents;
+use crate::api::sector_builder::helpers::piece_placement::{
+    add_pieces_planned, plan_piece_placement, record_planned_pieces,
+};
+use crate::api::sector_builder::helpers::regenerate_proof::ProofRegenerations;
+use crate::api::sector_builder::helpers::sector_ids::SectorIdAllocator;
+use crate::api::sector_builder::helpers::sector_transfer::import_sealed_sector;
 use crate::api::sector_builder::helpers::snapshots::make_snapshot;
 use crate::api::sector_builder::helpers::snapshots::persist_snapshot;
+use crate::api::sector_builder::helpers::staged_data::staged_digest;
+use crate::api::sector_builder::metadata::AddedPiece;
+use crate::api::sector_builder::metadata::DuplicatePiecePolicy;
+use crate::api::sector_builder::metadata::ExpiringPiece;
+use crate::api::sector_builder::metadata::PieceAlias;
+use crate::api::sector_builder::metadata::PlacementPlan;
+use crate::api::sector_builder::metadata::PlannedPiece;
+use crate::api::sector_builder::metadata::ProofRegeneration;
+use crate::api::sector_builder::metadata::ProofRegenerationStatus;
+use crate::api::sector_builder::metadata::SealOutcome;
 use crate::api::sector_builder::metadata::SealStatus;
 use crate::api::sector_builder::metadata::SealedSectorMetadata;
+use crate::api::sector_builder::metadata::SectorMigration;
+use crate::api::sector_builder::metadata::ShutdownReport;
 use crate::api::sector_builder::metadata::StagedSectorMetadata;
+use crate::api::sector_builder::retriever::RetrieverInput;
 use crate::api::sector_builder::sealer::SealerInput;
 use crate::api::sector_builder::state::SectorBuilderState;
-use crate::api::sector_builder::state::StagedState;
-use crate::api::sector_builder::SectorId;
 use crate::api::sector_builder::WrappedKeyValueStore;
 use crate::api::sector_builder::WrappedSectorStore;
 use crate::error::ExpectWithBacktrace;
 use crate::error::Result;
+use crate::FCP_LOG;
+use sector_base::api::disk_backed_storage::{new_sector_store_with_proof_variant, ConfiguredStore};
+use sector_base::api::errors::SectorManagerErr;
+use sector_base::api::sector_store::ProofVersion;
+use slog::*;
 use std::collections::HashMap;
+use std::fmt;
+use std::io::Read;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::Arc;
 use std::thread;
+use std::time::{Duration, Instant};
+use storage_proofs::crypto::constant_time::ConstantTimeEq;
+use storage_proofs::types::Commitment;
 
-const FATAL_NOLOAD: &str = "could not load snapshot";
 const FATAL_NORECV: &str = "could not receive task";
 const FATAL_NOSEND: &str = "could not send";
 const FATAL_SECMAP: &str = "insert failed";
 const FATAL_SNPSHT: &str = "could not snapshot";
 const FATAL_SLRSND: &str = "could not send to sealer";
+const FATAL_RTRSND: &str = "could not send to retriever";
 const FATAL_HUNGUP: &str = "could not send to ret channel";
 const FATAL_NOSECT: &str = "could not find sector";
 
@@ -39,21 +79,64 @@ pub struct Scheduler {
     pub thread: Option<thread::JoinHandle<()>>,
 }
 
+// The source of a piece which is streamed into a staged sector. It is read
+// from the scheduler thread.
+pub struct PieceSource(pub Box<Read + Send>);
+
+impl fmt::Debug for PieceSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PieceSource")
+    }
+}
+
 #[derive(Debug)]
 pub enum Request {
-    AddPiece(String, Vec<u8>, mpsc::SyncSender<Result<SectorId>>),
+    AddPiece(
+        String,
+        Vec<u8>,
+        Option<u64>,
+        mpsc::SyncSender<Result<AddedPiece>>,
+    ),
+    AddPieceFromReader(
+        String,
+        u64,
+        Option<u64>,
+        PieceSource,
+        mpsc::SyncSender<Result<AddedPiece>>,
+    ),
+    PlanPiecePlacement(Vec<(String, u64)>, mpsc::SyncSender<Result<PlacementPlan>>),
+    AddPiecesPlanned(
+        PlacementPlan,
+        Vec<PlannedPiece>,
+        mpsc::SyncSender<Result<Vec<u64>>>,
+    ),
     GetSealedSectors(mpsc::SyncSender<Result<Vec<SealedSectorMetadata>>>),
     GetStagedSectors(mpsc::SyncSender<Result<Vec<StagedSectorMetadata>>>),
-    GetSealStatus(SectorId, mpsc::SyncSender<Result<SealStatus>>),
+    GetSealStatus(u64, mpsc::SyncSender<Result<SealStatus>>),
+    GetExpiredSectors(u64, mpsc::SyncSender<Vec<u64>>),
+    GetExpiringPieces(u64, mpsc::SyncSender<Vec<ExpiringPiece>>),
     GeneratePoSt(
         Vec<[u8; 32]>,
         [u8; 32],
+        Vec<u64>,
         mpsc::SyncSender<Result<PoStOutput>>,
     ),
     RetrievePiece(String, mpsc::SyncSender<Result<Vec<u8>>>),
+    ExportSealedSector(u64, PathBuf, mpsc::SyncSender<Result<PathBuf>>),
+    ImportSealedSector(PathBuf, mpsc::SyncSender<Result<u64>>),
+    MigrateSectors(
+        Vec<u64>,
+        ConfiguredStore,
+        mpsc::SyncSender<Result<SectorMigration>>,
+    ),
     SealAllStagedSectors(mpsc::SyncSender<Result<()>>),
+    SealSector(u64, mpsc::SyncSender<Result<SealOutcome>>),
+    RegenerateAllProofs(ProofVersion, mpsc::SyncSender<Result<Vec<ProofRegeneration>>>),
     GetMaxUserBytesPerStagedSector(mpsc::SyncSender<u64>),
-    HandleSealResult(SectorId, Box<Result<SealedSectorMetadata>>),
+    GetSealAdmissions(mpsc::SyncSender<SealAdmissions>),
+    HandleSealResult(u64, Box<Result<SealedSectorMetadata>>),
+    HandleProofRegeneration(u64, Box<Result<SealedSectorMetadata>>),
+    Drain(Duration, mpsc::SyncSender<Result<ShutdownReport>>),
     Shutdown,
 }
 
@@ -63,31 +146,26 @@ impl Scheduler {
         scheduler_input_rx: mpsc::Receiver<Request>,
         scheduler_input_tx: mpsc::SyncSender<Request>,
         sealer_input_tx: mpsc::Sender<SealerInput>,
+        retriever_input_tx: mpsc::Sender<RetrieverInput>,
+        audit_log: AuditLog,
         kv_store: Arc<WrappedKeyValueStore>,
         sector_store: Arc<WrappedSectorStore>,
-        last_committed_sector_id: SectorId,
+        state: SectorBuilderState,
+        sector_ids: SectorIdAllocator,
+        piece_intents: PieceIntents,
+        journal: MetadataJournal,
+        pending_seals: Option<PendingSeals>,
+        restored_seals: Vec<u64>,
+        proof_regenerations: Option<ProofRegenerations>,
+        shutdown_token: CancellationToken,
         max_num_staged_sectors: u8,
-        prover_id: [u8; 31],
+        sector_dirs: SectorDirs,
+        max_seal_memory_bytes: Option<u64>,
+        seal_queue: SealQueue<SealerInput>,
+        duplicate_pieces: DuplicatePiecePolicy,
+        read_only: bool,
     ) -> Scheduler {
         let thread = thread::spawn(move || {
-            // Build the scheduler's initial state. If available, we
-            // reconstitute this state from persisted metadata. If not, we
-            // create it from scratch.
-            let state = {
-                let loaded = load_snapshot(&kv_store, &prover_id)
-                    .expects(FATAL_NOLOAD)
-                    .map(|x| x.into());
-
-                loaded.unwrap_or_else(|| SectorBuilderState {
-                    prover_id,
-                    staged: StagedState {
-                        sector_id_nonce: last_committed_sector_id,
-                        sectors: Default::default(),
-                    },
-                    sealed: Default::default(),
-                })
-            };
-
             let max_user_bytes_per_staged_sector =
                 sector_store.inner.config().max_unsealed_bytes_per_sector();
 
@@ -95,43 +173,55 @@ impl Scheduler {
                 kv_store,
                 sector_store,
                 state,
+                sector_ids,
+                piece_intents,
+                journal,
+                pending_seals,
+                proof_regenerations,
+                shutdown_token,
+                shutting_down: false,
                 sealer_input_tx,
+                retriever_input_tx,
+                audit_log,
                 scheduler_input_tx: scheduler_input_tx.clone(),
                 max_num_staged_sectors,
                 max_user_bytes_per_staged_sector,
+                sector_dirs,
+                max_seal_memory_bytes,
+                seal_queue,
+                duplicate_pieces,
+                read_only,
+                seal_waiters: HashMap::new(),
+                proof_regeneration: None,
             };
 
+            // Queue the seals the last builder left waiting when it shut
+            // down, and prove again the sectors it left unfinished, before
+            // taking any request.
+            m.queue_restored_seals(restored_seals);
+            m.resume_proof_regenerations();
+
             loop {
-                let task = scheduler_input_rx.recv().expects(FATAL_NORECV);
+                // Wake up to admit the next seal once its stagger is over, if
+                // nothing else comes first.
+                let task = match m.seal_queue.next_admission_in(Instant::now()) {
+                    Some(timeout) => match scheduler_input_rx.recv_timeout(timeout) {
+                        Err(mpsc::RecvTimeoutError::Timeout) => {
+                            m.admit_seals();
+                            continue;
+                        }
+                        received => received.expects(FATAL_NORECV),
+                    },
+                    None => scheduler_input_rx.recv().expects(FATAL_NORECV),
+                };
 
-                // Dispatch to the appropriate task-handler.
                 match task {
-                    Request::AddPiece(key, bytes, tx) => {
-                        tx.send(m.add_piece(key, &bytes)).expects(FATAL_NOSEND);
-                    }
-                    Request::GetSealStatus(sector_id, tx) => {
-                        tx.send(m.get_seal_status(sector_id)).expects(FATAL_NOSEND);
-                    }
-                    Request::RetrievePiece(piece_key, tx) => m.retrieve_piece(piece_key, tx),
-                    Request::GetSealedSectors(tx) => {
-                        tx.send(m.get_sealed_sectors()).expects(FATAL_NOSEND);
-                    }
-                    Request::GetStagedSectors(tx) => {
-                        tx.send(m.get_staged_sectors()).expect(FATAL_NOSEND);
-                    }
-                    Request::GetMaxUserBytesPerStagedSector(tx) => {
-                        tx.send(m.max_user_bytes()).expects(FATAL_NOSEND);
-                    }
-                    Request::SealAllStagedSectors(tx) => {
-                        tx.send(m.seal_all_staged_sectors()).expects(FATAL_NOSEND);
-                    }
-                    Request::HandleSealResult(sector_id, result) => {
-                        m.handle_seal_result(sector_id, *result);
-                    }
-                    Request::GeneratePoSt(comm_rs, chg_seed, tx) => {
-                        m.generate_post(&comm_rs, &chg_seed, tx)
+                    Request::Drain(drain_timeout, tx) => {
+                        let report = m.drain(drain_timeout, &scheduler_input_rx);
+                        tx.send(report).expects(FATAL_NOSEND);
                     }
                     Request::Shutdown => break,
+                    task => m.dispatch(task),
                 }
             }
         });
@@ -142,36 +232,222 @@ impl Scheduler {
     }
 }
 
+// The directories of the builder's sector store, in which sectors of other
+// sizes (e.g. those migrated into) are kept too.
+pub struct SectorDirs {
+    pub sealed: String,
+    pub staged: String,
+}
+
 // The SectorBuilderStateManager is the owner of all sector-related metadata.
-// It dispatches expensive operations (e.g. unseal and seal) to the sealer
-// worker-threads. Other, inexpensive work (or work which needs to be performed
-// serially) is handled by the SectorBuilderStateManager itself.
+// It dispatches expensive operations to worker-threads: seals (and exports) to
+// the sealers, and unseals to the retrievers, which seals never hold up.
+// Other, inexpensive work (or work which needs to be performed serially) is
+// handled by the SectorBuilderStateManager itself.
 pub struct SectorMetadataManager {
     kv_store: Arc<WrappedKeyValueStore>,
     sector_store: Arc<WrappedSectorStore>,
     state: SectorBuilderState,
+    sector_ids: SectorIdAllocator,
+    // The intent of the piece being staged, through which pieces are staged
+    // crash-consistently.
+    piece_intents: PieceIntents,
+    // The journal through which changes to several sectors' records are
+    // committed all at once.
+    journal: MetadataJournal,
+    // Where the seals queued when the builder shuts down are left for the
+    // next builder. None for read-only builders.
+    pending_seals: Option<PendingSeals>,
+    // Where the sectors being proven again are recorded until they're done.
+    // None for read-only builders.
+    proof_regenerations: Option<ProofRegenerations>,
+    // Cancelled as the builder shuts down, once its drain timeout has passed,
+    // which the sealers give up on their tasks at.
+    shutdown_token: CancellationToken,
+    // Set once the builder has begun to shut down, from when every request
+    // which would write is refused.
+    shutting_down: bool,
     sealer_input_tx: mpsc::Sender<SealerInput>,
+    retriever_input_tx: mpsc::Sender<RetrieverInput>,
+    // Records the pieces staged, the sectors sealed and the PoSts generated.
+    audit_log: AuditLog,
     scheduler_input_tx: mpsc::SyncSender<Request>,
     max_num_staged_sectors: u8,
     max_user_bytes_per_staged_sector: u64,
+    sector_dirs: SectorDirs,
+    max_seal_memory_bytes: Option<u64>,
+    // The seals scheduled, until the builder's SealScheduling admits them to
+    // the sealers.
+    seal_queue: SealQueue<SealerInput>,
+    // What add_piece does with pieces which have the same bytes as a piece
+    // placed before them.
+    duplicate_pieces: DuplicatePiecePolicy,
+    // Set for builders which only serve and verify sealed sectors, which
+    // refuse every request that would write to their metadata or store.
+    read_only: bool,
+    // The callers of seal_sector waiting on sectors being sealed, by id.
+    seal_waiters: HashMap<u64, Vec<mpsc::SyncSender<Result<SealOutcome>>>>,
+    // The sectors being proven again by regenerate_all_proofs, if any are.
+    proof_regeneration: Option<ProofRegenerationBatch>,
+}
+
+// The sectors of a call to regenerate_all_proofs: how many are still being
+// proven, what became of the others, and where to reply once all are done.
+// Batches resumed from the last builder have no caller to reply to.
+struct ProofRegenerationBatch {
+    pending: usize,
+    done: Vec<ProofRegeneration>,
+    tx: Option<mpsc::SyncSender<Result<Vec<ProofRegeneration>>>>,
 }
 
 impl SectorMetadataManager {
+    // Dispatches the request to the appropriate handler.
+    pub fn dispatch(&mut self, task: Request) {
+        match task {
+            Request::AddPiece(key, bytes, expires_at, tx) => {
+                tx.send(self.add_piece(key, &bytes, expires_at)).expects(FATAL_NOSEND);
+            }
+            Request::AddPieceFromReader(key, len, expires_at, mut source, tx) => {
+                let result = self.add_piece_from_reader(key, len, expires_at, &mut *source.0);
+                tx.send(result).expects(FATAL_NOSEND);
+            }
+            Request::PlanPiecePlacement(pending_pieces, tx) => {
+                tx.send(self.plan_piece_placement(&pending_pieces)).expects(FATAL_NOSEND);
+            }
+            Request::AddPiecesPlanned(plan, pieces, tx) => {
+                tx.send(self.add_pieces_planned(&plan, pieces)).expects(FATAL_NOSEND);
+            }
+            Request::GetSealStatus(sector_id, tx) => {
+                tx.send(self.get_seal_status(sector_id)).expects(FATAL_NOSEND);
+            }
+            Request::GetExpiredSectors(now, tx) => {
+                tx.send(self.get_expired_sectors(now)).expects(FATAL_NOSEND);
+            }
+            Request::GetExpiringPieces(before, tx) => {
+                tx.send(self.get_expiring_pieces(before)).expects(FATAL_NOSEND);
+            }
+            Request::RetrievePiece(piece_key, tx) => self.retrieve_piece(piece_key, tx),
+            Request::ExportSealedSector(sector_id, dest_dir, tx) => {
+                self.export_sealed_sector(sector_id, dest_dir, tx)
+            }
+            Request::ImportSealedSector(manifest_path, tx) => {
+                tx.send(self.import_sealed_sector(manifest_path)).expects(FATAL_NOSEND);
+            }
+            Request::MigrateSectors(source_sector_ids, target_config, tx) => {
+                let result = self.migrate_sectors(&source_sector_ids, &target_config);
+                tx.send(result).expects(FATAL_NOSEND);
+            }
+            Request::GetSealedSectors(tx) => {
+                tx.send(self.get_sealed_sectors()).expects(FATAL_NOSEND);
+            }
+            Request::GetStagedSectors(tx) => {
+                tx.send(self.get_staged_sectors()).expect(FATAL_NOSEND);
+            }
+            Request::GetMaxUserBytesPerStagedSector(tx) => {
+                tx.send(self.max_user_bytes()).expects(FATAL_NOSEND);
+            }
+            Request::GetSealAdmissions(tx) => {
+                tx.send(self.seal_queue.admissions()).expects(FATAL_NOSEND);
+            }
+            Request::SealAllStagedSectors(tx) => {
+                tx.send(self.seal_all_staged_sectors()).expects(FATAL_NOSEND);
+            }
+            Request::SealSector(sector_id, tx) => self.seal_sector(sector_id, tx),
+            Request::HandleSealResult(sector_id, result) => {
+                self.handle_seal_result(sector_id, *result);
+            }
+            Request::RegenerateAllProofs(target_version, tx) => {
+                self.regenerate_all_proofs(target_version, tx)
+            }
+            Request::HandleProofRegeneration(sector_id, result) => {
+                self.handle_proof_regeneration(sector_id, *result);
+            }
+            Request::GeneratePoSt(comm_rs, chg_seed, excluded_sector_ids, tx) => {
+                self.generate_post(&comm_rs, &chg_seed, &excluded_sector_ids, tx)
+            }
+            // The builder is being drained already.
+            Request::Drain(_, tx) => {
+                tx.send(Err(err_shutting_down().into())).expects(FATAL_NOSEND);
+            }
+            // Only ever sent once the builder has been drained, and handled
+            // by the main loop.
+            Request::Shutdown => (),
+        }
+    }
+
+    // Shuts the builder down, save for its threads. From now on, every request
+    // which would write is refused with a ShuttingDown error, and no seal is
+    // admitted. The seals queued are withdrawn, and recorded for the next
+    // builder (see pending_seals). The seals running, and the proofs being
+    // regenerated, are waited for, other requests being handled meanwhile,
+    // until drain_timeout has passed: then they're cancelled, and waited for
+    // until they give up. Each caller waiting on a seal which won't finish is
+    // replied to with an Interrupted error. Last, any piece intent left is
+    // resolved, the state checkpointed and the audit log flushed.
+    pub fn drain(
+        &mut self,
+        drain_timeout: Duration,
+        rx: &mpsc::Receiver<Request>,
+    ) -> Result<ShutdownReport> {
+        if self.shutting_down {
+            return Err(err_shutting_down().into());
+        }
+        self.shutting_down = true;
+
+        let persisted = self.persist_queued_seals();
+        let (drained, cancelled) = self.drain_running(drain_timeout, rx);
+
+        // Pieces are committed before add_piece returns, so an intent is only
+        // left if it couldn't be cleared.
+        if !self.read_only {
+            let resolution = self
+                .piece_intents
+                .resolve(self.sector_store.inner.manager(), &mut self.state)?;
+            if let Some(resolution) = resolution {
+                let resolution = format!("{:?}", resolution);
+                info!(FCP_LOG, "resolved piece intent"; "resolution" => resolution);
+            }
+
+            self.checkpoint()?;
+        }
+
+        self.audit_log.flush();
+
+        Ok(ShutdownReport {
+            drained,
+            cancelled,
+            persisted: persisted?,
+            audit_log: self.audit_log.stats(),
+        })
+    }
+
     pub fn generate_post(
         &self,
         comm_rs: &[[u8; 32]],
         challenge_seed: &[u8; 32],
+        excluded_sector_ids: &[u64],
         return_channel: mpsc::SyncSender<Result<PoStOutput>>,
     ) {
+        // the replicas of excluded sectors (e.g. those whose deals have all
+        // expired) are left out of the proof, rather than proven or faulted
+        let excluded_comm_rs: Vec<[u8; 32]> = excluded_sector_ids
+            .iter()
+            .filter_map(|sector_id| self.state.sealed.sectors.get(sector_id))
+            .map(|sector| sector.comm_r)
+            .collect();
+
         // reduce our sealed sector state-map to a mapping of comm_r to sealed
-        // sector access (AKA path to sealed sector file)
-        let comm_r_to_sector_access: HashMap<[u8; 32], String> = self
+        // sector access (AKA path to sealed sector file) and whether its replica
+        // may lack a format trailer, leaving out retired sectors, which are no
+        // longer proven
+        let comm_r_to_sector_access: HashMap<[u8; 32], (String, LegacyReplicas)> = self
             .state
             .sealed
             .sectors
             .values()
+            .filter(|sector| sector.retired_into.is_none())
             .fold(HashMap::new(), |mut acc, item| {
-                let v = item.sector_access.clone();
+                let v = (item.sector_access.clone(), item.legacy_replicas());
                 let k = item.comm_r;
                 acc.entry(k).or_insert(v);
                 acc
@@ -181,10 +457,13 @@ impl SectorMetadataManager {
 
         // eject from this loop with an error if we've been provided a comm_r
         // which does not correspond to any sealed sector metadata
-        for comm_r in comm_rs {
+        for comm_r in comm_rs.iter().filter(|c| !excluded_comm_rs.contains(c)) {
+            let sector = comm_r_to_sector_access.get(comm_r);
+
             input_parts.push(PoStInputPart {
-                sealed_sector_access: comm_r_to_sector_access.get(comm_r).cloned(),
+                sealed_sector_access: sector.map(|(access, _)| access.clone()),
                 comm_r: *comm_r,
+                legacy_replicas: sector.map_or(LegacyReplicas::Refuse, |(_, legacy)| *legacy),
             });
         }
 
@@ -196,6 +475,21 @@ impl SectorMetadataManager {
             },
         );
 
+        if let Ok(output) = &output {
+            self.audit_log.record(AuditEvent::PoStGenerated {
+                challenge_seed: *challenge_seed,
+                comm_rs: comm_rs.to_vec(),
+                proof_digest: audit_hash(&output.snark_proof),
+            });
+
+            if !output.faults.is_empty() {
+                self.audit_log.record(AuditEvent::FaultsDeclared {
+                    challenge_seed: *challenge_seed,
+                    sector_ids: output.faults.clone(),
+                });
+            }
+        }
+
         // TODO: Where should this work be scheduled? New worker type?
         return_channel.send(output).expects(FATAL_HUNGUP);
     }
@@ -208,16 +502,43 @@ impl SectorMetadataManager {
         piece_key: String,
         return_channel: mpsc::SyncSender<Result<Vec<u8>>>,
     ) {
+        // The pieces of a retired sector are read from the sector they were
+        // migrated into, and aliases from the pieces they're aliases of.
         let opt_sealed_sector = self.state.sealed.sectors.values().find(|sector| {
-            sector
-                .pieces
-                .iter()
-                .any(|piece| piece.piece_key == piece_key)
+            sector.retired_into.is_none()
+                && sector
+                    .pieces
+                    .iter()
+                    .any(|piece| piece.answers_to(&piece_key))
         });
 
         if let Some(sealed_sector) = opt_sealed_sector {
             let sealed_sector = Box::new(sealed_sector.clone());
-            let task = SealerInput::Unseal(piece_key, sealed_sector, return_channel);
+            let task = RetrieverInput::Unseal(piece_key, sealed_sector, return_channel);
+
+            self.retriever_input_tx
+                .clone()
+                .send(task)
+                .expects(FATAL_RTRSND);
+        } else {
+            return_channel
+                .send(Err(err_piecenotfound(piece_key.to_string()).into()))
+                .expects(FATAL_HUNGUP);
+        }
+    }
+
+    // Copies the sealed sector with the provided id, along with its manifest,
+    // to dest_dir. Produces an error if this sector builder has no sealed
+    // sector with the provided id.
+    pub fn export_sealed_sector(
+        &self,
+        sector_id: u64,
+        dest_dir: PathBuf,
+        return_channel: mpsc::SyncSender<Result<PathBuf>>,
+    ) {
+        if let Some(sealed_sector) = self.state.sealed.sectors.get(&sector_id) {
+            let sealed_sector = Box::new(sealed_sector.clone());
+            let task = SealerInput::Export(sealed_sector, dest_dir, return_channel);
 
             self.sealer_input_tx
                 .clone()
@@ -225,39 +546,253 @@ impl SectorMetadataManager {
                 .expects(FATAL_SLRSND);
         } else {
             return_channel
-                .send(Err(err_piecenotfound(piece_key.to_string()).into()))
+                .send(Err(err_sealed_sector_not_found(sector_id).into()))
                 .expects(FATAL_HUNGUP);
         }
     }
 
+    // Copies the sealed sector exported with the manifest at manifest_path
+    // into this builder's sealed sector directory, and records it as sealed.
+    // Other requests wait until the sector has been copied.
+    pub fn import_sealed_sector(&mut self, manifest_path: PathBuf) -> Result<u64> {
+        self.check_writable()?;
+
+        let sealed_sector = import_sealed_sector(
+            &self.sector_store,
+            &self.state,
+            &mut self.sector_ids,
+            &manifest_path,
+        )?;
+        let sector_id = sealed_sector.sector_id;
+
+        self.state.sealed.sectors.insert(sector_id, sealed_sector);
+        self.checkpoint()?;
+
+        Ok(sector_id)
+    }
+
+    // Migrates the pieces of the sealed sectors with the given ids into a new
+    // sector of the size of target_config, which is sealed, and records the
+    // sources as retired. Other requests wait until the new sector is sealed.
+    pub fn migrate_sectors(
+        &mut self,
+        source_sector_ids: &[u64],
+        target_config: &ConfiguredStore,
+    ) -> Result<SectorMigration> {
+        self.check_writable()?;
+
+        let target_store = Arc::new(WrappedSectorStore {
+            inner: Box::new(new_sector_store_with_proof_variant(
+                target_config,
+                self.sector_dirs.sealed.clone(),
+                self.sector_dirs.staged.clone(),
+                self.sector_store.inner.config().proof_variant(),
+            )),
+        });
+
+        let (sealed_sector, pieces) = migrate_sectors(
+            &self.sector_store,
+            &target_store,
+            &self.state,
+            &mut self.sector_ids,
+            source_sector_ids,
+            self.max_seal_memory_bytes,
+        )?;
+        let sector_id = sealed_sector.sector_id;
+        let audit_event = sector_sealed(&sealed_sector);
+
+        let mut txn = self.journal.begin_txn();
+        record_migration(&mut txn, &self.state, source_sector_ids, sealed_sector);
+        txn.commit(&mut self.state)?;
+
+        self.audit_log.record(audit_event);
+
+        Ok(SectorMigration { sector_id, pieces })
+    }
+
     // Returns sealing status for the sector with specified id. If no sealed or
     // staged sector exists with the provided id, produce an error.
-    pub fn get_seal_status(&self, sector_id: SectorId) -> Result<SealStatus> {
+    pub fn get_seal_status(&self, sector_id: u64) -> Result<SealStatus> {
         get_seal_status(&self.state.staged, &self.state.sealed, sector_id)
     }
 
+    // Returns the ids of the sealed sectors all of whose pieces have expired
+    // by now.
+    pub fn get_expired_sectors(&self, now: u64) -> Vec<u64> {
+        get_expired_sectors(&self.state.sealed, now)
+    }
+
+    // Returns the pieces which expire before the given horizon.
+    pub fn get_expiring_pieces(&self, before: u64) -> Vec<ExpiringPiece> {
+        get_expiring_pieces(&self.state.staged, &self.state.sealed, before)
+    }
+
     // Write the piece to storage, obtaining the sector id with which the
     // piece-bytes are now associated.
-    pub fn add_piece(&mut self, piece_key: String, piece_bytes: &[u8]) -> Result<u64> {
-        let destination_sector_id = add_piece(
+    pub fn add_piece(
+        &mut self,
+        piece_key: String,
+        piece_bytes: &[u8],
+        expires_at: Option<u64>,
+    ) -> Result<AddedPiece> {
+        self.check_writable()?;
+
+        self.add_piece_from_reader(
+            piece_key,
+            piece_bytes.len() as u64,
+            expires_at,
+            &mut &piece_bytes[..],
+        )
+    }
+
+    // Streams the piece from source to storage, obtaining the sector id with
+    // which the piece-bytes are now associated. Other requests wait until the
+    // source has been read to its end. A piece with the same bytes as a piece
+    // placed before it is dealt with as the builder's DuplicatePiecePolicy
+    // says.
+    pub fn add_piece_from_reader(
+        &mut self,
+        piece_key: String,
+        piece_bytes_len: u64,
+        expires_at: Option<u64>,
+        source: &mut Read,
+    ) -> Result<AddedPiece> {
+        self.check_writable()?;
+
+        let placed = PieceIndex::new(&self.state.staged, &self.state.sealed);
+        let outcome = add_piece_with_intent(
             &self.sector_store,
+            &self.piece_intents,
             &mut self.state.staged,
-            piece_key,
-            piece_bytes,
+            &mut self.sector_ids,
+            &placed,
+            self.duplicate_pieces,
+            piece_key.clone(),
+            piece_bytes_len,
+            expires_at,
+            source,
         )?;
 
+        let added = match outcome {
+            PieceOutcome::Staged {
+                sector_id,
+                duplicate_of,
+            } => {
+                if let Some(ref existing) = duplicate_of {
+                    let keys: Vec<String> = self
+                        .piece_comm_p(&existing.piece_key, existing.sector_id)
+                        .map(|comm_p| placed.placements(&comm_p))
+                        .unwrap_or_default()
+                        .into_iter()
+                        .map(|placement| placement.piece_key)
+                        .collect();
+                    warn!(FCP_LOG, "piece duplicates pieces placed before"; "piece_key" => &piece_key, "keys" => keys.join(","));
+                }
+
+                self.record_piece_added(piece_key, sector_id);
+                self.check_and_schedule(false)?;
+
+                AddedPiece {
+                    sector_id,
+                    duplicate_of,
+                }
+            }
+            PieceOutcome::Duplicate { existing, comm_p } => {
+                let alias = PieceAlias {
+                    piece_key: piece_key.clone(),
+                    expires_at,
+                };
+                add_alias(
+                    &mut self.state.staged,
+                    &mut self.state.sealed,
+                    &existing,
+                    &comm_p,
+                    alias,
+                )?;
+                self.record_piece_added(piece_key, existing.sector_id);
+
+                AddedPiece {
+                    sector_id: existing.sector_id,
+                    duplicate_of: Some(existing),
+                }
+            }
+        };
+
+        self.checkpoint()?;
+
+        // The piece is committed once checkpointed. An intent left behind is
+        // found to be so when the builder next starts.
+        if let Err(err) = self.piece_intents.clear() {
+            warn!(FCP_LOG, "could not clear piece intent"; "error" => err.to_string());
+        }
+
+        Ok(added)
+    }
+
+    // Plans where to stage the given pieces, without staging them.
+    pub fn plan_piece_placement(&self, pending_pieces: &[(String, u64)]) -> Result<PlacementPlan> {
+        plan_piece_placement(
+            &self.state.staged,
+            self.max_user_bytes_per_staged_sector,
+            pending_pieces,
+        )
+    }
+
+    // Stages the pieces as planned, obtaining the id of the sector each piece
+    // is now associated with. No piece is staged unless they all are, and the
+    // sectors they're staged in are recorded in a single transaction.
+    pub fn add_pieces_planned(
+        &mut self,
+        plan: &PlacementPlan,
+        pieces: Vec<PlannedPiece>,
+    ) -> Result<Vec<u64>> {
+        self.check_writable()?;
+
+        let piece_keys: Vec<String> = pieces.iter().map(|p| p.piece_key.clone()).collect();
+        let sector_ids = add_pieces_planned(
+            &self.sector_store,
+            &mut self.state.staged,
+            &mut self.sector_ids,
+            plan,
+            pieces,
+        )?;
+
+        // State already records the pieces, as it must agree with the staged
+        // files even if the commit fails, so the commit only persists them.
+        let mut txn = self.journal.begin_txn();
+        record_planned_pieces(&mut txn, &self.state.staged, &sector_ids);
+        txn.commit(&mut self.state)?;
+
+        for (piece_key, sector_id) in piece_keys.into_iter().zip(&sector_ids) {
+            self.record_piece_added(piece_key, *sector_id);
+        }
+
         self.check_and_schedule(false)?;
         self.checkpoint()?;
 
-        Ok(destination_sector_id)
+        Ok(sector_ids)
     }
 
     // For demo purposes. Schedules sealing of all staged sectors.
     pub fn seal_all_staged_sectors(&mut self) -> Result<()> {
+        self.check_writable()?;
+
         self.check_and_schedule(true)?;
         self.checkpoint()
     }
 
+    // Seals the sector with the given id, replying once it's sealed. A sector
+    // already sealed is replied with at once, marked cached, as long as its
+    // staged data is still what was sealed. A sector being sealed isn't
+    // sealed twice: the reply waits for the seal in progress.
+    pub fn seal_sector(&mut self, sector_id: u64, tx: mpsc::SyncSender<Result<SealOutcome>>) {
+        match self.schedule_seal(sector_id) {
+            Ok(Some(outcome)) => tx.send(Ok(outcome)).expects(FATAL_NOSEND),
+            Ok(None) => self.seal_waiters.entry(sector_id).or_default().push(tx),
+            Err(err) => tx.send(Err(err)).expects(FATAL_NOSEND),
+        }
+    }
+
     // Produces a vector containing metadata for all sealed sectors that this
     // SectorBuilder knows about.
     pub fn get_sealed_sectors(&self) -> Result<Vec<SealedSectorMetadata>> {
@@ -279,7 +814,7 @@ impl SectorMetadataManager {
     // Update metadata to reflect the sealing results.
     pub fn handle_seal_result(
         &mut self,
-        sector_id: SectorId,
+        sector_id: u64,
         result: Result<SealedSectorMetadata>,
     ) {
         // scope exists to end the mutable borrow of self so that we can
@@ -289,22 +824,389 @@ impl SectorMetadataManager {
             let sealed_state = &mut self.state.sealed;
 
             if result.is_err() {
+                let error = seal_failure(result.unwrap_err());
+                self.audit_log.record(AuditEvent::SealFailed {
+                    sector_id,
+                    error: error.clone(),
+                });
+
                 if let Some(staged_sector) = staged_state.sectors.get_mut(&sector_id) {
-                    staged_sector.seal_status =
-                        SealStatus::Failed(format!("{}", err_unrecov(result.unwrap_err())));
+                    staged_sector.seal_status = SealStatus::Failed(error);
                 };
             } else {
                 // Remove the staged sector from the state map.
-                let _ = staged_state.sectors.remove(&sector_id);
+                let staged_sector = staged_state.sectors.remove(&sector_id);
 
                 // Insert the newly-sealed sector into the other state map.
-                let sealed_sector = result.expects(FATAL_SECMAP);
+                let mut sealed_sector = result.expects(FATAL_SECMAP);
+
+                // The sealer was given the sector's pieces as they were when
+                // it was scheduled, so keep the aliases recorded since.
+                if let Some(staged_sector) = staged_sector {
+                    let pieces = sealed_sector.pieces.iter_mut().zip(staged_sector.pieces);
+                    for (sealed_piece, staged_piece) in pieces {
+                        sealed_piece.aliases = staged_piece.aliases;
+                    }
+                }
 
+                self.audit_log.record(sector_sealed(&sealed_sector));
                 sealed_state.sectors.insert(sector_id, sealed_sector);
             }
         }
 
         self.checkpoint().expects(FATAL_SNPSHT);
+        self.reply_to_seal_waiters(sector_id);
+
+        if let Some(ref pending_seals) = self.pending_seals {
+            if let Err(err) = pending_seals.finish(sector_id) {
+                let err = err.to_string();
+                warn!(FCP_LOG, "could not finish restored seal"; "sector_id" => sector_id, "error" => err);
+            }
+        }
+
+        self.seal_queue.finish(sector_id);
+        self.admit_seals();
+    }
+
+    // Proves each sealed sector which isn't retired again with target_version's
+    // parameters, on the sealers, replying with what became of each once all
+    // are done. Each new proof is recorded as soon as it's made. Only one
+    // batch is proven at a time.
+    pub fn regenerate_all_proofs(
+        &mut self,
+        target_version: ProofVersion,
+        tx: mpsc::SyncSender<Result<Vec<ProofRegeneration>>>,
+    ) {
+        if let Err(err) = self.check_writable() {
+            tx.send(Err(err)).expects(FATAL_NOSEND);
+            return;
+        }
+
+        if self.proof_regeneration.is_some() {
+            let err = err_unrecov("the sealed sectors' proofs are being regenerated already");
+            tx.send(Err(err.into())).expects(FATAL_NOSEND);
+            return;
+        }
+
+        let sectors: Vec<&SealedSectorMetadata> = self
+            .state
+            .sealed
+            .sectors
+            .values()
+            .filter(|sector| sector.retired_into.is_none())
+            .collect();
+
+        if sectors.is_empty() {
+            tx.send(Ok(Vec::new())).expects(FATAL_NOSEND);
+            return;
+        }
+
+        // Each sector is recorded before it's sent to the sealers, so that a
+        // builder which stops before its proof is recorded proves it again.
+        if let Some(ref regenerations) = self.proof_regenerations {
+            for sector in &sectors {
+                if let Err(err) = regenerations.begin(sector.sector_id, target_version) {
+                    tx.send(Err(err)).expects(FATAL_NOSEND);
+                    return;
+                }
+            }
+        }
+
+        let sectors: Vec<SealedSectorMetadata> = sectors.into_iter().cloned().collect();
+        self.proof_regeneration = Some(ProofRegenerationBatch {
+            pending: sectors.len(),
+            done: Vec::new(),
+            tx: Some(tx),
+        });
+
+        for sector in sectors {
+            self.send_proof_regeneration(sector, target_version);
+        }
+    }
+
+    // Proves the sectors the last builder left being proven again, each with
+    // the version it was being proven with, as a batch no caller waits on.
+    fn resume_proof_regenerations(&mut self) {
+        let resumed = match self.proof_regenerations {
+            Some(ref regenerations) => regenerations.resume(&self.state.sealed),
+            None => return,
+        };
+
+        let resumed = match resumed {
+            Ok(ref resumed) if resumed.is_empty() => return,
+            Ok(resumed) => resumed,
+            Err(err) => {
+                warn!(FCP_LOG, "could not resume proof regenerations"; "error" => err.to_string());
+                return;
+            }
+        };
+
+        self.proof_regeneration = Some(ProofRegenerationBatch {
+            pending: resumed.len(),
+            done: Vec::new(),
+            tx: None,
+        });
+
+        for (sector, version) in resumed {
+            info!(FCP_LOG, "resuming proof regeneration"; "sector_id" => sector.sector_id);
+            self.send_proof_regeneration(sector, version);
+        }
+    }
+
+    fn send_proof_regeneration(&self, sector: SealedSectorMetadata, target_version: ProofVersion) {
+        self.sealer_input_tx
+            .clone()
+            .send(SealerInput::RegenerateProof(
+                Box::new(sector),
+                target_version,
+                self.scheduler_input_tx.clone(),
+            ))
+            .expects(FATAL_SLRSND);
+    }
+
+    // Records a sector's new proof, or why it couldn't be made, replying to
+    // regenerate_all_proofs once it was the last of its batch. A sector whose
+    // proving was cancelled is left for the next builder to prove again.
+    pub fn handle_proof_regeneration(
+        &mut self,
+        sector_id: u64,
+        result: Result<SealedSectorMetadata>,
+    ) {
+        let cancelled = match result {
+            Err(ref err) => err.downcast_ref::<Interrupted>().is_some(),
+            Ok(_) => false,
+        };
+        if let Some(ref regenerations) = self.proof_regenerations {
+            if !cancelled {
+                if let Err(err) = regenerations.finish(sector_id) {
+                    let err = err.to_string();
+                    warn!(FCP_LOG, "could not finish proof regeneration"; "sector_id" => sector_id, "error" => err);
+                }
+            }
+        }
+
+        let status = match result {
+            Ok(regenerated) => {
+                // Only the proof is taken, as the sector may have been
+                // changed (e.g. retired) while it was being proven.
+                if let Some(sector) = self.state.sealed.sectors.get_mut(&sector_id) {
+                    sector.snark_proof = regenerated.snark_proof;
+                    sector.proof_tag = regenerated.proof_tag;
+                    sector.replaced_proofs = regenerated.replaced_proofs;
+                }
+
+                self.checkpoint().expects(FATAL_SNPSHT);
+                ProofRegenerationStatus::Regenerated
+            }
+            Err(err) => ProofRegenerationStatus::Failed(format!("{}", err)),
+        };
+
+        let finished = match self.proof_regeneration {
+            Some(ref mut batch) => {
+                batch.pending -= 1;
+                batch.done.push(ProofRegeneration { sector_id, status });
+                batch.pending == 0
+            }
+            None => false,
+        };
+
+        if finished {
+            if let Some(mut batch) = self.proof_regeneration.take() {
+                batch.done.sort_by_key(|regeneration| regeneration.sector_id);
+
+                match batch.tx {
+                    Some(tx) => tx.send(Ok(batch.done)).expects(FATAL_NOSEND),
+                    None => {
+                        let done = format!("{:?}", batch.done);
+                        info!(FCP_LOG, "resumed proof regenerations finished"; "regenerations" => done)
+                    }
+                }
+            }
+        }
+    }
+
+    // Returns the metadata of the sector with the given id if it's sealed.
+    // Otherwise, schedules its sealing, unless it's being sealed already, and
+    // returns None. Sectors which failed to seal are sealed again.
+    fn schedule_seal(&mut self, sector_id: u64) -> Result<Option<SealOutcome>> {
+        self.check_writable()?;
+
+        if let Some(sector) = self.state.sealed.sectors.get(&sector_id) {
+            // Hashing the staged data holds up other requests, but only for
+            // as long as reading it takes, rather than as long as sealing it.
+            if let Some(ref sealed_from) = sector.sealed_from {
+                let digest = staged_digest(&self.sector_store, &sealed_from.sector_access)?;
+                if !digest.ct_eq(&sealed_from.digest) {
+                    return Err(err_staged_data_changed(sector_id).into());
+                }
+            }
+
+            return Ok(Some(SealOutcome {
+                sector: sector.clone(),
+                cached: true,
+            }));
+        }
+
+        let sector = self
+            .state
+            .staged
+            .sectors
+            .get_mut(&sector_id)
+            .ok_or_else(|| err_unrecov(format!("no sector with id {} found", sector_id)))?;
+
+        match sector.seal_status {
+            SealStatus::Sealing => return Ok(None),
+            SealStatus::Sealed(ref sealed) => {
+                return Ok(Some(SealOutcome {
+                    sector: (**sealed).clone(),
+                    cached: true,
+                }));
+            }
+            SealStatus::Pending | SealStatus::Failed(_) => (),
+        }
+
+        sector.seal_status = SealStatus::Sealing;
+
+        self.seal_queue.push(
+            sector_id,
+            SealerInput::Seal(sector.clone(), self.scheduler_input_tx.clone()),
+        );
+        self.admit_seals();
+
+        self.checkpoint()?;
+
+        Ok(None)
+    }
+
+    // Queues the seals of the sectors with the given ids, restored from the
+    // record of the last builder to shut down.
+    fn queue_restored_seals(&mut self, sector_ids: Vec<u64>) {
+        for sector_id in sector_ids {
+            if let Some(sector) = self.state.staged.sectors.get(&sector_id) {
+                self.seal_queue.push(
+                    sector_id,
+                    SealerInput::Seal(sector.clone(), self.scheduler_input_tx.clone()),
+                );
+            }
+        }
+
+        self.admit_seals();
+    }
+
+    // Withdraws the seals queued, replying to the callers waiting on them, and
+    // records them for the next builder. Their sectors stay marked Sealing.
+    // Returns the ids of their sectors, next first.
+    fn persist_queued_seals(&mut self) -> Result<Vec<u64>> {
+        let sector_ids: Vec<u64> = self
+            .seal_queue
+            .withdraw_queued()
+            .into_iter()
+            .map(|(sector_id, _)| sector_id)
+            .collect();
+
+        for sector_id in &sector_ids {
+            self.cancel_seal_waiters(*sector_id, "waiting to be sealed");
+        }
+
+        if let Some(ref pending_seals) = self.pending_seals {
+            if !sector_ids.is_empty() {
+                pending_seals.persist(&sector_ids)?;
+            }
+        }
+
+        Ok(sector_ids)
+    }
+
+    // Handles requests until the seals running, and the proofs being
+    // regenerated, have finished, cancelling them once drain_timeout has
+    // passed. Returns the ids of the sectors whose seals ran to their end, and
+    // of those whose seals were cancelled.
+    fn drain_running(
+        &mut self,
+        drain_timeout: Duration,
+        rx: &mpsc::Receiver<Request>,
+    ) -> (Vec<u64>, Vec<u64>) {
+        let start = Instant::now();
+        let (mut drained, mut cancelled) = (Vec::new(), Vec::new());
+        let mut cancelling = false;
+
+        while !self.seal_queue.running_sector_ids().is_empty() || self.proof_regeneration.is_some()
+        {
+            let elapsed = start.elapsed();
+            if !cancelling && elapsed >= drain_timeout {
+                self.shutdown_token.cancel();
+                cancelling = true;
+            }
+
+            // Timeouts are waited for a minute at a time, as an Instant can't
+            // be that far ahead of now for the longest of them.
+            let task = if cancelling {
+                rx.recv().expects(FATAL_NORECV)
+            } else {
+                let timeout = (drain_timeout - elapsed).min(Duration::from_secs(60));
+
+                match rx.recv_timeout(timeout) {
+                    Err(mpsc::RecvTimeoutError::Timeout) => continue,
+                    received => received.expects(FATAL_NORECV),
+                }
+            };
+
+            if let Request::HandleSealResult(sector_id, ref result) = task {
+                match **result {
+                    Err(ref err) if err.downcast_ref::<Interrupted>().is_some() => {
+                        cancelled.push(sector_id)
+                    }
+                    _ => drained.push(sector_id),
+                }
+            }
+
+            self.dispatch(task);
+        }
+
+        (drained, cancelled)
+    }
+
+    // Replies to the callers of seal_sector waiting on the sector with the
+    // given id that its seal was cancelled while in the given phase.
+    fn cancel_seal_waiters(&mut self, sector_id: u64, phase: &str) {
+        for tx in self.seal_waiters.remove(&sector_id).unwrap_or_default() {
+            let interrupted = Interrupted::Cancelled {
+                phase: phase.to_string(),
+            };
+
+            tx.send(Err(interrupted.into())).expects(FATAL_NOSEND);
+        }
+    }
+
+    // Hands the queued seals which the builder's SealScheduling admits now to
+    // the sealers.
+    fn admit_seals(&mut self) {
+        for task in self.seal_queue.admit(Instant::now()) {
+            self.sealer_input_tx
+                .clone()
+                .send(task)
+                .expects(FATAL_SLRSND);
+        }
+    }
+
+    // Replies to the callers of seal_sector waiting on the sector with the
+    // given id, once its seal has been handled.
+    fn reply_to_seal_waiters(&mut self, sector_id: u64) {
+        for tx in self.seal_waiters.remove(&sector_id).unwrap_or_default() {
+            let reply = match self.get_seal_status(sector_id) {
+                Ok(SealStatus::Sealed(sector)) => Ok(SealOutcome {
+                    sector: *sector,
+                    cached: false,
+                }),
+                Ok(SealStatus::Failed(err)) => Err(format_err!("{}", err)),
+                Ok(status) => {
+                    Err(err_unrecov(format!("sector {} is {:?}", sector_id, status)).into())
+                }
+                Err(err) => Err(err),
+            };
+
+            tx.send(reply).expects(FATAL_NOSEND);
+        }
     }
 
     // Check for sectors which should no longer receive new user piece-bytes and
@@ -328,13 +1230,54 @@ impl SectorMetadataManager {
                 .expects(FATAL_NOSECT);
             sector.seal_status = SealStatus::Sealing;
 
-            self.sealer_input_tx
-                .clone()
-                .send(SealerInput::Seal(
-                    sector.clone(),
-                    self.scheduler_input_tx.clone(),
-                ))
-                .expects(FATAL_SLRSND);
+            self.seal_queue.push(
+                sector_id,
+                SealerInput::Seal(sector.clone(), self.scheduler_input_tx.clone()),
+            );
+        }
+
+        self.admit_seals();
+
+        Ok(())
+    }
+
+    // Records the staging of the piece with the given key in the sector with
+    // the given id, along with the comm_p it was staged with. An alias is
+    // recorded as staged in the sector holding the bytes it's read from.
+    fn record_piece_added(&self, piece_key: String, sector_id: u64) {
+        let comm_p = self.piece_comm_p(&piece_key, sector_id);
+
+        self.audit_log.record(AuditEvent::PieceAdded {
+            piece_key,
+            sector_id,
+            comm_p,
+        });
+    }
+
+    // The comm_p of the first piece answering to the given key in the staged
+    // or sealed sector with the given id, if it has one.
+    fn piece_comm_p(&self, piece_key: &str, sector_id: u64) -> Option<Commitment> {
+        let pieces = match self.state.staged.sectors.get(&sector_id) {
+            Some(sector) => &sector.pieces,
+            None => &self.state.sealed.sectors.get(&sector_id)?.pieces,
+        };
+
+        pieces
+            .iter()
+            .find(|piece| piece.answers_to(piece_key))
+            .and_then(|piece| piece.comm_p)
+    }
+
+    // Refuses, with a ReadOnlyStore error, requests which would write to a
+    // read-only builder's metadata or store, and, with a ShuttingDown error,
+    // those made to a builder which is shutting down.
+    fn check_writable(&self) -> Result<()> {
+        if self.read_only {
+            return Err(SectorManagerErr::ReadOnlyStore.into());
+        }
+
+        if self.shutting_down {
+            return Err(err_shutting_down().into());
         }
 
         Ok(())
@@ -352,3 +1295,23 @@ impl SectorMetadataManager {
         Ok(())
     }
 }
+
+// What a sector whose seal failed records of the error. A seal cancelled as
+// the builder shut down isn't unrecoverable: the sector may be sealed again.
+fn seal_failure(err: failure::Error) -> String {
+    match err.downcast::<Interrupted>() {
+        Ok(interrupted) => format!("{}", interrupted),
+        Err(err) => format!("{}", err_unrecov(err)),
+    }
+}
+
+// What the audit log records of a sealed sector, its proof by its digest.
+fn sector_sealed(sector: &SealedSectorMetadata) -> AuditEvent {
+    AuditEvent::SectorSealed {
+        sector_id: sector.sector_id,
+        comm_d: sector.comm_d,
+        comm_r: sector.comm_r,
+        comm_r_star: sector.comm_r_star,
+        proof_digest: audit_hash(&sector.snark_proof),
+    }
+}
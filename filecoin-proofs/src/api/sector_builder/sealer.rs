@@ -1,11 +1,16 @@
-use crate::api::sector_builder::helpers::retrieve_piece::retrieve_piece;
+use crate::api::cancellation::CancellationToken;
+use crate::api::io_priority::SealThrottle;
+use crate::api::sector_builder::helpers::regenerate_proof::regenerate_proof;
 use crate::api::sector_builder::helpers::seal::seal;
+use crate::api::sector_builder::helpers::sector_transfer::export_sealed_sector;
 use crate::api::sector_builder::metadata::SealedSectorMetadata;
 use crate::api::sector_builder::metadata::StagedSectorMetadata;
 use crate::api::sector_builder::scheduler::Request;
 use crate::api::sector_builder::WrappedSectorStore;
 use crate::error::ExpectWithBacktrace;
 use crate::error::Result;
+use sector_base::api::sector_store::ProofVersion;
+use std::path::PathBuf;
 use std::sync::mpsc;
 use std::sync::{Arc, Mutex};
 use std::thread;
@@ -22,20 +27,34 @@ pub struct SealerWorker {
 
 pub enum SealerInput {
     Seal(StagedSectorMetadata, mpsc::SyncSender<Request>),
-    Unseal(
-        String,
+    Export(
         Box<SealedSectorMetadata>,
-        mpsc::SyncSender<Result<Vec<u8>>>,
+        PathBuf,
+        mpsc::SyncSender<Result<PathBuf>>,
+    ),
+    RegenerateProof(
+        Box<SealedSectorMetadata>,
+        ProofVersion,
+        mpsc::SyncSender<Request>,
     ),
     Shutdown,
 }
 
 impl SealerWorker {
+    // Starts a sealer, which gives up on its tasks once token is cancelled:
+    // the one it's working on at its next checkpoint, and the others before
+    // they begin.
+    #[allow(clippy::too_many_arguments)]
     pub fn start(
         id: usize,
         seal_task_rx: Arc<Mutex<mpsc::Receiver<SealerInput>>>,
         sector_store: Arc<WrappedSectorStore>,
         prover_id: [u8; 31],
+        verify_staged_data: bool,
+        landing_sector_dir: Option<PathBuf>,
+        max_seal_memory_bytes: Option<u64>,
+        throttle: SealThrottle,
+        token: CancellationToken,
     ) -> SealerWorker {
         let thread = thread::spawn(move || loop {
             // Acquire a lock on the rx end of the channel, get a task,
@@ -50,21 +69,52 @@ impl SealerWorker {
             match task {
                 SealerInput::Seal(staged_sector, return_channel) => {
                     let sector_id = staged_sector.sector_id;
-                    let result = seal(&sector_store.clone(), &prover_id, staged_sector);
+                    let result = seal(
+                        &sector_store.clone(),
+                        &prover_id,
+                        staged_sector,
+                        verify_staged_data,
+                        landing_sector_dir.as_ref().map(PathBuf::as_path),
+                        max_seal_memory_bytes,
+                        &throttle,
+                        &token,
+                    );
                     let task = Request::HandleSealResult(sector_id, Box::new(result));
 
                     return_channel.send(task).expects(FATAL_SNDTSK);
                 }
-                SealerInput::Unseal(piece_key, sealed_sector, return_channel) => {
-                    let result = retrieve_piece(
-                        &sector_store.clone(),
-                        &sealed_sector,
-                        &prover_id,
-                        &piece_key,
-                    );
+                SealerInput::Export(sealed_sector, dest_dir, return_channel) => {
+                    let result = token
+                        .checkpoint("waiting to be exported")
+                        .map_err(failure::Error::from)
+                        .and_then(|_| {
+                            export_sealed_sector(
+                                &sector_store.clone(),
+                                &prover_id,
+                                &sealed_sector,
+                                &dest_dir,
+                            )
+                        });
 
                     return_channel.send(result).expects(FATAL_SNDRLT);
                 }
+                SealerInput::RegenerateProof(sealed_sector, target_version, return_channel) => {
+                    let result = token
+                        .checkpoint("waiting to be proven again")
+                        .map_err(failure::Error::from)
+                        .and_then(|_| {
+                            regenerate_proof(
+                                &sector_store.clone(),
+                                &sealed_sector,
+                                &prover_id,
+                                target_version,
+                            )
+                        });
+                    let task =
+                        Request::HandleProofRegeneration(sealed_sector.sector_id, Box::new(result));
+
+                    return_channel.send(task).expects(FATAL_SNDTSK);
+                }
                 SealerInput::Shutdown => break,
             }
         });
@@ -0,0 +1,1000 @@
+//! The format in which a builder's metadata snapshot is persisted.
+//!
+//! A snapshot is persisted as a header, naming the version of the format and
+//! carrying a checksum of the body, followed by the body:
+//!
+//! ```text
+//! "SBMD" | version: u16 (LE) | blake2b checksum of the body: [u8; 64] | body
+//! ```
+//!
+//! The body is the CBOR encoding of a SnapshotRecord, whose sectors are sorted
+//! by id so that a snapshot always encodes to the same bytes. Readers skip
+//! fields they don't know, so a field which may be left out can be added to a
+//! version without bumping it. Removing a field, or changing what one means,
+//! bumps the version, and migrate_metadata learns to bring records of the
+//! version before forward.
+//!
+//! Version 1 is the snapshot as persisted before it was versioned: the CBOR
+//! encoding of a StateSnapshot, without a header.
+//!
+//! The manifest written beside a sealed sector exported for another builder
+//! has the same header, but starts with "SBSM". Its body is the CBOR encoding
+//! of a ManifestRecord, which holds the sector's record along with the name,
+//! length and blake2b checksum of the copy of the sealed sector. Manifests
+//! were introduced at version 2.
+
+use crate::api::sector_builder::errors::{err_invalid_field, err_malformed_metadata, MetadataErr};
+use crate::api::sector_builder::metadata::{
+    PieceAlias, PieceMetadata, ReplacedProof, SealStatus, SealedFrom, SealedSectorMetadata,
+    StagedSectorMetadata,
+};
+use crate::api::sector_builder::state::{SealedState, StagedState, StateSnapshot};
+use crate::error::Result;
+use blake2::{Blake2b, Digest};
+use byteorder::{ByteOrder, LittleEndian};
+use sector_base::api::disk_backed_storage::check_sector_bytes;
+use serde::Serialize;
+use storage_proofs::crypto::constant_time::constant_time_eq;
+
+pub const CURRENT_VERSION: u16 = 2;
+
+pub const MAGIC: &[u8] = b"SBMD";
+pub const MANIFEST_MAGIC: &[u8] = b"SBSM";
+const CHECKSUM_BYTES: usize = 64;
+const HEADER_BYTES: usize = 4 + 2 + CHECKSUM_BYTES;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SnapshotRecord {
+    prover_id: [u8; 31],
+    sectors: Vec<SectorRecord>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct SectorRecord {
+    sector_id: u64,
+    sector_access: String,
+    state: SectorState,
+    pieces: Vec<PieceRecord>,
+
+    // Set if, and only if, the sector is sealed.
+    comm_d: Option<Vec<u8>>,
+    comm_r: Option<Vec<u8>>,
+    comm_r_star: Option<Vec<u8>>,
+    proof: Option<Vec<u8>>,
+
+    // Set only on sealed sectors: the size of a sector whose size differs
+    // from the builder's, and the sector into which a retired sector's
+    // pieces were migrated.
+    #[serde(default)]
+    sector_bytes: Option<u64>,
+    #[serde(default)]
+    retired_into: Option<u64>,
+
+    // Set only on sealed sectors sealed with a format trailer: its version.
+    #[serde(default)]
+    replica_format: Option<u16>,
+
+    // Set, both or neither, only on sealed sectors which record the staged
+    // sector they were sealed from: its access, and the digest of its data.
+    #[serde(default)]
+    staged_sector_access: Option<String>,
+    #[serde(default)]
+    staged_digest: Option<Vec<u8>>,
+
+    // Set only on sealed sectors: the tag of the envelope their proof was
+    // handed out in, if recorded, and the proofs it has replaced, oldest
+    // first.
+    #[serde(default)]
+    proof_tag: Option<u8>,
+    #[serde(default)]
+    replaced_proofs: Vec<ReplacedProofRecord>,
+
+    // Set only on sealed sectors whose replication's trees were kept: the
+    // directory of the prover bundle holding them.
+    #[serde(default)]
+    prover_bundle: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ReplacedProofRecord {
+    proof: Vec<u8>,
+    proof_tag: Option<u8>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum SectorState {
+    Pending,
+    Sealing,
+    Failed(String),
+    Sealed,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct ManifestRecord {
+    prover_id: [u8; 31],
+    sector: SectorRecord,
+    sealed_file: String,
+    sealed_bytes: u64,
+    sealed_checksum: Vec<u8>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PieceRecord {
+    key: String,
+
+    // Where the piece's bytes start among the sector's unsealed bytes. Pieces
+    // are laid out back to back, so this is the sum of the lengths of the
+    // pieces before it.
+    offset: u64,
+    length: u64,
+    comm_p: Option<Vec<u8>>,
+
+    // When the deal storing the piece expires, if it was given one.
+    #[serde(default)]
+    expires_at: Option<u64>,
+
+    // The pieces added with the same bytes since, which are read from the
+    // piece's bytes.
+    #[serde(default)]
+    aliases: Vec<PieceAliasRecord>,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct PieceAliasRecord {
+    key: String,
+    expires_at: Option<u64>,
+}
+
+/// What is written beside the copy of an exported sealed sector, from which
+/// another builder imports it.
+#[derive(Debug, PartialEq)]
+pub struct SealedSectorManifest {
+    pub prover_id: [u8; 31],
+
+    // The sector's access is the one it had in the builder it was exported
+    // from.
+    pub sector: SealedSectorMetadata,
+
+    // The name of the copy of the sealed sector, in the manifest's directory.
+    pub sealed_file: String,
+    pub sealed_bytes: u64,
+    pub sealed_checksum: Vec<u8>,
+}
+
+/// Encodes a snapshot in the current version of the format.
+pub fn encode_snapshot(snapshot: &StateSnapshot) -> Result<Vec<u8>> {
+    encode_record(MAGIC, CURRENT_VERSION, &SnapshotRecord::from(snapshot))
+}
+
+/// Decodes a snapshot of the current version of the format, or of the version
+/// before it, validating every sector's metadata.
+pub fn decode_snapshot(bytes: &[u8]) -> Result<StateSnapshot> {
+    if !bytes.starts_with(MAGIC) {
+        return migrate_metadata(1, bytes)?.into_snapshot();
+    }
+
+    let (version, body) = open_record(MAGIC, bytes)?;
+
+    migrate_metadata(version, body)?.into_snapshot()
+}
+
+/// Encodes a manifest in the current version of the format.
+pub fn encode_manifest(manifest: &SealedSectorManifest) -> Result<Vec<u8>> {
+    let record = ManifestRecord {
+        prover_id: manifest.prover_id,
+        sector: SectorRecord::from(&manifest.sector),
+        sealed_file: manifest.sealed_file.clone(),
+        sealed_bytes: manifest.sealed_bytes,
+        sealed_checksum: manifest.sealed_checksum.clone(),
+    };
+
+    encode_record(MANIFEST_MAGIC, CURRENT_VERSION, &record)
+}
+
+/// Decodes a manifest, validating the metadata of its sector, which must be
+/// sealed.
+pub fn decode_manifest(bytes: &[u8]) -> Result<SealedSectorManifest> {
+    if !bytes.starts_with(MANIFEST_MAGIC) {
+        return Err(err_malformed_metadata("not a sealed sector manifest").into());
+    }
+
+    let (_, body) = open_record(MANIFEST_MAGIC, bytes)?;
+    let record: ManifestRecord = serde_cbor::from_slice(body).map_err(err_malformed_metadata)?;
+
+    let sector_id = record.sector.sector_id;
+    let snapshot = SnapshotRecord {
+        prover_id: record.prover_id,
+        sectors: vec![record.sector],
+    }
+    .into_snapshot()?;
+
+    let sector = snapshot
+        .sealed
+        .sectors
+        .into_iter()
+        .map(|(_, sector)| sector)
+        .next()
+        .ok_or_else(|| err_invalid_field(sector_id, "state", "not sealed"))?;
+
+    Ok(SealedSectorManifest {
+        prover_id: record.prover_id,
+        sector,
+        sealed_file: record.sealed_file,
+        sealed_bytes: record.sealed_bytes,
+        sealed_checksum: record.sealed_checksum,
+    })
+}
+
+// Checks the header of a record starting with magic, returning its version
+// and its body.
+fn open_record<'a>(magic: &[u8], bytes: &'a [u8]) -> Result<(u16, &'a [u8])> {
+    if bytes.len() < HEADER_BYTES {
+        return Err(err_malformed_metadata("truncated header").into());
+    }
+
+    let version = LittleEndian::read_u16(&bytes[magic.len()..]);
+    if version <= 1 || version > CURRENT_VERSION {
+        return Err(MetadataErr::UnsupportedVersion(version).into());
+    }
+
+    let (expected, body) = bytes[magic.len() + 2..].split_at(CHECKSUM_BYTES);
+    if !constant_time_eq(&checksum(body), expected) {
+        return Err(MetadataErr::ChecksumMismatch.into());
+    }
+
+    Ok((version, body))
+}
+
+// Brings the body of a snapshot of version from_version forward to the
+// current version.
+fn migrate_metadata(from_version: u16, body: &[u8]) -> Result<SnapshotRecord> {
+    match from_version {
+        // StateSnapshot still has the shape it was persisted in at version 1.
+        // Once it changes, version 1 needs a copy of that shape here.
+        1 => {
+            let snapshot: StateSnapshot =
+                serde_cbor::from_slice(body).map_err(err_malformed_metadata)?;
+
+            Ok(SnapshotRecord::from(&snapshot))
+        }
+        CURRENT_VERSION => Ok(serde_cbor::from_slice(body).map_err(err_malformed_metadata)?),
+        version => Err(MetadataErr::UnsupportedVersion(version).into()),
+    }
+}
+
+fn encode_record<T: Serialize>(magic: &[u8], version: u16, record: &T) -> Result<Vec<u8>> {
+    Ok(frame_record(magic, version, &serde_cbor::to_vec(record)?))
+}
+
+/// Puts the header of a record starting with magic before its body.
+pub fn frame_record(magic: &[u8], version: u16, body: &[u8]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(HEADER_BYTES + body.len());
+    bytes.extend_from_slice(magic);
+    bytes.extend_from_slice(&[0; 2]);
+    LittleEndian::write_u16(&mut bytes[magic.len()..], version);
+    bytes.extend_from_slice(&checksum(body));
+    bytes.extend_from_slice(body);
+
+    bytes
+}
+
+fn checksum(body: &[u8]) -> Vec<u8> {
+    let mut hasher = Blake2b::new();
+    hasher.input(body);
+
+    hasher.result().to_vec()
+}
+
+impl<'a> From<&'a StateSnapshot> for SnapshotRecord {
+    fn from(snapshot: &StateSnapshot) -> SnapshotRecord {
+        let staged = snapshot.staged.sectors.values().map(|sector| {
+            let state = match sector.seal_status {
+                SealStatus::Pending => SectorState::Pending,
+                SealStatus::Sealing => SectorState::Sealing,
+                SealStatus::Failed(ref reason) => SectorState::Failed(reason.clone()),
+                // The scheduler moves sectors out of the staged state once
+                // they're sealed, but should one still be there, it's sealed.
+                SealStatus::Sealed(ref sealed) => return SectorRecord::from(&**sealed),
+            };
+
+            SectorRecord {
+                sector_id: sector.sector_id,
+                sector_access: sector.sector_access.clone(),
+                state,
+                pieces: piece_records(&sector.pieces),
+                comm_d: None,
+                comm_r: None,
+                comm_r_star: None,
+                proof: None,
+                sector_bytes: None,
+                retired_into: None,
+                replica_format: None,
+                staged_sector_access: None,
+                staged_digest: None,
+                proof_tag: None,
+                replaced_proofs: Vec::new(),
+                prover_bundle: None,
+            }
+        });
+
+        let sealed = snapshot.sealed.sectors.values().map(SectorRecord::from);
+
+        let mut sectors: Vec<SectorRecord> = staged.chain(sealed).collect();
+        sectors.sort_by_key(|sector| sector.sector_id);
+
+        SnapshotRecord {
+            prover_id: snapshot.prover_id,
+            sectors,
+        }
+    }
+}
+
+impl<'a> From<&'a SealedSectorMetadata> for SectorRecord {
+    fn from(sector: &SealedSectorMetadata) -> SectorRecord {
+        SectorRecord {
+            sector_id: sector.sector_id,
+            sector_access: sector.sector_access.clone(),
+            state: SectorState::Sealed,
+            pieces: piece_records(&sector.pieces),
+            comm_d: Some(sector.comm_d.to_vec()),
+            comm_r: Some(sector.comm_r.to_vec()),
+            comm_r_star: Some(sector.comm_r_star.to_vec()),
+            proof: Some(sector.snark_proof.to_vec()),
+            sector_bytes: sector.sector_bytes,
+            retired_into: sector.retired_into,
+            replica_format: sector.replica_format,
+            staged_sector_access: sector.sealed_from.as_ref().map(|s| s.sector_access.clone()),
+            staged_digest: sector.sealed_from.as_ref().map(|s| s.digest.to_vec()),
+            proof_tag: sector.proof_tag,
+            replaced_proofs: sector
+                .replaced_proofs
+                .iter()
+                .map(|replaced| ReplacedProofRecord {
+                    proof: replaced.snark_proof.to_vec(),
+                    proof_tag: replaced.proof_tag,
+                })
+                .collect(),
+            prover_bundle: sector.prover_bundle.clone(),
+        }
+    }
+}
+
+fn piece_records(pieces: &[PieceMetadata]) -> Vec<PieceRecord> {
+    let mut offset = 0;
+
+    pieces
+        .iter()
+        .map(|piece| {
+            let record = PieceRecord {
+                key: piece.piece_key.clone(),
+                offset,
+                length: piece.num_bytes,
+                comm_p: piece.comm_p.map(|comm_p| comm_p.to_vec()),
+                expires_at: piece.expires_at,
+                aliases: piece
+                    .aliases
+                    .iter()
+                    .map(|alias| PieceAliasRecord {
+                        key: alias.piece_key.clone(),
+                        expires_at: alias.expires_at,
+                    })
+                    .collect(),
+            };
+            offset += piece.num_bytes;
+
+            record
+        })
+        .collect()
+}
+
+impl SnapshotRecord {
+    fn into_snapshot(self) -> Result<StateSnapshot> {
+        let mut staged = StagedState::default();
+        let mut sealed = SealedState::default();
+
+        for sector in self.sectors {
+            let sector_id = sector.sector_id;
+
+            if staged.sectors.contains_key(&sector_id) || sealed.sectors.contains_key(&sector_id) {
+                return Err(err_invalid_field(sector_id, "sector_id", "recorded twice").into());
+            }
+
+            let pieces = pieces_from_records(sector_id, sector.pieces)?;
+
+            let seal_status = match sector.state {
+                SectorState::Pending => SealStatus::Pending,
+                SectorState::Sealing => SealStatus::Sealing,
+                SectorState::Failed(reason) => SealStatus::Failed(reason),
+                SectorState::Sealed => {
+                    // A sector sealed into another size records it, which must
+                    // be one this version can unseal and prove.
+                    if let Some(sector_bytes) = sector.sector_bytes {
+                        check_sector_bytes(sector_bytes)
+                            .map_err(|err| err_invalid_field(sector_id, "sector_bytes", err))?;
+                    }
+
+                    let mut meta = SealedSectorMetadata {
+                        sector_id,
+                        sector_access: sector.sector_access,
+                        pieces,
+                        sector_bytes: sector.sector_bytes,
+                        retired_into: sector.retired_into,
+                        replica_format: sector.replica_format,
+                        proof_tag: sector.proof_tag,
+                        prover_bundle: sector.prover_bundle,
+                        ..Default::default()
+                    };
+
+                    read_fixed(sector_id, "comm_d", sector.comm_d, &mut meta.comm_d)?;
+                    read_fixed(sector_id, "comm_r", sector.comm_r, &mut meta.comm_r)?;
+                    let comm_r_star = sector.comm_r_star;
+                    read_fixed(sector_id, "comm_r_star", comm_r_star, &mut meta.comm_r_star)?;
+                    read_fixed(sector_id, "proof", sector.proof, &mut meta.snark_proof)?;
+
+                    for (i, replaced) in sector.replaced_proofs.into_iter().enumerate() {
+                        let mut snark_proof = [0; 384];
+                        let field = format!("replaced_proofs[{}].proof", i);
+                        read_fixed(sector_id, field, Some(replaced.proof), &mut snark_proof)?;
+
+                        meta.replaced_proofs.push(ReplacedProof {
+                            snark_proof,
+                            proof_tag: replaced.proof_tag,
+                        });
+                    }
+
+                    meta.sealed_from = match (sector.staged_sector_access, sector.staged_digest) {
+                        (Some(sector_access), staged_digest) => {
+                            let mut digest = [0; 32];
+                            read_fixed(sector_id, "staged_digest", staged_digest, &mut digest)?;
+
+                            Some(SealedFrom {
+                                sector_access,
+                                digest,
+                            })
+                        }
+                        (None, Some(_)) => {
+                            let reason = "set without staged_sector_access";
+                            let err = err_invalid_field(sector_id, "staged_digest", reason);
+                            return Err(err.into());
+                        }
+                        (None, None) => None,
+                    };
+
+                    sealed.sectors.insert(sector_id, meta);
+                    continue;
+                }
+            };
+
+            let sealed_fields = [
+                ("comm_d", &sector.comm_d),
+                ("comm_r", &sector.comm_r),
+                ("comm_r_star", &sector.comm_r_star),
+                ("proof", &sector.proof),
+            ];
+
+            for (field, value) in &sealed_fields {
+                if value.is_some() {
+                    let reason = "set on an unsealed sector";
+                    return Err(err_invalid_field(sector_id, field, reason).into());
+                }
+            }
+
+            if sector.sector_bytes.is_some() {
+                let reason = "set on an unsealed sector";
+                return Err(err_invalid_field(sector_id, "sector_bytes", reason).into());
+            }
+
+            if sector.retired_into.is_some() {
+                let reason = "set on an unsealed sector";
+                return Err(err_invalid_field(sector_id, "retired_into", reason).into());
+            }
+
+            if sector.replica_format.is_some() {
+                let reason = "set on an unsealed sector";
+                return Err(err_invalid_field(sector_id, "replica_format", reason).into());
+            }
+
+            if sector.staged_sector_access.is_some() || sector.staged_digest.is_some() {
+                let reason = "set on an unsealed sector";
+                return Err(err_invalid_field(sector_id, "staged_digest", reason).into());
+            }
+
+            if sector.proof_tag.is_some() {
+                let reason = "set on an unsealed sector";
+                return Err(err_invalid_field(sector_id, "proof_tag", reason).into());
+            }
+
+            if !sector.replaced_proofs.is_empty() {
+                let reason = "set on an unsealed sector";
+                return Err(err_invalid_field(sector_id, "replaced_proofs", reason).into());
+            }
+
+            if sector.prover_bundle.is_some() {
+                let reason = "set on an unsealed sector";
+                return Err(err_invalid_field(sector_id, "prover_bundle", reason).into());
+            }
+
+            staged.sectors.insert(
+                sector_id,
+                StagedSectorMetadata {
+                    sector_id,
+                    sector_access: sector.sector_access,
+                    pieces,
+                    seal_status,
+                },
+            );
+        }
+
+        Ok(StateSnapshot {
+            prover_id: self.prover_id,
+            staged,
+            sealed,
+        })
+    }
+}
+
+fn pieces_from_records(
+    sector_id: u64,
+    records: Vec<PieceRecord>,
+) -> Result<Vec<PieceMetadata>> {
+    let mut offset: u64 = 0;
+    let mut pieces = Vec::with_capacity(records.len());
+
+    for (i, record) in records.into_iter().enumerate() {
+        if record.offset != offset {
+            let field = format!("pieces[{}].offset", i);
+            let reason = format!("expected {}, got {}", offset, record.offset);
+            return Err(err_invalid_field(sector_id, field, reason).into());
+        }
+
+        offset = offset.checked_add(record.length).ok_or_else(|| {
+            err_invalid_field(sector_id, format!("pieces[{}].length", i), "overflows")
+        })?;
+
+        let comm_p = match record.comm_p {
+            Some(bytes) => {
+                let mut comm_p = [0; 32];
+                let field = format!("pieces[{}].comm_p", i);
+                read_fixed(sector_id, field, Some(bytes), &mut comm_p)?;
+                Some(comm_p)
+            }
+            None => None,
+        };
+
+        pieces.push(PieceMetadata {
+            piece_key: record.key,
+            num_bytes: record.length,
+            comm_p,
+            expires_at: record.expires_at,
+            aliases: record
+                .aliases
+                .into_iter()
+                .map(|alias| PieceAlias {
+                    piece_key: alias.key,
+                    expires_at: alias.expires_at,
+                })
+                .collect(),
+        });
+    }
+
+    Ok(pieces)
+}
+
+// Copies a field which must be set, and must be exactly as long as out.
+fn read_fixed<F: std::fmt::Display>(
+    sector_id: u64,
+    field: F,
+    value: Option<Vec<u8>>,
+    out: &mut [u8],
+) -> Result<()> {
+    match value {
+        Some(ref bytes) if bytes.len() == out.len() => {
+            out.copy_from_slice(bytes);
+            Ok(())
+        }
+        Some(bytes) => {
+            let reason = format!("expected {} bytes, got {}", out.len(), bytes.len());
+            Err(err_invalid_field(sector_id, field, reason).into())
+        }
+        None => Err(err_invalid_field(sector_id, field, "missing").into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    // A snapshot persisted before snapshots were versioned. Its first piece
+    // was staged before piece commitments were recorded, and so has no comm_p
+    // at all.
+    const V1_FIXTURE: &[u8] =
+        include_bytes!("../../../tests/golden/sector_builder_snapshot_v1.cbor");
+
+    fn v1_fixture_snapshot() -> StateSnapshot {
+        let mut staged = HashMap::new();
+        staged.insert(
+            1,
+            StagedSectorMetadata {
+                sector_id: 1,
+                sector_access: "staged-1".to_string(),
+                pieces: vec![PieceMetadata {
+                    piece_key: "a".to_string(),
+                    num_bytes: 100,
+                    comm_p: None,
+                    expires_at: None,
+                    aliases: Vec::new(),
+                }],
+                seal_status: SealStatus::Pending,
+            },
+        );
+
+        let mut sealed = HashMap::new();
+        sealed.insert(
+            0,
+            SealedSectorMetadata {
+                sector_id: 0,
+                sector_access: "sealed-0".to_string(),
+                pieces: vec![
+                    PieceMetadata {
+                        piece_key: "b".to_string(),
+                        num_bytes: 200,
+                        comm_p: Some([3; 32]),
+                        expires_at: None,
+                        aliases: Vec::new(),
+                    },
+                    PieceMetadata {
+                        piece_key: "c".to_string(),
+                        num_bytes: 50,
+                        comm_p: None,
+                        expires_at: None,
+                        aliases: Vec::new(),
+                    },
+                ],
+                comm_r_star: [4; 32],
+                comm_r: [5; 32],
+                comm_d: [6; 32],
+                snark_proof: [8; 384],
+                sector_bytes: None,
+                retired_into: None,
+                replica_format: None,
+                sealed_from: None,
+                proof_tag: None,
+                replaced_proofs: Vec::new(),
+                prover_bundle: None,
+            },
+        );
+
+        StateSnapshot {
+            prover_id: [7; 31],
+            staged: StagedState { sectors: staged },
+            sealed: SealedState { sectors: sealed },
+        }
+    }
+
+    fn snapshot_with_states() -> StateSnapshot {
+        let mut snapshot = v1_fixture_snapshot();
+
+        for (sector_id, seal_status) in vec![
+            (2, SealStatus::Sealing),
+            (3, SealStatus::Failed("boom".to_string())),
+        ] {
+            snapshot.staged.sectors.insert(
+                sector_id,
+                StagedSectorMetadata {
+                    sector_id,
+                    seal_status,
+                    ..Default::default()
+                },
+            );
+        }
+
+        snapshot
+    }
+
+    fn invalid_field(record: &SnapshotRecord) -> String {
+        let bytes = encode_record(MAGIC, CURRENT_VERSION, record).unwrap();
+
+        match decode_snapshot(&bytes).map_err(|err| err.downcast::<MetadataErr>()) {
+            Err(Ok(MetadataErr::InvalidField { field, .. })) => field,
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn loads_v1_snapshots() {
+        assert_eq!(v1_fixture_snapshot(), decode_snapshot(V1_FIXTURE).unwrap());
+    }
+
+    #[test]
+    fn round_trips_current_snapshots() {
+        let snapshot = snapshot_with_states();
+
+        let bytes = encode_snapshot(&snapshot).unwrap();
+        assert_eq!(CURRENT_VERSION, LittleEndian::read_u16(&bytes[4..]));
+
+        let decoded = decode_snapshot(&bytes).unwrap();
+        assert_eq!(snapshot, decoded);
+        assert_eq!(bytes, encode_snapshot(&decoded).unwrap());
+    }
+
+    #[test]
+    fn skips_unknown_fields() {
+        #[derive(Serialize)]
+        struct FutureSnapshotRecord {
+            prover_id: [u8; 31],
+            sectors: Vec<SectorRecord>,
+            aux_trees: Vec<String>,
+        }
+
+        let record = SnapshotRecord::from(&snapshot_with_states());
+        let future = FutureSnapshotRecord {
+            prover_id: record.prover_id,
+            sectors: record.sectors,
+            aux_trees: vec!["tree-0".to_string()],
+        };
+
+        let bytes = encode_record(MAGIC, CURRENT_VERSION, &future).unwrap();
+        assert_eq!(snapshot_with_states(), decode_snapshot(&bytes).unwrap());
+    }
+
+    #[test]
+    fn rejects_invalid_fields() {
+        let valid = || SnapshotRecord::from(&snapshot_with_states());
+
+        // Sector 0 is sealed, and sector 1 pending.
+        let mut record = valid();
+        record.sectors[0].comm_r = Some(vec![5; 31]);
+        assert_eq!("comm_r", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[0].comm_r_star = None;
+        assert_eq!("comm_r_star", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[0].proof = Some(vec![8; 192]);
+        assert_eq!("proof", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[0].sector_bytes = Some(1536);
+        assert_eq!("sector_bytes", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[0].pieces[1].offset = 199;
+        assert_eq!("pieces[1].offset", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[0].pieces[0].comm_p = Some(vec![3; 33]);
+        assert_eq!("pieces[0].comm_p", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[1].comm_d = Some(vec![6; 32]);
+        assert_eq!("comm_d", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[1].retired_into = Some(0);
+        assert_eq!("retired_into", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[1].replica_format = Some(1);
+        assert_eq!("replica_format", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[1].staged_digest = Some(vec![9; 32]);
+        assert_eq!("staged_digest", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[0].staged_sector_access = Some("staged-0".to_string());
+        assert_eq!("staged_digest", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[0].staged_digest = Some(vec![9; 32]);
+        assert_eq!("staged_digest", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[0].replaced_proofs = vec![ReplacedProofRecord {
+            proof: vec![8; 383],
+            proof_tag: Some(0),
+        }];
+        assert_eq!("replaced_proofs[0].proof", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[1].proof_tag = Some(0);
+        assert_eq!("proof_tag", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[1].prover_bundle = Some("bundle-1".to_string());
+        assert_eq!("prover_bundle", invalid_field(&record));
+
+        let mut record = valid();
+        record.sectors[2].sector_id = 1;
+        assert_eq!("sector_id", invalid_field(&record));
+    }
+
+    #[test]
+    fn round_trips_migrated_sectors() {
+        let mut snapshot = v1_fixture_snapshot();
+        snapshot.sealed.sectors.get_mut(&0).unwrap().retired_into = Some(4);
+        snapshot.sealed.sectors.insert(
+            4,
+            SealedSectorMetadata {
+                sector_id: 4,
+                sector_access: "sealed-4".to_string(),
+                pieces: snapshot.sealed.sectors[&0].pieces.clone(),
+                sector_bytes: Some(2048),
+                replica_format: Some(1),
+                ..Default::default()
+            },
+        );
+
+        let decoded = decode_snapshot(&encode_snapshot(&snapshot).unwrap()).unwrap();
+        assert_eq!(snapshot, decoded);
+        assert_eq!(Some(4), decoded.sealed.sectors[&0].retired_into);
+        assert_eq!(Some(2048), decoded.sealed.sectors[&4].sector_bytes);
+
+        // Sectors sealed before format trailers were introduced have none.
+        assert_eq!(None, decoded.sealed.sectors[&0].replica_format);
+        assert_eq!(Some(1), decoded.sealed.sectors[&4].replica_format);
+    }
+
+    #[test]
+    fn round_trips_staged_digests() {
+        let mut snapshot = v1_fixture_snapshot();
+        snapshot.sealed.sectors.get_mut(&0).unwrap().sealed_from = Some(SealedFrom {
+            sector_access: "staged-0".to_string(),
+            digest: [9; 32],
+        });
+
+        let decoded = decode_snapshot(&encode_snapshot(&snapshot).unwrap()).unwrap();
+        assert_eq!(snapshot, decoded);
+    }
+
+    #[test]
+    fn round_trips_replaced_proofs() {
+        let mut snapshot = v1_fixture_snapshot();
+        {
+            let sector = snapshot.sealed.sectors.get_mut(&0).unwrap();
+            sector.proof_tag = Some(4);
+            sector.prover_bundle = Some("bundle-0".to_string());
+            sector.replaced_proofs = vec![
+                ReplacedProof {
+                    snark_proof: [1; 384],
+                    proof_tag: None,
+                },
+                ReplacedProof {
+                    snark_proof: [2; 384],
+                    proof_tag: Some(2),
+                },
+            ];
+        }
+
+        let decoded = decode_snapshot(&encode_snapshot(&snapshot).unwrap()).unwrap();
+        assert_eq!(snapshot, decoded);
+
+        // Proofs replaced are kept oldest first.
+        let replaced = &decoded.sealed.sectors[&0].replaced_proofs;
+        assert_eq!(vec![1, 2], replaced.iter().map(|r| r.snark_proof[0]).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn round_trips_piece_expiries() {
+        let mut snapshot = v1_fixture_snapshot();
+        snapshot.staged.sectors.get_mut(&1).unwrap().pieces[0].expires_at = Some(10);
+        snapshot.sealed.sectors.get_mut(&0).unwrap().pieces[1].expires_at = Some(20);
+
+        let decoded = decode_snapshot(&encode_snapshot(&snapshot).unwrap()).unwrap();
+        assert_eq!(snapshot, decoded);
+        assert_eq!(Some(10), decoded.staged.sectors[&1].pieces[0].expires_at);
+        assert_eq!(None, decoded.sealed.sectors[&0].pieces[0].expires_at);
+        assert_eq!(Some(20), decoded.sealed.sectors[&0].pieces[1].expires_at);
+
+        // Exported sectors keep the expiries of their pieces.
+        let manifest = SealedSectorManifest {
+            prover_id: snapshot.prover_id,
+            sector: snapshot.sealed.sectors[&0].clone(),
+            sealed_file: "sector-0.sealed".to_string(),
+            sealed_bytes: 1024,
+            sealed_checksum: vec![9; 64],
+        };
+
+        let decoded = decode_manifest(&encode_manifest(&manifest).unwrap()).unwrap();
+        assert_eq!(Some(20), decoded.sector.pieces[1].expires_at);
+    }
+
+    #[test]
+    fn round_trips_piece_aliases() {
+        let mut snapshot = v1_fixture_snapshot();
+        snapshot.sealed.sectors.get_mut(&0).unwrap().pieces[0].aliases = vec![
+            PieceAlias {
+                piece_key: "d".to_string(),
+                expires_at: Some(30),
+            },
+            PieceAlias {
+                piece_key: "e".to_string(),
+                expires_at: None,
+            },
+        ];
+
+        let decoded = decode_snapshot(&encode_snapshot(&snapshot).unwrap()).unwrap();
+        assert_eq!(snapshot, decoded);
+
+        // Aliases take no bytes of their own, so the pieces after them start
+        // where they did.
+        let record = SnapshotRecord::from(&snapshot);
+        assert_eq!(200, record.sectors[0].pieces[1].offset);
+    }
+
+    #[test]
+    fn round_trips_manifests() {
+        let snapshot = v1_fixture_snapshot();
+        let manifest = SealedSectorManifest {
+            prover_id: snapshot.prover_id,
+            sector: snapshot.sealed.sectors[&0].clone(),
+            sealed_file: "sector-0.sealed".to_string(),
+            sealed_bytes: 1024,
+            sealed_checksum: vec![9; 64],
+        };
+
+        let bytes = encode_manifest(&manifest).unwrap();
+        assert_eq!(manifest, decode_manifest(&bytes).unwrap());
+
+        // Neither kind of record is taken for the other.
+        for result in vec![
+            decode_snapshot(&bytes).map(|_| ()),
+            decode_manifest(&encode_snapshot(&snapshot).unwrap()).map(|_| ()),
+        ] {
+            match result.map_err(|err| err.downcast::<MetadataErr>()) {
+                Err(Ok(MetadataErr::Malformed(_))) => (),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_manifests_of_unsealed_sectors() {
+        let record = ManifestRecord {
+            prover_id: [7; 31],
+            sector: SnapshotRecord::from(&v1_fixture_snapshot()).sectors.remove(1),
+            sealed_file: "sector-1.sealed".to_string(),
+            sealed_bytes: 1024,
+            sealed_checksum: vec![9; 64],
+        };
+
+        let bytes = encode_record(MANIFEST_MAGIC, CURRENT_VERSION, &record).unwrap();
+
+        match decode_manifest(&bytes).map_err(|err| err.downcast::<MetadataErr>()) {
+            Err(Ok(MetadataErr::InvalidField { sector_id, field, .. })) => {
+                assert_eq!((1, "state".to_string()), (sector_id, field));
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_bad_headers_and_checksums() {
+        let bytes = encode_snapshot(&snapshot_with_states()).unwrap();
+
+        let mut corrupted = bytes.clone();
+        let last = corrupted.len() - 1;
+        corrupted[last] ^= 1;
+        match decode_snapshot(&corrupted).map(|_| ()).map_err(|e| e.downcast::<MetadataErr>()) {
+            Err(Ok(MetadataErr::ChecksumMismatch)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        let mut future = bytes.clone();
+        future[4] = 3;
+        match decode_snapshot(&future).map(|_| ()).map_err(|e| e.downcast::<MetadataErr>()) {
+            Err(Ok(MetadataErr::UnsupportedVersion(3))) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        match decode_snapshot(&bytes[..HEADER_BYTES - 1])
+            .map(|_| ())
+            .map_err(|e| e.downcast::<MetadataErr>())
+        {
+            Err(Ok(MetadataErr::Malformed(_))) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -1,16 +1,14 @@
 use crate::api::sector_builder::metadata::{SealedSectorMetadata, StagedSectorMetadata};
-use crate::api::sector_builder::SectorId;
 use std::collections::HashMap;
 
 #[derive(Default, Serialize, Deserialize, Debug, PartialEq)]
 pub struct StagedState {
-    pub sector_id_nonce: SectorId,
-    pub sectors: HashMap<SectorId, StagedSectorMetadata>,
+    pub sectors: HashMap<u64, StagedSectorMetadata>,
 }
 
 #[derive(Default, Serialize, Deserialize, Debug, PartialEq)]
 pub struct SealedState {
-    pub sectors: HashMap<SectorId, SealedSectorMetadata>,
+    pub sectors: HashMap<u64, SealedSectorMetadata>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
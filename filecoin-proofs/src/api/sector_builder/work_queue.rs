@@ -0,0 +1,743 @@
+//! A durable queue of work, for the builder's work which must outlive it: the
+//! seals queued when it shuts down, and the sectors of a batch of proofs being
+//! regenerated.
+//!
+//! A queue is a journal of what happened to its entries: each was enqueued,
+//! then started, then completed or abandoned. The journal is a header followed
+//! by records, each framed with its length and a checksum:
+//!
+//! ```text
+//! "SBWQ" | version: u16 (LE) | first id: u64 (LE)
+//! length: u32 (LE) | blake2b checksum of the record, cut: [u8; 8] | record
+//! ```
+//!
+//! The record is the CBOR encoding of a QueueRecord. Each is synced as it's
+//! appended, before the change it records is made in memory. The first id is
+//! the least id an entry enqueued after the header was written may have, so
+//! that ids aren't reused once a compaction drops the records of finished
+//! entries.
+//!
+//! Opening a journal replays its records. A record cut short by a crash (or
+//! which doesn't match its checksum) ends the journal: it, and whatever
+//! follows it, is cut off and logged. Entries started, but neither completed
+//! nor abandoned, go back to pending, to be started again, unless they were
+//! enqueued as not retryable: those are abandoned, and reported.
+//!
+//! Once most records are of finished entries, the journal is compacted: the
+//! live entries are written to a new journal, which is renamed over the old.
+
+use crate::api::sector_builder::errors::{err_malformed_queue, WorkQueueErr};
+use crate::error::Result;
+use crate::FCP_LOG;
+use blake2::{Blake2b, Digest};
+use byteorder::{ByteOrder, LittleEndian};
+use sector_base::api::util::rename_durably;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use slog::*;
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use storage_proofs::crypto::constant_time::constant_time_eq;
+
+pub const CURRENT_VERSION: u16 = 1;
+
+pub const MAGIC: &[u8] = b"SBWQ";
+const HEADER_BYTES: usize = 4 + 2 + 8;
+const FRAME_LENGTH_BYTES: usize = 4;
+const CHECKSUM_BYTES: usize = 8;
+
+// Journals of fewer records than this aren't compacted, however many are of
+// finished entries.
+const COMPACTION_MIN_RECORDS: u64 = 64;
+
+const FATAL_NOLOCK: &str = "[PersistedQueue] could not acquire journal lock";
+
+#[derive(Debug, Serialize, Deserialize)]
+enum QueueRecord<T> {
+    Enqueued { id: u64, item: T, retryable: bool },
+    Started { id: u64 },
+    Completed { id: u64 },
+    Abandoned { id: u64 },
+}
+
+/// An entry of a queue, which is neither completed nor abandoned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueEntry<T> {
+    pub id: u64,
+    pub item: T,
+    /// Whether the entry goes back to pending if the queue is opened while
+    /// it's started, rather than being abandoned.
+    pub retryable: bool,
+    pub started: bool,
+}
+
+/// What opening a queue found to recover from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueRecovery<T> {
+    /// The ids of the entries which were started, and went back to pending.
+    pub retried: Vec<u64>,
+    /// The entries which were started, but weren't retryable.
+    pub abandoned: Vec<QueueEntry<T>>,
+    /// The bytes cut off the end of the journal, as they weren't whole
+    /// records.
+    pub truncated_bytes: u64,
+}
+
+/// A queue of items, kept in a journal at its path. It's shared by reference
+/// between the threads which enqueue and start its entries.
+pub struct PersistedQueue<T> {
+    path: PathBuf,
+    journal: Mutex<Journal<T>>,
+}
+
+struct Journal<T> {
+    file: File,
+    len: u64,
+    next_id: u64,
+    entries: BTreeMap<u64, QueueEntry<T>>,
+    // The records in the file, including those of finished entries.
+    records: u64,
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> PersistedQueue<T> {
+    /// Opens the queue whose journal is at path, creating it if needs be, and
+    /// recovers from whatever a crash left it with.
+    pub fn open(path: PathBuf) -> Result<(PersistedQueue<T>, QueueRecovery<T>)> {
+        let (journal, recovery) = Journal::open(&path)?;
+
+        let queue = PersistedQueue {
+            path,
+            journal: Mutex::new(journal),
+        };
+
+        Ok((queue, recovery))
+    }
+
+    /// Durably adds an entry holding the item to the end of the queue,
+    /// returning its id.
+    pub fn enqueue(&self, item: T, retryable: bool) -> Result<u64> {
+        let mut journal = self.journal.lock().expect(FATAL_NOLOCK);
+        let id = journal.next_id;
+
+        journal.append(
+            &self.path,
+            QueueRecord::Enqueued {
+                id,
+                item,
+                retryable,
+            },
+        )?;
+
+        Ok(id)
+    }
+
+    /// Durably starts the first pending entry, if there is one, and returns it.
+    pub fn start_next(&self) -> Result<Option<QueueEntry<T>>> {
+        let mut journal = self.journal.lock().expect(FATAL_NOLOCK);
+
+        let id = match journal.entries.values().find(|entry| !entry.started) {
+            Some(entry) => entry.id,
+            None => return Ok(None),
+        };
+        journal.append(&self.path, QueueRecord::Started { id })?;
+
+        Ok(journal.entries.get(&id).cloned())
+    }
+
+    /// Durably starts the pending entry with the given id.
+    pub fn start(&self, id: u64) -> Result<()> {
+        let mut journal = self.journal.lock().expect(FATAL_NOLOCK);
+
+        match journal.entries.get(&id) {
+            Some(entry) if entry.started => Err(WorkQueueErr::AlreadyStarted(id).into()),
+            Some(_) => journal.append(&self.path, QueueRecord::Started { id }),
+            None => Err(WorkQueueErr::NoSuchEntry(id).into()),
+        }
+    }
+
+    /// Durably removes the entry with the given id, as its work is done.
+    pub fn complete(&self, id: u64) -> Result<()> {
+        self.finish(QueueRecord::Completed { id }, id)
+    }
+
+    /// Durably removes the entry with the given id, as its work won't be done.
+    pub fn abandon(&self, id: u64) -> Result<()> {
+        self.finish(QueueRecord::Abandoned { id }, id)
+    }
+
+    /// The entries neither completed nor abandoned, first enqueued first.
+    pub fn entries(&self) -> Vec<QueueEntry<T>> {
+        let journal = self.journal.lock().expect(FATAL_NOLOCK);
+
+        journal.entries.values().cloned().collect()
+    }
+
+    fn finish(&self, record: QueueRecord<T>, id: u64) -> Result<()> {
+        let mut journal = self.journal.lock().expect(FATAL_NOLOCK);
+
+        if !journal.entries.contains_key(&id) {
+            return Err(WorkQueueErr::NoSuchEntry(id).into());
+        }
+
+        journal.append(&self.path, record)
+    }
+}
+
+impl<T: Serialize + DeserializeOwned + Clone> Journal<T> {
+    fn open(path: &Path) -> Result<(Journal<T>, QueueRecovery<T>)> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        // A journal whose header was cut short by a crash holds no entries. What
+        // there is of the header still starts as a header does.
+        let magic_bytes = bytes.len().min(MAGIC.len());
+        if bytes[..magic_bytes] != MAGIC[..magic_bytes] {
+            return Err(err_malformed_queue("not a work queue").into());
+        }
+
+        if bytes.len() < HEADER_BYTES {
+            if !bytes.is_empty() {
+                warn!(FCP_LOG, "replacing partial work queue header"; "path" => format!("{:?}", path));
+            }
+
+            return Ok((Journal::create(path, 0)?, QueueRecovery::default()));
+        }
+
+        let first_id = read_header(&bytes[..HEADER_BYTES])?;
+        let (records, valid_len) = split_records(&bytes);
+
+        let file = OpenOptions::new().write(true).open(path)?;
+        let truncated_bytes = (bytes.len() - valid_len) as u64;
+        if truncated_bytes > 0 {
+            warn!(FCP_LOG, "cutting off partial work queue record"; "path" => format!("{:?}", path), "bytes" => truncated_bytes);
+            file.set_len(valid_len as u64)?;
+            file.sync_all()?;
+        }
+
+        let mut journal = Journal {
+            file,
+            len: valid_len as u64,
+            next_id: first_id,
+            entries: BTreeMap::new(),
+            records: records.len() as u64,
+        };
+        for record in records {
+            journal.apply(record);
+        }
+
+        let mut recovery = QueueRecovery {
+            truncated_bytes,
+            ..Default::default()
+        };
+        let started: Vec<QueueEntry<T>> = journal
+            .entries
+            .values()
+            .filter(|entry| entry.started)
+            .cloned()
+            .collect();
+
+        for entry in started {
+            if entry.retryable {
+                if let Some(pending) = journal.entries.get_mut(&entry.id) {
+                    pending.started = false;
+                }
+                recovery.retried.push(entry.id);
+            } else {
+                journal.append(path, QueueRecord::Abandoned { id: entry.id })?;
+                recovery.abandoned.push(entry);
+            }
+        }
+
+        Ok((journal, recovery))
+    }
+
+    // Durably writes a journal of no records to path, replacing whatever is
+    // there, and opens it.
+    fn create(path: &Path, first_id: u64) -> Result<Journal<T>> {
+        write_journal(path, &frame_header(first_id))?;
+
+        let file = OpenOptions::new().write(true).open(path)?;
+
+        Ok(Journal {
+            file,
+            len: HEADER_BYTES as u64,
+            next_id: first_id,
+            entries: BTreeMap::new(),
+            records: 0,
+        })
+    }
+
+    // Applies a record to the entries in memory. Records of entries the
+    // journal doesn't hold (or no longer holds) change nothing.
+    fn apply(&mut self, record: QueueRecord<T>) {
+        match record {
+            QueueRecord::Enqueued {
+                id,
+                item,
+                retryable,
+            } => {
+                self.next_id = self.next_id.max(id + 1);
+                self.entries.entry(id).or_insert(QueueEntry {
+                    id,
+                    item,
+                    retryable,
+                    started: false,
+                });
+            }
+            QueueRecord::Started { id } => {
+                if let Some(entry) = self.entries.get_mut(&id) {
+                    entry.started = true;
+                }
+            }
+            QueueRecord::Completed { id } | QueueRecord::Abandoned { id } => {
+                self.entries.remove(&id);
+            }
+        }
+    }
+
+    // Syncs the record to the end of the journal, then applies it, compacting
+    // the journal if most of its records are now of finished entries.
+    fn append(&mut self, path: &Path, record: QueueRecord<T>) -> Result<()> {
+        let frame = frame_record(&serde_cbor::to_vec(&record)?);
+
+        let written = self
+            .file
+            .seek(SeekFrom::Start(self.len))
+            .and_then(|_| self.file.write_all(&frame))
+            .and_then(|_| self.file.sync_data());
+
+        if let Err(err) = written {
+            // Leave no partial record for the next one to follow.
+            let _ = self.file.set_len(self.len);
+            return Err(err.into());
+        }
+
+        self.len += frame.len() as u64;
+        self.records += 1;
+        self.apply(record);
+
+        if self.records >= COMPACTION_MIN_RECORDS && self.live_records() * 2 < self.records {
+            // The records appended are durable whether or not compacting
+            // them succeeds.
+            if let Err(err) = self.compact(path) {
+                warn!(FCP_LOG, "could not compact work queue"; "path" => format!("{:?}", path), "error" => err.to_string());
+            }
+        }
+
+        Ok(())
+    }
+
+    // The records a journal of the entries held needs.
+    fn live_records(&self) -> u64 {
+        self.entries
+            .values()
+            .map(|entry| if entry.started { 2 } else { 1 })
+            .sum()
+    }
+
+    // Replaces the journal with one holding only the records of the entries
+    // held. A crash leaves either journal, which hold the same entries.
+    fn compact(&mut self, path: &Path) -> Result<()> {
+        let mut bytes = frame_header(self.next_id);
+        let mut records = 0;
+
+        for entry in self.entries.values() {
+            let enqueued: QueueRecord<&T> = QueueRecord::Enqueued {
+                id: entry.id,
+                item: &entry.item,
+                retryable: entry.retryable,
+            };
+            bytes.extend(frame_record(&serde_cbor::to_vec(&enqueued)?));
+            records += 1;
+
+            if entry.started {
+                let started: QueueRecord<&T> = QueueRecord::Started { id: entry.id };
+                bytes.extend(frame_record(&serde_cbor::to_vec(&started)?));
+                records += 1;
+            }
+        }
+
+        write_journal(path, &bytes)?;
+
+        self.file = OpenOptions::new().write(true).open(path)?;
+        self.len = bytes.len() as u64;
+        self.records = records;
+
+        Ok(())
+    }
+}
+
+impl<T> Default for QueueRecovery<T> {
+    fn default() -> QueueRecovery<T> {
+        QueueRecovery {
+            retried: Vec::new(),
+            abandoned: Vec::new(),
+            truncated_bytes: 0,
+        }
+    }
+}
+
+// Writes bytes to a temporary file beside path, syncs it, and renames it over
+// path.
+fn write_journal(path: &Path, bytes: &[u8]) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+
+    rename_durably(&tmp_path, path)?;
+
+    Ok(())
+}
+
+fn frame_header(first_id: u64) -> Vec<u8> {
+    let mut header = vec![0; HEADER_BYTES];
+    header[..4].copy_from_slice(MAGIC);
+    LittleEndian::write_u16(&mut header[4..6], CURRENT_VERSION);
+    LittleEndian::write_u64(&mut header[6..], first_id);
+
+    header
+}
+
+// Returns the first id of the journal with the given header.
+fn read_header(header: &[u8]) -> Result<u64> {
+    if &header[..4] != MAGIC {
+        return Err(err_malformed_queue("not a work queue").into());
+    }
+
+    let version = LittleEndian::read_u16(&header[4..6]);
+    if version != CURRENT_VERSION {
+        return Err(WorkQueueErr::UnsupportedVersion(version).into());
+    }
+
+    Ok(LittleEndian::read_u64(&header[6..]))
+}
+
+fn checksum(record_bytes: &[u8]) -> [u8; CHECKSUM_BYTES] {
+    let mut checksum = [0; CHECKSUM_BYTES];
+    checksum.copy_from_slice(&Blake2b::digest(record_bytes)[..CHECKSUM_BYTES]);
+    checksum
+}
+
+fn frame_record(record_bytes: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0; FRAME_LENGTH_BYTES];
+    LittleEndian::write_u32(&mut frame, record_bytes.len() as u32);
+    frame.extend_from_slice(&checksum(record_bytes));
+    frame.extend_from_slice(record_bytes);
+
+    frame
+}
+
+// Returns the records of a journal up to the first which isn't whole, doesn't
+// match its checksum or can't be decoded, and the length of the journal up to
+// that record.
+fn split_records<T: DeserializeOwned>(bytes: &[u8]) -> (Vec<QueueRecord<T>>, usize) {
+    let mut records = Vec::new();
+    let mut at = HEADER_BYTES;
+
+    while bytes.len() - at >= FRAME_LENGTH_BYTES + CHECKSUM_BYTES {
+        let len = LittleEndian::read_u32(&bytes[at..at + FRAME_LENGTH_BYTES]) as usize;
+        let start = at + FRAME_LENGTH_BYTES + CHECKSUM_BYTES;
+        if bytes.len() - start < len {
+            break;
+        }
+
+        let record_bytes = &bytes[start..start + len];
+        if !constant_time_eq(
+            &bytes[at + FRAME_LENGTH_BYTES..start],
+            &checksum(record_bytes),
+        ) {
+            break;
+        }
+
+        match serde_cbor::from_slice(record_bytes) {
+            Ok(record) => records.push(record),
+            Err(_) => break,
+        }
+        at = start + len;
+    }
+
+    (records, at)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+
+    fn open(path: &Path) -> (PersistedQueue<String>, QueueRecovery<String>) {
+        PersistedQueue::open(path.to_path_buf()).unwrap()
+    }
+
+    fn items(queue: &PersistedQueue<String>) -> Vec<(String, bool)> {
+        queue
+            .entries()
+            .into_iter()
+            .map(|entry| (entry.item, entry.started))
+            .collect()
+    }
+
+    #[test]
+    fn entries_are_started_and_completed_across_restarts() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.queue");
+
+        let (queue, recovery) = open(&path);
+        assert_eq!(QueueRecovery::default(), recovery);
+        let a = queue.enqueue("a".to_string(), true).unwrap();
+        let b = queue.enqueue("b".to_string(), true).unwrap();
+        let c = queue.enqueue("c".to_string(), false).unwrap();
+        queue.enqueue("d".to_string(), true).unwrap();
+
+        assert_eq!(Some(a), queue.start_next().unwrap().map(|entry| entry.id));
+        queue.complete(a).unwrap();
+        queue.start(b).unwrap();
+        queue.start(c).unwrap();
+        drop(queue);
+
+        // b goes back to pending, while c, which isn't retryable, is
+        // abandoned.
+        let (queue, recovery) = open(&path);
+        assert_eq!(vec![b], recovery.retried);
+        assert_eq!(
+            vec![c],
+            recovery.abandoned.iter().map(|e| e.id).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec![("b".to_string(), false), ("d".to_string(), false)],
+            items(&queue)
+        );
+
+        assert!(queue.complete(c).is_err());
+        queue.start(b).unwrap();
+        assert!(queue.start(b).is_err());
+        queue.abandon(b).unwrap();
+        let e = queue.enqueue("e".to_string(), true).unwrap();
+        assert!(e > c);
+        drop(queue);
+
+        let (queue, recovery) = open(&path);
+        assert_eq!(QueueRecovery::default(), recovery);
+        assert_eq!(
+            vec![("d".to_string(), false), ("e".to_string(), false)],
+            items(&queue)
+        );
+    }
+
+    #[test]
+    fn torn_records_are_cut_off() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.queue");
+
+        let (queue, _) = open(&path);
+        queue.enqueue("a".to_string(), true).unwrap();
+        queue.enqueue("b".to_string(), true).unwrap();
+        drop(queue);
+
+        // A crash cut the last record short.
+        let len = fs::metadata(&path).unwrap().len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 3)
+            .unwrap();
+
+        let (queue, recovery) = open(&path);
+        assert_eq!(vec![("a".to_string(), false)], items(&queue));
+        assert!(recovery.truncated_bytes > 0);
+        assert_eq!(
+            len - 3 - recovery.truncated_bytes,
+            fs::metadata(&path).unwrap().len()
+        );
+
+        // Appending continues from the last whole record.
+        queue.enqueue("c".to_string(), true).unwrap();
+        drop(queue);
+
+        // A record which doesn't match its checksum ends the journal too.
+        let mut bytes = fs::read(&path).unwrap();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        let (queue, recovery) = open(&path);
+        assert_eq!(vec![("a".to_string(), false)], items(&queue));
+        assert!(recovery.truncated_bytes > 0);
+
+        // A journal whose header was cut short holds nothing.
+        fs::write(&path, &MAGIC[..2]).unwrap();
+        let (queue, _) = open(&path);
+        assert!(queue.entries().is_empty());
+    }
+
+    #[test]
+    fn foreign_files_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.queue");
+
+        fs::write(&path, b"not a work queue").unwrap();
+        assert!(PersistedQueue::<String>::open(path.clone()).is_err());
+
+        // Nor is a file shorter than a header taken for one cut short.
+        fs::write(&path, b"short").unwrap();
+        assert!(PersistedQueue::<String>::open(path.clone()).is_err());
+
+        let mut header = frame_header(0);
+        header[4] = 9;
+        fs::write(&path, &header).unwrap();
+        match PersistedQueue::<String>::open(path) {
+            Err(err) => match err.downcast_ref::<WorkQueueErr>() {
+                Some(WorkQueueErr::UnsupportedVersion(9)) => (),
+                other => panic!("unexpected error: {:?}", other),
+            },
+            Ok(_) => panic!("opened a journal of an unknown version"),
+        }
+    }
+
+    #[test]
+    fn compaction_keeps_live_entries_and_ids() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.queue");
+        let records_on_disk = || split_records::<String>(&fs::read(&path).unwrap()).0.len();
+
+        let (queue, _) = open(&path);
+        let kept = queue.enqueue("kept".to_string(), true).unwrap();
+        queue.start(kept).unwrap();
+
+        for i in 0..COMPACTION_MIN_RECORDS {
+            let id = queue.enqueue(format!("done-{}", i), true).unwrap();
+            queue.complete(id).unwrap();
+        }
+        let waiting = queue.enqueue("waiting".to_string(), false).unwrap();
+
+        // More records were appended than a journal is left with uncompacted.
+        assert!((records_on_disk() as u64) < COMPACTION_MIN_RECORDS);
+
+        queue.journal.lock().unwrap().compact(&path).unwrap();
+        assert_eq!(3, records_on_disk());
+        assert!(!path.with_extension("tmp").exists());
+        drop(queue);
+
+        let (queue, recovery) = open(&path);
+        assert_eq!(vec![kept], recovery.retried);
+        assert_eq!(
+            vec![("kept".to_string(), false), ("waiting".to_string(), false)],
+            items(&queue)
+        );
+        assert!(!queue.entries()[1].retryable);
+
+        // Ids aren't reused, though the finished entries' records are gone.
+        queue.complete(kept).unwrap();
+        queue.abandon(waiting).unwrap();
+        queue.journal.lock().unwrap().compact(&path).unwrap();
+        assert_eq!(0, records_on_disk());
+        drop(queue);
+
+        let (queue, _) = open(&path);
+        assert!(queue.enqueue("next".to_string(), true).unwrap() > waiting);
+    }
+
+    // Producers and consumers share a queue while another thread copies its
+    // journal and opens the copies, as a crash at any moment would leave it.
+    #[test]
+    fn concurrent_use_leaves_a_consistent_journal() {
+        const PRODUCERS: usize = 3;
+        const CONSUMERS: usize = 3;
+        const ITEMS: usize = 100;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("work.queue");
+        let (queue, _) = open(&path);
+        let queue = Arc::new(queue);
+        let producing = Arc::new(AtomicBool::new(true));
+        let consuming = Arc::new(AtomicBool::new(true));
+
+        let producers: Vec<_> = (0..PRODUCERS)
+            .map(|p| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    for i in 0..ITEMS {
+                        queue.enqueue(format!("{}-{}", p, i), true).unwrap();
+                    }
+                })
+            })
+            .collect();
+
+        let consumers: Vec<_> = (0..CONSUMERS)
+            .map(|_| {
+                let (queue, producing) = (queue.clone(), producing.clone());
+                thread::spawn(move || {
+                    let mut done = Vec::new();
+                    loop {
+                        match queue.start_next().unwrap() {
+                            Some(entry) => {
+                                queue.complete(entry.id).unwrap();
+                                done.push(entry.item);
+                            }
+                            None if producing.load(Ordering::SeqCst) => thread::yield_now(),
+                            None => return done,
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        let snapshots = {
+            let (path, consuming) = (path.clone(), consuming.clone());
+            let copy = dir.path().join("copy.queue");
+            thread::spawn(move || {
+                let mut restored = 0;
+                while consuming.load(Ordering::SeqCst) {
+                    if fs::copy(&path, &copy).is_err() {
+                        continue;
+                    }
+
+                    let (queue, _) = open(&copy);
+                    let entries = queue.entries();
+                    let unique: HashSet<&String> = entries.iter().map(|e| &e.item).collect();
+                    assert_eq!(entries.len(), unique.len());
+                    assert!(entries.iter().all(|entry| !entry.started));
+                    restored += 1;
+                }
+                restored
+            })
+        };
+
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        producing.store(false, Ordering::SeqCst);
+
+        let mut done: Vec<String> = consumers
+            .into_iter()
+            .flat_map(|consumer| consumer.join().unwrap())
+            .collect();
+        consuming.store(false, Ordering::SeqCst);
+        assert!(snapshots.join().unwrap() > 0);
+
+        // Every item was consumed exactly once, and nothing is left.
+        done.sort();
+        let mut expected: Vec<String> = (0..PRODUCERS)
+            .flat_map(|p| (0..ITEMS).map(move |i| format!("{}-{}", p, i)))
+            .collect();
+        expected.sort();
+        assert_eq!(expected, done);
+        assert!(queue.entries().is_empty());
+
+        drop(queue);
+        let (queue, recovery) = open(&path);
+        assert_eq!(QueueRecovery::default(), recovery);
+        assert!(queue.entries().is_empty());
+    }
+}
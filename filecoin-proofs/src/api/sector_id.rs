@@ -0,0 +1,102 @@
+//! The canonical encoding of a sector id, as the sector builder allocates and
+//! records it (a u64), into the 31 bytes seal and verification take, from
+//! which the sector's replica id is derived:
+//!
+//! ```text
+//! id (u64, little-endian) | zeroes (23 bytes)
+//! ```
+//!
+//! Any other encoding of the same id derives another replica id, so proofs made
+//! with one don't verify with the other. Callers over FFI encode ids with
+//! sector_id_as_bytes.
+
+use byteorder::{ByteOrder, LittleEndian};
+
+/// A sector's id, as the sector builder allocates it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SectorId(pub u64);
+
+#[derive(Debug, Fail, PartialEq)]
+#[fail(
+    display = "byte {} of the sector id is {}, but only its first 8 bytes may be non-zero",
+    index, value
+)]
+pub struct InvalidSectorId {
+    pub index: usize,
+    pub value: u8,
+}
+
+impl SectorId {
+    /// Encodes the id as seal and verification take it: its little-endian
+    /// bytes, followed by zeroes.
+    pub fn to_fr_safe(&self) -> [u8; 31] {
+        let mut bytes = [0; 31];
+        LittleEndian::write_u64(&mut bytes[..8], self.0);
+
+        bytes
+    }
+
+    /// Decodes an id encoded by to_fr_safe, refusing bytes of which any but
+    /// the first 8 is non-zero, as they encode no u64.
+    pub fn from_fr_safe(bytes: &[u8; 31]) -> Result<SectorId, InvalidSectorId> {
+        if let Some((index, value)) = bytes.iter().enumerate().skip(8).find(|(_, b)| **b != 0) {
+            return Err(InvalidSectorId {
+                index,
+                value: *value,
+            });
+        }
+
+        Ok(SectorId(LittleEndian::read_u64(&bytes[..8])))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(low: &[u8]) -> [u8; 31] {
+        let mut bytes = [0; 31];
+        bytes[..low.len()].copy_from_slice(low);
+        bytes
+    }
+
+    #[test]
+    fn ids_are_encoded_little_endian_into_the_low_bytes() {
+        assert_eq!([0; 31], SectorId(0).to_fr_safe());
+        assert_eq!(bytes(&[1]), SectorId(1).to_fr_safe());
+        assert_eq!(bytes(&[0, 1]), SectorId(256).to_fr_safe());
+        assert_eq!(
+            bytes(&[0xef, 0xcd, 0xab, 0x89, 0x67, 0x45, 0x23, 0x01]),
+            SectorId(0x0123_4567_89ab_cdef).to_fr_safe()
+        );
+        assert_eq!(bytes(&[0xff; 8]), SectorId(u64::max_value()).to_fr_safe());
+    }
+
+    #[test]
+    fn ids_round_trip() {
+        for id in &[0, 1, 256, 0x0123_4567_89ab_cdef, u64::max_value()] {
+            let sector_id = SectorId(*id);
+            assert_eq!(Ok(sector_id), SectorId::from_fr_safe(&sector_id.to_fr_safe()));
+        }
+    }
+
+    #[test]
+    fn bytes_beyond_a_u64_are_refused() {
+        let mut encoded = SectorId(7).to_fr_safe();
+        encoded[8] = 1;
+        assert_eq!(
+            Err(InvalidSectorId { index: 8, value: 1 }),
+            SectorId::from_fr_safe(&encoded)
+        );
+
+        let mut encoded = SectorId(7).to_fr_safe();
+        encoded[30] = 0x80;
+        assert_eq!(
+            Err(InvalidSectorId {
+                index: 30,
+                value: 0x80
+            }),
+            SectorId::from_fr_safe(&encoded)
+        );
+    }
+}
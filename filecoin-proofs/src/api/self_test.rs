@@ -0,0 +1,487 @@
+//! A health check of an installation, for operators to run once binaries and
+//! parameters are in place on a machine: it seals, unseals and proves a test
+//! sector in a scratch directory, checking every result, and checks that the
+//! parameters sectors of the size the machine will seal are proven with are
+//! installed and intact.
+//!
+//! The test store is created in a temporary directory of the scratch directory,
+//! which is removed once the test is done. The groth parameters of test sectors
+//! are read from the parameter cache, as any store's are, and generated there
+//! the first time they're needed. Those of the real sector size never are.
+
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use pairing::bls12_381::Bls12;
+use sector_base::api::disk_backed_storage::{
+    check_sector_bytes, new_sector_store, ConfiguredStore,
+};
+use sector_base::api::sector_store::SectorStore;
+use storage_proofs::parameter_cache::{parameter_cache_dir, read_cached_params};
+use storage_proofs::types::{Fr32Ary, FrSafe};
+use tempfile;
+
+use crate::api::internal::{
+    generate_post, get_unsealed_range, parameter_file_names, seal, verify_post, verify_seal,
+    PoStInput, PoStInputPart,
+};
+use crate::api::replica_format::LegacyReplicas;
+use crate::error;
+use crate::param::{get_parameter_data, get_parameter_file_digest, get_parameter_map};
+use crate::FCP_LOG;
+
+const PROVER_ID: FrSafe = [1; 31];
+const SECTOR_ID: FrSafe = [2; 31];
+const CHALLENGE_SEED: Fr32Ary = [3; 32];
+const DATA_BYTES: usize = 500;
+
+/// What run_self_test tests.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SelfTestConfig {
+    /// The directory the test store is created in, which must exist.
+    pub scratch_dir: PathBuf,
+    /// The size of the sectors whose parameters are checked: those of the
+    /// sectors the machine will seal.
+    pub sector_bytes: u64,
+    /// The parameter cache the parameters are checked in, if not the one
+    /// parameters are read from (see FILECOIN_PARAMETER_CACHE).
+    pub parameter_cache_dir: Option<PathBuf>,
+    /// The parameter map (as read by paramfetch) the parameters' digests are
+    /// checked against, if any. Without one, the parameters are only loaded.
+    pub parameter_map: Option<PathBuf>,
+}
+
+/// The steps of a self test, in the order they're run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum SelfTestStep {
+    /// Creating the test store's directories, and checking that sectors may
+    /// be provisioned in them.
+    ScratchDir,
+    /// Writing the test data to a staged sector.
+    WriteUnsealed,
+    /// Sealing the staged sector.
+    Seal,
+    /// Verifying the seal's proof.
+    VerifySeal,
+    /// Checking that the seal's proof, with a byte flipped, doesn't verify.
+    RejectCorruptedProof,
+    /// Unsealing the sealed sector, and comparing what it holds with the test
+    /// data.
+    Unseal,
+    /// Generating a PoSt of the sealed sector.
+    GeneratePost,
+    /// Verifying the PoSt.
+    VerifyPost,
+    /// Loading (and digest-checking) the parameters of the real sector size.
+    Parameters,
+}
+
+const ROUND_TRIP_STEPS: [SelfTestStep; 8] = [
+    SelfTestStep::ScratchDir,
+    SelfTestStep::WriteUnsealed,
+    SelfTestStep::Seal,
+    SelfTestStep::VerifySeal,
+    SelfTestStep::RejectCorruptedProof,
+    SelfTestStep::Unseal,
+    SelfTestStep::GeneratePost,
+    SelfTestStep::VerifyPost,
+];
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub enum StepStatus {
+    Passed,
+    Failed(String),
+    /// Not run, as a step it depends on failed.
+    Skipped,
+    /// The parameter files at these paths aren't installed. They're not
+    /// generated: doing so takes hours for real sectors.
+    ParametersMissing(Vec<PathBuf>),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StepReport {
+    pub step: SelfTestStep,
+    pub status: StepStatus,
+    pub elapsed: Duration,
+}
+
+/// What run_self_test found, step by step, in the order the steps were run.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SelfTestReport {
+    pub steps: Vec<StepReport>,
+}
+
+impl SelfTestReport {
+    /// Whether every step passed. Missing parameters are no pass: the machine
+    /// can't prove its sectors without them.
+    pub fn passed(&self) -> bool {
+        self.steps.iter().all(|s| s.status == StepStatus::Passed)
+    }
+
+    pub fn step(&self, step: SelfTestStep) -> Option<&StepReport> {
+        self.steps.iter().find(|s| s.step == step)
+    }
+}
+
+/// Runs every step of the self test, returning what each found and how long it
+/// took. A failure is reported as the step's, rather than returned, so this
+/// never fails: the steps which depend on a failed one are skipped, and the
+/// parameters are checked whatever the round trips found.
+pub fn run_self_test(config: &SelfTestConfig) -> SelfTestReport {
+    let mut report = SelfTestReport { steps: Vec::new() };
+
+    round_trips(config, &mut report);
+
+    for step in &ROUND_TRIP_STEPS {
+        if report.step(*step).is_none() {
+            report.steps.push(StepReport {
+                step: *step,
+                status: StepStatus::Skipped,
+                elapsed: Duration::default(),
+            });
+        }
+    }
+
+    let start = Instant::now();
+    let status = check_parameters(config).unwrap_or_else(|err| StepStatus::Failed(err.to_string()));
+    report.steps.push(StepReport {
+        step: SelfTestStep::Parameters,
+        status,
+        elapsed: start.elapsed(),
+    });
+
+    info!(FCP_LOG, "ran self test"; "report" => format!("{:?}", report));
+
+    report
+}
+
+// Runs a step of the round trips, recording what it found. Returns None if it
+// failed, so that the round trips stop there.
+fn run<T, F: FnOnce() -> error::Result<T>>(
+    report: &mut SelfTestReport,
+    step: SelfTestStep,
+    f: F,
+) -> Option<T> {
+    let start = Instant::now();
+    let result = f();
+
+    let (status, value) = match result {
+        Ok(value) => (StepStatus::Passed, Some(value)),
+        Err(err) => (StepStatus::Failed(err.to_string()), None),
+    };
+    report.steps.push(StepReport {
+        step,
+        status,
+        elapsed: start.elapsed(),
+    });
+
+    value
+}
+
+// Seals, unseals and proves the test sector in a test store in the scratch
+// directory, until a step fails. The store's directory is removed on return.
+fn round_trips(config: &SelfTestConfig, report: &mut SelfTestReport) -> Option<()> {
+    let data: Vec<u8> = (0..DATA_BYTES).map(|i| i as u8).collect();
+
+    let (dir, store) = run(report, SelfTestStep::ScratchDir, || {
+        let dir = tempfile::Builder::new()
+            .prefix("self-test")
+            .tempdir_in(&config.scratch_dir)?;
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+
+        let store = new_sector_store(&ConfiguredStore::Test, path("sealed"), path("staging"));
+        store.manager().prepare_dirs()?;
+
+        Ok((dir, store))
+    })?;
+
+    let staged = run(report, SelfTestStep::WriteUnsealed, || {
+        let access = store.manager().new_staging_sector_access()?;
+        let written = store.manager().write_and_preprocess(&access, &data)?;
+        if written != data.len() as u64 {
+            return Err(format_err!("wrote {} of {} bytes", written, data.len()));
+        }
+
+        Ok(access)
+    })?;
+
+    let (sealed, output) = run(report, SelfTestStep::Seal, || {
+        let access = store.manager().new_sealed_sector_access()?;
+        let output = seal(store.config(), &staged, &access, &PROVER_ID, &SECTOR_ID)?;
+
+        Ok((access, output))
+    })?;
+
+    let verify = |proof: &[u8]| {
+        verify_seal(
+            store.config(),
+            output.comm_r,
+            output.comm_d,
+            output.comm_r_star,
+            &PROVER_ID,
+            &SECTOR_ID,
+            proof,
+        )
+    };
+
+    run(report, SelfTestStep::VerifySeal, || {
+        if !verify(&output.proof)? {
+            return Err(format_err!("the seal's proof didn't verify"));
+        }
+
+        Ok(())
+    })?;
+
+    run(report, SelfTestStep::RejectCorruptedProof, || {
+        let mut proof = output.proof.clone();
+        let last = proof.len() - 1;
+        proof[last] ^= 1;
+
+        match verify(&proof) {
+            Ok(true) => Err(format_err!("the seal's proof verified with a byte flipped")),
+            Ok(false) | Err(_) => Ok(()),
+        }
+    })?;
+
+    run(report, SelfTestStep::Unseal, || {
+        let unsealed_path = dir.path().join("unsealed");
+        get_unsealed_range(
+            store.config(),
+            PathBuf::from(&sealed),
+            unsealed_path.clone(),
+            &PROVER_ID,
+            &SECTOR_ID,
+            0,
+            data.len() as u64,
+            LegacyReplicas::Refuse,
+        )?;
+
+        if fs::read(&unsealed_path)? != data {
+            return Err(format_err!("unsealed data differs from sealed data"));
+        }
+
+        Ok(())
+    })?;
+
+    let sector_bytes = store.config().sector_bytes();
+
+    let post = run(report, SelfTestStep::GeneratePost, || {
+        // A PoSt proves a fixed number of sectors: prove the one sealed twice.
+        let part = || PoStInputPart {
+            sealed_sector_access: Some(sealed.clone()),
+            comm_r: output.comm_r,
+            legacy_replicas: LegacyReplicas::Refuse,
+        };
+
+        generate_post(
+            sector_bytes,
+            PoStInput {
+                challenge_seed: CHALLENGE_SEED,
+                input_parts: vec![part(), part()],
+            },
+        )
+    })?;
+
+    run(report, SelfTestStep::VerifyPost, || {
+        let verified = verify_post(
+            sector_bytes,
+            &[output.comm_r, output.comm_r],
+            &CHALLENGE_SEED,
+            &post.snark_proof,
+            post.faults,
+        )?;
+
+        if !verified {
+            return Err(format_err!("the PoSt didn't verify"));
+        }
+
+        Ok(())
+    })?;
+
+    Some(())
+}
+
+// Loads the parameters of the real sector size, checking their digests first if
+// there's a parameter map to check them against.
+fn check_parameters(config: &SelfTestConfig) -> error::Result<StepStatus> {
+    check_sector_bytes(config.sector_bytes)?;
+
+    let cache_dir = config
+        .parameter_cache_dir
+        .clone()
+        .unwrap_or_else(parameter_cache_dir);
+
+    let paths: Vec<PathBuf> = parameter_file_names(config.sector_bytes, &cache_dir)
+        .into_iter()
+        .map(|name| cache_dir.join(name))
+        .collect();
+
+    let missing: Vec<PathBuf> = paths.iter().filter(|p| !p.exists()).cloned().collect();
+    if !missing.is_empty() {
+        return Ok(StepStatus::ParametersMissing(missing));
+    }
+
+    let parameter_map = match config.parameter_map {
+        Some(ref path) => Some(get_parameter_map(path)?),
+        None => None,
+    };
+
+    for path in &paths {
+        if let Some(ref parameter_map) = parameter_map {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            let expected = &get_parameter_data(parameter_map, &name)?.digest;
+            let digest = get_parameter_file_digest(path)?;
+
+            // variable-time: the digests are of published parameters, and
+            // both are reported when they differ.
+            if *expected != digest {
+                return Err(format_err!(
+                    "{:?} has digest {}, not {}",
+                    path,
+                    digest,
+                    expected
+                ));
+            }
+        }
+
+        read_cached_params::<Bls12>(path)
+            .map_err(|err| format_err!("failed to load {:?}: {}", path, err))?;
+    }
+
+    Ok(StepStatus::Passed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sector_base::api::disk_backed_storage::TEST_SECTOR_SIZE;
+
+    fn statuses(report: &SelfTestReport) -> Vec<(SelfTestStep, StepStatus)> {
+        report
+            .steps
+            .iter()
+            .map(|s| (s.step, s.status.clone()))
+            .collect()
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn a_healthy_installation_passes() {
+        let scratch = tempfile::tempdir().unwrap();
+
+        let report = run_self_test(&SelfTestConfig {
+            scratch_dir: scratch.path().to_path_buf(),
+            sector_bytes: TEST_SECTOR_SIZE,
+            parameter_cache_dir: None,
+            parameter_map: None,
+        });
+
+        assert!(report.passed(), "{:?}", report);
+        let steps: Vec<_> = report.steps.iter().map(|s| s.step).collect();
+        assert_eq!(&ROUND_TRIP_STEPS[..], &steps[..8]);
+        assert_eq!(SelfTestStep::Parameters, steps[8]);
+
+        // The test store is gone.
+        assert_eq!(0, fs::read_dir(scratch.path()).unwrap().count());
+    }
+
+    #[test]
+    fn an_unwritable_scratch_dir_fails_its_step() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // No directory can be created in a file, whoever the test runs as.
+        let scratch_file = dir.path().join("scratch");
+        fs::write(&scratch_file, b"not a directory").unwrap();
+        let cache_dir = dir.path().join("cache");
+
+        let report = run_self_test(&SelfTestConfig {
+            scratch_dir: scratch_file,
+            sector_bytes: TEST_SECTOR_SIZE,
+            parameter_cache_dir: Some(cache_dir.clone()),
+            parameter_map: None,
+        });
+
+        assert!(!report.passed());
+        let statuses = statuses(&report);
+        match statuses[0] {
+            (SelfTestStep::ScratchDir, StepStatus::Failed(_)) => (),
+            ref other => panic!("unexpected status: {:?}", other),
+        }
+        for (step, status) in &statuses[1..8] {
+            assert_eq!(StepStatus::Skipped, *status, "{:?}", step);
+        }
+
+        // Missing parameters are reported as such, not generated.
+        match statuses[8] {
+            (SelfTestStep::Parameters, StepStatus::ParametersMissing(ref paths)) => {
+                assert_eq!(2, paths.len());
+                assert!(paths.iter().all(|p| p.starts_with(&cache_dir)));
+            }
+            ref other => panic!("unexpected status: {:?}", other),
+        }
+        assert!(!cache_dir.exists());
+    }
+
+    #[test]
+    fn corrupted_parameters_fail_their_step() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        fs::create_dir(&cache_dir).unwrap();
+
+        for name in parameter_file_names(TEST_SECTOR_SIZE, &cache_dir) {
+            fs::write(cache_dir.join(name), &[7; 100][..]).unwrap();
+        }
+
+        let report = run_self_test(&SelfTestConfig {
+            // Fails the round trips fast: they're not what's tested here.
+            scratch_dir: dir.path().join("missing"),
+            sector_bytes: TEST_SECTOR_SIZE,
+            parameter_cache_dir: Some(cache_dir),
+            parameter_map: None,
+        });
+
+        match report.step(SelfTestStep::Parameters) {
+            Some(StepReport {
+                status: StepStatus::Failed(ref err),
+                ..
+            }) => assert!(err.contains("failed to load"), "{}", err),
+            other => panic!("unexpected report: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parameters_with_other_digests_fail_their_step() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_dir = dir.path().join("cache");
+        fs::create_dir(&cache_dir).unwrap();
+
+        let mut map = String::from("{");
+        for (i, name) in parameter_file_names(TEST_SECTOR_SIZE, &cache_dir)
+            .iter()
+            .enumerate()
+        {
+            fs::write(cache_dir.join(name), &[7; 100][..]).unwrap();
+            let separator = if i == 0 { "" } else { "," };
+            map += &format!(r#"{}"{}":{{"cid":"","digest":"00"}}"#, separator, name);
+        }
+        map += "}";
+        let map_path = dir.path().join("parameters.json");
+        fs::write(&map_path, map).unwrap();
+
+        let report = run_self_test(&SelfTestConfig {
+            scratch_dir: dir.path().join("missing"),
+            sector_bytes: TEST_SECTOR_SIZE,
+            parameter_cache_dir: Some(cache_dir),
+            parameter_map: Some(map_path),
+        });
+
+        match report.step(SelfTestStep::Parameters) {
+            Some(StepReport {
+                status: StepStatus::Failed(ref err),
+                ..
+            }) => assert!(err.contains("has digest"), "{}", err),
+            other => panic!("unexpected report: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,262 @@
+//! Reading a staged sector's file while something else may be writing to it.
+//!
+//! The locks a sector builder takes on its staged sectors only keep its own
+//! threads apart: another process appending to a staged file while it's read
+//! for sealing would leave the seal with a torn view of it, part old and part
+//! new, whose commitments match neither. So a staged file is read under a
+//! shared advisory lock, which keeps out writers taking the lock exclusively,
+//! and its fingerprint (its length, modification time, and digests of its
+//! first and last blocks) is compared before and after it's read. Should it
+//! have changed, it's read again, up to STAGED_READ_ATTEMPTS times in all,
+//! before failing with StagedDataChangedDuringSeal.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use blake2::{Blake2b, Digest};
+use fs2::FileExt;
+use slog::*;
+
+use crate::api::cancellation::{read_to_end_checked, CancellationToken};
+use crate::error;
+use crate::FCP_LOG;
+
+/// How many times a staged file which changes while it's read is read before
+/// giving up.
+pub const STAGED_READ_ATTEMPTS: usize = 3;
+
+// The bytes at either end of a staged file which are digested into its
+// fingerprint.
+const FINGERPRINT_BLOCK_BYTES: u64 = 4096;
+
+#[derive(Debug, Fail)]
+#[fail(
+    display = "the staged data at {:?} changed each of the {} times it was read",
+    path, attempts
+)]
+pub struct StagedDataChangedDuringSeal {
+    pub path: PathBuf,
+    pub attempts: usize,
+}
+
+// What's compared of a staged file before and after it's read.
+#[derive(Debug, PartialEq)]
+struct Fingerprint {
+    len: u64,
+    modified: Option<SystemTime>,
+    head: Vec<u8>,
+    tail: Vec<u8>,
+}
+
+fn fingerprint(file: &mut File) -> io::Result<Fingerprint> {
+    let metadata = file.metadata()?;
+    let len = metadata.len();
+
+    let mut digest_block = |offset: u64| -> io::Result<Vec<u8>> {
+        let mut block = Vec::new();
+        file.seek(SeekFrom::Start(offset))?;
+        (&mut *file)
+            .take(FINGERPRINT_BLOCK_BYTES)
+            .read_to_end(&mut block)?;
+
+        Ok(Blake2b::digest(&block).to_vec())
+    };
+
+    let head = digest_block(0)?;
+    let tail = digest_block(len.saturating_sub(FINGERPRINT_BLOCK_BYTES))?;
+
+    Ok(Fingerprint {
+        len,
+        modified: metadata.modified().ok(),
+        head,
+        tail,
+    })
+}
+
+/// Calls read, which reads the staged file at path, e.g. through a sector
+/// manager which decrypts it, holding a shared lock on the file, and returns
+/// what it read once the file is found unchanged by the read. Should it change
+/// during every one of STAGED_READ_ATTEMPTS reads, fails with
+/// StagedDataChangedDuringSeal.
+pub fn read_stable<T, F>(path: &Path, mut read: F) -> error::Result<T>
+where
+    F: FnMut() -> error::Result<T>,
+{
+    // The file is locked, and fingerprinted, through a handle of its own, so
+    // that read may open it however it reads it. The lock is released when
+    // the handle is closed.
+    let mut file = File::open(path)?;
+    file.lock_shared()?;
+
+    for attempt in 1..=STAGED_READ_ATTEMPTS {
+        let before = fingerprint(&mut file)?;
+        let result = read()?;
+
+        if fingerprint(&mut file)? == before {
+            return Ok(result);
+        }
+
+        warn!(FCP_LOG, "staged data changed while it was read"; "path" => format!("{:?}", path), "attempt" => attempt);
+    }
+
+    Err(StagedDataChangedDuringSeal {
+        path: path.to_path_buf(),
+        attempts: STAGED_READ_ATTEMPTS,
+    }
+    .into())
+}
+
+/// The staged data a seal reads.
+pub trait StagedSource {
+    /// Reads up to limit bytes of it, checking token between its chunks.
+    fn read_staged(self, limit: u64, token: &CancellationToken) -> error::Result<Vec<u8>>;
+}
+
+/// Staged data read from a reader is read as it is, however it changes.
+impl<R: Read> StagedSource for R {
+    fn read_staged(self, limit: u64, token: &CancellationToken) -> error::Result<Vec<u8>> {
+        read_to_end_checked(self, limit, token, "reading staged data")
+    }
+}
+
+/// A staged file, read as read_stable reads it.
+pub struct StagedFile {
+    path: PathBuf,
+}
+
+impl StagedFile {
+    pub fn new<T: AsRef<Path>>(path: T) -> StagedFile {
+        StagedFile {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl StagedSource for StagedFile {
+    fn read_staged(self, limit: u64, token: &CancellationToken) -> error::Result<Vec<u8>> {
+        read_stable(&self.path, || {
+            File::open(&self.path)?.read_staged(limit, token)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::{self, OpenOptions};
+    use std::io::{BufReader, Write};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::api::internal::compute_comm_d;
+
+    const SECTOR_BYTES: usize = 1024;
+
+    // Reads a few bytes at a time, sleeping before each read, to widen the
+    // window in which a write lands mid-read.
+    struct SlowReader<R>(R);
+
+    impl<R: Read> Read for SlowReader<R> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            thread::sleep(Duration::from_millis(1));
+            let n = buf.len().min(64);
+            self.0.read(&mut buf[..n])
+        }
+    }
+
+    // Computes comm_d of the file at path as read_stable reads it, slowly,
+    // while another thread appends to it during the first `appends` reads.
+    fn comm_d_while_appending(path: &Path, appends: usize) -> error::Result<[u8; 32]> {
+        let mut reads = 0;
+
+        read_stable(path, || {
+            reads += 1;
+
+            let appender = if reads <= appends {
+                let path = path.to_path_buf();
+                Some(thread::spawn(move || {
+                    thread::sleep(Duration::from_millis(5));
+                    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+                    file.write_all(&[2; 64]).unwrap();
+                }))
+            } else {
+                None
+            };
+
+            let file = File::open(path)?;
+            let comm_d = compute_comm_d(BufReader::new(SlowReader(file)), SECTOR_BYTES);
+
+            if let Some(appender) = appender {
+                appender.join().unwrap();
+            }
+
+            comm_d
+        })
+    }
+
+    #[test]
+    fn files_changed_mid_read_are_read_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("staged");
+
+        let old = vec![1; 512];
+        let mut new = old.clone();
+        new.extend_from_slice(&[2; 64]);
+
+        let old_comm_d = compute_comm_d(&old[..], SECTOR_BYTES).unwrap();
+        let new_comm_d = compute_comm_d(&new[..], SECTOR_BYTES).unwrap();
+
+        fs::write(&path, &old).unwrap();
+        assert_eq!(old_comm_d, comm_d_while_appending(&path, 0).unwrap());
+
+        // The read torn by the append is thrown away, and the file read again
+        // as it is once appended to.
+        let comm_d = comm_d_while_appending(&path, 1).unwrap();
+        assert_ne!(old_comm_d, comm_d);
+        assert_eq!(new_comm_d, comm_d);
+    }
+
+    #[test]
+    fn files_changing_during_every_read_fail() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("staged");
+        fs::write(&path, &[1; 512][..]).unwrap();
+
+        match comm_d_while_appending(&path, STAGED_READ_ATTEMPTS)
+            .map_err(|err| err.downcast::<StagedDataChangedDuringSeal>())
+        {
+            Err(Ok(err)) => {
+                assert_eq!(path, err.path);
+                assert_eq!(STAGED_READ_ATTEMPTS, err.attempts);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn reads_wait_for_exclusive_lockers() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("staged");
+        fs::write(&path, &[1; 512][..]).unwrap();
+
+        // A cooperating writer, holding the lock exclusively while it writes.
+        let writer = OpenOptions::new().append(true).open(&path).unwrap();
+        writer.lock_exclusive().unwrap();
+
+        let reader = {
+            let path = path.clone();
+            thread::spawn(move || StagedFile::new(&path).read_staged(1024, &Default::default()))
+        };
+
+        thread::sleep(Duration::from_millis(50));
+        (&writer).write_all(&[2; 64]).unwrap();
+        writer.unlock().unwrap();
+
+        let mut expected = vec![1; 512];
+        expected.extend_from_slice(&[2; 64]);
+        assert_eq!(expected, reader.join().unwrap().unwrap());
+    }
+}
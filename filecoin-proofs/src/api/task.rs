@@ -0,0 +1,478 @@
+//! Sealing, unsealing and verifying from async code. Each of these blocks the
+//! thread it's called on for as long as it takes (minutes, for a seal), which
+//! would starve an async runtime of the worker threads it polls its futures on.
+//! The functions here instead run them on a pool of threads of their own, and
+//! return futures of their results.
+//!
+//! The futures are those of the futures crate, so may be polled by any
+//! executor. The pool isn't tied to any runtime: its threads are plain std
+//! threads, which hand each result back through a oneshot channel.
+//!
+//! Dropping a future before it's ready cancels its operation, as cancelling a
+//! CancellationToken (see api::cancellation) would: one not yet started is
+//! skipped, and one started stops at its next checkpoint. Its TaskProgress
+//! shows where it got to.
+
+use std::cmp;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use futures::sync::oneshot;
+use futures::{Async, Future, Poll};
+use sector_base::api::sector_store::SectorConfig;
+use storage_proofs::types::{Commitment, FrSafe};
+
+use crate::api::cancellation::{CancellationToken, Interrupted};
+use crate::api::internal::{
+    seal_with_cancellation, unseal_range_with_cancellation, verify_seal, SealOutput,
+};
+use crate::api::parallelism;
+use crate::api::replica_format::LegacyReplicas;
+use crate::error;
+
+/// The number of threads in the pool unless set_pool_size says otherwise, or
+/// the effective parallelism (see parallelism) is lower. Each SectorBuilder
+/// seals sectors with as many workers as the pool has threads.
+pub const DEFAULT_POOL_SIZE: usize = 2;
+
+// The size set by set_pool_size, or fixed as the pool started, or 0 for none.
+static POOL_SIZE: AtomicUsize = AtomicUsize::new(0);
+
+const FATAL_NOLOCK: &str = "error acquiring task pool lock";
+const FATAL_NORECV: &str = "error receiving task";
+const FATAL_NOSEND: &str = "error sending task";
+
+// A task's work, boxed to be sent to the pool. A boxed FnOnce can't be called,
+// so tasks are run through this instead.
+trait Job: Send {
+    fn run(self: Box<Self>);
+}
+
+impl<F: FnOnce() + Send> Job for F {
+    fn run(self: Box<Self>) {
+        (*self)()
+    }
+}
+
+lazy_static! {
+    // The pool's queue, once the pool has started.
+    static ref POOL: Mutex<Option<mpsc::Sender<Box<Job>>>> = Mutex::new(None);
+}
+
+/// The number of threads tasks are run on, which is also the number of seal
+/// workers each SectorBuilder starts.
+pub fn pool_size() -> usize {
+    match POOL_SIZE.load(Ordering::SeqCst) {
+        0 => cmp::min(DEFAULT_POOL_SIZE, parallelism::get_effective_parallelism()),
+        threads => threads,
+    }
+}
+
+/// Sets the number of threads tasks are run on (and SectorBuilders seal on).
+/// The pool starts with the first task, after which its size can't be changed.
+pub fn set_pool_size(threads: usize) -> error::Result<()> {
+    if threads == 0 {
+        return Err(format_err!("the task pool must have at least one thread"));
+    }
+
+    let pool = POOL.lock().expect(FATAL_NOLOCK);
+    if pool.is_some() {
+        return Err(format_err!(
+            "the task pool has started with {} threads",
+            pool_size()
+        ));
+    }
+
+    POOL_SIZE.store(threads, Ordering::SeqCst);
+
+    Ok(())
+}
+
+fn start_pool(threads: usize) -> mpsc::Sender<Box<Job>> {
+    let (tx, rx) = mpsc::channel::<Box<Job>>();
+    let rx = Arc::new(Mutex::new(rx));
+
+    for n in 0..threads {
+        let rx = rx.clone();
+
+        thread::Builder::new()
+            .name(format!("fcp-task-{}", n))
+            .spawn(move || loop {
+                let job = {
+                    let rx = rx.lock().expect(FATAL_NOLOCK);
+                    rx.recv().expect(FATAL_NORECV)
+                };
+
+                // A task which panics drops its end of the channel unanswered,
+                // which fails its future. The thread lives on for the next.
+                let _ = panic::catch_unwind(AssertUnwindSafe(|| job.run()));
+            })
+            .expect("failed to start task pool thread");
+    }
+
+    tx
+}
+
+/// Where a task has got to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TaskStatus {
+    /// Waiting for a thread of the pool.
+    Queued,
+    Running,
+    Done,
+    /// Cancelled (by dropping its future) before it finished, or before it
+    /// started.
+    Cancelled,
+    /// Failed with the given error.
+    Failed(String),
+}
+
+/// Shows where a task has got to, even once its future has been dropped.
+#[derive(Debug, Clone)]
+pub struct TaskProgress(Arc<Mutex<TaskStatus>>);
+
+impl TaskProgress {
+    pub fn status(&self) -> TaskStatus {
+        self.0.lock().expect(FATAL_NOLOCK).clone()
+    }
+
+    fn set(&self, status: TaskStatus) {
+        *self.0.lock().expect(FATAL_NOLOCK) = status;
+    }
+}
+
+/// A future of the result of an operation run on the task pool. Dropping it
+/// before it's ready cancels the operation.
+#[must_use = "tasks are cancelled when dropped"]
+pub struct Task<T> {
+    result: oneshot::Receiver<error::Result<T>>,
+    token: CancellationToken,
+    progress: TaskProgress,
+    finished: bool,
+}
+
+impl<T> Task<T> {
+    pub fn progress(&self) -> TaskProgress {
+        self.progress.clone()
+    }
+}
+
+impl<T> Future for Task<T> {
+    type Item = T;
+    type Error = failure::Error;
+
+    fn poll(&mut self) -> Poll<T, failure::Error> {
+        match self.result.poll() {
+            Ok(Async::Ready(result)) => {
+                self.finished = true;
+                result.map(Async::Ready)
+            }
+            Ok(Async::NotReady) => Ok(Async::NotReady),
+            Err(oneshot::Canceled) => {
+                self.finished = true;
+                Err(format_err!("task panicked"))
+            }
+        }
+    }
+}
+
+impl<T> Drop for Task<T> {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.token.cancel();
+        }
+    }
+}
+
+/// Runs f on the task pool, with a token which is cancelled should the task's
+/// future be dropped before it's ready.
+pub(crate) fn spawn<T, F>(f: F) -> Task<T>
+where
+    T: Send + 'static,
+    F: FnOnce(&CancellationToken) -> error::Result<T> + Send + 'static,
+{
+    let (tx, rx) = oneshot::channel();
+    let token = CancellationToken::new();
+    let progress = TaskProgress(Arc::new(Mutex::new(TaskStatus::Queued)));
+
+    let job = {
+        let token = token.clone();
+        let progress = progress.clone();
+
+        move || {
+            let result = token
+                .checkpoint("queued")
+                .map_err(failure::Error::from)
+                .and_then(|_| {
+                    progress.set(TaskStatus::Running);
+                    f(&token)
+                });
+
+            progress.set(match result {
+                Ok(_) => TaskStatus::Done,
+                Err(ref err) => match err.downcast_ref::<Interrupted>() {
+                    Some(Interrupted::Cancelled { .. }) => TaskStatus::Cancelled,
+                    _ => TaskStatus::Failed(err.to_string()),
+                },
+            });
+
+            // Nobody's waiting for the result if the future has been dropped.
+            let _ = tx.send(result);
+        }
+    };
+
+    POOL.lock()
+        .expect(FATAL_NOLOCK)
+        .get_or_insert_with(|| {
+            // The pool keeps its size, whatever the parallelism becomes.
+            let threads = pool_size();
+            POOL_SIZE.store(threads, Ordering::SeqCst);
+            start_pool(threads)
+        })
+        .send(Box::new(job))
+        .expect(FATAL_NOSEND);
+
+    Task {
+        result: rx,
+        token,
+        progress,
+        finished: false,
+    }
+}
+
+/// What seal_async seals, and where to.
+#[derive(Debug, Clone)]
+pub struct SealParams {
+    pub staged_path: PathBuf,
+    pub sealed_path: PathBuf,
+    pub prover_id: FrSafe,
+    pub sector_id: FrSafe,
+}
+
+/// Seals as internal::seal does, on the task pool. Should the future be
+/// dropped, sealing is cancelled as internal::seal_with_cancellation is, which
+/// removes the replica.
+pub fn seal_async(config: Arc<SectorConfig>, params: SealParams) -> Task<SealOutput> {
+    spawn(move |token| {
+        seal_with_cancellation(
+            config.as_ref(),
+            &params.staged_path,
+            &params.sealed_path,
+            &params.prover_id,
+            &params.sector_id,
+            token,
+        )
+    })
+}
+
+/// What unseal_async unseals.
+#[derive(Debug, Clone)]
+pub struct UnsealParams {
+    pub sealed_path: PathBuf,
+    pub prover_id: FrSafe,
+    pub sector_id: FrSafe,
+    pub offset: u64,
+    pub num_bytes: u64,
+    pub legacy: LegacyReplicas,
+}
+
+/// Unseals and returns a range of a sector's data as internal::unseal_range
+/// does, on the task pool. Should the future be dropped, unsealing stops at its
+/// next checkpoint.
+pub fn unseal_async(config: Arc<SectorConfig>, params: UnsealParams) -> Task<Vec<u8>> {
+    spawn(move |token| {
+        unseal_range_with_cancellation(
+            config.as_ref(),
+            &params.sealed_path,
+            &params.prover_id,
+            &params.sector_id,
+            params.offset,
+            params.num_bytes,
+            params.legacy,
+            token,
+        )
+    })
+}
+
+/// The proof verify_seal_async verifies, and what it proves.
+#[derive(Debug, Clone)]
+pub struct VerifySealParams {
+    pub comm_r: Commitment,
+    pub comm_d: Commitment,
+    pub comm_r_star: Commitment,
+    pub prover_id: FrSafe,
+    pub sector_id: FrSafe,
+    pub proof: Vec<u8>,
+}
+
+/// Verifies a seal proof as internal::verify_seal does, on the task pool.
+/// Verification has no checkpoints, so dropping the future only cancels it if
+/// it hasn't started.
+pub fn verify_seal_async(config: Arc<SectorConfig>, params: VerifySealParams) -> Task<bool> {
+    spawn(move |_| {
+        verify_seal(
+            config.as_ref(),
+            params.comm_r,
+            params.comm_d,
+            params.comm_r_star,
+            &params.prover_id,
+            &params.sector_id,
+            &params.proof,
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::time::{Duration, Instant};
+
+    use futures::future;
+    use sector_base::api::disk_backed_storage::{
+        new_sector_config_with_proof_variant, ConfiguredStore,
+    };
+    use sector_base::api::sector_store::ProofVariant;
+
+    // Waits up to 10 seconds for the task to reach status.
+    fn wait_for(progress: &TaskProgress, status: TaskStatus) {
+        let start = Instant::now();
+
+        while progress.status() != status {
+            assert!(
+                start.elapsed() < Duration::from_secs(10),
+                "still {:?}",
+                progress.status()
+            );
+            thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[test]
+    fn tasks_run_off_the_callers_thread() {
+        let caller = thread::current().id();
+        let tasks: Vec<_> = (0..4)
+            .map(|_| spawn(|_| Ok(thread::current().id())))
+            .collect();
+
+        for id in future::join_all(tasks).wait().unwrap() {
+            assert_ne!(caller, id);
+        }
+    }
+
+    #[test]
+    fn dropped_tasks_are_cancelled() {
+        let task = spawn(|token| -> error::Result<()> {
+            loop {
+                token.checkpoint("waiting")?;
+                thread::sleep(Duration::from_millis(10));
+            }
+        });
+        let progress = task.progress();
+
+        wait_for(&progress, TaskStatus::Running);
+        drop(task);
+        wait_for(&progress, TaskStatus::Cancelled);
+    }
+
+    #[test]
+    fn tasks_fail_with_their_operation() {
+        let task = spawn(|_| -> error::Result<()> { Err(format_err!("injected failure")) });
+        let progress = task.progress();
+
+        assert_eq!("injected failure", task.wait().unwrap_err().to_string());
+        assert_eq!(
+            TaskStatus::Failed("injected failure".to_string()),
+            progress.status()
+        );
+
+        let task = spawn(|_| -> error::Result<()> { panic!("injected panic") });
+        assert_eq!("task panicked", task.wait().unwrap_err().to_string());
+    }
+
+    #[test]
+    fn pools_cant_be_resized_once_started() {
+        spawn(|_| Ok(())).wait().unwrap();
+
+        assert!(set_pool_size(pool_size() + 1).is_err());
+        assert!(set_pool_size(0).is_err());
+    }
+
+    fn seal_params(dir: &tempfile::TempDir, n: u8) -> SealParams {
+        let staged_path = dir.path().join(format!("staged-{}", n));
+        fs::write(&staged_path, &[n; 500]).unwrap();
+
+        SealParams {
+            staged_path,
+            sealed_path: dir.path().join(format!("sealed-{}", n)),
+            prover_id: [1; 31],
+            sector_id: [n; 31],
+        }
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn concurrent_seals_verify() {
+        let config: Arc<SectorConfig> = Arc::from(new_sector_config_with_proof_variant(
+            &ConfiguredStore::Test,
+            ProofVariant::Vanilla,
+        ));
+        let dir = tempfile::tempdir().unwrap();
+        let params: Vec<SealParams> = (0..3).map(|n| seal_params(&dir, n)).collect();
+
+        let seals = params
+            .iter()
+            .map(|params| seal_async(config.clone(), params.clone()));
+        let outputs = future::join_all(seals).wait().expect("failed to seal");
+
+        let verifies = params.iter().zip(&outputs).map(|(params, output)| {
+            verify_seal_async(
+                config.clone(),
+                VerifySealParams {
+                    comm_r: output.comm_r,
+                    comm_d: output.comm_d,
+                    comm_r_star: output.comm_r_star,
+                    prover_id: params.prover_id,
+                    sector_id: params.sector_id,
+                    proof: output.proof.clone(),
+                },
+            )
+        });
+        let verified = future::join_all(verifies).wait().expect("failed to verify");
+        assert_eq!(vec![true; 3], verified);
+
+        let unsealed = unseal_async(
+            config.clone(),
+            UnsealParams {
+                sealed_path: params[2].sealed_path.clone(),
+                prover_id: params[2].prover_id,
+                sector_id: params[2].sector_id,
+                offset: 0,
+                num_bytes: 500,
+                legacy: LegacyReplicas::Refuse,
+            },
+        );
+        assert_eq!(vec![2; 500], unsealed.wait().expect("failed to unseal"));
+    }
+
+    #[test]
+    #[ignore] // Slow test – run only when compiled for release.
+    fn dropped_seals_are_cancelled() {
+        let config: Arc<SectorConfig> = Arc::from(new_sector_config_with_proof_variant(
+            &ConfiguredStore::Test,
+            ProofVariant::Vanilla,
+        ));
+        let dir = tempfile::tempdir().unwrap();
+        let params = seal_params(&dir, 7);
+
+        let task = seal_async(config, params.clone());
+        let progress = task.progress();
+        drop(task);
+
+        wait_for(&progress, TaskStatus::Cancelled);
+        assert!(!params.sealed_path.exists());
+    }
+}
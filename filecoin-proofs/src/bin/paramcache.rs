@@ -1,26 +1,73 @@
 extern crate filecoin_proofs;
 extern crate rand;
 extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
 extern crate storage_proofs;
 
+use clap::App;
 use filecoin_proofs::api::internal;
+use filecoin_proofs::cli::{finish, json_arg, OutputFormat, Report};
+use filecoin_proofs::error::Result;
 use pairing::bls12_381::Bls12;
+use std::time::{Duration, Instant};
 
 use sector_base::api::disk_backed_storage::{LIVE_SECTOR_SIZE, TEST_SECTOR_SIZE};
 use storage_proofs::circuit::vdf_post::{VDFPoStCircuit, VDFPostCompound};
 use storage_proofs::circuit::zigzag::ZigZagCompound;
 use storage_proofs::compound_proof::CompoundProof;
 use storage_proofs::hasher::pedersen::PedersenHasher;
-use storage_proofs::parameter_cache::CacheableParameters;
+use storage_proofs::parameter_cache::{parameter_cache_dir, CacheableParameters};
 use storage_proofs::vdf_post::VDFPoSt;
 use storage_proofs::vdf_sloth::Sloth;
 
 const GENERATE_POST_PARAMS: bool = false;
 
-fn cache_params(sector_size: u64) {
+// The seal parameters of a sector size: the file they're cached in, whether
+// they were cached already, and how long reading or generating them took.
+#[derive(Serialize)]
+struct CachedParams {
+    sector_bytes: u64,
+    parameter_file: String,
+    already_cached: bool,
+    elapsed: Duration,
+}
+
+#[derive(Serialize)]
+struct ParamCacheReport {
+    cached: Vec<CachedParams>,
+}
+
+impl Report for ParamCacheReport {
+    fn human(&self) -> String {
+        self.cached
+            .iter()
+            .map(|c| {
+                let how = if c.already_cached {
+                    "found"
+                } else {
+                    "generated"
+                };
+
+                format!(
+                    "{}-byte sectors: {} {} ({:?})\n",
+                    c.sector_bytes, how, c.parameter_file, c.elapsed
+                )
+            })
+            .collect()
+    }
+}
+
+fn cache_params(sector_size: u64) -> Result<CachedParams> {
+    let start = Instant::now();
+
+    let cache_dir = parameter_cache_dir();
+    let parameter_file = internal::parameter_file_names(sector_size, &cache_dir).remove(0);
+    let already_cached = cache_dir.join(&parameter_file).exists();
+
     let public_params = internal::public_params(sector_size as usize);
     let circuit = ZigZagCompound::blank_circuit(&public_params, &internal::ENGINE_PARAMS);
-    let _ = ZigZagCompound::get_groth_params(circuit, &public_params);
+    ZigZagCompound::get_groth_params(circuit, &public_params)?;
 
     if GENERATE_POST_PARAMS {
         let post_public_params = internal::post_public_params(sector_size as usize);
@@ -30,12 +77,33 @@ fn cache_params(sector_size: u64) {
                 VDFPoSt<PedersenHasher, Sloth>,
                 VDFPoStCircuit<Bls12>,
             >>::blank_circuit(&post_public_params, &internal::ENGINE_PARAMS);
-        let _ = VDFPostCompound::get_groth_params(post_circuit, &post_public_params);
+        VDFPostCompound::get_groth_params(post_circuit, &post_public_params)?;
     }
+
+    Ok(CachedParams {
+        sector_bytes: sector_size,
+        parameter_file,
+        already_cached,
+        elapsed: start.elapsed(),
+    })
+}
+
+fn run() -> Result<ParamCacheReport> {
+    let cached = vec![
+        cache_params(TEST_SECTOR_SIZE)?,
+        cache_params(LIVE_SECTOR_SIZE)?,
+    ];
+
+    Ok(ParamCacheReport { cached })
 }
 
 // Run this from the command-line to pre-generate the groth parameters used by the API.
 pub fn main() {
-    cache_params(TEST_SECTOR_SIZE);
-    cache_params(LIVE_SECTOR_SIZE);
+    let matches = App::new("paramcache")
+        .version("1.0")
+        .about("Generates the groth parameters of test and live sectors, if they aren't cached")
+        .arg(json_arg())
+        .get_matches();
+
+    finish(OutputFormat::from_matches(&matches), run())
 }
@@ -1,9 +1,138 @@
-use clap::{App, AppSettings, Arg, SubCommand};
+#[macro_use]
+extern crate serde_derive;
 
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use failure::format_err;
+
+use filecoin_proofs::cli::{finish, json_arg, OutputFormat, Report};
 use filecoin_proofs::param::*;
 use std::path::PathBuf;
 use storage_proofs::parameter_cache::PARAMETER_CACHE_DIR;
 
+// A parameter fetch tried, and the error it failed with, if it did.
+#[derive(Serialize)]
+struct FetchedParameter {
+    parameter_id: String,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct FetchReport {
+    fetched: Vec<FetchedParameter>,
+}
+
+impl Report for FetchReport {
+    fn human(&self) -> String {
+        if self.fetched.is_empty() {
+            return "nothing to fetch\n".to_string();
+        }
+
+        self.fetched
+            .iter()
+            .map(|fetched| match fetched.error {
+                Some(ref err) => format!("'{}': error: {}\n", fetched.parameter_id, err),
+                None => format!("'{}': ok\n", fetched.parameter_id),
+            })
+            .collect()
+    }
+
+    fn failure(&self) -> Option<String> {
+        let failed = self.fetched.iter().filter(|f| f.error.is_some()).count();
+
+        if failed > 0 {
+            Some(format!("{} parameters failed to fetch", failed))
+        } else {
+            None
+        }
+    }
+}
+
+// A mapped parameter, and whether it's in the parameter cache.
+#[derive(Serialize)]
+struct CheckedParameter {
+    parameter_id: String,
+    local: bool,
+}
+
+#[derive(Serialize)]
+struct CheckReport {
+    parameters: Vec<CheckedParameter>,
+}
+
+impl Report for CheckReport {
+    fn human(&self) -> String {
+        self.parameters
+            .iter()
+            .map(|p| {
+                let check = if p.local { "☑" } else { "☐" };
+
+                format!("{} {}\n", check, p.parameter_id)
+            })
+            .collect()
+    }
+}
+
+fn parameter_map(matches: &ArgMatches) -> Result<ParameterMap> {
+    let path = PathBuf::from(
+        matches
+            .value_of("parameter-map")
+            .unwrap_or("./parameters.json"),
+    );
+
+    get_parameter_map(&path).map_err(|err| format_err!("{}: {}", ERROR_PARAMETERS_MAPPED, err))
+}
+
+fn fetch(matches: &ArgMatches) -> Result<FetchReport> {
+    let parameter_map = parameter_map(matches)?;
+
+    let parameter_ids = if matches.is_present("all") {
+        get_mapped_parameter_ids(&parameter_map)?
+    } else if OutputFormat::from_matches(matches) == OutputFormat::Json {
+        return Err(format_err!(
+            "parameters are chosen interactively: pass --all with --json"
+        ));
+    } else {
+        choose_mapped_parameter_ids(&parameter_map)?
+    };
+
+    let fetched = parameter_ids
+        .into_iter()
+        .map(|parameter_id| {
+            // Fetches take a while, so progress goes to stderr as they're made.
+            eprintln!("fetching '{}'...", parameter_id);
+
+            let error = fetch_parameter_file(&parameter_map, &parameter_id)
+                .err()
+                .map(|err| err.to_string());
+
+            FetchedParameter {
+                parameter_id,
+                error,
+            }
+        })
+        .collect();
+
+    Ok(FetchReport { fetched })
+}
+
+fn check(matches: &ArgMatches) -> Result<CheckReport> {
+    let parameter_map = parameter_map(matches)?;
+
+    let mapped_parameters = get_mapped_parameter_ids(&parameter_map)?;
+    let local_parameters = get_local_parameter_ids()
+        .map_err(|err| format_err!("{}: {}", ERROR_PARAMETERS_LOCAL, err))?;
+
+    let parameters = mapped_parameters
+        .into_iter()
+        .map(|parameter_id| CheckedParameter {
+            local: local_parameters.contains(&parameter_id),
+            parameter_id,
+        })
+        .collect();
+
+    Ok(CheckReport { parameters })
+}
+
 pub fn main() {
     let matches = App::new("paramfetch")
         .setting(AppSettings::ArgRequiredElseHelp)
@@ -18,14 +147,15 @@ Set $FILECOIN_PARAMETER_CACHE to specify parameter directory. Defaults to '{}'
             )[..],
         )
         .arg(
-            Arg::with_name("json")
+            Arg::with_name("parameter-map")
                 .global(true)
                 .value_name("JSON")
                 .takes_value(true)
-                .short("j")
-                .long("json")
+                .short("m")
+                .long("parameter-map")
                 .help("Use specific json file"),
         )
+        .arg(json_arg())
         .subcommand(
             SubCommand::with_name("fetch")
                 .arg(
@@ -41,43 +171,11 @@ Set $FILECOIN_PARAMETER_CACHE to specify parameter directory. Defaults to '{}'
         )
         .get_matches();
 
-    let json = PathBuf::from(matches.value_of("json").unwrap_or("./parameters.json"));
-    let parameter_map = get_parameter_map(&json).expect(ERROR_PARAMETERS_MAPPED);
-
     if let Some(matches) = matches.subcommand_matches("fetch") {
-        let parameter_ids = if matches.is_present("all") {
-            get_mapped_parameter_ids(&parameter_map)
-        } else {
-            choose_mapped_parameter_ids(&parameter_map)
-        }
-        .expect(ERROR_PARAMETERS_MAPPED);
-
-        if !parameter_ids.is_empty() {
-            println!("fetching parameters");
-
-            for parameter_id in parameter_ids.iter() {
-                println!("fetching '{}'...", parameter_id);
-
-                match fetch_parameter_file(&parameter_map, &parameter_id) {
-                    Ok(_) => println!("ok"),
-                    Err(err) => println!("error: {}", err),
-                }
-            }
-        } else {
-            println!("nothing to fetch");
-        }
+        finish(OutputFormat::from_matches(matches), fetch(matches));
     }
 
-    if matches.subcommand_matches("check").is_some() {
-        let mapped_parameters =
-            get_mapped_parameter_ids(&parameter_map).expect(ERROR_PARAMETERS_MAPPED);
-        let local_parameters = get_local_parameter_ids().expect(ERROR_PARAMETERS_LOCAL);
-
-        mapped_parameters.iter().for_each(|p| {
-            let local = local_parameters.contains(p);
-            let check = if local { "☑" } else { "☐" };
-
-            println!("{} {}", check, p);
-        });
+    if let Some(matches) = matches.subcommand_matches("check") {
+        finish(OutputFormat::from_matches(matches), check(matches));
     }
 }
@@ -2,39 +2,89 @@ extern crate filecoin_proofs;
 extern crate phase2;
 extern crate rand;
 extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
 extern crate storage_proofs;
 
+use clap::{App, Arg};
+use failure::format_err;
 use rand::OsRng;
-use std::env;
 use std::fs::File;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
 use filecoin_proofs::api::internal;
+use filecoin_proofs::cli::{finish, json_arg, OutputFormat, Report};
+use filecoin_proofs::error::Result;
 use sector_base::api::disk_backed_storage::LIVE_SECTOR_SIZE;
 use storage_proofs::circuit::zigzag::ZigZagCompound;
 use storage_proofs::compound_proof::CompoundProof;
 
-// Run this from the command-line, passing the path to the file to which the parameters will be written.
-pub fn main() {
-    let args: Vec<String> = env::args().collect();
-    let out_file = &args[1];
+// The parameters written, and the hash of the contribution made to them.
+#[derive(Serialize)]
+struct ParamGenReport {
+    out_file: PathBuf,
+    contribution: String,
+    elapsed: Duration,
+}
+
+impl Report for ParamGenReport {
+    fn human(&self) -> String {
+        format!(
+            "wrote {} ({:?}), contribution {}\n",
+            self.out_file.display(),
+            self.elapsed,
+            self.contribution
+        )
+    }
+}
+
+fn run(out_file: PathBuf) -> Result<ParamGenReport> {
+    let start = Instant::now();
 
     let public_params = internal::public_params(LIVE_SECTOR_SIZE as usize);
 
     let circuit = ZigZagCompound::blank_circuit(&public_params, &internal::ENGINE_PARAMS);
-    let mut params = phase2::MPCParameters::new(circuit).unwrap();
+    let mut params = phase2::MPCParameters::new(circuit)?;
 
-    let rng = &mut OsRng::new().unwrap();
+    let rng = &mut OsRng::new()?;
     let hash = params.contribute(rng);
 
     {
         let circuit = ZigZagCompound::blank_circuit(&public_params, &internal::ENGINE_PARAMS);
-        let contributions = params.verify(circuit).expect("parameters should be valid!");
+        let contributions = params
+            .verify(circuit)
+            .map_err(|_| format_err!("the parameters generated aren't valid"))?;
 
         // We need to check the `contributions` to see if our `hash`
         // is in it (see above, when we first contributed)
         assert!(phase2::contains_contribution(&contributions, &hash));
     }
 
-    let mut buffer = File::create(out_file).unwrap();
-    params.write(&mut buffer).unwrap();
+    let mut buffer = File::create(&out_file)?;
+    params.write(&mut buffer)?;
+
+    Ok(ParamGenReport {
+        out_file,
+        contribution: hash.iter().map(|b| format!("{:02x}", b)).collect(),
+        elapsed: start.elapsed(),
+    })
+}
+
+// Run this from the command-line, passing the path to the file to which the parameters will be written.
+pub fn main() {
+    let matches = App::new("paramgen")
+        .version("1.0")
+        .arg(
+            Arg::with_name("out-file")
+                .value_name("FILE")
+                .required(true)
+                .help("File to write the parameters to"),
+        )
+        .arg(json_arg())
+        .get_matches();
+
+    let out_file = PathBuf::from(matches.value_of("out-file").unwrap());
+
+    finish(OutputFormat::from_matches(&matches), run(out_file))
 }
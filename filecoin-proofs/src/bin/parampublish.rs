@@ -1,10 +1,166 @@
-use clap::{App, AppSettings, Arg, SubCommand};
+#[macro_use]
+extern crate serde_derive;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use failure::format_err;
 use std::collections::HashMap;
 use std::path::PathBuf;
 
+use filecoin_proofs::cli::{finish, json_arg, OutputFormat, Report};
 use filecoin_proofs::param::*;
 use storage_proofs::parameter_cache::PARAMETER_CACHE_DIR;
 
+// A parameter publish tried: the cid and digest it was published with, or
+// the error it failed with.
+#[derive(Serialize)]
+struct PublishedParameter {
+    parameter_id: String,
+    cid: Option<String>,
+    digest: Option<String>,
+    error: Option<String>,
+}
+
+#[derive(Serialize)]
+struct PublishReport {
+    parameter_map: PathBuf,
+    published: Vec<PublishedParameter>,
+}
+
+impl Report for PublishReport {
+    fn human(&self) -> String {
+        if self.published.is_empty() {
+            return "nothing to publish\n".to_string();
+        }
+
+        self.published
+            .iter()
+            .map(|published| match published.error {
+                Some(ref err) => format!("'{}': err: {}\n", published.parameter_id, err),
+                None => format!("'{}': ok.\n", published.parameter_id),
+            })
+            .collect()
+    }
+
+    fn failure(&self) -> Option<String> {
+        let failed = self.published.iter().filter(|p| p.error.is_some()).count();
+
+        if failed > 0 {
+            Some(format!("{} parameters failed to publish", failed))
+        } else {
+            None
+        }
+    }
+}
+
+// A local parameter, and whether it's in the parameter map.
+#[derive(Serialize)]
+struct CheckedParameter {
+    parameter_id: String,
+    mapped: bool,
+}
+
+#[derive(Serialize)]
+struct CheckReport {
+    parameters: Vec<CheckedParameter>,
+}
+
+impl Report for CheckReport {
+    fn human(&self) -> String {
+        self.parameters
+            .iter()
+            .map(|p| {
+                let check = if p.mapped { "☑" } else { "☐" };
+
+                format!("{} {}\n", check, p.parameter_id)
+            })
+            .collect()
+    }
+}
+
+fn parameter_map_path(matches: &ArgMatches) -> PathBuf {
+    PathBuf::from(
+        matches
+            .value_of("parameter-map")
+            .unwrap_or("./parameters.json"),
+    )
+}
+
+fn publish(matches: &ArgMatches) -> Result<PublishReport> {
+    let parameter_ids = if matches.is_present("all") {
+        get_local_parameter_ids()
+    } else if OutputFormat::from_matches(matches) == OutputFormat::Json {
+        return Err(format_err!(
+            "parameters are chosen interactively: pass --all with --json"
+        ));
+    } else {
+        choose_local_parameter_ids()
+    }
+    .map_err(|err| format_err!("{}: {}", ERROR_PARAMETERS_LOCAL, err))?;
+
+    let mut new_parameter_map: ParameterMap = HashMap::new();
+    let mut published = Vec::new();
+
+    for parameter_id in parameter_ids.into_iter() {
+        // Publishes take a while, so progress goes to stderr as they're made.
+        eprintln!("publishing '{}'...", parameter_id);
+
+        let result = publish_parameter_file(&parameter_id).and_then(|cid| {
+            let digest = get_parameter_digest(&parameter_id)
+                .map_err(|err| format_err!("{}: {}", ERROR_DIGEST, err))?;
+
+            Ok(ParameterData { cid, digest })
+        });
+
+        match result {
+            Ok(data) => {
+                published.push(PublishedParameter {
+                    parameter_id: parameter_id.clone(),
+                    cid: Some(data.cid.clone()),
+                    digest: Some(data.digest.clone()),
+                    error: None,
+                });
+                new_parameter_map.insert(parameter_id, data);
+            }
+            Err(err) => published.push(PublishedParameter {
+                parameter_id,
+                cid: None,
+                digest: None,
+                error: Some(err.to_string()),
+            }),
+        }
+    }
+
+    let path = parameter_map_path(matches);
+    if !published.is_empty() {
+        save_parameter_map(&new_parameter_map, &path)
+            .map_err(|err| format_err!("{}: {}", ERROR_PARAMETER_MAP_SAVE, err))?;
+    }
+
+    Ok(PublishReport {
+        parameter_map: path,
+        published,
+    })
+}
+
+fn check(matches: &ArgMatches) -> Result<CheckReport> {
+    let parameter_map = get_parameter_map(&parameter_map_path(matches))
+        .map_err(|err| format_err!("{}: {}", ERROR_PARAMETERS_MAPPED, err))?;
+
+    let mapped_parameters = get_mapped_parameter_ids(&parameter_map)?;
+    let local_parameters = get_local_parameter_ids()
+        .map_err(|err| format_err!("{}: {}", ERROR_PARAMETERS_LOCAL, err))?;
+
+    let parameters = local_parameters
+        .into_iter()
+        .map(|parameter_id| CheckedParameter {
+            mapped: mapped_parameters.contains(&parameter_id),
+            parameter_id,
+        })
+        .collect();
+
+    Ok(CheckReport { parameters })
+}
+
 pub fn main() {
     let matches = App::new("parampublish")
         .setting(AppSettings::ArgRequiredElseHelp)
@@ -18,14 +174,15 @@ Set $FILECOIN_PARAMETER_CACHE to specify parameter directory. Defaults to '{}'
             )[..],
         )
         .arg(
-            Arg::with_name("json")
+            Arg::with_name("parameter-map")
                 .global(true)
                 .value_name("JSON")
                 .takes_value(true)
-                .short("j")
-                .long("json")
+                .short("m")
+                .long("parameter-map")
                 .help("Use specific json file"),
         )
+        .arg(json_arg())
         .subcommand(
             SubCommand::with_name("publish")
                 .arg(
@@ -42,53 +199,11 @@ Set $FILECOIN_PARAMETER_CACHE to specify parameter directory. Defaults to '{}'
         )
         .get_matches();
 
-    let json = PathBuf::from(matches.value_of("json").unwrap_or("./parameters.json"));
-    let parameter_map = get_parameter_map(&json).expect(ERROR_PARAMETERS_MAPPED);
-
     if let Some(matches) = matches.subcommand_matches("publish") {
-        let mut new_parameter_map: ParameterMap = HashMap::new();
-        let parameter_ids = if matches.is_present("all") {
-            get_local_parameter_ids()
-        } else {
-            choose_local_parameter_ids()
-        }
-        .expect(ERROR_PARAMETERS_LOCAL);
-
-        if !parameter_ids.is_empty() {
-            println!("publishing parameters");
-
-            for parameter_id in parameter_ids.into_iter() {
-                println!("publishing '{}'...", parameter_id);
-
-                match publish_parameter_file(&parameter_id) {
-                    Ok(cid) => {
-                        println!("generating digest...");
-                        let digest = get_parameter_digest(&parameter_id).expect(ERROR_DIGEST);
-                        let data = ParameterData { cid, digest };
-
-                        println!("ok.");
-                        new_parameter_map.insert(parameter_id, data);
-                    }
-                    Err(err) => println!("err: {}", err),
-                }
-            }
-
-            save_parameter_map(&new_parameter_map, &json).expect(ERROR_PARAMETER_MAP_SAVE);
-        } else {
-            println!("nothing to publish");
-        }
+        finish(OutputFormat::from_matches(matches), publish(matches));
     }
 
-    if matches.subcommand_matches("check").is_some() {
-        let mapped_parameters =
-            get_mapped_parameter_ids(&parameter_map).expect(ERROR_PARAMETERS_MAPPED);
-        let local_parameters = get_local_parameter_ids().expect(ERROR_PARAMETERS_LOCAL);
-
-        local_parameters.iter().for_each(|p| {
-            let mapped = mapped_parameters.contains(p);
-            let check = if mapped { "☑" } else { "☐" };
-
-            println!("{} {}", check, p);
-        });
+    if let Some(matches) = matches.subcommand_matches("check") {
+        finish(OutputFormat::from_matches(matches), check(matches));
     }
 }
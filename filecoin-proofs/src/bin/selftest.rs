@@ -0,0 +1,68 @@
+use clap::{App, Arg, ArgMatches};
+use failure::format_err;
+
+use filecoin_proofs::api::self_test::{run_self_test, SelfTestConfig, SelfTestReport};
+use filecoin_proofs::cli::{finish, json_arg, OutputFormat};
+use filecoin_proofs::error::Result;
+use sector_base::api::disk_backed_storage::LIVE_SECTOR_SIZE;
+use std::path::PathBuf;
+use storage_proofs::parameter_cache::PARAMETER_CACHE_DIR;
+
+fn run(matches: &ArgMatches) -> Result<SelfTestReport> {
+    let sector_bytes = match matches.value_of("sector-size") {
+        Some(bytes) => bytes
+            .parse()
+            .map_err(|_| format_err!("sector-size must be an integer, not '{}'", bytes))?,
+        None => LIVE_SECTOR_SIZE,
+    };
+
+    Ok(run_self_test(&SelfTestConfig {
+        scratch_dir: PathBuf::from(matches.value_of("scratch-dir").unwrap()),
+        sector_bytes,
+        parameter_cache_dir: None,
+        parameter_map: matches.value_of("parameter-map").map(PathBuf::from),
+    }))
+}
+
+// Run this once binaries and parameters are installed on a machine, to check
+// that it's ready to seal and prove sectors. Exits non-zero unless every step
+// passed.
+pub fn main() {
+    let matches = App::new("selftest")
+        .version("1.0")
+        .about(
+            &format!(
+                "
+Set $FILECOIN_PARAMETER_CACHE to specify parameter directory. Defaults to '{}'
+",
+                PARAMETER_CACHE_DIR
+            )[..],
+        )
+        .arg(
+            Arg::with_name("scratch-dir")
+                .value_name("DIR")
+                .takes_value(true)
+                .long("scratch-dir")
+                .default_value(".")
+                .help("Directory to create the test store in"),
+        )
+        .arg(
+            Arg::with_name("sector-size")
+                .value_name("BYTES")
+                .takes_value(true)
+                .long("sector-size")
+                .help("Size of the sectors whose parameters are checked"),
+        )
+        .arg(
+            Arg::with_name("parameter-map")
+                .value_name("JSON")
+                .takes_value(true)
+                .short("m")
+                .long("parameter-map")
+                .help("Check the parameters' digests against a specific json file"),
+        )
+        .arg(json_arg())
+        .get_matches();
+
+    finish(OutputFormat::from_matches(&matches), run(&matches))
+}
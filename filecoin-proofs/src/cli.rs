@@ -0,0 +1,260 @@
+//! What the crate's binaries share: a `--json` flag, and the plumbing which
+//! prints what a binary found, as text for people by default, or as a single
+//! JSON document for scripts to parse.
+//!
+//! A binary returns a Report, or the error which kept it from making one, and
+//! hands it to finish. With --json, a report which succeeded is printed to
+//! stdout as it serializes. A failure is printed to stdout too, as an object
+//! with an "error" message and, if there is one, the failed "report", and the
+//! binary exits with FAILURE_EXIT_CODE. Without --json, a report is printed as
+//! its text, and an error goes to stderr.
+
+use std::process;
+
+use clap::{Arg, ArgMatches};
+use failure::Error;
+use serde::Serialize;
+
+use crate::api::self_test::{SelfTestReport, StepStatus};
+
+/// The exit code of a binary which failed, whatever the format.
+pub const FAILURE_EXIT_CODE: i32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+impl OutputFormat {
+    /// The format asked for by matches, of an App given json_arg. The flag is
+    /// global, so the matches of a subcommand may be passed too.
+    pub fn from_matches(matches: &ArgMatches) -> OutputFormat {
+        if matches.is_present("json") {
+            OutputFormat::Json
+        } else {
+            OutputFormat::Human
+        }
+    }
+}
+
+/// The `--json` flag, the same for every binary.
+pub fn json_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("json")
+        .global(true)
+        .long("json")
+        .help("Print what was found as a single JSON document")
+}
+
+/// What a binary found.
+pub trait Report: Serialize {
+    /// The report as text, for people.
+    fn human(&self) -> String;
+
+    /// Why the report counts as a failure, if it does.
+    fn failure(&self) -> Option<String> {
+        None
+    }
+}
+
+// What --json prints when a binary fails.
+#[derive(Serialize)]
+struct ErrorReport<'a, R: 'a> {
+    error: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<&'a R>,
+}
+
+/// What a binary prints, and how it exits.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: i32,
+}
+
+/// Renders what a binary found in format, without printing it.
+pub fn render<R: Report>(format: OutputFormat, result: &Result<R, Error>) -> Output {
+    let failure = match result {
+        Ok(report) => report.failure(),
+        Err(err) => Some(err.to_string()),
+    };
+    let exit_code = if failure.is_some() {
+        FAILURE_EXIT_CODE
+    } else {
+        0
+    };
+
+    let (stdout, stderr) = match (format, result) {
+        (OutputFormat::Human, Ok(report)) => (report.human(), String::new()),
+        (OutputFormat::Human, Err(err)) => (String::new(), format!("error: {}\n", err)),
+        (OutputFormat::Json, _) => {
+            let report = result.as_ref().ok();
+            let json = match failure {
+                Some(error) => to_json(&ErrorReport { error, report }),
+                None => to_json(&report),
+            };
+            (json, String::new())
+        }
+    };
+
+    Output {
+        stdout,
+        stderr,
+        exit_code,
+    }
+}
+
+/// Prints what a binary found in format, and exits.
+pub fn finish<R: Report>(format: OutputFormat, result: Result<R, Error>) -> ! {
+    let output = render(format, &result);
+
+    print!("{}", output.stdout);
+    eprint!("{}", output.stderr);
+
+    process::exit(output.exit_code)
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    let mut json = serde_json::to_string_pretty(value).expect("a report serializes to JSON");
+    json.push('\n');
+    json
+}
+
+impl Report for SelfTestReport {
+    fn human(&self) -> String {
+        let mut text = String::new();
+
+        for step in &self.steps {
+            let status = match step.status {
+                StepStatus::Passed => "ok".to_string(),
+                StepStatus::Failed(ref err) => format!("failed: {}", err),
+                StepStatus::Skipped => "skipped".to_string(),
+                StepStatus::ParametersMissing(ref paths) => format!("missing: {:?}", paths),
+            };
+
+            text.push_str(&format!(
+                "{:?} ({:?}): {}\n",
+                step.step, step.elapsed, status
+            ));
+        }
+
+        text
+    }
+
+    fn failure(&self) -> Option<String> {
+        let failed: Vec<String> = self
+            .steps
+            .iter()
+            .filter(|s| s.status != StepStatus::Passed)
+            .map(|s| format!("{:?}", s.step))
+            .collect();
+
+        if failed.is_empty() {
+            None
+        } else {
+            Some(format!(
+                "self test steps didn't pass: {}",
+                failed.join(", ")
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::api::self_test::{SelfTestStep, StepReport};
+    use serde_json::Value;
+    use std::time::Duration;
+
+    #[derive(Serialize)]
+    struct Counted {
+        count: u64,
+    }
+
+    impl Report for Counted {
+        fn human(&self) -> String {
+            format!("counted {}\n", self.count)
+        }
+
+        fn failure(&self) -> Option<String> {
+            if self.count == 0 {
+                Some("counted nothing".to_string())
+            } else {
+                None
+            }
+        }
+    }
+
+    fn json(output: &Output) -> Value {
+        assert!(output.stderr.is_empty());
+        serde_json::from_str(&output.stdout).expect("output is a single JSON document")
+    }
+
+    #[test]
+    fn a_report_is_printed_as_is() {
+        let output = render(OutputFormat::Json, &Ok(Counted { count: 3 }));
+
+        assert_eq!(0, output.exit_code);
+        assert_eq!(3, json(&output)["count"]);
+
+        let output = render(OutputFormat::Human, &Ok(Counted { count: 3 }));
+        assert_eq!("counted 3\n", output.stdout);
+    }
+
+    #[test]
+    fn a_failed_report_is_printed_with_its_error() {
+        let output = render(OutputFormat::Json, &Ok(Counted { count: 0 }));
+
+        assert_eq!(FAILURE_EXIT_CODE, output.exit_code);
+        assert_eq!("counted nothing", json(&output)["error"]);
+        assert_eq!(0, json(&output)["report"]["count"]);
+    }
+
+    #[test]
+    fn an_error_is_printed_alone() {
+        let result: Result<Counted, Error> = Err(format_err!("couldn't count"));
+
+        let output = render(OutputFormat::Json, &result);
+        assert_eq!(FAILURE_EXIT_CODE, output.exit_code);
+        assert_eq!("couldn't count", json(&output)["error"]);
+        assert!(json(&output).get("report").is_none());
+
+        let output = render(OutputFormat::Human, &result);
+        assert_eq!(FAILURE_EXIT_CODE, output.exit_code);
+        assert!(output.stdout.is_empty());
+        assert_eq!("error: couldn't count\n", output.stderr);
+    }
+
+    #[test]
+    fn self_test_reports_name_the_steps_which_did_not_pass() {
+        let report = SelfTestReport {
+            steps: vec![
+                StepReport {
+                    step: SelfTestStep::ScratchDir,
+                    status: StepStatus::Passed,
+                    elapsed: Duration::from_millis(5),
+                },
+                StepReport {
+                    step: SelfTestStep::Seal,
+                    status: StepStatus::Failed("out of disk".to_string()),
+                    elapsed: Duration::from_millis(7),
+                },
+            ],
+        };
+
+        let output = render(OutputFormat::Json, &Ok(report));
+        let json = json(&output);
+
+        assert_eq!(FAILURE_EXIT_CODE, output.exit_code);
+        assert_eq!("self test steps didn't pass: Seal", json["error"]);
+        assert_eq!("ScratchDir", json["report"]["steps"][0]["step"]);
+        assert_eq!("Passed", json["report"]["steps"][0]["status"]);
+        assert_eq!(
+            "out of disk",
+            json["report"]["steps"][1]["status"]["Failed"]
+        );
+    }
+}
@@ -0,0 +1,223 @@
+//! The byte encodings which other implementations (e.g. go-filecoin) must
+//! reproduce exactly: how a replica id is derived from a prover id and a sector
+//! id, how field elements are serialized as commitments, and how commitments
+//! are written as strings.
+//!
+//! These encodings are pinned by the vectors in
+//! tests/golden/encoding_vectors.json, which other implementations are
+//! expected to mirror. A change to any of them is a breaking change, and must
+//! come with deliberately updated vectors.
+
+use std::fmt;
+use std::str::FromStr;
+
+use pairing::bls12_381::Fr;
+use storage_proofs::drgraph::DefaultTreeHasher;
+use storage_proofs::hasher::Hasher;
+use storage_proofs::porep::replica_id;
+use storage_proofs::types::{
+    commitment_from_fr, fr_from_commitment, pad_safe_fr, Commitment, Fr32Ary, FrSafe, FR32_BYTES,
+};
+
+use crate::error;
+
+/// Derives the replica id of the sector `sector_id` sealed by `prover_id`.
+///
+/// Each id is zero-padded to 32 bytes by appending a 0 byte, and the replica
+/// id is the pedersen hash (with the NoteCommitment personalization) of the
+/// 64 bytes `prover_id || 0 || sector_id || 0`, read as bits in little-endian
+/// order within each byte. The result is the x-coordinate of the hash,
+/// encoded as by `fr_to_commitment_bytes`.
+pub fn derive_replica_id(prover_id: FrSafe, sector_id: FrSafe) -> Fr32Ary {
+    commitment_from_fr(replica_id_domain(prover_id, sector_id).into())
+}
+
+pub(crate) fn replica_id_domain(
+    prover_id: FrSafe,
+    sector_id: FrSafe,
+) -> <DefaultTreeHasher as Hasher>::Domain {
+    replica_id::<DefaultTreeHasher>(pad_safe_fr(&prover_id), pad_safe_fr(&sector_id))
+}
+
+/// Serializes a field element as a commitment: the 32 bytes of its canonical
+/// (i.e. fully reduced, non-Montgomery) value, least significant byte first.
+/// The most significant bit of the last byte is therefore always 0. This is
+/// storage_proofs::types::commitment_from_fr, pinned.
+pub fn fr_to_commitment_bytes(fr: Fr) -> Commitment {
+    commitment_from_fr(fr)
+}
+
+/// Deserializes a commitment serialized by `fr_to_commitment_bytes`. Fails if
+/// the bytes are not the canonical encoding of a field element, i.e. if their
+/// little-endian value is not less than the field's modulus.
+pub fn commitment_bytes_to_fr(bytes: &Commitment) -> error::Result<Fr> {
+    Ok(fr_from_commitment(bytes)?)
+}
+
+/// A commitment (e.g. comm_r, comm_d or comm_p), which displays as, and parses
+/// from, its canonical string form: 'f', the multibase prefix of lowercase
+/// base16, followed by its 32 bytes in order, as 64 lowercase hex digits. The
+/// bytes needn't encode a field element. No other form parses: not uppercase
+/// digits, nor another prefix, nor surrounding whitespace.
+///
+/// Everything this crate logs or reports about a commitment uses this form (as
+/// does Comm's Debug), so that strings from operators, logs and other tools
+/// compare directly.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Comm(pub Commitment);
+
+#[derive(Debug, Fail, PartialEq)]
+pub enum ParseCommitmentError {
+    #[fail(display = "a commitment string must start with 'f', not {:?}", _0)]
+    WrongPrefix(Option<char>),
+
+    #[fail(
+        display = "a commitment string must have 64 hex digits, not {}",
+        digits
+    )]
+    WrongLength { digits: usize },
+
+    #[fail(
+        display = "a commitment string may only hold lowercase hex digits, not {:?} (at {})",
+        character, position
+    )]
+    BadCharacter { position: usize, character: char },
+}
+
+impl fmt::Display for Comm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "f")?;
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for Comm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl FromStr for Comm {
+    type Err = ParseCommitmentError;
+
+    fn from_str(s: &str) -> Result<Comm, ParseCommitmentError> {
+        let mut chars = s.chars();
+        match chars.next() {
+            Some('f') => (),
+            other => return Err(ParseCommitmentError::WrongPrefix(other)),
+        }
+
+        let digits = chars.as_str();
+        let count = digits.chars().count();
+        if count != 2 * FR32_BYTES {
+            return Err(ParseCommitmentError::WrongLength { digits: count });
+        }
+
+        let mut commitment = [0; FR32_BYTES];
+        for (i, character) in digits.chars().enumerate() {
+            let nibble = match character {
+                '0'..='9' => character as u8 - b'0',
+                'a'..='f' => character as u8 - b'a' + 10,
+                _ => {
+                    return Err(ParseCommitmentError::BadCharacter {
+                        position: i + 1,
+                        character,
+                    })
+                }
+            };
+            commitment[i / 2] |= nibble << (4 * (1 - i % 2));
+        }
+
+        Ok(Comm(commitment))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pairing::{Field, PrimeField};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    #[test]
+    fn commitments_round_trip() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+        for _ in 0..100 {
+            let fr: Fr = rng.gen();
+            let bytes = fr_to_commitment_bytes(fr);
+
+            assert_eq!(0, bytes[31] & 0x80);
+            assert_eq!(fr, commitment_bytes_to_fr(&bytes).unwrap());
+        }
+    }
+
+    #[test]
+    fn commitments_are_little_endian() {
+        let mut bytes = [0; 32];
+        bytes[0] = 1;
+        assert_eq!(Fr::one(), commitment_bytes_to_fr(&bytes).unwrap());
+
+        bytes[0] = 0;
+        bytes[1] = 1;
+        assert_eq!(Fr::from_str("256").unwrap(), commitment_bytes_to_fr(&bytes).unwrap());
+    }
+
+    #[test]
+    fn commitment_strings_round_trip() {
+        let rng = &mut XorShiftRng::from_seed([0x5c2e9a41, 0x0d7b33f2, 0x91e4c6a8, 0x27f0b15d]);
+
+        for _ in 0..100 {
+            let commitment = Comm(rng.gen());
+            let string = commitment.to_string();
+
+            assert_eq!(65, string.len());
+            assert_eq!(Ok(commitment), string.parse());
+        }
+    }
+
+    #[test]
+    fn commitment_strings_are_in_byte_order() {
+        let mut bytes = [0; 32];
+        bytes[0] = 0xab;
+        bytes[31] = 0x01;
+
+        let string = Comm(bytes).to_string();
+        assert!(string.starts_with("fab00"));
+        assert!(string.ends_with("0001"));
+    }
+
+    #[test]
+    fn malformed_commitment_strings_are_refused() {
+        let string = Comm([0xcd; 32]).to_string();
+
+        assert_eq!(
+            Err(ParseCommitmentError::WrongPrefix(Some('0'))),
+            format!("0x{}", &string[1..]).parse::<Comm>()
+        );
+        assert_eq!(
+            Err(ParseCommitmentError::WrongLength { digits: 63 }),
+            string[..64].parse::<Comm>()
+        );
+        assert_eq!(
+            Err(ParseCommitmentError::BadCharacter {
+                position: 1,
+                character: 'C'
+            }),
+            format!("f{}", string[1..].to_uppercase()).parse::<Comm>()
+        );
+    }
+
+    #[test]
+    fn replica_id_is_a_commitment() {
+        let replica_id = derive_replica_id([7; 31], [9; 31]);
+
+        assert_eq!(
+            replica_id,
+            fr_to_commitment_bytes(commitment_bytes_to_fr(&replica_id).unwrap())
+        );
+        assert_ne!(replica_id, derive_replica_id([9; 31], [7; 31]));
+    }
+}
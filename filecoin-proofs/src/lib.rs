@@ -23,13 +23,25 @@ extern crate serde_cbor;
 #[macro_use]
 extern crate serde_derive;
 extern crate blake2;
+extern crate fs2;
+extern crate futures;
+extern crate hmac;
+extern crate num_cpus;
+extern crate rayon;
+extern crate sha2;
+extern crate snap;
+#[macro_use]
 extern crate slog;
 
 pub mod api;
+pub mod cli;
+pub mod encoding;
 pub mod error;
 pub mod param;
 pub mod serde_big_array;
 
+pub use storage_proofs::types;
+
 use logging_toolkit::make_logger;
 use slog::Logger;
 
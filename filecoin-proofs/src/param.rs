@@ -5,7 +5,7 @@ use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs::{read_dir, rename, File};
 use std::io::{stdin, stdout, BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 use storage_proofs::parameter_cache::parameter_cache_dir;
 
@@ -51,7 +51,7 @@ pub fn get_local_parameter_ids() -> Result<Vec<String>> {
             })
             .collect())
     } else {
-        println!(
+        eprintln!(
             "parameter directory '{}' does not exist",
             path.as_path().to_str().unwrap()
         );
@@ -72,7 +72,7 @@ pub fn get_parameter_map(path: &PathBuf) -> Result<ParameterMap> {
 
         Ok(parameter_map)
     } else {
-        println!(
+        eprintln!(
             "parameter manifest '{}' does not exist",
             path.as_path().to_str().unwrap()
         );
@@ -107,7 +107,10 @@ pub fn get_parameter_file_path(parameter_id: &str) -> PathBuf {
 }
 
 pub fn get_parameter_digest(parameter_id: &str) -> Result<String> {
-    let path = get_parameter_file_path(parameter_id);
+    get_parameter_file_digest(&get_parameter_file_path(parameter_id))
+}
+
+pub fn get_parameter_file_digest(path: &Path) -> Result<String> {
     let mut file = File::open(path)?;
     let mut hasher = Blake2b::new();
 
@@ -142,7 +145,7 @@ pub fn fetch_parameter_file(parameter_map: &ParameterMap, parameter_id: &str) ->
     let path = get_parameter_file_path(parameter_id);
 
     if path.exists() {
-        println!(
+        eprintln!(
             "parameter file '{}' already exists",
             path.as_path().to_str().unwrap()
         );
@@ -179,6 +182,7 @@ pub fn validate_parameter_file(parameter_map: &ParameterMap, parameter_id: &str)
     let parameter_data = get_parameter_data(parameter_map, parameter_id)?;
     let digest = get_parameter_digest(parameter_id)?;
 
+    // variable-time: the digests are of published parameters.
     if parameter_data.digest != digest {
         Ok(false)
     } else {
@@ -0,0 +1,140 @@
+//! Checks that a sector builder's audit log records the pieces it stages,
+//! continues its chain across builders over the same metadata, and can be
+//! exported through the C API and verified.
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::audit_log::{
+    audit_log_path, decode_audit_record, verify_audit_log, AuditEvent, AuditRecord,
+};
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use tempfile::TempDir;
+
+// All of these fit in one test sector, so that none is sealed.
+const PIECE_BYTES: usize = 100;
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+unsafe fn init(dirs: &Dirs) -> *mut SectorBuilder {
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        1,
+        u64::max_value(),
+        c_str(dirs.metadata.path()),
+        &[5; 31],
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        10,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        ptr::null(),
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let builder = (*resp).sector_builder;
+    destroy_init_sector_builder_response(resp);
+
+    builder
+}
+
+unsafe fn add(builder: *mut SectorBuilder, key: &str) {
+    let bytes = [1; PIECE_BYTES];
+
+    let resp = add_piece(builder, rust_str_to_c_str(key), bytes.as_ptr(), bytes.len(), 0);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+    destroy_add_piece_response(resp);
+}
+
+unsafe fn export(builder: *mut SectorBuilder, since_record: u64) -> Vec<AuditRecord> {
+    let resp = export_audit_log(builder, since_record);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+    assert_eq!(0, (*resp).records_lost);
+
+    let records = slice::from_raw_parts((*resp).records_ptr, (*resp).records_len)
+        .iter()
+        .map(|record| {
+            let bytes = slice::from_raw_parts(record.record_ptr, record.record_len);
+            decode_audit_record(bytes).unwrap()
+        })
+        .collect();
+    destroy_export_audit_log_response(resp);
+
+    records
+}
+
+fn piece_key(record: &AuditRecord) -> &str {
+    match record.event {
+        AuditEvent::PieceAdded { ref piece_key, .. } => piece_key,
+        ref other => panic!("unexpected event: {:?}", other),
+    }
+}
+
+#[test]
+fn audit_logs_chain_across_builders() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let dirs = Dirs {
+        metadata: TempDir::new().unwrap(),
+        sealed: TempDir::new().unwrap(),
+        staged: TempDir::new().unwrap(),
+    };
+
+    unsafe {
+        let builder = init(&dirs);
+        add(builder, "a");
+        add(builder, "b");
+        destroy_sector_builder(builder, 0);
+
+        // A new builder over the same metadata appends to the same chain.
+        let builder = init(&dirs);
+        add(builder, "c");
+        add(builder, "d");
+
+        let records = export(builder, 0);
+        assert_eq!(
+            vec![0, 1, 2, 3],
+            records.iter().map(|r| r.index).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            vec!["a", "b", "c", "d"],
+            records.iter().map(piece_key).collect::<Vec<_>>()
+        );
+
+        let records = export(builder, 3);
+        assert_eq!(1, records.len());
+        assert_eq!("d", piece_key(&records[0]));
+
+        destroy_sector_builder(builder, 0);
+    }
+
+    let summary = verify_audit_log(audit_log_path(dirs.metadata.path())).unwrap();
+    assert_eq!(None, summary.first_break);
+    assert_eq!(4, summary.intact_records);
+}
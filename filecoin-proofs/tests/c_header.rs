@@ -0,0 +1,132 @@
+//! Guards the C API against accidental changes.
+//!
+//! Generates libfilecoin_proofs.h the same way build.rs does and compares it
+//! to tests/golden/libfilecoin_proofs.h. If the change to the header is
+//! intentional, regenerate the golden copy with
+//!
+//!     FILECOIN_PROOFS_UPDATE_GOLDEN_HEADER=1 cargo test -p filecoin-proofs --test c_header
+//!
+//! and commit it along with the change. A change to the C API must come with
+//! a bump of FILECOIN_PROOFS_ABI_VERSION, so that callers built against the
+//! old header are refused by filecoin_proofs_init: the header isn't
+//! regenerated while its declarations change and the version doesn't.
+
+extern crate cbindgen;
+extern crate filecoin_proofs;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use filecoin_proofs::api::FILECOIN_PROOFS_ABI_VERSION;
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+const ABI_VERSION_DEFINE: &str = "#define FILECOIN_PROOFS_ABI_VERSION ";
+
+fn golden_path() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join("libfilecoin_proofs.h")
+}
+
+fn generate_header() -> String {
+    let crate_dir = env!("CARGO_MANIFEST_DIR");
+    let cfg = cbindgen::Config::from_root_or_default(Path::new(crate_dir));
+
+    let bindings = cbindgen::Builder::new()
+        .with_config(cfg)
+        .with_crate(crate_dir)
+        .with_header(format!("/* libproofs Header Version {} */", VERSION))
+        .with_language(cbindgen::Language::C)
+        .generate()
+        .expect("unable to generate bindings");
+
+    let mut buf = Vec::new();
+    bindings.write(&mut buf);
+
+    String::from_utf8(buf).expect("header is not valid UTF-8")
+}
+
+// The ABI version the header declares.
+fn abi_version(header: &str) -> Option<u32> {
+    header
+        .lines()
+        .find(|line| line.starts_with(ABI_VERSION_DEFINE))
+        .and_then(|line| line[ABI_VERSION_DEFINE.len()..].trim().parse().ok())
+}
+
+// The header's declarations, but for the ABI version's: its lines, less its
+// comments (including the one naming the crate's version).
+fn declarations(header: &str) -> Vec<&str> {
+    header
+        .lines()
+        .filter(|line| {
+            let line = line.trim_start();
+            !line.starts_with("/*")
+                && !line.starts_with('*')
+                && !line.starts_with(ABI_VERSION_DEFINE)
+        })
+        .collect()
+}
+
+// A line-by-line listing of where the two headers differ.
+fn diff(expected: &str, actual: &str) -> String {
+    let expected: Vec<&str> = expected.lines().collect();
+    let actual: Vec<&str> = actual.lines().collect();
+    let mut out = String::new();
+
+    for i in 0..expected.len().max(actual.len()) {
+        match (expected.get(i), actual.get(i)) {
+            (Some(e), Some(a)) if e == a => {}
+            (e, a) => {
+                out.push_str(&format!("line {}:\n", i + 1));
+                if let Some(e) = e {
+                    out.push_str(&format!("-{}\n", e));
+                }
+                if let Some(a) = a {
+                    out.push_str(&format!("+{}\n", a));
+                }
+            }
+        }
+    }
+
+    out
+}
+
+#[test]
+fn c_header_matches_golden() {
+    let generated = generate_header();
+    let golden = fs::read_to_string(golden_path()).expect("could not read golden header");
+
+    assert_eq!(
+        Some(FILECOIN_PROOFS_ABI_VERSION),
+        abi_version(&generated),
+        "the generated C header doesn't declare the library's ABI version"
+    );
+
+    if declarations(&golden) != declarations(&generated) {
+        assert!(
+            abi_version(&golden) != abi_version(&generated),
+            "the C API changed, but FILECOIN_PROOFS_ABI_VERSION is still {}; bump it in \
+             src/api/mod.rs, then regenerate {:?}\n{}",
+            FILECOIN_PROOFS_ABI_VERSION,
+            golden_path(),
+            diff(&golden, &generated)
+        );
+    }
+
+    if env::var("FILECOIN_PROOFS_UPDATE_GOLDEN_HEADER").is_ok() {
+        fs::write(golden_path(), &generated).expect("could not write golden header");
+        return;
+    }
+
+    assert!(
+        golden == generated,
+        "the generated C header differs from {:?}; if this ABI change is intended, \
+         regenerate it with FILECOIN_PROOFS_UPDATE_GOLDEN_HEADER=1\n{}",
+        golden_path(),
+        diff(&golden, &generated)
+    );
+}
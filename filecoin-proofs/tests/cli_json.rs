@@ -0,0 +1,138 @@
+//! Runs the crate's binaries with --json, checking that they print a single
+//! JSON document, whether they succeed or fail, and exit accordingly.
+//!
+//! Cargo builds the binaries beside the test, so they're run from there:
+//!
+//!     cargo test -p filecoin-proofs --test cli_json
+
+extern crate serde_json;
+extern crate tempfile;
+
+use serde_json::Value;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+use tempfile::TempDir;
+
+const FETCHED: &str = "v9-zigzag-proof-of-replication-fetched";
+const MISSING: &str = "v9-zigzag-proof-of-replication-missing";
+
+// The path of one of the crate's binaries, which cargo builds in the directory
+// above the test's own.
+fn binary(name: &str) -> PathBuf {
+    let test = env::current_exe().unwrap();
+
+    test.parent().unwrap().parent().unwrap().join(name)
+}
+
+// Runs a binary with a parameter cache of its own, returning its exit code and
+// what it printed as JSON.
+fn run(name: &str, parameter_cache: &TempDir, args: &[&str]) -> (i32, Value) {
+    let output = Command::new(binary(name))
+        .args(args)
+        .arg("--json")
+        .env("FILECOIN_PARAMETER_CACHE", parameter_cache.path())
+        .output()
+        .expect("failed to run binary");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let json = serde_json::from_str(&stdout).expect("stdout isn't a single JSON document");
+
+    (output.status.code().unwrap(), json)
+}
+
+// A parameter cache holding the file of FETCHED, and a parameter map of both
+// FETCHED and MISSING.
+fn parameters() -> (TempDir, PathBuf) {
+    let cache = TempDir::new().unwrap();
+    fs::write(cache.path().join(FETCHED), b"parameters").unwrap();
+
+    let map = cache.path().join("parameters.json");
+    let entry = serde_json::json!({ "cid": "Qm", "digest": "00" });
+    let json = serde_json::json!({ FETCHED: entry, MISSING: entry });
+    fs::write(&map, json.to_string()).unwrap();
+
+    (cache, map)
+}
+
+#[test]
+fn paramfetch_reports_which_parameters_are_fetched() {
+    let (cache, map) = parameters();
+
+    let (code, json) = run(
+        "paramfetch",
+        &cache,
+        &["check", "--parameter-map", map.to_str().unwrap()],
+    );
+    assert_eq!(0, code);
+
+    let mut parameters: Vec<(String, bool)> = json["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|p| {
+            let id = p["parameter_id"].as_str().unwrap().to_string();
+            (id, p["local"].as_bool().unwrap())
+        })
+        .collect();
+    parameters.sort();
+
+    assert_eq!(
+        vec![(FETCHED.to_string(), true), (MISSING.to_string(), false)],
+        parameters
+    );
+}
+
+#[test]
+fn paramfetch_reports_a_malformed_parameter_map() {
+    let (cache, map) = parameters();
+    fs::write(&map, "{ not json").unwrap();
+
+    let (code, json) = run(
+        "paramfetch",
+        &cache,
+        &["check", "--parameter-map", map.to_str().unwrap()],
+    );
+
+    assert_eq!(1, code);
+    assert!(json["error"]
+        .as_str()
+        .unwrap()
+        .contains("failed to load mapped parameters"));
+    assert!(json.get("report").is_none());
+}
+
+#[test]
+fn parampublish_reports_which_parameters_are_published() {
+    let (cache, map) = parameters();
+
+    let (code, json) = run(
+        "parampublish",
+        &cache,
+        &["check", "--parameter-map", map.to_str().unwrap()],
+    );
+    assert_eq!(0, code);
+
+    // The parameter map is in the cache directory too, and is listed beside it.
+    let fetched = json["parameters"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|p| p["parameter_id"] == FETCHED)
+        .expect("the local parameter is reported");
+    assert_eq!(true, fetched["mapped"]);
+}
+
+#[test]
+fn selftest_reports_bad_arguments() {
+    let cache = TempDir::new().unwrap();
+
+    let (code, json) = run("selftest", &cache, &["--sector-size", "lots"]);
+
+    assert_eq!(1, code);
+    assert_eq!(
+        "sector-size must be an integer, not 'lots'",
+        json["error"].as_str().unwrap()
+    );
+}
@@ -0,0 +1,176 @@
+//! Flags comparisons of commitments, digests, checksums, replica ids, roots
+//! and proofs made with `==` or `!=` in the workspace's crates, which should
+//! be made in constant time with storage_proofs::crypto::constant_time.
+//!
+//! A comparison left variable-time deliberately follows a comment which
+//! starts `variable-time:` and says why. The comment covers the lines up to
+//! the next blank one.
+//!
+//! The check is textual. It looks at the operands on either side of each
+//! operator, as far as the nearest whitespace, and only at the source before
+//! a file's tests.
+
+extern crate regex;
+
+use regex::Regex;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CRATES: &[&str] = &["filecoin-proofs", "storage-proofs", "sector-base"];
+
+const EXEMPTION: &str = "variable-time:";
+
+struct Lint {
+    sensitive: Regex,
+    identifier: Regex,
+    index: Regex,
+    string: Regex,
+}
+
+impl Lint {
+    fn new() -> Lint {
+        Lint {
+            sensitive: Regex::new(r"^(comm_\w+|replica_id|\w*digest|\w*checksum|snark_proof|crs)$")
+                .unwrap(),
+            identifier: Regex::new(r"([A-Za-z_]\w*)(\(\))?").unwrap(),
+            index: Regex::new(r"\[[^\]]*\]").unwrap(),
+            string: Regex::new(r#""([^"\\]|\\.)*""#).unwrap(),
+        }
+    }
+
+    // Whether the operand names a sensitive value, rather than e.g. its length.
+    fn is_sensitive(&self, operand: &str) -> bool {
+        if operand.ends_with(".len()") || operand.ends_with(".is_empty()") {
+            return false;
+        }
+
+        let operand = self.index.replace_all(operand, "");
+        self.identifier.captures_iter(&operand).any(|caps| {
+            let name = &caps[1];
+            self.sensitive.is_match(name) || (name == "root" && caps.get(2).is_some())
+        })
+    }
+
+    // The numbers and text of the lines of source which compare sensitive
+    // values with `==` or `!=`.
+    fn check(&self, source: &str) -> Vec<(usize, String)> {
+        let mut flagged = Vec::new();
+        let mut exempt = false;
+
+        for (i, line) in source.lines().enumerate() {
+            if line.trim() == "#[cfg(test)]" {
+                break;
+            }
+            if line.trim().is_empty() {
+                exempt = false;
+                continue;
+            }
+
+            let code = self.string.replace_all(line, "\"\"");
+            let code = match code.find("//") {
+                Some(start) => {
+                    exempt |= code[start + 2..].trim_start().starts_with(EXEMPTION);
+                    &code[..start]
+                }
+                None => &code[..],
+            };
+
+            if !exempt && self.compares_sensitive_values(code) {
+                flagged.push((i + 1, line.to_string()));
+            }
+        }
+
+        flagged
+    }
+
+    fn compares_sensitive_values(&self, code: &str) -> bool {
+        let bytes = code.as_bytes();
+
+        (1..bytes.len()).any(|i| {
+            let op = &bytes[i - 1..=i];
+            let is_comparison = (op == b"==" || op == b"!=")
+                && (i < 2 || !b"=!<>".contains(&bytes[i - 2]))
+                && bytes.get(i + 1) != Some(&b'=');
+            if !is_comparison {
+                return false;
+            }
+
+            let left = code[..i - 1].split_whitespace().last();
+            let right = code[i + 1..].split_whitespace().next();
+
+            left.into_iter()
+                .chain(right)
+                .any(|operand| self.is_sensitive(operand))
+        })
+    }
+}
+
+fn source_files(dir: &Path, files: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            source_files(&path, files);
+        } else if path.extension().map_or(false, |ext| ext == "rs") {
+            files.push(path);
+        }
+    }
+}
+
+#[test]
+fn sensitive_values_are_compared_in_constant_time() {
+    let lint = Lint::new();
+    let workspace = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+
+    let mut files = Vec::new();
+    for name in CRATES {
+        source_files(&workspace.join(name).join("src"), &mut files);
+    }
+
+    let flagged: Vec<String> = files
+        .iter()
+        .flat_map(|path| {
+            let source = fs::read_to_string(path).unwrap();
+            lint.check(&source)
+                .into_iter()
+                .map(move |(line, text)| format!("{}:{}: {}", path.display(), line, text.trim()))
+        })
+        .collect();
+
+    assert!(
+        flagged.is_empty(),
+        "compare these in constant time, or say why they needn't be:\n{}",
+        flagged.join("\n")
+    );
+}
+
+#[test]
+fn the_lint_flags_only_what_it_should() {
+    let lint = Lint::new();
+    let flagged = |source: &str| lint.check(source).len();
+
+    assert_eq!(1, flagged("if proof.tau[0].comm_d != tau.comm_d {"));
+    assert_eq!(1, flagged("Ok(merkle_proof.root() == commitment)"));
+    assert_eq!(1, flagged("if checksum(body)[..] != expected[..] {"));
+    assert_eq!(1, flagged("if a == b {}\nlet ok = digest == expected;"));
+
+    // Lengths, other values, and comparisons within strings and comments.
+    assert_eq!(0, flagged("if proof.len() == snark_proof.len() {"));
+    assert_eq!(0, flagged("let file = if root == &self.staging_path {"));
+    assert_eq!(0, flagged("if x <= comm_d_len || y >= 3 {"));
+    assert_eq!(
+        0,
+        flagged("println!(\"comm_d == {}\", comm_d); // comm_r != comm_d")
+    );
+    assert_eq!(0, flagged("if comm_d.ct_eq(&other.comm_d) {"));
+
+    // An exemption lasts until the next blank line.
+    let exempted = "// variable-time: published.\nif digest != expected {\n    digest == x\n";
+    assert_eq!(0, flagged(exempted));
+    assert_eq!(
+        1,
+        flagged(&format!("{}\nif digest != expected {{", exempted))
+    );
+
+    // Tests aren't checked.
+    assert_eq!(0, flagged("#[cfg(test)]\nassert!(comm_d == comm_r);"));
+}
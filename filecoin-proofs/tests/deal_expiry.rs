@@ -0,0 +1,244 @@
+//! Checks that a sector builder tracks the deal expiries of the pieces it's
+//! given across two sectors with staggered expiries: that it lists expiring
+//! pieces and expired sectors as time passes, that expired sectors may be
+//! left out of a proof-of-spacetime without being faulted, and that expiries
+//! survive the builder being restarted.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals sectors:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test deal_expiry
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use rand::{thread_rng, Rng};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::ffi::CStr;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const SEAL_TIMEOUT: Duration = Duration::from_secs(600);
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+unsafe fn to_string(ptr: *const std::os::raw::c_char) -> String {
+    CStr::from_ptr(ptr).to_str().unwrap().to_owned()
+}
+
+unsafe fn init(dirs: &Dirs) -> *mut SectorBuilder {
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        0,
+        u64::max_value(),
+        c_str(dirs.metadata.path()),
+        &[5; 31],
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        2,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        ptr::null(),
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let builder = (*resp).sector_builder;
+    destroy_init_sector_builder_response(resp);
+
+    builder
+}
+
+unsafe fn add(builder: *mut SectorBuilder, key: &str, bytes: &[u8], expires_at: u64) -> u64 {
+    let resp = add_piece(builder, rust_str_to_c_str(key), bytes.as_ptr(), bytes.len(), expires_at);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let sector_id = (*resp).sector_id;
+    destroy_add_piece_response(resp);
+
+    sector_id
+}
+
+unsafe fn expired_sectors(builder: *mut SectorBuilder, now: u64) -> Vec<u64> {
+    let resp = get_expired_sectors(builder, now);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let sector_ids = slice::from_raw_parts((*resp).sector_ids_ptr, (*resp).sector_ids_len).to_vec();
+    destroy_get_expired_sectors_response(resp);
+
+    sector_ids
+}
+
+// The key, sector id and expiry of each piece expiring before the horizon.
+unsafe fn expiring_pieces(builder: *mut SectorBuilder, before: u64) -> Vec<(String, u64, u64)> {
+    let resp = get_expiring_pieces(builder, before, false);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let pieces = slice::from_raw_parts((*resp).pieces_ptr, (*resp).pieces_len)
+        .iter()
+        .map(|p| (to_string(p.piece_key), p.sector_id, p.expires_at))
+        .collect();
+    destroy_get_expiring_pieces_response(resp);
+
+    pieces
+}
+
+unsafe fn comm_r(builder: *mut SectorBuilder, sector_id: u64) -> [u8; 32] {
+    let resp = get_seal_status(builder, sector_id);
+    assert_eq!(FFISealStatus::Sealed, (*resp).seal_status_code);
+
+    let comm_r = (*resp).comm_r;
+    destroy_get_seal_status_response(resp);
+
+    comm_r
+}
+
+unsafe fn wait_until_sealed(builder: *mut SectorBuilder, sector_id: u64) {
+    let start = Instant::now();
+
+    loop {
+        let resp = get_seal_status(builder, sector_id);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+        if (*resp).seal_status_code == FFISealStatus::Failed {
+            panic!("sealing sector {} failed: {}", sector_id, to_string((*resp).seal_error_msg));
+        }
+
+        let sealed = (*resp).seal_status_code == FFISealStatus::Sealed;
+        destroy_get_seal_status_response(resp);
+
+        if sealed {
+            return;
+        }
+
+        assert!(start.elapsed() < SEAL_TIMEOUT, "sector {} took too long to seal", sector_id);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// Returns the number of faults reported by a proof-of-spacetime over the
+// given replicas, leaving out the excluded sectors.
+unsafe fn post_faults(
+    builder: *mut SectorBuilder,
+    comm_rs: &[[u8; 32]],
+    excluded: &[u64],
+) -> usize {
+    let flattened: Vec<u8> = comm_rs.iter().flat_map(|comm_r| comm_r.to_vec()).collect();
+
+    let resp = generate_post(
+        builder,
+        flattened.as_ptr(),
+        flattened.len(),
+        &[1; 32],
+        excluded.as_ptr(),
+        excluded.len(),
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let faults = (*resp).faults_len;
+    destroy_generate_post_response(resp);
+
+    faults
+}
+
+#[test]
+fn expired_sectors_are_listed_and_left_out_of_post() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let rng = &mut thread_rng();
+    let dirs = Dirs {
+        metadata: TempDir::new().unwrap(),
+        sealed: TempDir::new().unwrap(),
+        staged: TempDir::new().unwrap(),
+    };
+
+    unsafe {
+        let builder = init(&dirs);
+
+        let max_bytes = {
+            let resp = get_max_user_bytes_per_staged_sector(builder);
+            let max_bytes = (*resp).max_staged_bytes_per_sector as usize;
+            destroy_get_max_user_bytes_per_staged_sector_response(resp);
+            max_bytes
+        };
+
+        // Two pieces fill a sector: the first sector's last deal expires at
+        // 30, and the second's at 25.
+        let mut sector_ids = Vec::new();
+        for (key, expires_at) in &[("a", 10), ("b", 30), ("c", 20), ("d", 25)] {
+            let piece: Vec<u8> = (0..max_bytes / 2).map(|_| rng.gen()).collect();
+            sector_ids.push(add(builder, key, &piece, *expires_at));
+        }
+
+        assert_eq!(sector_ids[0], sector_ids[1]);
+        assert_eq!(sector_ids[2], sector_ids[3]);
+        let (first, second) = (sector_ids[0], sector_ids[2]);
+
+        // Staged pieces are listed too.
+        assert!(expiring_pieces(builder, 10).is_empty());
+        assert_eq!(
+            vec![("a".to_string(), first, 10), ("c".to_string(), second, 20)],
+            expiring_pieces(builder, 25)
+        );
+
+        let resp = seal_all_staged_sectors(builder);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_seal_all_staged_sectors_response(resp);
+
+        wait_until_sealed(builder, first);
+        wait_until_sealed(builder, second);
+
+        assert_eq!(4, expiring_pieces(builder, 31).len());
+        assert!(expired_sectors(builder, 24).is_empty());
+        assert_eq!(vec![second], expired_sectors(builder, 25));
+
+        let mut expected = vec![first, second];
+        expected.sort();
+        assert_eq!(expected, expired_sectors(builder, 30));
+
+        // Expired sectors are left out of the proof rather than faulted.
+        let comm_rs = [comm_r(builder, first), comm_r(builder, second)];
+        assert_ne!(0, post_faults(builder, &comm_rs, &[]));
+        assert_eq!(0, post_faults(builder, &comm_rs, &expired_sectors(builder, 30)));
+
+        destroy_sector_builder(builder, 0);
+
+        // The expiries are read back from the builder's metadata.
+        let builder = init(&dirs);
+
+        assert_eq!(vec![second], expired_sectors(builder, 25));
+        assert_eq!(
+            vec![("a".to_string(), first, 10), ("c".to_string(), second, 20)],
+            expiring_pieces(builder, 25)
+        );
+
+        destroy_sector_builder(builder, 0);
+    }
+}
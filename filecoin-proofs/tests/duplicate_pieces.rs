@@ -0,0 +1,235 @@
+//! Checks that a sector builder tells a piece added with the same bytes as a
+//! piece placed before it from a new one, and stores it again, refuses it or
+//! records it as an alias of that piece as its policy says: that an alias is
+//! read from the bytes of the piece it's recorded with once they're sealed,
+//! and that the sector holding them expires only once both deals do.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals a sector:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test duplicate_pieces
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use rand::{thread_rng, Rng};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::ffi::CStr;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const SEAL_TIMEOUT: Duration = Duration::from_secs(600);
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+impl Dirs {
+    fn new() -> Dirs {
+        Dirs {
+            metadata: TempDir::new().unwrap(),
+            sealed: TempDir::new().unwrap(),
+            staged: TempDir::new().unwrap(),
+        }
+    }
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+unsafe fn to_string(ptr: *const std::os::raw::c_char) -> String {
+    CStr::from_ptr(ptr).to_str().unwrap().to_owned()
+}
+
+unsafe fn init(dirs: &Dirs, policy: DuplicatePiecePolicy) -> *mut SectorBuilder {
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        0,
+        u64::max_value(),
+        c_str(dirs.metadata.path()),
+        &[6; 31],
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        2,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        policy,
+        ptr::null(),
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let builder = (*resp).sector_builder;
+    destroy_init_sector_builder_response(resp);
+
+    builder
+}
+
+unsafe fn max_bytes(builder: *mut SectorBuilder) -> usize {
+    let resp = get_max_user_bytes_per_staged_sector(builder);
+    let max_bytes = (*resp).max_staged_bytes_per_sector as usize;
+    destroy_get_max_user_bytes_per_staged_sector_response(resp);
+
+    max_bytes
+}
+
+// The status of the call, the id of the sector the piece was placed in and
+// whether it was found to be a duplicate.
+unsafe fn add(
+    builder: *mut SectorBuilder,
+    key: &str,
+    bytes: &[u8],
+    expires_at: u64,
+) -> (FCPResponseStatus, u64, bool) {
+    let resp = add_piece(builder, rust_str_to_c_str(key), bytes.as_ptr(), bytes.len(), expires_at);
+
+    let added = ((*resp).status_code, (*resp).sector_id, (*resp).duplicate);
+    destroy_add_piece_response(resp);
+
+    added
+}
+
+unsafe fn read(builder: *mut SectorBuilder, key: &str) -> Vec<u8> {
+    let resp = read_piece_from_sealed_sector(builder, rust_str_to_c_str(key));
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let bytes = slice::from_raw_parts((*resp).data_ptr, (*resp).data_len).to_vec();
+    destroy_read_piece_from_sealed_sector_response(resp);
+
+    bytes
+}
+
+unsafe fn expired_sectors(builder: *mut SectorBuilder, now: u64) -> Vec<u64> {
+    let resp = get_expired_sectors(builder, now);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let sector_ids = slice::from_raw_parts((*resp).sector_ids_ptr, (*resp).sector_ids_len).to_vec();
+    destroy_get_expired_sectors_response(resp);
+
+    sector_ids
+}
+
+unsafe fn wait_until_sealed(builder: *mut SectorBuilder, sector_id: u64) {
+    let start = Instant::now();
+
+    loop {
+        let resp = get_seal_status(builder, sector_id);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+        if (*resp).seal_status_code == FFISealStatus::Failed {
+            panic!("sealing sector {} failed: {}", sector_id, to_string((*resp).seal_error_msg));
+        }
+
+        let sealed = (*resp).seal_status_code == FFISealStatus::Sealed;
+        destroy_get_seal_status_response(resp);
+
+        if sealed {
+            return;
+        }
+
+        assert!(start.elapsed() < SEAL_TIMEOUT, "sector {} took too long to seal", sector_id);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn duplicate_pieces_are_stored_refused_or_deduplicated() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let rng = &mut thread_rng();
+
+    unsafe {
+        // Allowed duplicates are stored again, and flagged as such. A key
+        // given again with other bytes is staged as it always was.
+        let dirs = Dirs::new();
+        let builder = init(&dirs, DuplicatePiecePolicy::Allow);
+        let piece: Vec<u8> = (0..max_bytes(builder) / 4).map(|_| rng.gen()).collect();
+        let other: Vec<u8> = (0..max_bytes(builder) / 4).map(|_| rng.gen()).collect();
+
+        let (status, first, duplicate) = add(builder, "a", &piece, 0);
+        assert_eq!((FCPResponseStatus::FCPNoError, false), (status, duplicate));
+        assert_eq!(
+            (FCPResponseStatus::FCPNoError, first, true),
+            add(builder, "b", &piece, 0)
+        );
+        assert_eq!(
+            (FCPResponseStatus::FCPNoError, first, false),
+            add(builder, "a", &other, 0)
+        );
+
+        destroy_sector_builder(builder, 0);
+
+        // Refused duplicates leave nothing behind.
+        let dirs = Dirs::new();
+        let builder = init(&dirs, DuplicatePiecePolicy::Reject);
+
+        let (status, first, _) = add(builder, "a", &piece, 0);
+        assert_eq!(FCPResponseStatus::FCPNoError, status);
+        assert_eq!(
+            FCPResponseStatus::FCPCallerError,
+            add(builder, "b", &piece, 0).0
+        );
+        assert_eq!(
+            (FCPResponseStatus::FCPNoError, first, false),
+            add(builder, "b", &other, 0)
+        );
+
+        destroy_sector_builder(builder, 0);
+
+        // Deduplicated pieces are recorded with the piece they duplicate, and
+        // read from its bytes.
+        let dirs = Dirs::new();
+        let builder = init(&dirs, DuplicatePiecePolicy::Deduplicate);
+
+        let (status, sector_id, _) = add(builder, "a", &piece, 10);
+        assert_eq!(FCPResponseStatus::FCPNoError, status);
+        assert_eq!(
+            (FCPResponseStatus::FCPNoError, sector_id, true),
+            add(builder, "b", &piece, 30)
+        );
+
+        let resp = seal_all_staged_sectors(builder);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_seal_all_staged_sectors_response(resp);
+
+        wait_until_sealed(builder, sector_id);
+
+        assert_eq!(piece, read(builder, "a"));
+        assert_eq!(piece, read(builder, "b"));
+
+        // The sector is kept for the alias's deal once the piece's expires.
+        assert!(expired_sectors(builder, 20).is_empty());
+        assert_eq!(vec![sector_id], expired_sectors(builder, 30));
+
+        destroy_sector_builder(builder, 0);
+
+        // The alias is read back from the builder's metadata.
+        let builder = init(&dirs, DuplicatePiecePolicy::Deduplicate);
+
+        assert_eq!(piece, read(builder, "b"));
+        assert!(expired_sectors(builder, 20).is_empty());
+
+        destroy_sector_builder(builder, 0);
+    }
+}
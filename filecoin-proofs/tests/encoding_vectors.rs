@@ -0,0 +1,197 @@
+//! Checks the encodings in filecoin_proofs::encoding against the vectors in
+//! tests/golden/encoding_vectors.json.
+//!
+//! Other implementations mirror these vectors in their own test suites, so
+//! they must only ever be changed deliberately, along with the encodings.
+
+extern crate filecoin_proofs;
+extern crate pairing;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+use filecoin_proofs::api::derive_replica_id as ffi_derive_replica_id;
+use filecoin_proofs::api::prover_id_from_32_bytes;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::{
+    commitment_from_string, commitment_to_string, filecoin_proofs_init, FILECOIN_PROOFS_ABI_VERSION,
+};
+use filecoin_proofs::encoding::*;
+use pairing::bls12_381::Fr;
+use pairing::PrimeField;
+use std::ffi::{CStr, CString};
+
+#[derive(Deserialize)]
+struct Vectors {
+    replica_ids: Vec<ReplicaIdVector>,
+    commitments: Vec<CommitmentVector>,
+    non_canonical_commitments: Vec<String>,
+    commitment_strings: Vec<CommitmentStringVector>,
+    malformed_commitment_strings: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct ReplicaIdVector {
+    prover_id: String,
+    sector_id: String,
+    replica_id: String,
+}
+
+// `fr` is the decimal value of the field element.
+#[derive(Deserialize)]
+struct CommitmentVector {
+    fr: String,
+    bytes: String,
+}
+
+#[derive(Deserialize)]
+struct CommitmentStringVector {
+    bytes: String,
+    string: String,
+}
+
+fn vectors() -> Vectors {
+    serde_json::from_str(include_str!("golden/encoding_vectors.json"))
+        .expect("malformed encoding vectors")
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    assert_eq!(0, hex.len() % 2, "odd-length hex string: {}", hex);
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn id(hex: &str) -> [u8; 31] {
+    let mut id = [0; 31];
+    id.copy_from_slice(&from_hex(hex));
+    id
+}
+
+fn commitment(hex: &str) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(&from_hex(hex));
+    bytes
+}
+
+#[test]
+fn replica_id_vectors() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    for v in vectors().replica_ids {
+        let (prover_id, sector_id) = (id(&v.prover_id), id(&v.sector_id));
+        let expected = commitment(&v.replica_id);
+
+        assert_eq!(
+            expected,
+            derive_replica_id(prover_id, sector_id),
+            "replica id of prover {} and sector {}",
+            v.prover_id,
+            v.sector_id
+        );
+
+        unsafe {
+            let resp = ffi_derive_replica_id(&prover_id, &sector_id);
+            assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+            assert_eq!(expected, (*resp).replica_id);
+            destroy_derive_replica_id_response(resp);
+        }
+    }
+}
+
+#[test]
+fn prover_ids_of_32_bytes_drop_only_a_zero_tail() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    for v in vectors().replica_ids {
+        let prover_id = id(&v.prover_id);
+
+        let mut bytes = [0; 32];
+        bytes[..31].copy_from_slice(&prover_id);
+
+        unsafe {
+            let resp = prover_id_from_32_bytes(&bytes);
+            assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+            assert_eq!(prover_id, (*resp).prover_id);
+            destroy_prover_id_from_32_bytes_response(resp);
+
+            bytes[31] = 1;
+            let resp = prover_id_from_32_bytes(&bytes);
+            assert_eq!(FCPResponseStatus::FCPCallerError, (*resp).status_code);
+            destroy_prover_id_from_32_bytes_response(resp);
+        }
+    }
+}
+
+#[test]
+fn commitment_vectors() {
+    for v in vectors().commitments {
+        let fr = Fr::from_str(&v.fr).expect("vector is not a field element");
+        let bytes = commitment(&v.bytes);
+
+        assert_eq!(bytes, fr_to_commitment_bytes(fr), "encoding of {}", v.fr);
+        assert_eq!(fr, commitment_bytes_to_fr(&bytes).unwrap(), "decoding of {}", v.bytes);
+    }
+}
+
+#[test]
+fn non_canonical_commitment_vectors() {
+    for v in vectors().non_canonical_commitments {
+        assert!(
+            commitment_bytes_to_fr(&commitment(&v)).is_err(),
+            "{} was accepted",
+            v
+        );
+    }
+}
+
+#[test]
+fn commitment_string_vectors() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    for v in vectors().commitment_strings {
+        let bytes = commitment(&v.bytes);
+
+        assert_eq!(v.string, Comm(bytes).to_string());
+        assert_eq!(Ok(Comm(bytes)), v.string.parse(), "parse of {}", v.string);
+
+        unsafe {
+            let resp = commitment_to_string(&bytes);
+            assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+            assert_eq!(
+                v.string,
+                CStr::from_ptr((*resp).commitment_string).to_str().unwrap()
+            );
+            destroy_commitment_to_string_response(resp);
+
+            let string = CString::new(v.string.clone()).unwrap();
+            let resp = commitment_from_string(string.as_ptr());
+            assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+            assert_eq!(bytes, (*resp).commitment);
+            destroy_commitment_from_string_response(resp);
+        }
+    }
+}
+
+#[test]
+fn malformed_commitment_string_vectors() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    for v in vectors().malformed_commitment_strings {
+        assert!(v.parse::<Comm>().is_err(), "{:?} was accepted", v);
+
+        unsafe {
+            let string = CString::new(v.clone()).unwrap();
+            let resp = commitment_from_string(string.as_ptr());
+            assert_eq!(
+                FCPResponseStatus::FCPCallerError,
+                (*resp).status_code,
+                "{:?} was accepted",
+                v
+            );
+            destroy_commitment_from_string_response(resp);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Checks that seal estimates are in the right ballpark.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals a sector:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test estimate
+#![cfg(feature = "slow-tests")]
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::estimate::{estimate_seal_resources, HasherKind};
+use filecoin_proofs::api::internal::seal;
+use sector_base::api::disk_backed_storage::{new_sector_store, ConfiguredStore};
+use sector_base::api::sector_store::SectorStore;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+use crate::support::{make_random_bytes, run_case};
+
+// The parameters seal uses.
+const LAYERS: usize = 4;
+const DEGREE: usize = 5;
+const EXPANSION_DEGREE: usize = 8;
+
+// How far off an estimate may be, in either direction.
+const TOLERANCE: u32 = 3;
+
+fn measure_seal(cs: ConfiguredStore) -> Duration {
+    let dir = TempDir::new().expect("could not create temporary directory");
+    let path = dir.path().to_str().unwrap().to_owned();
+    let store = new_sector_store(&cs, path.clone(), path);
+
+    let mgr = store.manager();
+    let cfg = store.config();
+
+    let staged_access = mgr
+        .new_staging_sector_access()
+        .expect("could not create staging access");
+    let sealed_access = mgr
+        .new_sealed_sector_access()
+        .expect("could not create sealed access");
+
+    let contents = make_random_bytes(cfg.max_unsealed_bytes_per_sector());
+    mgr.write_and_preprocess(&staged_access, &contents)
+        .expect("failed to write and preprocess");
+
+    let start = Instant::now();
+    seal(cfg, &staged_access, &sealed_access, &[2; 31], &[0; 31]).expect("failed to seal");
+
+    start.elapsed()
+}
+
+fn estimate_within_tolerance_aux(cs: ConfiguredStore) {
+    let dir = TempDir::new().expect("could not create temporary directory");
+    let path = dir.path().to_str().unwrap().to_owned();
+    let sector_bytes = new_sector_store(&cs, path.clone(), path)
+        .config()
+        .sector_bytes();
+
+    // Estimating first also leaves the groth parameters cached, so the seal
+    // below doesn't pay for generating them.
+    let estimate = estimate_seal_resources(
+        sector_bytes,
+        LAYERS,
+        DEGREE,
+        EXPANSION_DEGREE,
+        HasherKind::Pedersen,
+    )
+    .expect("failed to estimate seal resources");
+
+    let measured = measure_seal(cs);
+
+    assert!(
+        estimate.total() <= measured * TOLERANCE && measured <= estimate.total() * TOLERANCE,
+        "estimated {:?} for a seal which took {:?}",
+        estimate,
+        measured
+    );
+
+    assert!(estimate.peak_memory_bytes > sector_bytes);
+}
+
+#[test]
+fn estimate_within_tolerance() {
+    run_case(
+        "estimate_within_tolerance",
+        ConfiguredStore::Test,
+        estimate_within_tolerance_aux,
+    );
+}
@@ -0,0 +1,1462 @@
+/* libproofs Header Version 0.1.0 */
+
+#include <stdint.h>
+#include <stdlib.h>
+#include <stdbool.h>
+
+#define API_POREP_PROOF_BYTES 384
+
+#define API_POST_PROOF_BYTES 192
+
+#define FILECOIN_PROOFS_ABI_VERSION 9
+
+#define LARGE_TEST_SECTOR_SIZE 2048
+
+#define LIVE_SECTOR_SIZE (1 << 28)
+
+#define TEST_SECTOR_SIZE 1024
+
+typedef enum {
+  ConfiguredStore_Live = 0,
+  ConfiguredStore_Test = 1,
+  ConfiguredStore_LargeTest = 2,
+} ConfiguredStore;
+
+typedef enum {
+  DuplicatePiecePolicy_Allow = 0,
+  DuplicatePiecePolicy_Reject = 1,
+  DuplicatePiecePolicy_Deduplicate = 2,
+} DuplicatePiecePolicy;
+
+typedef enum {
+  FCPResponseStatus_FCPNoError = 0,
+  FCPResponseStatus_FCPUnclassifiedError = 1,
+  FCPResponseStatus_FCPCallerError = 2,
+  FCPResponseStatus_FCPReceiverError = 3,
+  FCPResponseStatus_FCPTimeout = 4,
+  FCPResponseStatus_FCPIncompatibleAbi = 5,
+} FCPResponseStatus;
+
+typedef enum {
+  FFIMigratedPieceStatus_Verified = 0,
+  FFIMigratedPieceStatus_Unverified = 1,
+} FFIMigratedPieceStatus;
+
+typedef enum {
+  FFIPoSTFailureKind_PathMismatch = 0,
+  FFIPoSTFailureKind_LeafMismatch = 1,
+  FFIPoSTFailureKind_MissingProof = 2,
+  FFIPoSTFailureKind_FaultSetInconsistency = 3,
+} FFIPoSTFailureKind;
+
+typedef enum {
+  FFIProofRegenerationStatus_Regenerated = 0,
+  FFIProofRegenerationStatus_Failed = 1,
+} FFIProofRegenerationStatus;
+
+typedef enum {
+  FFISealStatus_Sealed = 0,
+  FFISealStatus_Pending = 1,
+  FFISealStatus_Failed = 2,
+  FFISealStatus_Sealing = 3,
+} FFISealStatus;
+
+typedef enum {
+  FFISelfTestStatus_Passed = 0,
+  FFISelfTestStatus_Failed = 1,
+  FFISelfTestStatus_Skipped = 2,
+  FFISelfTestStatus_ParametersMissing = 3,
+} FFISelfTestStatus;
+
+typedef enum {
+  HasherKind_Pedersen = 0,
+  HasherKind_Sha256 = 1,
+  HasherKind_Blake2s = 2,
+} HasherKind;
+
+typedef void (*LogCallback)(uint8_t, const char*, void*);
+
+typedef void (*ParameterProgressCallback)(const char*, double, void*);
+
+typedef intptr_t (*PieceReadCallback)(uint8_t*, size_t, void*);
+
+typedef struct SectorBuilder SectorBuilder;
+
+typedef uint64_t SectorStoreHandle;
+
+typedef bool (*YieldCallback)(void*);
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint64_t sector_id;
+  bool duplicate;
+} AddPieceResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t sector_ids_len;
+  const uint64_t *sector_ids_ptr;
+} AddPiecesPlannedResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint8_t commitment[32];
+} CommitmentFromStringResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  const char *commitment_string;
+} CommitmentToStringResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint8_t replica_id[32];
+} DeriveReplicaIdResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint64_t encoding_millis;
+  uint64_t tree_building_millis;
+  uint64_t proving_millis;
+  uint64_t peak_memory_bytes;
+} EstimateSealResourcesResponse;
+
+typedef struct {
+  size_t record_len;
+  const uint8_t *record_ptr;
+} FFIAuditRecord;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t records_len;
+  const FFIAuditRecord *records_ptr;
+  uint64_t records_lost;
+} ExportAuditLogResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  const char *manifest_path;
+} ExportSealedSectorResponse;
+
+typedef struct {
+  const char *piece_key;
+  uint64_t sector_id;
+  uint64_t expires_at;
+} FFIExpiringPiece;
+
+typedef struct {
+  const char *piece_key;
+  uint64_t source_sector_id;
+  FFIMigratedPieceStatus status;
+} FFIMigratedPiece;
+
+typedef struct {
+  const char *endpoint;
+  const char *bucket;
+  const char *region;
+  const char *access_key_id;
+  const char *secret_access_key;
+  const char *key_prefix;
+  const char *staging_dir_path;
+  const char *cache_dir_path;
+  uint64_t cache_bytes;
+} FFIObjectStoreConfig;
+
+typedef struct {
+  const char *piece_key;
+  uint64_t num_bytes;
+  uint64_t sector_id;
+  bool new_sector;
+} FFIPieceAssignment;
+
+typedef struct {
+  const char *piece_key;
+  uint64_t num_bytes;
+} FFIPieceMetadata;
+
+typedef struct {
+  uint64_t sector_id;
+  FFIProofRegenerationStatus status;
+  const char *error_msg;
+} FFIProofRegeneration;
+
+typedef struct {
+  uint8_t comm_d[32];
+  uint8_t comm_r[32];
+  uint8_t comm_r_star[32];
+  const char *sector_access;
+  uint64_t sector_id;
+  uint8_t snark_proof[API_POREP_PROOF_BYTES];
+  size_t pieces_len;
+  const FFIPieceMetadata *pieces_ptr;
+} FFISealedSectorMetadata;
+
+typedef struct {
+  const char *step;
+  FFISelfTestStatus status;
+  uint64_t millis;
+  const char *error_msg;
+} FFISelfTestStep;
+
+typedef struct {
+  const char *sector_access;
+  uint64_t sector_id;
+  size_t pieces_len;
+  const FFIPieceMetadata *pieces_ptr;
+  FFISealStatus seal_status_code;
+  const char *seal_error_msg;
+} FFIStagedSectorMetadata;
+
+typedef struct {
+  const char *path;
+  uint64_t num_bytes;
+} FFIUnsealedShard;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+} GenerateParametersResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t faults_len;
+  const uint64_t *faults_ptr;
+  uint8_t proof[API_POST_PROOF_BYTES];
+} GeneratePoSTResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint64_t effective;
+  uint64_t available;
+  uint64_t quota_cpus;
+  uint64_t cpuset_cpus;
+} GetEffectiveParallelismResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t sector_ids_len;
+  const uint64_t *sector_ids_ptr;
+} GetExpiredSectorsResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t pieces_len;
+  const FFIExpiringPiece *pieces_ptr;
+} GetExpiringPiecesResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint64_t max_staged_bytes_per_sector;
+} GetMaxStagedBytesPerSector;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint32_t layout_version;
+  size_t partitions;
+  size_t flattened_inputs_len;
+  const uint8_t *flattened_inputs_ptr;
+} GetPublicInputsForSealResponse;
+
+typedef struct {
+  uint64_t sector_id;
+  uint64_t memory_bytes;
+  uint64_t admitted_at_millis;
+  uint64_t finished_at_millis;
+} FFISealJob;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t running_len;
+  const FFISealJob *running_ptr;
+  size_t queued_len;
+  const uint64_t *queued_ptr;
+  size_t finished_len;
+  const FFISealJob *finished_ptr;
+  uint64_t memory_budget_bytes;
+  uint64_t memory_in_use_bytes;
+  size_t max_running;
+} GetSealAdmissionsResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  FFISealStatus seal_status_code;
+  const char *seal_error_msg;
+  uint8_t comm_d[32];
+  uint8_t comm_r[32];
+  uint8_t comm_r_star[32];
+  const char *sector_access;
+  uint64_t sector_id;
+  uint8_t snark_proof[API_POREP_PROOF_BYTES];
+  size_t pieces_len;
+  const FFIPieceMetadata *pieces_ptr;
+} GetSealStatusResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t sectors_len;
+  const FFISealedSectorMetadata *sectors_ptr;
+  const FCPResponseStatus *item_status_codes_ptr;
+  const char *const *item_error_msgs_ptr;
+} GetSealedSectorsResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t sectors_len;
+  const FFIStagedSectorMetadata *sectors_ptr;
+  const FCPResponseStatus *item_status_codes_ptr;
+  const char *const *item_error_msgs_ptr;
+} GetStagedSectorsResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t shards_len;
+  const FFIUnsealedShard *shards_ptr;
+} GetUnsealedRangeShardedResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint64_t sector_id;
+} ImportSealedSectorResponse;
+
+typedef struct {
+  uint8_t version_code;
+  uint64_t max_sector_bytes;
+} FFIProofVersion;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint32_t abi_version;
+  const char *library_version;
+  size_t proof_versions_len;
+  const FFIProofVersion *proof_versions_ptr;
+} InitResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  SectorBuilder *sector_builder;
+} InitSectorBuilderResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+} InstallParametersResponse;
+
+typedef struct {
+  const char *identifier;
+  uint64_t size;
+  const char *digest;
+} FFIParameterInfo;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t parameters_len;
+  const FFIParameterInfo *parameters_ptr;
+} ListInstalledParametersResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint64_t sector_id;
+  size_t pieces_len;
+  const FFIMigratedPiece *pieces_ptr;
+} MigrateSectorsResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t new_sectors;
+  uint64_t wasted_bytes;
+  size_t assignments_len;
+  const FFIPieceAssignment *assignments_ptr;
+} PlanPiecePlacementResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint64_t directories_millis;
+  uint64_t parameters_millis;
+  uint64_t graph_millis;
+  bool directories_cache_hit;
+  bool parameters_cache_hit;
+  bool graph_cache_hit;
+} PrefetchSealRequirementsResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint8_t comm_d[32];
+  uint8_t comm_r[32];
+  uint8_t comm_r_star[32];
+  uint8_t snark_proof[API_POREP_PROOF_BYTES];
+} ProveFromBundleResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  uint8_t prover_id[31];
+} ProverIdFrom32BytesResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t data_len;
+  const uint8_t *data_ptr;
+} ReadPieceFromSealedSectorResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t sectors_len;
+  const FFIProofRegeneration *sectors_ptr;
+} RegenerateAllProofsResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+} RemoveParametersResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  bool passed;
+  size_t steps_len;
+  const FFISelfTestStep *steps_ptr;
+} RunSelfTestResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+} SealAllStagedSectorsResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  bool cached;
+  uint8_t comm_d[32];
+  uint8_t comm_r[32];
+  uint8_t comm_r_star[32];
+  const char *sector_access;
+  uint64_t sector_id;
+  uint8_t snark_proof[API_POREP_PROOF_BYTES];
+} SealSectorResponse;
+
+typedef struct {
+  uint64_t sector_index;
+  uint8_t comm_r[32];
+  uint64_t challenge_index;
+  FFIPoSTFailureKind kind;
+} FFIPoSTFailure;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  bool is_valid;
+  size_t failures_len;
+  const FFIPoSTFailure *failures_ptr;
+} VerifyPoSTDetailedResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  bool is_valid;
+} VerifyPoSTResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  bool is_valid;
+} VerifySealResponse;
+
+typedef struct {
+  FCPResponseStatus status_code;
+  const char *error_msg;
+  size_t items_len;
+  const FCPResponseStatus *item_status_codes_ptr;
+  const char *const *item_error_msgs_ptr;
+  const bool *is_valid_ptr;
+} VerifySealsBatchResponse;
+
+/*
+ * Writes user piece-bytes to a staged sector and returns the id of the sector
+ * to which the bytes were written. If the piece has the same bytes as a piece
+ * placed before it, the response has `duplicate` set, and, if the
+ * SectorBuilder deduplicates pieces, names the sector holding that piece.
+ *
+ * # Arguments
+ *
+ * * `expires_at` - when the deal storing the piece expires, or 0 if it never
+ *                  does
+ */
+AddPieceResponse *add_piece(SectorBuilder *ptr,
+                            const char *piece_key,
+                            const uint8_t *piece_ptr,
+                            size_t piece_len,
+                            uint64_t expires_at);
+
+/*
+ * Writes a piece of `piece_len` bytes, pulled from `read`, to a staged sector
+ * and returns the id of the sector to which the bytes were written, as
+ * add_piece does. Unlike add_piece, the piece is never held in memory as a
+ * whole.
+ *
+ * `read` is called from another thread while this call blocks, until it
+ * signals the end of the piece. If the piece turns out not to be exactly
+ * `piece_len` bytes long, or `read` fails, nothing is written.
+ *
+ * # Arguments
+ *
+ * * `piece_len`  - length of the piece, in bytes
+ * * `expires_at` - when the deal storing the piece expires, or 0 if it never
+ *                  does
+ * * `read`       - read callback
+ * * `user_data`  - passed to each call of the callback, never dereferenced
+ */
+AddPieceResponse *add_piece_from_reader(SectorBuilder *ptr,
+                                        const char *piece_key,
+                                        uint64_t piece_len,
+                                        uint64_t expires_at,
+                                        PieceReadCallback read,
+                                        void *user_data);
+
+/*
+ * Stages the pieces of a plan returned by plan_piece_placement where it
+ * placed them, and returns the id of the sector each piece was staged in.
+ * Either every piece is staged, or none is. Fails if a staged sector no
+ * longer has room for the pieces planned for it.
+ *
+ * # Arguments
+ *
+ * * `plan`            - the plan, which isn't destroyed
+ * * `piece_ptrs_ptr`  - bytes of each of the plan's pieces, in its order, each
+ *                       as long as its assignment
+ * * `expires_ats_ptr` - when the deal storing each piece expires, or 0 if it
+ *                       never does
+ */
+AddPiecesPlannedResponse *add_pieces_planned(SectorBuilder *ptr,
+                                             const PlanPiecePlacementResponse *plan,
+                                             const uint8_t *const *piece_ptrs_ptr,
+                                             const uint64_t *expires_ats_ptr);
+
+/*
+ * Reads a commitment written by commitment_to_string. Fails with a caller
+ * error if the string isn't exactly in that form, e.g. if it has another
+ * prefix, uppercase digits or the wrong number of digits.
+ *
+ * # Arguments
+ *
+ * * `commitment_string` - commitment in its canonical string form
+ */
+CommitmentFromStringResponse *commitment_from_string(const char *commitment_string);
+
+/*
+ * Writes a commitment (e.g. comm_r, comm_d or comm_p) in its canonical string
+ * form: 'f', the multibase prefix of lowercase base16, followed by its 32
+ * bytes in order, as 64 lowercase hex digits. This is the form in which the
+ * library logs and reports commitments. See filecoin_proofs::encoding::Comm.
+ *
+ * # Arguments
+ *
+ * * `commitment` - commitment to write
+ */
+CommitmentToStringResponse *commitment_to_string(const uint8_t (*commitment)[32]);
+
+/*
+ * Derives the replica id of a sector from the prover id and sector id, as
+ * seal does. See filecoin_proofs::encoding for the exact encoding.
+ *
+ * # Arguments
+ *
+ * * `prover_id` - uniquely identifies the prover
+ * * `sector_id` - uniquely identifies the sector
+ */
+DeriveReplicaIdResponse *derive_replica_id(const uint8_t (*prover_id)[31],
+                                           const uint8_t (*sector_id)[31]);
+
+void destroy_add_piece_response(AddPieceResponse *ptr);
+
+void destroy_add_pieces_planned_response(AddPiecesPlannedResponse *ptr);
+
+void destroy_commitment_from_string_response(CommitmentFromStringResponse *ptr);
+
+void destroy_commitment_to_string_response(CommitmentToStringResponse *ptr);
+
+void destroy_derive_replica_id_response(DeriveReplicaIdResponse *ptr);
+
+void destroy_estimate_seal_resources_response(EstimateSealResourcesResponse *ptr);
+
+void destroy_export_audit_log_response(ExportAuditLogResponse *ptr);
+
+void destroy_export_sealed_sector_response(ExportSealedSectorResponse *ptr);
+
+void destroy_generate_parameters_response(GenerateParametersResponse *ptr);
+
+void destroy_generate_post_response(GeneratePoSTResponse *ptr);
+
+void destroy_get_effective_parallelism_response(GetEffectiveParallelismResponse *ptr);
+
+void destroy_get_expired_sectors_response(GetExpiredSectorsResponse *ptr);
+
+void destroy_get_expiring_pieces_response(GetExpiringPiecesResponse *ptr);
+
+void destroy_get_max_user_bytes_per_staged_sector_response(GetMaxStagedBytesPerSector *ptr);
+
+void destroy_get_public_inputs_for_seal_response(GetPublicInputsForSealResponse *ptr);
+
+void destroy_get_seal_admissions_response(GetSealAdmissionsResponse *ptr);
+
+void destroy_get_seal_status_response(GetSealStatusResponse *ptr);
+
+void destroy_get_sealed_sectors_response(GetSealedSectorsResponse *ptr);
+
+void destroy_get_staged_sectors_response(GetStagedSectorsResponse *ptr);
+
+void destroy_get_unsealed_range_sharded_response(GetUnsealedRangeShardedResponse *ptr);
+
+void destroy_import_sealed_sector_response(ImportSealedSectorResponse *ptr);
+
+void destroy_init_response(InitResponse *ptr);
+
+void destroy_init_sector_builder_response(InitSectorBuilderResponse *ptr);
+
+void destroy_install_parameters_response(InstallParametersResponse *ptr);
+
+void destroy_list_installed_parameters_response(ListInstalledParametersResponse *ptr);
+
+void destroy_migrate_sectors_response(MigrateSectorsResponse *ptr);
+
+void destroy_plan_piece_placement_response(PlanPiecePlacementResponse *ptr);
+
+void destroy_prefetch_seal_requirements_response(PrefetchSealRequirementsResponse *ptr);
+
+void destroy_prove_from_bundle_response(ProveFromBundleResponse *ptr);
+
+void destroy_prover_id_from_32_bytes_response(ProverIdFrom32BytesResponse *ptr);
+
+void destroy_read_piece_from_sealed_sector_response(ReadPieceFromSealedSectorResponse *ptr);
+
+void destroy_regenerate_all_proofs_response(RegenerateAllProofsResponse *ptr);
+
+void destroy_remove_parameters_response(RemoveParametersResponse *ptr);
+
+void destroy_run_self_test_response(RunSelfTestResponse *ptr);
+
+void destroy_seal_all_staged_sectors_response(SealAllStagedSectorsResponse *ptr);
+
+void destroy_seal_sector_response(SealSectorResponse *ptr);
+
+/*
+ * Shuts down and destroys a SectorBuilder. No more work is admitted. Seals
+ * queued, but not started, are persisted, and are queued again by the next
+ * SectorBuilder initialized over the same metadata directory. Running seals
+ * are given `drain_timeout_seconds` to finish, and are then cancelled: their
+ * sectors' seal status is Failed, and they may be sealed again. Calls to
+ * seal_sector waiting on a seal which won't finish fail with FCPCallerError,
+ * once each. The metadata is persisted and the audit log flushed before the
+ * SectorBuilder's threads are stopped.
+ *
+ * # Arguments
+ *
+ * * `drain_timeout_seconds` - how long running seals are given to finish, or 0
+ *                             to cancel them at once
+ */
+void destroy_sector_builder(SectorBuilder *ptr, uint64_t drain_timeout_seconds);
+
+/*
+ * Invalidates a SectorStore handle. The store itself is freed once every
+ * operation using it has finished. Destroying a stale handle does nothing.
+ *
+ * # Arguments
+ *
+ * * `handle` - handle returned by init_new_sector_store or init_new_test_sector_store
+ *
+ */
+void destroy_storage(SectorStoreHandle handle);
+
+void destroy_verify_post_detailed_response(VerifyPoSTDetailedResponse *ptr);
+
+void destroy_verify_post_response(VerifyPoSTResponse *ptr);
+
+void destroy_verify_seal_response(VerifySealResponse *ptr);
+
+void destroy_verify_seals_batch_response(VerifySealsBatchResponse *ptr);
+
+/*
+ * Estimates how long sealing a sector will take on this machine, and how
+ * much memory it will need, by sealing a small instance with the same
+ * parameters and extrapolating. This runs a real (small) seal, so it takes a
+ * while.
+ *
+ * # Arguments
+ *
+ * * `sector_bytes`     - size of the sealed sector, in bytes
+ * * `layers`           - number of zigzag layers
+ * * `degree`           - degree of the base DRG
+ * * `expansion_degree` - expansion degree of the zigzag graph, 0 for none
+ * * `hasher`           - hash function used for encoding and merkle trees
+ */
+EstimateSealResourcesResponse *estimate_seal_resources(uint64_t sector_bytes,
+                                                       size_t layers,
+                                                       size_t degree,
+                                                       size_t expansion_degree,
+                                                       HasherKind hasher);
+
+/*
+ * Returns the records of the SectorBuilder's audit log from the one with
+ * index since_record on, each as its CBOR-encoded bytes, once everything
+ * recorded so far has been written, e.g. for archiving elsewhere. The log
+ * records the pieces staged, the sectors sealed (or failed to be), and the
+ * proofs-of-spacetime generated and the faults they declared, each chained to
+ * the record before it by its hash. records_lost counts the records dropped,
+ * or which couldn't be written, since the SectorBuilder was initialized.
+ *
+ */
+ExportAuditLogResponse *export_audit_log(SectorBuilder *ptr, uint64_t since_record);
+
+/*
+ * Copies the sealed sector with the given id to dest_dir, along with a
+ * manifest from which a SectorBuilder on another machine can import it with
+ * import_sealed_sector, and returns the path of the manifest. The copy is
+ * synced to disk and checked against the sealed sector before the manifest
+ * is written, so a copy without a manifest is incomplete.
+ *
+ */
+ExportSealedSectorResponse *export_sealed_sector(SectorBuilder *ptr,
+                                                 uint64_t sector_id,
+                                                 const char *dest_dir);
+
+/*
+ * Makes the handshake the C API requires before any of its other functions
+ * is called: checks that the caller was built against the library's version
+ * of the C API, and reports the library's version and the proof versions it
+ * supports, each with the largest sectors it seals with them.
+ *
+ * If the caller's version isn't the library's, the status is
+ * FCPIncompatibleAbi and the caller must call nothing else, as it would
+ * misread the library's types. The rest of the response is filled in either
+ * way.
+ *
+ * # Arguments
+ *
+ * * `expected_abi_version` - the FILECOIN_PROOFS_ABI_VERSION of the header the
+ *                            caller was built against
+ */
+InitResponse *filecoin_proofs_init(uint32_t expected_abi_version);
+
+/*
+ * Generates and caches the groth parameters used to seal sectors of the given
+ * size, unless they are already cached. Otherwise the first seal generates
+ * them, which can take tens of minutes without any feedback.
+ *
+ * If `callback` is non-null, it is called on the calling thread as generation
+ * progresses, with the name of the current phase ("generating", "writing" or
+ * "complete"), the overall fraction of the work done (from 0 to 1, never
+ * decreasing) and `user_data`. On success, the last call is the only one
+ * with the "complete" phase. The phase name is only valid for the duration of
+ * the call.
+ *
+ * Given a timeout, generation gives up once it has passed, with the
+ * FCPTimeout status, before the parameters are generated, once they are or
+ * between the MiB they're written to the cache in. The single step which
+ * generates them is never interrupted. Nothing is left in the cache, so that
+ * generation may be tried again at once.
+ *
+ * # Arguments
+ *
+ * * `sector_bytes`    - size of the sealed sector, in bytes
+ * * `callback`        - progress callback, or null
+ * * `user_data`       - passed to each call of the callback, never dereferenced
+ * * `timeout_seconds` - seconds after which generation gives up, or 0 for none
+ */
+GenerateParametersResponse *generate_parameters_with_progress(uint64_t sector_bytes,
+                                                              ParameterProgressCallback callback,
+                                                              void *user_data,
+                                                              uint64_t timeout_seconds);
+
+/*
+ * Generates a proof-of-spacetime for the given replica commitments. The
+ * replicas of the sealed sectors with the excluded ids (e.g. those returned
+ * by get_expired_sectors) are left out of the proof, and aren't reported as
+ * faults.
+ *
+ * # Arguments
+ *
+ * * `excluded_sector_ids_ptr` - ids of the sealed sectors to leave out
+ * * `excluded_sector_ids_len` - number of ids, which may be 0
+ */
+GeneratePoSTResponse *generate_post(SectorBuilder *ptr,
+                                    const uint8_t *flattened_comm_rs_ptr,
+                                    size_t flattened_comm_rs_len,
+                                    const uint8_t (*challenge_seed)[32],
+                                    const uint64_t *excluded_sector_ids_ptr,
+                                    size_t excluded_sector_ids_len);
+
+/*
+ * Returns the number of threads the process's pools are sized by: the
+ * parallelism_override given to init_sector_builder, if any, or else the
+ * fewest CPUs the machine, the cgroup's CPU quota and the cgroup's cpuset
+ * allow (on Linux; elsewhere, the CPUs available). What was detected is
+ * returned with it, 0 standing for no quota, or no cpuset.
+ */
+GetEffectiveParallelismResponse *get_effective_parallelism(void);
+
+/*
+ * Returns the ids, in ascending order, of the sealed sectors all of whose
+ * pieces' deals have expired at `now`. Sectors holding a piece without an
+ * expiry never expire. The ids may be passed to generate_post to leave those
+ * sectors out of the proof.
+ *
+ */
+GetExpiredSectorsResponse *get_expired_sectors(SectorBuilder *ptr, uint64_t now);
+
+/*
+ * Returns the staged and sealed pieces whose deals expire before `before`,
+ * with the sectors holding them, ordered by expiry and then by key, so that
+ * they may be evicted once expired.
+ *
+ * If the SectorBuilder's piece keys are private (see init_sector_builder),
+ * pieces are listed by pseudonym, unless real_piece_keys is set and the
+ * SectorBuilder was given their material. Each is then listed by its key if
+ * it was added or read through this SectorBuilder, and by pseudonym if not.
+ *
+ */
+GetExpiringPiecesResponse *get_expiring_pieces(SectorBuilder *ptr,
+                                               uint64_t before,
+                                               bool real_piece_keys);
+
+/*
+ * Returns the number of user bytes that will fit into a staged sector.
+ *
+ */
+GetMaxStagedBytesPerSector *get_max_user_bytes_per_staged_sector(SectorBuilder *ptr);
+
+/*
+ * Returns the public inputs a seal's proof is verified against, so that
+ * external verifiers can check the inputs they derive against these. There's
+ * a vector of inputs for each of the proof's `partitions`, one after the
+ * other, all of the same length, laid out as `layout_version` of
+ * ZigZagCompound::public_input_vector documents. Each input is encoded as a
+ * commitment is: 32 bytes, least significant first.
+ *
+ * # Arguments
+ *
+ * * `cfg_ptr`     - pointer to ConfiguredStore
+ * * `comm_r`      - replica commitment
+ * * `comm_d`      - data commitment
+ * * `comm_r_star` - layer-aggregated replica commitment
+ * * `prover_id`   - uniquely identifies the prover
+ * * `sector_id`   - uniquely identifies the sector
+ */
+GetPublicInputsForSealResponse *get_public_inputs_for_seal(const ConfiguredStore *cfg_ptr,
+                                                           const uint8_t (*comm_r)[32],
+                                                           const uint8_t (*comm_d)[32],
+                                                           const uint8_t (*comm_r_star)[32],
+                                                           const uint8_t (*prover_id)[31],
+                                                           const uint8_t (*sector_id)[31]);
+
+/*
+ * Returns the seals the SectorBuilder is running, the sectors whose seals are
+ * queued (next first), the last seals to finish, and how much of the memory
+ * budget the running seals are estimated to take. memory_budget_bytes is 0
+ * where there's no budget.
+ *
+ */
+GetSealAdmissionsResponse *get_seal_admissions(SectorBuilder *ptr);
+
+/*
+ * Returns sector sealing status for the provided sector id if it exists. If
+ * we don't know about the provided sector id, produce an error.
+ *
+ */
+GetSealStatusResponse *get_seal_status(SectorBuilder *ptr, uint64_t sector_id);
+
+/*
+ * Returns the metadata of every sealed sector. A sector whose metadata can't
+ * be represented over FFI is reported through its item status code and error
+ * message without failing the other sectors. Pieces are listed as
+ * get_expiring_pieces lists them.
+ *
+ */
+GetSealedSectorsResponse *get_sealed_sectors(SectorBuilder *ptr, bool real_piece_keys);
+
+/*
+ * Returns the metadata of every staged sector. A sector whose metadata can't
+ * be represented over FFI is reported through its item status code and error
+ * message without failing the other sectors. Pieces are listed as
+ * get_expiring_pieces lists them.
+ *
+ */
+GetStagedSectorsResponse *get_staged_sectors(SectorBuilder *ptr, bool real_piece_keys);
+
+/*
+ * Unseals num_bytes bytes of a sealed sector's data, starting at offset, into
+ * files of shard_size bytes in output_dir, named shard-0, shard-1 and so on,
+ * and returns the path and length of each file in order. Every shard but the
+ * last is full, and an empty range makes none. Sectors sealed before format
+ * trailers were introduced are refused.
+ *
+ * Given a timeout, unsealing gives up once it has passed, at its next
+ * checkpoint, with the FCPTimeout status and a message naming the step it was
+ * in, and the shards written so far are removed. A read which hangs holds it
+ * until it returns, but the sealed sector is read in chunks of a MiB, with a
+ * checkpoint between each.
+ *
+ * # Arguments
+ *
+ * * `cfg_ptr`     - pointer to ConfiguredStore
+ * * `sealed_path` - path to the sealed sector
+ * * `output_dir`  - directory in which the shards are written
+ * * `shard_size`  - number of bytes in each shard but the last, non-zero
+ * * `prover_id`   - uniquely identifies the prover
+ * * `sector_id`   - uniquely identifies the sector
+ * * `offset`      - offset of the range in the sector's unsealed data
+ * * `num_bytes`   - number of bytes in the range
+ * * `timeout_seconds` - seconds after which unsealing gives up, or 0 for none
+ */
+GetUnsealedRangeShardedResponse *get_unsealed_range_sharded(const ConfiguredStore *cfg_ptr,
+                                                            const char *sealed_path,
+                                                            const char *output_dir,
+                                                            uint64_t shard_size,
+                                                            const uint8_t (*prover_id)[31],
+                                                            const uint8_t (*sector_id)[31],
+                                                            uint64_t offset,
+                                                            uint64_t num_bytes,
+                                                            uint64_t timeout_seconds);
+
+/*
+ * Imports a sealed sector exported with export_sealed_sector by a
+ * SectorBuilder of the same prover, copying it into this SectorBuilder's
+ * sealed sector directory, and returns its id. Fails, importing nothing, if
+ * the copy doesn't match the checksum in its manifest, or if this
+ * SectorBuilder already has a sector with the same id.
+ *
+ */
+ImportSealedSectorResponse *import_sealed_sector(SectorBuilder *ptr, const char *manifest_path);
+
+/*
+ * Initializes a SectorStore instance for non-test use, and returns a handle to it.
+ * See api/registry.rs for the rules governing handles.
+ *
+ * Returns 0, which is never issued as a handle, if the directories can't be
+ * created, or are the same directory, or one is inside the other (see
+ * check_sector_dirs).
+ *
+ * # Arguments
+ *
+ * * `staging_dir_path` - path to the staging directory
+ * * `sealed_dir_path`  - path to the sealed directory
+ */
+SectorStoreHandle init_new_sector_store(const char *staging_dir_path, const char *sealed_dir_path);
+
+/*
+ * Initializes a SectorStore instance with very small, unrealistic/insecure parameters
+ * for use in testing, and returns a handle to it. See api/registry.rs for the rules
+ * governing handles.
+ *
+ * Returns 0, which is never issued as a handle, if the directories can't be
+ * created, or are the same directory, or one is inside the other (see
+ * check_sector_dirs).
+ *
+ * # Arguments
+ *
+ * * `staging_dir_path` - path to the staging directory
+ * * `sealed_dir_path`  - path to the sealed directory
+ */
+SectorStoreHandle init_new_test_sector_store(const char *staging_dir_path,
+                                             const char *sealed_dir_path);
+
+/*
+ * Initializes a SectorStore instance whose sealed sectors are kept in an
+ * S3-compatible object store, and returns a handle to it. See
+ * api/registry.rs for the rules governing handles.
+ *
+ * Returns 0, which is never issued as a handle, if the library was built
+ * without the object-store feature, or the endpoint isn't an `http://` URL,
+ * or the directories can't be created, or are the same directory, or one is
+ * inside the other (see check_sector_dirs).
+ *
+ * # Arguments
+ *
+ * * `cfg_ptr`    - pointer to ConfiguredStore
+ * * `config_ptr` - pointer to where the store keeps its sectors
+ */
+SectorStoreHandle init_object_sector_store(const ConfiguredStore *cfg_ptr,
+                                           const FFIObjectStoreConfig *config_ptr);
+
+/*
+ * Initializes and returns a SectorBuilder.
+ *
+ * Sector ids are allocated in increasing order from the inclusive range
+ * first_sector_id..=last_sector_id, continuing after the last id allocated by
+ * any earlier SectorBuilder over the same metadata_dir. Initialization fails if
+ * that id lies beyond last_sector_id.
+ *
+ * If landing_sector_dir is not null, sectors are sealed there (e.g. on fast
+ * scratch storage) and then moved to sealed_sector_dir, which may be on another
+ * file system. A sector only appears in sealed_sector_dir once it has been
+ * sealed and its proof verified.
+ *
+ * If max_seal_memory_bytes is non-zero, sealing a sector fails, with a
+ * MemoryLimitExceeded error naming what it was about to allocate, before
+ * sealing's large allocations (the sector's data, copies of its layers, their
+ * merkle trees and circuit assignments) take more memory than that.
+ *
+ * Initialization fails, with FCPCallerError, if staged_sector_dir and
+ * sealed_sector_dir are the same directory, or one is inside the other.
+ *
+ * Pieces are read from sealed sectors by num_retrieval_workers threads of their
+ * own, which sealing never takes. While any piece is being read, sealing
+ * pauses for seal_write_pause_millis after writing each seal_write_chunk_bytes
+ * of a sector, leaving the disk to the read. Each of the three takes its
+ * default (2 threads, 4 MiB and 50ms) when 0.
+ *
+ * Seals start no more than as many at once as the task pool has threads. If
+ * seal_memory_budget_bytes is non-zero, a seal only starts beside those
+ * running if the memory they're all estimated to take fits in it; if
+ * min_seal_stagger_millis is non-zero, seals start at least that far apart.
+ * Seals waiting to start, and those running, are reported by
+ * get_seal_admissions.
+ *
+ * If parallelism_override is non-zero, it overrides the parallelism detected
+ * (see get_effective_parallelism) for every pool sized after it: the task
+ * pool and this SectorBuilder's sealers, the default number of retrieval
+ * workers and, if no SectorBuilder has been initialized before, the threads
+ * sectors are replicated and their trees built on.
+ *
+ * If read_only is set, the SectorBuilder only serves and verifies sectors
+ * already sealed in its directories, which may be mounted read-only: its
+ * metadata is loaded but never written, and nothing is created in any of its
+ * directories. Every call which would write (adding pieces, sealing, importing
+ * and migrating sectors) fails with FCPCallerError.
+ *
+ * If staging_key is not null, staged sectors are encrypted at rest with
+ * XChaCha20Poly1305 under the 32-byte key it points to, recorded in them as
+ * staging_key_id. The key is never persisted: every SectorBuilder over the same
+ * staged_sector_dir must be given it to seal the sectors staged there.
+ *
+ * A piece added with the same bytes as a piece placed before it, as told by
+ * their comm_ps and lengths, is stored again if duplicate_piece_policy is
+ * Allow, refused with FCPCallerError if it's Reject, and recorded as an alias
+ * of the piece placed before it, whose bytes it's read from, if it's
+ * Deduplicate. The AddPieceResponse of a piece stored or recorded as a duplicate
+ * has `duplicate` set.
+ *
+ * If piece_key_material is not null, pieces are recorded in the metadata and the
+ * audit log under pseudonyms of their keys, the hex-encoded HMAC-SHA256 of each
+ * key under the 32 bytes it points to, rather than under the keys themselves.
+ * Pieces are still added and read by key. The material is never persisted, but
+ * the metadata is marked as private: initialization fails with FCPCallerError
+ * if other material is given for it later, or if it holds pieces added without
+ * material. A SectorBuilder initialized over private metadata without material
+ * lists pieces by pseudonym, reads them by pseudonym, and fails to add any with
+ * FCPCallerError.
+ *
+ */
+InitSectorBuilderResponse *init_sector_builder(const ConfiguredStore *sector_store_config_ptr,
+                                               uint64_t first_sector_id,
+                                               uint64_t last_sector_id,
+                                               const char *metadata_dir,
+                                               const uint8_t (*prover_id)[31],
+                                               const char *sealed_sector_dir,
+                                               const char *landing_sector_dir,
+                                               const char *staged_sector_dir,
+                                               uint8_t max_num_staged_sectors,
+                                               uint64_t max_seal_memory_bytes,
+                                               uint8_t num_retrieval_workers,
+                                               uint64_t seal_write_chunk_bytes,
+                                               uint64_t seal_write_pause_millis,
+                                               uint64_t seal_memory_budget_bytes,
+                                               uint64_t min_seal_stagger_millis,
+                                               uint64_t parallelism_override,
+                                               bool read_only,
+                                               uint32_t staging_key_id,
+                                               const uint8_t (*staging_key)[32],
+                                               DuplicatePiecePolicy duplicate_piece_policy,
+                                               const uint8_t (*piece_key_material)[32]);
+
+/*
+ * Installs the parameter file at `source_path`, fetched out of band, in the
+ * parameter cache as `identifier`, if its digest is `expected_digest`. The
+ * file is checked as it's copied, and replaces whatever the cache held under
+ * that name atomically, only once it has been. Seals and verifications which
+ * use the file check it against the digest as they read it, and fail rather
+ * than use it if it has changed since.
+ *
+ * # Arguments
+ *
+ * * `source_path`     - path to the parameter file to install
+ * * `identifier`      - name of the file in the parameter cache, as in
+ *                       parameters.json
+ * * `expected_digest` - the file's digest, as in parameters.json
+ */
+InstallParametersResponse *install_parameters(const char *source_path,
+                                              const char *identifier,
+                                              const char *expected_digest);
+
+/*
+ * Lists the parameter files in the parameter cache, ordered by identifier,
+ * with their sizes, and the digests of those installed with
+ * install_parameters. The digest of any other file is null.
+ */
+ListInstalledParametersResponse *list_installed_parameters(void);
+
+/*
+ * Migrates the pieces of the sealed sectors with the given ids into a single
+ * new sector of the size of the target config, seals it, and returns its id
+ * along with the status of each piece, in the order in which they were
+ * migrated. Each piece keeps its key, and its comm_p is recomputed: if that
+ * doesn't match the comm_p recorded for it, the migration fails, naming the
+ * piece. The source sectors are then retired: they're kept, but no longer
+ * proven, and their pieces are read from the new sector. This call blocks
+ * until the new sector is sealed.
+ *
+ * # Arguments
+ *
+ * * `source_sector_ids_ptr` - ids of the sealed sectors to migrate
+ * * `source_sector_ids_len` - number of ids
+ * * `target_config_ptr`     - pointer to the ConfiguredStore of the new sector
+ *
+ */
+MigrateSectorsResponse *migrate_sectors(SectorBuilder *ptr,
+                                        const uint64_t *source_sector_ids_ptr,
+                                        size_t source_sector_ids_len,
+                                        const ConfiguredStore *target_config_ptr);
+
+/*
+ * Plans where to stage the given pieces, packing them into as few sectors as
+ * possible, largest first, without staging them. The assignments are in the
+ * order in which the pieces were given. Planning the same pieces against the
+ * same staged sectors always yields the same plan.
+ *
+ * Lengths are in user bytes, as is the capacity of staged sectors: there's no
+ * padding or alignment to account for.
+ *
+ * # Arguments
+ *
+ * * `piece_keys_ptr` - keys of the pieces
+ * * `piece_lens_ptr` - lengths of the pieces, in bytes
+ * * `pieces_len`     - number of pieces
+ */
+PlanPiecePlacementResponse *plan_piece_placement(SectorBuilder *ptr,
+                                                 const char *const *piece_keys_ptr,
+                                                 const uint64_t *piece_lens_ptr,
+                                                 size_t pieces_len);
+
+/*
+ * Prepares everything sealing a first sector in a store takes besides its
+ * data: creates the store's directories, reads or generates the groth
+ * parameters for its sector size and builds the zigzag graph seals share.
+ * Reports how long each step took, and whether it found its work done
+ * already. Safe to call concurrently, and again.
+ *
+ * Given a timeout, prefetching gives up once it has passed, with the
+ * FCPTimeout status, between its steps or as parameters are generated (see
+ * generate_parameters_with_progress). The steps it finished stay done.
+ *
+ * # Arguments
+ *
+ * * `handle`          - handle returned by init_new_sector_store or
+ *                       init_new_test_sector_store
+ * * `timeout_seconds` - seconds after which prefetching gives up, or 0 for none
+ */
+PrefetchSealRequirementsResponse *prefetch_seal_requirements(SectorStoreHandle handle,
+                                                             uint64_t timeout_seconds);
+
+/*
+ * Proves the seal whose prover bundle was written into `bundle_dir` by a seal
+ * which replicated its sector, whether or not that seal went on to prove it.
+ * The bundle may have been moved since. Bundles of seals proven with vanilla
+ * proofs are refused, as the response only holds a SNARK proof.
+ *
+ * # Arguments
+ *
+ * * `bundle_dir` - directory holding the prover bundle
+ */
+ProveFromBundleResponse *prove_from_bundle(const char *bundle_dir);
+
+/*
+ * Takes the 31 bytes which seal, verify_seal and derive_replica_id take as
+ * `prover_id` from a 32-byte prover id: its first 31 bytes, as those are
+ * padded with a zero byte. Fails with a caller error, rather than drop a byte
+ * of the id, if its last byte isn't zero. See filecoin_proofs::api::prover_id.
+ *
+ * # Arguments
+ *
+ * * `bytes` - 32-byte prover id, least significant byte first
+ */
+ProverIdFrom32BytesResponse *prover_id_from_32_bytes(const uint8_t (*bytes)[32]);
+
+/*
+ * Unseals and returns the bytes associated with the provided piece key.
+ *
+ */
+ReadPieceFromSealedSectorResponse *read_piece_from_sealed_sector(SectorBuilder *ptr,
+                                                                 const char *piece_key);
+
+/*
+ * Proves each sealed sector which isn't retired again with the current
+ * version's parameters, without sealing it again, e.g. once the groth
+ * parameters it was proven with have been replaced. A sector's trees are
+ * rebuilt from the sealed sector, unless its prover bundle was kept. Returns
+ * the status of each sector, by sector id: a sector which fails to be proven
+ * keeps its proof, and its error message says why. Each new proof is recorded
+ * as it's made, the proof it replaces kept in the sector's metadata. This call
+ * blocks until every sector has been proven, or has failed to be.
+ *
+ */
+RegenerateAllProofsResponse *regenerate_all_proofs(SectorBuilder *ptr);
+
+/*
+ * Removes the parameter file named `identifier` from the parameter cache,
+ * and the digest it was installed with, if any.
+ *
+ * # Arguments
+ *
+ * * `identifier` - name of the file in the parameter cache
+ */
+RemoveParametersResponse *remove_parameters(const char *identifier);
+
+/*
+ * Checks the installation before it's put to use: seals, unseals and proves a
+ * test sector in a store created (and removed again) in `scratch_dir`, then
+ * loads the parameters of the configured store's sector size from the
+ * parameter cache, without generating them if they're missing. Reports each
+ * step's outcome and duration, named as filecoin_proofs::api::self_test names
+ * them. A failed step is reported as the step's, not as the call's.
+ *
+ * # Arguments
+ *
+ * * `cfg_ptr`       - the store whose sector size's parameters are checked
+ * * `scratch_dir`   - existing directory the test store is created in
+ * * `parameter_map` - parameter map the parameters' digests are checked
+ *                     against, or null to only load them
+ */
+RunSelfTestResponse *run_self_test(const ConfiguredStore *cfg_ptr,
+                                   const char *scratch_dir,
+                                   const char *parameter_map);
+
+/*
+ * For demo purposes. Seals all staged sectors.
+ *
+ */
+SealAllStagedSectorsResponse *seal_all_staged_sectors(SectorBuilder *ptr);
+
+/*
+ * Seals the staged sector with the given id, blocking until it's sealed, and
+ * returns its commitments and proof. A sector already sealed isn't sealed
+ * again: its commitments and proof are returned at once, with cached set,
+ * unless its staged data has changed since it was sealed, which is an error.
+ * A sector being sealed isn't sealed twice: the call waits for the seal in
+ * progress. Sectors which failed to seal are sealed again.
+ *
+ */
+SealSectorResponse *seal_sector(SectorBuilder *ptr, uint64_t sector_id);
+
+/*
+ * Encodes a sector id, as a SectorBuilder allocates it, into the 31 bytes
+ * which verify_seal and derive_replica_id take as `sector_id`: its
+ * little-endian bytes, followed by zeroes. A SectorBuilder seals its sectors
+ * with their ids encoded this way, so its proofs only verify with them.
+ *
+ * # Arguments
+ *
+ * * `sector_id` - sector id, as allocated by a SectorBuilder
+ * * `out`       - receives the encoded sector id
+ */
+void sector_id_as_bytes(uint64_t sector_id, uint8_t (*out)[31]);
+
+/*
+ * Passes what filecoin-proofs and storage-proofs log to `callback`, rather
+ * than writing it to stderr (or, as JSON, to stdout), or writes it there
+ * again if `callback` is null. Once this returns, the callback it replaced is
+ * never called again, so its user data may be freed.
+ *
+ * The callback is called on the thread which logs, from any number of threads
+ * at once, and must not call into this library. The message is only valid for
+ * the duration of the call.
+ *
+ * # Arguments
+ *
+ * * `callback`  - log callback, or null
+ * * `user_data` - passed to each call of the callback, never dereferenced
+ */
+void set_log_callback(LogCallback callback, void *user_data);
+
+/*
+ * Sets the least severe level logged, from 1 (critical) to 6 (trace), in
+ * place of the level set by the RUST_PROOFS_MIN_LOG_LEVEL environment
+ * variable (info by default), or restores that level if `level` is 0. Any
+ * other level is ignored.
+ *
+ * # Arguments
+ *
+ * * `level` - least severe level logged, or 0
+ */
+void set_log_level(uint8_t level);
+
+/**
+ * Calls `callback` at bounded intervals from the long CPU-bound loops of
+ * sealing, unsealing and proving (once every yield interval's worth of nodes
+ * encoded, decoded or hashed into a tree, and between the layers and
+ * partitions proven), and at the checkpoints of every cancellable operation,
+ * or stops calling any callback if `callback` is null. Once this returns, the
+ * callback it replaced is never called again, so its user data may be freed.
+ *
+ * The callback may yield to the caller's scheduler, or report progress. Should
+ * it return false, the operation which called it stops within an interval, and
+ * fails as it would had it been cancelled. It's called from any number of
+ * threads at once, and must not call into this library.
+ *
+ * # Arguments
+ *
+ * * `callback`  - yield callback, or null
+ * * `user_data` - passed to each call of the callback, never dereferenced
+ */
+void set_yield_hook(YieldCallback callback, void *user_data);
+
+/**
+ * Sets the yield interval: the nodes encoded, decoded or hashed into a tree
+ * between two calls of the yield callback, or restores the default (4096) if
+ * `nodes` is 0.
+ *
+ * # Arguments
+ *
+ * * `nodes` - nodes worked on between two calls, or 0
+ */
+void set_yield_interval(uint64_t nodes);
+
+/*
+ * Verifies that a proof-of-spacetime is valid.
+ *
+ */
+VerifyPoSTResponse *verify_post(const uint8_t *_flattened_comm_rs_ptr,
+                                size_t _flattened_comm_rs_len,
+                                const uint8_t (*_challenge_seed)[32],
+                                const uint8_t (*proof)[API_POST_PROOF_BYTES],
+                                const uint64_t *_faults_ptr,
+                                size_t _faults_len,
+                                uint64_t _sector_bytes);
+
+/*
+ * Verifies a vanilla proof-of-spacetime, CBOR-encoded as
+ * internal::generate_vanilla_post encodes it, reporting every check which
+ * fails rather than only whether any did: for each, the sector it checked
+ * (its index among the comm_rs, and its comm_r), the challenge (its index
+ * among all those of the proof) and what's wrong. It's for debugging proofs
+ * off-chain, and is slower than verify_post.
+ *
+ * # Arguments
+ *
+ * * `proof_ptr`    - the CBOR-encoded vanilla proof
+ * * `proof_len`    - length of the proof, in bytes
+ * * `faults_ptr`   - indexes, among the comm_rs, of the sectors declared faulty
+ * * `faults_len`   - number of faults, which may be 0
+ * * `sector_bytes` - size of the sealed sectors, in bytes
+ */
+VerifyPoSTDetailedResponse *verify_post_detailed(const uint8_t *flattened_comm_rs_ptr,
+                                                 size_t flattened_comm_rs_len,
+                                                 const uint8_t (*challenge_seed)[32],
+                                                 const uint8_t *proof_ptr,
+                                                 size_t proof_len,
+                                                 const uint64_t *faults_ptr,
+                                                 size_t faults_len,
+                                                 uint64_t sector_bytes);
+
+/*
+ * Verifies the output of seal.
+ *
+ * # Arguments
+ *
+ * * `cfg_ptr`     - pointer to ConfiguredStore
+ * * `comm_r`      - replica commitment
+ * * `comm_d`      - data commitment
+ * * `comm_r_star` - layer-aggregated replica commitment
+ * * `prover_id`   - uniquely identifies the prover
+ * * `sector_id`   - uniquely identifies the sector
+ * * `proof`       - the proof, generated by seal()
+ */
+VerifySealResponse *verify_seal(const ConfiguredStore *cfg_ptr,
+                                const uint8_t (*comm_r)[32],
+                                const uint8_t (*comm_d)[32],
+                                const uint8_t (*comm_r_star)[32],
+                                const uint8_t (*prover_id)[31],
+                                const uint8_t (*sector_id)[31],
+                                const uint8_t (*proof)[API_POREP_PROOF_BYTES]);
+
+/*
+ * Verifies the output of seal as verify_seal does, but first checks that
+ * comm_d is the commitment to the given pieces, in the order they were added
+ * to the sector. Pieces which weren't laid out so that comm_d can be derived
+ * from their comm_ps, or which comm_d isn't the commitment to, are refused
+ * with FCPCallerError.
+ *
+ * # Arguments
+ *
+ * * `cfg_ptr`               - pointer to ConfiguredStore
+ * * `comm_r`                - replica commitment
+ * * `comm_d`                - data commitment
+ * * `comm_r_star`           - layer-aggregated replica commitment
+ * * `prover_id`             - uniquely identifies the prover
+ * * `sector_id`             - uniquely identifies the sector
+ * * `proof`                 - the proof, generated by seal()
+ * * `flattened_comm_ps_ptr` - the pieces' commitments, 32 bytes each
+ * * `piece_lens_ptr`        - lengths of the pieces, in bytes
+ * * `pieces_len`            - number of pieces, which may be 0
+ */
+VerifySealResponse *verify_seal_with_pieces(const ConfiguredStore *cfg_ptr,
+                                            const uint8_t (*comm_r)[32],
+                                            const uint8_t (*comm_d)[32],
+                                            const uint8_t (*comm_r_star)[32],
+                                            const uint8_t (*prover_id)[31],
+                                            const uint8_t (*sector_id)[31],
+                                            const uint8_t (*proof)[API_POREP_PROOF_BYTES],
+                                            const uint8_t *flattened_comm_ps_ptr,
+                                            const uint64_t *piece_lens_ptr,
+                                            size_t pieces_len);
+
+/*
+ * Verifies the outputs of many seals. Each item is verified independently:
+ * an item which can't be verified is reported through its item status code
+ * and error message, and doesn't affect the other items.
+ *
+ * # Arguments
+ *
+ * * `cfg_ptr`                   - pointer to ConfiguredStore
+ * * `num_items`                 - number of seal outputs to verify
+ * * `flattened_comm_rs_ptr`     - `num_items` replica commitments, 32 bytes each
+ * * `flattened_comm_ds_ptr`     - `num_items` data commitments, 32 bytes each
+ * * `flattened_comm_r_stars_ptr`- `num_items` layer-aggregated replica commitments, 32 bytes each
+ * * `prover_id`                 - uniquely identifies the prover
+ * * `flattened_sector_ids_ptr`  - `num_items` sector ids, 31 bytes each
+ * * `flattened_proofs_ptr`      - `num_items` proofs, API_POREP_PROOF_BYTES each
+ */
+VerifySealsBatchResponse *verify_seals_batch(const ConfiguredStore *cfg_ptr,
+                                             size_t num_items,
+                                             const uint8_t *flattened_comm_rs_ptr,
+                                             const uint8_t *flattened_comm_ds_ptr,
+                                             const uint8_t *flattened_comm_r_stars_ptr,
+                                             const uint8_t (*prover_id)[31],
+                                             const uint8_t *flattened_sector_ids_ptr,
+                                             const uint8_t *flattened_proofs_ptr);
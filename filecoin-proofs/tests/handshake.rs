@@ -0,0 +1,78 @@
+//! Checks the handshake callers of the C API make with filecoin_proofs_init
+//! before calling anything else.
+
+extern crate filecoin_proofs;
+extern crate sector_base;
+
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::{filecoin_proofs_init, FILECOIN_PROOFS_ABI_VERSION};
+use sector_base::api::disk_backed_storage::{
+    LARGE_TEST_SECTOR_SIZE, LIVE_SECTOR_SIZE, MINI_SECTOR_SIZE,
+};
+use std::ffi::CStr;
+use std::slice;
+
+unsafe fn library_version(resp: *const InitResponse) -> &'static str {
+    CStr::from_ptr((*resp).library_version).to_str().unwrap()
+}
+
+unsafe fn proof_versions(resp: *const InitResponse) -> Vec<(u8, u64)> {
+    slice::from_raw_parts((*resp).proof_versions_ptr, (*resp).proof_versions_len)
+        .iter()
+        .map(|version| (version.version_code, version.max_sector_bytes))
+        .collect()
+}
+
+#[test]
+fn matching_abi_versions_are_accepted() {
+    unsafe {
+        let resp = filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION);
+
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        assert!((*resp).error_msg.is_null());
+        assert_eq!(FILECOIN_PROOFS_ABI_VERSION, (*resp).abi_version);
+        assert_eq!(env!("CARGO_PKG_VERSION"), library_version(resp));
+
+        // V0Test, V1Alpha and Mini, by the codes their proofs' tags record.
+        assert_eq!(
+            vec![
+                (1, LARGE_TEST_SECTOR_SIZE),
+                (0, LIVE_SECTOR_SIZE),
+                (2, MINI_SECTOR_SIZE),
+            ],
+            proof_versions(resp)
+        );
+
+        destroy_init_response(resp);
+
+        // The handshake may be made again.
+        let resp = filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_init_response(resp);
+    }
+}
+
+#[test]
+fn mismatching_abi_versions_are_refused() {
+    for expected in &[0, FILECOIN_PROOFS_ABI_VERSION + 1] {
+        unsafe {
+            let resp = filecoin_proofs_init(*expected);
+
+            assert_eq!(FCPResponseStatus::FCPIncompatibleAbi, (*resp).status_code);
+            assert_eq!(
+                format!(
+                    "caller was built against ABI version {}, but the library's is {}",
+                    expected, FILECOIN_PROOFS_ABI_VERSION
+                ),
+                CStr::from_ptr((*resp).error_msg).to_str().unwrap()
+            );
+
+            // The rest is filled in, for the caller to report.
+            assert_eq!(FILECOIN_PROOFS_ABI_VERSION, (*resp).abi_version);
+            assert_eq!(env!("CARGO_PKG_VERSION"), library_version(resp));
+            assert_eq!(3, proof_versions(resp).len());
+
+            destroy_init_response(resp);
+        }
+    }
+}
@@ -0,0 +1,200 @@
+//! Checks that sealing a sector again returns the seal already made, at once,
+//! rather than sealing it anew, and that it's refused once the sector's staged
+//! data has changed since it was sealed.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals a sector:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test idempotent_seal
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::ffi::CStr;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::ptr;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const PROVER_ID: [u8; 31] = [7; 31];
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+impl Dirs {
+    fn new() -> Dirs {
+        Dirs {
+            metadata: TempDir::new().unwrap(),
+            sealed: TempDir::new().unwrap(),
+            staged: TempDir::new().unwrap(),
+        }
+    }
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+unsafe fn to_string(ptr: *const std::os::raw::c_char) -> String {
+    CStr::from_ptr(ptr).to_str().unwrap().to_owned()
+}
+
+unsafe fn init(dirs: &Dirs) -> *mut SectorBuilder {
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        0,
+        u64::max_value(),
+        c_str(dirs.metadata.path()),
+        &PROVER_ID,
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        1,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        ptr::null(),
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let builder = (*resp).sector_builder;
+    destroy_init_sector_builder_response(resp);
+
+    builder
+}
+
+// What seal_sector returned: whether the seal was cached, its commitments and
+// proof, or the error message.
+#[derive(Debug, PartialEq)]
+enum Sealed {
+    Ok {
+        cached: bool,
+        comms: [[u8; 32]; 3],
+        snark_proof: Vec<u8>,
+        sector_access: String,
+    },
+    Err(FCPResponseStatus, String),
+}
+
+unsafe fn seal(builder: *mut SectorBuilder, sector_id: u64) -> Sealed {
+    let resp = seal_sector(builder, sector_id);
+
+    let sealed = if (*resp).status_code == FCPResponseStatus::FCPNoError {
+        assert_eq!(sector_id, (*resp).sector_id);
+
+        Sealed::Ok {
+            cached: (*resp).cached,
+            comms: [(*resp).comm_d, (*resp).comm_r, (*resp).comm_r_star],
+            snark_proof: (*resp).snark_proof.to_vec(),
+            sector_access: to_string((*resp).sector_access),
+        }
+    } else {
+        Sealed::Err((*resp).status_code, to_string((*resp).error_msg))
+    };
+    destroy_seal_sector_response(resp);
+
+    sealed
+}
+
+#[test]
+fn sealing_again_returns_the_sealed_sector() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let dirs = Dirs::new();
+
+    unsafe {
+        let builder = init(&dirs);
+
+        let piece = vec![5; 100];
+        let resp = add_piece(
+            builder,
+            rust_str_to_c_str("piece"),
+            piece.as_ptr(),
+            piece.len(),
+            0,
+        );
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        let sector_id = (*resp).sector_id;
+        destroy_add_piece_response(resp);
+
+        let first = seal(builder, sector_id);
+        let sealed_access = match first {
+            Sealed::Ok {
+                cached: false,
+                ref sector_access,
+                ..
+            } => sector_access.clone(),
+            ref other => panic!("unexpected result: {:?}", other),
+        };
+        let sealed_bytes = fs::read(&sealed_access).unwrap();
+
+        // A retry, e.g. of a call whose response was lost, gets the same seal
+        // without waiting for it to be made again.
+        let start = Instant::now();
+        let second = seal(builder, sector_id);
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        match (&first, &second) {
+            (
+                Sealed::Ok {
+                    comms, snark_proof, ..
+                },
+                Sealed::Ok {
+                    cached: true,
+                    comms: cached_comms,
+                    snark_proof: cached_snark_proof,
+                    sector_access,
+                },
+            ) => {
+                assert_eq!(comms, cached_comms);
+                assert_eq!(snark_proof, cached_snark_proof);
+                assert_eq!(&sealed_access, sector_access);
+            }
+            other => panic!("unexpected results: {:?}", other),
+        }
+
+        // Once the staged data has changed, it's no longer what was sealed
+        // under the sector's id, so it isn't sealed in its place.
+        let staged_files: Vec<_> = fs::read_dir(dirs.staged.path()).unwrap().collect();
+        assert_eq!(1, staged_files.len());
+        OpenOptions::new()
+            .append(true)
+            .open(staged_files[0].as_ref().unwrap().path())
+            .unwrap()
+            .write_all(&[3; 32])
+            .unwrap();
+
+        match seal(builder, sector_id) {
+            Sealed::Err(FCPResponseStatus::FCPReceiverError, ref error_msg) => {
+                assert!(
+                    error_msg.contains("changed since it was sealed"),
+                    "{}",
+                    error_msg
+                )
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(sealed_bytes, fs::read(&sealed_access).unwrap());
+
+        destroy_sector_builder(builder, 0);
+    }
+}
@@ -0,0 +1,176 @@
+//! Checks that parameters installed through the C API are used to seal and
+//! verify, that corrupted ones are refused rather than used or replaced, and
+//! that installing them again repairs them.
+//!
+//! Compiled only with the `slow-tests` feature, as it generates parameters and
+//! seals a sector. The parameter cache is a directory of the test's own, set
+//! through the environment, so this binary holds a single test:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test installed_parameters
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate storage_proofs;
+extern crate tempfile;
+
+mod support;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::internal::{
+    clear_seal_verifier_cache, generate_zigzag_params_with_progress, parameter_file_names, seal,
+    verify_seal,
+};
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use filecoin_proofs::param::get_parameter_file_digest;
+use sector_base::api::disk_backed_storage::{ConfiguredStore, TEST_SECTOR_SIZE};
+use std::env;
+use std::ffi::CStr;
+use std::fs;
+use std::path::Path;
+use tempfile::TempDir;
+
+use crate::support::{create_harness, BytesAmount};
+
+fn c_str(s: &str) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(s)
+}
+
+// The status and error message of installing source_path as identifier.
+fn install(source_path: &Path, identifier: &str, digest: &str) -> (FCPResponseStatus, String) {
+    unsafe {
+        let resp = install_parameters(
+            c_str(source_path.to_str().unwrap()),
+            c_str(identifier),
+            c_str(digest),
+        );
+        let error_msg = if (*resp).error_msg.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr((*resp).error_msg)
+                .to_string_lossy()
+                .into_owned()
+        };
+        let status = ((*resp).status_code, error_msg);
+        destroy_install_parameters_response(resp);
+
+        status
+    }
+}
+
+// The identifier, size and digest of each parameter file in the cache.
+fn list() -> Vec<(String, u64, Option<String>)> {
+    unsafe {
+        let resp = list_installed_parameters();
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+        let infos = std::slice::from_raw_parts((*resp).parameters_ptr, (*resp).parameters_len)
+            .iter()
+            .map(|info| {
+                let digest = if info.digest.is_null() {
+                    None
+                } else {
+                    Some(CStr::from_ptr(info.digest).to_string_lossy().into_owned())
+                };
+                let identifier = CStr::from_ptr(info.identifier).to_string_lossy();
+
+                (identifier.into_owned(), info.size, digest)
+            })
+            .collect();
+        destroy_list_installed_parameters_response(resp);
+
+        infos
+    }
+}
+
+#[test]
+fn installed_parameters_are_checked_before_use() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let cache = TempDir::new().unwrap();
+    env::set_var("FILECOIN_PARAMETER_CACHE", cache.path());
+
+    // The fixture: the test sector size's seal parameters, moved out of the
+    // cache, as if fetched out of band.
+    generate_zigzag_params_with_progress(TEST_SECTOR_SIZE, &mut |_, _| ()).unwrap();
+    let identifier = parameter_file_names(TEST_SECTOR_SIZE, cache.path()).remove(0);
+    let installed = cache.path().join(&identifier);
+
+    let fixtures = TempDir::new().unwrap();
+    let fixture = fixtures.path().join(&identifier);
+    fs::copy(&installed, &fixture).unwrap();
+    fs::remove_file(&installed).unwrap();
+    let digest = get_parameter_file_digest(&fixture).unwrap();
+    let size = fs::metadata(&fixture).unwrap().len();
+
+    let (status, _) = install(&fixture, &identifier, &"0".repeat(32));
+    assert_eq!(FCPResponseStatus::FCPCallerError, status);
+    assert!(!installed.exists());
+
+    assert_eq!(
+        FCPResponseStatus::FCPNoError,
+        install(&fixture, &identifier, &digest).0
+    );
+    assert!(list().contains(&(identifier.clone(), size, Some(digest.clone()))));
+
+    // Sealing (and verifying the seal) uses the installed parameters.
+    let h = create_harness(&ConfiguredStore::Test, &[BytesAmount::Max]);
+
+    let verify = || {
+        verify_seal(
+            h.store.config(),
+            h.seal_output.comm_r,
+            h.seal_output.comm_d,
+            h.seal_output.comm_r_star,
+            &h.prover_id,
+            &h.sector_id,
+            &h.seal_output.proof,
+        )
+    };
+
+    let mut bytes = fs::read(&installed).unwrap();
+    let middle = bytes.len() / 2;
+    bytes[middle] ^= 0xff;
+    fs::write(&installed, &bytes).unwrap();
+    clear_seal_verifier_cache();
+
+    let err = verify().expect_err("verified with corrupted parameters");
+    assert!(format!("{}", err).contains("reinstall"), "{}", err);
+
+    let mgr = h.store.manager();
+    let staged_access = mgr.new_staging_sector_access().unwrap();
+    mgr.write_and_preprocess(&staged_access, &h.written_contents[0])
+        .unwrap();
+    let sealed_access = mgr.new_sealed_sector_access().unwrap();
+    let err = seal(
+        h.store.config(),
+        &staged_access,
+        &sealed_access,
+        &h.prover_id,
+        &h.sector_id,
+    )
+    .expect_err("sealed with corrupted parameters");
+    assert!(format!("{}", err).contains("reinstall"), "{}", err);
+
+    // Nothing was generated in place of the corrupted parameters.
+    assert_eq!(bytes, fs::read(&installed).unwrap());
+
+    assert_eq!(
+        FCPResponseStatus::FCPNoError,
+        install(&fixture, &identifier, &digest).0
+    );
+    assert!(verify().expect("failed to verify"));
+
+    unsafe {
+        let resp = remove_parameters(c_str(&identifier));
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_remove_parameters_response(resp);
+    }
+    assert!(list().iter().all(|(id, _, _)| *id != identifier));
+}
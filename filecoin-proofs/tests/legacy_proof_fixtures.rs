@@ -0,0 +1,127 @@
+//! Checks that a seal proof the release before envelopes made, of a test
+//! sector, is verified as that release verified it, once handed in wrapped in
+//! an envelope of format 0, unless the verifier no longer accepts that format.
+//!
+//! tests/golden/legacy_v0_proofs.json holds the proof and what it was verified
+//! against. It was sealed by that release's own internal::seal, with the
+//! parameters it generated for test sectors, which are those the current
+//! release generates for them with V1Alpha's.
+//!
+//! Compiled only with the `slow-tests` feature, as the parameters are generated
+//! unless they're cached:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test legacy_proof_fixtures
+#![cfg(feature = "slow-tests")]
+
+extern crate filecoin_proofs;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+
+use filecoin_proofs::api::internal::{
+    generate_zigzag_params_with_progress, verify_seal_with_legacy_support,
+};
+use filecoin_proofs::api::legacy::LegacyProofSupport;
+use filecoin_proofs::api::seal_proof::{seal_formatted_envelope, SealProofErr};
+use sector_base::api::disk_backed_storage::{new_sector_config, ConfiguredStore, TEST_SECTOR_SIZE};
+use sector_base::api::sector_store::{ProofVariant, ProofVersion};
+
+// `proof` is the proof's hex, split into lines.
+#[derive(Deserialize)]
+struct LegacyProof {
+    prover_id: String,
+    sector_id: String,
+    comm_d: String,
+    comm_r: String,
+    comm_r_star: String,
+    proof: Vec<String>,
+}
+
+fn legacy_proof() -> LegacyProof {
+    serde_json::from_str(include_str!("golden/legacy_v0_proofs.json"))
+        .expect("malformed legacy proof")
+}
+
+fn from_hex(hex: &str) -> Vec<u8> {
+    assert_eq!(0, hex.len() % 2, "odd-length hex string: {}", hex);
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+        .collect()
+}
+
+fn commitment(hex: &str) -> [u8; 32] {
+    let mut bytes = [0; 32];
+    bytes.copy_from_slice(&from_hex(hex));
+    bytes
+}
+
+fn fr_safe(hex: &str) -> [u8; 31] {
+    let mut bytes = [0; 31];
+    bytes.copy_from_slice(&from_hex(hex));
+    bytes
+}
+
+fn verify(
+    proof: &LegacyProof,
+    comm_r: [u8; 32],
+    legacy: LegacyProofSupport,
+) -> Result<bool, SealProofErr> {
+    let cfg = new_sector_config(&ConfiguredStore::Test);
+    let envelope = seal_formatted_envelope(
+        0,
+        ProofVersion::V1Alpha,
+        ProofVariant::Snark,
+        &from_hex(&proof.proof.concat()),
+    );
+
+    verify_seal_with_legacy_support(
+        cfg.as_ref(),
+        comm_r,
+        commitment(&proof.comm_d),
+        commitment(&proof.comm_r_star),
+        &fr_safe(&proof.prover_id),
+        &fr_safe(&proof.sector_id),
+        &envelope,
+        None,
+        &legacy,
+    )
+    .map_err(|err| {
+        err.downcast::<SealProofErr>()
+            .unwrap_or_else(|err| panic!("unexpected error: {}", err))
+    })
+}
+
+#[test]
+fn proofs_of_the_previous_release_are_verified() {
+    // Format 0's verifier only ever reads the parameters from the cache.
+    generate_zigzag_params_with_progress(TEST_SECTOR_SIZE, &mut |_, _| ()).unwrap();
+
+    let proof = legacy_proof();
+    let comm_r = commitment(&proof.comm_r);
+    let mut other_comm_r = comm_r;
+    other_comm_r[0] ^= 1;
+
+    let valid = verify(&proof, comm_r, LegacyProofSupport::default()).expect("failed to verify");
+    assert!(valid);
+
+    let valid =
+        verify(&proof, other_comm_r, LegacyProofSupport::default()).expect("failed to verify");
+    assert!(!valid);
+}
+
+#[test]
+fn proofs_of_the_previous_release_are_refused_once_their_format_is() {
+    let proof = legacy_proof();
+
+    match verify(
+        &proof,
+        commitment(&proof.comm_r),
+        LegacyProofSupport::none(),
+    ) {
+        Err(SealProofErr::FormatNotAccepted(0)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
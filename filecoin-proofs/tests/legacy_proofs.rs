@@ -0,0 +1,62 @@
+//! Checks that seal proofs of the previous format, as the release before
+//! envelopes made them and handed in wrapped in an envelope of format 0, are
+//! verified as that release verified them, unless the verifier no longer
+//! accepts that format.
+//!
+//! What format 0's proofs are verified against is checked against that
+//! release's own vectors by api::legacy::v0's tests. These check how they get
+//! there, without sealing, so they run on every `cargo test`. A proof that
+//! release made is verified by tests/legacy_proof_fixtures.rs.
+
+extern crate filecoin_proofs;
+extern crate sector_base;
+
+use filecoin_proofs::api::internal::verify_seal_with_legacy_support;
+use filecoin_proofs::api::legacy::LegacyProofSupport;
+use filecoin_proofs::api::seal_proof::{seal_formatted_envelope, SealProofErr};
+use sector_base::api::disk_backed_storage::{new_sector_config, ConfiguredStore};
+use sector_base::api::sector_store::{ProofVariant, ProofVersion};
+
+// The partitions' groth proofs, one after another, as that release wrote them.
+const PROOF_BYTES: usize = 2 * 192;
+
+fn verify(proof: &[u8], legacy: LegacyProofSupport) -> Result<bool, SealProofErr> {
+    let cfg = new_sector_config(&ConfiguredStore::Test);
+    let envelope = seal_formatted_envelope(0, ProofVersion::V1Alpha, ProofVariant::Snark, proof);
+
+    verify_seal_with_legacy_support(
+        cfg.as_ref(),
+        [1; 32],
+        [2; 32],
+        [3; 32],
+        &[4; 31],
+        &[5; 31],
+        &envelope,
+        None,
+        &legacy,
+    )
+    .map_err(|err| {
+        err.downcast::<SealProofErr>()
+            .unwrap_or_else(|err| panic!("unexpected error: {}", err))
+    })
+}
+
+#[test]
+fn proofs_of_the_previous_format_are_refused_once_their_format_is() {
+    match verify(&[6; PROOF_BYTES], LegacyProofSupport::none()) {
+        Err(SealProofErr::FormatNotAccepted(0)) => (),
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
+
+#[test]
+fn proofs_of_the_previous_format_are_read_as_that_release_read_them() {
+    // Short of a groth proof per partition, a proof is malformed, whatever the
+    // parameters it would be verified with.
+    for bytes in &[1, PROOF_BYTES / 2, PROOF_BYTES - 1] {
+        match verify(&vec![6; *bytes], LegacyProofSupport::default()) {
+            Err(SealProofErr::Malformed { .. }) => (),
+            other => panic!("unexpected result for {} bytes: {:?}", bytes, other),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+//! Checks that a log callback set over FFI receives what sealing logs, in
+//! order and at the expected levels, filtered by the log level, and that it's
+//! no longer called once cleared.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals a sector:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test log_callback
+#![cfg(feature = "slow-tests")]
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::internal::{public_params, verify_seal};
+use filecoin_proofs::api::responses::destroy_init_response;
+use filecoin_proofs::api::{
+    filecoin_proofs_init, set_log_callback, set_log_level, FILECOIN_PROOFS_ABI_VERSION,
+};
+use sector_base::api::disk_backed_storage::{ConfiguredStore, TEST_SECTOR_SIZE};
+use sector_base::api::sector_store::ProofVariant;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::ptr;
+use std::sync::Mutex;
+
+use crate::support::{create_harness_with_proof_variant, BytesAmount};
+
+const INFO: u8 = 4;
+const DEBUG: u8 = 5;
+
+// The records of interest, by the start of their message.
+const EVENTS: [&str; 6] = [
+    "seal started",
+    "encoded layer",
+    "replicated sector",
+    "seal verifier cache",
+    "verified seal",
+    "seal finished",
+];
+
+type Records = Mutex<Vec<(u8, String)>>;
+
+extern "C" fn record(level: u8, msg: *const c_char, user_data: *mut c_void) {
+    let records = unsafe { &*(user_data as *const Records) };
+    let msg = unsafe { CStr::from_ptr(msg) }.to_str().unwrap().to_owned();
+
+    records.lock().unwrap().push((level, msg));
+}
+
+#[test]
+fn seal_logs_through_the_callback() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let records: Records = Mutex::new(Vec::new());
+
+    set_log_level(DEBUG);
+    set_log_callback(Some(record), &records as *const Records as *mut c_void);
+
+    // Seals a sector and verifies its proof.
+    let h = create_harness_with_proof_variant(
+        &ConfiguredStore::Test,
+        ProofVariant::Vanilla,
+        &[BytesAmount::Max],
+    );
+
+    set_log_callback(None, ptr::null_mut());
+    set_log_level(0);
+    let logged = records.lock().unwrap().len();
+
+    // Logs, but not through the cleared callback.
+    assert!(verify_seal(
+        h.store.config(),
+        h.seal_output.comm_r,
+        h.seal_output.comm_d,
+        h.seal_output.comm_r_star,
+        &h.prover_id,
+        &h.sector_id,
+        &h.seal_output.proof,
+    )
+    .unwrap());
+    let records = records.lock().unwrap();
+    assert_eq!(logged, records.len());
+
+    let events: Vec<(u8, &str)> = records
+        .iter()
+        .filter_map(|(level, msg)| {
+            let event = EVENTS.iter().find(|event| msg.starts_with(*event))?;
+            Some((*level, *event))
+        })
+        .collect();
+
+    let layers = public_params(TEST_SECTOR_SIZE as usize)
+        .layer_challenges
+        .layers();
+
+    // Sealing verifies the proof it made, as does the harness.
+    let mut expected = vec![(INFO, "seal started")];
+    expected.extend(vec![(INFO, "encoded layer"); layers]);
+    expected.extend(vec![
+        (INFO, "replicated sector"),
+        (DEBUG, "seal verifier cache"),
+        (INFO, "verified seal"),
+        (INFO, "seal finished"),
+        (DEBUG, "seal verifier cache"),
+        (INFO, "verified seal"),
+    ]);
+
+    assert_eq!(expected, events);
+
+    // The first verification builds the verifier, which the second reuses,
+    // and both proofs are valid.
+    let details: Vec<&str> = records
+        .iter()
+        .map(|(_, msg)| msg.as_str())
+        .filter(|msg| msg.starts_with("seal verifier cache") || msg.starts_with("verified seal"))
+        .collect();
+
+    assert!(details[0].starts_with("seal verifier cache miss "), "{}", details[0]);
+    assert!(details[1].contains(" valid=true"), "{}", details[1]);
+    assert!(details[2].starts_with("seal verifier cache hit "), "{}", details[2]);
+    assert!(details[3].contains(" valid=true"), "{}", details[3]);
+}
@@ -0,0 +1,95 @@
+//! End-to-end seal, verify and unseal tests against a mini store, whose seals
+//! are made with ProofVersion::Mini's parameters and proven with vanilla
+//! proofs. They're real seals, but quick enough that, unlike the tests in
+//! seal.rs (which cover the other stores), these run on every `cargo test`.
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::internal::verify_seal;
+use filecoin_proofs::api::seal_proof::{open_versioned_envelope, SealProofErr};
+use sector_base::api::disk_backed_storage::{
+    new_mini_sector_config, new_sector_config_of_version, MINI_SECTOR_SIZE,
+};
+use sector_base::api::sector_store::{ProofVariant, ProofVersion};
+
+use crate::support::{assert_unsealed_range, create_mini_harness, BytesAmount};
+
+#[test]
+fn mini_seal_verify_unseal() {
+    // create_mini_harness asserts that the proof verifies and that the whole
+    // sector unseals.
+    let h = create_mini_harness(&[BytesAmount::Offset(5)]);
+
+    let (version, variant, _) = open_versioned_envelope(&h.seal_output.proof).unwrap();
+    assert_eq!(ProofVersion::Mini, version);
+    assert_eq!(ProofVariant::Vanilla, variant);
+
+    let written = h.written_contents[0].len() as u64;
+    assert_unsealed_range(&h, 0, written);
+    assert_unsealed_range(&h, 7, written - 100);
+
+    let is_valid = verify_seal(
+        h.store.config(),
+        h.seal_output.comm_d,
+        h.seal_output.comm_r,
+        h.seal_output.comm_r_star,
+        &h.prover_id,
+        &h.sector_id,
+        &h.seal_output.proof,
+    )
+    .expect("failed to run verify_seal");
+
+    assert!(
+        !is_valid,
+        "proof with swapped comm_r and comm_d should not be valid"
+    );
+}
+
+#[test]
+fn mini_seal_max_size_input() {
+    // Every bit set, filling the sector, exercises the fr32 padding at its
+    // upper bound.
+    let max = new_mini_sector_config().max_unsealed_bytes_per_sector();
+    let h = create_mini_harness(&[BytesAmount::Exact(vec![255; max as usize])]);
+
+    assert_eq!(0, h.byte_padding_amount());
+    assert_unsealed_range(&h, 0, max);
+}
+
+#[test]
+fn mini_proofs_are_refused_by_production_verifiers() {
+    let h = create_mini_harness(&[BytesAmount::Max]);
+
+    for version in &[ProofVersion::V0Test, ProofVersion::V1Alpha] {
+        for variant in &[ProofVariant::Snark, ProofVariant::Vanilla] {
+            let cfg = new_sector_config_of_version(MINI_SECTOR_SIZE, *variant, *version);
+
+            let err = verify_seal(
+                cfg.as_ref(),
+                h.seal_output.comm_r,
+                h.seal_output.comm_d,
+                h.seal_output.comm_r_star,
+                &h.prover_id,
+                &h.sector_id,
+                &h.seal_output.proof,
+            )
+            .expect_err("a mini proof was accepted by a production verifier");
+
+            match err.downcast::<SealProofErr>() {
+                Ok(SealProofErr::VersionMismatch { expected, found }) => {
+                    assert_eq!(*version, expected);
+                    assert_eq!(ProofVersion::Mini, found);
+                }
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+}
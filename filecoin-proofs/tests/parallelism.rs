@@ -0,0 +1,79 @@
+//! Checks that the parallelism given to the first SectorBuilder sizes the
+//! process's pools, and is reported by get_effective_parallelism.
+//!
+//! The global rayon pool is sized once per process, so this runs alone:
+//!
+//!     cargo test -p filecoin-proofs --test parallelism
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate rayon;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::path::Path;
+use std::ptr;
+use tempfile::TempDir;
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+#[test]
+fn the_parallelism_override_sizes_the_pools() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let metadata = TempDir::new().unwrap();
+    let sealed = TempDir::new().unwrap();
+    let staged = TempDir::new().unwrap();
+
+    unsafe {
+        let resp = init_sector_builder(
+            &ConfiguredStore::Test,
+            0,
+            u64::max_value(),
+            c_str(metadata.path()),
+            &[5; 31],
+            c_str(sealed.path()),
+            ptr::null(),
+            c_str(staged.path()),
+            2,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            1,
+            false,
+            0,
+            ptr::null(),
+            DuplicatePiecePolicy::Allow,
+            ptr::null(),
+        );
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        let builder = (*resp).sector_builder;
+        destroy_init_sector_builder_response(resp);
+
+        let resp = get_effective_parallelism();
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        assert_eq!(1, (*resp).effective);
+        assert!((*resp).available >= 1);
+        destroy_get_effective_parallelism_response(resp);
+
+        // Sectors are replicated on the global rayon pool, which the first
+        // SectorBuilder sized.
+        assert_eq!(1, rayon::current_num_threads());
+
+        let resp = get_seal_admissions(builder);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        assert_eq!(1, (*resp).max_running);
+        destroy_get_seal_admissions_response(resp);
+
+        destroy_sector_builder(builder, 0);
+    }
+}
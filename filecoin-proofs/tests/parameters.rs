@@ -0,0 +1,80 @@
+//! Checks the progress reported through the C API while generating groth
+//! parameters.
+//!
+//! Compiled only with the `slow-tests` feature, as it may generate
+//! parameters:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test parameters
+#![cfg(feature = "slow-tests")]
+
+extern crate filecoin_proofs;
+extern crate libc;
+extern crate sector_base;
+
+use filecoin_proofs::api::responses::{
+    destroy_generate_parameters_response, destroy_init_response, FCPResponseStatus,
+};
+use filecoin_proofs::api::{
+    filecoin_proofs_init, generate_parameters_with_progress, FILECOIN_PROOFS_ABI_VERSION,
+};
+use sector_base::api::disk_backed_storage::TEST_SECTOR_SIZE;
+use std::ffi::CStr;
+
+extern "C" fn record(phase: *const libc::c_char, progress: f64, user_data: *mut libc::c_void) {
+    let calls = unsafe { &mut *(user_data as *mut Vec<(String, f64)>) };
+    let phase = unsafe { CStr::from_ptr(phase) }.to_string_lossy().into_owned();
+
+    calls.push((phase, progress));
+}
+
+// Returns whether generation succeeded, and the progress reported.
+fn generate(sector_bytes: u64) -> (bool, Vec<(String, f64)>) {
+    let mut calls: Vec<(String, f64)> = Vec::new();
+
+    unsafe {
+        let resp = generate_parameters_with_progress(
+            sector_bytes,
+            Some(record),
+            &mut calls as *mut Vec<(String, f64)> as *mut libc::c_void,
+            0,
+        );
+        let ok = (*resp).status_code == FCPResponseStatus::FCPNoError;
+        destroy_generate_parameters_response(resp);
+
+        (ok, calls)
+    }
+}
+
+#[test]
+fn reports_monotonic_progress_and_one_completion() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    // Run twice, so that the second call finds the parameters in the cache
+    // whether or not the first one generated them.
+    for _ in 0..2 {
+        let (ok, calls) = generate(TEST_SECTOR_SIZE);
+        assert!(ok);
+
+        for pair in calls.windows(2) {
+            assert!(pair[0].1 <= pair[1].1, "progress decreased: {:?}", calls);
+        }
+
+        let completions = calls.iter().filter(|(phase, _)| phase == "complete").count();
+        assert_eq!(1, completions, "expected one completion: {:?}", calls);
+        assert_eq!(Some(&("complete".to_string(), 1.0)), calls.last());
+    }
+
+    // The parameters are cached now.
+    let (_, calls) = generate(TEST_SECTOR_SIZE);
+    assert_eq!(vec![("complete".to_string(), 1.0)], calls);
+}
+
+#[test]
+fn rejects_invalid_sector_sizes() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let (ok, calls) = generate(33);
+
+    assert!(!ok);
+    assert!(calls.is_empty());
+}
@@ -0,0 +1,160 @@
+//! Checks that destroying a batch response frees every per-item allocation.
+//!
+//! This binary installs a counting global allocator, so it must contain only
+//! the single test below: tests running concurrently would skew the count.
+
+extern crate failure;
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+
+use failure::err_msg;
+use ffi_toolkit::{raw_ptr, rust_str_to_c_str};
+use filecoin_proofs::api::responses::{
+    destroy_get_sealed_sectors_response, destroy_verify_seals_batch_response, FCPResponseStatus,
+    FFIPieceMetadata, FFISealedSectorMetadata, GetSealedSectorsResponse, PartialResults,
+    VerifySealsBatchResponse,
+};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::ffi::CStr;
+use std::slice;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+struct CountingAllocator;
+
+static LIVE_ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE_ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE_ALLOCATIONS.fetch_sub(1, Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+fn make_sealed_sector(sector_id: u64) -> FFISealedSectorMetadata {
+    let pieces = vec![
+        FFIPieceMetadata {
+            piece_key: rust_str_to_c_str(format!("piece-{}-a", sector_id)),
+            num_bytes: 10,
+        },
+        FFIPieceMetadata {
+            piece_key: rust_str_to_c_str(format!("piece-{}-b", sector_id)),
+            num_bytes: 20,
+        },
+    ]
+    .into_boxed_slice();
+
+    let mut sector: FFISealedSectorMetadata = Default::default();
+    sector.sector_access = rust_str_to_c_str(format!("/sealed/{}", sector_id));
+    sector.sector_id = sector_id;
+    sector.pieces_len = pieces.len();
+    sector.pieces_ptr = Box::into_raw(pieces) as *const FFIPieceMetadata;
+
+    sector
+}
+
+unsafe fn check_verify_seals_batch() {
+    let partial = PartialResults::collect(vec![
+        Ok(true),
+        Err(err_msg("could not verify item 2")),
+        Ok(false),
+        Ok(true),
+    ]);
+
+    let mut response: VerifySealsBatchResponse = Default::default();
+    response.items_len = partial.len();
+
+    let (status_codes, error_msgs, is_valid) = partial.into_raw_parts();
+    response.item_status_codes_ptr = status_codes;
+    response.item_error_msgs_ptr = error_msgs;
+    response.is_valid_ptr = is_valid;
+
+    let status_codes = slice::from_raw_parts(response.item_status_codes_ptr, 4);
+    let error_msgs = slice::from_raw_parts(response.item_error_msgs_ptr, 4);
+
+    assert_eq!(FCPResponseStatus::FCPNoError, response.status_code);
+    assert_eq!(FCPResponseStatus::FCPUnclassifiedError, status_codes[1]);
+    assert_eq!(
+        "could not verify item 2",
+        CStr::from_ptr(error_msgs[1]).to_str().unwrap()
+    );
+    assert_eq!(
+        &[true, false, false, true],
+        slice::from_raw_parts(response.is_valid_ptr, 4)
+    );
+
+    destroy_verify_seals_batch_response(raw_ptr(response));
+}
+
+unsafe fn check_get_sealed_sectors() {
+    let partial = PartialResults::collect(vec![
+        Ok(make_sealed_sector(1)),
+        Err(err_msg("sector 2 could not be represented")),
+        Ok(make_sealed_sector(3)),
+        Ok(make_sealed_sector(4)),
+    ]);
+
+    let mut response: GetSealedSectorsResponse = Default::default();
+    response.sectors_len = partial.len();
+
+    let (status_codes, error_msgs, sectors) = partial.into_raw_parts();
+    response.item_status_codes_ptr = status_codes;
+    response.item_error_msgs_ptr = error_msgs;
+    response.sectors_ptr = sectors;
+
+    let status_codes = slice::from_raw_parts(response.item_status_codes_ptr, 4);
+    let error_msgs = slice::from_raw_parts(response.item_error_msgs_ptr, 4);
+    let sectors = slice::from_raw_parts(response.sectors_ptr, 4);
+
+    for (i, sector_id) in &[(0, 1), (2, 3), (3, 4)] {
+        assert_eq!(FCPResponseStatus::FCPNoError, status_codes[*i]);
+        assert!(error_msgs[*i].is_null());
+        assert_eq!(*sector_id, sectors[*i].sector_id);
+        assert_eq!(
+            format!("/sealed/{}", sector_id),
+            CStr::from_ptr(sectors[*i].sector_access).to_str().unwrap()
+        );
+        assert_eq!(2, sectors[*i].pieces_len);
+    }
+
+    assert_eq!(FCPResponseStatus::FCPUnclassifiedError, status_codes[1]);
+    assert_eq!(
+        "sector 2 could not be represented",
+        CStr::from_ptr(error_msgs[1]).to_str().unwrap()
+    );
+    assert!(sectors[1].sector_access.is_null());
+    assert_eq!(0, sectors[1].pieces_len);
+
+    destroy_get_sealed_sectors_response(raw_ptr(response));
+}
+
+#[test]
+fn destroying_batch_responses_frees_every_item() {
+    // Warm up anything (e.g. the failure crate's backtrace machinery) which
+    // allocates once and keeps the allocation for the life of the process.
+    unsafe {
+        check_verify_seals_batch();
+        check_get_sealed_sectors();
+    }
+
+    let before = LIVE_ALLOCATIONS.load(Ordering::SeqCst);
+
+    unsafe {
+        check_verify_seals_batch();
+    }
+
+    assert_eq!(before, LIVE_ALLOCATIONS.load(Ordering::SeqCst));
+
+    unsafe {
+        check_get_sealed_sectors();
+    }
+
+    assert_eq!(before, LIVE_ALLOCATIONS.load(Ordering::SeqCst));
+}
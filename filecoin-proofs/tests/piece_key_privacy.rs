@@ -0,0 +1,326 @@
+//! Checks that a sector builder given piece key material records pieces under
+//! pseudonyms of their keys, while still adding, reading and listing them by
+//! key: that no key is found in any file of its metadata directory, that a
+//! builder reopened without the material serves its pieces by pseudonym, and
+//! that one given other material is refused.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals a sector:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test piece_key_privacy
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use rand::{thread_rng, Rng};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::ffi::CStr;
+use std::fs;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const SEAL_TIMEOUT: Duration = Duration::from_secs(600);
+
+const KEYS: [&str; 2] = ["private-deal-a", "private-deal-b"];
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+impl Dirs {
+    fn new() -> Dirs {
+        Dirs {
+            metadata: TempDir::new().unwrap(),
+            sealed: TempDir::new().unwrap(),
+            staged: TempDir::new().unwrap(),
+        }
+    }
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+unsafe fn to_string(ptr: *const std::os::raw::c_char) -> String {
+    CStr::from_ptr(ptr).to_str().unwrap().to_owned()
+}
+
+// The status of the call, and the builder if it succeeded.
+unsafe fn init(
+    dirs: &Dirs,
+    material: Option<&[u8; 32]>,
+) -> (FCPResponseStatus, *mut SectorBuilder) {
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        0,
+        u64::max_value(),
+        c_str(dirs.metadata.path()),
+        &[8; 31],
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        2,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        material.map_or(ptr::null(), |material| material as *const [u8; 32]),
+    );
+
+    let initialized = ((*resp).status_code, (*resp).sector_builder);
+    destroy_init_sector_builder_response(resp);
+
+    initialized
+}
+
+unsafe fn max_bytes(builder: *mut SectorBuilder) -> usize {
+    let resp = get_max_user_bytes_per_staged_sector(builder);
+    let max_bytes = (*resp).max_staged_bytes_per_sector as usize;
+    destroy_get_max_user_bytes_per_staged_sector_response(resp);
+
+    max_bytes
+}
+
+// The status of the call, and the id of the sector the piece was placed in.
+unsafe fn add(builder: *mut SectorBuilder, key: &str, bytes: &[u8]) -> (FCPResponseStatus, u64) {
+    let resp = add_piece(
+        builder,
+        rust_str_to_c_str(key),
+        bytes.as_ptr(),
+        bytes.len(),
+        100,
+    );
+
+    let added = ((*resp).status_code, (*resp).sector_id);
+    destroy_add_piece_response(resp);
+
+    added
+}
+
+// The status of the call, and the piece's bytes if it succeeded.
+unsafe fn read(builder: *mut SectorBuilder, key: &str) -> (FCPResponseStatus, Vec<u8>) {
+    let resp = read_piece_from_sealed_sector(builder, rust_str_to_c_str(key));
+
+    let read = if (*resp).status_code == FCPResponseStatus::FCPNoError {
+        slice::from_raw_parts((*resp).data_ptr, (*resp).data_len).to_vec()
+    } else {
+        Vec::new()
+    };
+    let status = (*resp).status_code;
+    destroy_read_piece_from_sealed_sector_response(resp);
+
+    (status, read)
+}
+
+// The keys of the pieces of the builder's sealed sectors, as listed, sorted.
+unsafe fn sealed_keys(builder: *mut SectorBuilder, real_keys: bool) -> Vec<String> {
+    let resp = get_sealed_sectors(builder, real_keys);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let mut keys: Vec<String> = slice::from_raw_parts((*resp).sectors_ptr, (*resp).sectors_len)
+        .iter()
+        .flat_map(|s| slice::from_raw_parts(s.pieces_ptr, s.pieces_len).iter())
+        .map(|p| to_string(p.piece_key))
+        .collect();
+    destroy_get_sealed_sectors_response(resp);
+
+    keys.sort();
+
+    keys
+}
+
+// The keys of the pieces expiring before 1000, as listed, sorted.
+unsafe fn expiring_keys(builder: *mut SectorBuilder, real_keys: bool) -> Vec<String> {
+    let resp = get_expiring_pieces(builder, 1000, real_keys);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let mut keys: Vec<String> = slice::from_raw_parts((*resp).pieces_ptr, (*resp).pieces_len)
+        .iter()
+        .map(|p| to_string(p.piece_key))
+        .collect();
+    destroy_get_expiring_pieces_response(resp);
+
+    keys.sort();
+
+    keys
+}
+
+unsafe fn wait_until_sealed(builder: *mut SectorBuilder, sector_id: u64) {
+    let start = Instant::now();
+
+    loop {
+        let resp = get_seal_status(builder, sector_id);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+        if (*resp).seal_status_code == FFISealStatus::Failed {
+            panic!(
+                "sealing sector {} failed: {}",
+                sector_id,
+                to_string((*resp).seal_error_msg)
+            );
+        }
+
+        let sealed = (*resp).seal_status_code == FFISealStatus::Sealed;
+        destroy_get_seal_status_response(resp);
+
+        if sealed {
+            return;
+        }
+
+        assert!(
+            start.elapsed() < SEAL_TIMEOUT,
+            "sector {} took too long to seal",
+            sector_id
+        );
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+// The contents of every file under dir.
+fn read_all(dir: &Path) -> Vec<(String, Vec<u8>)> {
+    let mut files = Vec::new();
+
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(read_all(&path));
+        } else {
+            files.push((path.display().to_string(), fs::read(&path).unwrap()));
+        }
+    }
+
+    files
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+#[test]
+fn private_piece_keys_are_never_recorded() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let rng = &mut thread_rng();
+    let material = [3; 32];
+    let dirs = Dirs::new();
+
+    unsafe {
+        let (status, builder) = init(&dirs, Some(&material));
+        assert_eq!(FCPResponseStatus::FCPNoError, status);
+
+        let pieces: Vec<Vec<u8>> = KEYS
+            .iter()
+            .map(|_| (0..max_bytes(builder) / 4).map(|_| rng.gen()).collect())
+            .collect();
+
+        let (status, sector_id) = add(builder, KEYS[0], &pieces[0]);
+        assert_eq!(FCPResponseStatus::FCPNoError, status);
+        assert_eq!(
+            (FCPResponseStatus::FCPNoError, sector_id),
+            add(builder, KEYS[1], &pieces[1])
+        );
+
+        let resp = seal_all_staged_sectors(builder);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_seal_all_staged_sectors_response(resp);
+
+        wait_until_sealed(builder, sector_id);
+
+        // Pieces are read, and listed, by key, or by pseudonym if asked.
+        assert_eq!(
+            (FCPResponseStatus::FCPNoError, pieces[0].clone()),
+            read(builder, KEYS[0])
+        );
+        assert_eq!(KEYS.to_vec(), sealed_keys(builder, true));
+        assert_eq!(KEYS.to_vec(), expiring_keys(builder, true));
+
+        let pseudonyms = sealed_keys(builder, false);
+        assert_eq!(2, pseudonyms.len());
+        assert!(pseudonyms
+            .iter()
+            .all(|p| p.len() == 64 && !KEYS.contains(&p.as_str())));
+        assert_eq!(pseudonyms, expiring_keys(builder, false));
+
+        destroy_sector_builder(builder, 0);
+
+        // No file of the metadata, the audit log included, names a piece.
+        let files = read_all(dirs.metadata.path());
+        assert!(!files.is_empty());
+        for (path, bytes) in files {
+            for key in &KEYS {
+                assert!(!contains(&bytes, key.as_bytes()), "{} names {}", path, key);
+            }
+        }
+
+        // Without the material, pieces are served by pseudonym, but none may
+        // be added.
+        let (status, builder) = init(&dirs, None);
+        assert_eq!(FCPResponseStatus::FCPNoError, status);
+
+        assert_eq!(pseudonyms, sealed_keys(builder, true));
+        assert_eq!(FCPResponseStatus::FCPCallerError, read(builder, KEYS[0]).0);
+
+        let mut read_by_pseudonym: Vec<Vec<u8>> = pseudonyms
+            .iter()
+            .map(|pseudonym| read(builder, pseudonym))
+            .map(|(status, bytes)| {
+                assert_eq!(FCPResponseStatus::FCPNoError, status);
+                bytes
+            })
+            .collect();
+        read_by_pseudonym.sort();
+        let mut expected = pieces.clone();
+        expected.sort();
+        assert_eq!(expected, read_by_pseudonym);
+
+        assert_eq!(
+            FCPResponseStatus::FCPCallerError,
+            add(builder, "another", &pieces[0]).0
+        );
+
+        destroy_sector_builder(builder, 0);
+
+        // Other material is refused.
+        assert_eq!(
+            FCPResponseStatus::FCPCallerError,
+            init(&dirs, Some(&[4; 32])).0
+        );
+
+        // With the material again, pieces are listed by key once read by it.
+        let (status, builder) = init(&dirs, Some(&material));
+        assert_eq!(FCPResponseStatus::FCPNoError, status);
+
+        assert_eq!(pseudonyms, sealed_keys(builder, true));
+        assert_eq!(
+            (FCPResponseStatus::FCPNoError, pieces[1].clone()),
+            read(builder, KEYS[1])
+        );
+
+        let listed = sealed_keys(builder, true);
+        assert!(listed.contains(&KEYS[1].to_string()));
+        assert!(!listed.contains(&KEYS[0].to_string()));
+
+        destroy_sector_builder(builder, 0);
+    }
+}
@@ -0,0 +1,121 @@
+//! Checks that verify_post_detailed finds nothing wrong with a vanilla PoSt of
+//! intact replicas, and attributes the failures of one over a corrupt replica
+//! to its sector.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals three sectors:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test post_diagnostics
+#![cfg(feature = "slow-tests")]
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::internal::{generate_vanilla_post, PoStInput, PoStInputPart};
+use filecoin_proofs::api::replica_format::LegacyReplicas;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use sector_base::api::disk_backed_storage::{ConfiguredStore, TEST_SECTOR_SIZE};
+use std::fs;
+use std::slice;
+
+use crate::support::{create_harness, BytesAmount, Harness};
+
+const CHALLENGE_SEED: [u8; 32] = [7; 32];
+
+fn vanilla_post(harnesses: &[Harness]) -> Vec<u8> {
+    let input_parts = harnesses
+        .iter()
+        .map(|h| PoStInputPart {
+            sealed_sector_access: Some(h.sealed_access.clone()),
+            comm_r: h.seal_output.comm_r,
+            legacy_replicas: LegacyReplicas::Refuse,
+        })
+        .collect();
+    let input = PoStInput {
+        challenge_seed: CHALLENGE_SEED,
+        input_parts,
+    };
+
+    generate_vanilla_post(harnesses[0].store.config().sector_bytes(), input)
+        .expect("failed to generate vanilla PoSt")
+}
+
+unsafe fn verify_detailed(
+    sector_bytes: u64,
+    comm_rs: &[[u8; 32]],
+    proof: &[u8],
+) -> *mut VerifyPoSTDetailedResponse {
+    let flattened_comm_rs: Vec<u8> = comm_rs.iter().flat_map(|comm_r| comm_r.to_vec()).collect();
+
+    verify_post_detailed(
+        flattened_comm_rs.as_ptr(),
+        flattened_comm_rs.len(),
+        &CHALLENGE_SEED,
+        proof.as_ptr(),
+        proof.len(),
+        [].as_ptr(),
+        0,
+        sector_bytes,
+    )
+}
+
+#[test]
+fn a_corrupt_replica_is_named_by_its_failures() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let cs = ConfiguredStore::Test;
+    let harnesses: Vec<Harness> = (0..3)
+        .map(|_| create_harness(&cs, &[BytesAmount::Max]))
+        .collect();
+    let sector_bytes = harnesses[0].store.config().sector_bytes();
+    let comm_rs: Vec<[u8; 32]> = harnesses.iter().map(|h| h.seal_output.comm_r).collect();
+
+    unsafe {
+        let resp = verify_detailed(sector_bytes, &comm_rs, &vanilla_post(&harnesses));
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        assert!((*resp).is_valid);
+        assert_eq!(0, (*resp).failures_len);
+        destroy_verify_post_detailed_response(resp);
+    }
+
+    // Every challenge currently falls on the first sector, so it's the one
+    // corrupted.
+    let mut replica = fs::read(&harnesses[0].sealed_access).unwrap();
+    replica[0] ^= 0xff;
+    fs::write(&harnesses[0].sealed_access, replica).unwrap();
+
+    unsafe {
+        let resp = verify_detailed(sector_bytes, &comm_rs, &vanilla_post(&harnesses));
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        assert!(!(*resp).is_valid);
+
+        let failures = slice::from_raw_parts((*resp).failures_ptr, (*resp).failures_len);
+        assert!(!failures.is_empty());
+        for (i, failure) in failures.iter().enumerate() {
+            assert_eq!(0, failure.sector_index);
+            assert_eq!(comm_rs[0], failure.comm_r);
+            assert_eq!(i as u64, failure.challenge_index);
+            assert_eq!(FFIPoSTFailureKind::PathMismatch, failure.kind);
+        }
+
+        destroy_verify_post_detailed_response(resp);
+    }
+}
+
+#[test]
+fn a_malformed_proof_is_an_error() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    unsafe {
+        let resp = verify_detailed(TEST_SECTOR_SIZE, &[[0; 32]], b"not a proof");
+        assert_eq!(FCPResponseStatus::FCPUnclassifiedError, (*resp).status_code);
+        destroy_verify_post_detailed_response(resp);
+    }
+}
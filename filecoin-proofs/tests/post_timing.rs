@@ -0,0 +1,87 @@
+//! Checks that the breakdowns of the time taken to generate and verify PoSts
+//! account for that time, and that a sweep over numbers of challenges (as run
+//! by the post-timing example) writes a line of CSV for each.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals two sectors and
+//! generates parameters for each number of challenges:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test post_timing
+#![cfg(feature = "slow-tests")]
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate storage_proofs;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::post_timing::{sweep_challenge_counts, sweep_csv_header, SweptSector};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use storage_proofs::post_timing::PostTimingBreakdown;
+use std::time::Duration;
+
+use crate::support::{create_harness, BytesAmount, Harness};
+
+const CHALLENGE_COUNTS: [usize; 2] = [1, 3];
+
+fn swept(h: &Harness) -> SweptSector {
+    SweptSector {
+        sealed_sector_access: h.sealed_access.clone(),
+        comm_r: h.seal_output.comm_r,
+    }
+}
+
+// The phases account for most of the time taken: what's left is spent setting
+// up, which is small next to proving once parameters are loaded.
+fn assert_accounted_for(breakdown: &PostTimingBreakdown) {
+    let phases = breakdown.phases_total();
+
+    assert!(phases <= breakdown.total, "{:?}", breakdown);
+    assert!(phases * 2 >= breakdown.total, "{:?}", breakdown);
+}
+
+#[test]
+fn sweeps_account_for_the_time_taken() {
+    let cs = ConfiguredStore::Test;
+    let harnesses = vec![
+        create_harness(&cs, &[BytesAmount::Max]),
+        create_harness(&cs, &[BytesAmount::Offset(50)]),
+    ];
+    let sectors: Vec<SweptSector> = harnesses.iter().map(swept).collect();
+    let sector_bytes = harnesses[0].store.config().sector_bytes();
+
+    let mut csv = Vec::new();
+    let results =
+        sweep_challenge_counts(sector_bytes, &sectors, [5; 32], &CHALLENGE_COUNTS, &mut csv)
+            .expect("failed to sweep challenge counts");
+
+    let csv = String::from_utf8(csv).unwrap();
+    let lines: Vec<&str> = csv.lines().collect();
+    assert_eq!(sweep_csv_header(), lines[0]);
+    assert_eq!(1 + 2 * CHALLENGE_COUNTS.len(), lines.len());
+
+    let columns = lines[0].split(',').count();
+    assert!(lines.iter().all(|line| line.split(',').count() == columns));
+
+    for (result, &challenge_count) in results.iter().zip(&CHALLENGE_COUNTS) {
+        assert_eq!(challenge_count, result.challenge_count);
+
+        let generation = &result.generation;
+        assert!(generation.challenges >= challenge_count as u64);
+        assert!(generation.leaf_read > Duration::default());
+        assert!(generation.path_hashing > Duration::default());
+        assert!(generation.aggregation > Duration::default());
+        assert_accounted_for(generation);
+
+        // Only the SNARK is verified. Verifying it takes little enough time
+        // that loading its parameters may take longer.
+        let verification = &result.verification;
+        assert!(verification.aggregation > Duration::default());
+        assert_eq!(verification.aggregation, verification.phases_total());
+        assert!(verification.phases_total() <= verification.total);
+    }
+}
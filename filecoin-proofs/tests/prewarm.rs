@@ -0,0 +1,93 @@
+//! Checks that prefetching a store's seal requirements on a fresh machine
+//! generates what a first seal needs, that prefetching again finds everything
+//! ready, and that a seal right after generates no parameters.
+//!
+//! Compiled only with the `slow-tests` feature, as it generates parameters and
+//! seals a sector. The parameter cache is pointed at a fresh directory for the
+//! whole process, so this binary holds a single test:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test prewarm
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate sector_base;
+extern crate storage_proofs;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::internal::seal;
+use filecoin_proofs::api::prefetch_seal_requirements as ffi_prefetch_seal_requirements;
+use filecoin_proofs::api::prewarm::prefetch_seal_requirements;
+use filecoin_proofs::api::responses::{
+    destroy_init_response, destroy_prefetch_seal_requirements_response, FCPResponseStatus,
+};
+use filecoin_proofs::api::{filecoin_proofs_init, FILECOIN_PROOFS_ABI_VERSION};
+use sector_base::api::disk_backed_storage::{
+    destroy_storage, init_new_test_sector_store, new_sector_store, ConfiguredStore,
+};
+use sector_base::api::sector_store::SectorStore;
+use std::env;
+use storage_proofs::parameter_cache::parameters_generated;
+use tempfile::TempDir;
+
+#[test]
+fn prefetched_requirements_are_cached_for_the_first_seal() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let params_dir = TempDir::new().unwrap();
+    env::set_var("FILECOIN_PARAMETER_CACHE", params_dir.path());
+
+    let root = TempDir::new().unwrap();
+    let path = |name: &str| root.path().join(name).to_str().unwrap().to_owned();
+    let store = new_sector_store(&ConfiguredStore::Test, path("sealed"), path("staging"));
+
+    let cold = prefetch_seal_requirements(&store).expect("failed to prefetch");
+    assert!(!cold.directories.cache_hit);
+    assert!(!cold.parameters.cache_hit);
+    assert!(!cold.graph.cache_hit);
+
+    let warm = prefetch_seal_requirements(&store).expect("failed to prefetch");
+    assert!(warm.all_cache_hits(), "{:?}", warm);
+    assert!(
+        warm.elapsed() * 10 <= cold.elapsed(),
+        "prefetching took {:?} when warm, and {:?} when cold",
+        warm.elapsed(),
+        cold.elapsed()
+    );
+
+    // The seal reads the parameters rather than generate them.
+    let generated = parameters_generated();
+
+    let mgr = store.manager();
+    let staged_access = mgr.new_staging_sector_access().unwrap();
+    let sealed_access = mgr.new_sealed_sector_access().unwrap();
+    let data = vec![7; store.config().max_unsealed_bytes_per_sector() as usize];
+    mgr.write_and_preprocess(&staged_access, &data).unwrap();
+
+    seal(store.config(), &staged_access, &sealed_access, &[1; 31], &[2; 31])
+        .expect("failed to seal");
+    assert_eq!(generated, parameters_generated());
+
+    // Over FFI, a store of the same size finds everything ready, its
+    // directories included, as creating the store made them.
+    unsafe {
+        let handle = init_new_test_sector_store(
+            rust_str_to_c_str(&path("other-staging")),
+            rust_str_to_c_str(&path("other-sealed")),
+        );
+
+        let resp = ffi_prefetch_seal_requirements(handle, 0);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        assert!((*resp).directories_cache_hit);
+        assert!((*resp).parameters_cache_hit);
+        assert!((*resp).graph_cache_hit);
+        destroy_prefetch_seal_requirements_response(resp);
+
+        destroy_storage(handle);
+
+        let resp = ffi_prefetch_seal_requirements(handle, 0);
+        assert_eq!(FCPResponseStatus::FCPCallerError, (*resp).status_code);
+        destroy_prefetch_seal_requirements_response(resp);
+    }
+}
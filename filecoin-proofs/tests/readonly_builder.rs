@@ -0,0 +1,310 @@
+//! Checks that a read-only sector builder, opened over the directories of a
+//! sector sealed by another builder with write permission removed from them,
+//! unseals and verifies that sector, and refuses every call which would write
+//! without touching its directories.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals a sector, and only
+//! on unix, where write permission is removed from directories by mode bits:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test readonly_builder
+#![cfg(all(unix, feature = "slow-tests"))]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use rand::{thread_rng, Rng};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::ffi::CStr;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tempfile::TempDir;
+
+const SEAL_TIMEOUT: Duration = Duration::from_secs(600);
+
+const PROVER_ID: [u8; 31] = [9; 31];
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+impl Dirs {
+    fn paths(&self) -> [&Path; 3] {
+        [self.metadata.path(), self.sealed.path(), self.staged.path()]
+    }
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+unsafe fn to_string(ptr: *const std::os::raw::c_char) -> String {
+    CStr::from_ptr(ptr).to_str().unwrap().to_owned()
+}
+
+// The sector id as verify_seal takes it, encoded as the builder encodes it.
+fn encoded_sector_id(sector_id: u64) -> [u8; 31] {
+    let mut bytes = [0; 31];
+    unsafe { sector_id_as_bytes(sector_id, &mut bytes) };
+    bytes
+}
+
+unsafe fn init(dirs: &Dirs, read_only: bool) -> *mut SectorBuilder {
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        0,
+        u64::max_value(),
+        c_str(dirs.metadata.path()),
+        &PROVER_ID,
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        1,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        read_only,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        ptr::null(),
+    );
+    assert_eq!(
+        FCPResponseStatus::FCPNoError,
+        (*resp).status_code,
+        "{}",
+        to_string((*resp).error_msg)
+    );
+
+    let builder = (*resp).sector_builder;
+    destroy_init_sector_builder_response(resp);
+
+    builder
+}
+
+unsafe fn wait_until_sealed(builder: *mut SectorBuilder, sector_id: u64) {
+    let start = Instant::now();
+
+    loop {
+        let resp = get_seal_status(builder, sector_id);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+        if (*resp).seal_status_code == FFISealStatus::Failed {
+            panic!("sealing sector {} failed: {}", sector_id, to_string((*resp).seal_error_msg));
+        }
+
+        let sealed = (*resp).seal_status_code == FFISealStatus::Sealed;
+        destroy_get_seal_status_response(resp);
+
+        if sealed {
+            return;
+        }
+
+        assert!(start.elapsed() < SEAL_TIMEOUT, "sector {} took too long to seal", sector_id);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+unsafe fn verify(builder: *mut SectorBuilder, sector_id: u64) -> bool {
+    let resp = get_seal_status(builder, sector_id);
+    assert_eq!(FFISealStatus::Sealed, (*resp).seal_status_code);
+
+    let verified = verify_seal(
+        &ConfiguredStore::Test,
+        &(*resp).comm_r,
+        &(*resp).comm_d,
+        &(*resp).comm_r_star,
+        &PROVER_ID,
+        &encoded_sector_id(sector_id),
+        &(*resp).snark_proof,
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*verified).status_code);
+
+    let is_valid = (*verified).is_valid;
+    destroy_verify_seal_response(verified);
+    destroy_get_seal_status_response(resp);
+
+    is_valid
+}
+
+unsafe fn read_piece(builder: *mut SectorBuilder, key: &str) -> Vec<u8> {
+    let resp = read_piece_from_sealed_sector(builder, rust_str_to_c_str(key));
+    assert_eq!(
+        FCPResponseStatus::FCPNoError,
+        (*resp).status_code,
+        "{}",
+        to_string((*resp).error_msg)
+    );
+
+    let bytes = slice::from_raw_parts((*resp).data_ptr, (*resp).data_len).to_vec();
+    destroy_read_piece_from_sealed_sector_response(resp);
+
+    bytes
+}
+
+// Checks that a call was refused as the builder is read-only.
+unsafe fn assert_read_only(
+    status_code: FCPResponseStatus,
+    error_msg: *const std::os::raw::c_char,
+) {
+    assert_eq!(FCPResponseStatus::FCPCallerError, status_code);
+
+    let error_msg = to_string(error_msg);
+    assert!(error_msg.contains("read-only"), "{}", error_msg);
+}
+
+// Every entry under the given directories, with its length and modification
+// time.
+fn tree(dirs: &[&Path]) -> Vec<(PathBuf, u64, SystemTime)> {
+    let mut entries = Vec::new();
+    let mut pending: Vec<PathBuf> = dirs.iter().map(|dir| dir.to_path_buf()).collect();
+
+    while let Some(dir) = pending.pop() {
+        for entry in fs::read_dir(&dir).unwrap() {
+            let path = entry.unwrap().path();
+            let metadata = fs::metadata(&path).unwrap();
+
+            if metadata.is_dir() {
+                pending.push(path.clone());
+            }
+
+            entries.push((path, metadata.len(), metadata.modified().unwrap()));
+        }
+    }
+
+    entries.sort();
+    entries
+}
+
+// Sets the mode of every entry under the given directories, and of the
+// directories themselves: 0o555 for directories, and 0o444 for files if
+// read_only is set, or 0o755 and 0o644 if not.
+fn set_read_only(dirs: &[&Path], read_only: bool) {
+    let (dir_mode, file_mode) = if read_only {
+        (0o555, 0o444)
+    } else {
+        (0o755, 0o644)
+    };
+
+    let mut pending: Vec<PathBuf> = dirs.iter().map(|dir| dir.to_path_buf()).collect();
+
+    while let Some(dir) = pending.pop() {
+        // A directory is made writable before its entries are changed, and
+        // read-only after.
+        let mut entries = Vec::new();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+
+        for entry in fs::read_dir(&dir).unwrap() {
+            entries.push(entry.unwrap().path());
+        }
+
+        for path in entries {
+            if fs::metadata(&path).unwrap().is_dir() {
+                pending.push(path);
+            } else {
+                fs::set_permissions(&path, fs::Permissions::from_mode(file_mode)).unwrap();
+            }
+        }
+
+        fs::set_permissions(&dir, fs::Permissions::from_mode(dir_mode)).unwrap();
+    }
+}
+
+#[test]
+fn read_only_builders_serve_sealed_sectors_without_writing() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let rng = &mut thread_rng();
+    let export_dir = TempDir::new().unwrap();
+    let dirs = Dirs {
+        metadata: TempDir::new().unwrap(),
+        sealed: TempDir::new().unwrap(),
+        staged: TempDir::new().unwrap(),
+    };
+
+    unsafe {
+        // A miner's builder seals a sector holding a single piece, and exports
+        // it, so that there's a manifest to try to import.
+        let miner = init(&dirs, false);
+
+        let max_bytes = {
+            let resp = get_max_user_bytes_per_staged_sector(miner);
+            let max_bytes = (*resp).max_staged_bytes_per_sector as usize;
+            destroy_get_max_user_bytes_per_staged_sector_response(resp);
+            max_bytes
+        };
+
+        let piece: Vec<u8> = (0..max_bytes / 2).map(|_| rng.gen()).collect();
+        let resp = add_piece(miner, rust_str_to_c_str("piece"), piece.as_ptr(), piece.len(), 0);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        let sector_id = (*resp).sector_id;
+        destroy_add_piece_response(resp);
+
+        let resp = seal_all_staged_sectors(miner);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_seal_all_staged_sectors_response(resp);
+
+        wait_until_sealed(miner, sector_id);
+
+        let resp = export_sealed_sector(miner, sector_id, c_str(export_dir.path()));
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        let manifest_path = to_string((*resp).manifest_path);
+        destroy_export_sealed_sector_response(resp);
+
+        destroy_sector_builder(miner, 0);
+
+        // A verifier opens the miner's directories, made read-only.
+        set_read_only(&dirs.paths(), true);
+        let before = tree(&dirs.paths());
+
+        let verifier = init(&dirs, true);
+
+        assert!(verify(verifier, sector_id));
+        assert_eq!(piece, read_piece(verifier, "piece"));
+
+        // Every call which would write is refused.
+        let key = rust_str_to_c_str("more");
+        let resp = add_piece(verifier, key, piece.as_ptr(), piece.len(), 0);
+        assert_read_only((*resp).status_code, (*resp).error_msg);
+        destroy_add_piece_response(resp);
+
+        let resp = seal_all_staged_sectors(verifier);
+        assert_read_only((*resp).status_code, (*resp).error_msg);
+        destroy_seal_all_staged_sectors_response(resp);
+
+        let resp = import_sealed_sector(verifier, rust_str_to_c_str(&manifest_path));
+        assert_read_only((*resp).status_code, (*resp).error_msg);
+        destroy_import_sealed_sector_response(resp);
+
+        let resp = migrate_sectors(verifier, &sector_id, 1, &ConfiguredStore::LargeTest);
+        assert_read_only((*resp).status_code, (*resp).error_msg);
+        destroy_migrate_sectors_response(resp);
+
+        // Reads still work once the refusals are done.
+        assert_eq!(piece, read_piece(verifier, "piece"));
+
+        destroy_sector_builder(verifier, 0);
+
+        // Nothing was created, changed or removed, even where the process
+        // could still write (e.g. when run as root).
+        assert_eq!(before, tree(&dirs.paths()));
+
+        set_read_only(&dirs.paths(), false);
+    }
+}
@@ -0,0 +1,259 @@
+//! End-to-end seal, verify and unseal tests.
+//!
+//! These are slow by design and are only compiled when the `slow-tests`
+//! feature is enabled:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests
+//!
+//! Cases against the Live sector size additionally require `slow-tests-live`.
+//! The quick cases against a mini store, run on every commit, are in mini.rs.
+#![cfg(feature = "slow-tests")]
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::internal::{
+    generate_post, verify_post, verify_seal, PoStInput, PoStInputPart,
+};
+use filecoin_proofs::api::replica_format::LegacyReplicas;
+use rand::{thread_rng, Rng};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::thread;
+
+use crate::support::{
+    assert_unsealed_range, create_harness, max_unsealed_bytes_per_sector, run_case, BytesAmount,
+};
+
+fn seal_verify_aux(cs: ConfiguredStore, bytes_amt: BytesAmount) {
+    // create_harness asserts that the proof verifies against the commitments
+    // produced by seal.
+    let h = create_harness(&cs, &[bytes_amt]);
+
+    let is_valid = verify_seal(
+        h.store.config(),
+        h.seal_output.comm_d,
+        h.seal_output.comm_r_star,
+        h.seal_output.comm_r,
+        &h.prover_id,
+        &h.sector_id,
+        &h.seal_output.proof,
+    )
+    .expect("failed to run verify_seal");
+
+    // Note that comm_d is passed for comm_r and comm_r_star for comm_d.
+    assert!(!is_valid, "proof with rotated commitments should not be valid");
+}
+
+fn seal_verify_swapped_comm_r_comm_d_aux(cs: ConfiguredStore) {
+    let h = create_harness(&cs, &[BytesAmount::Max]);
+
+    let is_valid = verify_seal(
+        h.store.config(),
+        h.seal_output.comm_d,
+        h.seal_output.comm_r,
+        h.seal_output.comm_r_star,
+        &h.prover_id,
+        &h.sector_id,
+        &h.seal_output.proof,
+    )
+    .expect("failed to run verify_seal");
+
+    assert!(
+        !is_valid,
+        "proof with swapped comm_r and comm_d should not be valid"
+    );
+}
+
+fn seal_unsealed_roundtrip_aux(cs: ConfiguredStore, bytes_amt: BytesAmount) {
+    let h = create_harness(&cs, &[bytes_amt]);
+    let buf = h.read_unsealed();
+    let mgr = h.store.manager();
+
+    // read_raw must agree with the unsealed file, in full and for an interior
+    // slice.
+    for (start, len) in &[(0, buf.len()), (1, buf.len() - 2)] {
+        let read_unsealed_buf = mgr
+            .read_raw(&h.unseal_access, *start as u64, *len as u64)
+            .expect("failed to read_raw");
+
+        assert_eq!(
+            &buf[*start..*start + *len],
+            &read_unsealed_buf[..],
+            "read_raw contents differed for cs={:?}, start={}, len={}",
+            cs,
+            start,
+            len
+        );
+    }
+
+    let written = h.written_contents.concat();
+
+    assert_eq!(
+        written[..],
+        buf[..],
+        "original and unsealed contents differed for cs={:?}",
+        cs
+    );
+}
+
+fn seal_unsealed_exact_lengths_aux(cs: ConfiguredStore) {
+    let max = max_unsealed_bytes_per_sector(&cs);
+
+    // Whatever was written, asking for the whole sector returns exactly that,
+    // with no padding and no knowledge of how much was written.
+    for len in &[0, 1, 127, 128, max / 2, max - 1, max] {
+        let h = create_harness(&cs, &[BytesAmount::Offset(max - len)]);
+
+        assert_eq!(
+            h.written_contents[0],
+            h.read_unsealed(),
+            "original and unsealed contents differed for cs={:?}, len={}",
+            cs,
+            len
+        );
+        assert_unsealed_range(&h, 0, *len);
+    }
+}
+
+fn seal_unsealed_range_roundtrip_aux(cs: ConfiguredStore, bytes_amt: BytesAmount) {
+    let h = create_harness(&cs, &[bytes_amt]);
+
+    let offset = 5;
+    let range_length = h.written_contents[0].len() as u64 - offset;
+
+    assert_unsealed_range(&h, offset, range_length);
+}
+
+fn write_and_preprocess_overwrites_unaligned_last_bytes_aux(cs: ConfiguredStore) {
+    // The minimal reproduction for the bug this regression test checks is to
+    // write 32 bytes, then 95 bytes. With suitable bytes (e.g. all 255), the
+    // bug always occurs when the first chunk is >= 32. The root problem was
+    // that write_and_preprocess was opening in append mode, so seeking
+    // backward to overwrite the last, incomplete byte, was not happening.
+    let h = create_harness(
+        &cs,
+        &[
+            BytesAmount::Exact(vec![255; 32]),
+            BytesAmount::Exact(vec![255; 95]),
+        ],
+    );
+
+    assert_unsealed_range(&h, 0, 32 + 95);
+}
+
+fn seal_max_size_input_aux(cs: ConfiguredStore) {
+    // Every bit set, filling the sector, exercises the fr32 padding at its
+    // upper bound.
+    let max = max_unsealed_bytes_per_sector(&cs);
+
+    let h = create_harness(&cs, &[BytesAmount::Exact(vec![255; max as usize])]);
+
+    assert_eq!(0, h.byte_padding_amount());
+    assert_unsealed_range(&h, 0, max);
+}
+
+fn post_verify_aux(cs: ConfiguredStore, bytes_amt: BytesAmount) {
+    let mut rng = thread_rng();
+    let h = create_harness(&cs, &[bytes_amt]);
+
+    let sector_bytes = h.store.config().sector_bytes();
+    let comm_r = h.seal_output.comm_r;
+    let comm_rs = vec![comm_r, comm_r];
+    let challenge_seed = rng.gen();
+
+    let post_output = generate_post(
+        sector_bytes,
+        PoStInput {
+            challenge_seed,
+            input_parts: vec![
+                PoStInputPart {
+                    sealed_sector_access: Some(h.sealed_access.clone()),
+                    comm_r,
+                    legacy_replicas: LegacyReplicas::Refuse,
+                },
+                PoStInputPart {
+                    sealed_sector_access: Some(h.sealed_access.clone()),
+                    comm_r,
+                    legacy_replicas: LegacyReplicas::Refuse,
+                },
+            ],
+        },
+    )
+    .expect("PoSt generation failed");
+
+    let is_valid = verify_post(
+        sector_bytes,
+        &comm_rs,
+        &challenge_seed,
+        &post_output.snark_proof,
+        post_output.faults,
+    )
+    .expect("failed to run verify_post");
+
+    assert!(is_valid, "verification of valid proof failed");
+}
+
+fn concurrent_seal_unsealed_range_roundtrip_aux(cs: ConfiguredStore) {
+    let threads = 5;
+
+    let spawned = (0..threads)
+        .map(|_| thread::spawn(move || seal_unsealed_range_roundtrip_aux(cs, BytesAmount::Max)))
+        .collect::<Vec<_>>();
+
+    for thread in spawned {
+        thread.join().expect("test thread panicked");
+    }
+}
+
+/// Expands to one #[test] per store for each case. Live cases are only
+/// compiled with the `slow-tests-live` feature.
+macro_rules! store_cases {
+    ($($name:ident: $body:expr,)*) => {
+        mod test_store {
+            use super::*;
+            $(
+                #[test]
+                fn $name() {
+                    run_case(stringify!($name), ConfiguredStore::Test, $body);
+                }
+            )*
+        }
+
+        #[cfg(feature = "slow-tests-live")]
+        mod live_store {
+            use super::*;
+            $(
+                #[test]
+                fn $name() {
+                    run_case(stringify!($name), ConfiguredStore::Live, $body);
+                }
+            )*
+        }
+    };
+}
+
+store_cases! {
+    seal_verify_max: |cs| seal_verify_aux(cs, BytesAmount::Max),
+    seal_verify_offset: |cs| seal_verify_aux(cs, BytesAmount::Offset(5)),
+    seal_verify_swapped_comm_r_comm_d: seal_verify_swapped_comm_r_comm_d_aux,
+    seal_unsealed_roundtrip_max: |cs| seal_unsealed_roundtrip_aux(cs, BytesAmount::Max),
+    seal_unsealed_roundtrip_offset: |cs| seal_unsealed_roundtrip_aux(cs, BytesAmount::Offset(5)),
+    seal_unsealed_range_roundtrip_max: |cs| seal_unsealed_range_roundtrip_aux(cs, BytesAmount::Max),
+    seal_unsealed_range_roundtrip_offset: |cs| {
+        seal_unsealed_range_roundtrip_aux(cs, BytesAmount::Offset(5))
+    },
+    write_and_preprocess_overwrites_unaligned_last_bytes: |cs| {
+        write_and_preprocess_overwrites_unaligned_last_bytes_aux(cs)
+    },
+    seal_max_size_input: seal_max_size_input_aux,
+    seal_unsealed_exact_lengths: seal_unsealed_exact_lengths_aux,
+    concurrent_seal_unsealed_range_roundtrip: concurrent_seal_unsealed_range_roundtrip_aux,
+    post_verify: |cs| post_verify_aux(cs, BytesAmount::Max),
+}
@@ -0,0 +1,201 @@
+//! Checks that a SectorBuilder's memory budget holds back seals which would
+//! exceed it until those running finish, and that without one they overlap.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals sectors:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test seal_scheduling
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::estimate::seal_working_memory_bytes;
+use filecoin_proofs::api::proof_params::proof_params;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use sector_base::api::disk_backed_storage::{ConfiguredStore, TEST_SECTOR_SIZE};
+use sector_base::api::sector_store::ProofVersion;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use tempfile::TempDir;
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+impl Dirs {
+    fn new() -> Dirs {
+        Dirs {
+            metadata: TempDir::new().unwrap(),
+            sealed: TempDir::new().unwrap(),
+            staged: TempDir::new().unwrap(),
+        }
+    }
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+unsafe fn init(dirs: &Dirs, seal_memory_budget_bytes: u64) -> *mut SectorBuilder {
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        0,
+        u64::max_value(),
+        c_str(dirs.metadata.path()),
+        &[3; 31],
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        2,
+        0,
+        0,
+        0,
+        0,
+        seal_memory_budget_bytes,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        ptr::null(),
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let builder = (*resp).sector_builder;
+    destroy_init_sector_builder_response(resp);
+
+    builder
+}
+
+// What get_seal_admissions reported: the sector ids of the running seals, the
+// queued ones, and the finished seals as (sector id, memory, admitted at,
+// finished at).
+struct Admissions {
+    running: Vec<u64>,
+    queued: Vec<u64>,
+    finished: Vec<(u64, u64, u64, u64)>,
+    memory_budget_bytes: u64,
+}
+
+unsafe fn admissions(builder: *mut SectorBuilder) -> Admissions {
+    let resp = get_seal_admissions(builder);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let running = slice::from_raw_parts((*resp).running_ptr, (*resp).running_len);
+    let queued = slice::from_raw_parts((*resp).queued_ptr, (*resp).queued_len);
+    let finished = slice::from_raw_parts((*resp).finished_ptr, (*resp).finished_len);
+
+    let admissions = Admissions {
+        running: running.iter().map(|job| job.sector_id).collect(),
+        queued: queued.to_vec(),
+        finished: finished
+            .iter()
+            .map(|job| {
+                (
+                    job.sector_id,
+                    job.memory_bytes,
+                    job.admitted_at_millis,
+                    job.finished_at_millis,
+                )
+            })
+            .collect(),
+        memory_budget_bytes: (*resp).memory_budget_bytes,
+    };
+    destroy_get_seal_admissions_response(resp);
+
+    admissions
+}
+
+// Adds two pieces which fill a sector each, so that both sectors are
+// scheduled for sealing at once, and waits for them to be sealed, returning
+// the seals as they finished.
+unsafe fn seal_two_sectors(builder: *mut SectorBuilder) -> (Admissions, Admissions) {
+    let resp = get_max_user_bytes_per_staged_sector(builder);
+    let piece = vec![9; (*resp).max_staged_bytes_per_sector as usize];
+    destroy_get_max_user_bytes_per_staged_sector_response(resp);
+
+    let mut sector_ids = Vec::new();
+    for key in &["first", "second"] {
+        let resp = add_piece(
+            builder,
+            rust_str_to_c_str(*key),
+            piece.as_ptr(),
+            piece.len(),
+            0,
+        );
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        sector_ids.push((*resp).sector_id);
+        destroy_add_piece_response(resp);
+    }
+
+    let scheduled = admissions(builder);
+
+    for sector_id in sector_ids {
+        let resp = seal_sector(builder, sector_id);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_seal_sector_response(resp);
+    }
+
+    (scheduled, admissions(builder))
+}
+
+#[test]
+fn a_memory_budget_serializes_seals() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let dirs = Dirs::new();
+
+    // Room for one seal, but not for two.
+    let layers = proof_params(ProofVersion::CURRENT).layers;
+    let budget = seal_working_memory_bytes(TEST_SECTOR_SIZE, layers) * 3 / 2;
+
+    unsafe {
+        let builder = init(&dirs, budget);
+        let (scheduled, sealed) = seal_two_sectors(builder);
+
+        assert_eq!(1, scheduled.running.len());
+        assert_eq!(1, scheduled.queued.len());
+        assert_eq!(budget, scheduled.memory_budget_bytes);
+
+        let (first, second) = (sealed.finished[0], sealed.finished[1]);
+        assert!(first.1 <= budget && first.1 + second.1 > budget);
+        assert_eq!(scheduled.running[0], first.0);
+        assert_eq!(scheduled.queued[0], second.0);
+
+        // The second seal was admitted only once the first had finished.
+        assert!(second.2 >= first.3);
+        assert!(sealed.running.is_empty() && sealed.queued.is_empty());
+
+        destroy_sector_builder(builder, 0);
+    }
+}
+
+#[test]
+fn without_a_budget_seals_overlap() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let dirs = Dirs::new();
+
+    unsafe {
+        let builder = init(&dirs, 0);
+        let (scheduled, sealed) = seal_two_sectors(builder);
+
+        assert_eq!(2, scheduled.running.len());
+        assert!(scheduled.queued.is_empty());
+        assert_eq!(0, scheduled.memory_budget_bytes);
+
+        // Each seal was admitted before the other finished.
+        let (first, second) = (sealed.finished[0], sealed.finished[1]);
+        assert!(first.2 <= second.3 && second.2 <= first.3);
+
+        destroy_sector_builder(builder, 0);
+    }
+}
@@ -0,0 +1,356 @@
+//! Checks that two sector builders in one process, each over its own
+//! directories and driven from its own thread, never observe each other's
+//! sectors, pieces or metadata.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals sectors:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test sector_builders
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use rand::{thread_rng, Rng};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::collections::HashSet;
+use std::ffi::CStr;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::sync::{Arc, Barrier};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const SEAL_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// What one builder saw, gathered once it had sealed its sectors.
+#[derive(Debug)]
+struct Observed {
+    prover_id: [u8; 31],
+    sector_ids: Vec<u64>,
+    sealed_accesses: Vec<String>,
+    staged_accesses: Vec<String>,
+    piece_keys: Vec<String>,
+    comm_rs: Vec<[u8; 32]>,
+}
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+impl Dirs {
+    fn new() -> Dirs {
+        Dirs {
+            metadata: TempDir::new().unwrap(),
+            sealed: TempDir::new().unwrap(),
+            staged: TempDir::new().unwrap(),
+        }
+    }
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+unsafe fn to_string(ptr: *const std::os::raw::c_char) -> String {
+    CStr::from_ptr(ptr).to_str().unwrap().to_owned()
+}
+
+// The sector id as verify_seal takes it, encoded as the builder encodes it.
+fn encoded_sector_id(sector_id: u64) -> [u8; 31] {
+    let mut bytes = [0; 31];
+    unsafe { sector_id_as_bytes(sector_id, &mut bytes) };
+    bytes
+}
+
+unsafe fn init(dirs: &Dirs, prover_id: &[u8; 31]) -> *mut SectorBuilder {
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        0,
+        u64::max_value(),
+        c_str(dirs.metadata.path()),
+        prover_id,
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        2,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        ptr::null(),
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let builder = (*resp).sector_builder;
+    destroy_init_sector_builder_response(resp);
+
+    builder
+}
+
+unsafe fn add(builder: *mut SectorBuilder, key: &str, bytes: &[u8]) -> u64 {
+    let resp = add_piece(builder, rust_str_to_c_str(key), bytes.as_ptr(), bytes.len(), 0);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let sector_id = (*resp).sector_id;
+    destroy_add_piece_response(resp);
+
+    sector_id
+}
+
+unsafe fn staged_sectors(builder: *mut SectorBuilder) -> Vec<(u64, String)> {
+    let resp = get_staged_sectors(builder, false);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let sectors = slice::from_raw_parts((*resp).sectors_ptr, (*resp).sectors_len)
+        .iter()
+        .map(|s| (s.sector_id, to_string(s.sector_access)))
+        .collect();
+    destroy_get_staged_sectors_response(resp);
+
+    sectors
+}
+
+// Sorted by sector id.
+unsafe fn sealed_sectors(builder: *mut SectorBuilder) -> Vec<(u64, String, [u8; 32])> {
+    let resp = get_sealed_sectors(builder, false);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let mut sectors: Vec<_> = slice::from_raw_parts((*resp).sectors_ptr, (*resp).sectors_len)
+        .iter()
+        .map(|s| (s.sector_id, to_string(s.sector_access), s.comm_r))
+        .collect();
+    destroy_get_sealed_sectors_response(resp);
+
+    sectors.sort();
+
+    sectors
+}
+
+unsafe fn wait_until_sealed(builder: *mut SectorBuilder, sector_id: u64) {
+    let start = Instant::now();
+
+    loop {
+        let resp = get_seal_status(builder, sector_id);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+        if (*resp).seal_status_code == FFISealStatus::Failed {
+            panic!("sealing sector {} failed: {}", sector_id, to_string((*resp).seal_error_msg));
+        }
+
+        let sealed = (*resp).seal_status_code == FFISealStatus::Sealed;
+        destroy_get_seal_status_response(resp);
+
+        if sealed {
+            return;
+        }
+
+        assert!(start.elapsed() < SEAL_TIMEOUT, "sector {} took too long to seal", sector_id);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+unsafe fn verify(builder: *mut SectorBuilder, prover_id: &[u8; 31], sector_id: u64) -> bool {
+    let resp = get_seal_status(builder, sector_id);
+    assert_eq!(FFISealStatus::Sealed, (*resp).seal_status_code);
+
+    let verified = verify_seal(
+        &ConfiguredStore::Test,
+        &(*resp).comm_r,
+        &(*resp).comm_d,
+        &(*resp).comm_r_star,
+        prover_id,
+        &encoded_sector_id(sector_id),
+        &(*resp).snark_proof,
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*verified).status_code);
+
+    let is_valid = (*verified).is_valid;
+    destroy_verify_seal_response(verified);
+    destroy_get_seal_status_response(resp);
+
+    is_valid
+}
+
+unsafe fn read_piece(builder: *mut SectorBuilder, key: &str) -> Vec<u8> {
+    let resp = read_piece_from_sealed_sector(builder, rust_str_to_c_str(key));
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let bytes = slice::from_raw_parts((*resp).data_ptr, (*resp).data_len).to_vec();
+    destroy_read_piece_from_sealed_sector_response(resp);
+
+    bytes
+}
+
+// Adds two pieces to each of two sectors, seals them, and checks everything
+// the builder reports at each step. `step` is shared with the other builder's
+// thread, so that the two builders' operations interleave.
+fn drive_builder(dirs: &Dirs, prover_id: [u8; 31], step: &Barrier) -> Observed {
+    let rng = &mut thread_rng();
+
+    unsafe {
+        let builder = init(dirs, &prover_id);
+
+        let max_bytes = {
+            let resp = get_max_user_bytes_per_staged_sector(builder);
+            let max_bytes = (*resp).max_staged_bytes_per_sector as usize;
+            destroy_get_max_user_bytes_per_staged_sector_response(resp);
+            max_bytes
+        };
+
+        // The same keys are used by both builders.
+        let piece_keys: Vec<String> = (0..4).map(|i| format!("piece-{}", i)).collect();
+        let pieces: Vec<Vec<u8>> = (0..4)
+            .map(|_| (0..max_bytes / 2).map(|_| rng.gen()).collect())
+            .collect();
+
+        let mut sector_ids = Vec::new();
+        for (key, bytes) in piece_keys.iter().zip(&pieces) {
+            step.wait();
+            sector_ids.push(add(builder, key, bytes));
+        }
+
+        // Two pieces fill a sector.
+        assert_eq!(sector_ids[0], sector_ids[1]);
+        assert_eq!(sector_ids[2], sector_ids[3]);
+        assert_ne!(sector_ids[0], sector_ids[2]);
+        sector_ids.dedup();
+
+        // Full sectors may already have been scheduled for sealing, and so
+        // may no longer be listed.
+        step.wait();
+        let staged = staged_sectors(builder);
+        for (sector_id, _) in &staged {
+            assert!(sector_ids.contains(sector_id));
+        }
+
+        step.wait();
+        let resp = seal_all_staged_sectors(builder);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_seal_all_staged_sectors_response(resp);
+
+        for sector_id in &sector_ids {
+            wait_until_sealed(builder, *sector_id);
+        }
+
+        step.wait();
+        let sealed = sealed_sectors(builder);
+        assert_eq!(
+            sector_ids,
+            sealed.iter().map(|(id, _, _)| *id).collect::<Vec<_>>()
+        );
+
+        for sector_id in &sector_ids {
+            step.wait();
+            assert!(verify(builder, &prover_id, *sector_id));
+        }
+
+        for (key, bytes) in piece_keys.iter().zip(&pieces) {
+            step.wait();
+            assert_eq!(bytes, &read_piece(builder, key));
+        }
+
+        destroy_sector_builder(builder, 0);
+
+        Observed {
+            prover_id,
+            sector_ids,
+            sealed_accesses: sealed.iter().map(|(_, access, _)| access.clone()).collect(),
+            staged_accesses: staged.into_iter().map(|(_, access)| access).collect(),
+            piece_keys,
+            comm_rs: sealed.iter().map(|(_, _, comm_r)| *comm_r).collect(),
+        }
+    }
+}
+
+fn assert_within(dir: &TempDir, accesses: &[String]) {
+    for access in accesses {
+        assert!(
+            Path::new(access).starts_with(dir.path()),
+            "{} is outside of {:?}",
+            access,
+            dir.path()
+        );
+    }
+}
+
+#[test]
+fn concurrent_builders_are_isolated() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let dirs = Arc::new([Dirs::new(), Dirs::new()]);
+    let step = Arc::new(Barrier::new(2));
+
+    let threads: Vec<_> = [[1; 31], [2; 31]]
+        .iter()
+        .enumerate()
+        .map(|(i, prover_id)| {
+            let (dirs, step, prover_id) = (dirs.clone(), step.clone(), *prover_id);
+            thread::spawn(move || drive_builder(&dirs[i], prover_id, &step))
+        })
+        .collect();
+
+    let observed: Vec<Observed> = threads
+        .into_iter()
+        .map(|t| t.join().expect("builder thread panicked"))
+        .collect();
+
+    // Sector ids are allocated per builder, so both start from the same id.
+    assert_eq!(observed[0].sector_ids, observed[1].sector_ids);
+    assert_eq!(observed[0].piece_keys, observed[1].piece_keys);
+
+    // Each builder's sectors live under its own directories...
+    for (dirs, observed) in dirs.iter().zip(&observed) {
+        assert_within(&dirs.sealed, &observed.sealed_accesses);
+        assert_within(&dirs.staged, &observed.staged_accesses);
+    }
+
+    // ...and the builders share none of them.
+    let accesses: HashSet<&String> = observed
+        .iter()
+        .flat_map(|o| o.sealed_accesses.iter().chain(&o.staged_accesses))
+        .collect();
+    assert_eq!(
+        observed
+            .iter()
+            .map(|o| o.sealed_accesses.len() + o.staged_accesses.len())
+            .sum::<usize>(),
+        accesses.len()
+    );
+
+    // Replicas are bound to their builder's prover id.
+    for comm_r in &observed[0].comm_rs {
+        assert!(!observed[1].comm_rs.contains(comm_r));
+    }
+
+    // Reopened from its metadata, each builder finds only its own sectors.
+    for (dirs, observed) in dirs.iter().zip(&observed) {
+        unsafe {
+            let builder = init(dirs, &observed.prover_id);
+            let sealed = sealed_sectors(builder);
+
+            assert_eq!(
+                observed.sealed_accesses,
+                sealed.into_iter().map(|(_, access, _)| access).collect::<Vec<_>>()
+            );
+
+            destroy_sector_builder(builder, 0);
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! Checks that a sector builder refuses to start over staging and sealed
+//! directories which are the same, or of which one is inside the other, as
+//! it could no longer tell staged sectors from sealed ones.
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::ffi::CStr;
+use std::path::Path;
+use std::ptr;
+use tempfile::TempDir;
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+// Returns the status of initializing a builder over the given directories,
+// and its error message, destroying the builder if it was initialized.
+unsafe fn init(metadata: &Path, sealed: &Path, staged: &Path) -> (FCPResponseStatus, String) {
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        0,
+        u64::max_value(),
+        c_str(metadata),
+        &[3; 31],
+        c_str(sealed),
+        ptr::null(),
+        c_str(staged),
+        2,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        ptr::null(),
+    );
+
+    let status_code = (*resp).status_code;
+    let error_msg = if status_code == FCPResponseStatus::FCPNoError {
+        destroy_sector_builder((*resp).sector_builder, 0);
+        String::new()
+    } else {
+        CStr::from_ptr((*resp).error_msg).to_str().unwrap().to_owned()
+    };
+    destroy_init_sector_builder_response(resp);
+
+    (status_code, error_msg)
+}
+
+#[test]
+fn overlapping_sector_dirs_are_refused() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let metadata = TempDir::new().unwrap();
+    let root = TempDir::new().unwrap();
+    let sealed = root.path().join("sealed");
+    let staged = root.path().join("staged");
+
+    unsafe {
+        for (sealed, staged) in &[
+            (sealed.clone(), sealed.clone()),
+            (sealed.clone(), sealed.join("staged")),
+            (staged.join("sealed"), staged.clone()),
+            (sealed.clone(), sealed.join("..").join("sealed")),
+        ] {
+            let (status_code, error_msg) = init(metadata.path(), sealed, staged);
+            assert_eq!(FCPResponseStatus::FCPCallerError, status_code);
+            assert!(error_msg.contains("overlaps"), "{}", error_msg);
+        }
+
+        // Sibling directories are fine, even when one's name is a prefix of
+        // the other's.
+        let (status_code, error_msg) = init(metadata.path(), &sealed, &staged);
+        assert_eq!(FCPResponseStatus::FCPNoError, status_code, "{}", error_msg);
+
+        let sealed_too = root.path().join("sealed2");
+        let (status_code, error_msg) = init(metadata.path(), &sealed, &sealed_too);
+        assert_eq!(FCPResponseStatus::FCPNoError, status_code, "{}", error_msg);
+    }
+}
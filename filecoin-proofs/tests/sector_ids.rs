@@ -0,0 +1,110 @@
+//! Checks that sector builders allocate sector ids in increasing order from
+//! the configured range, carry on where an earlier builder over the same
+//! metadata left off, and refuse to start once that is outside of the range.
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::path::Path;
+use std::ptr;
+use tempfile::TempDir;
+
+// Two of these don't fit in a test sector, so each is staged in a sector of
+// its own.
+const PIECE_BYTES: usize = 600;
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+unsafe fn init(
+    dirs: &Dirs,
+    first_sector_id: u64,
+    last_sector_id: u64,
+) -> *mut InitSectorBuilderResponse {
+    init_sector_builder(
+        &ConfiguredStore::Test,
+        first_sector_id,
+        last_sector_id,
+        c_str(dirs.metadata.path()),
+        &[5; 31],
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        10,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        ptr::null(),
+    )
+}
+
+unsafe fn add(builder: *mut SectorBuilder, key: &str) -> u64 {
+    let bytes = [1; PIECE_BYTES];
+
+    let resp = add_piece(builder, rust_str_to_c_str(key), bytes.as_ptr(), bytes.len(), 0);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let sector_id = (*resp).sector_id;
+    destroy_add_piece_response(resp);
+
+    sector_id
+}
+
+#[test]
+fn sector_ids_are_allocated_from_the_configured_range() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let dirs = Dirs {
+        metadata: TempDir::new().unwrap(),
+        sealed: TempDir::new().unwrap(),
+        staged: TempDir::new().unwrap(),
+    };
+
+    unsafe {
+        let resp = init(&dirs, 1000, u64::max_value());
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        let builder = (*resp).sector_builder;
+        destroy_init_sector_builder_response(resp);
+
+        let sector_ids: Vec<u64> = ["a", "b", "c"].iter().map(|key| add(builder, key)).collect();
+        assert_eq!(vec![1000, 1001, 1002], sector_ids);
+
+        destroy_sector_builder(builder, 0);
+
+        // A new builder over the same metadata continues after the last id.
+        let resp = init(&dirs, 1000, u64::max_value());
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        let builder = (*resp).sector_builder;
+        destroy_init_sector_builder_response(resp);
+
+        assert_eq!(1003, add(builder, "d"));
+
+        destroy_sector_builder(builder, 0);
+
+        // The metadata records ids beyond this range.
+        let resp = init(&dirs, 1000, 1002);
+        assert_eq!(FCPResponseStatus::FCPCallerError, (*resp).status_code);
+        destroy_init_sector_builder_response(resp);
+    }
+}
@@ -0,0 +1,293 @@
+//! Checks that the sealed sectors of a sector builder can be migrated into a
+//! single sector of a larger size, from which every piece can then be read
+//! back by its key, and that the sectors migrated from are retired rather
+//! than deleted.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals sectors:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test sector_migration
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use rand::{thread_rng, Rng};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::ffi::CStr;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const SEAL_TIMEOUT: Duration = Duration::from_secs(600);
+
+const PROVER_ID: [u8; 31] = [9; 31];
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+impl Dirs {
+    fn new() -> Dirs {
+        Dirs {
+            metadata: TempDir::new().unwrap(),
+            sealed: TempDir::new().unwrap(),
+            staged: TempDir::new().unwrap(),
+        }
+    }
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+unsafe fn to_string(ptr: *const std::os::raw::c_char) -> String {
+    CStr::from_ptr(ptr).to_str().unwrap().to_owned()
+}
+
+// The sector id as verify_seal takes it, encoded as the builder encodes it.
+fn encoded_sector_id(sector_id: u64) -> [u8; 31] {
+    let mut bytes = [0; 31];
+    unsafe { sector_id_as_bytes(sector_id, &mut bytes) };
+    bytes
+}
+
+unsafe fn init(dirs: &Dirs) -> *mut SectorBuilder {
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        0,
+        u64::max_value(),
+        c_str(dirs.metadata.path()),
+        &PROVER_ID,
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        2,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        ptr::null(),
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let builder = (*resp).sector_builder;
+    destroy_init_sector_builder_response(resp);
+
+    builder
+}
+
+unsafe fn add(builder: *mut SectorBuilder, key: &str, piece: &[u8]) -> u64 {
+    let resp = add_piece(builder, rust_str_to_c_str(key), piece.as_ptr(), piece.len(), 0);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let sector_id = (*resp).sector_id;
+    destroy_add_piece_response(resp);
+
+    sector_id
+}
+
+unsafe fn sealed_sector_ids(builder: *mut SectorBuilder) -> Vec<u64> {
+    let resp = get_sealed_sectors(builder, false);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let mut sector_ids: Vec<u64> = slice::from_raw_parts((*resp).sectors_ptr, (*resp).sectors_len)
+        .iter()
+        .map(|s| s.sector_id)
+        .collect();
+    destroy_get_sealed_sectors_response(resp);
+
+    sector_ids.sort();
+    sector_ids
+}
+
+unsafe fn wait_until_sealed(builder: *mut SectorBuilder, sector_id: u64) {
+    let start = Instant::now();
+
+    loop {
+        let resp = get_seal_status(builder, sector_id);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+        if (*resp).seal_status_code == FFISealStatus::Failed {
+            panic!("sealing sector {} failed: {}", sector_id, to_string((*resp).seal_error_msg));
+        }
+
+        let sealed = (*resp).seal_status_code == FFISealStatus::Sealed;
+        destroy_get_seal_status_response(resp);
+
+        if sealed {
+            return;
+        }
+
+        assert!(start.elapsed() < SEAL_TIMEOUT, "sector {} took too long to seal", sector_id);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+unsafe fn verify(builder: *mut SectorBuilder, cfg: &ConfiguredStore, sector_id: u64) -> bool {
+    let resp = get_seal_status(builder, sector_id);
+    assert_eq!(FFISealStatus::Sealed, (*resp).seal_status_code);
+
+    let verified = verify_seal(
+        cfg,
+        &(*resp).comm_r,
+        &(*resp).comm_d,
+        &(*resp).comm_r_star,
+        &PROVER_ID,
+        &encoded_sector_id(sector_id),
+        &(*resp).snark_proof,
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*verified).status_code);
+
+    let is_valid = (*verified).is_valid;
+    destroy_verify_seal_response(verified);
+    destroy_get_seal_status_response(resp);
+
+    is_valid
+}
+
+unsafe fn read_piece(builder: *mut SectorBuilder, key: &str) -> Vec<u8> {
+    let resp = read_piece_from_sealed_sector(builder, rust_str_to_c_str(key));
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let bytes = slice::from_raw_parts((*resp).data_ptr, (*resp).data_len).to_vec();
+    destroy_read_piece_from_sealed_sector_response(resp);
+
+    bytes
+}
+
+// Returns the status of the migration, along with the new sector's id and the
+// key, source sector and status of each piece, or the error message.
+unsafe fn migrate(
+    builder: *mut SectorBuilder,
+    source_sector_ids: &[u64],
+    target_config: &ConfiguredStore,
+) -> (FCPResponseStatus, u64, Vec<(String, u64, FFIMigratedPieceStatus)>, String) {
+    let resp = migrate_sectors(
+        builder,
+        source_sector_ids.as_ptr(),
+        source_sector_ids.len(),
+        target_config,
+    );
+
+    let status_code = (*resp).status_code;
+    let (pieces, error_msg) = if status_code == FCPResponseStatus::FCPNoError {
+        let pieces = slice::from_raw_parts((*resp).pieces_ptr, (*resp).pieces_len)
+            .iter()
+            .map(|p| {
+                let status = match p.status {
+                    FFIMigratedPieceStatus::Verified => FFIMigratedPieceStatus::Verified,
+                    FFIMigratedPieceStatus::Unverified => FFIMigratedPieceStatus::Unverified,
+                };
+                (to_string(p.piece_key), p.source_sector_id, status)
+            })
+            .collect();
+
+        (pieces, String::new())
+    } else {
+        (Vec::new(), to_string((*resp).error_msg))
+    };
+    let sector_id = (*resp).sector_id;
+    destroy_migrate_sectors_response(resp);
+
+    (status_code, sector_id, pieces, error_msg)
+}
+
+#[test]
+fn sealed_sectors_migrate_into_a_larger_sector() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let rng = &mut thread_rng();
+    let dirs = Dirs::new();
+
+    let pieces: Vec<(&str, Vec<u8>)> = vec![
+        ("a", (0..300).map(|_| rng.gen()).collect()),
+        ("b", (0..400).map(|_| rng.gen()).collect()),
+        ("c", (0..600).map(|_| rng.gen()).collect()),
+    ];
+
+    unsafe {
+        let builder = init(&dirs);
+
+        // Pieces a and b fit in one test sector, and c needs another.
+        let first = add(builder, pieces[0].0, &pieces[0].1);
+        assert_eq!(first, add(builder, pieces[1].0, &pieces[1].1));
+        let second = add(builder, pieces[2].0, &pieces[2].1);
+        assert_ne!(first, second);
+
+        let resp = seal_all_staged_sectors(builder);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_seal_all_staged_sectors_response(resp);
+
+        wait_until_sealed(builder, first);
+        wait_until_sealed(builder, second);
+
+        // Their pieces don't fit back into a test sector together.
+        let (status_code, _, _, error_msg) =
+            migrate(builder, &[first, second], &ConfiguredStore::Test);
+        assert_eq!(FCPResponseStatus::FCPCallerError, status_code);
+        assert!(error_msg.contains("capacity"), "{}", error_msg);
+
+        // Migrated into a larger sector, every piece keeps its key and its
+        // comm_p.
+        let (status_code, migrated, migrated_pieces, error_msg) =
+            migrate(builder, &[first, second], &ConfiguredStore::LargeTest);
+        assert_eq!(FCPResponseStatus::FCPNoError, status_code, "{}", error_msg);
+        assert_eq!(
+            vec![
+                ("a".to_string(), first, FFIMigratedPieceStatus::Verified),
+                ("b".to_string(), first, FFIMigratedPieceStatus::Verified),
+                ("c".to_string(), second, FFIMigratedPieceStatus::Verified),
+            ],
+            migrated_pieces
+        );
+
+        assert!(verify(builder, &ConfiguredStore::LargeTest, migrated));
+
+        for (key, bytes) in &pieces {
+            assert_eq!(*bytes, read_piece(builder, key));
+        }
+
+        // The sectors migrated from are kept, but can't be migrated again.
+        let mut expected_ids = vec![first, second, migrated];
+        expected_ids.sort();
+        assert_eq!(expected_ids, sealed_sector_ids(builder));
+
+        let (status_code, _, _, error_msg) =
+            migrate(builder, &[first], &ConfiguredStore::LargeTest);
+        assert_eq!(FCPResponseStatus::FCPCallerError, status_code);
+        assert!(error_msg.contains("already been migrated"), "{}", error_msg);
+
+        destroy_sector_builder(builder, 0);
+
+        // All of which survives a restart.
+        let builder = init(&dirs);
+
+        assert_eq!(expected_ids, sealed_sector_ids(builder));
+        for (key, bytes) in &pieces {
+            assert_eq!(*bytes, read_piece(builder, key));
+        }
+
+        let (status_code, _, _, _) = migrate(builder, &[second], &ConfiguredStore::LargeTest);
+        assert_eq!(FCPResponseStatus::FCPCallerError, status_code);
+
+        destroy_sector_builder(builder, 0);
+    }
+}
@@ -0,0 +1,275 @@
+//! Checks that a sealed sector exported by one sector builder can be imported
+//! by another of the same prover, which can then verify and unseal it, and
+//! that corrupted copies and sectors already known are refused.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals a sector:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test sector_transfer
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use rand::{thread_rng, Rng};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::ffi::CStr;
+use std::fs::{self, OpenOptions};
+use std::io::{Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const SEAL_TIMEOUT: Duration = Duration::from_secs(600);
+
+const PROVER_ID: [u8; 31] = [7; 31];
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+impl Dirs {
+    fn new() -> Dirs {
+        Dirs {
+            metadata: TempDir::new().unwrap(),
+            sealed: TempDir::new().unwrap(),
+            staged: TempDir::new().unwrap(),
+        }
+    }
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+unsafe fn to_string(ptr: *const std::os::raw::c_char) -> String {
+    CStr::from_ptr(ptr).to_str().unwrap().to_owned()
+}
+
+// The sector id as verify_seal takes it, encoded as the builder encodes it.
+fn encoded_sector_id(sector_id: u64) -> [u8; 31] {
+    let mut bytes = [0; 31];
+    unsafe { sector_id_as_bytes(sector_id, &mut bytes) };
+    bytes
+}
+
+unsafe fn init(dirs: &Dirs) -> *mut SectorBuilder {
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        0,
+        u64::max_value(),
+        c_str(dirs.metadata.path()),
+        &PROVER_ID,
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        1,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        ptr::null(),
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let builder = (*resp).sector_builder;
+    destroy_init_sector_builder_response(resp);
+
+    builder
+}
+
+unsafe fn sealed_sector_ids(builder: *mut SectorBuilder) -> Vec<u64> {
+    let resp = get_sealed_sectors(builder, false);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let sector_ids = slice::from_raw_parts((*resp).sectors_ptr, (*resp).sectors_len)
+        .iter()
+        .map(|s| s.sector_id)
+        .collect();
+    destroy_get_sealed_sectors_response(resp);
+
+    sector_ids
+}
+
+unsafe fn wait_until_sealed(builder: *mut SectorBuilder, sector_id: u64) {
+    let start = Instant::now();
+
+    loop {
+        let resp = get_seal_status(builder, sector_id);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+        if (*resp).seal_status_code == FFISealStatus::Failed {
+            panic!("sealing sector {} failed: {}", sector_id, to_string((*resp).seal_error_msg));
+        }
+
+        let sealed = (*resp).seal_status_code == FFISealStatus::Sealed;
+        destroy_get_seal_status_response(resp);
+
+        if sealed {
+            return;
+        }
+
+        assert!(start.elapsed() < SEAL_TIMEOUT, "sector {} took too long to seal", sector_id);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+unsafe fn verify(builder: *mut SectorBuilder, sector_id: u64) -> bool {
+    let resp = get_seal_status(builder, sector_id);
+    assert_eq!(FFISealStatus::Sealed, (*resp).seal_status_code);
+
+    let verified = verify_seal(
+        &ConfiguredStore::Test,
+        &(*resp).comm_r,
+        &(*resp).comm_d,
+        &(*resp).comm_r_star,
+        &PROVER_ID,
+        &encoded_sector_id(sector_id),
+        &(*resp).snark_proof,
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*verified).status_code);
+
+    let is_valid = (*verified).is_valid;
+    destroy_verify_seal_response(verified);
+    destroy_get_seal_status_response(resp);
+
+    is_valid
+}
+
+unsafe fn read_piece(builder: *mut SectorBuilder, key: &str) -> Vec<u8> {
+    let resp = read_piece_from_sealed_sector(builder, rust_str_to_c_str(key));
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let bytes = slice::from_raw_parts((*resp).data_ptr, (*resp).data_len).to_vec();
+    destroy_read_piece_from_sealed_sector_response(resp);
+
+    bytes
+}
+
+unsafe fn export(builder: *mut SectorBuilder, sector_id: u64, dest_dir: &Path) -> PathBuf {
+    let resp = export_sealed_sector(builder, sector_id, c_str(dest_dir));
+    assert_eq!(
+        FCPResponseStatus::FCPNoError,
+        (*resp).status_code,
+        "{}",
+        to_string((*resp).error_msg)
+    );
+
+    let manifest_path = PathBuf::from(to_string((*resp).manifest_path));
+    destroy_export_sealed_sector_response(resp);
+
+    manifest_path
+}
+
+// Returns the status of the import, along with the imported sector's id or
+// the error message.
+unsafe fn import(
+    builder: *mut SectorBuilder,
+    manifest_path: &Path,
+) -> (FCPResponseStatus, u64, String) {
+    let resp = import_sealed_sector(builder, c_str(manifest_path));
+
+    let status_code = (*resp).status_code;
+    let error_msg = if status_code == FCPResponseStatus::FCPNoError {
+        String::new()
+    } else {
+        to_string((*resp).error_msg)
+    };
+    let sector_id = (*resp).sector_id;
+    destroy_import_sealed_sector_response(resp);
+
+    (status_code, sector_id, error_msg)
+}
+
+#[test]
+fn sealed_sectors_move_between_builders() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let rng = &mut thread_rng();
+    let export_dir = TempDir::new().unwrap();
+
+    unsafe {
+        // Builder A seals a sector holding a single piece, and exports it.
+        let a_dirs = Dirs::new();
+        let a = init(&a_dirs);
+
+        let max_bytes = {
+            let resp = get_max_user_bytes_per_staged_sector(a);
+            let max_bytes = (*resp).max_staged_bytes_per_sector as usize;
+            destroy_get_max_user_bytes_per_staged_sector_response(resp);
+            max_bytes
+        };
+
+        let piece: Vec<u8> = (0..max_bytes / 2).map(|_| rng.gen()).collect();
+        let resp = add_piece(a, rust_str_to_c_str("piece"), piece.as_ptr(), piece.len(), 0);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        let sector_id = (*resp).sector_id;
+        destroy_add_piece_response(resp);
+
+        let resp = seal_all_staged_sectors(a);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_seal_all_staged_sectors_response(resp);
+
+        wait_until_sealed(a, sector_id);
+
+        let manifest_path = export(a, sector_id, export_dir.path());
+        assert!(manifest_path.starts_with(export_dir.path()));
+        destroy_sector_builder(a, 0);
+
+        // Builder B, of the same prover, imports it, and can then verify and
+        // unseal it.
+        let b_dirs = Dirs::new();
+        let b = init(&b_dirs);
+
+        let (status_code, imported_id, _) = import(b, &manifest_path);
+        assert_eq!(FCPResponseStatus::FCPNoError, status_code);
+        assert_eq!(sector_id, imported_id);
+
+        assert!(verify(b, sector_id));
+        assert_eq!(piece, read_piece(b, "piece"));
+
+        // Importing it again would make two sectors of the same id.
+        let (status_code, _, error_msg) = import(b, &manifest_path);
+        assert_eq!(FCPResponseStatus::FCPCallerError, status_code);
+        assert!(error_msg.contains("already known"), "{}", error_msg);
+        assert_eq!(vec![sector_id], sealed_sector_ids(b));
+
+        destroy_sector_builder(b, 0);
+
+        // A corrupted copy is refused, and leaves nothing behind.
+        let mut copy = OpenOptions::new()
+            .write(true)
+            .open(export_dir.path().join(format!("sector-{}.sealed", sector_id)))
+            .unwrap();
+        copy.seek(SeekFrom::Start(100)).unwrap();
+        copy.write_all(&[0xff; 8]).unwrap();
+        copy.sync_all().unwrap();
+
+        let c_dirs = Dirs::new();
+        let c = init(&c_dirs);
+
+        let (status_code, _, _) = import(c, &manifest_path);
+        assert_eq!(FCPResponseStatus::FCPReceiverError, status_code);
+        assert!(sealed_sector_ids(c).is_empty());
+        assert_eq!(0, fs::read_dir(c_dirs.sealed.path()).unwrap().count());
+
+        destroy_sector_builder(c, 0);
+    }
+}
@@ -0,0 +1,161 @@
+//! Checks that a SectorBuilder destroyed while it seals cancels the seal it
+//! was running, records those it had queued, and that a builder started over
+//! the same directories seals them.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals sectors:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test shutdown
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::{c_str_to_rust_str, rust_str_to_c_str};
+use filecoin_proofs::api::estimate::seal_working_memory_bytes;
+use filecoin_proofs::api::proof_params::proof_params;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use sector_base::api::disk_backed_storage::{ConfiguredStore, TEST_SECTOR_SIZE};
+use sector_base::api::sector_store::ProofVersion;
+use std::path::Path;
+use std::ptr;
+use std::slice;
+use tempfile::TempDir;
+
+struct Dirs {
+    metadata: TempDir,
+    sealed: TempDir,
+    staged: TempDir,
+}
+
+impl Dirs {
+    fn new() -> Dirs {
+        Dirs {
+            metadata: TempDir::new().unwrap(),
+            sealed: TempDir::new().unwrap(),
+            staged: TempDir::new().unwrap(),
+        }
+    }
+}
+
+fn c_str(path: &Path) -> *const std::os::raw::c_char {
+    rust_str_to_c_str(path.to_str().unwrap())
+}
+
+// A builder with room for one seal at a time.
+unsafe fn init(dirs: &Dirs) -> *mut SectorBuilder {
+    let layers = proof_params(ProofVersion::CURRENT).layers;
+    let budget = seal_working_memory_bytes(TEST_SECTOR_SIZE, layers) * 3 / 2;
+
+    let resp = init_sector_builder(
+        &ConfiguredStore::Test,
+        0,
+        u64::max_value(),
+        c_str(dirs.metadata.path()),
+        &[4; 31],
+        c_str(dirs.sealed.path()),
+        ptr::null(),
+        c_str(dirs.staged.path()),
+        3,
+        0,
+        0,
+        0,
+        0,
+        budget,
+        0,
+        0,
+        false,
+        0,
+        ptr::null(),
+        DuplicatePiecePolicy::Allow,
+        ptr::null(),
+    );
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let builder = (*resp).sector_builder;
+    destroy_init_sector_builder_response(resp);
+
+    builder
+}
+
+// The sector ids of the running seals and of the queued ones.
+unsafe fn admissions(builder: *mut SectorBuilder) -> (Vec<u64>, Vec<u64>) {
+    let resp = get_seal_admissions(builder);
+    assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+
+    let running = slice::from_raw_parts((*resp).running_ptr, (*resp).running_len);
+    let queued = slice::from_raw_parts((*resp).queued_ptr, (*resp).queued_len);
+    let admissions = (
+        running.iter().map(|job| job.sector_id).collect(),
+        queued.to_vec(),
+    );
+    destroy_get_seal_admissions_response(resp);
+
+    admissions
+}
+
+#[test]
+fn queued_seals_outlive_the_builder() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let dirs = Dirs::new();
+
+    unsafe {
+        let builder = init(&dirs);
+
+        let resp = get_max_user_bytes_per_staged_sector(builder);
+        let piece = vec![6; (*resp).max_staged_bytes_per_sector as usize];
+        destroy_get_max_user_bytes_per_staged_sector_response(resp);
+
+        // Each piece fills a sector, whose seal is scheduled at once.
+        let mut sector_ids = Vec::new();
+        for key in &["first", "second", "third"] {
+            let resp = add_piece(
+                builder,
+                rust_str_to_c_str(*key),
+                piece.as_ptr(),
+                piece.len(),
+                0,
+            );
+            assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+            sector_ids.push((*resp).sector_id);
+            destroy_add_piece_response(resp);
+        }
+
+        let (running, queued) = admissions(builder);
+        assert_eq!(vec![sector_ids[0]], running);
+        assert_eq!(sector_ids[1..].to_vec(), queued);
+
+        destroy_sector_builder(builder, 0);
+
+        let builder = init(&dirs);
+
+        // The queued seals are scheduled again, in order, before anything else.
+        let (running, queued) = admissions(builder);
+        assert_eq!(vec![sector_ids[1]], running);
+        assert_eq!(vec![sector_ids[2]], queued);
+
+        for sector_id in &sector_ids[1..] {
+            let resp = seal_sector(builder, *sector_id);
+            assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+            destroy_seal_sector_response(resp);
+        }
+
+        // The running seal was cancelled, unless it finished first.
+        let resp = get_seal_status(builder, sector_ids[0]);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        match (*resp).seal_status_code {
+            FFISealStatus::Sealed => (),
+            FFISealStatus::Failed => {
+                let error_msg = c_str_to_rust_str((*resp).seal_error_msg);
+                assert!(error_msg.contains("cancelled while"), "{}", error_msg);
+            }
+            ref other => panic!("unexpected seal status: {:?}", other),
+        }
+        destroy_get_seal_status_response(resp);
+
+        destroy_sector_builder(builder, 0);
+    }
+}
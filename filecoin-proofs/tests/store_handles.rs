@@ -0,0 +1,106 @@
+//! Checks that destroying a sector store handle while the store is in use by
+//! another thread neither frees the store out from under it nor leaves the
+//! handle usable.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals a sector:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test store_handles
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate tempfile;
+
+mod support;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::internal::{seal, verify_seal};
+use sector_base::api::disk_backed_storage::{
+    destroy_storage, init_new_test_sector_store, ConfiguredStore,
+};
+use sector_base::api::errors::SectorManagerErr;
+use sector_base::api::registry;
+use std::sync::mpsc;
+use std::thread;
+use tempfile::TempDir;
+
+use crate::support::{make_random_bytes, run_case};
+
+fn destroy_during_seal_aux(_cs: ConfiguredStore) {
+    let staging_dir = TempDir::new().expect("could not create temporary directory");
+    let sealed_dir = TempDir::new().expect("could not create temporary directory");
+
+    let handle = unsafe {
+        init_new_test_sector_store(
+            rust_str_to_c_str(staging_dir.path().to_str().unwrap()),
+            rust_str_to_c_str(sealed_dir.path().to_str().unwrap()),
+        )
+    };
+
+    let (destroyed_tx, destroyed_rx) = mpsc::channel();
+    let (started_tx, started_rx) = mpsc::channel();
+
+    let sealer = thread::spawn(move || {
+        // An in-flight operation holds on to the store for its duration.
+        let store = registry::get(handle).expect("handle should be valid");
+        started_tx.send(()).unwrap();
+
+        // Don't seal until the handle has been destroyed.
+        destroyed_rx.recv().unwrap();
+
+        let mgr = store.manager();
+        let cfg = store.config();
+
+        let staged_access = mgr.new_staging_sector_access().unwrap();
+        let sealed_access = mgr.new_sealed_sector_access().unwrap();
+
+        let contents = make_random_bytes(cfg.max_unsealed_bytes_per_sector());
+        mgr.write_and_preprocess(&staged_access, &contents).unwrap();
+
+        let output = seal(cfg, &staged_access, &sealed_access, &[2; 31], &[0; 31])
+            .expect("failed to seal");
+
+        verify_seal(
+            cfg,
+            output.comm_r,
+            output.comm_d,
+            output.comm_r_star,
+            &[2; 31],
+            &[0; 31],
+            &output.proof,
+        )
+        .expect("failed to run verify_seal")
+    });
+
+    started_rx.recv().unwrap();
+    destroy_storage(handle);
+    destroyed_tx.send(()).unwrap();
+
+    match registry::get(handle) {
+        Err(SectorManagerErr::StaleHandle(h)) => assert_eq!(handle, h),
+        Err(err) => panic!("unexpected error: {}", err),
+        Ok(_) => panic!("destroyed handle still resolved"),
+    }
+
+    // Destroying it again is harmless.
+    destroy_storage(handle);
+
+    assert!(
+        sealer.join().expect("sealer panicked"),
+        "seal which outlived its handle did not verify"
+    );
+}
+
+#[test]
+fn destroy_during_seal() {
+    run_case(
+        "destroy_during_seal",
+        ConfiguredStore::Test,
+        destroy_during_seal_aux,
+    );
+}
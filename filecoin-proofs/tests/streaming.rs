@@ -0,0 +1,156 @@
+//! Streams a piece which fills a whole sector into a sector builder through
+//! the FFI read callback, seals it and reads it back.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals a sector:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test streaming
+#![cfg(feature = "slow-tests")]
+
+extern crate ffi_toolkit;
+extern crate filecoin_proofs;
+extern crate libc;
+extern crate rand;
+extern crate sector_base;
+extern crate tempfile;
+
+use ffi_toolkit::rust_str_to_c_str;
+use filecoin_proofs::api::responses::*;
+use filecoin_proofs::api::*;
+use rand::{thread_rng, Rng};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::cmp;
+use std::io::{Cursor, Read};
+use std::slice;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const SEAL_TIMEOUT: Duration = Duration::from_secs(600);
+
+// Hands out at most 13 bytes per call, so that the piece arrives in many
+// uneven reads.
+extern "C" fn read_cursor(
+    buf: *mut u8,
+    max: libc::size_t,
+    user_data: *mut libc::c_void,
+) -> isize {
+    let cursor = unsafe { &mut *(user_data as *mut Cursor<Vec<u8>>) };
+    let buf = unsafe { slice::from_raw_parts_mut(buf, cmp::min(max, 13)) };
+
+    match cursor.read(buf) {
+        Ok(n) => n as isize,
+        Err(_) => -1,
+    }
+}
+
+extern "C" fn read_error(_: *mut u8, _: libc::size_t, _: *mut libc::c_void) -> isize {
+    -1
+}
+
+fn c_str(dir: &TempDir) -> *const libc::c_char {
+    rust_str_to_c_str(dir.path().to_str().unwrap())
+}
+
+#[test]
+fn streamed_piece_survives_sealing() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let (metadata, sealed, staged) = (
+        TempDir::new().unwrap(),
+        TempDir::new().unwrap(),
+        TempDir::new().unwrap(),
+    );
+
+    unsafe {
+        let resp = init_sector_builder(
+            &ConfiguredStore::Test,
+            0,
+            u64::max_value(),
+            c_str(&metadata),
+            &[3; 31],
+            c_str(&sealed),
+            std::ptr::null(),
+            c_str(&staged),
+            2,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            0,
+            false,
+            0,
+            std::ptr::null(),
+            DuplicatePiecePolicy::Allow,
+            std::ptr::null(),
+        );
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        let builder = (*resp).sector_builder;
+        destroy_init_sector_builder_response(resp);
+
+        let resp = get_max_user_bytes_per_staged_sector(builder);
+        let max_bytes = (*resp).max_staged_bytes_per_sector;
+        destroy_get_max_user_bytes_per_staged_sector_response(resp);
+
+        // A failing callback stages nothing...
+        let resp = add_piece_from_reader(
+            builder,
+            rust_str_to_c_str("broken"),
+            max_bytes,
+            0,
+            Some(read_error),
+            std::ptr::null_mut(),
+        );
+        assert_ne!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_add_piece_response(resp);
+
+        // ...so the whole sector is still free for the streamed piece.
+        let rng = &mut thread_rng();
+        let piece: Vec<u8> = (0..max_bytes).map(|_| rng.gen()).collect();
+        let mut source = Cursor::new(piece.clone());
+
+        let resp = add_piece_from_reader(
+            builder,
+            rust_str_to_c_str("streamed"),
+            max_bytes,
+            0,
+            Some(read_cursor),
+            &mut source as *mut Cursor<Vec<u8>> as *mut libc::c_void,
+        );
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        let sector_id = (*resp).sector_id;
+        destroy_add_piece_response(resp);
+
+        let resp = seal_all_staged_sectors(builder);
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        destroy_seal_all_staged_sectors_response(resp);
+
+        let start = Instant::now();
+        loop {
+            let resp = get_seal_status(builder, sector_id);
+            assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+            assert!((*resp).seal_status_code != FFISealStatus::Failed);
+
+            let sealed = (*resp).seal_status_code == FFISealStatus::Sealed;
+            destroy_get_seal_status_response(resp);
+
+            if sealed {
+                break;
+            }
+
+            assert!(start.elapsed() < SEAL_TIMEOUT, "sealing took too long");
+            thread::sleep(Duration::from_millis(100));
+        }
+
+        let resp = read_piece_from_sealed_sector(builder, rust_str_to_c_str("streamed"));
+        assert_eq!(FCPResponseStatus::FCPNoError, (*resp).status_code);
+        assert_eq!(
+            &piece[..],
+            slice::from_raw_parts((*resp).data_ptr, (*resp).data_len)
+        );
+        destroy_read_piece_from_sealed_sector_response(resp);
+
+        destroy_sector_builder(builder, 0);
+    }
+}
@@ -0,0 +1,433 @@
+// Shared helpers for the slow, end-to-end seal/verify/unseal tests. Each test
+// binary which uses these includes the module with `mod support;`.
+
+#![allow(dead_code)]
+
+use filecoin_proofs::api::fuzzing::vanilla_seal_input;
+use filecoin_proofs::api::internal::{get_unsealed_range, seal, verify_seal, SealOutput};
+use filecoin_proofs::api::replica_format::LegacyReplicas;
+use filecoin_proofs::api::seal_proof::open_envelope;
+use rand::{thread_rng, Rng};
+use sector_base::api::disk_backed_storage::{
+    new_mini_sector_store, new_sector_store, new_sector_store_with_proof_variant,
+};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use sector_base::api::sector_store::{ProofVariant, SectorStore};
+use std::env;
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+const FATAL_NOTEMP: &str = "could not create temporary directory";
+
+/// The prover and sector ids a harness seals under, unless given others.
+pub const PROVER_ID: [u8; 31] = [2; 31];
+pub const SECTOR_ID: [u8; 31] = [0; 31];
+
+/// How many bytes a harness should write into the staged sector.
+#[derive(Debug, Clone)]
+pub enum BytesAmount {
+    /// Fill the sector with random bytes.
+    Max,
+    /// Write random bytes, leaving this many bytes of the sector unused.
+    Offset(u64),
+    /// Write exactly these bytes.
+    Exact(Vec<u8>),
+}
+
+pub struct Harness {
+    pub prover_id: [u8; 31],
+    pub sector_id: [u8; 31],
+    pub seal_output: SealOutput,
+    pub sealed_access: String,
+    pub unseal_access: String,
+    pub store: Box<SectorStore>,
+    pub written_contents: Vec<Vec<u8>>,
+
+    // The directories backing the store. They are removed when the harness is
+    // dropped, so keep them alive for as long as the store is in use.
+    _staging_dir: TempDir,
+    _sealed_dir: TempDir,
+}
+
+impl Harness {
+    /// Reads the entire unseal access into memory.
+    pub fn read_unsealed(&self) -> Vec<u8> {
+        read_all(&self.unseal_access)
+    }
+
+    /// Number of bytes which were not written to the staged sector and were
+    /// therefore zero-padded before sealing, and aren't unsealed.
+    pub fn byte_padding_amount(&self) -> u64 {
+        let written: usize = self.written_contents.iter().map(Vec::len).sum();
+        self.store.config().max_unsealed_bytes_per_sector() - written as u64
+    }
+}
+
+/// Writes the requested amounts of bytes to a freshly-provisioned staged
+/// sector, seals it, checks that the resulting proof verifies and unseals the
+/// whole sector into `unseal_access`.
+pub fn create_harness(cs: &ConfiguredStore, bytes_amts: &[BytesAmount]) -> Harness {
+    create_harness_with_proof_variant(cs, ProofVariant::Snark, bytes_amts)
+}
+
+/// As create_harness, but seals with proofs of the given variant.
+pub fn create_harness_with_proof_variant(
+    cs: &ConfiguredStore,
+    proof_variant: ProofVariant,
+    bytes_amts: &[BytesAmount],
+) -> Harness {
+    let staging_dir = TempDir::new().expect(FATAL_NOTEMP);
+    let sealed_dir = TempDir::new().expect(FATAL_NOTEMP);
+
+    let store: Box<SectorStore> = Box::new(new_sector_store_with_proof_variant(
+        cs,
+        sealed_dir.path().to_str().unwrap().to_owned(),
+        staging_dir.path().to_str().unwrap().to_owned(),
+        proof_variant,
+    ));
+
+    seal_into_harness(
+        &format!("cs={:?}", cs),
+        store,
+        staging_dir,
+        sealed_dir,
+        (PROVER_ID, SECTOR_ID),
+        bytes_amts,
+    )
+}
+
+/// As create_harness, but seals under the given prover and sector ids.
+pub fn create_harness_with_ids(
+    cs: &ConfiguredStore,
+    prover_id: [u8; 31],
+    sector_id: [u8; 31],
+    bytes_amts: &[BytesAmount],
+) -> Harness {
+    let staging_dir = TempDir::new().expect(FATAL_NOTEMP);
+    let sealed_dir = TempDir::new().expect(FATAL_NOTEMP);
+
+    let store: Box<SectorStore> = Box::new(new_sector_store(
+        cs,
+        sealed_dir.path().to_str().unwrap().to_owned(),
+        staging_dir.path().to_str().unwrap().to_owned(),
+    ));
+
+    seal_into_harness(
+        &format!("cs={:?}", cs),
+        store,
+        staging_dir,
+        sealed_dir,
+        (prover_id, sector_id),
+        bytes_amts,
+    )
+}
+
+/// As create_harness, but against a mini store (see new_mini_sector_store),
+/// which seals quickly enough for tests which aren't slow ones.
+pub fn create_mini_harness(bytes_amts: &[BytesAmount]) -> Harness {
+    create_mini_harness_with_ids(PROVER_ID, SECTOR_ID, bytes_amts)
+}
+
+/// As create_mini_harness, but seals under the given prover and sector ids.
+pub fn create_mini_harness_with_ids(
+    prover_id: [u8; 31],
+    sector_id: [u8; 31],
+    bytes_amts: &[BytesAmount],
+) -> Harness {
+    let staging_dir = TempDir::new().expect(FATAL_NOTEMP);
+    let sealed_dir = TempDir::new().expect(FATAL_NOTEMP);
+
+    let store: Box<SectorStore> = Box::new(new_mini_sector_store(
+        sealed_dir.path().to_str().unwrap().to_owned(),
+        staging_dir.path().to_str().unwrap().to_owned(),
+    ));
+
+    seal_into_harness(
+        "the mini store",
+        store,
+        staging_dir,
+        sealed_dir,
+        (prover_id, sector_id),
+        bytes_amts,
+    )
+}
+
+// Does the work of create_harness against a store backed by the given
+// directories, described as store_description in failures, under the given
+// prover and sector ids.
+fn seal_into_harness(
+    store_description: &str,
+    store: Box<SectorStore>,
+    staging_dir: TempDir,
+    sealed_dir: TempDir,
+    (prover_id, sector_id): ([u8; 31], [u8; 31]),
+    bytes_amts: &[BytesAmount],
+) -> Harness {
+    let mgr = store.manager();
+    let cfg = store.config();
+
+    let staged_access = mgr
+        .new_staging_sector_access()
+        .expect("could not create staging access");
+
+    let sealed_access = mgr
+        .new_sealed_sector_access()
+        .expect("could not create sealed access");
+
+    let unseal_access = mgr
+        .new_sealed_sector_access()
+        .expect("could not create unseal access");
+
+    let mut written_contents: Vec<Vec<u8>> = Default::default();
+    for bytes_amt in bytes_amts {
+        let contents = match bytes_amt {
+            BytesAmount::Exact(bs) => bs.clone(),
+            BytesAmount::Max => make_random_bytes(cfg.max_unsealed_bytes_per_sector()),
+            BytesAmount::Offset(m) => make_random_bytes(cfg.max_unsealed_bytes_per_sector() - m),
+        };
+
+        assert_eq!(
+            contents.len() as u64,
+            mgr.write_and_preprocess(&staged_access, &contents)
+                .expect("failed to write and preprocess")
+        );
+
+        written_contents.push(contents);
+    }
+
+    let seal_output = seal(cfg, &staged_access, &sealed_access, &prover_id, &sector_id)
+        .expect("failed to seal");
+
+    let is_valid = verify_seal(
+        cfg,
+        seal_output.comm_r,
+        seal_output.comm_d,
+        seal_output.comm_r_star,
+        &prover_id,
+        &sector_id,
+        &seal_output.proof,
+    )
+    .expect("failed to run verify_seal");
+
+    assert!(
+        is_valid,
+        "verification of valid proof failed for {}, bytes_amts={:?}",
+        store_description, bytes_amts
+    );
+
+    save_fuzz_seed(&seal_output, &prover_id, &sector_id);
+
+    // Unsealing knows from the sector itself how much was written, so asking
+    // for the whole sector returns just that.
+    let written: usize = written_contents.iter().map(Vec::len).sum();
+
+    assert_eq!(
+        written as u64,
+        get_unsealed_range(
+            cfg,
+            &sealed_access,
+            &unseal_access,
+            &prover_id,
+            &sector_id,
+            0,
+            cfg.max_unsealed_bytes_per_sector(),
+            LegacyReplicas::Refuse,
+        )
+        .expect("failed to unseal")
+    );
+
+    Harness {
+        prover_id,
+        sector_id,
+        seal_output,
+        sealed_access,
+        unseal_access,
+        store,
+        written_contents,
+        _staging_dir: staging_dir,
+        _sealed_dir: sealed_dir,
+    }
+}
+
+/// Names a directory in which each harness saves its proof, as a seed for the
+/// fuzz targets in fuzz/ (see fuzz/README.md), if set.
+const FUZZ_SEEDS_ENV: &str = "FIL_PROOFS_FUZZ_SEEDS";
+
+fn save_fuzz_seed(seal_output: &SealOutput, prover_id: &[u8; 31], sector_id: &[u8; 31]) {
+    let dir = match env::var_os(FUZZ_SEEDS_ENV) {
+        Some(dir) => PathBuf::from(dir),
+        None => return,
+    };
+
+    let (variant, proof) = open_envelope(&seal_output.proof).expect("seal made no envelope");
+    let (target, seed) = match variant {
+        ProofVariant::Snark => ("read_snark_proofs", proof.to_vec()),
+        ProofVariant::Vanilla => (
+            "verify_vanilla_seal",
+            vanilla_seal_input(
+                &seal_output.comm_r,
+                &seal_output.comm_d,
+                &seal_output.comm_r_star,
+                prover_id,
+                sector_id,
+                proof,
+            ),
+        ),
+    };
+
+    let dir = dir.join(target);
+    fs::create_dir_all(&dir).expect("could not create fuzz seed directory");
+
+    let name: String = seal_output.comm_r[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    fs::write(dir.join(name), seed).expect("could not save fuzz seed");
+}
+
+/// Unseals `num_bytes` starting at `offset` into a new access and asserts
+/// that the result matches the concatenated written contents.
+pub fn assert_unsealed_range(h: &Harness, offset: u64, num_bytes: u64) {
+    let cfg = h.store.config();
+
+    let output_access = h
+        .store
+        .manager()
+        .new_sealed_sector_access()
+        .expect("could not create unseal access");
+
+    assert_eq!(
+        num_bytes,
+        get_unsealed_range(
+            cfg,
+            &PathBuf::from(&h.sealed_access),
+            &PathBuf::from(&output_access),
+            &h.prover_id,
+            &h.sector_id,
+            offset,
+            num_bytes,
+            LegacyReplicas::Refuse,
+        )
+        .expect("failed to unseal range")
+    );
+
+    let written = h.written_contents.concat();
+    let unsealed = read_all(&output_access);
+
+    assert_eq!(
+        num_bytes as usize,
+        unsealed.len(),
+        "length of original and unsealed range differed for offset={}, num_bytes={}",
+        offset,
+        num_bytes
+    );
+
+    assert_eq!(
+        written[offset as usize..(offset + num_bytes) as usize],
+        unsealed[..],
+        "original and unsealed range contents differed for offset={}, num_bytes={}",
+        offset,
+        num_bytes
+    );
+}
+
+/// The number of unsealed bytes which fit into a sector of the given store.
+pub fn max_unsealed_bytes_per_sector(cs: &ConfiguredStore) -> u64 {
+    let dir = TempDir::new().expect(FATAL_NOTEMP);
+    let path = dir.path().to_str().unwrap().to_owned();
+
+    new_sector_store(cs, path.clone(), path)
+        .config()
+        .max_unsealed_bytes_per_sector()
+}
+
+pub fn make_random_bytes(num_bytes_to_make: u64) -> Vec<u8> {
+    let mut rng = thread_rng();
+    (0..num_bytes_to_make).map(|_| rng.gen()).collect()
+}
+
+pub fn read_all(access: &str) -> Vec<u8> {
+    let mut file = File::open(access).expect("failed to open access");
+    let mut buf = Vec::new();
+    file.read_to_end(&mut buf).expect("failed to read access");
+    buf
+}
+
+/// The longest we are willing to wait for a single case against the given
+/// store. Can be overridden with FILECOIN_PROOFS_SLOW_TEST_TIMEOUT_SECS.
+pub fn case_timeout(cs: &ConfiguredStore) -> Duration {
+    if let Some(secs) = std::env::var("FILECOIN_PROOFS_SLOW_TEST_TIMEOUT_SECS")
+        .ok()
+        .and_then(|s| s.parse::<u64>().ok())
+    {
+        return Duration::from_secs(secs);
+    }
+
+    match cs {
+        ConfiguredStore::Test | ConfiguredStore::LargeTest => Duration::from_secs(10 * 60),
+        ConfiguredStore::Live => Duration::from_secs(6 * 60 * 60),
+    }
+}
+
+#[derive(Serialize)]
+struct CaseTiming<'a> {
+    case: &'a str,
+    store: String,
+    outcome: &'a str,
+    elapsed_ms: u64,
+}
+
+/// Runs `f` on its own thread, failing the test if it panics or does not
+/// complete within `case_timeout(cs)`. One line of JSON describing the case
+/// and its duration is written to stdout so that timings can be collected
+/// with `cargo test -- --nocapture | grep '^{'`.
+pub fn run_case<F>(case: &str, cs: ConfiguredStore, f: F)
+where
+    F: FnOnce(ConfiguredStore) + Send + 'static,
+{
+    let timeout = case_timeout(&cs);
+    let (tx, rx) = mpsc::channel();
+    let start = Instant::now();
+
+    let handle = thread::spawn(move || {
+        f(cs);
+        let _ = tx.send(());
+    });
+
+    let outcome = match rx.recv_timeout(timeout) {
+        Ok(()) => "ok",
+        Err(mpsc::RecvTimeoutError::Disconnected) => "panicked",
+        Err(mpsc::RecvTimeoutError::Timeout) => "timeout",
+    };
+
+    let elapsed = start.elapsed();
+
+    let timing = CaseTiming {
+        case,
+        store: format!("{:?}", cs),
+        outcome,
+        elapsed_ms: elapsed.as_secs() * 1000 + u64::from(elapsed.subsec_millis()),
+    };
+
+    println!(
+        "{}",
+        serde_json::to_string(&timing).expect("could not serialize timing")
+    );
+
+    match outcome {
+        "ok" => {
+            let _ = handle.join();
+        }
+        "panicked" => {
+            if let Err(err) = handle.join() {
+                std::panic::resume_unwind(err);
+            }
+        }
+        _ => panic!("case {} against {:?} exceeded {:?}", case, cs, timeout),
+    }
+}
@@ -0,0 +1,82 @@
+//! Checks that a seal proof is bound to the prover and sector it was made for:
+//! that a valid (comm_r, comm_d, comm_r_star, proof) fails to verify under
+//! another prover's id, under another sector's id, or under both ids of
+//! another sealed sector. The replica id both ids hash to is what binds them
+//! (see the module docs of api/internal.rs).
+//!
+//! The mini store's vanilla proofs are checked on every `cargo test`; the SNARK
+//! proofs of the test store only with the `slow-tests` feature:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test transplantation
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::internal::verify_seal;
+
+use crate::support::{create_mini_harness_with_ids, BytesAmount, Harness};
+
+// Whether h's proof verifies under the given ids.
+fn verifies_as(h: &Harness, prover_id: &[u8; 31], sector_id: &[u8; 31]) -> bool {
+    verify_seal(
+        h.store.config(),
+        h.seal_output.comm_r,
+        h.seal_output.comm_d,
+        h.seal_output.comm_r_star,
+        prover_id,
+        sector_id,
+        &h.seal_output.proof,
+    )
+    .expect("failed to run verify_seal")
+}
+
+// Checks the proofs of a and b, sealed by different provers in sectors of
+// different ids, against each other's ids.
+fn assert_not_transplantable(a: &Harness, b: &Harness) {
+    assert_ne!(a.prover_id, b.prover_id);
+    assert_ne!(a.sector_id, b.sector_id);
+
+    for (h, other) in &[(a, b), (b, a)] {
+        assert!(verifies_as(h, &h.prover_id, &h.sector_id));
+
+        assert!(
+            !verifies_as(h, &other.prover_id, &h.sector_id),
+            "a proof verified under another prover's id"
+        );
+        assert!(
+            !verifies_as(h, &h.prover_id, &other.sector_id),
+            "a proof verified under another sector's id"
+        );
+        assert!(
+            !verifies_as(h, &other.prover_id, &other.sector_id),
+            "a proof verified under the ids of another sealed sector"
+        );
+    }
+}
+
+#[test]
+fn vanilla_proofs_are_bound_to_their_prover_and_sector() {
+    let a = create_mini_harness_with_ids([2; 31], [0; 31], &[BytesAmount::Max]);
+    let b = create_mini_harness_with_ids([3; 31], [1; 31], &[BytesAmount::Max]);
+
+    assert_not_transplantable(&a, &b);
+}
+
+#[cfg(feature = "slow-tests")]
+#[test]
+fn snark_proofs_are_bound_to_their_prover_and_sector() {
+    use crate::support::create_harness_with_ids;
+    use sector_base::api::disk_backed_storage::ConfiguredStore;
+
+    let a = create_harness_with_ids(&ConfiguredStore::Test, [2; 31], [0; 31], &[BytesAmount::Max]);
+    let b = create_harness_with_ids(&ConfiguredStore::Test, [3; 31], [1; 31], &[BytesAmount::Max]);
+
+    assert_not_transplantable(&a, &b);
+}
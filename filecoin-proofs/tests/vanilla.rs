@@ -0,0 +1,88 @@
+//! End-to-end seal, verify and unseal tests for sectors sealed with vanilla
+//! proofs.
+//!
+//! Compiled only with the `slow-tests` feature, as they seal sectors:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test vanilla
+#![cfg(feature = "slow-tests")]
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::internal::verify_seal;
+use filecoin_proofs::api::seal_proof::{open_envelope, SealProofErr};
+use sector_base::api::disk_backed_storage::{new_sector_config, ConfiguredStore};
+use sector_base::api::sector_store::ProofVariant;
+
+use crate::support::{assert_unsealed_range, create_harness_with_proof_variant, BytesAmount};
+
+#[test]
+fn vanilla_seal_verify_unseal() {
+    // create_harness_with_proof_variant asserts that the proof verifies and
+    // that the whole sector unseals.
+    let h = create_harness_with_proof_variant(
+        &ConfiguredStore::Test,
+        ProofVariant::Vanilla,
+        &[BytesAmount::Offset(5)],
+    );
+
+    assert_eq!(
+        ProofVariant::Vanilla,
+        open_envelope(&h.seal_output.proof).unwrap().0
+    );
+
+    let written = h.written_contents[0].len() as u64;
+    assert_unsealed_range(&h, 5, written - 5);
+
+    // Commitments which don't match the proof are refused.
+    let is_valid = verify_seal(
+        h.store.config(),
+        h.seal_output.comm_d,
+        h.seal_output.comm_r,
+        h.seal_output.comm_r_star,
+        &h.prover_id,
+        &h.sector_id,
+        &h.seal_output.proof,
+    )
+    .expect("failed to run verify_seal");
+
+    assert!(
+        !is_valid,
+        "proof with swapped comm_r and comm_d should not be valid"
+    );
+}
+
+#[test]
+fn vanilla_proof_is_refused_by_snark_config() {
+    let h = create_harness_with_proof_variant(
+        &ConfiguredStore::Test,
+        ProofVariant::Vanilla,
+        &[BytesAmount::Max],
+    );
+
+    let err = verify_seal(
+        new_sector_config(&ConfiguredStore::Test).as_ref(),
+        h.seal_output.comm_r,
+        h.seal_output.comm_d,
+        h.seal_output.comm_r_star,
+        &h.prover_id,
+        &h.sector_id,
+        &h.seal_output.proof,
+    )
+    .expect_err("a vanilla proof was accepted as a SNARK");
+
+    match err.downcast::<SealProofErr>() {
+        Ok(SealProofErr::VariantMismatch { expected, found }) => {
+            assert_eq!(ProofVariant::Snark, expected);
+            assert_eq!(ProofVariant::Vanilla, found);
+        }
+        other => panic!("unexpected result: {:?}", other),
+    }
+}
@@ -0,0 +1,66 @@
+//! Checks that verify_seal builds what verifying a seal of some sector size
+//! takes only once, and is much faster for having cached it.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals sectors. Counting
+//! the graphs built relies on nothing else in the process building any, so
+//! this binary holds a single test:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test verify_cache
+#![cfg(feature = "slow-tests")]
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate storage_proofs;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::internal::{clear_seal_verifier_cache, verify_seal};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::time::{Duration, Instant};
+use storage_proofs::zigzag_graph::graphs_constructed;
+
+use crate::support::{create_harness, BytesAmount, Harness};
+
+fn timed_verify(h: &Harness) -> (bool, Duration) {
+    let start = Instant::now();
+
+    let is_valid = verify_seal(
+        h.store.config(),
+        h.seal_output.comm_r,
+        h.seal_output.comm_d,
+        h.seal_output.comm_r_star,
+        &h.prover_id,
+        &h.sector_id,
+        &h.seal_output.proof,
+    )
+    .expect("failed to run verify_seal");
+
+    (is_valid, start.elapsed())
+}
+
+#[test]
+fn seal_verifiers_are_cached() {
+    let first = create_harness(&ConfiguredStore::Test, &[BytesAmount::Max]);
+    let second = create_harness(&ConfiguredStore::Test, &[BytesAmount::Offset(5)]);
+
+    // Sealing verified both proofs, and so cached the verifier.
+    clear_seal_verifier_cache();
+    let graphs = graphs_constructed();
+
+    let (first_valid, first_took) = timed_verify(&first);
+    let (second_valid, second_took) = timed_verify(&second);
+
+    assert!(first_valid && second_valid, "verification of valid proof failed");
+    assert_eq!(1, graphs_constructed() - graphs);
+    assert!(
+        second_took * 10 <= first_took,
+        "verifying with a cached verifier took {:?}, and without {:?}",
+        second_took,
+        first_took
+    );
+}
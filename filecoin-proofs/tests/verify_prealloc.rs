@@ -0,0 +1,173 @@
+//! Checks that verify_seal_prealloc verifies proofs its handle has room for as
+//! verify_seal does, refuses the others with CapacityExceeded, and never grows
+//! the buffers its handle allocated up front.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals a sector. The
+//! allocations of the verifying thread are counted by this binary's global
+//! allocator:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test verify_prealloc
+#![cfg(feature = "slow-tests")]
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate storage_proofs;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::internal::{
+    seal_proof_capacity, verify_seal, verify_seal_prealloc, verify_seal_with_scratch,
+    CapacityExceeded, VerifierHandle, VerifierScratch,
+};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use crate::support::{create_harness, BytesAmount, Harness};
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+// Returns what f returns, and how many times it allocated (or reallocated).
+fn allocations<T, F: FnOnce() -> T>(f: F) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+// Whether verify_seal_prealloc accepts or refuses the proof against comm_r,
+// or why the handle has no room for it.
+fn verify_with(
+    handle: &mut VerifierHandle,
+    h: &Harness,
+    comm_r: [u8; 32],
+) -> Result<bool, CapacityExceeded> {
+    verify_seal_prealloc(
+        handle,
+        h.store.config(),
+        comm_r,
+        h.seal_output.comm_d,
+        h.seal_output.comm_r_star,
+        &h.prover_id,
+        &h.sector_id,
+        &h.seal_output.proof,
+    )
+    .map_err(|err| {
+        err.downcast::<CapacityExceeded>()
+            .unwrap_or_else(|err| panic!("unexpected error: {}", err))
+    })
+}
+
+#[test]
+fn prealloc_verification_stays_within_its_capacity() {
+    let h = create_harness(&ConfiguredStore::Test, &[BytesAmount::Max]);
+    let mut other_comm_r = h.seal_output.comm_r;
+    other_comm_r[0] ^= 1;
+
+    // The verifier is set up and cached, so that no verification below pays
+    // for it.
+    let valid = verify_seal(
+        h.store.config(),
+        h.seal_output.comm_r,
+        h.seal_output.comm_d,
+        h.seal_output.comm_r_star,
+        &h.prover_id,
+        &h.sector_id,
+        &h.seal_output.proof,
+    )
+    .expect("failed to verify");
+    assert!(valid);
+
+    // A handle with exactly the room the proof takes.
+    let (challenges, partitions) = seal_proof_capacity(h.store.config()).unwrap();
+    let mut handle = VerifierHandle::with_capacity(challenges, partitions);
+
+    let (first, first_allocations) =
+        allocations(|| verify_with(&mut handle, &h, h.seal_output.comm_r));
+    assert_eq!(Ok(true), first);
+    assert_eq!(Ok(false), verify_with(&mut handle, &h, other_comm_r));
+
+    let (second, second_allocations) =
+        allocations(|| verify_with(&mut handle, &h, h.seal_output.comm_r));
+    let (third, third_allocations) =
+        allocations(|| verify_with(&mut handle, &h, h.seal_output.comm_r));
+    assert_eq!(Ok(true), second);
+    assert_eq!(Ok(true), third);
+    assert_eq!(
+        second_allocations, third_allocations,
+        "verifications with the same handle allocated differently"
+    );
+
+    // A scratch grows its buffers on its first verification, which a handle
+    // allocated up front.
+    let mut scratch = VerifierScratch::new();
+    let (scratch_first, scratch_allocations) = allocations(|| {
+        verify_seal_with_scratch(
+            &mut scratch,
+            h.store.config(),
+            h.seal_output.comm_r,
+            h.seal_output.comm_d,
+            h.seal_output.comm_r_star,
+            &h.prover_id,
+            &h.sector_id,
+            &h.seal_output.proof,
+        )
+        .ok()
+    });
+    assert_eq!(Some(true), scratch_first);
+    assert!(
+        first_allocations < scratch_allocations,
+        "a fresh handle allocated {} times, and a fresh scratch {}",
+        first_allocations,
+        scratch_allocations
+    );
+
+    // Handles short of room refuse the proof, whether or not it's valid.
+    for comm_r in &[h.seal_output.comm_r, other_comm_r] {
+        let mut handle = VerifierHandle::with_capacity(challenges - 1, partitions);
+        assert_eq!(
+            Err(CapacityExceeded::Challenges {
+                needed: challenges,
+                capacity: challenges - 1,
+            }),
+            verify_with(&mut handle, &h, *comm_r)
+        );
+
+        let mut handle = VerifierHandle::with_capacity(challenges, partitions - 1);
+        assert_eq!(
+            Err(CapacityExceeded::Partitions {
+                needed: partitions,
+                capacity: partitions - 1,
+            }),
+            verify_with(&mut handle, &h, *comm_r)
+        );
+    }
+}
@@ -0,0 +1,153 @@
+//! Checks that verify_seal_with_scratch accepts and refuses the same proofs as
+//! verify_seal, and that, once warmed up, it allocates less than verify_seal
+//! per verification, and as much from one verification to the next.
+//!
+//! Compiled only with the `slow-tests` feature, as it seals a sector. The
+//! allocations of the verifying thread are counted by this binary's global
+//! allocator:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test verify_scratch
+#![cfg(feature = "slow-tests")]
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate storage_proofs;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::internal::{verify_seal, verify_seal_with_scratch, VerifierScratch};
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+use crate::support::{create_harness, BytesAmount, Harness};
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = Cell::new(0);
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let _ = ALLOCATIONS.try_with(|n| n.set(n.get() + 1));
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAlloc = CountingAlloc;
+
+// Returns what f returns, and how many times it allocated (or reallocated).
+fn allocations<T, F: FnOnce() -> T>(f: F) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let result = f();
+
+    (result, ALLOCATIONS.with(Cell::get) - before)
+}
+
+#[derive(Clone)]
+struct Case {
+    comm_r: [u8; 32],
+    sector_id: [u8; 31],
+    proof: Vec<u8>,
+}
+
+// Whether verify_seal accepts, refuses or fails on the case.
+fn verify_plain(h: &Harness, case: &Case) -> Option<bool> {
+    verify_seal(
+        h.store.config(),
+        case.comm_r,
+        h.seal_output.comm_d,
+        h.seal_output.comm_r_star,
+        &h.prover_id,
+        &case.sector_id,
+        &case.proof,
+    )
+    .ok()
+}
+
+// Whether verify_seal_with_scratch accepts, refuses or fails on the case.
+fn verify_with(scratch: &mut VerifierScratch, h: &Harness, case: &Case) -> Option<bool> {
+    verify_seal_with_scratch(
+        scratch,
+        h.store.config(),
+        case.comm_r,
+        h.seal_output.comm_d,
+        h.seal_output.comm_r_star,
+        &h.prover_id,
+        &case.sector_id,
+        &case.proof,
+    )
+    .ok()
+}
+
+#[test]
+fn scratch_verification_matches_and_allocates_less() {
+    let h = create_harness(&ConfiguredStore::Test, &[BytesAmount::Max]);
+    let valid = Case {
+        comm_r: h.seal_output.comm_r,
+        sector_id: h.sector_id,
+        proof: h.seal_output.proof.clone(),
+    };
+
+    // A valid proof, between ones of other sectors and damaged ones.
+    let mut cases = vec![valid.clone(); 6];
+    cases[1].comm_r[0] ^= 1;
+    cases[2].sector_id[0] ^= 1;
+    cases[3].proof[100] ^= 1;
+    cases[4].proof.pop();
+
+    let mut scratch = VerifierScratch::new();
+    for (i, case) in cases.iter().enumerate() {
+        let plain = verify_plain(&h, case);
+        let with_scratch = verify_with(&mut scratch, &h, case);
+
+        assert_eq!(plain, with_scratch, "case {} was verified differently", i);
+
+        let expected = match i {
+            0 | 5 => Some(Some(true)),
+            1 | 2 => Some(Some(false)),
+            4 => Some(None),
+            // A damaged proof may not decode, or not verify.
+            _ => None,
+        };
+        if let Some(expected) = expected {
+            assert_eq!(expected, plain, "case {} was verified wrongly", i);
+        }
+    }
+
+    // The scratch has verified valid proofs already, so it's warmed up.
+    let (plain_valid, plain) = allocations(|| verify_plain(&h, &valid));
+    let (first_valid, first) = allocations(|| verify_with(&mut scratch, &h, &valid));
+    let (second_valid, second) = allocations(|| verify_with(&mut scratch, &h, &valid));
+    assert_eq!(
+        vec![Some(true); 3],
+        vec![plain_valid, first_valid, second_valid]
+    );
+
+    assert_eq!(
+        first, second,
+        "warmed-up verifications allocated differently"
+    );
+    assert!(
+        first < plain,
+        "verifying with a scratch allocated {} times, and without {}",
+        first,
+        plain
+    );
+}
@@ -0,0 +1,70 @@
+//! Seals, verifies and unseals a sector on the Test store on Windows, where
+//! the unix runs of the other tests don't reach: paths are UTF-16 and
+//! canonicalized to `\\?\` paths, and files are published by MoveFileEx.
+//!
+//! Compiled only on Windows, with the `slow-tests` feature, as it seals a
+//! sector. On a Windows runner (or machine), with FILECOIN_PARAMETER_CACHE set
+//! to a writable directory:
+//!
+//!     cargo test --release -p filecoin-proofs --features slow-tests --test windows
+#![cfg(all(windows, feature = "slow-tests"))]
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::internal::{get_unsealed_range, verify_seal};
+use filecoin_proofs::api::replica_format::LegacyReplicas;
+use sector_base::api::disk_backed_storage::ConfiguredStore;
+use std::fs;
+use tempfile::TempDir;
+
+use crate::support::{create_harness, BytesAmount};
+
+#[test]
+fn seals_verifies_and_unseals() {
+    // create_harness seals, checks that the proof verifies, and unseals the
+    // whole sector.
+    let h = create_harness(&ConfiguredStore::Test, &[BytesAmount::Offset(5)]);
+    assert_eq!(h.written_contents[0], h.read_unsealed());
+
+    assert!(verify_seal(
+        h.store.config(),
+        h.seal_output.comm_r,
+        h.seal_output.comm_d,
+        h.seal_output.comm_r_star,
+        &h.prover_id,
+        &h.sector_id,
+        &h.seal_output.proof,
+    )
+    .expect("failed to run verify_seal"));
+
+    // Unsealing into a directory whose name isn't ASCII, nor representable in
+    // the system's ANSI code page.
+    let dir = TempDir::new().unwrap();
+    let out_dir = dir.path().join("unsealed-\u{00e9}\u{4e2d}");
+    fs::create_dir(&out_dir).unwrap();
+    let out_path = out_dir.join("range");
+
+    let written = get_unsealed_range(
+        h.store.config(),
+        h.sealed_access.clone().into(),
+        out_path.clone(),
+        &h.prover_id,
+        &h.sector_id,
+        10,
+        100,
+        LegacyReplicas::Refuse,
+    )
+    .expect("failed to unseal");
+
+    let unsealed = fs::read(&out_path).unwrap();
+    assert_eq!(100, written);
+    assert_eq!(h.written_contents[0][10..110], unsealed[..]);
+}
@@ -0,0 +1,137 @@
+//! Checks that sealing, proving and unsealing a sector call the yield hook in
+//! each of their phases, about once every interval, and that a yield callback
+//! set over FFI which returns false cancels the seal which called it.
+//!
+//! The hook is global, so this binary must contain only the single test below:
+//! tests running concurrently would call it too.
+
+extern crate filecoin_proofs;
+extern crate rand;
+extern crate sector_base;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_json;
+extern crate storage_proofs;
+extern crate tempfile;
+
+mod support;
+
+use filecoin_proofs::api::cancellation::{CancellationToken, Interrupted};
+use filecoin_proofs::api::internal::{public_params_for_version, seal_with_cancellation};
+use filecoin_proofs::api::responses::destroy_init_response;
+use filecoin_proofs::api::{
+    filecoin_proofs_init, set_yield_hook, set_yield_interval, FILECOIN_PROOFS_ABI_VERSION,
+};
+use sector_base::api::disk_backed_storage::MINI_SECTOR_SIZE;
+use sector_base::api::sector_store::{ProofVersion, SectorStore};
+use std::os::raw::c_void;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use storage_proofs::util::NODE_SIZE;
+use storage_proofs::yield_hook::{self, BUILDING_TREES, DECODING, ENCODING, PROVING};
+
+use crate::support::{create_mini_harness, make_random_bytes, BytesAmount};
+
+const INTERVAL: usize = 16;
+
+// The calls the callback goes on for.
+const ALLOWED_CALLS: usize = 5;
+
+extern "C" fn refuse_eventually(user_data: *mut c_void) -> bool {
+    let calls = unsafe { &*(user_data as *const AtomicUsize) };
+
+    calls.fetch_add(1, Ordering::SeqCst) < ALLOWED_CALLS
+}
+
+// Seals a new sector of store's with the given token.
+fn seal_another(
+    store: &SectorStore,
+    token: &CancellationToken,
+) -> filecoin_proofs::error::Result<()> {
+    let mgr = store.manager();
+    let staged = mgr.new_staging_sector_access().unwrap();
+    let sealed = mgr.new_sealed_sector_access().unwrap();
+
+    let bytes = make_random_bytes(store.config().max_unsealed_bytes_per_sector());
+    mgr.write_and_preprocess(&staged, &bytes).unwrap();
+
+    seal_with_cancellation(store.config(), &staged, &sealed, &[2; 31], &[1; 31], token)?;
+
+    Ok(())
+}
+
+#[test]
+fn sealing_yields_to_the_host() {
+    unsafe { destroy_init_response(filecoin_proofs_init(FILECOIN_PROOFS_ABI_VERSION)) };
+
+    let phases = Arc::new(Mutex::new(Vec::new()));
+    let recorded = phases.clone();
+
+    yield_hook::set_yield_interval(INTERVAL);
+    yield_hook::set_yield_hook(Some(Box::new(move |phase: &str| {
+        recorded.lock().unwrap().push(phase.to_string());
+        true
+    })));
+
+    // Seals, verifies and unseals a sector.
+    let h = create_mini_harness(&[BytesAmount::Max]);
+    yield_hook::set_yield_hook(None);
+
+    let phases = phases.lock().unwrap();
+    let count = |phase: &str| phases.iter().filter(|called| *called == phase).count();
+
+    // The loops' phases, and some of the checkpoints'.
+    for phase in &[
+        ENCODING,
+        BUILDING_TREES,
+        DECODING,
+        PROVING,
+        "replicating",
+        "unsealing",
+    ] {
+        assert!(count(phase) > 0, "the hook wasn't called while {}", phase);
+    }
+
+    // Sealing encodes every layer, and hashes a tree over each layer and over
+    // the replica. Unsealing decodes more.
+    let layers = public_params_for_version(MINI_SECTOR_SIZE as usize, ProofVersion::Mini)
+        .layer_challenges
+        .layers();
+    let nodes = MINI_SECTOR_SIZE as usize / NODE_SIZE;
+    let sealed_nodes = layers * nodes + (layers + 1) * (nodes - 1);
+
+    let looped = count(ENCODING) + count(BUILDING_TREES) + count(DECODING);
+    assert!(looped >= sealed_nodes / INTERVAL, "{} calls", looped);
+
+    // A callback which refuses a call stops the seal, which fails as though it
+    // had been cancelled. Each loop running stops at its next yield point.
+    let calls = AtomicUsize::new(0);
+    set_yield_interval(INTERVAL as u64);
+    set_yield_hook(
+        Some(refuse_eventually),
+        &calls as *const AtomicUsize as *mut c_void,
+    );
+
+    let err = seal_another(h.store.as_ref(), &CancellationToken::new())
+        .expect_err("the seal went on once the callback refused");
+    match err.downcast::<Interrupted>() {
+        Ok(Interrupted::Cancelled { .. }) => (),
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    let refused = calls.load(Ordering::SeqCst) - ALLOWED_CALLS;
+    assert!(
+        refused >= 1 && refused <= layers + 2,
+        "{} calls refused",
+        refused
+    );
+
+    // Once cleared, the callback is no longer called.
+    set_yield_hook(None, ptr::null_mut());
+    set_yield_interval(0);
+
+    let called = calls.load(Ordering::SeqCst);
+    seal_another(h.store.as_ref(), &CancellationToken::new()).unwrap();
+    assert_eq!(called, calls.load(Ordering::SeqCst));
+}
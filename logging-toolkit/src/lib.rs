@@ -1,4 +1,6 @@
 #[macro_use]
+extern crate lazy_static;
+#[macro_use]
 extern crate slog;
 extern crate slog_async;
 extern crate slog_json;
@@ -7,9 +9,40 @@ extern crate slog_term;
 use slog::Drain;
 use slog::FnValue;
 use slog::Level;
-use slog::LevelFilter;
 use slog::Logger;
+use slog::{Never, OwnedKVList, Record, KV};
 use std::env;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+/// Receives each record logged while it's installed: its level, and its
+/// message followed by its key-value pairs, as " key=value".
+pub type LogCallback = Box<Fn(Level, &str) + Send + Sync>;
+
+lazy_static! {
+    // Held for reading while a record is passed to the callback, so that
+    // replacing the callback waits for every record being passed to it.
+    static ref LOG_CALLBACK: RwLock<Option<LogCallback>> = RwLock::new(None);
+}
+
+// The level set by set_min_log_level, as given by Level::as_usize, or 0 if
+// none is set.
+static MIN_LOG_LEVEL: AtomicUsize = AtomicUsize::new(0);
+
+/// Passes what every logger made by make_logger logs to callback rather than
+/// to its drain, or restores their drains if None. Once this returns, the
+/// callback it replaced is never called again. Callbacks are called on the
+/// thread which logs, and mustn't log or set the callback themselves.
+pub fn set_log_callback(callback: Option<LogCallback>) {
+    *LOG_CALLBACK.write().unwrap_or_else(|p| p.into_inner()) = callback;
+}
+
+/// Sets the least severe level logged by every logger made by make_logger, in
+/// place of the level each was made with, or restores those if None.
+pub fn set_min_log_level(level: Option<Level>) {
+    MIN_LOG_LEVEL.store(level.map_or(0, |level| level.as_usize()), Ordering::SeqCst);
+}
 
 pub fn make_logger(
     root_name: &'static str,
@@ -44,10 +77,13 @@ pub fn make_logger(
         _ => Level::Info,
     };
 
-    let with_filter = LevelFilter::new(drain, min_log_level).map(slog::Fuse);
+    let with_callback = CallbackDrain {
+        default: drain,
+        min_log_level,
+    };
 
     Logger::root(
-        with_filter,
+        with_callback,
         o!("root" => root_name, "place" => FnValue(move |info| {
             format!("{}:{} {}",
                     info.file(),
@@ -57,3 +93,101 @@ pub fn make_logger(
         })),
     )
 }
+
+// Drops records below the minimum level, and passes the rest to the installed
+// log callback, if any, or else to the default drain. Records are passed to
+// the callback on the thread which logs them, rather than through the default
+// drain's queue, so that none is passed to a callback once it's replaced.
+struct CallbackDrain<D> {
+    default: D,
+    min_log_level: Level,
+}
+
+impl<D: Drain<Ok = (), Err = Never>> Drain for CallbackDrain<D> {
+    type Ok = ();
+    type Err = Never;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> Result<(), Never> {
+        let min_log_level = Level::from_usize(MIN_LOG_LEVEL.load(Ordering::SeqCst))
+            .unwrap_or(self.min_log_level);
+
+        if !record.level().is_at_least(min_log_level) {
+            return Ok(());
+        }
+
+        match *LOG_CALLBACK.read().unwrap_or_else(|p| p.into_inner()) {
+            Some(ref callback) => {
+                callback(record.level(), &format_record(record, values));
+                Ok(())
+            }
+            None => self.default.log(record, values),
+        }
+    }
+}
+
+// The record's message, followed by its key-value pairs and then its
+// logger's.
+fn format_record(record: &Record, values: &OwnedKVList) -> String {
+    let mut line = KeyValues(format!("{}", record.msg()));
+
+    // Neither can fail, as KeyValues never does.
+    let _ = record.kv().serialize(record, &mut line);
+    let _ = values.serialize(record, &mut line);
+
+    line.0
+}
+
+struct KeyValues(String);
+
+impl slog::Serializer for KeyValues {
+    fn emit_arguments(&mut self, key: slog::Key, val: &fmt::Arguments) -> slog::Result {
+        let _ = write!(self.0, " {}={}", key, val);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    // The callback and level are global, so they're only set by this test.
+    #[test]
+    fn records_reach_the_installed_callback_at_the_minimum_level() {
+        let logger = make_logger(
+            "logging-toolkit",
+            "LOGGING_TOOLKIT_TEST_LOG_JSON",
+            "LOGGING_TOOLKIT_TEST_MIN_LOG_LEVEL",
+        );
+
+        let records = Arc::new(Mutex::new(Vec::new()));
+        let recorded = records.clone();
+        set_log_callback(Some(Box::new(move |level, msg: &str| {
+            recorded.lock().unwrap().push((level, msg.to_string()));
+        })));
+
+        // Info is the default level.
+        info!(logger, "logged"; "n" => 1);
+        debug!(logger, "dropped");
+
+        set_min_log_level(Some(Level::Debug));
+        debug!(logger, "logged at debug"; "n" => 2);
+
+        set_min_log_level(Some(Level::Warning));
+        info!(logger, "dropped");
+
+        set_log_callback(None);
+        set_min_log_level(None);
+        warn!(logger, "logged to the default drain");
+
+        let records = records.lock().unwrap();
+        assert_eq!(2, records.len(), "{:?}", *records);
+
+        assert_eq!(Level::Info, records[0].0);
+        assert!(records[0].1.starts_with("logged n=1 "), "{}", records[0].1);
+        assert!(records[0].1.contains(" root=logging-toolkit"), "{}", records[0].1);
+
+        assert_eq!(Level::Debug, records[1].0);
+        assert!(records[1].1.starts_with("logged at debug n=2 "), "{}", records[1].1);
+    }
+}
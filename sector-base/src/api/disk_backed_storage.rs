@@ -1,28 +1,51 @@
 use crate::api::errors::SectorManagerErr;
-use crate::api::sector_store::{SectorConfig, SectorManager, SectorStore};
+use crate::api::registry::{self, SectorStoreHandle};
+use crate::api::sector_store::{
+    AuxCompression, ProofVariant, ProofVersion, SectorConfig, SectorManager, SectorStore,
+    UnsealedCheckpoint,
+};
 use crate::api::util;
 use crate::io::fr32::{
     almost_truncate_to_unpadded_bytes, target_unpadded_bytes, unpadded_bytes, write_padded,
+    write_padded_from_reader,
 };
-use ffi_toolkit::{c_str_to_rust_str, raw_ptr};
+use crate::io::sparse;
+use crate::io::staging_encryption::{EncryptedStagedFile, StagingEncryption};
+use crate::io::trailer::data_padded_bytes;
+use ffi_toolkit::c_str_to_rust_str;
 use libc;
-use std::fs::{create_dir_all, remove_file, File, OpenOptions};
-use std::io::{Read, Seek, SeekFrom};
-use std::path::Path;
+use std::fs::{canonicalize, create_dir_all, remove_file, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use storage_proofs::util::NODE_SIZE;
 
 // These sizes are for SEALED sectors. They are used to calculate the values of setup parameters.
 // They can be overridden by setting the corresponding environment variable (with FILECOIN_PROOFS_ prefix),
 // but this is not recommended, since some sealed sector sizes are invalid. If you must set this manually,
-// ensure the chosen sector size is a multiple of 32.
+// ensure the chosen sector size passes check_sector_bytes.
 
 // Sector size, in bytes, for tests.
 pub const TEST_SECTOR_SIZE: u64 = 1024;
 
+// Sector size, in bytes, for tests which need sectors larger than test
+// sectors, e.g. to migrate test sectors into.
+pub const LARGE_TEST_SECTOR_SIZE: u64 = 2048;
+
+// Sector size, in bytes, of mini stores (see new_mini_sector_store): the
+// smallest whose graphs aren't degenerate with Mini's degrees.
+pub const MINI_SECTOR_SIZE: u64 = 2048;
+
 // Sector size, in bytes, during live operation.
 pub const LIVE_SECTOR_SIZE: u64 = 1 << 28; // 256MiB
 
-/// Initializes and returns a boxed SectorStore instance with very small, unrealistic/insecure parameters
-/// for use in testing.
+/// Initializes a SectorStore instance with very small, unrealistic/insecure parameters
+/// for use in testing, and returns a handle to it. See api/registry.rs for the rules
+/// governing handles.
+///
+/// Returns 0, which is never issued as a handle, if the directories can't be
+/// created, or are the same directory, or one is inside the other (see
+/// check_sector_dirs).
 ///
 /// # Arguments
 ///
@@ -32,16 +55,16 @@ pub const LIVE_SECTOR_SIZE: u64 = 1 << 28; // 256MiB
 pub unsafe extern "C" fn init_new_test_sector_store(
     staging_dir_path: *const libc::c_char,
     sealed_dir_path: *const libc::c_char,
-) -> *mut Box<SectorStore> {
-    let boxed = Box::new(new_sector_store(
-        &ConfiguredStore::Test,
-        c_str_to_rust_str(sealed_dir_path).to_string(),
-        c_str_to_rust_str(staging_dir_path).to_string(),
-    ));
-    raw_ptr(boxed)
+) -> SectorStoreHandle {
+    init_sector_store(&ConfiguredStore::Test, staging_dir_path, sealed_dir_path)
 }
 
-/// Initializes and returns a boxed SectorStore instance for non-test use.
+/// Initializes a SectorStore instance for non-test use, and returns a handle to it.
+/// See api/registry.rs for the rules governing handles.
+///
+/// Returns 0, which is never issued as a handle, if the directories can't be
+/// created, or are the same directory, or one is inside the other (see
+/// check_sector_dirs).
 ///
 /// # Arguments
 ///
@@ -51,83 +74,217 @@ pub unsafe extern "C" fn init_new_test_sector_store(
 pub unsafe extern "C" fn init_new_sector_store(
     staging_dir_path: *const libc::c_char,
     sealed_dir_path: *const libc::c_char,
-) -> *mut Box<SectorStore> {
-    let boxed = Box::new(new_sector_store(
-        &ConfiguredStore::Live,
-        c_str_to_rust_str(sealed_dir_path).to_string(),
-        c_str_to_rust_str(staging_dir_path).to_string(),
-    ));
+) -> SectorStoreHandle {
+    init_sector_store(&ConfiguredStore::Live, staging_dir_path, sealed_dir_path)
+}
 
-    raw_ptr(boxed)
+unsafe fn init_sector_store(
+    cs: &ConfiguredStore,
+    staging_dir_path: *const libc::c_char,
+    sealed_dir_path: *const libc::c_char,
+) -> SectorStoreHandle {
+    let staging_path = c_str_to_rust_str(staging_dir_path).to_string();
+    let sealed_path = c_str_to_rust_str(sealed_dir_path).to_string();
+
+    if check_sector_dirs(&staging_path, &sealed_path).is_err() {
+        return 0;
+    }
+
+    registry::register(Arc::new(new_sector_store(cs, sealed_path, staging_path)))
 }
 
-/// Destroys a boxed SectorStore by freeing its memory.
+/// Invalidates a SectorStore handle. The store itself is freed once every
+/// operation using it has finished. Destroying a stale handle does nothing.
 ///
 /// # Arguments
 ///
-/// * `ss_ptr` - pointer to a boxed SectorStore
+/// * `handle` - handle returned by init_new_sector_store or init_new_test_sector_store
 ///
 #[no_mangle]
-pub unsafe extern "C" fn destroy_storage(ss_ptr: *mut Box<SectorStore>) {
-    let _ = Box::from_raw(ss_ptr);
+pub extern "C" fn destroy_storage(handle: SectorStoreHandle) {
+    let _ = registry::remove(handle);
 }
 
 pub struct DiskManager {
     staging_path: String,
     sealed_path: String,
+
+    // If set, nothing is written to either directory: every call which would
+    // fails with ReadOnlyStore.
+    read_only: bool,
+
+    // How staged sectors are kept on disk. Sealed sectors are always kept as
+    // replicated.
+    staging_encryption: StagingEncryption,
 }
 
 impl SectorManager for DiskManager {
     fn new_sealed_sector_access(&self) -> Result<String, SectorManagerErr> {
+        self.check_writable()?;
         self.new_sector_access(Path::new(&self.sealed_path))
     }
 
     fn new_staging_sector_access(&self) -> Result<String, SectorManagerErr> {
-        self.new_sector_access(Path::new(&self.staging_path))
+        self.check_writable()?;
+        let access = self.new_sector_access(Path::new(&self.staging_path))?;
+
+        if let StagingEncryption::XChaCha20Poly1305(ref key) = self.staging_encryption {
+            OpenOptions::new()
+                .read(true)
+                .write(true)
+                .open(&access)
+                .and_then(|file| EncryptedStagedFile::create(file, key))
+                .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))?;
+        }
+
+        Ok(access)
+    }
+
+    fn prepare_dirs(&self) -> Result<bool, SectorManagerErr> {
+        self.check_writable()?;
+
+        let mut created = false;
+        for root in &[&self.staging_path, &self.sealed_path] {
+            let root = Path::new(root);
+            created |= !root.exists();
+
+            // Provisioning a sector, and removing it, checks that the directory
+            // may be written to.
+            let probe = self.new_sector_access(root)?;
+            remove_file(probe)
+                .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))?;
+        }
+
+        Ok(created)
     }
 
     fn num_unsealed_bytes(&self, access: &str) -> Result<u64, SectorManagerErr> {
-        OpenOptions::new()
-            .read(true)
-            .open(access)
-            .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))
-            .map(|mut f| {
-                target_unpadded_bytes(&mut f)
-                    .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
-            })
-            .and_then(|n| n)
+        let mut staged = self.open_staged(access, false)?;
+
+        target_unpadded_bytes(&mut staged)
+            .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
+    }
+
+    fn open_unsealed(&self, access: &str) -> Result<Box<Read>, SectorManagerErr> {
+        Ok(Box::new(self.open_staged(access, false)?))
     }
 
     fn truncate_unsealed(&self, access: &str, size: u64) -> Result<(), SectorManagerErr> {
-        // I couldn't wrap my head around all ths result mapping, so here it is all laid out.
-        match OpenOptions::new().write(true).open(&access) {
-            Ok(mut file) => match almost_truncate_to_unpadded_bytes(&mut file, size) {
-                Ok(padded_size) => match file.set_len(padded_size as u64) {
-                    Ok(_) => Ok(()),
-                    Err(err) => Err(SectorManagerErr::ReceiverError(format!("{:?}", err))),
-                },
-                Err(err) => Err(SectorManagerErr::ReceiverError(format!("{:?}", err))),
-            },
-            Err(err) => Err(SectorManagerErr::CallerError(format!("{:?}", err))),
-        }
+        self.check_writable()?;
+
+        let mut staged = self.open_staged(access, true)?;
+
+        let mut truncate = || -> io::Result<()> {
+            let padded_size = almost_truncate_to_unpadded_bytes(&mut staged, size)?;
+            staged.set_len(padded_size as u64)?;
+            staged.flush()
+        };
+
+        truncate().map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
+    }
+
+    fn checkpoint_unsealed(&self, access: &str) -> Result<UnsealedCheckpoint, SectorManagerErr> {
+        let mut staged = self.open_staged(access, false)?;
+
+        UnsealedCheckpoint::of(&mut staged)
+            .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
+    }
+
+    fn restore_unsealed(
+        &self,
+        access: &str,
+        checkpoint: &UnsealedCheckpoint,
+    ) -> Result<(), SectorManagerErr> {
+        self.check_writable()?;
+
+        let mut staged = self.open_staged(access, true)?;
+
+        checkpoint
+            .restore(&mut staged)
+            .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))?;
+
+        self.sync_unsealed(access)
+    }
+
+    fn sync_unsealed(&self, access: &str) -> Result<(), SectorManagerErr> {
+        let (path, _) = self.resolve_access(access, &[&self.staging_path])?;
+
+        // Whatever was written through other handles has been flushed to the
+        // file, so syncing any handle to it makes that durable.
+        File::open(path)
+            .and_then(|file| file.sync_all())
+            .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
     }
 
     // TODO: write_and_preprocess should refuse to write more data than will fit. In that case, return 0.
     fn write_and_preprocess(&self, access: &str, data: &[u8]) -> Result<u64, SectorManagerErr> {
-        OpenOptions::new()
-            .read(true)
-            .write(true)
-            .open(access)
-            .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))
-            .and_then(|mut file| {
-                write_padded(data, &mut file)
-                    .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
-                    .map(|n| n as u64)
+        self.check_writable()?;
+
+        let mut staged = self.open_staged(access, true)?;
+
+        write_padded(data, &mut staged)
+            .and_then(|n| staged.flush().map(|_| n as u64))
+            .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
+    }
+
+    fn write_and_preprocess_from_reader(
+        &self,
+        access: &str,
+        data: &mut Read,
+    ) -> Result<u64, SectorManagerErr> {
+        self.check_writable()?;
+
+        let mut staged = self.open_staged(access, true)?;
+
+        let checkpoint = UnsealedCheckpoint::of(&mut staged)
+            .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))?;
+
+        write_padded_from_reader(data, &mut staged)
+            .and_then(|n| staged.flush().map(|_| n))
+            .or_else(|err| {
+                checkpoint
+                    .restore(&mut staged)
+                    .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))?;
+
+                Err(SectorManagerErr::ReceiverError(format!("{:?}", err)))
             })
     }
 
     fn delete_staging_sector_access(&self, access: &str) -> Result<(), SectorManagerErr> {
-        remove_file(access).map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))
+        self.check_writable()?;
+
+        let (path, _) = self.resolve_access(access, &[&self.staging_path])?;
+
+        remove_file(path).map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))
+    }
+
+    fn allocated_bytes(&self, access: &str) -> Result<u64, SectorManagerErr> {
+        let (path, _) = self.resolve_access(access, &[&self.staging_path, &self.sealed_path])?;
+
+        sparse::allocated_bytes(&path)
+            .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
+    }
+
+    fn copy_sector_access(
+        &self,
+        src_access: &str,
+        dest_access: &str,
+    ) -> Result<u64, SectorManagerErr> {
+        let (src_path, _) =
+            self.resolve_access(src_access, &[&self.staging_path, &self.sealed_path])?;
+
+        let mut src = File::open(src_path)
+            .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))?;
+
+        let dest_path =
+            self.resolve_new_access(dest_access, &[&self.staging_path, &self.sealed_path])?;
+
+        let mut dest = File::create(dest_path)
+            .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))?;
+
+        io::copy(&mut src, &mut dest)
+            .and_then(|n| dest.sync_all().map(|_| n))
+            .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
     }
 
     fn read_raw(
@@ -136,25 +293,232 @@ impl SectorManager for DiskManager {
         start_offset: u64,
         num_bytes: u64,
     ) -> Result<Vec<u8>, SectorManagerErr> {
-        OpenOptions::new()
-            .read(true)
-            .open(access)
-            .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))
-            .and_then(|mut file| -> Result<Vec<u8>, SectorManagerErr> {
-                file.seek(SeekFrom::Start(start_offset))
-                    .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))?;
+        let (path, root) = self.resolve_access(access, &[&self.staging_path, &self.sealed_path])?;
 
-                let mut buf = vec![0; num_bytes as usize];
+        // Staged sectors are read as they were written, before any encryption.
+        let file = if root == &self.staging_path {
+            self.open_staged(access, false)
+        } else {
+            File::open(path)
+                .map(SectorFile::Plain)
+                .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))
+        };
 
-                file.read_exact(buf.as_mut_slice())
-                    .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))?;
+        file.and_then(|mut file| -> Result<Vec<u8>, SectorManagerErr> {
+            file.seek(SeekFrom::Start(start_offset))
+                .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))?;
 
-                Ok(buf)
-            })
+            let mut buf = vec![0; num_bytes as usize];
+
+            file.read_exact(buf.as_mut_slice())
+                .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))?;
+
+            Ok(buf)
+        })
+    }
+}
+
+// A sector's file, through which the bytes written to it are read and written,
+// decrypting and encrypting them if the file is an encrypted staged file.
+enum SectorFile {
+    Plain(File),
+    Encrypted(EncryptedStagedFile),
+}
+
+impl SectorFile {
+    fn set_len(&mut self, len: u64) -> io::Result<()> {
+        match self {
+            SectorFile::Plain(file) => file.set_len(len),
+            SectorFile::Encrypted(file) => file.set_len(len),
+        }
+    }
+}
+
+impl Read for SectorFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            SectorFile::Plain(file) => file.read(buf),
+            SectorFile::Encrypted(file) => file.read(buf),
+        }
+    }
+}
+
+// Plain files are written sparsely, so that the zeros staged sectors are
+// padded with take up no disk. Encrypted files can't be: their zeros are
+// encrypted.
+impl Write for SectorFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            SectorFile::Plain(file) => sparse::write_sparse(file, buf).map(|_| buf.len()),
+            SectorFile::Encrypted(file) => file.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            SectorFile::Plain(file) => file.flush(),
+            SectorFile::Encrypted(file) => file.flush(),
+        }
+    }
+}
+
+impl Seek for SectorFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        match self {
+            SectorFile::Plain(file) => file.seek(pos),
+            SectorFile::Encrypted(file) => file.seek(pos),
+        }
+    }
+}
+
+// Padding rewrites the last byte of a file if it's only partly used, so the
+// byte is needed, along with the length, to undo a write.
+impl UnsealedCheckpoint {
+    fn of(file: &mut SectorFile) -> io::Result<UnsealedCheckpoint> {
+        let len = file.seek(SeekFrom::End(0))?;
+
+        let last_byte = if len > 0 {
+            let mut byte = [0; 1];
+            file.seek(SeekFrom::Start(len - 1))?;
+            file.read_exact(&mut byte)?;
+            Some(byte[0])
+        } else {
+            None
+        };
+
+        Ok(UnsealedCheckpoint { len, last_byte })
+    }
+
+    fn restore(&self, file: &mut SectorFile) -> io::Result<()> {
+        file.set_len(self.len)?;
+
+        if let Some(byte) = self.last_byte {
+            file.seek(SeekFrom::Start(self.len - 1))?;
+            file.write_all(&[byte])?;
+        }
+
+        file.flush()
     }
 }
 
 impl DiskManager {
+    // A writable manager of plaintext staged sectors in staging_path, and of
+    // sealed sectors in sealed_path, for other managers to build on.
+    pub(crate) fn new(staging_path: String, sealed_path: String) -> DiskManager {
+        DiskManager {
+            staging_path,
+            sealed_path,
+            read_only: false,
+            staging_encryption: StagingEncryption::None,
+        }
+    }
+
+    fn check_writable(&self) -> Result<(), SectorManagerErr> {
+        if self.read_only {
+            return Err(SectorManagerErr::ReadOnlyStore);
+        }
+
+        Ok(())
+    }
+
+    // Resolves access, as handed in by a caller, to the file it names, which
+    // must be strictly inside one of roots once `.`, `..` and symlinks are
+    // resolved: whatever the caller hands in, nothing outside the store's
+    // directories is read, written, truncated or deleted. Returns the resolved
+    // path, and the root it's inside. Refuses any other access (including one
+    // naming no file) with CallerError, before the file is opened.
+    fn resolve_access<'a>(
+        &self,
+        access: &str,
+        roots: &[&'a String],
+    ) -> Result<(PathBuf, &'a String), SectorManagerErr> {
+        let invalid = |reason: String| {
+            SectorManagerErr::CallerError(format!("invalid sector access {:?}: {}", access, reason))
+        };
+
+        if access.is_empty() || access.contains('\0') {
+            return Err(invalid("empty or holds a NUL".to_string()));
+        }
+
+        let path = canonicalize(access).map_err(|err| invalid(format!("{:?}", err)))?;
+
+        for root in roots {
+            if let Ok(root_path) = canonicalize(root) {
+                if path != root_path && path.starts_with(&root_path) {
+                    return Ok((path, root));
+                }
+            }
+        }
+
+        Err(invalid("outside the store's directories".to_string()))
+    }
+
+    // As resolve_access, but for an access which is to be created (or
+    // replaced) in one of roots: its directory, rather than the file, must
+    // resolve inside a root. One which exists, e.g. as a link, must resolve
+    // inside one as it is.
+    fn resolve_new_access(
+        &self,
+        access: &str,
+        roots: &[&String],
+    ) -> Result<PathBuf, SectorManagerErr> {
+        let invalid = |reason: String| {
+            SectorManagerErr::CallerError(format!("invalid sector access {:?}: {}", access, reason))
+        };
+
+        if access.is_empty() || access.contains('\0') {
+            return Err(invalid("empty or holds a NUL".to_string()));
+        }
+
+        let path = Path::new(access);
+
+        if path.symlink_metadata().is_ok() {
+            return self.resolve_access(access, roots).map(|(path, _)| path);
+        }
+
+        let (dir, name) = match (path.parent(), path.components().last()) {
+            (Some(dir), Some(Component::Normal(name))) => (dir, name),
+            _ => return Err(invalid("not a file's path".to_string())),
+        };
+
+        // A bare file name is relative to the working directory.
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let dir = canonicalize(dir).map_err(|err| invalid(format!("{:?}", err)))?;
+
+        for root in roots {
+            if let Ok(root_path) = canonicalize(root) {
+                if dir.starts_with(&root_path) {
+                    return Ok(dir.join(name));
+                }
+            }
+        }
+
+        Err(invalid("outside the store's directories".to_string()))
+    }
+
+    // Opens the staged sector at access, to be written to as well as read if
+    // writable is set.
+    fn open_staged(&self, access: &str, writable: bool) -> Result<SectorFile, SectorManagerErr> {
+        let (path, _) = self.resolve_access(access, &[&self.staging_path])?;
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(writable)
+            .open(path)
+            .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))?;
+
+        match self.staging_encryption {
+            StagingEncryption::None => Ok(SectorFile::Plain(file)),
+            StagingEncryption::XChaCha20Poly1305(ref key) => EncryptedStagedFile::open(file, key)
+                .map(SectorFile::Encrypted)
+                .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err))),
+        }
+    }
+
     fn new_sector_access(&self, root: &Path) -> Result<String, SectorManagerErr> {
         let pbuf = root.join(util::rand_alpha_string(32));
 
@@ -178,20 +542,67 @@ impl DiskManager {
     }
 }
 
+/// Creates the staging and sealed directories, if need be, and checks that
+/// they're neither the same directory nor one inside the other, which would
+/// mix staged and sealed sectors. The directories are compared once symlinks,
+/// `.` and `..` are resolved, so that e.g. `/data/store` and `/data/./store`
+/// are found to be the same.
+pub fn check_sector_dirs(staging_path: &str, sealed_path: &str) -> Result<(), SectorManagerErr> {
+    let resolve = |path: &str| {
+        create_dir_all(path)
+            .and_then(|_| canonicalize(path))
+            .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
+    };
+
+    let staging = resolve(staging_path)?;
+    let sealed = resolve(sealed_path)?;
+
+    if staging.starts_with(&sealed) || sealed.starts_with(&staging) {
+        return Err(SectorManagerErr::OverlappingSectorDirs {
+            staging: staging_path.to_string(),
+            sealed: sealed_path.to_string(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks that sectors of `sector_bytes` (sealed) bytes are supported: that
+/// they hold a power-of-two number of nodes of NODE_SIZE bytes. The trees committed to
+/// by comm_d and comm_r, the unsealing of a range of nodes and PoSt are only
+/// defined for such sectors, so any other size is refused wherever one enters
+/// (e.g. generating parameters, reading a sealed sector's recorded size or a
+/// prover bundle) rather than padded.
+pub fn check_sector_bytes(sector_bytes: u64) -> Result<(), SectorManagerErr> {
+    let node_bytes = NODE_SIZE as u64;
+    let nodes = sector_bytes / node_bytes;
+
+    if sector_bytes % node_bytes != 0 || !nodes.is_power_of_two() {
+        return Err(SectorManagerErr::UnsupportedSectorSize(sector_bytes));
+    }
+
+    Ok(())
+}
+
 pub struct Config {
     sector_bytes: u64,
+    proof_variant: ProofVariant,
+    proof_version: ProofVersion,
+    post_seal_sample_check: Option<usize>,
+    aux_compression: AuxCompression,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 #[repr(C)]
 pub enum ConfiguredStore {
     Live = 0,
     Test = 1,
+    LargeTest = 2,
 }
 
 pub struct ConcreteSectorStore {
-    config: Box<SectorConfig>,
-    manager: Box<SectorManager>,
+    pub(crate) config: Box<SectorConfig>,
+    pub(crate) manager: Box<SectorManager>,
 }
 
 impl SectorStore for ConcreteSectorStore {
@@ -208,10 +619,65 @@ pub fn new_sector_store(
     cs: &ConfiguredStore,
     sealed_path: String,
     staging_path: String,
+) -> ConcreteSectorStore {
+    new_sector_store_with_proof_variant(cs, sealed_path, staging_path, ProofVariant::Snark)
+}
+
+/// Like new_sector_store, but the seals of the store's sectors are proven with
+/// the given variant.
+pub fn new_sector_store_with_proof_variant(
+    cs: &ConfiguredStore,
+    sealed_path: String,
+    staging_path: String,
+    proof_variant: ProofVariant,
+) -> ConcreteSectorStore {
+    new_sector_store_with_staging_encryption(
+        cs,
+        sealed_path,
+        staging_path,
+        proof_variant,
+        StagingEncryption::None,
+    )
+}
+
+/// Like new_sector_store_with_proof_variant, but the store's staged sectors
+/// are kept on disk as staging_encryption says (see io::staging_encryption),
+/// e.g. encrypted with a key given by the caller. Sealed sectors are never
+/// encrypted. Staged sectors written with another staging_encryption can't be
+/// read through the store.
+pub fn new_sector_store_with_staging_encryption(
+    cs: &ConfiguredStore,
+    sealed_path: String,
+    staging_path: String,
+    proof_variant: ProofVariant,
+    staging_encryption: StagingEncryption,
+) -> ConcreteSectorStore {
+    let manager = Box::new(DiskManager {
+        staging_path,
+        sealed_path,
+        read_only: false,
+        staging_encryption,
+    });
+
+    let config = new_sector_config_with_proof_variant(cs, proof_variant);
+
+    ConcreteSectorStore { config, manager }
+}
+
+/// Like new_sector_store, but nothing is ever written to the store's
+/// directories, which may be on a read-only file system: provisioning, writing,
+/// truncating or deleting a sector fails with ReadOnlyStore. Sectors may still
+/// be read, and copied out of the store.
+pub fn new_readonly_sector_store(
+    cs: &ConfiguredStore,
+    sealed_path: String,
+    staging_path: String,
 ) -> ConcreteSectorStore {
     let manager = Box::new(DiskManager {
         staging_path,
         sealed_path,
+        read_only: true,
+        staging_encryption: StagingEncryption::None,
     });
 
     let config = new_sector_config(cs);
@@ -219,25 +685,139 @@ pub fn new_sector_store(
     ConcreteSectorStore { config, manager }
 }
 
-pub fn new_sector_config(cs: &ConfiguredStore) -> Box<SectorConfig> {
+/// Returns a store of MINI_SECTOR_SIZE-byte sectors whose seals are made with
+/// ProofVersion::Mini's parameters and proven with vanilla proofs, so that
+/// sealing, verifying and unsealing one takes well under a second: there are
+/// no groth parameters to generate or load. For tests which run on every
+/// commit. Its proofs are refused by every other store's config.
+pub fn new_mini_sector_store(sealed_path: String, staging_path: String) -> ConcreteSectorStore {
+    let manager = Box::new(DiskManager::new(staging_path, sealed_path));
+
+    ConcreteSectorStore {
+        config: new_mini_sector_config(),
+        manager,
+    }
+}
+
+/// Returns the config of a store made by new_mini_sector_store.
+pub fn new_mini_sector_config() -> Box<SectorConfig> {
+    new_sector_config_of_version(MINI_SECTOR_SIZE, ProofVariant::Vanilla, ProofVersion::Mini)
+}
+
+fn configured_sector_bytes(cs: &ConfiguredStore) -> u64 {
     match *cs {
-        ConfiguredStore::Live => Box::new(Config {
-            sector_bytes: LIVE_SECTOR_SIZE,
-        }),
-        ConfiguredStore::Test => Box::new(Config {
-            sector_bytes: TEST_SECTOR_SIZE,
-        }),
+        ConfiguredStore::Live => LIVE_SECTOR_SIZE,
+        ConfiguredStore::Test => TEST_SECTOR_SIZE,
+        ConfiguredStore::LargeTest => LARGE_TEST_SECTOR_SIZE,
     }
 }
 
+pub fn new_sector_config(cs: &ConfiguredStore) -> Box<SectorConfig> {
+    new_sector_config_with_proof_variant(cs, ProofVariant::Snark)
+}
+
+pub fn new_sector_config_with_proof_variant(
+    cs: &ConfiguredStore,
+    proof_variant: ProofVariant,
+) -> Box<SectorConfig> {
+    new_sector_config_with_sample_check(cs, proof_variant, None)
+}
+
+/// Like new_sector_config_with_proof_variant, but once a sector's replica is
+/// written, the given number of its nodes are read back from the file and
+/// compared with those sealed, if given.
+pub fn new_sector_config_with_sample_check(
+    cs: &ConfiguredStore,
+    proof_variant: ProofVariant,
+    post_seal_sample_check: Option<usize>,
+) -> Box<SectorConfig> {
+    let sector_bytes = configured_sector_bytes(cs);
+
+    Box::new(Config {
+        sector_bytes,
+        proof_variant,
+        proof_version: ProofVersion::CURRENT,
+        post_seal_sample_check,
+        aux_compression: AuxCompression::None,
+    })
+}
+
+/// Like new_sector_config_with_proof_variant, but the merkle trees written to
+/// disk while sealing are compressed as aux_compression says.
+pub fn new_sector_config_with_aux_compression(
+    cs: &ConfiguredStore,
+    proof_variant: ProofVariant,
+    aux_compression: AuxCompression,
+) -> Box<SectorConfig> {
+    Box::new(Config {
+        sector_bytes: configured_sector_bytes(cs),
+        proof_variant,
+        proof_version: ProofVersion::CURRENT,
+        post_seal_sample_check: None,
+        aux_compression,
+    })
+}
+
+/// Like new_sector_config_with_proof_variant, but seals are made and proven
+/// with the parameters of the given version rather than the current one's.
+pub fn new_sector_config_with_proof_version(
+    cs: &ConfiguredStore,
+    proof_variant: ProofVariant,
+    proof_version: ProofVersion,
+) -> Box<SectorConfig> {
+    new_sector_config_of_version(configured_sector_bytes(cs), proof_variant, proof_version)
+}
+
+/// Returns the config of sectors of sector_bytes (sealed) bytes, whose seals
+/// are proven with the given variant, e.g. to unseal a sector recorded with
+/// its size. The size must have passed check_sector_bytes.
+pub fn new_sector_config_of_size(
+    sector_bytes: u64,
+    proof_variant: ProofVariant,
+) -> Box<SectorConfig> {
+    new_sector_config_of_version(sector_bytes, proof_variant, ProofVersion::CURRENT)
+}
+
+/// Like new_sector_config_of_size, for sectors sealed with the parameters of
+/// the given version, e.g. to prove a seal recorded with its version.
+pub fn new_sector_config_of_version(
+    sector_bytes: u64,
+    proof_variant: ProofVariant,
+    proof_version: ProofVersion,
+) -> Box<SectorConfig> {
+    Box::new(Config {
+        sector_bytes,
+        proof_variant,
+        proof_version,
+        post_seal_sample_check: None,
+        aux_compression: AuxCompression::None,
+    })
+}
+
 impl SectorConfig for Config {
     fn max_unsealed_bytes_per_sector(&self) -> u64 {
-        unpadded_bytes(self.sector_bytes)
+        unpadded_bytes(data_padded_bytes(self.sector_bytes))
     }
 
     fn sector_bytes(&self) -> u64 {
         self.sector_bytes
     }
+
+    fn proof_variant(&self) -> ProofVariant {
+        self.proof_variant
+    }
+
+    fn proof_version(&self) -> ProofVersion {
+        self.proof_version
+    }
+
+    fn post_seal_sample_check(&self) -> Option<usize> {
+        self.post_seal_sample_check
+    }
+
+    fn aux_compression(&self) -> AuxCompression {
+        self.aux_compression
+    }
 }
 
 #[cfg(test)]
@@ -245,6 +825,8 @@ pub mod tests {
     use super::*;
 
     use crate::io::fr32::FR32_PADDING_MAP;
+    use crate::io::staging_encryption::StagingKey;
+    use std::fs;
     use std::fs::create_dir_all;
     use std::fs::File;
     use std::io::Read;
@@ -275,8 +857,9 @@ pub mod tests {
     #[test]
     fn max_unsealed_bytes_per_sector_checks() {
         let xs = vec![
-            (ConfiguredStore::Live, 266338304),
-            (ConfiguredStore::Test, 1016),
+            (ConfiguredStore::Live, 266338272),
+            (ConfiguredStore::Test, 984),
+            (ConfiguredStore::LargeTest, 2000),
         ];
 
         for (configured_store, num_bytes) in xs {
@@ -286,6 +869,50 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn only_power_of_two_node_counts_are_supported() {
+        for cs in &[
+            ConfiguredStore::Live,
+            ConfiguredStore::Test,
+            ConfiguredStore::LargeTest,
+        ] {
+            let sector_bytes = new_sector_config(cs).sector_bytes();
+            assert!(check_sector_bytes(sector_bytes).is_ok());
+        }
+
+        assert!(check_sector_bytes(new_mini_sector_config().sector_bytes()).is_ok());
+        assert!(check_sector_bytes(32).is_ok());
+
+        for sector_bytes in &[0, 33, 96, 1000, 1536, (1 << 30) + 32] {
+            match check_sector_bytes(*sector_bytes) {
+                Err(SectorManagerErr::UnsupportedSectorSize(n)) => assert_eq!(*sector_bytes, n),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn mini_stores_prove_mini_seals_with_vanilla_proofs() {
+        let cfg = new_mini_sector_config();
+
+        assert_eq!(MINI_SECTOR_SIZE, cfg.sector_bytes());
+        assert_eq!(ProofVariant::Vanilla, cfg.proof_variant());
+        assert_eq!(ProofVersion::Mini, cfg.proof_version());
+    }
+
+    #[test]
+    fn proof_variant_defaults_to_snark() {
+        assert_eq!(
+            ProofVariant::Snark,
+            new_sector_config(&ConfiguredStore::Test).proof_variant()
+        );
+        assert_eq!(
+            ProofVariant::Vanilla,
+            new_sector_config_with_proof_variant(&ConfiguredStore::Test, ProofVariant::Vanilla)
+                .proof_variant()
+        );
+    }
+
     #[test]
     fn unsealed_sector_write_and_truncate() {
         let configured_store = ConfiguredStore::Test;
@@ -368,6 +995,337 @@ pub mod tests {
         }
     }
 
+    #[test]
+    fn zeros_of_staged_sectors_are_left_as_holes() {
+        let storage = create_sector_store(&ConfiguredStore::Test);
+        let mgr = storage.manager();
+        let access = mgr.new_staging_sector_access().unwrap();
+
+        // One small piece, and a large one of zeros, as a sector is padded.
+        let piece = [7u8; 100];
+        let zeros = vec![0u8; 1 << 20];
+        mgr.write_and_preprocess(&access, &piece).unwrap();
+        mgr.write_and_preprocess(&access, &zeros).unwrap();
+
+        // The sector reads as one written densely does.
+        let mut dense = io::Cursor::new(Vec::new());
+        write_padded(&piece, &mut dense).unwrap();
+        write_padded(&zeros, &mut dense).unwrap();
+        let dense = dense.into_inner();
+
+        assert_eq!(dense, read_all_bytes(&access));
+        assert_eq!(
+            (piece.len() + zeros.len()) as u64,
+            mgr.num_unsealed_bytes(&access).unwrap()
+        );
+
+        let allocated = mgr.allocated_bytes(&access).unwrap();
+        let dir = Path::new(&access).parent().unwrap();
+        if sparse::holes_supported(dir).unwrap() {
+            assert!(allocated < dense.len() as u64 / 2, "{} bytes allocated", allocated);
+        }
+    }
+
+    // Fails after producing `len` bytes.
+    struct FailingReader {
+        len: usize,
+    }
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.len == 0 {
+                return Err(io::Error::new(io::ErrorKind::Other, "source failed"));
+            }
+
+            let n = ::std::cmp::min(self.len, buf.len());
+            for b in &mut buf[..n] {
+                *b = 7;
+            }
+            self.len -= n;
+
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn write_from_reader_matches_write() {
+        let storage = create_sector_store(&ConfiguredStore::Test);
+        let mgr = storage.manager();
+
+        let written = mgr.new_staging_sector_access().unwrap();
+        let streamed = mgr.new_staging_sector_access().unwrap();
+
+        for contents in &[vec![1u8; 27], vec![2u8; 300]] {
+            mgr.write_and_preprocess(&written, contents).unwrap();
+
+            let n = mgr
+                .write_and_preprocess_from_reader(&streamed, &mut &contents[..])
+                .unwrap();
+            assert_eq!(contents.len() as u64, n);
+        }
+
+        assert_eq!(read_all_bytes(&written), read_all_bytes(&streamed));
+    }
+
+    #[test]
+    fn failed_write_from_reader_is_rolled_back() {
+        let storage = create_sector_store(&ConfiguredStore::Test);
+        let mgr = storage.manager();
+        let access = mgr.new_staging_sector_access().unwrap();
+
+        // Leave the last byte only partly used: 32 bytes are padded to 33, the
+        // last holding 2 bits. The failing source writes a whole chunk before
+        // it fails.
+        mgr.write_and_preprocess(&access, &[3u8; 32]).unwrap();
+        let before = read_all_bytes(&access);
+        assert_eq!(33, before.len());
+
+        assert!(mgr
+            .write_and_preprocess_from_reader(&access, &mut FailingReader { len: 200_000 })
+            .is_err());
+
+        assert_eq!(before, read_all_bytes(&access));
+        assert_eq!(32, mgr.num_unsealed_bytes(&access).unwrap());
+    }
+
+    #[test]
+    fn unsealed_sectors_are_restored_to_checkpoints() {
+        let storage = create_sector_store(&ConfiguredStore::Test);
+        let mgr = storage.manager();
+        let access = mgr.new_staging_sector_access().unwrap();
+
+        // Leave the last byte only partly used, so that the next write
+        // rewrites it: 32 bytes are padded to 33, the last holding 2 bits.
+        mgr.write_and_preprocess(&access, &[3u8; 32]).unwrap();
+        let before = read_all_bytes(&access);
+        assert_eq!(33, before.len());
+        let checkpoint = mgr.checkpoint_unsealed(&access).unwrap();
+        assert_eq!(before.len() as u64, checkpoint.len);
+
+        mgr.write_and_preprocess(&access, &[0xffu8; 100]).unwrap();
+        mgr.sync_unsealed(&access).unwrap();
+        assert_ne!(before, read_all_bytes(&access)[..before.len()].to_vec());
+
+        mgr.restore_unsealed(&access, &checkpoint).unwrap();
+        assert_eq!(before, read_all_bytes(&access));
+        assert_eq!(32, mgr.num_unsealed_bytes(&access).unwrap());
+    }
+
+    #[test]
+    fn encrypted_staged_sectors_read_as_plaintext_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+
+        let plain = create_sector_store(&ConfiguredStore::Test);
+        let encrypted = new_sector_store_with_staging_encryption(
+            &ConfiguredStore::Test,
+            path("sealed"),
+            path("staged"),
+            ProofVariant::Snark,
+            StagingEncryption::XChaCha20Poly1305(StagingKey::from_bytes(1, [5; 32])),
+        );
+
+        let contents: Vec<u8> = (0..900).map(|_| rand::random()).collect();
+
+        let read_unsealed = |mgr: &SectorManager, access: &str| {
+            let mut bytes = Vec::new();
+            mgr.open_unsealed(access)
+                .unwrap()
+                .read_to_end(&mut bytes)
+                .unwrap();
+            bytes
+        };
+
+        let stage = |mgr: &SectorManager| {
+            let access = mgr.new_staging_sector_access().unwrap();
+            mgr.write_and_preprocess(&access, &contents[..27]).unwrap();
+            mgr.write_and_preprocess_from_reader(&access, &mut &contents[27..600])
+                .unwrap();
+            assert!(mgr
+                .write_and_preprocess_from_reader(&access, &mut FailingReader { len: 200_000 })
+                .is_err());
+            mgr.truncate_unsealed(&access, 500).unwrap();
+            mgr.write_and_preprocess(&access, &contents[500..]).unwrap();
+            access
+        };
+
+        let plain_access = stage(plain.manager());
+        let encrypted_access = stage(encrypted.manager());
+
+        assert_eq!(
+            read_unsealed(plain.manager(), &plain_access),
+            read_unsealed(encrypted.manager(), &encrypted_access)
+        );
+        assert_eq!(
+            900,
+            encrypted
+                .manager()
+                .num_unsealed_bytes(&encrypted_access)
+                .unwrap()
+        );
+        assert_eq!(
+            plain.manager().read_raw(&plain_access, 100, 200).unwrap(),
+            encrypted
+                .manager()
+                .read_raw(&encrypted_access, 100, 200)
+                .unwrap()
+        );
+
+        // Nothing written is found on disk, as given or as preprocessed.
+        let on_disk = read_all_bytes(&encrypted_access);
+        let preprocessed = read_all_bytes(&plain_access);
+        for written in &[&contents, &preprocessed] {
+            assert!(written
+                .windows(8)
+                .all(|run| !on_disk.windows(8).any(|window| window == run)));
+        }
+
+        // Nor can the staged sector be read without its key.
+        let other = new_sector_store_with_staging_encryption(
+            &ConfiguredStore::Test,
+            path("sealed"),
+            path("staged"),
+            ProofVariant::Snark,
+            StagingEncryption::XChaCha20Poly1305(StagingKey::from_bytes(1, [6; 32])),
+        );
+        // It's opened, as its header names the same key id, but the first
+        // chunk read fails to authenticate.
+        let mut staged = other.manager().open_unsealed(&encrypted_access).unwrap();
+        assert!(staged.read_to_end(&mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn copies_sector_access() {
+        let store = create_sector_store(&ConfiguredStore::Test);
+        let mgr = store.manager();
+
+        let src = mgr.new_sealed_sector_access().unwrap();
+        let dest = mgr.new_sealed_sector_access().unwrap();
+        File::create(&src).unwrap().write_all(&[9u8; 1024]).unwrap();
+
+        assert_eq!(1024, mgr.copy_sector_access(&src, &dest).unwrap());
+        assert_eq!(read_all_bytes(&src), read_all_bytes(&dest));
+
+        match mgr.copy_sector_access(&format!("{}.missing", src), &dest) {
+            Err(SectorManagerErr::CallerError(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn overlapping_sector_dirs_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |rel: &str| dir.path().join(rel).to_str().unwrap().to_owned();
+
+        let overlapping = |staging: &str, sealed: &str| match check_sector_dirs(staging, sealed) {
+            Err(SectorManagerErr::OverlappingSectorDirs { .. }) => true,
+            Ok(()) => false,
+            Err(err) => panic!("unexpected error: {:?}", err),
+        };
+
+        assert!(!overlapping(&path("staging"), &path("sealed")));
+
+        // A directory which merely shares a prefix with the other is another.
+        assert!(!overlapping(&path("store"), &path("store2")));
+
+        assert!(overlapping(&path("store"), &path("store")));
+        assert!(overlapping(&path("store"), &path("./store")));
+        assert!(overlapping(&path("store"), &path("other/../store/")));
+
+        // Either may be inside the other.
+        assert!(overlapping(&path("store"), &path("store/sealed")));
+        assert!(overlapping(&path("store/staging"), &path("store")));
+
+        // Including through a symlink.
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(path("store"), path("link")).unwrap();
+            assert!(overlapping(&path("store"), &path("link")));
+            assert!(overlapping(&path("link/staging"), &path("store")));
+        }
+
+        // Both were created, even when refused.
+        assert!(dir.path().join("store/sealed").is_dir());
+        assert!(dir.path().join("other").is_dir());
+    }
+
+    #[test]
+    fn stores_are_not_initialized_over_overlapping_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let c_path = |rel: &str| {
+            let path = dir.path().join(rel);
+            ffi_toolkit::rust_str_to_c_str(path.to_str().unwrap())
+        };
+
+        unsafe {
+            assert_eq!(0, init_new_test_sector_store(c_path("store"), c_path("./store")));
+            assert_eq!(0, init_new_test_sector_store(c_path("store"), c_path("store/sealed")));
+
+            let handle = init_new_test_sector_store(c_path("staging"), c_path("sealed"));
+            assert_ne!(0, handle);
+            destroy_storage(handle);
+        }
+    }
+
+    #[test]
+    fn readonly_stores_read_but_never_write() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_owned();
+
+        let writable = new_sector_store(&ConfiguredStore::Test, path.clone(), path.clone());
+        let access = writable.manager().new_staging_sector_access().unwrap();
+        writable.manager().write_and_preprocess(&access, &[3; 100]).unwrap();
+
+        let store = new_readonly_sector_store(&ConfiguredStore::Test, path.clone(), path);
+        let mgr = store.manager();
+        let entries = || fs::read_dir(dir.path()).unwrap().count();
+        let entries_before = entries();
+        let bytes_before = read_all_bytes(&access);
+
+        assert_eq!(100, mgr.num_unsealed_bytes(&access).unwrap());
+        assert_eq!(bytes_before[..32], mgr.read_raw(&access, 0, 32).unwrap()[..]);
+
+        let read_only = |result: Result<(), SectorManagerErr>| match result {
+            Err(SectorManagerErr::ReadOnlyStore) => (),
+            other => panic!("unexpected result: {:?}", other),
+        };
+
+        read_only(mgr.new_sealed_sector_access().map(|_| ()));
+        read_only(mgr.new_staging_sector_access().map(|_| ()));
+        read_only(mgr.prepare_dirs().map(|_| ()));
+        read_only(mgr.write_and_preprocess(&access, &[4; 10]).map(|_| ()));
+        read_only(
+            mgr.write_and_preprocess_from_reader(&access, &mut &[4; 10][..])
+                .map(|_| ()),
+        );
+        read_only(mgr.truncate_unsealed(&access, 10));
+        read_only(mgr.delete_staging_sector_access(&access));
+
+        assert_eq!(entries_before, entries());
+        assert_eq!(bytes_before, read_all_bytes(&access));
+    }
+
+    #[test]
+    fn prepares_missing_dirs() {
+        let dir = tempfile::tempdir().unwrap();
+        let staging = dir.path().join("store").join("staging");
+        let sealed = dir.path().join("store").join("sealed");
+
+        let store = new_sector_store(
+            &ConfiguredStore::Test,
+            sealed.to_str().unwrap().to_owned(),
+            staging.to_str().unwrap().to_owned(),
+        );
+
+        assert!(store.manager().prepare_dirs().unwrap());
+        assert!(staging.is_dir() && sealed.is_dir());
+
+        // Preparing again finds the directories, and leaves nothing in them.
+        assert!(!store.manager().prepare_dirs().unwrap());
+        assert_eq!(0, fs::read_dir(&staging).unwrap().count());
+        assert_eq!(0, fs::read_dir(&sealed).unwrap().count());
+    }
+
     #[test]
     fn deletes_staging_access() {
         let configured_store = ConfiguredStore::Test;
@@ -384,4 +1342,178 @@ pub mod tests {
 
         assert!(store.manager().read_raw(&access, 0, 0).is_err());
     }
+
+    #[test]
+    fn accesses_outside_the_store_are_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+
+        let store = new_sector_store(&ConfiguredStore::Test, path("sealed"), path("staging"));
+        let mgr = store.manager();
+        mgr.prepare_dirs().unwrap();
+
+        let outside = path("outside");
+        fs::write(&outside, &[7; 100]).unwrap();
+
+        let mut accesses = vec![
+            format!("{}/../outside", path("staging")),
+            format!("{}/../../outside", path("sealed/x")),
+            outside.clone(),
+            path("staging"),
+            format!("{}\0", mgr.new_staging_sector_access().unwrap()),
+            String::new(),
+        ];
+
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink(&outside, path("staging/link")).unwrap();
+            accesses.push(path("staging/link"));
+        }
+
+        let refused = |access: &str, result: Result<(), SectorManagerErr>| match result {
+            Err(SectorManagerErr::CallerError(ref msg))
+                if msg.contains("invalid sector access") => {}
+            other => panic!("{:?} was not refused: {:?}", access, other),
+        };
+
+        for access in &accesses {
+            let checkpoint = UnsealedCheckpoint {
+                len: 0,
+                last_byte: None,
+            };
+
+            refused(
+                access,
+                mgr.write_and_preprocess(access, &[4; 10]).map(|_| ()),
+            );
+            refused(
+                access,
+                mgr.write_and_preprocess_from_reader(access, &mut &[4; 10][..])
+                    .map(|_| ()),
+            );
+            refused(access, mgr.truncate_unsealed(access, 0));
+            refused(access, mgr.num_unsealed_bytes(access).map(|_| ()));
+            refused(access, mgr.open_unsealed(access).map(|_| ()));
+            refused(access, mgr.checkpoint_unsealed(access).map(|_| ()));
+            refused(access, mgr.restore_unsealed(access, &checkpoint));
+            refused(access, mgr.sync_unsealed(access));
+            refused(access, mgr.delete_staging_sector_access(access));
+            refused(access, mgr.read_raw(access, 0, 1).map(|_| ()));
+            refused(
+                access,
+                mgr.copy_sector_access(access, &path("copy")).map(|_| ()),
+            );
+        }
+
+        // Nor is a copy made to any of them, or to a new file outside.
+        let src = mgr.new_sealed_sector_access().unwrap();
+        fs::write(&src, &[9; 100]).unwrap();
+        accesses.push(path("copy"));
+        accesses.push(format!("{}/../copy", path("sealed")));
+
+        for dest in &accesses {
+            refused(dest, mgr.copy_sector_access(&src, dest).map(|_| ()));
+        }
+
+        assert_eq!(vec![7; 100], read_all_bytes(&outside));
+        assert!(!dir.path().join("copy").exists());
+
+        // A new file in either directory is fine.
+        let copy = format!("{}/copy", path("staging"));
+        assert_eq!(100, mgr.copy_sector_access(&src, &copy).unwrap());
+        assert_eq!(vec![9; 100], read_all_bytes(&copy));
+    }
+}
+
+// The store's file handling, as run on Windows, where paths are canonicalized
+// to `\\?\` paths, and files open elsewhere can't be renamed over. The unix
+// runs are covered by the tests above. Run on a Windows machine with:
+//
+//     cargo test -p sector-base windows_tests
+#[cfg(all(test, windows))]
+mod windows_tests {
+    use super::*;
+
+    use crate::api::util::rename_durably;
+    use std::fs;
+
+    fn store_in(dir: &Path) -> ConcreteSectorStore {
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_owned();
+
+        new_sector_store(&ConfiguredStore::Test, path("sealed"), path("staging"))
+    }
+
+    #[test]
+    fn accesses_are_created_inside_the_store() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store_in(dir.path());
+        let mgr = store.manager();
+
+        let staged = mgr.new_staging_sector_access().unwrap();
+        let sealed = mgr.new_sealed_sector_access().unwrap();
+
+        assert!(Path::new(&staged).starts_with(dir.path().join("staging")));
+        assert!(Path::new(&sealed).starts_with(dir.path().join("sealed")));
+        assert_eq!(0, mgr.num_unsealed_bytes(&staged).unwrap());
+
+        // Backslashes and forward slashes name the same file.
+        let forward_slashed = staged.replace('\\', "/");
+        assert_eq!(0, mgr.num_unsealed_bytes(&forward_slashed).unwrap());
+
+        match mgr.num_unsealed_bytes(&format!("{}\\..\\..\\x", staged)) {
+            Err(SectorManagerErr::CallerError(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn sectors_are_written_truncated_and_read() {
+        let dir = tempfile::tempdir().unwrap();
+        let store = store_in(dir.path());
+        let mgr = store.manager();
+
+        let access = mgr.new_staging_sector_access().unwrap();
+        let data: Vec<u8> = (0..200).map(|i| i as u8).collect();
+
+        assert_eq!(200, mgr.write_and_preprocess(&access, &data).unwrap());
+        assert_eq!(200, mgr.num_unsealed_bytes(&access).unwrap());
+
+        mgr.truncate_unsealed(&access, 100).unwrap();
+        assert_eq!(100, mgr.num_unsealed_bytes(&access).unwrap());
+
+        let mut unsealed = Vec::new();
+        mgr.open_unsealed(&access)
+            .unwrap()
+            .read_to_end(&mut unsealed)
+            .unwrap();
+        assert_eq!(data[..100], unsealed[..]);
+
+        mgr.sync_unsealed(&access).unwrap();
+        mgr.delete_staging_sector_access(&access).unwrap();
+        assert!(!Path::new(&access).exists());
+    }
+
+    #[test]
+    fn files_are_published_over_open_ones() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("replica.tmp");
+        let to = dir.path().join("replica");
+
+        fs::write(&from, &[1; 64]).unwrap();
+        fs::write(&to, &[2; 64]).unwrap();
+
+        // A reader holding the destination open, as a scanner might, only for
+        // a moment.
+        let reader = File::open(&to).unwrap();
+        let released = std::thread::spawn(move || {
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            drop(reader);
+        });
+
+        rename_durably(&from, &to).unwrap();
+        released.join().unwrap();
+
+        assert_eq!(vec![1; 64], fs::read(&to).unwrap());
+        assert!(!from.exists());
+    }
 }
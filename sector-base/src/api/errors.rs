@@ -8,4 +8,19 @@ pub enum SectorManagerErr {
 
     #[fail(display = "receiver error: {}", _0)]
     ReceiverError(String),
+
+    #[fail(display = "stale or unknown sector store handle: {}", _0)]
+    StaleHandle(u64),
+
+    #[fail(display = "staging directory {} overlaps sealed directory {}", staging, sealed)]
+    OverlappingSectorDirs { staging: String, sealed: String },
+
+    #[fail(display = "the sector store is read-only")]
+    ReadOnlyStore,
+
+    #[fail(
+        display = "sector size {} is not a power-of-two number of 32-byte nodes",
+        _0
+    )]
+    UnsupportedSectorSize(u64),
 }
@@ -1,4 +1,6 @@
 pub mod disk_backed_storage;
 pub mod errors;
+pub mod object_backed_storage;
+pub mod registry;
 pub mod sector_store;
 pub mod util;
@@ -0,0 +1,529 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{canonicalize, read_dir, remove_file, File};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+use std::time::Instant;
+
+use crate::api::disk_backed_storage::{
+    check_sector_dirs, new_sector_config, ConcreteSectorStore, ConfiguredStore, DiskManager,
+};
+use crate::api::errors::SectorManagerErr;
+use crate::api::object_backed_storage::s3::Client;
+use crate::api::object_backed_storage::ObjectStoreConfig;
+use crate::api::sector_store::{SectorManager, UnsealedCheckpoint};
+use crate::api::util;
+
+/// Creates a store whose sealed sectors are kept in the object store config
+/// names (see the module's documentation). Fails with CallerError if the
+/// endpoint isn't an `http://` URL, and as check_sector_dirs does if the
+/// staging and cache directories can't be created, or overlap.
+pub fn new_object_sector_store(
+    cs: &ConfiguredStore,
+    config: &ObjectStoreConfig,
+) -> Result<ConcreteSectorStore, SectorManagerErr> {
+    check_sector_dirs(&config.staging_dir, &config.cache_dir)?;
+
+    let client = Client::new(
+        &config.endpoint,
+        &config.bucket,
+        &config.region,
+        &config.access_key_id,
+        &config.secret_access_key,
+        config.part_bytes,
+    )?;
+
+    let manager = ObjectManager {
+        disk: DiskManager::new(config.staging_dir.clone(), config.cache_dir.clone()),
+        client,
+        key_prefix: config.key_prefix.clone(),
+        cache_dir: PathBuf::from(&config.cache_dir),
+        cache_bytes: config.cache_bytes,
+        cache: Default::default(),
+    };
+
+    Ok(ConcreteSectorStore {
+        config: new_sector_config(cs),
+        manager: Box::new(manager),
+    })
+}
+
+pub struct ObjectManager {
+    // Staged sectors, and sealed sectors while they're cached, as a
+    // disk-backed store whose sealed directory is the cache.
+    disk: DiskManager,
+    client: Client,
+    key_prefix: String,
+    cache_dir: PathBuf,
+    cache_bytes: u64,
+    cache: Mutex<Cache>,
+}
+
+#[derive(Default)]
+struct Cache {
+    // The names of sealed sectors provisioned, but not yet published, which
+    // are never evicted.
+    unpublished: HashSet<String>,
+    // When each cached sector was last used. Sectors cached before the
+    // manager was created count as used before any other.
+    last_used: HashMap<String, Instant>,
+}
+
+impl SectorManager for ObjectManager {
+    fn new_sealed_sector_access(&self) -> Result<String, SectorManagerErr> {
+        let access = self.disk.new_sealed_sector_access()?;
+
+        if let Some(name) = self.cached_name(&access) {
+            self.cache().unpublished.insert(name);
+        }
+
+        Ok(access)
+    }
+
+    fn new_staging_sector_access(&self) -> Result<String, SectorManagerErr> {
+        self.disk.new_staging_sector_access()
+    }
+
+    fn prepare_dirs(&self) -> Result<bool, SectorManagerErr> {
+        self.disk.prepare_dirs()
+    }
+
+    fn num_unsealed_bytes(&self, access: &str) -> Result<u64, SectorManagerErr> {
+        self.disk.num_unsealed_bytes(access)
+    }
+
+    fn open_unsealed(&self, access: &str) -> Result<Box<Read>, SectorManagerErr> {
+        self.disk.open_unsealed(access)
+    }
+
+    fn truncate_unsealed(&self, access: &str, size: u64) -> Result<(), SectorManagerErr> {
+        self.disk.truncate_unsealed(access, size)
+    }
+
+    fn checkpoint_unsealed(&self, access: &str) -> Result<UnsealedCheckpoint, SectorManagerErr> {
+        self.disk.checkpoint_unsealed(access)
+    }
+
+    fn restore_unsealed(
+        &self,
+        access: &str,
+        checkpoint: &UnsealedCheckpoint,
+    ) -> Result<(), SectorManagerErr> {
+        self.disk.restore_unsealed(access, checkpoint)
+    }
+
+    fn sync_unsealed(&self, access: &str) -> Result<(), SectorManagerErr> {
+        self.disk.sync_unsealed(access)
+    }
+
+    fn write_and_preprocess(&self, access: &str, data: &[u8]) -> Result<u64, SectorManagerErr> {
+        self.disk.write_and_preprocess(access, data)
+    }
+
+    fn write_and_preprocess_from_reader(
+        &self,
+        access: &str,
+        data: &mut Read,
+    ) -> Result<u64, SectorManagerErr> {
+        self.disk.write_and_preprocess_from_reader(access, data)
+    }
+
+    fn delete_staging_sector_access(&self, access: &str) -> Result<(), SectorManagerErr> {
+        self.disk.delete_staging_sector_access(access)
+    }
+
+    fn allocated_bytes(&self, access: &str) -> Result<u64, SectorManagerErr> {
+        // A sealed sector evicted from the cache takes up no local disk.
+        if self.cached_name(access).is_some() && !Path::new(access).exists() {
+            return Ok(0);
+        }
+
+        self.disk.allocated_bytes(access)
+    }
+
+    fn copy_sector_access(
+        &self,
+        src_access: &str,
+        dest_access: &str,
+    ) -> Result<u64, SectorManagerErr> {
+        if self.cached_name(src_access).is_some() {
+            self.fetch_sealed(src_access)?;
+        }
+
+        self.disk.copy_sector_access(src_access, dest_access)
+    }
+
+    fn read_raw(
+        &self,
+        access: &str,
+        start_offset: u64,
+        num_bytes: u64,
+    ) -> Result<Vec<u8>, SectorManagerErr> {
+        let name = match self.cached_name(access) {
+            Some(name) => name,
+            None => return self.disk.read_raw(access, start_offset, num_bytes),
+        };
+
+        // A sector evicted since it was found cached is read from the object
+        // store after all.
+        if Path::new(access).exists() {
+            if let Ok(bytes) = self.disk.read_raw(access, start_offset, num_bytes) {
+                self.cache().last_used.insert(name, Instant::now());
+                return Ok(bytes);
+            }
+        }
+
+        self.client
+            .get_range(&self.key(&name), start_offset, num_bytes)
+    }
+
+    fn publish_sealed(&self, access: &str) -> Result<(), SectorManagerErr> {
+        let name = self.sealed_name(access)?;
+
+        let mut file = File::open(access)
+            .map_err(|err| SectorManagerErr::CallerError(format!("{:?}", err)))?;
+        self.client.put(&self.key(&name), &mut file)?;
+
+        {
+            let mut cache = self.cache();
+            cache.unpublished.remove(&name);
+            cache.last_used.insert(name, Instant::now());
+        }
+
+        self.evict(None)
+    }
+
+    fn fetch_sealed(&self, access: &str) -> Result<(), SectorManagerErr> {
+        let name = self.sealed_name(access)?;
+
+        if !Path::new(access).exists() {
+            // Fetched beside the sector's access, under a name of its own, so
+            // that nothing is ever found at the access but the whole sector.
+            let partial = self
+                .cache_dir
+                .join(format!(".{}.{}", name, util::rand_alpha_string(8)));
+
+            let fetched = File::create(&partial)
+                .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
+                .and_then(|mut file| {
+                    self.client.get(&self.key(&name), &mut file)?;
+
+                    file.sync_all()
+                        .and_then(|_| util::rename_durably(&partial, access))
+                        .map_err(|err| SectorManagerErr::ReceiverError(format!("{:?}", err)))
+                });
+
+            if fetched.is_err() {
+                let _ = remove_file(&partial);
+            }
+            fetched?;
+        }
+
+        self.cache().last_used.insert(name.clone(), Instant::now());
+
+        self.evict(Some(&name))
+    }
+}
+
+impl ObjectManager {
+    fn cache(&self) -> MutexGuard<Cache> {
+        // The cache's bookkeeping is never left inconsistent by a panic, so a
+        // poisoned lock is safe to keep using.
+        self.cache
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn key(&self, name: &str) -> String {
+        format!("{}{}", self.key_prefix, name)
+    }
+
+    // The name of the sealed sector whose access is access, if it's one: a
+    // file in the cache directory whose name is as util::rand_alpha_string
+    // makes them. Whether the file exists doesn't matter, as an evicted
+    // sector's doesn't.
+    fn cached_name(&self, access: &str) -> Option<String> {
+        let path = Path::new(access);
+        let name = path.file_name()?.to_str()?;
+
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return None;
+        }
+
+        if canonicalize(path.parent()?).ok()? != canonicalize(&self.cache_dir).ok()? {
+            return None;
+        }
+
+        Some(name.to_string())
+    }
+
+    fn sealed_name(&self, access: &str) -> Result<String, SectorManagerErr> {
+        self.cached_name(access).ok_or_else(|| {
+            SectorManagerErr::CallerError(format!("{:?} is not a sealed sector access", access))
+        })
+    }
+
+    // Removes the least recently used sealed sectors from the cache, but keep
+    // and those not yet published, until the cache holds no more than
+    // cache_bytes bytes.
+    fn evict(&self, keep: Option<&str>) -> Result<(), SectorManagerErr> {
+        let mut cache = self.cache();
+        let to_receiver_err = |err| SectorManagerErr::ReceiverError(format!("{:?}", err));
+
+        let mut total = 0;
+        let mut evictable = Vec::new();
+        for entry in read_dir(&self.cache_dir).map_err(to_receiver_err)? {
+            let entry = entry.map_err(to_receiver_err)?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+
+            // Sectors being fetched.
+            if name.starts_with('.') {
+                continue;
+            }
+
+            let len = entry.metadata().map_err(to_receiver_err)?.len();
+            total += len;
+
+            if !cache.unpublished.contains(&name) && keep != Some(name.as_str()) {
+                evictable.push((cache.last_used.get(&name).cloned(), name, len));
+            }
+        }
+
+        // Least recently used first, those never used by this manager before
+        // any other.
+        evictable.sort();
+
+        for (_, name, len) in evictable {
+            if total <= self.cache_bytes {
+                break;
+            }
+
+            remove_file(self.cache_dir.join(&name)).map_err(to_receiver_err)?;
+            cache.last_used.remove(&name);
+            total -= len;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::api::object_backed_storage::mock::MockObjectStore;
+    use crate::api::sector_store::SectorStore;
+    use std::fs;
+    use tempfile::TempDir;
+
+    struct Harness {
+        mock: MockObjectStore,
+        store: ConcreteSectorStore,
+        dir: TempDir,
+    }
+
+    fn harness(cache_bytes: u64, part_bytes: usize) -> Harness {
+        let mock = MockObjectStore::start();
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name).to_str().unwrap().to_owned();
+
+        let store = new_object_sector_store(
+            &ConfiguredStore::Test,
+            &ObjectStoreConfig {
+                endpoint: mock.endpoint(),
+                bucket: "sectors".to_string(),
+                region: "us-east-1".to_string(),
+                access_key_id: "id".to_string(),
+                secret_access_key: "secret".to_string(),
+                key_prefix: "sealed/".to_string(),
+                staging_dir: path("staging"),
+                cache_dir: path("cache"),
+                cache_bytes,
+                part_bytes,
+            },
+        )
+        .unwrap();
+
+        Harness { mock, store, dir }
+    }
+
+    // Provisions a sealed sector holding bytes, as sealing leaves it, and
+    // publishes it.
+    fn publish(h: &Harness, bytes: &[u8]) -> Result<String, SectorManagerErr> {
+        let access = h.store.manager().new_sealed_sector_access()?;
+        fs::write(&access, bytes).unwrap();
+        h.store.manager().publish_sealed(&access)?;
+
+        Ok(access)
+    }
+
+    fn key_of(access: &str) -> String {
+        let name = Path::new(access).file_name().unwrap().to_str().unwrap();
+
+        format!("sealed/{}", name)
+    }
+
+    fn sector_bytes() -> Vec<u8> {
+        (0..1024).map(|i| (i * 7) as u8).collect()
+    }
+
+    #[test]
+    fn sectors_are_uploaded_in_parts() {
+        let h = harness(u64::max_value(), 300);
+        let bytes = sector_bytes();
+
+        let access = publish(&h, &bytes).unwrap();
+
+        assert_eq!(Some(bytes), h.mock.object("sectors", &key_of(&access)));
+
+        // One request to start the upload, four parts (of 300, 300, 300 and
+        // 124 bytes) and one to complete it.
+        let requests = h.mock.requests();
+        assert_eq!(6, requests.len());
+        assert!(requests[0].target.ends_with("?uploads="));
+        for (i, request) in requests[1..5].iter().enumerate() {
+            assert_eq!("PUT", request.method);
+            assert!(request.target.contains(&format!("partNumber={}&", i + 1)));
+        }
+        assert_eq!(0, h.mock.uploads_in_progress());
+
+        // Published sectors stay cached while there's room.
+        assert!(Path::new(&access).exists());
+    }
+
+    #[test]
+    fn evicted_sectors_are_read_by_range() {
+        let h = harness(0, 300);
+        let mgr = h.store.manager();
+        let bytes = sector_bytes();
+
+        let access = publish(&h, &bytes).unwrap();
+        assert!(!Path::new(&access).exists());
+        assert_eq!(0, mgr.allocated_bytes(&access).unwrap());
+
+        let before = h.mock.requests().len();
+        assert_eq!(bytes[100..132], mgr.read_raw(&access, 100, 32).unwrap()[..]);
+        assert_eq!(bytes[992..], mgr.read_raw(&access, 992, 32).unwrap()[..]);
+
+        let requests = h.mock.requests();
+        assert_eq!(2, requests.len() - before);
+        assert_eq!(Some("bytes=100-131".to_string()), requests[before].range);
+        assert_eq!(
+            Some("bytes=992-1023".to_string()),
+            requests[before + 1].range
+        );
+
+        match mgr.read_raw(&access, 1000, 32) {
+            Err(SectorManagerErr::CallerError(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Fetching the sector makes it a file again, which is then read from.
+        mgr.fetch_sealed(&access).unwrap();
+        assert_eq!(bytes, fs::read(&access).unwrap());
+
+        let before = h.mock.requests().len();
+        assert_eq!(bytes[..32], mgr.read_raw(&access, 0, 32).unwrap()[..]);
+        assert_eq!(before, h.mock.requests().len());
+
+        let copy = h.dir.path().join("staging").join("copy");
+        assert_eq!(
+            1024,
+            mgr.copy_sector_access(&access, copy.to_str().unwrap())
+                .unwrap()
+        );
+        assert_eq!(bytes, fs::read(&copy).unwrap());
+    }
+
+    #[test]
+    fn the_least_recently_used_sectors_are_evicted() {
+        let h = harness(3072, 1024);
+        let mgr = h.store.manager();
+
+        let first = publish(&h, &[1; 1024]).unwrap();
+        let second = publish(&h, &[2; 1024]).unwrap();
+        mgr.read_raw(&first, 0, 1).unwrap();
+
+        // A sector being sealed fills the cache, but isn't evicted before it's
+        // published.
+        let sealing = mgr.new_sealed_sector_access().unwrap();
+        fs::write(&sealing, &[3; 1024]).unwrap();
+
+        let fourth = publish(&h, &[4; 1024]).unwrap();
+        assert!(Path::new(&first).exists());
+        assert!(!Path::new(&second).exists());
+        assert!(Path::new(&sealing).exists());
+        assert!(Path::new(&fourth).exists());
+
+        mgr.publish_sealed(&sealing).unwrap();
+        assert!(Path::new(&first).exists());
+        assert!(!Path::new(&fourth).exists());
+    }
+
+    #[test]
+    fn failed_requests_are_retried() {
+        let h = harness(0, 300);
+        let mgr = h.store.manager();
+        let bytes = sector_bytes();
+        let failed = || h.mock.requests().iter().filter(|r| r.status == 500).count();
+
+        // Starting an upload again would start another, so it isn't retried.
+        let access = mgr.new_sealed_sector_access().unwrap();
+        fs::write(&access, &bytes).unwrap();
+        h.mock.fail_next("POST", 1);
+        match mgr.publish_sealed(&access) {
+            Err(SectorManagerErr::ReceiverError(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(1, failed());
+        assert!(Path::new(&access).exists());
+
+        // Parts, and ranges, are.
+        h.mock.fail_next("PUT", 2);
+        mgr.publish_sealed(&access).unwrap();
+        assert_eq!(
+            Some(bytes.clone()),
+            h.mock.object("sectors", &key_of(&access))
+        );
+
+        h.mock.fail_next("GET", 2);
+        assert_eq!(bytes[..10], mgr.read_raw(&access, 0, 10).unwrap()[..]);
+        assert_eq!(5, failed());
+
+        // Failing for longer than the retries wait fails the request, and
+        // abandons the upload.
+        h.mock.fail_next("GET", 100);
+        match mgr.read_raw(&access, 0, 10) {
+            Err(SectorManagerErr::ReceiverError(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        h.mock.fail_next("PUT", 100);
+        let access = mgr.new_sealed_sector_access().unwrap();
+        fs::write(&access, &bytes).unwrap();
+        match mgr.publish_sealed(&access) {
+            Err(SectorManagerErr::ReceiverError(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(None, h.mock.object("sectors", &key_of(&access)));
+        assert_eq!(0, h.mock.uploads_in_progress());
+    }
+
+    #[test]
+    fn staged_sectors_stay_on_disk() {
+        let h = harness(0, 300);
+        let mgr = h.store.manager();
+
+        let access = mgr.new_staging_sector_access().unwrap();
+        assert_eq!(100, mgr.write_and_preprocess(&access, &[5; 100]).unwrap());
+        assert_eq!(100, mgr.num_unsealed_bytes(&access).unwrap());
+        mgr.delete_staging_sector_access(&access).unwrap();
+
+        assert!(h.mock.requests().is_empty());
+
+        match mgr.publish_sealed(&access) {
+            Err(SectorManagerErr::CallerError(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
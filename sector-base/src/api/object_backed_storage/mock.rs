@@ -0,0 +1,346 @@
+//! An in-process stand-in for an S3-compatible object store, for tests of the
+//! object-backed store (here, and in crates which build on it). It serves the
+//! few requests the store makes, over HTTP on a port of the loopback
+//! interface, keeping objects in memory. Signatures aren't checked, but every
+//! request must be signed. Requests are logged, and may be made to fail.
+
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+/// A request the mock store answered, and the status it answered with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LoggedRequest {
+    pub method: String,
+    /// The request's path (`/bucket/key`) and query.
+    pub target: String,
+    /// The Range header, if any.
+    pub range: Option<String>,
+    pub status: u16,
+}
+
+pub struct MockObjectStore {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    stopped: Arc<AtomicBool>,
+}
+
+#[derive(Default)]
+struct State {
+    // By path: `/bucket/key`.
+    objects: HashMap<String, Vec<u8>>,
+    // The parts of each upload in progress, by upload id.
+    uploads: HashMap<String, HashMap<u32, Vec<u8>>>,
+    next_upload_id: u64,
+    // How many of the next requests of each method to answer with 500.
+    failures: HashMap<String, usize>,
+    requests: Vec<LoggedRequest>,
+}
+
+struct Response {
+    status: u16,
+    headers: Vec<(&'static str, String)>,
+    body: Vec<u8>,
+}
+
+impl Response {
+    fn status(status: u16) -> Response {
+        Response {
+            status,
+            headers: Vec::new(),
+            body: Vec::new(),
+        }
+    }
+}
+
+impl MockObjectStore {
+    /// Starts a store, serving until it's dropped.
+    pub fn start() -> MockObjectStore {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind mock object store");
+        let addr = listener.local_addr().unwrap();
+
+        let store = MockObjectStore {
+            addr,
+            state: Default::default(),
+            stopped: Default::default(),
+        };
+
+        let state = store.state.clone();
+        let stopped = store.stopped.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+
+                if let Ok(stream) = stream {
+                    let state = state.clone();
+                    thread::spawn(move || {
+                        let _ = serve(stream, &state);
+                    });
+                }
+            }
+        });
+
+        store
+    }
+
+    /// The store's endpoint, as the object-backed store is configured with.
+    pub fn endpoint(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Answers the next count requests of method (e.g. `PUT`) with 500, as a
+    /// store failing for a moment does.
+    pub fn fail_next(&self, method: &str, count: usize) {
+        self.state
+            .lock()
+            .unwrap()
+            .failures
+            .insert(method.to_string(), count);
+    }
+
+    /// The object stored at key in bucket.
+    pub fn object(&self, bucket: &str, key: &str) -> Option<Vec<u8>> {
+        let path = format!("/{}/{}", bucket, key);
+
+        self.state.lock().unwrap().objects.get(&path).cloned()
+    }
+
+    /// The number of uploads started but neither completed nor aborted.
+    pub fn uploads_in_progress(&self) -> usize {
+        self.state.lock().unwrap().uploads.len()
+    }
+
+    /// Every request answered so far, in the order they were answered.
+    pub fn requests(&self) -> Vec<LoggedRequest> {
+        self.state.lock().unwrap().requests.clone()
+    }
+}
+
+impl Drop for MockObjectStore {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+
+        // Wakes the listener, which is blocked accepting a connection.
+        let _ = TcpStream::connect(self.addr);
+    }
+}
+
+fn serve(stream: TcpStream, state: &Mutex<State>) -> std::io::Result<()> {
+    let mut reader = BufReader::new(&stream);
+
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut words = line.split_whitespace();
+    let method = words.next().unwrap_or("").to_string();
+    let target = words.next().unwrap_or("").to_string();
+
+    let mut headers = HashMap::new();
+    loop {
+        line.clear();
+        reader.read_line(&mut line)?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+
+        if let Some(colon) = header.find(':') {
+            headers.insert(
+                header[..colon].trim().to_lowercase(),
+                header[colon + 1..].trim().to_string(),
+            );
+        }
+    }
+
+    let content_length = headers
+        .get("content-length")
+        .and_then(|len| len.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+
+    let response = {
+        let mut state = state.lock().unwrap();
+
+        let signed = headers.get("authorization").map_or(false, |auth| {
+            auth.starts_with("AWS4-HMAC-SHA256 Credential=")
+        });
+
+        let failures = state.failures.entry(method.clone()).or_insert(0);
+
+        let response = if !signed {
+            Response::status(403)
+        } else if *failures > 0 {
+            *failures -= 1;
+            Response::status(500)
+        } else {
+            let range = headers.get("range").map(String::as_str);
+            answer(&mut state, &method, &target, range, body)
+        };
+
+        state.requests.push(LoggedRequest {
+            method: method.clone(),
+            target,
+            range: headers.get("range").cloned(),
+            status: response.status,
+        });
+
+        response
+    };
+
+    // The response to a HEAD has the length of the object, but no body.
+    let mut message = format!(
+        "HTTP/1.1 {} Mock\r\ncontent-length: {}\r\nconnection: close\r\n",
+        response.status,
+        response.body.len()
+    );
+    for (name, value) in &response.headers {
+        message.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    message.push_str("\r\n");
+
+    let mut writer = &stream;
+    writer.write_all(message.as_bytes())?;
+    if method != "HEAD" {
+        writer.write_all(&response.body)?;
+    }
+
+    writer.flush()
+}
+
+fn answer(
+    state: &mut State,
+    method: &str,
+    target: &str,
+    range: Option<&str>,
+    body: Vec<u8>,
+) -> Response {
+    let (path, query) = match target.find('?') {
+        Some(i) => (&target[..i], &target[i + 1..]),
+        None => (target, ""),
+    };
+
+    let params: HashMap<&str, &str> = query
+        .split('&')
+        .filter(|param| !param.is_empty())
+        .map(|param| match param.find('=') {
+            Some(i) => (&param[..i], &param[i + 1..]),
+            None => (param, ""),
+        })
+        .collect();
+
+    match (method, params.get("uploadId")) {
+        ("GET", None) | ("HEAD", None) => match state.objects.get(path) {
+            None => Response::status(404),
+            Some(object) => match range.and_then(parse_range) {
+                None => Response {
+                    status: 200,
+                    headers: Vec::new(),
+                    body: object.clone(),
+                },
+                Some((first, _)) if first >= object.len() => Response::status(416),
+                Some((first, last)) => {
+                    let last = std::cmp::min(last, object.len() - 1);
+
+                    Response {
+                        status: 206,
+                        headers: vec![(
+                            "content-range",
+                            format!("bytes {}-{}/{}", first, last, object.len()),
+                        )],
+                        body: object[first..=last].to_vec(),
+                    }
+                }
+            },
+        },
+        ("POST", None) if params.contains_key("uploads") => {
+            let upload_id = format!("upload{}", state.next_upload_id);
+            state.next_upload_id += 1;
+            state.uploads.insert(upload_id.clone(), HashMap::new());
+
+            Response {
+                status: 200,
+                headers: Vec::new(),
+                body: format!(
+                    "<InitiateMultipartUploadResult><UploadId>{}</UploadId>\
+                     </InitiateMultipartUploadResult>",
+                    upload_id
+                )
+                .into_bytes(),
+            }
+        }
+        ("PUT", Some(upload_id)) => {
+            let part_number = params.get("partNumber").and_then(|n| n.parse().ok());
+
+            match (state.uploads.get_mut(*upload_id), part_number) {
+                (Some(parts), Some(part_number)) => {
+                    parts.insert(part_number, body);
+
+                    Response {
+                        status: 200,
+                        headers: vec![("etag", format!("\"{}-{}\"", upload_id, part_number))],
+                        body: Vec::new(),
+                    }
+                }
+                _ => Response::status(404),
+            }
+        }
+        ("POST", Some(upload_id)) => {
+            let parts = match state.uploads.get(*upload_id) {
+                Some(parts) => parts,
+                None => return Response::status(404),
+            };
+
+            // The object is made of the parts listed, in the order listed.
+            let listed = String::from_utf8_lossy(&body);
+            let mut object = Vec::new();
+            for number in listed.split("<PartNumber>").skip(1) {
+                let part = number
+                    .split("</PartNumber>")
+                    .next()
+                    .and_then(|n| n.parse::<u32>().ok())
+                    .and_then(|n| parts.get(&n));
+
+                match part {
+                    Some(part) => object.extend_from_slice(part),
+                    None => return Response::status(400),
+                }
+            }
+
+            state.uploads.remove(*upload_id);
+            state.objects.insert(path.to_string(), object);
+
+            Response {
+                status: 200,
+                headers: Vec::new(),
+                body: b"<CompleteMultipartUploadResult></CompleteMultipartUploadResult>".to_vec(),
+            }
+        }
+        ("DELETE", Some(upload_id)) => {
+            state.uploads.remove(*upload_id);
+            Response::status(204)
+        }
+        ("DELETE", None) => {
+            state.objects.remove(path);
+            Response::status(204)
+        }
+        _ => Response::status(400),
+    }
+}
+
+// The first and last byte of a Range header's `bytes=first-last`.
+fn parse_range(range: &str) -> Option<(usize, usize)> {
+    let mut bounds = range.trim_start_matches("bytes=").splitn(2, '-');
+    let first = bounds.next()?.parse().ok()?;
+    let last = bounds.next()?.parse().ok()?;
+
+    if last < first {
+        return None;
+    }
+
+    Some((first, last))
+}
@@ -0,0 +1,125 @@
+//! A sector store whose sealed sectors are kept in an S3-compatible object
+//! store, and whose staged sectors are kept on local disk, as a disk-backed
+//! store keeps them.
+//!
+//! A sealed sector's access is a path in a local cache directory, whose file
+//! name is the key of the sector's object (after a prefix). Sectors are sealed
+//! into the cache, as into a disk-backed store's sealed directory, and then
+//! uploaded by publish_sealed; read_raw reads the ranges it's asked for from
+//! the object store, unless the sector is still cached, and fetch_sealed
+//! fetches a sector into the cache for the code which reads sealed sectors as
+//! files. The cache holds up to a configured number of bytes, evicting the
+//! sectors used least recently.
+//!
+//! The object store is only available with the `object-store` feature. Without
+//! it, init_object_sector_store always fails.
+
+use crate::api::disk_backed_storage::ConfiguredStore;
+use crate::api::registry::SectorStoreHandle;
+use ffi_toolkit::c_str_to_rust_str;
+use libc;
+
+#[cfg(feature = "object-store")]
+mod manager;
+#[cfg(feature = "object-store")]
+pub mod mock;
+#[cfg(feature = "object-store")]
+mod s3;
+
+#[cfg(feature = "object-store")]
+pub use self::manager::{new_object_sector_store, ObjectManager};
+
+/// The size of each part but the last of a sector's upload: S3's minimum.
+pub const DEFAULT_PART_BYTES: usize = 5 << 20;
+
+/// Where an object-backed sector store keeps its sectors.
+#[derive(Debug, Clone)]
+pub struct ObjectStoreConfig {
+    /// The store's URL, e.g. `http://127.0.0.1:9000`. Only plain HTTP is
+    /// spoken, so a store which isn't on a trusted network is to be reached
+    /// through a proxy which speaks HTTPS to it.
+    pub endpoint: String,
+    pub bucket: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    /// Prepended to the name of each sealed sector's object.
+    pub key_prefix: String,
+    pub staging_dir: String,
+    /// Where sealed sectors are sealed into, and cached.
+    pub cache_dir: String,
+    /// How many bytes of sealed sectors the cache keeps, once they're
+    /// uploaded.
+    pub cache_bytes: u64,
+    /// The size of each part but the last of a sector's upload.
+    pub part_bytes: usize,
+}
+
+/// An ObjectStoreConfig, as handed in over FFI. Sectors are uploaded in parts
+/// of DEFAULT_PART_BYTES.
+#[repr(C)]
+pub struct FFIObjectStoreConfig {
+    pub endpoint: *const libc::c_char,
+    pub bucket: *const libc::c_char,
+    pub region: *const libc::c_char,
+    pub access_key_id: *const libc::c_char,
+    pub secret_access_key: *const libc::c_char,
+    pub key_prefix: *const libc::c_char,
+    pub staging_dir_path: *const libc::c_char,
+    pub cache_dir_path: *const libc::c_char,
+    pub cache_bytes: u64,
+}
+
+/// Initializes a SectorStore instance whose sealed sectors are kept in an
+/// S3-compatible object store, and returns a handle to it. See
+/// api/registry.rs for the rules governing handles.
+///
+/// Returns 0, which is never issued as a handle, if the library was built
+/// without the object-store feature, or the endpoint isn't an `http://` URL,
+/// or the directories can't be created, or are the same directory, or one is
+/// inside the other (see check_sector_dirs).
+///
+/// # Arguments
+///
+/// * `cfg_ptr`    - pointer to ConfiguredStore
+/// * `config_ptr` - pointer to where the store keeps its sectors
+#[no_mangle]
+pub unsafe extern "C" fn init_object_sector_store(
+    cfg_ptr: *const ConfiguredStore,
+    config_ptr: *const FFIObjectStoreConfig,
+) -> SectorStoreHandle {
+    let (cs, config) = match (cfg_ptr.as_ref(), config_ptr.as_ref()) {
+        (Some(cs), Some(config)) => (cs, config),
+        _ => return 0,
+    };
+
+    let config = ObjectStoreConfig {
+        endpoint: c_str_to_rust_str(config.endpoint).to_string(),
+        bucket: c_str_to_rust_str(config.bucket).to_string(),
+        region: c_str_to_rust_str(config.region).to_string(),
+        access_key_id: c_str_to_rust_str(config.access_key_id).to_string(),
+        secret_access_key: c_str_to_rust_str(config.secret_access_key).to_string(),
+        key_prefix: c_str_to_rust_str(config.key_prefix).to_string(),
+        staging_dir: c_str_to_rust_str(config.staging_dir_path).to_string(),
+        cache_dir: c_str_to_rust_str(config.cache_dir_path).to_string(),
+        cache_bytes: config.cache_bytes,
+        part_bytes: DEFAULT_PART_BYTES,
+    };
+
+    register(cs, &config)
+}
+
+#[cfg(feature = "object-store")]
+fn register(cs: &ConfiguredStore, config: &ObjectStoreConfig) -> SectorStoreHandle {
+    use crate::api::registry;
+    use std::sync::Arc;
+
+    new_object_sector_store(cs, config)
+        .map(|store| registry::register(Arc::new(store)))
+        .unwrap_or(0)
+}
+
+#[cfg(not(feature = "object-store"))]
+fn register(_: &ConfiguredStore, _: &ObjectStoreConfig) -> SectorStoreHandle {
+    0
+}
@@ -0,0 +1,132 @@
+// Sector stores created over FFI are owned by this registry, and callers refer
+// to them by opaque handle rather than by pointer. The aliasing rules are:
+//
+// * A handle is valid from the call which created it until it is passed to
+//   destroy_storage. Handles are never reused, so a destroyed handle stays
+//   stale for the life of the process.
+// * Any number of threads may use a handle concurrently.
+// * Each operation looks its store up by handle and holds on to it until the
+//   operation finishes. Destroying a handle only removes it from the registry;
+//   a store in use by an operation is freed when the last such operation
+//   finishes, never out from under it.
+// * Looking up a stale (or never issued) handle is an error, not undefined
+//   behavior.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use crate::api::errors::SectorManagerErr;
+use crate::api::sector_store::SectorStore;
+
+pub type SectorStoreHandle = u64;
+
+struct Registry {
+    // Handle 0 is never issued, so callers can use it as a null handle.
+    next_handle: SectorStoreHandle,
+    stores: HashMap<SectorStoreHandle, Arc<SectorStore>>,
+}
+
+lazy_static! {
+    static ref REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+        next_handle: 1,
+        stores: HashMap::new(),
+    });
+}
+
+fn registry() -> ::std::sync::MutexGuard<'static, Registry> {
+    // The registry is never left inconsistent by a panic, so a poisoned lock
+    // is safe to keep using.
+    REGISTRY.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+/// Takes ownership of `store`, returning the handle through which it can be
+/// used.
+pub fn register(store: Arc<SectorStore>) -> SectorStoreHandle {
+    let mut registry = registry();
+
+    let handle = registry.next_handle;
+    registry.next_handle += 1;
+    registry.stores.insert(handle, store);
+
+    handle
+}
+
+/// Returns the store for `handle`. The store stays alive for as long as the
+/// returned Arc does, even if the handle is destroyed in the meantime.
+pub fn get(handle: SectorStoreHandle) -> Result<Arc<SectorStore>, SectorManagerErr> {
+    registry()
+        .stores
+        .get(&handle)
+        .cloned()
+        .ok_or_else(|| SectorManagerErr::StaleHandle(handle))
+}
+
+/// Invalidates `handle`. The store is freed once no operation is using it.
+pub fn remove(handle: SectorStoreHandle) -> Result<(), SectorManagerErr> {
+    registry()
+        .stores
+        .remove(&handle)
+        .map(|_| ())
+        .ok_or_else(|| SectorManagerErr::StaleHandle(handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::api::disk_backed_storage::{new_sector_store, ConfiguredStore};
+
+    fn test_store() -> Arc<SectorStore> {
+        let dir = ::tempfile::tempdir().unwrap();
+        let path = dir.path().to_str().unwrap().to_owned();
+
+        Arc::new(new_sector_store(&ConfiguredStore::Test, path.clone(), path))
+    }
+
+    #[test]
+    fn handles_are_unique_and_never_zero() {
+        let a = register(test_store());
+        let b = register(test_store());
+
+        assert_ne!(0, a);
+        assert_ne!(0, b);
+        assert_ne!(a, b);
+
+        remove(a).unwrap();
+        remove(b).unwrap();
+    }
+
+    #[test]
+    fn removed_handles_are_stale() {
+        let handle = register(test_store());
+
+        assert!(get(handle).is_ok());
+        remove(handle).unwrap();
+
+        match get(handle) {
+            Err(SectorManagerErr::StaleHandle(h)) => assert_eq!(handle, h),
+            Err(err) => panic!("unexpected error: {}", err),
+            Ok(_) => panic!("stale handle still resolved"),
+        }
+
+        assert!(remove(handle).is_err());
+        assert!(get(0).is_err());
+    }
+
+    #[test]
+    fn stores_outlive_removal_while_in_use() {
+        let store = test_store();
+        let weak = Arc::downgrade(&store);
+        let handle = register(store);
+
+        let in_use = get(handle).unwrap();
+        remove(handle).unwrap();
+
+        // Still usable through the operation's reference...
+        assert_eq!(984, in_use.config().max_unsealed_bytes_per_sector());
+
+        // ...and freed once the operation lets go of it.
+        drop(in_use);
+        assert!(weak.upgrade().is_none());
+    }
+}
@@ -1,40 +1,198 @@
 use crate::api::errors::SectorManagerErr;
+use std::io::Read;
 
-pub trait SectorConfig {
+// Stores are shared between threads (e.g. by the SectorBuilder's workers, or
+// by FFI calls made concurrently through a registry handle), so every part of
+// a store must be safe to use from any thread.
+
+/// How the seals of sectors are proven.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProofVariant {
+    /// A Groth16 SNARK of the layered proof of replication. Small and quick to
+    /// verify, but generating it dominates the time taken to seal.
+    Snark,
+    /// The layered proof of replication itself (merkle paths and encoding
+    /// checks). Quick to generate, but far larger than a SNARK, so only fit
+    /// for testing and low-security deployments.
+    Vanilla,
+}
+
+impl Default for ProofVariant {
+    fn default() -> ProofVariant {
+        ProofVariant::Snark
+    }
+}
+
+/// Which of the protocol's parameter sets (graph degrees, layers, challenges and
+/// so on) the seals of sectors are made and proven with. A proof only verifies
+/// with the parameters it was made with, so every proof records its version.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProofVersion {
+    /// Tiny placeholder parameters, kept for old test fixtures.
+    V0Test,
+    /// The parameters of the alpha network.
+    V1Alpha,
+    /// The smallest parameters whose seals are still real ones, for sealing,
+    /// verifying and unsealing on every commit. Never used by a network, so
+    /// its proofs are refused wherever another version is configured.
+    Mini,
+}
+
+impl ProofVersion {
+    /// The version seals are made with unless a config says otherwise.
+    pub const CURRENT: ProofVersion = ProofVersion::V1Alpha;
+}
+
+impl Default for ProofVersion {
+    fn default() -> ProofVersion {
+        ProofVersion::CURRENT
+    }
+}
+
+/// How the merkle trees written to disk while sealing (e.g. into prover
+/// bundles) are compressed. Readers tell from the files themselves, whatever
+/// the config reading them says.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuxCompression {
+    None,
+    /// Snappy's framing format. The trees' digests don't compress, but their
+    /// framing and the nodes of runs of zeros do, at little cost in time.
+    Snappy,
+}
+
+impl Default for AuxCompression {
+    fn default() -> AuxCompression {
+        AuxCompression::None
+    }
+}
+
+pub trait SectorConfig: Send + Sync {
     /// returns the number of bytes that will fit into a sector managed by this store
     fn max_unsealed_bytes_per_sector(&self) -> u64;
 
     /// returns the number of bytes in a sealed sector managed by this store
     fn sector_bytes(&self) -> u64;
+
+    /// returns how the seals of sectors managed by this store are proven
+    fn proof_variant(&self) -> ProofVariant {
+        ProofVariant::Snark
+    }
+
+    /// returns the parameter set the seals of sectors managed by this store are made and
+    /// proven with
+    fn proof_version(&self) -> ProofVersion {
+        ProofVersion::CURRENT
+    }
+
+    /// returns how many nodes of a sector's replica are read back from the file it was written
+    /// to, once sealed, and compared with those sealed in memory, if any
+    fn post_seal_sample_check(&self) -> Option<usize> {
+        None
+    }
+
+    /// returns how the merkle trees written to disk while sealing sectors managed by this store
+    /// are compressed
+    fn aux_compression(&self) -> AuxCompression {
+        AuxCompression::None
+    }
+}
+
+/// What an unsealed sector held at some point, as far as restoring it after later writes goes:
+/// its length in (preprocessed) bytes, and its last byte, which a write rewrites if it's only
+/// partly used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsealedCheckpoint {
+    pub len: u64,
+    pub last_byte: Option<u8>,
 }
 
-pub trait SectorManager {
+pub trait SectorManager: Send + Sync {
     /// provisions a new sealed sector and reports the corresponding access
     fn new_sealed_sector_access(&self) -> Result<String, SectorManagerErr>;
 
     /// provisions a new staging sector and reports the corresponding access
     fn new_staging_sector_access(&self) -> Result<String, SectorManagerErr>;
 
+    /// creates the directories sectors are provisioned in, if need be, and checks that sectors
+    /// can be provisioned there; reports whether any directory had to be created
+    fn prepare_dirs(&self) -> Result<bool, SectorManagerErr>;
+
     /// reports the number of bytes written to an unsealed sector
     fn num_unsealed_bytes(&self, access: &str) -> Result<u64, SectorManagerErr>;
 
+    /// opens the unsealed sector identified by `access` for reading the (preprocessed) bytes
+    /// written to it, as they were written, e.g. decrypted if staged sectors are encrypted
+    fn open_unsealed(&self, access: &str) -> Result<Box<Read>, SectorManagerErr>;
+
     /// sets the number of bytes in an unsealed sector identified by `access`
     fn truncate_unsealed(&self, access: &str, size: u64) -> Result<(), SectorManagerErr>;
 
+    /// reports what the unsealed sector identified by `access` holds now, for restore_unsealed
+    fn checkpoint_unsealed(&self, access: &str) -> Result<UnsealedCheckpoint, SectorManagerErr>;
+
+    /// restores the unsealed sector identified by `access` to what it held at `checkpoint`,
+    /// dropping everything written since, and syncs it to disk
+    fn restore_unsealed(
+        &self,
+        access: &str,
+        checkpoint: &UnsealedCheckpoint,
+    ) -> Result<(), SectorManagerErr>;
+
+    /// syncs everything written to the unsealed sector identified by `access` to disk
+    fn sync_unsealed(&self, access: &str) -> Result<(), SectorManagerErr>;
+
     /// writes `data` to the staging sector identified by `access`, incrementally preprocessing `access`
     fn write_and_preprocess(&self, access: &str, data: &[u8]) -> Result<u64, SectorManagerErr>;
 
+    /// streams everything read from `data` to the staging sector identified by `access`, as
+    /// write_and_preprocess writes it, and reports the number of bytes read; if reading or
+    /// writing fails, `access` is restored to what it held before the call
+    fn write_and_preprocess_from_reader(
+        &self,
+        access: &str,
+        data: &mut Read,
+    ) -> Result<u64, SectorManagerErr>;
+
     fn delete_staging_sector_access(&self, access: &str) -> Result<(), SectorManagerErr>;
 
+    /// reports the number of bytes of disk taken up by the sector identified by `access`, which
+    /// is less than its length where its zeros are left as holes (see io::sparse), and 0 if the
+    /// manager keeps it elsewhere
+    fn allocated_bytes(&self, access: &str) -> Result<u64, SectorManagerErr>;
+
+    /// copies the sector at `src_access` to `dest_access`, which needn't be provisioned by this
+    /// manager but must be in one of its directories (e.g. when taking in a sector from another
+    /// machine), and reports the number of bytes copied; the copy is synced to disk before this
+    /// returns
+    fn copy_sector_access(
+        &self,
+        src_access: &str,
+        dest_access: &str,
+    ) -> Result<u64, SectorManagerErr>;
+
     fn read_raw(
         &self,
         access: &str,
         start_offset: u64,
         num_bytes: u64,
     ) -> Result<Vec<u8>, SectorManagerErr>;
+
+    /// stores the sealed sector just written to `access` wherever the manager keeps sealed
+    /// sectors, e.g. uploads it to an object store; managers which keep sealed sectors where
+    /// they're written have nothing to do
+    fn publish_sealed(&self, _access: &str) -> Result<(), SectorManagerErr> {
+        Ok(())
+    }
+
+    /// makes the sealed sector at `access` readable as a file at `access`, e.g. by fetching it
+    /// into a local cache, before it's read directly rather than through read_raw; managers
+    /// which keep sealed sectors on local disk have nothing to do
+    fn fetch_sealed(&self, _access: &str) -> Result<(), SectorManagerErr> {
+        Ok(())
+    }
 }
 
-pub trait SectorStore {
+pub trait SectorStore: Send + Sync {
     fn config(&self) -> &SectorConfig;
     fn manager(&self) -> &SectorManager;
 }
@@ -1,4 +1,7 @@
 use rand::{thread_rng, Rng};
+use std::fs::{self, File};
+use std::io;
+use std::path::Path;
 
 // creates a string of size len containing uppercase alpha-chars
 pub fn rand_alpha_string(len: u8) -> String {
@@ -12,3 +15,79 @@ pub fn rand_alpha_string(len: u8) -> String {
 
     str
 }
+
+// Renames from over whatever is at to, and makes the rename durable. On unix,
+// the rename is atomic, and is synced by syncing to's directory. On Windows,
+// it's done by MoveFileEx with MOVEFILE_REPLACE_EXISTING, which fails with
+// access denied while another process (e.g. a virus scanner or the search
+// indexer) briefly holds to open, so it's retried for a while; NTFS journals
+// the rename itself, and a directory can't be opened to be synced.
+pub fn rename_durably<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    rename_over(from.as_ref(), to.as_ref())?;
+
+    match to.as_ref().parent() {
+        Some(dir) if dir != Path::new("") => sync_dir(dir),
+        _ => sync_dir(Path::new(".")),
+    }
+}
+
+#[cfg(not(windows))]
+fn rename_over(from: &Path, to: &Path) -> io::Result<()> {
+    fs::rename(from, to)
+}
+
+#[cfg(windows)]
+fn rename_over(from: &Path, to: &Path) -> io::Result<()> {
+    use std::thread;
+    use std::time::Duration;
+
+    let mut delay = Duration::from_millis(10);
+    for _ in 0..8 {
+        match fs::rename(from, to) {
+            Err(ref err) if err.kind() == io::ErrorKind::PermissionDenied => {
+                thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+
+    fs::rename(from, to)
+}
+
+// Syncs the directory at dir, so that the entries last created, renamed or
+// removed in it survive a crash. A no-op on Windows (see rename_durably).
+#[cfg(not(windows))]
+pub fn sync_dir(dir: &Path) -> io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(windows)]
+pub fn sync_dir(_dir: &Path) -> io::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renames_over_existing_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let from = dir.path().join("from");
+        let to = dir.path().join("to");
+
+        fs::write(&from, b"new").unwrap();
+        fs::write(&to, b"old").unwrap();
+        rename_durably(&from, &to).unwrap();
+
+        assert_eq!(b"new".to_vec(), fs::read(&to).unwrap());
+        assert!(!from.exists());
+
+        // Including onto a path with nothing at it.
+        fs::write(&from, b"newer").unwrap();
+        fs::remove_file(&to).unwrap();
+        rename_durably(&from, &to).unwrap();
+        assert_eq!(b"newer".to_vec(), fs::read(&to).unwrap());
+    }
+}
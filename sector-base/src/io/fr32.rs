@@ -2,6 +2,7 @@ use std::cmp::min;
 use std::io::{self, Read, Seek, SeekFrom, Write};
 
 use bitvec::{self, BitVec, LittleEndian};
+use storage_proofs::fr32::{self, FR_PADDED_BITS, FR_UNPADDED_BITS};
 
 /** PaddingMap represents a mapping between data and its padded equivalent.
 
@@ -165,12 +166,13 @@ pub struct PaddingMap {
 // data bits in the element, etc.) to avoid recalculating them each time across
 // different (un)pad calls.
 
-// This is the padding map corresponding to Fr32.
+// This is the padding map corresponding to Fr32, whose bits storage_proofs::fr32
+// defines for both the padding and the conversions to and from Frs.
 // Most of the code in this module is general-purpose and could move elsewhere.
 // The application-specific wrappers which implicitly use Fr32 embed the FR32_PADDING_MAP.
 pub const FR32_PADDING_MAP: PaddingMap = PaddingMap {
-    data_bits: 254,
-    element_bits: 256,
+    data_bits: FR_UNPADDED_BITS,
+    element_bits: FR_PADDED_BITS,
 };
 
 pub type BitVecLEu8 = BitVec<LittleEndian, u8>;
@@ -611,11 +613,11 @@ pub fn extract_bits_and_shift(
 
     // (3).
     if new_offset != 0 {
-        clear_right_bits(output.first_mut().unwrap(), new_offset);
+        fr32::clear_right_bits(output.first_mut().unwrap(), new_offset);
     }
     let end_offset = (new_offset + num_bits) % 8;
     if end_offset != 0 {
-        clear_left_bits(output.last_mut().unwrap(), end_offset);
+        fr32::clear_left_bits(output.last_mut().unwrap(), end_offset);
     }
 
     output
@@ -623,37 +625,157 @@ pub fn extract_bits_and_shift(
 
 // Set to zero all the bits to the "left" of the `offset` including
 // it, that is, [MSB; `offset`].
+#[deprecated(note = "use storage_proofs::fr32::clear_left_bits")]
 #[inline]
 pub fn clear_left_bits(byte: &mut u8, offset: usize) {
-    *(byte) &= (1 << offset) - 1
+    fr32::clear_left_bits(byte, offset)
 }
 
 // Set to zero all the bits to the "right" of the `offset` excluding
 // it, that is, (`offset`; LSB].
+#[deprecated(note = "use storage_proofs::fr32::clear_right_bits")]
 #[inline]
 pub fn clear_right_bits(byte: &mut u8, offset: usize) {
-    *(byte) &= !((1 << offset) - 1)
+    fr32::clear_right_bits(byte, offset)
 }
 
+// In order to optimize alignment in the common case of writing from an aligned start,
+// we should make the chunk a multiple of 127 (4 full elements, see `PaddingMap#alignment`).
+// The multiplier was hand-tuned to do reasonably well in the benchmarks.
+const PADDING_CHUNK_SIZE: usize = 127 * 1000;
+
+const FATAL_NOMEMWRITE: &str = "[fr32] could not write to memory";
+
 pub fn write_padded<W: ?Sized>(source: &[u8], target: &mut W) -> io::Result<usize>
 where
     W: Read + Write + Seek,
 {
-    // In order to optimize alignment in the common case of writing from an aligned start,
-    // we should make the chunk a multiple of 127 (4 full elements, see `PaddingMap#alignment`).
-    // n was hand-tuned to do reasonably well in the benchmarks.
-    let n = 1000;
-    let chunk_size = 127 * n;
-
     let mut written = 0;
 
-    for chunk in source.chunks(chunk_size) {
+    for chunk in source.chunks(PADDING_CHUNK_SIZE) {
         written += write_padded_aux(&FR32_PADDING_MAP, chunk, target)?;
     }
 
     Ok(written)
 }
 
+/// Pads everything read from `source` into `target`, as `write_padded` does,
+/// holding no more than one chunk of the source in memory at a time. Returns
+/// the number of bytes read from `source`. If reading or writing fails, some
+/// of the source may already have been written to `target`.
+pub fn write_padded_from_reader<R: ?Sized, W: ?Sized>(
+    source: &mut R,
+    target: &mut W,
+) -> io::Result<u64>
+where
+    R: Read,
+    W: Read + Write + Seek,
+{
+    let mut chunk = vec![0; PADDING_CHUNK_SIZE];
+    let mut written = 0;
+
+    loop {
+        let n = fill(source, &mut chunk)?;
+
+        if n > 0 {
+            written += write_padded_aux(&FR32_PADDING_MAP, &chunk[..n], target)? as u64;
+        }
+
+        // The source is exhausted once it can't fill a chunk.
+        if n < chunk.len() {
+            return Ok(written);
+        }
+    }
+}
+
+// Reads from `source` until `buf` is full or `source` is exhausted, returning
+// the number of bytes read.
+fn fill<R: ?Sized + Read>(source: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buf.len() {
+        match source.read(&mut buf[filled..]) {
+            Ok(0) => break,
+            Ok(n) => filled += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+
+    Ok(filled)
+}
+
+/// User bytes packed into Fr32 elements, 254 bits to an element, exactly as
+/// `write_padded` writes them, along with the number of bytes packed. Every
+/// element of the packed bytes is a valid Fr32, whatever the user bytes were:
+/// the last, once zero-extended to 32 bytes, if it's incomplete.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PackedFr32 {
+    bytes: Vec<u8>,
+    original_len: usize,
+}
+
+impl PackedFr32 {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// The number of user bytes packed.
+    pub fn original_len(&self) -> usize {
+        self.original_len
+    }
+}
+
+/// Packs arbitrary user bytes into Fr32 elements in memory. Everything which
+/// turns user bytes into node data, rather than padding them into a file with
+/// `write_padded`, packs them with this (or an `Fr32Packer`).
+pub fn pack_user_bytes(input: &[u8]) -> PackedFr32 {
+    let mut packer = Fr32Packer::new();
+    packer.push(input);
+
+    packer.finish()
+}
+
+/// Recovers the original_len user bytes packed into packed, or as many of
+/// them as packed holds.
+pub fn unpack_user_bytes(packed: &[u8], original_len: usize) -> Vec<u8> {
+    let mut unpacked = Vec::with_capacity(original_len);
+    write_unpadded(packed, &mut unpacked, 0, original_len).expect(FATAL_NOMEMWRITE);
+
+    unpacked
+}
+
+/// Packs user bytes given a slice at a time, as `pack_user_bytes` packs them
+/// given all at once.
+#[derive(Debug, Default)]
+pub struct Fr32Packer {
+    packed: io::Cursor<Vec<u8>>,
+    original_len: usize,
+}
+
+impl Fr32Packer {
+    pub fn new() -> Fr32Packer {
+        Default::default()
+    }
+
+    /// Packs the bytes after those pushed before them.
+    pub fn push(&mut self, bytes: &[u8]) {
+        write_padded(bytes, &mut self.packed).expect(FATAL_NOMEMWRITE);
+        self.original_len += bytes.len();
+    }
+
+    pub fn finish(self) -> PackedFr32 {
+        PackedFr32 {
+            bytes: self.packed.into_inner(),
+            original_len: self.original_len,
+        }
+    }
+}
+
 /** Padding process.
 
 Read a `source` of raw byte-aligned data, pad it in a bit stream and
@@ -691,9 +813,9 @@ where
     let (padded_bytes, _, padded_bits) = padding_map.target_offsets(target)?;
 
     // (1): Overwrite the extra bits (if any): we actually don't write in-place, we
-    // remove the last byte and extract its valid bits to `last_bits` to be later
-    // rewritten with new data taken from the `source`.
-    let mut last_bits = BitVecLEu8::new();
+    // remove the last byte and keep its valid bits in `head` to be later rewritten
+    // with new data taken from the `source`.
+    let mut head: Vec<u8> = Vec::new();
     if !padded_bits.is_byte_aligned() {
         // Read the last incomplete byte and left the `target` positioned to overwrite
         // it in the next `write_all`.
@@ -704,11 +826,11 @@ where
         // TODO: Can we use a relative `SeekFrom::End` seek to avoid
         // setting our absolute `padded_bytes` position?
 
-        // Extract the valid bit from the last byte (the `bits` fraction
-        // of the `padded_bits` bit stream that doesn't complete a byte).
-        let mut last_byte_as_bitvec = BitVecLEu8::from(&last_byte[..]);
-        last_byte_as_bitvec.truncate(padded_bits.bits);
-        last_bits.extend(last_byte_as_bitvec);
+        // Keep the valid bits of the last byte (the `bits` fraction of the
+        // `padded_bits` bit stream that doesn't complete a byte), which are
+        // its least significant ones.
+        fr32::clear_left_bits(&mut last_byte[0], padded_bits.bits);
+        head.push(last_byte[0]);
     };
 
     // (2): Fill the current data unit adding `missing_data_bits` from the
@@ -727,18 +849,21 @@ where
     // TODO: What happens if we were already at the element boundary?
     // Would this code write 0 (`data_bits_to_write`) bits and then
     // add an extra padding?
-    last_bits.extend(
-        BitVecLEu8::from(source)
-            .into_iter()
-            .take(data_bits_to_write),
-    );
+    if data_bits_to_write > 0 {
+        // Place the data bits right after the valid bits of the last byte, the
+        // bits beyond them are left at zero and so become the padding bits.
+        let mut data = extract_bits_and_shift(source, 0, data_bits_to_write, padded_bits.bits);
+
+        match head.last_mut() {
+            Some(last_byte) => {
+                *last_byte |= data[0];
+                head.extend_from_slice(&data[1..]);
+            }
+            None => head.append(&mut data),
+        }
+    }
 
-    target.write_all(&last_bits.into_boxed_slice())?;
-    // The `into_boxed_slice` conversion will byte-align the bit stream and implicitly
-    // add the padding bits (that by definition are the bits necessary to reach the byte
-    // boundary).
-    // TODO: Optimization: Remove the use of this `BitVecLEu8`. (Low priority, it's
-    // a corner case that doesn't take too much of the overall operations.)
+    target.write_all(&head)?;
 
     // Now we are at the element boundary, write entire chunks of full data
     // units with its padding.
@@ -967,10 +1092,11 @@ where
 mod tests {
     use super::*;
     use itertools::Itertools;
-    use pairing::bls12_381::Bls12;
+    use pairing::bls12_381::{Bls12, Fr};
+    use pairing::{PrimeField, PrimeFieldRepr};
     use rand::{Rng, SeedableRng, XorShiftRng};
     use std::io::Cursor;
-    use storage_proofs::fr32::bytes_into_fr;
+    use storage_proofs::fr32::{bytes_into_fr, frs_into_bytes};
 
     #[test]
     fn test_position() {
@@ -1288,6 +1414,210 @@ mod tests {
         }
     }
 
+    // Reads at most `max` bytes at a time.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        max: usize,
+    }
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let n = min(self.max, buf.len());
+            (&mut self.data).take(n as u64).read(buf)
+        }
+    }
+
+    // `write_padded_from_reader` behind an unaligned `write_padded`, reading
+    // the source a few bytes at a time, matches a single `write_padded`.
+    #[test]
+    fn test_write_padded_from_reader() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let data: Vec<u8> = (0..PADDING_CHUNK_SIZE * 2 + 300).map(|_| rng.gen()).collect();
+
+        let mut expected = Cursor::new(Vec::new());
+        write_padded(&data, &mut expected).unwrap();
+
+        for (split, max) in &[(0, 7), (13, 1000), (127, PADDING_CHUNK_SIZE + 1)] {
+            let mut streamed = Cursor::new(Vec::new());
+            write_padded(&data[..*split], &mut streamed).unwrap();
+
+            let mut source = Trickle {
+                data: &data[*split..],
+                max: *max,
+            };
+            let read = write_padded_from_reader(&mut source, &mut streamed).unwrap();
+
+            assert_eq!((data.len() - split) as u64, read);
+            assert_eq!(expected.get_ref(), streamed.get_ref());
+        }
+    }
+
+    // Padded layouts written out by hand, rather than by another padder, so that
+    // a padder leaking the byte order of the host would fail them on big-endian
+    // hosts. Data bits fill each byte from its least significant bit up.
+    #[test]
+    fn test_write_padded_known_bytes() {
+        let mut ones = vec![0xff; 31];
+        ones.extend_from_slice(&[0b0011_1111, 0b0000_0011]);
+
+        // The two most significant bits of the 32nd byte move to the next element.
+        let mut high_bits = vec![0; 32];
+        high_bits.push(0b0000_0011);
+
+        let mut high_bits_source = vec![0; 31];
+        high_bits_source.push(0b1100_0000);
+
+        for (source, expected) in &[(vec![0xff; 32], ones), (high_bits_source, high_bits)] {
+            let mut cursor = Cursor::new(Vec::new());
+            write_padded(source, &mut cursor).unwrap();
+
+            assert_eq!(expected, cursor.get_ref());
+        }
+    }
+
+    // As above, appending to a padded layout which ends mid-byte.
+    #[test]
+    fn test_write_padded_unaligned_known_bytes() {
+        let mut cursor = Cursor::new(Vec::new());
+        write_padded(&[0xff; 32], &mut cursor).unwrap();
+        write_padded(&[0b1010_0101], &mut cursor).unwrap();
+
+        let mut expected = vec![0xff; 31];
+        expected.extend_from_slice(&[0b0011_1111, 0b1001_0111, 0b0000_0010]);
+        assert_eq!(&expected, cursor.get_ref());
+
+        let mut unpadded = Vec::new();
+        write_unpadded(cursor.get_ref(), &mut unpadded, 0, 33).unwrap();
+
+        let mut source = vec![0xff; 32];
+        source.push(0b1010_0101);
+        assert_eq!(source, unpadded);
+    }
+
+    // Every full element `write_padded` writes out of random data of random
+    // lengths is an Fr32 which `bytes_into_fr` accepts.
+    #[test]
+    fn test_padded_elements_are_fr32() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+        for _ in 0..50 {
+            let len = rng.gen_range(1, 1000);
+            let data: Vec<u8> = (0..len).map(|_| rng.gen()).collect();
+
+            let mut cursor = Cursor::new(Vec::new());
+            write_padded(&data, &mut cursor).unwrap();
+            let padded = cursor.into_inner();
+
+            for chunk in padded.chunks(32).filter(|chunk| chunk.len() == 32) {
+                assert!(bytes_into_fr::<Bls12>(chunk).is_ok(), "{:?}", chunk);
+            }
+        }
+    }
+
+    // Random user bytes, of random lengths, whose high bits are set as often
+    // as not, and all-ones bytes, the worst case for an element's top bits.
+    fn user_bytes(rng: &mut XorShiftRng) -> Vec<Vec<u8>> {
+        let mut inputs: Vec<Vec<u8>> = (0..50)
+            .map(|_| {
+                let len = rng.gen_range(0, 2000);
+                (0..len).map(|_| rng.gen()).collect()
+            })
+            .collect();
+        inputs.extend(vec![
+            vec![],
+            vec![0xff; 31],
+            vec![0xff; 32],
+            vec![0xff; 1000],
+        ]);
+
+        inputs
+    }
+
+    #[test]
+    fn test_packed_user_bytes_unpack() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+        for input in user_bytes(rng) {
+            let packed = pack_user_bytes(&input);
+            assert_eq!(input.len(), packed.original_len());
+            assert_eq!(padded_bytes(input.len()), packed.as_bytes().len());
+            assert_eq!(
+                input,
+                unpack_user_bytes(packed.as_bytes(), packed.original_len())
+            );
+
+            // Packing a slice at a time packs the same bytes.
+            let split = rng.gen_range(0, input.len() + 1);
+            let mut packer = Fr32Packer::new();
+            packer.push(&input[..split]);
+            packer.push(&input[split..]);
+            assert_eq!(packed, packer.finish());
+        }
+    }
+
+    #[test]
+    fn test_packed_user_bytes_are_fr32() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+        for input in user_bytes(rng) {
+            for chunk in pack_user_bytes(&input).as_bytes().chunks(32) {
+                let mut element = [0; 32];
+                element[..chunk.len()].copy_from_slice(chunk);
+
+                assert!(bytes_into_fr::<Bls12>(&element).is_ok(), "{:?}", chunk);
+            }
+        }
+    }
+
+    // Packing in memory writes what padding into a file does.
+    #[test]
+    fn test_packed_user_bytes_match_padded_files() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+        for input in user_bytes(rng) {
+            let mut file = tempfile::tempfile().unwrap();
+            write_padded(&input, &mut file).unwrap();
+
+            let mut padded = Vec::new();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.read_to_end(&mut padded).unwrap();
+
+            assert_eq!(padded, pack_user_bytes(&input).into_bytes());
+        }
+    }
+
+    // Unpadding the bytes of random Frs and padding them again gives back each
+    // Fr's bytes with only the bits of it which aren't data cleared: those of
+    // any Fr which doesn't fit in `FR_UNPADDED_BITS`.
+    #[test]
+    fn test_fr_bytes_unpad_and_pad_consistently() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+        // A multiple of 4 elements unpads to whole bytes.
+        let frs: Vec<Fr> = (0..64).map(|_| rng.gen()).collect();
+        let bytes = frs_into_bytes::<Bls12>(&frs);
+
+        let mut unpadded = Vec::new();
+        let len = unpadded_bytes(bytes.len() as u64) as usize;
+        assert_eq!(len, write_unpadded(&bytes, &mut unpadded, 0, len).unwrap());
+
+        let mut repadded = Cursor::new(Vec::new());
+        write_padded(&unpadded, &mut repadded).unwrap();
+
+        let mut expected = bytes.clone();
+        for chunk in expected.chunks_mut(32) {
+            fr32::trim_to_fr32(chunk);
+        }
+        assert_eq!(&expected, repadded.get_ref());
+
+        // Frs which fit in the data bits of an element survive unchanged.
+        for (fr, chunk) in frs.iter().zip(repadded.get_ref().chunks(32)) {
+            if fr.into_repr().num_bits() as usize <= FR_UNPADDED_BITS {
+                assert_eq!(*fr, bytes_into_fr::<Bls12>(chunk).unwrap());
+            }
+        }
+    }
+
     // TODO: Add a test that drops the last part of an element and tries to recover
     // the rest of the data (may already be present in some form in the above tests).
 }
@@ -1 +1,6 @@
 pub mod fr32;
+pub mod sparse;
+pub mod staging_encryption;
+pub mod trailer;
+
+mod xchacha20poly1305;
@@ -0,0 +1,152 @@
+//! Writing staged sectors sparsely. A staged sector's zero padding (and the
+//! zeros of pieces which are mostly empty) needn't take up any disk: where
+//! whole blocks of zeros are written past the end of a file, the file is
+//! extended over them instead, leaving holes which read back as zeros. File
+//! systems which don't keep holes fill them with zeros themselves, so the file
+//! reads the same either way.
+
+use std::cmp;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+
+/// The granularity of holes: the block size of common file systems. Only
+/// whole blocks of zeros are left as holes, as a block holding anything else
+/// is allocated anyway.
+pub const HOLE_BYTES: u64 = 4096;
+
+/// Writes buf at the file's position, as write_all does, but leaves the
+/// blocks past the end of the file which buf only has zeros for as holes.
+/// What buf overwrites of the file's contents is always written.
+pub fn write_sparse(file: &mut File, buf: &[u8]) -> io::Result<()> {
+    let start = file.seek(SeekFrom::Current(0))?;
+    let end = start + buf.len() as u64;
+    let len = file.metadata()?.len();
+
+    let overwritten = cmp::min(buf.len() as u64, len.saturating_sub(start)) as usize;
+    file.write_all(&buf[..overwritten])?;
+
+    // The bytes still to be written, which begin at offset in the file.
+    let mut offset = start + overwritten as u64;
+    let mut pending = &buf[overwritten..];
+
+    while !pending.is_empty() {
+        let block_end = cmp::min(end, (offset / HOLE_BYTES + 1) * HOLE_BYTES);
+        let (block, rest) = pending.split_at((block_end - offset) as usize);
+
+        if block.iter().all(|b| *b == 0) {
+            file.seek(SeekFrom::Start(block_end))?;
+        } else {
+            file.write_all(block)?;
+        }
+
+        offset = block_end;
+        pending = rest;
+    }
+
+    // A trailing hole is only made by extending the file over it.
+    if file.metadata()?.len() < end {
+        file.set_len(end)?;
+    }
+
+    Ok(())
+}
+
+/// The bytes the file system has allocated to the file at path, which are
+/// fewer than its length if it has holes. Where allocation isn't known (i.e.
+/// on Windows), the file's length.
+pub fn allocated_bytes(path: &Path) -> io::Result<u64> {
+    fs::metadata(path).map(|metadata| allocated_bytes_of(&metadata))
+}
+
+#[cfg(unix)]
+fn allocated_bytes_of(metadata: &Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+
+    // st_blocks counts 512-byte units, whatever the file system's block size.
+    metadata.blocks() * 512
+}
+
+#[cfg(not(unix))]
+fn allocated_bytes_of(metadata: &Metadata) -> u64 {
+    metadata.len()
+}
+
+/// Reports whether the file system dir is on leaves holes in files, so that
+/// tests of sparse files can be skipped where it doesn't.
+pub fn holes_supported(dir: &Path) -> io::Result<bool> {
+    let path = dir.join(".holes-probe");
+
+    let supported = File::create(&path)
+        .and_then(|file| file.set_len(16 * HOLE_BYTES))
+        .and_then(|_| allocated_bytes(&path))
+        .map(|allocated| allocated < 16 * HOLE_BYTES);
+
+    let _ = fs::remove_file(&path);
+
+    supported
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs::OpenOptions;
+
+    // Writes buf sparsely at offset of the file at path, and at offset of
+    // expected, and checks that the file then holds what expected does.
+    fn write_at(path: &Path, expected: &mut Vec<u8>, offset: usize, buf: &[u8]) {
+        let mut file = OpenOptions::new().write(true).open(path).unwrap();
+        file.seek(SeekFrom::Start(offset as u64)).unwrap();
+        write_sparse(&mut file, buf).unwrap();
+
+        if expected.len() < offset + buf.len() {
+            expected.resize(offset + buf.len(), 0);
+        }
+        expected[offset..offset + buf.len()].copy_from_slice(buf);
+
+        assert_eq!(*expected, fs::read(path).unwrap());
+    }
+
+    #[test]
+    fn files_read_back_as_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("sparse");
+        File::create(&path).unwrap();
+        let mut expected = Vec::new();
+
+        write_at(&path, &mut expected, 0, &[1; 100]);
+        write_at(&path, &mut expected, 100, &[0; 3 * HOLE_BYTES as usize]);
+        write_at(&path, &mut expected, 5000, &[2; 10]);
+
+        // Zeros over the file's contents are written.
+        write_at(&path, &mut expected, 0, &[0; 50]);
+        write_at(&path, &mut expected, 4990, &[0; 20]);
+
+        // Blocks of zeros and of data in one write.
+        let mut mixed = vec![0; 5 * HOLE_BYTES as usize];
+        mixed[3 * HOLE_BYTES as usize + 7] = 3;
+        let end = expected.len();
+        write_at(&path, &mut expected, end, &mixed);
+        let end = expected.len();
+        write_at(&path, &mut expected, end + 7, &[0; 9]);
+    }
+
+    #[test]
+    fn zero_tails_are_left_as_holes() {
+        let dir = tempfile::tempdir().unwrap();
+        if !holes_supported(dir.path()).unwrap() {
+            return;
+        }
+
+        let path = dir.path().join("sparse");
+        let mut file = File::create(&path).unwrap();
+
+        let mut buf = vec![7; 100];
+        buf.resize(1 << 20, 0);
+        write_sparse(&mut file, &buf).unwrap();
+
+        assert_eq!(buf, fs::read(&path).unwrap());
+        assert!(allocated_bytes(&path).unwrap() < 1 << 20);
+    }
+}
@@ -0,0 +1,650 @@
+// Staged sectors hold clients' data, preprocessed but otherwise as it was
+// given, for as long as they wait to be sealed, so a store may be configured to
+// keep them encrypted at rest (see StagingEncryption). Sealed sectors are never
+// encrypted: replication already transforms their data.
+//
+// An encrypted staged file is a header, followed by the (preprocessed) bytes
+// written to the sector in chunks of the header's chunk size, each sealed on
+// its own with XChaCha20Poly1305 under a random nonce:
+//
+//   | header (16) | nonce (24) | ciphertext (chunk size) | tag (16) | ...
+//
+// Every chunk but the last is full, so both where a chunk starts and how many
+// bytes the file holds follow from the length of the file alone. The header
+// records the version of the format, the algorithm, the chunk size and the id
+// of the key the chunks are sealed with, so that a file is never opened with a
+// key other than its own; the key itself is never written anywhere. Each chunk
+// is authenticated along with the header and its index, so that neither can
+// be altered, nor chunks moved around, without decryption failing.
+
+use crate::api::errors::SectorManagerErr;
+use crate::io::xchacha20poly1305::{self, XChaCha20Poly1305};
+use rand::{OsRng, Rng};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::ptr;
+
+/// The length of a staging key.
+pub const STAGING_KEY_BYTES: usize = 32;
+
+/// The version of the format of the encrypted staged files written.
+pub const FORMAT_VERSION: u8 = 1;
+
+/// The number of (preprocessed) bytes sealed in each chunk of the encrypted
+/// staged files written.
+pub const DEFAULT_CHUNK_BYTES: u32 = 64 << 10;
+
+const MAGIC: &[u8; 4] = b"FSTE";
+
+const ALGORITHM_XCHACHA20POLY1305: u8 = 1;
+
+// The magic, the version, the algorithm and two reserved (zero) bytes, then
+// the key id and the chunk size, u32s in little-endian order.
+const HEADER_BYTES: usize = 16;
+
+const NONCE_BYTES: usize = xchacha20poly1305::NONCE_BYTES;
+
+const TAG_BYTES: usize = xchacha20poly1305::TAG_BYTES;
+
+const CHUNK_OVERHEAD: u64 = (NONCE_BYTES + TAG_BYTES) as u64;
+
+const LOADED: &str = "chunk was just loaded";
+
+/// A key staged sectors are encrypted with, and the id recorded for it in the
+/// files it encrypts. The key is zeroed once dropped, and never printed.
+pub struct StagingKey {
+    id: u32,
+    bytes: [u8; STAGING_KEY_BYTES],
+}
+
+impl StagingKey {
+    pub fn from_bytes(id: u32, bytes: [u8; STAGING_KEY_BYTES]) -> StagingKey {
+        StagingKey { id, bytes }
+    }
+
+    /// Fetches the key with the given id from key_for (e.g. a key management
+    /// service), failing with a CallerError should key_for fail.
+    pub fn from_callback<F>(id: u32, key_for: F) -> Result<StagingKey, SectorManagerErr>
+    where
+        F: FnOnce(u32) -> Result<[u8; STAGING_KEY_BYTES], String>,
+    {
+        key_for(id)
+            .map(|bytes| StagingKey::from_bytes(id, bytes))
+            .map_err(|err| {
+                SectorManagerErr::CallerError(format!("staging key {} is unavailable: {}", id, err))
+            })
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    fn cipher(&self) -> XChaCha20Poly1305 {
+        XChaCha20Poly1305::new(&self.bytes)
+    }
+}
+
+impl fmt::Debug for StagingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StagingKey").field("id", &self.id).finish()
+    }
+}
+
+impl Drop for StagingKey {
+    fn drop(&mut self) {
+        for byte in self.bytes.iter_mut() {
+            // Volatile, so that the zeroing isn't optimized away.
+            unsafe { ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+/// How a store keeps its staged sectors on disk. A store's staged sectors must
+/// all be kept the same way: a store never opens a staged sector it didn't
+/// write the way it's configured to.
+#[derive(Debug)]
+pub enum StagingEncryption {
+    /// In plaintext.
+    None,
+    /// Encrypted with XChaCha20Poly1305 under the key, as described above.
+    XChaCha20Poly1305(StagingKey),
+}
+
+impl Default for StagingEncryption {
+    fn default() -> StagingEncryption {
+        StagingEncryption::None
+    }
+}
+
+/// An encrypted staged file, through which the bytes it holds are read,
+/// written and seeked over as those of a plaintext staged file are. Writes are
+/// buffered a chunk at a time, and the chunk written last is sealed when the
+/// file is flushed or dropped: flush it to learn whether sealing failed.
+pub struct EncryptedStagedFile {
+    file: File,
+    cipher: XChaCha20Poly1305,
+    rng: OsRng,
+    header: [u8; HEADER_BYTES],
+    chunk_bytes: u64,
+    len: u64,
+    pos: u64,
+    chunk: Option<Chunk>,
+}
+
+// The plaintext of a chunk, and whether it has been written to since it was
+// last sealed.
+struct Chunk {
+    index: u64,
+    bytes: Vec<u8>,
+    dirty: bool,
+}
+
+impl EncryptedStagedFile {
+    /// Writes the header of an empty staged file, encrypted with key, over
+    /// whatever file holds.
+    pub fn create(file: File, key: &StagingKey) -> io::Result<EncryptedStagedFile> {
+        EncryptedStagedFile::create_with_chunk_bytes(file, key, DEFAULT_CHUNK_BYTES)
+    }
+
+    fn create_with_chunk_bytes(
+        mut file: File,
+        key: &StagingKey,
+        chunk_bytes: u32,
+    ) -> io::Result<EncryptedStagedFile> {
+        let header = encode_header(key.id, chunk_bytes);
+
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        file.write_all(&header)?;
+
+        Ok(EncryptedStagedFile {
+            file,
+            cipher: key.cipher(),
+            rng: OsRng::new()?,
+            header,
+            chunk_bytes: u64::from(chunk_bytes),
+            len: 0,
+            pos: 0,
+            chunk: None,
+        })
+    }
+
+    /// Opens an encrypted staged file, failing with InvalidData if it isn't
+    /// one, or wasn't encrypted with key.
+    pub fn open(mut file: File, key: &StagingKey) -> io::Result<EncryptedStagedFile> {
+        let mut header = [0; HEADER_BYTES];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_exact(&mut header)
+            .map_err(|_| invalid_data("not an encrypted staged file".to_string()))?;
+
+        let (key_id, chunk_bytes) = decode_header(&header)?;
+        if key_id != key.id {
+            let msg = format!(
+                "staged file was encrypted with key {}, not {}",
+                key_id, key.id
+            );
+            return Err(invalid_data(msg));
+        }
+
+        let chunk_bytes = u64::from(chunk_bytes);
+        let body_bytes = file.seek(SeekFrom::End(0))? - HEADER_BYTES as u64;
+
+        Ok(EncryptedStagedFile {
+            file,
+            cipher: key.cipher(),
+            rng: OsRng::new()?,
+            header,
+            chunk_bytes,
+            len: plaintext_bytes(body_bytes, chunk_bytes)?,
+            pos: 0,
+            chunk: None,
+        })
+    }
+
+    /// Truncates the bytes the file holds to len, or extends them with zeroes
+    /// up to len, as File::set_len does.
+    pub fn set_len(&mut self, len: u64) -> io::Result<()> {
+        if len >= self.len {
+            let pos = self.pos;
+            self.pos = self.len;
+            io::copy(&mut io::repeat(0).take(len - self.len), self)?;
+            self.pos = pos;
+
+            return self.flush();
+        }
+
+        // Seal any pending write before the chunks are cut.
+        self.seal_chunk()?;
+
+        let last = len / self.chunk_bytes;
+        let kept = len % self.chunk_bytes;
+        let mut file_len = self.chunk_offset(last);
+
+        // The last chunk kept, if only partly, is sealed again without what's
+        // cut from it.
+        if kept > 0 {
+            self.load(last)?;
+            let chunk = self.chunk.as_mut().expect(LOADED);
+            chunk.bytes.truncate(kept as usize);
+            chunk.dirty = true;
+            self.seal_chunk()?;
+
+            file_len += kept + CHUNK_OVERHEAD;
+        }
+
+        self.chunk = None;
+        self.file.set_len(file_len)?;
+        self.len = len;
+
+        Ok(())
+    }
+
+    fn chunk_offset(&self, index: u64) -> u64 {
+        HEADER_BYTES as u64 + index * (self.chunk_bytes + CHUNK_OVERHEAD)
+    }
+
+    // The header, then the chunk's index as a u64 in little-endian order.
+    fn associated_data(&self, index: u64) -> [u8; HEADER_BYTES + 8] {
+        let mut data = [0; HEADER_BYTES + 8];
+        data[..HEADER_BYTES].copy_from_slice(&self.header);
+        for i in 0..8 {
+            data[HEADER_BYTES + i] = (index >> (8 * i)) as u8;
+        }
+
+        data
+    }
+
+    // Makes the chunk with the given index the current one, decrypting it if
+    // it holds any bytes. The current chunk is sealed first, if it has been
+    // written to.
+    fn load(&mut self, index: u64) -> io::Result<()> {
+        if self.chunk.as_ref().map(|chunk| chunk.index) == Some(index) {
+            return Ok(());
+        }
+
+        self.seal_chunk()?;
+
+        let start = index * self.chunk_bytes;
+        let mut bytes = vec![0; self.len.saturating_sub(start).min(self.chunk_bytes) as usize];
+
+        if !bytes.is_empty() {
+            let mut nonce = [0; NONCE_BYTES];
+            bytes.resize(bytes.len() + TAG_BYTES, 0);
+
+            let offset = self.chunk_offset(index);
+            self.file.seek(SeekFrom::Start(offset))?;
+            self.file.read_exact(&mut nonce)?;
+            self.file.read_exact(&mut bytes)?;
+
+            let associated_data = self.associated_data(index);
+            self.cipher
+                .open_in_place(&nonce, &associated_data, &mut bytes)
+                .map_err(|_| invalid_data(format!("chunk {} failed to decrypt", index)))?;
+
+            let plaintext_bytes = bytes.len() - TAG_BYTES;
+            bytes.truncate(plaintext_bytes);
+        }
+
+        self.chunk = Some(Chunk {
+            index,
+            bytes,
+            dirty: false,
+        });
+
+        Ok(())
+    }
+
+    // Seals the current chunk, under a fresh nonce, if it has been written to
+    // since it was last sealed.
+    fn seal_chunk(&mut self) -> io::Result<()> {
+        let index = match self.chunk {
+            Some(ref chunk) if chunk.dirty => chunk.index,
+            _ => return Ok(()),
+        };
+
+        let associated_data = self.associated_data(index);
+        let offset = self.chunk_offset(index);
+        let chunk = self.chunk.as_mut().expect(LOADED);
+
+        let mut nonce = [0; NONCE_BYTES];
+        self.rng.fill_bytes(&mut nonce);
+
+        let mut sealed = nonce.to_vec();
+        sealed.extend_from_slice(&chunk.bytes);
+        sealed.resize(sealed.len() + TAG_BYTES, 0);
+
+        self.cipher
+            .seal_in_place(&nonce, &associated_data, &mut sealed[NONCE_BYTES..])
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "chunk failed to encrypt"))?;
+
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.write_all(&sealed)?;
+        chunk.dirty = false;
+
+        Ok(())
+    }
+}
+
+impl Read for EncryptedStagedFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.pos >= self.len {
+            return Ok(0);
+        }
+
+        let offset = (self.pos % self.chunk_bytes) as usize;
+        self.load(self.pos / self.chunk_bytes)?;
+
+        let chunk = &self.chunk.as_ref().expect(LOADED).bytes;
+        let n = buf.len().min(chunk.len() - offset);
+        buf[..n].copy_from_slice(&chunk[offset..offset + n]);
+        self.pos += n as u64;
+
+        Ok(n)
+    }
+}
+
+impl Write for EncryptedStagedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        // Chunks are sealed whole, so there can be no gaps between them.
+        if self.pos > self.len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "cannot write past the end of an encrypted staged file",
+            ));
+        }
+
+        let offset = (self.pos % self.chunk_bytes) as usize;
+        let n = buf.len().min(self.chunk_bytes as usize - offset);
+        self.load(self.pos / self.chunk_bytes)?;
+
+        let chunk = self.chunk.as_mut().expect(LOADED);
+        if chunk.bytes.len() < offset + n {
+            chunk.bytes.resize(offset + n, 0);
+        }
+        chunk.bytes[offset..offset + n].copy_from_slice(&buf[..n]);
+        chunk.dirty = true;
+
+        self.pos += n as u64;
+        self.len = self.len.max(self.pos);
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.seal_chunk()?;
+        self.file.flush()
+    }
+}
+
+impl Seek for EncryptedStagedFile {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let pos = match pos {
+            SeekFrom::Start(n) => Some(n),
+            SeekFrom::End(delta) => offset_by(self.len, delta),
+            SeekFrom::Current(delta) => offset_by(self.pos, delta),
+        };
+
+        match pos {
+            Some(pos) => {
+                self.pos = pos;
+                Ok(pos)
+            }
+            None => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "invalid seek to a negative or overflowing position",
+            )),
+        }
+    }
+}
+
+impl Drop for EncryptedStagedFile {
+    fn drop(&mut self) {
+        let _ = self.seal_chunk();
+    }
+}
+
+fn encode_header(key_id: u32, chunk_bytes: u32) -> [u8; HEADER_BYTES] {
+    let mut header = [0; HEADER_BYTES];
+
+    header[..4].copy_from_slice(MAGIC);
+    header[4] = FORMAT_VERSION;
+    header[5] = ALGORITHM_XCHACHA20POLY1305;
+    for i in 0..4 {
+        header[8 + i] = (key_id >> (8 * i)) as u8;
+        header[12 + i] = (chunk_bytes >> (8 * i)) as u8;
+    }
+
+    header
+}
+
+// Returns the key id and chunk size the header records.
+fn decode_header(header: &[u8; HEADER_BYTES]) -> io::Result<(u32, u32)> {
+    if &header[..4] != MAGIC {
+        return Err(invalid_data("not an encrypted staged file".to_string()));
+    }
+
+    if header[4] != FORMAT_VERSION
+        || header[5] != ALGORITHM_XCHACHA20POLY1305
+        || header[6..8] != [0; 2]
+    {
+        let msg = format!("unsupported encrypted staged file version {}", header[4]);
+        return Err(invalid_data(msg));
+    }
+
+    let decode = |bytes: &[u8]| {
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |acc, b| (acc << 8) | u32::from(*b))
+    };
+
+    match decode(&header[12..]) {
+        0 => Err(invalid_data(
+            "encrypted staged file has empty chunks".to_string(),
+        )),
+        chunk_bytes => Ok((decode(&header[8..12]), chunk_bytes)),
+    }
+}
+
+// The number of bytes held in chunks of chunk_bytes taking up body_bytes of a
+// file, once sealed.
+fn plaintext_bytes(body_bytes: u64, chunk_bytes: u64) -> io::Result<u64> {
+    let sealed_chunk_bytes = chunk_bytes + CHUNK_OVERHEAD;
+    let full_chunks = body_bytes / sealed_chunk_bytes;
+
+    match body_bytes % sealed_chunk_bytes {
+        0 => Ok(full_chunks * chunk_bytes),
+        partial if partial > CHUNK_OVERHEAD => {
+            Ok(full_chunks * chunk_bytes + partial - CHUNK_OVERHEAD)
+        }
+        _ => Err(invalid_data(
+            "encrypted staged file ends within a chunk's nonce or tag".to_string(),
+        )),
+    }
+}
+
+fn offset_by(base: u64, delta: i64) -> Option<u64> {
+    if delta >= 0 {
+        base.checked_add(delta as u64)
+    } else {
+        base.checked_sub(delta.wrapping_neg() as u64)
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{thread_rng, Rng};
+    use std::fs::OpenOptions;
+    use tempfile::NamedTempFile;
+
+    const KEY: [u8; STAGING_KEY_BYTES] = [7; STAGING_KEY_BYTES];
+
+    fn reopen(file: &NamedTempFile) -> File {
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(file.path())
+            .unwrap()
+    }
+
+    fn read_all(file: &NamedTempFile, key: &StagingKey) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        EncryptedStagedFile::open(reopen(file), key)?.read_to_end(&mut bytes)?;
+
+        Ok(bytes)
+    }
+
+    fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+        haystack
+            .windows(needle.len())
+            .any(|window| window == needle)
+    }
+
+    #[test]
+    fn writes_read_back_across_chunks() {
+        let key = StagingKey::from_bytes(3, KEY);
+        let file = NamedTempFile::new().unwrap();
+        let bytes: Vec<u8> = (0..1000).map(|_| thread_rng().gen()).collect();
+
+        // Written in pieces which straddle the chunks, and flushed in between.
+        let mut staged =
+            EncryptedStagedFile::create_with_chunk_bytes(reopen(&file), &key, 64).unwrap();
+        for piece in bytes.chunks(100) {
+            staged.write_all(piece).unwrap();
+            staged.flush().unwrap();
+        }
+        drop(staged);
+
+        assert_eq!(bytes, read_all(&file, &key).unwrap());
+
+        // No run of the bytes written is found in the file.
+        let on_disk = std::fs::read(file.path()).unwrap();
+        assert!(bytes.windows(8).all(|run| !contains(&on_disk, run)));
+
+        // Bytes are rewritten in place, and reading starts anywhere.
+        let mut staged = EncryptedStagedFile::open(reopen(&file), &key).unwrap();
+        assert_eq!(1000, staged.seek(SeekFrom::End(0)).unwrap());
+        staged.seek(SeekFrom::Start(60)).unwrap();
+        staged.write_all(&[0xff; 10]).unwrap();
+        staged.flush().unwrap();
+        drop(staged);
+
+        let mut expected = bytes.clone();
+        expected[60..70].copy_from_slice(&[0xff; 10]);
+
+        let mut staged = EncryptedStagedFile::open(reopen(&file), &key).unwrap();
+        let mut tail = Vec::new();
+        staged.seek(SeekFrom::Start(63)).unwrap();
+        staged.read_to_end(&mut tail).unwrap();
+        assert_eq!(&expected[63..], &tail[..]);
+    }
+
+    #[test]
+    fn truncates_and_extends() {
+        let key = StagingKey::from_bytes(3, KEY);
+        let file = NamedTempFile::new().unwrap();
+        let bytes: Vec<u8> = (0..200).map(|n| n as u8).collect();
+
+        let mut staged =
+            EncryptedStagedFile::create_with_chunk_bytes(reopen(&file), &key, 64).unwrap();
+        staged.write_all(&bytes).unwrap();
+
+        for len in &[150, 128, 1, 0] {
+            staged.set_len(*len).unwrap();
+            assert_eq!(&bytes[..*len as usize], &read_all(&file, &key).unwrap()[..]);
+        }
+
+        staged.set_len(70).unwrap();
+        assert_eq!(vec![0; 70], read_all(&file, &key).unwrap());
+    }
+
+    #[test]
+    fn files_only_open_with_their_own_key() {
+        let key = StagingKey::from_bytes(3, KEY);
+        let file = NamedTempFile::new().unwrap();
+
+        let mut staged = EncryptedStagedFile::create(reopen(&file), &key).unwrap();
+        staged.write_all(&[1; 100]).unwrap();
+        drop(staged);
+
+        // Another key with the same id.
+        let other = StagingKey::from_bytes(3, [8; STAGING_KEY_BYTES]);
+        let err = read_all(&file, &other).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+
+        // The key recorded for the file is checked before any chunk is read.
+        let other = StagingKey::from_bytes(4, KEY);
+        let err = EncryptedStagedFile::open(reopen(&file), &other)
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(err.to_string().contains("key 3, not 4"), "{}", err);
+
+        // Nor are plaintext files mistaken for encrypted ones.
+        std::fs::write(file.path(), &[1; 100][..]).unwrap();
+        let err = EncryptedStagedFile::open(reopen(&file), &key)
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn tampering_is_detected() {
+        let key = StagingKey::from_bytes(3, KEY);
+        let file = NamedTempFile::new().unwrap();
+
+        let mut staged =
+            EncryptedStagedFile::create_with_chunk_bytes(reopen(&file), &key, 64).unwrap();
+        staged.write_all(&[1; 128]).unwrap();
+        drop(staged);
+
+        let original = std::fs::read(file.path()).unwrap();
+
+        // A flipped bit in the header or in any chunk.
+        for offset in &[
+            9,
+            HEADER_BYTES + 3,
+            HEADER_BYTES + NONCE_BYTES + 70,
+            original.len() - 1,
+        ] {
+            let mut tampered = original.clone();
+            tampered[*offset] ^= 1;
+            std::fs::write(file.path(), &tampered).unwrap();
+
+            assert!(read_all(&file, &key).is_err(), "offset {}", offset);
+        }
+
+        // Chunks swapped around.
+        let sealed_chunk_bytes = 64 + CHUNK_OVERHEAD as usize;
+        let (header, chunks) = original.split_at(HEADER_BYTES);
+        let mut swapped = header.to_vec();
+        swapped.extend_from_slice(&chunks[sealed_chunk_bytes..]);
+        swapped.extend_from_slice(&chunks[..sealed_chunk_bytes]);
+        std::fs::write(file.path(), &swapped).unwrap();
+
+        assert!(read_all(&file, &key).is_err());
+    }
+
+    #[test]
+    fn keys_are_fetched_by_id() {
+        let key = StagingKey::from_callback(9, |id| Ok([id as u8; STAGING_KEY_BYTES])).unwrap();
+        assert_eq!(9, key.id());
+        assert_eq!([9; STAGING_KEY_BYTES], key.bytes);
+        assert_eq!("StagingKey { id: 9 }", format!("{:?}", key));
+
+        match StagingKey::from_callback(9, |_| Err("no such key".to_string())) {
+            Err(SectorManagerErr::CallerError(msg)) => assert!(msg.contains("no such key")),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,88 @@
+// The final node of every sealed sector is reserved for a trailer recording
+// how many bytes of (unpadded) data the sector holds, so that unsealing can
+// return exactly those bytes rather than the zeroes padding the sector out.
+// The trailer is sealed along with the data, and so committed to by comm_d.
+//
+// Sectors sealed before the trailer was introduced hold data in their final
+// node, so a trailer is only recognized by its magic bytes and version.
+
+use storage_proofs::util::NODE_SIZE;
+
+/// Bytes at the end of a (padded) sector reserved for the trailer: a single
+/// node.
+pub const TRAILER_BYTES: u64 = NODE_SIZE as u64;
+
+const MAGIC: &[u8; 4] = b"FEOD";
+
+const VERSION: u8 = 1;
+
+// The magic, the version and the data length, a u64 in little-endian order.
+// The rest of the node is zero, which keeps it a valid field element.
+const ENCODED_BYTES: usize = 4 + 1 + 8;
+
+/// Returns the bytes of a sector of sector_bytes (padded) bytes in which data
+/// may be held, which is all but the trailer.
+pub fn data_padded_bytes(sector_bytes: u64) -> u64 {
+    sector_bytes - TRAILER_BYTES
+}
+
+/// Returns the trailer of a sector holding data_bytes bytes of unpadded data.
+pub fn encode_trailer(data_bytes: u64) -> [u8; TRAILER_BYTES as usize] {
+    let mut trailer = [0; TRAILER_BYTES as usize];
+
+    trailer[..4].copy_from_slice(MAGIC);
+    trailer[4] = VERSION;
+    for i in 0..8 {
+        trailer[5 + i] = (data_bytes >> (8 * i)) as u8;
+    }
+
+    trailer
+}
+
+/// Returns the number of bytes of unpadded data recorded by the trailer in
+/// node, the final node of an unsealed sector, or None if node holds no
+/// trailer, as the sector was sealed before trailers were introduced.
+pub fn decode_trailer(node: &[u8]) -> Option<u64> {
+    if node.len() != TRAILER_BYTES as usize
+        || &node[..4] != MAGIC
+        || node[4] != VERSION
+        || node[ENCODED_BYTES..].iter().any(|b| *b != 0)
+    {
+        return None;
+    }
+
+    let data_bytes = node[5..ENCODED_BYTES]
+        .iter()
+        .rev()
+        .fold(0, |acc, b| (acc << 8) | u64::from(*b));
+
+    Some(data_bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trailers_roundtrip() {
+        for data_bytes in &[0, 1, 984, 266338272, u64::max_value()] {
+            assert_eq!(Some(*data_bytes), decode_trailer(&encode_trailer(*data_bytes)));
+        }
+    }
+
+    #[test]
+    fn data_is_not_mistaken_for_a_trailer() {
+        assert_eq!(None, decode_trailer(&[0; 32]));
+        assert_eq!(None, decode_trailer(&[0xff; 32]));
+
+        let mut trailer = encode_trailer(7);
+        trailer[4] = VERSION + 1;
+        assert_eq!(None, decode_trailer(&trailer));
+
+        let mut trailer = encode_trailer(7);
+        trailer[31] = 1;
+        assert_eq!(None, decode_trailer(&trailer));
+
+        assert_eq!(None, decode_trailer(&encode_trailer(7)[..31]));
+    }
+}
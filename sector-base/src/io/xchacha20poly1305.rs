@@ -0,0 +1,231 @@
+// XChaCha20Poly1305 (draft-irtf-cfrg-xchacha), as libsodium implements it:
+// each message is sealed with ChaCha20Poly1305 (RFC 7539) under a subkey
+// derived from the key and the first 16 bytes of its 24 byte nonce with
+// HChaCha20, and under the remaining 8 bytes of the nonce, prefixed with four
+// zero bytes. Nonces are long enough to be drawn at random, then, which
+// ChaCha20Poly1305's 12 byte nonces aren't.
+
+use libsodium_sys as sodium;
+use std::ptr;
+use std::sync::Once;
+
+pub const KEY_BYTES: usize = 32;
+
+pub const NONCE_BYTES: usize = 24;
+
+pub const TAG_BYTES: usize = 16;
+
+static SODIUM_INIT: Once = Once::new();
+
+/// A key to seal and open messages with. The key is zeroed once dropped.
+pub struct XChaCha20Poly1305 {
+    key: [u8; KEY_BYTES],
+}
+
+impl XChaCha20Poly1305 {
+    pub fn new(key: &[u8; KEY_BYTES]) -> XChaCha20Poly1305 {
+        // libsodium picks the implementations its functions use, and seeds
+        // its generator, once initialized.
+        SODIUM_INIT.call_once(|| {
+            let status = unsafe { sodium::sodium_init() };
+            assert!(status >= 0, "failed to initialize libsodium");
+        });
+
+        XChaCha20Poly1305 { key: *key }
+    }
+
+    /// Encrypts in_out, but for its last TAG_BYTES, in place, and writes the
+    /// tag authenticating it and the associated data over those last bytes.
+    pub fn seal_in_place(
+        &self,
+        nonce: &[u8; NONCE_BYTES],
+        associated_data: &[u8],
+        in_out: &mut [u8],
+    ) -> Result<(), ()> {
+        if in_out.len() < TAG_BYTES {
+            return Err(());
+        }
+
+        let message_bytes = in_out.len() - TAG_BYTES;
+        let (message, tag) = in_out.split_at_mut(message_bytes);
+        let message = message.as_mut_ptr();
+
+        // libsodium encrypts in place when the message and ciphertext are the
+        // same.
+        let status = unsafe {
+            sodium::crypto_aead_xchacha20poly1305_ietf_encrypt_detached(
+                message,
+                tag.as_mut_ptr(),
+                ptr::null_mut(),
+                message,
+                message_bytes as u64,
+                associated_data.as_ptr(),
+                associated_data.len() as u64,
+                ptr::null(),
+                nonce.as_ptr(),
+                self.key.as_ptr(),
+            )
+        };
+
+        if status == 0 {
+            Ok(())
+        } else {
+            Err(())
+        }
+    }
+
+    /// Decrypts in_out, a ciphertext followed by its tag, in place, returning
+    /// the plaintext should the tag authenticate it and the associated data.
+    /// Nothing is decrypted otherwise.
+    pub fn open_in_place<'a>(
+        &self,
+        nonce: &[u8; NONCE_BYTES],
+        associated_data: &[u8],
+        in_out: &'a mut [u8],
+    ) -> Result<&'a mut [u8], ()> {
+        if in_out.len() < TAG_BYTES {
+            return Err(());
+        }
+
+        let ciphertext_bytes = in_out.len() - TAG_BYTES;
+        let (ciphertext, tag) = in_out.split_at_mut(ciphertext_bytes);
+
+        let status = unsafe {
+            let ciphertext = ciphertext.as_mut_ptr();
+
+            sodium::crypto_aead_xchacha20poly1305_ietf_decrypt_detached(
+                ciphertext,
+                ptr::null_mut(),
+                ciphertext,
+                ciphertext_bytes as u64,
+                tag.as_ptr(),
+                associated_data.as_ptr(),
+                associated_data.len() as u64,
+                nonce.as_ptr(),
+                self.key.as_ptr(),
+            )
+        };
+
+        if status == 0 {
+            Ok(ciphertext)
+        } else {
+            Err(())
+        }
+    }
+}
+
+impl Drop for XChaCha20Poly1305 {
+    fn drop(&mut self) {
+        unsafe { sodium::sodium_memzero(self.key.as_mut_ptr() as *mut _, KEY_BYTES) };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn seals_as_the_draft_does() {
+        // draft-irtf-cfrg-xchacha-03, appendix A.3.1.
+        let plaintext = concat!(
+            "Ladies and Gentlemen of the class of '99: If I could offer you only one tip for ",
+            "the future, sunscreen would be it."
+        )
+        .as_bytes();
+        let associated_data = from_hex("50515253c0c1c2c3c4c5c6c7");
+
+        let mut key = [0; KEY_BYTES];
+        key.copy_from_slice(&from_hex(
+            "808182838485868788898a8b8c8d8e8f909192939495969798999a9b9c9d9e9f",
+        ));
+        let mut nonce = [0; NONCE_BYTES];
+        nonce.copy_from_slice(&from_hex(
+            "404142434445464748494a4b4c4d4e4f5051525354555657",
+        ));
+
+        let expected = from_hex(concat!(
+            "bd6d179d3e83d43b9576579493c0e939572a1700252bfaccbed2902c21396cbb",
+            "731c7f1b0b4aa6440bf3a82f4eda7e39ae64c6708c54c216cb96b72e1213b452",
+            "2f8c9ba40db5d945b11b69b982c1bb9e3f3fac2bc369488f76b2383565d3fff9",
+            "21f9664c97637da9768812f615c68b13b52e",
+            // The tag.
+            "c0875924c1c7987947deafd8780acf49"
+        ));
+
+        let cipher = XChaCha20Poly1305::new(&key);
+        let mut in_out = plaintext.to_vec();
+        in_out.extend_from_slice(&[0; TAG_BYTES]);
+        cipher
+            .seal_in_place(&nonce, &associated_data, &mut in_out)
+            .unwrap();
+        assert_eq!(expected, in_out);
+
+        let opened = cipher
+            .open_in_place(&nonce, &associated_data, &mut in_out)
+            .unwrap();
+        assert_eq!(plaintext, &opened[..]);
+    }
+
+    #[test]
+    fn seals_as_other_implementations_do() {
+        let mut key = [0; KEY_BYTES];
+        for (i, byte) in key.iter_mut().enumerate() {
+            *byte = i as u8;
+        }
+        let mut nonce = [0; NONCE_BYTES];
+        for (i, byte) in nonce.iter_mut().enumerate() {
+            *byte = (i as u8).wrapping_mul(7);
+        }
+        let plaintext = b"staged sectors are encrypted at rest";
+
+        // As sealed by the chacha20poly1305 crate.
+        let expected = from_hex(concat!(
+            "72651556718cb9f0c1d0eb94c544f41930b8e93e158b67684e7c0d332e160e99",
+            "ef52d6b0237f9941fac6c16535a26ca638e86b09"
+        ));
+
+        let cipher = XChaCha20Poly1305::new(&key);
+        let mut in_out = plaintext.to_vec();
+        in_out.extend_from_slice(&[0; TAG_BYTES]);
+        cipher
+            .seal_in_place(&nonce, b"header", &mut in_out)
+            .unwrap();
+        assert_eq!(expected, in_out);
+
+        let opened = cipher
+            .open_in_place(&nonce, b"header", &mut in_out)
+            .unwrap();
+        assert_eq!(&plaintext[..], &opened[..]);
+
+        let mut in_out = expected.clone();
+        assert!(cipher
+            .open_in_place(&nonce, b"footer", &mut in_out)
+            .is_err());
+
+        let mut in_out = expected.clone();
+        in_out[0] ^= 1;
+        assert!(cipher
+            .open_in_place(&nonce, b"header", &mut in_out)
+            .is_err());
+    }
+
+    #[test]
+    fn messages_shorter_than_a_tag_are_refused() {
+        let cipher = XChaCha20Poly1305::new(&[1; KEY_BYTES]);
+
+        let mut in_out = [0; TAG_BYTES - 1];
+        assert!(cipher
+            .seal_in_place(&[2; NONCE_BYTES], &[], &mut in_out)
+            .is_err());
+        assert!(cipher
+            .open_in_place(&[2; NONCE_BYTES], &[], &mut in_out)
+            .is_err());
+    }
+}
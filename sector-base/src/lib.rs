@@ -5,10 +5,17 @@ extern crate bitvec;
 #[macro_use]
 extern crate failure;
 extern crate ffi_toolkit;
+#[cfg(feature = "object-store")]
+extern crate hmac;
 extern crate itertools;
+#[macro_use]
+extern crate lazy_static;
 extern crate libc;
+extern crate libsodium_sys;
 extern crate pairing;
 extern crate rand;
+#[cfg(feature = "object-store")]
+extern crate sha2;
 extern crate storage_proofs;
 
 #[cfg(test)]
@@ -17,3 +24,5 @@ extern crate tempfile;
 pub mod api;
 pub mod error;
 pub mod io;
+
+pub use storage_proofs::types;
@@ -0,0 +1,65 @@
+#[macro_use]
+extern crate criterion;
+extern crate pairing;
+extern crate rand;
+extern crate storage_proofs;
+
+use criterion::{black_box, Criterion, ParameterizedBenchmark};
+use pairing::bls12_381::Bls12;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use storage_proofs::drgraph::*;
+use storage_proofs::fr32::fr_into_bytes;
+use storage_proofs::hasher::pedersen::*;
+use storage_proofs::vde::{self, EncodingScratch};
+
+// Compares encoding with one scratch reused across every node against a fresh
+// scratch per node, which is how the encoder allocated before scratch buffers
+// were reused.
+fn encode(c: &mut Criterion) {
+    let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+    let replica_id: PedersenDomain = rng.gen();
+
+    let params: Vec<_> = vec![1024, 10 * 1024]
+        .into_iter()
+        .map(|nodes| {
+            let graph = BucketGraph::<PedersenHasher>::new(nodes, 5, 0, new_seed());
+            let data: Vec<u8> = (0..nodes)
+                .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+                .collect();
+
+            (graph, data)
+        })
+        .collect();
+
+    c.bench(
+        "encode",
+        ParameterizedBenchmark::new(
+            "reused-scratch",
+            move |b, (graph, data)| {
+                let mut scratch = EncodingScratch::new(graph.degree());
+
+                b.iter(|| {
+                    let mut data = data.clone();
+                    vde::encode_with_scratch(graph, 1, &replica_id, &mut data, &mut scratch)
+                        .unwrap();
+                    black_box(data)
+                })
+            },
+            params,
+        )
+        .with_function("scratch-per-node", move |b, (graph, data)| {
+            b.iter(|| {
+                let mut data = data.clone();
+                for node in 0..graph.size() {
+                    let mut scratch = EncodingScratch::default();
+                    vde::encode_node(graph, 1, &replica_id, &mut data, node, &mut scratch).unwrap();
+                }
+                black_box(data)
+            })
+        })
+        .sample_size(10),
+    );
+}
+
+criterion_group!(benches, encode);
+criterion_main!(benches);
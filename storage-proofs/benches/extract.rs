@@ -0,0 +1,87 @@
+#[macro_use]
+extern crate criterion;
+extern crate pairing;
+extern crate rand;
+extern crate storage_proofs;
+
+use criterion::{black_box, Benchmark, Criterion};
+use pairing::bls12_381::Bls12;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use storage_proofs::crypto::feistel::FeistelConfig;
+use storage_proofs::drgporep;
+use storage_proofs::drgraph::new_seed;
+use storage_proofs::fr32::fr_into_bytes;
+use storage_proofs::hasher::Blake2sHasher;
+use storage_proofs::layered_drgporep::{LayerChallenges, Layers, SetupParams};
+use storage_proofs::porep::PoRep;
+use storage_proofs::proof::ProofScheme;
+use storage_proofs::zigzag_drgporep::ZigZagDrgPoRep;
+
+// A sector of 2^20 nodes (32MiB), scaled down from the 1GiB-class sectors so
+// that replicating it in setup stays quick, with 4 layers as sealed today.
+const NODES: usize = 1 << 20;
+const LAYERS: usize = 4;
+
+// Compares extracting 4KiB from the middle of a replica by decoding only the
+// nodes it depends on against decoding the whole replica.
+fn extract(c: &mut Criterion) {
+    let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+    let sp = SetupParams {
+        drg_porep_setup_params: drgporep::SetupParams {
+            drg: drgporep::DrgParams {
+                nodes: NODES,
+                degree: 5,
+                expansion_degree: 8,
+                seed: new_seed(),
+                feistel: FeistelConfig::default(),
+            },
+            sloth_iter: 0,
+        },
+        layer_challenges: LayerChallenges::new_fixed(LAYERS, 1),
+    };
+
+    let pp = ZigZagDrgPoRep::<Blake2sHasher>::setup(&sp).unwrap();
+    let replica_id = rng.gen();
+    let mut replica: Vec<u8> = (0..NODES)
+        .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+        .collect();
+
+    ZigZagDrgPoRep::<Blake2sHasher>::replicate(&pp, &replica_id, &mut replica, None).unwrap();
+
+    let nodes = NODES / 2..NODES / 2 + 4096 / 32;
+    let (pp_all, replica_id_all, replica_all) = (pp.clone(), replica_id, replica.clone());
+
+    c.bench(
+        "extract-4KiB",
+        Benchmark::new("range", move |b| {
+            b.iter(|| {
+                black_box(
+                    ZigZagDrgPoRep::<Blake2sHasher>::extract_range(
+                        &pp,
+                        &replica_id,
+                        &replica,
+                        nodes.clone(),
+                    )
+                    .unwrap(),
+                )
+            })
+        })
+        .with_function("all", move |b| {
+            b.iter(|| {
+                black_box(
+                    ZigZagDrgPoRep::<Blake2sHasher>::extract_all(
+                        &pp_all,
+                        &replica_id_all,
+                        &replica_all,
+                    )
+                    .unwrap(),
+                )
+            })
+        })
+        .sample_size(10),
+    );
+}
+
+criterion_group!(benches, extract);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+#[macro_use]
+extern crate criterion;
+extern crate rand;
+extern crate rayon;
+extern crate storage_proofs;
+
+use criterion::{black_box, Criterion, ParameterizedBenchmark};
+use rand::{thread_rng, Rng};
+use storage_proofs::hasher::{Blake2sHasher, Hasher};
+use storage_proofs::merkle::{MerkleTree, Parallelism};
+
+type Domain = <Blake2sHasher as Hasher>::Domain;
+type Function = <Blake2sHasher as Hasher>::Function;
+
+// Builds a tree over 2^20 leaves with 1, 2, 4, ... threads, up to one thread
+// per core. Sequential is the baseline.
+fn merkle_tree(c: &mut Criterion) {
+    let rng = &mut thread_rng();
+    let leaves: Vec<Domain> = (0..1 << 20).map(|_| rng.gen()).collect();
+
+    let mut params = vec![Parallelism::Sequential];
+    let mut threads = 1;
+    while threads <= rayon::current_num_threads() {
+        params.push(Parallelism::Threads(threads));
+        threads *= 2;
+    }
+
+    c.bench(
+        "merkle-tree",
+        ParameterizedBenchmark::new(
+            "blake2s/leaves=2^20",
+            move |b, parallelism| {
+                b.iter(|| {
+                    black_box(MerkleTree::<Domain, Function>::with_parallelism(
+                        leaves.clone(),
+                        *parallelism,
+                    ))
+                })
+            },
+            params,
+        )
+        .sample_size(10),
+    );
+}
+
+criterion_group!(benches, merkle_tree);
+criterion_main!(benches);
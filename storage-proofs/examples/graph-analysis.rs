@@ -0,0 +1,128 @@
+extern crate clap;
+extern crate storage_proofs;
+
+use clap::{App, Arg};
+
+use storage_proofs::analysis::{estimate_depth_after_removal, estimate_expansion};
+use storage_proofs::drgraph::Graph;
+use storage_proofs::hasher::PedersenHasher;
+use storage_proofs::zigzag_graph::ZigZagBucketGraph;
+
+// Sweeps the degree and expansion degree of zigzag graphs of a fixed number of
+// nodes, printing the estimates of each as a line of CSV.
+fn sweep(
+    nodes: usize,
+    max_degree: usize,
+    max_expansion_degree: usize,
+    fraction_removed: f64,
+    set_size: usize,
+    trials: usize,
+    seed: u64,
+) {
+    println!(
+        "nodes,degree,expansion_degree,nodes_removed,depth_min,depth_mean,depth_max,\
+         depth_std_dev,set_size,boundary_min,boundary_mean,boundary_max,boundary_std_dev"
+    );
+
+    for degree in 1..=max_degree {
+        for expansion_degree in 0..=max_expansion_degree {
+            let g: ZigZagBucketGraph<PedersenHasher> =
+                Graph::new(nodes, degree, expansion_degree, [1, 2, 3, 4, 5, 6, 7]);
+
+            let depth = estimate_depth_after_removal(&g, fraction_removed, trials, seed);
+            let expansion = estimate_expansion(&g, set_size, trials, seed);
+
+            println!(
+                "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                nodes,
+                degree,
+                expansion_degree,
+                depth.nodes_removed,
+                depth.depth.min,
+                depth.depth.mean,
+                depth.depth.max,
+                depth.depth.std_dev,
+                expansion.set_size,
+                expansion.boundary.min,
+                expansion.boundary.mean,
+                expansion.boundary.max,
+                expansion.boundary.std_dev,
+            );
+        }
+    }
+}
+
+fn main() {
+    let matches = App::new(stringify!("Graph Analysis"))
+        .version("1.0")
+        .arg(
+            Arg::with_name("nodes")
+                .help("The number of nodes of the graphs")
+                .long("nodes")
+                .default_value("1048576")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-degree")
+                .help("The largest degree to sweep")
+                .long("max-degree")
+                .default_value("8")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-expansion-degree")
+                .help("The largest expansion degree to sweep")
+                .long("max-expansion-degree")
+                .default_value("8")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("fraction-removed")
+                .help("The fraction of nodes removed when estimating depth")
+                .long("fraction-removed")
+                .default_value("0.2")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("set-size")
+                .help("The number of nodes in the sets whose boundaries are measured")
+                .long("set-size")
+                .default_value("1024")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("trials")
+                .help("The number of trials of each estimate")
+                .long("trials")
+                .default_value("10")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .help("The seed of the random node sets")
+                .long("seed")
+                .default_value("0")
+                .takes_value(true),
+        )
+        .get_matches();
+
+    let value = |name: &str| matches.value_of(name).unwrap();
+
+    sweep(
+        value("nodes").parse().expect("nodes must be an integer"),
+        value("max-degree")
+            .parse()
+            .expect("max-degree must be an integer"),
+        value("max-expansion-degree")
+            .parse()
+            .expect("max-expansion-degree must be an integer"),
+        value("fraction-removed")
+            .parse()
+            .expect("fraction-removed must be a number"),
+        value("set-size")
+            .parse()
+            .expect("set-size must be an integer"),
+        value("trials").parse().expect("trials must be an integer"),
+        value("seed").parse().expect("seed must be an integer"),
+    );
+}
@@ -0,0 +1,224 @@
+extern crate clap;
+extern crate pairing;
+extern crate rand;
+extern crate storage_proofs;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use pairing::bls12_381::Bls12;
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use storage_proofs::crypto::feistel::FeistelConfig;
+use storage_proofs::debug_tools::{diff_replica_files, diff_replication};
+use storage_proofs::drgporep;
+use storage_proofs::fr32::fr_into_bytes;
+use storage_proofs::hasher::PedersenHasher;
+use storage_proofs::layered_drgporep::{LayerChallenges, PublicParams, SetupParams};
+use storage_proofs::proof::ProofScheme;
+use storage_proofs::zigzag_drgporep::ZigZagDrgPoRep;
+use storage_proofs::zigzag_graph::ZigZagBucketGraph;
+
+type Params = PublicParams<PedersenHasher, ZigZagBucketGraph<PedersenHasher>>;
+
+fn parse<T: std::str::FromStr>(matches: &ArgMatches, name: &str) -> T {
+    matches
+        .value_of(name)
+        .unwrap()
+        .parse()
+        .unwrap_or_else(|_| panic!("{} is malformed", name))
+}
+
+fn keys(matches: &ArgMatches, name: &str) -> Vec<u32> {
+    matches
+        .value_of(name)
+        .unwrap()
+        .split(',')
+        .map(|key| key.trim().parse().expect("keys must be integers"))
+        .collect()
+}
+
+fn params(matches: &ArgMatches, keys: Vec<u32>) -> Params {
+    let sp = SetupParams {
+        drg_porep_setup_params: drgporep::SetupParams {
+            drg: drgporep::DrgParams {
+                nodes: parse(matches, "nodes"),
+                degree: parse(matches, "degree"),
+                expansion_degree: parse(matches, "expansion-degree"),
+                seed: [1, 2, 3, 4, 5, 6, 7],
+                feistel: FeistelConfig::new(keys, parse(matches, "rounds")),
+            },
+            sloth_iter: 1,
+        },
+        layer_challenges: LayerChallenges::new_fixed(parse(matches, "layers"), 1),
+    };
+
+    ZigZagDrgPoRep::<PedersenHasher>::setup(&sp).expect("setup failed")
+}
+
+// Replicates random data under the protocol's parameters, with the Feistel
+// keys given for each side, and prints where the replications diverge.
+fn diff_feistel_keys(matches: &ArgMatches) {
+    let pp_a = params(matches, keys(matches, "keys-a"));
+    let pp_b = params(matches, keys(matches, "keys-b"));
+
+    let rng = &mut XorShiftRng::from_seed([parse(matches, "seed"), 1, 2, 3]);
+    let replica_id = rng.gen();
+    let data: Vec<u8> = (0..parse::<usize>(matches, "nodes"))
+        .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+        .collect();
+
+    let diff = diff_replication::<ZigZagDrgPoRep<PedersenHasher>>(&pp_a, &pp_b, &replica_id, &data)
+        .expect("replication failed");
+
+    match diff.first_divergence {
+        Some(ref divergence) => {
+            println!(
+                "first divergence: layer {}, node {}",
+                divergence.layer, divergence.node
+            );
+            if divergence.parents_differ() {
+                println!("  parents a: {:?}", divergence.parents_a);
+                println!("  parents b: {:?}", divergence.parents_b);
+            } else {
+                println!("  parents: {:?} (the same)", divergence.parents_a);
+            }
+        }
+        None => println!("no divergence"),
+    }
+
+    for taus in &diff.layer_taus {
+        let verdict = |matches| if matches { "same" } else { "differ" };
+
+        println!(
+            "layer {}: comm_d {}, comm_r {}",
+            taus.layer,
+            verdict(taus.comm_d_matches()),
+            verdict(taus.comm_r_matches())
+        );
+    }
+}
+
+// Compares two replicas on disk, printing the first nodes which differ.
+fn diff_files(matches: &ArgMatches) {
+    let diff = diff_replica_files(
+        matches.value_of("a").unwrap(),
+        matches.value_of("b").unwrap(),
+        parse(matches, "lambda"),
+        parse(matches, "max-reported"),
+    )
+    .expect("could not read replicas");
+
+    println!(
+        "{} of {} nodes differ (a has {} nodes, b has {})",
+        diff.differing_nodes,
+        diff.nodes_a.min(diff.nodes_b),
+        diff.nodes_a,
+        diff.nodes_b
+    );
+
+    for node in &diff.first_differences {
+        println!("node {}:", node.node);
+        println!("  a: {:02x?}", node.a);
+        println!("  b: {:02x?}", node.b);
+    }
+}
+
+fn main() {
+    let protocol_keys = FeistelConfig::default()
+        .keys
+        .iter()
+        .map(|key| key.to_string())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let matches = App::new("replication-diff")
+        .version("1.0")
+        .about("Finds where two replications, or two replicas, diverge")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("feistel-keys")
+                .about("Replicates random data with two sets of Feistel keys")
+                .arg(
+                    Arg::with_name("keys-a")
+                        .help("The keys of the first replication, comma-separated")
+                        .long("keys-a")
+                        .default_value(&protocol_keys)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("keys-b")
+                        .help("The keys of the second replication, comma-separated")
+                        .long("keys-b")
+                        .required(true)
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("rounds")
+                        .help("The number of Feistel rounds")
+                        .long("rounds")
+                        .default_value("3")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("nodes")
+                        .help("The number of nodes replicated")
+                        .long("nodes")
+                        .default_value("1024")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("layers")
+                        .help("The number of layers")
+                        .long("layers")
+                        .default_value("4")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("degree")
+                        .help("The degree of the base graph")
+                        .long("degree")
+                        .default_value("5")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("expansion-degree")
+                        .help("The expansion degree")
+                        .long("expansion-degree")
+                        .default_value("8")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("seed")
+                        .help("The seed of the data and replica id")
+                        .long("seed")
+                        .default_value("0")
+                        .takes_value(true),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("files")
+                .about("Compares two replicas on disk node by node")
+                .arg(Arg::with_name("a").required(true).index(1))
+                .arg(Arg::with_name("b").required(true).index(2))
+                .arg(
+                    Arg::with_name("lambda")
+                        .help("The size of a node in bytes")
+                        .long("lambda")
+                        .default_value("32")
+                        .takes_value(true),
+                )
+                .arg(
+                    Arg::with_name("max-reported")
+                        .help("The number of differing nodes to print")
+                        .long("max-reported")
+                        .default_value("10")
+                        .takes_value(true),
+                ),
+        )
+        .get_matches();
+
+    match matches.subcommand() {
+        ("feistel-keys", Some(matches)) => diff_feistel_keys(matches),
+        ("files", Some(matches)) => diff_files(matches),
+        _ => unreachable!(),
+    }
+}
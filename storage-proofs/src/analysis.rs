@@ -0,0 +1,361 @@
+//! Empirical estimates of the properties which make a DRG fit for proofs of
+//! replication, for choosing its degree and expansion degree: how long a path
+//! of dependencies remains once a fraction of its nodes is removed (its depth
+//! robustness), and how many nodes outside a set of nodes the set depends on
+//! (its expansion).
+//!
+//! Both sample random node sets, so they're estimates rather than bounds, but
+//! are deterministic given their seed. Each trial's sets are kept in bitsets,
+//! and every buffer is allocated once per estimate rather than once per trial,
+//! so that graphs of 2^20 nodes and more can be estimated in seconds.
+
+use std::cmp;
+
+use rand::{Rng, SeedableRng, XorShiftRng};
+
+use crate::drgraph::Graph;
+use crate::hasher::Hasher;
+
+/// Summary statistics of a metric over the trials of an estimate.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub trials: usize,
+    pub min: usize,
+    pub max: usize,
+    pub mean: f64,
+    /// The population standard deviation.
+    pub std_dev: f64,
+}
+
+impl Summary {
+    fn of(samples: &[usize]) -> Summary {
+        let trials = samples.len();
+        let mean = samples.iter().sum::<usize>() as f64 / trials as f64;
+        let variance = samples
+            .iter()
+            .map(|s| (*s as f64 - mean).powi(2))
+            .sum::<f64>()
+            / trials as f64;
+
+        Summary {
+            trials,
+            min: *samples.iter().min().expect("no trials"),
+            max: *samples.iter().max().expect("no trials"),
+            mean,
+            std_dev: variance.sqrt(),
+        }
+    }
+}
+
+/// What estimate_depth_after_removal found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DepthEstimate {
+    /// The number of nodes removed in each trial.
+    pub nodes_removed: usize,
+    /// The number of nodes on the longest path left in each trial.
+    pub depth: Summary,
+}
+
+/// What estimate_expansion found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpansionEstimate {
+    pub set_size: usize,
+    /// The number of nodes outside the set which nodes of the set have as
+    /// parents, in each trial.
+    pub boundary: Summary,
+}
+
+/// Estimates the depth robustness of `g`: in each of `trials` trials, removes
+/// a random `fraction_removed` of its nodes, and measures the longest path of
+/// dependencies between the nodes left.
+pub fn estimate_depth_after_removal<H: Hasher, G: Graph<H>>(
+    g: &G,
+    fraction_removed: f64,
+    trials: usize,
+    rng_seed: u64,
+) -> DepthEstimate {
+    assert!(
+        fraction_removed >= 0.0 && fraction_removed <= 1.0,
+        "fraction_removed must be between 0 and 1"
+    );
+    assert!(trials > 0, "trials must be non-zero");
+
+    let nodes = g.size();
+    let nodes_removed = (fraction_removed * nodes as f64).round() as usize;
+
+    let mut rng = seeded_rng(rng_seed);
+    let mut order: Vec<usize> = (0..nodes).collect();
+    let mut removed = NodeSet::new(nodes);
+    let mut depths = vec![0u32; nodes];
+    let mut parents = Vec::with_capacity(g.degree());
+    let mut samples = Vec::with_capacity(trials);
+
+    for _ in 0..trials {
+        removed.clear();
+        for node in sample_nodes(&mut rng, &mut order, nodes_removed) {
+            removed.insert(*node);
+        }
+
+        // A node's depth is that of the deepest of its parents left, plus one.
+        // Visiting nodes in the order their parents precede them visits every
+        // parent first.
+        let mut longest = 0;
+        for i in 0..nodes {
+            let node = if g.forward() { i } else { nodes - 1 - i };
+            if removed.contains(node) {
+                continue;
+            }
+
+            g.parents_into(node, &mut parents);
+            let deepest_parent = parents
+                .iter()
+                .filter(|p| if g.forward() { **p < node } else { **p > node })
+                .filter(|p| !removed.contains(**p))
+                .map(|p| depths[*p])
+                .max()
+                .unwrap_or(0);
+
+            depths[node] = deepest_parent + 1;
+            longest = cmp::max(longest, depths[node]);
+        }
+
+        samples.push(longest as usize);
+    }
+
+    DepthEstimate {
+        nodes_removed,
+        depth: Summary::of(&samples),
+    }
+}
+
+/// Estimates the expansion of `g`: in each of `trials` trials, picks a random
+/// set of `set_size` of its nodes, and counts the nodes outside the set which
+/// nodes of the set have as parents.
+pub fn estimate_expansion<H: Hasher, G: Graph<H>>(
+    g: &G,
+    set_size: usize,
+    trials: usize,
+    rng_seed: u64,
+) -> ExpansionEstimate {
+    let nodes = g.size();
+    assert!(
+        set_size <= nodes,
+        "set_size must not exceed the graph's size"
+    );
+    assert!(trials > 0, "trials must be non-zero");
+
+    let mut rng = seeded_rng(rng_seed);
+    let mut order: Vec<usize> = (0..nodes).collect();
+    let mut set = NodeSet::new(nodes);
+    let mut boundary = NodeSet::new(nodes);
+    let mut parents = Vec::with_capacity(g.degree());
+    let mut samples = Vec::with_capacity(trials);
+
+    for _ in 0..trials {
+        set.clear();
+        boundary.clear();
+
+        let sampled = sample_nodes(&mut rng, &mut order, set_size);
+        for node in sampled.iter() {
+            set.insert(*node);
+        }
+
+        let mut boundary_size = 0;
+        for node in sampled.iter() {
+            g.parents_into(*node, &mut parents);
+
+            for parent in &parents {
+                if !set.contains(*parent) && !boundary.contains(*parent) {
+                    boundary.insert(*parent);
+                    boundary_size += 1;
+                }
+            }
+        }
+
+        samples.push(boundary_size);
+    }
+
+    ExpansionEstimate {
+        set_size,
+        boundary: Summary::of(&samples),
+    }
+}
+
+fn seeded_rng(rng_seed: u64) -> XorShiftRng {
+    // The constant half keeps the state non-zero, as XorShiftRng requires.
+    XorShiftRng::from_seed([
+        rng_seed as u32,
+        (rng_seed >> 32) as u32,
+        0x3237db17,
+        0xe5bc0654,
+    ])
+}
+
+// Moves a uniformly random choice of count of the nodes in order to its front
+// (shuffling it partially, which keeps it a permutation of the nodes for the
+// next trial), and returns them.
+fn sample_nodes<'a, R: Rng>(rng: &mut R, order: &'a mut [usize], count: usize) -> &'a [usize] {
+    for i in 0..count {
+        let j = rng.gen_range(i, order.len());
+        order.swap(i, j);
+    }
+
+    &order[..count]
+}
+
+// A set of a graph's nodes, one bit per node.
+struct NodeSet {
+    words: Vec<u64>,
+}
+
+impl NodeSet {
+    fn new(nodes: usize) -> NodeSet {
+        NodeSet {
+            words: vec![0; (nodes + 63) / 64],
+        }
+    }
+
+    fn clear(&mut self) {
+        for word in self.words.iter_mut() {
+            *word = 0;
+        }
+    }
+
+    #[inline]
+    fn insert(&mut self, node: usize) {
+        self.words[node / 64] |= 1 << (node % 64);
+    }
+
+    #[inline]
+    fn contains(&self, node: usize) -> bool {
+        self.words[node / 64] & (1 << (node % 64)) != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::drgraph::{new_seed, BucketGraph};
+    use crate::hasher::PedersenHasher;
+
+    // A graph small enough to work the estimates out by hand: each node's
+    // parents are the two nodes before it.
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    struct Ladder {
+        nodes: usize,
+    }
+
+    impl Graph<PedersenHasher> for Ladder {
+        fn parents(&self, node: usize) -> Vec<usize> {
+            match node {
+                0 | 1 => vec![0, 0],
+                _ => vec![node - 2, node - 1],
+            }
+        }
+
+        fn size(&self) -> usize {
+            self.nodes
+        }
+
+        fn degree(&self) -> usize {
+            2
+        }
+
+        fn new(nodes: usize, _: usize, _: usize, _: [u32; 7]) -> Self {
+            Ladder { nodes }
+        }
+
+        fn seed(&self) -> [u32; 7] {
+            [0; 7]
+        }
+    }
+
+    #[test]
+    fn estimates_are_pinned() {
+        let g = Ladder { nodes: 8 };
+
+        assert_eq!(
+            DepthEstimate {
+                nodes_removed: 2,
+                depth: Summary {
+                    trials: 4,
+                    min: 5,
+                    max: 6,
+                    mean: 5.75,
+                    std_dev: 0.4330127018922193,
+                },
+            },
+            estimate_depth_after_removal(&g, 0.25, 4, 42)
+        );
+
+        assert_eq!(
+            ExpansionEstimate {
+                set_size: 3,
+                boundary: Summary {
+                    trials: 4,
+                    min: 2,
+                    max: 3,
+                    mean: 2.5,
+                    std_dev: 0.5,
+                },
+            },
+            estimate_expansion(&g, 3, 4, 42)
+        );
+    }
+
+    #[test]
+    fn extreme_sets_have_exact_estimates() {
+        let g = Ladder { nodes: 8 };
+
+        let untouched = estimate_depth_after_removal(&g, 0.0, 3, 1);
+        assert_eq!((8, 8), (untouched.depth.min, untouched.depth.max));
+
+        let emptied = estimate_depth_after_removal(&g, 1.0, 3, 1);
+        assert_eq!(
+            (8, 0, 0),
+            (emptied.nodes_removed, emptied.depth.min, emptied.depth.max)
+        );
+
+        let everything = estimate_expansion(&g, 8, 3, 1);
+        assert_eq!((0, 0), (everything.boundary.min, everything.boundary.max));
+    }
+
+    #[test]
+    fn estimates_are_deterministic() {
+        let g = BucketGraph::<PedersenHasher>::new(1024, 5, 0, new_seed());
+
+        assert_eq!(
+            estimate_depth_after_removal(&g, 0.3, 5, 7),
+            estimate_depth_after_removal(&g, 0.3, 5, 7)
+        );
+        assert_eq!(
+            estimate_expansion(&g, 100, 5, 7),
+            estimate_expansion(&g, 100, 5, 7)
+        );
+    }
+
+    #[test]
+    fn more_parents_never_make_graphs_shallower() {
+        let seeds = [[1; 7], [2; 7], [3; 7], [4; 7]];
+
+        let mean_depth = |degree| {
+            let total: f64 = seeds
+                .iter()
+                .map(|seed| {
+                    let g = BucketGraph::<PedersenHasher>::new(1024, degree, 0, *seed);
+                    estimate_depth_after_removal(&g, 0.2, 8, seed[0] as u64)
+                        .depth
+                        .mean
+                })
+                .sum();
+
+            total / seeds.len() as f64
+        };
+
+        let depths: Vec<f64> = [1, 2, 4, 8].iter().map(|d| mean_depth(*d)).collect();
+        for pair in depths.windows(2) {
+            assert!(pair[0] <= pair[1], "{:?}", depths);
+        }
+    }
+}
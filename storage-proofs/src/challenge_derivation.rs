@@ -1,43 +1,67 @@
+use std::collections::HashSet;
+
 use byteorder::{LittleEndian, WriteBytesExt};
 use num_bigint::BigUint;
 use num_traits::cast::ToPrimitive;
 
 use crate::crypto::blake2s::blake2s;
-use crate::hasher::Domain;
-use crate::layered_drgporep::LayerChallenges;
 
-pub fn derive_challenges<D: Domain>(
-    challenges: &LayerChallenges,
+/// Derives the `count` challenges of partition `k` at `layer` from `seed`.
+///
+/// The challenges of a layer are drawn, in order, from the hashes of the seed, the layer and a
+/// counter, each reduced uniformly into the challengeable nodes, so each layer gets its own.
+/// A node drawn again before every challengeable node has been drawn is skipped and the next
+/// hash drawn instead, so no node is challenged twice unless there are more challenges than
+/// nodes. Partition `k` gets the `k`th run of `count` of them, so the partitions of a proof
+/// never share a challenge either, and together get the challenges a single partition would.
+///
+/// The first and last nodes are never challenged, as layered verification fails for a node
+/// whose parents are all itself: node 0 is such a node, and since the reversed layers of
+/// zigzag number nodes from the end, so is the last node in those.
+pub fn derive_challenges(
+    seed: &[u8; 32],
     layer: u8,
     leaves: usize,
-    replica_id: &D,
-    commitment: &D,
+    count: usize,
     k: u8,
 ) -> Vec<usize> {
-    let n = challenges.challenges_for_layer(layer as usize);
-    (0..n)
-        .map(|i| {
-            let mut bytes = replica_id.into_bytes();
-            let j = ((n * k as usize) + i) as u32;
-            bytes.extend(commitment.into_bytes());
-            bytes.push(layer);
-            bytes.write_u32::<LittleEndian>(j).unwrap();
-
-            let hash = blake2s(bytes.as_slice());
-            let big_challenge = BigUint::from_bytes_le(hash.as_slice());
-
-            // For now, we cannot try to prove the first or last node, so make sure the challenge can never be 0 or leaves - 1.
-            let big_mod_challenge = big_challenge % (leaves - 2);
-            big_mod_challenge.to_usize().unwrap() + 1
-        })
-        .collect()
+    assert!(leaves > 2, "cannot challenge a graph of {} nodes", leaves);
+
+    let challengeable = leaves - 2;
+
+    let mut bytes = seed.to_vec();
+    bytes.push(layer);
+    let prefix_len = bytes.len();
+
+    let wanted = count * (k as usize + 1);
+    let mut drawn = Vec::with_capacity(wanted);
+    let mut seen = HashSet::new();
+    let mut j: u32 = 0;
+
+    while drawn.len() < wanted {
+        bytes.truncate(prefix_len);
+        bytes.write_u32::<LittleEndian>(j).unwrap();
+        j += 1;
+
+        let hash = blake2s(bytes.as_slice());
+        let big_challenge = BigUint::from_bytes_le(hash.as_slice());
+        let challenge = (big_challenge % challengeable).to_usize().unwrap() + 1;
+
+        if seen.len() == challengeable {
+            seen.clear();
+        }
+        if seen.insert(challenge) {
+            drawn.push(challenge);
+        }
+    }
+
+    drawn.split_off(count * k as usize)
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
-    use crate::hasher::pedersen::PedersenDomain;
-    use rand::{thread_rng, Rng};
+    use rand::{thread_rng, Rng, SeedableRng, XorShiftRng};
     use std::collections::HashMap;
 
     #[test]
@@ -45,11 +69,9 @@ mod test {
         let n = 200;
         let layers = 100;
 
-        let challenges = LayerChallenges::new_fixed(layers, n);
         let leaves = 1 << 30;
         let mut rng = thread_rng();
-        let replica_id: PedersenDomain = rng.gen();
-        let commitment: PedersenDomain = rng.gen();
+        let seed: [u8; 32] = rng.gen();
         let partitions = 5;
         let total_challenges = partitions * n;
 
@@ -58,14 +80,7 @@ mod test {
         for layer in 0..layers {
             let mut histogram = HashMap::new();
             for k in 0..partitions {
-                let challenges = derive_challenges(
-                    &challenges,
-                    layer as u8,
-                    leaves,
-                    &replica_id,
-                    &commitment,
-                    k as u8,
-                );
+                let challenges = derive_challenges(&seed, layer as u8, leaves, n, k as u8);
 
                 for challenge in challenges {
                     let counter = histogram.entry(challenge).or_insert(0);
@@ -78,9 +93,62 @@ mod test {
             }
         }
 
-        // If we generate 100 layers with 1,000 challenges in each, at most two layers can contain
-        // any duplicates for this assertion to succeed.
-        assert!(layers_with_duplicates < 3);
+        // No layer's challenges repeat, across partitions or within one.
+        assert_eq!(0, layers_with_duplicates);
+    }
+
+    #[test]
+    fn challenges_cover_every_node_before_repeating() {
+        let mut rng = XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let seed: [u8; 32] = rng.gen();
+
+        // 10 challengeable nodes: 1 to 10.
+        let leaves = 12;
+
+        for (n, k) in &[(10, 0), (5, 1), (25, 0)] {
+            let drawn = (0..=*k)
+                .flat_map(|k| derive_challenges(&seed, 0, leaves, *n, k))
+                .collect::<Vec<_>>();
+
+            assert!(drawn.iter().all(|c| *c >= 1 && *c <= 10), "{:?}", drawn);
+
+            for round in drawn.chunks(10) {
+                let mut distinct = round.to_vec();
+                distinct.sort();
+                distinct.dedup();
+                assert_eq!(round.len(), distinct.len(), "{:?}", drawn);
+            }
+        }
+    }
+
+    #[test]
+    fn challenges_are_uniform() {
+        let mut rng = XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+        // 256 challengeable nodes, counted in 16 buckets of 16.
+        let leaves = 258;
+        let buckets = 16;
+        let derivations = 2000;
+
+        let mut counts = vec![0; buckets];
+        for _ in 0..derivations {
+            let seed: [u8; 32] = rng.gen();
+
+            let drawn = derive_challenges(&seed, 0, leaves, 8, 0);
+            for challenge in drawn {
+                counts[(challenge - 1) / 16] += 1;
+            }
+        }
+
+        let expected = (derivations * 8 / buckets) as f64;
+        let chi_squared: f64 = counts
+            .iter()
+            .map(|count| (f64::from(*count) - expected).powi(2) / expected)
+            .sum();
+
+        // The critical value of the chi-squared distribution with 15 degrees of freedom at
+        // p = 0.001.
+        assert!(chi_squared < 37.7, "chi-squared {} for {:?}", chi_squared, counts);
     }
 
     #[test]
@@ -90,36 +158,63 @@ mod test {
         let n = 40;
         let leaves = 1 << 30;
         let mut rng = thread_rng();
-        let replica_id: PedersenDomain = rng.gen();
-        let commitment: PedersenDomain = rng.gen();
+        let seed: [u8; 32] = rng.gen();
         let partitions = 5;
         let layers = 100;
         let total_challenges = n * partitions;
 
         for layer in 0..layers {
-            let one_partition_challenges = derive_challenges(
-                &LayerChallenges::new_fixed(layers, total_challenges),
-                layer as u8,
-                leaves,
-                &replica_id,
-                &commitment,
-                0,
-            );
+            let one_partition_challenges =
+                derive_challenges(&seed, layer as u8, leaves, total_challenges, 0);
             let many_partition_challenges = (0..partitions)
-                .flat_map(|k| {
-                    derive_challenges(
-                        &LayerChallenges::new_fixed(layers, n),
-                        layer as u8,
-                        leaves,
-                        &replica_id,
-                        &commitment,
-                        k as u8,
-                    )
-                })
+                .flat_map(|k| derive_challenges(&seed, layer as u8, leaves, n, k as u8))
                 .collect::<Vec<_>>();
 
             assert_eq!(one_partition_challenges, many_partition_challenges);
         }
     }
 
+    #[test]
+    fn challenges_are_salted_by_layer() {
+        let seed = [7; 32];
+        let leaves = 1 << 20;
+
+        let first = derive_challenges(&seed, 0, leaves, 8, 0);
+        for layer in 1..10 {
+            assert_ne!(first, derive_challenges(&seed, layer, leaves, 8, 0));
+        }
+
+        // Though a layer's challenges depend on nothing else.
+        assert_eq!(first, derive_challenges(&seed, 0, leaves, 8, 0));
+    }
+
+    #[test]
+    fn pinned_challenge_vectors() {
+        let mut seed = [0; 32];
+        for (i, b) in seed.iter_mut().enumerate() {
+            *b = i as u8;
+        }
+
+        // Any change to the derivation changes the challenges of every proof, and so must be
+        // deliberate.
+        let vectors: &[(u8, usize, usize, u8, &[usize])] = &[
+            (0, 1 << 20, 4, 0, &[63923, 955220, 129380, 40604]),
+            (1, 1 << 20, 4, 0, &[171625, 889823, 92242, 661787]),
+            (1, 1 << 20, 4, 1, &[913274, 420692, 356953, 128097]),
+            (3, 1024, 6, 0, &[462, 577, 756, 529, 396, 991]),
+            (0, 12, 12, 0, &[7, 10, 8, 4, 9, 6, 2, 3, 1, 5, 10, 3]),
+        ];
+
+        for (layer, leaves, count, k, expected) in vectors {
+            assert_eq!(
+                expected.to_vec(),
+                derive_challenges(&seed, *layer, *leaves, *count, *k),
+                "layer {}, leaves {}, count {}, k {}",
+                layer,
+                leaves,
+                count,
+                k
+            );
+        }
+    }
 }
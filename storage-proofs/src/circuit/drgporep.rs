@@ -10,7 +10,7 @@ use crate::circuit::kdf::kdf;
 use crate::circuit::sloth;
 use crate::circuit::variables::Root;
 use crate::compound_proof::{CircuitComponent, CompoundProof};
-use crate::drgporep::DrgPoRep;
+use crate::drgporep::{self, DrgPoRep};
 use crate::drgraph::Graph;
 use crate::fr32::fr_into_bytes;
 use crate::merklepor;
@@ -148,21 +148,21 @@ impl<E: JubjubEngine, C: Circuit<E>, H: Hasher, G: Graph<H>, P: ParameterSetIden
     }
 }
 
-impl<'a, H, G> CompoundProof<'a, Bls12, DrgPoRep<'a, H, G>, DrgPoRepCircuit<'a, Bls12>>
-    for DrgPoRepCompound<H, G>
+impl<H, G> DrgPoRepCompound<H, G>
 where
-    H: 'a + Hasher,
-    G: 'a + Graph<H> + ParameterSetIdentifier + Sync + Send,
+    H: Hasher,
+    G: Graph<H> + ParameterSetIdentifier,
 {
-    fn generate_public_inputs(
-        pub_in: &<DrgPoRep<'a, H, G> as ProofScheme<'a>>::PublicInputs,
-        pub_params: &<DrgPoRep<'a, H, G> as ProofScheme<'a>>::PublicParams,
-        // We can ignore k because challenges are generated by caller and included
-        // in PublicInputs.
-        _k: Option<usize>,
+    /// The public inputs of a proof of the `layer`th layer of a layered replication. The
+    /// challenges are derived from the seed outside the circuit, and enter it as the nodes whose
+    /// inclusion it proves.
+    pub fn generate_public_inputs_at_layer(
+        pub_in: &drgporep::PublicInputs<H::Domain>,
+        pub_params: &drgporep::PublicParams<H, G>,
+        layer: usize,
     ) -> Vec<Fr> {
         let replica_id = pub_in.replica_id;
-        let challenges = &pub_in.challenges;
+        let challenges = pub_in.challenges(pub_params.graph.size(), layer);
         let (comm_r, comm_d) = match pub_in.tau {
             None => (None, None),
             Some(tau) => (Some(tau.comm_r), Some(tau.comm_d)),
@@ -184,8 +184,8 @@ where
         input.extend(packed_replica_id.clone());
 
         for challenge in challenges {
-            let mut por_nodes = vec![*challenge];
-            let parents = pub_params.graph.parents(*challenge);
+            let mut por_nodes = vec![challenge];
+            let parents = pub_params.graph.parents(challenge);
             por_nodes.extend(parents);
 
             for node in por_nodes {
@@ -204,7 +204,7 @@ where
 
             let por_pub_inputs = merklepor::PublicInputs {
                 commitment: comm_d,
-                challenge: *challenge,
+                challenge,
             };
 
             let por_inputs =
@@ -213,6 +213,23 @@ where
         }
         input
     }
+}
+
+impl<'a, H, G> CompoundProof<'a, Bls12, DrgPoRep<'a, H, G>, DrgPoRepCircuit<'a, Bls12>>
+    for DrgPoRepCompound<H, G>
+where
+    H: 'a + Hasher,
+    G: 'a + Graph<H> + ParameterSetIdentifier + Sync + Send,
+{
+    fn generate_public_inputs(
+        pub_in: &<DrgPoRep<'a, H, G> as ProofScheme<'a>>::PublicInputs,
+        pub_params: &<DrgPoRep<'a, H, G> as ProofScheme<'a>>::PublicParams,
+        k: Option<usize>,
+    ) -> Vec<Fr> {
+        let pub_in = DrgPoRep::<H, G>::with_partition(pub_in.clone(), k);
+
+        Self::generate_public_inputs_at_layer(&pub_in, pub_params, 0)
+    }
 
     fn circuit<'b>(
         public_inputs: &'b <DrgPoRep<'a, H, G> as ProofScheme<'a>>::PublicInputs,
@@ -462,6 +479,7 @@ mod tests {
     use super::*;
     use crate::circuit::test::*;
     use crate::compound_proof;
+    use crate::crypto::feistel::FeistelConfig;
     use crate::drgporep;
     use crate::drgraph::{graph_height, new_seed, BucketGraph};
     use crate::fr32::{bytes_into_fr, fr_into_bytes};
@@ -505,6 +523,7 @@ mod tests {
                 degree,
                 expansion_degree: 0,
                 seed: new_seed(),
+                feistel: FeistelConfig::default(),
             },
             sloth_iter,
         };
@@ -519,12 +538,12 @@ mod tests {
         )
         .expect("failed to replicate");
 
-        let pub_inputs = drgporep::PublicInputs {
-            replica_id: replica_id.into(),
-            challenges: vec![challenge],
-            tau: Some(tau.into()),
-        };
-        let priv_inputs = drgporep::PrivateInputs::<PedersenHasher> { aux: &aux };
+        let pub_inputs = drgporep::PublicInputs::with_challenges(
+            replica_id.into(),
+            &[challenge],
+            Some(tau.into()),
+        );
+        let priv_inputs = drgporep::PrivateInputs::<PedersenHasher>::new(&aux);
 
         let proof_nc =
             drgporep::DrgPoRep::<PedersenHasher, _>::prove(&pp, &pub_inputs, &priv_inputs)
@@ -662,6 +681,7 @@ mod tests {
                     degree,
                     expansion_degree: 0,
                     seed,
+                    feistel: FeistelConfig::default(),
                 },
                 sloth_iter,
             },
@@ -681,12 +701,12 @@ mod tests {
         )
         .expect("failed to replicate");
 
-        let public_inputs = drgporep::PublicInputs::<PedersenDomain> {
-            replica_id: replica_id.into(),
-            challenges,
-            tau: Some(tau),
-        };
-        let private_inputs = drgporep::PrivateInputs { aux: &aux };
+        let public_inputs = drgporep::PublicInputs::<PedersenDomain>::with_challenges(
+            replica_id.into(),
+            &challenges,
+            Some(tau),
+        );
+        let private_inputs = drgporep::PrivateInputs::new(&aux);
 
         // This duplication is necessary so public_params don't outlive public_inputs and private_inputs.
         let setup_params = compound_proof::SetupParams {
@@ -696,6 +716,7 @@ mod tests {
                     degree,
                     expansion_degree: 0,
                     seed,
+                    feistel: FeistelConfig::default(),
                 },
                 sloth_iter,
             },
@@ -205,16 +205,46 @@ impl<E: JubjubEngine, C: Circuit<E>, P: ParameterSetIdentifier> CacheableParamet
     }
 }
 
-impl<'a, H: 'static + Hasher>
-    CompoundProof<'a, Bls12, ZigZagDrgPoRep<'a, H>, ZigZagCircuit<'a, Bls12, H>>
-    for ZigZagCompound
-{
-    fn generate_public_inputs(
+/// The version of the layout of the circuit's public inputs, as returned by
+/// `ZigZagCompound::public_input_vector`. It's bumped whenever an input is added, removed or
+/// moved, as every external verifier must then be changed to match.
+pub const PUBLIC_INPUTS_VERSION: u32 = 1;
+
+impl ZigZagCompound {
+    /// Returns the public inputs the circuit of partition `pub_in.k` (0 if unset) is verified
+    /// against, in this order:
+    ///
+    /// 1. `comm_d`
+    /// 2. `comm_r`
+    /// 3. For each layer, in order:
+    ///    * the replica id's low `Fr::CAPACITY` bits, packed into one element;
+    ///    * for each of the layer's challenges, in order: the packed auth path of the challenged
+    ///      node and then of each of its parents, in ascending order, in the layer's replica
+    ///      tree, followed by the packed auth path of the challenged node in the layer's data
+    ///      tree.
+    /// 4. `comm_r_star`
+    ///
+    /// An auth path is packed as the node's index, its bits taken least significant first, one
+    /// per level of the tree. The layers' own commitments aren't inputs. See
+    /// `PUBLIC_INPUTS_VERSION`.
+    pub fn public_input_vector<H: 'static + Hasher>(
         pub_in: &<ZigZagDrgPoRep<H> as ProofScheme>::PublicInputs,
         pub_params: &<ZigZagDrgPoRep<H> as ProofScheme>::PublicParams,
-        k: Option<usize>,
     ) -> Vec<Fr> {
-        let mut inputs = Vec::new();
+        Self::public_inputs_for_partition::<H>(pub_in, pub_params, pub_in.k)
+    }
+
+    /// Writes the public inputs of the circuit of partition `k` (0 if None) into `inputs`,
+    /// which is cleared first, in the order public_input_vector returns them. Verifiers checking
+    /// many proofs can so reuse the one vector, which stops growing once it has held the inputs
+    /// of a partition.
+    pub fn public_inputs_into<H: 'static + Hasher>(
+        pub_in: &<ZigZagDrgPoRep<H> as ProofScheme>::PublicInputs,
+        pub_params: &<ZigZagDrgPoRep<H> as ProofScheme>::PublicParams,
+        k: Option<usize>,
+        inputs: &mut Vec<Fr>,
+    ) {
+        inputs.clear();
 
         let mut drgporep_pub_params = drgporep::PublicParams::new(
             pub_params.drg_porep_public_params.graph.clone(),
@@ -230,18 +260,15 @@ impl<'a, H: 'static + Hasher>
         for i in 0..pub_params.layer_challenges.layers() {
             let drgporep_pub_inputs = drgporep::PublicInputs {
                 replica_id: pub_in.replica_id,
-                challenges: pub_in.challenges(
-                    &pub_params.layer_challenges,
-                    pub_params.drg_porep_public_params.graph.size(),
-                    i as u8,
-                    k,
-                ),
+                challenge_seed: pub_in.challenge_seed,
+                challenge_count: pub_params.layer_challenges.challenges_for_layer(i),
+                k,
                 tau: None,
             };
-            let drgporep_inputs = DrgPoRepCompound::generate_public_inputs(
+            let drgporep_inputs = DrgPoRepCompound::generate_public_inputs_at_layer(
                 &drgporep_pub_inputs,
                 &drgporep_pub_params,
-                None,
+                i,
             );
             inputs.extend(drgporep_inputs);
 
@@ -252,8 +279,30 @@ impl<'a, H: 'static + Hasher>
             );
         }
         inputs.push(pub_in.comm_r_star.into());
+    }
+
+    fn public_inputs_for_partition<H: 'static + Hasher>(
+        pub_in: &<ZigZagDrgPoRep<H> as ProofScheme>::PublicInputs,
+        pub_params: &<ZigZagDrgPoRep<H> as ProofScheme>::PublicParams,
+        k: Option<usize>,
+    ) -> Vec<Fr> {
+        let mut inputs = Vec::new();
+        Self::public_inputs_into::<H>(pub_in, pub_params, k, &mut inputs);
         inputs
     }
+}
+
+impl<'a, H: 'static + Hasher>
+    CompoundProof<'a, Bls12, ZigZagDrgPoRep<'a, H>, ZigZagCircuit<'a, Bls12, H>>
+    for ZigZagCompound
+{
+    fn generate_public_inputs(
+        pub_in: &<ZigZagDrgPoRep<H> as ProofScheme>::PublicInputs,
+        pub_params: &<ZigZagDrgPoRep<H> as ProofScheme>::PublicParams,
+        k: Option<usize>,
+    ) -> Vec<Fr> {
+        Self::public_inputs_for_partition::<H>(pub_in, pub_params, k)
+    }
 
     fn circuit<'b>(
         public_inputs: &'b <ZigZagDrgPoRep<H> as ProofScheme>::PublicInputs,
@@ -267,7 +316,9 @@ impl<'a, H: 'static + Hasher>
                 let layer_public_inputs = drgporep::PublicInputs {
                     replica_id: public_inputs.replica_id,
                     // Challenges are not used in circuit synthesis. Don't bother generating.
-                    challenges: vec![],
+                    challenge_seed: public_inputs.challenge_seed,
+                    challenge_count: 0,
+                    k: None,
                     tau: None,
                 };
                 let layer_proof = vanilla_proof.encoding_proofs[l].clone();
@@ -300,7 +351,9 @@ impl<'a, H: 'static + Hasher>
                 let layer_public_inputs = drgporep::PublicInputs {
                     replica_id,
                     // Challenges are not used in circuit synthesis. Don't bother generating.
-                    challenges: vec![],
+                    challenge_seed: [0; 32],
+                    challenge_count: 0,
+                    k: None,
                     tau: None,
                 };
                 (layer_public_inputs, None)
@@ -327,6 +380,7 @@ mod tests {
     use super::*;
     use crate::circuit::test::*;
     use crate::compound_proof;
+    use crate::crypto::feistel::FeistelConfig;
     use crate::drgporep;
     use crate::drgraph::new_seed;
     use crate::fr32::fr_into_bytes;
@@ -334,9 +388,10 @@ mod tests {
     use crate::layered_drgporep::{self, LayerChallenges};
     use crate::porep::PoRep;
     use crate::proof::ProofScheme;
-    use crate::zigzag_graph::{ZigZag, ZigZagGraph};
+    use crate::zigzag_graph::{ZigZag, ZigZagBucketGraph, ZigZagGraph};
 
-    use pairing::Field;
+    use pairing::bls12_381::FrRepr;
+    use pairing::{Field, PrimeField};
     use rand::{Rng, SeedableRng, XorShiftRng};
     use sapling_crypto::jubjub::JubjubBls12;
 
@@ -370,6 +425,7 @@ mod tests {
                     degree,
                     expansion_degree,
                     seed: new_seed(),
+                    feistel: FeistelConfig::default(),
                 },
                 sloth_iter,
             },
@@ -386,6 +442,10 @@ mod tests {
 
         let pub_inputs = layered_drgporep::PublicInputs::<PedersenDomain> {
             replica_id: replica_id.into(),
+            challenge_seed: layered_drgporep::challenge_seed(
+                &replica_id.into(),
+                &tau.comm_r_star,
+            ),
             tau: Some(tau.simplify().into()),
             comm_r_star: tau.comm_r_star.into(),
             k: None,
@@ -444,6 +504,101 @@ mod tests {
 
         // This test was modeled on equivalent from drgporep circuit.
         // TODO: add add assertions about other inputs.
+
+        let inputs = ZigZagCompound::public_input_vector::<PedersenHasher>(&pub_inputs, &pp);
+        assert!(cs.verify(&inputs), "the proof's own inputs were refused");
+
+        // The proof doesn't verify as the proof of another replica id, as of another prover's
+        // or another sector's, over the same commitments.
+        let other_id: Fr = rng.gen();
+        let transplanted = layered_drgporep::PublicInputs::<PedersenDomain> {
+            replica_id: other_id.into(),
+            challenge_seed: layered_drgporep::challenge_seed(
+                &other_id.into(),
+                &pub_inputs.comm_r_star,
+            ),
+            ..pub_inputs.clone()
+        };
+        let inputs = ZigZagCompound::public_input_vector::<PedersenHasher>(&transplanted, &pp);
+        assert!(!cs.verify(&inputs), "the proof verified under another replica id");
+    }
+
+    // The public inputs of a three node graph, whose only challengeable node is 1, so that they
+    // can be worked out by hand: node 1's parent is node 0 in the forward layers, and node 2 in
+    // the reversed one, and each auth path packs to the index of its node.
+    const GOLDEN_PUBLIC_INPUTS: [&str; 15] = [
+        // comm_d, comm_r
+        "0x0000000000000000000000000000000000000000000000000000000000000011",
+        "0x0000000000000000000000000000000000000000000000000000000000000022",
+        // layer 0: replica id, node 1 and its parent 0 in tree_r, node 1 in tree_d
+        "0x0000000000000000000000000000000000000000000000000000000000000044",
+        "0x0000000000000000000000000000000000000000000000000000000000000001",
+        "0x0000000000000000000000000000000000000000000000000000000000000000",
+        "0x0000000000000000000000000000000000000000000000000000000000000001",
+        // layer 1 (reversed): replica id, node 1 and its parent 2, node 1
+        "0x0000000000000000000000000000000000000000000000000000000000000044",
+        "0x0000000000000000000000000000000000000000000000000000000000000001",
+        "0x0000000000000000000000000000000000000000000000000000000000000002",
+        "0x0000000000000000000000000000000000000000000000000000000000000001",
+        // layer 2: as layer 0
+        "0x0000000000000000000000000000000000000000000000000000000000000044",
+        "0x0000000000000000000000000000000000000000000000000000000000000001",
+        "0x0000000000000000000000000000000000000000000000000000000000000000",
+        "0x0000000000000000000000000000000000000000000000000000000000000001",
+        // comm_r_star
+        "0x0000000000000000000000000000000000000000000000000000000000000033",
+    ];
+
+    #[test]
+    fn public_input_vector_layout_is_pinned() {
+        // If this fails, the layout changed: bump PUBLIC_INPUTS_VERSION along with the golden
+        // inputs, as every external verifier breaks.
+        assert_eq!(1, PUBLIC_INPUTS_VERSION);
+
+        let fr = |n: u64| Fr::from_repr(FrRepr::from(n)).unwrap();
+
+        let pub_params = layered_drgporep::PublicParams {
+            drg_porep_public_params: drgporep::PublicParams::new(
+                ZigZagBucketGraph::<PedersenHasher>::new_zigzag(3, 1, 0, [7; 7]),
+                1,
+            ),
+            layer_challenges: LayerChallenges::new_fixed(3, 1),
+        };
+
+        // The inputs are laid out whether or not they're consistent.
+        let pub_in = layered_drgporep::PublicInputs::<PedersenDomain> {
+            replica_id: fr(0x44).into(),
+            challenge_seed: [0; 32],
+            tau: Some(porep::Tau {
+                comm_d: fr(0x11).into(),
+                comm_r: fr(0x22).into(),
+            }),
+            comm_r_star: fr(0x33).into(),
+            k: None,
+        };
+
+        let inputs: Vec<String> =
+            ZigZagCompound::public_input_vector::<PedersenHasher>(&pub_in, &pub_params)
+                .iter()
+                .map(|input| input.into_repr().to_string())
+                .collect();
+
+        assert_eq!(GOLDEN_PUBLIC_INPUTS.to_vec(), inputs);
+
+        // Written into a reused vector, the inputs are the same.
+        let mut reused = vec![fr(0x55); 100];
+        ZigZagCompound::public_inputs_into::<PedersenHasher>(
+            &pub_in,
+            &pub_params,
+            None,
+            &mut reused,
+        );
+        let reused: Vec<String> = reused
+            .iter()
+            .map(|input| input.into_repr().to_string())
+            .collect();
+
+        assert_eq!(inputs, reused);
     }
 
     #[test]
@@ -465,11 +620,8 @@ mod tests {
         let layers = (0..num_layers)
             .map(|_l| {
                 // l is ignored because we assume uniform layers here.
-                let public_inputs = drgporep::PublicInputs {
-                    replica_id: replica_id.into(),
-                    challenges: vec![challenge],
-                    tau: None,
-                };
+                let public_inputs =
+                    drgporep::PublicInputs::with_challenges(replica_id.into(), &[challenge], None);
                 let proof = None;
                 (public_inputs, proof)
             })
@@ -535,6 +687,7 @@ mod tests {
                         degree,
                         expansion_degree,
                         seed: new_seed(),
+                        feistel: FeistelConfig::default(),
                     },
                     sloth_iter,
                 },
@@ -555,6 +708,10 @@ mod tests {
 
         let public_inputs = layered_drgporep::PublicInputs::<PedersenDomain> {
             replica_id: replica_id.into(),
+            challenge_seed: layered_drgporep::challenge_seed(
+                &replica_id.into(),
+                &tau.comm_r_star,
+            ),
             tau: Some(tau.simplify()),
             comm_r_star: tau.comm_r_star,
             k: None,
@@ -586,7 +743,7 @@ mod tests {
             &public_params,
             &public_inputs,
             &private_inputs,
-            Some(blank_groth_params),
+            Some(blank_groth_params.clone()),
         )
         .expect("failed while proving");
 
@@ -594,5 +751,29 @@ mod tests {
             .expect("failed while verifying");
 
         assert!(verified);
+
+        // Proving the vanilla proofs needs nothing more of the replica, so they still prove once
+        // the private inputs are dropped.
+        let vanilla_proofs = ZigZagDrgPoRep::prove_all_partitions(
+            &public_params.vanilla_params,
+            &public_inputs,
+            &private_inputs,
+            partition_count,
+        )
+        .unwrap();
+        drop(private_inputs);
+
+        let proof = ZigZagCompound::prove_vanilla_proofs(
+            &public_params,
+            &public_inputs,
+            &vanilla_proofs,
+            Some(blank_groth_params),
+        )
+        .expect("failed while proving vanilla proofs");
+
+        let verified = ZigZagCompound::verify(&public_params, &public_inputs, &proof)
+            .expect("failed while verifying");
+
+        assert!(verified);
     }
 }
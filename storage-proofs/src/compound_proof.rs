@@ -2,9 +2,10 @@ use rayon::prelude::*;
 
 use crate::circuit::multi_proof::MultiProof;
 use crate::error::Result;
-use crate::parameter_cache::{CacheableParameters, ParameterSetIdentifier};
+use crate::parameter_cache::{CacheableParameters, ParameterPhase, ParameterSetIdentifier};
 use crate::partitions;
 use crate::proof::ProofScheme;
+use crate::yield_hook::{self, PROVING};
 use bellman::{groth16, Circuit};
 use rand::OsRng;
 use sapling_crypto::jubjub::JubjubEngine;
@@ -77,13 +78,31 @@ where
         E::Params: Sync,
     {
         let partitions = Self::partition_count(pub_params);
-        let partition_count = Self::partition_count(pub_params);
 
         let vanilla_proofs =
             S::prove_all_partitions(&pub_params.vanilla_params, &pub_in, priv_in, partitions)?;
 
+        Self::prove_vanilla_proofs(pub_params, pub_in, &vanilla_proofs, groth_params)
+    }
+
+    /// prove_vanilla_proofs is as prove, but proves the vanilla proof of each partition, as
+    /// returned by ProofScheme::prove_all_partitions. The circuits are built from the vanilla
+    /// proofs alone, so callers may free the private inputs, however large, before proving.
+    /// The yield hook (see yield_hook) is called before each partition is proven.
+    fn prove_vanilla_proofs<'b>(
+        pub_params: &'b PublicParams<'a, E, S>,
+        pub_in: &'b S::PublicInputs,
+        vanilla_proofs: &'b [S::Proof],
+        groth_params: Option<groth16::Parameters<E>>,
+    ) -> Result<MultiProof<E>>
+    where
+        E::Params: Sync,
+    {
+        let partition_count = Self::partition_count(pub_params);
+        assert_eq!(partition_count, vanilla_proofs.len(), "one vanilla proof per partition");
+
         let sanity_check =
-            S::verify_all_partitions(&pub_params.vanilla_params, &pub_in, &vanilla_proofs)?;
+            S::verify_all_partitions(&pub_params.vanilla_params, &pub_in, vanilla_proofs)?;
         assert!(sanity_check, "sanity check failed");
 
         // This will always run at least once, since there cannot be zero partitions.
@@ -108,6 +127,8 @@ where
         let groth_proofs: Result<Vec<_>> = vanilla_proofs
             .par_iter()
             .map(|vanilla_proof| {
+                yield_hook::yield_now(PROVING)?;
+
                 Self::circuit_proof(
                     pub_in,
                     &vanilla_proof,
@@ -127,16 +148,34 @@ where
         public_inputs: &S::PublicInputs,
         multi_proof: &MultiProof<E>,
     ) -> Result<bool> {
-        let vanilla_public_params = &public_params.vanilla_params;
         let pvk = groth16::prepare_verifying_key(&multi_proof.groth_params.vk);
-        if multi_proof.circuit_proofs.len() != Self::partition_count(public_params) {
+
+        Self::verify_with_prepared_key(
+            public_params,
+            public_inputs,
+            &pvk,
+            &multi_proof.circuit_proofs,
+        )
+    }
+
+    /// verify_with_prepared_key is as verify, but takes the prepared verifying key and the
+    /// proof of each partition, so that callers verifying many proofs against the same
+    /// parameters need only prepare the key once.
+    fn verify_with_prepared_key(
+        public_params: &PublicParams<'a, E, S>,
+        public_inputs: &S::PublicInputs,
+        pvk: &groth16::PreparedVerifyingKey<E>,
+        circuit_proofs: &[groth16::Proof<E>],
+    ) -> Result<bool> {
+        let vanilla_public_params = &public_params.vanilla_params;
+        if circuit_proofs.len() != Self::partition_count(public_params) {
             return Ok(false);
         }
-        for (k, circuit_proof) in multi_proof.circuit_proofs.iter().enumerate() {
+        for (k, circuit_proof) in circuit_proofs.iter().enumerate() {
             let inputs =
                 Self::generate_public_inputs(public_inputs, vanilla_public_params, Some(k));
 
-            if !groth16::verify_proof(&pvk, &circuit_proof, inputs.as_slice())? {
+            if !groth16::verify_proof(pvk, &circuit_proof, inputs.as_slice())? {
                 return Ok(false);
             }
         }
@@ -213,6 +252,37 @@ where
         )
     }
 
+    /// Like groth_params, but reports the progress of generating parameters
+    /// which are not yet cached to `progress`.
+    fn groth_params_with_progress(
+        public_params: &S::PublicParams,
+        engine_params: &'a E::Params,
+        progress: &mut FnMut(ParameterPhase, f64),
+    ) -> Result<groth16::Parameters<E>> {
+        Self::get_groth_params_with_progress(
+            Self::blank_circuit(public_params, engine_params),
+            public_params,
+            progress,
+        )
+    }
+
+    /// Like groth_params_with_progress, but stops generating parameters, with
+    /// the error it returns, once `checkpoint` fails (see
+    /// CacheableParameters::get_groth_params_cancellable).
+    fn groth_params_cancellable(
+        public_params: &S::PublicParams,
+        engine_params: &'a E::Params,
+        progress: &mut FnMut(ParameterPhase, f64),
+        checkpoint: &Fn(ParameterPhase) -> Result<()>,
+    ) -> Result<groth16::Parameters<E>> {
+        Self::get_groth_params_cancellable(
+            Self::blank_circuit(public_params, engine_params),
+            public_params,
+            progress,
+            checkpoint,
+        )
+    }
+
     fn circuit_for_test(
         public_parameters: &PublicParams<'a, E, S>,
         public_inputs: &S::PublicInputs,
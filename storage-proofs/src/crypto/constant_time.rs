@@ -0,0 +1,121 @@
+//! Comparisons of commitments, digests and proofs whose time doesn't depend on
+//! where, or whether, the values compared differ. Verification compares values
+//! a prover supplies with values it computes, so those comparisons are made
+//! here rather than with `==`, which returns at the first byte to differ.
+//!
+//! Some comparisons of such values are left variable-time, deliberately:
+//!
+//! - lengths, versions and proof variants, which are public;
+//! - the digests of parameter files, which are computed over published files
+//!   and named in the errors reported when they differ;
+//! - the PartialEq impls of the sector builder's metadata, which compare
+//!   records the builder wrote itself, rather than anything claimed to it;
+//! - debug_tools, which is there to report where two replications differ.
+//!
+//! Such a comparison of a commitment, digest, checksum, replica id, root or
+//! proof follows a comment starting `variable-time:` which says why it's left
+//! as it is, as filecoin-proofs' constant_time_lint test flags those which
+//! don't.
+
+use std::ptr;
+
+/// Whether a and b hold the same bytes, comparing all of them whatever their
+/// values. Only their lengths, which are taken to be public, are compared in
+/// variable time.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    let diff = a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y));
+
+    // Reading the accumulated difference back through a volatile read keeps
+    // the compiler from making the fold stop early once it knows the answer.
+    unsafe { ptr::read_volatile(&diff) == 0 }
+}
+
+/// Equality compared in constant time, as constant_time_eq compares bytes.
+/// The hashers' domains have the same method, through Domain.
+pub trait ConstantTimeEq {
+    fn ct_eq(&self, other: &Self) -> bool;
+}
+
+impl ConstantTimeEq for [u8] {
+    fn ct_eq(&self, other: &[u8]) -> bool {
+        constant_time_eq(self, other)
+    }
+}
+
+impl ConstantTimeEq for Vec<u8> {
+    fn ct_eq(&self, other: &Vec<u8>) -> bool {
+        constant_time_eq(self, other)
+    }
+}
+
+// Commitments, and the digests which are of the same size.
+impl ConstantTimeEq for [u8; 32] {
+    fn ct_eq(&self, other: &[u8; 32]) -> bool {
+        constant_time_eq(self, other)
+    }
+}
+
+// Prover and sector ids.
+impl ConstantTimeEq for [u8; 31] {
+    fn ct_eq(&self, other: &[u8; 31]) -> bool {
+        constant_time_eq(self, other)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::pedersen::PedersenDomain;
+    use crate::hasher::Domain;
+    use proptest::prelude::*;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    proptest! {
+        #[test]
+        fn agrees_with_eq(
+            ref a in prop::collection::vec(any::<u8>(), 0..40),
+            ref b in prop::collection::vec(any::<u8>(), 0..40)
+        ) {
+            assert_eq!(a == b, constant_time_eq(&a, &b));
+            assert_eq!(a == b, a.ct_eq(&b));
+            assert!(a.ct_eq(&a.clone()));
+        }
+    }
+
+    #[test]
+    fn any_differing_byte_is_found() {
+        let commitment = [7; 32];
+
+        for i in 0..32 {
+            for bit in 0..8 {
+                let mut other = commitment;
+                other[i] ^= 1 << bit;
+
+                assert!(!commitment.ct_eq(&other));
+                assert!(!commitment[..i + 1].ct_eq(&other[..i + 1]));
+                assert!(commitment[..i].ct_eq(&other[..i]));
+            }
+        }
+
+        assert!(commitment.ct_eq(&[7; 32]));
+        assert!(!constant_time_eq(&commitment, &commitment[1..]));
+        assert!([1; 31].ct_eq(&[1; 31]) && ![1; 31].ct_eq(&[2; 31]));
+    }
+
+    #[test]
+    fn domains_agree_with_eq() {
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        for _ in 0..100 {
+            let a: PedersenDomain = rng.gen();
+            let b: PedersenDomain = rng.gen();
+
+            assert!(a.ct_eq(&a));
+            assert_eq!(a == b, a.ct_eq(&b));
+        }
+    }
+}
@@ -1,8 +1,39 @@
 use blake2::{Blake2s, Digest};
 
 pub const FEISTEL_ROUNDS: usize = 3;
+pub const FEISTEL_KEYS: [u32; 4] = [1, 2, 3, 4];
 pub type FeistelPrecomputed = (u32, u32, u32);
 
+/// The keys and number of rounds a permutation is computed with: the first
+/// `rounds` keys are used, one per round, and any after them are ignored.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeistelConfig {
+    pub keys: Vec<u32>,
+    pub rounds: usize,
+}
+
+impl FeistelConfig {
+    pub fn new(keys: Vec<u32>, rounds: usize) -> FeistelConfig {
+        assert!(rounds > 0, "a permutation takes at least one round");
+        assert!(
+            keys.len() >= rounds,
+            "{} rounds take as many keys, but {} were given",
+            rounds,
+            keys.len()
+        );
+
+        FeistelConfig { keys, rounds }
+    }
+}
+
+impl Default for FeistelConfig {
+    /// The keys and rounds of the protocol, which zigzag graphs are built with
+    /// unless configured otherwise.
+    fn default() -> FeistelConfig {
+        FeistelConfig::new(FEISTEL_KEYS.to_vec(), FEISTEL_ROUNDS)
+    }
+}
+
 pub fn precompute(num_elements: u32) -> FeistelPrecomputed {
     let mut next_pow4 = 4;
     let mut log4 = 1;
@@ -23,12 +54,13 @@ pub fn permute(
     num_elements: u32,
     index: u32,
     keys: &[u32],
+    rounds: usize,
     precomputed: FeistelPrecomputed,
 ) -> u32 {
-    let mut u = encode(index, keys, precomputed);
+    let mut u = encode(index, keys, rounds, precomputed);
 
     while u >= num_elements {
-        u = encode(u, keys, precomputed)
+        u = encode(u, keys, rounds, precomputed)
     }
     u
 }
@@ -37,12 +69,13 @@ pub fn invert_permute(
     num_elements: u32,
     index: u32,
     keys: &[u32],
+    rounds: usize,
     precomputed: FeistelPrecomputed,
 ) -> u32 {
-    let mut u = decode(index, keys, precomputed);
+    let mut u = decode(index, keys, rounds, precomputed);
 
     while u >= num_elements {
-        u = decode(u, keys, precomputed);
+        u = decode(u, keys, rounds, precomputed);
     }
     u
 }
@@ -57,10 +90,10 @@ fn common_setup(index: u32, precomputed: FeistelPrecomputed) -> (u32, u32, u32,
     (left, right, right_mask, half_bits)
 }
 
-fn encode(index: u32, keys: &[u32], precomputed: FeistelPrecomputed) -> u32 {
+fn encode(index: u32, keys: &[u32], rounds: usize, precomputed: FeistelPrecomputed) -> u32 {
     let (mut left, mut right, right_mask, half_bits) = common_setup(index, precomputed);
 
-    for key in keys.iter().take(FEISTEL_ROUNDS) {
+    for key in &keys[..rounds] {
         let (l, r) = (right, left ^ feistel(right, *key, right_mask));
         left = l;
         right = r;
@@ -69,10 +102,10 @@ fn encode(index: u32, keys: &[u32], precomputed: FeistelPrecomputed) -> u32 {
     (left << half_bits) | right
 }
 
-fn decode(index: u32, keys: &[u32], precomputed: FeistelPrecomputed) -> u32 {
+fn decode(index: u32, keys: &[u32], rounds: usize, precomputed: FeistelPrecomputed) -> u32 {
     let (mut left, mut right, right_mask, half_bits) = common_setup(index, precomputed);
 
-    for i in (0..FEISTEL_ROUNDS).rev() {
+    for i in (0..rounds).rev() {
         let (l, r) = ((right ^ feistel(left, keys[i], right_mask)), left);
         left = l;
         right = r;
@@ -115,8 +148,8 @@ mod tests {
         let mut failed = false;
         let precomputed = precompute(n);
         for i in 0..n {
-            let p = encode(i, &[1, 2, 3, 4], precomputed);
-            let v = decode(p, &[1, 2, 3, 4], precomputed);
+            let p = encode(i, &FEISTEL_KEYS, FEISTEL_ROUNDS, precomputed);
+            let v = decode(p, &FEISTEL_KEYS, FEISTEL_ROUNDS, precomputed);
             let equal = i == v;
             let in_range = p <= n;
             if expect_success {
@@ -157,8 +190,8 @@ mod tests {
         for n in BAD_NS.iter() {
             let precomputed = precompute(*n as u32);
             for i in 0..*n {
-                let p = permute(*n, i, &[1, 2, 3, 4], precomputed);
-                let v = invert_permute(*n, p, &[1, 2, 3, 4], precomputed);
+                let p = permute(*n, i, &FEISTEL_KEYS, FEISTEL_ROUNDS, precomputed);
+                let v = invert_permute(*n, p, &FEISTEL_KEYS, FEISTEL_ROUNDS, precomputed);
                 // Since every element in the set is reversibly mapped to another element also in the set,
                 // this is indeed a permutation.
                 assert_eq!(i, v, "failed to permute");
@@ -166,4 +199,27 @@ mod tests {
             }
         }
     }
+
+    #[test]
+    fn rounds_are_given_rather_than_taken_from_the_keys() {
+        let precomputed = precompute(64);
+        let permutation = |rounds| {
+            (0..64)
+                .map(|i| permute(64, i, &FEISTEL_KEYS, rounds, precomputed))
+                .collect::<Vec<_>>()
+        };
+
+        assert_ne!(permutation(3), permutation(4));
+
+        for i in 0..64 {
+            let p = permute(64, i, &FEISTEL_KEYS, 4, precomputed);
+            assert_eq!(i, invert_permute(64, p, &FEISTEL_KEYS, 4, precomputed));
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "4 rounds take as many keys, but 3 were given")]
+    fn configs_need_a_key_per_round() {
+        FeistelConfig::new(vec![1, 2, 3], 4);
+    }
 }
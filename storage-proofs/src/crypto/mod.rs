@@ -1,5 +1,6 @@
 pub mod aes;
 pub mod blake2s;
+pub mod constant_time;
 pub mod feistel;
 pub mod kdf;
 pub mod pedersen;
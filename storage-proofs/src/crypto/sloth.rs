@@ -1,3 +1,24 @@
+//! Sloth, the verifiable delay encoding used by the VDE to encode each node of a replica.
+//!
+//! For `rounds > 0`, each round computes `c = (c + k)^v` in the scalar field, where `v` is the
+//! inverse of 5 modulo `r - 1`. Decoding inverts each round with the much cheaper `c^5 - k`, which
+//! is what makes the encoding slow to compute but fast to verify. With `rounds == 0` the encoding
+//! degenerates to field addition of the key.
+//!
+//! The vectors in `tests::pinned_vectors` are shared with other implementations; any change to
+//! the arithmetic here must be reflected there deliberately.
+//!
+//! # Timing considerations
+//!
+//! - The exponents `v` and `5` are public constants, so the sequence of squarings and
+//!   multiplications performed by `pow` does not depend on the key or the data.
+//! - The field arithmetic in `pairing` is not guaranteed to be constant-time: reductions after
+//!   additions and Montgomery multiplications branch on their operands. Run time may therefore
+//!   vary slightly with the key and plaintext.
+//! - Both the key (derived from the replica id and parent nodes) and the plaintext (the sector
+//!   data, which the prover stores) are known to the prover, so this leaks nothing the prover does
+//!   not already have. Do not use these functions to encrypt data under a secret key.
+
 use pairing::{Engine, Field};
 
 pub const DEFAULT_ROUNDS: usize = 1;
@@ -20,7 +41,7 @@ const SLOTH_V: [u64; 4] = [
 /// The number five, as an array so we can use it in `pow`.
 const FIVE: [u64; 1] = [5];
 
-/// Sloth based encoding.
+/// Sloth based encoding of `plaintext` under `key`, applying `rounds` rounds of `(c + k)^v`.
 pub fn encode<E: Engine>(key: &E::Fr, plaintext: &E::Fr, rounds: usize) -> E::Fr {
     let mut ciphertext = *plaintext;
 
@@ -36,7 +57,7 @@ pub fn encode<E: Engine>(key: &E::Fr, plaintext: &E::Fr, rounds: usize) -> E::Fr
     ciphertext
 }
 
-/// Sloth based decoding.
+/// Sloth based decoding, the inverse of `encode` for the same `key` and `rounds`.
 pub fn decode<E: Engine>(key: &E::Fr, ciphertext: &E::Fr, rounds: usize) -> E::Fr {
     let mut plaintext = *ciphertext;
 
@@ -121,6 +142,79 @@ mod tests {
         assert_ne!(plaintext, decrypted);
     }
 
+    const R_MINUS_ONE_STR: &str =
+        &"52435875175126190479447740508185965837690552500527637822603658699938581184512";
+
+    // (key, plaintext, rounds, ciphertext), with every element of Fr in decimal.
+    const PINNED_VECTORS: [(&str, &str, usize, &str); 9] = [
+        ("11111111", "123456789", 0, "134567900"),
+        (
+            "11111111",
+            "123456789",
+            1,
+            "8012419965833876202904555801761465900367810720919349292197135105411877102914",
+        ),
+        (
+            "11111111",
+            "123456789",
+            10,
+            "4060864108586805501669732134355289714517139716820957007832564845415132731428",
+        ),
+        (R_MINUS_ONE_STR, "42", 0, "41"),
+        (
+            R_MINUS_ONE_STR,
+            "42",
+            1,
+            "2452868919594920079881588261359512739491002400154619405309554553452376992394",
+        ),
+        (
+            R_MINUS_ONE_STR,
+            "42",
+            10,
+            "43888807173281885209492426401650312980327641883043445397470520739435241165122",
+        ),
+        (
+            "1606938044258990275541962092341162602522202993782792835301383",
+            "52435875175126190479447740508185965837690552500527637822603658699938581184508",
+            0,
+            "1606938044258990275541962092341162602522202993782792835301378",
+        ),
+        (
+            "1606938044258990275541962092341162602522202993782792835301383",
+            "52435875175126190479447740508185965837690552500527637822603658699938581184508",
+            1,
+            "10379453850969695871882728427179583616133686817644110028438110349933523630848",
+        ),
+        (
+            "1606938044258990275541962092341162602522202993782792835301383",
+            "52435875175126190479447740508185965837690552500527637822603658699938581184508",
+            10,
+            "39455028585977823110295764412037458205966390921907249356179846971704583187110",
+        ),
+    ];
+
+    #[test]
+    fn pinned_vectors() {
+        for (key, plaintext, rounds, ciphertext) in PINNED_VECTORS.iter() {
+            let key = Fr::from_str(key).unwrap();
+            let plaintext = Fr::from_str(plaintext).unwrap();
+            let ciphertext = Fr::from_str(ciphertext).unwrap();
+
+            assert_eq!(
+                encode::<Bls12>(&key, &plaintext, *rounds),
+                ciphertext,
+                "encoding changed for rounds={}",
+                rounds
+            );
+            assert_eq!(
+                decode::<Bls12>(&key, &ciphertext, *rounds),
+                plaintext,
+                "decoding changed for rounds={}",
+                rounds
+            );
+        }
+    }
+
     prop_compose! {
         fn arb_fr()(a in 0..MODULUS[0], b in 0..MODULUS[1], c in 0..MODULUS[2], d in 0..MODULUS[3]) -> Fr {
             Fr::from_repr(FrRepr([a, b, c, d])).unwrap()
@@ -0,0 +1,478 @@
+//! Tools for finding where replication goes wrong, for when a change to the
+//! protocol or to the code alters what it produces, and all there is to go on
+//! is that commitments differ.
+//!
+//! diff_replication replicates the same data under two parameterizations (or
+//! with two encoders, to compare code paths), layer by layer, and reports the
+//! first node encoded differently, along with the parents each side encoded
+//! it with, and the commitments of each layer. diff_replica_files compares two
+//! replicas on disk node by node.
+
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+use crate::drgporep;
+use crate::drgraph::Graph;
+use crate::error::Result;
+use crate::hasher::{Domain, Hasher};
+use crate::layered_drgporep::{Layers, PublicParams};
+use crate::porep;
+use crate::util::{data_at_node_offset, NODE_SIZE};
+use crate::vde;
+
+/// The first node two replications encoded differently.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Divergence {
+    pub layer: usize,
+    pub node: usize,
+    /// The parents of the node in each side's graph of the layer.
+    pub parents_a: Vec<usize>,
+    pub parents_b: Vec<usize>,
+}
+
+impl Divergence {
+    /// Whether the sides encoded the node with different parents, rather than
+    /// encoding the same parents differently.
+    pub fn parents_differ(&self) -> bool {
+        self.parents_a != self.parents_b
+    }
+}
+
+/// The commitments to a layer's data and to its encoding, on each side.
+#[derive(Debug, Clone)]
+pub struct TauComparison<T: Domain> {
+    pub layer: usize,
+    pub a: porep::Tau<T>,
+    pub b: porep::Tau<T>,
+}
+
+impl<T: Domain> TauComparison<T> {
+    // variable-time: these report differences, rather than verify anything.
+    pub fn comm_d_matches(&self) -> bool {
+        self.a.comm_d == self.b.comm_d
+    }
+
+    // variable-time: as comm_d_matches.
+    pub fn comm_r_matches(&self) -> bool {
+        self.a.comm_r == self.b.comm_r
+    }
+}
+
+/// What diff_replication found.
+#[derive(Debug, Clone)]
+pub struct ReplicationDiff<T: Domain> {
+    /// None if every layer was encoded the same on both sides.
+    pub first_divergence: Option<Divergence>,
+    pub layer_taus: Vec<TauComparison<T>>,
+}
+
+impl<T: Domain> ReplicationDiff<T> {
+    pub fn is_identical(&self) -> bool {
+        self.first_divergence.is_none()
+            && self
+                .layer_taus
+                .iter()
+                .all(|taus| taus.comm_d_matches() && taus.comm_r_matches())
+    }
+}
+
+/// A node two replica files hold differently, and what each holds of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NodeDiff {
+    pub node: usize,
+    pub a: Vec<u8>,
+    pub b: Vec<u8>,
+}
+
+/// What diff_replica_files found.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplicaFileDiff {
+    /// The nodes in each file, counting a trailing partial node.
+    pub nodes_a: usize,
+    pub nodes_b: usize,
+    /// How many of the nodes both files hold differ.
+    pub differing_nodes: usize,
+    /// The first of the nodes which differ, as many as were asked for.
+    pub first_differences: Vec<NodeDiff>,
+}
+
+impl ReplicaFileDiff {
+    pub fn is_identical(&self) -> bool {
+        self.nodes_a == self.nodes_b && self.differing_nodes == 0
+    }
+}
+
+/// Replicates `data` under `pp_a` and under `pp_b`, which must have the same
+/// number of layers and of nodes, and reports where the encodings diverge.
+pub fn diff_replication<L: Layers>(
+    pp_a: &PublicParams<L::Hasher, L::Graph>,
+    pp_b: &PublicParams<L::Hasher, L::Graph>,
+    replica_id: &<L::Hasher as Hasher>::Domain,
+    data: &[u8],
+) -> Result<ReplicationDiff<<L::Hasher as Hasher>::Domain>> {
+    diff_replication_with::<L, _, _>(
+        pp_a,
+        pp_b,
+        replica_id,
+        data,
+        encode_layer::<L>,
+        encode_layer::<L>,
+    )
+}
+
+/// As diff_replication, but encodes each side's layers with its own encoder,
+/// which is given the layer's parameters, the replica id and the layer's data
+/// to encode in place, so that two implementations of encoding can be
+/// compared over the same parameters.
+pub fn diff_replication_with<L, A, B>(
+    pp_a: &PublicParams<L::Hasher, L::Graph>,
+    pp_b: &PublicParams<L::Hasher, L::Graph>,
+    replica_id: &<L::Hasher as Hasher>::Domain,
+    data: &[u8],
+    encode_a: A,
+    encode_b: B,
+) -> Result<ReplicationDiff<<L::Hasher as Hasher>::Domain>>
+where
+    L: Layers,
+    A: Fn(
+        &drgporep::PublicParams<L::Hasher, L::Graph>,
+        &<L::Hasher as Hasher>::Domain,
+        &mut [u8],
+    ) -> Result<()>,
+    B: Fn(
+        &drgporep::PublicParams<L::Hasher, L::Graph>,
+        &<L::Hasher as Hasher>::Domain,
+        &mut [u8],
+    ) -> Result<()>,
+{
+    let layers = pp_a.layer_challenges.layers();
+    assert_eq!(
+        layers,
+        pp_b.layer_challenges.layers(),
+        "the parameterizations have different numbers of layers"
+    );
+    assert_eq!(
+        pp_a.drg_porep_public_params.graph.size(),
+        pp_b.drg_porep_public_params.graph.size(),
+        "the parameterizations have different numbers of nodes"
+    );
+
+    let mut data_a = data.to_vec();
+    let mut data_b = data.to_vec();
+    let mut drgpp_a = pp_a.drg_porep_public_params.clone();
+    let mut drgpp_b = pp_b.drg_porep_public_params.clone();
+
+    let mut first_divergence = None;
+    let mut layer_taus = Vec::with_capacity(layers);
+
+    for layer in 0..layers {
+        let comm_d_a = drgpp_a.graph.merkle_tree(&data_a)?.root();
+        let comm_d_b = drgpp_b.graph.merkle_tree(&data_b)?.root();
+
+        encode_a(&drgpp_a, replica_id, &mut data_a)?;
+        encode_b(&drgpp_b, replica_id, &mut data_b)?;
+
+        layer_taus.push(TauComparison {
+            layer,
+            a: porep::Tau::new(comm_d_a, drgpp_a.graph.merkle_tree(&data_a)?.root()),
+            b: porep::Tau::new(comm_d_b, drgpp_b.graph.merkle_tree(&data_b)?.root()),
+        });
+
+        if first_divergence.is_none() {
+            let graph = &drgpp_a.graph;
+
+            if let Some(node) = first_differing_node::<L::Hasher, _>(graph, &data_a, &data_b) {
+                first_divergence = Some(Divergence {
+                    layer,
+                    node,
+                    parents_a: graph.parents(node),
+                    parents_b: drgpp_b.graph.parents(node),
+                });
+            }
+        }
+
+        drgpp_a = L::transform(&drgpp_a, layer, layers);
+        drgpp_b = L::transform(&drgpp_b, layer, layers);
+    }
+
+    Ok(ReplicationDiff {
+        first_divergence,
+        layer_taus,
+    })
+}
+
+/// Compares the replicas at `path_a` and `path_b`, whose nodes are `lambda`
+/// bytes long, node by node, reporting the first `max_reported` nodes which
+/// differ. The files are streamed, so replicas of any size may be compared.
+pub fn diff_replica_files<P: AsRef<Path>>(
+    path_a: P,
+    path_b: P,
+    lambda: usize,
+    max_reported: usize,
+) -> Result<ReplicaFileDiff> {
+    assert!(lambda > 0, "nodes are at least a byte long");
+
+    let mut file_a = BufReader::new(File::open(path_a)?);
+    let mut file_b = BufReader::new(File::open(path_b)?);
+    let mut node_a = vec![0; lambda];
+    let mut node_b = vec![0; lambda];
+
+    let mut diff = ReplicaFileDiff {
+        nodes_a: 0,
+        nodes_b: 0,
+        differing_nodes: 0,
+        first_differences: Vec::new(),
+    };
+
+    loop {
+        let read_a = read_node(&mut file_a, &mut node_a)?;
+        let read_b = read_node(&mut file_b, &mut node_b)?;
+
+        if read_a > 0 {
+            diff.nodes_a += 1;
+        }
+        if read_b > 0 {
+            diff.nodes_b += 1;
+        }
+
+        if read_a == 0 || read_b == 0 {
+            break;
+        }
+
+        if node_a[..read_a] != node_b[..read_b] {
+            if diff.first_differences.len() < max_reported {
+                diff.first_differences.push(NodeDiff {
+                    node: diff.nodes_a - 1,
+                    a: node_a[..read_a].to_vec(),
+                    b: node_b[..read_b].to_vec(),
+                });
+            }
+            diff.differing_nodes += 1;
+        }
+    }
+
+    // Count what's left of the longer file.
+    while read_node(&mut file_a, &mut node_a)? > 0 {
+        diff.nodes_a += 1;
+    }
+    while read_node(&mut file_b, &mut node_b)? > 0 {
+        diff.nodes_b += 1;
+    }
+
+    Ok(diff)
+}
+
+fn encode_layer<L: Layers>(
+    pp: &drgporep::PublicParams<L::Hasher, L::Graph>,
+    replica_id: &<L::Hasher as Hasher>::Domain,
+    data: &mut [u8],
+) -> Result<()> {
+    vde::encode(&pp.graph, pp.sloth_iter, replica_id, data)
+}
+
+// The first node, in the order graph encodes them, which a and b hold
+// differently.
+fn first_differing_node<H: Hasher, G: Graph<H>>(graph: &G, a: &[u8], b: &[u8]) -> Option<usize> {
+    let differs = |node: &usize| {
+        let start = data_at_node_offset(*node);
+        a[start..start + NODE_SIZE] != b[start..start + NODE_SIZE]
+    };
+
+    if graph.forward() {
+        (0..graph.size()).find(differs)
+    } else {
+        (0..graph.size()).rev().find(differs)
+    }
+}
+
+// Reads a node into buf, returning how many of its bytes there were: fewer
+// than buf's length only at the end of the file.
+fn read_node<R: Read>(reader: &mut R, buf: &mut [u8]) -> Result<usize> {
+    let mut read = 0;
+
+    while read < buf.len() {
+        match reader.read(&mut buf[read..])? {
+            0 => break,
+            n => read += n,
+        }
+    }
+
+    Ok(read)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pairing::bls12_381::Bls12;
+    use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::fs;
+
+    use crate::crypto::feistel::FeistelConfig;
+    use crate::drgraph::new_seed;
+    use crate::fr32::fr_into_bytes;
+    use crate::hasher::Blake2sHasher;
+    use crate::layered_drgporep::{LayerChallenges, SetupParams};
+    use crate::proof::ProofScheme;
+    use crate::zigzag_drgporep::ZigZagDrgPoRep;
+    use crate::zigzag_graph::{ZigZag, ZigZagBucketGraph};
+
+    type H = Blake2sHasher;
+    type Params = PublicParams<H, ZigZagBucketGraph<H>>;
+
+    const NODES: usize = 64;
+
+    fn params(seed: [u32; 7], feistel: FeistelConfig) -> Params {
+        let sp = SetupParams {
+            drg_porep_setup_params: drgporep::SetupParams {
+                drg: drgporep::DrgParams {
+                    nodes: NODES,
+                    degree: 5,
+                    expansion_degree: 8,
+                    seed,
+                    feistel,
+                },
+                sloth_iter: 1,
+            },
+            layer_challenges: LayerChallenges::new_fixed(4, 1),
+        };
+
+        ZigZagDrgPoRep::<H>::setup(&sp).unwrap()
+    }
+
+    fn random_data(rng: &mut XorShiftRng) -> Vec<u8> {
+        (0..NODES)
+            .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+            .collect()
+    }
+
+    #[test]
+    fn identical_parameterizations_replicate_identically() {
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+        let replica_id = rng.gen();
+        let data = random_data(rng);
+        let pp = params(new_seed(), FeistelConfig::default());
+
+        let diff = diff_replication::<ZigZagDrgPoRep<H>>(&pp, &pp, &replica_id, &data).unwrap();
+
+        assert!(diff.is_identical());
+        assert_eq!(None, diff.first_divergence);
+        assert_eq!(4, diff.layer_taus.len());
+    }
+
+    #[test]
+    fn a_feistel_key_change_diverges_where_parents_first_differ() {
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+        let replica_id = rng.gen();
+        let data = random_data(rng);
+        let seed = new_seed();
+
+        let default = FeistelConfig::default();
+        let mut keys = default.keys.clone();
+        keys[0] ^= 1;
+        let pp_a = params(seed, default.clone());
+        let pp_b = params(seed, FeistelConfig::new(keys, default.rounds));
+
+        // The base graphs are the same, so the first layer's parents only
+        // differ where the expanded parents do. Nodes before the first such
+        // node are encoded the same, as are their parents.
+        let (g_a, g_b) = (
+            &pp_a.drg_porep_public_params.graph,
+            &pp_b.drg_porep_public_params.graph,
+        );
+        let expected = (0..NODES)
+            .find(|&node| g_a.parents(node) != g_b.parents(node))
+            .expect("the key changes no parents");
+        assert_ne!(
+            g_a.expanded_parents(expected),
+            g_b.expanded_parents(expected)
+        );
+
+        let diff = diff_replication::<ZigZagDrgPoRep<H>>(&pp_a, &pp_b, &replica_id, &data).unwrap();
+        let divergence = diff.first_divergence.clone().unwrap();
+
+        assert_eq!((0, expected), (divergence.layer, divergence.node));
+        assert!(divergence.parents_differ());
+        assert_eq!(g_a.parents(expected), divergence.parents_a);
+
+        assert!(!diff.is_identical());
+        assert!(diff.layer_taus[0].comm_d_matches());
+        assert!(!diff.layer_taus[0].comm_r_matches());
+        assert!(!diff.layer_taus[1].comm_d_matches());
+    }
+
+    #[test]
+    fn encoders_are_compared_over_the_same_parameters() {
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+        let replica_id = rng.gen();
+        let data = random_data(rng);
+        let pp = params(new_seed(), FeistelConfig::default());
+
+        // An encoder which gets node 3 of the second layer wrong.
+        fn faulty(
+            drgpp: &drgporep::PublicParams<H, ZigZagBucketGraph<H>>,
+            replica_id: &<H as Hasher>::Domain,
+            data: &mut [u8],
+        ) -> Result<()> {
+            vde::encode(&drgpp.graph, drgpp.sloth_iter, replica_id, data)?;
+            if drgpp.graph.reversed() {
+                data[data_at_node_offset(3)] ^= 1;
+            }
+
+            Ok(())
+        }
+
+        let diff = diff_replication_with::<ZigZagDrgPoRep<H>, _, _>(
+            &pp,
+            &pp,
+            &replica_id,
+            &data,
+            encode_layer::<ZigZagDrgPoRep<H>>,
+            faulty,
+        )
+        .unwrap();
+        let divergence = diff.first_divergence.unwrap();
+
+        assert_eq!((1, 3), (divergence.layer, divergence.node));
+        assert!(!divergence.parents_differ());
+        assert!(diff.layer_taus[0].comm_r_matches());
+        assert!(!diff.layer_taus[1].comm_r_matches());
+    }
+
+    #[test]
+    fn replica_files_are_compared_node_by_node() {
+        let dir = tempfile::tempdir().unwrap();
+        let path_a = dir.path().join("a");
+        let path_b = dir.path().join("b");
+
+        let a: Vec<u8> = (0..10 * 32).map(|i| i as u8).collect();
+        let mut b = a.clone();
+        for node in &[2, 5, 7] {
+            b[node * 32 + 31] ^= 0xff;
+        }
+        // A partial node past the end of a.
+        b.extend_from_slice(&[1; 7]);
+
+        fs::write(&path_a, &a).unwrap();
+        fs::write(&path_b, &b).unwrap();
+
+        let diff = diff_replica_files(&path_a, &path_b, 32, 2).unwrap();
+        assert_eq!((10, 11), (diff.nodes_a, diff.nodes_b));
+        assert_eq!(3, diff.differing_nodes);
+        assert_eq!(
+            vec![2, 5],
+            diff.first_differences
+                .iter()
+                .map(|d| d.node)
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(&a[64..96], &diff.first_differences[0].a[..]);
+        assert_eq!(&b[64..96], &diff.first_differences[0].b[..]);
+        assert!(!diff.is_identical());
+
+        assert!(diff_replica_files(&path_a, &path_a, 32, 2)
+            .unwrap()
+            .is_identical());
+    }
+}
@@ -1,28 +1,111 @@
+use std::iter;
 use std::marker::PhantomData;
 
+#[cfg(test)]
+use byteorder::ReadBytesExt;
 use byteorder::{LittleEndian, WriteBytesExt};
 use serde::de::Deserialize;
 use serde::ser::Serialize;
 
-use crate::drgraph::Graph;
+use crate::challenge_derivation::derive_challenges;
+use crate::crypto::feistel::FeistelConfig;
+use crate::drgraph::{graph_height, Graph};
 use crate::error::Result;
 use crate::hasher::{Domain, Hasher};
 use crate::merkle::{MerkleProof, MerkleTree};
+use crate::node_source::NodeSource;
 use crate::parameter_cache::ParameterSetIdentifier;
 use crate::porep::{self, PoRep};
 use crate::proof::ProofScheme;
-use crate::vde::{self, decode_block, decode_domain_block};
+use crate::util::NODE_SIZE;
+use crate::vde::{self, decode_block, decode_node};
 
 #[derive(Debug, Clone)]
 pub struct PublicInputs<T: Domain> {
     pub replica_id: T,
-    pub challenges: Vec<usize>,
+    /// The seed the challenged nodes are derived from, by prover and verifier alike.
+    pub challenge_seed: [u8; 32],
+    /// The number of nodes challenged in each partition.
+    pub challenge_count: usize,
+    /// The partition proven, if the proof is partitioned.
+    pub k: Option<usize>,
     pub tau: Option<porep::Tau<T>>,
 }
 
+// Marks a seed carrying explicit challenges, for tests which must challenge particular nodes.
+#[cfg(test)]
+const EXPLICIT_CHALLENGES_TAG: &[u8; 8] = b"explicit";
+
+impl<T: Domain> PublicInputs<T> {
+    /// The nodes challenged when proving the `layer`th layer of `nodes` nodes: `challenge_count`
+    /// of them, derived from the seed and salted with the layer, so that each layer's differ.
+    pub fn challenges(&self, nodes: usize, layer: usize) -> Vec<usize> {
+        #[cfg(test)]
+        {
+            if self.challenge_seed.starts_with(EXPLICIT_CHALLENGES_TAG) {
+                return self.challenge_seed[8..]
+                    .chunks(4)
+                    .take(self.challenge_count)
+                    .map(|mut bytes| bytes.read_u32::<LittleEndian>().unwrap() as usize)
+                    .collect();
+            }
+        }
+
+        derive_challenges(
+            &self.challenge_seed,
+            layer as u8,
+            nodes,
+            self.challenge_count,
+            self.k.unwrap_or(0) as u8,
+        )
+    }
+
+    /// Public inputs challenging exactly `challenges`, of which there may be at most 6.
+    #[cfg(test)]
+    pub fn with_challenges(
+        replica_id: T,
+        challenges: &[usize],
+        tau: Option<porep::Tau<T>>,
+    ) -> PublicInputs<T> {
+        assert!(challenges.len() <= 6, "too many explicit challenges");
+
+        let mut challenge_seed = [0; 32];
+        challenge_seed[..8].copy_from_slice(EXPLICIT_CHALLENGES_TAG);
+        for (i, challenge) in challenges.iter().enumerate() {
+            (&mut challenge_seed[8 + 4 * i..])
+                .write_u32::<LittleEndian>(*challenge as u32)
+                .unwrap();
+        }
+
+        PublicInputs {
+            replica_id,
+            challenge_seed,
+            challenge_count: challenges.len(),
+            k: None,
+            tau,
+        }
+    }
+}
+
+/// The trees of the layer proven, borrowed so that proving each layer of a layered replica
+/// needn't copy them, and where to read the replica's nodes from.
 #[derive(Debug)]
 pub struct PrivateInputs<'a, H: 'a + Hasher> {
-    pub aux: &'a porep::ProverAux<H>,
+    pub tree_d: &'a MerkleTree<H::Domain, H::Function>,
+    pub tree_r: &'a MerkleTree<H::Domain, H::Function>,
+    /// The nodes of the replica committed to by `tree_r`. Only the challenged nodes and their
+    /// parents are read, so they may as well be read from disk as from `tree_r`'s leaves.
+    pub replica: &'a NodeSource,
+}
+
+impl<'a, H: 'a + Hasher> PrivateInputs<'a, H> {
+    pub fn new(aux: &'a porep::ProverAux<H>) -> Self {
+        PrivateInputs {
+            tree_d: &aux.tree_d,
+            tree_r: &aux.tree_r,
+            replica: &aux.tree_r,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -43,6 +126,9 @@ pub struct DrgParams {
 
     // Random seed
     pub seed: [u32; 7],
+
+    // Keys and rounds of the Feistel permutation of expansion edges
+    pub feistel: FeistelConfig,
 }
 
 #[derive(Debug, Clone)]
@@ -112,6 +198,16 @@ impl<H: Hasher> DataProof<H> {
         out
     }
 
+    /// The length of the proof's serialization.
+    pub fn serialized_size(&self) -> usize {
+        self.proof.serialized_size() + NODE_SIZE
+    }
+
+    /// The length of the serialization of a proof of a node of a tree of the given height.
+    pub fn projected_size(height: usize) -> usize {
+        MerkleProof::<H>::projected_size(height) + NODE_SIZE
+    }
+
     /// proves_challenge returns true if this self.proof corresponds to challenge.
     /// This is useful for verifying that a supplied proof is actually relevant to a given challenge.
     pub fn proves_challenge(&self, challenge: usize) -> bool {
@@ -128,6 +224,9 @@ impl<H: Hasher> DataProof<H> {
 
 pub type ReplicaParents<H> = Vec<(usize, DataProof<H>)>;
 
+/// The length of the index which precedes each parent's proof in a serialized proof.
+const PARENT_INDEX_BYTES: usize = 4;
+
 #[derive(Default, Debug, Clone, Serialize, Deserialize)]
 pub struct Proof<H: Hasher> {
     #[serde(bound(
@@ -178,9 +277,7 @@ impl<H: Hasher> Proof<H> {
                     self.replica_parents[i]
                         .iter()
                         .fold(Vec::new(), |mut acc, (s, p)| {
-                            let mut v = vec![0u8; 4];
-                            v.write_u32::<LittleEndian>(*s as u32).unwrap();
-                            acc.extend(v);
+                            acc.write_u32::<LittleEndian>(*s as u32).unwrap();
                             acc.extend(p.serialize());
                             acc
                         }),
@@ -194,6 +291,35 @@ impl<H: Hasher> Proof<H> {
         res
     }
 
+    /// The length of the proof's serialization.
+    pub fn serialized_size(&self) -> usize {
+        (0..self.nodes.len())
+            .map(|i| {
+                let parents: usize = self.replica_parents[i]
+                    .iter()
+                    .map(|(_, p)| PARENT_INDEX_BYTES + p.serialized_size())
+                    .sum();
+
+                self.replica_nodes[i].serialized_size() + parents + self.nodes[i].serialized_size()
+            })
+            .sum()
+    }
+
+    /// The length of the serialization of a proof of challenge_count challenges with the given
+    /// public params: per challenge, the proofs of its replica node, of each of its parents and of
+    /// its data node. Every challenged node is taken to have as many parents as the graph's degree,
+    /// as each node of a plain DRG does, so that the projection is exact for those graphs, and an
+    /// upper bound for graphs (like zigzag graphs) some of whose nodes have fewer.
+    pub fn projected_size<G>(pub_params: &PublicParams<H, G>, challenge_count: usize) -> usize
+    where
+        G: Graph<H> + ParameterSetIdentifier,
+    {
+        let node = DataProof::<H>::projected_size(graph_height(pub_params.graph.size()));
+        let parent = PARENT_INDEX_BYTES + node;
+
+        challenge_count * (2 * node + pub_params.graph.degree() * parent)
+    }
+
     pub fn new(
         replica_nodes: Vec<DataProof<H>>,
         replica_parents: Vec<ReplicaParents<H>>,
@@ -243,11 +369,12 @@ where
     type Proof = Proof<H>;
 
     fn setup(sp: &Self::SetupParams) -> Result<Self::PublicParams> {
-        let graph = G::new(
+        let graph = G::new_with_feistel(
             sp.drg.nodes,
             sp.drg.degree,
             sp.drg.expansion_degree,
             sp.drg.seed,
+            &sp.drg.feistel,
         );
 
         Ok(PublicParams::new(graph, sp.sloth_iter))
@@ -258,21 +385,51 @@ where
         pub_inputs: &'b Self::PublicInputs,
         priv_inputs: &'b Self::PrivateInputs,
     ) -> Result<Self::Proof> {
-        let len = pub_inputs.challenges.len();
+        Self::prove_at_layer(pub_params, pub_inputs, priv_inputs, 0)
+    }
+
+    fn verify(
+        pub_params: &Self::PublicParams,
+        pub_inputs: &Self::PublicInputs,
+        proof: &Self::Proof,
+    ) -> Result<bool> {
+        Self::verify_at_layer(pub_params, pub_inputs, proof, 0)
+    }
+
+    fn with_partition(pub_in: Self::PublicInputs, k: Option<usize>) -> Self::PublicInputs {
+        PublicInputs { k, ..pub_in }
+    }
+}
+
+impl<'a, H, G> DrgPoRep<'a, H, G>
+where
+    H: 'a + Hasher,
+    G: 'a + Graph<H> + ParameterSetIdentifier,
+{
+    /// Proves the `layer`th layer of a layered replication, whose challenges are salted with
+    /// the layer. A replication of a single layer is layer 0.
+    pub fn prove_at_layer(
+        pub_params: &PublicParams<H, G>,
+        pub_inputs: &PublicInputs<H::Domain>,
+        priv_inputs: &PrivateInputs<'a, H>,
+        layer: usize,
+    ) -> Result<Proof<H>> {
+        let challenges = pub_inputs.challenges(pub_params.graph.size(), layer);
+        let len = challenges.len();
 
         let mut replica_nodes = Vec::with_capacity(len);
         let mut replica_parents = Vec::with_capacity(len);
         let mut data_nodes: Vec<DataProof<H>> = Vec::with_capacity(len);
 
         for i in 0..len {
-            let challenge = pub_inputs.challenges[i] % pub_params.graph.size();
+            let challenge = challenges[i] % pub_params.graph.size();
             assert_ne!(challenge, 0, "cannot prove the first node");
 
-            let tree_d = &priv_inputs.aux.tree_d;
-            let tree_r = &priv_inputs.aux.tree_r;
-            let domain_replica = tree_r.as_slice();
+            let tree_d = priv_inputs.tree_d;
+            let tree_r = priv_inputs.tree_r;
+            let replica = priv_inputs.replica;
 
-            let data = domain_replica[challenge];
+            let data = H::Domain::try_from_bytes(&replica.read_node(challenge)?)?;
 
             replica_nodes.push(DataProof {
                 proof: MerkleProof::new_from_proof(&tree_r.gen_proof(challenge)),
@@ -287,7 +444,7 @@ where
                     let proof = tree_r.gen_proof(p);
                     DataProof {
                         proof: MerkleProof::new_from_proof(&proof),
-                        data: domain_replica[p],
+                        data: H::Domain::try_from_bytes(&replica.read_node(p)?)?,
                     }
                 }));
             }
@@ -305,11 +462,11 @@ where
                 //     challenge,
                 // )?;
 
-                let extracted = decode_domain_block(
+                let extracted = decode_node(
                     &pub_params.graph,
                     pub_params.sloth_iter,
                     &pub_inputs.replica_id,
-                    domain_replica,
+                    replica,
                     challenge,
                 )?
                 .into_bytes();
@@ -325,27 +482,39 @@ where
         Ok(proof)
     }
 
-    fn verify(
-        pub_params: &Self::PublicParams,
-        pub_inputs: &Self::PublicInputs,
-        proof: &Self::Proof,
+    /// Verifies a proof of the `layer`th layer of a layered replication.
+    pub fn verify_at_layer(
+        pub_params: &PublicParams<H, G>,
+        pub_inputs: &PublicInputs<H::Domain>,
+        proof: &Proof<H>,
+        layer: usize,
     ) -> Result<bool> {
-        for i in 0..pub_inputs.challenges.len() {
+        let challenges = pub_inputs.challenges(pub_params.graph.size(), layer);
+
+        // A proof must prove every challenge, and nothing else.
+        if proof.nodes.len() != challenges.len()
+            || proof.replica_nodes.len() != challenges.len()
+            || proof.replica_parents.len() != challenges.len()
+        {
+            return Ok(false);
+        }
+
+        for i in 0..challenges.len() {
             {
                 // This was verify_proof_meta.
-                if pub_inputs.challenges[i] >= pub_params.graph.size() {
+                if challenges[i] >= pub_params.graph.size() {
                     return Ok(false);
                 }
 
-                if !(proof.nodes[i].proves_challenge(pub_inputs.challenges[i])) {
+                if !(proof.nodes[i].proves_challenge(challenges[i])) {
                     return Ok(false);
                 }
 
-                if !(proof.replica_nodes[i].proves_challenge(pub_inputs.challenges[i])) {
+                if !(proof.replica_nodes[i].proves_challenge(challenges[i])) {
                     return Ok(false);
                 }
 
-                let expected_parents = pub_params.graph.parents(pub_inputs.challenges[i]);
+                let expected_parents = pub_params.graph.parents(challenges[i]);
                 if proof.replica_parents[i].len() != expected_parents.len() {
                     println!(
                         "proof parents were not the same length as in public parameters: {} != {}",
@@ -364,9 +533,21 @@ where
                     println!("proof parents were not those provided in public parameters");
                     return Ok(false);
                 }
+
+                // Merkle paths are only hashed up to the tree's height, and the hashes of longer
+                // ones can't be computed at all.
+                let height = graph_height(pub_params.graph.size());
+                let paths_as_expected = iter::once(&proof.nodes[i])
+                    .chain(iter::once(&proof.replica_nodes[i]))
+                    .chain(proof.replica_parents[i].iter().map(|(_, p)| p))
+                    .all(|p| p.proof.path().len() == height);
+
+                if !paths_as_expected {
+                    return Ok(false);
+                }
             }
 
-            let challenge = pub_inputs.challenges[i] % pub_params.graph.size();
+            let challenge = challenges[i] % pub_params.graph.size();
             assert_ne!(challenge, 0, "cannot prove the first node");
 
             if !proof.replica_nodes[i].proof.validate(challenge) {
@@ -395,7 +576,7 @@ where
             let unsealed =
                 H::sloth_decode(&key, &proof.replica_nodes[i].data, pub_params.sloth_iter);
 
-            if unsealed != proof.nodes[i].data {
+            if !unsealed.ct_eq(&proof.nodes[i].data) {
                 return Ok(false);
             }
 
@@ -472,7 +653,9 @@ mod tests {
 
     use crate::drgraph::{new_seed, BucketGraph};
     use crate::fr32::fr_into_bytes;
+    use crate::hasher::pedersen::PedersenDomain;
     use crate::hasher::{Blake2sHasher, PedersenHasher, Sha256Hasher};
+    use crate::merkle::make_proof_for_test;
     use crate::util::data_at_node;
 
     pub fn file_backed_mmap_from(data: &[u8]) -> MmapMut {
@@ -497,6 +680,7 @@ mod tests {
                 degree: 5,
                 expansion_degree: 0,
                 seed: new_seed(),
+                feistel: FeistelConfig::default(),
             },
             sloth_iter,
         };
@@ -546,6 +730,7 @@ mod tests {
                 degree: 5,
                 expansion_degree: 0,
                 seed: new_seed(),
+                feistel: FeistelConfig::default(),
             },
             sloth_iter,
         };
@@ -618,6 +803,7 @@ mod tests {
                     degree,
                     expansion_degree,
                     seed,
+                    feistel: FeistelConfig::default(),
                 },
                 sloth_iter,
             };
@@ -633,13 +819,13 @@ mod tests {
 
             assert_ne!(data, copied, "replication did not change data");
 
-            let pub_inputs = PublicInputs::<H::Domain> {
+            let pub_inputs = PublicInputs::<H::Domain>::with_challenges(
                 replica_id,
-                challenges: vec![challenge, challenge],
-                tau: Some(tau.clone().into()),
-            };
+                &[challenge, challenge],
+                Some(tau.clone().into()),
+            );
 
-            let priv_inputs = PrivateInputs::<H> { aux: &aux };
+            let priv_inputs = PrivateInputs::<H>::new(&aux);
 
             let real_proof = DrgPoRep::<H, _>::prove(&pp, &pub_inputs, &priv_inputs).unwrap();
 
@@ -648,13 +834,18 @@ mod tests {
                 assert!(!use_wrong_challenge);
                 let real_parents = real_proof.replica_parents;
 
-                // Parent vector claiming the wrong parents.
-                let fake_parents = vec![real_parents[0]
+                // Parent vectors claiming the wrong parents.
+                let fake_parents = real_parents
                     .iter()
-                    // Incrementing each parent node will give us a different parent set.
-                    // It's fine to be out of range, since this only needs to fail.
-                    .map(|(i, data_proof)| (i + 1, data_proof.clone()))
-                    .collect::<Vec<_>>()];
+                    .map(|parents| {
+                        parents
+                            .iter()
+                            // Incrementing each parent node will give us a different parent set.
+                            // It's fine to be out of range, since this only needs to fail.
+                            .map(|(i, data_proof)| (i + 1, data_proof.clone()))
+                            .collect::<Vec<_>>()
+                    })
+                    .collect();
 
                 let proof = Proof::new(
                     real_proof.replica_nodes.clone(),
@@ -684,16 +875,21 @@ mod tests {
 
                 // Parent vector claiming the right parents but providing valid proofs for different
                 // parents.
-                let fake_proof_parents = vec![real_parents[0]
+                let fake_proof_parents = real_parents
                     .iter()
-                    .enumerate()
-                    .map(|(i, (p, _))| {
-                        // Rotate the real parent proofs.
-                        let x = (i + 1) % real_parents[0].len();
-                        let j = real_parents[0][x].0;
-                        (*p, real_parents[0][j].1.clone())
+                    .map(|parents| {
+                        parents
+                            .iter()
+                            .enumerate()
+                            .map(|(i, (p, _))| {
+                                // Rotate the real parent proofs.
+                                let x = (i + 1) % parents.len();
+                                let j = parents[x].0;
+                                (*p, parents[j].1.clone())
+                            })
+                            .collect::<Vec<_>>()
                     })
-                    .collect::<Vec<_>>()];
+                    .collect();
 
                 let proof2 = Proof::new(
                     real_proof.replica_nodes,
@@ -712,11 +908,13 @@ mod tests {
             let proof = real_proof;
 
             if use_wrong_challenge {
-                let pub_inputs_with_wrong_challenge_for_proof = PublicInputs::<H::Domain> {
-                    replica_id,
-                    challenges: vec![if challenge == 1 { 2 } else { 1 }],
-                    tau: Some(tau.into()),
-                };
+                let wrong_challenge = if challenge == 1 { 2 } else { 1 };
+                let pub_inputs_with_wrong_challenge_for_proof =
+                    PublicInputs::<H::Domain>::with_challenges(
+                        replica_id,
+                        &[wrong_challenge, wrong_challenge],
+                        Some(tau.into()),
+                    );
                 let verified = DrgPoRep::<H, _>::verify(
                     &pp,
                     &pub_inputs_with_wrong_challenge_for_proof,
@@ -772,6 +970,119 @@ mod tests {
         }
     }
 
+    fn prove_verify_derived<H: Hasher>() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let nodes = 16;
+
+        let sp = SetupParams {
+            drg: DrgParams {
+                nodes,
+                degree: 5,
+                expansion_degree: 0,
+                seed: new_seed(),
+                feistel: FeistelConfig::default(),
+            },
+            sloth_iter: 1,
+        };
+        let pp = DrgPoRep::<H, BucketGraph<_>>::setup(&sp).unwrap();
+
+        let replica_id: H::Domain = rng.gen();
+        let data: Vec<u8> = (0..nodes)
+            .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+            .collect();
+        let mut mmapped_data_copy = file_backed_mmap_from(&data);
+        let (tau, aux) =
+            DrgPoRep::<H, _>::replicate(&pp, &replica_id, &mut mmapped_data_copy, None).unwrap();
+        let priv_inputs = PrivateInputs::<H>::new(&aux);
+
+        for challenge_count in 1..4 {
+            let pub_inputs = PublicInputs::<H::Domain> {
+                replica_id,
+                challenge_seed: rng.gen(),
+                challenge_count,
+                k: None,
+                tau: Some(tau.clone()),
+            };
+
+            for layer in 0..3 {
+                let proof =
+                    DrgPoRep::prove_at_layer(&pp, &pub_inputs, &priv_inputs, layer).unwrap();
+                assert_eq!(challenge_count, proof.nodes.len());
+
+                assert!(
+                    DrgPoRep::verify_at_layer(&pp, &pub_inputs, &proof, layer).unwrap(),
+                    "failed to verify layer {} with {} challenges",
+                    layer,
+                    challenge_count
+                );
+
+                // Nor does a proof of some challenges prove others, of another layer or seed.
+                let challenges = pub_inputs.challenges(nodes, layer);
+                if pub_inputs.challenges(nodes, layer + 3) != challenges {
+                    assert!(
+                        !DrgPoRep::verify_at_layer(&pp, &pub_inputs, &proof, layer + 3).unwrap()
+                    );
+                }
+
+                let other_seed = PublicInputs {
+                    challenge_seed: rng.gen(),
+                    ..pub_inputs.clone()
+                };
+                if other_seed.challenges(nodes, layer) != challenges {
+                    assert!(!DrgPoRep::verify_at_layer(&pp, &other_seed, &proof, layer).unwrap());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn prove_verify_derived_challenges() {
+        prove_verify_derived::<PedersenHasher>();
+        prove_verify_derived::<Sha256Hasher>();
+        prove_verify_derived::<Blake2sHasher>();
+    }
+
+    #[test]
+    fn verify_rejects_overlong_paths() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let nodes = 8;
+
+        let sp = SetupParams {
+            drg: DrgParams {
+                nodes,
+                degree: 3,
+                expansion_degree: 0,
+                seed: new_seed(),
+                feistel: FeistelConfig::default(),
+            },
+            sloth_iter: 1,
+        };
+        let pp = DrgPoRep::<PedersenHasher, BucketGraph<_>>::setup(&sp).unwrap();
+
+        let replica_id: PedersenDomain = rng.gen();
+        let data: Vec<u8> = (0..nodes)
+            .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+            .collect();
+        let mut mmapped_data_copy = file_backed_mmap_from(&data);
+        let (tau, aux) =
+            DrgPoRep::<PedersenHasher, _>::replicate(&pp, &replica_id, &mut mmapped_data_copy, None)
+                .unwrap();
+
+        let pub_inputs = PublicInputs::with_challenges(replica_id, &[5], Some(tau));
+        let mut proof = DrgPoRep::prove(&pp, &pub_inputs, &PrivateInputs::new(&aux)).unwrap();
+        assert!(DrgPoRep::verify(&pp, &pub_inputs, &proof).unwrap());
+
+        // Padding the path with nodes to the left still proves the same challenge, but its
+        // hashes past height 62 can't be computed.
+        let honest = proof.replica_nodes[0].proof.clone();
+        let mut path = honest.path().clone();
+        path.extend(vec![(PedersenDomain::default(), false); 64]);
+        proof.replica_nodes[0].proof = make_proof_for_test(*honest.root(), *honest.leaf(), path);
+        assert!(proof.replica_nodes[0].proves_challenge(5));
+
+        assert!(!DrgPoRep::verify(&pp, &pub_inputs, &proof).unwrap());
+    }
+
     #[test]
     fn test_drgporep_verifies_using_challenge() {
         prove_verify_wrong_challenge(5, 1);
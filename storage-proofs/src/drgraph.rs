@@ -4,17 +4,16 @@ use std::marker::PhantomData;
 use rand::{ChaChaRng, OsRng, Rng, SeedableRng};
 use rayon::prelude::*;
 
+use crate::crypto::feistel::FeistelConfig;
 use crate::error::*;
 use crate::hasher::pedersen::PedersenHasher;
 use crate::hasher::{Domain, Hasher};
-use crate::merkle::MerkleTree;
+use crate::merkle::{MerkleTree, Parallelism};
 use crate::parameter_cache::ParameterSetIdentifier;
-use crate::util::data_at_node;
+use crate::util::{data_at_node, node_size_matches, NODE_SIZE};
 /// The default hasher currently in use.
 pub type DefaultTreeHasher = PedersenHasher;
 
-pub const PARALLEL_MERKLE: bool = true;
-
 /// A depth robust graph.
 pub trait Graph<H: Hasher>: ::std::fmt::Debug + Clone + PartialEq + Eq {
     /// Returns the expected size of all nodes in the graph.
@@ -24,15 +23,16 @@ pub trait Graph<H: Hasher>: ::std::fmt::Debug + Clone + PartialEq + Eq {
 
     /// Builds a merkle tree based on the given data.
     fn merkle_tree<'a>(&self, data: &'a [u8]) -> Result<MerkleTree<H::Domain, H::Function>> {
-        self.merkle_tree_aux(data, 32, PARALLEL_MERKLE)
+        self.merkle_tree_aux(data, NODE_SIZE, Parallelism::default())
     }
 
-    /// Builds a merkle tree based on the given data.
+    /// Builds a merkle tree based on the given data, with the given parallelism, calling the
+    /// yield hook as it goes (see yield_hook).
     fn merkle_tree_aux<'a>(
         &self,
         data: &'a [u8],
         node_size: usize,
-        parallel: bool,
+        parallelism: Parallelism,
     ) -> Result<MerkleTree<H::Domain, H::Function>> {
         if data.len() != (node_size * self.size()) as usize {
             return Err(Error::InvalidMerkleTreeArgs(
@@ -42,11 +42,15 @@ pub trait Graph<H: Hasher>: ::std::fmt::Debug + Clone + PartialEq + Eq {
             ));
         }
 
-        // To avoid hashing the first node, the node size has to be the hash size.else
+        // To avoid hashing the first node, the node size has to be the hash size.
         // We make this assumption pervasively anyway.
-        if node_size != 32 {
+        if node_size != NODE_SIZE {
             return Err(Error::InvalidNodeSize(node_size));
         }
+        debug_assert!(
+            node_size_matches::<H>(),
+            "nodes are not elements of the domain"
+        );
 
         let f = |i| {
             let d = data_at_node(&data, i).expect("data_at_node math failed");
@@ -58,13 +62,12 @@ pub trait Graph<H: Hasher>: ::std::fmt::Debug + Clone + PartialEq + Eq {
             H::Domain::try_from_bytes(d).unwrap()
         };
 
-        if parallel {
-            Ok(MerkleTree::from_par_iter(
-                (0..self.size()).into_par_iter().map(f),
-            ))
-        } else {
-            Ok(MerkleTree::new((0..self.size()).map(f)))
-        }
+        let leaves = match parallelism {
+            Parallelism::Sequential => (0..self.size()).map(f).collect(),
+            Parallelism::Threads(_) => (0..self.size()).into_par_iter().map(f).collect(),
+        };
+
+        MerkleTree::yielding_with_parallelism(leaves, parallelism)
     }
 
     /// Returns the merkle tree depth.
@@ -75,6 +78,14 @@ pub trait Graph<H: Hasher>: ::std::fmt::Debug + Clone + PartialEq + Eq {
     /// Returns a sorted list of all parents of this node.
     fn parents(&self, node: usize) -> Vec<usize>;
 
+    /// Replaces the contents of `parents` with the sorted list of all parents
+    /// of this node. Reusing the same buffer across nodes avoids allocating
+    /// one per node.
+    fn parents_into(&self, node: usize, parents: &mut Vec<usize>) {
+        parents.clear();
+        parents.extend(self.parents(node));
+    }
+
     /// Returns the size of the node.
     fn size(&self) -> usize;
 
@@ -82,6 +93,19 @@ pub trait Graph<H: Hasher>: ::std::fmt::Debug + Clone + PartialEq + Eq {
     fn degree(&self) -> usize;
 
     fn new(nodes: usize, base_degree: usize, expansion_degree: usize, seed: [u32; 7]) -> Self;
+
+    /// Constructs a graph as `new` does, whose expansion edges, if it has any, are permuted
+    /// with the given keys and rounds. Graphs without expansion edges ignore them.
+    fn new_with_feistel(
+        nodes: usize,
+        base_degree: usize,
+        expansion_degree: usize,
+        seed: [u32; 7],
+        _feistel: &FeistelConfig,
+    ) -> Self {
+        Self::new(nodes, base_degree, expansion_degree, seed)
+    }
+
     fn seed(&self) -> [u32; 7];
 
     // Returns true if a node's parents have lower index than the node.
@@ -116,13 +140,21 @@ impl<H: Hasher> ParameterSetIdentifier for BucketGraph<H> {
 impl<H: Hasher> Graph<H> for BucketGraph<H> {
     #[inline]
     fn parents(&self, node: usize) -> Vec<usize> {
+        let mut parents = Vec::with_capacity(self.base_degree);
+        self.parents_into(node, &mut parents);
+
+        parents
+    }
+
+    #[inline]
+    fn parents_into(&self, node: usize, parents: &mut Vec<usize>) {
         let m = self.base_degree;
+        parents.clear();
 
         match node {
             // Special case for the first node, it self references.
-            0 => vec![0; m as usize],
             // Special case for the second node, it references only the first one.
-            1 => vec![0; m as usize],
+            0 | 1 => parents.resize(m, 0),
             _ => {
                 // seed = self.seed | node
                 let mut seed = [0u32; 8];
@@ -130,7 +162,6 @@ impl<H: Hasher> Graph<H> for BucketGraph<H> {
                 seed[7] = node as u32;
                 let mut rng = ChaChaRng::from_seed(&seed);
 
-                let mut parents = Vec::with_capacity(m);
                 for k in 0..m {
                     // iterate over m meta nodes of the ith real node
                     // simulate the edges that we would add from previous graph nodes
@@ -151,8 +182,6 @@ impl<H: Hasher> Graph<H> for BucketGraph<H> {
                 }
 
                 parents.sort_unstable();
-
-                parents
             }
         }
     }
@@ -232,6 +261,13 @@ mod tests {
                     p1.sort();
                     assert_eq!(p1, p2, "not sorted");
                 }
+
+                // A reused buffer yields the same parents as a fresh one.
+                let mut buf = vec![usize::max_value(); 3];
+                for i in 0..size {
+                    g.parents_into(i, &mut buf);
+                    assert_eq!(g.parents(i), buf);
+                }
             }
         }
     }
@@ -251,33 +287,50 @@ mod tests {
         graph_bucket::<PedersenHasher>();
     }
 
-    fn gen_proof<H: Hasher>(parallel: bool) {
+    fn gen_proof<H: Hasher>(parallelism: Parallelism) {
         let g = BucketGraph::<H>::new(5, 3, 0, new_seed());
         let node_size = 32;
         let data = vec![2u8; node_size * 5];
 
         let mmapped = &mmap_from(&data);
-        let tree = g.merkle_tree_aux(mmapped, node_size, parallel).unwrap();
+        let tree = g.merkle_tree_aux(mmapped, node_size, parallelism).unwrap();
         let proof = tree.gen_proof(2);
 
         assert!(proof.validate::<H::Function>());
     }
 
+    #[test]
+    fn other_node_sizes_are_refused() {
+        let g = BucketGraph::<PedersenHasher>::new(4, 3, 0, new_seed());
+
+        for node_size in &[16, 64] {
+            let data = vec![2u8; node_size * 4];
+            match g.merkle_tree_aux(&data, *node_size, Parallelism::default()) {
+                Err(Error::InvalidNodeSize(size)) => assert_eq!(*node_size, size),
+                other => panic!("unexpected result: {:?}", other.map(|tree| tree.root())),
+            }
+        }
+
+        assert!(node_size_matches::<PedersenHasher>());
+        assert!(node_size_matches::<Sha256Hasher>());
+        assert!(node_size_matches::<Blake2sHasher>());
+    }
+
     #[test]
     fn gen_proof_pedersen() {
-        gen_proof::<PedersenHasher>(true);
-        gen_proof::<PedersenHasher>(false);
+        gen_proof::<PedersenHasher>(Parallelism::default());
+        gen_proof::<PedersenHasher>(Parallelism::Sequential);
     }
 
     #[test]
     fn gen_proof_sha256() {
-        gen_proof::<Sha256Hasher>(true);
-        gen_proof::<Sha256Hasher>(false);
+        gen_proof::<Sha256Hasher>(Parallelism::default());
+        gen_proof::<Sha256Hasher>(Parallelism::Sequential);
     }
 
     #[test]
     fn gen_proof_blake2s() {
-        gen_proof::<Blake2sHasher>(true);
-        gen_proof::<Blake2sHasher>(false);
+        gen_proof::<Blake2sHasher>(Parallelism::default());
+        gen_proof::<Blake2sHasher>(Parallelism::Sequential);
     }
 }
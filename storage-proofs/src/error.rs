@@ -1,5 +1,9 @@
 use bellman::SynthesisError;
 
+use std::path::PathBuf;
+
+use crate::memory::SealPhase;
+
 pub type Result<T> = ::std::result::Result<T, Error>;
 
 /// Custom error types
@@ -14,7 +18,7 @@ pub enum Error {
         _0, _1, _2
     )]
     InvalidMerkleTreeArgs(usize, usize, usize),
-    #[fail(display = "invalid node size ({}), must be 16, 32 or 64", _0)]
+    #[fail(display = "invalid node size ({}), must be 32", _0)]
     InvalidNodeSize(usize),
     #[fail(display = "{}", _0)]
     Synthesis(#[cause] SynthesisError),
@@ -28,6 +32,40 @@ pub enum Error {
     InvalidInputSize,
     #[fail(display = "merkle tree generation error: {}", _0)]
     MerkleTreeGenerationError(String),
+    #[fail(
+        display = "{} needs {} bytes, over the memory limit of {} bytes",
+        phase, needed, limit
+    )]
+    MemoryLimitExceeded {
+        phase: SealPhase,
+        needed: u64,
+        limit: u64,
+    },
+    #[fail(display = "{:?} has digest {}, not the expected {}", path, actual, expected)]
+    ParameterDigestMismatch {
+        path: PathBuf,
+        expected: String,
+        actual: String,
+    },
+    #[fail(
+        display = "installed parameters {:?} have digest {}, not {} as installed; reinstall them",
+        path, actual, recorded
+    )]
+    InstalledParametersCorrupted {
+        path: PathBuf,
+        recorded: String,
+        actual: String,
+    },
+    #[fail(display = "invalid parameter identifier {:?}", _0)]
+    InvalidParameterIdentifier(String),
+    #[fail(display = "no parameters are installed as {:?}", _0)]
+    ParametersNotInstalled(String),
+    #[fail(display = "parameter generation stopped: {}", reason)]
+    ParameterGenerationCancelled { reason: String, timed_out: bool },
+    #[fail(display = "stopped by the yield hook while {}", phase)]
+    YieldCancelled { phase: String },
+    #[fail(display = "failpoint {} reached", _0)]
+    Failpoint(&'static str),
 }
 
 impl From<SynthesisError> for Error {
@@ -0,0 +1,332 @@
+//! Named points at the boundaries a crash or a failure can leave work at, which tests arm to fail
+//! there on purpose, rather than each hooking into the code it tests in its own way.
+//!
+//! A point is marked with `fail_point!(NAME)` in a function returning a `Result` whose error
+//! converts from `Error`. Armed, it returns `Error::Failpoint`, panics (as a crash would), sleeps
+//! or calls a closure, as the test arming it asks. The points are compiled in only with the
+//! `failpoints` feature (and in this crate's own tests): without it, `fail_point!` expands to
+//! nothing but its name, so release builds neither check nor pay for them.
+//!
+//! Points are armed through a `FailScenario`, which a test holds for as long as they're to stay
+//! armed. Only one scenario exists at a time, so that tests arming points run one after another,
+//! and every point is disarmed when it's dropped, however the test ends. A point armed with
+//! `arm` or `arm_at` only fires on the thread which armed it, so that tests running alongside,
+//! which pass the same points, are unaffected. One reached on other threads (e.g. the yield
+//! hook's, called from the threads building trees) is armed with `arm_on_any_thread`.
+
+#[cfg(any(test, feature = "failpoints"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(any(test, feature = "failpoints"))]
+use std::sync::{Arc, Mutex, MutexGuard};
+#[cfg(any(test, feature = "failpoints"))]
+use std::thread::{self, ThreadId};
+#[cfg(any(test, feature = "failpoints"))]
+use std::time::Duration;
+
+#[cfg(any(test, feature = "failpoints"))]
+use crate::error::{Error, Result};
+
+/// After each layer of a replica is encoded, on the thread replicating it. Its nth hit follows the
+/// nth layer.
+pub const LAYER_REPLICATED: &str = "layer-replicated";
+
+/// Midway through writing groth parameters to the cache: once they're written beside the cache
+/// file, but before they're synced and renamed over it.
+pub const PARAMETER_CACHE_WRITE: &str = "parameter-cache-write";
+
+/// Before the host's yield hook is called. Trees are built on threads of their own, which call it
+/// too.
+pub const YIELD_HOOK_CALL: &str = "yield-hook-call";
+
+/// Before a sealed file, written under a temporary name beside its path, is renamed into place.
+pub const SEALED_FILE_PUBLISH: &str = "sealed-file-publish";
+
+/// After a sector builder writes a piece intent. Staging a piece hits it twice: once the intent is
+/// recorded, and once it marks the piece's bytes as written.
+pub const PIECE_INTENT_WRITTEN: &str = "piece-intent-written";
+
+/// Between a metadata journal entry being written and its changes being applied.
+pub const JOURNAL_WRITTEN: &str = "journal-written";
+
+/// After a sector builder persists the snapshot of its metadata.
+pub const SNAPSHOT_PERSISTED: &str = "snapshot-persisted";
+
+/// Every point marked, by name. Arming any other name panics.
+pub const NAMES: &[&str] = &[
+    LAYER_REPLICATED,
+    PARAMETER_CACHE_WRITE,
+    YIELD_HOOK_CALL,
+    SEALED_FILE_PUBLISH,
+    PIECE_INTENT_WRITTEN,
+    JOURNAL_WRITTEN,
+    SNAPSHOT_PERSISTED,
+];
+
+/// Marks a point, named by one of this module's constants, at which tests may fail on purpose.
+#[cfg(any(test, feature = "failpoints"))]
+#[macro_export]
+macro_rules! fail_point {
+    ($name:expr) => {
+        $crate::failpoint::eval($name)?
+    };
+}
+
+/// Marks a point, named by one of this module's constants, at which tests may fail on purpose.
+#[cfg(not(any(test, feature = "failpoints")))]
+#[macro_export]
+macro_rules! fail_point {
+    ($name:expr) => {
+        $crate::__fail_point_compiled_out!($name)
+    };
+}
+
+// What fail_point! expands to without the feature: the name, which is a constant, is only
+// checked to be a name. Kept apart so that tests, which always have the feature, can check it.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __fail_point_compiled_out {
+    ($name:expr) => {
+        let _: &'static str = $name;
+    };
+}
+
+/// What an armed point does when it's reached.
+#[cfg(any(test, feature = "failpoints"))]
+#[derive(Clone)]
+pub enum FailAction {
+    /// Returns `Error::Failpoint`, naming the point.
+    Error,
+    /// Panics, as a crash there would stop the work.
+    Panic,
+    /// Sleeps, then goes on.
+    Sleep(Duration),
+    /// Calls the closure, then goes on.
+    Call(Arc<Fn() + Send + Sync>),
+}
+
+#[cfg(any(test, feature = "failpoints"))]
+struct ArmedPoint {
+    name: &'static str,
+    action: FailAction,
+    // The thread it fires on, or None for any.
+    thread: Option<ThreadId>,
+    // The hit it fires on, or None for every one.
+    hit: Option<usize>,
+    hits: usize,
+}
+
+#[cfg(any(test, feature = "failpoints"))]
+lazy_static! {
+    static ref SCENARIO: Mutex<()> = Mutex::new(());
+    static ref ARMED_POINTS: Mutex<Vec<ArmedPoint>> = Mutex::new(Vec::new());
+}
+
+// Whether any point is armed, so that points needn't take the lock to find out.
+#[cfg(any(test, feature = "failpoints"))]
+static ANY_ARMED: AtomicBool = AtomicBool::new(false);
+
+/// Holds the points a test arms armed until it's dropped.
+#[cfg(any(test, feature = "failpoints"))]
+pub struct FailScenario {
+    _serialized: MutexGuard<'static, ()>,
+}
+
+#[cfg(any(test, feature = "failpoints"))]
+impl FailScenario {
+    /// Waits for any other scenario to end, and starts one with every point disarmed.
+    pub fn setup() -> FailScenario {
+        let serialized = SCENARIO.lock().unwrap_or_else(|p| p.into_inner());
+        disarm_all();
+
+        FailScenario {
+            _serialized: serialized,
+        }
+    }
+
+    /// Arms the point to act every time this thread reaches it.
+    pub fn arm(&self, name: &'static str, action: FailAction) {
+        arm(name, action, Some(thread::current().id()), None);
+    }
+
+    /// Arms the point to act only the hit-th time (counting from 0) this thread reaches it.
+    pub fn arm_at(&self, name: &'static str, hit: usize, action: FailAction) {
+        arm(name, action, Some(thread::current().id()), Some(hit));
+    }
+
+    /// Arms the point to act every time any thread reaches it.
+    pub fn arm_on_any_thread(&self, name: &'static str, action: FailAction) {
+        arm(name, action, None, None);
+    }
+}
+
+#[cfg(any(test, feature = "failpoints"))]
+impl Drop for FailScenario {
+    fn drop(&mut self) {
+        disarm_all();
+    }
+}
+
+#[cfg(any(test, feature = "failpoints"))]
+fn arm(name: &'static str, action: FailAction, thread: Option<ThreadId>, hit: Option<usize>) {
+    assert!(NAMES.contains(&name), "no failpoint is named {:?}", name);
+
+    let mut armed = ARMED_POINTS.lock().unwrap_or_else(|p| p.into_inner());
+    armed.retain(|point| point.name != name);
+    armed.push(ArmedPoint {
+        name,
+        action,
+        thread,
+        hit,
+        hits: 0,
+    });
+
+    ANY_ARMED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(any(test, feature = "failpoints"))]
+fn disarm_all() {
+    let mut armed = ARMED_POINTS.lock().unwrap_or_else(|p| p.into_inner());
+
+    ANY_ARMED.store(false, Ordering::SeqCst);
+    armed.clear();
+}
+
+/// Acts as the point is armed to, if it is. Called by `fail_point!`.
+#[cfg(any(test, feature = "failpoints"))]
+pub fn eval(name: &'static str) -> Result<()> {
+    if !ANY_ARMED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    // Taken out of the lock, which is released before acting, so that a panic doesn't poison it
+    // and a closure may arm other points.
+    let action = {
+        let current = thread::current().id();
+        let mut armed = ARMED_POINTS.lock().unwrap_or_else(|p| p.into_inner());
+        let point = armed
+            .iter_mut()
+            .find(|point| point.name == name && point.thread.map_or(true, |t| t == current));
+
+        match point {
+            Some(point) => {
+                let hit = point.hits;
+                point.hits += 1;
+
+                match point.hit {
+                    Some(at) if at != hit => return Ok(()),
+                    _ => point.action.clone(),
+                }
+            }
+            None => return Ok(()),
+        }
+    };
+
+    match action {
+        FailAction::Error => Err(Error::Failpoint(name)),
+        FailAction::Panic => panic!("failpoint {} reached", name),
+        FailAction::Sleep(duration) => {
+            thread::sleep(duration);
+            Ok(())
+        }
+        FailAction::Call(f) => {
+            f();
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::panic;
+    use std::sync::atomic::AtomicUsize;
+
+    fn reach(name: &'static str) -> Result<()> {
+        fail_point!(name);
+        Ok(())
+    }
+
+    #[test]
+    fn armed_points_act_until_disarmed() {
+        {
+            let scenario = FailScenario::setup();
+            assert!(reach(JOURNAL_WRITTEN).is_ok());
+
+            scenario.arm(JOURNAL_WRITTEN, FailAction::Error);
+            for _ in 0..2 {
+                match reach(JOURNAL_WRITTEN) {
+                    Err(Error::Failpoint(name)) => assert_eq!(JOURNAL_WRITTEN, name),
+                    other => panic!("unexpected result: {:?}", other),
+                }
+            }
+            assert!(reach(SNAPSHOT_PERSISTED).is_ok());
+
+            scenario.arm(SNAPSHOT_PERSISTED, FailAction::Panic);
+            assert!(panic::catch_unwind(|| reach(SNAPSHOT_PERSISTED)).is_err());
+        }
+
+        // Dropping the scenario disarmed them.
+        let _scenario = FailScenario::setup();
+        assert!(reach(JOURNAL_WRITTEN).is_ok());
+        assert!(reach(SNAPSHOT_PERSISTED).is_ok());
+    }
+
+    #[test]
+    fn points_armed_at_a_hit_act_only_then() {
+        let scenario = FailScenario::setup();
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counted = calls.clone();
+
+        scenario.arm_at(
+            LAYER_REPLICATED,
+            2,
+            FailAction::Call(Arc::new(move || {
+                counted.fetch_add(1, Ordering::SeqCst);
+            })),
+        );
+
+        for _ in 0..5 {
+            reach(LAYER_REPLICATED).unwrap();
+        }
+        assert_eq!(1, calls.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn points_act_only_on_the_threads_they_are_armed_for() {
+        let scenario = FailScenario::setup();
+
+        scenario.arm(PIECE_INTENT_WRITTEN, FailAction::Error);
+        assert!(thread::spawn(|| reach(PIECE_INTENT_WRITTEN).is_ok())
+            .join()
+            .unwrap());
+        assert!(reach(PIECE_INTENT_WRITTEN).is_err());
+
+        scenario.arm_on_any_thread(PIECE_INTENT_WRITTEN, FailAction::Error);
+        assert!(thread::spawn(|| reach(PIECE_INTENT_WRITTEN).is_err())
+            .join()
+            .unwrap());
+    }
+
+    #[test]
+    #[should_panic(expected = "no failpoint is named")]
+    fn unknown_points_are_refused() {
+        FailScenario::setup().arm("no-such-point", FailAction::Panic);
+    }
+
+    // What fail_point! expands to without the feature neither acts nor returns: it can mark a
+    // point in a function which returns no Result at all.
+    fn reach_compiled_out(name: &'static str) -> usize {
+        __fail_point_compiled_out!(name);
+        7
+    }
+
+    #[test]
+    fn compiled_out_points_do_nothing() {
+        let scenario = FailScenario::setup();
+
+        scenario.arm(SEALED_FILE_PUBLISH, FailAction::Panic);
+        assert_eq!(7, reach_compiled_out(SEALED_FILE_PUBLISH));
+        assert!(panic::catch_unwind(|| reach(SEALED_FILE_PUBLISH)).is_err());
+    }
+}
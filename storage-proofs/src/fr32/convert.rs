@@ -1,4 +1,7 @@
+//! Conversions between Frs and their Fr32 bytes.
+
 use crate::error::*;
+use crate::types::FR32_BYTES;
 
 use byteorder::{LittleEndian, WriteBytesExt};
 
@@ -17,15 +20,14 @@ pub type Fr32 = [u8];
 // That is to say: each 32-byte chunk taken alone must be a valid Fr32.
 pub type Fr32Vec = Vec<u8>;
 
-// Array whose little-endian value represents an Fr.
-// Invariants:
-// - Value MUST represent a valid Fr.
-pub type Fr32Ary = [u8; 32];
+// Array whose little-endian value represents an Fr, declared with the other
+// fundamental types.
+pub use crate::types::Fr32Ary;
 
 // Takes a slice of bytes and returns an Fr if byte slice is exactly 32 bytes and does not overflow.
 // Otherwise, returns a BadFrBytesError.
 pub fn bytes_into_fr<E: Engine>(bytes: &[u8]) -> Result<E::Fr> {
-    if bytes.len() != 32 {
+    if bytes.len() != FR32_BYTES {
         return Err(Error::BadFrBytes);
     }
     let mut fr_repr = <<<E as Engine>::Fr as PrimeField>::Repr as Default>::default();
@@ -36,7 +38,7 @@ pub fn bytes_into_fr<E: Engine>(bytes: &[u8]) -> Result<E::Fr> {
 
 // Takes an Fr and returns a vector of exactly 32 bytes guaranteed to contain a valid Fr.
 pub fn fr_into_bytes<E: Engine>(fr: &E::Fr) -> Fr32Vec {
-    let mut out = Vec::with_capacity(32);
+    let mut out = Vec::with_capacity(FR32_BYTES);
     fr.into_repr().write_le(&mut out).unwrap();
     out
 }
@@ -45,7 +47,7 @@ pub fn fr_into_bytes<E: Engine>(fr: &E::Fr) -> Fr32Vec {
 // or any 32-byte chunk overflows and does not contain a valid Fr.
 pub fn bytes_into_frs<E: Engine>(bytes: &[u8]) -> Result<Vec<E::Fr>> {
     bytes
-        .chunks(32)
+        .chunks(FR32_BYTES)
         .map(|ref chunk| bytes_into_fr::<E>(chunk))
         .collect()
 }
@@ -58,7 +60,7 @@ pub fn frs_into_bytes<E: Engine>(frs: &[E::Fr]) -> Fr32Vec {
 
 // Takes a u32 and returns an Fr.
 pub fn u32_into_fr<E: Engine>(n: u32) -> E::Fr {
-    let mut buf: Fr32Vec = vec![0u8; 32];
+    let mut buf: Fr32Vec = vec![0u8; FR32_BYTES];
     let mut w = &mut buf[0..4];
     w.write_u32::<LittleEndian>(n).unwrap();
 
@@ -68,7 +70,7 @@ pub fn u32_into_fr<E: Engine>(n: u32) -> E::Fr {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use pairing::bls12_381::Bls12;
+    use pairing::bls12_381::{Bls12, FrRepr};
 
     fn bytes_fr_test<E: Engine>(bytes: Fr32Ary, expect_success: bool) {
         let mut b = &bytes[..];
@@ -125,6 +127,29 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_fr_bytes_are_little_endian() {
+        // The least significant limb comes first, and each limb is written least
+        // significant byte first, whatever the byte order of the host.
+        let bytes: Vec<u8> = (0..32).collect();
+        let fr = bytes_into_fr::<Bls12>(&bytes).unwrap();
+
+        assert_eq!(
+            FrRepr([
+                0x0706_0504_0302_0100,
+                0x0f0e_0d0c_0b0a_0908,
+                0x1716_1514_1312_1110,
+                0x1f1e_1d1c_1b1a_1918,
+            ]),
+            fr.into_repr()
+        );
+        assert_eq!(bytes, fr_into_bytes::<Bls12>(&fr));
+
+        let mut expected = vec![0u8; 32];
+        expected[..4].copy_from_slice(&[0x01, 0x02, 0x03, 0x04]);
+        assert_eq!(expected, fr_into_bytes::<Bls12>(&u32_into_fr::<Bls12>(0x0403_0201)));
+    }
+
     fn bytes_into_frs_into_bytes_test<E: Engine>(bytes: &Fr32) {
         let mut bytes = bytes.clone();
         let frs = bytes_into_frs::<E>(&mut bytes).unwrap();
@@ -0,0 +1,75 @@
+//! Fr32: the 32-byte, little-endian representation of an Fr, and the bits of
+//! it which data may fill. An Fr holds any 254-bit value, so data is padded
+//! (see sector_base::io::fr32) by writing 254 bits of it into each 32-byte
+//! element and leaving the 2 most significant bits zero. The constants and
+//! masking helpers here are shared by the conversions (`convert`, re-exported
+//! here) and the padding, so that the two agree on which bits are data.
+
+pub mod convert;
+
+pub use self::convert::*;
+
+use crate::types::FR32_BYTES;
+
+/// The bits of an Fr32 which data may fill: any 254-bit value is in the field.
+pub const FR_UNPADDED_BITS: usize = 254;
+
+/// The bits of an Fr32, padding included.
+pub const FR_PADDED_BITS: usize = FR32_BYTES * 8;
+
+/// Clears the bits of byte from offset to the MSB, offset included.
+#[inline]
+pub fn clear_left_bits(byte: &mut u8, offset: usize) {
+    *(byte) &= (1 << offset) - 1
+}
+
+/// Clears the bits of byte from offset (excluded) to the LSB.
+#[inline]
+pub fn clear_right_bits(byte: &mut u8, offset: usize) {
+    *(byte) &= !((1 << offset) - 1)
+}
+
+/// Clears the padding bits of bytes, which must be 32 long, leaving a valid
+/// Fr32 whatever the bytes were.
+pub fn trim_to_fr32(bytes: &mut Fr32) {
+    assert_eq!(FR32_BYTES, bytes.len(), "an Fr32 is {} bytes", FR32_BYTES);
+
+    clear_left_bits(&mut bytes[FR32_BYTES - 1], FR_UNPADDED_BITS % 8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use pairing::bls12_381::{Bls12, Fr};
+    use pairing::PrimeField;
+
+    #[test]
+    fn unpadded_bits_are_the_capacity_of_fr() {
+        assert_eq!(Fr::CAPACITY as usize, FR_UNPADDED_BITS);
+        assert!(FR_PADDED_BITS - FR_UNPADDED_BITS < 8);
+    }
+
+    #[test]
+    fn trimmed_bytes_are_valid_fr32() {
+        let mut bytes = [0xff; FR32_BYTES];
+        assert!(bytes_into_fr::<Bls12>(&bytes).is_err());
+
+        trim_to_fr32(&mut bytes);
+
+        assert_eq!(0b0011_1111, bytes[FR32_BYTES - 1]);
+        assert!(bytes[..FR32_BYTES - 1].iter().all(|b| *b == 0xff));
+        assert!(bytes_into_fr::<Bls12>(&bytes).is_ok());
+    }
+
+    #[test]
+    fn test_clear_bits() {
+        let mut byte = 0xff;
+        clear_left_bits(&mut byte, 6);
+        assert_eq!(0b0011_1111, byte);
+
+        let mut byte = 0xff;
+        clear_right_bits(&mut byte, 6);
+        assert_eq!(0b1100_0000, byte);
+    }
+}
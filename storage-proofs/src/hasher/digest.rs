@@ -11,6 +11,7 @@ use sha2::Digest;
 use super::{Domain, HashFunction, Hasher};
 use crate::crypto::sloth;
 use crate::error::*;
+use crate::fr32;
 
 pub trait Digester: Digest + Clone + Default + ::std::fmt::Debug + Send + Sync {}
 
@@ -92,7 +93,7 @@ pub struct DigestDomain(pub [u8; 32]);
 impl DigestDomain {
     fn trim_to_fr32(&mut self) {
         // strip last two bits, to ensure result is in Fr.
-        self.0[31] &= 0b0011_1111;
+        fr32::trim_to_fr32(&mut self.0);
     }
 }
 
@@ -1,13 +1,13 @@
 use std::hash::Hasher as StdHasher;
 
 use bitvec::{self, BitVec};
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
 use merkle_light::hash::{Algorithm as LightAlgorithm, Hashable};
 use pairing::bls12_381::{Bls12, Fr, FrRepr};
 use pairing::{PrimeField, PrimeFieldRepr};
 use rand::{Rand, Rng};
 use sapling_crypto::pedersen_hash::{pedersen_hash, Personalization};
-use serde::de::{Deserialize, Deserializer};
+use serde::de::{self, Deserialize, Deserializer};
 use serde::ser::Serializer;
 
 use super::{Domain, HashFunction, Hasher};
@@ -96,18 +96,59 @@ impl FrReprDef {
     {
         let arr: Vec<u8> = if deserializer.is_human_readable() {
             let raw = String::deserialize(deserializer)?;
-            base64::decode(&raw).unwrap()
+            base64::decode(&raw).map_err(de::Error::custom)?
         } else {
-            Vec::deserialize(deserializer)?
+            deserializer.deserialize_bytes(BytesVisitor)?
         };
 
+        if arr.len() != 32 {
+            return Err(de::Error::invalid_length(arr.len(), &"32 bytes"));
+        }
+
         let mut digits = [0u64; 4];
-        let mut source = ::std::io::Cursor::new(arr);
-        for digit in digits.iter_mut() {
-            *digit = source.read_u64::<LittleEndian>().unwrap();
+        for (digit, bytes) in digits.iter_mut().zip(arr.chunks(8)) {
+            *digit = LittleEndian::read_u64(bytes);
+        }
+
+        // Domains are converted to field elements as they're hashed, which panics for any other
+        // value, so only those of field elements are accepted from serialized proofs.
+        let repr = FrRepr(digits);
+        if Fr::from_repr(repr).is_err() {
+            return Err(de::Error::custom("not the representation of a field element"));
         }
 
-        Ok(FrRepr(digits))
+        Ok(repr)
+    }
+}
+
+// Reads what FrReprDef::serialize writes to binary formats, which is a byte
+// string rather than a sequence of u8s.
+struct BytesVisitor;
+
+impl<'de> de::Visitor<'de> for BytesVisitor {
+    type Value = Vec<u8>;
+
+    fn expecting(&self, formatter: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        formatter.write_str("32 bytes")
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> ::std::result::Result<Vec<u8>, E> {
+        Ok(v.to_vec())
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> ::std::result::Result<Vec<u8>, E> {
+        Ok(v)
+    }
+
+    fn visit_seq<A: de::SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> ::std::result::Result<Vec<u8>, A::Error> {
+        let mut bytes = Vec::with_capacity(32);
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(bytes)
     }
 }
 
@@ -138,6 +179,9 @@ impl PartialOrd for PedersenDomain {
     }
 }
 
+/// The bytes of the limbs as they lie in memory, which are their little-endian
+/// encoding only on little-endian hosts. Anything written out or hashed must use
+/// `into_bytes` or `write_bytes` instead, which are little-endian on every host.
 impl AsRef<[u8]> for PedersenDomain {
     #[inline]
     fn as_ref(&self) -> &[u8] {
@@ -369,9 +413,33 @@ mod tests {
                     mem::transmute::<[u8; 8], u64>(val)
                 });
             }
+
+            if cfg!(target_endian = "little") {
+                assert_eq!(raw, &val.into_bytes()[..]);
+            }
         }
     }
 
+    #[test]
+    fn test_into_bytes_is_little_endian() {
+        let val = PedersenDomain(FrRepr([
+            0x0706_0504_0302_0100,
+            0x0f0e_0d0c_0b0a_0908,
+            0x1716_1514_1312_1110,
+            0x1f1e_1d1c_1b1a_1918,
+        ]));
+        let expected: Vec<u8> = (0..32).collect();
+
+        assert_eq!(expected, val.into_bytes());
+        assert_eq!(expected, val.serialize());
+
+        let mut written = [0u8; 32];
+        val.write_bytes(&mut written).unwrap();
+        assert_eq!(&expected[..], &written[..]);
+
+        assert_eq!(val, PedersenDomain::try_from_bytes(&expected).unwrap());
+    }
+
     #[test]
     fn test_serialize() {
         let repr = FrRepr([1, 2, 3, 4]);
@@ -381,5 +449,24 @@ mod tests {
         let val_back = serde_json::from_str(&ser).unwrap();
 
         assert_eq!(val, val_back);
+
+        // Binary formats are written as a byte string, as proofs are.
+        let ser = serde_cbor::to_vec(&val).unwrap();
+        let val_back = serde_cbor::from_slice(&ser).unwrap();
+
+        assert_eq!(val, val_back);
+    }
+
+    #[test]
+    fn deserializing_rejects_what_is_not_a_field_element() {
+        let too_short = serde_json::to_string(&base64::encode(&[1u8; 31])).unwrap();
+        assert!(serde_json::from_str::<PedersenDomain>(&too_short).is_err());
+
+        let not_base64 = serde_json::to_string("not base64!").unwrap();
+        assert!(serde_json::from_str::<PedersenDomain>(&not_base64).is_err());
+
+        // Above the field's modulus.
+        let too_big = serde_json::to_string(&base64::encode(&[0xff; 32])).unwrap();
+        assert!(serde_json::from_str::<PedersenDomain>(&too_big).is_err());
     }
 }
@@ -1,3 +1,4 @@
+use crate::crypto::constant_time::constant_time_eq;
 use crate::error::Result;
 use merkle_light::hash::{Algorithm as LightAlgorithm, Hashable as LightHashable};
 use pairing::bls12_381::Fr;
@@ -26,6 +27,12 @@ pub trait Domain:
     fn try_from_bytes(raw: &[u8]) -> Result<Self>;
     /// Write itself into the given slice, LittleEndian bytes.
     fn write_bytes(&self, _: &mut [u8]) -> Result<()>;
+
+    /// Whether other is equal, compared in constant time. Verification
+    /// compares roots and commitments with this rather than with `==`.
+    fn ct_eq(&self, other: &Self) -> bool {
+        constant_time_eq(self.as_ref(), other.as_ref())
+    }
 }
 
 pub trait HashFunction<T: Domain>:
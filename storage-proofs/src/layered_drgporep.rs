@@ -1,6 +1,9 @@
 use std::cmp::{max, min};
+use std::ops::Range;
 use std::sync::mpsc::channel;
+use std::time::Instant;
 
+use byteorder::{LittleEndian, WriteBytesExt};
 use crossbeam_utils::thread;
 use rayon::prelude::*;
 use serde::de::Deserialize;
@@ -8,17 +11,27 @@ use serde::ser::Serialize;
 use slog::*;
 
 use crate::challenge_derivation::derive_challenges;
+use crate::crypto::blake2s::blake2s;
 use crate::drgporep::{self, DrgPoRep};
 use crate::drgraph::Graph;
 use crate::error::{Error, Result};
+use crate::failpoint;
 use crate::hasher::{Domain, HashFunction, Hasher};
-use crate::merkle::MerkleTree;
+use crate::memory::{self, SealPhase};
+use crate::merkle::{MerkleTree, Parallelism};
+use crate::node_source::NodeSource;
 use crate::parameter_cache::ParameterSetIdentifier;
 use crate::porep::{self, PoRep};
 use crate::proof::ProofScheme;
+use crate::util::{data_at_node_offset, node_size_matches, NODE_SIZE};
 use crate::vde;
+use crate::yield_hook::{self, DECODING, PROVING};
 use crate::SP_LOG;
 
+/// The largest fraction of a graph's nodes which `Layers::extract_range` decodes per layer by
+/// following dependencies. Past it, decoding every node (which needs no bookkeeping) is cheaper.
+pub const MAX_CONE_FRACTION: f64 = 0.5;
+
 type Tree<H> = MerkleTree<<H as Hasher>::Domain, <H as Hasher>::Function>;
 
 #[derive(Debug, Clone)]
@@ -151,6 +164,10 @@ pub type EncodingProof<H> = drgporep::Proof<H>;
 #[derive(Debug, Clone)]
 pub struct PublicInputs<T: Domain> {
     pub replica_id: T,
+    /// The seed every layer's challenges are derived from, as returned by `challenge_seed`. How
+    /// many each layer gets is set by the public parameters' `LayerChallenges`, so isn't carried
+    /// here.
+    pub challenge_seed: [u8; 32],
     pub tau: Option<porep::Tau<T>>,
     pub comm_r_star: T,
     pub k: Option<usize>,
@@ -164,17 +181,28 @@ impl<T: Domain> PublicInputs<T> {
         layer: u8,
         partition_k: Option<usize>,
     ) -> Vec<usize> {
-        derive_challenges::<T>(
-            layer_challenges,
+        derive_challenges(
+            &self.challenge_seed,
             layer,
             leaves,
-            &self.replica_id,
-            &self.comm_r_star,
+            layer_challenges.challenges_for_layer(layer as usize),
             partition_k.unwrap_or(0) as u8,
         )
     }
 }
 
+/// The seed from which the challenges of a replica with the given `comm_r_star` are derived.
+/// Hashing in the commitment to every layer means the challenges can't be known, and so
+/// prepared for, before replicating.
+pub fn challenge_seed<D: Domain>(replica_id: &D, comm_r_star: &D) -> [u8; 32] {
+    let mut bytes = replica_id.into_bytes();
+    bytes.extend(comm_r_star.into_bytes());
+
+    let mut seed = [0; 32];
+    seed.copy_from_slice(&blake2s(&bytes));
+    seed
+}
+
 pub struct PrivateInputs<H: Hasher> {
     pub aux: Vec<Tree<H>>,
     pub tau: Vec<porep::Tau<H::Domain>>,
@@ -190,9 +218,66 @@ pub struct Proof<H: Hasher> {
     pub tau: Vec<porep::Tau<H::Domain>>,
 }
 
+/// The length of the challenge count which precedes each layer's encoding proof in a serialized
+/// proof.
+const LAYER_HEADER_BYTES: usize = 4;
+
 impl<H: Hasher> Proof<H> {
+    /// Serializes the proof: for each layer, its challenge count (as a little-endian u32) and its
+    /// encoding proof, followed by each layer's tau (its comm_d, then its comm_r).
     pub fn serialize(&self) -> Vec<u8> {
-        unimplemented!();
+        let mut out = Vec::with_capacity(self.serialized_size());
+
+        for encoding_proof in &self.encoding_proofs {
+            out.write_u32::<LittleEndian>(encoding_proof.nodes.len() as u32).unwrap();
+            out.extend(encoding_proof.serialize());
+        }
+
+        for tau in &self.tau {
+            out.extend(Domain::serialize(&tau.comm_d));
+            out.extend(Domain::serialize(&tau.comm_r));
+        }
+
+        assert_eq!(
+            self.serialized_size(),
+            out.len(),
+            "serialized_size disagrees with the serialization"
+        );
+
+        out
+    }
+
+    /// The length of the proof's serialization, computed without serializing it.
+    pub fn serialized_size(&self) -> usize {
+        let layers: usize = self
+            .encoding_proofs
+            .iter()
+            .map(|proof| LAYER_HEADER_BYTES + proof.serialized_size())
+            .sum();
+
+        layers + self.tau.len() * 2 * NODE_SIZE
+    }
+
+    /// The length of the serialization of a proof with the given public params, computed from
+    /// them alone, so that a prover can tell how large its proofs will be before making them.
+    /// As drgporep::Proof::projected_size, of which it sums each layer's, it's exact for plain
+    /// DRGs, and an upper bound for zigzag graphs.
+    pub fn projected_size<G>(pub_params: &PublicParams<H, G>) -> usize
+    where
+        G: Graph<H> + ParameterSetIdentifier,
+    {
+        let layer_challenges = &pub_params.layer_challenges;
+        let layers: usize = (0..layer_challenges.layers())
+            .map(|layer| {
+                LAYER_HEADER_BYTES
+                    + EncodingProof::<H>::projected_size(
+                        &pub_params.drg_porep_public_params,
+                        layer_challenges.challenges_for_layer(layer),
+                    )
+            })
+            .sum();
+
+        layers + layer_challenges.layers() * 2 * NODE_SIZE
     }
 }
 
@@ -221,97 +306,216 @@ pub trait Layers {
     type Hasher: Hasher;
     type Graph: Layerable<Self::Hasher> + ParameterSetIdentifier + Sync + Send;
 
-    /// transform a layer's public parameters, returning new public parameters corresponding to the next layer.
+    /// transform the public parameters of layer `layer` of `layers`, returning those of the next
+    /// layer. The replica itself is the (never encoded) layer `layers`.
     fn transform(
         pp: &drgporep::PublicParams<Self::Hasher, Self::Graph>,
         layer: usize,
         layers: usize,
     ) -> drgporep::PublicParams<Self::Hasher, Self::Graph>;
 
-    /// transform a layer's public parameters, returning new public parameters corresponding to the previous layer.
+    /// transform the public parameters of layer `layer` of `layers`, returning those of the
+    /// previous layer. Extraction starts from the replica's, those of layer `layers`.
     fn invert_transform(
         pp: &drgporep::PublicParams<Self::Hasher, Self::Graph>,
         layer: usize,
         layers: usize,
     ) -> drgporep::PublicParams<Self::Hasher, Self::Graph>;
 
-    #[allow(clippy::too_many_arguments)]
-    fn prove_layers<'a>(
+    /// Proves each layer in turn, returning the proofs of each layer's partitions. `aux` holds
+    /// the tree of every layer and of the replica, so each layer is proven with the trees
+    /// `aux[layer]` and `aux[layer + 1]`. The replica's nodes are read from `replica` if given
+    /// (e.g. from its file, so that only the challenged nodes and their parents are read), and
+    /// otherwise from the leaves of its tree, as are every other layer's. The yield hook (see
+    /// yield_hook) is called before each layer is proven.
+    fn prove_layers(
         pp: &drgporep::PublicParams<Self::Hasher, Self::Graph>,
         pub_inputs: &PublicInputs<<Self::Hasher as Hasher>::Domain>,
         tau: &[PorepTau<Self::Hasher>],
-        aux: &'a [Tree<Self::Hasher>],
+        aux: &[Tree<Self::Hasher>],
+        replica: Option<&NodeSource>,
         layer_challenges: &LayerChallenges,
-        layers: usize,
-        total_layers: usize,
         partition_count: usize,
     ) -> Result<Vec<Vec<EncodingProof<Self::Hasher>>>> {
+        let layers = layer_challenges.layers();
         assert!(layers > 0);
+        assert_eq!(tau.len(), layers);
+        assert_eq!(aux.len(), layers + 1);
 
-        let mut new_pp = None;
+        let mut proofs = Vec::with_capacity(layers);
+        let mut layer_pp = (*pp).clone();
 
-        (0..layers)
-            .map(|layer| {
-                let pp = match new_pp {
-                    Some(ref new_pp) => new_pp,
-                    None => pp,
-                };
-                let inner_layers = layers - layer;
+        for layer in 0..layers {
+            yield_hook::yield_now(PROVING)?;
 
-                let new_priv_inputs = drgporep::PrivateInputs {
-                    aux: &porep::ProverAux {
-                        tree_d: aux[layer].clone(),
-                        tree_r: aux[layer + 1].clone(),
-                    },
-                };
-                let layer_diff = total_layers - inner_layers;
-
-                let partition_proofs: Vec<_> = (0..partition_count)
-                    .into_par_iter()
-                    .map(|k| {
-                        let drgporep_pub_inputs = drgporep::PublicInputs {
-                            replica_id: pub_inputs.replica_id,
-                            challenges: pub_inputs.challenges(
-                                layer_challenges,
-                                pp.graph.size(),
-                                layer_diff as u8,
-                                Some(k),
-                            ),
-                            tau: Some(tau[layer]),
-                        };
+            let priv_inputs = drgporep::PrivateInputs {
+                tree_d: &aux[layer],
+                tree_r: &aux[layer + 1],
+                replica: match replica {
+                    Some(replica) if layer + 1 == layers => replica,
+                    _ => &aux[layer + 1],
+                },
+            };
 
-                        DrgPoRep::prove(pp, &drgporep_pub_inputs, &new_priv_inputs)
-                    })
-                    .collect::<Result<Vec<_>>>()?;
+            let partition_proofs = (0..partition_count)
+                .into_par_iter()
+                .map(|k| {
+                    let drgporep_pub_inputs = drgporep::PublicInputs {
+                        replica_id: pub_inputs.replica_id,
+                        challenge_seed: pub_inputs.challenge_seed,
+                        challenge_count: layer_challenges.challenges_for_layer(layer),
+                        k: Some(k),
+                        tau: Some(tau[layer]),
+                    };
 
-                new_pp = Some(Self::transform(pp, layer_diff, total_layers));
+                    DrgPoRep::prove_at_layer(&layer_pp, &drgporep_pub_inputs, &priv_inputs, layer)
+                })
+                .collect::<Result<Vec<_>>>()?;
 
-                Ok(partition_proofs)
-            })
-            .collect::<Result<Vec<_>>>()
+            proofs.push(partition_proofs);
+
+            if layer + 1 < layers {
+                layer_pp = Self::transform(&layer_pp, layer, layers);
+            }
+        }
+
+        Ok(proofs)
     }
 
-    fn extract_and_invert_transform_layers<'a>(
+    /// Decodes `data`, the replica of `layers` layers, in place. `drgpp` are the public
+    /// parameters of the replica, as transformed from those of the first layer `layers` times.
+    fn extract_and_invert_transform_layers(
         drgpp: &drgporep::PublicParams<Self::Hasher, Self::Graph>,
         layers: usize,
         replica_id: &<Self::Hasher as Hasher>::Domain,
-        data: &'a mut [u8],
+        data: &mut [u8],
     ) -> Result<()> {
         assert!(layers > 0);
 
-        (0..layers).fold((*drgpp).clone(), |current_drgpp, layer| {
-            let inverted = Self::invert_transform(&current_drgpp, layer, layers);
-            let mut res = DrgPoRep::extract_all(&inverted, replica_id, data).unwrap();
+        let mut layer_pp = (*drgpp).clone();
 
-            for (i, r) in res.iter_mut().enumerate() {
-                data[i] = *r;
-            }
-            inverted
-        });
+        for layer in (0..layers).rev() {
+            layer_pp = Self::invert_transform(&layer_pp, layer + 1, layers);
+            let res = DrgPoRep::extract_all(&layer_pp, replica_id, data)?;
+
+            data[..res.len()].copy_from_slice(&res);
+        }
 
         Ok(())
     }
 
+    /// Decodes the original data of the nodes in `nodes`, decoding in each layer only the nodes
+    /// which those depend on, rather than every node as extract_and_invert_transform_layers does.
+    ///
+    /// Decoding a node needs its layer's encoded values of the node and of its parents, which are
+    /// themselves decoded from the layer before. So, walking backwards from the requested nodes,
+    /// each layer must decode the nodes needed by the next one plus all of their parents. As
+    /// zigzag graphs alternate direction, this cone of dependencies widens on both sides.
+    ///
+    /// Returns None, without decoding anything, if the cone of any layer holds more than
+    /// `max_cone_nodes` nodes.
+    fn extract_cone_and_invert_transform_layers(
+        drgpp: &drgporep::PublicParams<Self::Hasher, Self::Graph>,
+        layers: usize,
+        replica_id: &<Self::Hasher as Hasher>::Domain,
+        data: &[u8],
+        nodes: Range<usize>,
+        max_cone_nodes: usize,
+    ) -> Result<Option<Vec<u8>>> {
+        assert!(layers > 0);
+        assert!(nodes.end <= drgpp.graph.size(), "nodes out of range");
+
+        if nodes.len() > max_cone_nodes {
+            return Ok(None);
+        }
+
+        // The public parameters each layer is decoded with, in the order they're used by
+        // extract_and_invert_transform_layers.
+        let layer_pps: Vec<_> = (0..layers)
+            .rev()
+            .scan((*drgpp).clone(), |current_drgpp, layer| {
+                *current_drgpp = Self::invert_transform(current_drgpp, layer + 1, layers);
+                Some(current_drgpp.clone())
+            })
+            .collect();
+
+        // The nodes decoded in each layer, starting with the last.
+        let mut cones: Vec<Vec<usize>> = vec![nodes.clone().collect()];
+        let mut parents = Vec::with_capacity(drgpp.graph.degree());
+
+        for pp in layer_pps[1..].iter().rev() {
+            let outer = &cones[cones.len() - 1];
+            let mut cone = outer.clone();
+
+            for node in outer {
+                pp.graph.parents_into(*node, &mut parents);
+                cone.extend_from_slice(&parents);
+            }
+
+            cone.sort_unstable();
+            cone.dedup();
+
+            if cone.len() > max_cone_nodes {
+                return Ok(None);
+            }
+
+            cones.push(cone);
+        }
+
+        let mut data = data.to_vec();
+
+        for (pp, cone) in layer_pps.iter().zip(cones.iter().rev()) {
+            // Every node of a layer is decoded before any is written back, as they all need the
+            // previous layer's values of their parents.
+            let decoded = cone
+                .par_iter()
+                .map(|node| {
+                    yield_hook::yield_point(DECODING, 1)?;
+                    vde::decode_block(&pp.graph, pp.sloth_iter, replica_id, &data, *node)
+                })
+                .collect::<Result<Vec<_>>>()?;
+
+            for (node, value) in cone.iter().zip(decoded) {
+                let start = data_at_node_offset(*node);
+                value.write_bytes(&mut data[start..start + NODE_SIZE])?;
+            }
+        }
+
+        Ok(Some(
+            data[data_at_node_offset(nodes.start)..data_at_node_offset(nodes.end)].to_vec(),
+        ))
+    }
+
+    /// Returns the original data of the nodes in `nodes`. Unless their cone of dependencies covers
+    /// more than MAX_CONE_FRACTION of the graph in some layer, only that cone is decoded.
+    /// Otherwise, every node is.
+    fn extract_range(
+        pp: &PublicParams<Self::Hasher, Self::Graph>,
+        replica_id: &<Self::Hasher as Hasher>::Domain,
+        data: &[u8],
+        nodes: Range<usize>,
+    ) -> Result<Vec<u8>> {
+        let drgpp = &pp.drg_porep_public_params;
+        let layers = pp.layer_challenges.layers();
+        let max_cone_nodes = (drgpp.graph.size() as f64 * MAX_CONE_FRACTION) as usize;
+
+        if let Some(extracted) = Self::extract_cone_and_invert_transform_layers(
+            drgpp,
+            layers,
+            replica_id,
+            data,
+            nodes.clone(),
+            max_cone_nodes,
+        )? {
+            return Ok(extracted);
+        }
+
+        let mut data = data.to_vec();
+        Self::extract_and_invert_transform_layers(drgpp, layers, replica_id, &mut data)?;
+
+        Ok(data[data_at_node_offset(nodes.start)..data_at_node_offset(nodes.end)].to_vec())
+    }
+
     fn transform_and_replicate_layers(
         drgpp: &drgporep::PublicParams<Self::Hasher, Self::Graph>,
         layers: usize,
@@ -355,6 +559,14 @@ pub trait Layers {
             // for merkle tree generation and sending the results back to a channel.
             // The received results need to be sorted by layer because ordering of the completed results
             // is not guaranteed. Misordered results will be seen in practice when trees are small.
+            //
+            // Nothing replication returns, or a seal persists from it, may depend on how its
+            // threads are scheduled, nor on how many there are: layers are encoded one after
+            // another, each tree is built over subtrees fixed by node index (see MerkleTree), and
+            // every parallel result is collected in index order, or sorted as the trees are. The
+            // trees are built with the parallelism of the pool replication is called from, which
+            // the threads spawned here would otherwise not see.
+            let parallelism = Parallelism::default();
 
             // The outer scope ensure that `tx` is dropped and closed before we read from `outer_rx`.
             // Otherwise, the read loop will block forever waiting for more input.
@@ -367,10 +579,18 @@ pub trait Layers {
                     Error::MerkleTreeGenerationError(err_string)
                 };
 
-                let _ = thread::scope(|scope| -> Result<()> {
+                thread::scope(|scope| -> Result<()> {
                     let mut threads = Vec::with_capacity(layers + 1);
-                    let initial_pp = (*drgpp).clone();
-                    (0..=layers).fold(initial_pp, |current_drgpp, layer| {
+                    let mut current_drgpp = (*drgpp).clone();
+                    for layer in 0..=layers {
+                        // The copy is released once its tree has been built, but the tree is
+                        // kept until the layers are proven.
+                        let copy_reservation =
+                            memory::reserve(SealPhase::CopyLayers, data.len() as u64)?;
+                        let tree_bytes =
+                            memory::merkle_tree_bytes::<Self::Hasher>(current_drgpp.graph.size());
+                        memory::reserve(SealPhase::BuildTrees, tree_bytes)?.keep();
+
                         let mut data_copy = vec![0; data.len()];
                         data_copy[0..data.len()].clone_from_slice(data);
 
@@ -380,38 +600,47 @@ pub trait Layers {
 
                         transfer_tx.send(current_drgpp.clone()).unwrap();
 
-                        let thread = scope.spawn(move |_| {
+                        let thread = scope.spawn(move |_| -> Result<()> {
                             // If we panic anywhere in this closure, thread.join() below will receive an error —
-                            // so it is safe to unwrap.
+                            // so it is safe to unwrap. Building the tree fails, rather than
+                            // panics, if the yield hook asks it to stop.
                             let drgpp = transfer_rx.recv().unwrap();
-                            let tree_d = drgpp.graph.merkle_tree(&data_copy).unwrap();
+                            let tree_d = drgpp
+                                .graph
+                                .merkle_tree_aux(&data_copy, NODE_SIZE, parallelism)?;
+                            drop(data_copy);
+                            drop(copy_reservation);
 
                             info!(SP_LOG, "returning tree"; "layer" => format!("{}", layer));
                             return_channel.send((layer, tree_d)).unwrap();
+
+                            Ok(())
                         });
 
                         threads.push(thread);
 
                         if layer < layers {
-                            info!(SP_LOG, "encoding"; "layer {}" => format!("{}", layer));
+                            info!(SP_LOG, "encoding"; "layer" => format!("{}", layer));
+                            let start = Instant::now();
                             vde::encode(
                                 &current_drgpp.graph,
                                 current_drgpp.sloth_iter,
                                 replica_id,
                                 data,
-                            )
-                            .expect("encoding failed in thread");
+                            )?;
+                            info!(SP_LOG, "encoded layer"; "layer" => format!("{}", layer), "elapsed" => format!("{:?}", start.elapsed()));
+                            fail_point!(failpoint::LAYER_REPLICATED);
+                            current_drgpp = Self::transform(&current_drgpp, layer, layers);
                         }
-                        Self::transform(&current_drgpp, layer, layers)
-                    });
+                    }
 
                     for thread in threads {
-                        thread.join().map_err(errf)?;
+                        thread.join().map_err(errf)??;
                     }
 
                     Ok(())
                 })
-                .map_err(errf)?;
+                .map_err(errf)??;
 
                 rx
             };
@@ -489,9 +718,8 @@ impl<'a, L: Layers> ProofScheme<'a> for L {
             pub_inputs,
             &priv_inputs.tau,
             &priv_inputs.aux,
+            None,
             &pub_params.layer_challenges,
-            pub_params.layer_challenges.layers(),
-            pub_params.layer_challenges.layers(),
             partition_count,
         )?;
 
@@ -517,14 +745,16 @@ impl<'a, L: Layers> ProofScheme<'a> for L {
         partition_proofs: &[Self::Proof],
     ) -> Result<bool> {
         for (k, proof) in partition_proofs.iter().enumerate() {
-            if proof.encoding_proofs.len() != pub_params.layer_challenges.layers() {
+            if proof.encoding_proofs.len() != pub_params.layer_challenges.layers()
+                || proof.tau.len() != pub_params.layer_challenges.layers()
+            {
                 return Ok(false);
             }
 
             let total_layers = pub_params.layer_challenges.layers();
             let mut pp = pub_params.drg_porep_public_params.clone();
-            // TODO: verification is broken for the first node, figure out how to unbreak
-            // with permutations
+            // Verification fails for nodes which are their own parents, which is why
+            // derive_challenges never challenges the first or last node.
 
             let mut comm_rs = Vec::new();
 
@@ -533,17 +763,14 @@ impl<'a, L: Layers> ProofScheme<'a> for L {
 
                 let new_pub_inputs = drgporep::PublicInputs {
                     replica_id: pub_inputs.replica_id,
-                    challenges: pub_inputs.challenges(
-                        &pub_params.layer_challenges,
-                        pub_params.drg_porep_public_params.graph.size(),
-                        layer as u8,
-                        Some(k),
-                    ),
+                    challenge_seed: pub_inputs.challenge_seed,
+                    challenge_count: pub_params.layer_challenges.challenges_for_layer(layer),
+                    k: Some(k),
                     tau: Some(proof.tau[layer]),
                 };
 
                 let ep = &proof_layer;
-                let res = DrgPoRep::verify(
+                let res = DrgPoRep::verify_at_layer(
                     &pp,
                     &new_pub_inputs,
                     &drgporep::Proof {
@@ -554,17 +781,20 @@ impl<'a, L: Layers> ProofScheme<'a> for L {
                         // TODO: investigate if clone can be avoided by using a reference in drgporep::DataProof
                         nodes: ep.nodes.clone(),
                     },
+                    layer,
                 )?;
 
-                pp = Self::transform(&pp, layer, total_layers);
-
                 if !res {
                     return Ok(false);
                 }
+
+                if layer + 1 < total_layers {
+                    pp = Self::transform(&pp, layer, total_layers);
+                }
             }
             let crs = comm_r_star::<L::Hasher>(&pub_inputs.replica_id, &comm_rs)?;
 
-            if crs != pub_inputs.comm_r_star {
+            if !crs.ct_eq(&pub_inputs.comm_r_star) {
                 return Ok(false);
             }
         }
@@ -574,6 +804,7 @@ impl<'a, L: Layers> ProofScheme<'a> for L {
     fn with_partition(pub_in: Self::PublicInputs, k: Option<usize>) -> Self::PublicInputs {
         self::PublicInputs {
             replica_id: pub_in.replica_id,
+            challenge_seed: pub_in.challenge_seed,
             tau: pub_in.tau,
             comm_r_star: pub_in.comm_r_star,
             k,
@@ -583,13 +814,19 @@ impl<'a, L: Layers> ProofScheme<'a> for L {
 
 // We need to calculate CommR* -- which is: H(replica_id|comm_r[0]|comm_r[1]|…comm_r[n])
 fn comm_r_star<H: Hasher>(replica_id: &H::Domain, comm_rs: &[H::Domain]) -> Result<H::Domain> {
-    let l = (comm_rs.len() + 1) * 32;
+    // Commitments are elements of the domain, and so the size of a node.
+    debug_assert!(
+        node_size_matches::<H>(),
+        "nodes are not elements of the domain"
+    );
+
+    let l = (comm_rs.len() + 1) * NODE_SIZE;
     let mut bytes = vec![0; l];
 
-    replica_id.write_bytes(&mut bytes[0..32])?;
+    replica_id.write_bytes(&mut bytes[0..NODE_SIZE])?;
 
     for (i, comm_r) in comm_rs.iter().enumerate() {
-        comm_r.write_bytes(&mut bytes[(i + 1) * 32..(i + 2) * 32])?;
+        comm_r.write_bytes(&mut bytes[(i + 1) * NODE_SIZE..(i + 2) * NODE_SIZE])?;
     }
 
     Ok(H::Function::hash(&bytes))
@@ -652,6 +889,150 @@ impl<'a, 'c, L: Layers> PoRep<'a, L::Hasher> for L {
 mod tests {
     use super::*;
 
+    use std::cell::RefCell;
+
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    use crate::crypto::feistel::FeistelConfig;
+    use crate::drgraph::new_seed;
+    use crate::failpoint::{FailAction, FailScenario};
+    use crate::hasher::PedersenHasher;
+    use crate::zigzag_graph::{ZigZag, ZigZagBucketGraph};
+
+    thread_local! {
+        // The (layer, layers) arguments of each call to RecordingZigZag's transforms.
+        static TRANSFORMS: RefCell<Vec<(usize, usize)>> = RefCell::new(Vec::new());
+        static INVERT_TRANSFORMS: RefCell<Vec<(usize, usize)>> = RefCell::new(Vec::new());
+    }
+
+    fn take_calls(
+        calls: &'static std::thread::LocalKey<RefCell<Vec<(usize, usize)>>>,
+    ) -> Vec<(usize, usize)> {
+        calls.with(|c| c.replace(Vec::new()))
+    }
+
+    // Zigzag layers which record what their transforms are told.
+    #[derive(Debug)]
+    struct RecordingZigZag;
+
+    impl Layers for RecordingZigZag {
+        type Hasher = PedersenHasher;
+        type Graph = ZigZagBucketGraph<PedersenHasher>;
+
+        fn transform(
+            pp: &drgporep::PublicParams<Self::Hasher, Self::Graph>,
+            layer: usize,
+            layers: usize,
+        ) -> drgporep::PublicParams<Self::Hasher, Self::Graph> {
+            TRANSFORMS.with(|c| c.borrow_mut().push((layer, layers)));
+            drgporep::PublicParams::new(pp.graph.zigzag(), pp.sloth_iter)
+        }
+
+        fn invert_transform(
+            pp: &drgporep::PublicParams<Self::Hasher, Self::Graph>,
+            layer: usize,
+            layers: usize,
+        ) -> drgporep::PublicParams<Self::Hasher, Self::Graph> {
+            INVERT_TRANSFORMS.with(|c| c.borrow_mut().push((layer, layers)));
+            drgporep::PublicParams::new(pp.graph.zigzag(), pp.sloth_iter)
+        }
+    }
+
+    #[test]
+    fn transforms_are_told_the_layer_of_their_params() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let layers = 3;
+
+        let replica_id: <PedersenHasher as Hasher>::Domain = rng.gen();
+        let data = vec![2u8; 32 * 8];
+        let mut replica = data.clone();
+
+        let mut pp = RecordingZigZag::setup(&SetupParams {
+            drg_porep_setup_params: drgporep::SetupParams {
+                drg: drgporep::DrgParams {
+                    nodes: data.len() / 32,
+                    degree: 3,
+                    expansion_degree: 2,
+                    seed: new_seed(),
+                    feistel: FeistelConfig::default(),
+                },
+                sloth_iter: 1,
+            },
+            layer_challenges: LayerChallenges::new_fixed(layers, 2),
+        })
+        .unwrap();
+
+        // Each layer's params are transformed into the next's, up to the replica's, which are
+        // those of layer 3.
+        let (tau, aux) = RecordingZigZag::replicate(&pp, &replica_id, &mut replica, None).unwrap();
+        assert_eq!(vec![(0, 3), (1, 3), (2, 3)], take_calls(&TRANSFORMS));
+
+        let pub_inputs = PublicInputs {
+            replica_id,
+            challenge_seed: challenge_seed(&replica_id, &tau.comm_r_star),
+            tau: Some(tau.simplify()),
+            comm_r_star: tau.comm_r_star,
+            k: None,
+        };
+        let priv_inputs = PrivateInputs {
+            aux,
+            tau: tau.layer_taus,
+        };
+
+        // Proving and verifying need only the params of the layers proven.
+        let proofs =
+            RecordingZigZag::prove_all_partitions(&pp, &pub_inputs, &priv_inputs, 1).unwrap();
+        assert_eq!(vec![(0, 3), (1, 3)], take_calls(&TRANSFORMS));
+
+        assert!(RecordingZigZag::verify_all_partitions(&pp, &pub_inputs, &proofs).unwrap());
+        assert_eq!(vec![(0, 3), (1, 3)], take_calls(&TRANSFORMS));
+
+        // Extraction inverts the replica's params back to those of layer 2, then 1, then 0.
+        for layer in 0..layers {
+            pp.drg_porep_public_params =
+                RecordingZigZag::transform(&pp.drg_porep_public_params, layer, layers);
+        }
+        take_calls(&TRANSFORMS);
+
+        let extracted = RecordingZigZag::extract_all(&pp, &replica_id, &replica).unwrap();
+        assert_eq!(data, extracted);
+        assert_eq!(vec![(3, 3), (2, 3), (1, 3)], take_calls(&INVERT_TRANSFORMS));
+        assert!(take_calls(&TRANSFORMS).is_empty());
+    }
+
+    #[test]
+    fn replication_failing_after_a_layer_goes_no_further() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let replica_id: <PedersenHasher as Hasher>::Domain = rng.gen();
+        let mut replica = vec![2u8; 32 * 8];
+
+        let pp = RecordingZigZag::setup(&SetupParams {
+            drg_porep_setup_params: drgporep::SetupParams {
+                drg: drgporep::DrgParams {
+                    nodes: replica.len() / 32,
+                    degree: 3,
+                    expansion_degree: 2,
+                    seed: new_seed(),
+                    feistel: FeistelConfig::default(),
+                },
+                sloth_iter: 1,
+            },
+            layer_challenges: LayerChallenges::new_fixed(3, 2),
+        })
+        .unwrap();
+
+        let scenario = FailScenario::setup();
+        scenario.arm_at(failpoint::LAYER_REPLICATED, 1, FailAction::Error);
+
+        match RecordingZigZag::replicate(&pp, &replica_id, &mut replica, None) {
+            Err(Error::Failpoint(name)) => assert_eq!(failpoint::LAYER_REPLICATED, name),
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+
+        // Only the first layer's params were transformed into the next's.
+        assert_eq!(vec![(0, 3)], take_calls(&TRANSFORMS));
+    }
+
     #[test]
     fn test_calculate_taper_challenges() {
         let layer_challenges = LayerChallenges::new_tapered(10, 333, 7, 1.0 / 3.0);
@@ -46,34 +46,45 @@ extern crate serde_derive;
 
 #[macro_use]
 pub mod test_helper;
+#[macro_use]
+pub mod failpoint;
 
 pub mod example_helper;
 
+#[cfg(feature = "analysis")]
+pub mod analysis;
 pub mod batchpost;
 pub mod beacon_post;
 pub mod challenge_derivation;
 pub mod circuit;
 pub mod compound_proof;
 pub mod crypto;
+#[cfg(feature = "debug-tools")]
+pub mod debug_tools;
 pub mod drgporep;
 pub mod drgraph;
 pub mod error;
 pub mod fr32;
 pub mod hasher;
 pub mod layered_drgporep;
+pub mod memory;
 pub mod merkle;
 pub mod merklepor;
+pub mod node_source;
 pub mod parameter_cache;
 pub mod partitions;
 pub mod piece_inclusion_proof;
 pub mod porc;
 pub mod porep;
+pub mod post_timing;
 pub mod proof;
+pub mod types;
 pub mod util;
 pub mod vdf;
 pub mod vdf_post;
 pub mod vdf_sloth;
 pub mod zigzag_drgporep;
+pub mod yield_hook;
 pub mod zigzag_graph;
 
 pub mod vde;
@@ -0,0 +1,340 @@
+//! Accounting of the memory taken by the few large allocations made while sealing: the sector's
+//! data, the copy of each layer its merkle tree is built from, the trees themselves and the
+//! circuit assignments proven. Everything else sealing allocates is small next to these.
+//!
+//! Each such allocation is reserved with a `MemoryMeter` just before it's made, and released when
+//! the `Reservation` is dropped. A meter with a limit refuses any reservation which would take
+//! what it has reserved past the limit, so that sealing fails before allocating rather than
+//! being killed by the operating system part way through.
+//!
+//! Code which can't be handed a meter (e.g. `PoRep::replicate`) reserves with the current
+//! thread's meter, set by `with_meter`; without one, its reservations count for nothing.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+use crate::error::{Error, Result};
+use crate::hasher::Hasher;
+
+/// What the memory reserved while sealing was allocated for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SealPhase {
+    /// The sector's data, read in full before it's replicated.
+    ReadData,
+    /// The copy of each layer from which its merkle tree is built, alongside the encoding of the
+    /// next layer.
+    CopyLayers,
+    /// The merkle tree of each layer, kept until the seal's vanilla proofs have been generated.
+    BuildTrees,
+    /// The assignment of each partition's circuit.
+    Prove,
+}
+
+impl SealPhase {
+    pub fn name(self) -> &'static str {
+        match self {
+            SealPhase::ReadData => "read_data",
+            SealPhase::CopyLayers => "copy_layers",
+            SealPhase::BuildTrees => "build_trees",
+            SealPhase::Prove => "prove",
+        }
+    }
+}
+
+impl fmt::Display for SealPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The high-water marks of what a meter reserved.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MemoryReport {
+    /// The most bytes reserved at once.
+    pub peak_bytes: u64,
+    /// The most bytes reserved at once for each phase, in the order in which the phases first
+    /// reserved any.
+    pub phases: Vec<(SealPhase, u64)>,
+}
+
+impl MemoryReport {
+    /// The most bytes reserved at once for `phase`, or 0 if it reserved none.
+    pub fn phase_peak_bytes(&self, phase: SealPhase) -> u64 {
+        self.phases
+            .iter()
+            .find(|(p, _)| *p == phase)
+            .map(|(_, bytes)| *bytes)
+            .unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Default)]
+struct MeterState {
+    limit: Option<u64>,
+    reserved: u64,
+    // Bytes reserved for each phase, in the same order as report.phases.
+    phase_reserved: Vec<u64>,
+    report: MemoryReport,
+}
+
+/// Keeps count of the memory reserved for a seal, which may be reserved from several threads.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryMeter {
+    state: Arc<Mutex<MeterState>>,
+}
+
+impl MemoryMeter {
+    /// Returns a meter which refuses reservations past `limit` bytes, if given.
+    pub fn new(limit: Option<u64>) -> MemoryMeter {
+        MemoryMeter {
+            state: Arc::new(Mutex::new(MeterState {
+                limit,
+                ..Default::default()
+            })),
+        }
+    }
+
+    /// Reserves `bytes` bytes for `phase`, or fails with `Error::MemoryLimitExceeded` if that
+    /// would take what's reserved past the meter's limit.
+    pub fn reserve(&self, phase: SealPhase, bytes: u64) -> Result<Reservation> {
+        let mut state = self.state.lock().unwrap_or_else(|p| p.into_inner());
+
+        let needed = state.reserved + bytes;
+        if let Some(limit) = state.limit {
+            if needed > limit {
+                return Err(Error::MemoryLimitExceeded {
+                    phase,
+                    needed,
+                    limit,
+                });
+            }
+        }
+
+        let i = match state.report.phases.iter().position(|(p, _)| *p == phase) {
+            Some(i) => i,
+            None => {
+                state.report.phases.push((phase, 0));
+                state.phase_reserved.push(0);
+                state.report.phases.len() - 1
+            }
+        };
+
+        state.reserved = needed;
+        state.phase_reserved[i] += bytes;
+
+        let phase_reserved = state.phase_reserved[i];
+        let report = &mut state.report;
+        report.peak_bytes = report.peak_bytes.max(needed);
+        report.phases[i].1 = report.phases[i].1.max(phase_reserved);
+
+        Ok(Reservation {
+            meter: Some(self.clone()),
+            phase,
+            bytes,
+        })
+    }
+
+    /// The high-water marks of what's been reserved so far.
+    pub fn report(&self) -> MemoryReport {
+        self.state
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .report
+            .clone()
+    }
+
+    /// Bytes currently reserved.
+    pub fn reserved_bytes(&self) -> u64 {
+        self.state.lock().unwrap_or_else(|p| p.into_inner()).reserved
+    }
+
+    /// Releases `bytes` bytes of what was reserved for `phase` and kept (see `Reservation::keep`),
+    /// once the allocations they were kept for have been freed.
+    pub fn release_kept(&self, phase: SealPhase, bytes: u64) {
+        self.release(phase, bytes);
+    }
+
+    fn release(&self, phase: SealPhase, bytes: u64) {
+        let mut state = self.state.lock().unwrap_or_else(|p| p.into_inner());
+
+        let i = state
+            .report
+            .phases
+            .iter()
+            .position(|(p, _)| *p == phase)
+            .expect("released a phase which never reserved");
+
+        state.reserved -= bytes;
+        state.phase_reserved[i] -= bytes;
+    }
+}
+
+/// Memory reserved with a `MemoryMeter`, released when dropped.
+#[derive(Debug)]
+#[must_use]
+pub struct Reservation {
+    // None for reservations made without a meter.
+    meter: Option<MemoryMeter>,
+    phase: SealPhase,
+    bytes: u64,
+}
+
+impl Reservation {
+    /// Keeps the memory reserved for as long as the meter lives, for allocations handed on to
+    /// code which can't hold the reservation.
+    pub fn keep(mut self) {
+        self.bytes = 0;
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if let Some(ref meter) = self.meter {
+            meter.release(self.phase, self.bytes);
+        }
+    }
+}
+
+thread_local! {
+    static CURRENT_METER: RefCell<Option<MemoryMeter>> = RefCell::new(None);
+}
+
+// Restores the meter which was current before with_meter, even when unwinding.
+struct RestoreMeter(Option<MemoryMeter>);
+
+impl Drop for RestoreMeter {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT_METER.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Calls `f` with `meter` as the current thread's meter, for `reserve` to reserve with.
+pub fn with_meter<T, F: FnOnce() -> T>(meter: &MemoryMeter, f: F) -> T {
+    let previous = CURRENT_METER.with(|current| current.replace(Some(meter.clone())));
+    let _restore = RestoreMeter(previous);
+
+    f()
+}
+
+/// Reserves `bytes` bytes for `phase` with the current thread's meter. Without one, the
+/// reservation counts for nothing, and is never refused.
+pub fn reserve(phase: SealPhase, bytes: u64) -> Result<Reservation> {
+    CURRENT_METER.with(|current| match *current.borrow() {
+        Some(ref meter) => meter.reserve(phase, bytes),
+        None => Ok(Reservation {
+            meter: None,
+            phase,
+            bytes,
+        }),
+    })
+}
+
+/// Size, in bytes, of a merkle tree over `leaves` leaves hashed with `H`.
+pub fn merkle_tree_bytes<H: Hasher>(leaves: usize) -> u64 {
+    ((2 * leaves).saturating_sub(1) * mem::size_of::<H::Domain>()) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hasher::PedersenHasher;
+
+    #[test]
+    fn reservations_are_counted_until_dropped() {
+        let meter = MemoryMeter::new(None);
+
+        let data = meter.reserve(SealPhase::ReadData, 100).unwrap();
+        let copy = meter.reserve(SealPhase::CopyLayers, 100).unwrap();
+        assert_eq!(200, meter.reserved_bytes());
+
+        drop(copy);
+        let copy = meter.reserve(SealPhase::CopyLayers, 50).unwrap();
+        meter.reserve(SealPhase::BuildTrees, 30).unwrap().keep();
+        assert_eq!(180, meter.reserved_bytes());
+
+        drop(copy);
+        drop(data);
+
+        // Kept reservations are never released.
+        assert_eq!(30, meter.reserved_bytes());
+
+        assert_eq!(
+            MemoryReport {
+                peak_bytes: 200,
+                phases: vec![
+                    (SealPhase::ReadData, 100),
+                    (SealPhase::CopyLayers, 100),
+                    (SealPhase::BuildTrees, 30),
+                ],
+            },
+            meter.report()
+        );
+        assert_eq!(0, meter.report().phase_peak_bytes(SealPhase::Prove));
+    }
+
+    #[test]
+    fn kept_reservations_are_released_once_freed() {
+        let meter = MemoryMeter::new(Some(100));
+
+        meter.reserve(SealPhase::BuildTrees, 80).unwrap().keep();
+        assert!(meter.reserve(SealPhase::Prove, 80).is_err());
+
+        meter.release_kept(SealPhase::BuildTrees, 80);
+        let _prove = meter.reserve(SealPhase::Prove, 80).unwrap();
+
+        // The peak is what was reserved at once, not what was reserved in all.
+        assert_eq!(80, meter.report().peak_bytes);
+        assert_eq!(80, meter.report().phase_peak_bytes(SealPhase::BuildTrees));
+    }
+
+    #[test]
+    fn reservations_past_the_limit_are_refused() {
+        let meter = MemoryMeter::new(Some(150));
+
+        let _data = meter.reserve(SealPhase::ReadData, 100).unwrap();
+
+        match meter.reserve(SealPhase::CopyLayers, 100) {
+            Err(Error::MemoryLimitExceeded {
+                phase: SealPhase::CopyLayers,
+                needed: 200,
+                limit: 150,
+            }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // The refused reservation isn't counted.
+        assert_eq!(100, meter.reserved_bytes());
+        assert_eq!(100, meter.report().peak_bytes);
+
+        let _copy = meter.reserve(SealPhase::CopyLayers, 50).unwrap();
+    }
+
+    #[test]
+    fn reserves_with_the_current_meter() {
+        let meter = MemoryMeter::new(Some(100));
+
+        with_meter(&meter, || {
+            reserve(SealPhase::ReadData, 60).unwrap().keep();
+            assert!(reserve(SealPhase::ReadData, 60).is_err());
+
+            // Other threads have no meter, so nothing they reserve is refused.
+            std::thread::spawn(|| reserve(SealPhase::ReadData, 1000).unwrap().keep())
+                .join()
+                .unwrap();
+        });
+
+        // Nor is anything reserved once the meter is no longer current.
+        reserve(SealPhase::ReadData, 1000).unwrap().keep();
+
+        assert_eq!(60, meter.reserved_bytes());
+    }
+
+    #[test]
+    fn sizes_merkle_trees() {
+        assert_eq!(7 * 32, merkle_tree_bytes::<PedersenHasher>(4));
+        assert_eq!(0, merkle_tree_bytes::<PedersenHasher>(0));
+    }
+}
@@ -1,14 +1,748 @@
 #![allow(clippy::len_without_is_empty)]
 
+use std::cmp;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::iter::FromIterator;
 use std::marker::PhantomData;
+use std::ops;
 
-// Reexport here, so we don't depend on merkle_light directly in other places.
-use merkle_light::hash::Algorithm;
-pub use merkle_light::merkle::MerkleTree;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use merkle_light::hash::{Algorithm, Hashable};
 use merkle_light::proof;
 use pairing::bls12_381::Fr;
+use rayon::prelude::*;
+use rayon::ThreadPoolBuilder;
 
+use crate::crypto::constant_time::constant_time_eq;
+use crate::error::{Error, Result};
 use crate::hasher::{Domain, Hasher};
+use crate::types::{commitment_from_slice, Commitment};
+use crate::util::NODE_SIZE;
+use crate::yield_hook::{self, BUILDING_TREES};
+
+/// The smallest subtree, in leaves, built by a single worker when a tree is
+/// built in parallel. Smaller subtrees cost more to hand out than they save.
+const MIN_CHUNK_LEAFS: usize = 1 << 10;
+
+/// How many threads build a merkle tree. The tree built is the same, node for
+/// node, whichever is used.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parallelism {
+    /// Build the tree on the calling thread, one node after another. Slower,
+    /// but simpler to follow when debugging.
+    Sequential,
+    /// Build the tree on this many threads.
+    Threads(usize),
+}
+
+impl Default for Parallelism {
+    /// One thread per thread of the current rayon pool: per available core,
+    /// unless called from within a smaller pool.
+    fn default() -> Self {
+        Parallelism::Threads(rayon::current_num_threads())
+    }
+}
+
+/// A merkle tree, laid out as merkle_light lays out its trees: the leaves,
+/// followed by each layer above them, ending with the root. A layer with an
+/// odd number of nodes (other than the root) is padded with a copy of its
+/// last node, which is hashed with itself.
+///
+/// Built in parallel, the leaves are split into subtrees of a power-of-two
+/// number of leaves, and each worker builds every layer of its subtrees. As
+/// the subtrees are aligned to a power of two, each of their nodes is hashed
+/// from the same pair of children (at the same height) as in a sequential
+/// build, so the result does not depend on the number of threads.
+pub struct MerkleTree<T, A> {
+    data: Vec<T>,
+    leafs: usize,
+    height: usize,
+    _a: PhantomData<A>,
+}
+
+impl<T, A> MerkleTree<T, A>
+where
+    T: Eq + Clone + AsRef<[u8]> + Send + Sync,
+    A: Algorithm<T>,
+{
+    /// Builds a tree over `leaves`, which must number at least two.
+    pub fn new<I: IntoIterator<Item = T>>(leaves: I) -> Self {
+        Self::with_parallelism(leaves.into_iter().collect(), Parallelism::default())
+    }
+
+    /// Builds a tree whose leaves are the hashes of `data`.
+    pub fn from_data<O: Hashable<A>, I: IntoIterator<Item = O>>(data: I) -> Self {
+        let mut a = A::default();
+        let leaves = data
+            .into_iter()
+            .map(|item| {
+                a.reset();
+                item.hash(&mut a);
+                let hash = a.hash();
+                a.leaf(hash)
+            })
+            .collect();
+
+        Self::from_leaves(leaves, Parallelism::default(), &|_| Ok(()))
+            .expect("building a tree without yielding failed")
+    }
+
+    /// Builds a tree over `leaves` with the given parallelism.
+    pub fn with_parallelism(leaves: Vec<T>, parallelism: Parallelism) -> Self {
+        Self::build(leaves, parallelism, &|_| Ok(()))
+            .expect("building a tree without yielding failed")
+    }
+
+    /// Builds a tree as with_parallelism does, counting each node hashed towards the next call
+    /// of the yield hook (see yield_hook), and failing if the hook asks it to stop.
+    pub fn yielding_with_parallelism(leaves: Vec<T>, parallelism: Parallelism) -> Result<Self> {
+        Self::build(leaves, parallelism, &|hashed| {
+            yield_hook::yield_point(BUILDING_TREES, hashed)
+        })
+    }
+
+    fn build(leaves: Vec<T>, parallelism: Parallelism, yield_point: &YieldPoint) -> Result<Self> {
+        let leaves = match parallelism {
+            Parallelism::Sequential => leaves.into_iter().map(leaf::<T, A>).collect(),
+            Parallelism::Threads(_) => leaves.into_par_iter().map(leaf::<T, A>).collect(),
+        };
+
+        Self::from_leaves(leaves, parallelism, yield_point)
+    }
+
+    // Builds a tree over leaves which have already been passed through
+    // Algorithm::leaf.
+    fn from_leaves(
+        leaves: Vec<T>,
+        parallelism: Parallelism,
+        yield_point: &YieldPoint,
+    ) -> Result<Self> {
+        assert!(leaves.len() > 1, "a merkle tree needs at least two leaves");
+
+        let layers = match parallelism {
+            Parallelism::Threads(threads) if threads > 1 => {
+                let chunk_leafs = chunk_leafs(leaves.len(), threads);
+                let build = || chunked_layers::<T, A>(&leaves, chunk_leafs, yield_point);
+
+                if threads == rayon::current_num_threads() {
+                    build()
+                } else {
+                    match ThreadPoolBuilder::new().num_threads(threads).build() {
+                        Ok(pool) => pool.install(build),
+                        Err(_) => build(),
+                    }
+                }
+            }
+            _ => hash_to_root::<T, A>(&leaves, 0, yield_point),
+        }?;
+
+        Ok(Self::from_layers(leaves, layers))
+    }
+
+    fn from_layers(leaves: Vec<T>, layers: Vec<Vec<T>>) -> Self {
+        let leafs = leaves.len();
+        let height = layers.len() + 1;
+
+        let mut data = leaves;
+        data.reserve(layers.iter().map(|layer| layer.len() + 1).sum());
+        pad(&mut data);
+
+        let top = layers.len() - 1;
+        for (i, layer) in layers.into_iter().enumerate() {
+            data.extend(layer);
+            if i < top {
+                pad(&mut data);
+            }
+        }
+
+        MerkleTree {
+            data,
+            leafs,
+            height,
+            _a: PhantomData,
+        }
+    }
+
+    /// Generates a proof that the `i`th leaf is in the tree.
+    pub fn gen_proof(&self, i: usize) -> proof::Proof<T> {
+        assert!(i < self.leafs, "leaf index out of bounds");
+
+        let mut lemma: Vec<T> = Vec::with_capacity(self.height + 1);
+        let mut path: Vec<bool> = Vec::with_capacity(self.height - 1);
+
+        lemma.push(self.data[i].clone());
+
+        let mut base = 0;
+        let mut j = i;
+        let mut width = self.leafs;
+
+        while base + 1 < self.data.len() {
+            width += width & 1;
+
+            lemma.push(if j & 1 == 0 {
+                self.data[base + j + 1].clone()
+            } else {
+                self.data[base + j - 1].clone()
+            });
+            path.push(j & 1 == 0);
+
+            base += width;
+            width >>= 1;
+            j >>= 1;
+        }
+
+        lemma.push(self.root());
+
+        proof::Proof::new(lemma, path)
+    }
+
+    /// Returns the root hash.
+    pub fn root(&self) -> T {
+        self.data[self.data.len() - 1].clone()
+    }
+
+    /// Returns the number of layers, including the leaves and the root.
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the number of leaves.
+    pub fn leafs(&self) -> usize {
+        self.leafs
+    }
+
+    /// Returns the number of nodes, including padding.
+    pub fn len(&self) -> usize {
+        self.data.len()
+    }
+
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+}
+
+impl<T, A> MerkleTree<T, A>
+where
+    T: Domain,
+    A: Algorithm<T>,
+{
+    /// Writes the tree's number of leaves, as a little-endian u64, followed by
+    /// each of its nodes (padding included), in little-endian bytes, so that
+    /// it may be read back without being built again.
+    pub fn write_nodes<W: Write>(&self, target: &mut W) -> Result<()> {
+        target.write_u64::<LittleEndian>(self.leafs as u64)?;
+
+        let mut node = [0; NODE_SIZE];
+        for n in &self.data {
+            n.write_bytes(&mut node)?;
+            target.write_all(&node)?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a tree written by write_nodes. Fails with MalformedInput if the
+    /// number of leaves is that of no tree, and with BadFrBytes or Io if the
+    /// nodes can't all be read.
+    pub fn read_nodes<R: Read>(source: &mut R) -> Result<Self> {
+        let leafs = source.read_u64::<LittleEndian>()?;
+        if leafs < 2 || leafs > usize::max_value() as u64 / 2 {
+            return Err(Error::MalformedInput);
+        }
+
+        let leafs = leafs as usize;
+        let (len, height) = tree_shape(leafs);
+
+        // The length is taken from the source, so the nodes are read rather
+        // than allocated up front.
+        let mut data = Vec::new();
+        let mut node = [0; NODE_SIZE];
+        for _ in 0..len {
+            source.read_exact(&mut node)?;
+            data.push(T::try_from_bytes(&node)?);
+        }
+
+        Ok(MerkleTree {
+            data,
+            leafs,
+            height,
+            _a: PhantomData,
+        })
+    }
+}
+
+impl<T, A> FromIterator<T> for MerkleTree<T, A>
+where
+    T: Eq + Clone + AsRef<[u8]> + Send + Sync,
+    A: Algorithm<T>,
+{
+    fn from_iter<I: IntoIterator<Item = T>>(leaves: I) -> Self {
+        Self::new(leaves)
+    }
+}
+
+impl<T, A> FromParallelIterator<T> for MerkleTree<T, A>
+where
+    T: Eq + Clone + AsRef<[u8]> + Send + Sync,
+    A: Algorithm<T>,
+{
+    fn from_par_iter<I: IntoParallelIterator<Item = T>>(leaves: I) -> Self {
+        Self::with_parallelism(leaves.into_par_iter().collect(), Parallelism::default())
+    }
+}
+
+impl<T, A> ops::Deref for MerkleTree<T, A> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        &self.data
+    }
+}
+
+impl<T: Clone, A> Clone for MerkleTree<T, A> {
+    fn clone(&self) -> Self {
+        MerkleTree {
+            data: self.data.clone(),
+            leafs: self.leafs,
+            height: self.height,
+            _a: PhantomData,
+        }
+    }
+}
+
+impl<T: fmt::Debug, A> fmt::Debug for MerkleTree<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("MerkleTree")
+            .field("data", &self.data)
+            .field("leafs", &self.leafs)
+            .field("height", &self.height)
+            .finish()
+    }
+}
+
+/// Computes the root of a merkle tree over leaves as they're pushed, holding
+/// only the root of each completed subtree. Once every leaf has been pushed,
+/// the leaves are padded to a power-of-two number (at least two) with copies
+/// of a padding leaf, as when a tree is built over zero-padded data.
+pub struct IncrementalRoot<T, A> {
+    // Roots of the completed subtrees and their heights, tallest first.
+    subtrees: Vec<(usize, T)>,
+    leafs: usize,
+    _a: PhantomData<A>,
+}
+
+impl<T, A> IncrementalRoot<T, A>
+where
+    T: Clone + AsRef<[u8]>,
+    A: Algorithm<T>,
+{
+    pub fn new() -> Self {
+        IncrementalRoot {
+            subtrees: Vec::new(),
+            leafs: 0,
+            _a: PhantomData,
+        }
+    }
+
+    pub fn push(&mut self, leaf: T) {
+        let node = A::default().leaf(leaf);
+        self.push_subtree(0, node);
+    }
+
+    /// Pushes the root of a complete subtree of `2^height` leaves, as though
+    /// each of its leaves were pushed in turn. The leaves pushed before it
+    /// must number a multiple of `2^height`, so that it's a subtree of the
+    /// tree over all of them.
+    pub fn push_subtree(&mut self, height: usize, root: T) {
+        assert_eq!(0, self.leafs % (1 << height), "misaligned subtree");
+
+        let mut a = A::default();
+        let mut node = root;
+        let mut node_height = height;
+
+        while self
+            .subtrees
+            .last()
+            .map_or(false, |(h, _)| *h == node_height)
+        {
+            let (_, left) = self.subtrees.pop().expect("subtree vanished");
+            a.reset();
+            node = a.node(left, node, node_height);
+            node_height += 1;
+        }
+
+        self.subtrees.push((node_height, node));
+        self.leafs += 1 << height;
+    }
+
+    /// Pushes `count` copies of `leaf`, hashing the subtrees of copies it can
+    /// push whole once per height rather than leaf by leaf.
+    pub fn push_copies(&mut self, leaf: T, mut count: usize) {
+        let mut a = A::default();
+        // The root of a subtree of 2^h copies, for each height h so far.
+        let mut roots = vec![a.leaf(leaf)];
+
+        while count > 0 {
+            let mut height = 0;
+            while self.leafs % (2 << height) == 0 && (2 << height) <= count {
+                height += 1;
+            }
+
+            while roots.len() <= height {
+                let below = roots.last().cloned().expect("no roots");
+                a.reset();
+                let root = a.node(below.clone(), below, roots.len() - 1);
+                roots.push(root);
+            }
+
+            self.push_subtree(height, roots[height].clone());
+            count -= 1 << height;
+        }
+    }
+
+    /// Returns the number of leaves pushed so far.
+    pub fn leafs(&self) -> usize {
+        self.leafs
+    }
+
+    /// Pads the leaves with `padding` and returns the root.
+    pub fn root(mut self, padding: T) -> T {
+        let leafs = cmp::max(2, self.leafs.next_power_of_two());
+
+        while self.leafs < leafs {
+            self.push(padding.clone());
+        }
+
+        debug_assert_eq!(1, self.subtrees.len());
+        self.subtrees.pop().expect("no subtrees").1
+    }
+
+    /// Returns the root of the tree `MerkleTree` builds over the leaves pushed,
+    /// which must number at least two: the lone last node of a layer is hashed
+    /// with itself rather than with padding.
+    pub fn unpadded_root(mut self) -> T {
+        assert!(self.leafs > 1, "a merkle tree needs at least two leaves");
+
+        let mut a = A::default();
+        let (mut height, mut node) = self.subtrees.pop().expect("no subtrees");
+
+        // The smallest subtree ends every layer up to the next smallest, alone.
+        while let Some((subtree_height, left)) = self.subtrees.pop() {
+            while height < subtree_height {
+                a.reset();
+                node = a.node(node.clone(), node, height);
+                height += 1;
+            }
+
+            a.reset();
+            node = a.node(left, node, height);
+            height += 1;
+        }
+
+        node
+    }
+}
+
+impl<T, A> Default for IncrementalRoot<T, A>
+where
+    T: Clone + AsRef<[u8]>,
+    A: Algorithm<T>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The size of the buffer `streaming_commitment` reads through.
+const STREAMING_BUFFER_BYTES: usize = 1 << 13;
+
+/// Computes the root of the tree `Graph::merkle_tree` builds over data zero-padded to a
+/// sector's size, from the data as it's streamed in. Whatever the size of the sector, it holds
+/// no more than one incomplete node of the data and one subtree root per level of the tree.
+pub struct StreamingCommitment<H: Hasher> {
+    root: IncrementalRoot<H::Domain, H::Function>,
+    // The bytes of the incomplete node at the end of the data so far.
+    node: [u8; NODE_SIZE],
+    node_bytes: usize,
+    data_bytes: usize,
+    // The number of nodes the data is padded to, or None if it's padded to a power of two.
+    nodes: Option<usize>,
+}
+
+impl<H: Hasher> StreamingCommitment<H> {
+    /// Commits to data of at most `sector_bytes`, which must be a whole number of at least two
+    /// nodes of `lambda` bytes. Only nodes of `NODE_SIZE` bytes are supported.
+    pub fn new(sector_bytes: usize, lambda: usize) -> Result<Self> {
+        check_node_size(lambda)?;
+
+        let nodes = sector_bytes / lambda;
+        if nodes < 2 || nodes * lambda != sector_bytes {
+            return Err(Error::InvalidMerkleTreeArgs(sector_bytes, lambda, nodes));
+        }
+
+        Ok(Self::with_nodes(Some(nodes)))
+    }
+
+    /// Commits to data of any length, zero-padded to a power-of-two number of nodes (at least
+    /// two), as pieces are.
+    pub fn padded_to_power_of_two(lambda: usize) -> Result<Self> {
+        check_node_size(lambda)?;
+
+        Ok(Self::with_nodes(None))
+    }
+
+    fn with_nodes(nodes: Option<usize>) -> Self {
+        StreamingCommitment {
+            root: IncrementalRoot::new(),
+            node: [0; NODE_SIZE],
+            node_bytes: 0,
+            data_bytes: 0,
+            nodes,
+        }
+    }
+
+    /// Appends bytes to the data. Fails if the data grows past the sector's size, or if a node
+    /// isn't an element of the hasher's domain.
+    pub fn update(&mut self, mut bytes: &[u8]) -> Result<()> {
+        self.data_bytes += bytes.len();
+
+        if let Some(nodes) = self.nodes {
+            if self.data_bytes > nodes * NODE_SIZE {
+                return Err(Error::InvalidMerkleTreeArgs(
+                    self.data_bytes,
+                    NODE_SIZE,
+                    nodes,
+                ));
+            }
+        }
+
+        while !bytes.is_empty() {
+            let n = cmp::min(NODE_SIZE - self.node_bytes, bytes.len());
+            self.node[self.node_bytes..self.node_bytes + n].copy_from_slice(&bytes[..n]);
+            self.node_bytes += n;
+            bytes = &bytes[n..];
+
+            if self.node_bytes == NODE_SIZE {
+                self.push_node()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Zero-pads the data and returns the root of the tree over it.
+    pub fn finish(mut self) -> Result<Commitment> {
+        if self.node_bytes > 0 {
+            self.node[self.node_bytes..].iter_mut().for_each(|b| *b = 0);
+            self.push_node()?;
+        }
+
+        let zero = H::Domain::try_from_bytes(&[0; NODE_SIZE])?;
+
+        let root = match self.nodes {
+            Some(nodes) => {
+                while self.root.leafs() < nodes {
+                    self.root.push(zero);
+                }
+
+                self.root.unpadded_root()
+            }
+            None => self.root.root(zero),
+        };
+
+        Ok(commitment_from_slice(&root.into_bytes()))
+    }
+
+    fn push_node(&mut self) -> Result<()> {
+        self.root.push(H::Domain::try_from_bytes(&self.node)?);
+        self.node_bytes = 0;
+
+        Ok(())
+    }
+}
+
+/// Computes the commitment to the data read from `reader`, zero-padded to `sector_bytes`, as
+/// `StreamingCommitment` does, reading it through a buffer of a fixed size. Fails if the reader
+/// holds more than `sector_bytes`.
+pub fn streaming_commitment<H: Hasher, R: Read>(
+    mut reader: R,
+    sector_bytes: usize,
+    lambda: usize,
+) -> Result<Commitment> {
+    let mut commitment = StreamingCommitment::<H>::new(sector_bytes, lambda)?;
+    let mut buf = [0; STREAMING_BUFFER_BYTES];
+
+    loop {
+        match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => commitment.update(&buf[..n])?,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err.into()),
+        }
+    }
+
+    commitment.finish()
+}
+
+// To avoid hashing the first node, the node size has to be the hash size.
+fn check_node_size(lambda: usize) -> Result<()> {
+    if lambda != NODE_SIZE {
+        return Err(Error::InvalidNodeSize(lambda));
+    }
+
+    Ok(())
+}
+
+// Counts the nodes hashed, failing once the tree is to stop being built.
+type YieldPoint<'a> = Fn(usize) -> Result<()> + Sync + 'a;
+
+fn leaf<T, A: Algorithm<T>>(node: T) -> T
+where
+    T: Clone + AsRef<[u8]>,
+{
+    A::default().leaf(node)
+}
+
+// The largest power of two which still gives every thread a few subtrees to
+// build, so that one slow subtree doesn't hold up the rest.
+fn chunk_leafs(leafs: usize, threads: usize) -> usize {
+    let target = leafs / (threads * 4);
+
+    cmp::max(MIN_CHUNK_LEAFS, (target + 1).next_power_of_two() / 2)
+}
+
+// The number of nodes (padding included) and the height of a tree over the
+// given number of leaves.
+fn tree_shape(leafs: usize) -> (usize, usize) {
+    let (mut len, mut height, mut width) = (1, 1, leafs);
+
+    while width > 1 {
+        len += width + (width & 1);
+        height += 1;
+        width = (width + 1) / 2;
+    }
+
+    (len, height)
+}
+
+// Pads a layer with an odd number of nodes with a copy of its last node.
+fn pad<T: Clone>(layer: &mut Vec<T>) {
+    if layer.len() & 1 == 1 {
+        let last = layer[layer.len() - 1].clone();
+        layer.push(last);
+    }
+}
+
+// Returns every layer above `leaves`, building each subtree of `chunk_leafs`
+// leaves as its own task.
+fn chunked_layers<T, A>(
+    leaves: &[T],
+    chunk_leafs: usize,
+    yield_point: &YieldPoint,
+) -> Result<Vec<Vec<T>>>
+where
+    T: Clone + AsRef<[u8]> + Send + Sync,
+    A: Algorithm<T>,
+{
+    assert!(chunk_leafs > 1 && chunk_leafs.is_power_of_two());
+
+    if leaves.len() <= chunk_leafs {
+        return hash_to_root::<T, A>(leaves, 0, yield_point);
+    }
+
+    let levels = chunk_leafs.trailing_zeros() as usize;
+
+    let subtrees: Vec<Vec<Vec<T>>> = leaves
+        .par_chunks(chunk_leafs)
+        .map(|chunk| hash_layers::<T, A>(chunk, 0, levels, yield_point))
+        .collect::<Result<_>>()?;
+
+    // Each layer up to the subtrees' roots is the concatenation of the same
+    // layer of every subtree.
+    let mut layers: Vec<Vec<T>> = (0..levels)
+        .map(|level| {
+            subtrees
+                .iter()
+                .flat_map(|subtree| subtree[level].iter().cloned())
+                .collect()
+        })
+        .collect();
+    drop(subtrees);
+
+    let above = hash_to_root::<T, A>(&layers[levels - 1], levels, yield_point)?;
+    layers.extend(above);
+
+    Ok(layers)
+}
+
+// Hashes the nodes of `layer`, at `height` in the tree, pairwise into the
+// layer above. A lone last node is hashed with itself.
+fn hash_layer<T, A>(
+    a: &mut A,
+    layer: &[T],
+    height: usize,
+    yield_point: &YieldPoint,
+) -> Result<Vec<T>>
+where
+    T: Clone + AsRef<[u8]>,
+    A: Algorithm<T>,
+{
+    layer
+        .chunks(2)
+        .map(|pair| {
+            yield_point(1)?;
+
+            let right = pair.get(1).unwrap_or(&pair[0]);
+            a.reset();
+            Ok(a.node(pair[0].clone(), right.clone(), height))
+        })
+        .collect()
+}
+
+// Returns the `levels` layers above `layer`, which is at `height` in the tree.
+fn hash_layers<T, A>(
+    layer: &[T],
+    height: usize,
+    levels: usize,
+    yield_point: &YieldPoint,
+) -> Result<Vec<Vec<T>>>
+where
+    T: Clone + AsRef<[u8]>,
+    A: Algorithm<T>,
+{
+    let mut a = A::default();
+    let mut layers: Vec<Vec<T>> = Vec::with_capacity(levels);
+
+    for level in 0..levels {
+        let below = layers.last().map_or(layer, |l| &l[..]);
+        let next = hash_layer(&mut a, below, height + level, yield_point)?;
+        layers.push(next);
+    }
+
+    Ok(layers)
+}
+
+// Returns every layer above `layer`, which is at `height` in the tree, up to
+// and including the root.
+fn hash_to_root<T, A>(layer: &[T], height: usize, yield_point: &YieldPoint) -> Result<Vec<Vec<T>>>
+where
+    T: Clone + AsRef<[u8]>,
+    A: Algorithm<T>,
+{
+    let mut a = A::default();
+    let mut layers: Vec<Vec<T>> = Vec::new();
+    let mut level = 0;
+
+    while layers.last().map_or(layer.len(), |l| l.len()) > 1 {
+        let below = layers.last().map_or(layer, |l| &l[..]);
+        let next = hash_layer(&mut a, below, height + level, yield_point)?;
+        layers.push(next);
+        level += 1;
+    }
+
+    Ok(layers)
+}
 
 /// Representation of a merkle proof.
 /// Each element in the `path` vector consists of a tuple `(hash, is_right)`, with `hash` being the the hash of the node at the current level and `is_right` a boolean indicating if the path is taking the right path.
@@ -83,24 +817,25 @@ impl<H: Hasher> MerkleProof<H> {
             return false;
         }
 
-        self.root()
-            == &(0..self.path.len()).fold(self.leaf, |h, i| {
-                a.reset();
-                let is_right = self.path[i].1;
+        let root = (0..self.path.len()).fold(self.leaf, |h, i| {
+            a.reset();
+            let is_right = self.path[i].1;
 
-                let (left, right) = if is_right {
-                    (self.path[i].0, h)
-                } else {
-                    (h, self.path[i].0)
-                };
+            let (left, right) = if is_right {
+                (self.path[i].0, h)
+            } else {
+                (h, self.path[i].0)
+            };
 
-                a.node(left, right, i)
-            })
+            a.node(left, right, i)
+        });
+
+        self.root().ct_eq(&root)
     }
 
     /// Validates that the data hashes to the leaf of the merkle path.
     pub fn validate_data(&self, data: &[u8]) -> bool {
-        self.leaf().into_bytes() == data
+        constant_time_eq(&self.leaf().into_bytes(), data)
     }
 
     /// Returns the hash of leaf that this MerkleProof represents.
@@ -134,6 +869,17 @@ impl<H: Hasher> MerkleProof<H> {
         out
     }
 
+    /// The length of the proof's serialization.
+    pub fn serialized_size(&self) -> usize {
+        Self::projected_size(self.path.len())
+    }
+
+    /// The length of the serialization of a proof whose path has the given length: each path
+    /// element and its direction, then the leaf and the root.
+    pub fn projected_size(path_len: usize) -> usize {
+        path_len * (NODE_SIZE + 1) + 2 * NODE_SIZE
+    }
+
     pub fn path(&self) -> &Vec<(H::Domain, bool)> {
         &self.path
     }
@@ -149,8 +895,9 @@ fn path_index<T: Domain>(path: &[(T, bool)]) -> usize {
 mod tests {
     use super::*;
 
-    use rand::{self, Rng};
+    use rand::{self, Rng, SeedableRng, XorShiftRng};
     use std::io::Write;
+    use std::sync::atomic::{AtomicUsize, Ordering};
 
     use crate::drgraph::new_seed;
     use crate::drgraph::{BucketGraph, Graph};
@@ -200,4 +947,399 @@ mod tests {
     fn merklepath_blake2s() {
         merklepath::<Blake2sHasher>();
     }
+
+    // Leaf counts around and between the chunk sizes below, including odd and
+    // non-power-of-two counts.
+    const LEAF_COUNTS: &[usize] = &[2, 3, 5, 7, 8, 31, 64, 65, 100, 1000, 1023, 1024, 1025, 3001];
+
+    fn random_leaves<H: Hasher>(n: usize) -> Vec<H::Domain> {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+        (0..n).map(|_| rng.gen()).collect()
+    }
+
+    fn assert_same_tree<H: Hasher>(
+        a: &MerkleTree<H::Domain, H::Function>,
+        b: &MerkleTree<H::Domain, H::Function>,
+    ) {
+        assert_eq!(a.as_slice(), b.as_slice());
+        assert_eq!(a.root(), b.root());
+        assert_eq!(a.height(), b.height());
+        assert_eq!(a.leafs(), b.leafs());
+
+        let last = a.leafs() - 1;
+        for i in &[0, 1, last / 2, last - 1, last] {
+            let (pa, pb) = (a.gen_proof(*i), b.gen_proof(*i));
+
+            assert_eq!(pa.lemma(), pb.lemma());
+            assert_eq!(pa.path(), pb.path());
+            assert!(pa.validate::<H::Function>());
+        }
+    }
+
+    fn chunked_build_matches_sequential<H: Hasher>() {
+        for n in LEAF_COUNTS {
+            let leaves = random_leaves::<H>(*n);
+            let sequential = MerkleTree::<H::Domain, H::Function>::with_parallelism(
+                leaves.clone(),
+                Parallelism::Sequential,
+            );
+
+            for chunk_leafs in &[2, 4, 16, 256] {
+                let layers =
+                    chunked_layers::<H::Domain, H::Function>(&leaves, *chunk_leafs, &|_| Ok(()))
+                        .unwrap();
+                let chunked = MerkleTree::from_layers(leaves.clone(), layers);
+
+                assert_same_tree::<H>(&sequential, &chunked);
+            }
+
+            for threads in &[1, 2, 3, 8] {
+                let parallel = MerkleTree::<H::Domain, H::Function>::with_parallelism(
+                    leaves.clone(),
+                    Parallelism::Threads(*threads),
+                );
+
+                assert_same_tree::<H>(&sequential, &parallel);
+            }
+        }
+    }
+
+    #[test]
+    fn chunked_build_matches_sequential_sha256() {
+        chunked_build_matches_sequential::<Sha256Hasher>();
+    }
+
+    #[test]
+    fn chunked_build_matches_sequential_blake2s() {
+        chunked_build_matches_sequential::<Blake2sHasher>();
+    }
+
+    #[test]
+    fn chunked_build_matches_sequential_pedersen() {
+        let leaves = random_leaves::<PedersenHasher>(37);
+        let sequential = MerkleTree::<_, <PedersenHasher as Hasher>::Function>::with_parallelism(
+            leaves.clone(),
+            Parallelism::Sequential,
+        );
+
+        for chunk_leafs in &[2, 4, 8] {
+            let layers = chunked_layers::<_, <PedersenHasher as Hasher>::Function>(
+                &leaves,
+                *chunk_leafs,
+                &|_| Ok(()),
+            )
+            .unwrap();
+            let chunked = MerkleTree::from_layers(leaves.clone(), layers);
+
+            assert_same_tree::<PedersenHasher>(&sequential, &chunked);
+        }
+    }
+
+    #[test]
+    fn yield_points_count_every_node_hashed() {
+        type Domain = <Sha256Hasher as Hasher>::Domain;
+        type Function = <Sha256Hasher as Hasher>::Function;
+
+        let leaves = random_leaves::<Sha256Hasher>(5000);
+        let expected = MerkleTree::<Domain, Function>::new(leaves.clone());
+
+        // Each node above the leaves is hashed once, but the copies padding odd layers aren't.
+        let (mut hashed, mut width) = (0, leaves.len());
+        while width > 1 {
+            width = (width + 1) / 2;
+            hashed += width;
+        }
+
+        for parallelism in &[Parallelism::Sequential, Parallelism::Threads(2)] {
+            let counted = AtomicUsize::new(0);
+            let tree = MerkleTree::<Domain, Function>::build(leaves.clone(), *parallelism, &|n| {
+                counted.fetch_add(n, Ordering::SeqCst);
+                Ok(())
+            })
+            .unwrap();
+
+            assert_eq!(hashed, counted.load(Ordering::SeqCst));
+            assert_eq!(expected.root(), tree.root());
+        }
+
+        let counted = AtomicUsize::new(0);
+        let refuse_after_100 = |n| {
+            if counted.fetch_add(n, Ordering::SeqCst) + n > 100 {
+                return Err(Error::YieldCancelled {
+                    phase: BUILDING_TREES.to_string(),
+                });
+            }
+            Ok(())
+        };
+        let stopped = MerkleTree::<Domain, Function>::build(
+            leaves,
+            Parallelism::Sequential,
+            &refuse_after_100,
+        );
+
+        assert!(stopped.is_err());
+        assert_eq!(101, counted.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn matches_merkle_light() {
+        type Domain = <Sha256Hasher as Hasher>::Domain;
+        type Function = <Sha256Hasher as Hasher>::Function;
+
+        for n in LEAF_COUNTS {
+            let leaves = random_leaves::<Sha256Hasher>(*n);
+            let ours = MerkleTree::<Domain, Function>::new(leaves.clone());
+            let theirs = merkle_light::merkle::MerkleTree::<Domain, Function>::new(leaves);
+
+            assert_eq!(&ours[..], &theirs[..]);
+            assert_eq!(ours.root(), theirs.root());
+            assert_eq!(ours.height(), theirs.height());
+
+            for i in 0..*n {
+                let (ours, theirs) = (ours.gen_proof(i), theirs.gen_proof(i));
+
+                assert_eq!(ours.lemma(), theirs.lemma());
+                assert_eq!(ours.path(), theirs.path());
+            }
+        }
+    }
+
+    #[test]
+    fn written_trees_read_back_node_for_node() {
+        type Domain = <PedersenHasher as Hasher>::Domain;
+        type Function = <PedersenHasher as Hasher>::Function;
+
+        for n in &[2, 3, 5, 8, 100] {
+            let tree = MerkleTree::<Domain, Function>::new(random_leaves::<PedersenHasher>(*n));
+            assert_eq!((tree.len(), tree.height()), tree_shape(*n));
+
+            let mut bytes = Vec::new();
+            tree.write_nodes(&mut bytes).unwrap();
+            assert_eq!(8 + tree.len() * NODE_SIZE, bytes.len());
+
+            let read = MerkleTree::<Domain, Function>::read_nodes(&mut &bytes[..]).unwrap();
+            assert_same_tree::<PedersenHasher>(&tree, &read);
+
+            // A tree cut short isn't read.
+            let short = &bytes[..bytes.len() - 1];
+            assert!(MerkleTree::<Domain, Function>::read_nodes(&mut &short[..]).is_err());
+        }
+
+        let mut one_leaf = Vec::new();
+        one_leaf.write_u64::<LittleEndian>(1).unwrap();
+        match MerkleTree::<Domain, Function>::read_nodes(&mut &one_leaf[..]) {
+            Err(Error::MalformedInput) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn incremental_root_matches_padded_tree() {
+        type Domain = <Sha256Hasher as Hasher>::Domain;
+        type Function = <Sha256Hasher as Hasher>::Function;
+
+        for n in &[0, 1, 2, 3, 5, 8, 13, 64, 100] {
+            let leaves = random_leaves::<Sha256Hasher>(*n);
+
+            let mut incremental = IncrementalRoot::<Domain, Function>::new();
+            for leaf in &leaves {
+                incremental.push(*leaf);
+            }
+            assert_eq!(*n, incremental.leafs());
+
+            let mut padded = leaves.clone();
+            padded.resize(cmp::max(2, n.next_power_of_two()), Domain::default());
+            let tree = MerkleTree::<Domain, Function>::new(padded);
+
+            assert_eq!(tree.root(), incremental.root(Domain::default()));
+        }
+    }
+
+    #[test]
+    fn subtrees_push_as_their_leaves_do() {
+        type Domain = <Sha256Hasher as Hasher>::Domain;
+        type Function = <Sha256Hasher as Hasher>::Function;
+
+        // Leaves, then subtrees of 4 and 2 leaves, leaves again, then
+        // subtrees of 4 and 8 leaves.
+        let leaves = random_leaves::<Sha256Hasher>(24);
+        let subtree_root = |range: std::ops::Range<usize>| {
+            MerkleTree::<Domain, Function>::new(leaves[range].to_vec()).root()
+        };
+
+        let mut incremental = IncrementalRoot::<Domain, Function>::new();
+        for leaf in &leaves[..4] {
+            incremental.push(*leaf);
+        }
+        incremental.push_subtree(2, subtree_root(4..8));
+        incremental.push_subtree(1, subtree_root(8..10));
+        incremental.push(leaves[10]);
+        incremental.push(leaves[11]);
+        incremental.push_subtree(2, subtree_root(12..16));
+        incremental.push_subtree(3, subtree_root(16..24));
+        assert_eq!(24, incremental.leafs());
+
+        let tree = MerkleTree::<Domain, Function>::new(leaves);
+        assert_eq!(tree.root(), incremental.unpadded_root());
+    }
+
+    #[test]
+    fn copies_push_as_their_leaves_do() {
+        type Domain = <Sha256Hasher as Hasher>::Domain;
+        type Function = <Sha256Hasher as Hasher>::Function;
+
+        let leaves = random_leaves::<Sha256Hasher>(3);
+
+        for (before, copies) in &[(0, 2), (0, 64), (1, 2), (3, 13), (2, 100), (3, 1000)] {
+            let mut incremental = IncrementalRoot::<Domain, Function>::new();
+            let mut one_by_one = IncrementalRoot::<Domain, Function>::new();
+            for leaf in &leaves[..*before] {
+                incremental.push(*leaf);
+                one_by_one.push(*leaf);
+            }
+
+            incremental.push_copies(leaves[2], *copies);
+            for _ in 0..*copies {
+                one_by_one.push(leaves[2]);
+            }
+
+            assert_eq!(one_by_one.leafs(), incremental.leafs());
+            assert_eq!(one_by_one.unpadded_root(), incremental.unpadded_root());
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "misaligned subtree")]
+    fn misaligned_subtrees_are_refused() {
+        type Domain = <Sha256Hasher as Hasher>::Domain;
+        type Function = <Sha256Hasher as Hasher>::Function;
+
+        let mut incremental = IncrementalRoot::<Domain, Function>::new();
+        incremental.push(Domain::default());
+        incremental.push_subtree(1, Domain::default());
+    }
+
+    #[test]
+    fn unpadded_root_matches_tree() {
+        type Domain = <Sha256Hasher as Hasher>::Domain;
+        type Function = <Sha256Hasher as Hasher>::Function;
+
+        for n in LEAF_COUNTS {
+            let leaves = random_leaves::<Sha256Hasher>(*n);
+
+            let mut incremental = IncrementalRoot::<Domain, Function>::new();
+            for leaf in &leaves {
+                incremental.push(*leaf);
+            }
+
+            let tree = MerkleTree::<Domain, Function>::new(leaves);
+
+            assert_eq!(tree.root(), incremental.unpadded_root());
+        }
+    }
+
+    // The root of the tree over data zero-padded to the given number of nodes.
+    fn padded_data_root<H: Hasher>(data: &[u8], nodes: usize) -> Commitment {
+        let mut padded = data.to_vec();
+        padded.resize(nodes * NODE_SIZE, 0);
+
+        let leaves = padded
+            .chunks(NODE_SIZE)
+            .map(|node| H::Domain::try_from_bytes(node).unwrap());
+        let root = MerkleTree::<H::Domain, H::Function>::new(leaves).root();
+
+        commitment_from_slice(&root.into_bytes())
+    }
+
+    #[test]
+    fn streaming_commitment_matches_tree() {
+        for n in LEAF_COUNTS {
+            let data: Vec<u8> = random_leaves::<Sha256Hasher>(*n)
+                .iter()
+                .flat_map(|leaf| leaf.into_bytes())
+                .collect();
+
+            // Data filling the sector, ending within a node, and none at all.
+            for len in &[data.len(), data.len() / 2 + 7, 0] {
+                let data = &data[..*len];
+                let expected = padded_data_root::<Sha256Hasher>(data, *n);
+
+                let streamed =
+                    streaming_commitment::<Sha256Hasher, _>(data, n * NODE_SIZE, NODE_SIZE);
+                assert_eq!(expected, streamed.unwrap());
+
+                // Given in uneven pieces.
+                let mut commitment =
+                    StreamingCommitment::<Sha256Hasher>::new(n * NODE_SIZE, NODE_SIZE).unwrap();
+                for chunk in data.chunks(45) {
+                    commitment.update(chunk).unwrap();
+                }
+                assert_eq!(expected, commitment.finish().unwrap());
+
+                let nodes = cmp::max(2, ((len + NODE_SIZE - 1) / NODE_SIZE).next_power_of_two());
+                let mut commitment =
+                    StreamingCommitment::<Sha256Hasher>::padded_to_power_of_two(NODE_SIZE).unwrap();
+                commitment.update(data).unwrap();
+                assert_eq!(
+                    padded_data_root::<Sha256Hasher>(data, nodes),
+                    commitment.finish().unwrap()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn streaming_commitment_matches_graph_tree() {
+        for n in &[2, 3, 10, 16] {
+            let g = BucketGraph::<PedersenHasher>::new(*n, 5, 0, new_seed());
+            let data: Vec<u8> = random_leaves::<PedersenHasher>(*n)
+                .iter()
+                .flat_map(|leaf| leaf.into_bytes())
+                .collect();
+
+            let expected = g.merkle_tree(&data).unwrap().root().into_bytes();
+            let streamed =
+                streaming_commitment::<PedersenHasher, _>(&data[..], n * NODE_SIZE, NODE_SIZE);
+
+            assert_eq!(&expected[..], &streamed.unwrap()[..]);
+        }
+    }
+
+    #[test]
+    fn streaming_commitment_checks_its_arguments() {
+        let commit = |data: &[u8], sector_bytes, lambda| {
+            streaming_commitment::<Sha256Hasher, _>(data, sector_bytes, lambda)
+        };
+
+        match commit(&[], 128, 64) {
+            Err(Error::InvalidNodeSize(64)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        for sector_bytes in &[0, 32, 100] {
+            match commit(&[], *sector_bytes, NODE_SIZE) {
+                Err(Error::InvalidMerkleTreeArgs(..)) => (),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+
+        // Data longer than the sector isn't cut short.
+        match commit(&[0; 129], 128, NODE_SIZE) {
+            Err(Error::InvalidMerkleTreeArgs(129, NODE_SIZE, 4)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn chunks_are_aligned_to_a_power_of_two() {
+        for (leafs, threads) in &[(1 << 20, 8), (1 << 20, 3), (1000, 4), (3001, 16)] {
+            let chunk = chunk_leafs(*leafs, *threads);
+
+            assert!(chunk.is_power_of_two());
+            assert!(chunk >= MIN_CHUNK_LEAFS);
+        }
+
+        assert_eq!(1 << 15, chunk_leafs(1 << 20, 8));
+    }
 }
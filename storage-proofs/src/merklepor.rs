@@ -92,7 +92,7 @@ impl<'a, H: 'a + Hasher> ProofScheme<'a> for MerklePoR<H> {
         let tree = priv_inputs.tree;
 
         if let Some(ref commitment) = pub_inputs.commitment {
-            if commitment != &tree.root() {
+            if !commitment.ct_eq(&tree.root()) {
                 return Err(Error::InvalidCommitment);
             }
         }
@@ -111,7 +111,7 @@ impl<'a, H: 'a + Hasher> ProofScheme<'a> for MerklePoR<H> {
         {
             // This was verify_proof_meta.
             let commitments_match = match pub_inputs.commitment {
-                Some(ref commitment) => commitment == proof.proof.root(),
+                Some(ref commitment) => commitment.ct_eq(proof.proof.root()),
                 None => true,
             };
 
@@ -0,0 +1,174 @@
+//! Sources of the nodes of a replica (or of the data it was replicated from), which proving reads
+//! node by node: only the challenged nodes and their parents are ever needed, so a replica needn't
+//! be held in memory to be proven.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Mutex;
+
+use merkle_light::hash::Algorithm;
+
+use crate::error::Result;
+use crate::hasher::Domain;
+use crate::merkle::MerkleTree;
+use crate::util::NODE_SIZE;
+
+/// Reads the nodes of a replica by index. Sources are shared between the threads proving each
+/// partition, so must be `Sync`.
+pub trait NodeSource: Sync {
+    /// The bytes of node `index`.
+    fn read_node(&self, index: usize) -> Result<[u8; NODE_SIZE]>;
+}
+
+impl<'a> fmt::Debug for NodeSource + 'a {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("NodeSource")
+    }
+}
+
+/// The nodes of a replica held in memory.
+impl<T: Domain> NodeSource for [T] {
+    fn read_node(&self, index: usize) -> Result<[u8; NODE_SIZE]> {
+        let mut node = [0; NODE_SIZE];
+        self[index].write_bytes(&mut node)?;
+
+        Ok(node)
+    }
+}
+
+/// The nodes of a replica held in memory as the leaves of the tree committing to it.
+impl<T: Domain, A: Algorithm<T> + Sync> NodeSource for MerkleTree<T, A> {
+    fn read_node(&self, index: usize) -> Result<[u8; NODE_SIZE]> {
+        self.as_slice()[..self.leafs()].read_node(index)
+    }
+}
+
+/// The nodes of a replica read from a file (or anything else which can seek), keeping the most
+/// recently read in memory. A node's parents mostly lie close to it, and challenged nodes often
+/// share parents, so a small cache saves many reads.
+#[derive(Debug)]
+pub struct FileNodeSource<R: Read + Seek + Send> {
+    state: Mutex<FileNodes<R>>,
+}
+
+#[derive(Debug)]
+struct FileNodes<R> {
+    reader: R,
+    // Where in the reader node 0 starts.
+    offset: u64,
+    capacity: usize,
+    cached: HashMap<usize, [u8; NODE_SIZE]>,
+    // The indexes of the cached nodes, least recently read first.
+    recency: VecDeque<usize>,
+    reads: u64,
+}
+
+impl<R: Read + Seek + Send> FileNodeSource<R> {
+    /// Returns a source of the nodes starting at `offset` bytes into `reader`, which keeps up to
+    /// `capacity` of them in memory.
+    pub fn new(reader: R, offset: u64, capacity: usize) -> FileNodeSource<R> {
+        FileNodeSource {
+            state: Mutex::new(FileNodes {
+                reader,
+                offset,
+                capacity,
+                cached: HashMap::with_capacity(capacity),
+                recency: VecDeque::with_capacity(capacity),
+                reads: 0,
+            }),
+        }
+    }
+
+    /// The number of nodes read from the reader so far, i.e. those which weren't cached.
+    pub fn reads(&self) -> u64 {
+        self.state.lock().unwrap_or_else(|p| p.into_inner()).reads
+    }
+}
+
+impl<R: Read + Seek + Send> NodeSource for FileNodeSource<R> {
+    fn read_node(&self, index: usize) -> Result<[u8; NODE_SIZE]> {
+        let mut state = self.state.lock().unwrap_or_else(|p| p.into_inner());
+
+        if let Some(node) = state.cached.get(&index).cloned() {
+            if let Some(i) = state.recency.iter().position(|cached| *cached == index) {
+                state.recency.remove(i);
+            }
+            state.recency.push_back(index);
+
+            return Ok(node);
+        }
+
+        let mut node = [0; NODE_SIZE];
+        let position = state.offset + (index * NODE_SIZE) as u64;
+        state.reader.seek(SeekFrom::Start(position))?;
+        state.reader.read_exact(&mut node)?;
+        state.reads += 1;
+
+        if state.capacity > 0 {
+            if state.cached.len() == state.capacity {
+                if let Some(evicted) = state.recency.pop_front() {
+                    state.cached.remove(&evicted);
+                }
+            }
+            state.cached.insert(index, node);
+            state.recency.push_back(index);
+        }
+
+        Ok(node)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    use crate::hasher::pedersen::PedersenDomain;
+
+    fn nodes(count: usize) -> Vec<u8> {
+        (0..count).flat_map(|i| vec![i as u8; NODE_SIZE]).collect()
+    }
+
+    #[test]
+    fn file_sources_read_the_nodes_after_their_offset() {
+        let mut bytes = vec![9; 7];
+        bytes.extend(nodes(4));
+        let source = FileNodeSource::new(Cursor::new(bytes), 7, 0);
+
+        assert_eq!([2; NODE_SIZE], source.read_node(2).unwrap());
+        assert_eq!([0; NODE_SIZE], source.read_node(0).unwrap());
+        assert_eq!([3; NODE_SIZE], source.read_node(3).unwrap());
+        assert!(source.read_node(4).is_err());
+    }
+
+    #[test]
+    fn file_sources_reread_only_the_least_recently_read_nodes() {
+        let source = FileNodeSource::new(Cursor::new(nodes(8)), 0, 2);
+
+        source.read_node(1).unwrap();
+        source.read_node(2).unwrap();
+        source.read_node(1).unwrap();
+        assert_eq!(2, source.reads());
+
+        // Evicts 2, which was read less recently than 1.
+        source.read_node(3).unwrap();
+        source.read_node(1).unwrap();
+        assert_eq!(3, source.reads());
+
+        assert_eq!([2; NODE_SIZE], source.read_node(2).unwrap());
+        assert_eq!(4, source.reads());
+    }
+
+    #[test]
+    fn memory_sources_read_the_bytes_of_each_node() {
+        let bytes = nodes(3);
+        let domains: Vec<PedersenDomain> = bytes
+            .chunks(NODE_SIZE)
+            .map(|node| PedersenDomain::try_from_bytes(node).unwrap())
+            .collect();
+        let source = &domains[..];
+
+        assert_eq!([1; NODE_SIZE], source.read_node(1).unwrap());
+    }
+}
@@ -1,6 +1,8 @@
 use crate::error::*;
+use crate::failpoint;
 use bellman::groth16::Parameters;
 use bellman::{groth16, Circuit};
+use blake2::Blake2b;
 use fs2::FileExt;
 use itertools::Itertools;
 use rand::{SeedableRng, XorShiftRng};
@@ -8,10 +10,12 @@ use sapling_crypto::jubjub::JubjubEngine;
 use sha2::{Digest, Sha256};
 
 use std::env;
-use std::fs::{self, create_dir_all};
-use std::io::{Seek, SeekFrom};
+use std::fs::{self, create_dir_all, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::time::Instant;
+use tempfile::{Builder, NamedTempFile};
 
 use crate::SP_LOG;
 
@@ -23,6 +27,50 @@ pub const PARAMETER_CACHE_DIR: &str = "/tmp/filecoin-proof-parameters/";
 /// If this changes, parameters generated under different conditions may vary. Don't change it.
 pub const PARAMETER_RNG_SEED: [u32; 4] = [0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654];
 
+/// The directory, within a parameter directory, holding the digest of each
+/// parameter file installed in it, in a file named as the parameter file is.
+const MANIFEST_DIR: &str = "manifest";
+
+/// The most bytes of parameters written to the cache between two checkpoints
+/// of a cancellable generation.
+pub const WRITE_CHUNK_BYTES: u64 = 1 << 20;
+
+/// Share of overall progress taken by generating parameters; writing them to the
+/// cache takes the rest.
+const GENERATION_SHARE: f64 = 0.9;
+
+// Counts the groth parameters generated by this process, so that callers which
+// expect parameters to be cached can test that none were generated.
+static PARAMETERS_GENERATED: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of times this process has generated groth parameters.
+pub fn parameters_generated() -> usize {
+    PARAMETERS_GENERATED.load(Ordering::SeqCst)
+}
+
+/// A phase of groth parameter generation, as reported to a progress callback.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterPhase {
+    /// Synthesizing the circuit and applying the powers of tau. bellman does
+    /// both in a single call, so this is only reported as it starts and ends.
+    Generating,
+    /// Writing generated parameters to the cache.
+    Writing,
+    /// The parameters are ready, whether generated or read from the cache.
+    /// Reported exactly once, as the last call.
+    Complete,
+}
+
+impl ParameterPhase {
+    pub fn name(self) -> &'static str {
+        match self {
+            ParameterPhase::Generating => "generating",
+            ParameterPhase::Writing => "writing",
+            ParameterPhase::Complete => "complete",
+        }
+    }
+}
+
 pub fn parameter_cache_dir_name() -> String {
     match env::var("FILECOIN_PARAMETER_CACHE") {
         Ok(dir) => dir,
@@ -63,51 +111,254 @@ where
     }
 
     fn get_groth_params(circuit: C, pub_params: &PP) -> Result<groth16::Parameters<E>> {
-        // Always seed the rng identically so parameter generation will be deterministic.
-
-        let generate = || {
-            let rng = &mut XorShiftRng::from_seed(PARAMETER_RNG_SEED);
-            info!(SP_LOG, "Actually generating groth params."; "target" => "params");
-            let start = Instant::now();
-            let parameters = groth16::generate_random_parameters::<E, _, _>(circuit, rng);
-            let generation_time = start.elapsed();
-            info!(SP_LOG, "groth_parameter_generation_time: {:?}", generation_time; "target" => "stats");
-            parameters
-        };
+        let start = Instant::now();
+        let mut last_phase = None;
 
-        match Self::cache_identifier(pub_params) {
+        // Log each phase as it begins, so that a long generation is visibly
+        // making progress.
+        Self::get_groth_params_with_progress(circuit, pub_params, &mut |phase, _| {
+            if last_phase != Some(phase) {
+                last_phase = Some(phase);
+                info!(SP_LOG, "groth parameters: {} after {:?}", phase.name(), start.elapsed(); "target" => "params");
+            }
+        })
+    }
+
+    /// Reads the groth parameters from the cache, generating and caching them
+    /// first if need be. `progress` is called with each phase and with the
+    /// overall fraction of the work done, which never decreases.
+    fn get_groth_params_with_progress(
+        circuit: C,
+        pub_params: &PP,
+        progress: &mut FnMut(ParameterPhase, f64),
+    ) -> Result<groth16::Parameters<E>> {
+        Self::get_groth_params_cancellable(circuit, pub_params, progress, &|_| Ok(()))
+    }
+
+    /// Like get_groth_params_with_progress, but calls `checkpoint` with the
+    /// phase generation is in before generating, once generated and every
+    /// WRITE_CHUNK_BYTES of writing the parameters to the cache. An error it
+    /// returns (e.g. a ParameterGenerationCancelled) stops generation there,
+    /// and is returned, leaving the cache as it was.
+    fn get_groth_params_cancellable(
+        circuit: C,
+        pub_params: &PP,
+        progress: &mut FnMut(ParameterPhase, f64),
+        checkpoint: &Fn(ParameterPhase) -> Result<()>,
+    ) -> Result<groth16::Parameters<E>> {
+        let params = match Self::cache_identifier(pub_params) {
             Some(id) => {
                 let cache_dir = parameter_cache_dir();
                 create_dir_all(cache_dir)?;
                 let cache_path = parameter_cache_path(&id);
                 info!(SP_LOG, "checking cache_path: {:?}", cache_path; "target" => "params");
 
-                read_cached_params(&cache_path).or_else(|_| {
-                    ensure_parent(&cache_path)?;
+                match read_cached_params(&cache_path) {
+                    Ok(p) => {
+                        info!(SP_LOG, "groth parameter cache hit"; "target" => "params");
+                        p
+                    }
+                    // Installed parameters are refused rather than replaced
+                    // with generated ones.
+                    Err(err @ Error::InstalledParametersCorrupted { .. }) => return Err(err),
+                    Err(_) => {
+                        info!(SP_LOG, "groth parameter cache miss"; "target" => "params");
+                        ensure_parent(&cache_path)?;
+
+                        let _lock = GenerationLock::acquire(&cache_path)?;
+
+                        // Another caller may have generated the parameters
+                        // while this one waited for the lock.
+                        if let Ok(p) = read_cached_params(&cache_path) {
+                            info!(SP_LOG, "groth parameter cache hit"; "target" => "params");
+                            progress(ParameterPhase::Complete, 1.0);
+                            return Ok(p);
+                        }
+
+                        checkpoint(ParameterPhase::Generating)?;
+                        let p = generate(circuit, progress)?;
 
-                    let mut f = fs::OpenOptions::new()
-                        .read(true)
-                        .write(true)
-                        .create(true)
-                        .open(&cache_path)?;
-                    f.lock_exclusive()?;
+                        // Serialize once to learn the size, so that writing can
+                        // report how far along it is.
+                        let mut size = ByteCounter(0);
+                        p.write(&mut size)?;
 
-                    let p = generate()?;
+                        // Written beside the cache file and renamed over it, so
+                        // that it's never found half written. Should writing
+                        // stop, the partial file is removed as it's dropped.
+                        let mut partial = Builder::new()
+                            .prefix(&hidden_name(&cache_path, ""))
+                            .tempfile_in(cache_path.parent().unwrap_or(Path::new(".")))?;
 
-                    p.write(&mut f)?;
+                        let mut writer = ProgressWriter {
+                            inner: partial.as_file_mut(),
+                            written: 0,
+                            total: size.0,
+                            reported: GENERATION_SHARE,
+                            progress: &mut *progress,
+                            checkpoint,
+                            next_checkpoint: 0,
+                            stopped: None,
+                        };
+                        let written = p.write(&mut writer);
+                        if let Some(err) = writer.stopped.take() {
+                            return Err(err);
+                        }
+                        written?;
+                        fail_point!(failpoint::PARAMETER_CACHE_WRITE);
 
-                    let bytes = f.seek(SeekFrom::End(0))?;
+                        partial.as_file().sync_all()?;
+                        let bytes = partial.as_file().metadata()?.len();
+                        persist(partial, &cache_path)?;
 
-                    info!(SP_LOG, "wrote parameters to cache {:?} ", f; "target" => "params");
-                    info!(SP_LOG, "groth_parameter_bytes: {}", bytes; "target" => "stats");
-                    Ok(p)
-                })
+                        info!(SP_LOG, "wrote parameters to {:?}", cache_path; "target" => "params");
+                        info!(SP_LOG, "groth_parameter_bytes: {}", bytes; "target" => "stats");
+                        p
+                    }
+                }
+            }
+            None => {
+                checkpoint(ParameterPhase::Generating)?;
+                generate(circuit, progress)?
+            }
+        };
+
+        progress(ParameterPhase::Complete, 1.0);
+
+        Ok(params)
+    }
+}
+
+fn generate<E: JubjubEngine, C: Circuit<E>>(
+    circuit: C,
+    progress: &mut FnMut(ParameterPhase, f64),
+) -> Result<groth16::Parameters<E>> {
+    progress(ParameterPhase::Generating, 0.0);
+    PARAMETERS_GENERATED.fetch_add(1, Ordering::SeqCst);
+
+    // Always seed the rng identically so parameter generation will be deterministic.
+    let rng = &mut XorShiftRng::from_seed(PARAMETER_RNG_SEED);
+    info!(SP_LOG, "Actually generating groth params."; "target" => "params");
+    let start = Instant::now();
+    let parameters = groth16::generate_random_parameters::<E, _, _>(circuit, rng)?;
+    let generation_time = start.elapsed();
+    info!(SP_LOG, "groth_parameter_generation_time: {:?}", generation_time; "target" => "stats");
+
+    progress(ParameterPhase::Generating, GENERATION_SHARE);
+
+    Ok(parameters)
+}
+
+/// An io::Write which discards everything written to it, counting the bytes.
+struct ByteCounter(u64);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Reports the progress of writing `total` bytes to `inner`. Parameters are
+/// written a point at a time, so progress is only reported once it has
+/// advanced by at least a tenth of a percent. `checkpoint` is called before
+/// the first write and every WRITE_CHUNK_BYTES after; once it fails, so does
+/// every write, and its error is kept in `stopped`.
+struct ProgressWriter<'a, W: Write> {
+    inner: W,
+    written: u64,
+    total: u64,
+    reported: f64,
+    progress: &'a mut FnMut(ParameterPhase, f64),
+    checkpoint: &'a Fn(ParameterPhase) -> Result<()>,
+    next_checkpoint: u64,
+    stopped: Option<Error>,
+}
+
+impl<'a, W: Write> Write for ProgressWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.stopped.is_none() && self.written >= self.next_checkpoint {
+            match (self.checkpoint)(ParameterPhase::Writing) {
+                Ok(()) => self.next_checkpoint = self.written + WRITE_CHUNK_BYTES,
+                Err(err) => self.stopped = Some(err),
             }
-            None => Ok(generate()?),
         }
+
+        if self.stopped.is_some() {
+            // Not ErrorKind::Interrupted, which write_all retries.
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "parameter generation stopped",
+            ));
+        }
+
+        let n = self.inner.write(buf)?;
+        self.written += n as u64;
+
+        let done = self.written as f64 / self.total.max(1) as f64;
+        let fraction = GENERATION_SHARE + (1.0 - GENERATION_SHARE) * done.min(1.0);
+
+        if fraction - self.reported >= 0.001 || self.written == self.total {
+            self.reported = fraction;
+            (self.progress)(ParameterPhase::Writing, fraction);
+        }
+
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The lock callers generating the parameters cached at a path take, so that
+/// they're generated once: an exclusive lock on a hidden file beside the cache
+/// file. The file is removed as the lock is released, however generation
+/// ended, leaving nothing behind. A caller which was waiting on the removed
+/// file may then generate parameters alongside one which created it anew; as
+/// both check the cache first, and the cache file is replaced whole, that can
+/// only cost time.
+struct GenerationLock {
+    path: PathBuf,
+    file: File,
+}
+
+impl GenerationLock {
+    fn acquire(cache_path: &Path) -> Result<GenerationLock> {
+        let path = cache_path.with_file_name(hidden_name(cache_path, "lock"));
+        let file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)?;
+        file.lock_exclusive()?;
+
+        Ok(GenerationLock { path, file })
+    }
+}
+
+impl Drop for GenerationLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+        let _ = self.file.unlock();
     }
 }
 
+// The name of a hidden file beside the file at path, e.g. `.params.lock` for
+// `params` and `lock`. With an empty suffix, the prefix of such names.
+fn hidden_name(path: &Path, suffix: &str) -> String {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    format!(".{}.{}", name, suffix)
+}
+
 fn ensure_parent(path: &PathBuf) -> Result<()> {
     match path.parent() {
         Some(dir) => {
@@ -118,14 +369,41 @@ fn ensure_parent(path: &PathBuf) -> Result<()> {
     }
 }
 
+/// Reads groth parameters from `cache_path`. Parameters installed with
+/// install_parameters are checked against the digest they were installed with
+/// as they're read, and refused with an InstalledParametersCorrupted error if
+/// it no longer matches.
 pub fn read_cached_params<E: JubjubEngine>(cache_path: &PathBuf) -> Result<groth16::Parameters<E>> {
     ensure_parent(cache_path)?;
 
+    let recorded = installed_digest(cache_path)?;
+
     let mut f = fs::OpenOptions::new().read(true).open(&cache_path)?;
     f.lock_exclusive()?;
     info!(SP_LOG, "reading groth params from cache: {:?}", cache_path; "target" => "params");
 
-    let params = Parameters::read(&f, false).map_err(Error::from);
+    let params = match recorded {
+        Some(recorded) => {
+            let mut reader = DigestingReader::new(&f);
+            let params = Parameters::read(&mut reader, false);
+
+            // Whatever the parameters' encoding left unread counts too, as
+            // does the rest of a file they failed to be read from.
+            io::copy(&mut reader, &mut io::sink())?;
+            let actual = reader.digest();
+
+            if actual != recorded {
+                return Err(Error::InstalledParametersCorrupted {
+                    path: cache_path.clone(),
+                    recorded,
+                    actual,
+                });
+            }
+
+            params.map_err(Error::from)
+        }
+        None => Parameters::read(&f, false).map_err(Error::from),
+    };
 
     let bytes = f.seek(SeekFrom::End(0))?;
     info!(SP_LOG, "groth_parameter_bytes: {}", bytes; "target" => "stats");
@@ -150,3 +428,505 @@ pub fn write_params_to_cache<E: JubjubEngine>(
     info!(SP_LOG, "wrote parameters to cache {:?} ", f; "target" => "params");
     Ok(p)
 }
+
+/// A parameter file in the parameter cache.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParameterInfo {
+    /// The file's name in the parameter cache directory, e.g. `params.out`.
+    pub identifier: String,
+    pub size: u64,
+    /// The digest the file was installed with, or None if it wasn't installed
+    /// with install_parameters but generated or copied there.
+    pub digest: Option<String>,
+}
+
+/// Installs the parameter file at `source_path` in the parameter cache as
+/// `identifier`, which names the file there, if its digest is
+/// `expected_digest`: a Blake2b digest formatted as in parameters.json. The
+/// file is digested as it's copied, and only replaces what the cache held
+/// under that name, atomically, once it has been checked, so a failed install
+/// leaves the cache as it was. Reading the parameters checks them against the
+/// digest again.
+pub fn install_parameters(
+    source_path: &Path,
+    identifier: &str,
+    expected_digest: &str,
+) -> Result<()> {
+    check_identifier(identifier)?;
+
+    let dir = parameter_cache_dir();
+    create_dir_all(&dir)?;
+
+    let mut source = DigestingReader::new(File::open(source_path)?);
+    let mut partial = NamedTempFile::new_in(&dir)?;
+    let bytes = io::copy(&mut source, &mut partial)?;
+
+    // variable-time: the digests are of published parameters, and both are
+    // reported when they differ.
+    let actual = source.digest();
+    if actual != expected_digest.to_lowercase() {
+        return Err(Error::ParameterDigestMismatch {
+            path: source_path.to_path_buf(),
+            expected: expected_digest.to_string(),
+            actual,
+        });
+    }
+
+    partial.as_file().sync_all()?;
+    persist(partial, &dir.join(identifier))?;
+
+    // Should the digest fail to be recorded, the file fails its check against
+    // whatever was recorded before, until it's installed again.
+    let manifest = dir.join(MANIFEST_DIR);
+    create_dir_all(&manifest)?;
+    let mut record = NamedTempFile::new_in(&manifest)?;
+    record.write_all(actual.as_bytes())?;
+    record.as_file().sync_all()?;
+    persist(record, &manifest.join(identifier))?;
+
+    info!(SP_LOG, "installed parameters {} ({} bytes)", identifier, bytes; "target" => "params");
+
+    Ok(())
+}
+
+/// Lists the parameter files in the parameter cache, ordered by identifier,
+/// with the digests of those installed with install_parameters.
+pub fn list_installed_parameters() -> Result<Vec<ParameterInfo>> {
+    let dir = parameter_cache_dir();
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut infos = Vec::new();
+    for entry in fs::read_dir(&dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let identifier = entry.file_name().to_string_lossy().into_owned();
+
+        // Installs and generations in progress, and their locks, are hidden.
+        if !metadata.is_file() || identifier.starts_with('.') {
+            continue;
+        }
+
+        infos.push(ParameterInfo {
+            digest: installed_digest(&entry.path())?,
+            identifier,
+            size: metadata.len(),
+        });
+    }
+    infos.sort_by(|a, b| a.identifier.cmp(&b.identifier));
+
+    Ok(infos)
+}
+
+/// Removes the parameter file named `identifier` from the parameter cache,
+/// and the digest it was installed with, if any.
+pub fn remove_parameters(identifier: &str) -> Result<()> {
+    check_identifier(identifier)?;
+
+    let dir = parameter_cache_dir();
+    let removed_file = remove_if_present(&dir.join(identifier))?;
+    let removed_record = remove_if_present(&dir.join(MANIFEST_DIR).join(identifier))?;
+
+    if !removed_file && !removed_record {
+        return Err(Error::ParametersNotInstalled(identifier.to_string()));
+    }
+
+    info!(SP_LOG, "removed parameters {}", identifier; "target" => "params");
+
+    Ok(())
+}
+
+/// Returns the digest the parameter file at `path` was installed with, or None
+/// if it wasn't installed with install_parameters.
+pub fn installed_digest(path: &Path) -> Result<Option<String>> {
+    let record = match (path.parent(), path.file_name()) {
+        (Some(dir), Some(name)) => dir.join(MANIFEST_DIR).join(name),
+        _ => return Ok(None),
+    };
+
+    match fs::read_to_string(record) {
+        Ok(digest) => Ok(Some(digest.trim().to_string())),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// Identifiers name files in the cache directory itself, other than the hidden
+// files of installs in progress.
+fn check_identifier(identifier: &str) -> Result<()> {
+    let valid = !identifier.is_empty()
+        && !identifier.starts_with('.')
+        && !identifier.contains('/')
+        && !identifier.contains('\\')
+        && identifier != MANIFEST_DIR;
+
+    if valid {
+        Ok(())
+    } else {
+        Err(Error::InvalidParameterIdentifier(identifier.to_string()))
+    }
+}
+
+fn persist(file: NamedTempFile, path: &Path) -> Result<()> {
+    file.persist(path).map_err(|err| err.error)?;
+    Ok(())
+}
+
+fn remove_if_present(path: &Path) -> Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// An io::Read which digests everything read through it, as parameter files
+/// are digested in parameters.json.
+struct DigestingReader<R: Read> {
+    inner: R,
+    hasher: Blake2b,
+}
+
+impl<R: Read> DigestingReader<R> {
+    fn new(inner: R) -> DigestingReader<R> {
+        DigestingReader {
+            inner,
+            hasher: Blake2b::new(),
+        }
+    }
+
+    fn digest(self) -> String {
+        format!("{:.32x}", self.hasher.result())
+    }
+}
+
+impl<R: Read> Read for DigestingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.input(&buf[..n]);
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::failpoint::{FailAction, FailScenario};
+    use bellman::{ConstraintSystem, SynthesisError};
+    use pairing::bls12_381::Bls12;
+    use pairing::Field;
+    use rand::{thread_rng, Rng};
+    use std::panic;
+    use std::sync::atomic::AtomicBool;
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    use self::ParameterPhase::*;
+
+    // Knowledge of a square root of one.
+    struct SquareRoot;
+
+    impl<E: JubjubEngine> Circuit<E> for SquareRoot {
+        fn synthesize<CS: ConstraintSystem<E>>(
+            self,
+            cs: &mut CS,
+        ) -> ::std::result::Result<(), SynthesisError> {
+            let x = cs.alloc(|| "x", || Ok(E::Fr::one()))?;
+            let y = cs.alloc_input(|| "y", || Ok(E::Fr::one()))?;
+            cs.enforce(|| "x * x = y", |lc| lc + x, |lc| lc + x, |lc| lc + y);
+
+            Ok(())
+        }
+    }
+
+    // A SquareRoot which signals that synthesis has started and then takes its
+    // time, so that generation can be stopped while it's under way.
+    struct SlowSquareRoot(mpsc::Sender<()>, Duration);
+
+    impl<E: JubjubEngine> Circuit<E> for SlowSquareRoot {
+        fn synthesize<CS: ConstraintSystem<E>>(
+            self,
+            cs: &mut CS,
+        ) -> ::std::result::Result<(), SynthesisError> {
+            let _ = self.0.send(());
+            thread::sleep(self.1);
+
+            SquareRoot.synthesize(cs)
+        }
+    }
+
+    #[derive(Clone)]
+    struct TestParams(String);
+
+    impl ParameterSetIdentifier for TestParams {
+        fn parameter_set_identifier(&self) -> String {
+            self.0.clone()
+        }
+    }
+
+    struct TestCache;
+
+    impl CacheableParameters<Bls12, SquareRoot, TestParams> for TestCache {
+        fn cache_prefix() -> String {
+            String::from("progress-test")
+        }
+    }
+
+    // Caches the same parameters as TestCache does, generating them slowly.
+    struct SlowTestCache;
+
+    impl CacheableParameters<Bls12, SlowSquareRoot, TestParams> for SlowTestCache {
+        fn cache_prefix() -> String {
+            String::from("progress-test")
+        }
+    }
+
+    fn record_progress(pp: &TestParams) -> Vec<(ParameterPhase, f64)> {
+        let mut calls = Vec::new();
+
+        TestCache::get_groth_params_with_progress(SquareRoot, pp, &mut |phase, fraction| {
+            calls.push((phase, fraction))
+        })
+        .expect("failed to get groth params");
+
+        calls
+    }
+
+    fn assert_well_formed(calls: &[(ParameterPhase, f64)]) {
+        for pair in calls.windows(2) {
+            assert!(pair[0].1 <= pair[1].1, "progress decreased: {:?}", calls);
+        }
+
+        let completions = calls.iter().filter(|(phase, _)| *phase == Complete).count();
+        assert_eq!(1, completions, "expected one completion: {:?}", calls);
+        assert_eq!(Some(&(Complete, 1.0)), calls.last());
+    }
+
+    #[test]
+    fn reports_progress_when_generating_and_when_cached() {
+        // A fresh identifier, so that the first call generates parameters.
+        let pp = TestParams(format!("progress {}", thread_rng().gen::<u64>()));
+
+        let generated = record_progress(&pp);
+        assert_well_formed(&generated);
+        assert_eq!((Generating, 0.0), generated[0]);
+        assert!(generated.iter().any(|(phase, _)| *phase == Writing));
+
+        let cached = record_progress(&pp);
+        assert_well_formed(&cached);
+        assert_eq!(vec![(Complete, 1.0)], cached);
+
+        let id = TestCache::cache_identifier(&pp).unwrap();
+        fs::remove_file(parameter_cache_path(&id)).unwrap();
+    }
+
+    #[test]
+    fn concurrent_callers_generate_parameters_once() {
+        let pp = TestParams(format!("concurrent {}", thread_rng().gen::<u64>()));
+
+        let callers: Vec<_> = (0..4)
+            .map(|_| {
+                let pp = pp.clone();
+                thread::spawn(move || record_progress(&pp))
+            })
+            .collect();
+
+        let generations = callers
+            .into_iter()
+            .map(|caller| caller.join().expect("caller panicked"))
+            .filter(|calls| calls.iter().any(|(phase, _)| *phase == Generating))
+            .count();
+        assert_eq!(1, generations);
+
+        let id = TestCache::cache_identifier(&pp).unwrap();
+        fs::remove_file(parameter_cache_path(&id)).unwrap();
+    }
+
+    fn file_digest(path: &Path) -> String {
+        let mut reader = DigestingReader::new(File::open(path).unwrap());
+        io::copy(&mut reader, &mut io::sink()).unwrap();
+        reader.digest()
+    }
+
+    #[test]
+    fn installed_parameters_are_checked_when_read() {
+        let pp = TestParams(format!("install {}", thread_rng().gen::<u64>()));
+        let path = parameter_cache_path(&TestCache::cache_identifier(&pp).unwrap());
+        let identifier = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        // The fixture: the circuit's parameters, moved out of the cache.
+        record_progress(&pp);
+        let fixture = NamedTempFile::new().unwrap();
+        fs::copy(&path, fixture.path()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let digest = file_digest(fixture.path());
+
+        match install_parameters(fixture.path(), &identifier, &"0".repeat(32)) {
+            Err(Error::ParameterDigestMismatch { .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert!(!path.exists());
+
+        install_parameters(fixture.path(), &identifier, &digest).unwrap();
+        let installed = list_installed_parameters()
+            .unwrap()
+            .into_iter()
+            .find(|info| info.identifier == identifier);
+        assert_eq!(
+            Some(ParameterInfo {
+                identifier: identifier.clone(),
+                size: fs::metadata(fixture.path()).unwrap().len(),
+                digest: Some(digest.clone()),
+            }),
+            installed
+        );
+        assert!(TestCache::get_groth_params(SquareRoot, &pp).is_ok());
+
+        // Corrupted parameters are refused, rather than used or generated
+        // again in their place.
+        let mut bytes = fs::read(&path).unwrap();
+        let middle = bytes.len() / 2;
+        bytes[middle] ^= 0xff;
+        fs::write(&path, &bytes).unwrap();
+
+        match TestCache::get_groth_params(SquareRoot, &pp).map(|_| ()) {
+            Err(Error::InstalledParametersCorrupted { .. }) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+        assert_eq!(bytes, fs::read(&path).unwrap());
+
+        // Installing them again repairs them.
+        install_parameters(fixture.path(), &identifier, &digest).unwrap();
+        assert!(TestCache::get_groth_params(SquareRoot, &pp).is_ok());
+
+        remove_parameters(&identifier).unwrap();
+        assert!(!path.exists());
+        assert!(installed_digest(&path).unwrap().is_none());
+        match remove_parameters(&identifier) {
+            Err(Error::ParametersNotInstalled(_)) => (),
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+
+    // The files in the cache directory named after the parameters at path.
+    fn residue(path: &Path) -> Vec<String> {
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+
+        fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|entry| entry.contains(&name))
+            .collect()
+    }
+
+    #[test]
+    fn cancelled_generation_leaves_nothing_behind() {
+        let pp = TestParams(format!("cancel {}", thread_rng().gen::<u64>()));
+        let path = parameter_cache_path(&TestCache::cache_identifier(&pp).unwrap());
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let (started, synthesizing) = mpsc::channel();
+
+        let generation = {
+            let (pp, cancelled) = (pp.clone(), cancelled.clone());
+            let circuit = SlowSquareRoot(started, Duration::from_millis(200));
+
+            thread::spawn(move || {
+                let checkpoint = |phase: ParameterPhase| {
+                    if cancelled.load(Ordering::SeqCst) {
+                        return Err(Error::ParameterGenerationCancelled {
+                            reason: format!("cancelled while {}", phase.name()),
+                            timed_out: false,
+                        });
+                    }
+
+                    Ok(())
+                };
+
+                SlowTestCache::get_groth_params_cancellable(
+                    circuit,
+                    &pp,
+                    &mut |_, _| (),
+                    &checkpoint,
+                )
+                .map(|_| ())
+            })
+        };
+
+        // Cancelled while the parameters are generated, generation stops as
+        // they start to be written.
+        synthesizing.recv().unwrap();
+        cancelled.store(true, Ordering::SeqCst);
+
+        match generation.join().expect("generation panicked") {
+            Err(Error::ParameterGenerationCancelled { reason, timed_out }) => {
+                assert_eq!("cancelled while writing", reason);
+                assert!(!timed_out);
+            }
+            other => panic!("unexpected result: {:?}", other),
+        }
+
+        // Neither the parameters, nor a partial file, nor the lock remain.
+        let residue = residue(&path);
+        assert!(residue.is_empty(), "left behind: {:?}", residue);
+
+        // Trying again generates the parameters at once, as they're generated
+        // under any other identifier.
+        let retried = record_progress(&pp);
+        assert_well_formed(&retried);
+        assert!(retried.iter().any(|(phase, _)| *phase == Generating));
+        assert!(read_cached_params::<Bls12>(&path).is_ok());
+
+        let reference = TestParams(format!("reference {}", thread_rng().gen::<u64>()));
+        let reference_path =
+            parameter_cache_path(&TestCache::cache_identifier(&reference).unwrap());
+        record_progress(&reference);
+        assert_eq!(file_digest(&reference_path), file_digest(&path));
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&reference_path).unwrap();
+    }
+
+    #[test]
+    fn generation_failing_mid_write_leaves_nothing_behind() {
+        let pp = TestParams(format!("fail {}", thread_rng().gen::<u64>()));
+        let path = parameter_cache_path(&TestCache::cache_identifier(&pp).unwrap());
+
+        for action in vec![FailAction::Error, FailAction::Panic] {
+            let scenario = FailScenario::setup();
+            scenario.arm(failpoint::PARAMETER_CACHE_WRITE, action);
+
+            let generated = panic::catch_unwind(|| {
+                TestCache::get_groth_params_with_progress(SquareRoot, &pp, &mut |_, _| ())
+            });
+
+            match generated {
+                Ok(Err(Error::Failpoint(_))) | Err(_) => (),
+                Ok(other) => panic!("unexpected result: {:?}", other.map(|_| ())),
+            }
+
+            let residue = residue(&path);
+            assert!(residue.is_empty(), "left behind: {:?}", residue);
+        }
+
+        assert_well_formed(&record_progress(&pp));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn identifiers_name_files_in_the_cache_directory() {
+        for identifier in &[
+            "",
+            ".partial",
+            "../params.out",
+            "dir/params.out",
+            MANIFEST_DIR,
+        ] {
+            match remove_parameters(identifier) {
+                Err(Error::InvalidParameterIdentifier(_)) => (),
+                other => panic!("unexpected result for {:?}: {:?}", identifier, other),
+            }
+        }
+    }
+}
@@ -79,7 +79,7 @@ impl<H: Hasher> PieceInclusionProof<H> {
         // If the computed root is equal to the provided root, then the piece was provably
         // present in the data from which the merkle tree was constructed.
         match compute_root::<H>(&self.first_node_proof, &self.last_node_proof, piece) {
-            Ok(computed_root) => *root == computed_root,
+            Ok(computed_root) => root.ct_eq(&computed_root),
             Err(_) => false,
         }
     }
@@ -10,6 +10,7 @@ use crate::error::{Error, Result};
 use crate::hasher::{Domain, Hasher};
 use crate::merkle::{MerkleProof, MerkleTree};
 use crate::parameter_cache::ParameterSetIdentifier;
+use crate::post_timing::{self, PostPhase};
 use crate::proof::ProofScheme;
 
 #[derive(Debug, Clone)]
@@ -75,6 +76,18 @@ impl<H: Hasher> Proof<H> {
     }
 }
 
+/// Why a challenge of a PoRC proof fails verification, as found by `PoRC::check_challenges`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChallengeFailure {
+    /// The path proven leads to a root other than the challenged sector's commitment, or is
+    /// longer or shorter than the sector's tree is high.
+    PathMismatch,
+    /// The leaf proven isn't the one challenged, or doesn't hash up its path to its root.
+    LeafMismatch,
+    /// The proof has no path for the challenge.
+    MissingProof,
+}
+
 #[derive(Debug, Clone)]
 pub struct PoRC<'a, H>
 where
@@ -109,20 +122,26 @@ impl<'a, H: 'a + Hasher> ProofScheme<'a> for PoRC<'a, H> {
         if priv_inputs.trees.len() != pub_params.sectors_count {
             return Err(Error::MalformedInput);
         }
+        post_timing::count_challenges(pub_inputs.challenges.len());
+
         let proofs = pub_inputs
             .challenges
             .iter()
             .zip(pub_inputs.challenged_sectors)
             .map(|(challenged_leaf, challenged_sector)| {
-                let tree = priv_inputs.trees[*challenged_sector];
+                let tree = post_timing::time(PostPhase::SectorLookup, || {
+                    let tree = priv_inputs.trees[*challenged_sector];
 
-                if pub_inputs.commitments[*challenged_sector] != tree.root() {
-                    return Err(Error::InvalidCommitment);
-                }
+                    if !pub_inputs.commitments[*challenged_sector].ct_eq(&tree.root()) {
+                        return Err(Error::InvalidCommitment);
+                    }
+
+                    Ok(tree)
+                })?;
 
-                Ok(MerkleProof::new_from_proof(
-                    &tree.gen_proof(*challenged_leaf),
-                ))
+                Ok(post_timing::time(PostPhase::PathHashing, || {
+                    MerkleProof::new_from_proof(&tree.gen_proof(*challenged_leaf))
+                }))
             })
             .collect::<Result<Vec<_>>>()?;
 
@@ -142,16 +161,20 @@ impl<'a, H: 'a + Hasher> ProofScheme<'a> for PoRC<'a, H> {
                 .zip(pub_inputs.challenged_sectors.iter()),
         ) {
             // validate the commitment
-            if merkle_proof.root() != &pub_inputs.commitments[*challenged_sector] {
+            let commitment = &pub_inputs.commitments[*challenged_sector];
+            let valid_root = post_timing::time(PostPhase::SectorLookup, || {
+                merkle_proof.root().ct_eq(commitment)
+            });
+            if !valid_root {
                 return Ok(false);
             }
 
-            // validate the path length
-            if graph_height(pub_params.leaves) != merkle_proof.path().len() {
-                return Ok(false);
-            }
-
-            if !merkle_proof.validate(*challenged_leaf) {
+            let valid_path = post_timing::time(PostPhase::PathHashing, || {
+                // validate the path length
+                graph_height(pub_params.leaves) == merkle_proof.path().len()
+                    && merkle_proof.validate(*challenged_leaf)
+            });
+            if !valid_path {
                 return Ok(false);
             }
         }
@@ -160,6 +183,41 @@ impl<'a, H: 'a + Hasher> ProofScheme<'a> for PoRC<'a, H> {
     }
 }
 
+impl<'a, H: 'a + Hasher> PoRC<'a, H> {
+    /// Checks each challenge as `verify` does, but rather than stop at the first to fail,
+    /// returns why each failed, or None for those which pass, in the order of the challenges.
+    pub fn check_challenges(
+        pub_params: &PublicParams,
+        pub_inputs: &PublicInputs<'a, H::Domain>,
+        proof: &Proof<H>,
+    ) -> Vec<Option<ChallengeFailure>> {
+        pub_inputs
+            .challenges
+            .iter()
+            .zip(pub_inputs.challenged_sectors)
+            .enumerate()
+            .map(|(i, (challenged_leaf, challenged_sector))| {
+                let merkle_proof = match proof.0.get(i) {
+                    Some(merkle_proof) => merkle_proof,
+                    None => return Some(ChallengeFailure::MissingProof),
+                };
+
+                let commitment = pub_inputs.commitments.get(*challenged_sector);
+
+                if !commitment.map_or(false, |commitment| commitment.ct_eq(merkle_proof.root()))
+                    || graph_height(pub_params.leaves) != merkle_proof.path().len()
+                {
+                    Some(ChallengeFailure::PathMismatch)
+                } else if !merkle_proof.validate(*challenged_leaf) {
+                    Some(ChallengeFailure::LeafMismatch)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+}
+
 pub fn slice_mod(challenge: impl AsRef<[u8]>, count: usize) -> usize {
     // TODO: verify this is the correct way to derive the challenge
     let big_challenge = BigUint::from_bytes_be(challenge.as_ref());
@@ -344,6 +402,65 @@ mod tests {
         test_porc_validates_challenge_identity::<PedersenHasher>();
     }
 
+    #[test]
+    fn check_challenges_says_why_each_fails() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+        let leaves = 32;
+        let pub_params = PublicParams {
+            leaves,
+            sectors_count: 2,
+        };
+
+        let trees: Vec<_> = (0..2)
+            .map(|_| {
+                let data: Vec<u8> = (0..leaves)
+                    .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+                    .collect();
+
+                BucketGraph::<PedersenHasher>::new(leaves, 5, 0, new_seed())
+                    .merkle_tree(data.as_slice())
+                    .unwrap()
+            })
+            .collect();
+
+        let pub_inputs = PublicInputs {
+            challenges: &[3, 7, 11],
+            challenged_sectors: &[0, 1, 1],
+            commitments: &[trees[0].root(), trees[1].root()],
+        };
+        let priv_inputs = PrivateInputs::<PedersenHasher> {
+            trees: &[&trees[0], &trees[1]],
+        };
+
+        let mut proof = PoRC::prove(&pub_params, &pub_inputs, &priv_inputs).unwrap();
+        assert_eq!(
+            vec![None, None, None],
+            PoRC::check_challenges(&pub_params, &pub_inputs, &proof)
+        );
+
+        // The first sector committed to as the second, and another leaf challenged last.
+        let wrong_inputs = PublicInputs {
+            challenges: &[3, 7, 12],
+            challenged_sectors: &[0, 1, 1],
+            commitments: &[trees[1].root(), trees[1].root()],
+        };
+        assert_eq!(
+            vec![
+                Some(ChallengeFailure::PathMismatch),
+                None,
+                Some(ChallengeFailure::LeafMismatch)
+            ],
+            PoRC::check_challenges(&pub_params, &wrong_inputs, &proof)
+        );
+
+        proof.0.pop();
+        assert_eq!(
+            vec![None, None, Some(ChallengeFailure::MissingProof)],
+            PoRC::check_challenges(&pub_params, &pub_inputs, &proof)
+        );
+    }
+
     #[test]
     fn test_slice_mod() {
         let cases: [(Vec<u8>, usize, usize); 5] = [
@@ -0,0 +1,261 @@
+//! Timing of the work done to generate and verify proofs-of-spacetime, broken down by what it was
+//! spent on, for choosing PoSt parameters (e.g. the number of challenges per sector) from
+//! measurements rather than guesses.
+//!
+//! Work is timed with `time`, which only reads the clock while `record` is recording a breakdown
+//! on the current thread. Otherwise it costs a thread-local lookup, so proofs which aren't being
+//! recorded take no longer than before. Work timed within other timed work counts towards its own
+//! phase only, so that no time is counted twice.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+/// What the time taken by a PoSt was spent on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PostPhase {
+    /// Finding each challenged sector's tree, and checking it against the sector's commitment.
+    SectorLookup,
+    /// Reading the challenged sectors' replicas from disk, for their leaves to be proven.
+    LeafRead,
+    /// Hashing the challenged sectors' merkle trees, and building (or checking) each challenged
+    /// leaf's path.
+    PathHashing,
+    /// Folding each epoch's proofs into the next epoch's challenges (by way of the VDF), and
+    /// proving (or verifying) the SNARK aggregating every epoch's proofs.
+    Aggregation,
+}
+
+impl PostPhase {
+    pub fn name(self) -> &'static str {
+        match self {
+            PostPhase::SectorLookup => "sector_lookup",
+            PostPhase::LeafRead => "leaf_read",
+            PostPhase::PathHashing => "path_hashing",
+            PostPhase::Aggregation => "aggregation",
+        }
+    }
+}
+
+impl fmt::Display for PostPhase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Where the time taken to generate or verify a PoSt went.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PostTimingBreakdown {
+    /// The number of challenges proven.
+    pub challenges: u64,
+    pub sector_lookup: Duration,
+    pub leaf_read: Duration,
+    pub path_hashing: Duration,
+    pub aggregation: Duration,
+    /// The time taken overall, including what was spent in none of the phases (e.g. loading
+    /// parameters).
+    pub total: Duration,
+}
+
+impl PostTimingBreakdown {
+    /// The columns of `csv_row`.
+    pub const CSV_HEADER: &'static str =
+        "challenges,sector_lookup_us,leaf_read_us,path_hashing_us,aggregation_us,total_us";
+
+    /// The time spent in `phase`.
+    pub fn phase(&self, phase: PostPhase) -> Duration {
+        match phase {
+            PostPhase::SectorLookup => self.sector_lookup,
+            PostPhase::LeafRead => self.leaf_read,
+            PostPhase::PathHashing => self.path_hashing,
+            PostPhase::Aggregation => self.aggregation,
+        }
+    }
+
+    fn phase_mut(&mut self, phase: PostPhase) -> &mut Duration {
+        match phase {
+            PostPhase::SectorLookup => &mut self.sector_lookup,
+            PostPhase::LeafRead => &mut self.leaf_read,
+            PostPhase::PathHashing => &mut self.path_hashing,
+            PostPhase::Aggregation => &mut self.aggregation,
+        }
+    }
+
+    /// The time spent in all of the phases together, at most `total`.
+    pub fn phases_total(&self) -> Duration {
+        self.sector_lookup + self.leaf_read + self.path_hashing + self.aggregation
+    }
+
+    /// The breakdown as a line of CSV, with the columns of `CSV_HEADER`, in microseconds.
+    pub fn csv_row(&self) -> String {
+        let micros = |d: Duration| d.as_secs() * 1_000_000 + u64::from(d.subsec_micros());
+
+        format!(
+            "{},{},{},{},{},{}",
+            self.challenges,
+            micros(self.sector_lookup),
+            micros(self.leaf_read),
+            micros(self.path_hashing),
+            micros(self.aggregation),
+            micros(self.total)
+        )
+    }
+}
+
+#[derive(Default)]
+struct Recording {
+    breakdown: PostTimingBreakdown,
+    // For each call to time in progress, innermost last, the time taken by the work it has timed
+    // within it so far.
+    nested: Vec<Duration>,
+}
+
+thread_local! {
+    static CURRENT_RECORDING: RefCell<Option<Recording>> = RefCell::new(None);
+}
+
+// Restores the recording which was current before record, even when unwinding.
+struct RestoreRecording(Option<Recording>);
+
+impl Drop for RestoreRecording {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT_RECORDING.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+/// Calls `f`, returning what it returns along with where the time it took went.
+pub fn record<T, F: FnOnce() -> T>(f: F) -> (T, PostTimingBreakdown) {
+    let previous = CURRENT_RECORDING.with(|current| current.replace(Some(Recording::default())));
+    let _restore = RestoreRecording(previous);
+
+    let start = Instant::now();
+    let value = f();
+    let total = start.elapsed();
+
+    let recording = CURRENT_RECORDING.with(|current| current.borrow_mut().take());
+    let mut breakdown = recording.map(|r| r.breakdown).unwrap_or_default();
+    breakdown.total = total;
+
+    (value, breakdown)
+}
+
+/// Calls `f`, counting the time it takes towards `phase` if a breakdown is being recorded, less
+/// the time taken by any work timed within it.
+pub fn time<T, F: FnOnce() -> T>(phase: PostPhase, f: F) -> T {
+    let recording = CURRENT_RECORDING.with(|current| match *current.borrow_mut() {
+        Some(ref mut recording) => {
+            recording.nested.push(Duration::default());
+            true
+        }
+        None => false,
+    });
+
+    if !recording {
+        return f();
+    }
+
+    let start = Instant::now();
+    let value = f();
+    let elapsed = start.elapsed();
+
+    CURRENT_RECORDING.with(|current| {
+        if let Some(ref mut recording) = *current.borrow_mut() {
+            let nested = recording.nested.pop().unwrap_or_default();
+            *recording.breakdown.phase_mut(phase) +=
+                elapsed.checked_sub(nested).unwrap_or_default();
+
+            if let Some(outer) = recording.nested.last_mut() {
+                *outer += elapsed;
+            }
+        }
+    });
+
+    value
+}
+
+/// Counts `challenges` more challenges as proven, if a breakdown is being recorded.
+pub fn count_challenges(challenges: usize) {
+    CURRENT_RECORDING.with(|current| {
+        if let Some(ref mut recording) = *current.borrow_mut() {
+            recording.breakdown.challenges += challenges as u64;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    fn sleep_ms(ms: u64) {
+        thread::sleep(Duration::from_millis(ms));
+    }
+
+    #[test]
+    fn nested_work_counts_towards_its_own_phase() {
+        let (value, breakdown) = record(|| {
+            time(PostPhase::Aggregation, || {
+                sleep_ms(20);
+                time(PostPhase::PathHashing, || sleep_ms(30));
+                count_challenges(2);
+                sleep_ms(10);
+            });
+            time(PostPhase::LeafRead, || sleep_ms(10));
+
+            42
+        });
+
+        assert_eq!(42, value);
+        assert_eq!(2, breakdown.challenges);
+        assert!(breakdown.path_hashing >= Duration::from_millis(30));
+        assert!(breakdown.aggregation >= Duration::from_millis(30));
+        assert!(breakdown.aggregation < Duration::from_millis(60));
+        assert!(breakdown.leaf_read >= Duration::from_millis(10));
+        assert_eq!(Duration::default(), breakdown.sector_lookup);
+        assert!(breakdown.phases_total() <= breakdown.total);
+        assert!(breakdown.phases_total() >= Duration::from_millis(70));
+    }
+
+    #[test]
+    fn nothing_is_recorded_outside_of_record() {
+        time(PostPhase::Aggregation, || sleep_ms(5));
+        count_challenges(3);
+
+        let (_, breakdown) = record(|| ());
+        assert_eq!(0, breakdown.challenges);
+        assert_eq!(Duration::default(), breakdown.phases_total());
+    }
+
+    #[test]
+    fn recordings_restore_the_one_they_interrupt() {
+        let (inner, outer) = record(|| {
+            count_challenges(1);
+            let (_, inner) = record(|| count_challenges(5));
+            count_challenges(1);
+
+            inner
+        });
+
+        assert_eq!(5, inner.challenges);
+        assert_eq!(2, outer.challenges);
+    }
+
+    #[test]
+    fn csv_rows_match_the_header() {
+        let breakdown = PostTimingBreakdown {
+            challenges: 30,
+            sector_lookup: Duration::from_micros(5),
+            leaf_read: Duration::from_millis(2),
+            path_hashing: Duration::from_secs(1),
+            aggregation: Duration::from_micros(1_500_001),
+            total: Duration::from_secs(3),
+        };
+
+        assert_eq!("30,5,2000,1000000,1500001,3000000", breakdown.csv_row());
+        assert_eq!(
+            PostTimingBreakdown::CSV_HEADER.split(',').count(),
+            breakdown.csv_row().split(',').count()
+        );
+    }
+}
@@ -0,0 +1,126 @@
+//! The fixed-size byte arrays in which field elements, commitments and ids are
+//! passed between the crates of this workspace, and across the C API, along
+//! with the conversions between them and field elements. sector-base and
+//! filecoin-proofs re-export this module rather than declare their own.
+//!
+//! Every array holds a little-endian value: its first byte is the least
+//! significant. A field element is encoded as its canonical (i.e. fully
+//! reduced, non-Montgomery) value, so the most significant bit of its last
+//! byte is always 0.
+
+use pairing::bls12_381::{Bls12, Fr};
+
+use crate::error::Result;
+use crate::fr32::{bytes_into_fr, fr_into_bytes};
+
+/// The number of bytes in which a field element is encoded.
+pub const FR32_BYTES: usize = 32;
+
+/// The largest whole number of bytes whose every value is a field element.
+pub const FR_SAFE_BYTES: usize = 31;
+
+/// An array whose little-endian value is a field element.
+pub type Fr32Ary = [u8; FR32_BYTES];
+
+/// A commitment (e.g. comm_r, comm_d or comm_r_star): the encoding of the field
+/// element committed to. Verification compares commitments with ct_eq, from
+/// crypto::constant_time::ConstantTimeEq, rather than with `==`.
+pub type Commitment = Fr32Ary;
+
+/// An array whose little-endian value is a field element, whatever its bytes,
+/// such as a prover or sector id.
+pub type FrSafe = [u8; FR_SAFE_BYTES];
+
+/// Encodes a field element as a commitment.
+pub fn commitment_from_fr(fr: Fr) -> Commitment {
+    let mut commitment = [0; FR32_BYTES];
+    commitment.copy_from_slice(&fr_into_bytes::<Bls12>(&fr));
+    commitment
+}
+
+/// Decodes a commitment encoded by commitment_from_fr. Fails with BadFrBytes if
+/// its little-endian value isn't less than the field's modulus, as then it's
+/// the encoding of no field element.
+pub fn fr_from_commitment(commitment: &Commitment) -> Result<Fr> {
+    bytes_into_fr::<Bls12>(commitment)
+}
+
+/// Pads an FrSafe to the 32 bytes of a field element, by appending a zero
+/// (most significant) byte, which leaves its value unchanged.
+pub fn pad_safe_fr(bytes: &FrSafe) -> Fr32Ary {
+    let mut padded = [0; FR32_BYTES];
+    padded[..FR_SAFE_BYTES].copy_from_slice(bytes);
+    padded
+}
+
+/// Copies the commitment at the start of bytes, e.g. one of a concatenation
+/// of commitments. Panics if bytes is shorter than a commitment.
+pub fn commitment_from_slice(bytes: &[u8]) -> Commitment {
+    let mut commitment = [0; FR32_BYTES];
+    commitment.copy_from_slice(&bytes[..FR32_BYTES]);
+    commitment
+}
+
+/// Copies the FrSafe at the start of bytes, as commitment_from_slice copies a
+/// commitment.
+pub fn fr_safe_from_slice(bytes: &[u8]) -> FrSafe {
+    let mut fr_safe = [0; FR_SAFE_BYTES];
+    fr_safe.copy_from_slice(&bytes[..FR_SAFE_BYTES]);
+    fr_safe
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error;
+    use pairing::{Field, PrimeField};
+    use rand::{Rng, SeedableRng, XorShiftRng};
+
+    #[test]
+    fn commitments_round_trip() {
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+
+        for _ in 0..100 {
+            let fr: Fr = rng.gen();
+            let commitment = commitment_from_fr(fr);
+
+            assert_eq!(0, commitment[31] & 0x80);
+            assert_eq!(fr, fr_from_commitment(&commitment).unwrap());
+        }
+    }
+
+    #[test]
+    fn non_canonical_commitments_are_refused() {
+        // The modulus itself, and the largest value 32 bytes can hold.
+        let mut modulus = [0; FR32_BYTES];
+        let mut minus_one = Fr::zero();
+        minus_one.sub_assign(&Fr::one());
+        modulus.copy_from_slice(&fr_into_bytes::<Bls12>(&minus_one));
+        modulus[0] += 1;
+
+        for commitment in &[modulus, [0xff; FR32_BYTES]] {
+            match fr_from_commitment(commitment) {
+                Err(Error::BadFrBytes) => (),
+                other => panic!("unexpected result: {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn padding_keeps_safe_values() {
+        let mut bytes = [0; FR_SAFE_BYTES];
+        bytes[0] = 1;
+        bytes[1] = 2;
+
+        let padded = pad_safe_fr(&bytes);
+        assert_eq!(0, padded[31]);
+        assert_eq!(&bytes[..], &padded[..31]);
+        assert_eq!(
+            Fr::from_str("513").unwrap(),
+            fr_from_commitment(&padded).unwrap()
+        );
+
+        // Any 31 bytes are a field element.
+        assert!(fr_from_commitment(&pad_safe_fr(&[0xff; FR_SAFE_BYTES])).is_ok());
+    }
+}
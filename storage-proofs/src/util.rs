@@ -3,9 +3,20 @@ use pairing::Engine;
 use sapling_crypto::circuit::boolean::{self, AllocatedBit, Boolean};
 
 use crate::error;
+use crate::hasher::{Domain, Hasher};
 
+/// The bytes in each node of a replica (lambda). Every offset of a node, and
+/// every size counted in nodes, is computed with it, rather than with a
+/// literal. Only one size is supported: the trees hash nodes as their leaves,
+/// so a node must be the size of the hashers' domains.
 pub const NODE_SIZE: usize = 32;
 
+/// Whether nodes of NODE_SIZE bytes are elements of H's domain, as the trees
+/// built with H, and the encoding, take them to be.
+pub fn node_size_matches<H: Hasher>() -> bool {
+    H::Domain::default().into_bytes().len() == NODE_SIZE
+}
+
 /// Returns the start position of the data, 0-indexed.
 pub fn data_at_node_offset(v: usize) -> usize {
     v * NODE_SIZE
@@ -1,7 +1,34 @@
 use crate::drgraph::Graph;
 use crate::error::Result;
 use crate::hasher::{Domain, Hasher};
-use crate::util::{data_at_node, data_at_node_offset};
+use crate::node_source::NodeSource;
+use crate::util::{data_at_node, data_at_node_offset, node_size_matches, NODE_SIZE};
+use crate::yield_hook::{self, DECODING, ENCODING};
+
+/// Buffers reused from one node to the next while encoding, so that encoding
+/// a node performs no heap allocations once the buffers have grown to fit the
+/// graph. A scratch must not be shared between threads; give each worker its
+/// own.
+///
+/// The field element temporaries used by key derivation and sloth live on the
+/// stack, so only the variable-length buffers need to be kept here.
+#[derive(Debug, Default, Clone)]
+pub struct EncodingScratch {
+    /// The parents of the node being encoded.
+    parents: Vec<usize>,
+    /// Key derivation input: replica id | parent 1 | parent 2 | ...
+    key_material: Vec<u8>,
+}
+
+impl EncodingScratch {
+    /// Creates a scratch already sized for a graph of the given degree.
+    pub fn new(degree: usize) -> EncodingScratch {
+        EncodingScratch {
+            parents: Vec::with_capacity(degree),
+            key_material: Vec::with_capacity(NODE_SIZE * (degree + 1)),
+        }
+    }
+}
 
 /// encodes the data and overwrites the original data slice.
 pub fn encode<'a, H, G>(
@@ -14,8 +41,26 @@ where
     H: Hasher,
     G: Graph<H>,
 {
-    let degree = graph.degree();
+    let mut scratch = EncodingScratch::new(graph.degree());
+
+    encode_with_scratch(graph, sloth_iter, replica_id, data, &mut scratch)
+}
 
+/// Like `encode`, but uses the caller's scratch buffers, which lets a worker
+/// encode many replicas without allocating. Each node encoded counts towards
+/// the next call of the yield hook (see yield_hook), and encoding stops if it
+/// asks to.
+pub fn encode_with_scratch<'a, H, G>(
+    graph: &'a G,
+    sloth_iter: usize,
+    replica_id: &'a H::Domain,
+    data: &'a mut [u8],
+    scratch: &mut EncodingScratch,
+) -> Result<()>
+where
+    H: Hasher,
+    G: Graph<H>,
+{
     // Because a node always follows all of its parents in the data,
     // the nodes are by definition already topologically sorted.
     // Therefore, if we simply traverse the data in order, encoding each node in place,
@@ -32,20 +77,43 @@ where
             (graph.size() - n) - 1
         };
 
-        let parents = graph.parents(node);
-        assert_eq!(parents.len(), graph.degree(), "wrong number of parents");
+        yield_hook::yield_point(ENCODING, 1)?;
+        encode_node(graph, sloth_iter, replica_id, data, node, scratch)?;
+    }
 
-        let key = create_key::<H>(replica_id, node, &parents, data, degree)?;
-        let start = data_at_node_offset(node);
-        let end = start + 32;
+    Ok(())
+}
 
-        let node_data = H::Domain::try_from_bytes(&data[start..end])?;
-        let encoded = H::sloth_encode(&key, &node_data, sloth_iter);
+/// Encodes a single node in place. All of the node's parents must already
+/// have been encoded.
+pub fn encode_node<'a, H, G>(
+    graph: &'a G,
+    sloth_iter: usize,
+    replica_id: &'a H::Domain,
+    data: &'a mut [u8],
+    node: usize,
+    scratch: &mut EncodingScratch,
+) -> Result<()>
+where
+    H: Hasher,
+    G: Graph<H>,
+{
+    let EncodingScratch {
+        parents,
+        key_material,
+    } = scratch;
 
-        encoded.write_bytes(&mut data[start..end])?;
-    }
+    graph.parents_into(node, parents);
+    assert_eq!(parents.len(), graph.degree(), "wrong number of parents");
 
-    Ok(())
+    let key = create_key_into::<H>(replica_id, node, parents, data, graph.degree(), key_material)?;
+    let start = data_at_node_offset(node);
+    let end = start + NODE_SIZE;
+
+    let node_data = H::Domain::try_from_bytes(&data[start..end])?;
+    let encoded = H::sloth_encode(&key, &node_data, sloth_iter);
+
+    encoded.write_bytes(&mut data[start..end])
 }
 
 pub fn decode<'a, H, G>(
@@ -61,6 +129,7 @@ where
     // TODO: parallelize
     (0..graph.size()).fold(Ok(Vec::with_capacity(data.len())), |acc, i| {
         acc.and_then(|mut acc| {
+            yield_hook::yield_point(DECODING, 1)?;
             acc.extend(decode_block(graph, sloth_iter, replica_id, data, i)?.into_bytes());
             Ok(acc)
         })
@@ -96,16 +165,39 @@ pub fn decode_domain_block<'a, H, G>(
 where
     H: Hasher,
     G: Graph<H>,
+{
+    decode_node(graph, sloth_iter, replica_id, data, v)
+}
+
+/// Decodes node `v`, reading only it and its parents from `nodes`, so that the replica needn't
+/// be held in memory (or copied) to decode a few of its nodes.
+pub fn decode_node<'a, H, G, S>(
+    graph: &'a G,
+    sloth_iter: usize,
+    replica_id: &'a H::Domain,
+    nodes: &'a S,
+    v: usize,
+) -> Result<H::Domain>
+where
+    H: Hasher,
+    G: Graph<H>,
+    S: NodeSource + ?Sized,
 {
     let parents = graph.parents(v);
 
-    let byte_data = data
-        .iter()
-        .flat_map(H::Domain::into_bytes)
-        .collect::<Vec<u8>>();
+    // Laid out as create_key_into lays out the key derivation input.
+    let mut ciphertexts = vec![0; NODE_SIZE * (parents.len() + 1)];
+    replica_id.write_bytes(&mut ciphertexts[0..NODE_SIZE])?;
+
+    if v != parents[0] {
+        for (i, parent) in parents.iter().enumerate() {
+            let start = (i + 1) * NODE_SIZE;
+            ciphertexts[start..start + NODE_SIZE].copy_from_slice(&nodes.read_node(*parent)?);
+        }
+    }
 
-    let key = create_key::<H>(replica_id, v, &parents, &byte_data, graph.degree())?;
-    let node_data = data[v];
+    let key = H::kdf(&ciphertexts, graph.degree());
+    let node_data = H::Domain::try_from_bytes(&nodes.read_node(v)?)?;
 
     // TODO: round constant
     Ok(H::sloth_decode(&key, &node_data, sloth_iter))
@@ -117,21 +209,44 @@ fn create_key<H: Hasher>(
     parents: &[usize],
     data: &[u8],
     m: usize,
+) -> Result<H::Domain> {
+    let mut ciphertexts = Vec::with_capacity(NODE_SIZE * (parents.len() + 1));
+
+    create_key_into::<H>(id, node, parents, data, m, &mut ciphertexts)
+}
+
+/// Derives the key for `node`, using `ciphertexts` as the buffer for the key
+/// derivation input. Whatever `ciphertexts` held before is overwritten.
+fn create_key_into<H: Hasher>(
+    id: &H::Domain,
+    node: usize,
+    parents: &[usize],
+    data: &[u8],
+    m: usize,
+    ciphertexts: &mut Vec<u8>,
 ) -> Result<H::Domain> {
     // ciphertexts will become a buffer of the layout
     // id | encodedParentNode1 | encodedParentNode1 | ...
+    debug_assert!(
+        node_size_matches::<H>(),
+        "nodes are not elements of the domain"
+    );
 
-    let mut ciphertexts = vec![0u8; 32 * (parents.len() + 1)];
-    id.write_bytes(&mut ciphertexts[0..32])?;
+    ciphertexts.resize(NODE_SIZE * (parents.len() + 1), 0);
+    id.write_bytes(&mut ciphertexts[0..NODE_SIZE])?;
 
     for (i, parent) in parents.iter().enumerate() {
+        let start = (i + 1) * NODE_SIZE;
+        let end = (i + 2) * NODE_SIZE;
+
         // special super shitty case
         // TODO: unsuck
         if node == parents[0] {
-            // skip, as we would only write 0s, but the vector is prefilled with 0.
+            // write 0s, as the buffer may still hold a previous node's parents.
+            for b in &mut ciphertexts[start..end] {
+                *b = 0;
+            }
         } else {
-            let start = (i + 1) * 32;
-            let end = (i + 2) * 32;
             ciphertexts[start..end].copy_from_slice(data_at_node(data, *parent)?);
         }
     }
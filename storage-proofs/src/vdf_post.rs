@@ -1,4 +1,5 @@
 use std::cmp;
+use std::collections::HashSet;
 use std::marker::PhantomData;
 
 use bitvec::{self, BitVec};
@@ -15,6 +16,7 @@ use crate::hasher::{Domain, HashFunction, Hasher};
 use crate::merkle::MerkleTree;
 use crate::parameter_cache::ParameterSetIdentifier;
 use crate::porc::{self, PoRC};
+use crate::post_timing::{self, PostPhase};
 use crate::proof::ProofScheme;
 use crate::vdf::Vdf;
 
@@ -118,6 +120,44 @@ pub struct Proof<'a, H: Hasher + 'a, V: Vdf<H::Domain>> {
     _v: PhantomData<V>,
 }
 
+/// What's wrong with a check of a VDF-PoSt which fails, as found by `VDFPoSt::diagnose`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailureKind {
+    /// The path proven for a challenge leads to a root other than the challenged sector's
+    /// commitment (e.g. as its replica has changed, or the commitments are out of order), or
+    /// isn't as long as the sector's tree is high.
+    PathMismatch,
+    /// The leaf proven for a challenge isn't the one challenged, doesn't hash up its path to its
+    /// root, or isn't among the leaves the VDF output of its epoch was computed from.
+    LeafMismatch,
+    /// The proof has no path for a challenge.
+    MissingProof,
+    /// A fault names no sector among the commitments, or a sector an earlier fault named.
+    FaultSetInconsistency,
+}
+
+impl From<porc::ChallengeFailure> for FailureKind {
+    fn from(failure: porc::ChallengeFailure) -> FailureKind {
+        match failure {
+            porc::ChallengeFailure::PathMismatch => FailureKind::PathMismatch,
+            porc::ChallengeFailure::LeafMismatch => FailureKind::LeafMismatch,
+            porc::ChallengeFailure::MissingProof => FailureKind::MissingProof,
+        }
+    }
+}
+
+/// A check of a VDF-PoSt which fails.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Failure {
+    /// The index, among the commitments, of the sector checked: the challenged sector, or the
+    /// one a fault names.
+    pub sector: usize,
+    /// The index of the challenge among all those of the proof, epoch after epoch, or None if
+    /// the check concerns no challenge.
+    pub challenge: Option<usize>,
+    pub kind: FailureKind,
+}
+
 #[derive(Clone, Debug)]
 pub struct VDFPoSt<H: Hasher, V: Vdf<H::Domain>> {
     _t: PhantomData<H>,
@@ -216,8 +256,10 @@ impl<'a, H: Hasher + 'a, V: Vdf<H::Domain>> ProofScheme<'a> for VDFPoSt<H, V> {
 
                 // Skip last VDF evaluation.
                 if i < post_epochs {
-                    let x = extract_vdf_input::<H>(&proof);
-                    let (y, vdf_proof) = V::eval(&pub_params.pub_params_vdf, &x)?;
+                    let (y, vdf_proof) = post_timing::time(PostPhase::Aggregation, || {
+                        let x = extract_vdf_input::<H>(&proof);
+                        V::eval(&pub_params.pub_params_vdf, &x)
+                    })?;
 
                     ys.push(y);
                     vdf_proofs.push(vdf_proof);
@@ -259,11 +301,14 @@ impl<'a, H: Hasher + 'a, V: Vdf<H::Domain>> ProofScheme<'a> for VDFPoSt<H, V> {
 
             // VDF Output Verification
             {
-                if !V::verify(
-                    &pub_params.pub_params_vdf,
-                    &extract_vdf_input::<H>(&proof.porep_proofs[i]),
-                    &proof.vdf_proofs[i],
-                )? {
+                let valid = post_timing::time(PostPhase::Aggregation, || {
+                    V::verify(
+                        &pub_params.pub_params_vdf,
+                        &extract_vdf_input::<H>(&proof.porep_proofs[i]),
+                        &proof.vdf_proofs[i],
+                    )
+                })?;
+                if !valid {
                     return Ok(false);
                 }
             }
@@ -300,9 +345,109 @@ impl<'a, H: Hasher + 'a, V: Vdf<H::Domain>> ProofScheme<'a> for VDFPoSt<H, V> {
     }
 }
 
+impl<H: Hasher, V: Vdf<H::Domain>> VDFPoSt<H, V> {
+    /// Checks a proof as `verify` does, but rather than stop at the first check to fail, returns
+    /// every one which does, for debugging proofs which don't verify: a proof verifies if none
+    /// do. It's slower than `verify`, which is to be used wherever only validity matters.
+    pub fn diagnose<'a>(
+        pub_params: &PublicParams<H::Domain, V>,
+        pub_inputs: &PublicInputs<H::Domain>,
+        proof: &Proof<'a, H, V>,
+    ) -> Result<Vec<Failure>> {
+        let post_epochs = pub_params.post_epochs;
+
+        let mut failures = fault_set_failures(pub_inputs.commitments.len(), &pub_inputs.faults);
+
+        let pub_params_porep = porc::PublicParams {
+            leaves: pub_params.leaves,
+            sectors_count: pub_params.sectors_count,
+        };
+
+        let mut mix = pub_inputs.challenge_seed;
+        let mut challenge_stream = ChallengeStream::<H, V>::new(pub_params);
+
+        let mut epoch = 0;
+        while let Some((challenges, challenged_sectors)) = challenge_stream.next(mix) {
+            if epoch >= post_epochs {
+                break;
+            }
+
+            // The challenges of each epoch are mixed with the VDF output of the one before, so
+            // without it, those of the rest can't be known.
+            let (y, vdf_proof) = match (proof.ys.get(epoch), proof.vdf_proofs.get(epoch)) {
+                (Some(y), Some(vdf_proof)) => (y, vdf_proof),
+                _ => return Err(Error::MalformedInput),
+            };
+
+            let first_challenge = epoch * pub_params.challenge_count;
+            let failure = |j: usize, kind: FailureKind| Failure {
+                sector: challenged_sectors[j],
+                challenge: Some(first_challenge + j),
+                kind,
+            };
+
+            match proof.porep_proofs.get(epoch) {
+                Some(porep_proof) => {
+                    let pub_inputs_porep = porc::PublicInputs {
+                        challenges: &challenges,
+                        challenged_sectors: &challenged_sectors,
+                        commitments: &pub_inputs.commitments,
+                    };
+
+                    let checks =
+                        PoRC::check_challenges(&pub_params_porep, &pub_inputs_porep, porep_proof);
+                    let vdf_valid = V::verify(
+                        &pub_params.pub_params_vdf,
+                        &extract_vdf_input::<H>(porep_proof),
+                        vdf_proof,
+                    )?;
+
+                    for (j, check) in checks.into_iter().enumerate() {
+                        match check {
+                            Some(kind) => failures.push(failure(j, kind.into())),
+                            None if !vdf_valid => {
+                                failures.push(failure(j, FailureKind::LeafMismatch))
+                            }
+                            None => {}
+                        }
+                    }
+                }
+                None => {
+                    for j in 0..challenges.len() {
+                        failures.push(failure(j, FailureKind::MissingProof));
+                    }
+                }
+            }
+
+            mix = *y;
+            epoch += 1;
+        }
+
+        Ok(failures)
+    }
+}
+
+// The faults which name no sector among sectors_count, or a sector an earlier fault named.
+fn fault_set_failures(sectors_count: usize, faults: &[u64]) -> Vec<Failure> {
+    let mut named = HashSet::new();
+    let mut failures = Vec::new();
+
+    for &fault in faults {
+        if fault >= sectors_count as u64 || !named.insert(fault) {
+            failures.push(Failure {
+                sector: fault as usize,
+                challenge: None,
+                kind: FailureKind::FaultSetInconsistency,
+            });
+        }
+    }
+
+    failures
+}
+
 pub fn extract_vdf_input<H: Hasher>(proof: &porc::Proof<H>) -> H::Domain {
     let leafs: Vec<u8> = proof.leafs().iter().fold(Vec::new(), |mut acc, leaf| {
-        acc.extend(leaf.as_ref());
+        acc.extend(leaf.into_bytes());
         acc
     });
 
@@ -580,4 +725,98 @@ mod tests {
         )
         .unwrap());
     }
+
+    #[test]
+    fn diagnose_finds_every_failed_check() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+        let sp = SetupParams::<PedersenDomain, vdf_sloth::Sloth> {
+            challenge_count: 30,
+            sector_size: 1024 * 32,
+            post_epochs: 3,
+            setup_params_vdf: vdf_sloth::SetupParams {
+                key: rng.gen(),
+                rounds: 1,
+            },
+            sectors_count: 2,
+        };
+
+        let pub_params = VDFPoSt::<PedersenHasher, vdf_sloth::Sloth>::setup(&sp).unwrap();
+
+        let trees: Vec<_> = (0..2)
+            .map(|_| {
+                let data: Vec<u8> = (0..1024)
+                    .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+                    .collect();
+
+                BucketGraph::<PedersenHasher>::new(1024, 5, 0, new_seed())
+                    .merkle_tree(data.as_slice())
+                    .unwrap()
+            })
+            .collect();
+
+        let pub_inputs = PublicInputs {
+            challenge_seed: rng.gen(),
+            commitments: vec![trees[0].root(), trees[1].root()],
+            faults: Vec::new(),
+        };
+        let tree_refs = [&trees[0], &trees[1]];
+        let priv_inputs = PrivateInputs::new(&tree_refs);
+
+        let mut proof = VDFPoSt::<PedersenHasher, vdf_sloth::Sloth>::prove(
+            &pub_params,
+            &pub_inputs,
+            &priv_inputs,
+        )
+        .unwrap();
+
+        let diagnose = |pub_inputs: &PublicInputs<PedersenDomain>,
+                        proof: &Proof<PedersenHasher, vdf_sloth::Sloth>| {
+            VDFPoSt::diagnose(&pub_params, pub_inputs, proof).unwrap()
+        };
+
+        assert_eq!(Vec::<Failure>::new(), diagnose(&pub_inputs, &proof));
+
+        // Every challenge is of the first sector (see derive_final_challenges), so with the
+        // commitments out of order, every one fails.
+        let reordered = PublicInputs {
+            commitments: vec![trees[1].root(), trees[0].root()],
+            ..pub_inputs.clone()
+        };
+        let failures = diagnose(&reordered, &proof);
+        assert_eq!(90, failures.len());
+        for (i, failure) in failures.iter().enumerate() {
+            assert_eq!((0, Some(i)), (failure.sector, failure.challenge));
+            assert_eq!(FailureKind::PathMismatch, failure.kind);
+        }
+
+        let faulty = PublicInputs {
+            faults: vec![1, 2, 1],
+            ..pub_inputs.clone()
+        };
+        let failures = diagnose(&faulty, &proof);
+        assert_eq!(
+            vec![(2, None), (1, None)],
+            failures
+                .iter()
+                .map(|failure| (failure.sector, failure.challenge))
+                .collect::<Vec<_>>()
+        );
+        assert!(failures
+            .iter()
+            .all(|failure| failure.kind == FailureKind::FaultSetInconsistency));
+
+        proof.porep_proofs.pop();
+        let failures = diagnose(&pub_inputs, &proof);
+        assert_eq!(
+            (60..90).map(Some).collect::<Vec<_>>(),
+            failures
+                .iter()
+                .map(|failure| failure.challenge)
+                .collect::<Vec<_>>()
+        );
+        assert!(failures
+            .iter()
+            .all(|failure| failure.kind == FailureKind::MissingProof));
+    }
 }
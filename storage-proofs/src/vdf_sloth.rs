@@ -3,6 +3,7 @@ use pairing::bls12_381::{Bls12, Fr};
 use crate::crypto::sloth;
 use crate::error::Result;
 use crate::hasher::pedersen::PedersenDomain;
+use crate::hasher::Domain;
 use crate::parameter_cache::ParameterSetIdentifier;
 use crate::vdf::Vdf;
 
@@ -64,7 +65,7 @@ impl Vdf<PedersenDomain> for Sloth {
         let key: Fr = pp.key.into();
         let decoded: PedersenDomain = sloth::decode::<Bls12>(&key, &y, pp.rounds).into();
 
-        Ok(&decoded == x)
+        Ok(decoded.ct_eq(x))
     }
 
     fn key(pp: &self::PublicParams) -> PedersenDomain {
@@ -0,0 +1,109 @@
+//! A hook the host may install, which the long CPU-bound loops of sealing and unsealing call at
+//! bounded intervals: once every `yield_interval()` nodes encoded, decoded or hashed into a tree,
+//! and between the layers of a proof. A host whose runtime schedules its threads cooperatively
+//! (e.g. Go's, which copes poorly with a C call holding a thread for hours) may yield to it
+//! there, report progress, or check whether the operation is still wanted.
+//!
+//! The hook returns whether the operation is to go on. Once it returns false, the loop which
+//! called it fails with `Error::YieldCancelled`, naming the phase it was in, so the operation
+//! stops within an interval of the refusal. filecoin-proofs turns that error into the one a
+//! cancelled token gives (see its cancellation module).
+//!
+//! The work counted towards the next call is shared by every loop of every thread, so the hook
+//! is called about as often however many operations run at once. Without a hook, a yield point
+//! costs a relaxed atomic load.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::RwLock;
+
+use crate::error::{Error, Result};
+use crate::failpoint;
+
+/// The phases which call the hook, as named to it and in the errors of those it stops.
+pub const ENCODING: &str = "encoding";
+pub const BUILDING_TREES: &str = "building trees";
+pub const DECODING: &str = "decoding";
+pub const PROVING: &str = "proving";
+
+/// The nodes worked on between two calls of the hook, unless set_yield_interval sets another
+/// interval.
+pub const DEFAULT_YIELD_INTERVAL: usize = 4096;
+
+/// Called at each yield point with the phase of the operation which reached it. Returns whether
+/// the operation is to go on.
+pub type YieldHook = Box<Fn(&str) -> bool + Send + Sync>;
+
+lazy_static! {
+    // Held for reading while the hook is called, so that replacing the hook waits for every
+    // call of it to return.
+    static ref YIELD_HOOK: RwLock<Option<YieldHook>> = RwLock::new(None);
+}
+
+// Whether YIELD_HOOK holds a hook, so that yield points needn't take its lock to find out.
+static INSTALLED: AtomicBool = AtomicBool::new(false);
+
+// The interval set by set_yield_interval, or 0 for the default.
+static INTERVAL: AtomicUsize = AtomicUsize::new(0);
+
+// The nodes worked on since the hook was installed, by every loop.
+static WORK: AtomicUsize = AtomicUsize::new(0);
+
+/// Calls hook at every yield point, in place of the hook installed before (if any), or stops
+/// calling any if None. Once this returns, the hook replaced is never called again.
+pub fn set_yield_hook(hook: Option<YieldHook>) {
+    let mut installed = YIELD_HOOK.write().unwrap_or_else(|p| p.into_inner());
+
+    INSTALLED.store(hook.is_some(), Ordering::Relaxed);
+    WORK.store(0, Ordering::Relaxed);
+    *installed = hook;
+}
+
+/// Calls the hook once every `nodes` nodes worked on, or once every DEFAULT_YIELD_INTERVAL if
+/// `nodes` is 0.
+pub fn set_yield_interval(nodes: usize) {
+    INTERVAL.store(nodes, Ordering::Relaxed);
+}
+
+/// The nodes worked on between two calls of the hook.
+pub fn yield_interval() -> usize {
+    match INTERVAL.load(Ordering::Relaxed) {
+        0 => DEFAULT_YIELD_INTERVAL,
+        nodes => nodes,
+    }
+}
+
+/// Counts `nodes` more nodes worked on in `phase`, calling the hook if that completes an
+/// interval. Fails if the hook asks the operation to stop.
+pub fn yield_point(phase: &str, nodes: usize) -> Result<()> {
+    if !INSTALLED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    let interval = yield_interval();
+    let before = WORK.fetch_add(nodes, Ordering::Relaxed);
+
+    if before % interval + nodes < interval {
+        return Ok(());
+    }
+
+    yield_now(phase)
+}
+
+/// Calls the hook, if one is installed, whatever the work done since it was last called. Fails if
+/// it asks the operation to stop.
+pub fn yield_now(phase: &str) -> Result<()> {
+    if !INSTALLED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    fail_point!(failpoint::YIELD_HOOK_CALL);
+
+    let hook = YIELD_HOOK.read().unwrap_or_else(|p| p.into_inner());
+
+    match *hook {
+        Some(ref hook) if !hook(phase) => Err(Error::YieldCancelled {
+            phase: phase.to_string(),
+        }),
+        _ => Ok(()),
+    }
+}
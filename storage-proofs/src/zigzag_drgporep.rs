@@ -58,14 +58,20 @@ mod tests {
 
     use pairing::bls12_381::Bls12;
     use rand::{Rng, SeedableRng, XorShiftRng};
+    use std::cmp;
+    use std::io::Write;
+    use tempfile;
 
-    use crate::drgraph::new_seed;
+    use crate::crypto::feistel::FeistelConfig;
+    use crate::drgraph::{graph_height, new_seed};
     use crate::fr32::fr_into_bytes;
     use crate::hasher::{Blake2sHasher, PedersenHasher, Sha256Hasher};
     use crate::layered_drgporep::{
-        LayerChallenges, PrivateInputs, PublicInputs, PublicParams, SetupParams,
+        challenge_seed, LayerChallenges, PrivateInputs, Proof, PublicInputs, PublicParams,
+        SetupParams,
     };
-    use crate::porep::PoRep;
+    use crate::node_source::{FileNodeSource, NodeSource};
+    use crate::porep::{self, PoRep};
     use crate::proof::ProofScheme;
 
     const DEFAULT_ZIGZAG_LAYERS: usize = 10;
@@ -102,6 +108,7 @@ mod tests {
                     degree: 5,
                     expansion_degree: 8,
                     seed: new_seed(),
+                    feistel: FeistelConfig::default(),
                 },
                 sloth_iter,
             },
@@ -134,6 +141,89 @@ mod tests {
         assert_eq!(data, decoded_data);
     }
 
+    #[test]
+    fn extract_range_pedersen() {
+        test_extract_range::<PedersenHasher>(4);
+    }
+
+    #[test]
+    fn extract_range_sha256() {
+        test_extract_range::<Sha256Hasher>(50);
+    }
+
+    #[test]
+    fn extract_range_blake2s() {
+        test_extract_range::<Blake2sHasher>(50);
+    }
+
+    fn test_extract_range<H: 'static + Hasher>(ranges: usize) {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let nodes = 256;
+        let layers = 4;
+        let replica_id: H::Domain = rng.gen();
+        let data: Vec<u8> = (0..nodes)
+            .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+            .collect();
+
+        let sp = SetupParams {
+            drg_porep_setup_params: drgporep::SetupParams {
+                drg: drgporep::DrgParams {
+                    nodes,
+                    degree: 5,
+                    expansion_degree: 8,
+                    seed: new_seed(),
+                    feistel: FeistelConfig::default(),
+                },
+                sloth_iter: 1,
+            },
+            layer_challenges: LayerChallenges::new_fixed(layers, 1),
+        };
+
+        let pp = ZigZagDrgPoRep::<H>::setup(&sp).unwrap();
+        let mut replica = data.clone();
+        ZigZagDrgPoRep::<H>::replicate(&pp, &replica_id, &mut replica, None).unwrap();
+
+        let extracted = ZigZagDrgPoRep::<H>::extract_all(&pp, &replica_id, &replica).unwrap();
+        assert_eq!(data, extracted);
+
+        for _ in 0..ranges {
+            let start = rng.gen_range(0, nodes);
+            let end = cmp::min(start + rng.gen_range(1, 9), nodes);
+            let expected = &extracted[start * 32..end * 32];
+
+            // Decoding the cone, however wide it gets...
+            let cone = ZigZagDrgPoRep::<H>::extract_cone_and_invert_transform_layers(
+                &pp.drg_porep_public_params,
+                layers,
+                &replica_id,
+                &replica,
+                start..end,
+                nodes,
+            )
+            .unwrap()
+            .expect("a cone can't hold more than every node");
+            assert_eq!(expected, &cone[..], "nodes {}..{}", start, end);
+
+            // ...and when falling back to decoding every node.
+            let range =
+                ZigZagDrgPoRep::<H>::extract_range(&pp, &replica_id, &replica, start..end).unwrap();
+            assert_eq!(expected, &range[..], "nodes {}..{}", start, end);
+        }
+
+        // Decoding even a single node needs its parents from the layer before.
+        let node = nodes / 2;
+        assert!(ZigZagDrgPoRep::<H>::extract_cone_and_invert_transform_layers(
+            &pp.drg_porep_public_params,
+            layers,
+            &replica_id,
+            &replica,
+            node..node + 1,
+            1,
+        )
+        .unwrap()
+        .is_none());
+    }
+
     fn prove_verify_fixed(n: usize, i: usize) {
         let challenges = LayerChallenges::new_fixed(DEFAULT_ZIGZAG_LAYERS, 5);
 
@@ -150,11 +240,28 @@ mod tests {
         test_prove_verify::<Blake2sHasher>(n, i, challenges.clone());
     }
 
+    #[test]
+    fn prove_verify_no_expansion() {
+        // Without expansion edges, zigzag degrades to layered plain DRGs.
+        let challenges = LayerChallenges::new_fixed(DEFAULT_ZIGZAG_LAYERS, 5);
+
+        test_prove_verify_degrees::<PedersenHasher>(5, 3, 0, challenges.clone());
+        test_prove_verify_degrees::<Sha256Hasher>(5, 3, 0, challenges.clone());
+        test_prove_verify_degrees::<Blake2sHasher>(5, 3, 0, challenges.clone());
+    }
+
     fn test_prove_verify<H: 'static + Hasher>(n: usize, i: usize, challenges: LayerChallenges) {
+        test_prove_verify_degrees::<H>(n, 1 + i, i, challenges);
+    }
+
+    fn test_prove_verify_degrees<H: 'static + Hasher>(
+        n: usize,
+        degree: usize,
+        expansion_degree: usize,
+        challenges: LayerChallenges,
+    ) {
         let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
 
-        let degree = 1 + i;
-        let expansion_degree = i;
         let sloth_iter = 1;
         let replica_id: H::Domain = rng.gen();
         let data: Vec<u8> = (0..n)
@@ -171,6 +278,7 @@ mod tests {
                     degree,
                     expansion_degree,
                     seed: new_seed(),
+                    feistel: FeistelConfig::default(),
                 },
                 sloth_iter,
             },
@@ -185,6 +293,7 @@ mod tests {
 
         let pub_inputs = PublicInputs::<H::Domain> {
             replica_id,
+            challenge_seed: challenge_seed(&replica_id, &tau.comm_r_star),
             tau: Some(tau.simplify().into()),
             comm_r_star: tau.comm_r_star,
             k: None,
@@ -203,6 +312,305 @@ mod tests {
             ZigZagDrgPoRep::<H>::verify_all_partitions(&pp, &pub_inputs, all_partition_proofs)
                 .unwrap()
         );
+
+        // Proofs are as large as projected, save for the expansion parents which their
+        // challenged nodes lack: a node's expansion parents are only those on one side of it.
+        let projected = Proof::<H>::projected_size(&pp);
+        let height = graph_height(n);
+        let parent_bytes = 4 + drgporep::DataProof::<H>::projected_size(height);
+        let tolerance = challenges.total_challenges() * expansion_degree * parent_bytes;
+
+        for proof in all_partition_proofs {
+            let size = proof.serialized_size();
+            assert_eq!(size, proof.serialize().len());
+            assert!(size <= projected, "{} bytes, projected {}", size, projected);
+            assert!(projected - size <= tolerance, "{} bytes, projected {}", size, projected);
+        }
+    }
+
+    fn test_prove_verify_seeded<H: 'static + Hasher>() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let n = 16;
+
+        let replica_id: H::Domain = rng.gen();
+        let data: Vec<u8> = (0..n)
+            .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+            .collect();
+
+        for layers in &[1, 2, 4] {
+            for count in &[1, 3] {
+                let sp = SetupParams {
+                    drg_porep_setup_params: drgporep::SetupParams {
+                        drg: drgporep::DrgParams {
+                            nodes: n,
+                            degree: 3,
+                            expansion_degree: 2,
+                            seed: new_seed(),
+                            feistel: FeistelConfig::default(),
+                        },
+                        sloth_iter: 1,
+                    },
+                    layer_challenges: LayerChallenges::new_fixed(*layers, *count),
+                };
+
+                let pp = ZigZagDrgPoRep::<H>::setup(&sp).unwrap();
+                let mut data_copy = data.clone();
+                let (tau, aux) =
+                    ZigZagDrgPoRep::<H>::replicate(&pp, &replica_id, data_copy.as_mut_slice(), None)
+                        .unwrap();
+
+                let pub_inputs = PublicInputs::<H::Domain> {
+                    replica_id,
+                    challenge_seed: rng.gen(),
+                    tau: Some(tau.simplify().into()),
+                    comm_r_star: tau.comm_r_star,
+                    k: None,
+                };
+                let priv_inputs = PrivateInputs {
+                    aux,
+                    tau: tau.layer_taus,
+                };
+
+                let proofs =
+                    ZigZagDrgPoRep::<H>::prove_all_partitions(&pp, &pub_inputs, &priv_inputs, 2)
+                        .unwrap();
+                assert!(
+                    ZigZagDrgPoRep::<H>::verify_all_partitions(&pp, &pub_inputs, &proofs).unwrap(),
+                    "failed to verify {} layers of {} challenges",
+                    layers,
+                    count
+                );
+
+                // Proofs missing the tau of a layer are refused, rather than indexed past.
+                let mut truncated = proofs.clone();
+                truncated[1].tau.pop();
+                assert!(
+                    !ZigZagDrgPoRep::<H>::verify_all_partitions(&pp, &pub_inputs, &truncated)
+                        .unwrap()
+                );
+
+                // The proofs are of the seed's challenges, and no others.
+                let other_seed = PublicInputs {
+                    challenge_seed: rng.gen(),
+                    ..pub_inputs.clone()
+                };
+                let same_challenges = (0..*layers).all(|layer| {
+                    (0..2).all(|k| {
+                        let challenges = |p: &PublicInputs<H::Domain>| {
+                            p.challenges(&pp.layer_challenges, n, layer as u8, Some(k))
+                        };
+                        challenges(&pub_inputs) == challenges(&other_seed)
+                    })
+                });
+                if !same_challenges {
+                    assert!(
+                        !ZigZagDrgPoRep::<H>::verify_all_partitions(&pp, &other_seed, &proofs)
+                            .unwrap()
+                    );
+                }
+            }
+        }
+    }
+
+    // Proves every layer the way prove_layers did before it borrowed the trees: with a copy of
+    // each layer's trees, and params transformed after every layer.
+    fn prove_layers_reference<H: 'static + Hasher>(
+        pp: &PublicParams<H, ZigZagBucketGraph<H>>,
+        pub_inputs: &PublicInputs<H::Domain>,
+        priv_inputs: &PrivateInputs<H>,
+        k: usize,
+    ) -> Vec<drgporep::Proof<H>> {
+        let layers = pp.layer_challenges.layers();
+        let mut layer_pp = pp.drg_porep_public_params.clone();
+
+        (0..layers)
+            .map(|layer| {
+                let aux = porep::ProverAux {
+                    tree_d: priv_inputs.aux[layer].clone(),
+                    tree_r: priv_inputs.aux[layer + 1].clone(),
+                };
+                let drgporep_pub_inputs = drgporep::PublicInputs {
+                    replica_id: pub_inputs.replica_id,
+                    challenge_seed: pub_inputs.challenge_seed,
+                    challenge_count: pp.layer_challenges.challenges_for_layer(layer),
+                    k: Some(k),
+                    tau: Some(priv_inputs.tau[layer]),
+                };
+
+                let proof = drgporep::DrgPoRep::prove_at_layer(
+                    &layer_pp,
+                    &drgporep_pub_inputs,
+                    &drgporep::PrivateInputs::new(&aux),
+                    layer,
+                )
+                .unwrap();
+                layer_pp = zigzag(&layer_pp);
+
+                proof
+            })
+            .collect()
+    }
+
+    fn test_prove_layers_matches_reference<H: 'static + Hasher>() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let n = 16;
+        let partitions = 2;
+
+        let replica_id: H::Domain = rng.gen();
+        let data: Vec<u8> = (0..n)
+            .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+            .collect();
+
+        for layers in &[1, 2, 4] {
+            let sp = SetupParams {
+                drg_porep_setup_params: drgporep::SetupParams {
+                    drg: drgporep::DrgParams {
+                        nodes: n,
+                        degree: 3,
+                        expansion_degree: 2,
+                        seed: new_seed(),
+                        feistel: FeistelConfig::default(),
+                    },
+                    sloth_iter: 1,
+                },
+                layer_challenges: LayerChallenges::new_tapered(*layers, 3, 2, 1.0 / 3.0),
+            };
+
+            let pp = ZigZagDrgPoRep::<H>::setup(&sp).unwrap();
+            let mut data_copy = data.clone();
+            let (tau, aux) =
+                ZigZagDrgPoRep::<H>::replicate(&pp, &replica_id, data_copy.as_mut_slice(), None)
+                    .unwrap();
+
+            let pub_inputs = PublicInputs::<H::Domain> {
+                replica_id,
+                challenge_seed: challenge_seed(&replica_id, &tau.comm_r_star),
+                tau: Some(tau.simplify()),
+                comm_r_star: tau.comm_r_star,
+                k: None,
+            };
+            let priv_inputs = PrivateInputs {
+                aux,
+                tau: tau.layer_taus,
+            };
+
+            let proofs = ZigZagDrgPoRep::<H>::prove_all_partitions(
+                &pp,
+                &pub_inputs,
+                &priv_inputs,
+                partitions,
+            )
+            .unwrap();
+            assert_eq!(partitions, proofs.len());
+
+            for (k, proof) in proofs.iter().enumerate() {
+                let expected = prove_layers_reference(&pp, &pub_inputs, &priv_inputs, k);
+
+                assert_eq!(
+                    serde_json::to_string(&expected).unwrap(),
+                    serde_json::to_string(&proof.encoding_proofs).unwrap(),
+                    "proofs of partition {} of {} layers differ",
+                    k,
+                    layers
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn prove_layers_matches_reference() {
+        test_prove_layers_matches_reference::<PedersenHasher>();
+        test_prove_layers_matches_reference::<Sha256Hasher>();
+        test_prove_layers_matches_reference::<Blake2sHasher>();
+    }
+
+    fn test_prove_layers_from_file<H: 'static + Hasher>() {
+        let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+        let n = 64;
+        let degree = 3;
+        let expansion_degree = 2;
+        let partitions = 2;
+        let layers = 3;
+
+        let replica_id: H::Domain = rng.gen();
+        let mut replica: Vec<u8> = (0..n)
+            .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+            .collect();
+
+        let sp = SetupParams {
+            drg_porep_setup_params: drgporep::SetupParams {
+                drg: drgporep::DrgParams {
+                    nodes: n,
+                    degree,
+                    expansion_degree,
+                    seed: new_seed(),
+                    feistel: FeistelConfig::default(),
+                },
+                sloth_iter: 1,
+            },
+            layer_challenges: LayerChallenges::new_fixed(layers, 4),
+        };
+
+        let pp = ZigZagDrgPoRep::<H>::setup(&sp).unwrap();
+        let (tau, aux) =
+            ZigZagDrgPoRep::<H>::replicate(&pp, &replica_id, replica.as_mut_slice(), None).unwrap();
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&replica).unwrap();
+        let source = FileNodeSource::new(file, 0, 16);
+
+        let pub_inputs = PublicInputs::<H::Domain> {
+            replica_id,
+            challenge_seed: challenge_seed(&replica_id, &tau.comm_r_star),
+            tau: Some(tau.simplify()),
+            comm_r_star: tau.comm_r_star,
+            k: None,
+        };
+        let prove = |replica: Option<&NodeSource>| {
+            ZigZagDrgPoRep::<H>::prove_layers(
+                &pp.drg_porep_public_params,
+                &pub_inputs,
+                &tau.layer_taus,
+                &aux,
+                replica,
+                &pp.layer_challenges,
+                partitions,
+            )
+            .unwrap()
+        };
+
+        assert_eq!(
+            serde_json::to_string(&prove(None)).unwrap(),
+            serde_json::to_string(&prove(Some(&source))).unwrap(),
+        );
+
+        // Every challenged node and each of its parents is read twice: once to be proven, and
+        // again to decode the node. The cache keeps the second reads (and those of parents
+        // shared between challenges) off the disk.
+        let nodes_read = partitions
+            * pp.layer_challenges.challenges_for_layer(layers - 1)
+            * (1 + degree + expansion_degree);
+        assert!(source.reads() > 0);
+        assert!(
+            source.reads() <= nodes_read as u64,
+            "read {} nodes from disk, for {} challenged nodes and their parents",
+            source.reads(),
+            nodes_read
+        );
+    }
+
+    #[test]
+    fn prove_layers_from_file() {
+        test_prove_layers_from_file::<PedersenHasher>();
+        test_prove_layers_from_file::<Sha256Hasher>();
+        test_prove_layers_from_file::<Blake2sHasher>();
+    }
+
+    #[test]
+    fn prove_verify_seeded() {
+        test_prove_verify_seeded::<PedersenHasher>();
+        test_prove_verify_seeded::<Sha256Hasher>();
+        test_prove_verify_seeded::<Blake2sHasher>();
     }
 
     table_tests! {
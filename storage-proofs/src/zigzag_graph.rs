@@ -1,6 +1,7 @@
 use std::marker::PhantomData;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use crate::crypto::feistel::{self, FeistelPrecomputed};
+use crate::crypto::feistel::{self, FeistelConfig, FeistelPrecomputed};
 use crate::drgraph::{BucketGraph, Graph};
 use crate::hasher::Hasher;
 use crate::layered_drgporep::Layerable;
@@ -8,6 +9,15 @@ use crate::parameter_cache::ParameterSetIdentifier;
 
 pub const DEFAULT_EXPANSION_DEGREE: usize = 8;
 
+// Counts the graphs built by ZigZagGraph::new, so that callers which keep
+// graphs around can test that they don't build them again.
+static GRAPHS_CONSTRUCTED: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns the number of zigzag graphs built by this process so far.
+pub fn graphs_constructed() -> usize {
+    GRAPHS_CONSTRUCTED.load(Ordering::SeqCst)
+}
+
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct ZigZagGraph<H, G>
 where
@@ -17,6 +27,7 @@ where
     expansion_degree: usize,
     base_graph: G,
     pub reversed: bool,
+    feistel: FeistelConfig,
     feistel_precomputed: FeistelPrecomputed,
     _h: PhantomData<H>,
 }
@@ -41,7 +52,10 @@ where
         base_degree: usize,
         expansion_degree: usize,
         seed: [u32; 7],
+        feistel: FeistelConfig,
     ) -> Self {
+        GRAPHS_CONSTRUCTED.fetch_add(1, Ordering::SeqCst);
+
         ZigZagGraph {
             base_graph: match base_graph {
                 Some(graph) => graph,
@@ -49,21 +63,53 @@ where
             },
             expansion_degree,
             reversed: false,
-            feistel_precomputed: feistel::precompute((expansion_degree * nodes) as u32),
+            feistel,
+            feistel_precomputed: feistel_precompute(expansion_degree, nodes),
             _h: PhantomData,
         }
     }
 }
 
+// An expansion degree of 0 leaves nothing to permute, so that configuration
+// (which reduces the zigzag graph to its base DRG) skips the precomputation.
+fn feistel_precompute(expansion_degree: usize, nodes: usize) -> FeistelPrecomputed {
+    if expansion_degree == 0 {
+        Default::default()
+    } else {
+        feistel::precompute((expansion_degree * nodes) as u32)
+    }
+}
+
 impl<H, G> ParameterSetIdentifier for ZigZagGraph<H, G>
 where
     H: Hasher,
     G: Graph<H> + ParameterSetIdentifier,
 {
     fn parameter_set_identifier(&self) -> String {
+        if self.expansion_degree == 0 {
+            return format!(
+                "zigzag_graph::ZigZagGraph{{no expansion; base_graph: {} }}",
+                self.base_graph.parameter_set_identifier()
+            );
+        }
+
+        // The protocol's keys and rounds are left out, so that the identifiers
+        // of graphs using them, and the parameters and sectors keyed by those,
+        // are those of graphs built before the keys could be configured.
+        if self.feistel == FeistelConfig::default() {
+            return format!(
+                "zigzag_graph::ZigZagGraph{{expansion_degree: {} base_graph: {} }}",
+                self.expansion_degree,
+                self.base_graph.parameter_set_identifier()
+            );
+        }
+
         format!(
-            "zigzag_graph::ZigZagGraph{{expansion_degree: {} base_graph: {} }}",
+            "zigzag_graph::ZigZagGraph{{expansion_degree: {} feistel_keys: {:?} \
+             feistel_rounds: {} base_graph: {} }}",
             self.expansion_degree,
+            self.feistel.keys,
+            self.feistel.rounds,
             self.base_graph.parameter_set_identifier()
         )
     }
@@ -82,12 +128,33 @@ pub trait ZigZag: ::std::fmt::Debug + Clone + PartialEq + Eq {
     fn expansion_degree(&self) -> usize;
     fn reversed(&self) -> bool;
     fn expanded_parents(&self, node: usize) -> Vec<usize>;
+    /// Appends the expansion parents of `node` to `parents`.
+    fn expanded_parents_into(&self, node: usize, parents: &mut Vec<usize>);
     fn real_index(&self, i: usize) -> usize;
+    /// Returns the keys and rounds the expansion edges are permuted with.
+    fn feistel(&self) -> &FeistelConfig;
     fn new_zigzag(
         nodes: usize,
         base_degree: usize,
         expansion_degree: usize,
         seed: [u32; 7],
+    ) -> Self {
+        Self::new_zigzag_with_feistel(
+            nodes,
+            base_degree,
+            expansion_degree,
+            seed,
+            FeistelConfig::default(),
+        )
+    }
+    /// Constructs a new graph whose expansion edges are permuted with the given
+    /// keys and rounds, rather than the protocol's.
+    fn new_zigzag_with_feistel(
+        nodes: usize,
+        base_degree: usize,
+        expansion_degree: usize,
+        seed: [u32; 7],
+        feistel: FeistelConfig,
     ) -> Self;
 }
 
@@ -102,22 +169,26 @@ impl<Z: ZigZag> Graph<Z::BaseHasher> for Z {
 
     #[inline]
     fn parents(&self, raw_node: usize) -> Vec<usize> {
+        let mut parents = Vec::with_capacity(self.degree());
+        self.parents_into(raw_node, &mut parents);
+
+        parents
+    }
+
+    #[inline]
+    fn parents_into(&self, raw_node: usize, parents: &mut Vec<usize>) {
         // If graph is reversed, use real_index to convert index to reversed index.
         // So we convert a raw reversed node to an unreversed node, calculate its parents,
         // then convert the parents to reversed.
 
-        let drg_parents = self
-            .base_graph()
-            .parents(self.real_index(raw_node))
-            .iter()
-            .map(|i| self.real_index(*i))
-            .collect::<Vec<_>>();
+        self.base_graph().parents_into(self.real_index(raw_node), parents);
 
-        let mut parents = drg_parents;
-        // expanded_parents takes raw_node
-        let expanded_parents = self.expanded_parents(raw_node);
+        for parent in parents.iter_mut() {
+            *parent = self.real_index(*parent);
+        }
 
-        parents.extend(expanded_parents.iter());
+        // expanded_parents takes raw_node
+        self.expanded_parents_into(raw_node, parents);
 
         // Pad so all nodes have correct degree.
         for _ in 0..(self.degree() - parents.len()) {
@@ -128,15 +199,13 @@ impl<Z: ZigZag> Graph<Z::BaseHasher> for Z {
             }
         }
         assert!(parents.len() == self.degree());
-        parents.sort();
+        parents.sort_unstable();
 
         assert!(parents.iter().all(|p| if self.forward() {
             *p <= raw_node
         } else {
             *p >= raw_node
         }));
-
-        parents
     }
 
     fn seed(&self) -> [u32; 7] {
@@ -147,6 +216,16 @@ impl<Z: ZigZag> Graph<Z::BaseHasher> for Z {
         Z::new_zigzag(nodes, base_degree, expansion_degree, seed)
     }
 
+    fn new_with_feistel(
+        nodes: usize,
+        base_degree: usize,
+        expansion_degree: usize,
+        seed: [u32; 7],
+        feistel: &FeistelConfig,
+    ) -> Self {
+        Z::new_zigzag_with_feistel(nodes, base_degree, expansion_degree, seed, feistel.clone())
+    }
+
     fn forward(&self) -> bool {
         !self.reversed()
     }
@@ -159,20 +238,21 @@ where
 {
     fn correspondent(&self, node: usize, i: usize) -> usize {
         let a = (node * self.expansion_degree) as u32 + i as u32;
-        let feistel_keys = &[1, 2, 3, 4];
 
         let transformed = if self.reversed {
             feistel::invert_permute(
                 self.size() as u32 * self.expansion_degree as u32,
                 a,
-                feistel_keys,
+                &self.feistel.keys,
+                self.feistel.rounds,
                 self.feistel_precomputed,
             )
         } else {
             feistel::permute(
                 self.size() as u32 * self.expansion_degree as u32,
                 a,
-                feistel_keys,
+                &self.feistel.keys,
+                self.feistel.rounds,
                 self.feistel_precomputed,
             )
         };
@@ -188,13 +268,14 @@ where
     type BaseHasher = H;
     type BaseGraph = G;
 
-    fn new_zigzag(
+    fn new_zigzag_with_feistel(
         nodes: usize,
         base_degree: usize,
         expansion_degree: usize,
         seed: [u32; 7],
+        feistel: FeistelConfig,
     ) -> Self {
-        Self::new(None, nodes, base_degree, expansion_degree, seed)
+        Self::new(None, nodes, base_degree, expansion_degree, seed, feistel)
     }
 
     /// To zigzag a graph, we just toggle its reversed field.
@@ -204,7 +285,8 @@ where
             base_graph: self.base_graph.clone(),
             expansion_degree: self.expansion_degree,
             reversed: !self.reversed,
-            feistel_precomputed: feistel::precompute((self.expansion_degree * self.size()) as u32),
+            feistel: self.feistel.clone(),
+            feistel_precomputed: feistel_precompute(self.expansion_degree, self.size()),
             _h: PhantomData,
         }
     }
@@ -221,24 +303,38 @@ where
         self.reversed
     }
 
+    fn feistel(&self) -> &FeistelConfig {
+        &self.feistel
+    }
+
     #[inline]
     fn expanded_parents(&self, node: usize) -> Vec<usize> {
-        (0..self.expansion_degree)
-            .filter_map(|i| {
-                let other = self.correspondent(node, i);
-                if self.reversed {
-                    if other > node {
-                        Some(other)
-                    } else {
-                        None
-                    }
-                } else if other < node {
+        let mut parents = Vec::with_capacity(self.expansion_degree);
+        self.expanded_parents_into(node, &mut parents);
+
+        parents
+    }
+
+    #[inline]
+    fn expanded_parents_into(&self, node: usize, parents: &mut Vec<usize>) {
+        if self.expansion_degree == 0 {
+            return;
+        }
+
+        parents.extend((0..self.expansion_degree).filter_map(|i| {
+            let other = self.correspondent(node, i);
+            if self.reversed {
+                if other > node {
                     Some(other)
                 } else {
                     None
                 }
-            })
-            .collect()
+            } else if other < node {
+                Some(other)
+            } else {
+                None
+            }
+        }));
     }
 
     #[inline]
@@ -323,6 +419,37 @@ mod tests {
         test_expansion::<Blake2sHasher>();
     }
 
+    #[test]
+    fn no_expansion_matches_base_graph() {
+        let g = ZigZagBucketGraph::<PedersenHasher>::new_zigzag(50, 5, 0, new_seed());
+        let gz = g.zigzag();
+        let base = g.base_graph();
+
+        assert_eq!(base.degree(), g.degree());
+        assert_ne!(
+            g.parameter_set_identifier(),
+            ZigZagBucketGraph::<PedersenHasher>::new_zigzag(50, 5, 1, g.seed())
+                .parameter_set_identifier()
+        );
+
+        for i in 0..g.size() {
+            assert!(g.expanded_parents(i).is_empty());
+            assert!(gz.expanded_parents(i).is_empty());
+
+            assert_eq!(base.parents(i), g.parents(i));
+
+            // The reversed graph's parents are the base graph's, reversed.
+            let mut expected: Vec<usize> = base
+                .parents(gz.real_index(i))
+                .iter()
+                .map(|p| gz.real_index(*p))
+                .collect();
+            expected.sort();
+
+            assert_eq!(expected, gz.parents(i));
+        }
+    }
+
     fn test_expansion<H: 'static + Hasher>() {
         // We need a graph.
         let g = ZigZagBucketGraph::<H>::new_zigzag(25, 5, DEFAULT_EXPANSION_DEGREE, new_seed());
@@ -365,4 +492,74 @@ mod tests {
         // Having checked both ways, we know the graph and its zigzag counterpart have 'expanded' components
         // which are each other's inverses. It's important that this be true.
     }
+
+    #[test]
+    fn default_expanded_parents_are_pinned() {
+        // The seed only shapes the base graph, so the expansion is the same for
+        // any seed.
+        let g = ZigZagBucketGraph::<PedersenHasher>::new_zigzag(16, 5, 8, [7; 7]);
+
+        let expected: [&[usize]; 16] = [
+            &[],
+            &[],
+            &[1],
+            &[1, 0, 0],
+            &[2, 3, 3],
+            &[],
+            &[4],
+            &[5, 2, 1, 4, 5],
+            &[0, 1, 5, 3],
+            &[1, 0, 5, 8, 6],
+            &[0, 2, 2, 7],
+            &[7, 0, 3, 5, 8],
+            &[2, 2, 5, 0, 10, 5, 0],
+            &[6, 6, 3, 12, 9, 3],
+            &[2, 4, 12, 1, 9, 7, 6],
+            &[4, 6, 9, 2, 5, 7, 14],
+        ];
+
+        for (i, parents) in expected.iter().enumerate() {
+            assert_eq!(parents.to_vec(), g.expanded_parents(i), "node {}", i);
+        }
+
+        assert_eq!(&FeistelConfig::default(), g.feistel());
+        assert_eq!(
+            "zigzag_graph::ZigZagGraph{expansion_degree: 8 \
+             base_graph: drgraph::BucketGraph{size: 16; degree: 5} }",
+            g.parameter_set_identifier()
+        );
+    }
+
+    #[test]
+    fn feistel_keys_and_rounds_shape_the_expansion() {
+        let seed = new_seed();
+        let default = ZigZagBucketGraph::<PedersenHasher>::new_zigzag(16, 5, 8, seed);
+        let other = |keys: Vec<u32>, rounds| {
+            ZigZagBucketGraph::<PedersenHasher>::new_zigzag_with_feistel(
+                16,
+                5,
+                8,
+                seed,
+                FeistelConfig::new(keys, rounds),
+            )
+        };
+
+        let expanded = |g: &ZigZagBucketGraph<PedersenHasher>| {
+            (0..g.size()).map(|i| g.expanded_parents(i)).collect::<Vec<_>>()
+        };
+
+        // Keys past the rounds are unused, as they always were.
+        assert_eq!(default, other(vec![1, 2, 3, 4], 3));
+        assert_eq!(expanded(&default), expanded(&other(vec![1, 2, 3, 5], 3)));
+
+        for g in &[other(vec![5, 6, 7, 8], 3), other(vec![1, 2, 3, 4], 4)] {
+            assert_ne!(expanded(&default), expanded(g));
+            assert_ne!(default.parameter_set_identifier(), g.parameter_set_identifier());
+
+            // The reversed graph is permuted with the same keys and rounds.
+            assert_eq!(g.feistel(), g.zigzag().feistel());
+            assert_graph_ascending(g.clone());
+            assert_graph_descending(g.zigzag());
+        }
+    }
 }
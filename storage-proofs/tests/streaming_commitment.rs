@@ -0,0 +1,130 @@
+//! Checks that computing a commitment by streaming holds only a few kilobytes
+//! of memory, however large the data committed to.
+//!
+//! This binary installs a counting global allocator, so it must not run tests
+//! concurrently: the full-size test is ignored, and so never runs alongside
+//! the other.
+
+extern crate storage_proofs;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use storage_proofs::hasher::Sha256Hasher;
+use storage_proofs::merkle::streaming_commitment;
+use storage_proofs::util::NODE_SIZE;
+
+// The size of the largest sectors sealed (LIVE_SECTOR_SIZE in sector-base).
+const MAX_SECTOR_BYTES: usize = 1 << 28;
+
+// The most memory computing a commitment may hold at once.
+const MEMORY_BUDGET: usize = 64 << 10;
+
+struct CountingAllocator;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+fn allocated(size: usize) {
+    let live = LIVE.fetch_add(size, Ordering::SeqCst) + size;
+    let mut peak = PEAK.load(Ordering::SeqCst);
+
+    while live > peak {
+        match PEAK.compare_exchange(peak, live, Ordering::SeqCst, Ordering::SeqCst) {
+            Ok(_) => break,
+            Err(current) => peak = current,
+        }
+    }
+}
+
+fn freed(size: usize) {
+    LIVE.fetch_sub(size, Ordering::SeqCst);
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        allocated(layout.size());
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        freed(layout.size());
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        allocated(new_size);
+        let new_ptr = System.realloc(ptr, layout, new_size);
+        freed(layout.size());
+
+        new_ptr
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+// Yields `len` bytes of a repeating pattern of nodes, each of them an element
+// of the field, without holding them in memory.
+struct PatternReader {
+    offset: usize,
+    len: usize,
+}
+
+impl Read for PatternReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = std::cmp::min(buf.len(), self.len - self.offset);
+
+        for (i, b) in buf[..n].iter_mut().enumerate() {
+            let offset = self.offset + i;
+            *b = match offset % NODE_SIZE {
+                31 => 0,
+                byte => (offset / NODE_SIZE + byte) as u8,
+            };
+        }
+        self.offset += n;
+
+        Ok(n)
+    }
+}
+
+// Commits to sector_bytes of data, of which all but the last quarter is read
+// from the pattern, and returns the most memory held meanwhile.
+fn peak_memory(sector_bytes: usize) -> usize {
+    let reader = PatternReader {
+        offset: 0,
+        len: sector_bytes / 4 * 3,
+    };
+
+    let before = LIVE.load(Ordering::SeqCst);
+    PEAK.store(before, Ordering::SeqCst);
+
+    streaming_commitment::<Sha256Hasher, _>(reader, sector_bytes, NODE_SIZE).unwrap();
+
+    PEAK.load(Ordering::SeqCst) - before
+}
+
+#[test]
+fn commitment_memory_is_bounded() {
+    let peak = peak_memory(4 << 20);
+
+    assert!(
+        peak < MEMORY_BUDGET,
+        "{} bytes held committing to 4MiB",
+        peak
+    );
+}
+
+#[test]
+#[ignore] // Slow test – run only when compiled for release.
+fn commitment_memory_is_bounded_at_the_largest_sector_size() {
+    let peak = peak_memory(MAX_SECTOR_BYTES);
+
+    assert!(
+        peak < MEMORY_BUDGET,
+        "{} bytes held committing to {} bytes",
+        peak,
+        MAX_SECTOR_BYTES
+    );
+}
@@ -0,0 +1,81 @@
+//! Checks that encoding reuses its scratch buffers instead of allocating per
+//! node.
+//!
+//! This binary installs a counting global allocator, so it must contain only
+//! the single test below: tests running concurrently would skew the count.
+
+extern crate pairing;
+extern crate rand;
+extern crate storage_proofs;
+
+use pairing::bls12_381::Bls12;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use storage_proofs::drgraph::{new_seed, BucketGraph, Graph};
+use storage_proofs::fr32::fr_into_bytes;
+use storage_proofs::hasher::pedersen::{PedersenDomain, PedersenHasher};
+use storage_proofs::vde::{self, EncodingScratch};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::SeqCst);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+#[test]
+fn encoding_does_not_allocate_per_node() {
+    let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+    let nodes = 10_000;
+    let sloth_iter = 1;
+    let graph = BucketGraph::<PedersenHasher>::new(nodes, 5, 0, new_seed());
+    let replica_id: PedersenDomain = rng.gen();
+    let data: Vec<u8> = (0..nodes)
+        .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+        .collect();
+
+    let mut scratch = EncodingScratch::default();
+
+    // The first replica grows the scratch buffers to fit the graph.
+    let mut warm_up = data.clone();
+    vde::encode_with_scratch(&graph, sloth_iter, &replica_id, &mut warm_up, &mut scratch).unwrap();
+
+    let mut encoded = data.clone();
+
+    let before = ALLOCATIONS.load(Ordering::SeqCst);
+    vde::encode_with_scratch(&graph, sloth_iter, &replica_id, &mut encoded, &mut scratch).unwrap();
+    let allocations = ALLOCATIONS.load(Ordering::SeqCst) - before;
+
+    assert!(
+        allocations * 100 < nodes,
+        "{} allocations while encoding {} nodes",
+        allocations,
+        nodes
+    );
+
+    // Reusing the scratch doesn't change the encoding.
+    let mut expected = data.clone();
+    vde::encode(&graph, sloth_iter, &replica_id, &mut expected).unwrap();
+
+    assert_eq!(expected, warm_up);
+    assert_eq!(expected, encoded);
+}
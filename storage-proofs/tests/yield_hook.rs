@@ -0,0 +1,91 @@
+//! Checks that encoding, tree building and decoding call the yield hook once
+//! every interval, and that encoding stops at the yield point whose call asks
+//! it to.
+//!
+//! The hook is global, so this binary must contain only the single test below:
+//! tests running concurrently would call it too.
+
+extern crate pairing;
+extern crate rand;
+extern crate storage_proofs;
+
+use pairing::bls12_381::Bls12;
+use rand::{Rng, SeedableRng, XorShiftRng};
+use std::sync::{Arc, Mutex};
+
+use storage_proofs::drgraph::{new_seed, BucketGraph, Graph};
+use storage_proofs::error::Error;
+use storage_proofs::fr32::fr_into_bytes;
+use storage_proofs::hasher::pedersen::{PedersenDomain, PedersenHasher};
+use storage_proofs::util::NODE_SIZE;
+use storage_proofs::vde;
+use storage_proofs::yield_hook::{self, BUILDING_TREES, DECODING, ENCODING};
+
+const INTERVAL: usize = 100;
+
+// Installs a hook which records the phase of each call, and returns whether
+// the operation is to go on: for the first `allowed` calls.
+fn record_calls(allowed: usize) -> Arc<Mutex<Vec<String>>> {
+    let calls = Arc::new(Mutex::new(Vec::new()));
+    let recorded = calls.clone();
+
+    yield_hook::set_yield_hook(Some(Box::new(move |phase: &str| {
+        let mut calls = recorded.lock().unwrap();
+        calls.push(phase.to_string());
+        calls.len() <= allowed
+    })));
+
+    calls
+}
+
+#[test]
+fn loops_yield_once_every_interval() {
+    let rng = &mut XorShiftRng::from_seed([0x3dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]);
+
+    let nodes = 1000;
+    let sloth_iter = 1;
+    let graph = BucketGraph::<PedersenHasher>::new(nodes, 5, 0, new_seed());
+    let replica_id: PedersenDomain = rng.gen();
+    let data: Vec<u8> = (0..nodes)
+        .flat_map(|_| fr_into_bytes::<Bls12>(&rng.gen()))
+        .collect();
+
+    yield_hook::set_yield_interval(INTERVAL);
+    let calls = record_calls(usize::max_value());
+
+    let mut encoded = data.clone();
+    vde::encode(&graph, sloth_iter, &replica_id, &mut encoded).unwrap();
+    graph.merkle_tree(&encoded).unwrap();
+    let decoded = vde::decode(&graph, sloth_iter, &replica_id, &encoded).unwrap();
+    assert_eq!(data, decoded);
+
+    // The tree hashes 1001 nodes above its 1000 leaves, so the count of the
+    // nodes worked on reaches 3001.
+    let mut expected = vec![ENCODING; 10];
+    expected.extend(vec![BUILDING_TREES; 10]);
+    expected.extend(vec![DECODING; 10]);
+    assert_eq!(expected, *calls.lock().unwrap());
+
+    // The third call is made before the 300th node is encoded, which it stops.
+    let calls = record_calls(2);
+
+    let mut stopped = data.clone();
+    match vde::encode(&graph, sloth_iter, &replica_id, &mut stopped) {
+        Err(Error::YieldCancelled { phase }) => assert_eq!(ENCODING, phase),
+        other => panic!("unexpected result: {:?}", other),
+    }
+    assert_eq!(3, calls.lock().unwrap().len());
+
+    let split = 299 * NODE_SIZE;
+    assert_eq!(encoded[..split], stopped[..split]);
+    assert_eq!(data[split..], stopped[split..]);
+
+    // Without a hook, nothing stops encoding.
+    yield_hook::set_yield_hook(None);
+    yield_hook::set_yield_interval(0);
+
+    let mut unstopped = data.clone();
+    vde::encode(&graph, sloth_iter, &replica_id, &mut unstopped).unwrap();
+    assert_eq!(encoded, unstopped);
+    assert_eq!(3, calls.lock().unwrap().len());
+}